@@ -0,0 +1,141 @@
+//! Message parsing cost at high peer counts
+//!
+//! Every peer delivers the same block in 1 KiB reads. The owned path
+//! collects each peer's bytes and deserializes a `RawNetworkMessage`; the
+//! framed path feeds a `FrameDecoder` per peer and indexes the block with a
+//! `BlockView`. Besides timings, allocations per message are counted with a
+//! wrapping global allocator and printed once per peer count.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anya_core::net::messages::FrameDecoder;
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hash_types::TxMerkleNode;
+use bitcoin::hashes::Hash;
+use bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::{
+    absolute::LockTime, Block, BlockHash, CompactTarget, Network, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const PEER_COUNTS: [usize; 3] = [10, 100, 1_000];
+const READ_SIZE: usize = 1_024;
+const TXS: usize = 200;
+
+fn block_message() -> Vec<u8> {
+    let txdata = (0..TXS)
+        .map(|i| Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), i as u32),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::from_slice(&[vec![7; 72], vec![2; 33]]),
+            }],
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: ScriptBuf::from_bytes(vec![0; 22]),
+                };
+                2
+            ],
+        })
+        .collect();
+    let block = Block {
+        header: Header {
+            version: Version::TWO,
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: 1_700_000_000,
+            bits: CompactTarget::from_consensus(0x207f_ffff),
+            nonce: 0,
+        },
+        txdata,
+    };
+    serialize(&RawNetworkMessage {
+        magic: Network::Bitcoin.magic(),
+        payload: NetworkMessage::Block(block),
+    })
+}
+
+fn owned(wire: &[u8], peers: usize) {
+    for _ in 0..peers {
+        let mut received = Vec::new();
+        for read in wire.chunks(READ_SIZE) {
+            received.extend_from_slice(read);
+        }
+        let message: RawNetworkMessage = deserialize(&received).unwrap();
+        black_box(message);
+    }
+}
+
+fn framed(wire: &[u8], peers: usize) {
+    for _ in 0..peers {
+        let mut decoder = FrameDecoder::new(Network::Bitcoin.magic());
+        for read in wire.chunks(READ_SIZE) {
+            decoder.extend(read);
+            if let Some(frame) = decoder.next_frame().unwrap() {
+                let view = frame.block().unwrap();
+                black_box(view.raw_transactions().map(|tx| tx.len()).sum::<usize>());
+            }
+        }
+    }
+}
+
+fn allocations_per_message(run: impl Fn(&[u8], usize), wire: &[u8], peers: usize) -> u64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    run(wire, peers);
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) / peers as u64
+}
+
+fn block_parsing(c: &mut Criterion) {
+    let wire = block_message();
+    let mut group = c.benchmark_group("net/block");
+    group.sample_size(10);
+    for peers in PEER_COUNTS {
+        println!(
+            "{} peers: owned {} allocations/message, framed {}",
+            peers,
+            allocations_per_message(owned, &wire, peers),
+            allocations_per_message(framed, &wire, peers),
+        );
+        group.throughput(Throughput::Bytes((wire.len() * peers) as u64));
+        group.bench_with_input(BenchmarkId::new("owned", peers), &peers, |b, &peers| {
+            b.iter(|| owned(&wire, peers))
+        });
+        group.bench_with_input(BenchmarkId::new("framed", peers), &peers, |b, &peers| {
+            b.iter(|| framed(&wire, peers))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, block_parsing);
+criterion_main!(benches);
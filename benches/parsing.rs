@@ -0,0 +1,40 @@
+//! Parsing of untrusted input, measured on the fuzz seed corpora
+
+use std::path::Path;
+
+use anya_core::bitcoin::labels::Label;
+use anya_core::mobile::qr::QrPayload;
+use anya_core::mobile::signer::import_psbt;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn seed(path: &str) -> Vec<u8> {
+    std::fs::read(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/corpus")
+            .join(path),
+    )
+    .unwrap()
+}
+
+fn parsing(c: &mut Criterion) {
+    let psbt = seed("psbt/derivations.psbt");
+    let psbt_b64 = seed("psbt/unsigned.b64");
+    let uri = String::from_utf8(seed("qr_payload/unified.txt")).unwrap();
+    let label = String::from_utf8(seed("label/output.json")).unwrap();
+
+    c.bench_function("parse/psbt_binary", |b| {
+        b.iter(|| import_psbt(black_box(&psbt)).unwrap())
+    });
+    c.bench_function("parse/psbt_base64", |b| {
+        b.iter(|| import_psbt(black_box(&psbt_b64)).unwrap())
+    });
+    c.bench_function("parse/qr_unified_uri", |b| {
+        b.iter(|| QrPayload::parse(black_box(&uri)).unwrap())
+    });
+    c.bench_function("parse/bip329_label", |b| {
+        b.iter(|| Label::parse(black_box(&label)).unwrap())
+    });
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+//! Storage backend and cache throughput
+
+use std::sync::Arc;
+
+use anya_core::cache::{Cache, CacheConfig, CacheManager, FEE_ESTIMATE_CACHE};
+use anya_core::storage::memory::MemoryBackend;
+use anya_core::storage::{Namespace, StorageBackend};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+const ENTRIES: u64 = 1_000;
+
+fn memory_backend(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+    let ns = Namespace::new("bench").unwrap();
+    rt.block_on(backend.ensure_namespace(&ns)).unwrap();
+    let value = vec![7u8; 256];
+
+    let mut group = c.benchmark_group("storage/memory");
+    group.throughput(Throughput::Elements(ENTRIES));
+    group.bench_function("put_1000", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for i in 0..ENTRIES {
+                    backend
+                        .put(&ns, &format!("key/{:06}", i), &value)
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+    });
+    group.bench_function("get_1000", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for i in 0..ENTRIES {
+                    black_box(backend.get(&ns, &format!("key/{:06}", i)).await.unwrap());
+                }
+            })
+        })
+    });
+    group.bench_function("scan_prefix_1000", |b| {
+        b.iter(|| rt.block_on(backend.scan_prefix(&ns, "key/")).unwrap())
+    });
+    group.finish();
+}
+
+fn cache(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let cache = CacheManager::new().build::<u16, u64>(FEE_ESTIMATE_CACHE);
+    rt.block_on(cache.insert(6, 12));
+    c.bench_function("cache/hit", |b| {
+        b.iter(|| rt.block_on(cache.get(black_box(&6))))
+    });
+}
+
+const TASKS: u64 = 8;
+
+/// Lookups from several threads against one lock versus sharded locks,
+/// printing the share of acquisitions that had to wait
+fn cache_contention(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(TASKS as usize)
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("cache/contended");
+    group.throughput(Throughput::Elements(TASKS * ENTRIES));
+    for shards in [1, 16] {
+        let cache = Arc::new(Cache::<u64, u64>::new(
+            "bench",
+            CacheConfig {
+                shards,
+                ..CacheConfig::default()
+            },
+        ));
+        rt.block_on(async {
+            for i in 0..ENTRIES {
+                cache.insert(i, i).await;
+            }
+        });
+        group.bench_function(format!("get_{}_shards", shards), |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let tasks: Vec<_> = (0..TASKS)
+                        .map(|_| {
+                            let cache = Arc::clone(&cache);
+                            tokio::spawn(async move {
+                                for i in 0..ENTRIES {
+                                    black_box(cache.get(&i).await);
+                                }
+                            })
+                        })
+                        .collect();
+                    for task in tasks {
+                        task.await.unwrap();
+                    }
+                })
+            })
+        });
+        let stats = cache.lock_stats();
+        println!(
+            "{} shards: {} of {} acquisitions contended ({:.3}%), {} us waiting",
+            shards,
+            stats.contended,
+            stats.acquisitions,
+            stats.contention_rate() * 100.0,
+            stats.wait_micros
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, memory_backend, cache, cache_contention);
+criterion_main!(benches);
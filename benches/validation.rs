@@ -0,0 +1,72 @@
+//! Mempool acceptance and block connection on the simulated chain
+
+use anya_core::bitcoin::regtest::RegtestChain;
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, WPubkeyHash, Witness,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const CHAIN_LENGTH: u64 = 100;
+
+fn spend(prevout: OutPoint, script_pubkey: &ScriptBuf, value: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: prevout,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// A funded chain and a sequence of transactions each spending the last
+fn setup() -> (RegtestChain, Vec<Transaction>) {
+    let chain = RegtestChain::new(Network::Regtest).unwrap();
+    let script = ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::all_zeros());
+    let mut prevout = chain.fund(&script, 10_000_000);
+    let txs = (1..=CHAIN_LENGTH)
+        .map(|i| {
+            let tx = spend(prevout, &script, 10_000_000 - i * 1_000);
+            prevout = OutPoint::new(tx.txid(), 0);
+            tx
+        })
+        .collect();
+    (chain, txs)
+}
+
+fn mempool(c: &mut Criterion) {
+    c.bench_function("validation/accept_100_tx_chain", |b| {
+        b.iter_batched(
+            setup,
+            |(chain, txs)| {
+                for tx in &txs {
+                    chain.broadcast(tx).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("validation/accept_and_mine_100_tx_chain", |b| {
+        b.iter_batched(
+            setup,
+            |(chain, txs)| {
+                for tx in &txs {
+                    chain.broadcast(tx).unwrap();
+                }
+                chain.generate(1)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, mempool);
+criterion_main!(benches);
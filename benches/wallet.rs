@@ -0,0 +1,59 @@
+//! Coin selection and transaction size estimation
+
+use anya_core::bitcoin::accounts::{AccountId, KeyChain, ScriptType};
+use anya_core::bitcoin::coins::{select_coins, SelectionParams, Utxo};
+use anya_core::bitcoin::fees::TxShape;
+use bitcoin::hashes::Hash;
+use bitcoin::{FeeRate, OutPoint, ScriptBuf, TxOut, Txid, WPubkeyHash};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const ACCOUNT: AccountId = AccountId {
+    script_type: ScriptType::NativeSegwit,
+    index: 0,
+};
+
+fn utxos(count: u32) -> Vec<Utxo> {
+    let script_pubkey = ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::all_zeros());
+    (0..count)
+        .map(|i| Utxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), i),
+            txout: TxOut {
+                value: 1_000 + u64::from(i) * 137 % 50_000,
+                script_pubkey: script_pubkey.clone(),
+            },
+            account: ACCOUNT,
+            chain: KeyChain::External,
+            index: i,
+            height: Some(1),
+        })
+        .collect()
+}
+
+fn coin_selection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("coin_selection");
+    for count in [100, 1_000, 10_000] {
+        let candidates = utxos(count);
+        let params = SelectionParams {
+            target_sat: u64::from(count) * 5_000,
+            base_weight: 500,
+            change_weight: 124,
+            change_dust_sat: 294,
+            fee_rate: FeeRate::from_sat_per_vb_unchecked(12),
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(count), &candidates, |b, c| {
+            b.iter(|| select_coins(Vec::new(), black_box(c.clone()), &params))
+        });
+    }
+    group.finish();
+}
+
+fn size_estimation(c: &mut Criterion) {
+    let shape = TxShape::uniform(ScriptType::Taproot, 50, 100);
+    let rate = FeeRate::from_sat_per_vb_unchecked(5);
+    c.bench_function("tx_shape/estimate_50_in_100_out", |b| {
+        b.iter(|| black_box(&shape).estimate(rate))
+    });
+}
+
+criterion_group!(benches, coin_selection, size_estimation);
+criterion_main!(benches);
@@ -0,0 +1,77 @@
+use std::process::Command;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    build_info();
+
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto");
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::configure().compile(
+            &[
+                "proto/anya/v1/wallet.proto",
+                "proto/anya/v1/chain.proto",
+                "proto/anya/v1/inference.proto",
+                "proto/anya/v1/workflow.proto",
+                "proto/anya/v1/auth.proto",
+                "proto/anya/v1/analytics.proto",
+                "proto/anya/v1/peers.proto",
+            ],
+            &["proto"],
+        )?;
+    }
+    Ok(())
+}
+
+/// Export build metadata read by `anya_core::build_info`.
+///
+/// Only inputs that are identical across reproducible builds of the same
+/// commit are recorded: no hostnames, paths, or wall-clock times. The
+/// timestamp is `SOURCE_DATE_EPOCH` when set, else the commit time.
+fn build_info() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+    };
+    let commit = git(&["rev-parse", "HEAD"]).unwrap_or_default();
+    let dirty =
+        git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|s| !s.is_empty());
+    let epoch = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .or_else(|| git(&["log", "-1", "--format=%ct"]))
+        .unwrap_or_default();
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default();
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .filter(|f| f != "default")
+        .collect();
+    features.sort();
+
+    let env = |name: &str| std::env::var(name).unwrap_or_default();
+    println!("cargo:rustc-env=ANYA_BUILD_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=ANYA_BUILD_GIT_DIRTY={}", dirty);
+    println!("cargo:rustc-env=ANYA_BUILD_SOURCE_DATE_EPOCH={}", epoch);
+    println!("cargo:rustc-env=ANYA_BUILD_RUSTC={}", rustc_version);
+    println!("cargo:rustc-env=ANYA_BUILD_TARGET={}", env("TARGET"));
+    println!("cargo:rustc-env=ANYA_BUILD_PROFILE={}", env("PROFILE"));
+    println!("cargo:rustc-env=ANYA_BUILD_FEATURES={}", features.join(","));
+}
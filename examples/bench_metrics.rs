@@ -0,0 +1,89 @@
+//! Collect criterion results into one metrics file for regression tracking
+//!
+//! Run after `cargo bench`:
+//!
+//! ```text
+//! cargo run --example bench_metrics -- [target/criterion] [target/bench-metrics.json]
+//! ```
+//!
+//! The output maps each benchmark id to its mean, median, and standard
+//! deviation in nanoseconds, and can be archived by CI and diffed between
+//! runs.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+struct BenchmarkInfo {
+    full_id: String,
+}
+
+#[derive(Deserialize)]
+struct Estimate {
+    point_estimate: f64,
+}
+
+#[derive(Deserialize)]
+struct Estimates {
+    mean: Estimate,
+    median: Estimate,
+    std_dev: Estimate,
+}
+
+#[derive(Serialize)]
+struct Metric {
+    mean_ns: f64,
+    median_ns: f64,
+    std_dev_ns: f64,
+}
+
+/// Every `new/` result directory below `dir`
+fn result_dirs(dir: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        if path.file_name().is_some_and(|n| n == "new") {
+            found.push(path);
+        } else if path
+            .file_name()
+            .is_some_and(|n| n != "base" && n != "report")
+        {
+            result_dirs(&path, found)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let input = PathBuf::from(args.next().unwrap_or_else(|| "target/criterion".into()));
+    let output = PathBuf::from(
+        args.next()
+            .unwrap_or_else(|| "target/bench-metrics.json".into()),
+    );
+
+    let mut dirs = Vec::new();
+    result_dirs(&input, &mut dirs)?;
+    let mut metrics = BTreeMap::new();
+    for dir in dirs {
+        let info: BenchmarkInfo = serde_json::from_slice(&fs::read(dir.join("benchmark.json"))?)?;
+        let estimates: Estimates = serde_json::from_slice(&fs::read(dir.join("estimates.json"))?)?;
+        metrics.insert(
+            info.full_id,
+            Metric {
+                mean_ns: estimates.mean.point_estimate,
+                median_ns: estimates.median.point_estimate,
+                std_dev_ns: estimates.std_dev.point_estimate,
+            },
+        );
+    }
+
+    fs::write(&output, serde_json::to_vec_pretty(&metrics)?)?;
+    println!("wrote {} benchmarks to {}", metrics.len(), output.display());
+    Ok(())
+}
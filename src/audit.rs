@@ -0,0 +1,195 @@
+//! Read-only audit access to node state
+//!
+//! [`AuditNode`] gives auditors and analysts the node's query surface,
+//! covering accounts, balances, coins, labels, the event log, and chain
+//! lookups, over replicated state. It cannot sign or broadcast. It is
+//! built without key material, its chain access is a [`ChainSource`], which
+//! has no broadcast method, and its storage is wrapped in
+//! [`ReadOnlyBackend`], so any API that would persist a change fails with
+//! [`ErrorCode::PermissionDenied`](crate::ErrorCode::PermissionDenied).
+//!
+//! Run it in-process over a [`Replicator`](crate::storage::replica::Replicator)
+//! replica, or in a separate process over a database replica.
+
+use std::sync::Arc;
+
+use ::bitcoin::Network;
+
+use crate::bitcoin::accounts::{AccountManager, Balance, BalanceView};
+use crate::bitcoin::coins::CoinStore;
+use crate::bitcoin::labels::LabelStore;
+use crate::bitcoin::tracker::ChainSource;
+use crate::events::{EventStore, EventStoreConfig};
+use crate::storage::replica::ReadOnlyBackend;
+use crate::storage::{Namespace, StorageBackend};
+use crate::AnyaResult;
+
+/// Query-only facade over replicated node state
+pub struct AuditNode {
+    network: Network,
+    storage: Arc<dyn StorageBackend>,
+    coins: CoinStore,
+    labels: LabelStore,
+    chain: Option<Arc<dyn ChainSource>>,
+}
+
+impl AuditNode {
+    /// Open over `replica`, which is wrapped read-only, with optional chain
+    /// lookups through `chain`
+    pub async fn open(
+        network: Network,
+        replica: Arc<dyn StorageBackend>,
+        chain: Option<Arc<dyn ChainSource>>,
+    ) -> AnyaResult<Self> {
+        let storage: Arc<dyn StorageBackend> = Arc::new(ReadOnlyBackend::new(replica));
+        Ok(Self {
+            network,
+            coins: CoinStore::open(Arc::clone(&storage)).await?,
+            labels: LabelStore::open(Arc::clone(&storage)).await?,
+            storage,
+            chain,
+        })
+    }
+
+    /// Network of the audited wallet
+    pub const fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Wallet accounts as of the latest replication.
+    ///
+    /// The manager caches accounts, so it is reopened on every call.
+    pub async fn accounts(&self) -> AnyaResult<AccountManager> {
+        AccountManager::open(self.network, Arc::clone(&self.storage)).await
+    }
+
+    /// Wallet coins
+    pub const fn coins(&self) -> &CoinStore {
+        &self.coins
+    }
+
+    /// BIP-329 labels
+    pub const fn labels(&self) -> &LabelStore {
+        &self.labels
+    }
+
+    /// Event log as of the latest replication.
+    ///
+    /// The store caches its head, so it is reopened on every call.
+    pub async fn events(&self) -> AnyaResult<Arc<EventStore>> {
+        EventStore::open(EventStoreConfig::default(), Arc::clone(&self.storage)).await
+    }
+
+    /// Chain lookups, if configured
+    pub fn chain(&self) -> Option<&dyn ChainSource> {
+        self.chain.as_deref()
+    }
+
+    /// Confirmed and unconfirmed balance of every account
+    pub async fn balances(&self) -> AnyaResult<BalanceView> {
+        let coins = self.coins.coins(None).await?;
+        let accounts = self.accounts().await?;
+        Ok(accounts
+            .balance_view(|account| {
+                coins.iter().filter(|c| c.utxo.account == account.id).fold(
+                    Balance::default(),
+                    |mut balance, c| {
+                        if c.utxo.height.is_some() {
+                            balance.confirmed_sat += c.utxo.txout.value;
+                        } else {
+                            balance.unconfirmed_sat += c.utxo.txout.value;
+                        }
+                        balance
+                    },
+                )
+            })
+            .await)
+    }
+
+    /// Raw entries of any replicated namespace, for analysts
+    pub async fn scan(&self, namespace: &str, prefix: &str) -> AnyaResult<Vec<(String, Vec<u8>)>> {
+        self.storage
+            .scan_prefix(&Namespace::new(namespace)?, prefix)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::{AccountId, KeyChain, ScriptType};
+    use crate::bitcoin::coins::Utxo;
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::replica::{ReplicaConfig, Replicator};
+    use crate::ErrorCode;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{OutPoint, ScriptBuf, TxOut, Txid};
+
+    #[tokio::test]
+    async fn audit_node_queries_replica_but_cannot_write() {
+        let primary: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[3; 32]).unwrap();
+        let accounts = AccountManager::open(Network::Regtest, Arc::clone(&primary))
+            .await
+            .unwrap();
+        let account = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "treasury")
+            .await
+            .unwrap();
+        let coins = CoinStore::open(Arc::clone(&primary)).await.unwrap();
+        coins
+            .insert(&Utxo {
+                outpoint: OutPoint::new(Txid::all_zeros(), 0),
+                txout: TxOut {
+                    value: 50_000,
+                    script_pubkey: ScriptBuf::new(),
+                },
+                account: account.id,
+                chain: KeyChain::External,
+                index: 0,
+                height: Some(10),
+            })
+            .await
+            .unwrap();
+
+        let replicator = Replicator::new(
+            ReplicaConfig {
+                namespaces: vec!["wallet_accounts".into(), "wallet_coins".into()],
+                ..ReplicaConfig::default()
+            },
+            primary,
+            Arc::new(MemoryBackend::new()),
+        )
+        .unwrap();
+        replicator.sync().await.unwrap();
+
+        let audit = AuditNode::open(Network::Regtest, replicator.read_only(), None)
+            .await
+            .unwrap();
+        let view = audit.balances().await.unwrap();
+        assert_eq!(view.total.confirmed_sat, 50_000);
+        assert_eq!(view.accounts[0].label, "treasury");
+
+        // Deriving an address persists the index, which the replica refuses
+        let err = audit
+            .accounts()
+            .await
+            .unwrap()
+            .next_address(
+                AccountId {
+                    script_type: ScriptType::NativeSegwit,
+                    index: 0,
+                },
+                KeyChain::External,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        assert!(audit
+            .coins()
+            .freeze(&OutPoint::new(Txid::all_zeros(), 0))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,619 @@
+//! State snapshot and backup/restore facility
+//!
+//! Each persistent subsystem (chainstate, wallet DB, Web5 records, DAO state)
+//! implements [`BackupSource`] by exporting its state as a key/value map. The
+//! [`BackupManager`] combines all sources into a single archive encrypted
+//! with ChaCha20-Poly1305 under a passphrase-derived key.
+//!
+//! Incremental archives only contain entries whose content hash changed since
+//! the previous archive, plus tombstones for deleted keys; restoring an
+//! incremental archive replays its parent chain back to the last full backup.
+//! Every entry is hashed, and all hashes are checked before anything is handed
+//! back to a source.
+//!
+//! Archive layout: `MAGIC | salt(16) | nonce(12) | AEAD(payload)`, where the
+//! payload is `manifest_len(u32 BE) | manifest JSON | entry bytes...`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::utils::encoding::{sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+const MAGIC: &[u8; 8] = b"ANYABK01";
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const ARCHIVE_EXTENSION: &str = "anyabk";
+
+/// A component whose state can be captured in a backup
+#[async_trait]
+pub trait BackupSource: Send + Sync {
+    /// Unique source name, e.g. `chainstate`, `wallet`, `web5`, `dao`
+    fn name(&self) -> &str;
+
+    /// Export the full current state as key/value entries
+    async fn export(&self) -> AnyaResult<BTreeMap<String, Vec<u8>>>;
+
+    /// Replace the current state with the given entries
+    async fn import(&self, entries: BTreeMap<String, Vec<u8>>) -> AnyaResult<()>;
+}
+
+/// Kind of backup archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackupKind {
+    /// Self-contained snapshot of every source
+    Full,
+    /// Changes relative to the parent archive
+    Incremental,
+}
+
+/// Metadata for one entry stored in an archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryMeta {
+    /// Source the entry belongs to
+    pub source: String,
+    /// Entry key within the source
+    pub key: String,
+    /// Hex SHA-256 of the entry bytes
+    pub sha256: String,
+    /// Offset of the entry in the payload data section
+    pub offset: u64,
+    /// Entry length in bytes
+    pub len: u64,
+}
+
+/// Manifest describing an archive's contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// Archive identifier; also the file stem
+    pub id: String,
+    /// Parent archive for incremental backups
+    pub parent: Option<String>,
+    /// Archive kind
+    pub kind: BackupKind,
+    /// Creation time in milliseconds since the Unix epoch
+    pub created_at_ms: u64,
+    /// Entries stored in this archive
+    pub entries: Vec<EntryMeta>,
+    /// `(source, key)` pairs deleted since the parent archive
+    pub deleted: Vec<(String, String)>,
+    /// Content hashes of the complete state after this archive is applied
+    pub state: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Decrypted manifest together with its verified entries
+type LoadedArchive = (BackupManifest, Vec<(EntryMeta, Vec<u8>)>);
+
+/// Backup scheduling and storage configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupConfig {
+    /// Directory where archives are written
+    pub directory: PathBuf,
+    /// Interval between scheduled backups
+    pub interval: Duration,
+    /// Take a full backup after this many incrementals
+    pub full_every: u32,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("backups"),
+            interval: Duration::from_secs(6 * 60 * 60),
+            full_every: 7,
+        }
+    }
+}
+
+/// Creates, verifies, and restores encrypted backups
+pub struct BackupManager {
+    config: BackupConfig,
+    passphrase: Vec<u8>,
+    sources: Vec<Arc<dyn BackupSource>>,
+    last: Mutex<Option<BackupManifest>>,
+    incrementals_since_full: Mutex<u32>,
+    rng: SystemRandom,
+}
+
+impl BackupManager {
+    /// Create a manager that encrypts archives with `passphrase`
+    pub fn new(config: BackupConfig, passphrase: impl Into<Vec<u8>>) -> Self {
+        Self {
+            config,
+            passphrase: passphrase.into(),
+            sources: Vec::new(),
+            last: Mutex::new(None),
+            incrementals_since_full: Mutex::new(0),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Register a source to include in backups
+    pub fn add_source(&mut self, source: Arc<dyn BackupSource>) {
+        self.sources.push(source);
+    }
+
+    /// Take a backup of all sources and write it to the backup directory.
+    ///
+    /// An incremental backup falls back to a full one when no previous
+    /// archive is known.
+    pub async fn backup(&self, kind: BackupKind) -> AnyaResult<BackupManifest> {
+        let mut last = self.last.lock().await;
+        let kind = if last.is_none() {
+            BackupKind::Full
+        } else {
+            kind
+        };
+        let created_at_ms = now_ms();
+        let id = format!(
+            "{}-{}",
+            created_at_ms,
+            if kind == BackupKind::Full {
+                "full"
+            } else {
+                "incr"
+            }
+        );
+
+        let mut state = BTreeMap::new();
+        let mut entries = Vec::new();
+        let mut data = Vec::new();
+        let mut deleted = Vec::new();
+
+        for source in &self.sources {
+            let exported = source
+                .export()
+                .await
+                .with_context(|| format!("exporting backup source {}", source.name()))?;
+            let previous = last
+                .as_ref()
+                .filter(|_| kind == BackupKind::Incremental)
+                .and_then(|m| m.state.get(source.name()));
+
+            let mut hashes = BTreeMap::new();
+            for (key, value) in exported {
+                let hash = to_hex(&sha256(&value));
+                let unchanged = previous.and_then(|p| p.get(&key)) == Some(&hash);
+                if !unchanged {
+                    entries.push(EntryMeta {
+                        source: source.name().to_string(),
+                        key: key.clone(),
+                        sha256: hash.clone(),
+                        offset: data.len() as u64,
+                        len: value.len() as u64,
+                    });
+                    data.extend_from_slice(&value);
+                }
+                hashes.insert(key, hash);
+            }
+            if let Some(previous) = previous {
+                deleted.extend(
+                    previous
+                        .keys()
+                        .filter(|k| !hashes.contains_key(*k))
+                        .map(|k| (source.name().to_string(), k.clone())),
+                );
+            }
+            state.insert(source.name().to_string(), hashes);
+        }
+
+        let manifest = BackupManifest {
+            id,
+            parent: match kind {
+                BackupKind::Full => None,
+                BackupKind::Incremental => last.as_ref().map(|m| m.id.clone()),
+            },
+            kind,
+            created_at_ms,
+            entries,
+            deleted,
+            state,
+        };
+
+        let archive = self.seal(&manifest, &data)?;
+        tokio::fs::create_dir_all(&self.config.directory)
+            .await
+            .context("creating backup directory")?;
+        let path = self.archive_path(&manifest.id);
+        tokio::fs::write(&path, archive)
+            .await
+            .with_context(|| format!("writing backup {}", path.display()))?;
+
+        {
+            let mut counter = self.incrementals_since_full.lock().await;
+            *counter = match kind {
+                BackupKind::Full => 0,
+                BackupKind::Incremental => *counter + 1,
+            };
+        }
+        info!(id = %manifest.id, kind = ?kind, entries = manifest.entries.len(), "backup written");
+        *last = Some(manifest.clone());
+        drop(last);
+        Ok(manifest)
+    }
+
+    /// Decrypt an archive and verify every entry hash
+    pub async fn verify(&self, id: &str) -> AnyaResult<BackupManifest> {
+        let (manifest, _) = self.load(id).await?;
+        Ok(manifest)
+    }
+
+    /// Restore all sources to the state captured by archive `id`.
+    ///
+    /// The full parent chain is decrypted and verified before any source is
+    /// modified.
+    pub async fn restore(&self, id: &str) -> AnyaResult<BackupManifest> {
+        let mut chain = Vec::new();
+        let mut next = Some(id.to_string());
+        while let Some(current) = next {
+            let (manifest, entries) = self.load(&current).await?;
+            next = manifest.parent.clone();
+            chain.push((manifest, entries));
+        }
+        chain.reverse();
+
+        let mut state: HashMap<String, BTreeMap<String, Vec<u8>>> = HashMap::new();
+        for (manifest, entries) in &chain {
+            for (source, key) in &manifest.deleted {
+                if let Some(s) = state.get_mut(source) {
+                    s.remove(key);
+                }
+            }
+            for (meta, value) in entries {
+                state
+                    .entry(meta.source.clone())
+                    .or_default()
+                    .insert(meta.key.clone(), value.clone());
+            }
+        }
+
+        let target = chain
+            .last()
+            .map(|(m, _)| m.clone())
+            .ok_or_else(|| AnyaError::not_found(format!("backup {}", id)))?;
+        for (source_name, hashes) in &target.state {
+            let restored_hashes: BTreeMap<_, _> = state
+                .get(source_name)
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .map(|(k, v)| (k.clone(), to_hex(&sha256(v))))
+                        .collect()
+                })
+                .unwrap_or_default();
+            if &restored_hashes != hashes {
+                return Err(AnyaError::new(
+                    ErrorCode::StorageFailure,
+                    format!("backup chain for {} is inconsistent", source_name),
+                ));
+            }
+        }
+
+        for source in &self.sources {
+            let entries = state.remove(source.name()).unwrap_or_default();
+            source
+                .import(entries)
+                .await
+                .with_context(|| format!("restoring backup source {}", source.name()))?;
+        }
+
+        info!(id = %target.id, "backup restored");
+        *self.last.lock().await = Some(target.clone());
+        Ok(target)
+    }
+
+    /// Identifiers of all archives in the backup directory, oldest first
+    pub async fn list(&self) -> AnyaResult<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut dir = match tokio::fs::read_dir(&self.config.directory).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ids),
+            Err(e) => return Err(AnyaError::from(e).context("listing backups")),
+        };
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(ARCHIVE_EXTENSION) {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    ids.push(stem.to_string());
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    async fn load(&self, id: &str) -> AnyaResult<LoadedArchive> {
+        let path = self.archive_path(id);
+        let archive = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("reading backup {}", path.display()))?;
+        let payload = self.open(&archive)?;
+        parse_payload(&payload)
+    }
+
+    fn archive_path(&self, id: &str) -> PathBuf {
+        self.config
+            .directory
+            .join(format!("{}.{}", id, ARCHIVE_EXTENSION))
+    }
+
+    fn derive_key(&self, salt: &[u8]) -> AnyaResult<LessSafeKey> {
+        let mut key = [0u8; 32];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ITERATIONS).unwrap_or(NonZeroU32::MIN),
+            salt,
+            &self.passphrase,
+            &mut key,
+        );
+        let unbound = UnboundKey::new(&CHACHA20_POLY1305, &key)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "invalid backup key length"))?;
+        Ok(LessSafeKey::new(unbound))
+    }
+
+    fn seal(&self, manifest: &BackupManifest, data: &[u8]) -> AnyaResult<Vec<u8>> {
+        let manifest_json = serde_json::to_vec(manifest)?;
+        let manifest_len = u32::try_from(manifest_json.len())
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "backup manifest too large"))?;
+        let mut payload = Vec::with_capacity(4 + manifest_json.len() + data.len());
+        payload.extend_from_slice(&manifest_len.to_be_bytes());
+        payload.extend_from_slice(&manifest_json);
+        payload.extend_from_slice(data);
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut salt)
+            .and_then(|()| self.rng.fill(&mut nonce))
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "system RNG failure"))?;
+
+        let key = self.derive_key(&salt)?;
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut payload,
+        )
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "backup encryption failed"))?;
+
+        let mut archive = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + payload.len());
+        archive.extend_from_slice(MAGIC);
+        archive.extend_from_slice(&salt);
+        archive.extend_from_slice(&nonce);
+        archive.extend_from_slice(&payload);
+        Ok(archive)
+    }
+
+    fn open(&self, archive: &[u8]) -> AnyaResult<Vec<u8>> {
+        let header_len = MAGIC.len() + SALT_LEN + NONCE_LEN;
+        if archive.len() < header_len || &archive[..MAGIC.len()] != MAGIC {
+            return Err(AnyaError::invalid_input("not an Anya backup archive"));
+        }
+        let salt = &archive[MAGIC.len()..MAGIC.len() + SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&archive[MAGIC.len() + SALT_LEN..header_len]);
+
+        let key = self.derive_key(salt)?;
+        let mut buf = archive[header_len..].to_vec();
+        let plaintext_len = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(MAGIC),
+                &mut buf,
+            )
+            .map_err(|_| {
+                AnyaError::new(
+                    ErrorCode::PermissionDenied,
+                    "backup authentication failed: wrong passphrase or corrupted archive",
+                )
+            })?
+            .len();
+        buf.truncate(plaintext_len);
+        Ok(buf)
+    }
+
+    /// Run scheduled backups until `token` is cancelled.
+    ///
+    /// A full backup is taken every [`BackupConfig::full_every`] runs,
+    /// incrementals otherwise.
+    pub async fn run_schedule(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.interval, || {
+            let manager = Arc::clone(&self);
+            async move {
+                let kind =
+                    if *manager.incrementals_since_full.lock().await >= manager.config.full_every {
+                        BackupKind::Full
+                    } else {
+                        BackupKind::Incremental
+                    };
+                if let Err(e) = manager.backup(kind).await {
+                    warn!(error = %e, "scheduled backup failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+/// Scheduled backups as a lifecycle-managed subsystem
+pub struct BackupService {
+    manager: Arc<BackupManager>,
+}
+
+impl BackupService {
+    /// Wrap a manager for registration with the lifecycle manager
+    pub const fn new(manager: Arc<BackupManager>) -> Self {
+        Self { manager }
+    }
+}
+
+#[async_trait]
+impl Subsystem for BackupService {
+    fn name(&self) -> &str {
+        "backup"
+    }
+
+    fn startup_order(&self) -> u32 {
+        // Start after the storage-owning subsystems it reads from
+        900
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let manager = Arc::clone(&self.manager);
+        spawner
+            .spawn("schedule", move |token| manager.run_schedule(token))
+            .await;
+        Ok(())
+    }
+}
+
+fn parse_payload(payload: &[u8]) -> AnyaResult<LoadedArchive> {
+    let corrupt = || AnyaError::new(ErrorCode::StorageFailure, "backup payload is corrupt");
+    let len_bytes: [u8; 4] = payload
+        .get(..4)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(corrupt)?;
+    let manifest_len = u32::from_be_bytes(len_bytes) as usize;
+    let manifest_bytes = payload.get(4..4 + manifest_len).ok_or_else(corrupt)?;
+    let manifest: BackupManifest = serde_json::from_slice(manifest_bytes)?;
+    let data = &payload[4 + manifest_len..];
+
+    let mut entries = Vec::with_capacity(manifest.entries.len());
+    for meta in &manifest.entries {
+        let start = usize::try_from(meta.offset).map_err(|_| corrupt())?;
+        let end = start + usize::try_from(meta.len).map_err(|_| corrupt())?;
+        let value = data.get(start..end).ok_or_else(corrupt)?;
+        if to_hex(&sha256(value)) != meta.sha256 {
+            return Err(AnyaError::new(
+                ErrorCode::StorageFailure,
+                format!("hash mismatch for {}/{}", meta.source, meta.key),
+            ));
+        }
+        entries.push((meta.clone(), value.to_vec()));
+    }
+    Ok((manifest, entries))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Check whether `path` looks like an Anya backup archive without decrypting it
+pub async fn is_backup_archive(path: &Path) -> bool {
+    tokio::fs::read(path)
+        .await
+        .is_ok_and(|bytes| bytes.starts_with(MAGIC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MemorySource {
+        name: &'static str,
+        data: std::sync::Mutex<BTreeMap<String, Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl BackupSource for MemorySource {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn export(&self) -> AnyaResult<BTreeMap<String, Vec<u8>>> {
+            Ok(self.data.lock().unwrap().clone())
+        }
+
+        async fn import(&self, entries: BTreeMap<String, Vec<u8>>) -> AnyaResult<()> {
+            *self.data.lock().unwrap() = entries;
+            Ok(())
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("anya-backup-{}-{}", name, now_ms()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_incremental_backup_and_restore() {
+        let wallet = Arc::new(MemorySource {
+            name: "wallet",
+            data: std::sync::Mutex::new(BTreeMap::from([
+                ("a".to_string(), b"one".to_vec()),
+                ("b".to_string(), b"two".to_vec()),
+            ])),
+        });
+        let config = BackupConfig {
+            directory: temp_dir("incr"),
+            ..BackupConfig::default()
+        };
+        let mut manager = BackupManager::new(config, "correct horse");
+        manager.add_source(wallet.clone());
+
+        let full = manager.backup(BackupKind::Full).await.unwrap();
+        assert_eq!(full.entries.len(), 2);
+
+        {
+            let mut data = wallet.data.lock().unwrap();
+            data.remove("a");
+            data.insert("c".to_string(), b"three".to_vec());
+        }
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let incr = manager.backup(BackupKind::Incremental).await.unwrap();
+        assert_eq!(incr.parent.as_deref(), Some(full.id.as_str()));
+        assert_eq!(incr.entries.len(), 1);
+        assert_eq!(incr.deleted, vec![("wallet".to_string(), "a".to_string())]);
+
+        wallet.data.lock().unwrap().clear();
+        manager.restore(&incr.id).await.unwrap();
+        let restored = wallet.data.lock().unwrap().clone();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored["c"], b"three");
+        assert_eq!(manager.list().await.unwrap(), vec![full.id, incr.id]);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_passphrase_and_tampering_rejected() {
+        let dir = temp_dir("tamper");
+        let config = BackupConfig {
+            directory: dir.clone(),
+            ..BackupConfig::default()
+        };
+        let mut manager = BackupManager::new(config.clone(), "secret");
+        manager.add_source(Arc::new(MemorySource {
+            name: "dao",
+            data: std::sync::Mutex::new(BTreeMap::from([("p1".to_string(), vec![1, 2, 3])])),
+        }));
+        let manifest = manager.backup(BackupKind::Full).await.unwrap();
+        assert!(manager.verify(&manifest.id).await.is_ok());
+
+        let other = BackupManager::new(config, "not the secret");
+        let err = other.verify(&manifest.id).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+
+        let path = dir.join(format!("{}.{}", manifest.id, ARCHIVE_EXTENSION));
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+        assert!(manager.verify(&manifest.id).await.is_err());
+        assert!(is_backup_archive(&path).await);
+    }
+}
@@ -0,0 +1,26 @@
+//! `anya-cli`: operate an Anya node and its wallets over RPC
+
+use std::process::ExitCode;
+
+use anya_core::cli::{self, Cli, HttpRpcClient};
+use clap::Parser;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = Cli::parse();
+    let format = args.format();
+    let result = match HttpRpcClient::new(&args.rpc_url, args.token.clone()) {
+        Ok(rpc) => cli::run(&args, &rpc).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", cli::render_error(&err, format));
+            ExitCode::FAILURE
+        }
+    }
+}
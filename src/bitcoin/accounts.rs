@@ -0,0 +1,631 @@
+//! HD wallet accounts
+//!
+//! A wallet holds any number of accounts per script type, each derived at
+//! the standard path for its type — BIP-44 (`m/44'/coin'/n'`, P2PKH), BIP-84
+//! (`m/84'/coin'/n'`, P2WPKH), or BIP-86 (`m/86'/coin'/n'`, P2TR key path).
+//! Only the account xpub is stored, so the [`AccountManager`] works the same
+//! for hot wallets, the mobile app, and watch-only coordinators.
+//!
+//! Address discovery follows BIP-44: each chain is scanned until
+//! [`Account::gap_limit`] consecutive unused addresses are found, and a new
+//! account of a type can only be created once the previous one has been used.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use ::bitcoin::bip32::{
+    ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint, KeySource,
+};
+use ::bitcoin::secp256k1::{Secp256k1, Verification};
+use ::bitcoin::{Address, Network, PublicKey, Script};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "wallet_accounts";
+const ACCOUNT_PREFIX: &str = "account/";
+
+/// Default number of consecutive unused addresses scanned per chain
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Script type of an account, which fixes its BIP-43 purpose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptType {
+    /// BIP-44 legacy P2PKH
+    Legacy,
+    /// BIP-84 native segwit P2WPKH
+    NativeSegwit,
+    /// BIP-86 single-key taproot
+    Taproot,
+}
+
+impl ScriptType {
+    /// BIP-43 purpose field
+    pub const fn purpose(self) -> u32 {
+        match self {
+            Self::Legacy => 44,
+            Self::NativeSegwit => 84,
+            Self::Taproot => 86,
+        }
+    }
+
+    /// Weight of an input spending this script type with a typical signature
+    pub const fn input_weight(self) -> u64 {
+        match self {
+            // 148 non-witness bytes
+            Self::Legacy => 592,
+            // 41 non-witness bytes plus a 108-byte signature and key witness
+            Self::NativeSegwit => 272,
+            // 41 non-witness bytes plus a 66-byte schnorr signature witness
+            Self::Taproot => 230,
+        }
+    }
+
+    /// Length of the output script paying this script type
+    pub const fn output_script_len(self) -> usize {
+        match self {
+            Self::Legacy => 25,
+            Self::NativeSegwit => 22,
+            Self::Taproot => 34,
+        }
+    }
+
+    /// Script type of a single-key output descriptor such as `wpkh(...)`
+    pub fn from_descriptor(descriptor: &str) -> AnyaResult<Self> {
+        let descriptor = descriptor.trim();
+        [Self::Legacy, Self::NativeSegwit, Self::Taproot]
+            .into_iter()
+            .find(|t| {
+                descriptor
+                    .strip_prefix(t.descriptor_fn())
+                    .is_some_and(|rest| rest.starts_with('('))
+            })
+            .ok_or_else(|| {
+                AnyaError::invalid_input(format!("unsupported descriptor: {}", descriptor))
+            })
+    }
+
+    /// Output descriptor function wrapping the key
+    const fn descriptor_fn(self) -> &'static str {
+        match self {
+            Self::Legacy => "pkh",
+            Self::NativeSegwit => "wpkh",
+            Self::Taproot => "tr",
+        }
+    }
+}
+
+/// External (receive) or internal (change) chain of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyChain {
+    /// Receive addresses, `.../0/i`
+    External,
+    /// Change addresses, `.../1/i`
+    Internal,
+}
+
+impl KeyChain {
+    const fn index(self) -> u32 {
+        match self {
+            Self::External => 0,
+            Self::Internal => 1,
+        }
+    }
+}
+
+/// Identifies an account within a wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct AccountId {
+    /// Script type
+    pub script_type: ScriptType,
+    /// Hardened account index
+    pub index: u32,
+}
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}h/{}h", self.script_type.purpose(), self.index)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct ChainState {
+    last_used: Option<u32>,
+    next_index: u32,
+}
+
+/// A single HD account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    /// Account identifier
+    pub id: AccountId,
+    /// User-visible label
+    pub label: String,
+    /// Consecutive unused addresses scanned per chain
+    pub gap_limit: u32,
+    /// Master key fingerprint
+    pub fingerprint: Fingerprint,
+    /// Path from the master key to the account key
+    pub path: DerivationPath,
+    /// Account-level extended public key
+    pub xpub: ExtendedPubKey,
+    chains: [ChainState; 2],
+}
+
+impl Account {
+    /// Address at `index` on `chain`
+    pub fn address<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        chain: KeyChain,
+        index: u32,
+    ) -> AnyaResult<Address> {
+        let key = self.public_key(secp, chain, index)?;
+        let network = self.xpub.network;
+        Ok(match self.id.script_type {
+            ScriptType::Legacy => Address::p2pkh(&PublicKey::new(key), network),
+            ScriptType::NativeSegwit => Address::p2wpkh(&PublicKey::new(key), network)?,
+            ScriptType::Taproot => Address::p2tr(secp, key.x_only_public_key().0, None, network),
+        })
+    }
+
+    /// Public key at `index` on `chain`
+    pub fn public_key<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        chain: KeyChain,
+        index: u32,
+    ) -> AnyaResult<::bitcoin::secp256k1::PublicKey> {
+        let path = [
+            ChildNumber::from_normal_idx(chain.index())?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        Ok(self.xpub.derive_pub(secp, &path)?.public_key)
+    }
+
+    /// Key origin of the key at `index` on `chain`, as recorded in PSBTs
+    pub fn key_source(&self, chain: KeyChain, index: u32) -> AnyaResult<KeySource> {
+        let path = self.path.extend([
+            ChildNumber::from_normal_idx(chain.index())?,
+            ChildNumber::from_normal_idx(index)?,
+        ]);
+        Ok((self.fingerprint, path))
+    }
+
+    /// Output descriptor for `chain`, e.g. `wpkh([fp/84h/0h/0h]xpub.../0/*)`
+    pub fn descriptor(&self, chain: KeyChain) -> String {
+        let origin = self.path.to_string().replace('\'', "h");
+        format!(
+            "{}([{}{}]{}/{}/*)",
+            self.id.script_type.descriptor_fn(),
+            self.fingerprint,
+            origin.trim_start_matches('m'),
+            self.xpub,
+            chain.index()
+        )
+    }
+
+    /// Indices that must be watched on `chain` to honour the gap limit
+    pub fn lookahead(&self, chain: KeyChain) -> std::ops::Range<u32> {
+        let state = self.chain(chain);
+        let end = state
+            .last_used
+            .map_or(0, |i| i + 1)
+            .max(state.next_index)
+            .saturating_add(self.gap_limit);
+        0..end
+    }
+
+    /// Chain and index of the key paying to `script`, searching the
+    /// gap-limit window of both chains
+    pub fn find_script<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        script: &Script,
+    ) -> AnyaResult<Option<(KeyChain, u32)>> {
+        for chain in [KeyChain::External, KeyChain::Internal] {
+            for index in self.lookahead(chain) {
+                if self
+                    .address(secp, chain, index)?
+                    .script_pubkey()
+                    .as_script()
+                    == script
+                {
+                    return Ok(Some((chain, index)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether any address of the account has been used
+    pub const fn is_used(&self) -> bool {
+        self.chains[0].last_used.is_some() || self.chains[1].last_used.is_some()
+    }
+
+    /// Whether the address at `index` on `chain` is at or below the highest
+    /// index seen on chain, so has likely received funds
+    pub const fn is_used_at(&self, chain: KeyChain, index: u32) -> bool {
+        match self.chain(chain).last_used {
+            Some(last) => index <= last,
+            None => false,
+        }
+    }
+
+    const fn chain(&self, chain: KeyChain) -> &ChainState {
+        &self.chains[chain.index() as usize]
+    }
+}
+
+/// Confirmed and unconfirmed funds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    /// Confirmed satoshis
+    pub confirmed_sat: u64,
+    /// Unconfirmed satoshis
+    pub unconfirmed_sat: u64,
+}
+
+impl Balance {
+    /// Confirmed plus unconfirmed
+    pub const fn total_sat(&self) -> u64 {
+        self.confirmed_sat + self.unconfirmed_sat
+    }
+}
+
+impl std::ops::Add for Balance {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            confirmed_sat: self.confirmed_sat + other.confirmed_sat,
+            unconfirmed_sat: self.unconfirmed_sat + other.unconfirmed_sat,
+        }
+    }
+}
+
+/// Balance of one account in a [`BalanceView`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountBalance {
+    /// Account identifier
+    pub id: AccountId,
+    /// Account label
+    pub label: String,
+    /// Account balance
+    pub balance: Balance,
+}
+
+/// Per-account balances plus the wallet total
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceView {
+    /// Accounts in id order
+    pub accounts: Vec<AccountBalance>,
+    /// Sum over all accounts
+    pub total: Balance,
+}
+
+/// Persistent set of accounts for one wallet
+pub struct AccountManager {
+    network: Network,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    accounts: RwLock<BTreeMap<AccountId, Account>>,
+}
+
+impl AccountManager {
+    /// Load the accounts stored in `storage`
+    pub async fn open(network: Network, storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let mut accounts = BTreeMap::new();
+        for (_, bytes) in storage.scan_prefix(&ns, ACCOUNT_PREFIX).await? {
+            let account: Account = serde_json::from_slice(&bytes)?;
+            accounts.insert(account.id, account);
+        }
+        Ok(Self {
+            network,
+            storage,
+            ns,
+            accounts: RwLock::new(accounts),
+        })
+    }
+
+    /// Derive and store the next account of `script_type` from the master key.
+    ///
+    /// Fails with [`ErrorCode::Conflict`] while the previous account of the
+    /// same type has never been used, as required by BIP-44.
+    pub async fn create_account(
+        &self,
+        master: &ExtendedPrivKey,
+        script_type: ScriptType,
+        label: impl Into<String>,
+    ) -> AnyaResult<Account> {
+        if master.network != self.network {
+            return Err(AnyaError::invalid_input(
+                "master key network does not match the wallet",
+            ));
+        }
+        let secp = Secp256k1::new();
+        let mut accounts = self.accounts.write().await;
+        let previous = accounts
+            .values()
+            .rev()
+            .find(|a| a.id.script_type == script_type);
+        if previous.is_some_and(|a| !a.is_used()) {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                "the previous account has no history yet",
+            ));
+        }
+        let index = previous.map_or(0, |a| a.id.index + 1);
+        let coin = u32::from(self.network != Network::Bitcoin);
+        let path = DerivationPath::from(vec![
+            ChildNumber::from_hardened_idx(script_type.purpose())?,
+            ChildNumber::from_hardened_idx(coin)?,
+            ChildNumber::from_hardened_idx(index)?,
+        ]);
+        let account_key = master.derive_priv(&secp, &path)?;
+        let account = Account {
+            id: AccountId { script_type, index },
+            label: label.into(),
+            gap_limit: DEFAULT_GAP_LIMIT,
+            fingerprint: master.fingerprint(&secp),
+            path,
+            xpub: ExtendedPubKey::from_priv(&secp, &account_key),
+            chains: [ChainState::default(); 2],
+        };
+        self.save(&account).await?;
+        accounts.insert(account.id, account.clone());
+        drop(accounts);
+        Ok(account)
+    }
+
+    /// All accounts in id order
+    pub async fn accounts(&self) -> Vec<Account> {
+        self.accounts.read().await.values().cloned().collect()
+    }
+
+    /// Look up an account
+    pub async fn account(&self, id: AccountId) -> AnyaResult<Account> {
+        self.accounts
+            .read()
+            .await
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| AnyaError::not_found(format!("account {}", id)))
+    }
+
+    /// Rename an account
+    pub async fn set_label(&self, id: AccountId, label: impl Into<String>) -> AnyaResult<()> {
+        let label = label.into();
+        self.update(id, |account| {
+            account.label = label;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Change how many unused addresses are scanned per chain
+    pub async fn set_gap_limit(&self, id: AccountId, gap_limit: u32) -> AnyaResult<()> {
+        if gap_limit == 0 {
+            return Err(AnyaError::invalid_input("gap limit must be positive"));
+        }
+        self.update(id, |account| {
+            account.gap_limit = gap_limit;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Hand out the next fresh address on `chain`, with its index.
+    ///
+    /// Refuses to hand out more than the gap limit of unused addresses, since
+    /// funds sent beyond the gap would not be found on restore.
+    pub async fn next_address(&self, id: AccountId, chain: KeyChain) -> AnyaResult<(u32, Address)> {
+        let mut issued = 0;
+        self.update(id, |account| {
+            let gap_limit = account.gap_limit;
+            let state = &mut account.chains[chain.index() as usize];
+            let first_unused = state.last_used.map_or(0, |i| i + 1);
+            if state.next_index >= first_unused.saturating_add(gap_limit) {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!("gap limit of {} unused addresses reached", gap_limit),
+                ));
+            }
+            issued = state.next_index.max(first_unused);
+            state.next_index = issued + 1;
+            Ok(())
+        })
+        .await?;
+        let address =
+            self.account(id)
+                .await?
+                .address(&Secp256k1::verification_only(), chain, issued)?;
+        Ok((issued, address))
+    }
+
+    /// Record that the address at `index` on `chain` has received funds
+    pub async fn mark_used(&self, id: AccountId, chain: KeyChain, index: u32) -> AnyaResult<()> {
+        self.update(id, |account| {
+            let state = &mut account.chains[chain.index() as usize];
+            state.last_used = Some(state.last_used.map_or(index, |i| i.max(index)));
+            state.next_index = state.next_index.max(index + 1);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Balances of every account and their sum
+    pub async fn balance_view<F>(&self, mut balance_of: F) -> BalanceView
+    where
+        F: FnMut(&Account) -> Balance,
+    {
+        let accounts: Vec<AccountBalance> = self
+            .accounts
+            .read()
+            .await
+            .values()
+            .map(|account| AccountBalance {
+                id: account.id,
+                label: account.label.clone(),
+                balance: balance_of(account),
+            })
+            .collect();
+        let total = accounts
+            .iter()
+            .fold(Balance::default(), |sum, a| sum + a.balance);
+        BalanceView { accounts, total }
+    }
+
+    async fn update<F>(&self, id: AccountId, change: F) -> AnyaResult<()>
+    where
+        F: FnOnce(&mut Account) -> AnyaResult<()>,
+    {
+        let mut accounts = self.accounts.write().await;
+        let account = accounts
+            .get_mut(&id)
+            .ok_or_else(|| AnyaError::not_found(format!("account {}", id)))?;
+        let mut updated = account.clone();
+        change(&mut updated)?;
+        self.save(&updated).await?;
+        *account = updated;
+        drop(accounts);
+        Ok(())
+    }
+
+    async fn save(&self, account: &Account) -> AnyaResult<()> {
+        let bytes = serde_json::to_vec(account)?;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", ACCOUNT_PREFIX, account.id),
+                &bytes,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    fn master() -> ExtendedPrivKey {
+        // BIP-84 test vector seed ("abandon ... about")
+        let seed = crate::utils::encoding::from_hex(
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4",
+        )
+        .unwrap();
+        ExtendedPrivKey::new_master(Network::Bitcoin, &seed).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_standard_paths_match_bip_vectors() {
+        let manager = AccountManager::open(Network::Bitcoin, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let master = master();
+        let segwit = manager
+            .create_account(&master, ScriptType::NativeSegwit, "Savings")
+            .await
+            .unwrap();
+        let taproot = manager
+            .create_account(&master, ScriptType::Taproot, "Taproot")
+            .await
+            .unwrap();
+        let secp = Secp256k1::verification_only();
+        assert_eq!(
+            segwit
+                .address(&secp, KeyChain::External, 0)
+                .unwrap()
+                .to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(
+            segwit
+                .address(&secp, KeyChain::Internal, 0)
+                .unwrap()
+                .to_string(),
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el"
+        );
+        assert_eq!(
+            taproot
+                .address(&secp, KeyChain::External, 0)
+                .unwrap()
+                .to_string(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+        assert!(segwit
+            .descriptor(KeyChain::External)
+            .starts_with("wpkh([73c5da0a/84h/0h/0h]xpub"));
+    }
+
+    #[tokio::test]
+    async fn test_account_creation_and_gap_limit_rules() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let manager = AccountManager::open(Network::Bitcoin, Arc::clone(&storage))
+            .await
+            .unwrap();
+        let master = master();
+        let first = manager
+            .create_account(&master, ScriptType::NativeSegwit, "Daily")
+            .await
+            .unwrap();
+        assert_eq!(
+            manager
+                .create_account(&master, ScriptType::NativeSegwit, "Second")
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::Conflict
+        );
+
+        manager.set_gap_limit(first.id, 2).await.unwrap();
+        manager
+            .next_address(first.id, KeyChain::External)
+            .await
+            .unwrap();
+        manager
+            .next_address(first.id, KeyChain::External)
+            .await
+            .unwrap();
+        assert!(manager
+            .next_address(first.id, KeyChain::External)
+            .await
+            .is_err());
+        manager
+            .mark_used(first.id, KeyChain::External, 1)
+            .await
+            .unwrap();
+        manager
+            .next_address(first.id, KeyChain::External)
+            .await
+            .unwrap();
+
+        let second = manager
+            .create_account(&master, ScriptType::NativeSegwit, "Second")
+            .await
+            .unwrap();
+        assert_eq!(second.id.index, 1);
+
+        let reopened = AccountManager::open(Network::Bitcoin, storage)
+            .await
+            .unwrap();
+        let reloaded = reopened.account(first.id).await.unwrap();
+        assert_eq!(reloaded.lookahead(KeyChain::External), 0..5);
+        let view = reopened
+            .balance_view(|a| Balance {
+                confirmed_sat: 1_000 * u64::from(a.id.index + 1),
+                unconfirmed_sat: 0,
+            })
+            .await;
+        assert_eq!(view.accounts.len(), 2);
+        assert_eq!(view.total.total_sat(), 3_000);
+    }
+}
@@ -0,0 +1,339 @@
+//! Payout batching
+//!
+//! Services paying many users (exchanges, mining pools, payroll) save most of
+//! the per-payment overhead by paying everyone in one transaction. The
+//! [`PaymentQueue`] accumulates payouts and releases them as a single
+//! shuffled transaction once the fee is a small enough share of the amount
+//! paid, the oldest payout has waited too long, or the batch is full.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::{FeeRate, TxOut};
+use serde::{Deserialize, Serialize};
+
+use super::accounts::AccountId;
+use super::builder::{BuiltTx, InputSelection, Recipient, TxBuilder, TxRequest};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::to_hex;
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "payment_queue";
+const PAYMENT_PREFIX: &str = "payment/";
+
+/// When a queued batch is released
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchPolicy {
+    /// Release once the fee is at most this fraction of the amount paid
+    pub max_fee_ratio: f64,
+    /// Release once the oldest payout has waited this long
+    pub max_wait: Duration,
+    /// Most payouts in one transaction
+    pub max_outputs: usize,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        Self {
+            max_fee_ratio: 0.01,
+            max_wait: Duration::from_secs(6 * 60 * 60),
+            max_outputs: 100,
+        }
+    }
+}
+
+/// A payout waiting in the queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedPayment {
+    /// Queue-assigned identifier
+    pub id: String,
+    /// Output to create
+    pub recipient: Recipient,
+    /// Unix time the payout was queued
+    pub queued_at: u64,
+}
+
+impl QueuedPayment {
+    fn key(&self) -> String {
+        // Zero-padded time keeps prefix scans in queue order
+        format!("{}{:020}-{}", PAYMENT_PREFIX, self.queued_at, self.id)
+    }
+}
+
+/// A released batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    /// Unsigned batch transaction
+    pub built: BuiltTx,
+    /// Payouts in the batch; payout `i` is paid by output
+    /// `built.recipient_vouts[i]`
+    pub payments: Vec<QueuedPayment>,
+}
+
+/// Persistent queue of payouts for one account
+pub struct PaymentQueue {
+    account: AccountId,
+    policy: BatchPolicy,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+}
+
+impl PaymentQueue {
+    /// Open the queue of `account` in `storage`
+    pub async fn open(
+        account: AccountId,
+        policy: BatchPolicy,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            account,
+            policy,
+            storage,
+            ns,
+        })
+    }
+
+    /// Queue a payout
+    pub async fn enqueue(&self, recipient: Recipient) -> AnyaResult<QueuedPayment> {
+        let dust = TxOut::minimal_non_dust(recipient.script_pubkey.clone()).value;
+        if recipient.amount_sat < dust {
+            return Err(AnyaError::invalid_input(format!(
+                "amount {} sat is below the dust limit of {} sat",
+                recipient.amount_sat, dust
+            )));
+        }
+        let payment = QueuedPayment {
+            id: to_hex(&rand::random::<[u8; 8]>()),
+            recipient,
+            queued_at: unix_now(),
+        };
+        self.save(&payment).await?;
+        Ok(payment)
+    }
+
+    /// Payouts waiting, oldest first
+    pub async fn pending(&self) -> AnyaResult<Vec<QueuedPayment>> {
+        self.storage
+            .scan_prefix(&self.ns, &self.prefix())
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(&bytes)?))
+            .collect()
+    }
+
+    /// Drop a queued payout, returning whether it was found
+    pub async fn cancel(&self, id: &str) -> AnyaResult<bool> {
+        match self.pending().await?.into_iter().find(|p| p.id == id) {
+            Some(payment) => self.storage.delete(&self.ns, &self.key(&payment)).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Put the payouts of a batch that was not broadcast back in the queue
+    pub async fn requeue(&self, batch: &Batch) -> AnyaResult<()> {
+        for payment in &batch.payments {
+            self.save(payment).await?;
+        }
+        Ok(())
+    }
+
+    /// Release the queued payouts as one transaction if the policy allows.
+    ///
+    /// Released payouts leave the queue; call [`Self::requeue`] if the batch
+    /// is abandoned before broadcast.
+    pub async fn flush(
+        &self,
+        builder: &TxBuilder<'_>,
+        fee_rate: FeeRate,
+    ) -> AnyaResult<Option<Batch>> {
+        self.flush_at(builder, fee_rate, unix_now(), false).await
+    }
+
+    /// Release the queued payouts now, regardless of policy
+    pub async fn flush_now(
+        &self,
+        builder: &TxBuilder<'_>,
+        fee_rate: FeeRate,
+    ) -> AnyaResult<Option<Batch>> {
+        self.flush_at(builder, fee_rate, unix_now(), true).await
+    }
+
+    async fn flush_at(
+        &self,
+        builder: &TxBuilder<'_>,
+        fee_rate: FeeRate,
+        now: u64,
+        force: bool,
+    ) -> AnyaResult<Option<Batch>> {
+        let mut payments = self.pending().await?;
+        let Some(oldest) = payments.first().map(|p| p.queued_at) else {
+            return Ok(None);
+        };
+        let full = payments.len() >= self.policy.max_outputs;
+        payments.truncate(self.policy.max_outputs);
+        let request = TxRequest {
+            account: self.account,
+            recipients: payments.iter().map(|p| p.recipient.clone()).collect(),
+            fee_rate,
+            inputs: InputSelection::Auto,
+            shuffle_outputs: true,
+        };
+
+        if !force && !full && now.saturating_sub(oldest) < self.policy.max_wait.as_secs() {
+            let fee = builder.estimate_fee(&request).await?;
+            let paid: u64 = request.recipients.iter().map(|r| r.amount_sat).sum();
+            #[allow(clippy::cast_precision_loss)]
+            let ratio = fee as f64 / paid as f64;
+            if ratio > self.policy.max_fee_ratio {
+                return Ok(None);
+            }
+        }
+
+        let built = builder.build(&request).await?;
+        for payment in &payments {
+            self.storage.delete(&self.ns, &self.key(payment)).await?;
+        }
+        Ok(Some(Batch { built, payments }))
+    }
+
+    fn prefix(&self) -> String {
+        format!("{}/", self.account)
+    }
+
+    fn key(&self, payment: &QueuedPayment) -> String {
+        format!("{}{}", self.prefix(), payment.key())
+    }
+
+    async fn save(&self, payment: &QueuedPayment) -> AnyaResult<()> {
+        let bytes = serde_json::to_vec(payment)?;
+        self.storage.put(&self.ns, &self.key(payment), &bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::{AccountManager, KeyChain, ScriptType};
+    use crate::bitcoin::coins::{CoinStore, Utxo};
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::secp256k1::Secp256k1;
+    use ::bitcoin::{Network, OutPoint, ScriptBuf, Txid, WPubkeyHash};
+
+    async fn funded() -> (AccountManager, CoinStore, PaymentQueue) {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let accounts = AccountManager::open(Network::Regtest, Arc::clone(&storage))
+            .await
+            .unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[3; 32]).unwrap();
+        let account = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "Payouts")
+            .await
+            .unwrap();
+        let coins = CoinStore::open(Arc::clone(&storage)).await.unwrap();
+        let script_pubkey = account
+            .address(&Secp256k1::verification_only(), KeyChain::External, 0)
+            .unwrap()
+            .script_pubkey();
+        coins
+            .insert(&Utxo {
+                outpoint: OutPoint::new(Txid::all_zeros(), 0),
+                txout: TxOut {
+                    value: 10_000_000,
+                    script_pubkey,
+                },
+                account: account.id,
+                chain: KeyChain::External,
+                index: 0,
+                height: Some(1),
+            })
+            .await
+            .unwrap();
+        let policy = BatchPolicy {
+            max_fee_ratio: 0.01,
+            max_wait: Duration::from_secs(3600),
+            max_outputs: 3,
+        };
+        let queue = PaymentQueue::open(account.id, policy, storage)
+            .await
+            .unwrap();
+        (accounts, coins, queue)
+    }
+
+    fn payout(n: u8, amount_sat: u64) -> Recipient {
+        Recipient {
+            script_pubkey: ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::from_byte_array([n; 20])),
+            amount_sat,
+            metadata: Some(format!("user-{}", n)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_waits_for_fee_efficiency() {
+        let (accounts, coins, queue) = funded().await;
+        let builder = TxBuilder::new(&accounts, &coins);
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(10);
+        let now = unix_now();
+
+        // ~1.4k sat fee on a 10k sat payout is far above 1%
+        queue.enqueue(payout(1, 10_000)).await.unwrap();
+        assert!(queue
+            .flush_at(&builder, fee_rate, now, false)
+            .await
+            .unwrap()
+            .is_none());
+        // Waiting past max_wait releases it anyway
+        let late = now + 3600;
+        assert!(queue
+            .flush_at(&builder, fee_rate, late, false)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(queue.pending().await.unwrap().is_empty());
+
+        // Enough value in the batch makes the fee share acceptable
+        queue.enqueue(payout(2, 100_000)).await.unwrap();
+        queue.enqueue(payout(3, 150_000)).await.unwrap();
+        let batch = queue
+            .flush_at(&builder, fee_rate, now, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.payments.len(), 2);
+        let tx = &batch.built.psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 3);
+        for (payment, vout) in batch.payments.iter().zip(&batch.built.recipient_vouts) {
+            assert_eq!(
+                tx.output[*vout].script_pubkey,
+                payment.recipient.script_pubkey
+            );
+            assert_eq!(tx.output[*vout].value, payment.recipient.amount_sat);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_batch_releases_oldest_and_requeue_restores() {
+        let (accounts, coins, queue) = funded().await;
+        let builder = TxBuilder::new(&accounts, &coins);
+        for n in 1..=4 {
+            queue.enqueue(payout(n, 1_000)).await.unwrap();
+        }
+        let cancelled = queue.enqueue(payout(9, 1_000)).await.unwrap();
+        assert!(queue.cancel(&cancelled.id).await.unwrap());
+
+        let batch = queue
+            .flush(&builder, FeeRate::from_sat_per_vb_unchecked(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.payments.len(), 3);
+        assert_eq!(queue.pending().await.unwrap().len(), 1);
+
+        queue.requeue(&batch).await.unwrap();
+        assert_eq!(queue.pending().await.unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,439 @@
+//! Transaction builder
+//!
+//! Builds unsigned PSBTs paying a set of recipients from one account. Inputs
+//! are chosen automatically from unlocked coins, given explicitly for coin
+//! control, or both. Inputs and change carry their key origins so any BIP-174
+//! signer, including the mobile air-gapped signer, can sign and verify change.
+
+use ::bitcoin::absolute::LockTime;
+use ::bitcoin::psbt::{Input, Output, Psbt};
+use ::bitcoin::secp256k1::{Secp256k1, VerifyOnly};
+use ::bitcoin::{
+    Address, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use super::accounts::{Account, AccountId, AccountManager, KeyChain, ScriptType};
+use super::coins::{select_coins, CoinStore, Selection, SelectionParams, Utxo};
+use super::fees::{output_weight, TxShape, SEGWIT_MARKER_WEIGHT};
+use super::labels::LabelStore;
+use super::privacy::{self, PrivacyContext, PrivacyReport};
+use crate::{AnyaError, AnyaResult};
+
+/// A payment output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recipient {
+    /// Destination script
+    pub script_pubkey: ScriptBuf,
+    /// Amount in satoshis
+    pub amount_sat: u64,
+    /// Caller reference carried through batching, e.g. a payout id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+}
+
+impl Recipient {
+    /// Pay `amount_sat` to `address`
+    pub fn new(address: &Address, amount_sat: u64) -> Self {
+        Self {
+            script_pubkey: address.script_pubkey(),
+            amount_sat,
+            metadata: None,
+        }
+    }
+}
+
+/// How inputs are chosen
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "mode", content = "outpoints")]
+pub enum InputSelection {
+    /// Pick from the account's unlocked coins
+    #[default]
+    Auto,
+    /// Spend exactly these coins
+    Manual(Vec<OutPoint>),
+    /// Spend these coins, adding more automatically if needed
+    Include(Vec<OutPoint>),
+}
+
+/// A transaction to build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRequest {
+    /// Account paying
+    pub account: AccountId,
+    /// Outputs to create
+    pub recipients: Vec<Recipient>,
+    /// Fee rate to pay
+    pub fee_rate: FeeRate,
+    /// Input selection
+    #[serde(default)]
+    pub inputs: InputSelection,
+    /// Randomize output order so change cannot be spotted by position
+    #[serde(default)]
+    pub shuffle_outputs: bool,
+}
+
+/// An unsigned transaction ready for signing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuiltTx {
+    /// Unsigned PSBT
+    pub psbt: Psbt,
+    /// Fee paid in satoshis
+    pub fee_sat: u64,
+    /// Index of the change output, if one was added
+    pub change_vout: Option<usize>,
+    /// Output index of each recipient, in request order
+    pub recipient_vouts: Vec<usize>,
+}
+
+/// Builds PSBTs from an account's coins
+pub struct TxBuilder<'a> {
+    accounts: &'a AccountManager,
+    coins: &'a CoinStore,
+    secp: Secp256k1<VerifyOnly>,
+}
+
+impl<'a> TxBuilder<'a> {
+    /// Builder over the given accounts and coins
+    pub fn new(accounts: &'a AccountManager, coins: &'a CoinStore) -> Self {
+        Self {
+            accounts,
+            coins,
+            secp: Secp256k1::verification_only(),
+        }
+    }
+
+    /// Accounts the builder spends from
+    pub(crate) const fn accounts(&self) -> &AccountManager {
+        self.accounts
+    }
+
+    /// Fee `request` would pay, without reserving a change address
+    pub async fn estimate_fee(&self, request: &TxRequest) -> AnyaResult<u64> {
+        Ok(self.plan(request, &[], false).await?.1.fee_sat)
+    }
+
+    /// Select inputs and build the unsigned PSBT for `request`
+    pub async fn build(&self, request: &TxRequest) -> AnyaResult<BuiltTx> {
+        let (account, selection) = self.plan(request, &[], false).await?;
+        self.assemble(request, account, selection).await
+    }
+
+    /// Review `built`, made from `request`, for privacy leaks before it is
+    /// signed; `labels` lets the review spot merged sources
+    pub async fn review_privacy(
+        &self,
+        request: &TxRequest,
+        built: &BuiltTx,
+        labels: Option<&LabelStore>,
+    ) -> AnyaResult<PrivacyReport> {
+        let context = PrivacyContext::gather(self.accounts, request.account, labels, built).await?;
+        Ok(privacy::analyze(built, &context))
+    }
+
+    /// Plan a transaction that must spend `forced` in addition to the
+    /// request's own inputs, optionally adding only confirmed coins.
+    ///
+    /// Used for replacements and CPFP children, whose forced inputs are
+    /// unconfirmed and may no longer be in the coin store. A request without
+    /// recipients sweeps everything to change.
+    pub(crate) async fn plan(
+        &self,
+        request: &TxRequest,
+        forced: &[Utxo],
+        confirmed_only: bool,
+    ) -> AnyaResult<(Account, Selection)> {
+        if request.recipients.is_empty() && forced.is_empty() {
+            return Err(AnyaError::invalid_input("no recipients"));
+        }
+        for recipient in &request.recipients {
+            let dust = TxOut::minimal_non_dust(recipient.script_pubkey.clone()).value;
+            if recipient.amount_sat < dust {
+                return Err(AnyaError::invalid_input(format!(
+                    "amount {} sat is below the dust limit of {} sat",
+                    recipient.amount_sat, dust
+                )));
+            }
+        }
+        if matches!(request.inputs, InputSelection::Manual(ref o) if o.is_empty()) {
+            return Err(AnyaError::invalid_input("no inputs selected"));
+        }
+        let account = self.accounts.account(request.account).await?;
+        let (mut required, candidates) = match &request.inputs {
+            InputSelection::Auto => (Vec::new(), self.coins.spendable(account.id).await?),
+            InputSelection::Manual(outpoints) => {
+                (self.coins.resolve(account.id, outpoints).await?, Vec::new())
+            }
+            InputSelection::Include(outpoints) => {
+                let required = self.coins.resolve(account.id, outpoints).await?;
+                let candidates = self
+                    .coins
+                    .spendable(account.id)
+                    .await?
+                    .into_iter()
+                    .filter(|u| !outpoints.contains(&u.outpoint))
+                    .collect();
+                (required, candidates)
+            }
+        };
+        required.extend(forced.iter().cloned());
+        let candidates = candidates
+            .into_iter()
+            .filter(|u| !forced.iter().any(|f| f.outpoint == u.outpoint))
+            .filter(|u| !confirmed_only || u.height.is_some())
+            .collect();
+
+        let change_script = account
+            .address(&self.secp, KeyChain::Internal, 0)?
+            .script_pubkey();
+        let shape = TxShape {
+            inputs: Vec::new(),
+            output_script_lens: request
+                .recipients
+                .iter()
+                .map(|r| r.script_pubkey.len())
+                .collect(),
+        };
+        let mut base_weight = shape.weight();
+        if account.id.script_type != ScriptType::Legacy {
+            base_weight += SEGWIT_MARKER_WEIGHT;
+        }
+        let params = SelectionParams {
+            target_sat: request.recipients.iter().map(|r| r.amount_sat).sum(),
+            base_weight,
+            change_weight: output_weight(change_script.len()),
+            change_dust_sat: TxOut::minimal_non_dust(change_script).value,
+            fee_rate: request.fee_rate,
+        };
+        let selection = select_coins(required, candidates, &params)?;
+        if request.recipients.is_empty() && selection.change_sat.is_none() {
+            return Err(AnyaError::invalid_input(
+                "inputs are too small to pay for their own spend",
+            ));
+        }
+        Ok((account, selection))
+    }
+
+    /// Turn a planned selection into the unsigned PSBT
+    pub(crate) async fn assemble(
+        &self,
+        request: &TxRequest,
+        account: Account,
+        selection: Selection,
+    ) -> AnyaResult<BuiltTx> {
+        // (recipient index, output), with change last
+        let mut outputs: Vec<(Option<usize>, TxOut)> = request
+            .recipients
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let txout = TxOut {
+                    value: r.amount_sat,
+                    script_pubkey: r.script_pubkey.clone(),
+                };
+                (Some(i), txout)
+            })
+            .collect();
+        let change_index = match selection.change_sat {
+            Some(change_sat) => {
+                let (index, address) = self
+                    .accounts
+                    .next_address(account.id, KeyChain::Internal)
+                    .await?;
+                let txout = TxOut {
+                    value: change_sat,
+                    script_pubkey: address.script_pubkey(),
+                };
+                outputs.push((None, txout));
+                Some(index)
+            }
+            None => None,
+        };
+        if request.shuffle_outputs {
+            outputs.shuffle(&mut rand::thread_rng());
+        }
+        let mut recipient_vouts = vec![0; request.recipients.len()];
+        let mut change_vout = None;
+        for (vout, (recipient, _)) in outputs.iter().enumerate() {
+            match recipient {
+                Some(i) => recipient_vouts[*i] = vout,
+                None => change_vout = Some(vout),
+            }
+        }
+
+        let unsigned = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: selection
+                .inputs
+                .iter()
+                .map(|u| TxIn {
+                    previous_output: u.outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: outputs.into_iter().map(|(_, txout)| txout).collect(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(unsigned)?;
+        for (input, utxo) in psbt.inputs.iter_mut().zip(&selection.inputs) {
+            self.describe_input(&account, input, utxo)?;
+        }
+        if let (Some(vout), Some(index)) = (change_vout, change_index) {
+            self.describe_output(&account, &mut psbt.outputs[vout], index)?;
+        }
+        Ok(BuiltTx {
+            psbt,
+            fee_sat: selection.fee_sat,
+            change_vout,
+            recipient_vouts,
+        })
+    }
+
+    fn describe_input(&self, account: &Account, input: &mut Input, utxo: &Utxo) -> AnyaResult<()> {
+        input.witness_utxo = Some(utxo.txout.clone());
+        let key = account.public_key(&self.secp, utxo.chain, utxo.index)?;
+        let source = account.key_source(utxo.chain, utxo.index)?;
+        if account.id.script_type == ScriptType::Taproot {
+            let xonly = key.x_only_public_key().0;
+            input.tap_internal_key = Some(xonly);
+            input.tap_key_origins.insert(xonly, (Vec::new(), source));
+        } else {
+            input.bip32_derivation.insert(key, source);
+        }
+        Ok(())
+    }
+
+    fn describe_output(
+        &self,
+        account: &Account,
+        output: &mut Output,
+        index: u32,
+    ) -> AnyaResult<()> {
+        let key = account.public_key(&self.secp, KeyChain::Internal, index)?;
+        let source = account.key_source(KeyChain::Internal, index)?;
+        if account.id.script_type == ScriptType::Taproot {
+            let xonly = key.x_only_public_key().0;
+            output.tap_internal_key = Some(xonly);
+            output.tap_key_origins.insert(xonly, (Vec::new(), source));
+        } else {
+            output.bip32_derivation.insert(key, source);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::coins::CoinLock;
+    use crate::storage::memory::MemoryBackend;
+    use crate::ErrorCode;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{Network, Txid};
+    use std::sync::Arc;
+
+    async fn wallet() -> (AccountManager, CoinStore, ExtendedPrivKey, AccountId) {
+        let storage: Arc<dyn crate::storage::StorageBackend> = Arc::new(MemoryBackend::new());
+        let accounts = AccountManager::open(Network::Regtest, Arc::clone(&storage))
+            .await
+            .unwrap();
+        let coins = CoinStore::open(storage).await.unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let account = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "Main")
+            .await
+            .unwrap();
+        let secp = Secp256k1::verification_only();
+        for (vout, value) in [(0, 40_000), (1, 70_000)] {
+            coins
+                .insert(&Utxo {
+                    outpoint: OutPoint::new(Txid::all_zeros(), vout),
+                    txout: TxOut {
+                        value,
+                        script_pubkey: account
+                            .address(&secp, KeyChain::External, vout)
+                            .unwrap()
+                            .script_pubkey(),
+                    },
+                    account: account.id,
+                    chain: KeyChain::External,
+                    index: vout,
+                    height: Some(1),
+                })
+                .await
+                .unwrap();
+        }
+        (accounts, coins, master, account.id)
+    }
+
+    fn payment(account: AccountId, amount_sat: u64, inputs: InputSelection) -> TxRequest {
+        TxRequest {
+            account,
+            recipients: vec![Recipient {
+                script_pubkey: ScriptBuf::new_v0_p2wpkh(&::bitcoin::WPubkeyHash::all_zeros()),
+                amount_sat,
+                metadata: None,
+            }],
+            fee_rate: FeeRate::from_sat_per_vb_unchecked(5),
+            inputs,
+            shuffle_outputs: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_signs_with_change_verified() {
+        let (accounts, coins, master, id) = wallet().await;
+        let builder = TxBuilder::new(&accounts, &coins);
+        let mut built = builder
+            .build(&payment(id, 50_000, InputSelection::Auto))
+            .await
+            .unwrap();
+        let tx = &built.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output.vout, 1);
+        let vout = built.change_vout.unwrap();
+        assert_eq!(tx.output[vout].value + 50_000 + built.fee_sat, 70_000);
+        assert_eq!(built.psbt.outputs[vout].bip32_derivation.len(), 1);
+
+        let secp = Secp256k1::new();
+        let signed = built.psbt.sign(&master, &secp).unwrap();
+        assert_eq!(signed.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_coin_control_respects_locks() {
+        let (accounts, coins, _, id) = wallet().await;
+        let small = OutPoint::new(Txid::all_zeros(), 0);
+        let large = OutPoint::new(Txid::all_zeros(), 1);
+        let builder = TxBuilder::new(&accounts, &coins);
+
+        let built = builder
+            .build(&payment(id, 20_000, InputSelection::Manual(vec![small])))
+            .await
+            .unwrap();
+        assert_eq!(built.psbt.unsigned_tx.input[0].previous_output, small);
+
+        let built = builder
+            .build(&payment(id, 90_000, InputSelection::Include(vec![small])))
+            .await
+            .unwrap();
+        assert_eq!(built.psbt.unsigned_tx.input.len(), 2);
+
+        coins.freeze(&large).await.unwrap();
+        assert_eq!(coins.lock(&large).await.unwrap(), Some(CoinLock::Frozen));
+        let err = builder
+            .build(&payment(id, 50_000, InputSelection::Auto))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InsufficientFunds);
+        let err = builder
+            .build(&payment(id, 50_000, InputSelection::Manual(vec![large])))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+    }
+}
@@ -0,0 +1,448 @@
+//! Fee bumping for stuck transactions
+//!
+//! [`FeeBumper::bump_fee`] picks the strategy the wallet can actually carry
+//! out. When the wallet signed every input and the transaction signals
+//! BIP-125 replaceability it is replaced (RBF): the same payments are kept,
+//! change absorbs the extra fee, and confirmed coins are added if change runs
+//! out. Otherwise, if the transaction pays the wallet — an incoming payment
+//! or change — a child spending that output pays for the whole package
+//! (CPFP).
+
+use ::bitcoin::{FeeRate, OutPoint, Transaction, TxOut};
+use serde::{Deserialize, Serialize};
+
+use super::accounts::{AccountId, KeyChain};
+use super::builder::{BuiltTx, InputSelection, Recipient, TxBuilder, TxRequest};
+use super::coins::Utxo;
+use super::fees::{fee_for, FeeEstimator};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Fee rate increase per vbyte BIP-125 requires of a replacement
+const INCREMENTAL_RELAY_SAT_KWU: u64 = 250;
+/// Planning rounds allowed to settle a fee whose size depends on weight
+const MAX_ROUNDS: usize = 3;
+
+/// A broadcast transaction that is not confirming
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StuckTx {
+    /// The transaction as broadcast
+    pub tx: Transaction,
+    /// Outputs spent by each input, in input order
+    pub prevouts: Vec<TxOut>,
+}
+
+impl StuckTx {
+    /// Absolute fee paid
+    pub fn fee_sat(&self) -> AnyaResult<u64> {
+        if self.prevouts.len() != self.tx.input.len() {
+            return Err(AnyaError::invalid_input(
+                "a prevout is required for every input",
+            ));
+        }
+        let spent: u64 = self.prevouts.iter().map(|o| o.value).sum();
+        let paid: u64 = self.tx.output.iter().map(|o| o.value).sum();
+        spent
+            .checked_sub(paid)
+            .ok_or_else(|| AnyaError::invalid_input("outputs exceed inputs"))
+    }
+}
+
+/// Fee rate a bump aims for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BumpTarget {
+    /// Explicit fee rate
+    FeeRate(FeeRate),
+    /// Whatever the fee estimator expects to confirm within this many blocks
+    Blocks(u16),
+}
+
+/// How a transaction is bumped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BumpStrategy {
+    /// Replace it with a higher-fee version
+    Rbf,
+    /// Spend its output `vout` with a high-fee child
+    Cpfp {
+        /// Output of the stuck transaction spent by the child
+        vout: u32,
+    },
+}
+
+/// An unsigned bump transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeBump {
+    /// Strategy used
+    pub strategy: BumpStrategy,
+    /// Replacement or child transaction
+    pub built: BuiltTx,
+    /// Effective rate of the replacement, or of parent and child together
+    pub fee_rate: FeeRate,
+}
+
+/// Wallet key controlling a script
+type Owner = (AccountId, KeyChain, u32);
+
+/// Builds RBF replacements and CPFP children
+pub struct FeeBumper<'a> {
+    builder: &'a TxBuilder<'a>,
+    estimator: Option<&'a dyn FeeEstimator>,
+}
+
+impl<'a> FeeBumper<'a> {
+    /// Bumper building through `builder`, resolving block targets with
+    /// `estimator`
+    pub const fn new(builder: &'a TxBuilder<'a>, estimator: Option<&'a dyn FeeEstimator>) -> Self {
+        Self { builder, estimator }
+    }
+
+    /// Decide how `stuck` can be bumped
+    pub async fn analyze(&self, stuck: &StuckTx) -> AnyaResult<BumpStrategy> {
+        stuck.fee_sat()?;
+        let mut input_owners = Vec::with_capacity(stuck.prevouts.len());
+        for prevout in &stuck.prevouts {
+            input_owners.push(self.owner(prevout).await?);
+        }
+        let first = input_owners.first().copied().flatten();
+        let single_account = input_owners
+            .iter()
+            .all(|o| o.map(|(id, _, _)| id) == first.map(|(id, _, _)| id));
+        if stuck.tx.is_explicitly_rbf() && first.is_some() && single_account {
+            return Ok(BumpStrategy::Rbf);
+        }
+
+        let mut owned = Vec::new();
+        for (vout, output) in (0u32..).zip(&stuck.tx.output) {
+            if self.owner(output).await?.is_some() {
+                owned.push((vout, output.value));
+            }
+        }
+        owned
+            .into_iter()
+            .max_by_key(|&(_, value)| value)
+            .map(|(vout, _)| BumpStrategy::Cpfp { vout })
+            .ok_or_else(|| {
+                AnyaError::invalid_input("transaction has no wallet inputs or outputs to bump with")
+            })
+    }
+
+    /// Build a transaction getting `stuck` confirmed at `target`
+    pub async fn bump_fee(&self, stuck: &StuckTx, target: BumpTarget) -> AnyaResult<FeeBump> {
+        let target = match target {
+            BumpTarget::FeeRate(rate) => rate,
+            BumpTarget::Blocks(blocks) => {
+                let estimator = self.estimator.ok_or_else(|| {
+                    AnyaError::new(ErrorCode::Config, "no fee estimator configured")
+                })?;
+                estimator.estimate(blocks).await?
+            }
+        };
+        let fee = stuck.fee_sat()?;
+        let weight = stuck.tx.weight().to_wu();
+        if fee * 1000 >= target.to_sat_per_kwu() * weight {
+            return Err(AnyaError::invalid_input(format!(
+                "transaction already pays at least {} sat/vB",
+                target.to_sat_per_vb_floor()
+            )));
+        }
+        match self.analyze(stuck).await? {
+            BumpStrategy::Rbf => self.replace(stuck, fee, target).await,
+            BumpStrategy::Cpfp { vout } => self.child(stuck, fee, vout, target).await,
+        }
+    }
+
+    async fn replace(&self, stuck: &StuckTx, fee: u64, target: FeeRate) -> AnyaResult<FeeBump> {
+        let mut forced = Vec::with_capacity(stuck.tx.input.len());
+        for (input, prevout) in stuck.tx.input.iter().zip(&stuck.prevouts) {
+            let owner = self.owner(prevout).await?;
+            forced.push(utxo(input.previous_output, prevout, owner)?);
+        }
+        let account = forced[0].account;
+
+        let mut recipients = Vec::new();
+        for output in &stuck.tx.output {
+            let is_change = matches!(
+                self.owner(output).await?,
+                Some((id, KeyChain::Internal, _)) if id == account
+            );
+            if !is_change {
+                recipients.push(Recipient {
+                    script_pubkey: output.script_pubkey.clone(),
+                    amount_sat: output.value,
+                    metadata: None,
+                });
+            }
+        }
+
+        let current_kwu = fee * 1000 / stuck.tx.weight().to_wu();
+        let mut request = TxRequest {
+            account,
+            recipients,
+            fee_rate: FeeRate::from_sat_per_kwu(
+                target
+                    .to_sat_per_kwu()
+                    .max(current_kwu + INCREMENTAL_RELAY_SAT_KWU),
+            ),
+            inputs: InputSelection::Auto,
+            shuffle_outputs: false,
+        };
+        for _ in 0..MAX_ROUNDS {
+            let (account, selection) = self.builder.plan(&request, &forced, true).await?;
+            // BIP-125 rule 4: pay for the replacement's own relay on top
+            let min_fee = fee
+                + fee_for(
+                    FeeRate::from_sat_per_kwu(INCREMENTAL_RELAY_SAT_KWU),
+                    selection.weight,
+                );
+            if selection.fee_sat >= min_fee {
+                let fee_rate =
+                    FeeRate::from_sat_per_kwu(selection.fee_sat * 1000 / selection.weight);
+                let built = self.builder.assemble(&request, account, selection).await?;
+                return Ok(FeeBump {
+                    strategy: BumpStrategy::Rbf,
+                    built,
+                    fee_rate,
+                });
+            }
+            request.fee_rate =
+                FeeRate::from_sat_per_kwu(ceil_div(min_fee * 1000, selection.weight));
+        }
+        Err(AnyaError::new(
+            ErrorCode::Internal,
+            "replacement fee did not converge",
+        ))
+    }
+
+    async fn child(
+        &self,
+        stuck: &StuckTx,
+        parent_fee: u64,
+        vout: u32,
+        target: FeeRate,
+    ) -> AnyaResult<FeeBump> {
+        let output = &stuck.tx.output[vout as usize];
+        let owner = self.owner(output).await?;
+        let forced = [utxo(OutPoint::new(stuck.tx.txid(), vout), output, owner)?];
+        let parent_weight = stuck.tx.weight().to_wu();
+
+        let mut request = TxRequest {
+            account: forced[0].account,
+            recipients: Vec::new(),
+            fee_rate: target,
+            inputs: InputSelection::Auto,
+            shuffle_outputs: false,
+        };
+        for _ in 0..MAX_ROUNDS {
+            let (account, selection) = self.builder.plan(&request, &forced, true).await?;
+            let package_fee = fee_for(target, parent_weight + selection.weight);
+            let needed = package_fee.saturating_sub(parent_fee);
+            if selection.fee_sat >= needed {
+                let fee_rate = FeeRate::from_sat_per_kwu(
+                    (parent_fee + selection.fee_sat) * 1000 / (parent_weight + selection.weight),
+                );
+                let built = self.builder.assemble(&request, account, selection).await?;
+                return Ok(FeeBump {
+                    strategy: BumpStrategy::Cpfp { vout },
+                    built,
+                    fee_rate,
+                });
+            }
+            request.fee_rate = FeeRate::from_sat_per_kwu(ceil_div(needed * 1000, selection.weight));
+        }
+        Err(AnyaError::new(
+            ErrorCode::Internal,
+            "child fee did not converge",
+        ))
+    }
+
+    async fn owner(&self, output: &TxOut) -> AnyaResult<Option<Owner>> {
+        let secp = ::bitcoin::secp256k1::Secp256k1::verification_only();
+        for account in self.builder.accounts().accounts().await {
+            if let Some((chain, index)) = account.find_script(&secp, &output.script_pubkey)? {
+                return Ok(Some((account.id, chain, index)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn utxo(outpoint: OutPoint, txout: &TxOut, owner: Option<Owner>) -> AnyaResult<Utxo> {
+    let (account, chain, index) = owner
+        .ok_or_else(|| AnyaError::invalid_input(format!("{} is not a wallet output", outpoint)))?;
+    Ok(Utxo {
+        outpoint,
+        txout: txout.clone(),
+        account,
+        chain,
+        index,
+        height: None,
+    })
+}
+
+const fn ceil_div(n: u64, d: u64) -> u64 {
+    n.saturating_add(d - 1) / d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::{AccountManager, ScriptType};
+    use crate::bitcoin::coins::CoinStore;
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::StorageBackend;
+    use ::bitcoin::absolute::LockTime;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::secp256k1::Secp256k1;
+    use ::bitcoin::{Network, ScriptBuf, Sequence, TxIn, Txid, WPubkeyHash, Witness};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct Fixed(FeeRate);
+
+    #[async_trait]
+    impl FeeEstimator for Fixed {
+        async fn estimate(&self, _target_blocks: u16) -> AnyaResult<FeeRate> {
+            Ok(self.0)
+        }
+    }
+
+    async fn wallet() -> (AccountManager, CoinStore, AccountId) {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let accounts = AccountManager::open(Network::Regtest, Arc::clone(&storage))
+            .await
+            .unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[5; 32]).unwrap();
+        let account = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "Main")
+            .await
+            .unwrap();
+        let coins = CoinStore::open(storage).await.unwrap();
+        (accounts, coins, account.id)
+    }
+
+    fn external() -> ScriptBuf {
+        ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::from_byte_array([9; 20]))
+    }
+
+    #[tokio::test]
+    async fn test_rbf_keeps_payments_and_pays_more() {
+        let (accounts, coins, id) = wallet().await;
+        let account = accounts.account(id).await.unwrap();
+        let secp = Secp256k1::verification_only();
+        let funding = Utxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), 0),
+            txout: TxOut {
+                value: 100_000,
+                script_pubkey: account
+                    .address(&secp, KeyChain::External, 0)
+                    .unwrap()
+                    .script_pubkey(),
+            },
+            account: id,
+            chain: KeyChain::External,
+            index: 0,
+            height: Some(1),
+        };
+        coins.insert(&funding).await.unwrap();
+        let builder = TxBuilder::new(&accounts, &coins);
+        let original = builder
+            .build(&TxRequest {
+                account: id,
+                recipients: vec![Recipient {
+                    script_pubkey: external(),
+                    amount_sat: 30_000,
+                    metadata: None,
+                }],
+                fee_rate: FeeRate::from_sat_per_vb_unchecked(1),
+                inputs: InputSelection::Auto,
+                shuffle_outputs: false,
+            })
+            .await
+            .unwrap();
+        let stuck = StuckTx {
+            tx: original.psbt.unsigned_tx.clone(),
+            prevouts: vec![funding.txout.clone()],
+        };
+        let bumper = FeeBumper::new(&builder, None);
+        assert_eq!(bumper.analyze(&stuck).await.unwrap(), BumpStrategy::Rbf);
+
+        let target = FeeRate::from_sat_per_vb_unchecked(20);
+        let bump = bumper
+            .bump_fee(&stuck, BumpTarget::FeeRate(target))
+            .await
+            .unwrap();
+        let tx = &bump.built.psbt.unsigned_tx;
+        assert_eq!(tx.input[0].previous_output, funding.outpoint);
+        assert!(tx
+            .output
+            .iter()
+            .any(|o| o.script_pubkey == external() && o.value == 30_000));
+        assert!(bump.built.fee_sat > original.fee_sat);
+        assert!(bump.fee_rate >= target);
+        assert!(bumper
+            .bump_fee(&stuck, BumpTarget::Blocks(1))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_incoming_payment_is_bumped_with_cpfp() {
+        let (accounts, coins, id) = wallet().await;
+        let account = accounts.account(id).await.unwrap();
+        let secp = Secp256k1::verification_only();
+        let ours = account
+            .address(&secp, KeyChain::External, 0)
+            .unwrap()
+            .script_pubkey();
+        let parent = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 7),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value: 20_000,
+                    script_pubkey: external(),
+                },
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: ours,
+                },
+            ],
+        };
+        let stuck = StuckTx {
+            tx: parent,
+            prevouts: vec![TxOut {
+                value: 70_100,
+                script_pubkey: external(),
+            }],
+        };
+        let builder = TxBuilder::new(&accounts, &coins);
+        let target = FeeRate::from_sat_per_vb_unchecked(15);
+        let estimator = Fixed(target);
+        let bumper = FeeBumper::new(&builder, Some(&estimator));
+        assert_eq!(
+            bumper.analyze(&stuck).await.unwrap(),
+            BumpStrategy::Cpfp { vout: 1 }
+        );
+
+        let bump = bumper
+            .bump_fee(&stuck, BumpTarget::Blocks(2))
+            .await
+            .unwrap();
+        let child = &bump.built.psbt.unsigned_tx;
+        assert_eq!(
+            child.input[0].previous_output,
+            OutPoint::new(stuck.tx.txid(), 1)
+        );
+        assert_eq!(child.output.len(), 1);
+        assert!(bump.fee_rate >= target);
+        assert_eq!(child.output[0].value + bump.built.fee_sat, 50_000);
+    }
+}
@@ -0,0 +1,419 @@
+//! Seed backup ceremonies
+//!
+//! A [`SplitCeremony`] walks through creating a SLIP-39 backup: the shares
+//! of a [`SharePlan`] are shown one at a time, each holder writes theirs
+//! down and enters it back, and the ceremony only completes once every
+//! share has been re-entered correctly and a threshold subset has been
+//! shown to recover the seed. A [`RecoverySession`] collects shares in any
+//! order, rejecting ones from another backup as soon as they are entered,
+//! and reports which groups still need shares.
+//!
+//! Both flows work on word indices and convert to and from words with a
+//! [`Wordlist`] supplied by the caller: the CLI reads it from a file and the
+//! mobile apps bundle it. Seeds, shares and entered words are kept in
+//! [`Zeroizing`] buffers and wiped when the ceremony is dropped.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::bip32::{ExtendedPrivKey, Fingerprint};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::Network;
+use serde::{Deserialize, Serialize};
+
+use super::slip39::{self, GroupSpec, Share};
+use crate::utils::zeroize::Zeroizing;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Mnemonic wordlist: 1024 words for SLIP-39 or 2048 for BIP-39
+pub struct Wordlist {
+    words: Vec<String>,
+    index: HashMap<String, u16>,
+}
+
+impl Wordlist {
+    /// Parse a list with one word per line, in index order.
+    ///
+    /// Both standard lists are unique in their first four letters, so
+    /// entered words may be abbreviated to them.
+    pub fn parse(text: &str) -> AnyaResult<Self> {
+        let words: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        if words.len() != 1024 && words.len() != 2048 {
+            return Err(AnyaError::invalid_input(format!(
+                "wordlist has {} words, expected 1024 or 2048",
+                words.len()
+            )));
+        }
+        let mut index = HashMap::with_capacity(words.len() * 2);
+        for (i, word) in words.iter().enumerate() {
+            let position = u16::try_from(i).unwrap_or(u16::MAX);
+            let prefix: String = word.chars().take(4).collect();
+            if index.insert(prefix.clone(), position).is_some() {
+                return Err(AnyaError::invalid_input(format!(
+                    "wordlist prefix {:?} is not unique",
+                    prefix
+                )));
+            }
+            index.insert(word.clone(), position);
+        }
+        Ok(Self { words, index })
+    }
+
+    /// Number of words
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Whether the list is empty; a parsed list never is
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Words for `indices`, separated by spaces
+    pub fn encode(&self, indices: &[u16]) -> AnyaResult<Zeroizing<String>> {
+        let mut phrase = Zeroizing::new(String::with_capacity(indices.len() * 9));
+        for index in indices {
+            let word = self
+                .words
+                .get(usize::from(*index))
+                .ok_or_else(|| AnyaError::invalid_input("word index outside the wordlist"))?;
+            if !phrase.is_empty() {
+                phrase.push(' ');
+            }
+            phrase.push_str(word);
+        }
+        Ok(phrase)
+    }
+
+    /// Indices of the words in `phrase`
+    pub fn decode(&self, phrase: &str) -> AnyaResult<Zeroizing<Vec<u16>>> {
+        let mut indices = Zeroizing::new(Vec::new());
+        for (position, word) in phrase.split_whitespace().enumerate() {
+            let word = Zeroizing::new(word.to_lowercase());
+            let index = self.index.get(word.as_str()).ok_or_else(|| {
+                AnyaError::invalid_input(format!("word {} is not in the wordlist", position + 1))
+            })?;
+            indices.push(*index);
+        }
+        Ok(indices)
+    }
+}
+
+/// How a seed is split into shares
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharePlan {
+    /// Groups needed to recover the seed
+    pub group_threshold: u8,
+    /// Shape of each group
+    pub groups: Vec<GroupSpec>,
+    /// PBKDF2 cost exponent of the passphrase encryption
+    #[serde(default = "default_iteration_exponent")]
+    pub iteration_exponent: u8,
+}
+
+const fn default_iteration_exponent() -> u8 {
+    1
+}
+
+impl SharePlan {
+    /// Single group where `threshold` of `count` shares recover the seed
+    pub fn single(threshold: u8, count: u8) -> Self {
+        Self {
+            group_threshold: 1,
+            groups: vec![GroupSpec { threshold, count }],
+            iteration_exponent: default_iteration_exponent(),
+        }
+    }
+}
+
+/// Master key fingerprint of a seed, for comparing backups without
+/// revealing them
+pub fn seed_fingerprint(seed: &[u8]) -> AnyaResult<Fingerprint> {
+    let secp = Secp256k1::signing_only();
+    Ok(ExtendedPrivKey::new_master(Network::Bitcoin, seed)?.fingerprint(&secp))
+}
+
+/// Creating a SLIP-39 backup and confirming every share was recorded
+pub struct SplitCeremony {
+    seed: Zeroizing<Vec<u8>>,
+    passphrase: Zeroizing<String>,
+    shares: Vec<Vec<Share>>,
+    verified: Vec<Vec<bool>>,
+}
+
+impl SplitCeremony {
+    /// Split `seed` according to `plan`
+    pub fn start(seed: &[u8], passphrase: &str, plan: &SharePlan) -> AnyaResult<Self> {
+        let shares = slip39::split(
+            seed,
+            passphrase,
+            plan.group_threshold,
+            &plan.groups,
+            plan.iteration_exponent,
+        )?;
+        Ok(Self {
+            seed: Zeroizing::new(seed.to_vec()),
+            passphrase: Zeroizing::new(passphrase.to_string()),
+            verified: shares.iter().map(|g| vec![false; g.len()]).collect(),
+            shares,
+        })
+    }
+
+    /// Fingerprint of the seed being backed up
+    pub fn fingerprint(&self) -> AnyaResult<Fingerprint> {
+        seed_fingerprint(&self.seed)
+    }
+
+    /// Share `member` of `group`, to show to its holder
+    pub fn share(&self, group: usize, member: usize) -> AnyaResult<&Share> {
+        self.shares
+            .get(group)
+            .and_then(|g| g.get(member))
+            .ok_or_else(|| AnyaError::not_found(format!("no share {} in group {}", member, group)))
+    }
+
+    /// Shares not yet entered back, as `(group, member)`
+    pub fn pending(&self) -> Vec<(usize, usize)> {
+        self.verified
+            .iter()
+            .enumerate()
+            .flat_map(|(g, members)| {
+                members
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, done)| !**done)
+                    .map(move |(m, _)| (g, m))
+            })
+            .collect()
+    }
+
+    /// Check the holder's re-entry of share `member` of `group`
+    pub fn verify(&mut self, group: usize, member: usize, words: &[u16]) -> AnyaResult<()> {
+        let expected = self.share(group, member)?;
+        let entered = Share::from_words(words)?;
+        let matches = entered.is_compatible(expected)
+            && entered.group_index == expected.group_index
+            && entered.member_index == expected.member_index
+            && entered.member_threshold == expected.member_threshold
+            && ring::constant_time::verify_slices_are_equal(entered.value(), expected.value())
+                .is_ok();
+        if !matches {
+            return Err(AnyaError::invalid_input(format!(
+                "entered share does not match share {} of group {}",
+                member + 1,
+                group + 1
+            )));
+        }
+        self.verified[group][member] = true;
+        Ok(())
+    }
+
+    /// Complete the ceremony once every share is verified, recovering the
+    /// seed from a threshold subset as a final check
+    pub fn finish(self) -> AnyaResult<Fingerprint> {
+        let pending = self.pending().len();
+        if pending > 0 {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("{} shares have not been verified", pending),
+            ));
+        }
+        let group_threshold = usize::from(self.shares[0][0].group_threshold);
+        let subset: Vec<Share> = self
+            .shares
+            .iter()
+            .take(group_threshold)
+            .flat_map(|g| g.iter().take(usize::from(g[0].member_threshold)).cloned())
+            .collect();
+        let recovered = slip39::combine(&subset, &self.passphrase)?;
+        if ring::constant_time::verify_slices_are_equal(&recovered, &self.seed).is_err() {
+            return Err(AnyaError::new(
+                ErrorCode::Internal,
+                "shares do not recover the seed",
+            ));
+        }
+        self.fingerprint()
+    }
+}
+
+/// Shares collected for one group during recovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupProgress {
+    /// Group index
+    pub group_index: u8,
+    /// Distinct member shares entered
+    pub entered: u8,
+    /// Member shares needed
+    pub threshold: u8,
+}
+
+/// Recovery status shown between shares
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryProgress {
+    /// Groups with enough shares
+    pub groups_complete: u8,
+    /// Groups needed
+    pub group_threshold: u8,
+    /// Groups with at least one share entered
+    pub groups: Vec<GroupProgress>,
+}
+
+impl RecoveryProgress {
+    /// Whether enough shares are in to recover the seed
+    pub const fn is_complete(&self) -> bool {
+        self.groups_complete >= self.group_threshold
+    }
+}
+
+/// Collecting SLIP-39 shares until the seed can be recovered
+#[derive(Default)]
+pub struct RecoverySession {
+    shares: BTreeMap<(u8, u8), Share>,
+}
+
+impl RecoverySession {
+    /// Start with no shares
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a share given as word indices
+    pub fn add(&mut self, words: &[u16]) -> AnyaResult<RecoveryProgress> {
+        let share = Share::from_words(words)?;
+        if let Some(first) = self.shares.values().next() {
+            if !first.is_compatible(&share) {
+                return Err(AnyaError::invalid_input(
+                    "share belongs to a different backup",
+                ));
+            }
+        }
+        if let Some(member) = self
+            .shares
+            .values()
+            .find(|s| s.group_index == share.group_index)
+        {
+            if member.member_threshold != share.member_threshold {
+                return Err(AnyaError::invalid_input(
+                    "share disagrees with its group's threshold",
+                ));
+            }
+        }
+        match self.shares.get(&(share.group_index, share.member_index)) {
+            Some(existing) if existing != &share => {
+                return Err(AnyaError::invalid_input(
+                    "a different share was already entered for this member",
+                ))
+            }
+            Some(_) => {}
+            None => {
+                self.shares
+                    .insert((share.group_index, share.member_index), share);
+            }
+        }
+        Ok(self.progress())
+    }
+
+    /// Shares entered so far, by group
+    pub fn progress(&self) -> RecoveryProgress {
+        let mut groups: BTreeMap<u8, GroupProgress> = BTreeMap::new();
+        for share in self.shares.values() {
+            groups
+                .entry(share.group_index)
+                .or_insert(GroupProgress {
+                    group_index: share.group_index,
+                    entered: 0,
+                    threshold: share.member_threshold,
+                })
+                .entered += 1;
+        }
+        let groups: Vec<GroupProgress> = groups.into_values().collect();
+        RecoveryProgress {
+            groups_complete: groups.iter().filter(|g| g.entered >= g.threshold).count() as u8,
+            group_threshold: self.shares.values().next().map_or(1, |s| s.group_threshold),
+            groups,
+        }
+    }
+
+    /// Recover the seed, failing if shares are still missing or the
+    /// passphrase-independent digest check fails
+    pub fn finish(self, passphrase: &str) -> AnyaResult<Zeroizing<Vec<u8>>> {
+        if self.shares.is_empty() || !self.progress().is_complete() {
+            return Err(AnyaError::invalid_input(
+                "not enough shares to recover the seed",
+            ));
+        }
+        let shares: Vec<Share> = self.shares.into_values().collect();
+        slip39::combine(&shares, passphrase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Synthetic list with unique four-letter prefixes, standing in for the
+    /// SLIP-39 list
+    fn wordlist() -> Wordlist {
+        let letters = b"abcdefghijklmnopqrstuvwxyz";
+        let words: Vec<String> = (0..1024)
+            .map(|i| {
+                let mut word: String = [i / 676, i / 26 % 26, i % 26]
+                    .iter()
+                    .map(|l| char::from(letters[*l]))
+                    .collect();
+                word.insert(0, 'w');
+                word.push_str("ord");
+                word
+            })
+            .collect();
+        Wordlist::parse(&words.join("\n")).unwrap()
+    }
+
+    #[test]
+    fn test_ceremony_requires_every_share_verified() {
+        let wordlist = wordlist();
+        let seed = [0x17u8; 16];
+        let mut ceremony = SplitCeremony::start(&seed, "", &SharePlan::single(2, 3)).unwrap();
+        let shown: Vec<Zeroizing<String>> = (0..3)
+            .map(|m| {
+                wordlist
+                    .encode(&ceremony.share(0, m).unwrap().to_words())
+                    .unwrap()
+            })
+            .collect();
+
+        // Share 1 copied into share 0's slot, then entered correctly
+        let wrong = wordlist.decode(&shown[1]).unwrap();
+        assert!(ceremony.verify(0, 0, &wrong).is_err());
+        for (member, phrase) in shown.iter().enumerate().take(2) {
+            let words = wordlist.decode(phrase).unwrap();
+            ceremony.verify(0, member, &words).unwrap();
+        }
+        assert_eq!(ceremony.pending(), vec![(0, 2)]);
+        let abbreviated: String = shown[2]
+            .split(' ')
+            .map(|w| &w[..4])
+            .collect::<Vec<_>>()
+            .join(" ");
+        ceremony
+            .verify(0, 2, &wordlist.decode(&abbreviated).unwrap())
+            .unwrap();
+        let fingerprint = ceremony.finish().unwrap();
+        assert_eq!(fingerprint, seed_fingerprint(&seed).unwrap());
+
+        let mut session = RecoverySession::new();
+        let progress = session.add(&wordlist.decode(&shown[2]).unwrap()).unwrap();
+        assert!(!progress.is_complete());
+        assert_eq!(progress.groups[0].entered, 1);
+        assert!(session.add(&wordlist.decode(&shown[2]).unwrap()).is_ok());
+        assert!(session
+            .add(&wordlist.decode(&shown[0]).unwrap())
+            .unwrap()
+            .is_complete());
+        assert_eq!(*session.finish("").unwrap(), seed);
+    }
+}
@@ -0,0 +1,500 @@
+//! Wallet UTXO set, coin locks, and coin selection
+//!
+//! Every wallet output is tracked with the account key that controls it.
+//! Coins can be frozen temporarily or marked do-not-spend (e.g. dust sent to
+//! deanonymize the wallet); locked coins are never chosen by
+//! [`select_coins`] and are rejected when passed as explicit inputs.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::bitcoin::{FeeRate, OutPoint, TxOut};
+use serde::{Deserialize, Serialize};
+
+use super::accounts::{AccountId, KeyChain};
+use super::fees::fee_for;
+use super::labels::{LabelStore, LabelType};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::pagination::{paginate, Page, PageRequest};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "wallet_coins";
+const UTXO_PREFIX: &str = "utxo/";
+const LOCK_PREFIX: &str = "lock/";
+
+/// An unspent output owned by the wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Utxo {
+    /// Output reference
+    pub outpoint: OutPoint,
+    /// Value and script
+    pub txout: TxOut,
+    /// Account holding the key
+    pub account: AccountId,
+    /// Chain of the key within the account
+    pub chain: KeyChain,
+    /// Index of the key on its chain
+    pub index: u32,
+    /// Confirmation height, `None` while in the mempool
+    pub height: Option<u32>,
+}
+
+/// Why a coin may not be spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinLock {
+    /// Temporarily held back by the user
+    Frozen,
+    /// Never to be spent; only cleared explicitly
+    DoNotSpend,
+}
+
+/// A wallet coin with its lock state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Coin {
+    /// The output
+    pub utxo: Utxo,
+    /// Lock preventing it from being spent
+    pub lock: Option<CoinLock>,
+}
+
+/// Field coin listings are sorted by; ties are broken by outpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSort {
+    /// Outpoint only
+    #[default]
+    Outpoint,
+    /// Output value
+    Value,
+    /// Confirmation height, unconfirmed coins last
+    Height,
+}
+
+/// Filter for [`CoinStore::query`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoinQuery {
+    /// Only coins of this account
+    pub account: Option<AccountId>,
+    /// Only coins worth at least this much
+    pub min_value_sat: Option<u64>,
+    /// Only confirmed coins
+    pub confirmed_only: bool,
+    /// Leave out frozen and do-not-spend coins
+    pub unlocked_only: bool,
+    /// Sort field
+    pub sort: CoinSort,
+}
+
+impl CoinQuery {
+    fn matches(&self, coin: &Coin) -> bool {
+        (self.account.is_none() || self.account == Some(coin.utxo.account))
+            && self.min_value_sat.unwrap_or(0) <= coin.utxo.txout.value
+            && !(self.confirmed_only && coin.utxo.height.is_none())
+            && !(self.unlocked_only && coin.lock.is_some())
+    }
+
+    fn sort_key(&self, coin: &Coin) -> (u64, String) {
+        let primary = match self.sort {
+            CoinSort::Outpoint => 0,
+            CoinSort::Value => coin.utxo.txout.value,
+            CoinSort::Height => coin.utxo.height.map_or(u64::MAX, u64::from),
+        };
+        (primary, coin.utxo.outpoint.to_string())
+    }
+}
+
+/// Persistent UTXO set with coin locks
+pub struct CoinStore {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+}
+
+impl CoinStore {
+    /// Open the coin store in `storage`
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self { storage, ns })
+    }
+
+    /// Add or update a wallet output
+    pub async fn insert(&self, utxo: &Utxo) -> AnyaResult<()> {
+        let bytes = serde_json::to_vec(utxo)?;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", UTXO_PREFIX, utxo.outpoint),
+                &bytes,
+            )
+            .await
+    }
+
+    /// Forget a spent output along with any lock on it
+    pub async fn remove(&self, outpoint: &OutPoint) -> AnyaResult<()> {
+        self.storage
+            .delete(&self.ns, &format!("{}{}", UTXO_PREFIX, outpoint))
+            .await?;
+        self.storage
+            .delete(&self.ns, &format!("{}{}", LOCK_PREFIX, outpoint))
+            .await?;
+        Ok(())
+    }
+
+    /// Look up a coin
+    pub async fn coin(&self, outpoint: &OutPoint) -> AnyaResult<Option<Coin>> {
+        let Some(bytes) = self
+            .storage
+            .get(&self.ns, &format!("{}{}", UTXO_PREFIX, outpoint))
+            .await?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Coin {
+            utxo: serde_json::from_slice(&bytes)?,
+            lock: self.lock(outpoint).await?,
+        }))
+    }
+
+    /// All coins, optionally restricted to one account
+    pub async fn coins(&self, account: Option<AccountId>) -> AnyaResult<Vec<Coin>> {
+        let mut coins = Vec::new();
+        for (_, bytes) in self.storage.scan_prefix(&self.ns, UTXO_PREFIX).await? {
+            let utxo: Utxo = serde_json::from_slice(&bytes)?;
+            if account.is_none() || account == Some(utxo.account) {
+                let lock = self.lock(&utxo.outpoint).await?;
+                coins.push(Coin { utxo, lock });
+            }
+        }
+        Ok(coins)
+    }
+
+    /// One page of the coins matching `query`
+    pub async fn query(&self, query: &CoinQuery, page: &PageRequest) -> AnyaResult<Page<Coin>> {
+        let mut coins = self.coins(query.account).await?;
+        coins.retain(|c| query.matches(c));
+        paginate(coins, page, |c| query.sort_key(c))
+    }
+
+    /// Unlocked coins of `account`, the candidates for automatic selection
+    pub async fn spendable(&self, account: AccountId) -> AnyaResult<Vec<Utxo>> {
+        Ok(self
+            .coins(Some(account))
+            .await?
+            .into_iter()
+            .filter(|c| c.lock.is_none())
+            .map(|c| c.utxo)
+            .collect())
+    }
+
+    /// Resolve explicitly chosen inputs, rejecting unknown, foreign, or
+    /// locked coins
+    pub async fn resolve(
+        &self,
+        account: AccountId,
+        outpoints: &[OutPoint],
+    ) -> AnyaResult<Vec<Utxo>> {
+        let mut utxos = Vec::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            let coin = self
+                .coin(outpoint)
+                .await?
+                .ok_or_else(|| AnyaError::not_found(format!("coin {}", outpoint)))?;
+            if coin.utxo.account != account {
+                return Err(AnyaError::invalid_input(format!(
+                    "coin {} belongs to account {}",
+                    outpoint, coin.utxo.account
+                )));
+            }
+            if let Some(lock) = coin.lock {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!("coin {} is locked ({:?})", outpoint, lock),
+                ));
+            }
+            utxos.push(coin.utxo);
+        }
+        Ok(utxos)
+    }
+
+    /// Current lock on a coin
+    pub async fn lock(&self, outpoint: &OutPoint) -> AnyaResult<Option<CoinLock>> {
+        match self
+            .storage
+            .get(&self.ns, &format!("{}{}", LOCK_PREFIX, outpoint))
+            .await?
+        {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Freeze a coin so it is not spent until unfrozen
+    pub async fn freeze(&self, outpoint: &OutPoint) -> AnyaResult<()> {
+        if self.lock(outpoint).await? == Some(CoinLock::DoNotSpend) {
+            return Ok(());
+        }
+        self.set_lock(outpoint, Some(CoinLock::Frozen)).await
+    }
+
+    /// Unfreeze a frozen coin; do-not-spend coins stay locked
+    pub async fn unfreeze(&self, outpoint: &OutPoint) -> AnyaResult<()> {
+        match self.lock(outpoint).await? {
+            Some(CoinLock::DoNotSpend) => Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("coin {} is marked do-not-spend", outpoint),
+            )),
+            Some(CoinLock::Frozen) => self.set_lock(outpoint, None).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Mark or unmark a coin as do-not-spend; unmarking leaves frozen coins
+    /// frozen
+    pub async fn set_do_not_spend(
+        &self,
+        outpoint: &OutPoint,
+        do_not_spend: bool,
+    ) -> AnyaResult<()> {
+        if do_not_spend {
+            self.set_lock(outpoint, Some(CoinLock::DoNotSpend)).await
+        } else if self.lock(outpoint).await? == Some(CoinLock::DoNotSpend) {
+            self.set_lock(outpoint, None).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Apply the BIP-329 `spendable` flags of output labels to coin locks
+    pub async fn apply_labels(&self, labels: &LabelStore) -> AnyaResult<usize> {
+        let mut applied = 0;
+        for label in labels.labels(Some(LabelType::Output)).await? {
+            let (Some(spendable), Ok(outpoint)) =
+                (label.spendable, OutPoint::from_str(&label.reference))
+            else {
+                continue;
+            };
+            if self.coin(&outpoint).await?.is_some() {
+                self.set_do_not_spend(&outpoint, !spendable).await?;
+                applied += 1;
+            }
+        }
+        Ok(applied)
+    }
+
+    async fn set_lock(&self, outpoint: &OutPoint, lock: Option<CoinLock>) -> AnyaResult<()> {
+        if self.coin(outpoint).await?.is_none() {
+            return Err(AnyaError::not_found(format!("coin {}", outpoint)));
+        }
+        let key = format!("{}{}", LOCK_PREFIX, outpoint);
+        match lock {
+            Some(lock) => {
+                let bytes = serde_json::to_vec(&lock)?;
+                self.storage.put(&self.ns, &key, &bytes).await
+            }
+            None => self.storage.delete(&self.ns, &key).await.map(drop),
+        }
+    }
+}
+
+/// Parameters for [`select_coins`]
+#[derive(Debug, Clone)]
+pub struct SelectionParams {
+    /// Amount paid to recipients
+    pub target_sat: u64,
+    /// Weight of the transaction without inputs or change
+    pub base_weight: u64,
+    /// Weight added by a change output
+    pub change_weight: u64,
+    /// Smallest change worth creating
+    pub change_dust_sat: u64,
+    /// Fee rate to pay
+    pub fee_rate: FeeRate,
+}
+
+/// Result of coin selection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// Chosen inputs
+    pub inputs: Vec<Utxo>,
+    /// Fee paid
+    pub fee_sat: u64,
+    /// Change returned to the wallet, if any
+    pub change_sat: Option<u64>,
+    /// Expected weight of the signed transaction
+    pub weight: u64,
+}
+
+/// Choose inputs covering `params.target_sat` plus fees.
+///
+/// All `required` coins are spent; further coins are added from `candidates`
+/// largest effective value first, skipping coins that cost more to spend than
+/// they are worth. Change below the dust threshold is left to fees.
+pub fn select_coins(
+    required: Vec<Utxo>,
+    mut candidates: Vec<Utxo>,
+    params: &SelectionParams,
+) -> AnyaResult<Selection> {
+    let input_fee = |u: &Utxo| fee_for(params.fee_rate, u.account.script_type.input_weight());
+    candidates.retain(|u| u.txout.value > input_fee(u));
+    candidates.sort_by_key(|u| std::cmp::Reverse(u.txout.value - input_fee(u)));
+
+    let mut inputs = required;
+    let mut candidates = candidates.into_iter();
+    loop {
+        let value: u64 = inputs.iter().map(|u| u.txout.value).sum();
+        let weight = params.base_weight
+            + inputs
+                .iter()
+                .map(|u| u.account.script_type.input_weight())
+                .sum::<u64>();
+        let fee = fee_for(params.fee_rate, weight);
+        if value >= params.target_sat + fee {
+            let change_fee = fee_for(params.fee_rate, weight + params.change_weight);
+            let change = value.saturating_sub(params.target_sat + change_fee);
+            return Ok(if change >= params.change_dust_sat {
+                Selection {
+                    inputs,
+                    fee_sat: change_fee,
+                    change_sat: Some(change),
+                    weight: weight + params.change_weight,
+                }
+            } else {
+                Selection {
+                    fee_sat: value - params.target_sat,
+                    inputs,
+                    change_sat: None,
+                    weight,
+                }
+            });
+        }
+        match candidates.next() {
+            Some(utxo) => inputs.push(utxo),
+            None => {
+                return Err(AnyaError::new(
+                    ErrorCode::InsufficientFunds,
+                    format!(
+                        "need {} sat plus fees, have {} sat spendable",
+                        params.target_sat, value
+                    ),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::ScriptType;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{ScriptBuf, Txid};
+
+    fn utxo(vout: u32, value: u64) -> Utxo {
+        Utxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), vout),
+            txout: TxOut {
+                value,
+                script_pubkey: ScriptBuf::new(),
+            },
+            account: AccountId {
+                script_type: ScriptType::NativeSegwit,
+                index: 0,
+            },
+            chain: KeyChain::External,
+            index: vout,
+            height: Some(100),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_locks_exclude_coins_from_spending() {
+        let coins = CoinStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let (a, b) = (utxo(0, 50_000), utxo(1, 546));
+        coins.insert(&a).await.unwrap();
+        coins.insert(&b).await.unwrap();
+        let account = a.account;
+
+        coins.freeze(&a.outpoint).await.unwrap();
+        coins.set_do_not_spend(&b.outpoint, true).await.unwrap();
+        assert!(coins.spendable(account).await.unwrap().is_empty());
+        assert_eq!(
+            coins
+                .resolve(account, &[a.outpoint])
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::Conflict
+        );
+        assert_eq!(
+            coins.unfreeze(&b.outpoint).await.unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+
+        coins.unfreeze(&a.outpoint).await.unwrap();
+        assert_eq!(coins.spendable(account).await.unwrap(), vec![a.clone()]);
+        coins.remove(&b.outpoint).await.unwrap();
+        assert_eq!(coins.coins(None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_filters_and_pages_by_value() {
+        let coins = CoinStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        for (vout, value) in [(0, 7_000), (1, 300), (2, 9_000), (3, 7_000), (4, 1_000)] {
+            coins.insert(&utxo(vout, value)).await.unwrap();
+        }
+        coins
+            .freeze(&OutPoint::new(Txid::all_zeros(), 2))
+            .await
+            .unwrap();
+        let query = CoinQuery {
+            min_value_sat: Some(500),
+            unlocked_only: true,
+            sort: CoinSort::Value,
+            ..CoinQuery::default()
+        };
+
+        let first = coins.query(&query, &PageRequest::first(2)).await.unwrap();
+        let values: Vec<_> = first.items.iter().map(|c| c.utxo.txout.value).collect();
+        assert_eq!(values, [1_000, 7_000]);
+        let next = PageRequest::first(2).after(first.next_cursor.unwrap());
+        let second = coins.query(&query, &next).await.unwrap();
+        assert_eq!(second.items.len(), 1);
+        assert_eq!(second.items[0].utxo.outpoint.vout, 3);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[test]
+    fn test_selection_prefers_large_coins_and_drops_dust_change() {
+        let params = SelectionParams {
+            target_sat: 60_000,
+            base_weight: 172,
+            change_weight: 124,
+            change_dust_sat: 294,
+            fee_rate: FeeRate::from_sat_per_vb_unchecked(2),
+        };
+        let candidates = vec![utxo(0, 10_000), utxo(1, 100), utxo(2, 55_000)];
+        let selection = select_coins(Vec::new(), candidates.clone(), &params).unwrap();
+        assert_eq!(selection.inputs.len(), 2);
+        assert_eq!(selection.inputs[0].txout.value, 55_000);
+        let change = selection.change_sat.unwrap();
+        assert_eq!(65_000, params.target_sat + selection.fee_sat + change);
+
+        let exact = SelectionParams {
+            target_sat: 54_700,
+            ..params
+        };
+        let selection = select_coins(Vec::new(), candidates, &exact).unwrap();
+        assert_eq!(selection.change_sat, None);
+        assert_eq!(selection.fee_sat, 300);
+
+        let err = select_coins(vec![utxo(3, 1_000)], Vec::new(), &params).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InsufficientFunds);
+    }
+}
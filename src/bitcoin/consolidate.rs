@@ -0,0 +1,541 @@
+//! UTXO consolidation advisor
+//!
+//! Small coins cost a fixed amount of block space to spend, so when fees
+//! rise they become uneconomic: spending them later costs a large share of
+//! their value, or all of it. Sweeping them into one output while fees are
+//! low pays for that block space at the low rate instead.
+//!
+//! [`ConsolidationAdvisor::advise`] classifies an account's confirmed coins
+//! against the policy's long-term fee rate, and recommends consolidating the
+//! uneconomic ones when the current rate is below the policy ceiling and the
+//! fee market analytics place the current hour among the cheapest upcoming
+//! send windows. [`ConsolidationService`] checks periodically and, when the
+//! policy allows automatic execution, hands the sweep to a
+//! [`ConsolidationExecutor`] to sign and broadcast.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use ::bitcoin::{FeeRate, OutPoint, Txid};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::accounts::{AccountId, AccountManager};
+use super::builder::{BuiltTx, InputSelection, TxBuilder, TxRequest};
+use super::coins::{CoinStore, Utxo};
+use super::fees::{fee_for, FeeEstimator, TxShape};
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::ml::fee_market::{FeeMarket, SendWindow};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult};
+
+/// When coins are consolidated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsolidationPolicy {
+    /// Sign and broadcast recommended consolidations without asking
+    pub auto_execute: bool,
+    /// Fee rate coins are expected to be spent at in the long run
+    pub long_term_fee_rate: FeeRate,
+    /// A coin is uneconomic when spending it at the long-term rate costs
+    /// more than this share of its value
+    pub max_spend_cost_ratio: f64,
+    /// Never consolidate above this fee rate
+    pub max_fee_rate: FeeRate,
+    /// Fewest uneconomic coins worth a consolidation
+    pub min_inputs: usize,
+    /// Most coins swept in one transaction
+    pub max_inputs: usize,
+    /// Confirmation target the current fee rate is estimated for
+    pub confirm_target: u16,
+    /// How far ahead the cheapest send windows are looked for
+    pub window_horizon: Duration,
+    /// Number of cheapest hours within the horizon that count as low-fee
+    pub windows: usize,
+    /// How often the service checks
+    pub check_interval: Duration,
+}
+
+impl Default for ConsolidationPolicy {
+    fn default() -> Self {
+        Self {
+            auto_execute: false,
+            long_term_fee_rate: FeeRate::from_sat_per_vb_unchecked(20),
+            max_spend_cost_ratio: 0.05,
+            max_fee_rate: FeeRate::from_sat_per_vb_unchecked(5),
+            min_inputs: 5,
+            max_inputs: 200,
+            confirm_target: 144,
+            window_horizon: Duration::from_secs(24 * 60 * 60),
+            windows: 4,
+            check_interval: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// How economic a coin is to spend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinClass {
+    /// Cheap to spend relative to its value
+    Economic,
+    /// Expensive to spend at the long-term rate; consolidate while cheap
+    Uneconomic,
+    /// Worth no more than it costs to spend at the current rate
+    Dust,
+}
+
+/// Spending cost of one coin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoinAssessment {
+    /// The coin
+    pub outpoint: OutPoint,
+    /// Its value
+    pub value_sat: u64,
+    /// Fee to spend it at the current rate
+    pub spend_cost_now_sat: u64,
+    /// Fee to spend it at the long-term rate
+    pub spend_cost_long_term_sat: u64,
+    /// Resulting class
+    pub class: CoinClass,
+}
+
+/// Consolidation advice for one account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConsolidationAdvice {
+    /// Account assessed
+    pub account: AccountId,
+    /// Current fee rate
+    pub fee_rate: FeeRate,
+    /// Every confirmed, unlocked coin, smallest first
+    pub assessments: Vec<CoinAssessment>,
+    /// Uneconomic coins to sweep, up to the policy's input limit
+    pub candidates: Vec<OutPoint>,
+    /// Fee of sweeping the candidates now
+    pub estimated_fee_sat: u64,
+    /// Fees saved compared with spending the candidates one by one at the
+    /// long-term rate; negative when consolidating costs more
+    pub savings_sat: i64,
+    /// Whether the current hour is one of the cheapest upcoming windows
+    pub in_low_fee_window: bool,
+    /// Next cheap window, when not in one
+    pub next_window: Option<SendWindow>,
+    /// Whether consolidating now is advised
+    pub recommended: bool,
+}
+
+/// Signs and broadcasts a consolidation, e.g. a hot wallet or an HSM
+#[async_trait]
+pub trait ConsolidationExecutor: Send + Sync {
+    /// Sign `built` and broadcast it
+    async fn execute(&self, built: BuiltTx) -> AnyaResult<Txid>;
+}
+
+/// Recommends and carries out consolidations
+pub struct ConsolidationAdvisor {
+    policy: ConsolidationPolicy,
+    market: Arc<FeeMarket>,
+    estimator: Arc<dyn FeeEstimator>,
+    /// Coins swept by a broadcast consolidation the coin store may not have
+    /// seen confirm yet
+    pending: Mutex<HashSet<OutPoint>>,
+}
+
+impl ConsolidationAdvisor {
+    /// Advisor applying `policy`, with fee windows from `market` and the
+    /// current rate from `estimator`
+    pub fn new(
+        policy: ConsolidationPolicy,
+        market: Arc<FeeMarket>,
+        estimator: Arc<dyn FeeEstimator>,
+    ) -> Self {
+        Self {
+            policy,
+            market,
+            estimator,
+            pending: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Policy applied
+    pub const fn policy(&self) -> &ConsolidationPolicy {
+        &self.policy
+    }
+
+    fn pending(&self) -> MutexGuard<'_, HashSet<OutPoint>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn classify(&self, utxo: &Utxo, fee_rate: FeeRate) -> CoinAssessment {
+        let weight = utxo.account.script_type.input_weight();
+        let now = fee_for(fee_rate, weight);
+        let long_term = fee_for(self.policy.long_term_fee_rate, weight);
+        let value = utxo.txout.value;
+        #[allow(clippy::cast_precision_loss)]
+        let class = if value <= now {
+            CoinClass::Dust
+        } else if long_term as f64 > value as f64 * self.policy.max_spend_cost_ratio {
+            CoinClass::Uneconomic
+        } else {
+            CoinClass::Economic
+        };
+        CoinAssessment {
+            outpoint: utxo.outpoint,
+            value_sat: value,
+            spend_cost_now_sat: now,
+            spend_cost_long_term_sat: long_term,
+            class,
+        }
+    }
+
+    /// Assess the coins of `account` at time `now` (Unix seconds)
+    pub async fn advise(
+        &self,
+        coins: &CoinStore,
+        account: AccountId,
+        now: u64,
+    ) -> AnyaResult<ConsolidationAdvice> {
+        let fee_rate = self.estimator.estimate(self.policy.confirm_target).await?;
+        let pending = self.pending().clone();
+        let mut utxos: Vec<Utxo> = coins
+            .spendable(account)
+            .await?
+            .into_iter()
+            .filter(|u| u.height.is_some() && !pending.contains(&u.outpoint))
+            .collect();
+        utxos.sort_by_key(|u| u.txout.value);
+        let assessments: Vec<CoinAssessment> =
+            utxos.iter().map(|u| self.classify(u, fee_rate)).collect();
+        let candidates: Vec<&CoinAssessment> = assessments
+            .iter()
+            .filter(|a| a.class == CoinClass::Uneconomic)
+            .take(self.policy.max_inputs)
+            .collect();
+
+        let estimated_fee_sat = TxShape::uniform(account.script_type, candidates.len(), 1)
+            .estimate(fee_rate)
+            .fee_sat;
+        let later: u64 = candidates.iter().map(|a| a.spend_cost_long_term_sat).sum();
+        let savings_sat = i64::try_from(later).unwrap_or(i64::MAX)
+            - i64::try_from(estimated_fee_sat).unwrap_or(i64::MAX);
+
+        let windows =
+            self.market
+                .send_windows(now, self.policy.window_horizon, self.policy.windows);
+        let in_low_fee_window = windows.iter().any(|w| w.start <= now && now < w.end);
+        let next_window = if in_low_fee_window {
+            None
+        } else {
+            windows.into_iter().find(|w| w.start > now)
+        };
+        let recommended = candidates.len() >= self.policy.min_inputs
+            && fee_rate <= self.policy.max_fee_rate
+            && in_low_fee_window
+            && savings_sat > 0;
+
+        Ok(ConsolidationAdvice {
+            account,
+            fee_rate,
+            candidates: candidates.iter().map(|a| a.outpoint).collect(),
+            assessments,
+            estimated_fee_sat,
+            savings_sat,
+            in_low_fee_window,
+            next_window,
+            recommended,
+        })
+    }
+
+    /// Build the sweep of `advice.candidates` into one change output
+    pub async fn plan(
+        &self,
+        builder: &TxBuilder<'_>,
+        coins: &CoinStore,
+        advice: &ConsolidationAdvice,
+    ) -> AnyaResult<BuiltTx> {
+        if advice.candidates.is_empty() {
+            return Err(AnyaError::invalid_input("no coins to consolidate"));
+        }
+        let forced = coins.resolve(advice.account, &advice.candidates).await?;
+        let request = TxRequest {
+            account: advice.account,
+            recipients: Vec::new(),
+            fee_rate: advice.fee_rate,
+            inputs: InputSelection::Auto,
+            shuffle_outputs: false,
+        };
+        // Forced inputs with no recipients sweep to change; further coins
+        // are only added if the candidates cannot pay their own fee
+        let (account, selection) = builder.plan(&request, &forced, true).await?;
+        builder.assemble(&request, account, selection).await
+    }
+
+    /// Consolidate `account` if advised and the policy allows automatic
+    /// execution, returning the broadcast transaction
+    pub async fn run_once(
+        &self,
+        builder: &TxBuilder<'_>,
+        coins: &CoinStore,
+        account: AccountId,
+        executor: &dyn ConsolidationExecutor,
+        now: u64,
+    ) -> AnyaResult<Option<Txid>> {
+        let advice = self.advise(coins, account, now).await?;
+        if !(advice.recommended && self.policy.auto_execute) {
+            return Ok(None);
+        }
+        let built = self.plan(builder, coins, &advice).await?;
+        let txid = executor.execute(built).await?;
+        self.pending().extend(advice.candidates.iter().copied());
+        info!(
+            %txid,
+            %account,
+            inputs = advice.candidates.len(),
+            fee_sat = advice.estimated_fee_sat,
+            "consolidated uneconomic coins"
+        );
+        Ok(Some(txid))
+    }
+
+    /// Check `watched` accounts every policy interval until `token` is
+    /// cancelled, consolidating when advised
+    pub async fn run_schedule(
+        self: Arc<Self>,
+        accounts: Arc<AccountManager>,
+        coins: Arc<CoinStore>,
+        executor: Arc<dyn ConsolidationExecutor>,
+        watched: Vec<AccountId>,
+        token: CancellationToken,
+    ) -> AnyaResult<()> {
+        run_loop(token, self.policy.check_interval, || {
+            let advisor = Arc::clone(&self);
+            let (accounts, coins) = (Arc::clone(&accounts), Arc::clone(&coins));
+            let executor = Arc::clone(&executor);
+            let watched = watched.clone();
+            async move {
+                let builder = TxBuilder::new(&accounts, &coins);
+                for account in watched {
+                    if let Err(e) = advisor
+                        .run_once(&builder, &coins, account, executor.as_ref(), unix_now())
+                        .await
+                    {
+                        warn!(%account, error = %e, "consolidation failed");
+                    }
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    /// Forget swept coins once the coin store has caught up with the
+    /// consolidation
+    pub fn clear_pending(&self, outpoints: &[OutPoint]) {
+        let mut pending = self.pending();
+        for outpoint in outpoints {
+            pending.remove(outpoint);
+        }
+        drop(pending);
+    }
+}
+
+/// Consolidation of a set of accounts as a lifecycle-managed subsystem
+pub struct ConsolidationService {
+    advisor: Arc<ConsolidationAdvisor>,
+    accounts: Arc<AccountManager>,
+    coins: Arc<CoinStore>,
+    executor: Arc<dyn ConsolidationExecutor>,
+    watched: Vec<AccountId>,
+}
+
+impl ConsolidationService {
+    /// Consolidate `watched` accounts with `advisor`, broadcasting through
+    /// `executor`
+    pub fn new(
+        advisor: Arc<ConsolidationAdvisor>,
+        accounts: Arc<AccountManager>,
+        coins: Arc<CoinStore>,
+        executor: Arc<dyn ConsolidationExecutor>,
+        watched: Vec<AccountId>,
+    ) -> Self {
+        Self {
+            advisor,
+            accounts,
+            coins,
+            executor,
+            watched,
+        }
+    }
+}
+
+#[async_trait]
+impl Subsystem for ConsolidationService {
+    fn name(&self) -> &str {
+        "consolidation"
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let advisor = Arc::clone(&self.advisor);
+        let (accounts, coins) = (Arc::clone(&self.accounts), Arc::clone(&self.coins));
+        let executor = Arc::clone(&self.executor);
+        let watched = self.watched.clone();
+        spawner
+            .spawn("schedule", move |token| {
+                advisor.run_schedule(accounts, coins, executor, watched, token)
+            })
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::{KeyChain, ScriptType};
+    use crate::ml::fee_market::{FeeBucket, FeeMarketConfig, MempoolSnapshot};
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::StorageBackend;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::secp256k1::Secp256k1;
+    use ::bitcoin::{Network, TxOut};
+
+    const HOUR: u64 = 3_600;
+
+    struct Fixed(u64);
+
+    #[async_trait]
+    impl FeeEstimator for Fixed {
+        async fn estimate(&self, _target_blocks: u16) -> AnyaResult<FeeRate> {
+            Ok(FeeRate::from_sat_per_vb_unchecked(self.0))
+        }
+    }
+
+    struct Recorder(Mutex<Vec<BuiltTx>>);
+
+    #[async_trait]
+    impl ConsolidationExecutor for Recorder {
+        async fn execute(&self, built: BuiltTx) -> AnyaResult<Txid> {
+            let txid = built.psbt.unsigned_tx.txid();
+            self.0.lock().unwrap().push(built);
+            Ok(txid)
+        }
+    }
+
+    /// Fee market whose night hours (00:00-06:00 UTC) are quiet
+    fn market() -> Arc<FeeMarket> {
+        let market = Arc::new(FeeMarket::new(FeeMarketConfig::default()));
+        for h in 0..48 {
+            let fee_rate = if h % 24 < 6 { 1.0 } else { 30.0 };
+            market.record(MempoolSnapshot {
+                timestamp: h * HOUR,
+                buckets: vec![FeeBucket {
+                    fee_rate,
+                    vsize: 500_000,
+                }],
+            });
+        }
+        market
+    }
+
+    #[tokio::test]
+    async fn test_advise_and_execute() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let accounts = AccountManager::open(Network::Regtest, Arc::clone(&storage))
+            .await
+            .unwrap();
+        let coins = CoinStore::open(storage).await.unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[3; 32]).unwrap();
+        let account = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "main")
+            .await
+            .unwrap();
+        let script = account
+            .address(&Secp256k1::verification_only(), KeyChain::External, 0)
+            .unwrap()
+            .script_pubkey();
+        // Six 20k coins, one large coin, and one dust coin
+        let values = [
+            20_000, 20_000, 20_000, 20_000, 20_000, 20_000, 5_000_000, 200,
+        ];
+        for (vout, value) in (0u32..).zip(values) {
+            coins
+                .insert(&Utxo {
+                    outpoint: OutPoint::new(Txid::all_zeros(), vout),
+                    txout: TxOut {
+                        value,
+                        script_pubkey: script.clone(),
+                    },
+                    account: account.id,
+                    chain: KeyChain::External,
+                    index: 0,
+                    height: Some(100),
+                })
+                .await
+                .unwrap();
+        }
+
+        let policy = ConsolidationPolicy {
+            auto_execute: true,
+            ..ConsolidationPolicy::default()
+        };
+        let night = 2 * 24 * HOUR + 2 * HOUR;
+        let advisor = ConsolidationAdvisor::new(policy, market(), Arc::new(Fixed(3)));
+        let advice = advisor.advise(&coins, account.id, night).await.unwrap();
+        assert_eq!(advice.assessments.len(), 8);
+        assert_eq!(advice.assessments[0].class, CoinClass::Dust);
+        assert_eq!(advice.assessments[7].class, CoinClass::Economic);
+        assert_eq!(advice.candidates.len(), 6);
+        assert!(advice.in_low_fee_window);
+        assert!(advice.savings_sat > 0);
+        assert!(advice.recommended);
+
+        // Afternoon is busy: wait for the night window
+        let afternoon = night + 12 * HOUR;
+        let later = advisor.advise(&coins, account.id, afternoon).await.unwrap();
+        assert!(!later.recommended);
+        assert!(later.next_window.unwrap().start > afternoon);
+
+        // Too expensive right now
+        let pricey = ConsolidationAdvisor::new(
+            ConsolidationPolicy::default(),
+            market(),
+            Arc::new(Fixed(10)),
+        );
+        assert!(
+            !pricey
+                .advise(&coins, account.id, night)
+                .await
+                .unwrap()
+                .recommended
+        );
+
+        let builder = TxBuilder::new(&accounts, &coins);
+        let executor = Recorder(Mutex::new(Vec::new()));
+        let txid = advisor
+            .run_once(&builder, &coins, account.id, &executor, night)
+            .await
+            .unwrap()
+            .unwrap();
+        let built = executor.0.lock().unwrap().remove(0);
+        assert_eq!(built.psbt.unsigned_tx.txid(), txid);
+        assert_eq!(built.psbt.unsigned_tx.input.len(), 6);
+        assert_eq!(built.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(built.change_vout, Some(0));
+        assert_eq!(
+            built.psbt.unsigned_tx.output[0].value,
+            120_000 - built.fee_sat
+        );
+
+        // Swept coins are not proposed again while pending
+        let again = advisor
+            .run_once(&builder, &coins, account.id, &executor, night)
+            .await
+            .unwrap();
+        assert!(again.is_none());
+    }
+}
@@ -0,0 +1,251 @@
+//! Watch-only single-key output descriptors
+//!
+//! Parses the descriptors wallets export for an account, such as
+//! `wpkh([73c5da0a/84h/0h/0h]xpub.../0/*)#checksum`, into a
+//! [`WatchOnlyDescriptor`] that derives addresses without any private key
+//! material. The BIP-380 checksum is verified when present. Only the
+//! single-key forms produced by [`Account::descriptor`](super::accounts::Account::descriptor)
+//! are supported: `pkh`, `wpkh`, and key-path `tr` over an extended public
+//! key with unhardened steps ending in `/*`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use ::bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource};
+use ::bitcoin::secp256k1::{Secp256k1, Verification};
+use ::bitcoin::{Address, PublicKey};
+
+use super::accounts::ScriptType;
+use crate::{AnyaError, AnyaResult};
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// A ranged single-key descriptor over an extended public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOnlyDescriptor {
+    /// Script type selected by the outer function
+    pub script_type: ScriptType,
+    /// Master fingerprint and path of the key, when given
+    pub origin: Option<KeySource>,
+    /// Extended public key
+    pub xpub: ExtendedPubKey,
+    /// Unhardened steps between the xpub and the wildcard, e.g. `[0]`
+    pub steps: Vec<ChildNumber>,
+}
+
+impl WatchOnlyDescriptor {
+    /// Parse a descriptor, verifying its checksum if present
+    pub fn parse(descriptor: &str) -> AnyaResult<Self> {
+        let descriptor = descriptor.trim();
+        let body = match descriptor.split_once('#') {
+            Some((body, checksum)) => {
+                if checksum_of(body)? != checksum {
+                    return Err(AnyaError::invalid_input("descriptor checksum mismatch"));
+                }
+                body
+            }
+            None => descriptor,
+        };
+        let script_type = ScriptType::from_descriptor(body)?;
+        let inner = body
+            .split_once('(')
+            .and_then(|(_, rest)| rest.strip_suffix(')'))
+            .ok_or_else(|| invalid(body))?;
+
+        let (origin, key) = match inner.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest.split_once(']').ok_or_else(|| invalid(body))?;
+                (Some(parse_origin(origin)?), key)
+            }
+            None => (None, inner),
+        };
+        let mut parts = key.split('/');
+        let xpub = ExtendedPubKey::from_str(parts.next().unwrap_or_default())
+            .map_err(|e| AnyaError::invalid_input(format!("invalid extended key: {}", e)))?;
+        let mut parts: Vec<_> = parts.collect();
+        if parts.pop() != Some("*") {
+            return Err(AnyaError::invalid_input(
+                "descriptor must be ranged with a trailing /*",
+            ));
+        }
+        let steps = parts
+            .into_iter()
+            .map(|step| {
+                step.parse::<u32>()
+                    .ok()
+                    .and_then(|n| ChildNumber::from_normal_idx(n).ok())
+                    .ok_or_else(|| {
+                        AnyaError::invalid_input(format!(
+                            "unsupported derivation step {:?}; watch-only keys need unhardened steps",
+                            step
+                        ))
+                    })
+            })
+            .collect::<AnyaResult<_>>()?;
+        Ok(Self {
+            script_type,
+            origin,
+            xpub,
+            steps,
+        })
+    }
+
+    /// Address at `index` of the range
+    pub fn address<C: Verification>(&self, secp: &Secp256k1<C>, index: u32) -> AnyaResult<Address> {
+        let path: Vec<_> = self
+            .steps
+            .iter()
+            .copied()
+            .chain([ChildNumber::from_normal_idx(index)?])
+            .collect();
+        let key = self.xpub.derive_pub(secp, &path)?.public_key;
+        let network = self.xpub.network;
+        Ok(match self.script_type {
+            ScriptType::Legacy => Address::p2pkh(&PublicKey::new(key), network),
+            ScriptType::NativeSegwit => Address::p2wpkh(&PublicKey::new(key), network)?,
+            ScriptType::Taproot => Address::p2tr(secp, key.x_only_public_key().0, None, network),
+        })
+    }
+
+    /// Descriptor with its checksum appended
+    pub fn to_string_with_checksum(&self) -> String {
+        let body = self.to_string();
+        let checksum = checksum_of(&body).expect("rendered descriptors use the input charset");
+        format!("{}#{}", body, checksum)
+    }
+}
+
+impl fmt::Display for WatchOnlyDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let function = match self.script_type {
+            ScriptType::Legacy => "pkh",
+            ScriptType::NativeSegwit => "wpkh",
+            ScriptType::Taproot => "tr",
+        };
+        write!(f, "{}(", function)?;
+        if let Some((fingerprint, path)) = &self.origin {
+            let path = path.to_string().replace('\'', "h");
+            write!(f, "[{}{}]", fingerprint, path.trim_start_matches('m'))?;
+        }
+        write!(f, "{}", self.xpub)?;
+        for step in &self.steps {
+            write!(f, "/{}", step)?;
+        }
+        write!(f, "/*)")
+    }
+}
+
+impl FromStr for WatchOnlyDescriptor {
+    type Err = AnyaError;
+
+    fn from_str(s: &str) -> AnyaResult<Self> {
+        Self::parse(s)
+    }
+}
+
+fn invalid(descriptor: &str) -> AnyaError {
+    AnyaError::invalid_input(format!("malformed descriptor: {}", descriptor))
+}
+
+fn parse_origin(origin: &str) -> AnyaResult<KeySource> {
+    let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+    let fingerprint = Fingerprint::from_str(fingerprint)
+        .map_err(|_| AnyaError::invalid_input(format!("invalid key origin: [{}]", origin)))?;
+    let path = DerivationPath::from_str(format!("m/{}", path).trim_end_matches('/'))?;
+    Ok((fingerprint, path))
+}
+
+/// BIP-380 descriptor checksum of `body`
+pub fn checksum_of(body: &str) -> AnyaResult<String> {
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+    for ch in body.chars() {
+        let position = INPUT_CHARSET.find(ch).ok_or_else(|| {
+            AnyaError::invalid_input(format!("invalid descriptor character {:?}", ch))
+        })? as u64;
+        c = polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8)
+        .map(|j| char::from(CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize]))
+        .collect())
+}
+
+const fn polymod(c: u64, value: u64) -> u64 {
+    let top = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    if top & 1 != 0 {
+        c ^= 0xf5_dee5_1989;
+    }
+    if top & 2 != 0 {
+        c ^= 0xa9_fdca_3312;
+    }
+    if top & 4 != 0 {
+        c ^= 0x1b_ab10_e32d;
+    }
+    if top & 8 != 0 {
+        c ^= 0x37_06b1_677a;
+    }
+    if top & 16 != 0 {
+        c ^= 0x64_4d62_6ffd;
+    }
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP-84 account 0 of the "abandon ... about" seed
+    const BIP84: &str = "wpkh([73c5da0a/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*)";
+
+    #[test]
+    fn test_parse_and_derive() {
+        let descriptor = WatchOnlyDescriptor::parse(BIP84).unwrap();
+        assert_eq!(descriptor.script_type, ScriptType::NativeSegwit);
+        assert_eq!(descriptor.to_string(), BIP84);
+        let secp = Secp256k1::verification_only();
+        assert_eq!(
+            descriptor.address(&secp, 0).unwrap().to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+
+        let with_checksum = descriptor.to_string_with_checksum();
+        assert_eq!(
+            WatchOnlyDescriptor::parse(&with_checksum).unwrap(),
+            descriptor
+        );
+        let mut tampered = with_checksum.into_bytes();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'q' { b'p' } else { b'q' };
+        assert!(WatchOnlyDescriptor::parse(std::str::from_utf8(&tampered).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_checksum_vector_and_rejections() {
+        // BIP-380 test vector
+        assert_eq!(checksum_of("raw(deadbeef)").unwrap(), "89f8spxm");
+
+        let hardened = BIP84.replace("/0/*", "/0h/*");
+        assert!(WatchOnlyDescriptor::parse(&hardened).is_err());
+        let fixed = BIP84.replace("/0/*", "/0/5");
+        assert!(WatchOnlyDescriptor::parse(&fixed).is_err());
+        assert!(WatchOnlyDescriptor::parse("sh(multi(1,xpub))").is_err());
+    }
+}
@@ -0,0 +1,741 @@
+//! 2-of-3 multisig escrow
+//!
+//! A buyer pays into a P2WSH address locked by
+//! `wsh(sortedmulti(2,BUYER,SELLER,ARBITER))`. Any two parties can move the
+//! coin: buyer and seller together release it to the seller or refund it to
+//! the buyer, and if they fall out the arbiter decides the dispute and signs
+//! the payout with the party it favors.
+//!
+//! [`EscrowManager`] keeps each [`Escrow`] and its lifecycle in storage,
+//! builds the release, refund, and resolution PSBTs, and assembles the
+//! witness once two parties have signed. Every state change is appended to
+//! the event log under [`ESCROW_TOPIC`] and sent to each party through the
+//! registered [`EscrowNotifier`]s.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::bitcoin::absolute::LockTime;
+use ::bitcoin::blockdata::opcodes::all::OP_CHECKMULTISIG;
+use ::bitcoin::blockdata::script::Builder;
+use ::bitcoin::psbt::Psbt;
+use ::bitcoin::{
+    Address, FeeRate, Network, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness,
+};
+use async_trait::async_trait;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::descriptor::checksum_of;
+use super::fees::fee_for;
+use crate::events::EventStore;
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::to_hex;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Event log topic escrow state changes are appended under
+pub const ESCROW_TOPIC: &str = "wallet.escrow";
+
+const NAMESPACE: &str = "escrow";
+const ESCROW_PREFIX: &str = "escrow/";
+/// Witness weight of a DER signature with its sighash byte and length
+const SIGNATURE_WEIGHT: u64 = 73;
+
+/// A party to an escrow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscrowRole {
+    /// Pays into the escrow
+    Buyer,
+    /// Is paid on release
+    Seller,
+    /// Decides disputes
+    Arbiter,
+}
+
+/// A party's key and how to reach them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscrowParty {
+    /// Key signing for the party
+    pub key: PublicKey,
+    /// Where notifications go, e.g. a DID or Nostr public key
+    pub contact: String,
+}
+
+/// What the parties agreed to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscrowTerms {
+    /// Network the escrow lives on
+    pub network: Network,
+    /// Amount the buyer pays in, in satoshis
+    pub amount_sat: u64,
+    /// What is being bought
+    pub description: String,
+    /// Buyer
+    pub buyer: EscrowParty,
+    /// Seller
+    pub seller: EscrowParty,
+    /// Arbiter
+    pub arbiter: EscrowParty,
+    /// Seller's address for the release
+    pub seller_address: String,
+    /// Buyer's address for a refund
+    pub refund_address: String,
+}
+
+impl EscrowTerms {
+    /// The party in `role`
+    pub const fn party(&self, role: EscrowRole) -> &EscrowParty {
+        match role {
+            EscrowRole::Buyer => &self.buyer,
+            EscrowRole::Seller => &self.seller,
+            EscrowRole::Arbiter => &self.arbiter,
+        }
+    }
+
+    fn keys(&self) -> [PublicKey; 3] {
+        let mut keys = [self.buyer.key, self.seller.key, self.arbiter.key];
+        keys.sort_by_key(|k| k.to_bytes());
+        keys
+    }
+
+    /// Witness script of the escrow address
+    pub fn witness_script(&self) -> ScriptBuf {
+        let mut builder = Builder::new().push_int(2);
+        for key in self.keys() {
+            builder = builder.push_key(&key);
+        }
+        builder
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    /// Address the buyer pays into
+    pub fn address(&self) -> Address {
+        Address::p2wsh(&self.witness_script(), self.network)
+    }
+
+    /// `wsh(sortedmulti())` descriptor with its checksum, for watching the
+    /// escrow in other wallets
+    pub fn descriptor(&self) -> String {
+        let keys = [&self.buyer.key, &self.seller.key, &self.arbiter.key];
+        let body = format!("wsh(sortedmulti(2,{},{},{}))", keys[0], keys[1], keys[2]);
+        let checksum = checksum_of(&body).expect("rendered descriptors use the input charset");
+        format!("{}#{}", body, checksum)
+    }
+
+    fn validate(&self) -> AnyaResult<()> {
+        let keys = self.keys();
+        if keys[0] == keys[1] || keys[1] == keys[2] {
+            return Err(AnyaError::invalid_input(
+                "escrow parties need distinct keys",
+            ));
+        }
+        if self.amount_sat == 0 {
+            return Err(AnyaError::invalid_input("escrow amount must be positive"));
+        }
+        self.payout_script(&self.seller_address)?;
+        self.payout_script(&self.refund_address)?;
+        Ok(())
+    }
+
+    fn payout_script(&self, address: &str) -> AnyaResult<ScriptBuf> {
+        Ok(Address::from_str(address)?
+            .require_network(self.network)?
+            .script_pubkey())
+    }
+}
+
+/// Who receives the escrowed coin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Payout {
+    /// Everything, less the fee, to the seller
+    Release,
+    /// Everything, less the fee, to the buyer
+    Refund,
+    /// This much to the seller, the rest less the fee to the buyer
+    Split {
+        /// Seller's share in satoshis
+        seller_sat: u64,
+    },
+}
+
+/// Where an escrow is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum EscrowState {
+    /// Waiting for the buyer's payment
+    AwaitingFunding,
+    /// Coin locked in the escrow
+    Funded,
+    /// A party disputed; only the arbiter's resolution pays out
+    Disputed,
+    /// A payout PSBT was built and awaits two signatures
+    Settling {
+        /// Payout being signed
+        payout: Payout,
+    },
+    /// The payout transaction was assembled
+    Settled {
+        /// Payout made
+        payout: Payout,
+        /// Transaction paying it
+        txid: Txid,
+    },
+}
+
+/// The escrowed coin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscrowUtxo {
+    /// Output reference
+    pub outpoint: OutPoint,
+    /// Value and script
+    pub txout: TxOut,
+}
+
+/// A dispute raised by a party
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dispute {
+    /// Party that raised it
+    pub raised_by: EscrowRole,
+    /// Their reason
+    pub reason: String,
+    /// Unix time it was raised
+    pub raised_at: u64,
+}
+
+/// A state an escrow entered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateChange {
+    /// State entered
+    pub state: EscrowState,
+    /// Unix time
+    pub at: u64,
+}
+
+/// An escrow and its history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Escrow {
+    /// Random identifier
+    pub id: String,
+    /// Agreed terms
+    pub terms: EscrowTerms,
+    /// Current state
+    pub state: EscrowState,
+    /// Coin paid in, once funded
+    pub funding: Option<EscrowUtxo>,
+    /// Dispute, if one was raised
+    pub dispute: Option<Dispute>,
+    /// Every state entered, oldest first
+    pub history: Vec<StateChange>,
+}
+
+/// Payload of escrow events and notifications
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscrowEvent {
+    /// Escrow concerned
+    pub escrow_id: String,
+    /// State entered
+    pub state: EscrowState,
+    /// Human-readable summary
+    pub message: String,
+    /// Unix time
+    pub at: u64,
+}
+
+/// Delivers escrow events to a party
+#[async_trait]
+pub trait EscrowNotifier: Send + Sync {
+    /// Tell the party in `role`, reachable at `party.contact`, about `event`
+    async fn notify(
+        &self,
+        role: EscrowRole,
+        party: &EscrowParty,
+        event: &EscrowEvent,
+    ) -> AnyaResult<()>;
+}
+
+/// Escrows kept in storage
+pub struct EscrowManager {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    events: Arc<EventStore>,
+    notifiers: Vec<Arc<dyn EscrowNotifier>>,
+    write: Mutex<()>,
+}
+
+impl EscrowManager {
+    /// Open the escrows in `storage`, logging state changes to `events`
+    pub async fn open(
+        storage: Arc<dyn StorageBackend>,
+        events: Arc<EventStore>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            storage,
+            ns,
+            events,
+            notifiers: Vec::new(),
+            write: Mutex::new(()),
+        })
+    }
+
+    /// Add a notifier for state changes
+    pub fn add_notifier(&mut self, notifier: Arc<dyn EscrowNotifier>) {
+        self.notifiers.push(notifier);
+    }
+
+    /// Set up an escrow on `terms`; the buyer pays into
+    /// [`EscrowTerms::address`]
+    pub async fn create(&self, terms: EscrowTerms, now: u64) -> AnyaResult<Escrow> {
+        terms.validate()?;
+        let mut id = [0u8; 16];
+        SystemRandom::new()
+            .fill(&mut id)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "system randomness unavailable"))?;
+        let mut escrow = Escrow {
+            id: to_hex(&id),
+            terms,
+            state: EscrowState::AwaitingFunding,
+            funding: None,
+            dispute: None,
+            history: Vec::new(),
+        };
+        let message = format!(
+            "escrow of {} sat for {} created; pay to {}",
+            escrow.terms.amount_sat,
+            escrow.terms.description,
+            escrow.terms.address()
+        );
+        self.transition(&mut escrow, EscrowState::AwaitingFunding, message, now)
+            .await?;
+        Ok(escrow)
+    }
+
+    /// An escrow by id
+    pub async fn escrow(&self, id: &str) -> AnyaResult<Escrow> {
+        let bytes = self
+            .storage
+            .get(&self.ns, &format!("{}{}", ESCROW_PREFIX, id))
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("escrow {}", id)))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Record the buyer's payment into the escrow
+    pub async fn fund(&self, id: &str, utxo: EscrowUtxo, now: u64) -> AnyaResult<Escrow> {
+        let _guard = self.write.lock().await;
+        let mut escrow = self.escrow(id).await?;
+        expect_state(&escrow, &[EscrowState::AwaitingFunding])?;
+        if utxo.txout.script_pubkey != escrow.terms.address().script_pubkey() {
+            return Err(AnyaError::invalid_input(
+                "coin is not locked to the escrow address",
+            ));
+        }
+        if utxo.txout.value < escrow.terms.amount_sat {
+            return Err(AnyaError::invalid_input(format!(
+                "escrow needs {} sat, coin holds {} sat",
+                escrow.terms.amount_sat, utxo.txout.value
+            )));
+        }
+        let message = format!("escrow funded by {}", utxo.outpoint);
+        escrow.funding = Some(utxo);
+        self.transition(&mut escrow, EscrowState::Funded, message, now)
+            .await?;
+        Ok(escrow)
+    }
+
+    /// Build the PSBT paying the seller, for buyer and seller to sign
+    pub async fn release(&self, id: &str, fee_rate: FeeRate, now: u64) -> AnyaResult<Psbt> {
+        self.settle(id, Payout::Release, &[EscrowState::Funded], fee_rate, now)
+            .await
+    }
+
+    /// Build the PSBT refunding the buyer, for buyer and seller to sign
+    pub async fn refund(&self, id: &str, fee_rate: FeeRate, now: u64) -> AnyaResult<Psbt> {
+        self.settle(id, Payout::Refund, &[EscrowState::Funded], fee_rate, now)
+            .await
+    }
+
+    /// Dispute a funded escrow, or one whose payout is still unsigned.
+    /// Only [`Self::resolve`] can pay out afterwards.
+    pub async fn dispute(
+        &self,
+        id: &str,
+        raised_by: EscrowRole,
+        reason: &str,
+        now: u64,
+    ) -> AnyaResult<Escrow> {
+        if raised_by == EscrowRole::Arbiter {
+            return Err(AnyaError::invalid_input("only buyer or seller can dispute"));
+        }
+        let _guard = self.write.lock().await;
+        let mut escrow = self.escrow(id).await?;
+        if !matches!(
+            escrow.state,
+            EscrowState::Funded | EscrowState::Settling { .. }
+        ) {
+            return Err(state_conflict(&escrow));
+        }
+        escrow.dispute = Some(Dispute {
+            raised_by,
+            reason: reason.to_string(),
+            raised_at: now,
+        });
+        let message = format!("{:?} disputed the escrow: {}", raised_by, reason);
+        self.transition(&mut escrow, EscrowState::Disputed, message, now)
+            .await?;
+        Ok(escrow)
+    }
+
+    /// The arbiter's decision on a disputed escrow: build the PSBT paying
+    /// out per `payout`, for the arbiter and the favored party to sign
+    pub async fn resolve(
+        &self,
+        id: &str,
+        payout: Payout,
+        fee_rate: FeeRate,
+        now: u64,
+    ) -> AnyaResult<Psbt> {
+        self.settle(id, payout, &[EscrowState::Disputed], fee_rate, now)
+            .await
+    }
+
+    /// Assemble the witness of a payout PSBT signed by two parties and
+    /// extract the transaction; broadcasting is up to the caller
+    pub async fn finalize(&self, id: &str, mut psbt: Psbt, now: u64) -> AnyaResult<Transaction> {
+        let _guard = self.write.lock().await;
+        let mut escrow = self.escrow(id).await?;
+        let EscrowState::Settling { payout } = escrow.state else {
+            return Err(state_conflict(&escrow));
+        };
+        let script = escrow.terms.witness_script();
+        let input = psbt
+            .inputs
+            .first_mut()
+            .ok_or_else(|| AnyaError::invalid_input("payout PSBT has no input"))?;
+        // CHECKMULTISIG pops one element too many
+        let mut witness = Witness::new();
+        witness.push([0u8; 0]);
+        let mut signed = 0;
+        for key in escrow.terms.keys() {
+            if let Some(sig) = input.partial_sigs.get(&key).filter(|_| signed < 2) {
+                witness.push(sig.to_vec());
+                signed += 1;
+            }
+        }
+        if signed < 2 {
+            return Err(AnyaError::invalid_input(format!(
+                "escrow payout has {} of 2 signatures",
+                signed
+            )));
+        }
+        witness.push(script.as_bytes());
+        input.final_script_witness = Some(witness);
+        input.partial_sigs.clear();
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        let message = format!("payout {:?} signed in {}", payout, txid);
+        self.transition(
+            &mut escrow,
+            EscrowState::Settled { payout, txid },
+            message,
+            now,
+        )
+        .await?;
+        Ok(tx)
+    }
+
+    async fn settle(
+        &self,
+        id: &str,
+        payout: Payout,
+        allowed: &[EscrowState],
+        fee_rate: FeeRate,
+        now: u64,
+    ) -> AnyaResult<Psbt> {
+        let _guard = self.write.lock().await;
+        let mut escrow = self.escrow(id).await?;
+        expect_state(&escrow, allowed)?;
+        let psbt = payout_psbt(&escrow, payout, fee_rate)?;
+        let message = format!("payout {:?} awaiting signatures", payout);
+        self.transition(&mut escrow, EscrowState::Settling { payout }, message, now)
+            .await?;
+        Ok(psbt)
+    }
+
+    /// Enter `state`, then log and notify every party
+    async fn transition(
+        &self,
+        escrow: &mut Escrow,
+        state: EscrowState,
+        message: String,
+        now: u64,
+    ) -> AnyaResult<()> {
+        escrow.state = state;
+        escrow.history.push(StateChange { state, at: now });
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", ESCROW_PREFIX, escrow.id),
+                &serde_json::to_vec(escrow)?,
+            )
+            .await?;
+        let event = EscrowEvent {
+            escrow_id: escrow.id.clone(),
+            state,
+            message,
+            at: now,
+        };
+        self.events
+            .append(
+                ESCROW_TOPIC,
+                state_name(state),
+                serde_json::to_value(&event)?,
+            )
+            .await?;
+        info!(escrow = %escrow.id, state = state_name(state), "escrow state changed");
+        for role in [EscrowRole::Buyer, EscrowRole::Seller, EscrowRole::Arbiter] {
+            for notifier in &self.notifiers {
+                if let Err(e) = notifier
+                    .notify(role, escrow.terms.party(role), &event)
+                    .await
+                {
+                    warn!(escrow = %escrow.id, ?role, error = %e, "escrow notification failed");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+const fn state_name(state: EscrowState) -> &'static str {
+    match state {
+        EscrowState::AwaitingFunding => "awaiting_funding",
+        EscrowState::Funded => "funded",
+        EscrowState::Disputed => "disputed",
+        EscrowState::Settling { .. } => "settling",
+        EscrowState::Settled { .. } => "settled",
+    }
+}
+
+fn state_conflict(escrow: &Escrow) -> AnyaError {
+    AnyaError::new(
+        ErrorCode::Conflict,
+        format!(
+            "escrow {} is {}",
+            escrow.id,
+            state_name(escrow.state).replace('_', " ")
+        ),
+    )
+}
+
+fn expect_state(escrow: &Escrow, allowed: &[EscrowState]) -> AnyaResult<()> {
+    if allowed.contains(&escrow.state) {
+        Ok(())
+    } else {
+        Err(state_conflict(escrow))
+    }
+}
+
+/// Unsigned transaction spending the escrowed coin per `payout`
+fn payout_psbt(escrow: &Escrow, payout: Payout, fee_rate: FeeRate) -> AnyaResult<Psbt> {
+    let terms = &escrow.terms;
+    let funding = escrow
+        .funding
+        .as_ref()
+        .ok_or_else(|| AnyaError::invalid_input("escrow is not funded"))?;
+    let seller = terms.payout_script(&terms.seller_address)?;
+    let buyer = terms.payout_script(&terms.refund_address)?;
+    let outputs = match payout {
+        Payout::Release => vec![(seller, None)],
+        Payout::Refund => vec![(buyer, None)],
+        Payout::Split { seller_sat } => vec![(seller, Some(seller_sat)), (buyer, None)],
+    };
+    let mut tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: funding.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: outputs
+            .iter()
+            .map(|(script, _)| TxOut {
+                value: 0,
+                script_pubkey: script.clone(),
+            })
+            .collect(),
+    };
+    let script_len = terms.witness_script().len() as u64;
+    // Segwit marker and flag, item count, multisig dummy, two signatures,
+    // and the script
+    let weight = tx.weight().to_wu() + 2 + 1 + 1 + 2 * SIGNATURE_WEIGHT + 1 + script_len;
+    let fee = fee_for(fee_rate, weight);
+    let mut remaining = funding.txout.value.checked_sub(fee).ok_or_else(|| {
+        AnyaError::invalid_input(format!(
+            "escrowed {} sat cannot pay a fee of {} sat",
+            funding.txout.value, fee
+        ))
+    })?;
+    for (output, (script, fixed)) in tx.output.iter_mut().zip(outputs) {
+        output.value = fixed.unwrap_or(remaining);
+        remaining = remaining.checked_sub(output.value).ok_or_else(|| {
+            AnyaError::invalid_input("seller's share exceeds the escrowed amount")
+        })?;
+        if output.value < TxOut::minimal_non_dust(script).value {
+            return Err(AnyaError::invalid_input(format!(
+                "payout output of {} sat would be dust",
+                output.value
+            )));
+        }
+    }
+    let mut psbt = Psbt::from_unsigned_tx(tx)?;
+    psbt.inputs[0].witness_utxo = Some(funding.txout.clone());
+    psbt.inputs[0].witness_script = Some(terms.witness_script());
+    Ok(psbt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventStoreConfig;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use ::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+    use std::sync::Mutex as StdMutex;
+
+    struct Recorder(StdMutex<Vec<(EscrowRole, String)>>);
+
+    #[async_trait]
+    impl EscrowNotifier for Recorder {
+        async fn notify(
+            &self,
+            role: EscrowRole,
+            _party: &EscrowParty,
+            event: &EscrowEvent,
+        ) -> AnyaResult<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((role, state_name(event.state).to_string()));
+            Ok(())
+        }
+    }
+
+    fn secret(n: u8) -> SecretKey {
+        SecretKey::from_slice(&[n; 32]).unwrap()
+    }
+
+    fn party(n: u8) -> EscrowParty {
+        EscrowParty {
+            key: PublicKey::new(secret(n).public_key(&Secp256k1::new())),
+            contact: format!("party-{}", n),
+        }
+    }
+
+    fn address(n: u8) -> String {
+        let key = PublicKey::new(secret(n).public_key(&Secp256k1::new()));
+        Address::p2wpkh(&key, Network::Regtest).unwrap().to_string()
+    }
+
+    fn sign(psbt: &mut Psbt, n: u8) {
+        let secp = Secp256k1::new();
+        let input = &psbt.inputs[0];
+        let script = input.witness_script.clone().unwrap();
+        let value = input.witness_utxo.as_ref().unwrap().value;
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .segwit_signature_hash(0, &script, value, EcdsaSighashType::All)
+            .unwrap();
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        let signature =
+            ::bitcoin::ecdsa::Signature::sighash_all(secp.sign_ecdsa(&message, &secret(n)));
+        psbt.inputs[0].partial_sigs.insert(party(n).key, signature);
+    }
+
+    #[tokio::test]
+    async fn disputed_escrow_is_split_by_arbiter() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let events = EventStore::open(EventStoreConfig::default(), Arc::clone(&storage))
+            .await
+            .unwrap();
+        let mut manager = EscrowManager::open(storage, Arc::clone(&events))
+            .await
+            .unwrap();
+        let recorder = Arc::new(Recorder(StdMutex::new(Vec::new())));
+        manager.add_notifier(recorder.clone());
+
+        let terms = EscrowTerms {
+            network: Network::Regtest,
+            amount_sat: 100_000,
+            description: "laptop".into(),
+            buyer: party(1),
+            seller: party(2),
+            arbiter: party(3),
+            seller_address: address(4),
+            refund_address: address(5),
+        };
+        assert!(terms.descriptor().starts_with("wsh(sortedmulti(2,"));
+        let escrow = manager.create(terms.clone(), 0).await.unwrap();
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let err = manager.release(&escrow.id, fee_rate, 1).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+
+        let utxo = EscrowUtxo {
+            outpoint: OutPoint::new(Txid::from_byte_array([9; 32]), 0),
+            txout: TxOut {
+                value: 100_000,
+                script_pubkey: terms.address().script_pubkey(),
+            },
+        };
+        manager.fund(&escrow.id, utxo, 10).await.unwrap();
+        manager.release(&escrow.id, fee_rate, 20).await.unwrap();
+        manager
+            .dispute(&escrow.id, EscrowRole::Buyer, "arrived broken", 30)
+            .await
+            .unwrap();
+        assert!(manager.release(&escrow.id, fee_rate, 31).await.is_err());
+
+        let mut psbt = manager
+            .resolve(
+                &escrow.id,
+                Payout::Split { seller_sat: 40_000 },
+                fee_rate,
+                40,
+            )
+            .await
+            .unwrap();
+        sign(&mut psbt, 3);
+        assert!(manager
+            .finalize(&escrow.id, psbt.clone(), 50)
+            .await
+            .is_err());
+        sign(&mut psbt, 1);
+        let tx = manager.finalize(&escrow.id, psbt, 50).await.unwrap();
+        assert_eq!(tx.output[0].value, 40_000);
+        assert!(tx.output[1].value > 59_000);
+        assert_eq!(tx.input[0].witness.len(), 4);
+
+        let escrow = manager.escrow(&escrow.id).await.unwrap();
+        assert_eq!(
+            escrow.state,
+            EscrowState::Settled {
+                payout: Payout::Split { seller_sat: 40_000 },
+                txid: tx.txid()
+            }
+        );
+        assert_eq!(escrow.history.len(), 6);
+        // Every party hears about every state change
+        assert_eq!(recorder.0.lock().unwrap().len(), 18);
+        assert_eq!(events.head().await, 6);
+    }
+}
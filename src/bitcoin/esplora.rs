@@ -0,0 +1,596 @@
+//! Esplora-compatible REST API over the internal indexes
+//!
+//! [`EsploraApi`] answers the block, transaction, address, and mempool
+//! endpoints of the Esplora HTTP API from the node's
+//! [`ChainIndex`], so explorer frontends and wallet libraries written
+//! against Blockstream's or mempool.space's API can use an Anya node as a
+//! drop-in data source. Paths are relative to the API root, e.g.
+//! `/block-height/800000` or `/address/<address>/utxo`.
+//!
+//! The handler is independent of the HTTP server: [`EsploraApi::handle`]
+//! maps a method, path, and body to an [`HttpResponse`]. The API also
+//! implements [`HttpTransport`], so an
+//! [`EsploraProvider`](super::provider::EsploraProvider) can query a node
+//! in process. As upstream, list endpoints page 25 confirmed transactions
+//! at a time, newest first, and `/blocks` returns ten blocks.
+
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::bitcoin::address::NetworkUnchecked;
+use ::bitcoin::consensus::encode::{deserialize, serialize};
+use ::bitcoin::{Address, BlockHash, Network, OutPoint, ScriptBuf, Transaction, Txid};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::index::{script_hash, ChainIndex};
+use super::tracker::{ChainSource, TxStatus};
+use crate::integrations::{HttpRequest, HttpResponse, HttpTransport};
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Confirmed transactions per page of a transaction list
+pub const TXS_PER_PAGE: usize = 25;
+/// Unconfirmed transactions listed before the confirmed ones
+pub const MEMPOOL_TXS_LIMIT: usize = 50;
+/// Blocks per `/blocks` page
+pub const BLOCKS_PER_PAGE: u32 = 10;
+
+/// Esplora REST endpoints served from a [`ChainIndex`]
+pub struct EsploraApi {
+    index: Arc<ChainIndex>,
+    network: Network,
+}
+
+enum Body {
+    Text(String),
+    Json(Value),
+    Raw(Vec<u8>),
+}
+
+/// A script queried by address or script hash
+struct ScriptQuery {
+    hash: String,
+    script: Option<ScriptBuf>,
+}
+
+impl EsploraApi {
+    /// API over `index` for addresses on `network`
+    pub const fn new(index: Arc<ChainIndex>, network: Network) -> Self {
+        Self { index, network }
+    }
+
+    /// Answer `method path` with `body`; errors become Esplora-style
+    /// plain-text responses
+    pub async fn handle(&self, method: &str, path: &str, body: &[u8]) -> HttpResponse {
+        let path = path.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let (status, body) = match self.route(method, &segments, body).await {
+            Ok(body) => (200, body),
+            Err(e) => (e.code().http_status(), Body::Text(e.to_string())),
+        };
+        let (content_type, body) = match body {
+            Body::Text(text) => ("text/plain", text.into_bytes()),
+            Body::Json(value) => (
+                "application/json",
+                serde_json::to_vec(&value).unwrap_or_default(),
+            ),
+            Body::Raw(bytes) => ("application/octet-stream", bytes),
+        };
+        HttpResponse {
+            status,
+            headers: BTreeMap::from([("content-type".to_string(), content_type.to_string())]),
+            body,
+        }
+    }
+
+    async fn route(&self, method: &str, segments: &[&str], body: &[u8]) -> AnyaResult<Body> {
+        match (method, segments) {
+            ("POST", ["tx"]) => self.broadcast(body).map(Body::Text),
+            ("GET", ["blocks", "tip", "height"]) => {
+                Ok(Body::Text(self.tip_height().await?.to_string()))
+            }
+            ("GET", ["blocks", "tip", "hash"]) => {
+                let height = self.tip_height().await?;
+                Ok(Body::Text(self.hash_at(height).await?.to_string()))
+            }
+            ("GET", ["blocks"]) => self.blocks(None).await.map(Body::Json),
+            ("GET", ["blocks", start]) => self.blocks(Some(parse(start)?)).await.map(Body::Json),
+            ("GET", ["block-height", height]) => {
+                Ok(Body::Text(self.hash_at(parse(height)?).await?.to_string()))
+            }
+            ("GET", ["block", hash, rest @ ..]) => self.block(parse(hash)?, rest).await,
+            ("GET", ["tx", txid, rest @ ..]) => self.tx(parse(txid)?, rest).await,
+            ("GET", ["address", address, rest @ ..]) => {
+                let address = Address::<NetworkUnchecked>::from_str(address)
+                    .map_err(|e| AnyaError::invalid_input(format!("invalid address: {}", e)))?
+                    .require_network(self.network)
+                    .map_err(|e| AnyaError::invalid_input(e.to_string()))?;
+                let script = address.script_pubkey();
+                let query = ScriptQuery {
+                    hash: script_hash(&script),
+                    script: Some(script),
+                };
+                self.script(&query, rest, json!({ "address": address.to_string() }))
+                    .await
+            }
+            ("GET", ["scripthash", hash, rest @ ..]) => {
+                if hash.len() != 64 || from_hex(hash).is_err() {
+                    return Err(AnyaError::invalid_input("invalid script hash"));
+                }
+                let query = ScriptQuery {
+                    hash: hash.to_lowercase(),
+                    script: None,
+                };
+                self.script(&query, rest, json!({ "scripthash": query.hash }))
+                    .await
+            }
+            ("GET", ["mempool", "txids"]) => Ok(Body::Json(json!(self
+                .index
+                .mempool_transactions()
+                .iter()
+                .map(|tx| tx.txid().to_string())
+                .collect::<Vec<_>>()))),
+            _ => Err(AnyaError::not_found("endpoint not found")),
+        }
+    }
+
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        self.index
+            .tip()
+            .await?
+            .map(|tip| tip.height)
+            .ok_or_else(|| AnyaError::new(ErrorCode::Unavailable, "no blocks indexed yet"))
+    }
+
+    async fn hash_at(&self, height: u32) -> AnyaResult<BlockHash> {
+        self.index
+            .block_hash(height)
+            .await?
+            .ok_or_else(|| AnyaError::not_found("Block not found"))
+    }
+
+    async fn blocks(&self, start: Option<u32>) -> AnyaResult<Value> {
+        let tip = self.tip_height().await?;
+        let start = start.unwrap_or(tip).min(tip);
+        let mut blocks = Vec::new();
+        for height in (start.saturating_sub(BLOCKS_PER_PAGE - 1)..=start).rev() {
+            blocks.push(self.block_json(&self.hash_at(height).await?).await?);
+        }
+        Ok(Value::Array(blocks))
+    }
+
+    async fn block_json(&self, hash: &BlockHash) -> AnyaResult<Value> {
+        let meta = self
+            .index
+            .block_meta(hash)
+            .await?
+            .ok_or_else(|| AnyaError::not_found("Block not found"))?;
+        let block = self.load_block(hash).await?;
+        let header = block.header;
+        Ok(json!({
+            "id": hash.to_string(),
+            "height": meta.height,
+            "version": header.version.to_consensus(),
+            "timestamp": header.time,
+            "tx_count": meta.tx_count,
+            "size": meta.size,
+            "weight": meta.weight,
+            "merkle_root": header.merkle_root.to_string(),
+            "previousblockhash": (meta.height > 0).then(|| header.prev_blockhash.to_string()),
+            "nonce": header.nonce,
+            "bits": header.bits.to_consensus(),
+        }))
+    }
+
+    async fn load_block(&self, hash: &BlockHash) -> AnyaResult<::bitcoin::Block> {
+        self.index
+            .block(hash)
+            .await?
+            .ok_or_else(|| AnyaError::not_found("Block not found"))
+    }
+
+    async fn block(&self, hash: BlockHash, rest: &[&str]) -> AnyaResult<Body> {
+        match rest {
+            [] => self.block_json(&hash).await.map(Body::Json),
+            ["header"] => Ok(Body::Text(to_hex(&serialize(
+                &self.load_block(&hash).await?.header,
+            )))),
+            ["raw"] => Ok(Body::Raw(serialize(&self.load_block(&hash).await?))),
+            ["status"] => {
+                let Some(meta) = self.index.block_meta(&hash).await? else {
+                    return Ok(Body::Json(json!({ "in_best_chain": false })));
+                };
+                let next = self.index.block_hash(meta.height + 1).await?;
+                Ok(Body::Json(json!({
+                    "in_best_chain": true,
+                    "height": meta.height,
+                    "next_best": next.map(|h| h.to_string()),
+                })))
+            }
+            ["txids"] => Ok(Body::Json(json!(self
+                .load_block(&hash)
+                .await?
+                .txdata
+                .iter()
+                .map(|tx| tx.txid().to_string())
+                .collect::<Vec<_>>()))),
+            ["txid", index] => {
+                let index: usize = parse(index)?;
+                let block = self.load_block(&hash).await?;
+                let tx = block
+                    .txdata
+                    .get(index)
+                    .ok_or_else(|| AnyaError::not_found("Transaction not found"))?;
+                Ok(Body::Text(tx.txid().to_string()))
+            }
+            ["txs"] | ["txs", _] => {
+                let start: usize = rest.get(1).map_or(Ok(0), |s| parse(s))?;
+                if start % TXS_PER_PAGE != 0 {
+                    return Err(AnyaError::invalid_input(format!(
+                        "start index must be a multiple of {}",
+                        TXS_PER_PAGE
+                    )));
+                }
+                let block = self.load_block(&hash).await?;
+                let mut txs = Vec::new();
+                for tx in block.txdata.iter().skip(start).take(TXS_PER_PAGE) {
+                    txs.push(self.tx_json(tx).await?);
+                }
+                Ok(Body::Json(Value::Array(txs)))
+            }
+            _ => Err(AnyaError::not_found("endpoint not found")),
+        }
+    }
+
+    async fn load_tx(&self, txid: &Txid) -> AnyaResult<Transaction> {
+        self.index
+            .transaction(txid)
+            .await?
+            .ok_or_else(|| AnyaError::not_found("Transaction not found"))
+    }
+
+    async fn tx(&self, txid: Txid, rest: &[&str]) -> AnyaResult<Body> {
+        match rest {
+            [] => {
+                let tx = self.load_tx(&txid).await?;
+                self.tx_json(&tx).await.map(Body::Json)
+            }
+            ["hex"] => Ok(Body::Text(to_hex(&serialize(&self.load_tx(&txid).await?)))),
+            ["raw"] => Ok(Body::Raw(serialize(&self.load_tx(&txid).await?))),
+            ["status"] => {
+                if self.index.status(&txid).await? == TxStatus::Unknown {
+                    return Err(AnyaError::not_found("Transaction not found"));
+                }
+                self.status_json(&txid).await.map(Body::Json)
+            }
+            ["outspend", vout] => self
+                .outspend_json(&OutPoint::new(txid, parse(vout)?))
+                .await
+                .map(Body::Json),
+            ["outspends"] => {
+                let tx = self.load_tx(&txid).await?;
+                let mut spends = Vec::new();
+                for vout in 0..tx.output.len() {
+                    let vout = u32::try_from(vout).unwrap_or(u32::MAX);
+                    spends.push(self.outspend_json(&OutPoint::new(txid, vout)).await?);
+                }
+                Ok(Body::Json(Value::Array(spends)))
+            }
+            _ => Err(AnyaError::not_found("endpoint not found")),
+        }
+    }
+
+    async fn status_json(&self, txid: &Txid) -> AnyaResult<Value> {
+        let Some(location) = self.index.tx_location(txid).await? else {
+            return Ok(json!({ "confirmed": false }));
+        };
+        let time = self
+            .index
+            .block_meta(&location.block_hash)
+            .await?
+            .map(|meta| meta.time);
+        Ok(json!({
+            "confirmed": true,
+            "block_height": location.height,
+            "block_hash": location.block_hash.to_string(),
+            "block_time": time,
+        }))
+    }
+
+    async fn outspend_json(&self, outpoint: &OutPoint) -> AnyaResult<Value> {
+        let Some(spender) = self.index.spender(outpoint).await? else {
+            return Ok(json!({ "spent": false }));
+        };
+        let tx = self.load_tx(&spender).await?;
+        let vin = tx.input.iter().position(|i| i.previous_output == *outpoint);
+        Ok(json!({
+            "spent": true,
+            "txid": spender.to_string(),
+            "vin": vin,
+            "status": self.status_json(&spender).await?,
+        }))
+    }
+
+    fn output_json(&self, output: &::bitcoin::TxOut) -> Value {
+        json!({
+            "scriptpubkey": to_hex(output.script_pubkey.as_bytes()),
+            "scriptpubkey_address": Address::from_script(&output.script_pubkey, self.network)
+                .ok()
+                .map(|a| a.to_string()),
+            "value": output.value,
+        })
+    }
+
+    async fn tx_json(&self, tx: &Transaction) -> AnyaResult<Value> {
+        let mut vin = Vec::new();
+        let mut input_value = Some(0_u64);
+        for input in &tx.input {
+            let coinbase = tx.is_coin_base();
+            let prevout = if coinbase {
+                None
+            } else {
+                self.index.output(&input.previous_output).await?
+            };
+            input_value = input_value
+                .zip(prevout.as_ref())
+                .map(|(sum, prevout)| sum + prevout.value);
+            vin.push(json!({
+                "txid": input.previous_output.txid.to_string(),
+                "vout": input.previous_output.vout,
+                "prevout": prevout.as_ref().map(|o| self.output_json(o)),
+                "scriptsig": to_hex(input.script_sig.as_bytes()),
+                "witness": input.witness.iter().map(to_hex).collect::<Vec<_>>(),
+                "is_coinbase": coinbase,
+                "sequence": input.sequence.0,
+            }));
+        }
+        let output_value: u64 = tx.output.iter().map(|o| o.value).sum();
+        let fee = if tx.is_coin_base() {
+            Some(0)
+        } else {
+            input_value.map(|inputs| inputs.saturating_sub(output_value))
+        };
+        Ok(json!({
+            "txid": tx.txid().to_string(),
+            "version": tx.version,
+            "locktime": tx.lock_time.to_consensus_u32(),
+            "vin": vin,
+            "vout": tx.output.iter().map(|o| self.output_json(o)).collect::<Vec<_>>(),
+            "size": tx.size(),
+            "weight": tx.weight().to_wu(),
+            "fee": fee,
+            "status": self.status_json(&tx.txid()).await?,
+        }))
+    }
+
+    /// Confirmed txids touching the script, newest first
+    async fn chain_txids(&self, query: &ScriptQuery) -> AnyaResult<Vec<Txid>> {
+        let mut txids: Vec<Txid> = self
+            .index
+            .history(&query.hash)
+            .await?
+            .into_iter()
+            .rev()
+            .map(|(_, txid)| txid)
+            .collect();
+        txids.dedup();
+        Ok(txids)
+    }
+
+    async fn mempool_txids(&self, query: &ScriptQuery) -> AnyaResult<Vec<Txid>> {
+        match &query.script {
+            Some(script) => self.index.mempool_history(script).await,
+            // Without the script, match the hashes of spent and created
+            // outputs in the mempool
+            None => {
+                let mut txids = Vec::new();
+                for tx in self.index.mempool_transactions() {
+                    let mut scripts: Vec<ScriptBuf> =
+                        tx.output.iter().map(|o| o.script_pubkey.clone()).collect();
+                    for input in &tx.input {
+                        if let Some(spent) = self.index.output(&input.previous_output).await? {
+                            scripts.push(spent.script_pubkey);
+                        }
+                    }
+                    if scripts.iter().any(|s| script_hash(s) == query.hash) {
+                        txids.push(tx.txid());
+                    }
+                }
+                Ok(txids)
+            }
+        }
+    }
+
+    async fn script(&self, query: &ScriptQuery, rest: &[&str], mut id: Value) -> AnyaResult<Body> {
+        match rest {
+            [] => {
+                id["chain_stats"] = self.stats(query, &self.chain_txids(query).await?).await?;
+                id["mempool_stats"] = self.stats(query, &self.mempool_txids(query).await?).await?;
+                Ok(Body::Json(id))
+            }
+            ["txs"] => {
+                let mut txids = self.mempool_txids(query).await?;
+                txids.truncate(MEMPOOL_TXS_LIMIT);
+                txids.extend(
+                    self.chain_txids(query)
+                        .await?
+                        .into_iter()
+                        .take(TXS_PER_PAGE),
+                );
+                self.txs_json(&txids).await.map(Body::Json)
+            }
+            ["txs", "mempool"] => {
+                let mut txids = self.mempool_txids(query).await?;
+                txids.truncate(MEMPOOL_TXS_LIMIT);
+                self.txs_json(&txids).await.map(Body::Json)
+            }
+            ["txs", "chain"] | ["txs", "chain", _] => {
+                let chain = self.chain_txids(query).await?;
+                let skip = match rest.get(2) {
+                    Some(last) => {
+                        let last: Txid = parse(last)?;
+                        chain.iter().position(|t| *t == last).map_or(0, |i| i + 1)
+                    }
+                    None => 0,
+                };
+                let page: Vec<Txid> = chain.into_iter().skip(skip).take(TXS_PER_PAGE).collect();
+                self.txs_json(&page).await.map(Body::Json)
+            }
+            ["utxo"] => self.utxos(query).await.map(Body::Json),
+            _ => Err(AnyaError::not_found("endpoint not found")),
+        }
+    }
+
+    async fn txs_json(&self, txids: &[Txid]) -> AnyaResult<Value> {
+        let mut txs = Vec::new();
+        for txid in txids {
+            txs.push(self.tx_json(&self.load_tx(txid).await?).await?);
+        }
+        Ok(Value::Array(txs))
+    }
+
+    /// Outputs to the script created and spent by `txids`
+    async fn stats(&self, query: &ScriptQuery, txids: &[Txid]) -> AnyaResult<Value> {
+        let (mut funded, mut funded_sum, mut spent, mut spent_sum) = (0_u64, 0_u64, 0_u64, 0_u64);
+        for txid in txids {
+            let tx = self.load_tx(txid).await?;
+            for output in &tx.output {
+                if script_hash(&output.script_pubkey) == query.hash {
+                    funded += 1;
+                    funded_sum += output.value;
+                }
+            }
+            for input in &tx.input {
+                if let Some(prevout) = self.index.output(&input.previous_output).await? {
+                    if script_hash(&prevout.script_pubkey) == query.hash {
+                        spent += 1;
+                        spent_sum += prevout.value;
+                    }
+                }
+            }
+        }
+        Ok(json!({
+            "funded_txo_count": funded,
+            "funded_txo_sum": funded_sum,
+            "spent_txo_count": spent,
+            "spent_txo_sum": spent_sum,
+            "tx_count": txids.len(),
+        }))
+    }
+
+    async fn utxos(&self, query: &ScriptQuery) -> AnyaResult<Value> {
+        let mut txids = self.mempool_txids(query).await?;
+        txids.extend(self.chain_txids(query).await?);
+        let mut seen = HashSet::new();
+        let mut utxos = Vec::new();
+        for txid in txids.into_iter().filter(|t| seen.insert(*t)) {
+            let tx = self.load_tx(&txid).await?;
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, u32::try_from(vout).unwrap_or(u32::MAX));
+                if script_hash(&output.script_pubkey) != query.hash
+                    || self.index.spender(&outpoint).await?.is_some()
+                {
+                    continue;
+                }
+                utxos.push(json!({
+                    "txid": txid.to_string(),
+                    "vout": outpoint.vout,
+                    "value": output.value,
+                    "status": self.status_json(&txid).await?,
+                }));
+            }
+        }
+        Ok(Value::Array(utxos))
+    }
+
+    fn broadcast(&self, body: &[u8]) -> AnyaResult<String> {
+        let hex = std::str::from_utf8(body)
+            .map_err(|_| AnyaError::invalid_input("transaction must be hex"))?;
+        let tx: Transaction = deserialize(&from_hex(hex.trim())?)
+            .map_err(|e| AnyaError::invalid_input(format!("invalid transaction: {}", e)))?;
+        Ok(self.index.accept_to_mempool(tx)?.to_string())
+    }
+}
+
+fn parse<T: FromStr>(segment: &str) -> AnyaResult<T> {
+    segment
+        .parse()
+        .map_err(|_| AnyaError::invalid_input(format!("invalid path segment {}", segment)))
+}
+
+#[async_trait]
+impl HttpTransport for EsploraApi {
+    async fn send(&self, request: HttpRequest) -> AnyaResult<HttpResponse> {
+        let path = request
+            .url
+            .split_once("://")
+            .map_or(request.url.as_str(), |(_, rest)| {
+                rest.find('/').map_or("/", |i| &rest[i..])
+            });
+        Ok(self
+            .handle(
+                &request.method,
+                path,
+                request.body.as_deref().unwrap_or_default(),
+            )
+            .await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index::tests::{indexed_chain, tx};
+    use super::super::provider::{ChainDataProvider, EsploraProvider};
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_esplora_endpoints_from_the_index() {
+        let (index, _, bob) = indexed_chain().await;
+        let index = Arc::new(index);
+        let api = Arc::new(EsploraApi::new(Arc::clone(&index), Network::Regtest));
+        let get = |path: &'static str| {
+            let api = Arc::clone(&api);
+            async move {
+                let response = api.handle("GET", path, &[]).await;
+                (
+                    response.status,
+                    serde_json::from_slice::<Value>(&response.body).ok(),
+                )
+            }
+        };
+        let (_, blocks) = get("/blocks").await;
+        assert_eq!(blocks.unwrap().as_array().unwrap().len(), 2);
+        assert_eq!(get("/block-height/7").await.0, 404);
+        assert_eq!(get("/address/not-an-address").await.0, 400);
+
+        // A provider pointed at the API sees the same chain as the index
+        let esplora = EsploraProvider::new("http://node", api.clone());
+        let payment = index.script_history(&bob).await.unwrap()[0];
+        assert_eq!(esplora.script_history(&bob).await.unwrap(), [payment]);
+        assert_eq!(
+            esplora.block_hash(1).await.unwrap(),
+            index.block_hash(1).await.unwrap()
+        );
+        let coin = OutPoint::new(payment, 0);
+        assert_eq!(esplora.spender(&coin).await.unwrap(), None);
+
+        // Paying a P2WSH address from bob's coin shows up in its stats
+        let address = Address::p2wsh(&bob, Network::Regtest);
+        let spend = tx(&[coin], &[(&address.script_pubkey(), 39_000)]);
+        assert_eq!(esplora.broadcast(&spend).await.unwrap(), spend.txid());
+        let path = format!("/address/{}", address);
+        let stats = api.handle("GET", &path, &[]).await;
+        let stats: Value = serde_json::from_slice(&stats.body).unwrap();
+        assert_eq!(stats["mempool_stats"]["funded_txo_sum"], 39_000);
+        let tx = api
+            .handle("GET", &format!("/tx/{}", spend.txid()), &[])
+            .await;
+        let tx: Value = serde_json::from_slice(&tx.body).unwrap();
+        assert_eq!(
+            (tx["fee"].as_u64(), tx["status"]["confirmed"].as_bool()),
+            (Some(1_000), Some(false))
+        );
+    }
+}
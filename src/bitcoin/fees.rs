@@ -0,0 +1,220 @@
+//! Fee estimation and transaction size calculation
+//!
+//! Fee rates come from a pluggable [`FeeEstimator`] (a node's
+//! `estimatesmartfee`, a mempool explorer API, ...). Wrapping it in a
+//! [`CachedFeeEstimator`] shares one lookup per confirmation target across
+//! concurrent callers for the lifetime of the `bitcoin.fee_estimates` cache.
+//!
+//! [`TxShape`] predicts the weight, vsize, and fee of a transaction from its
+//! input script types and output scripts before it is built, assuming
+//! signatures of typical (72-byte DER, 64-byte schnorr) size. Coin selection,
+//! payout batching, and the mobile send screen all estimate through it.
+
+use std::sync::Arc;
+
+use ::bitcoin::{FeeRate, Script};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::accounts::ScriptType;
+use crate::cache::{Cache, CacheManager, FEE_ESTIMATE_CACHE};
+use crate::AnyaResult;
+
+/// Weight of the segwit marker and flag bytes
+pub const SEGWIT_MARKER_WEIGHT: u64 = 2;
+
+/// Fee for `weight` at `rate`, rounded up to the next satoshi
+pub const fn fee_for(rate: FeeRate, weight: u64) -> u64 {
+    (rate.to_sat_per_kwu() * weight).saturating_add(999) / 1000
+}
+
+/// Weight of an output whose script is `script_len` bytes
+pub const fn output_weight(script_len: usize) -> u64 {
+    4 * (8 + compact_size_len(script_len as u64) + script_len as u64)
+}
+
+/// Bytes taken by a Bitcoin compact-size integer
+const fn compact_size_len(n: u64) -> u64 {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// Inputs and outputs of a transaction whose size is being estimated
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxShape {
+    /// Script type spent by each input
+    pub inputs: Vec<ScriptType>,
+    /// Script length of each output
+    pub output_script_lens: Vec<usize>,
+}
+
+impl TxShape {
+    /// `inputs` inputs and `outputs` outputs, all of `script_type`
+    pub fn uniform(script_type: ScriptType, inputs: usize, outputs: usize) -> Self {
+        Self {
+            inputs: vec![script_type; inputs],
+            output_script_lens: vec![script_type.output_script_len(); outputs],
+        }
+    }
+
+    /// Add an input spending `script_type`
+    #[must_use]
+    pub fn with_input(mut self, script_type: ScriptType) -> Self {
+        self.inputs.push(script_type);
+        self
+    }
+
+    /// Add an output paying `script`
+    #[must_use]
+    pub fn with_output(mut self, script: &Script) -> Self {
+        self.output_script_lens.push(script.len());
+        self
+    }
+
+    /// Expected weight once signed
+    pub fn weight(&self) -> u64 {
+        let counts = compact_size_len(self.inputs.len() as u64)
+            + compact_size_len(self.output_script_lens.len() as u64);
+        let mut weight = 4 * (4 + 4 + counts)
+            + self.inputs.iter().map(|t| t.input_weight()).sum::<u64>()
+            + self
+                .output_script_lens
+                .iter()
+                .map(|len| output_weight(*len))
+                .sum::<u64>();
+        if self.inputs.iter().any(|t| *t != ScriptType::Legacy) {
+            // Legacy inputs of a segwit transaction carry an empty witness
+            let legacy = self
+                .inputs
+                .iter()
+                .filter(|t| **t == ScriptType::Legacy)
+                .count() as u64;
+            weight += SEGWIT_MARKER_WEIGHT + legacy;
+        }
+        weight
+    }
+
+    /// Expected virtual size once signed
+    pub fn vsize(&self) -> u64 {
+        self.weight().saturating_add(3) / 4
+    }
+
+    /// Weight, vsize, and fee at `fee_rate`
+    pub fn estimate(&self, fee_rate: FeeRate) -> SizeEstimate {
+        let weight = self.weight();
+        SizeEstimate {
+            weight,
+            vsize: self.vsize(),
+            fee_sat: fee_for(fee_rate, weight),
+        }
+    }
+}
+
+/// Predicted size and fee of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeEstimate {
+    /// Weight units
+    pub weight: u64,
+    /// Virtual bytes
+    pub vsize: u64,
+    /// Fee in satoshis
+    pub fee_sat: u64,
+}
+
+/// Source of fee rate estimates
+#[async_trait]
+pub trait FeeEstimator: Send + Sync {
+    /// Fee rate expected to confirm within `target_blocks` blocks
+    async fn estimate(&self, target_blocks: u16) -> AnyaResult<FeeRate>;
+}
+
+/// [`FeeEstimator`] caching estimates per confirmation target
+pub struct CachedFeeEstimator {
+    inner: Arc<dyn FeeEstimator>,
+    cache: Arc<Cache<u16, FeeRate>>,
+}
+
+impl CachedFeeEstimator {
+    /// Cache `inner` using the configured fee estimate cache
+    pub fn new(inner: Arc<dyn FeeEstimator>, caches: &CacheManager) -> Self {
+        Self {
+            inner,
+            cache: caches.build(FEE_ESTIMATE_CACHE),
+        }
+    }
+}
+
+#[async_trait]
+impl FeeEstimator for CachedFeeEstimator {
+    async fn estimate(&self, target_blocks: u16) -> AnyaResult<FeeRate> {
+        self.cache
+            .get_or_try_insert_with(target_blocks, || self.inner.estimate(target_blocks))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counting(AtomicUsize);
+
+    #[async_trait]
+    impl FeeEstimator for Counting {
+        async fn estimate(&self, target_blocks: u16) -> AnyaResult<FeeRate> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(FeeRate::from_sat_per_vb_unchecked(u64::from(
+                60 / target_blocks,
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_estimates_are_cached_per_target() {
+        let inner = Arc::new(Counting(AtomicUsize::new(0)));
+        let estimator = CachedFeeEstimator::new(inner.clone(), &CacheManager::new());
+        for _ in 0..3 {
+            assert_eq!(
+                estimator.estimate(6).await.unwrap(),
+                FeeRate::from_sat_per_vb_unchecked(10)
+            );
+        }
+        estimator.estimate(2).await.unwrap();
+        assert_eq!(inner.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_shapes_match_known_sizes() {
+        // Canonical sizes of common single-key transactions
+        assert_eq!(TxShape::uniform(ScriptType::Legacy, 1, 2).vsize(), 226);
+        assert_eq!(
+            TxShape::uniform(ScriptType::NativeSegwit, 1, 2).vsize(),
+            141
+        );
+        assert_eq!(TxShape::uniform(ScriptType::Taproot, 1, 1).vsize(), 111);
+
+        let mixed = TxShape::uniform(ScriptType::NativeSegwit, 1, 1).with_input(ScriptType::Legacy);
+        assert_eq!(mixed.weight(), 40 + 2 + 1 + 272 + 592 + 124);
+        let estimate = TxShape::uniform(ScriptType::NativeSegwit, 1, 2)
+            .estimate(FeeRate::from_sat_per_vb_unchecked(3));
+        assert_eq!(estimate.fee_sat, 422);
+
+        let descriptor = "wpkh([73c5da0a/84h/0h/0h]xpub6CatWdiZ/0/*)#2ag6nxcd";
+        assert_eq!(
+            ScriptType::from_descriptor(descriptor).unwrap(),
+            ScriptType::NativeSegwit
+        );
+        assert_eq!(
+            ScriptType::from_descriptor("sh(wpkh(xpub/0/*))")
+                .unwrap_err()
+                .code(),
+            ErrorCode::InvalidInput
+        );
+    }
+}
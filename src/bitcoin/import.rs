@@ -0,0 +1,644 @@
+//! Wallet import from Electrum, Bitcoin Core, and Sparrow
+//!
+//! [`WalletImporter::import`] converts another wallet's export into a
+//! watch-only descriptor wallet:
+//!
+//! - Electrum: an unencrypted standard wallet file. SLIP-132 `zpub`/`vpub`
+//!   keys become native segwit descriptors and `xpub`/`tpub` keys legacy
+//!   ones; the file's labels are imported.
+//! - Bitcoin Core: the JSON printed by `listdescriptors`. Active single-key
+//!   descriptors are kept and the earliest `timestamp` is recorded as the
+//!   wallet's birthday.
+//! - Sparrow: the output descriptor export, including BIP-389 `<0;1>`
+//!   receive/change descriptors, optionally followed by its BIP-329 label
+//!   export.
+//!
+//! Only the single-key forms of [`WatchOnlyDescriptor`] are supported;
+//! anything else, and any private key material, is skipped with a warning.
+//! Each import schedules a rescan: [`WalletImporter::pending_rescans`] lists
+//! wallets whose history has not been scanned yet, and
+//! [`WalletImporter::rescan_request`] builds the [`RescanRequest`] for one.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::bitcoin::address::NetworkUnchecked;
+use ::bitcoin::base58;
+use ::bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+use ::bitcoin::{Address, Network};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use super::accounts::ScriptType;
+use super::descriptor::{checksum_of, WatchOnlyDescriptor};
+use super::labels::{ImportReport, Label, LabelStore};
+use super::rescan::{RescanReport, RescanRequest};
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "wallet_imports";
+const WALLET_PREFIX: &str = "wallet/";
+
+/// SLIP-132 version bytes and what they stand for
+const SLIP132_VERSIONS: [([u8; 4], ScriptType, bool); 4] = [
+    ([0x04, 0x88, 0xb2, 0x1e], ScriptType::Legacy, true), // xpub
+    ([0x04, 0x35, 0x87, 0xcf], ScriptType::Legacy, false), // tpub
+    ([0x04, 0xb2, 0x47, 0x46], ScriptType::NativeSegwit, true), // zpub
+    ([0x04, 0x5f, 0x1c, 0xf6], ScriptType::NativeSegwit, false), // vpub
+];
+
+/// Wallet software an export comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSource {
+    /// Electrum wallet file
+    Electrum,
+    /// Bitcoin Core `listdescriptors` output
+    CoreDescriptors,
+    /// Sparrow output descriptor and BIP-329 label export
+    Sparrow,
+}
+
+/// Options of an import
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportOptions {
+    /// Height the scheduled rescan starts at; use the wallet's birthday
+    /// when known
+    pub rescan_from: u32,
+    /// Replace labels that already exist
+    pub overwrite_labels: bool,
+}
+
+/// A descriptor of an imported wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportedDescriptor {
+    /// Descriptor with checksum
+    pub descriptor: String,
+    /// Whether it derives change addresses
+    pub internal: bool,
+}
+
+/// Whether an imported wallet's history has been scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RescanState {
+    /// Waiting for a rescan from `from_height`
+    Pending {
+        /// First height to scan
+        from_height: u32,
+    },
+    /// Scanned up to, not including, `next_height`
+    Done {
+        /// Height the wallet's own sync continues from
+        next_height: u32,
+    },
+}
+
+/// A wallet converted from another wallet's export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportedWallet {
+    /// Wallet name, unique per node
+    pub name: String,
+    /// Software it came from
+    pub source: ImportSource,
+    /// Receive and change descriptors
+    pub descriptors: Vec<ImportedDescriptor>,
+    /// Unix time of the wallet's first key, when the export says
+    pub birth_time: Option<u64>,
+    /// Post-import rescan
+    pub rescan: RescanState,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportOutcome {
+    /// The wallet created
+    pub wallet: ImportedWallet,
+    /// Labels imported
+    pub labels: ImportReport,
+    /// Parts of the export that were skipped
+    pub warnings: Vec<String>,
+}
+
+/// An export parsed into descriptors and labels
+#[derive(Debug, Default)]
+struct Parsed {
+    descriptors: Vec<(WatchOnlyDescriptor, bool)>,
+    labels_jsonl: String,
+    birth_time: Option<u64>,
+    warnings: Vec<String>,
+}
+
+impl Parsed {
+    fn add_chains(&mut self, descriptor: &WatchOnlyDescriptor) {
+        for (chain, internal) in [(0, false), (1, true)] {
+            let mut descriptor = descriptor.clone();
+            descriptor.steps.push(ChildNumber::Normal { index: chain });
+            self.descriptors.push((descriptor, internal));
+        }
+    }
+
+    fn add_label(&mut self, label: &Label) -> AnyaResult<()> {
+        self.labels_jsonl.push_str(&serde_json::to_string(label)?);
+        self.labels_jsonl.push('\n');
+        Ok(())
+    }
+}
+
+/// Imports wallets from other software as descriptor wallets
+pub struct WalletImporter {
+    network: Network,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    labels: Arc<LabelStore>,
+}
+
+impl WalletImporter {
+    /// Importer for wallets on `network`, writing labels to `labels`
+    pub async fn open(
+        network: Network,
+        storage: Arc<dyn StorageBackend>,
+        labels: Arc<LabelStore>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            network,
+            storage,
+            ns,
+            labels,
+        })
+    }
+
+    /// Import `export` from `source` as wallet `name`.
+    ///
+    /// Fails with [`ErrorCode::Conflict`] if the name is taken and with
+    /// [`ErrorCode::InvalidInput`] if the export holds no usable descriptor.
+    pub async fn import(
+        &self,
+        name: &str,
+        source: ImportSource,
+        export: &str,
+        options: &ImportOptions,
+    ) -> AnyaResult<ImportOutcome> {
+        if name.is_empty() || name.contains('/') {
+            return Err(AnyaError::invalid_input(format!(
+                "invalid wallet name {:?}",
+                name
+            )));
+        }
+        if self.wallet(name).await?.is_some() {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("wallet {} already exists", name),
+            ));
+        }
+        let parsed = match source {
+            ImportSource::Electrum => self.parse_electrum(export)?,
+            ImportSource::CoreDescriptors => parse_core(export)?,
+            ImportSource::Sparrow => parse_sparrow(export)?,
+        };
+        let network_ok = |d: &WatchOnlyDescriptor| {
+            (d.xpub.network == Network::Bitcoin) == (self.network == Network::Bitcoin)
+        };
+        if parsed.descriptors.is_empty() || !parsed.descriptors.iter().all(|(d, _)| network_ok(d)) {
+            return Err(AnyaError::invalid_input(format!(
+                "no usable {:?} descriptors in the export",
+                self.network
+            )));
+        }
+        let wallet = ImportedWallet {
+            name: name.to_string(),
+            source,
+            descriptors: parsed
+                .descriptors
+                .iter()
+                .map(|(descriptor, internal)| ImportedDescriptor {
+                    descriptor: descriptor.to_string_with_checksum(),
+                    internal: *internal,
+                })
+                .collect(),
+            birth_time: parsed.birth_time,
+            rescan: RescanState::Pending {
+                from_height: options.rescan_from,
+            },
+        };
+        let labels = self
+            .labels
+            .import_jsonl(&parsed.labels_jsonl, options.overwrite_labels)
+            .await?;
+        self.save(&wallet).await?;
+        info!(
+            wallet = name,
+            ?source,
+            descriptors = wallet.descriptors.len(),
+            labels = labels.imported,
+            "wallet imported"
+        );
+        Ok(ImportOutcome {
+            wallet,
+            labels,
+            warnings: parsed.warnings,
+        })
+    }
+
+    /// Look up an imported wallet
+    pub async fn wallet(&self, name: &str) -> AnyaResult<Option<ImportedWallet>> {
+        self.storage
+            .get(&self.ns, &format!("{}{}", WALLET_PREFIX, name))
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Every imported wallet
+    pub async fn wallets(&self) -> AnyaResult<Vec<ImportedWallet>> {
+        self.storage
+            .scan_prefix(&self.ns, WALLET_PREFIX)
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(&bytes)?))
+            .collect()
+    }
+
+    /// Wallets waiting for their post-import rescan
+    pub async fn pending_rescans(&self) -> AnyaResult<Vec<ImportedWallet>> {
+        Ok(self
+            .wallets()
+            .await?
+            .into_iter()
+            .filter(|w| matches!(w.rescan, RescanState::Pending { .. }))
+            .collect())
+    }
+
+    /// Rescan covering `wallet`'s descriptors from its scheduled height
+    pub fn rescan_request(&self, wallet: &ImportedWallet) -> AnyaResult<RescanRequest> {
+        let from_height = match wallet.rescan {
+            RescanState::Pending { from_height } => from_height,
+            RescanState::Done { next_height } => next_height,
+        };
+        wallet
+            .descriptors
+            .iter()
+            .try_fold(RescanRequest::from_height(from_height), |request, d| {
+                Ok(request.with_descriptor(WatchOnlyDescriptor::parse(&d.descriptor)?))
+            })
+    }
+
+    /// Record the rescan of `name`; an incomplete one stays pending from
+    /// where it stopped
+    pub async fn record_rescan(&self, name: &str, report: &RescanReport) -> AnyaResult<()> {
+        let mut wallet = self
+            .wallet(name)
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("imported wallet {}", name)))?;
+        wallet.rescan = if report.completed {
+            RescanState::Done {
+                next_height: report.next_height,
+            }
+        } else {
+            RescanState::Pending {
+                from_height: report.next_height,
+            }
+        };
+        self.save(&wallet).await
+    }
+
+    async fn save(&self, wallet: &ImportedWallet) -> AnyaResult<()> {
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", WALLET_PREFIX, wallet.name),
+                &serde_json::to_vec(wallet)?,
+            )
+            .await
+    }
+
+    fn parse_electrum(&self, export: &str) -> AnyaResult<Parsed> {
+        let file: Value = serde_json::from_str(export).map_err(|_| {
+            AnyaError::invalid_input(
+                "not an Electrum wallet file; encrypted files must be decrypted first",
+            )
+        })?;
+        let wallet_type = file["wallet_type"].as_str().unwrap_or("standard");
+        if wallet_type != "standard" {
+            return Err(AnyaError::invalid_input(format!(
+                "Electrum {} wallets are not supported",
+                wallet_type
+            )));
+        }
+        let keystore = &file["keystore"];
+        let xpub = keystore["xpub"]
+            .as_str()
+            .ok_or_else(|| AnyaError::invalid_input("Electrum keystore has no xpub"))?;
+        let (script_type, xpub) = slip132_decode(xpub)?;
+        let origin = match (
+            keystore["root_fingerprint"].as_str(),
+            keystore["derivation"].as_str(),
+        ) {
+            (Some(fingerprint), Some(path)) => Some((
+                Fingerprint::from_str(fingerprint)
+                    .map_err(|_| AnyaError::invalid_input("invalid root fingerprint"))?,
+                DerivationPath::from_str(path)?,
+            )),
+            _ => None,
+        };
+        let mut parsed = Parsed::default();
+        if !keystore["xprv"].is_null() || !keystore["seed"].is_null() {
+            parsed
+                .warnings
+                .push("private keys in the wallet file were not imported".into());
+        }
+        parsed.add_chains(&WatchOnlyDescriptor {
+            script_type,
+            origin,
+            xpub,
+            steps: Vec::new(),
+        });
+        for (reference, text) in file["labels"].as_object().into_iter().flatten() {
+            let text = text.as_str().unwrap_or_default();
+            let label = if let Ok(txid) = reference.parse() {
+                Label::tx(&txid, text)
+            } else if let Ok(address) = Address::<NetworkUnchecked>::from_str(reference)
+                .map_err(AnyaError::from)
+                .and_then(|a| Ok(a.require_network(self.network)?))
+            {
+                Label::address(&address, text)
+            } else {
+                parsed
+                    .warnings
+                    .push(format!("label for unknown reference {} skipped", reference));
+                continue;
+            };
+            parsed.add_label(&label)?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// Decode an Electrum extended public key, returning the script type its
+/// SLIP-132 version implies and the key with standard version bytes
+fn slip132_decode(key: &str) -> AnyaResult<(ScriptType, ExtendedPubKey)> {
+    let mut data = base58::decode_check(key)
+        .map_err(|e| AnyaError::invalid_input(format!("invalid extended key: {}", e)))?;
+    let version: [u8; 4] = data
+        .get(..4)
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| AnyaError::invalid_input("extended key too short"))?;
+    let (script_type, mainnet) = SLIP132_VERSIONS
+        .iter()
+        .find(|(v, _, _)| *v == version)
+        .map(|(_, script_type, mainnet)| (*script_type, *mainnet))
+        .ok_or_else(|| {
+            AnyaError::invalid_input(
+                "unsupported extended key version; only xpub, tpub, zpub, and vpub keys import",
+            )
+        })?;
+    data[..4].copy_from_slice(&SLIP132_VERSIONS[usize::from(!mainnet)].0);
+    let xpub = ExtendedPubKey::decode(&data)
+        .map_err(|e| AnyaError::invalid_input(format!("invalid extended key: {}", e)))?;
+    Ok((script_type, xpub))
+}
+
+fn parse_core(export: &str) -> AnyaResult<Parsed> {
+    let dump: Value = serde_json::from_str(export)?;
+    let entries = dump["descriptors"]
+        .as_array()
+        .ok_or_else(|| AnyaError::invalid_input("not listdescriptors output"))?;
+    let mut parsed = Parsed::default();
+    for entry in entries {
+        let desc = entry["desc"].as_str().unwrap_or_default();
+        if entry["active"].as_bool() != Some(true) {
+            parsed
+                .warnings
+                .push(format!("inactive descriptor {} skipped", desc));
+            continue;
+        }
+        match WatchOnlyDescriptor::parse(desc) {
+            Ok(descriptor) => {
+                parsed
+                    .descriptors
+                    .push((descriptor, entry["internal"].as_bool() == Some(true)));
+                if let Some(time) = entry["timestamp"].as_u64() {
+                    parsed.birth_time = Some(parsed.birth_time.map_or(time, |t| t.min(time)));
+                }
+            }
+            Err(e) => parsed
+                .warnings
+                .push(format!("descriptor {} skipped: {}", desc, e)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_sparrow(export: &str) -> AnyaResult<Parsed> {
+    let mut parsed = Parsed::default();
+    for line in export.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('{') {
+            parsed.labels_jsonl.push_str(line);
+            parsed.labels_jsonl.push('\n');
+            continue;
+        }
+        let body = match line.split_once('#') {
+            Some((body, checksum)) if checksum_of(body)? != checksum => {
+                return Err(AnyaError::invalid_input("descriptor checksum mismatch"));
+            }
+            Some((body, _)) => body,
+            None => line,
+        };
+        // Expand a BIP-389 receive/change pair into the two descriptors
+        match body.split_once("/<0;1>/") {
+            Some((head, tail)) => {
+                let descriptor = WatchOnlyDescriptor::parse(&format!("{}/{}", head, tail))?;
+                parsed.add_chains(&descriptor);
+            }
+            None => {
+                let descriptor = WatchOnlyDescriptor::parse(body)?;
+                let internal = descriptor.steps.last() == Some(&ChildNumber::Normal { index: 1 });
+                parsed.descriptors.push((descriptor, internal));
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::labels::LabelType;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::secp256k1::Secp256k1;
+
+    const TXID: &str = "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd";
+
+    async fn importer() -> (WalletImporter, Arc<LabelStore>) {
+        let storage = Arc::new(MemoryBackend::new());
+        let labels = Arc::new(LabelStore::open(storage.clone()).await.unwrap());
+        let importer = WalletImporter::open(Network::Testnet, storage, labels.clone())
+            .await
+            .unwrap();
+        (importer, labels)
+    }
+
+    fn account_xpub() -> (Fingerprint, ExtendedPubKey) {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::Testnet, &[9; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84h/1h/0h").unwrap();
+        let account = master.derive_priv(&secp, &path).unwrap();
+        (
+            master.fingerprint(&secp),
+            ExtendedPubKey::from_priv(&secp, &account),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_electrum_file_becomes_descriptor_wallet() {
+        let (importer, labels) = importer().await;
+        let (fingerprint, xpub) = account_xpub();
+        let mut vpub = xpub.encode();
+        vpub[..4].copy_from_slice(&[0x04, 0x5f, 0x1c, 0xf6]);
+        let descriptor = WatchOnlyDescriptor {
+            script_type: ScriptType::NativeSegwit,
+            origin: None,
+            xpub,
+            steps: vec![ChildNumber::Normal { index: 0 }],
+        };
+        let receive = descriptor.address(&Secp256k1::new(), 0).unwrap();
+        let file = serde_json::json!({
+            "wallet_type": "standard",
+            "keystore": {
+                "type": "bip32",
+                "xpub": base58::encode_check(&vpub),
+                "xprv": null,
+                "derivation": "m/84'/1'/0'",
+                "root_fingerprint": fingerprint.to_string(),
+            },
+            "labels": { TXID: "rent", receive.to_string(): "from alice", "junk": "?" },
+        });
+
+        let outcome = importer
+            .import(
+                "old",
+                ImportSource::Electrum,
+                &file.to_string(),
+                &ImportOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome.labels.imported, 2);
+        assert_eq!(outcome.warnings.len(), 1);
+        assert!(outcome.wallet.descriptors[0]
+            .descriptor
+            .starts_with(&format!("wpkh([{}/84h/1h/0h]tpub", fingerprint)));
+        assert_eq!(
+            labels
+                .get(LabelType::Addr, &receive.to_string())
+                .await
+                .unwrap()
+                .unwrap()
+                .label,
+            "from alice"
+        );
+        let err = importer
+            .import(
+                "old",
+                ImportSource::Electrum,
+                &file.to_string(),
+                &ImportOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+
+        // The scheduled rescan watches the same addresses
+        let pending = importer.pending_rescans().await.unwrap();
+        let request = importer.rescan_request(&pending[0]).unwrap();
+        assert_eq!(
+            request.descriptors[0]
+                .address(&Secp256k1::new(), 0)
+                .unwrap(),
+            receive
+        );
+        let report = RescanReport {
+            next_height: 120,
+            completed: true,
+            ..RescanReport::default()
+        };
+        importer.record_rescan("old", &report).await.unwrap();
+        assert!(importer.pending_rescans().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_core_and_sparrow_exports() {
+        let (importer, labels) = importer().await;
+        let (fingerprint, xpub) = account_xpub();
+        let origin = format!("[{}/84h/1h/0h]{}", fingerprint, xpub);
+        let desc = |path: &str| {
+            let body = format!("wpkh({}/{})", origin, path);
+            format!("{}#{}", body, checksum_of(&body).unwrap())
+        };
+        let dump = serde_json::json!({
+            "wallet_name": "core",
+            "descriptors": [
+                { "desc": desc("0/*"), "timestamp": 1_700_000_500, "active": true, "internal": false },
+                { "desc": desc("1/*"), "timestamp": 1_700_000_000, "active": true, "internal": true },
+                { "desc": format!("sh(wpkh({}/0/*))", origin), "active": true },
+                { "desc": desc("2/*"), "active": false },
+            ],
+        });
+        let core = importer
+            .import(
+                "core",
+                ImportSource::CoreDescriptors,
+                &dump.to_string(),
+                &ImportOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(core.wallet.birth_time, Some(1_700_000_000));
+        assert_eq!(core.warnings.len(), 2);
+
+        let sparrow = format!(
+            "# Receive and change descriptor (BIP389):\n{}\n{{\"type\":\"tx\",\"ref\":\"{}\",\"label\":\"coffee\"}}\n",
+            desc("<0;1>/*"),
+            TXID
+        );
+        let imported = importer
+            .import(
+                "sparrow",
+                ImportSource::Sparrow,
+                &sparrow,
+                &ImportOptions::default(),
+            )
+            .await
+            .unwrap();
+        let descriptors: Vec<_> = imported
+            .wallet
+            .descriptors
+            .iter()
+            .map(|d| (d.descriptor.clone(), d.internal))
+            .collect();
+        let core: Vec<_> = core
+            .wallet
+            .descriptors
+            .iter()
+            .map(|d| (d.descriptor.clone(), d.internal))
+            .collect();
+        assert_eq!(descriptors, core);
+        assert_eq!(
+            labels
+                .get(LabelType::Tx, TXID)
+                .await
+                .unwrap()
+                .unwrap()
+                .label,
+            "coffee"
+        );
+    }
+}
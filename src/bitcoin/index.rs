@@ -0,0 +1,1008 @@
+//! Block store and chain indexes of the internal node
+//!
+//! The [`ChainIndex`] keeps every block the node accepts together with the
+//! indexes a wallet or explorer queries: block hash by height, transaction
+//! location by txid, every output, the transaction spending each output,
+//! and the transaction history of every script. Blocks are connected to the
+//! tip with [`ChainIndex::connect_block`] and taken off it again with
+//! [`ChainIndex::disconnect_tip`] during a reorganization, which removes
+//! their index entries and returns their transactions to the mempool.
+//!
+//! [`ChainIndex::reindex`] rebuilds every index from the stored blocks,
+//! e.g. after an upgrade changes the index layout.
+//!
+//! With cold storage attached through [`ChainIndex::with_cold_storage`],
+//! [`ChainIndex::offload`] moves blocks older than the
+//! [`TieringPolicy`]'s hot window, and outputs spent within that range, to
+//! an [`ObjectStore`] such as S3. Reads fetch them back transparently and
+//! verify them against the digest recorded locally.
+//!
+//! Scripts are indexed by their SHA-256 in hex, the Esplora script hash.
+//! Consensus rules are checked elsewhere; the index only verifies that a
+//! block links to the tip and commits to its transactions.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use ::bitcoin::consensus::encode::{deserialize, serialize};
+use ::bitcoin::{Block, BlockHash, OutPoint, Script, Transaction, TxOut, Txid};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[cfg(not(target_arch = "wasm32"))]
+use super::provider::ChainDataProvider;
+use super::rescan::{ReindexReport, ScanProgress};
+use super::tracker::{ChainSource, TxStatus};
+use crate::storage::object::{ObjectKind, ObjectRef, ObjectStore};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "chain_index";
+const TIP_KEY: &str = "tip";
+const HEIGHT_PREFIX: &str = "height/";
+const BLOCK_PREFIX: &str = "block/";
+const META_PREFIX: &str = "meta/";
+const TX_PREFIX: &str = "tx/";
+const OUTPUT_PREFIX: &str = "out/";
+const SPEND_PREFIX: &str = "spend/";
+const SCRIPT_PREFIX: &str = "script/";
+const REINDEX_KEY: &str = "reindex";
+const COLD_BLOCK_PREFIX: &str = "cold/block/";
+const COLD_SPENT_PREFIX: &str = "cold/spent/";
+const OFFLOADED_KEY: &str = "cold/height";
+
+/// Blocks kept local by default, about two weeks of blocks
+pub const DEFAULT_HOT_BLOCKS: u32 = 2016;
+
+/// Esplora script hash of `script`: its SHA-256 in hex
+pub fn script_hash(script: &Script) -> String {
+    to_hex(&sha256(script.as_bytes()))
+}
+
+/// Summary of an indexed block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockMeta {
+    /// Block hash
+    pub hash: BlockHash,
+    /// Height in the chain
+    pub height: u32,
+    /// Header timestamp
+    pub time: u32,
+    /// Number of transactions
+    pub tx_count: u32,
+    /// Serialized size in bytes
+    pub size: u32,
+    /// Weight in weight units
+    pub weight: u64,
+}
+
+/// Where a confirmed transaction is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxLocation {
+    /// Block containing it
+    pub block_hash: BlockHash,
+    /// Height of that block
+    pub height: u32,
+    /// Position in the block
+    pub index: u32,
+}
+
+/// Which chain data [`ChainIndex::offload`] moves to cold storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TieringPolicy {
+    /// Most recent blocks kept local
+    pub hot_blocks: u32,
+    /// Also offload outputs created and spent below the hot window,
+    /// together with their spend entries
+    pub offload_spent: bool,
+}
+
+impl Default for TieringPolicy {
+    fn default() -> Self {
+        Self {
+            hot_blocks: DEFAULT_HOT_BLOCKS,
+            offload_spent: true,
+        }
+    }
+}
+
+/// What a [`ChainIndex::offload`] run moved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffloadReport {
+    /// Blocks offloaded
+    pub blocks: u32,
+    /// Spent outputs offloaded
+    pub spent_outputs: u32,
+    /// Bytes written to cold storage
+    pub bytes: u64,
+}
+
+/// A spent output archived in cold storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SpentOutput {
+    outpoint: OutPoint,
+    output: TxOut,
+    spender: Txid,
+}
+
+struct ColdTier {
+    store: Arc<dyn ObjectStore>,
+    policy: TieringPolicy,
+}
+
+/// Blocks accepted by the node and the indexes over them
+pub struct ChainIndex {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    cold: Option<ColdTier>,
+    mempool: Mutex<HashMap<Txid, Transaction>>,
+    write: tokio::sync::Mutex<()>,
+}
+
+impl ChainIndex {
+    /// Open the index kept in `storage`
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            storage,
+            ns,
+            cold: None,
+            mempool: Mutex::new(HashMap::new()),
+            write: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Offload old chain data to `store` according to `policy`
+    #[must_use]
+    pub fn with_cold_storage(mut self, store: Arc<dyn ObjectStore>, policy: TieringPolicy) -> Self {
+        self.cold = Some(ColdTier { store, policy });
+        self
+    }
+
+    /// Best block, `None` before the first block is connected
+    pub async fn tip(&self) -> AnyaResult<Option<BlockMeta>> {
+        self.get_json(TIP_KEY).await
+    }
+
+    /// Append `block` to the tip, returning its height. The first block
+    /// connected is taken as height 0.
+    pub async fn connect_block(&self, block: &Block) -> AnyaResult<u32> {
+        let _guard = self.write.lock().await;
+        if self.storage.get(&self.ns, REINDEX_KEY).await?.is_some() {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                "an interrupted reindex must finish before blocks are connected",
+            ));
+        }
+        self.connect(block).await
+    }
+
+    async fn connect(&self, block: &Block) -> AnyaResult<u32> {
+        let tip = self.tip().await?;
+        if let Some(tip) = tip {
+            if block.header.prev_blockhash != tip.hash {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!(
+                        "block {} does not extend tip {}",
+                        block.block_hash(),
+                        tip.hash
+                    ),
+                ));
+            }
+        }
+        if !block.check_merkle_root() || !block.check_witness_commitment() {
+            return Err(AnyaError::invalid_input(format!(
+                "block {} does not commit to its transactions",
+                block.block_hash()
+            )));
+        }
+        let height = tip.map_or(0, |tip| tip.height + 1);
+        let hash = block.block_hash();
+        let bytes = serialize(block);
+        let meta = BlockMeta {
+            hash,
+            height,
+            time: block.header.time,
+            tx_count: u32::try_from(block.txdata.len()).unwrap_or(u32::MAX),
+            size: u32::try_from(bytes.len()).unwrap_or(u32::MAX),
+            weight: block.weight().to_wu(),
+        };
+        for (index, tx) in block.txdata.iter().enumerate() {
+            let txid = tx.txid();
+            let location = TxLocation {
+                block_hash: hash,
+                height,
+                index: u32::try_from(index).unwrap_or(u32::MAX),
+            };
+            self.put_json(&format!("{}{}", TX_PREFIX, txid), &location)
+                .await?;
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, u32::try_from(vout).unwrap_or(u32::MAX));
+                self.put_json(&format!("{}{}", OUTPUT_PREFIX, outpoint), output)
+                    .await?;
+                self.put_history(&output.script_pubkey, height, txid)
+                    .await?;
+            }
+            if tx.is_coin_base() {
+                continue;
+            }
+            for input in &tx.input {
+                let prevout = input.previous_output;
+                self.put_json(&format!("{}{}", SPEND_PREFIX, prevout), &txid)
+                    .await?;
+                if let Some(spent) = self.output(&prevout).await? {
+                    self.put_history(&spent.script_pubkey, height, txid).await?;
+                }
+            }
+        }
+        // A block already in cold storage stays there when a reindex
+        // replays it
+        if self
+            .storage
+            .get(&self.ns, &format!("{}{}", COLD_BLOCK_PREFIX, hash))
+            .await?
+            .is_none()
+        {
+            self.storage
+                .put(&self.ns, &format!("{}{}", BLOCK_PREFIX, hash), &bytes)
+                .await?;
+        }
+        self.put_json(&format!("{}{}", META_PREFIX, hash), &meta)
+            .await?;
+        self.put_json(&height_key(height), &hash).await?;
+        self.put_json(TIP_KEY, &meta).await?;
+        {
+            let mut mempool = self.mempool();
+            for tx in &block.txdata {
+                mempool.remove(&tx.txid());
+            }
+        }
+        info!(%hash, height, "block connected");
+        Ok(height)
+    }
+
+    /// Remove the tip block from the chain and its indexes, returning it.
+    /// Its transactions other than the coinbase go back to the mempool.
+    pub async fn disconnect_tip(&self) -> AnyaResult<Option<Block>> {
+        let _guard = self.write.lock().await;
+        let Some(tip) = self.tip().await? else {
+            return Ok(None);
+        };
+        if self
+            .storage
+            .get(&self.ns, &format!("{}{}", COLD_BLOCK_PREFIX, tip.hash))
+            .await?
+            .is_some()
+        {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!(
+                    "block {} is in cold storage and cannot be disconnected",
+                    tip.hash
+                ),
+            ));
+        }
+        let block = self.block(&tip.hash).await?.ok_or_else(|| {
+            AnyaError::new(
+                ErrorCode::StorageFailure,
+                format!("tip block {} missing", tip.hash),
+            )
+        })?;
+        for tx in block.txdata.iter().rev() {
+            let txid = tx.txid();
+            if !tx.is_coin_base() {
+                for input in &tx.input {
+                    let prevout = input.previous_output;
+                    self.storage
+                        .delete(&self.ns, &format!("{}{}", SPEND_PREFIX, prevout))
+                        .await?;
+                    if let Some(spent) = self.output(&prevout).await? {
+                        self.delete_history(&spent.script_pubkey, tip.height, txid)
+                            .await?;
+                    }
+                }
+            }
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, u32::try_from(vout).unwrap_or(u32::MAX));
+                self.storage
+                    .delete(&self.ns, &format!("{}{}", OUTPUT_PREFIX, outpoint))
+                    .await?;
+                self.delete_history(&output.script_pubkey, tip.height, txid)
+                    .await?;
+            }
+            self.storage
+                .delete(&self.ns, &format!("{}{}", TX_PREFIX, txid))
+                .await?;
+        }
+        for key in [
+            format!("{}{}", BLOCK_PREFIX, tip.hash),
+            format!("{}{}", META_PREFIX, tip.hash),
+            height_key(tip.height),
+        ] {
+            self.storage.delete(&self.ns, &key).await?;
+        }
+        match tip.height.checked_sub(1) {
+            Some(height) => {
+                let previous = self
+                    .block_meta(&block.header.prev_blockhash)
+                    .await?
+                    .ok_or_else(|| {
+                        AnyaError::new(
+                            ErrorCode::StorageFailure,
+                            format!("block at height {} missing", height),
+                        )
+                    })?;
+                self.put_json(TIP_KEY, &previous).await?;
+            }
+            None => {
+                self.storage.delete(&self.ns, TIP_KEY).await?;
+            }
+        }
+        {
+            let mut mempool = self.mempool();
+            for tx in block.txdata.iter().filter(|tx| !tx.is_coin_base()) {
+                mempool.insert(tx.txid(), tx.clone());
+            }
+        }
+        info!(hash = %tip.hash, height = tip.height, "block disconnected");
+        Ok(Some(block))
+    }
+
+    /// Rebuild every index from the stored blocks, reporting the blocks
+    /// done through `progress`.
+    ///
+    /// The chain being rebuilt is recorded first, so a reindex stopped by
+    /// `token` or a crash resumes where it left off when called again;
+    /// until then no other block can be connected.
+    pub async fn reindex(
+        &self,
+        token: &CancellationToken,
+        progress: &watch::Sender<ScanProgress>,
+    ) -> AnyaResult<ReindexReport> {
+        let _guard = self.write.lock().await;
+        let chain: Vec<BlockHash> = match self.get_json(REINDEX_KEY).await? {
+            Some(chain) => chain,
+            None => {
+                let chain = self
+                    .storage
+                    .scan_prefix(&self.ns, HEIGHT_PREFIX)
+                    .await?
+                    .into_iter()
+                    .map(|(_, hash)| serde_json::from_slice(&hash))
+                    .collect::<Result<Vec<BlockHash>, _>>()?;
+                self.put_json(REINDEX_KEY, &chain).await?;
+                for prefix in [
+                    HEIGHT_PREFIX,
+                    META_PREFIX,
+                    TX_PREFIX,
+                    OUTPUT_PREFIX,
+                    SPEND_PREFIX,
+                    SCRIPT_PREFIX,
+                    COLD_SPENT_PREFIX,
+                ] {
+                    for (key, _) in self.storage.scan_prefix(&self.ns, prefix).await? {
+                        self.storage.delete(&self.ns, &key).await?;
+                    }
+                }
+                self.storage.delete(&self.ns, TIP_KEY).await?;
+                chain
+            }
+        };
+        let total = u32::try_from(chain.len()).unwrap_or(u32::MAX);
+        let mut report = ReindexReport {
+            blocks: total,
+            completed: false,
+        };
+        let start = self.tip().await?.map_or(0, |tip| tip.height + 1);
+        for (height, hash) in (start..).zip(chain.iter().skip(start as usize)) {
+            if token.is_cancelled() {
+                info!(height, total, "reindex interrupted");
+                return Ok(report);
+            }
+            let block = self.block(hash).await?.ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::StorageFailure,
+                    format!("block {} at height {} missing", hash, height),
+                )
+            })?;
+            self.connect(&block).await?;
+            progress.send_replace(ScanProgress {
+                height,
+                done: height + 1,
+                total,
+                matches: 0,
+            });
+        }
+        self.storage.delete(&self.ns, REINDEX_KEY).await?;
+        report.completed = true;
+        info!(blocks = total, "reindex finished");
+        Ok(report)
+    }
+
+    /// Hash of the block at `height`
+    pub async fn block_hash(&self, height: u32) -> AnyaResult<Option<BlockHash>> {
+        self.get_json(&height_key(height)).await
+    }
+
+    /// Summary of block `hash`
+    pub async fn block_meta(&self, hash: &BlockHash) -> AnyaResult<Option<BlockMeta>> {
+        self.get_json(&format!("{}{}", META_PREFIX, hash)).await
+    }
+
+    /// Block `hash`, fetched from cold storage if it was offloaded
+    pub async fn block(&self, hash: &BlockHash) -> AnyaResult<Option<Block>> {
+        if let Some(bytes) = self
+            .storage
+            .get(&self.ns, &format!("{}{}", BLOCK_PREFIX, hash))
+            .await?
+        {
+            return deserialize(&bytes).map(Some).map_err(corrupt);
+        }
+        let Some(object) = self
+            .get_json::<ObjectRef>(&format!("{}{}", COLD_BLOCK_PREFIX, hash))
+            .await?
+        else {
+            return Ok(None);
+        };
+        let block: Block = deserialize(&self.cold_store()?.get(&object).await?).map_err(corrupt)?;
+        if block.block_hash() != *hash {
+            return Err(AnyaError::new(
+                ErrorCode::StorageFailure,
+                format!("cold copy of block {} failed integrity verification", hash),
+            ));
+        }
+        Ok(Some(block))
+    }
+
+    /// Move chain data below the hot window to cold storage.
+    ///
+    /// Each block is written to the cold store and its digest recorded
+    /// before the local copy is deleted, so an interrupted run loses
+    /// nothing and the next one carries on.
+    pub async fn offload(&self) -> AnyaResult<OffloadReport> {
+        let cold = self
+            .cold
+            .as_ref()
+            .ok_or_else(|| AnyaError::new(ErrorCode::Config, "no cold storage is configured"))?;
+        let _guard = self.write.lock().await;
+        let mut report = OffloadReport::default();
+        let Some(cutoff) = self
+            .tip()
+            .await?
+            .and_then(|tip| (tip.height + 1).checked_sub(cold.policy.hot_blocks))
+        else {
+            return Ok(report);
+        };
+        let start: u32 = self.get_json(OFFLOADED_KEY).await?.unwrap_or(0);
+        for height in start..cutoff {
+            let hash = self
+                .block_hash(height)
+                .await?
+                .ok_or_else(|| corrupt(height_key(height)))?;
+            let key = format!("{}{}", BLOCK_PREFIX, hash);
+            if let Some(bytes) = self.storage.get(&self.ns, &key).await? {
+                let object = cold
+                    .store
+                    .put(&bytes, "application/octet-stream", ObjectKind::ChainData)
+                    .await?;
+                self.put_json(&format!("{}{}", COLD_BLOCK_PREFIX, hash), &object)
+                    .await?;
+                self.storage.delete(&self.ns, &key).await?;
+                report.blocks += 1;
+                report.bytes += object.size;
+            }
+            self.put_json(OFFLOADED_KEY, &(height + 1)).await?;
+        }
+        if cold.policy.offload_spent {
+            let mut segments: HashMap<BlockHash, Vec<SpentOutput>> = HashMap::new();
+            for (key, spender) in self.storage.scan_prefix(&self.ns, SPEND_PREFIX).await? {
+                let outpoint: OutPoint = key[SPEND_PREFIX.len()..].parse().map_err(corrupt)?;
+                let spender: Txid = serde_json::from_slice(&spender)?;
+                let (Some(created), Some(spent)) = (
+                    self.tx_location(&outpoint.txid).await?,
+                    self.tx_location(&spender).await?,
+                ) else {
+                    continue;
+                };
+                if spent.height >= cutoff {
+                    continue;
+                }
+                let output = self
+                    .get_json(&format!("{}{}", OUTPUT_PREFIX, outpoint))
+                    .await?
+                    .ok_or_else(|| corrupt(&key))?;
+                segments
+                    .entry(created.block_hash)
+                    .or_default()
+                    .push(SpentOutput {
+                        outpoint,
+                        output,
+                        spender,
+                    });
+            }
+            for (hash, spent) in segments {
+                let object = cold
+                    .store
+                    .put(
+                        &serde_json::to_vec(&spent)?,
+                        "application/json",
+                        ObjectKind::ChainData,
+                    )
+                    .await?;
+                self.put_json(
+                    &format!("{}{}/{}", COLD_SPENT_PREFIX, hash, object.sha256),
+                    &object,
+                )
+                .await?;
+                for entry in &spent {
+                    self.storage
+                        .delete(&self.ns, &format!("{}{}", OUTPUT_PREFIX, entry.outpoint))
+                        .await?;
+                    self.storage
+                        .delete(&self.ns, &format!("{}{}", SPEND_PREFIX, entry.outpoint))
+                        .await?;
+                }
+                report.spent_outputs += u32::try_from(spent.len()).unwrap_or(u32::MAX);
+                report.bytes += object.size;
+            }
+        }
+        info!(
+            blocks = report.blocks,
+            spent_outputs = report.spent_outputs,
+            bytes = report.bytes,
+            "chain data offloaded"
+        );
+        Ok(report)
+    }
+
+    /// Spent output `outpoint` from cold storage
+    async fn cold_spent(&self, outpoint: &OutPoint) -> AnyaResult<Option<SpentOutput>> {
+        let Some(location) = self.tx_location(&outpoint.txid).await? else {
+            return Ok(None);
+        };
+        let prefix = format!("{}{}/", COLD_SPENT_PREFIX, location.block_hash);
+        for (_, object) in self.storage.scan_prefix(&self.ns, &prefix).await? {
+            let object: ObjectRef = serde_json::from_slice(&object)?;
+            let segment: Vec<SpentOutput> =
+                serde_json::from_slice(&self.cold_store()?.get(&object).await?).map_err(corrupt)?;
+            if let Some(entry) = segment.into_iter().find(|e| e.outpoint == *outpoint) {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    fn cold_store(&self) -> AnyaResult<&dyn ObjectStore> {
+        self.cold.as_ref().map(|cold| &*cold.store).ok_or_else(|| {
+            AnyaError::new(
+                ErrorCode::Config,
+                "chain data was offloaded but no cold storage is configured",
+            )
+        })
+    }
+
+    /// Where confirmed transaction `txid` is
+    pub async fn tx_location(&self, txid: &Txid) -> AnyaResult<Option<TxLocation>> {
+        self.get_json(&format!("{}{}", TX_PREFIX, txid)).await
+    }
+
+    /// A confirmed output, spent or not, or an output of a mempool
+    /// transaction
+    pub async fn output(&self, outpoint: &OutPoint) -> AnyaResult<Option<TxOut>> {
+        if let Some(output) = self
+            .get_json(&format!("{}{}", OUTPUT_PREFIX, outpoint))
+            .await?
+        {
+            return Ok(Some(output));
+        }
+        if let Some(entry) = self.cold_spent(outpoint).await? {
+            return Ok(Some(entry.output));
+        }
+        Ok(self
+            .mempool()
+            .get(&outpoint.txid)
+            .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned()))
+    }
+
+    /// Confirmed transactions touching the script with Esplora hash
+    /// `hash`, oldest first, as `(height, txid)`
+    pub async fn history(&self, hash: &str) -> AnyaResult<Vec<(u32, Txid)>> {
+        let prefix = format!("{}{}/", SCRIPT_PREFIX, hash);
+        self.storage
+            .scan_prefix(&self.ns, &prefix)
+            .await?
+            .into_iter()
+            .map(|(key, _)| {
+                let rest = key.strip_prefix(&prefix).unwrap_or(&key);
+                let (height, txid) = rest.split_once('/').ok_or_else(|| corrupt(&key))?;
+                Ok((
+                    height.parse().map_err(corrupt)?,
+                    txid.parse().map_err(corrupt)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Unconfirmed transactions touching `script`
+    pub async fn mempool_history(&self, script: &Script) -> AnyaResult<Vec<Txid>> {
+        let mut txids = Vec::new();
+        for tx in self.mempool_transactions() {
+            let mut touches = tx
+                .output
+                .iter()
+                .any(|o| o.script_pubkey.as_script() == script);
+            for input in &tx.input {
+                if touches {
+                    break;
+                }
+                touches = self
+                    .output(&input.previous_output)
+                    .await?
+                    .is_some_and(|o| o.script_pubkey.as_script() == script);
+            }
+            if touches {
+                txids.push(tx.txid());
+            }
+        }
+        Ok(txids)
+    }
+
+    /// Add an unconfirmed transaction, rejecting one that spends an output
+    /// already spent in the mempool
+    pub fn accept_to_mempool(&self, tx: Transaction) -> AnyaResult<Txid> {
+        let txid = tx.txid();
+        let mut mempool = self.mempool();
+        let conflict = mempool.values().find(|other| {
+            other.txid() != txid
+                && other.input.iter().any(|i| {
+                    tx.input
+                        .iter()
+                        .any(|mine| mine.previous_output == i.previous_output)
+                })
+        });
+        if let Some(conflict) = conflict {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!(
+                    "{} conflicts with mempool transaction {}",
+                    txid,
+                    conflict.txid()
+                ),
+            ));
+        }
+        mempool.insert(txid, tx);
+        drop(mempool);
+        Ok(txid)
+    }
+
+    /// Unconfirmed transactions
+    pub fn mempool_transactions(&self) -> Vec<Transaction> {
+        self.mempool().values().cloned().collect()
+    }
+
+    fn mempool(&self) -> MutexGuard<'_, HashMap<Txid, Transaction>> {
+        self.mempool.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    async fn put_history(&self, script: &Script, height: u32, txid: Txid) -> AnyaResult<()> {
+        self.storage
+            .put(&self.ns, &history_key(script, height, txid), &[])
+            .await
+    }
+
+    async fn delete_history(&self, script: &Script, height: u32, txid: Txid) -> AnyaResult<()> {
+        self.storage
+            .delete(&self.ns, &history_key(script, height, txid))
+            .await?;
+        Ok(())
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, key: &str) -> AnyaResult<Option<T>> {
+        self.storage
+            .get(&self.ns, key)
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    async fn put_json<T: Serialize + Sync + ?Sized>(&self, key: &str, value: &T) -> AnyaResult<()> {
+        self.storage
+            .put(&self.ns, key, &serde_json::to_vec(value)?)
+            .await
+    }
+}
+
+fn height_key(height: u32) -> String {
+    format!("{}{:010}", HEIGHT_PREFIX, height)
+}
+
+fn history_key(script: &Script, height: u32, txid: Txid) -> String {
+    format!(
+        "{}{}/{:010}/{}",
+        SCRIPT_PREFIX,
+        script_hash(script),
+        height,
+        txid
+    )
+}
+
+fn corrupt(e: impl std::fmt::Display) -> AnyaError {
+    AnyaError::new(
+        ErrorCode::StorageFailure,
+        format!("corrupt chain index entry: {}", e),
+    )
+}
+
+#[async_trait]
+impl ChainSource for ChainIndex {
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        self.tip()
+            .await?
+            .map(|tip| tip.height)
+            .ok_or_else(|| AnyaError::new(ErrorCode::Unavailable, "chain index is empty"))
+    }
+
+    async fn script_history(&self, script: &Script) -> AnyaResult<Vec<Txid>> {
+        let mut txids: Vec<Txid> = self
+            .history(&script_hash(script))
+            .await?
+            .into_iter()
+            .map(|(_, txid)| txid)
+            .collect();
+        txids.dedup();
+        txids.extend(self.mempool_history(script).await?);
+        Ok(txids)
+    }
+
+    async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        if let Some(tx) = self.mempool().get(txid) {
+            return Ok(Some(tx.clone()));
+        }
+        let Some(location) = self.tx_location(txid).await? else {
+            return Ok(None);
+        };
+        Ok(self
+            .block(&location.block_hash)
+            .await?
+            .and_then(|block| block.txdata.into_iter().nth(location.index as usize)))
+    }
+
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        if let Some(location) = self.tx_location(txid).await? {
+            return Ok(TxStatus::Confirmed {
+                height: location.height,
+            });
+        }
+        Ok(if self.mempool().contains_key(txid) {
+            TxStatus::Mempool
+        } else {
+            TxStatus::Unknown
+        })
+    }
+
+    async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+        if let Some(txid) = self
+            .get_json(&format!("{}{}", SPEND_PREFIX, outpoint))
+            .await?
+        {
+            return Ok(Some(txid));
+        }
+        if let Some(entry) = self.cold_spent(outpoint).await? {
+            return Ok(Some(entry.spender));
+        }
+        Ok(self
+            .mempool()
+            .iter()
+            .find(|(_, tx)| tx.input.iter().any(|i| i.previous_output == *outpoint))
+            .map(|(txid, _)| *txid))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl ChainDataProvider for ChainIndex {
+    fn name(&self) -> &str {
+        "internal"
+    }
+
+    async fn block_hash(&self, height: u32) -> AnyaResult<Option<BlockHash>> {
+        Self::block_hash(self, height).await
+    }
+
+    async fn block(&self, hash: &BlockHash) -> AnyaResult<Option<Block>> {
+        Self::block(self, hash).await
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid> {
+        self.accept_to_mempool(tx.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::absolute::LockTime;
+    use ::bitcoin::block::{Header, Version};
+    use ::bitcoin::hash_types::TxMerkleNode;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::pow::CompactTarget;
+    use ::bitcoin::{ScriptBuf, Sequence, TxIn, Witness};
+
+    /// A transaction spending `inputs` to one output per script
+    pub fn tx(inputs: &[OutPoint], outputs: &[(&ScriptBuf, u64)]) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|prevout| TxIn {
+                    previous_output: *prevout,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|(script, value)| TxOut {
+                    value: *value,
+                    script_pubkey: (*script).clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// A block on `prev` with a coinbase paying `miner` followed by `txs`
+    pub fn block(prev: BlockHash, height: u32, miner: &ScriptBuf, txs: Vec<Transaction>) -> Block {
+        let mut coinbase = tx(&[OutPoint::null()], &[(miner, 50_000)]);
+        coinbase.input[0].script_sig = ScriptBuf::from_bytes(height.to_le_bytes().to_vec());
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: prev,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_700_000_000 + height * 600,
+                bits: CompactTarget::from_consensus(0x207f_ffff),
+                nonce: 0,
+            },
+            txdata: std::iter::once(coinbase).chain(txs).collect(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    /// An index holding two blocks: the second spends the first's coinbase
+    /// from `alice` to `bob`
+    pub async fn indexed_chain() -> (ChainIndex, ScriptBuf, ScriptBuf) {
+        let alice = ScriptBuf::from_bytes(vec![0x51]);
+        let bob = ScriptBuf::from_bytes(vec![0x52]);
+        let index = ChainIndex::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let genesis = block(BlockHash::all_zeros(), 0, &alice, vec![]);
+        index.connect_block(&genesis).await.unwrap();
+        let payment = tx(
+            &[OutPoint::new(genesis.txdata[0].txid(), 0)],
+            &[(&bob, 40_000)],
+        );
+        let next = block(genesis.block_hash(), 1, &alice, vec![payment]);
+        index.connect_block(&next).await.unwrap();
+        (index, alice, bob)
+    }
+
+    #[tokio::test]
+    async fn blocks_index_history_and_unwind() {
+        let (index, alice, bob) = indexed_chain().await;
+        let tip = index.tip().await.unwrap().unwrap();
+        assert_eq!((tip.height, tip.tx_count), (1, 2));
+        let payment = index.block(&tip.hash).await.unwrap().unwrap().txdata[1].clone();
+        let coin = payment.input[0].previous_output;
+
+        assert_eq!(index.script_history(&alice).await.unwrap().len(), 3);
+        assert_eq!(index.script_history(&bob).await.unwrap(), [payment.txid()]);
+        assert_eq!(index.spender(&coin).await.unwrap(), Some(payment.txid()));
+        assert_eq!(
+            index.status(&payment.txid()).await.unwrap(),
+            TxStatus::Confirmed { height: 1 }
+        );
+        let stale = block(BlockHash::all_zeros(), 1, &alice, vec![]);
+        assert_eq!(
+            index.connect_block(&stale).await.unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+
+        // Unwinding returns the payment to the mempool, where it stays
+        // visible and conflicting spends are refused
+        index.disconnect_tip().await.unwrap().unwrap();
+        assert_eq!(index.tip().await.unwrap().unwrap().height, 0);
+        assert_eq!(index.block_hash(1).await.unwrap(), None);
+        assert_eq!(
+            index.status(&payment.txid()).await.unwrap(),
+            TxStatus::Mempool
+        );
+        assert_eq!(index.history(&script_hash(&bob)).await.unwrap(), []);
+        assert_eq!(index.script_history(&bob).await.unwrap(), [payment.txid()]);
+        let double_spend = tx(&[coin], &[(&alice, 39_000)]);
+        assert_eq!(
+            index.accept_to_mempool(double_spend).unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+    }
+
+    #[tokio::test]
+    async fn old_blocks_offload_to_cold_storage() {
+        let root = std::env::temp_dir().join(format!(
+            "anya-cold-{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let policy = TieringPolicy {
+            hot_blocks: 1,
+            offload_spent: true,
+        };
+        let (index, alice, _) = indexed_chain().await;
+        let index = index.with_cold_storage(
+            Arc::new(crate::storage::object::LocalObjectStore::new(&root)),
+            policy,
+        );
+        let genesis = index.block_hash(0).await.unwrap().unwrap();
+        let second = index
+            .block(&index.block_hash(1).await.unwrap().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        let coin = second.txdata[1].input[0].previous_output;
+        index
+            .connect_block(&block(second.block_hash(), 2, &alice, vec![]))
+            .await
+            .unwrap();
+
+        let report = index.offload().await.unwrap();
+        assert_eq!((report.blocks, report.spent_outputs), (2, 1));
+        assert_eq!(index.offload().await.unwrap(), OffloadReport::default());
+        assert_eq!(
+            index.block(&second.block_hash()).await.unwrap().unwrap(),
+            second
+        );
+        assert_eq!(
+            index.spender(&coin).await.unwrap(),
+            Some(second.txdata[1].txid())
+        );
+        assert_eq!(
+            index.output(&coin).await.unwrap().unwrap().script_pubkey,
+            alice
+        );
+
+        // The hot block unwinds; cold ones are refused
+        index.disconnect_tip().await.unwrap().unwrap();
+        assert_eq!(
+            index.disconnect_tip().await.unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+
+        // A tampered cold copy is detected on fetch
+        let object: ObjectRef = index
+            .get_json(&format!("{}{}", COLD_BLOCK_PREFIX, genesis))
+            .await
+            .unwrap()
+            .unwrap();
+        let path = root.join(&object.sha256[..2]).join(&object.sha256);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[0] ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(
+            index.block(&genesis).await.unwrap_err().code(),
+            ErrorCode::StorageFailure
+        );
+    }
+}
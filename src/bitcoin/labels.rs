@@ -0,0 +1,310 @@
+//! Wallet labels with BIP-329 import/export
+//!
+//! Labels attach user notes to transactions, addresses, public keys, inputs,
+//! outputs, and xpubs. They are stored one record per reference and can be
+//! exchanged with other wallets as BIP-329 JSON Lines.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::bitcoin::bip32::ExtendedPubKey;
+use ::bitcoin::{Address, OutPoint, PublicKey, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::pagination::{paginate, Page, PageRequest};
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "wallet_labels";
+
+/// What a label refers to, as named by BIP-329
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    /// Transaction id
+    Tx,
+    /// Address
+    Addr,
+    /// Hex public key
+    Pubkey,
+    /// Spent outpoint, `txid:vout`
+    Input,
+    /// Created outpoint, `txid:vout`
+    Output,
+    /// Extended public key
+    Xpub,
+}
+
+impl LabelType {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Tx => "tx",
+            Self::Addr => "addr",
+            Self::Pubkey => "pubkey",
+            Self::Input => "input",
+            Self::Output => "output",
+            Self::Xpub => "xpub",
+        }
+    }
+
+    fn validate(self, reference: &str) -> bool {
+        match self {
+            Self::Tx => Txid::from_str(reference).is_ok(),
+            Self::Addr => Address::from_str(reference).is_ok(),
+            Self::Pubkey => PublicKey::from_str(reference).is_ok(),
+            Self::Input | Self::Output => OutPoint::from_str(reference).is_ok(),
+            Self::Xpub => ExtendedPubKey::from_str(reference).is_ok(),
+        }
+    }
+}
+
+/// A single BIP-329 label record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    /// Kind of reference
+    #[serde(rename = "type")]
+    pub kind: LabelType,
+    /// Referenced item in its canonical string form
+    #[serde(rename = "ref")]
+    pub reference: String,
+    /// Label text
+    pub label: String,
+    /// Descriptor of the wallet the item belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Whether an output may be spent; only meaningful for outputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    /// Parse one BIP-329 JSON line, checking the reference matches its type
+    pub fn parse(line: &str) -> AnyaResult<Self> {
+        let label: Self = serde_json::from_str(line)?;
+        if !label.kind.validate(&label.reference) {
+            return Err(AnyaError::invalid_input(format!(
+                "invalid {} reference: {}",
+                label.kind.as_str(),
+                label.reference
+            )));
+        }
+        Ok(label)
+    }
+
+    /// Label for a transaction
+    pub fn tx(txid: &Txid, label: impl Into<String>) -> Self {
+        Self::new(LabelType::Tx, txid.to_string(), label)
+    }
+
+    /// Label for an address
+    pub fn address(address: &Address, label: impl Into<String>) -> Self {
+        Self::new(LabelType::Addr, address.to_string(), label)
+    }
+
+    /// Label for a wallet output
+    pub fn output(outpoint: &OutPoint, label: impl Into<String>) -> Self {
+        Self::new(LabelType::Output, outpoint.to_string(), label)
+    }
+
+    fn new(kind: LabelType, reference: String, label: impl Into<String>) -> Self {
+        Self {
+            kind,
+            reference,
+            label: label.into(),
+            origin: None,
+            spendable: None,
+        }
+    }
+
+    fn key(&self) -> String {
+        key(self.kind, &self.reference)
+    }
+}
+
+fn key(kind: LabelType, reference: &str) -> String {
+    format!("{}/{}", kind.as_str(), reference)
+}
+
+/// Outcome of a BIP-329 import
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Records written
+    pub imported: usize,
+    /// Records left alone because a label already existed
+    pub skipped: usize,
+    /// Lines that were not valid BIP-329 records
+    pub invalid: usize,
+}
+
+/// Persistent label store
+pub struct LabelStore {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+}
+
+impl LabelStore {
+    /// Open the label store in `storage`
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self { storage, ns })
+    }
+
+    /// Create or replace a label
+    pub async fn set(&self, label: &Label) -> AnyaResult<()> {
+        if !label.kind.validate(&label.reference) {
+            return Err(AnyaError::invalid_input(format!(
+                "invalid {} reference: {}",
+                label.kind.as_str(),
+                label.reference
+            )));
+        }
+        let bytes = serde_json::to_vec(label)?;
+        self.storage.put(&self.ns, &label.key(), &bytes).await
+    }
+
+    /// Look up the label for a reference
+    pub async fn get(&self, kind: LabelType, reference: &str) -> AnyaResult<Option<Label>> {
+        match self.storage.get(&self.ns, &key(kind, reference)).await? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove the label for a reference, returning whether one existed
+    pub async fn remove(&self, kind: LabelType, reference: &str) -> AnyaResult<bool> {
+        self.storage.delete(&self.ns, &key(kind, reference)).await
+    }
+
+    /// All labels, optionally restricted to one kind
+    pub async fn labels(&self, kind: Option<LabelType>) -> AnyaResult<Vec<Label>> {
+        let prefix = kind.map_or_else(String::new, |k| format!("{}/", k.as_str()));
+        self.storage
+            .scan_prefix(&self.ns, &prefix)
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(&bytes)?))
+            .collect()
+    }
+
+    /// One page of labels ordered by type and reference
+    pub async fn labels_page(
+        &self,
+        kind: Option<LabelType>,
+        page: &PageRequest,
+    ) -> AnyaResult<Page<Label>> {
+        paginate(self.labels(kind).await?, page, Label::key)
+    }
+
+    /// Export every label as BIP-329 JSON Lines
+    pub async fn export_jsonl(&self) -> AnyaResult<String> {
+        let mut out = String::new();
+        for label in self.labels(None).await? {
+            out.push_str(&serde_json::to_string(&label)?);
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    /// Import BIP-329 JSON Lines.
+    ///
+    /// Unknown types and malformed lines are counted and skipped rather than
+    /// failing the import. Existing labels are kept unless `overwrite` is set.
+    pub async fn import_jsonl(&self, jsonl: &str, overwrite: bool) -> AnyaResult<ImportReport> {
+        let mut report = ImportReport::default();
+        for line in jsonl.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let Ok(label) = Label::parse(line) else {
+                report.invalid += 1;
+                continue;
+            };
+            if !overwrite && self.get(label.kind, &label.reference).await?.is_some() {
+                report.skipped += 1;
+                continue;
+            }
+            self.set(&label).await?;
+            report.imported += 1;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    const TXID: &str = "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd";
+
+    #[tokio::test]
+    async fn test_export_round_trips_through_import() {
+        let labels = LabelStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let txid = Txid::from_str(TXID).unwrap();
+        labels.set(&Label::tx(&txid, "Rent")).await.unwrap();
+        let mut output = Label::output(&OutPoint::new(txid, 1), "Cold storage");
+        output.spendable = Some(false);
+        labels.set(&output).await.unwrap();
+
+        let exported = labels.export_jsonl().await.unwrap();
+        assert_eq!(exported.lines().count(), 2);
+        assert!(exported.contains(r#""spendable":false"#));
+        assert!(!exported.contains("origin"));
+
+        let other = LabelStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let report = other.import_jsonl(&exported, false).await.unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(
+            other.labels(None).await.unwrap(),
+            labels.labels(None).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_skips_invalid_and_existing_records() {
+        let labels = LabelStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let txid = Txid::from_str(TXID).unwrap();
+        labels.set(&Label::tx(&txid, "Mine")).await.unwrap();
+
+        let jsonl = format!(
+            "{{\"type\":\"tx\",\"ref\":\"{TXID}\",\"label\":\"Theirs\"}}\n\
+             {{\"type\":\"addr\",\"ref\":\"bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq\",\"label\":\"Donations\",\"origin\":\"wpkh([d34db33f/84'/0'/0'])\"}}\n\
+             {{\"type\":\"tx\",\"ref\":\"not-a-txid\",\"label\":\"Bad\"}}\n\
+             {{\"type\":\"unknown\",\"ref\":\"x\",\"label\":\"Future\"}}\n\
+             not json\n"
+        );
+        let report = labels.import_jsonl(&jsonl, false).await.unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 1,
+                skipped: 1,
+                invalid: 3
+            }
+        );
+        assert_eq!(
+            labels
+                .get(LabelType::Tx, TXID)
+                .await
+                .unwrap()
+                .unwrap()
+                .label,
+            "Mine"
+        );
+
+        labels.import_jsonl(&jsonl, true).await.unwrap();
+        assert_eq!(
+            labels
+                .get(LabelType::Tx, TXID)
+                .await
+                .unwrap()
+                .unwrap()
+                .label,
+            "Theirs"
+        );
+    }
+}
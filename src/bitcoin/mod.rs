@@ -0,0 +1,144 @@
+//! Bitcoin and Lightning Network functionality
+
+use ::bitcoin::{Network, ScriptBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::encoding::from_hex;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+pub mod accounts;
+pub mod batch;
+pub mod builder;
+pub mod bump;
+pub mod ceremony;
+pub mod coins;
+pub mod consolidate;
+pub mod descriptor;
+pub mod escrow;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod esplora;
+pub mod fees;
+pub mod import;
+pub mod index;
+pub mod labels;
+pub mod privacy;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod provider;
+pub mod psbt_verify;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod regtest;
+pub mod rescan;
+pub mod reserves;
+pub mod seedqr;
+pub mod sigcache;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod signer;
+pub mod slip39;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod snapshot;
+pub mod spv;
+pub mod tracker;
+pub mod utxo;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod validation;
+pub mod vault;
+
+/// Configuration for the Bitcoin subsystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BitcoinConfig {
+    /// Whether the Bitcoin subsystem is enabled
+    pub enabled: bool,
+    /// Network the node operates on
+    pub network: Network,
+    /// Hex block-signing challenge of a custom signet; the default signet
+    /// is used when unset
+    #[serde(default)]
+    pub signet_challenge: Option<String>,
+}
+
+impl Default for BitcoinConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            network: Network::Bitcoin,
+            signet_challenge: None,
+        }
+    }
+}
+
+impl BitcoinConfig {
+    /// Configuration for a local regtest chain
+    pub fn regtest() -> Self {
+        Self {
+            network: Network::Regtest,
+            ..Self::default()
+        }
+    }
+
+    /// Configuration for a signet, the default one when `challenge` is `None`
+    pub fn signet(challenge: Option<String>) -> Self {
+        Self {
+            network: Network::Signet,
+            signet_challenge: challenge,
+            ..Self::default()
+        }
+    }
+
+    /// Whether coins on the configured network are worthless
+    pub fn is_test_network(&self) -> bool {
+        self.network != Network::Bitcoin
+    }
+
+    /// Decoded custom signet challenge
+    pub fn signet_challenge_script(&self) -> AnyaResult<Option<ScriptBuf>> {
+        self.signet_challenge
+            .as_deref()
+            .map(|hex| Ok(ScriptBuf::from_bytes(from_hex(hex)?)))
+            .transpose()
+    }
+
+    /// Check the configuration is consistent
+    pub fn validate(&self) -> AnyaResult<()> {
+        if self.signet_challenge.is_some() && self.network != Network::Signet {
+            return Err(AnyaError::new(
+                ErrorCode::Config,
+                format!("signet_challenge set for network {}", self.network),
+            ));
+        }
+        match self.signet_challenge_script()? {
+            Some(script) if script.is_empty() => Err(AnyaError::new(
+                ErrorCode::Config,
+                "signet_challenge is empty",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_config_validation() {
+        assert!(BitcoinConfig::default().validate().is_ok());
+        assert!(!BitcoinConfig::default().is_test_network());
+        assert!(BitcoinConfig::regtest().is_test_network());
+
+        let challenge = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+        let signet = BitcoinConfig::signet(Some(challenge.to_string()));
+        signet.validate().unwrap();
+        assert_eq!(signet.signet_challenge_script().unwrap().unwrap().len(), 71);
+
+        let misplaced = BitcoinConfig {
+            signet_challenge: Some(challenge.to_string()),
+            ..BitcoinConfig::regtest()
+        };
+        assert_eq!(misplaced.validate().unwrap_err().code(), ErrorCode::Config);
+        let garbled = BitcoinConfig::signet(Some("51zz".to_string()));
+        assert_eq!(
+            garbled.validate().unwrap_err().code(),
+            ErrorCode::InvalidInput
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! Privacy review of transactions before broadcast
+//!
+//! Chain analysis clusters addresses and tells payments from change with a
+//! handful of well-known heuristics. [`analyze`] runs them against a built
+//! transaction and reports each leak with a suggested mitigation, so the
+//! user can rework the transaction before it becomes public:
+//!
+//! - address reuse: paying to an address that already received funds, or
+//!   spending several coins held by one address
+//! - round amounts: a round payment next to non-round change gives the
+//!   change away
+//! - common-input-ownership: spending coins together proves one owner;
+//!   worst when they carry different labels, i.e. come from different
+//!   sources
+//! - unnecessary inputs: an input the payment did not need shows the change
+//!   is the output smaller than every input
+//! - change script type: change of a different address type than every
+//!   payment stands out
+//!
+//! [`PrivacyContext::gather`] collects what the wallet knows about the
+//! transaction's scripts and coins; [`TxBuilder::review_privacy`] and
+//! [`MobileWallet::review_privacy`] wrap both steps.
+//!
+//! [`TxBuilder::review_privacy`]: super::builder::TxBuilder::review_privacy
+//! [`MobileWallet::review_privacy`]: crate::mobile::wallet::MobileWallet::review_privacy
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+use ::bitcoin::address::NetworkUnchecked;
+use ::bitcoin::secp256k1::Secp256k1;
+use ::bitcoin::{Address, OutPoint, Script, ScriptBuf};
+use serde::{Deserialize, Serialize};
+
+use super::accounts::{AccountId, AccountManager};
+use super::builder::BuiltTx;
+use super::labels::{LabelStore, LabelType};
+use crate::AnyaResult;
+
+/// Amounts that are a multiple of this many satoshis (0.001 BTC) look
+/// chosen by a person
+const ROUND_AMOUNT_SAT: u64 = 100_000;
+
+/// Kind of privacy leak
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Leak {
+    /// An address is used more than once
+    AddressReuse,
+    /// Round payment amounts reveal the change
+    RoundAmount,
+    /// Inputs are linked as having one owner
+    InputMerge,
+    /// More inputs than needed reveal the change
+    UnnecessaryInput,
+    /// The change address type differs from the payments
+    ChangeScriptType,
+}
+
+/// How much a leak reveals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Weak evidence on its own
+    Low,
+    /// Usually enough to identify change
+    Medium,
+    /// Links identities or funds sources outright
+    High,
+}
+
+impl Severity {
+    /// Points taken off the privacy score
+    pub const fn penalty(self) -> u8 {
+        match self {
+            Self::Low => 10,
+            Self::Medium => 25,
+            Self::High => 40,
+        }
+    }
+}
+
+/// One detected leak
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    /// Heuristic that fired
+    pub leak: Leak,
+    /// How much it reveals
+    pub severity: Severity,
+    /// What was found
+    pub detail: String,
+    /// What to change to avoid it
+    pub mitigation: String,
+}
+
+/// Result of a privacy review
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacyReport {
+    /// 100 without findings, lower the more the transaction leaks
+    pub score: u8,
+    /// Leaks found, most severe first
+    pub findings: Vec<Finding>,
+}
+
+impl PrivacyReport {
+    /// Whether no leak was found
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Whether any finding is at least `severity`
+    pub fn has(&self, severity: Severity) -> bool {
+        self.findings.iter().any(|f| f.severity >= severity)
+    }
+}
+
+/// What the wallet knows beyond the transaction itself
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrivacyContext {
+    /// Output scripts known to have been used before
+    pub used_scripts: HashSet<ScriptBuf>,
+    /// Labels of the coins being spent
+    pub input_labels: HashMap<OutPoint, String>,
+}
+
+impl PrivacyContext {
+    /// Collect what `accounts` and `labels` know about `built`, which pays
+    /// from `account`. Output scripts count as used when they are addresses
+    /// of the account below its highest used index, or labelled addresses.
+    pub async fn gather(
+        accounts: &AccountManager,
+        account: AccountId,
+        labels: Option<&LabelStore>,
+        built: &BuiltTx,
+    ) -> AnyaResult<Self> {
+        let secp = Secp256k1::verification_only();
+        let account = accounts.account(account).await?;
+        let tx = &built.psbt.unsigned_tx;
+        let mut context = Self::default();
+        for (vout, output) in tx.output.iter().enumerate() {
+            if Some(vout) == built.change_vout {
+                continue;
+            }
+            if let Some((chain, index)) = account.find_script(&secp, &output.script_pubkey)? {
+                if account.is_used_at(chain, index) {
+                    context.used_scripts.insert(output.script_pubkey.clone());
+                }
+            }
+        }
+        let Some(labels) = labels else {
+            return Ok(context);
+        };
+        for label in labels.labels(Some(LabelType::Addr)).await? {
+            let Ok(address) = Address::<NetworkUnchecked>::from_str(&label.reference) else {
+                continue;
+            };
+            let script = address.assume_checked().script_pubkey();
+            if tx.output.iter().any(|o| o.script_pubkey == script) {
+                context.used_scripts.insert(script);
+            }
+        }
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            if let Some(label) = labels.get(LabelType::Output, &outpoint.to_string()).await? {
+                context.input_labels.insert(outpoint, label.label);
+            }
+        }
+        Ok(context)
+    }
+}
+
+fn script_kind(script: &Script) -> &'static str {
+    if script.is_v1_p2tr() {
+        "taproot"
+    } else if script.is_v0_p2wpkh() {
+        "p2wpkh"
+    } else if script.is_v0_p2wsh() {
+        "p2wsh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else {
+        "other"
+    }
+}
+
+const fn is_round(amount_sat: u64) -> bool {
+    amount_sat > 0 && amount_sat % ROUND_AMOUNT_SAT == 0
+}
+
+/// Review `built` for privacy leaks. Inputs without a `witness_utxo` are
+/// left out of the value-based checks.
+pub fn analyze(built: &BuiltTx, context: &PrivacyContext) -> PrivacyReport {
+    let tx = &built.psbt.unsigned_tx;
+    let inputs: Vec<(OutPoint, Option<&::bitcoin::TxOut>)> = tx
+        .input
+        .iter()
+        .zip(&built.psbt.inputs)
+        .map(|(txin, input)| (txin.previous_output, input.witness_utxo.as_ref()))
+        .collect();
+    let change = built.change_vout.and_then(|vout| tx.output.get(vout));
+    let payments: Vec<_> = tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(vout, _)| Some(*vout) != built.change_vout)
+        .map(|(_, output)| output)
+        .collect();
+    let mut findings = Vec::new();
+
+    // Address reuse
+    let input_scripts: Vec<&ScriptBuf> = inputs
+        .iter()
+        .filter_map(|(_, utxo)| utxo.map(|u| &u.script_pubkey))
+        .collect();
+    let distinct_inputs: HashSet<_> = input_scripts.iter().collect();
+    if distinct_inputs.len() < input_scripts.len() {
+        findings.push(Finding {
+            leak: Leak::AddressReuse,
+            severity: Severity::Medium,
+            detail: "several inputs are held by the same address".into(),
+            mitigation: "give out a fresh address for every payment received".into(),
+        });
+    }
+    let reused = payments
+        .iter()
+        .filter(|o| {
+            context.used_scripts.contains(&o.script_pubkey)
+                || input_scripts.contains(&&o.script_pubkey)
+        })
+        .count();
+    if reused > 0 {
+        findings.push(Finding {
+            leak: Leak::AddressReuse,
+            severity: Severity::High,
+            detail: format!("{} payment(s) go to an address used before", reused),
+            mitigation: "ask the recipient for a fresh address".into(),
+        });
+    }
+    if change.is_some_and(|c| input_scripts.contains(&&c.script_pubkey)) {
+        findings.push(Finding {
+            leak: Leak::AddressReuse,
+            severity: Severity::High,
+            detail: "change returns to an address being spent from".into(),
+            mitigation: "send change to a new internal address".into(),
+        });
+    }
+
+    if let Some(change) = change {
+        // Round amounts
+        if !is_round(change.value) && payments.iter().any(|p| is_round(p.value)) {
+            findings.push(Finding {
+                leak: Leak::RoundAmount,
+                severity: Severity::Medium,
+                detail: "round payment amounts set the non-round change apart".into(),
+                mitigation: "pay a non-round amount, or spend whole coins without change".into(),
+            });
+        }
+
+        // Change script type
+        let change_kind = script_kind(&change.script_pubkey);
+        if !payments.is_empty()
+            && payments
+                .iter()
+                .all(|p| script_kind(&p.script_pubkey) != change_kind)
+        {
+            findings.push(Finding {
+                leak: Leak::ChangeScriptType,
+                severity: Severity::Low,
+                detail: format!("change is the only {} output", change_kind),
+                mitigation: "pay from an account of the recipient's address type".into(),
+            });
+        }
+
+        // Unnecessary inputs
+        let values: Vec<u64> = inputs
+            .iter()
+            .filter_map(|(_, utxo)| utxo.map(|u| u.value))
+            .collect();
+        if values.len() == inputs.len() && values.len() > 1 {
+            let needed: u64 = payments.iter().map(|p| p.value).sum::<u64>() + built.fee_sat;
+            let smallest = values.iter().copied().min().unwrap_or_default();
+            if values.iter().sum::<u64>() - smallest >= needed {
+                findings.push(Finding {
+                    leak: Leak::UnnecessaryInput,
+                    severity: Severity::Medium,
+                    detail: "the payment could be made without the smallest input".into(),
+                    mitigation: "select coins manually to spend only what is needed".into(),
+                });
+            }
+        }
+    }
+
+    // Common-input-ownership
+    if inputs.len() > 1 {
+        let sources: BTreeSet<&str> = inputs
+            .iter()
+            .filter_map(|(outpoint, _)| context.input_labels.get(outpoint))
+            .map(String::as_str)
+            .collect();
+        if sources.len() > 1 {
+            findings.push(Finding {
+                leak: Leak::InputMerge,
+                severity: Severity::High,
+                detail: format!(
+                    "merges coins from different sources: {}",
+                    sources.into_iter().collect::<Vec<_>>().join(", ")
+                ),
+                mitigation: "spend coins of each source in separate transactions".into(),
+            });
+        } else {
+            findings.push(Finding {
+                leak: Leak::InputMerge,
+                severity: Severity::Low,
+                detail: format!("links {} coins as owned by one wallet", inputs.len()),
+                mitigation: "spend a single coin large enough for the payment".into(),
+            });
+        }
+    }
+
+    findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+    let penalty: u32 = findings
+        .iter()
+        .map(|f| u32::from(f.severity.penalty()))
+        .sum();
+    PrivacyReport {
+        score: u8::try_from(100u32.saturating_sub(penalty)).unwrap_or_default(),
+        findings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::{KeyChain, ScriptType};
+    use crate::bitcoin::builder::{InputSelection, Recipient, TxBuilder, TxRequest};
+    use crate::bitcoin::coins::{CoinStore, Utxo};
+    use crate::bitcoin::labels::Label;
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::StorageBackend;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{FeeRate, Network, TxOut, Txid};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_review() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let accounts = AccountManager::open(Network::Regtest, Arc::clone(&storage))
+            .await
+            .unwrap();
+        let coins = CoinStore::open(Arc::clone(&storage)).await.unwrap();
+        let labels = LabelStore::open(storage).await.unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let funded = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "main")
+            .await
+            .unwrap();
+        let account = funded.id;
+        let secp = Secp256k1::verification_only();
+        for (n, value) in [(0u32, 60_000u64), (1, 70_000)] {
+            let address = funded.address(&secp, KeyChain::External, n).unwrap();
+            let outpoint = OutPoint::new(Txid::from_byte_array([n as u8 + 1; 32]), 0);
+            coins
+                .insert(&Utxo {
+                    outpoint,
+                    txout: TxOut {
+                        value,
+                        script_pubkey: address.script_pubkey(),
+                    },
+                    account,
+                    chain: KeyChain::External,
+                    index: n,
+                    height: Some(100),
+                })
+                .await
+                .unwrap();
+            labels
+                .set(&Label::output(&outpoint, format!("source {}", n)))
+                .await
+                .unwrap();
+            accounts
+                .mark_used(account, KeyChain::External, n)
+                .await
+                .unwrap();
+        }
+
+        // Pays a round amount back to a used receive address, merging both
+        // labelled coins
+        let builder = TxBuilder::new(&accounts, &coins);
+        let reused = funded.address(&secp, KeyChain::External, 0).unwrap();
+        let request = TxRequest {
+            account,
+            recipients: vec![Recipient::new(&reused, 100_000)],
+            fee_rate: FeeRate::from_sat_per_vb_unchecked(2),
+            inputs: InputSelection::Auto,
+            shuffle_outputs: false,
+        };
+        let built = builder.build(&request).await.unwrap();
+        let report = builder
+            .review_privacy(&request, &built, Some(&labels))
+            .await
+            .unwrap();
+        let leaks: HashSet<Leak> = report.findings.iter().map(|f| f.leak).collect();
+        assert!(leaks.contains(&Leak::AddressReuse));
+        assert!(leaks.contains(&Leak::RoundAmount));
+        assert!(report.findings.iter().any(|f| f.leak == Leak::InputMerge
+            && f.severity == Severity::High
+            && f.detail.contains("source 0")));
+        assert!(!leaks.contains(&Leak::UnnecessaryInput));
+        assert!(report.has(Severity::High));
+        assert_eq!(report.findings[0].severity, Severity::High);
+        assert!(report.score < 50);
+
+        // One coin, fresh address of the same type, odd amount
+        let fresh = funded.address(&secp, KeyChain::External, 5).unwrap();
+        let request = TxRequest {
+            recipients: vec![Recipient::new(&fresh, 41_234)],
+            inputs: InputSelection::Manual(vec![OutPoint::new(Txid::from_byte_array([2; 32]), 0)]),
+            ..request
+        };
+        let built = builder.build(&request).await.unwrap();
+        let report = builder
+            .review_privacy(&request, &built, Some(&labels))
+            .await
+            .unwrap();
+        assert!(report.is_clean(), "{:?}", report.findings);
+        assert_eq!(report.score, 100);
+    }
+}
@@ -0,0 +1,751 @@
+//! Blockchain data providers
+//!
+//! A [`ChainDataProvider`] answers the chain queries wallets and analytics
+//! need, extending the tracker's [`ChainSource`] with blocks and broadcast.
+//! Three backends are available:
+//!
+//! - [`ChainIndex`](super::index::ChainIndex): the internal node's own
+//!   block store and indexes
+//! - [`CoreRpcProvider`]: a Bitcoin Core node over JSON-RPC; Core keeps no
+//!   address index, so script history is unavailable and spenders are only
+//!   found in the mempool
+//! - [`EsploraProvider`]: an Esplora HTTP API, so components can run in
+//!   light mode without a local node
+//!
+//! [`FailoverProvider`] puts several providers behind one, trying them in
+//! order and passing over a provider for a cooldown after it fails.
+//! Immutable data, blocks and transactions, is read through a cache so the
+//! remote providers are asked for it once. The remote providers check that
+//! a transaction or block hashes to the id it was requested by, so a faulty
+//! or hostile server fails over instead of poisoning that cache.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use ::bitcoin::consensus::encode::{deserialize, serialize};
+use ::bitcoin::{Block, BlockHash, OutPoint, Script, Transaction, Txid};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use super::index::script_hash;
+use super::tracker::{ChainSource, TxStatus};
+use crate::cache::{Cache, CacheConfig};
+use crate::integrations::{http_error, Auth, Authenticator, HttpRequest, HttpTransport};
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Bitcoin Core RPC error for an unknown transaction or block
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+/// Bitcoin Core RPC error for a height beyond the tip
+const RPC_INVALID_PARAMETER: i64 = -8;
+
+/// Source of blockchain data for wallets and analytics
+#[async_trait]
+pub trait ChainDataProvider: ChainSource {
+    /// Short name used in logs
+    fn name(&self) -> &str;
+    /// Hash of the block at `height` on the best chain
+    async fn block_hash(&self, height: u32) -> AnyaResult<Option<BlockHash>>;
+    /// Fetch a block
+    async fn block(&self, hash: &BlockHash) -> AnyaResult<Option<Block>>;
+    /// Submit a transaction to the network
+    async fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid>;
+}
+
+fn decode<T: ::bitcoin::consensus::Decodable>(hex: &str) -> AnyaResult<T> {
+    deserialize(&from_hex(hex.trim())?).map_err(|e| {
+        AnyaError::new(
+            ErrorCode::BitcoinFailure,
+            format!("undecodable chain data: {}", e),
+        )
+    })
+}
+
+/// `tx` if it is the transaction `txid` names.
+///
+/// A mismatch fails as a retryable `NetworkFailure`, so failover asks the
+/// next provider instead of caching whatever this one sent.
+fn verified_tx(provider: &str, txid: &Txid, tx: Transaction) -> AnyaResult<Transaction> {
+    if tx.txid() == *txid {
+        Ok(tx)
+    } else {
+        Err(mismatch(provider, "transaction", txid))
+    }
+}
+
+/// `block` if its header hashes to `hash` and commits to its transactions
+fn verified_block(provider: &str, hash: &BlockHash, block: Block) -> AnyaResult<Block> {
+    if block.block_hash() == *hash && block.check_merkle_root() {
+        Ok(block)
+    } else {
+        Err(mismatch(provider, "block", hash))
+    }
+}
+
+fn mismatch(provider: &str, what: &str, id: &dyn std::fmt::Display) -> AnyaError {
+    AnyaError::new(
+        ErrorCode::NetworkFailure,
+        format!(
+            "{} provider returned data not matching {} {}",
+            provider, what, id
+        ),
+    )
+}
+
+fn unsupported(provider: &str, what: &str) -> AnyaError {
+    AnyaError::new(
+        ErrorCode::Unavailable,
+        format!("{} provider cannot look up {}", provider, what),
+    )
+}
+
+/// Bitcoin Core over JSON-RPC
+pub struct CoreRpcProvider {
+    url: String,
+    transport: Arc<dyn HttpTransport>,
+    auth: Authenticator,
+    next_id: AtomicU64,
+}
+
+impl CoreRpcProvider {
+    /// Provider calling the RPC server at `url` with `auth`, usually
+    /// [`Auth::Basic`] with the `rpcauth` credentials
+    pub fn new(url: impl Into<String>, auth: Auth, transport: Arc<dyn HttpTransport>) -> Self {
+        Self {
+            url: url.into(),
+            auth: Authenticator::new(auth, Arc::clone(&transport)),
+            transport,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Call `method`, returning `None` when Core answers with the RPC error
+    /// `absent`
+    async fn call(&self, method: &str, params: Value, absent: i64) -> AnyaResult<Option<Value>> {
+        let mut request = HttpRequest::new("POST", self.url.clone());
+        request
+            .headers
+            .insert("content-type".into(), "application/json".into());
+        request.body = Some(serde_json::to_vec(&json!({
+            "jsonrpc": "1.0",
+            "id": self.next_id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        }))?);
+        self.auth.apply(&mut request).await?;
+        let response = self.transport.send(request.clone()).await?;
+        // Core reports RPC errors in the body of a 404 or 500 response
+        let Ok(body) = response.json() else {
+            return Err(http_error(&request, &response));
+        };
+        match body.get("error").filter(|e| !e.is_null()) {
+            Some(error) if error["code"].as_i64() == Some(absent) => Ok(None),
+            Some(error) => Err(AnyaError::new(
+                ErrorCode::BitcoinFailure,
+                format!(
+                    "{} failed: {}",
+                    method,
+                    error["message"].as_str().unwrap_or("unknown error")
+                ),
+            )),
+            None if (200..300).contains(&response.status) => Ok(body.get("result").cloned()),
+            None => Err(http_error(&request, &response)),
+        }
+    }
+
+    async fn call_str(
+        &self,
+        method: &str,
+        params: Value,
+        absent: i64,
+    ) -> AnyaResult<Option<String>> {
+        self.call(method, params, absent)
+            .await?
+            .map(|result| {
+                result.as_str().map(str::to_string).ok_or_else(|| {
+                    AnyaError::new(
+                        ErrorCode::BitcoinFailure,
+                        format!("{} returned {}", method, result),
+                    )
+                })
+            })
+            .transpose()
+    }
+}
+
+#[async_trait]
+impl ChainSource for CoreRpcProvider {
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        self.call("getblockcount", json!([]), 0)
+            .await?
+            .and_then(|count| count.as_u64())
+            .and_then(|count| u32::try_from(count).ok())
+            .ok_or_else(|| AnyaError::new(ErrorCode::BitcoinFailure, "getblockcount failed"))
+    }
+
+    async fn script_history(&self, _script: &Script) -> AnyaResult<Vec<Txid>> {
+        Err(unsupported(self.name(), "script history"))
+    }
+
+    async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        self.call_str(
+            "getrawtransaction",
+            json!([txid.to_string()]),
+            RPC_INVALID_ADDRESS_OR_KEY,
+        )
+        .await?
+        .map(|hex| verified_tx(self.name(), txid, decode(&hex)?))
+        .transpose()
+    }
+
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        let Some(tx) = self
+            .call(
+                "getrawtransaction",
+                json!([txid.to_string(), true]),
+                RPC_INVALID_ADDRESS_OR_KEY,
+            )
+            .await?
+        else {
+            return Ok(TxStatus::Unknown);
+        };
+        let Some(block) = tx["blockhash"].as_str() else {
+            return Ok(TxStatus::Mempool);
+        };
+        let height = self
+            .call("getblockheader", json!([block]), RPC_INVALID_ADDRESS_OR_KEY)
+            .await?
+            .and_then(|header| header["height"].as_u64())
+            .and_then(|height| u32::try_from(height).ok());
+        Ok(height.map_or(TxStatus::Unknown, |height| TxStatus::Confirmed { height }))
+    }
+
+    async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+        let spending = self
+            .call(
+                "gettxspendingprevout",
+                json!([[{ "txid": outpoint.txid.to_string(), "vout": outpoint.vout }]]),
+                0,
+            )
+            .await?;
+        spending
+            .as_ref()
+            .and_then(|s| s[0]["spendingtxid"].as_str())
+            .map(|txid| {
+                txid.parse()
+                    .map_err(|_| AnyaError::new(ErrorCode::BitcoinFailure, "invalid spending txid"))
+            })
+            .transpose()
+    }
+}
+
+#[async_trait]
+impl ChainDataProvider for CoreRpcProvider {
+    fn name(&self) -> &str {
+        "core"
+    }
+
+    async fn block_hash(&self, height: u32) -> AnyaResult<Option<BlockHash>> {
+        self.call_str("getblockhash", json!([height]), RPC_INVALID_PARAMETER)
+            .await?
+            .map(|hash| {
+                hash.parse()
+                    .map_err(|_| AnyaError::new(ErrorCode::BitcoinFailure, "invalid block hash"))
+            })
+            .transpose()
+    }
+
+    async fn block(&self, hash: &BlockHash) -> AnyaResult<Option<Block>> {
+        self.call_str(
+            "getblock",
+            json!([hash.to_string(), 0]),
+            RPC_INVALID_ADDRESS_OR_KEY,
+        )
+        .await?
+        .map(|hex| verified_block(self.name(), hash, decode(&hex)?))
+        .transpose()
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid> {
+        self.call_str("sendrawtransaction", json!([to_hex(&serialize(tx))]), 0)
+            .await?;
+        Ok(tx.txid())
+    }
+}
+
+/// An Esplora HTTP API such as Blockstream's or mempool.space's
+pub struct EsploraProvider {
+    base_url: String,
+    transport: Arc<dyn HttpTransport>,
+}
+
+impl EsploraProvider {
+    /// Provider for the API at `base_url`, e.g.
+    /// `https://blockstream.info/api`
+    pub fn new(base_url: impl Into<String>, transport: Arc<dyn HttpTransport>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            transport,
+        }
+    }
+
+    /// `GET path`, `None` on 404
+    async fn get(&self, path: &str) -> AnyaResult<Option<Vec<u8>>> {
+        let request = HttpRequest::new("GET", format!("{}{}", self.base_url, path));
+        let response = self.transport.send(request.clone()).await?;
+        match response.status {
+            200..=299 => Ok(Some(response.body)),
+            404 => Ok(None),
+            _ => Err(http_error(&request, &response)),
+        }
+    }
+
+    async fn get_text(&self, path: &str) -> AnyaResult<Option<String>> {
+        Ok(self
+            .get(path)
+            .await?
+            .map(|body| String::from_utf8_lossy(&body).trim().to_string()))
+    }
+
+    async fn get_json(&self, path: &str) -> AnyaResult<Option<Value>> {
+        self.get(path)
+            .await?
+            .map(|body| serde_json::from_slice(&body).map_err(Into::into))
+            .transpose()
+    }
+}
+
+fn txids(list: &Value) -> Vec<Txid> {
+    list.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|tx| tx["txid"].as_str()?.parse().ok())
+        .collect()
+}
+
+#[async_trait]
+impl ChainSource for EsploraProvider {
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        self.get_text("/blocks/tip/height")
+            .await?
+            .and_then(|height| height.parse().ok())
+            .ok_or_else(|| AnyaError::new(ErrorCode::BitcoinFailure, "no tip height"))
+    }
+
+    async fn script_history(&self, script: &Script) -> AnyaResult<Vec<Txid>> {
+        // The first page holds the mempool transactions and the newest
+        // confirmed ones; older confirmed pages follow the last txid seen
+        let path = format!("/scripthash/{}/txs", script_hash(script));
+        let mut history = txids(&self.get_json(&path).await?.unwrap_or_default());
+        let mut seen = history.len();
+        while let Some(last) = history.last().copied().filter(|_| seen > 0) {
+            let page = self
+                .get_json(&format!("{}/chain/{}", path, last))
+                .await?
+                .unwrap_or_default();
+            let page = txids(&page);
+            seen = page.len();
+            history.extend(page.into_iter().filter(|txid| *txid != last));
+            if history.last() == Some(&last) {
+                break;
+            }
+        }
+        Ok(history)
+    }
+
+    async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        self.get_text(&format!("/tx/{}/hex", txid))
+            .await?
+            .map(|hex| verified_tx(self.name(), txid, decode(&hex)?))
+            .transpose()
+    }
+
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        let Some(status) = self.get_json(&format!("/tx/{}/status", txid)).await? else {
+            return Ok(TxStatus::Unknown);
+        };
+        let height = status["block_height"]
+            .as_u64()
+            .and_then(|h| u32::try_from(h).ok());
+        Ok(match (status["confirmed"].as_bool(), height) {
+            (Some(true), Some(height)) => TxStatus::Confirmed { height },
+            _ => TxStatus::Mempool,
+        })
+    }
+
+    async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+        Ok(self
+            .get_json(&format!("/tx/{}/outspend/{}", outpoint.txid, outpoint.vout))
+            .await?
+            .filter(|spend| spend["spent"].as_bool() == Some(true))
+            .and_then(|spend| spend["txid"].as_str()?.parse().ok()))
+    }
+}
+
+#[async_trait]
+impl ChainDataProvider for EsploraProvider {
+    fn name(&self) -> &str {
+        "esplora"
+    }
+
+    async fn block_hash(&self, height: u32) -> AnyaResult<Option<BlockHash>> {
+        self.get_text(&format!("/block-height/{}", height))
+            .await?
+            .map(|hash| {
+                hash.parse()
+                    .map_err(|_| AnyaError::new(ErrorCode::BitcoinFailure, "invalid block hash"))
+            })
+            .transpose()
+    }
+
+    async fn block(&self, hash: &BlockHash) -> AnyaResult<Option<Block>> {
+        self.get(&format!("/block/{}/raw", hash))
+            .await?
+            .map(|raw| {
+                let block = deserialize(&raw).map_err(|e| {
+                    AnyaError::new(
+                        ErrorCode::BitcoinFailure,
+                        format!("undecodable block {}: {}", hash, e),
+                    )
+                })?;
+                verified_block(self.name(), hash, block)
+            })
+            .transpose()
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid> {
+        let mut request = HttpRequest::new("POST", format!("{}/tx", self.base_url));
+        request.body = Some(to_hex(&serialize(tx)).into_bytes());
+        let response = self.transport.send(request.clone()).await?;
+        if !(200..300).contains(&response.status) {
+            return Err(http_error(&request, &response));
+        }
+        Ok(tx.txid())
+    }
+}
+
+/// Several providers behind one, with failover and a read-through cache
+pub struct FailoverProvider {
+    providers: Vec<Arc<dyn ChainDataProvider>>,
+    cooldown: Duration,
+    failed: Mutex<HashMap<usize, Instant>>,
+    blocks: Cache<BlockHash, Block>,
+    transactions: Cache<Txid, Transaction>,
+}
+
+impl FailoverProvider {
+    /// Try `providers` in order, passing over one for `cooldown` after a
+    /// failure
+    pub fn new(providers: Vec<Arc<dyn ChainDataProvider>>, cooldown: Duration) -> Self {
+        let config = CacheConfig {
+            capacity: 256,
+            default_ttl: Duration::from_secs(3600),
+            ..CacheConfig::default()
+        };
+        Self {
+            providers,
+            cooldown,
+            failed: Mutex::new(HashMap::new()),
+            blocks: Cache::new("chain_blocks", config.clone()),
+            transactions: Cache::new("chain_transactions", config),
+        }
+    }
+
+    /// Names of the providers currently passed over
+    pub fn cooling_down(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.failed()
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(i, _)| self.providers[*i].name().to_string())
+            .collect()
+    }
+
+    fn failed(&self) -> MutexGuard<'_, HashMap<usize, Instant>> {
+        self.failed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run `op` against the providers in order, healthy ones first, until
+    /// one succeeds or fails with an error retrying elsewhere cannot fix
+    async fn first<T, F, Fut>(&self, what: &str, op: F) -> AnyaResult<T>
+    where
+        F: Fn(Arc<dyn ChainDataProvider>) -> Fut,
+        Fut: Future<Output = AnyaResult<T>>,
+    {
+        let now = Instant::now();
+        let mut order: Vec<usize> = (0..self.providers.len()).collect();
+        {
+            let failed = self.failed();
+            order.sort_by_key(|i| failed.get(i).is_some_and(|until| *until > now));
+        }
+        let mut last = AnyaError::new(ErrorCode::Unavailable, "no chain data provider configured");
+        for i in order {
+            let provider = Arc::clone(&self.providers[i]);
+            match op(Arc::clone(&provider)).await {
+                Ok(value) => {
+                    self.failed().remove(&i);
+                    return Ok(value);
+                }
+                Err(e) if e.is_retryable() => {
+                    // Unsupported queries say nothing about the provider's health
+                    if e.code() != ErrorCode::Unavailable {
+                        warn!(provider = provider.name(), what, error = %e, "chain data provider failed");
+                        self.failed().insert(i, Instant::now() + self.cooldown);
+                    }
+                    last = e;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last)
+    }
+}
+
+#[async_trait]
+impl ChainSource for FailoverProvider {
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        self.first("tip height", |p| async move { p.tip_height().await })
+            .await
+    }
+
+    async fn script_history(&self, script: &Script) -> AnyaResult<Vec<Txid>> {
+        self.first("script history", |p| async move {
+            p.script_history(script).await
+        })
+        .await
+    }
+
+    async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        if let Some(tx) = self.transactions.get(txid).await {
+            return Ok(Some(tx));
+        }
+        let tx = self
+            .first("transaction", |p| async move { p.transaction(txid).await })
+            .await?;
+        if let Some(tx) = &tx {
+            self.transactions.insert(*txid, tx.clone()).await;
+        }
+        Ok(tx)
+    }
+
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        self.first("status", |p| async move { p.status(txid).await })
+            .await
+    }
+
+    async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+        self.first("spender", |p| async move { p.spender(outpoint).await })
+            .await
+    }
+}
+
+#[async_trait]
+impl ChainDataProvider for FailoverProvider {
+    fn name(&self) -> &str {
+        "failover"
+    }
+
+    async fn block_hash(&self, height: u32) -> AnyaResult<Option<BlockHash>> {
+        self.first("block hash", |p| async move { p.block_hash(height).await })
+            .await
+    }
+
+    async fn block(&self, hash: &BlockHash) -> AnyaResult<Option<Block>> {
+        if let Some(block) = self.blocks.get(hash).await {
+            return Ok(Some(block));
+        }
+        let block = self
+            .first("block", |p| async move { p.block(hash).await })
+            .await?;
+        if let Some(block) = &block {
+            self.blocks.insert(*hash, block.clone()).await;
+        }
+        Ok(block)
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid> {
+        self.first("broadcast", |p| async move { p.broadcast(tx).await })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index::tests::indexed_chain;
+    use super::*;
+    use crate::integrations::HttpResponse;
+    use std::collections::BTreeMap;
+
+    /// Answers requests from a path table and counts them; unknown paths
+    /// fail like an unreachable server
+    #[derive(Default)]
+    struct Server {
+        routes: BTreeMap<String, (u16, Vec<u8>)>,
+        hits: AtomicU64,
+    }
+
+    #[async_trait]
+    impl HttpTransport for Server {
+        async fn send(&self, request: HttpRequest) -> AnyaResult<HttpResponse> {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            let path = request.url.trim_start_matches("http://esplora");
+            let (status, body) =
+                self.routes.get(path).cloned().ok_or_else(|| {
+                    AnyaError::new(ErrorCode::NetworkFailure, "connection refused")
+                })?;
+            Ok(HttpResponse {
+                status,
+                headers: BTreeMap::new(),
+                body,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn esplora_answers_are_parsed() {
+        let txid = "ab".repeat(32);
+        let spender = "cd".repeat(32);
+        let mut server = Server::default();
+        for (path, status, body) in [
+            (
+                "/blocks/tip/height".to_string(),
+                200,
+                "812345\n".to_string(),
+            ),
+            (
+                format!("/tx/{}/status", txid),
+                200,
+                r#"{"confirmed":true,"block_height":812000}"#.into(),
+            ),
+            (
+                format!("/tx/{}/outspend/1", txid),
+                200,
+                format!(r#"{{"spent":true,"txid":"{}"}}"#, spender),
+            ),
+            (
+                format!("/tx/{}/outspend/2", txid),
+                200,
+                r#"{"spent":false}"#.into(),
+            ),
+            (
+                format!("/tx/{}/status", spender),
+                404,
+                "Transaction not found".into(),
+            ),
+        ] {
+            server.routes.insert(path, (status, body.into_bytes()));
+        }
+        let esplora = EsploraProvider::new("http://esplora/", Arc::new(server));
+        let txid: Txid = txid.parse().unwrap();
+        assert_eq!(esplora.tip_height().await.unwrap(), 812_345);
+        assert_eq!(
+            esplora.status(&txid).await.unwrap(),
+            TxStatus::Confirmed { height: 812_000 }
+        );
+        assert_eq!(
+            esplora.spender(&OutPoint::new(txid, 1)).await.unwrap(),
+            Some(spender.parse().unwrap())
+        );
+        assert_eq!(
+            esplora.spender(&OutPoint::new(txid, 2)).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            esplora.status(&spender.parse().unwrap()).await.unwrap(),
+            TxStatus::Unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn failover_skips_down_and_unsupported_providers() {
+        let (index, _, bob) = indexed_chain().await;
+        let core_server = Arc::new(Server::default());
+        let core = CoreRpcProvider::new("http://core", Auth::None, core_server.clone());
+        let failover = FailoverProvider::new(
+            vec![Arc::new(core), Arc::new(index)],
+            Duration::from_secs(60),
+        );
+
+        // Core is unreachable and cools down after the first failure
+        assert_eq!(failover.tip_height().await.unwrap(), 1);
+        assert_eq!(failover.cooling_down(), ["core"]);
+        assert_eq!(failover.script_history(&bob).await.unwrap().len(), 1);
+        let hash = failover.block_hash(1).await.unwrap().unwrap();
+        assert_eq!(core_server.hits.load(Ordering::Relaxed), 1);
+
+        // Blocks are read through the cache
+        let block = failover.block(&hash).await.unwrap().unwrap();
+        assert_eq!(failover.block(&hash).await.unwrap(), Some(block));
+        assert_eq!(failover.blocks.stats().await.hits, 1);
+    }
+
+    #[tokio::test]
+    async fn mismatched_chain_data_fails_over() {
+        let (index, _, _) = indexed_chain().await;
+        let hash = index.block_hash(1).await.unwrap().unwrap();
+        let block = index.block(&hash).await.unwrap().unwrap();
+        let (coinbase, payment) = (&block.txdata[0], &block.txdata[1]);
+
+        // Esplora answers with another transaction and a block whose
+        // transactions were swapped under the original header
+        let mut tampered = block.clone();
+        tampered.txdata.swap(0, 1);
+        let mut server = Server::default();
+        server.routes.insert(
+            format!("/tx/{}/hex", payment.txid()),
+            (200, to_hex(&serialize(coinbase)).into_bytes()),
+        );
+        server
+            .routes
+            .insert(format!("/block/{}/raw", hash), (200, serialize(&tampered)));
+        let esplora = Arc::new(EsploraProvider::new("http://esplora", Arc::new(server)));
+        assert_eq!(
+            esplora
+                .transaction(&payment.txid())
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::NetworkFailure
+        );
+        assert_eq!(
+            esplora.block(&hash).await.unwrap_err().code(),
+            ErrorCode::NetworkFailure
+        );
+
+        // Core answers every call with the same raw hex
+        let core = |hex: String| {
+            let mut server = Server::default();
+            let body = json!({ "result": hex, "error": null }).to_string();
+            server
+                .routes
+                .insert("http://core".into(), (200, body.into_bytes()));
+            CoreRpcProvider::new("http://core", Auth::None, Arc::new(server))
+        };
+        let err = core(to_hex(&serialize(coinbase)))
+            .transaction(&payment.txid())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NetworkFailure);
+        let err = core(to_hex(&serialize(&tampered)))
+            .block(&hash)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NetworkFailure);
+
+        // Failover moves to the index and caches only what it verified
+        let failover = FailoverProvider::new(vec![esplora, Arc::new(index)], Duration::ZERO);
+        assert_eq!(
+            failover
+                .transaction(&payment.txid())
+                .await
+                .unwrap()
+                .as_ref(),
+            Some(payment)
+        );
+        assert_eq!(failover.block(&hash).await.unwrap(), Some(block));
+    }
+}
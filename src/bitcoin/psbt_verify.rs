@@ -0,0 +1,322 @@
+//! Verifying a PSBT's claims about the signer's own key
+//!
+//! Whoever builds a PSBT can label any output as change. Signers use a
+//! [`WalletKey`] to re-derive every key origin naming their master key and
+//! check it against the script it is attached to, so a payment cannot be
+//! passed off as change. A [`SignedLedger`] records each signed input, and
+//! a second transaction spending one of them is refused, so an earlier
+//! approval cannot be replayed with altered outputs.
+//!
+//! PSBT amounts are untrusted: [`sum_sat`] rejects totals that overflow or
+//! exceed the 21M BTC supply instead of wrapping or saturating.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use ::bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint, KeySource};
+use ::bitcoin::psbt::{self, Psbt};
+use ::bitcoin::secp256k1::{self, All, Secp256k1, XOnlyPublicKey};
+use ::bitcoin::taproot::TapLeafHash;
+use ::bitcoin::{Address, Amount, Network, OutPoint, PublicKey, ScriptBuf, TxOut, Txid};
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Taproot key origins of a PSBT input or output
+pub type TapKeyOrigins = BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>;
+
+/// Master key that checks and signs PSBTs
+pub struct WalletKey {
+    xpriv: ExtendedPrivKey,
+    fingerprint: Fingerprint,
+    secp: Secp256k1<All>,
+}
+
+impl WalletKey {
+    /// Wrap `xpriv`
+    pub fn new(xpriv: ExtendedPrivKey) -> Self {
+        let secp = Secp256k1::new();
+        Self {
+            fingerprint: xpriv.fingerprint(&secp),
+            xpriv,
+            secp,
+        }
+    }
+
+    /// Master key fingerprint
+    pub const fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    /// Network the key signs for
+    pub const fn network(&self) -> Network {
+        self.xpriv.network
+    }
+
+    /// Public key at `path`
+    pub fn derive(&self, path: &DerivationPath) -> AnyaResult<secp256k1::PublicKey> {
+        let child = self.xpriv.derive_priv(&self.secp, path)?;
+        Ok(secp256k1::PublicKey::from_secret_key(
+            &self.secp,
+            &child.private_key,
+        ))
+    }
+
+    /// Derivation path proving `script` belongs to this key.
+    ///
+    /// Returns `None` when no key origin references our fingerprint and an
+    /// error when one does but does not re-derive to the same key and script.
+    pub fn verified_path(
+        &self,
+        script: &ScriptBuf,
+        ecdsa: &BTreeMap<secp256k1::PublicKey, KeySource>,
+        taproot: &TapKeyOrigins,
+    ) -> AnyaResult<Option<DerivationPath>> {
+        let network = self.network();
+        for (key, (fingerprint, path)) in ecdsa {
+            if *fingerprint != self.fingerprint {
+                continue;
+            }
+            let pubkey = PublicKey::new(*key);
+            let matches = self.derive(path)? == *key
+                && [
+                    Address::p2wpkh(&pubkey, network)?,
+                    Address::p2shwpkh(&pubkey, network)?,
+                    Address::p2pkh(&pubkey, network),
+                ]
+                .iter()
+                .any(|a| a.script_pubkey() == *script);
+            return self.checked(matches, path);
+        }
+        for (key, (_, (fingerprint, path))) in taproot {
+            if *fingerprint != self.fingerprint {
+                continue;
+            }
+            let matches = self.derive(path)?.x_only_public_key().0 == *key
+                && Address::p2tr(&self.secp, *key, None, network).script_pubkey() == *script;
+            return self.checked(matches, path);
+        }
+        Ok(None)
+    }
+
+    /// Sign every input of `psbt` this key owns
+    pub fn sign(&self, psbt: &mut Psbt) -> AnyaResult<()> {
+        psbt.sign(&self.xpriv, &self.secp).map_err(|(_, errors)| {
+            AnyaError::new(
+                ErrorCode::BitcoinFailure,
+                format!("signing failed for inputs {:?}", errors.keys()),
+            )
+        })?;
+        Ok(())
+    }
+
+    fn checked(&self, matches: bool, path: &DerivationPath) -> AnyaResult<Option<DerivationPath>> {
+        if matches {
+            Ok(Some(path.clone()))
+        } else {
+            Err(AnyaError::invalid_input(format!(
+                "key origin {}/{} does not match its script; PSBT may be tampered",
+                self.fingerprint, path
+            )))
+        }
+    }
+}
+
+/// Inputs already signed, by the transaction they were signed in
+pub struct SignedLedger {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+}
+
+impl SignedLedger {
+    /// Open the ledger kept in `namespace` of `storage`
+    pub async fn open(storage: Arc<dyn StorageBackend>, namespace: &str) -> AnyaResult<Self> {
+        let ns = Namespace::new(namespace)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self { storage, ns })
+    }
+
+    /// Fail with `Conflict` if any of `inputs` was signed in another
+    /// transaction than `txid`
+    pub async fn check(&self, inputs: &[OutPoint], txid: &Txid) -> AnyaResult<()> {
+        let txid = txid.to_string();
+        for outpoint in inputs {
+            if let Some(previous) = self.storage.get(&self.ns, &ledger_key(outpoint)).await? {
+                let previous = String::from_utf8_lossy(&previous);
+                if previous != txid {
+                    return Err(AnyaError::new(
+                        ErrorCode::Conflict,
+                        format!(
+                            "input {} was already signed in transaction {}",
+                            outpoint, previous
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record `inputs` as signed in `txid`
+    pub async fn record(&self, inputs: &[OutPoint], txid: &Txid) -> AnyaResult<()> {
+        let txid = txid.to_string();
+        for outpoint in inputs {
+            self.storage
+                .put(&self.ns, &ledger_key(outpoint), txid.as_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Output spent by PSBT input `index`, checked against its outpoint
+pub fn spent_output(index: usize, outpoint: OutPoint, input: &psbt::Input) -> AnyaResult<TxOut> {
+    if let Some(prev) = &input.non_witness_utxo {
+        if prev.txid() != outpoint.txid {
+            return Err(AnyaError::invalid_input(format!(
+                "input {} previous transaction does not match its outpoint",
+                index
+            )));
+        }
+        return prev
+            .output
+            .get(outpoint.vout as usize)
+            .cloned()
+            .ok_or_else(|| AnyaError::invalid_input(format!("input {} vout out of range", index)));
+    }
+    input
+        .witness_utxo
+        .clone()
+        .ok_or_else(|| AnyaError::invalid_input(format!("input {} is missing its UTXO", index)))
+}
+
+/// BIP-44 style paths put change on the internal chain, `.../1/<index>`
+pub fn is_internal_chain(path: &DerivationPath) -> bool {
+    let children: &[ChildNumber] = path.as_ref();
+    children.len() >= 2 && children[children.len() - 2] == ChildNumber::Normal { index: 1 }
+}
+
+/// Total of PSBT amounts, rejected as `InvalidInput` above 21M BTC
+pub fn sum_sat(amounts: impl IntoIterator<Item = u64>) -> AnyaResult<u64> {
+    amounts.into_iter().try_fold(0u64, |total, amount| {
+        total
+            .checked_add(amount)
+            .filter(|total| *total <= Amount::MAX_MONEY.to_sat())
+            .ok_or_else(|| AnyaError::invalid_input("PSBT amounts exceed 21M BTC"))
+    })
+}
+
+fn ledger_key(outpoint: &OutPoint) -> String {
+    format!("signed/{}", outpoint)
+}
+
+/// PSBTs spending from a [`WalletKey`], shared by the signer tests
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::str::FromStr;
+
+    use ::bitcoin::absolute::LockTime;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{Sequence, Transaction, TxIn, Witness};
+
+    use super::*;
+
+    /// Key and origin at `path`
+    fn key_at(key: &WalletKey, path: &str) -> (secp256k1::PublicKey, KeySource) {
+        let path = DerivationPath::from_str(path).unwrap();
+        (key.derive(&path).unwrap(), (key.fingerprint(), path))
+    }
+
+    fn p2wpkh(key: secp256k1::PublicKey, network: Network) -> ScriptBuf {
+        Address::p2wpkh(&PublicKey::new(key), network)
+            .unwrap()
+            .script_pubkey()
+    }
+
+    /// Spend of a 100k sat input at `m/84'/1'/0'/0/0`, paying
+    /// `external_value` to a foreign key and the rest less a 1k sat fee to
+    /// change at `m/84'/1'/0'/1/0`
+    pub fn build_psbt(key: &WalletKey, external_value: u64) -> Psbt {
+        let network = key.network();
+        let (input_key, input_origin) = key_at(key, "m/84'/1'/0'/0/0");
+        let (change_key, change_origin) = key_at(key, "m/84'/1'/0'/1/0");
+        let external = p2wpkh(
+            secp256k1::PublicKey::from_secret_key(
+                &key.secp,
+                &secp256k1::SecretKey::from_slice(&[9u8; 32]).unwrap(),
+            ),
+            network,
+        );
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1u8; 32]), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value: external_value,
+                    script_pubkey: external,
+                },
+                TxOut {
+                    value: 99_000 - external_value,
+                    script_pubkey: p2wpkh(change_key, network),
+                },
+            ],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: p2wpkh(input_key, network),
+        });
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(input_key, input_origin);
+        psbt.outputs[1]
+            .bip32_derivation
+            .insert(change_key, change_origin);
+        psbt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::build_psbt;
+    use super::*;
+
+    #[test]
+    fn test_rejects_fake_change_and_oversized_totals() {
+        let key =
+            WalletKey::new(ExtendedPrivKey::new_master(Network::Regtest, &[5u8; 32]).unwrap());
+        let psbt = build_psbt(&key, 60_000);
+        let change = &psbt.outputs[1];
+        let path = key
+            .verified_path(
+                &psbt.unsigned_tx.output[1].script_pubkey,
+                &change.bip32_derivation,
+                &change.tap_key_origins,
+            )
+            .unwrap()
+            .unwrap();
+        assert!(is_internal_chain(&path));
+        // The change origin attached to the external script
+        assert!(key
+            .verified_path(
+                &psbt.unsigned_tx.output[0].script_pubkey,
+                &change.bip32_derivation,
+                &change.tap_key_origins,
+            )
+            .is_err());
+
+        assert_eq!(sum_sat([60_000, 39_000]).unwrap(), 99_000);
+        let max = Amount::MAX_MONEY.to_sat();
+        assert_eq!(
+            sum_sat([max, 1]).unwrap_err().code(),
+            ErrorCode::InvalidInput
+        );
+        assert!(sum_sat([u64::MAX, 2]).is_err());
+    }
+}
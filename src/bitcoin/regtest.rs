@@ -0,0 +1,556 @@
+//! Simulated regtest/signet chain for integration tests
+//!
+//! [`RegtestChain`] keeps a whole chain in process: transactions are accepted
+//! into a mempool, mined on demand with [`RegtestChain::generate`], and block
+//! timestamps follow a clock that tests move forward explicitly. Timelock
+//! dependent flows such as Lightning force-closes or DLC refunds therefore run
+//! deterministically without a `bitcoind`. The chain implements
+//! [`ChainSource`], so it drives the [`TxTracker`](super::tracker::TxTracker)
+//! directly.
+//!
+//! Inputs are checked for existence, double spends, value, and absolute
+//! timelocks; scripts and signatures are not verified.
+//!
+//! Compiled for the crate's own tests and with the `test-harness` feature.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use ::bitcoin::absolute::{Height, LockTime, Time};
+use ::bitcoin::hashes::Hash;
+use ::bitcoin::{
+    Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use async_trait::async_trait;
+
+use super::accounts::{AccountId, AccountManager, KeyChain};
+use super::coins::{CoinStore, Utxo};
+use super::tracker::{ChainSource, TxStatus};
+use super::BitcoinConfig;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Timestamp of the regtest genesis block
+const GENESIS_TIME: u32 = 1_296_688_602;
+/// Clock advance per generated block
+const BLOCK_INTERVAL_SECS: u32 = 600;
+/// Blocks in the median-time-past window
+const MEDIAN_TIME_SPAN: usize = 11;
+
+struct Block {
+    time: u32,
+    txids: Vec<Txid>,
+}
+
+struct State {
+    blocks: Vec<Block>,
+    txs: HashMap<Txid, Transaction>,
+    heights: HashMap<Txid, u32>,
+    mempool: Vec<Txid>,
+    spenders: HashMap<OutPoint, Txid>,
+    clock: u32,
+    faucet_nonce: u32,
+}
+
+impl State {
+    fn tip(&self) -> u32 {
+        u32::try_from(self.blocks.len() - 1).unwrap_or(u32::MAX)
+    }
+
+    fn median_time_past(&self) -> u32 {
+        let mut times: Vec<u32> = self
+            .blocks
+            .iter()
+            .rev()
+            .take(MEDIAN_TIME_SPAN)
+            .map(|b| b.time)
+            .collect();
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    fn status(&self, txid: &Txid) -> TxStatus {
+        match self.heights.get(txid) {
+            Some(height) => TxStatus::Confirmed { height: *height },
+            None if self.mempool.contains(txid) => TxStatus::Mempool,
+            None => TxStatus::Unknown,
+        }
+    }
+
+    fn output(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        if self.status(&outpoint.txid) == TxStatus::Unknown {
+            return None;
+        }
+        self.txs
+            .get(&outpoint.txid)?
+            .output
+            .get(outpoint.vout as usize)
+    }
+
+    fn is_final(&self, tx: &Transaction) -> bool {
+        // Final when the lock time is below the next block's height or the
+        // tip's median time past (BIP-113)
+        match (
+            Height::from_consensus(self.tip()),
+            Time::from_consensus(self.median_time_past().saturating_sub(1)),
+        ) {
+            (Ok(height), Ok(time)) => tx.is_absolute_timelock_satisfied(height, time),
+            _ => false,
+        }
+    }
+
+    fn accept(&mut self, tx: &Transaction) -> AnyaResult<Txid> {
+        let txid = tx.txid();
+        if self.status(&txid) != TxStatus::Unknown {
+            return Ok(txid);
+        }
+        let mut input_sat = 0u64;
+        let mut conflicts = Vec::new();
+        for input in &tx.input {
+            let prevout = input.previous_output;
+            let Some(spent) = self.output(&prevout) else {
+                return Err(rejected(format!("input {} does not exist", prevout)));
+            };
+            input_sat += spent.value;
+            if let Some(spender) = self.spenders.get(&prevout) {
+                if self.heights.contains_key(spender) {
+                    return Err(rejected(format!("input {} already spent", prevout)));
+                }
+                if !self.txs[spender].is_explicitly_rbf() {
+                    return Err(rejected(format!(
+                        "input {} spent by non-replaceable {}",
+                        prevout, spender
+                    )));
+                }
+                conflicts.push(*spender);
+            }
+        }
+        let output_sat: u64 = tx.output.iter().map(|o| o.value).sum();
+        if output_sat > input_sat {
+            return Err(rejected(format!(
+                "outputs of {} sat exceed inputs of {} sat",
+                output_sat, input_sat
+            )));
+        }
+        if !self.is_final(tx) {
+            return Err(rejected(format!("{} is not final", txid)));
+        }
+        for conflict in conflicts {
+            self.evict(&conflict);
+        }
+        self.insert(tx.clone());
+        Ok(txid)
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        let txid = tx.txid();
+        for input in &tx.input {
+            self.spenders.insert(input.previous_output, txid);
+        }
+        self.txs.insert(txid, tx);
+        self.mempool.push(txid);
+    }
+
+    fn evict(&mut self, txid: &Txid) -> bool {
+        let Some(position) = self.mempool.iter().position(|t| t == txid) else {
+            return false;
+        };
+        self.mempool.remove(position);
+        self.spenders.retain(|_, spender| spender != txid);
+        let children: Vec<Txid> = self
+            .spenders
+            .iter()
+            .filter(|(outpoint, _)| outpoint.txid == *txid)
+            .map(|(_, spender)| *spender)
+            .collect();
+        for child in children {
+            self.evict(&child);
+        }
+        true
+    }
+
+    fn mine(&mut self) {
+        let txids = std::mem::take(&mut self.mempool);
+        let height = self.tip() + 1;
+        for txid in &txids {
+            self.heights.insert(*txid, height);
+        }
+        self.blocks.push(Block {
+            time: self.clock,
+            txids,
+        });
+        self.clock = self.clock.saturating_add(BLOCK_INTERVAL_SECS);
+    }
+
+    fn disconnect(&mut self, depth: u32) -> AnyaResult<()> {
+        if depth > self.tip() {
+            return Err(AnyaError::invalid_input(format!(
+                "cannot disconnect {} blocks from height {}",
+                depth,
+                self.tip()
+            )));
+        }
+        for _ in 0..depth {
+            let Some(block) = self.blocks.pop() else {
+                break;
+            };
+            for txid in &block.txids {
+                self.heights.remove(txid);
+            }
+            let mut mempool = block.txids;
+            mempool.append(&mut self.mempool);
+            self.mempool = mempool;
+        }
+        Ok(())
+    }
+
+    fn fund(&mut self, script_pubkey: ScriptBuf, amount_sat: u64) -> OutPoint {
+        self.faucet_nonce += 1;
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), self.faucet_nonce),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: amount_sat,
+                script_pubkey,
+            }],
+        };
+        let outpoint = OutPoint::new(tx.txid(), 0);
+        self.insert(tx);
+        self.mine();
+        outpoint
+    }
+}
+
+fn rejected(message: String) -> AnyaError {
+    AnyaError::new(ErrorCode::TransactionRejected, message)
+}
+
+/// In-process chain with manual block generation and clock control
+pub struct RegtestChain {
+    network: Network,
+    state: Mutex<State>,
+}
+
+impl RegtestChain {
+    /// A chain holding only its genesis block. Only regtest and signet can
+    /// be simulated.
+    pub fn new(network: Network) -> AnyaResult<Self> {
+        if !matches!(network, Network::Regtest | Network::Signet) {
+            return Err(AnyaError::invalid_input(format!(
+                "cannot simulate network {}",
+                network
+            )));
+        }
+        let genesis = Block {
+            time: GENESIS_TIME,
+            txids: Vec::new(),
+        };
+        Ok(Self {
+            network,
+            state: Mutex::new(State {
+                blocks: vec![genesis],
+                txs: HashMap::new(),
+                heights: HashMap::new(),
+                mempool: Vec::new(),
+                spenders: HashMap::new(),
+                clock: GENESIS_TIME + BLOCK_INTERVAL_SECS,
+                faucet_nonce: 0,
+            }),
+        })
+    }
+
+    /// A chain for the network of `config`
+    pub fn from_config(config: &BitcoinConfig) -> AnyaResult<Self> {
+        config.validate()?;
+        Self::new(config.network)
+    }
+
+    /// Simulated network
+    pub const fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Height of the best block
+    pub fn height(&self) -> u32 {
+        self.state().tip()
+    }
+
+    /// Timestamp the next block will carry
+    pub fn time(&self) -> u32 {
+        self.state().clock
+    }
+
+    /// Median timestamp of the last eleven blocks, the reference for
+    /// time-based lock times
+    pub fn median_time_past(&self) -> u32 {
+        self.state().median_time_past()
+    }
+
+    /// Move the clock forward without mining
+    pub fn advance_time(&self, by: Duration) {
+        let secs = u32::try_from(by.as_secs()).unwrap_or(u32::MAX);
+        let mut state = self.state();
+        state.clock = state.clock.saturating_add(secs);
+    }
+
+    /// Mine `blocks` blocks, the first including the whole mempool, and
+    /// return the new tip height
+    pub fn generate(&self, blocks: u32) -> u32 {
+        let mut state = self.state();
+        for _ in 0..blocks {
+            state.mine();
+        }
+        state.tip()
+    }
+
+    /// Mine blocks until `time` is reached, returning the new tip height
+    pub fn generate_until(&self, time: u32) -> u32 {
+        let mut state = self.state();
+        while state.median_time_past() < time {
+            state.mine();
+        }
+        state.tip()
+    }
+
+    /// Submit a transaction to the mempool, replacing conflicting
+    /// transactions that signal replaceability
+    pub fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid> {
+        self.state().accept(tx)
+    }
+
+    /// Drop a transaction and its descendants from the mempool, returning
+    /// whether it was there
+    pub fn evict(&self, txid: &Txid) -> bool {
+        self.state().evict(txid)
+    }
+
+    /// Transactions waiting in the mempool, parents first
+    pub fn mempool(&self) -> Vec<Txid> {
+        self.state().mempool.clone()
+    }
+
+    /// Disconnect the top `depth` blocks, returning their transactions to
+    /// the mempool. Mine afterwards to build the competing branch.
+    pub fn reorg(&self, depth: u32) -> AnyaResult<()> {
+        self.state().disconnect(depth)
+    }
+
+    /// Pay `amount_sat` to `script_pubkey` from an unlimited faucet and mine
+    /// it, along with the rest of the mempool, in a new block
+    pub fn fund(&self, script_pubkey: &Script, amount_sat: u64) -> OutPoint {
+        self.state().fund(script_pubkey.to_owned(), amount_sat)
+    }
+
+    /// Fund the next receive address of an account and record the confirmed
+    /// output in `coins`
+    pub async fn fund_account(
+        &self,
+        accounts: &AccountManager,
+        coins: &CoinStore,
+        account: AccountId,
+        amount_sat: u64,
+    ) -> AnyaResult<Utxo> {
+        let (index, address) = accounts.next_address(account, KeyChain::External).await?;
+        let outpoint = self.fund(&address.script_pubkey(), amount_sat);
+        let utxo = Utxo {
+            outpoint,
+            txout: TxOut {
+                value: amount_sat,
+                script_pubkey: address.script_pubkey(),
+            },
+            account,
+            chain: KeyChain::External,
+            index,
+            height: Some(self.height()),
+        };
+        accounts
+            .mark_used(account, KeyChain::External, index)
+            .await?;
+        coins.insert(&utxo).await?;
+        Ok(utxo)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl ChainSource for RegtestChain {
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        Ok(self.height())
+    }
+
+    async fn script_history(&self, script: &Script) -> AnyaResult<Vec<Txid>> {
+        let state = self.state();
+        Ok(state
+            .txs
+            .iter()
+            .filter(|(txid, tx)| {
+                state.status(txid) != TxStatus::Unknown
+                    && (tx
+                        .output
+                        .iter()
+                        .any(|o| o.script_pubkey.as_script() == script)
+                        || tx.input.iter().any(|i| {
+                            state
+                                .output(&i.previous_output)
+                                .is_some_and(|o| o.script_pubkey.as_script() == script)
+                        }))
+            })
+            .map(|(txid, _)| *txid)
+            .collect())
+    }
+
+    async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        Ok(self.state().txs.get(txid).cloned())
+    }
+
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        Ok(self.state().status(txid))
+    }
+
+    async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+        Ok(self.state().spenders.get(outpoint).copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::ScriptType;
+    use crate::bitcoin::tracker::{TrackerConfig, TxEvent, TxTracker};
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::StorageBackend;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::WPubkeyHash;
+    use std::sync::Arc;
+
+    fn spend(prevout: OutPoint, to: &ScriptBuf, value: u64, sequence: Sequence) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevout,
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: to.clone(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_mempool_policy_and_timelocks() {
+        assert!(RegtestChain::new(Network::Bitcoin).is_err());
+        let chain = RegtestChain::from_config(&BitcoinConfig::regtest()).unwrap();
+        let script = ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::from_byte_array([1; 20]));
+        let funding = chain.fund(&script, 50_000);
+        assert_eq!(chain.height(), 1);
+
+        let first = spend(funding, &script, 49_000, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        chain.broadcast(&first).unwrap();
+        let child = spend(
+            OutPoint::new(first.txid(), 0),
+            &script,
+            48_000,
+            Sequence::MAX,
+        );
+        chain.broadcast(&child).unwrap();
+        let greedy = spend(funding, &script, 60_000, Sequence::MAX);
+        assert_eq!(
+            chain.broadcast(&greedy).unwrap_err().code(),
+            ErrorCode::TransactionRejected
+        );
+
+        // Replacing the parent evicts its child too
+        let replacement = spend(funding, &script, 48_500, Sequence::MAX);
+        chain.broadcast(&replacement).unwrap();
+        assert_eq!(chain.mempool(), vec![replacement.txid()]);
+        let again = spend(funding, &script, 48_000, Sequence::MAX);
+        assert!(chain.broadcast(&again).is_err());
+
+        // A refund locked an hour ahead needs the median time to pass it
+        chain.generate(1);
+        let unlock = chain.time() + 3600;
+        let mut refund = spend(
+            OutPoint::new(replacement.txid(), 0),
+            &script,
+            48_000,
+            Sequence::ENABLE_LOCKTIME_NO_RBF,
+        );
+        refund.lock_time = LockTime::from_consensus(unlock);
+        assert!(chain.broadcast(&refund).is_err());
+        chain.advance_time(Duration::from_secs(3600));
+        chain.generate_until(unlock + 1);
+        chain.broadcast(&refund).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_funded_account_drives_tracker_through_reorg() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let accounts = AccountManager::open(Network::Regtest, Arc::clone(&storage))
+            .await
+            .unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[5; 32]).unwrap();
+        let account = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "Test")
+            .await
+            .unwrap();
+        let coins = CoinStore::open(Arc::clone(&storage)).await.unwrap();
+        let chain = Arc::new(RegtestChain::new(Network::Regtest).unwrap());
+        let config = TrackerConfig {
+            milestones: vec![1, 3],
+            ..TrackerConfig::default()
+        };
+        let tracker = TxTracker::open(config, Arc::clone(&chain) as _, storage)
+            .await
+            .unwrap();
+        tracker
+            .watch_account(&accounts.account(account.id).await.unwrap())
+            .await
+            .unwrap();
+
+        let utxo = chain
+            .fund_account(&accounts, &coins, account.id, 100_000)
+            .await
+            .unwrap();
+        assert_eq!(coins.spendable(account.id).await.unwrap().len(), 1);
+        assert!(accounts.account(account.id).await.unwrap().is_used());
+        assert!(matches!(
+            &tracker.poll().await.unwrap()[..],
+            [
+                TxEvent::FirstSeen {
+                    confirmations: 1,
+                    ..
+                },
+                ..
+            ]
+        ));
+
+        chain.reorg(1).unwrap();
+        assert_eq!(chain.mempool(), vec![utxo.outpoint.txid]);
+        assert!(matches!(
+            &tracker.poll().await.unwrap()[..],
+            [TxEvent::Reorged { .. }]
+        ));
+        chain.generate(3);
+        let events = tracker.poll().await.unwrap();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            TxEvent::Confirmed {
+                confirmations: 3,
+                ..
+            }
+        )));
+    }
+}
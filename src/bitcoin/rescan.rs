@@ -0,0 +1,440 @@
+//! Chain reindex and targeted rescans
+//!
+//! Two admin operations walk the chain block by block:
+//!
+//! - [`ChainIndex::reindex`] rebuilds the internal indexes from the stored
+//!   blocks.
+//! - [`rescan`] looks for the transactions of a set of addresses and
+//!   descriptors from a given height, as needed after importing an old
+//!   wallet. Blocks come from any [`ChainDataProvider`]. Descriptor ranges
+//!   grow past each match by a gap limit, like wallet address discovery.
+//!
+//! Both report progress on a [`watch`] channel and stop at the next block
+//! when cancelled. [`ScanJob`] runs either in the background and gives an
+//! admin interface a handle to poll, cancel, and await.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::bitcoin::address::NetworkUnchecked;
+use ::bitcoin::secp256k1::Secp256k1;
+use ::bitcoin::{Address, Network, OutPoint, ScriptBuf, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use super::descriptor::WatchOnlyDescriptor;
+use super::index::ChainIndex;
+#[cfg(not(target_arch = "wasm32"))]
+use super::provider::ChainDataProvider;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Default unused descriptor addresses watched past the last match
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// How far a scan has got
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanProgress {
+    /// Height of the last block processed
+    pub height: u32,
+    /// Blocks processed
+    pub done: u32,
+    /// Blocks to process
+    pub total: u32,
+    /// Matching transactions found so far
+    pub matches: u32,
+}
+
+/// Outcome of [`ChainIndex::reindex`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReindexReport {
+    /// Blocks in the rebuilt chain
+    pub blocks: u32,
+    /// Whether every block was reindexed; call again to resume if not
+    pub completed: bool,
+}
+
+/// What a rescan looks for and where
+#[derive(Debug, Clone)]
+pub struct RescanRequest {
+    /// First height to scan
+    pub from_height: u32,
+    /// Last height to scan, the tip when the scan starts if `None`
+    pub to_height: Option<u32>,
+    /// Scripts watched individually
+    pub scripts: Vec<ScriptBuf>,
+    /// Ranged descriptors watched
+    pub descriptors: Vec<WatchOnlyDescriptor>,
+    /// Unused descriptor addresses watched past the last match
+    pub gap_limit: u32,
+}
+
+impl RescanRequest {
+    /// Scan from `from_height` to the tip
+    pub const fn from_height(from_height: u32) -> Self {
+        Self {
+            from_height,
+            to_height: None,
+            scripts: Vec::new(),
+            descriptors: Vec::new(),
+            gap_limit: DEFAULT_GAP_LIMIT,
+        }
+    }
+
+    /// Watch `addresses`, which must be for `network`
+    pub fn with_addresses<S: AsRef<str>>(
+        mut self,
+        addresses: &[S],
+        network: Network,
+    ) -> AnyaResult<Self> {
+        for address in addresses {
+            let address = Address::<NetworkUnchecked>::from_str(address.as_ref())?
+                .require_network(network)?;
+            self.scripts.push(address.script_pubkey());
+        }
+        Ok(self)
+    }
+
+    /// Watch the addresses of `descriptor`
+    #[must_use]
+    pub fn with_descriptor(mut self, descriptor: WatchOnlyDescriptor) -> Self {
+        self.descriptors.push(descriptor);
+        self
+    }
+}
+
+/// A watched output found by a rescan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoundOutput {
+    /// Output reference
+    pub outpoint: OutPoint,
+    /// Value and script
+    pub txout: TxOut,
+    /// Confirmation height
+    pub height: u32,
+    /// Descriptor position in the request and address index, if derived
+    pub derivation: Option<(usize, u32)>,
+}
+
+/// A transaction touching watched scripts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RescanMatch {
+    /// Transaction id
+    pub txid: Txid,
+    /// Confirmation height
+    pub height: u32,
+    /// Watched outputs it created
+    pub received: Vec<FoundOutput>,
+    /// Watched outputs it spent
+    pub spent: Vec<OutPoint>,
+}
+
+/// Outcome of a [`rescan`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RescanReport {
+    /// Matching transactions, in chain order
+    pub transactions: Vec<RescanMatch>,
+    /// Watched outputs still unspent at the last block scanned
+    pub unspent: Vec<FoundOutput>,
+    /// Height to continue from; past the last height when completed
+    pub next_height: u32,
+    /// Highest address index matched per descriptor
+    pub last_used: Vec<Option<u32>>,
+    /// Whether the whole range was scanned
+    pub completed: bool,
+}
+
+/// Watched scripts, growing descriptor ranges as they are used
+struct Watchlist<'a> {
+    request: &'a RescanRequest,
+    scripts: HashMap<ScriptBuf, Option<(usize, u32)>>,
+    derived: Vec<u32>,
+}
+
+impl<'a> Watchlist<'a> {
+    fn new(request: &'a RescanRequest) -> AnyaResult<Self> {
+        let mut watchlist = Self {
+            request,
+            scripts: request.scripts.iter().map(|s| (s.clone(), None)).collect(),
+            derived: vec![0; request.descriptors.len()],
+        };
+        for i in 0..request.descriptors.len() {
+            watchlist.derive_to(i, request.gap_limit)?;
+        }
+        Ok(watchlist)
+    }
+
+    /// Derive descriptor `i` up to, not including, address `end`
+    fn derive_to(&mut self, i: usize, end: u32) -> AnyaResult<()> {
+        let secp = Secp256k1::verification_only();
+        let descriptor = &self.request.descriptors[i];
+        for index in self.derived[i]..end {
+            let script = descriptor.address(&secp, index)?.script_pubkey();
+            self.scripts.insert(script, Some((i, index)));
+        }
+        self.derived[i] = self.derived[i].max(end);
+        Ok(())
+    }
+
+    fn matches(&mut self, script: &ScriptBuf) -> AnyaResult<Option<Option<(usize, u32)>>> {
+        let Some(derivation) = self.scripts.get(script).copied() else {
+            return Ok(None);
+        };
+        if let Some((i, index)) = derivation {
+            self.derive_to(i, index.saturating_add(1 + self.request.gap_limit))?;
+        }
+        Ok(Some(derivation))
+    }
+}
+
+/// Scan blocks from `provider` for the transactions of the scripts in
+/// `request`.
+///
+/// A cancelled scan returns what it found so far with `completed` unset;
+/// rescanning from `next_height` continues it, though outputs found before
+/// that height are then not tracked as spent.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn rescan(
+    provider: &dyn ChainDataProvider,
+    request: &RescanRequest,
+    token: &CancellationToken,
+    progress: &watch::Sender<ScanProgress>,
+) -> AnyaResult<RescanReport> {
+    let to_height = match request.to_height {
+        Some(height) => height,
+        None => provider.tip_height().await?,
+    };
+    if to_height < request.from_height {
+        return Err(AnyaError::invalid_input(format!(
+            "cannot rescan from {} to {}",
+            request.from_height, to_height
+        )));
+    }
+    let mut watchlist = Watchlist::new(request)?;
+    let mut unspent: HashMap<OutPoint, FoundOutput> = HashMap::new();
+    let mut report = RescanReport {
+        next_height: request.from_height,
+        last_used: vec![None; request.descriptors.len()],
+        ..RescanReport::default()
+    };
+    let total = to_height - request.from_height + 1;
+    for height in request.from_height..=to_height {
+        if token.is_cancelled() {
+            info!(height, "rescan interrupted");
+            break;
+        }
+        let hash = provider.block_hash(height).await?;
+        let block = match hash {
+            Some(hash) => provider.block(&hash).await?,
+            None => None,
+        }
+        .ok_or_else(|| {
+            AnyaError::new(
+                ErrorCode::Unavailable,
+                format!("block at height {} unavailable", height),
+            )
+        })?;
+        for tx in &block.txdata {
+            let txid = tx.txid();
+            let spent: Vec<OutPoint> = tx
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .filter(|prevout| unspent.remove(prevout).is_some())
+                .collect();
+            let mut received = Vec::new();
+            for (vout, txout) in tx.output.iter().enumerate() {
+                let Some(derivation) = watchlist.matches(&txout.script_pubkey)? else {
+                    continue;
+                };
+                if let Some((i, index)) = derivation {
+                    report.last_used[i] = report.last_used[i].max(Some(index));
+                }
+                let found = FoundOutput {
+                    outpoint: OutPoint::new(txid, u32::try_from(vout).unwrap_or(u32::MAX)),
+                    txout: txout.clone(),
+                    height,
+                    derivation,
+                };
+                unspent.insert(found.outpoint, found.clone());
+                received.push(found);
+            }
+            if !spent.is_empty() || !received.is_empty() {
+                report.transactions.push(RescanMatch {
+                    txid,
+                    height,
+                    received,
+                    spent,
+                });
+            }
+        }
+        report.next_height = height + 1;
+        progress.send_replace(ScanProgress {
+            height,
+            done: height - request.from_height + 1,
+            total,
+            matches: u32::try_from(report.transactions.len()).unwrap_or(u32::MAX),
+        });
+    }
+    report.completed = report.next_height > to_height;
+    report.unspent = unspent.into_values().collect();
+    report
+        .unspent
+        .sort_by_key(|found| (found.height, found.outpoint.txid, found.outpoint.vout));
+    info!(
+        from = request.from_height,
+        to = report.next_height.saturating_sub(1),
+        matches = report.transactions.len(),
+        "rescan finished"
+    );
+    Ok(report)
+}
+
+/// A reindex or rescan running in the background
+pub struct ScanJob<T> {
+    progress: watch::Receiver<ScanProgress>,
+    token: CancellationToken,
+    handle: JoinHandle<AnyaResult<T>>,
+}
+
+impl<T: Send + 'static> ScanJob<T> {
+    fn spawn<F, Fut>(run: F) -> Self
+    where
+        F: FnOnce(CancellationToken, watch::Sender<ScanProgress>) -> Fut,
+        Fut: Future<Output = AnyaResult<T>> + Send + 'static,
+    {
+        let (sender, progress) = watch::channel(ScanProgress::default());
+        let token = CancellationToken::new();
+        let handle = tokio::spawn(run(token.clone(), sender));
+        Self {
+            progress,
+            token,
+            handle,
+        }
+    }
+
+    /// Latest progress
+    pub fn progress(&self) -> ScanProgress {
+        *self.progress.borrow()
+    }
+
+    /// Receiver notified on every progress update
+    pub fn subscribe(&self) -> watch::Receiver<ScanProgress> {
+        self.progress.clone()
+    }
+
+    /// Ask the job to stop after the current block
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// Wait for the job's outcome
+    pub async fn wait(self) -> AnyaResult<T> {
+        self.handle
+            .await
+            .map_err(|e| AnyaError::new(ErrorCode::Internal, format!("scan task failed: {}", e)))?
+    }
+}
+
+impl ScanJob<ReindexReport> {
+    /// Rebuild `index` in the background
+    pub fn reindex(index: Arc<ChainIndex>) -> Self {
+        Self::spawn(|token, progress| async move { index.reindex(&token, &progress).await })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ScanJob<RescanReport> {
+    /// Run `request` against `provider` in the background
+    pub fn rescan(provider: Arc<dyn ChainDataProvider>, request: RescanRequest) -> Self {
+        Self::spawn(|token, progress| async move {
+            rescan(provider.as_ref(), &request, &token, &progress).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index::tests::{block, indexed_chain, tx};
+    use super::*;
+    use crate::bitcoin::accounts::ScriptType;
+    use crate::bitcoin::tracker::ChainSource;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::bip32::{ExtendedPrivKey, ExtendedPubKey};
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::BlockHash;
+
+    #[tokio::test]
+    async fn rescan_follows_descriptor_past_the_gap_limit() {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[3; 32]).unwrap();
+        let descriptor = WatchOnlyDescriptor {
+            script_type: ScriptType::NativeSegwit,
+            origin: None,
+            xpub: ExtendedPubKey::from_priv(&secp, &master),
+            steps: vec![],
+        };
+        let addr = |i| descriptor.address(&secp, i).unwrap().script_pubkey();
+        let other = ScriptBuf::from_bytes(vec![0x51]);
+        let index = Arc::new(
+            ChainIndex::open(Arc::new(MemoryBackend::new()))
+                .await
+                .unwrap(),
+        );
+        // Coins hop from address 0 to 15 to 30, each beyond the gap limit
+        // of the previous range
+        let b0 = block(BlockHash::all_zeros(), 0, &addr(0), vec![]);
+        let pay15 = tx(
+            &[OutPoint::new(b0.txdata[0].txid(), 0)],
+            &[(&addr(15), 49_000)],
+        );
+        let b1 = block(b0.block_hash(), 1, &other, vec![pay15.clone()]);
+        let pay30 = tx(&[OutPoint::new(pay15.txid(), 0)], &[(&addr(30), 48_000)]);
+        let b2 = block(b1.block_hash(), 2, &other, vec![pay30.clone()]);
+        for b in [&b0, &b1, &b2] {
+            index.connect_block(b).await.unwrap();
+        }
+
+        let request = RescanRequest::from_height(0).with_descriptor(descriptor.clone());
+        let job = ScanJob::rescan(index.clone(), request.clone());
+        let report = job.wait().await.unwrap();
+        assert!(report.completed);
+        assert_eq!(report.transactions.len(), 3);
+        assert_eq!(report.last_used, [Some(30)]);
+        assert_eq!(report.unspent.len(), 1);
+        assert_eq!(report.unspent[0].outpoint, OutPoint::new(pay30.txid(), 0));
+
+        // A cancelled scan reports where to resume
+        let (sender, progress) = watch::channel(ScanProgress::default());
+        let token = CancellationToken::new();
+        token.cancel();
+        let report = rescan(index.as_ref(), &request, &token, &sender)
+            .await
+            .unwrap();
+        assert!(!report.completed);
+        assert_eq!((report.next_height, progress.borrow().done), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn reindex_rebuilds_the_indexes() {
+        let (index, alice, bob) = indexed_chain().await;
+        let index = Arc::new(index);
+        let before = index.script_history(&alice).await.unwrap();
+        let job = ScanJob::reindex(index.clone());
+        let report = job.wait().await.unwrap();
+        assert_eq!(
+            report,
+            ReindexReport {
+                blocks: 2,
+                completed: true
+            }
+        );
+        assert_eq!(index.script_history(&alice).await.unwrap(), before);
+        assert_eq!(index.script_history(&bob).await.unwrap().len(), 1);
+        assert_eq!(index.tip_height().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,722 @@
+//! Proof of reserves
+//!
+//! A [`ReserveProof`] shows control of a set of wallet coins at a point in
+//! time. It is a BIP-322 "proof of funds": the [`ReserveClaim`] is the
+//! signed message, the virtual `to_sign` transaction spends the BIP-322
+//! `to_spend` output together with every coin being claimed, and each input
+//! carries a real signature. The transaction can never be mined, because its
+//! first input spends an output of a transaction that does not exist, so
+//! producing the proof never puts the coins at risk.
+//!
+//! A claim may commit to customer liabilities through the root of a Merkle
+//! sum tree ([`LiabilityTree`]). Auditors compare the committed total with
+//! the proven reserves; each customer checks that their balance is included
+//! with an [`InclusionProof`] without learning anyone else's.
+//!
+//! Native segwit and taproot key-path coins are supported; legacy P2PKH
+//! coins are rejected.
+
+use std::collections::HashSet;
+
+use ::bitcoin::absolute::LockTime;
+use ::bitcoin::bip32::ExtendedPrivKey;
+use ::bitcoin::blockdata::opcodes::all::{OP_PUSHBYTES_0, OP_RETURN};
+use ::bitcoin::blockdata::script::Builder;
+use ::bitcoin::hashes::{sha256, Hash, HashEngine};
+use ::bitcoin::key::TapTweak;
+use ::bitcoin::secp256k1::{KeyPair, Message, Secp256k1, XOnlyPublicKey};
+use ::bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use ::bitcoin::{
+    BlockHash, Network, OutPoint, PublicKey, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness,
+};
+use serde::{Deserialize, Serialize};
+
+use super::accounts::{AccountManager, ScriptType};
+use super::coins::Utxo;
+use super::tracker::{ChainSource, TxStatus};
+use crate::utils::encoding::to_hex;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+const LEAF_TAG: &[u8] = b"anya/reserves/leaf";
+const NODE_TAG: &[u8] = b"anya/reserves/node";
+
+/// Commitment to customer liabilities: the root of a Merkle sum tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiabilityCommitment {
+    /// Root hash
+    pub root: sha256::Hash,
+    /// Sum of all liabilities in satoshis
+    pub total_sat: u64,
+}
+
+/// Statement signed by a reserve proof
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReserveClaim {
+    /// Network the coins are on
+    pub network: Network,
+    /// Block at which the reserves are claimed
+    pub block_hash: BlockHash,
+    /// Height of that block
+    pub block_height: u32,
+    /// Free-form statement, e.g. the auditor's challenge nonce
+    pub message: String,
+    /// Committed customer liabilities, if any
+    pub liabilities: Option<LiabilityCommitment>,
+}
+
+impl ReserveClaim {
+    /// Bytes signed under BIP-322
+    pub fn to_message(&self) -> AnyaResult<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+}
+
+/// Signed attestation over a set of coins
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReserveProof {
+    /// What is being attested
+    pub claim: ReserveClaim,
+    /// BIP-322 challenge script, owned by the prover
+    pub challenge: ScriptBuf,
+    /// Signed BIP-322 `to_sign` transaction; inputs after the first are the
+    /// claimed coins
+    pub tx: Transaction,
+    /// Outputs spent by the claimed coins, in input order
+    pub prevouts: Vec<TxOut>,
+}
+
+impl ReserveProof {
+    /// Coins claimed by the proof
+    pub fn outpoints(&self) -> Vec<OutPoint> {
+        self.tx
+            .input
+            .iter()
+            .skip(1)
+            .map(|i| i.previous_output)
+            .collect()
+    }
+}
+
+/// Result of verifying a [`ReserveProof`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifiedReserves {
+    /// Sum of the claimed coins in satoshis
+    pub reserves_sat: u64,
+    /// Number of coins
+    pub coins: usize,
+    /// Committed liabilities, if the claim carries them
+    pub liabilities_sat: Option<u64>,
+}
+
+impl VerifiedReserves {
+    /// Whether reserves cover the committed liabilities; `None` without a
+    /// liability commitment
+    pub fn is_solvent(&self) -> Option<bool> {
+        self.liabilities_sat.map(|l| self.reserves_sat >= l)
+    }
+}
+
+/// BIP-322 tagged hash of `message`
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+    tagged_hash(MESSAGE_TAG, &[message])
+}
+
+/// BIP-322 `to_spend` transaction committing `message` to `challenge`
+pub fn to_spend(challenge: &Script, message: &[u8]) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFF_FFFF),
+            script_sig: Builder::new()
+                .push_opcode(OP_PUSHBYTES_0)
+                .push_slice(message_hash(message).to_byte_array())
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: challenge.to_owned(),
+        }],
+    }
+}
+
+/// Unsigned BIP-322 `to_sign` transaction spending `to_spend` and `coins`
+fn to_sign(to_spend: Txid, coins: &[OutPoint]) -> Transaction {
+    let input = std::iter::once(OutPoint::new(to_spend, 0))
+        .chain(coins.iter().copied())
+        .map(|previous_output| TxIn {
+            previous_output,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        })
+        .collect();
+    Transaction {
+        version: 0,
+        lock_time: LockTime::ZERO,
+        input,
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+/// Sign a proof of control over `utxos` with keys derived from `master`.
+///
+/// The first coin's script doubles as the BIP-322 challenge.
+pub async fn prove(
+    master: &ExtendedPrivKey,
+    accounts: &AccountManager,
+    utxos: &[Utxo],
+    claim: ReserveClaim,
+) -> AnyaResult<ReserveProof> {
+    let first = utxos
+        .first()
+        .ok_or_else(|| AnyaError::invalid_input("no coins to prove"))?;
+    let mut seen = HashSet::new();
+    if let Some(dup) = utxos.iter().find(|u| !seen.insert(u.outpoint)) {
+        return Err(AnyaError::invalid_input(format!(
+            "coin {} listed twice",
+            dup.outpoint
+        )));
+    }
+    if master.network != claim.network
+        && (master.network == Network::Bitcoin || claim.network == Network::Bitcoin)
+    {
+        return Err(AnyaError::invalid_input(format!(
+            "key is for {} but the claim is for {}",
+            master.network, claim.network
+        )));
+    }
+
+    let secp = Secp256k1::new();
+    let challenge = first.txout.script_pubkey.clone();
+    let message = claim.to_message()?;
+    let to_spend = to_spend(&challenge, &message);
+    let mut tx = to_sign(
+        to_spend.txid(),
+        &utxos.iter().map(|u| u.outpoint).collect::<Vec<_>>(),
+    );
+
+    // The challenge input is signed by the first coin's key
+    let signers: Vec<&Utxo> = std::iter::once(first).chain(utxos).collect();
+    let prevouts: Vec<TxOut> = std::iter::once(to_spend.output[0].clone())
+        .chain(utxos.iter().map(|u| u.txout.clone()))
+        .collect();
+    let mut witnesses = Vec::with_capacity(signers.len());
+    let mut cache = SighashCache::new(&tx);
+    for (index, utxo) in signers.iter().enumerate() {
+        let account = accounts.account(utxo.account).await?;
+        let (_, path) = account.key_source(utxo.chain, utxo.index)?;
+        let key = master.derive_priv(&secp, &path)?.private_key;
+        let script = &prevouts[index].script_pubkey;
+        if account
+            .address(&secp, utxo.chain, utxo.index)?
+            .script_pubkey()
+            != *script
+        {
+            return Err(AnyaError::invalid_input(format!(
+                "coin {} does not pay to {}/{}",
+                utxo.outpoint, account.id, utxo.index
+            )));
+        }
+        let witness = match account.id.script_type {
+            ScriptType::NativeSegwit => {
+                let script_code = script
+                    .p2wpkh_script_code()
+                    .ok_or_else(|| AnyaError::invalid_input("not a P2WPKH script"))?;
+                let sighash = cache.segwit_signature_hash(
+                    index,
+                    &script_code,
+                    prevouts[index].value,
+                    EcdsaSighashType::All,
+                )?;
+                let signature = ::bitcoin::ecdsa::Signature::sighash_all(
+                    secp.sign_ecdsa(&Message::from_slice(&sighash[..])?, &key),
+                );
+                Witness::from_slice(&[
+                    signature.to_vec(),
+                    key.public_key(&secp).serialize().to_vec(),
+                ])
+            }
+            ScriptType::Taproot => {
+                let keypair = KeyPair::from_secret_key(&secp, &key)
+                    .tap_tweak(&secp, None)
+                    .to_inner();
+                let sighash = cache.taproot_key_spend_signature_hash(
+                    index,
+                    &Prevouts::All(&prevouts),
+                    TapSighashType::Default,
+                )?;
+                let signature = ::bitcoin::taproot::Signature {
+                    sig: secp
+                        .sign_schnorr_no_aux_rand(&Message::from_slice(&sighash[..])?, &keypair),
+                    hash_ty: TapSighashType::Default,
+                };
+                Witness::from_slice(&[signature.to_vec()])
+            }
+            ScriptType::Legacy => {
+                return Err(AnyaError::invalid_input(format!(
+                    "coin {} is legacy P2PKH, which reserve proofs do not support",
+                    utxo.outpoint
+                )))
+            }
+        };
+        witnesses.push(witness);
+    }
+    for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+        input.witness = witness;
+    }
+
+    tracing::info!(
+        coins = utxos.len(),
+        reserves_sat = utxos.iter().map(|u| u.txout.value).sum::<u64>(),
+        height = claim.block_height,
+        "signed proof of reserves"
+    );
+    Ok(ReserveProof {
+        claim,
+        challenge,
+        tx,
+        prevouts: prevouts.into_iter().skip(1).collect(),
+    })
+}
+
+/// Check the structure and every signature of `proof`.
+///
+/// This proves control of the coins but not that they are unspent; see
+/// [`check_unspent`].
+pub fn verify(proof: &ReserveProof) -> AnyaResult<VerifiedReserves> {
+    let invalid = |msg: String| AnyaError::new(ErrorCode::InvalidInput, msg);
+    let tx = &proof.tx;
+    let to_spend = to_spend(&proof.challenge, &proof.claim.to_message()?);
+    if tx.version != 0
+        || tx.lock_time != LockTime::ZERO
+        || tx.output.len() != 1
+        || tx.output[0].value != 0
+        || tx.output[0].script_pubkey.as_bytes() != [OP_RETURN.to_u8()]
+    {
+        return Err(invalid("not a BIP-322 to_sign transaction".into()));
+    }
+    if tx.input.first().map(|i| i.previous_output) != Some(OutPoint::new(to_spend.txid(), 0)) {
+        return Err(invalid("proof does not commit to its claim".into()));
+    }
+    if tx.input.len() < 2 || tx.input.len() != proof.prevouts.len() + 1 {
+        return Err(invalid(
+            "proof must list one prevout per claimed coin".into(),
+        ));
+    }
+    let mut seen = HashSet::new();
+    if let Some(dup) = tx.input.iter().find(|i| !seen.insert(i.previous_output)) {
+        return Err(invalid(format!(
+            "coin {} claimed twice",
+            dup.previous_output
+        )));
+    }
+
+    let secp = Secp256k1::verification_only();
+    let prevouts: Vec<TxOut> = std::iter::once(to_spend.output[0].clone())
+        .chain(proof.prevouts.iter().cloned())
+        .collect();
+    let mut cache = SighashCache::new(tx);
+    for (index, input) in tx.input.iter().enumerate() {
+        if !input.script_sig.is_empty() || input.sequence != Sequence::ZERO {
+            return Err(invalid(format!("input {} is malformed", index)));
+        }
+        verify_input(&secp, &mut cache, index, &input.witness, &prevouts)
+            .map_err(|e| invalid(format!("input {}: {}", index, e)))?;
+    }
+
+    Ok(VerifiedReserves {
+        reserves_sat: proof.prevouts.iter().map(|o| o.value).sum(),
+        coins: proof.prevouts.len(),
+        liabilities_sat: proof.claim.liabilities.map(|l| l.total_sat),
+    })
+}
+
+/// Coins of `proof` that are spent, unconfirmed at the claimed height, or
+/// do not match their stated prevout
+pub async fn check_unspent(
+    proof: &ReserveProof,
+    chain: &dyn ChainSource,
+) -> AnyaResult<Vec<OutPoint>> {
+    let mut bad = Vec::new();
+    for (outpoint, prevout) in proof.outpoints().into_iter().zip(&proof.prevouts) {
+        let matches = chain
+            .transaction(&outpoint.txid)
+            .await?
+            .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned())
+            .is_some_and(|out| out == *prevout);
+        let confirmed = matches!(
+            chain.status(&outpoint.txid).await?,
+            TxStatus::Confirmed { height } if height <= proof.claim.block_height
+        );
+        if !matches || !confirmed || chain.spender(&outpoint).await?.is_some() {
+            bad.push(outpoint);
+        }
+    }
+    Ok(bad)
+}
+
+fn verify_input(
+    secp: &Secp256k1<::bitcoin::secp256k1::VerifyOnly>,
+    cache: &mut SighashCache<&Transaction>,
+    index: usize,
+    witness: &Witness,
+    prevouts: &[TxOut],
+) -> AnyaResult<()> {
+    let prevout = &prevouts[index];
+    let script = &prevout.script_pubkey;
+    if script.is_v0_p2wpkh() {
+        let (Some(sig), Some(key), 2) = (witness.nth(0), witness.nth(1), witness.len()) else {
+            return Err(AnyaError::invalid_input("expected a signature and key"));
+        };
+        let key = PublicKey::from_slice(key)?;
+        if key
+            .wpubkey_hash()
+            .map(|h| ScriptBuf::new_v0_p2wpkh(&h))
+            .as_ref()
+            != Some(script)
+        {
+            return Err(AnyaError::invalid_input("key does not match the script"));
+        }
+        let sig = ::bitcoin::ecdsa::Signature::from_slice(sig)
+            .map_err(|e| AnyaError::with_source(ErrorCode::InvalidInput, "bad signature", e))?;
+        if sig.hash_ty != EcdsaSighashType::All {
+            return Err(AnyaError::invalid_input("signature must be SIGHASH_ALL"));
+        }
+        let script_code = script
+            .p2wpkh_script_code()
+            .ok_or_else(|| AnyaError::invalid_input("not a P2WPKH script"))?;
+        let sighash =
+            cache.segwit_signature_hash(index, &script_code, prevout.value, sig.hash_ty)?;
+        secp.verify_ecdsa(&Message::from_slice(&sighash[..])?, &sig.sig, &key.inner)
+            .map_err(|e| AnyaError::with_source(ErrorCode::InvalidInput, "invalid signature", e))
+    } else if script.is_v1_p2tr() {
+        let (Some(sig), 1) = (witness.nth(0), witness.len()) else {
+            return Err(AnyaError::invalid_input("expected a key-path signature"));
+        };
+        let key = XOnlyPublicKey::from_slice(&script.as_bytes()[2..])?;
+        let sig = ::bitcoin::taproot::Signature::from_slice(sig)
+            .map_err(|e| AnyaError::with_source(ErrorCode::InvalidInput, "bad signature", e))?;
+        if !matches!(sig.hash_ty, TapSighashType::Default | TapSighashType::All) {
+            return Err(AnyaError::invalid_input(
+                "signature must commit to all inputs",
+            ));
+        }
+        let sighash =
+            cache.taproot_key_spend_signature_hash(index, &Prevouts::All(prevouts), sig.hash_ty)?;
+        secp.verify_schnorr(&sig.sig, &Message::from_slice(&sighash[..])?, &key)
+            .map_err(|e| AnyaError::with_source(ErrorCode::InvalidInput, "invalid signature", e))
+    } else {
+        Err(AnyaError::invalid_input(format!(
+            "unsupported script {}",
+            to_hex(script.as_bytes())
+        )))
+    }
+}
+
+/// A customer balance committed in a [`LiabilityTree`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Liability {
+    /// Customer identifier known to the customer
+    pub customer: String,
+    /// Balance owed in satoshis
+    pub amount_sat: u64,
+    /// Per-customer random blinding, shared only with that customer
+    pub nonce: [u8; 32],
+}
+
+impl Liability {
+    fn leaf(&self) -> (sha256::Hash, u64) {
+        let hash = tagged_hash(
+            LEAF_TAG,
+            &[
+                &self.nonce,
+                self.customer.as_bytes(),
+                &self.amount_sat.to_le_bytes(),
+            ],
+        );
+        (hash, self.amount_sat)
+    }
+}
+
+/// Sibling along the path from a leaf to the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SumBranch {
+    /// Sibling hash
+    pub hash: sha256::Hash,
+    /// Sibling sum
+    pub sum_sat: u64,
+    /// Whether the sibling is on the left
+    pub left: bool,
+}
+
+/// Proof that a customer's balance is included in a liability commitment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// The customer's entry
+    pub liability: Liability,
+    /// Siblings from the leaf up
+    pub path: Vec<SumBranch>,
+}
+
+impl InclusionProof {
+    /// Whether the entry is included in `commitment`. Sums are checked at
+    /// every level, so a negative or overflowing sibling cannot offset it.
+    pub fn verify(&self, commitment: &LiabilityCommitment) -> bool {
+        let mut node = Some(self.liability.leaf());
+        for branch in &self.path {
+            node = node.and_then(|(hash, sum)| {
+                let sibling = (branch.hash, branch.sum_sat);
+                if branch.left {
+                    parent(sibling, (hash, sum))
+                } else {
+                    parent((hash, sum), sibling)
+                }
+            });
+        }
+        node == Some((commitment.root, commitment.total_sat))
+    }
+}
+
+/// Merkle sum tree over customer liabilities
+#[derive(Debug, Clone)]
+pub struct LiabilityTree {
+    liabilities: Vec<Liability>,
+    levels: Vec<Vec<(sha256::Hash, u64)>>,
+}
+
+impl LiabilityTree {
+    /// Build the tree; fails on duplicate customers or if the total overflows
+    pub fn new(liabilities: Vec<Liability>) -> AnyaResult<Self> {
+        if liabilities.is_empty() {
+            return Err(AnyaError::invalid_input("no liabilities"));
+        }
+        let mut seen = HashSet::new();
+        if let Some(dup) = liabilities.iter().find(|l| !seen.insert(&l.customer)) {
+            return Err(AnyaError::invalid_input(format!(
+                "customer {} listed twice",
+                dup.customer
+            )));
+        }
+        let mut levels = vec![liabilities.iter().map(Liability::leaf).collect::<Vec<_>>()];
+        while levels.last().is_some_and(|l| l.len() > 1) {
+            let below = levels.last().map(Vec::as_slice).unwrap_or_default();
+            let level = below
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => parent(*left, *right)
+                        .ok_or_else(|| AnyaError::invalid_input("liabilities overflow")),
+                    [single] => Ok(*single),
+                    _ => unreachable!("chunks(2) yields one or two nodes"),
+                })
+                .collect::<AnyaResult<Vec<_>>>()?;
+            levels.push(level);
+        }
+        Ok(Self {
+            liabilities,
+            levels,
+        })
+    }
+
+    /// Root commitment to publish in a [`ReserveClaim`]
+    pub fn commitment(&self) -> LiabilityCommitment {
+        let (root, total_sat) = self.levels[self.levels.len() - 1][0];
+        LiabilityCommitment { root, total_sat }
+    }
+
+    /// Inclusion proof for `customer`
+    pub fn proof(&self, customer: &str) -> Option<InclusionProof> {
+        let leaf = self
+            .liabilities
+            .iter()
+            .position(|l| l.customer == customer)?;
+        let mut index = leaf;
+        let mut path = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some((hash, sum_sat)) = level.get(sibling) {
+                path.push(SumBranch {
+                    hash: *hash,
+                    sum_sat: *sum_sat,
+                    left: sibling < index,
+                });
+            }
+            index /= 2;
+        }
+        Some(InclusionProof {
+            liability: self.liabilities[leaf].clone(),
+            path,
+        })
+    }
+}
+
+fn parent(left: (sha256::Hash, u64), right: (sha256::Hash, u64)) -> Option<(sha256::Hash, u64)> {
+    let sum = left.1.checked_add(right.1)?;
+    let hash = tagged_hash(
+        NODE_TAG,
+        &[
+            &left.0[..],
+            &left.1.to_le_bytes(),
+            &right.0[..],
+            &right.1.to_le_bytes(),
+        ],
+    );
+    Some((hash, sum))
+}
+
+fn tagged_hash(tag: &[u8], parts: &[&[u8]]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for part in parts {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::accounts::KeyChain;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::Address;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_bip322_vectors() {
+        assert_eq!(
+            message_hash(b"").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        let challenge = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+            .unwrap()
+            .assume_checked()
+            .script_pubkey();
+        assert_eq!(
+            to_spend(&challenge, b"").txid().to_string(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            to_spend(&challenge, b"Hello World").txid().to_string(),
+            "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prove_and_verify_reserves() {
+        let accounts = AccountManager::open(Network::Regtest, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[4; 32]).unwrap();
+        let secp = Secp256k1::verification_only();
+        let mut utxos = Vec::new();
+        for (n, script_type) in [ScriptType::NativeSegwit, ScriptType::Taproot]
+            .into_iter()
+            .enumerate()
+        {
+            let account = accounts
+                .create_account(&master, script_type, "Treasury")
+                .await
+                .unwrap();
+            utxos.push(Utxo {
+                outpoint: OutPoint::new(Txid::from_byte_array([n as u8 + 1; 32]), 0),
+                txout: TxOut {
+                    value: 300_000 * (n as u64 + 1),
+                    script_pubkey: account
+                        .address(&secp, KeyChain::External, 0)
+                        .unwrap()
+                        .script_pubkey(),
+                },
+                account: account.id,
+                chain: KeyChain::External,
+                index: 0,
+                height: Some(100),
+            });
+        }
+
+        let nonce = |b: u8| [b; 32];
+        let tree = LiabilityTree::new(vec![
+            Liability {
+                customer: "alice".into(),
+                amount_sat: 500_000,
+                nonce: nonce(1),
+            },
+            Liability {
+                customer: "bob".into(),
+                amount_sat: 250_000,
+                nonce: nonce(2),
+            },
+        ])
+        .unwrap();
+        let claim = ReserveClaim {
+            network: Network::Regtest,
+            block_hash: BlockHash::all_zeros(),
+            block_height: 200,
+            message: "audit 2026-Q3".into(),
+            liabilities: Some(tree.commitment()),
+        };
+        let proof = prove(&master, &accounts, &utxos, claim).await.unwrap();
+        let verified = verify(&proof).unwrap();
+        assert_eq!(verified.reserves_sat, 900_000);
+        assert_eq!(verified.coins, 2);
+        assert_eq!(verified.is_solvent(), Some(true));
+
+        // A proof cannot be re-used for a different claim
+        let mut altered = proof.clone();
+        altered.claim.message = "audit 2026-Q4".into();
+        assert!(verify(&altered).is_err());
+        // Nor can the coin values be inflated
+        let mut inflated = proof.clone();
+        inflated.prevouts[1].value *= 10;
+        assert!(verify(&inflated).is_err());
+        // Nor can a signed coin be dropped and another claimed in its place
+        let mut swapped = proof;
+        swapped.tx.input[1].previous_output.vout = 1;
+        assert!(verify(&swapped).is_err());
+    }
+
+    #[test]
+    fn test_liability_inclusion_proofs() {
+        let liabilities: Vec<Liability> = (0..5u8)
+            .map(|i| Liability {
+                customer: format!("customer-{}", i),
+                amount_sat: 1_000 * u64::from(i + 1),
+                nonce: [i; 32],
+            })
+            .collect();
+        let tree = LiabilityTree::new(liabilities).unwrap();
+        let commitment = tree.commitment();
+        assert_eq!(commitment.total_sat, 15_000);
+        for i in 0..5 {
+            let proof = tree.proof(&format!("customer-{}", i)).unwrap();
+            assert!(proof.verify(&commitment));
+            let mut understated = proof.clone();
+            understated.liability.amount_sat -= 1;
+            assert!(!understated.verify(&commitment));
+        }
+        assert!(tree.proof("stranger").is_none());
+
+        let duplicate = vec![
+            Liability {
+                customer: "a".into(),
+                amount_sat: 1,
+                nonce: [0; 32],
+            };
+            2
+        ];
+        assert!(LiabilityTree::new(duplicate).is_err());
+    }
+}
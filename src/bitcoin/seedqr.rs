@@ -0,0 +1,207 @@
+//! SeedQR export and import
+//!
+//! SeedQR stores a 12- or 24-word BIP-39 seed in a QR code for air-gapped
+//! signers. The standard format is a numeric string of each word's
+//! four-digit index in the BIP-39 list; the compact format is the raw
+//! entropy as binary, without the checksum word bits. Word indices are
+//! derived from the entropy directly, so no wordlist is needed to produce
+//! or read either format.
+
+use bitcoin::hashes::{sha256, Hash};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::zeroize::Zeroizing;
+use crate::{AnyaError, AnyaResult};
+
+const WORD_BITS: usize = 11;
+const DIGITS_PER_WORD: usize = 4;
+
+/// SeedQR encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedQrFormat {
+    /// Numeric word indices, readable by any QR scanner
+    Standard,
+    /// Binary entropy, giving a smaller code
+    Compact,
+}
+
+/// Entropy of a 12- or 24-word BIP-39 seed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedQr {
+    entropy: Zeroizing<Vec<u8>>,
+}
+
+impl SeedQr {
+    /// Wrap 16 or 32 bytes of entropy
+    pub fn from_entropy(entropy: &[u8]) -> AnyaResult<Self> {
+        if entropy.len() != 16 && entropy.len() != 32 {
+            return Err(AnyaError::invalid_input(
+                "SeedQR holds 12 or 24 word seeds (16 or 32 bytes of entropy)",
+            ));
+        }
+        Ok(Self {
+            entropy: Zeroizing::new(entropy.to_vec()),
+        })
+    }
+
+    /// Seed entropy
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+
+    /// Number of mnemonic words
+    pub fn word_count(&self) -> usize {
+        self.entropy.len() * 3 / 4
+    }
+
+    /// BIP-39 word indices, checksum bits included
+    pub fn word_indices(&self) -> Zeroizing<Vec<u16>> {
+        let checksum = sha256::Hash::hash(&self.entropy).to_byte_array()[0];
+        let count = self.word_count();
+        let mut words = Zeroizing::new(Vec::with_capacity(count));
+        let mut acc = 0u32;
+        let mut bits = 0;
+        for byte in self.entropy.iter().chain(std::iter::once(&checksum)) {
+            acc = (acc << 8) | u32::from(*byte);
+            bits += 8;
+            while bits >= WORD_BITS && words.len() < count {
+                bits -= WORD_BITS;
+                words.push(((acc >> bits) & 0x7ff) as u16);
+                acc &= (1 << bits) - 1;
+            }
+        }
+        words
+    }
+
+    /// Rebuild from BIP-39 word indices, verifying the checksum
+    pub fn from_word_indices(words: &[u16]) -> AnyaResult<Self> {
+        let entropy_len = match words.len() {
+            12 => 16,
+            24 => 32,
+            _ => return Err(AnyaError::invalid_input("SeedQR holds 12 or 24 word seeds")),
+        };
+        if words.iter().any(|w| *w > 0x7ff) {
+            return Err(AnyaError::invalid_input("BIP-39 word index out of range"));
+        }
+        let mut entropy = Zeroizing::new(Vec::with_capacity(entropy_len + 1));
+        let mut acc = 0u32;
+        let mut bits = 0;
+        for word in words {
+            acc = (acc << WORD_BITS) | u32::from(*word);
+            bits += WORD_BITS;
+            while bits >= 8 {
+                bits -= 8;
+                entropy.push((acc >> bits) as u8);
+                acc &= (1 << bits) - 1;
+            }
+        }
+        // 24 words leave a whole checksum byte, 12 words its top 4 bits
+        let checksum = if entropy.len() > entropy_len {
+            entropy.pop().unwrap_or_default()
+        } else {
+            (acc << (8 - bits)) as u8
+        };
+        let seed = Self::from_entropy(&entropy)?;
+        let expected = sha256::Hash::hash(&entropy).to_byte_array()[0];
+        let mask = 0xffu8 << (8 - entropy_len / 4);
+        if checksum & mask != expected & mask {
+            return Err(AnyaError::invalid_input(
+                "BIP-39 checksum mismatch; a word may be wrong",
+            ));
+        }
+        Ok(seed)
+    }
+
+    /// Standard SeedQR digits
+    pub fn to_standard(&self) -> Zeroizing<String> {
+        let words = self.word_indices();
+        let mut digits = Zeroizing::new(String::with_capacity(words.len() * DIGITS_PER_WORD));
+        for word in words.iter() {
+            for divisor in [1000, 100, 10, 1] {
+                digits.push(char::from(b'0' + (word / divisor % 10) as u8));
+            }
+        }
+        digits
+    }
+
+    /// Compact SeedQR bytes
+    pub fn to_compact(&self) -> Zeroizing<Vec<u8>> {
+        self.entropy.clone()
+    }
+
+    /// Payload in `format`, as bytes to put in the QR code
+    pub fn encode(&self, format: SeedQrFormat) -> Zeroizing<Vec<u8>> {
+        match format {
+            SeedQrFormat::Standard => {
+                let digits = self.to_standard();
+                Zeroizing::new(digits.as_bytes().to_vec())
+            }
+            SeedQrFormat::Compact => self.to_compact(),
+        }
+    }
+
+    /// Parse standard SeedQR digits
+    pub fn parse_standard(digits: &str) -> AnyaResult<Self> {
+        let digits = digits.trim();
+        if digits.len() % DIGITS_PER_WORD != 0 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(AnyaError::invalid_input(
+                "standard SeedQR is groups of four digits",
+            ));
+        }
+        let words: Zeroizing<Vec<u16>> = Zeroizing::new(
+            digits
+                .as_bytes()
+                .chunks(DIGITS_PER_WORD)
+                .map(|group| group.iter().fold(0u16, |n, d| n * 10 + u16::from(d - b'0')))
+                .collect(),
+        );
+        Self::from_word_indices(&words)
+    }
+
+    /// Parse compact SeedQR bytes
+    pub fn parse_compact(bytes: &[u8]) -> AnyaResult<Self> {
+        Self::from_entropy(bytes)
+    }
+
+    /// Parse a scanned payload in either format
+    pub fn parse(data: &[u8]) -> AnyaResult<Self> {
+        match std::str::from_utf8(data) {
+            Ok(text)
+                if [12, 24].contains(&(text.len() / DIGITS_PER_WORD))
+                    && text.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                Self::parse_standard(text)
+            }
+            _ => Self::parse_compact(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_and_compact_roundtrip() {
+        // "abandon" x11 + "about": all-zero entropy, checksum word 3
+        let zero = SeedQr::from_entropy(&[0u8; 16]).unwrap();
+        assert_eq!(*zero.to_standard(), format!("{}0003", "0000".repeat(11)));
+        // The last of 24 words is 3 entropy bits and the 8-bit checksum
+        let ones = SeedQr::from_entropy(&[0xffu8; 32]).unwrap();
+        assert_eq!(ones.word_indices()[23], 0b111 << 8 | 0xaf);
+
+        let seed = SeedQr::from_entropy(&(1..=32).collect::<Vec<u8>>()).unwrap();
+        let digits = seed.to_standard();
+        assert_eq!(digits.len(), 96);
+        assert_eq!(SeedQr::parse(digits.as_bytes()).unwrap(), seed);
+        assert_eq!(
+            SeedQr::parse(&seed.encode(SeedQrFormat::Compact)).unwrap(),
+            seed
+        );
+
+        let mut wrong = digits.to_string();
+        wrong.replace_range(92.., "0000");
+        assert!(SeedQr::parse_standard(&wrong).is_err());
+    }
+}
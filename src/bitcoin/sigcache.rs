@@ -0,0 +1,218 @@
+//! Signature and script caches shared by mempool and block validation
+//!
+//! A transaction is usually checked twice: when it enters the mempool and
+//! again when a block confirms it. [`ValidationCaches`] remembers the work
+//! of the first check so the second is nearly free:
+//!
+//! - the script cache holds inputs whose scripts verified, keyed by the
+//!   spending transaction's wtxid, the input index and the spent output;
+//! - the signature cache holds individual signatures that verified, keyed
+//!   by message, public key and signature, which also covers a transaction
+//!   replaced by one with a different wtxid reusing its signatures.
+//!
+//! Every key is a SHA-256 over a random per-process salt and the data, so a
+//! peer cannot craft transactions whose entries collide or predict which
+//! entries evict each other. When a cache is full the oldest entry goes.
+//! Entries are spread over [`Sharded`] locks so validation threads checking
+//! different inputs do not serialize on one mutex; eviction order is kept
+//! per shard.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ::bitcoin::hashes::Hash;
+use ::bitcoin::{TxOut, Wtxid};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::encoding::sha256;
+use crate::utils::sharded::{LockStats, Sharded, DEFAULT_SHARDS};
+
+/// Entries of each cache by default
+pub const DEFAULT_CACHE_ENTRIES: usize = 100_000;
+/// Fewest entries a shard is sized for
+const MIN_SHARD_ENTRIES: usize = 1_024;
+
+/// Hit counters of [`ValidationCaches`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationCacheStats {
+    /// Script checks found in the cache
+    pub script_hits: u64,
+    /// Script checks not found
+    pub script_misses: u64,
+    /// Signatures found in the cache
+    pub signature_hits: u64,
+    /// Signatures not found
+    pub signature_misses: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    set: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl Entries {
+    fn insert(&mut self, key: [u8; 32], capacity: usize) {
+        if !self.set.insert(key) {
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Bounded set of salted hashes evicting the oldest entry
+struct SaltedSet {
+    capacity: usize,
+    entries: Sharded<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SaltedSet {
+    fn new(name: &str, capacity: usize) -> Self {
+        let shards = DEFAULT_SHARDS.clamp(1, (capacity / MIN_SHARD_ENTRIES).max(1));
+        Self {
+            capacity: (capacity + shards - 1) / shards,
+            entries: Sharded::new(name, shards, Entries::default),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn contains(&self, key: &[u8; 32]) -> bool {
+        let found = self.entries.read(key).set.contains(key);
+        let counter = if found { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    fn insert(&self, key: [u8; 32]) {
+        if self.capacity > 0 {
+            self.entries.write(&key).insert(key, self.capacity);
+        }
+    }
+}
+
+/// Caches of verified scripts and signatures
+pub struct ValidationCaches {
+    salt: [u8; 32],
+    scripts: SaltedSet,
+    signatures: SaltedSet,
+}
+
+impl Default for ValidationCaches {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_ENTRIES, DEFAULT_CACHE_ENTRIES)
+    }
+}
+
+impl ValidationCaches {
+    /// Caches holding up to `scripts` script checks and `signatures`
+    /// signatures, under a fresh random salt
+    pub fn new(scripts: usize, signatures: usize) -> Self {
+        Self {
+            salt: rand::random(),
+            scripts: SaltedSet::new("sigcache.scripts", scripts),
+            signatures: SaltedSet::new("sigcache.signatures", signatures),
+        }
+    }
+
+    /// Script cache key of input `index` of transaction `wtxid` spending
+    /// `prevout`
+    pub fn script_key(&self, wtxid: &Wtxid, index: usize, prevout: &TxOut) -> [u8; 32] {
+        self.salted(&[
+            wtxid.as_byte_array(),
+            &u32::try_from(index).unwrap_or(u32::MAX).to_le_bytes(),
+            &prevout.value.to_le_bytes(),
+            prevout.script_pubkey.as_bytes(),
+        ])
+    }
+
+    /// Signature cache key of `signature` by `pubkey` over `message`
+    pub fn signature_key(&self, message: &[u8], pubkey: &[u8], signature: &[u8]) -> [u8; 32] {
+        self.salted(&[message, pubkey, signature])
+    }
+
+    /// Whether the script check `key` verified before
+    pub fn has_script(&self, key: &[u8; 32]) -> bool {
+        self.scripts.contains(key)
+    }
+
+    /// Remember that the script check `key` verified
+    pub fn add_script(&self, key: [u8; 32]) {
+        self.scripts.insert(key);
+    }
+
+    /// Whether the signature `key` verified before
+    pub fn has_signature(&self, key: &[u8; 32]) -> bool {
+        self.signatures.contains(key)
+    }
+
+    /// Remember that the signature `key` verified
+    pub fn add_signature(&self, key: [u8; 32]) {
+        self.signatures.insert(key);
+    }
+
+    /// Hit counters
+    pub fn stats(&self) -> ValidationCacheStats {
+        ValidationCacheStats {
+            script_hits: self.scripts.hits.load(Ordering::Relaxed),
+            script_misses: self.scripts.misses.load(Ordering::Relaxed),
+            signature_hits: self.signatures.hits.load(Ordering::Relaxed),
+            signature_misses: self.signatures.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Contention of the script and signature cache locks
+    pub fn lock_stats(&self) -> (LockStats, LockStats) {
+        (
+            self.scripts.entries.stats(),
+            self.signatures.entries.stats(),
+        )
+    }
+
+    fn salted(&self, parts: &[&[u8]]) -> [u8; 32] {
+        let mut data = self.salt.to_vec();
+        for part in parts {
+            // Length-prefixed so parts cannot shift into each other
+            data.extend_from_slice(&u32::try_from(part.len()).unwrap_or(u32::MAX).to_le_bytes());
+            data.extend_from_slice(part);
+        }
+        sha256(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_salted_keys_and_eviction() {
+        let caches = ValidationCaches::new(2, 2);
+        let other = ValidationCaches::new(2, 2);
+        let key = caches.signature_key(b"message", b"key", b"sig");
+        assert_ne!(key, other.signature_key(b"message", b"key", b"sig"));
+        assert_ne!(key, caches.signature_key(b"messagek", b"ey", b"sig"));
+
+        assert!(!caches.has_signature(&key));
+        caches.add_signature(key);
+        assert!(caches.has_signature(&key));
+        for sig in [b"a", b"b"] {
+            caches.add_signature(caches.signature_key(b"message", b"key", sig));
+        }
+        assert!(!caches.has_signature(&key));
+        assert_eq!(
+            caches.stats(),
+            ValidationCacheStats {
+                signature_hits: 1,
+                signature_misses: 2,
+                ..ValidationCacheStats::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,816 @@
+//! Remote signing service
+//!
+//! Splits key custody from the node: a [`RemoteSigner`] holds the master
+//! key in its own process, while the node keeps only watch-only descriptors
+//! and asks a [`SignerClient`] for signatures. Requests are newline-delimited
+//! JSON over TCP, served by [`SignerServer`].
+//!
+//! Every request is wrapped in an [`AuthenticatedRequest`]: the payload
+//! names the client, a timestamp and a random nonce, and carries an
+//! HMAC-SHA256 tag under that client's secret. Requests with a bad tag, a
+//! timestamp outside the allowed clock skew, or a nonce already seen are
+//! rejected as `Unauthenticated`.
+//!
+//! Before signing, the signer re-derives every input and change output that
+//! claims to be its own, so a compromised node cannot pass a payment off as
+//! change. The resulting [`SpendSummary`] is checked by the
+//! [`PolicyEngine`] as a `signer.sign_psbt` action with the client as actor,
+//! so spending limits and approval rules live next to the key. Signed inputs
+//! are recorded and a second transaction spending one of them is refused.
+//!
+//! Approval rules only count [`Approval`]s: HMAC tags over the transaction
+//! id and a timestamp under a secret the signer registered for that
+//! approver. The node relays them but cannot forge them, so naming an
+//! approver is not enough to satisfy a rule.
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use ::bitcoin::bip32::{ExtendedPrivKey, Fingerprint};
+use ::bitcoin::psbt::Psbt;
+use ::bitcoin::{Network, OutPoint, Txid};
+use async_trait::async_trait;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use super::psbt_verify::{is_internal_chain, spent_output, sum_sat, SignedLedger, WalletKey};
+use crate::error::ErrorReport;
+use crate::keys::KeyRing;
+use crate::lifecycle::{Subsystem, TaskSpawner};
+use crate::policy::{PolicyEngine, SystemAction};
+use crate::storage::StorageBackend;
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "remote_signer";
+
+/// Policy action kind checked before every signature
+pub const SIGN_ACTION: &str = "signer.sign_psbt";
+
+/// Largest request or response line accepted
+const MAX_LINE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Signer settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignerConfig {
+    /// Largest accepted difference between a request's timestamp and the
+    /// signer's clock, in seconds
+    pub max_clock_skew_secs: u64,
+    /// Oldest accepted approval, in seconds
+    pub max_approval_age_secs: u64,
+}
+
+impl Default for SignerConfig {
+    fn default() -> Self {
+        Self {
+            max_clock_skew_secs: 60,
+            max_approval_age_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+/// Operation asked of the signer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignerRequest {
+    /// Report the signer's key fingerprint and network
+    Describe,
+    /// Sign every input of a base64 PSBT the signer owns
+    SignPsbt {
+        /// Base64 PSBT
+        psbt: String,
+        /// Signed approvals of the spend, checked by approval rules
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+}
+
+/// An approver's consent to one transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Approval {
+    /// Name the approver was registered under
+    pub approver: String,
+    /// Unix time the approval was given
+    pub timestamp: u64,
+    /// Hex HMAC-SHA256 over the approver, transaction id and timestamp
+    /// under the approver's secret
+    pub mac: String,
+}
+
+impl Approval {
+    /// Approve `txid` as `approver`, tagging with `secret`
+    pub fn sign(approver: impl Into<String>, secret: &[u8], txid: &Txid, timestamp: u64) -> Self {
+        let approver = approver.into();
+        let message = approval_message(&approver, txid, timestamp);
+        let tag = ring::hmac::sign(
+            &ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret),
+            message.as_bytes(),
+        );
+        Self {
+            approver,
+            timestamp,
+            mac: to_hex(tag.as_ref()),
+        }
+    }
+}
+
+/// What the signer holds keys for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignerInfo {
+    /// Master key fingerprint referenced by the node's descriptors
+    pub fingerprint: Fingerprint,
+    /// Network the key signs for
+    pub network: Network,
+}
+
+/// Verified view of a spend, as checked against the policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendSummary {
+    /// Id of the transaction being signed
+    pub txid: Txid,
+    /// Inputs the signer owns
+    pub owned_inputs: Vec<OutPoint>,
+    /// Value of all inputs in satoshis
+    pub input_sat: u64,
+    /// Paid to outputs that are not the signer's, in satoshis
+    pub external_sat: u64,
+    /// Returned to verified wallet outputs, in satoshis
+    pub change_sat: u64,
+    /// Fee paid in satoshis
+    pub fee_sat: u64,
+}
+
+/// Reply to a [`SignerRequest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignerResponse {
+    /// Answer to [`SignerRequest::Describe`]
+    Info(SignerInfo),
+    /// Answer to [`SignerRequest::SignPsbt`]
+    Signed {
+        /// Base64 PSBT with the signer's signatures added
+        psbt: String,
+        /// What was signed
+        summary: SpendSummary,
+    },
+    /// The request was refused
+    Error(ErrorReport),
+}
+
+/// Request as sent over the wire: a JSON payload and its HMAC tag
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthenticatedRequest {
+    /// JSON-encoded [`RequestPayload`]
+    pub payload: String,
+    /// Hex HMAC-SHA256 of `payload` under the client's secret
+    pub mac: String,
+}
+
+/// Authenticated contents of a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestPayload {
+    /// Name the client was registered under
+    pub client: String,
+    /// Unix time the request was made
+    pub timestamp: u64,
+    /// Random hex value, never reused within the skew window
+    pub nonce: String,
+    /// The operation
+    pub request: SignerRequest,
+}
+
+/// Shared secret of one client or approver
+enum SharedKey {
+    Secret(ring::hmac::Key),
+    Ring(Arc<KeyRing>),
+}
+
+impl SharedKey {
+    fn secret(secret: &[u8]) -> Self {
+        Self::Secret(ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret))
+    }
+
+    fn tag(&self, data: &[u8]) -> AnyaResult<Vec<u8>> {
+        match self {
+            Self::Secret(key) => Ok(ring::hmac::sign(key, data).as_ref().to_vec()),
+            Self::Ring(ring) => ring.sign_hmac(data),
+        }
+    }
+
+    fn verify(&self, data: &[u8], tag: &[u8]) -> bool {
+        match self {
+            Self::Secret(key) => ring::hmac::verify(key, data, tag).is_ok(),
+            Self::Ring(ring) => ring.verify_hmac(data, tag).is_ok(),
+        }
+    }
+}
+
+/// Key-holding side of the split, enforcing policy on every signature
+pub struct RemoteSigner {
+    key: WalletKey,
+    ledger: SignedLedger,
+    policy: Arc<PolicyEngine>,
+    config: SignerConfig,
+    clients: HashMap<String, SharedKey>,
+    approvers: HashMap<String, SharedKey>,
+    /// Nonces seen within the skew window, by client, with their timestamp
+    nonces: Mutex<HashMap<(String, String), u64>>,
+    /// Serializes ledger checks with the writes that follow them
+    signing: tokio::sync::Mutex<()>,
+}
+
+impl RemoteSigner {
+    /// Create a signer for `xpriv`, recording signed inputs in `storage`
+    /// and checking spends against `policy`
+    pub async fn open(
+        xpriv: ExtendedPrivKey,
+        storage: Arc<dyn StorageBackend>,
+        policy: Arc<PolicyEngine>,
+        config: SignerConfig,
+    ) -> AnyaResult<Self> {
+        Ok(Self {
+            key: WalletKey::new(xpriv),
+            ledger: SignedLedger::open(storage, NAMESPACE).await?,
+            policy,
+            config,
+            clients: HashMap::new(),
+            approvers: HashMap::new(),
+            nonces: Mutex::new(HashMap::new()),
+            signing: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Accept requests from `client` tagged with `secret`
+    #[must_use]
+    pub fn with_client(mut self, client: impl Into<String>, secret: &[u8]) -> Self {
+        self.clients
+            .insert(client.into(), SharedKey::secret(secret));
+        self
+    }
+
+    /// Accept requests from `client` tagged with any secret `ring`
+    /// currently accepts, so the secret can be rotated without downtime
+    #[must_use]
+    pub fn with_client_key_ring(mut self, client: impl Into<String>, ring: Arc<KeyRing>) -> Self {
+        self.clients.insert(client.into(), SharedKey::Ring(ring));
+        self
+    }
+
+    /// Count approvals from `approver` signed with `secret`
+    #[must_use]
+    pub fn with_approver(mut self, approver: impl Into<String>, secret: &[u8]) -> Self {
+        self.approvers
+            .insert(approver.into(), SharedKey::secret(secret));
+        self
+    }
+
+    /// Key fingerprint and network
+    pub const fn info(&self) -> SignerInfo {
+        SignerInfo {
+            fingerprint: self.key.fingerprint(),
+            network: self.key.network(),
+        }
+    }
+
+    /// Authenticate and answer one request line
+    pub async fn handle(&self, line: &str) -> SignerResponse {
+        match self.try_handle(line).await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!(error = %e, "signer request refused");
+                SignerResponse::Error(e.to_report())
+            }
+        }
+    }
+
+    async fn try_handle(&self, line: &str) -> AnyaResult<SignerResponse> {
+        let request: AuthenticatedRequest = serde_json::from_str(line)?;
+        let payload = self.authenticate(&request, unix_now())?;
+        match payload.request {
+            SignerRequest::Describe => Ok(SignerResponse::Info(self.info())),
+            SignerRequest::SignPsbt { psbt, approvals } => {
+                let mut psbt = Psbt::from_str(&psbt).map_err(|e| {
+                    AnyaError::with_source(ErrorCode::InvalidInput, "invalid base64 PSBT", e)
+                })?;
+                let summary = self.sign(&mut psbt, &payload.client, &approvals).await?;
+                Ok(SignerResponse::Signed {
+                    psbt: psbt.to_string(),
+                    summary,
+                })
+            }
+        }
+    }
+
+    fn authenticate(&self, request: &AuthenticatedRequest, now: u64) -> AnyaResult<RequestPayload> {
+        let unauthenticated = |message: &str| AnyaError::new(ErrorCode::Unauthenticated, message);
+        let payload: RequestPayload = serde_json::from_str(&request.payload)?;
+        let key = self
+            .clients
+            .get(&payload.client)
+            .ok_or_else(|| unauthenticated("unknown signer client"))?;
+        let tag = from_hex(&request.mac).map_err(|_| unauthenticated("malformed request tag"))?;
+        if !key.verify(request.payload.as_bytes(), &tag) {
+            return Err(unauthenticated("bad request tag"));
+        }
+        let skew = self.config.max_clock_skew_secs;
+        if payload.timestamp.abs_diff(now) > skew {
+            return Err(unauthenticated(
+                "request timestamp outside the allowed skew",
+            ));
+        }
+
+        let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+        nonces.retain(|_, timestamp| timestamp.saturating_add(2 * skew) >= now);
+        let seen = nonces
+            .insert(
+                (payload.client.clone(), payload.nonce.clone()),
+                payload.timestamp,
+            )
+            .is_some();
+        drop(nonces);
+        if seen {
+            return Err(unauthenticated("replayed request nonce"));
+        }
+        Ok(payload)
+    }
+
+    /// Names of the registered approvers behind `approvals` of `txid`.
+    ///
+    /// Fails as `Unauthenticated` if any approval names an unknown
+    /// approver, has a bad tag, or is too old or from the future.
+    fn verify_approvals(
+        &self,
+        approvals: &[Approval],
+        txid: &Txid,
+        now: u64,
+    ) -> AnyaResult<Vec<String>> {
+        let unauthenticated = |message: String| AnyaError::new(ErrorCode::Unauthenticated, message);
+        let mut approvers = BTreeSet::new();
+        for approval in approvals {
+            let key = self.approvers.get(&approval.approver).ok_or_else(|| {
+                unauthenticated(format!("unknown approver {}", approval.approver))
+            })?;
+            let message = approval_message(&approval.approver, txid, approval.timestamp);
+            let valid =
+                from_hex(&approval.mac).is_ok_and(|tag| key.verify(message.as_bytes(), &tag));
+            if !valid {
+                return Err(unauthenticated(format!(
+                    "bad approval tag from {}",
+                    approval.approver
+                )));
+            }
+            let too_old = approval
+                .timestamp
+                .saturating_add(self.config.max_approval_age_secs)
+                < now;
+            let too_new = approval.timestamp > now.saturating_add(self.config.max_clock_skew_secs);
+            if too_old || too_new {
+                return Err(unauthenticated(format!(
+                    "approval from {} outside its validity window",
+                    approval.approver
+                )));
+            }
+            approvers.insert(approval.approver.clone());
+        }
+        Ok(approvers.into_iter().collect())
+    }
+
+    /// Verify a PSBT's claims about this wallet and summarize the spend.
+    ///
+    /// Fails if an input or output references this key but does not
+    /// re-derive to the same script.
+    pub fn inspect(&self, psbt: &Psbt) -> AnyaResult<SpendSummary> {
+        let tx = &psbt.unsigned_tx;
+        let mut owned_inputs = Vec::new();
+        let mut input_values = Vec::with_capacity(tx.input.len());
+        for (index, (txin, input)) in tx.input.iter().zip(&psbt.inputs).enumerate() {
+            let spent = spent_output(index, txin.previous_output, input)?;
+            input_values.push(spent.value);
+            if self
+                .key
+                .verified_path(
+                    &spent.script_pubkey,
+                    &input.bip32_derivation,
+                    &input.tap_key_origins,
+                )?
+                .is_some()
+            {
+                owned_inputs.push(txin.previous_output);
+            }
+        }
+
+        let input_sat = sum_sat(input_values)?;
+        let (mut external, mut change) = (Vec::new(), Vec::new());
+        for (txout, output) in tx.output.iter().zip(&psbt.outputs) {
+            match self.key.verified_path(
+                &txout.script_pubkey,
+                &output.bip32_derivation,
+                &output.tap_key_origins,
+            )? {
+                Some(path) if is_internal_chain(&path) => change.push(txout.value),
+                _ => external.push(txout.value),
+            }
+        }
+        let (external_sat, change_sat) = (sum_sat(external)?, sum_sat(change)?);
+        let fee_sat = input_sat
+            .checked_sub(sum_sat([external_sat, change_sat])?)
+            .ok_or_else(|| AnyaError::invalid_input("PSBT outputs exceed its inputs"))?;
+        Ok(SpendSummary {
+            txid: tx.txid(),
+            owned_inputs,
+            input_sat,
+            external_sat,
+            change_sat,
+            fee_sat,
+        })
+    }
+
+    /// Verify, check against the policy, and sign every input this key owns
+    pub async fn sign(
+        &self,
+        psbt: &mut Psbt,
+        client: &str,
+        approvals: &[Approval],
+    ) -> AnyaResult<SpendSummary> {
+        let summary = self.inspect(psbt)?;
+        let approvals = self.verify_approvals(approvals, &summary.txid, unix_now())?;
+        if summary.owned_inputs.is_empty() {
+            return Err(AnyaError::invalid_input(
+                "PSBT does not spend from this signer's key",
+            ));
+        }
+
+        let guard = self.signing.lock().await;
+        self.ledger
+            .check(&summary.owned_inputs, &summary.txid)
+            .await?;
+        let txid = summary.txid.to_string();
+        self.policy
+            .enforce(&SystemAction {
+                kind: SIGN_ACTION.to_string(),
+                actor: client.to_string(),
+                attributes: json!({
+                    "txid": txid,
+                    "amount_sat": sum_sat([summary.external_sat, summary.fee_sat])?,
+                    "external_sat": summary.external_sat,
+                    "change_sat": summary.change_sat,
+                    "fee_sat": summary.fee_sat,
+                    "inputs": summary.owned_inputs.len(),
+                    "network": self.key.network().to_string(),
+                }),
+                approvals,
+            })
+            .await?;
+
+        self.key.sign(psbt)?;
+        self.ledger
+            .record(&summary.owned_inputs, &summary.txid)
+            .await?;
+        drop(guard);
+        metrics::increment_counter!("anya_signer_signed_total", "client" => client.to_string());
+        tracing::info!(%txid, client, inputs = summary.owned_inputs.len(), "signed PSBT");
+        Ok(summary)
+    }
+}
+
+/// Signer listening on a TCP address, run as a lifecycle subsystem
+pub struct SignerServer {
+    signer: Arc<RemoteSigner>,
+    addr: SocketAddr,
+}
+
+impl SignerServer {
+    /// Serve `signer` on `addr` once started
+    pub const fn new(signer: Arc<RemoteSigner>, addr: SocketAddr) -> Self {
+        Self { signer, addr }
+    }
+}
+
+#[async_trait]
+impl Subsystem for SignerServer {
+    fn name(&self) -> &str {
+        "remote-signer"
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let listener = TcpListener::bind(self.addr).await?;
+        tracing::info!(addr = %self.addr, "remote signer listening");
+        let signer = self.signer.clone();
+        spawner
+            .spawn("accept", move |token| serve(signer, listener, token))
+            .await;
+        Ok(())
+    }
+}
+
+/// Accept clients on `listener` until `token` is cancelled
+pub async fn serve(
+    signer: Arc<RemoteSigner>,
+    listener: TcpListener,
+    token: CancellationToken,
+) -> AnyaResult<()> {
+    loop {
+        let (stream, peer) = tokio::select! {
+            () = token.cancelled() => return Ok(()),
+            accepted = listener.accept() => accepted?,
+        };
+        let signer = signer.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(signer, stream, token).await {
+                tracing::debug!(%peer, error = %e, "signer client disconnected");
+            }
+        });
+    }
+}
+
+async fn serve_client(
+    signer: Arc<RemoteSigner>,
+    stream: TcpStream,
+    token: CancellationToken,
+) -> AnyaResult<()> {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    loop {
+        let line = tokio::select! {
+            () = token.cancelled() => return Ok(()),
+            line = read_line(&mut reader) => line?,
+        };
+        let Some(line) = line else {
+            return Ok(());
+        };
+        let mut response = serde_json::to_vec(&signer.handle(&line).await)?;
+        response.push(b'\n');
+        write.write_all(&response).await?;
+    }
+}
+
+/// Next line from `reader`, or `None` at end of stream
+async fn read_line<R: AsyncBufReadExt + AsyncReadExt + Unpin>(
+    reader: &mut R,
+) -> AnyaResult<Option<String>> {
+    let mut line = String::new();
+    if reader.take(MAX_LINE_BYTES).read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(AnyaError::invalid_input("signer message too long"));
+    }
+    Ok(Some(line))
+}
+
+/// Watch-only node's handle on a [`RemoteSigner`]
+pub struct SignerClient {
+    addr: SocketAddr,
+    client: String,
+    key: SharedKey,
+}
+
+impl SignerClient {
+    /// Talk to the signer at `addr` as `client`, tagging requests with `secret`
+    pub fn new(addr: SocketAddr, client: impl Into<String>, secret: &[u8]) -> Self {
+        Self {
+            addr,
+            client: client.into(),
+            key: SharedKey::secret(secret),
+        }
+    }
+
+    /// Talk to the signer at `addr` as `client`, tagging requests with the
+    /// active secret of `ring`
+    pub fn with_key_ring(addr: SocketAddr, client: impl Into<String>, ring: Arc<KeyRing>) -> Self {
+        Self {
+            addr,
+            client: client.into(),
+            key: SharedKey::Ring(ring),
+        }
+    }
+
+    /// Fingerprint and network of the signer's key
+    pub async fn describe(&self) -> AnyaResult<SignerInfo> {
+        match self.call(SignerRequest::Describe).await? {
+            SignerResponse::Info(info) => Ok(info),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    /// Have the signer sign `psbt`, citing `approvals` for approval rules
+    pub async fn sign(
+        &self,
+        psbt: &Psbt,
+        approvals: Vec<Approval>,
+    ) -> AnyaResult<(Psbt, SpendSummary)> {
+        let request = SignerRequest::SignPsbt {
+            psbt: psbt.to_string(),
+            approvals,
+        };
+        match self.call(request).await? {
+            SignerResponse::Signed {
+                psbt: signed,
+                summary,
+            } => {
+                let signed = Psbt::from_str(&signed).map_err(|e| {
+                    AnyaError::with_source(
+                        ErrorCode::BitcoinFailure,
+                        "signer returned a bad PSBT",
+                        e,
+                    )
+                })?;
+                if signed.unsigned_tx != psbt.unsigned_tx {
+                    return Err(AnyaError::new(
+                        ErrorCode::BitcoinFailure,
+                        "signer returned a different transaction",
+                    ));
+                }
+                Ok((signed, summary))
+            }
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    async fn call(&self, request: SignerRequest) -> AnyaResult<SignerResponse> {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let payload = serde_json::to_string(&RequestPayload {
+            client: self.client.clone(),
+            timestamp: unix_now(),
+            nonce: to_hex(&nonce),
+            request,
+        })?;
+        let mut line = serde_json::to_vec(&AuthenticatedRequest {
+            mac: to_hex(&self.key.tag(payload.as_bytes())?),
+            payload,
+        })?;
+        line.push(b'\n');
+
+        let stream = TcpStream::connect(self.addr).await.map_err(|e| {
+            AnyaError::with_source(ErrorCode::Unavailable, "remote signer unreachable", e)
+        })?;
+        let (read, mut write) = stream.into_split();
+        write.write_all(&line).await?;
+        let response = read_line(&mut BufReader::new(read))
+            .await?
+            .ok_or_else(|| AnyaError::new(ErrorCode::Unavailable, "remote signer hung up"))?;
+        match serde_json::from_str(&response)? {
+            SignerResponse::Error(report) => Err(report.into()),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected(response: &SignerResponse) -> AnyaError {
+    AnyaError::new(
+        ErrorCode::Internal,
+        format!("unexpected signer response {:?}", response),
+    )
+}
+
+fn approval_message(approver: &str, txid: &Txid, timestamp: u64) -> String {
+    format!("anya-signer-approval:{}:{}:{}", approver, txid, timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::psbt_verify::fixtures;
+    use crate::policy::{Condition, Effect, Operator, PolicyConfig, PolicyRule};
+    use crate::storage::memory::MemoryBackend;
+
+    const SECRET: &[u8] = b"node-signer-secret";
+    const APPROVER_SECRET: &[u8] = b"treasurer-secret";
+
+    async fn signer() -> RemoteSigner {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let policy = PolicyEngine::open(PolicyConfig::default(), Arc::clone(&storage))
+            .await
+            .unwrap();
+        policy
+            .set_rules(vec![PolicyRule {
+                name: "large-spend".into(),
+                actions: vec![SIGN_ACTION.into()],
+                when: vec![Condition {
+                    field: "amount_sat".into(),
+                    op: Operator::Gt,
+                    value: json!(50_000),
+                }],
+                effect: Effect::RequireApprovals(1),
+                message: "spends above 50k sat need an approval".into(),
+            }])
+            .await
+            .unwrap();
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &[3u8; 32]).unwrap();
+        RemoteSigner::open(xpriv, storage, Arc::new(policy), SignerConfig::default())
+            .await
+            .unwrap()
+            .with_client("node", SECRET)
+            .with_approver("treasurer", APPROVER_SECRET)
+    }
+
+    fn build_psbt(signer: &RemoteSigner, external_value: u64) -> Psbt {
+        fixtures::build_psbt(&signer.key, external_value)
+    }
+
+    fn tagged(payload: &RequestPayload, secret: &[u8]) -> String {
+        let payload = serde_json::to_string(payload).unwrap();
+        serde_json::to_string(&AuthenticatedRequest {
+            mac: to_hex(&SharedKey::secret(secret).tag(payload.as_bytes()).unwrap()),
+            payload,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rejects_forged_stale_and_replayed_requests() {
+        let signer = signer().await;
+        let payload = RequestPayload {
+            client: "node".into(),
+            timestamp: unix_now(),
+            nonce: "01".into(),
+            request: SignerRequest::Describe,
+        };
+        let code = |response: SignerResponse| match response {
+            SignerResponse::Error(report) => ErrorCode::from_u32(report.code),
+            _ => None,
+        };
+
+        let forged = signer.handle(&tagged(&payload, b"wrong")).await;
+        assert_eq!(code(forged), Some(ErrorCode::Unauthenticated));
+        let stale = RequestPayload {
+            timestamp: payload.timestamp - 600,
+            ..payload.clone()
+        };
+        let stale = signer.handle(&tagged(&stale, SECRET)).await;
+        assert_eq!(code(stale), Some(ErrorCode::Unauthenticated));
+
+        let line = tagged(&payload, SECRET);
+        assert_eq!(
+            signer.handle(&line).await,
+            SignerResponse::Info(signer.info())
+        );
+        assert_eq!(
+            code(signer.handle(&line).await),
+            Some(ErrorCode::Unauthenticated)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_only_client_signs_within_policy() {
+        let signer = signer().await;
+        let unsigned = build_psbt(&signer, 60_000);
+        let tampered = {
+            let mut psbt = build_psbt(&signer, 60_000);
+            psbt.unsigned_tx.output[1].script_pubkey =
+                psbt.unsigned_tx.output[0].script_pubkey.clone();
+            psbt
+        };
+        let replay = build_psbt(&signer, 20_000);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let token = CancellationToken::new();
+        let server = tokio::spawn(serve(Arc::new(signer), listener, token.clone()));
+        let client = SignerClient::new(addr, "node", SECRET);
+
+        assert_eq!(client.describe().await.unwrap().network, Network::Regtest);
+        let err = client.sign(&tampered, vec![]).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        let err = client.sign(&unsigned, vec![]).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+
+        // The node can name approvers but cannot sign for them
+        let txid = unsigned.unsigned_tx.txid();
+        let made_up = Approval::sign("cfo", b"guessed", &txid, unix_now());
+        let err = client.sign(&unsigned, vec![made_up]).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+        let forged = Approval::sign("treasurer", b"guessed", &txid, unix_now());
+        let err = client.sign(&unsigned, vec![forged]).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+        let other_tx = Approval::sign(
+            "treasurer",
+            APPROVER_SECRET,
+            &replay.unsigned_tx.txid(),
+            unix_now(),
+        );
+        let err = client.sign(&unsigned, vec![other_tx]).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+
+        let approval = Approval::sign("treasurer", APPROVER_SECRET, &txid, unix_now());
+        let (signed, summary) = client.sign(&unsigned, vec![approval]).await.unwrap();
+        assert_eq!((summary.external_sat, summary.change_sat), (60_000, 39_000));
+        assert_eq!(summary.fee_sat, 1_000);
+        assert_eq!(signed.inputs[0].partial_sigs.len(), 1);
+        // Same input, different payment
+        let err = client.sign(&replay, vec![]).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+
+        token.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,624 @@
+//! SLIP-39 Shamir backups
+//!
+//! [`split`] encrypts a master secret under an optional passphrase and
+//! splits it into groups of member shares, any `group_threshold` groups of
+//! which recover it; [`combine`] reverses this. Shares use the two-level
+//! scheme, GF(256) interpolation with a digest share, the 4-round Feistel
+//! cipher, and the RS1024 checksum from the SLIP-39 specification, and are
+//! created with the extendable flag set.
+//!
+//! Shares are handled here as 10-bit word indices; turning them into words
+//! needs the 1024-word SLIP-39 list, see
+//! [`Wordlist`](super::ceremony::Wordlist). Every buffer holding secret
+//! material is a [`Zeroizing`] wrapper.
+
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::zeroize::Zeroizing;
+use crate::{AnyaError, AnyaResult};
+
+/// Shortest master secret accepted, in bytes
+pub const MIN_SECRET_BYTES: usize = 16;
+
+/// Most shares in a group, and most groups
+pub const MAX_SHARES: u8 = 16;
+
+/// Fewest words in a share, for a 128-bit secret
+pub const MIN_SHARE_WORDS: usize = 20;
+
+const RADIX_BITS: usize = 10;
+const CHECKSUM_WORDS: usize = 3;
+const DIGEST_BYTES: usize = 4;
+const SECRET_INDEX: u8 = 255;
+const DIGEST_INDEX: u8 = 254;
+const BASE_ITERATIONS: u32 = 10_000;
+const ROUNDS: u8 = 4;
+const CUSTOMIZATION: &[u8] = b"shamir";
+const CUSTOMIZATION_EXTENDABLE: &[u8] = b"shamir_extendable";
+const RS1024_GEN: [u32; 10] = [
+    0x00E0_E040,
+    0x01C1_C080,
+    0x0383_8100,
+    0x0707_0200,
+    0x0E0E_0009,
+    0x1C0C_2412,
+    0x3808_6C24,
+    0x3090_FC48,
+    0x21B1_F890,
+    0x03F3_F120,
+];
+
+/// Exponent and logarithm tables of GF(256) over x^8 + x^4 + x^3 + x + 1
+const GF: ([u8; 255], [u8; 256]) = gf_tables();
+
+const fn gf_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        // Multiply by the generator x + 1
+        x ^= x << 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11b;
+        }
+        i += 1;
+    }
+    (exp, log)
+}
+
+/// Shape of one group: `threshold` of `count` members recover it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupSpec {
+    /// Members needed
+    pub threshold: u8,
+    /// Members created
+    pub count: u8,
+}
+
+/// One member share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    /// Random identifier shared by all shares of one split
+    pub identifier: u16,
+    /// Whether the passphrase encryption omits the identifier
+    pub extendable: bool,
+    /// PBKDF2 cost exponent
+    pub iteration_exponent: u8,
+    /// Group this share belongs to
+    pub group_index: u8,
+    /// Groups needed to recover the secret
+    pub group_threshold: u8,
+    /// Groups created
+    pub group_count: u8,
+    /// Position within the group
+    pub member_index: u8,
+    /// Members of this group needed to recover its group share
+    pub member_threshold: u8,
+    value: Zeroizing<Vec<u8>>,
+}
+
+impl Share {
+    /// Share value, as long as the master secret
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Whether `other` comes from the same split and can be combined with
+    /// this share
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.identifier == other.identifier
+            && self.extendable == other.extendable
+            && self.iteration_exponent == other.iteration_exponent
+            && self.group_threshold == other.group_threshold
+            && self.group_count == other.group_count
+            && self.value.len() == other.value.len()
+    }
+
+    /// Share as 10-bit word indices, checksum included
+    pub fn to_words(&self) -> Zeroizing<Vec<u16>> {
+        let value_words = (self.value.len() * 8 + RADIX_BITS - 1) / RADIX_BITS;
+        let mut words = Zeroizing::new(Vec::with_capacity(4 + value_words + CHECKSUM_WORDS));
+        let id_exp = u32::from(self.identifier) << 5
+            | u32::from(self.extendable) << 4
+            | u32::from(self.iteration_exponent);
+        let params = u32::from(self.group_index) << 16
+            | u32::from(self.group_threshold - 1) << 12
+            | u32::from(self.group_count - 1) << 8
+            | u32::from(self.member_index) << 4
+            | u32::from(self.member_threshold - 1);
+        for field in [id_exp, params] {
+            words.push((field >> RADIX_BITS) as u16);
+            words.push((field & 0x3ff) as u16);
+        }
+
+        // The value is left-padded with zero bits to whole words
+        let mut acc = 0u32;
+        let mut bits = value_words * RADIX_BITS - self.value.len() * 8;
+        for byte in self.value.iter() {
+            acc = (acc << 8) | u32::from(*byte);
+            bits += 8;
+            while bits >= RADIX_BITS {
+                bits -= RADIX_BITS;
+                words.push(((acc >> bits) & 0x3ff) as u16);
+                acc &= (1 << bits) - 1;
+            }
+        }
+
+        let checksum = rs1024_checksum(customization(self.extendable), &words);
+        words.extend_from_slice(&checksum);
+        words
+    }
+
+    /// Parse word indices, verifying the checksum
+    pub fn from_words(words: &[u16]) -> AnyaResult<Self> {
+        if words.len() < MIN_SHARE_WORDS {
+            return Err(AnyaError::invalid_input(format!(
+                "a SLIP-39 share has at least {} words",
+                MIN_SHARE_WORDS
+            )));
+        }
+        if words.iter().any(|w| *w > 0x3ff) {
+            return Err(AnyaError::invalid_input("SLIP-39 word index out of range"));
+        }
+        let id_exp = u32::from(words[0]) << RADIX_BITS | u32::from(words[1]);
+        let extendable = id_exp & 0x10 != 0;
+        if rs1024_polymod(customization(extendable), words) != 1 {
+            return Err(AnyaError::invalid_input(
+                "SLIP-39 checksum mismatch; a word may be mistyped",
+            ));
+        }
+        let params = u32::from(words[2]) << RADIX_BITS | u32::from(words[3]);
+        let nibble = |shift: u32| ((params >> shift) & 0xf) as u8;
+        let (group_threshold, group_count) = (nibble(12) + 1, nibble(8) + 1);
+        if group_threshold > group_count {
+            return Err(AnyaError::invalid_input(
+                "share group threshold exceeds its group count",
+            ));
+        }
+
+        let value_words = &words[4..words.len() - CHECKSUM_WORDS];
+        let padding = value_words.len() * RADIX_BITS % 16;
+        if padding > 8 {
+            return Err(AnyaError::invalid_input("invalid SLIP-39 share length"));
+        }
+        let mut value = Zeroizing::new(Vec::with_capacity(
+            (value_words.len() * RADIX_BITS - padding) / 8,
+        ));
+        let mut acc = 0u32;
+        let mut bits = 0usize;
+        let mut skip = padding;
+        for word in value_words {
+            acc = (acc << RADIX_BITS) | u32::from(*word);
+            bits += RADIX_BITS;
+            if skip > 0 {
+                bits -= skip;
+                if acc >> bits != 0 {
+                    return Err(AnyaError::invalid_input("nonzero SLIP-39 share padding"));
+                }
+                skip = 0;
+            }
+            while bits >= 8 {
+                bits -= 8;
+                value.push((acc >> bits) as u8);
+                acc &= (1 << bits) - 1;
+            }
+        }
+
+        Ok(Self {
+            identifier: (id_exp >> 5) as u16,
+            extendable,
+            iteration_exponent: (id_exp & 0xf) as u8,
+            group_index: nibble(16),
+            group_threshold,
+            group_count,
+            member_index: nibble(4),
+            member_threshold: nibble(0) + 1,
+            value,
+        })
+    }
+}
+
+/// Encrypt `master_secret` under `passphrase` and split it into shares,
+/// returned per group
+pub fn split(
+    master_secret: &[u8],
+    passphrase: &str,
+    group_threshold: u8,
+    groups: &[GroupSpec],
+    iteration_exponent: u8,
+) -> AnyaResult<Vec<Vec<Share>>> {
+    if master_secret.len() < MIN_SECRET_BYTES || master_secret.len() % 2 != 0 {
+        return Err(AnyaError::invalid_input(format!(
+            "master secret must be an even number of bytes, at least {}",
+            MIN_SECRET_BYTES
+        )));
+    }
+    if iteration_exponent > 0xf {
+        return Err(AnyaError::invalid_input("iteration exponent above 15"));
+    }
+    check_passphrase(passphrase)?;
+    let group_count = u8::try_from(groups.len())
+        .ok()
+        .filter(|n| (1..=MAX_SHARES).contains(n))
+        .ok_or_else(|| AnyaError::invalid_input("between 1 and 16 groups are needed"))?;
+    if group_threshold == 0 || group_threshold > group_count {
+        return Err(AnyaError::invalid_input(
+            "group threshold must be between 1 and the group count",
+        ));
+    }
+    for group in groups {
+        if group.count == 0 || group.count > MAX_SHARES || group.threshold == 0 {
+            return Err(AnyaError::invalid_input(
+                "groups need between 1 and 16 members and a nonzero threshold",
+            ));
+        }
+        if group.threshold > group.count {
+            return Err(AnyaError::invalid_input(
+                "member threshold exceeds the group size",
+            ));
+        }
+        if group.threshold == 1 && group.count > 1 {
+            return Err(AnyaError::invalid_input(
+                "a 1-of-n group is a copy of one share; use a single member instead",
+            ));
+        }
+    }
+
+    let identifier = (rand::thread_rng().next_u32() & 0x7fff) as u16;
+    let encrypted = crypt(
+        master_secret,
+        passphrase,
+        iteration_exponent,
+        identifier,
+        true,
+        &[0, 1, 2, 3],
+    );
+    let group_shares = split_secret(group_threshold, group_count, &encrypted)?;
+    groups
+        .iter()
+        .zip(group_shares)
+        .map(|(group, (group_index, group_secret))| {
+            Ok(split_secret(group.threshold, group.count, &group_secret)?
+                .into_iter()
+                .map(|(member_index, value)| Share {
+                    identifier,
+                    extendable: true,
+                    iteration_exponent,
+                    group_index,
+                    group_threshold,
+                    group_count,
+                    member_index,
+                    member_threshold: group.threshold,
+                    value,
+                })
+                .collect())
+        })
+        .collect()
+}
+
+/// Recover the master secret from enough shares of one split.
+///
+/// Extra shares and groups beyond the thresholds are ignored.
+pub fn combine(shares: &[Share], passphrase: &str) -> AnyaResult<Zeroizing<Vec<u8>>> {
+    let first = shares
+        .first()
+        .ok_or_else(|| AnyaError::invalid_input("no shares given"))?;
+    check_passphrase(passphrase)?;
+    let mut groups: BTreeMap<u8, Vec<&Share>> = BTreeMap::new();
+    for share in shares {
+        if !first.is_compatible(share) {
+            return Err(AnyaError::invalid_input(
+                "shares come from different backups",
+            ));
+        }
+        let members = groups.entry(share.group_index).or_default();
+        if let Some(other) = members.first() {
+            if other.member_threshold != share.member_threshold {
+                return Err(AnyaError::invalid_input(
+                    "shares of one group disagree on its threshold",
+                ));
+            }
+        }
+        if let Some(existing) = members
+            .iter()
+            .find(|m| m.member_index == share.member_index)
+        {
+            if existing.value != share.value {
+                return Err(AnyaError::invalid_input(
+                    "two different shares claim the same member index",
+                ));
+            }
+            continue;
+        }
+        members.push(share);
+    }
+
+    let mut group_shares = Vec::new();
+    for (group_index, members) in &groups {
+        let threshold = members[0].member_threshold;
+        if members.len() < usize::from(threshold) {
+            continue;
+        }
+        let points: Vec<(u8, &[u8])> = members[..usize::from(threshold)]
+            .iter()
+            .map(|m| (m.member_index, m.value()))
+            .collect();
+        group_shares.push((*group_index, recover_secret(threshold, &points)?));
+        if group_shares.len() == usize::from(first.group_threshold) {
+            break;
+        }
+    }
+    if group_shares.len() < usize::from(first.group_threshold) {
+        return Err(AnyaError::invalid_input(format!(
+            "{} complete groups are needed, {} given",
+            first.group_threshold,
+            group_shares.len()
+        )));
+    }
+    let points: Vec<(u8, &[u8])> = group_shares
+        .iter()
+        .map(|(index, value)| (*index, value.as_slice()))
+        .collect();
+    let encrypted = recover_secret(first.group_threshold, &points)?;
+    Ok(crypt(
+        &encrypted,
+        passphrase,
+        first.iteration_exponent,
+        first.identifier,
+        first.extendable,
+        &[3, 2, 1, 0],
+    ))
+}
+
+fn check_passphrase(passphrase: &str) -> AnyaResult<()> {
+    if passphrase.bytes().all(|b| (32..=126).contains(&b)) {
+        Ok(())
+    } else {
+        Err(AnyaError::invalid_input(
+            "SLIP-39 passphrases are printable ASCII",
+        ))
+    }
+}
+
+const fn customization(extendable: bool) -> &'static [u8] {
+    if extendable {
+        CUSTOMIZATION_EXTENDABLE
+    } else {
+        CUSTOMIZATION
+    }
+}
+
+fn rs1024_polymod(customization: &[u8], words: &[u16]) -> u32 {
+    let values = customization
+        .iter()
+        .map(|b| u32::from(*b))
+        .chain(words.iter().map(|w| u32::from(*w)));
+    let mut chk = 1u32;
+    for value in values {
+        let top = chk >> 20;
+        chk = ((chk & 0xf_ffff) << RADIX_BITS) ^ value;
+        for (i, generator) in RS1024_GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn rs1024_checksum(customization: &[u8], words: &[u16]) -> [u16; CHECKSUM_WORDS] {
+    let mut padded = Zeroizing::new(Vec::with_capacity(words.len() + CHECKSUM_WORDS));
+    padded.extend_from_slice(words);
+    padded.extend_from_slice(&[0; CHECKSUM_WORDS]);
+    let polymod = rs1024_polymod(customization, &padded) ^ 1;
+    [2, 1, 0].map(|i| ((polymod >> (RADIX_BITS * i)) & 0x3ff) as u16)
+}
+
+/// Feistel cipher over the two halves of `secret`, running `rounds` in
+/// order; encryption is rounds 0..4 and decryption the reverse
+fn crypt(
+    secret: &[u8],
+    passphrase: &str,
+    iteration_exponent: u8,
+    identifier: u16,
+    extendable: bool,
+    rounds: &[u8],
+) -> Zeroizing<Vec<u8>> {
+    let half = secret.len() / 2;
+    let mut left = Zeroizing::new(secret[..half].to_vec());
+    let mut right = Zeroizing::new(secret[half..].to_vec());
+    let mut salt = Zeroizing::new(Vec::with_capacity(8 + half));
+    if !extendable {
+        salt.extend_from_slice(CUSTOMIZATION);
+        salt.extend_from_slice(&identifier.to_be_bytes());
+    }
+    let salt_len = salt.len();
+    let mut password = Zeroizing::new(Vec::with_capacity(1 + passphrase.len()));
+    password.push(0);
+    password.extend_from_slice(passphrase.as_bytes());
+    let iterations = NonZeroU32::new((BASE_ITERATIONS / u32::from(ROUNDS)) << iteration_exponent)
+        .unwrap_or(NonZeroU32::MIN);
+
+    for round in rounds {
+        password[0] = *round;
+        salt.truncate(salt_len);
+        salt.extend_from_slice(&right);
+        let mut mixed = Zeroizing::new(vec![0u8; half]);
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            &password,
+            &mut mixed,
+        );
+        for (m, l) in mixed.iter_mut().zip(left.iter()) {
+            *m ^= l;
+        }
+        left = std::mem::replace(&mut right, mixed);
+    }
+    let mut out = Zeroizing::new(Vec::with_capacity(secret.len()));
+    out.extend_from_slice(&right);
+    out.extend_from_slice(&left);
+    out
+}
+
+/// Evaluate at `x` the polynomial through `points`
+fn interpolate(points: &[(u8, &[u8])], x: u8) -> AnyaResult<Zeroizing<Vec<u8>>> {
+    let len = points.first().map_or(0, |(_, v)| v.len());
+    if points.iter().any(|(_, v)| v.len() != len) {
+        return Err(AnyaError::invalid_input("share values differ in length"));
+    }
+    for (i, (xi, _)) in points.iter().enumerate() {
+        if points[i + 1..].iter().any(|(xj, _)| xj == xi) {
+            return Err(AnyaError::invalid_input("duplicate share index"));
+        }
+    }
+    if let Some((_, value)) = points.iter().find(|(xi, _)| *xi == x) {
+        return Ok(Zeroizing::new(value.to_vec()));
+    }
+
+    let (exp, log) = &GF;
+    let log_of = |v: u8| i32::from(log[usize::from(v)]);
+    let log_prod: i32 = points.iter().map(|(xi, _)| log_of(xi ^ x)).sum();
+    let mut result = Zeroizing::new(vec![0u8; len]);
+    for (xi, value) in points {
+        let denominator: i32 = points
+            .iter()
+            .filter(|(xj, _)| xj != xi)
+            .map(|(xj, _)| log_of(xi ^ xj))
+            .sum();
+        let log_basis = (log_prod - log_of(xi ^ x) - denominator).rem_euclid(255);
+        for (out, byte) in result.iter_mut().zip(value.iter()) {
+            if *byte != 0 {
+                *out ^= exp[((log_of(*byte) + log_basis) % 255) as usize];
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn split_secret(
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+) -> AnyaResult<Vec<(u8, Zeroizing<Vec<u8>>)>> {
+    if threshold == 1 {
+        return Ok((0..count)
+            .map(|i| (i, Zeroizing::new(secret.to_vec())))
+            .collect());
+    }
+    let random_count = threshold - 2;
+    let mut rng = rand::thread_rng();
+    let mut shares: Vec<(u8, Zeroizing<Vec<u8>>)> = (0..random_count)
+        .map(|i| {
+            let mut value = Zeroizing::new(vec![0u8; secret.len()]);
+            rng.fill_bytes(&mut value);
+            (i, value)
+        })
+        .collect();
+    let mut digest_share = Zeroizing::new(vec![0u8; secret.len()]);
+    rng.fill_bytes(&mut digest_share[DIGEST_BYTES..]);
+    let digest = share_digest(&digest_share[DIGEST_BYTES..], secret);
+    digest_share[..DIGEST_BYTES].copy_from_slice(&digest);
+
+    let mut base: Vec<(u8, &[u8])> = shares.iter().map(|(i, v)| (*i, v.as_slice())).collect();
+    base.push((DIGEST_INDEX, digest_share.as_slice()));
+    base.push((SECRET_INDEX, secret));
+    let derived = (random_count..count)
+        .map(|i| Ok((i, interpolate(&base, i)?)))
+        .collect::<AnyaResult<Vec<_>>>()?;
+    shares.extend(derived);
+    Ok(shares)
+}
+
+fn recover_secret(threshold: u8, points: &[(u8, &[u8])]) -> AnyaResult<Zeroizing<Vec<u8>>> {
+    if threshold == 1 {
+        return Ok(Zeroizing::new(points[0].1.to_vec()));
+    }
+    let secret = interpolate(points, SECRET_INDEX)?;
+    let digest_share = interpolate(points, DIGEST_INDEX)?;
+    let expected = share_digest(&digest_share[DIGEST_BYTES..], &secret);
+    if ring::constant_time::verify_slices_are_equal(&digest_share[..DIGEST_BYTES], &expected)
+        .is_err()
+    {
+        return Err(AnyaError::invalid_input(
+            "share digest mismatch; a share is wrong or corrupted",
+        ));
+    }
+    Ok(secret)
+}
+
+fn share_digest(random_part: &[u8], secret: &[u8]) -> [u8; DIGEST_BYTES] {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, random_part);
+    let tag = ring::hmac::sign(&key, secret);
+    let mut digest = [0u8; DIGEST_BYTES];
+    digest.copy_from_slice(&tag.as_ref()[..DIGEST_BYTES]);
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_two_level_split_roundtrips_through_words() {
+        let secret = [0x42u8; 16];
+        let groups = [
+            GroupSpec {
+                threshold: 1,
+                count: 1,
+            },
+            GroupSpec {
+                threshold: 2,
+                count: 3,
+            },
+            GroupSpec {
+                threshold: 3,
+                count: 5,
+            },
+        ];
+        let shares = split(&secret, "TREZOR", 2, &groups, 0).unwrap();
+        assert_eq!(shares.iter().map(Vec::len).collect::<Vec<_>>(), [1, 3, 5]);
+
+        let words = shares[2][4].to_words();
+        assert_eq!(words.len(), MIN_SHARE_WORDS);
+        let parsed = Share::from_words(&words).unwrap();
+        assert_eq!(parsed, shares[2][4]);
+        let mut typo = words;
+        typo[7] ^= 1;
+        assert!(Share::from_words(&typo).is_err());
+
+        let chosen = [
+            shares[0][0].clone(),
+            shares[2][4].clone(),
+            shares[2][0].clone(),
+            shares[2][2].clone(),
+        ];
+        assert_eq!(*combine(&chosen, "TREZOR").unwrap(), secret);
+        // A different passphrase decrypts to a different, valid secret
+        assert_ne!(*combine(&chosen, "").unwrap(), secret);
+        let short = [shares[1][0].clone(), shares[2][0].clone()];
+        assert!(combine(&short, "TREZOR").is_err());
+    }
+
+    #[test]
+    fn test_corrupted_share_fails_digest_check() {
+        let secret: Vec<u8> = (0..32).collect();
+        let groups = [GroupSpec {
+            threshold: 3,
+            count: 5,
+        }];
+        let mut shares = split(&secret, "", 1, &groups, 0).unwrap().remove(0);
+        assert_eq!(*combine(&shares[1..4], "").unwrap(), secret);
+        shares[2].value[5] ^= 0x80;
+        let err = combine(&shares[1..4], "").unwrap_err();
+        assert!(err.to_string().contains("digest"));
+    }
+}
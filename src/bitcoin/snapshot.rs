@@ -0,0 +1,738 @@
+//! Fast sync from a signed UTXO set snapshot (assumeutxo)
+//!
+//! Instead of validating every block since genesis before it is usable, a
+//! node can load the [`UtxoCache`] as of a recent block from a snapshot and
+//! follow the chain from there at once. A snapshot is trusted only when
+//!
+//! - its manifest is signed by one of the configured publisher keys, and
+//! - its height, block hash, coin count and UTXO set hash match a
+//!   [`SnapshotParams`] entry compiled into the node ([`assumeutxo_params`])
+//!   or supplied by the operator,
+//!
+//! and the coins are then hashed while they are staged in a namespace of
+//! their own. They are copied into the UTXO set only once the hash matches,
+//! so a snapshot whose contents differ from its manifest never reaches it.
+//! No parameters have been reviewed for mainnet, testnet or signet yet, so
+//! snapshots are refused there until a release ships them.
+//!
+//! [`Backfill`] then downloads the blocks up to the snapshot height in the
+//! background, connects them into a second chainstate and, on reaching the
+//! snapshot block, compares that chainstate's set hash with the snapshot's.
+//! Only then is the snapshot [`SnapshotStatus::Validated`]; a mismatch marks
+//! it [`SnapshotStatus::Invalid`] and is logged as an error. Backfilled
+//! blocks can also be connected into a [`ChainIndex`] for historical
+//! lookups.
+//!
+//! A snapshot is newline-delimited JSON: the [`SnapshotManifest`], then one
+//! coin per line in ascending outpoint order.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::hashes::{sha256, Hash, HashEngine};
+use ::bitcoin::secp256k1::schnorr::Signature;
+use ::bitcoin::secp256k1::{KeyPair, Message, Secp256k1, XOnlyPublicKey};
+use ::bitcoin::{BlockHash, Network, OutPoint};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::index::ChainIndex;
+use super::provider::ChainDataProvider;
+use super::utxo::{Coin, UtxoCache, UtxoCacheConfig};
+use crate::lifecycle::run_loop;
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "snapshot";
+const STATE_KEY: &str = "state";
+/// Namespace of the chainstate rebuilt by [`Backfill`]
+const BACKFILL_NAMESPACE: &str = "utxo_backfill";
+/// Namespace of coins read from a snapshot that is not yet verified
+const STAGING_NAMESPACE: &str = "snapshot_staging";
+const STAGING_PREFIX: &str = "coin/";
+/// Coins stored per write while loading
+const IMPORT_BATCH: usize = 10_000;
+/// Domain separation of the manifest signature
+const SIGNATURE_TAG: &[u8] = b"anya/utxo-snapshot/v1";
+
+/// A snapshot the node accepts: the set as of `block_hash` at `height`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotParams {
+    /// Height of the snapshot block
+    pub height: u32,
+    /// Hash of the snapshot block
+    pub block_hash: BlockHash,
+    /// Hash of the coins, see [`SetHasher`]
+    pub utxo_hash: sha256::Hash,
+    /// Number of coins
+    pub coins: u64,
+}
+
+/// Snapshots of each network reviewed for inclusion in releases, as
+/// `(height, block hash, UTXO set hash, coins)`.
+///
+/// The set hash is specific to this snapshot format, so Bitcoin Core's
+/// published values do not apply; entries are added once a snapshot in
+/// this format has been produced and independently reproduced.
+const MAINNET_PARAMS: &[(u32, &str, &str, u64)] = &[];
+const TESTNET_PARAMS: &[(u32, &str, &str, u64)] = &[];
+const SIGNET_PARAMS: &[(u32, &str, &str, u64)] = &[];
+
+/// Snapshots compiled into the node for `network`
+pub fn assumeutxo_params(network: Network) -> Vec<SnapshotParams> {
+    let table = match network {
+        Network::Bitcoin => MAINNET_PARAMS,
+        Network::Testnet => TESTNET_PARAMS,
+        Network::Signet => SIGNET_PARAMS,
+        _ => &[],
+    };
+    table
+        .iter()
+        .filter_map(|(height, block, utxo, coins)| {
+            Some(SnapshotParams {
+                height: *height,
+                block_hash: BlockHash::from_str(block).ok()?,
+                utxo_hash: sha256::Hash::from_str(utxo).ok()?,
+                coins: *coins,
+            })
+        })
+        .collect()
+}
+
+/// First line of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Network the snapshot belongs to
+    pub network: Network,
+    /// Block the set is current with, and the set's hash
+    #[serde(flatten)]
+    pub params: SnapshotParams,
+    /// Hex x-only key of the publisher
+    pub publisher: String,
+    /// Hex BIP-340 signature of the publisher over the fields above
+    pub signature: String,
+}
+
+impl SnapshotManifest {
+    fn digest(network: Network, params: &SnapshotParams) -> AnyaResult<Message> {
+        let mut engine = sha256::Hash::engine();
+        engine.input(SIGNATURE_TAG);
+        engine.input(&network.magic().to_bytes());
+        engine.input(&params.height.to_le_bytes());
+        engine.input(params.block_hash.as_byte_array());
+        engine.input(params.utxo_hash.as_byte_array());
+        engine.input(&params.coins.to_le_bytes());
+        Ok(Message::from_slice(
+            sha256::Hash::from_engine(engine).as_byte_array(),
+        )?)
+    }
+
+    fn sign(network: Network, params: SnapshotParams, keys: &KeyPair) -> AnyaResult<Self> {
+        let secp = Secp256k1::signing_only();
+        let signature = secp.sign_schnorr_no_aux_rand(&Self::digest(network, &params)?, keys);
+        Ok(Self {
+            network,
+            params,
+            publisher: to_hex(&keys.x_only_public_key().0.serialize()),
+            signature: to_hex(signature.as_ref()),
+        })
+    }
+
+    /// Publisher key, once the signature is checked
+    pub fn verify(&self) -> AnyaResult<XOnlyPublicKey> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            AnyaError::invalid_input(format!("bad snapshot signature: {}", e))
+        };
+        let publisher =
+            XOnlyPublicKey::from_slice(&from_hex(&self.publisher)?).map_err(|e| invalid(&e))?;
+        let signature =
+            Signature::from_slice(&from_hex(&self.signature)?).map_err(|e| invalid(&e))?;
+        Secp256k1::verification_only()
+            .verify_schnorr(
+                &signature,
+                &Self::digest(self.network, &self.params)?,
+                &publisher,
+            )
+            .map_err(|e| invalid(&e))?;
+        Ok(publisher)
+    }
+}
+
+/// One coin line of a snapshot
+#[derive(Serialize, Deserialize)]
+struct SnapshotCoin {
+    outpoint: OutPoint,
+    #[serde(flatten)]
+    coin: Coin,
+}
+
+/// Running hash of a UTXO set.
+///
+/// Coins are hashed in ascending outpoint order, each as its outpoint,
+/// `height * 2 + coinbase`, value and length-prefixed script, so the hash
+/// identifies the set and out-of-order or duplicate coins are refused.
+#[derive(Default)]
+pub struct SetHasher {
+    engine: sha256::HashEngine,
+    last: Option<OutPoint>,
+    coins: u64,
+}
+
+impl SetHasher {
+    /// Empty hasher
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next coin
+    pub fn add(&mut self, outpoint: &OutPoint, coin: &Coin) -> AnyaResult<()> {
+        if self.last.is_some_and(|last| last >= *outpoint) {
+            return Err(AnyaError::invalid_input(format!(
+                "snapshot coin {} out of order",
+                outpoint
+            )));
+        }
+        let code = coin.height.saturating_mul(2) | u32::from(coin.coinbase);
+        let script = coin.output.script_pubkey.as_bytes();
+        self.engine.input(outpoint.txid.as_byte_array());
+        self.engine.input(&outpoint.vout.to_le_bytes());
+        self.engine.input(&code.to_le_bytes());
+        self.engine.input(&coin.output.value.to_le_bytes());
+        self.engine.input(
+            &u32::try_from(script.len())
+                .unwrap_or(u32::MAX)
+                .to_le_bytes(),
+        );
+        self.engine.input(script);
+        self.last = Some(*outpoint);
+        self.coins += 1;
+        Ok(())
+    }
+
+    /// Hash and number of the coins added
+    pub fn finish(self) -> (sha256::Hash, u64) {
+        (sha256::Hash::from_engine(self.engine), self.coins)
+    }
+}
+
+/// Hash and size of the coins in `utxo`
+pub async fn utxo_set_hash(utxo: &UtxoCache) -> AnyaResult<(sha256::Hash, u64)> {
+    let mut hasher = SetHasher::new();
+    for (outpoint, coin) in utxo.coins().await? {
+        hasher.add(&outpoint, &coin)?;
+    }
+    Ok(hasher.finish())
+}
+
+/// Write a snapshot of `utxo`, current with the block at `height`, signed
+/// by `keys`
+pub async fn write_snapshot<W: AsyncWrite + Unpin>(
+    utxo: &UtxoCache,
+    network: Network,
+    height: u32,
+    keys: &KeyPair,
+    out: &mut W,
+) -> AnyaResult<SnapshotManifest> {
+    let block_hash = utxo
+        .best_block()
+        .await
+        .ok_or_else(|| AnyaError::invalid_input("UTXO set has no best block"))?;
+    let coins = utxo.coins().await?;
+    let mut hasher = SetHasher::new();
+    for (outpoint, coin) in &coins {
+        hasher.add(outpoint, coin)?;
+    }
+    let (utxo_hash, count) = hasher.finish();
+    let params = SnapshotParams {
+        height,
+        block_hash,
+        utxo_hash,
+        coins: count,
+    };
+    let manifest = SnapshotManifest::sign(network, params, keys)?;
+    let mut line = serde_json::to_vec(&manifest)?;
+    line.push(b'\n');
+    out.write_all(&line).await?;
+    for (outpoint, coin) in coins {
+        let mut line = serde_json::to_vec(&SnapshotCoin { outpoint, coin })?;
+        line.push(b'\n');
+        out.write_all(&line).await?;
+    }
+    out.flush().await?;
+    Ok(manifest)
+}
+
+/// Progress of a loaded snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotStatus {
+    /// Historical blocks are still being validated
+    Backfilling {
+        /// Next height to download
+        next_height: u32,
+    },
+    /// The chain up to the snapshot block produced the snapshot's set
+    Validated,
+    /// The chain up to the snapshot block produced a different set
+    Invalid,
+}
+
+/// Persisted record of the snapshot the node started from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotState {
+    /// The loaded snapshot
+    pub params: SnapshotParams,
+    /// Background validation progress
+    pub status: SnapshotStatus,
+}
+
+async fn load_state(
+    storage: &dyn StorageBackend,
+    ns: &Namespace,
+) -> AnyaResult<Option<SnapshotState>> {
+    storage
+        .get(ns, STATE_KEY)
+        .await?
+        .map(|bytes| serde_json::from_slice(&bytes))
+        .transpose()
+        .map_err(Into::into)
+}
+
+async fn save_state(
+    storage: &dyn StorageBackend,
+    ns: &Namespace,
+    state: &SnapshotState,
+) -> AnyaResult<()> {
+    storage
+        .put(ns, STATE_KEY, &serde_json::to_vec(state)?)
+        .await
+}
+
+/// Verifies and loads snapshots into an empty UTXO set
+pub struct SnapshotLoader {
+    network: Network,
+    publishers: Vec<XOnlyPublicKey>,
+    params: Vec<SnapshotParams>,
+}
+
+impl SnapshotLoader {
+    /// Loader accepting snapshots signed by `publishers` that match
+    /// [`assumeutxo_params`] of `network`
+    pub fn new(network: Network, publishers: Vec<XOnlyPublicKey>) -> Self {
+        Self {
+            network,
+            publishers,
+            params: assumeutxo_params(network),
+        }
+    }
+
+    /// Also accept `params`, e.g. on regtest or for an operator-reviewed
+    /// snapshot newer than this release. Networks without compiled
+    /// parameters other than regtest still refuse every snapshot.
+    #[must_use]
+    pub fn with_params(mut self, params: impl IntoIterator<Item = SnapshotParams>) -> Self {
+        self.params.extend(params);
+        self
+    }
+
+    /// Check a manifest before any coin is read
+    pub fn check(&self, manifest: &SnapshotManifest) -> AnyaResult<()> {
+        if self.network != Network::Regtest && assumeutxo_params(self.network).is_empty() {
+            return Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                format!("no UTXO snapshots are reviewed for {} yet", self.network),
+            ));
+        }
+        if manifest.network != self.network {
+            return Err(AnyaError::invalid_input(format!(
+                "snapshot is for {}, not {}",
+                manifest.network, self.network
+            )));
+        }
+        let publisher = manifest.verify()?;
+        if !self.publishers.contains(&publisher) {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("snapshot publisher {} is not trusted", publisher),
+            ));
+        }
+        if !self.params.contains(&manifest.params) {
+            return Err(AnyaError::invalid_input(format!(
+                "no assumeutxo parameters for a snapshot at height {} with hash {}",
+                manifest.params.height, manifest.params.utxo_hash
+            )));
+        }
+        Ok(())
+    }
+
+    /// Load the snapshot read from `reader` into `utxo`, which must be
+    /// empty, and record it for [`Backfill`] in `storage`.
+    ///
+    /// Fails with [`ErrorCode::InvalidInput`] for an unknown or malformed
+    /// snapshot, [`ErrorCode::PermissionDenied`] for an untrusted
+    /// publisher, [`ErrorCode::Unavailable`] on a network without reviewed
+    /// snapshots and [`ErrorCode::Conflict`] when `utxo` is not empty. Coins
+    /// left in `utxo` or the staging namespace by an interrupted load are
+    /// removed first.
+    pub async fn load<R: AsyncBufRead + Unpin>(
+        &self,
+        storage: &dyn StorageBackend,
+        utxo: &UtxoCache,
+        reader: R,
+    ) -> AnyaResult<SnapshotManifest> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        if utxo.best_block().await.is_some() || load_state(storage, &ns).await?.is_some() {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                "a snapshot can only be loaded into an empty UTXO set",
+            ));
+        }
+        let mut lines = reader.lines();
+        let first = lines
+            .next_line()
+            .await?
+            .ok_or_else(|| AnyaError::invalid_input("empty snapshot"))?;
+        let manifest: SnapshotManifest = serde_json::from_str(&first)?;
+        self.check(&manifest)?;
+
+        // Without a best block, any coins are left from an interrupted load
+        let staging = Namespace::new(STAGING_NAMESPACE)?;
+        storage.ensure_namespace(&staging).await?;
+        clear_staging(storage, &staging).await?;
+        utxo.clear().await?;
+
+        let result = Self::stage(&mut lines, storage, &staging, &manifest.params).await;
+        if let Err(e) = result {
+            warn!(error = %e, "discarding rejected UTXO snapshot");
+            clear_staging(storage, &staging).await?;
+            return Err(e);
+        }
+        Self::commit(storage, &staging, utxo).await?;
+        utxo.finish_import(manifest.params.block_hash).await?;
+        save_state(
+            storage,
+            &ns,
+            &SnapshotState {
+                params: manifest.params.clone(),
+                status: SnapshotStatus::Backfilling { next_height: 0 },
+            },
+        )
+        .await?;
+        info!(
+            height = manifest.params.height,
+            coins = manifest.params.coins,
+            "loaded UTXO snapshot"
+        );
+        Ok(manifest)
+    }
+
+    /// Write the coins to `staging` and check them against `params`
+    async fn stage<R: AsyncBufRead + Unpin>(
+        lines: &mut tokio::io::Lines<R>,
+        storage: &dyn StorageBackend,
+        staging: &Namespace,
+        params: &SnapshotParams,
+    ) -> AnyaResult<()> {
+        let mut hasher = SetHasher::new();
+        while let Some(line) = lines.next_line().await? {
+            let SnapshotCoin { outpoint, coin } = serde_json::from_str(&line)?;
+            hasher.add(&outpoint, &coin)?;
+            storage
+                .put(
+                    staging,
+                    &staging_key(&outpoint),
+                    &serde_json::to_vec(&coin)?,
+                )
+                .await?;
+        }
+        let (hash, coins) = hasher.finish();
+        if (hash, coins) != (params.utxo_hash, params.coins) {
+            return Err(AnyaError::invalid_input(format!(
+                "snapshot coins hash to {} ({} coins), manifest says {} ({} coins)",
+                hash, coins, params.utxo_hash, params.coins
+            )));
+        }
+        Ok(())
+    }
+
+    /// Move the verified coins from `staging` into `utxo`
+    async fn commit(
+        storage: &dyn StorageBackend,
+        staging: &Namespace,
+        utxo: &UtxoCache,
+    ) -> AnyaResult<()> {
+        let staged = storage.scan_prefix(staging, STAGING_PREFIX).await?;
+        for chunk in staged.chunks(IMPORT_BATCH) {
+            let batch = chunk
+                .iter()
+                .map(|(key, value)| {
+                    let outpoint = OutPoint::from_str(&key[STAGING_PREFIX.len()..])
+                        .map_err(|e| AnyaError::invalid_input(e.to_string()))?;
+                    Ok((outpoint, serde_json::from_slice(value)?))
+                })
+                .collect::<AnyaResult<Vec<(OutPoint, Coin)>>>()?;
+            utxo.import_coins(&batch).await?;
+        }
+        clear_staging(storage, staging).await
+    }
+}
+
+fn staging_key(outpoint: &OutPoint) -> String {
+    format!("{}{}", STAGING_PREFIX, outpoint)
+}
+
+async fn clear_staging(storage: &dyn StorageBackend, staging: &Namespace) -> AnyaResult<()> {
+    for (key, _) in storage.scan_prefix(staging, STAGING_PREFIX).await? {
+        storage.delete(staging, &key).await?;
+    }
+    Ok(())
+}
+
+/// Settings of [`Backfill`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackfillConfig {
+    /// Blocks connected per step
+    pub blocks_per_step: u32,
+    /// Pause between steps
+    pub interval: Duration,
+    /// Cache settings of the background chainstate
+    pub cache: UtxoCacheConfig,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            blocks_per_step: 100,
+            interval: Duration::from_secs(1),
+            cache: UtxoCacheConfig::default(),
+        }
+    }
+}
+
+/// Background download and validation of the blocks below a snapshot
+pub struct Backfill {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    provider: Arc<dyn ChainDataProvider>,
+    config: BackfillConfig,
+    chainstate: UtxoCache,
+    index: Option<Arc<ChainIndex>>,
+    lock: Mutex<()>,
+}
+
+impl Backfill {
+    /// Backfill from `provider` the snapshot recorded in `storage`
+    pub async fn open(
+        storage: Arc<dyn StorageBackend>,
+        provider: Arc<dyn ChainDataProvider>,
+        config: BackfillConfig,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let chainstate =
+            UtxoCache::open_in(Arc::clone(&storage), BACKFILL_NAMESPACE, config.cache).await?;
+        Ok(Self {
+            storage,
+            ns,
+            provider,
+            config,
+            chainstate,
+            index: None,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Also connect backfilled blocks into `index`
+    #[must_use]
+    pub fn with_index(mut self, index: Arc<ChainIndex>) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Recorded snapshot and progress, if a snapshot was loaded
+    pub async fn state(&self) -> AnyaResult<Option<SnapshotState>> {
+        load_state(self.storage.as_ref(), &self.ns).await
+    }
+
+    /// Connect the next blocks below the snapshot and, on reaching it,
+    /// compare the resulting set with the snapshot
+    pub async fn step(&self) -> AnyaResult<Option<SnapshotStatus>> {
+        let _guard = self.lock.lock().await;
+        let Some(mut state) = self.state().await? else {
+            return Ok(None);
+        };
+        let SnapshotStatus::Backfilling { mut next_height } = state.status else {
+            return Ok(Some(state.status));
+        };
+        let end = next_height
+            .saturating_add(self.config.blocks_per_step)
+            .min(state.params.height.saturating_add(1));
+        while next_height < end {
+            let block = match self.provider.block_hash(next_height).await? {
+                Some(hash) => self.provider.block(&hash).await?,
+                None => None,
+            };
+            let Some(block) = block else {
+                // The provider has not caught up yet; retry next step
+                break;
+            };
+            self.chainstate.connect_block(&block, next_height).await?;
+            if let Some(index) = &self.index {
+                index.connect_block(&block).await?;
+            }
+            next_height += 1;
+        }
+        state.status = if next_height <= state.params.height {
+            SnapshotStatus::Backfilling { next_height }
+        } else {
+            self.verdict(&state.params).await?
+        };
+        save_state(self.storage.as_ref(), &self.ns, &state).await?;
+        metrics::gauge!("anya_snapshot_backfill_height", f64::from(next_height));
+        Ok(Some(state.status))
+    }
+
+    async fn verdict(&self, params: &SnapshotParams) -> AnyaResult<SnapshotStatus> {
+        let best = self.chainstate.best_block().await;
+        let (hash, coins) = utxo_set_hash(&self.chainstate).await?;
+        if best == Some(params.block_hash) && (hash, coins) == (params.utxo_hash, params.coins) {
+            info!(height = params.height, "UTXO snapshot validated");
+            return Ok(SnapshotStatus::Validated);
+        }
+        error!(
+            height = params.height,
+            expected = %params.utxo_hash,
+            actual = %hash,
+            "UTXO snapshot does not match the chain"
+        );
+        metrics::increment_counter!("anya_snapshot_invalid_total");
+        Ok(SnapshotStatus::Invalid)
+    }
+
+    /// Step until the snapshot is validated or rejected, or `token` is
+    /// cancelled
+    pub async fn run(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        let interval = self.config.interval;
+        let done = token.child_token();
+        run_loop(done.clone(), interval, || {
+            let backfill = Arc::clone(&self);
+            let done = done.clone();
+            async move {
+                match backfill.step().await {
+                    Ok(Some(SnapshotStatus::Backfilling { .. })) => {}
+                    Ok(_) => done.cancel(),
+                    Err(e) => warn!(error = %e, "snapshot backfill step failed"),
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::index::tests::indexed_chain;
+    use crate::storage::memory::MemoryBackend;
+
+    #[tokio::test]
+    async fn test_snapshot_load_and_backfill() {
+        // A node that validated the chain publishes a snapshot of its set
+        let (source, _, _) = indexed_chain().await;
+        let source = Arc::new(source);
+        let publisher = Arc::new(MemoryBackend::new());
+        let full = UtxoCache::open(publisher, UtxoCacheConfig::default())
+            .await
+            .unwrap();
+        for height in 0..=1 {
+            let hash = source.block_hash(height).await.unwrap().unwrap();
+            let block = ChainIndex::block(&source, &hash).await.unwrap().unwrap();
+            full.connect_block(&block, height).await.unwrap();
+        }
+        let keys = KeyPair::from_seckey_slice(&Secp256k1::new(), &[7; 32]).unwrap();
+        let mut snapshot = Vec::new();
+        let manifest = write_snapshot(&full, Network::Regtest, 1, &keys, &mut snapshot)
+            .await
+            .unwrap();
+        assert_eq!(manifest.params.coins, 2);
+
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let utxo = UtxoCache::open(Arc::clone(&storage), UtxoCacheConfig::default())
+            .await
+            .unwrap();
+        let trusted = keys.x_only_public_key().0;
+        let loader = SnapshotLoader::new(Network::Regtest, vec![trusted]);
+        // Without matching parameters nothing is read
+        let err = loader
+            .load(storage.as_ref(), &utxo, snapshot.as_slice())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        let loader = loader.with_params([manifest.params.clone()]);
+
+        // Coins that differ from the manifest are refused and removed
+        let text = String::from_utf8(snapshot.clone()).unwrap();
+        let tampered = text.replacen("40000", "40001", 1);
+        assert_ne!(tampered, text);
+        let err = loader
+            .load(storage.as_ref(), &utxo, tampered.as_bytes())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        assert!(utxo.coins().await.unwrap().is_empty());
+        let staging = Namespace::new(STAGING_NAMESPACE).unwrap();
+        assert!(storage
+            .scan_prefix(&staging, STAGING_PREFIX)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Public networks have no reviewed snapshots, so none is accepted
+        let mainnet = SnapshotLoader::new(Network::Bitcoin, vec![trusted])
+            .with_params([manifest.params.clone()]);
+        let err = mainnet
+            .load(storage.as_ref(), &utxo, snapshot.as_slice())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unavailable);
+
+        loader
+            .load(storage.as_ref(), &utxo, snapshot.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(utxo.best_block().await, Some(manifest.params.block_hash));
+        assert_eq!(
+            utxo_set_hash(&utxo).await.unwrap().0,
+            manifest.params.utxo_hash
+        );
+
+        // History arrives in the background and confirms the snapshot
+        let index = Arc::new(ChainIndex::open(Arc::clone(&storage)).await.unwrap());
+        let backfill = Backfill::open(
+            Arc::clone(&storage),
+            source,
+            BackfillConfig {
+                blocks_per_step: 1,
+                ..BackfillConfig::default()
+            },
+        )
+        .await
+        .unwrap()
+        .with_index(Arc::clone(&index));
+        assert_eq!(
+            backfill.step().await.unwrap(),
+            Some(SnapshotStatus::Backfilling { next_height: 1 })
+        );
+        assert_eq!(
+            backfill.step().await.unwrap(),
+            Some(SnapshotStatus::Validated)
+        );
+        assert_eq!(index.tip().await.unwrap().unwrap().height, 1);
+    }
+}
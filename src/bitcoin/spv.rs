@@ -0,0 +1,278 @@
+//! Simplified payment verification
+//!
+//! A [`HeaderChain`] starts from a trusted checkpoint and accepts headers
+//! that link to its tip and carry valid proof of work for their own target,
+//! which must not exceed the network's proof-of-work limit. Difficulty
+//! retargeting is not recomputed, so the checkpoint should be recent and
+//! the headers come from more than one source.
+//!
+//! Transaction inclusion is proven with a BIP-37 merkle block: the partial
+//! merkle tree must hash to the header's merkle root and the header must be
+//! on the verified chain. Nothing here touches storage or the network, so
+//! the module also builds for browsers through the `wasm` bindings.
+
+use ::bitcoin::block::Header;
+use ::bitcoin::merkle_tree::MerkleBlock;
+use ::bitcoin::pow::{CompactTarget, Target, Work};
+use ::bitcoin::{BlockHash, Network, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Where a transaction was proven to be included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxInclusion {
+    /// Proven transaction
+    pub txid: Txid,
+    /// Block committing to it
+    pub block_hash: BlockHash,
+    /// Height of that block
+    pub height: u32,
+    /// Position of the transaction in the block
+    pub index: u32,
+    /// Confirmations relative to the verified tip
+    pub confirmations: u32,
+}
+
+/// Headers verified from a trusted checkpoint
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    network: Network,
+    base_height: u32,
+    headers: Vec<Header>,
+    work: Work,
+}
+
+impl HeaderChain {
+    /// Chain rooted at `checkpoint`, trusted to be at `height`
+    pub fn new(network: Network, checkpoint: Header, height: u32) -> Self {
+        Self {
+            network,
+            base_height: height,
+            headers: vec![checkpoint],
+            work: Work::from_be_bytes([0; 32]),
+        }
+    }
+
+    /// Network whose proof-of-work limit applies
+    pub const fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Latest verified header
+    pub fn tip(&self) -> &Header {
+        self.headers.last().expect("chain holds the checkpoint")
+    }
+
+    /// Height of the latest verified header
+    pub fn height(&self) -> u32 {
+        self.base_height + u32::try_from(self.headers.len() - 1).unwrap_or(u32::MAX)
+    }
+
+    /// Work added on top of the checkpoint
+    pub const fn work(&self) -> Work {
+        self.work
+    }
+
+    /// Header at `height`, if verified
+    pub fn header_at(&self, height: u32) -> Option<&Header> {
+        let offset = height.checked_sub(self.base_height)?;
+        self.headers.get(usize::try_from(offset).ok()?)
+    }
+
+    /// Height of the verified header with `hash`
+    pub fn height_of(&self, hash: BlockHash) -> Option<u32> {
+        self.headers
+            .iter()
+            .rposition(|h| h.block_hash() == hash)
+            .and_then(|offset| u32::try_from(offset).ok())
+            .map(|offset| self.base_height + offset)
+    }
+
+    /// Verify `headers` in order and append them to the tip.
+    ///
+    /// Nothing is appended if any header fails.
+    pub fn extend(&mut self, headers: &[Header]) -> AnyaResult<()> {
+        let limit = pow_limit(self.network);
+        let mut prev = self.tip().block_hash();
+        let mut work = self.work;
+        for (offset, header) in headers.iter().enumerate() {
+            let height = self.height() as usize + offset + 1;
+            if header.prev_blockhash != prev {
+                return Err(AnyaError::invalid_input(format!(
+                    "header at height {} does not link to {}",
+                    height, prev
+                )));
+            }
+            let target = header.target();
+            if target > limit {
+                return Err(rejected(height, "target above the proof-of-work limit"));
+            }
+            prev = header
+                .validate_pow(target)
+                .map_err(|_| rejected(height, "insufficient proof of work"))?;
+            work = work + header.work();
+        }
+        self.headers.extend_from_slice(headers);
+        self.work = work;
+        Ok(())
+    }
+
+    /// Verify that `proof` commits to `txid` in a block of this chain
+    pub fn verify_inclusion(&self, proof: &MerkleBlock, txid: Txid) -> AnyaResult<TxInclusion> {
+        let index = verify_merkle_proof(proof, txid)?;
+        let block_hash = proof.header.block_hash();
+        let height = self.height_of(block_hash).ok_or_else(|| {
+            AnyaError::not_found(format!("block {} is not on the verified chain", block_hash))
+        })?;
+        Ok(TxInclusion {
+            txid,
+            block_hash,
+            height,
+            index,
+            confirmations: self.height() - height + 1,
+        })
+    }
+}
+
+/// Position of `txid` in the block of `proof`, if the partial merkle tree
+/// matches the header and includes it
+pub fn verify_merkle_proof(proof: &MerkleBlock, txid: Txid) -> AnyaResult<u32> {
+    let mut matches = Vec::new();
+    let mut indexes = Vec::new();
+    proof
+        .extract_matches(&mut matches, &mut indexes)
+        .map_err(|e| AnyaError::invalid_input(format!("invalid merkle proof: {:?}", e)))?;
+    matches
+        .iter()
+        .position(|m| *m == txid)
+        .map(|i| indexes[i])
+        .ok_or_else(|| AnyaError::not_found(format!("merkle proof does not include {}", txid)))
+}
+
+/// Easiest target allowed on `network`
+pub(crate) fn pow_limit(network: Network) -> Target {
+    let bits = match network {
+        Network::Signet => 0x1e03_77ae,
+        Network::Regtest => 0x207f_ffff,
+        _ => 0x1d00_ffff,
+    };
+    Target::from_compact(CompactTarget::from_consensus(bits))
+}
+
+fn rejected(height: usize, reason: &str) -> AnyaError {
+    AnyaError::new(
+        ErrorCode::BitcoinFailure,
+        format!("header at height {} rejected: {}", height, reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::absolute::LockTime;
+    use ::bitcoin::block::Version;
+    use ::bitcoin::hash_types::TxMerkleNode;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{Block, OutPoint, ScriptBuf, Sequence, Transaction, TxIn};
+    use ::bitcoin::{TxOut, Witness};
+
+    const REGTEST_BITS: u32 = 0x207f_ffff;
+
+    fn tx(nonce: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), nonce),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn mine(prev: BlockHash, txdata: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: prev,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_700_000_000,
+                bits: CompactTarget::from_consensus(REGTEST_BITS),
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+        block
+    }
+
+    fn chain_of(len: u32) -> (Header, Vec<Block>) {
+        let checkpoint = mine(BlockHash::all_zeros(), vec![tx(0)]).header;
+        let mut prev = checkpoint.block_hash();
+        let blocks: Vec<_> = (1..=len)
+            .map(|n| {
+                let block = mine(prev, vec![tx(n * 10), tx(n * 10 + 1), tx(n * 10 + 2)]);
+                prev = block.block_hash();
+                block
+            })
+            .collect();
+        (checkpoint, blocks)
+    }
+
+    #[test]
+    fn test_headers_and_inclusion_proof() {
+        let (checkpoint, blocks) = chain_of(4);
+        let mut chain = HeaderChain::new(Network::Regtest, checkpoint, 100);
+        let headers: Vec<_> = blocks.iter().map(|b| b.header).collect();
+        chain.extend(&headers).unwrap();
+        assert_eq!(chain.height(), 104);
+        assert_eq!(chain.header_at(102), Some(&headers[1]));
+
+        let target = blocks[1].txdata[2].txid();
+        let proof = MerkleBlock::from_block_with_predicate(&blocks[1], |t| *t == target);
+        let inclusion = chain.verify_inclusion(&proof, target).unwrap();
+        assert_eq!(inclusion.height, 102);
+        assert_eq!(inclusion.index, 2);
+        assert_eq!(inclusion.confirmations, 3);
+
+        // A proof for another transaction does not prove this one
+        let other = blocks[1].txdata[0].txid();
+        let proof = MerkleBlock::from_block_with_predicate(&blocks[1], |t| *t == other);
+        let err = chain.verify_inclusion(&proof, target).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+    }
+
+    #[test]
+    fn test_rejects_broken_or_weak_headers() {
+        let (checkpoint, blocks) = chain_of(3);
+        let mut headers: Vec<_> = blocks.iter().map(|b| b.header).collect();
+
+        let mut chain = HeaderChain::new(Network::Regtest, checkpoint, 0);
+        let err = chain.extend(&headers[1..]).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        assert_eq!(chain.height(), 0);
+
+        // Regtest difficulty is far above mainnet's proof-of-work limit
+        let mut mainnet = HeaderChain::new(Network::Bitcoin, checkpoint, 0);
+        assert_eq!(
+            mainnet.extend(&headers).unwrap_err().code(),
+            ErrorCode::BitcoinFailure
+        );
+
+        while headers[2].validate_pow(headers[2].target()).is_ok() {
+            headers[2].nonce += 1;
+        }
+        let err = chain.extend(&headers).unwrap_err();
+        assert!(err.to_string().contains("insufficient proof of work"));
+        assert_eq!(chain.height(), 0);
+    }
+}
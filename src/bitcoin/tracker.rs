@@ -0,0 +1,612 @@
+//! Wallet transaction tracking
+//!
+//! The [`TxTracker`] polls a [`ChainSource`] for transactions touching the
+//! wallet's scripts, plus any transaction handed to it after broadcast, and
+//! follows each one until it is buried. Lifecycle changes are published as
+//! [`TxEvent`]s on a broadcast channel and delivered to registered
+//! [`TxEventSink`]s such as mobile push notifications or HTTP webhooks.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::{OutPoint, Script, ScriptBuf, Transaction, Txid};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::accounts::{Account, KeyChain};
+use crate::lifecycle::run_loop;
+use crate::storage::{Namespace, StorageBackend};
+use crate::AnyaResult;
+
+const NAMESPACE: &str = "tx_tracker";
+const TX_PREFIX: &str = "tx/";
+
+/// Where a transaction currently is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum TxStatus {
+    /// Neither in the mempool nor in the chain
+    Unknown,
+    /// Waiting in the mempool
+    Mempool,
+    /// Included in a block
+    Confirmed {
+        /// Block height
+        height: u32,
+    },
+}
+
+/// Chain backend queried by the tracker (a node, Electrum, or Esplora)
+#[async_trait]
+pub trait ChainSource: Send + Sync {
+    /// Height of the best block
+    async fn tip_height(&self) -> AnyaResult<u32>;
+    /// Transactions paying to or spending from `script`, mempool included
+    async fn script_history(&self, script: &Script) -> AnyaResult<Vec<Txid>>;
+    /// Fetch a transaction
+    async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>>;
+    /// Current status of a transaction
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus>;
+    /// Transaction spending `outpoint`, if any
+    async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>>;
+}
+
+/// Tracker settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackerConfig {
+    /// Confirmation counts that produce an event; tracking ends at the last
+    pub milestones: Vec<u32>,
+    /// How often the chain is polled
+    pub poll_interval: Duration,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            milestones: vec![1, 3, 6],
+            poll_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Output of a tracked transaction paying a watched script
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceivedOutput {
+    /// Output index
+    pub vout: u32,
+    /// Receiving script
+    pub script_pubkey: ScriptBuf,
+    /// Amount in satoshis
+    pub amount_sat: u64,
+}
+
+/// Lifecycle change of a tracked transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum TxEvent {
+    /// Seen for the first time, in the mempool or already mined
+    FirstSeen {
+        /// Transaction id
+        txid: Txid,
+        /// Outputs paying the wallet
+        received: Vec<ReceivedOutput>,
+        /// Confirmations when first seen
+        confirmations: u32,
+    },
+    /// Reached a configured confirmation milestone
+    Confirmed {
+        /// Transaction id
+        txid: Txid,
+        /// Outputs paying the wallet
+        received: Vec<ReceivedOutput>,
+        /// Block height
+        height: u32,
+        /// Milestone reached
+        confirmations: u32,
+    },
+    /// A conflicting transaction took its place
+    Replaced {
+        /// Transaction id
+        txid: Txid,
+        /// Transaction spending the same inputs
+        replacement: Txid,
+    },
+    /// Dropped from the mempool without a replacement
+    Evicted {
+        /// Transaction id
+        txid: Txid,
+    },
+    /// Its block was reorganized out and it is back in the mempool
+    Reorged {
+        /// Transaction id
+        txid: Txid,
+    },
+}
+
+/// Consumer of tracker events
+#[async_trait]
+pub trait TxEventSink: Send + Sync {
+    /// Handle one event; failures are logged and do not stop the tracker
+    async fn handle(&self, event: &TxEvent) -> AnyaResult<()>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Tracked {
+    tx: Transaction,
+    status: TxStatus,
+    /// Confirmations last reported
+    confirmations: u32,
+    /// Finished tracking: buried, replaced, or evicted
+    closed: bool,
+}
+
+/// Follows wallet transactions from mempool to burial
+pub struct TxTracker {
+    config: TrackerConfig,
+    source: Arc<dyn ChainSource>,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    scripts: RwLock<HashSet<ScriptBuf>>,
+    sinks: RwLock<Vec<Arc<dyn TxEventSink>>>,
+    events: broadcast::Sender<TxEvent>,
+    poll_lock: Mutex<()>,
+}
+
+impl TxTracker {
+    /// Open the tracker, resuming transactions tracked in `storage`
+    pub async fn open(
+        mut config: TrackerConfig,
+        source: Arc<dyn ChainSource>,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        config.milestones.sort_unstable();
+        config.milestones.dedup();
+        let (events, _) = broadcast::channel(256);
+        Ok(Self {
+            config,
+            source,
+            storage,
+            ns,
+            scripts: RwLock::new(HashSet::new()),
+            sinks: RwLock::new(Vec::new()),
+            events,
+            poll_lock: Mutex::new(()),
+        })
+    }
+
+    /// Watch a script for incoming and outgoing transactions
+    pub async fn watch_script(&self, script: ScriptBuf) {
+        self.scripts.write().await.insert(script);
+    }
+
+    /// Watch every address inside the account's gap-limit window
+    pub async fn watch_account(&self, account: &Account) -> AnyaResult<()> {
+        let secp = ::bitcoin::secp256k1::Secp256k1::verification_only();
+        let mut scripts = self.scripts.write().await;
+        for chain in [KeyChain::External, KeyChain::Internal] {
+            for index in account.lookahead(chain) {
+                scripts.insert(account.address(&secp, chain, index)?.script_pubkey());
+            }
+        }
+        drop(scripts);
+        Ok(())
+    }
+
+    /// Track a transaction the wallet just broadcast
+    pub async fn track(&self, tx: Transaction) -> AnyaResult<()> {
+        if self.load(&tx.txid()).await?.is_some() {
+            return Ok(());
+        }
+        self.save(&Tracked {
+            tx,
+            status: TxStatus::Unknown,
+            confirmations: 0,
+            closed: false,
+        })
+        .await
+    }
+
+    /// Deliver future events to `sink`
+    pub async fn add_sink(&self, sink: Arc<dyn TxEventSink>) {
+        self.sinks.write().await.push(sink);
+    }
+
+    /// Subscribe to tracker events
+    pub fn subscribe(&self) -> broadcast::Receiver<TxEvent> {
+        self.events.subscribe()
+    }
+
+    /// Discover new wallet transactions and update tracked ones
+    pub async fn poll(&self) -> AnyaResult<Vec<TxEvent>> {
+        let _guard = self.poll_lock.lock().await;
+        let tip = self.source.tip_height().await?;
+        let scripts: Vec<ScriptBuf> = self.scripts.read().await.iter().cloned().collect();
+        for script in &scripts {
+            for txid in self.source.script_history(script).await? {
+                if self.load(&txid).await?.is_some() {
+                    continue;
+                }
+                if let Some(tx) = self.source.transaction(&txid).await? {
+                    self.track(tx).await?;
+                }
+            }
+        }
+
+        let mut events = Vec::new();
+        for (_, bytes) in self.storage.scan_prefix(&self.ns, TX_PREFIX).await? {
+            let mut tracked: Tracked = serde_json::from_slice(&bytes)?;
+            if tracked.closed {
+                continue;
+            }
+            self.update(&mut tracked, tip, &mut events).await?;
+        }
+
+        let sinks = self.sinks.read().await.clone();
+        for event in &events {
+            for sink in &sinks {
+                if let Err(e) = sink.handle(event).await {
+                    warn!(error = %e, "transaction event sink failed");
+                }
+            }
+            // No subscribers is fine
+            let _ = self.events.send(event.clone());
+        }
+        Ok(events)
+    }
+
+    /// Poll every `poll_interval` until `token` is cancelled
+    pub async fn run(&self, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.poll_interval, || async {
+            self.poll().await.map(drop)
+        })
+        .await
+    }
+
+    async fn update(
+        &self,
+        tracked: &mut Tracked,
+        tip: u32,
+        events: &mut Vec<TxEvent>,
+    ) -> AnyaResult<()> {
+        let txid = tracked.tx.txid();
+        let status = self.source.status(&txid).await?;
+        let before = events.len();
+        let final_milestone = self.config.milestones.last().copied().unwrap_or(1);
+        match (tracked.status, status) {
+            (TxStatus::Unknown, TxStatus::Unknown) => {}
+            (TxStatus::Unknown, _) => {
+                let confirmations = confirmations(status, tip);
+                events.push(TxEvent::FirstSeen {
+                    txid,
+                    received: self.received(&tracked.tx).await,
+                    confirmations,
+                });
+                tracked.confirmations = confirmations;
+                tracked.closed = confirmations >= final_milestone;
+            }
+            (_, TxStatus::Unknown) => {
+                let mut replacement = None;
+                for input in &tracked.tx.input {
+                    match self.source.spender(&input.previous_output).await? {
+                        Some(spender) if spender != txid => {
+                            replacement = Some(spender);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                events.push(
+                    replacement.map_or(TxEvent::Evicted { txid }, |replacement| {
+                        TxEvent::Replaced { txid, replacement }
+                    }),
+                );
+                tracked.closed = true;
+            }
+            (TxStatus::Confirmed { .. }, TxStatus::Mempool) => {
+                events.push(TxEvent::Reorged { txid });
+                tracked.confirmations = 0;
+            }
+            (_, TxStatus::Confirmed { height }) => {
+                let now = confirmations(status, tip);
+                let reached: Vec<u32> = self
+                    .config
+                    .milestones
+                    .iter()
+                    .copied()
+                    .filter(|m| *m > tracked.confirmations && *m <= now)
+                    .collect();
+                if !reached.is_empty() {
+                    let received = self.received(&tracked.tx).await;
+                    for milestone in reached {
+                        events.push(TxEvent::Confirmed {
+                            txid,
+                            received: received.clone(),
+                            height,
+                            confirmations: milestone,
+                        });
+                    }
+                    tracked.confirmations = now;
+                    tracked.closed = now >= final_milestone;
+                }
+            }
+            (TxStatus::Mempool, TxStatus::Mempool) => {}
+        }
+        if tracked.status != status || events.len() > before {
+            tracked.status = status;
+            self.save(tracked).await?;
+        }
+        Ok(())
+    }
+
+    async fn received(&self, tx: &Transaction) -> Vec<ReceivedOutput> {
+        let scripts = self.scripts.read().await;
+        (0u32..)
+            .zip(&tx.output)
+            .filter(|(_, output)| scripts.contains(&output.script_pubkey))
+            .map(|(vout, output)| ReceivedOutput {
+                vout,
+                script_pubkey: output.script_pubkey.clone(),
+                amount_sat: output.value,
+            })
+            .collect()
+    }
+
+    async fn load(&self, txid: &Txid) -> AnyaResult<Option<Tracked>> {
+        match self
+            .storage
+            .get(&self.ns, &format!("{}{}", TX_PREFIX, txid))
+            .await?
+        {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, tracked: &Tracked) -> AnyaResult<()> {
+        let bytes = serde_json::to_vec(tracked)?;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", TX_PREFIX, tracked.tx.txid()),
+                &bytes,
+            )
+            .await
+    }
+}
+
+const fn confirmations(status: TxStatus, tip: u32) -> u32 {
+    match status {
+        TxStatus::Confirmed { height } => tip.saturating_sub(height) + 1,
+        _ => 0,
+    }
+}
+
+/// Sink POSTing events as JSON to an HTTP endpoint.
+///
+/// With a secret configured, each request carries an
+/// `X-Anya-Signature: sha256=<hex>` HMAC of the body so receivers can
+/// authenticate it.
+#[cfg(feature = "http")]
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<ring::hmac::Key>,
+}
+
+#[cfg(feature = "http")]
+impl WebhookSink {
+    /// Sink posting to `url`, signing with `secret` when given
+    pub fn new(url: impl Into<String>, secret: Option<&[u8]>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            secret: secret.map(|s| ring::hmac::Key::new(ring::hmac::HMAC_SHA256, s)),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl TxEventSink for WebhookSink {
+    async fn handle(&self, event: &TxEvent) -> AnyaResult<()> {
+        let body = serde_json::to_vec(event)?;
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(key) = &self.secret {
+            let tag = ring::hmac::sign(key, &body);
+            let signature = format!("sha256={}", crate::utils::encoding::to_hex(tag.as_ref()));
+            request = request.header("X-Anya-Signature", signature);
+        }
+        request.body(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::absolute::LockTime;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{Sequence, TxIn, TxOut, WPubkeyHash, Witness};
+    use std::collections::HashMap;
+    use std::sync::Mutex as StdMutex;
+
+    #[derive(Default)]
+    struct MockChain {
+        tip: StdMutex<u32>,
+        txs: StdMutex<HashMap<Txid, (Transaction, TxStatus)>>,
+        spenders: StdMutex<HashMap<OutPoint, Txid>>,
+    }
+
+    impl MockChain {
+        fn set(&self, tx: &Transaction, status: TxStatus) {
+            self.txs
+                .lock()
+                .unwrap()
+                .insert(tx.txid(), (tx.clone(), status));
+            for input in &tx.input {
+                if status != TxStatus::Unknown {
+                    self.spenders
+                        .lock()
+                        .unwrap()
+                        .insert(input.previous_output, tx.txid());
+                }
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChainSource for MockChain {
+        async fn tip_height(&self) -> AnyaResult<u32> {
+            Ok(*self.tip.lock().unwrap())
+        }
+
+        async fn script_history(&self, script: &Script) -> AnyaResult<Vec<Txid>> {
+            Ok(self
+                .txs
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|(tx, status)| {
+                    *status != TxStatus::Unknown
+                        && tx
+                            .output
+                            .iter()
+                            .any(|o| o.script_pubkey.as_script() == script)
+                })
+                .map(|(tx, _)| tx.txid())
+                .collect())
+        }
+
+        async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+            Ok(self.txs.lock().unwrap().get(txid).map(|(tx, _)| tx.clone()))
+        }
+
+        async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+            Ok(self
+                .txs
+                .lock()
+                .unwrap()
+                .get(txid)
+                .map_or(TxStatus::Unknown, |(_, status)| *status))
+        }
+
+        async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+            Ok(self.spenders.lock().unwrap().get(outpoint).copied())
+        }
+    }
+
+    fn payment(to: &ScriptBuf, value: u64, input_vout: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), input_vout),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: to.clone(),
+            }],
+        }
+    }
+
+    async fn tracker(chain: Arc<MockChain>) -> TxTracker {
+        let config = TrackerConfig {
+            milestones: vec![6, 1],
+            ..TrackerConfig::default()
+        };
+        TxTracker::open(config, chain, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_incoming_payment_lifecycle() {
+        let chain = Arc::new(MockChain::default());
+        *chain.tip.lock().unwrap() = 100;
+        let tracker = tracker(Arc::clone(&chain)).await;
+        let ours = ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::from_byte_array([1; 20]));
+        tracker.watch_script(ours.clone()).await;
+        let mut events = tracker.subscribe();
+
+        let tx = payment(&ours, 25_000, 0);
+        chain.set(&tx, TxStatus::Mempool);
+        let first = tracker.poll().await.unwrap();
+        assert!(matches!(
+            &first[..],
+            [TxEvent::FirstSeen { received, confirmations: 0, .. }] if received[0].amount_sat == 25_000
+        ));
+        assert_eq!(events.recv().await.unwrap(), first[0]);
+        assert!(tracker.poll().await.unwrap().is_empty());
+
+        chain.set(&tx, TxStatus::Confirmed { height: 101 });
+        *chain.tip.lock().unwrap() = 101;
+        let confirmed = tracker.poll().await.unwrap();
+        assert!(matches!(
+            &confirmed[..],
+            [TxEvent::Confirmed {
+                confirmations: 1,
+                ..
+            }]
+        ));
+
+        chain.set(&tx, TxStatus::Mempool);
+        assert!(matches!(
+            &tracker.poll().await.unwrap()[..],
+            [TxEvent::Reorged { .. }]
+        ));
+
+        chain.set(&tx, TxStatus::Confirmed { height: 102 });
+        *chain.tip.lock().unwrap() = 110;
+        let buried = tracker.poll().await.unwrap();
+        assert_eq!(buried.len(), 2);
+        *chain.tip.lock().unwrap() = 120;
+        assert!(tracker.poll().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replacement_and_eviction() {
+        let chain = Arc::new(MockChain::default());
+        let tracker = tracker(Arc::clone(&chain)).await;
+        let elsewhere = ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::from_byte_array([2; 20]));
+
+        let original = payment(&elsewhere, 10_000, 1);
+        let dropped = payment(&elsewhere, 10_000, 2);
+        tracker.track(original.clone()).await.unwrap();
+        tracker.track(dropped.clone()).await.unwrap();
+        assert!(tracker.poll().await.unwrap().is_empty());
+        chain.set(&original, TxStatus::Mempool);
+        chain.set(&dropped, TxStatus::Mempool);
+        assert_eq!(tracker.poll().await.unwrap().len(), 2);
+
+        let replacement = payment(&elsewhere, 9_000, 1);
+        chain.set(&original, TxStatus::Unknown);
+        chain.set(&dropped, TxStatus::Unknown);
+        chain
+            .spenders
+            .lock()
+            .unwrap()
+            .remove(&dropped.input[0].previous_output);
+        chain.set(&replacement, TxStatus::Mempool);
+        let events = tracker.poll().await.unwrap();
+        assert!(events.contains(&TxEvent::Replaced {
+            txid: original.txid(),
+            replacement: replacement.txid(),
+        }));
+        assert!(events.contains(&TxEvent::Evicted {
+            txid: dropped.txid()
+        }));
+    }
+}
@@ -0,0 +1,607 @@
+//! Unspent output set with an in-memory write-back cache
+//!
+//! [`UtxoCache`] is the chainstate consulted while validating blocks: the
+//! coins every connected block left unspent. Lookups, spends and new coins
+//! are served from memory; changes are only written to storage at flush
+//! points, either every [`UtxoCacheConfig::flush_interval`] blocks or when
+//! the cache outgrows [`UtxoCacheConfig::memory_budget`].
+//!
+//! As in Bitcoin Core, a coin created since the last flush is marked fresh:
+//! if it is spent before the next flush it never reaches storage at all,
+//! which saves most of the writes during initial block download. The best
+//! block is written after the coins it covers, so a flush interrupted by a
+//! crash is repaired by connecting the blocks after the recorded best block
+//! again.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use ::bitcoin::{Block, BlockHash, OutPoint, TxOut};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "utxo_set";
+const COIN_PREFIX: &str = "coin/";
+const BEST_KEY: &str = "best";
+
+/// Estimated memory of a cache entry beyond its script
+const ENTRY_OVERHEAD: usize = 96;
+
+/// Configuration of a [`UtxoCache`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoCacheConfig {
+    /// Bytes the cache may use before it is flushed and emptied
+    pub memory_budget: usize,
+    /// Flush every this many connected blocks; 0 flushes on the memory
+    /// budget only
+    pub flush_interval: u32,
+}
+
+impl Default for UtxoCacheConfig {
+    fn default() -> Self {
+        Self {
+            memory_budget: 450 * 1024 * 1024,
+            flush_interval: 0,
+        }
+    }
+}
+
+/// An unspent output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Coin {
+    /// The output
+    pub output: TxOut,
+    /// Height of the block that created it
+    pub height: u32,
+    /// Whether it was created by a coinbase
+    pub coinbase: bool,
+}
+
+/// Cache counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoCacheStats {
+    /// Lookups served from memory
+    pub hits: u64,
+    /// Lookups that went to storage
+    pub misses: u64,
+    /// Entries in memory
+    pub entries: u64,
+    /// Entries changed since the last flush
+    pub dirty: u64,
+    /// Estimated memory in use
+    pub memory_bytes: u64,
+    /// Flushes performed
+    pub flushes: u64,
+}
+
+impl UtxoCacheStats {
+    /// Fraction of lookups served from memory
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+struct Entry {
+    /// `None` once spent
+    coin: Option<Coin>,
+    /// Differs from storage
+    dirty: bool,
+    /// Not in storage, so a spend can simply forget it
+    fresh: bool,
+}
+
+impl Entry {
+    fn usage(&self) -> usize {
+        ENTRY_OVERHEAD
+            + self
+                .coin
+                .as_ref()
+                .map_or(0, |c| c.output.script_pubkey.len())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<OutPoint, Entry>,
+    usage: usize,
+    best: Option<BlockHash>,
+    since_flush: u32,
+}
+
+impl State {
+    fn insert(&mut self, outpoint: OutPoint, entry: Entry) {
+        self.usage += entry.usage();
+        if let Some(old) = self.entries.insert(outpoint, entry) {
+            self.usage -= old.usage();
+        }
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) {
+        if let Some(old) = self.entries.remove(outpoint) {
+            self.usage -= old.usage();
+        }
+    }
+}
+
+/// Unspent outputs of the best chain, cached in memory
+pub struct UtxoCache {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    config: UtxoCacheConfig,
+    state: Mutex<State>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    flushes: AtomicU64,
+}
+
+impl UtxoCache {
+    /// Open the set kept in `storage`
+    pub async fn open(
+        storage: Arc<dyn StorageBackend>,
+        config: UtxoCacheConfig,
+    ) -> AnyaResult<Self> {
+        Self::open_in(storage, NAMESPACE, config).await
+    }
+
+    /// Open a set kept in its own `namespace`, such as a second chainstate
+    /// validating a snapshot in the background
+    pub async fn open_in(
+        storage: Arc<dyn StorageBackend>,
+        namespace: &str,
+        config: UtxoCacheConfig,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(namespace)?;
+        storage.ensure_namespace(&ns).await?;
+        let best = storage
+            .get(&ns, BEST_KEY)
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()?;
+        Ok(Self {
+            storage,
+            ns,
+            config,
+            state: Mutex::new(State {
+                best,
+                ..State::default()
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            flushes: AtomicU64::new(0),
+        })
+    }
+
+    /// Block the set is current with
+    pub async fn best_block(&self) -> Option<BlockHash> {
+        self.state.lock().await.best
+    }
+
+    /// Unspent coin at `outpoint`
+    pub async fn get(&self, outpoint: &OutPoint) -> AnyaResult<Option<Coin>> {
+        let mut state = self.state.lock().await;
+        self.fetch(&mut state, outpoint).await
+    }
+
+    /// Apply `block` at `height`: spend its inputs and add its outputs.
+    ///
+    /// Returns the coins spent, in input order, which
+    /// [`UtxoCache::disconnect_block`] needs to undo it. Fails with
+    /// [`ErrorCode::Conflict`] if the block does not extend the best block
+    /// and with [`ErrorCode::InvalidInput`] if it spends a missing coin, in
+    /// which case the set is left unchanged.
+    pub async fn connect_block(&self, block: &Block, height: u32) -> AnyaResult<Vec<Coin>> {
+        let mut state = self.state.lock().await;
+        if state
+            .best
+            .is_some_and(|best| best != block.header.prev_blockhash)
+        {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("block {} does not extend the UTXO set", block.block_hash()),
+            ));
+        }
+        let mut spent = Vec::new();
+        let mut added = Vec::new();
+        for tx in &block.txdata {
+            let txid = tx.txid();
+            if !tx.is_coin_base() {
+                for input in &tx.input {
+                    match self.spend(&mut state, &input.previous_output).await {
+                        Ok(Some(coin)) => spent.push((input.previous_output, coin)),
+                        result => {
+                            // Put back what this block changed so far, newest
+                            // first: restore spent coins, then drop the
+                            // outputs it created, including any it spent
+                            for (outpoint, coin) in spent.into_iter().rev() {
+                                add(&mut state, outpoint, coin, false);
+                            }
+                            for outpoint in added.iter().rev() {
+                                self.spend(&mut state, outpoint).await?;
+                            }
+                            result?;
+                            return Err(AnyaError::invalid_input(format!(
+                                "{} spends missing coin {}",
+                                txid, input.previous_output
+                            )));
+                        }
+                    }
+                }
+            }
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, u32::try_from(vout).unwrap_or(u32::MAX));
+                let coin = Coin {
+                    output: output.clone(),
+                    height,
+                    coinbase: tx.is_coin_base(),
+                };
+                // A duplicate coinbase txid may still exist in storage
+                add(&mut state, outpoint, coin, !tx.is_coin_base());
+                added.push(outpoint);
+            }
+        }
+        state.best = Some(block.block_hash());
+        state.since_flush += 1;
+        self.maybe_flush(&mut state).await?;
+        drop(state);
+        Ok(spent.into_iter().map(|(_, coin)| coin).collect())
+    }
+
+    /// Undo `block`, the best block, restoring the coins in `undo` that
+    /// [`UtxoCache::connect_block`] returned for it
+    pub async fn disconnect_block(&self, block: &Block, undo: &[Coin]) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        if state.best != Some(block.block_hash()) {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("block {} is not the best block", block.block_hash()),
+            ));
+        }
+        let inputs: usize = block
+            .txdata
+            .iter()
+            .filter(|tx| !tx.is_coin_base())
+            .map(|tx| tx.input.len())
+            .sum();
+        if inputs != undo.len() {
+            return Err(AnyaError::invalid_input(format!(
+                "undo data of block {} has {} coins for {} inputs",
+                block.block_hash(),
+                undo.len(),
+                inputs
+            )));
+        }
+        // Newest transaction first, so an output spent within the block is
+        // restored by its spender and then removed with its creator
+        let mut undo = undo;
+        for tx in block.txdata.iter().rev() {
+            let txid = tx.txid();
+            for vout in 0..tx.output.len() {
+                let outpoint = OutPoint::new(txid, u32::try_from(vout).unwrap_or(u32::MAX));
+                self.spend(&mut state, &outpoint).await?;
+            }
+            if tx.is_coin_base() {
+                continue;
+            }
+            let (rest, coins) = undo.split_at(undo.len() - tx.input.len());
+            for (input, coin) in tx.input.iter().zip(coins) {
+                add(&mut state, input.previous_output, coin.clone(), false);
+            }
+            undo = rest;
+        }
+        state.best = Some(block.header.prev_blockhash);
+        state.since_flush += 1;
+        self.maybe_flush(&mut state).await?;
+        drop(state);
+        Ok(())
+    }
+
+    /// Write every change to storage
+    pub async fn flush(&self) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        self.write_back(&mut state).await
+    }
+
+    /// Every unspent coin, ordered by outpoint, after writing back
+    pub async fn coins(&self) -> AnyaResult<Vec<(OutPoint, Coin)>> {
+        let mut state = self.state.lock().await;
+        self.write_back(&mut state).await?;
+        let stored = self.storage.scan_prefix(&self.ns, COIN_PREFIX).await?;
+        drop(state);
+        let mut coins = Vec::with_capacity(stored.len());
+        for (key, value) in stored {
+            let outpoint: OutPoint = key[COIN_PREFIX.len()..].parse().map_err(|e| {
+                AnyaError::new(
+                    ErrorCode::Serialization,
+                    format!("bad coin key {}: {}", key, e),
+                )
+            })?;
+            coins.push((outpoint, serde_json::from_slice(&value)?));
+        }
+        coins.sort_unstable_by_key(|(outpoint, _)| *outpoint);
+        Ok(coins)
+    }
+
+    /// Store snapshot coins directly, bypassing the cache. Fails with
+    /// [`ErrorCode::Conflict`] once the set has a best block.
+    pub(crate) async fn import_coins(&self, coins: &[(OutPoint, Coin)]) -> AnyaResult<()> {
+        let state = self.state.lock().await;
+        if state.best.is_some() {
+            return Err(AnyaError::new(ErrorCode::Conflict, "UTXO set is not empty"));
+        }
+        for (outpoint, coin) in coins {
+            self.storage
+                .put(&self.ns, &coin_key(outpoint), &serde_json::to_vec(coin)?)
+                .await?;
+        }
+        drop(state);
+        Ok(())
+    }
+
+    /// Make `base` the best block once every snapshot coin is stored
+    pub(crate) async fn finish_import(&self, base: BlockHash) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        self.storage
+            .put(&self.ns, BEST_KEY, &serde_json::to_vec(&base)?)
+            .await?;
+        state.best = Some(base);
+        drop(state);
+        Ok(())
+    }
+
+    /// Forget every coin and the best block
+    pub(crate) async fn clear(&self) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        for (key, _) in self.storage.scan_prefix(&self.ns, COIN_PREFIX).await? {
+            self.storage.delete(&self.ns, &key).await?;
+        }
+        self.storage.delete(&self.ns, BEST_KEY).await?;
+        *state = State::default();
+        drop(state);
+        Ok(())
+    }
+
+    /// Counters and current size
+    pub async fn stats(&self) -> UtxoCacheStats {
+        let (entries, dirty, memory_bytes) = {
+            let state = self.state.lock().await;
+            (
+                state.entries.len() as u64,
+                state.entries.values().filter(|e| e.dirty).count() as u64,
+                state.usage as u64,
+            )
+        };
+        UtxoCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+            dirty,
+            memory_bytes,
+            flushes: self.flushes.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn fetch(&self, state: &mut State, outpoint: &OutPoint) -> AnyaResult<Option<Coin>> {
+        if let Some(entry) = state.entries.get(outpoint) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            metrics::increment_counter!("anya_utxo_cache_hits_total");
+            return Ok(entry.coin.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        metrics::increment_counter!("anya_utxo_cache_misses_total");
+        let Some(bytes) = self.storage.get(&self.ns, &coin_key(outpoint)).await? else {
+            return Ok(None);
+        };
+        let coin: Coin = serde_json::from_slice(&bytes)?;
+        state.insert(
+            *outpoint,
+            Entry {
+                coin: Some(coin.clone()),
+                dirty: false,
+                fresh: false,
+            },
+        );
+        Ok(Some(coin))
+    }
+
+    async fn spend(&self, state: &mut State, outpoint: &OutPoint) -> AnyaResult<Option<Coin>> {
+        let Some(coin) = self.fetch(state, outpoint).await? else {
+            return Ok(None);
+        };
+        if state.entries.get(outpoint).is_some_and(|e| e.fresh) {
+            state.remove(outpoint);
+        } else {
+            state.insert(
+                *outpoint,
+                Entry {
+                    coin: None,
+                    dirty: true,
+                    fresh: false,
+                },
+            );
+        }
+        Ok(Some(coin))
+    }
+
+    async fn maybe_flush(&self, state: &mut State) -> AnyaResult<()> {
+        let interval = self.config.flush_interval;
+        if state.usage > self.config.memory_budget {
+            self.write_back(state).await?;
+            // Over budget: start again from an empty cache
+            state.entries.clear();
+            state.usage = 0;
+        } else if interval > 0 && state.since_flush >= interval {
+            self.write_back(state).await?;
+        }
+        metrics::gauge!("anya_utxo_cache_bytes", state.usage as f64);
+        Ok(())
+    }
+
+    async fn write_back(&self, state: &mut State) -> AnyaResult<()> {
+        let mut written = 0usize;
+        for (outpoint, entry) in state.entries.iter_mut().filter(|(_, e)| e.dirty) {
+            match &entry.coin {
+                Some(coin) => {
+                    self.storage
+                        .put(&self.ns, &coin_key(outpoint), &serde_json::to_vec(coin)?)
+                        .await?
+                }
+                None => {
+                    self.storage.delete(&self.ns, &coin_key(outpoint)).await?;
+                }
+            }
+            entry.dirty = false;
+            entry.fresh = false;
+            written += 1;
+        }
+        let spent: Vec<OutPoint> = state
+            .entries
+            .iter()
+            .filter(|(_, e)| e.coin.is_none())
+            .map(|(outpoint, _)| *outpoint)
+            .collect();
+        for outpoint in &spent {
+            state.remove(outpoint);
+        }
+        if let Some(best) = state.best {
+            self.storage
+                .put(&self.ns, BEST_KEY, &serde_json::to_vec(&best)?)
+                .await?;
+        }
+        state.since_flush = 0;
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        debug!(written, "UTXO cache flushed");
+        Ok(())
+    }
+}
+
+fn add(state: &mut State, outpoint: OutPoint, coin: Coin, fresh: bool) {
+    // Replacing a spent entry that storage still holds must reach storage
+    let fresh = fresh && state.entries.get(&outpoint).map_or(true, |e| e.fresh);
+    state.insert(
+        outpoint,
+        Entry {
+            coin: Some(coin),
+            dirty: true,
+            fresh,
+        },
+    );
+}
+
+fn coin_key(outpoint: &OutPoint) -> String {
+    format!("{}{}", COIN_PREFIX, outpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::index::tests::{block, tx};
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::ScriptBuf;
+
+    #[tokio::test]
+    async fn test_write_back_and_undo() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let ns = Namespace::new(NAMESPACE).unwrap();
+        let alice = ScriptBuf::from_bytes(vec![0x51]);
+        let bob = ScriptBuf::from_bytes(vec![0x52]);
+        let cache = UtxoCache::open(storage.clone(), UtxoCacheConfig::default())
+            .await
+            .unwrap();
+        let genesis = block(BlockHash::all_zeros(), 0, &alice, vec![]);
+        let coinbase = OutPoint::new(genesis.txdata[0].txid(), 0);
+        cache.connect_block(&genesis, 0).await.unwrap();
+
+        // The payment's output is created and spent between flushes, so
+        // it never reaches storage
+        let payment = tx(&[coinbase], &[(&bob, 40_000)]);
+        let paid = OutPoint::new(payment.txid(), 0);
+        let onward = tx(&[paid], &[(&alice, 30_000)]);
+        let next = block(genesis.block_hash(), 1, &bob, vec![payment, onward]);
+        let undo = cache.connect_block(&next, 1).await.unwrap();
+        assert_eq!(undo.len(), 2);
+        assert!(storage
+            .scan_prefix(&ns, COIN_PREFIX)
+            .await
+            .unwrap()
+            .is_empty());
+        cache.flush().await.unwrap();
+        assert_eq!(
+            storage.scan_prefix(&ns, COIN_PREFIX).await.unwrap().len(),
+            2
+        );
+
+        // A double spend leaves the set as it was
+        let double = block(
+            next.block_hash(),
+            2,
+            &bob,
+            vec![tx(&[coinbase], &[(&bob, 1)])],
+        );
+        assert_eq!(
+            cache.connect_block(&double, 2).await.unwrap_err().code(),
+            ErrorCode::InvalidInput
+        );
+        assert_eq!(cache.best_block().await, Some(next.block_hash()));
+
+        cache.disconnect_block(&next, &undo).await.unwrap();
+        assert_eq!(cache.get(&coinbase).await.unwrap().unwrap().height, 0);
+        assert_eq!(cache.get(&paid).await.unwrap(), None);
+        let stats = cache.stats().await;
+        assert!(stats.hits > 0 && stats.dirty > 0);
+
+        // A budget too small for any entry flushes after every block
+        let tiny = UtxoCache::open(
+            storage,
+            UtxoCacheConfig {
+                memory_budget: 1,
+                flush_interval: 0,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(tiny.best_block().await, Some(next.block_hash()));
+        assert!(tiny.get(&paid).await.unwrap().is_none());
+        let last = block(next.block_hash(), 2, &bob, vec![]);
+        tiny.connect_block(&last, 2).await.unwrap();
+        let stats = tiny.stats().await;
+        assert_eq!((stats.flushes, stats.entries), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_block_leaves_no_phantom_coins() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let alice = ScriptBuf::from_bytes(vec![0x51]);
+        let bob = ScriptBuf::from_bytes(vec![0x52]);
+        let cache = UtxoCache::open(storage, UtxoCacheConfig::default())
+            .await
+            .unwrap();
+        let genesis = block(BlockHash::all_zeros(), 0, &alice, vec![]);
+        let coinbase = OutPoint::new(genesis.txdata[0].txid(), 0);
+        cache.connect_block(&genesis, 0).await.unwrap();
+
+        // X is created and spent inside the block, then spent again
+        let payment = tx(&[coinbase], &[(&bob, 40_000)]);
+        let x = OutPoint::new(payment.txid(), 0);
+        let first = tx(&[x], &[(&alice, 30_000)]);
+        let second = tx(&[x], &[(&alice, 20_000)]);
+        let bad = block(genesis.block_hash(), 1, &bob, vec![payment, first, second]);
+        assert_eq!(
+            cache.connect_block(&bad, 1).await.unwrap_err().code(),
+            ErrorCode::InvalidInput
+        );
+        assert_eq!(cache.get(&x).await.unwrap(), None);
+        assert_eq!(cache.get(&coinbase).await.unwrap().unwrap().height, 0);
+        assert_eq!(cache.best_block().await, Some(genesis.block_hash()));
+    }
+}
@@ -0,0 +1,533 @@
+//! Parallel script verification for block validation
+//!
+//! [`ScriptValidator`] runs a fixed pool of worker threads that verify the
+//! input scripts of a block before it is handed to
+//! [`ChainIndex::connect_block`]. A block's checks are split into batches
+//! of [`ValidationConfig::batch_size`] inputs which the workers pick up as
+//! they free, so one large transaction cannot stall the others.
+//!
+//! Inputs and signatures that verified are remembered in
+//! [`ValidationCaches`]. Mempool acceptance runs through
+//! [`ScriptValidator::validate_transaction`] on the same caches, so a
+//! transaction checked when it entered the mempool is not verified again
+//! when a block confirms it; validators built with
+//! [`ScriptValidator::with_caches`] share them.
+//!
+//! Signatures are checked for P2WPKH and taproot key-path spends. Other
+//! inputs are counted as unsupported and left to the consensus engine.
+//! Each run reports its speedup, worker busy time over wall time, which is
+//! also exported as the `anya_script_validation_speedup` gauge.
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use ::bitcoin::ecdsa;
+use ::bitcoin::secp256k1::{Message, Secp256k1, VerifyOnly, XOnlyPublicKey};
+use ::bitcoin::sighash::{Prevouts, SighashCache};
+use ::bitcoin::{taproot, Block, OutPoint, PublicKey, ScriptBuf, Transaction, TxOut};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use super::index::ChainIndex;
+use super::sigcache::ValidationCaches;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Configuration of a [`ScriptValidator`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationConfig {
+    /// Worker threads; 0 uses one per available core
+    pub threads: usize,
+    /// Inputs handed to a worker at a time
+    pub batch_size: usize,
+    /// Entries of the script and signature caches of
+    /// [`ScriptValidator::new`]
+    pub cache_capacity: usize,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            batch_size: 128,
+            cache_capacity: super::sigcache::DEFAULT_CACHE_ENTRIES,
+        }
+    }
+}
+
+/// Work done validating blocks or transactions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Blocks validated
+    pub blocks: u64,
+    /// Transactions validated outside a block
+    pub transactions: u64,
+    /// Inputs checked, coinbases excluded
+    pub inputs: u64,
+    /// Inputs skipped because they verified before
+    pub cached: u64,
+    /// Inputs whose signatures were verified
+    pub verified: u64,
+    /// Inputs of script types not verified here
+    pub unsupported: u64,
+    /// Time the workers spent verifying
+    pub busy: Duration,
+    /// Wall time from dispatching the first batch to the last result
+    pub wall: Duration,
+}
+
+impl ValidationReport {
+    /// Verification speedup over a single thread: busy time over wall time
+    pub fn speedup(&self) -> f64 {
+        if self.wall.is_zero() {
+            1.0
+        } else {
+            self.busy.as_secs_f64() / self.wall.as_secs_f64()
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.inputs += other.inputs;
+        self.cached += other.cached;
+        self.verified += other.verified;
+        self.unsupported += other.unsupported;
+        self.busy += other.busy;
+        self.wall += other.wall;
+    }
+}
+
+struct Check {
+    tx: Arc<Transaction>,
+    prevouts: Arc<Vec<TxOut>>,
+    index: usize,
+    key: [u8; 32],
+}
+
+enum Verdict {
+    Valid,
+    Unsupported,
+}
+
+struct Batch {
+    busy: Duration,
+    outcomes: Vec<([u8; 32], Result<Verdict, String>)>,
+}
+
+struct Job {
+    checks: Vec<Check>,
+    reply: oneshot::Sender<Batch>,
+}
+
+/// Pool of script verification workers
+pub struct ScriptValidator {
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    batch_size: usize,
+    caches: Arc<ValidationCaches>,
+    stats: Mutex<ValidationReport>,
+}
+
+impl ScriptValidator {
+    /// Start the worker threads with caches of their own
+    pub fn new(config: ValidationConfig) -> AnyaResult<Self> {
+        let caches = ValidationCaches::new(config.cache_capacity, config.cache_capacity);
+        Self::with_caches(config, Arc::new(caches))
+    }
+
+    /// Start the worker threads using `caches`, which other validators may
+    /// share
+    pub fn with_caches(
+        config: ValidationConfig,
+        caches: Arc<ValidationCaches>,
+    ) -> AnyaResult<Self> {
+        let threads = match config.threads {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            n => n,
+        };
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                let caches = caches.clone();
+                std::thread::Builder::new()
+                    .name(format!("script-check-{}", i))
+                    .spawn(move || work(&receiver, &caches))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            jobs: Some(jobs),
+            workers,
+            batch_size: config.batch_size.max(1),
+            caches,
+            stats: Mutex::new(ValidationReport::default()),
+        })
+    }
+
+    /// Number of worker threads
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Caches the validator reads and fills
+    pub const fn caches(&self) -> &Arc<ValidationCaches> {
+        &self.caches
+    }
+
+    /// Verify the input scripts of `block`, looking up the outputs it
+    /// spends in `chain` or earlier in the block itself.
+    ///
+    /// Fails with [`ErrorCode::InvalidInput`] if an input spends an
+    /// unknown output or carries an invalid signature.
+    pub async fn validate_block(
+        &self,
+        block: &Block,
+        chain: &ChainIndex,
+    ) -> AnyaResult<ValidationReport> {
+        let (mut report, failure) = self.run(&block.txdata, chain).await?;
+        report.blocks = 1;
+        self.record(&report);
+        if let Some(e) = failure {
+            return Err(AnyaError::invalid_input(format!(
+                "block {}: {}",
+                block.block_hash(),
+                e
+            )));
+        }
+        Ok(report)
+    }
+
+    /// Verify the input scripts of `tx` before it enters the mempool,
+    /// looking up the outputs it spends in `chain`, whose
+    /// [`ChainIndex::output`] also finds outputs of unconfirmed parents
+    pub async fn validate_transaction(
+        &self,
+        tx: &Transaction,
+        chain: &ChainIndex,
+    ) -> AnyaResult<ValidationReport> {
+        let (mut report, failure) = self.run(std::slice::from_ref(tx), chain).await?;
+        report.transactions = 1;
+        self.record(&report);
+        failure.map_or(Ok(report), |e| Err(AnyaError::invalid_input(e)))
+    }
+
+    /// Totals over everything validated so far
+    pub fn stats(&self) -> ValidationReport {
+        *self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Check the inputs of `txs`, which may spend each other's outputs,
+    /// returning the work done and the first invalid input
+    async fn run(
+        &self,
+        txs: &[Transaction],
+        chain: &ChainIndex,
+    ) -> AnyaResult<(ValidationReport, Option<String>)> {
+        let mut report = ValidationReport::default();
+        let mut created: HashMap<OutPoint, TxOut> = HashMap::new();
+        let mut pending = Vec::new();
+        for tx in txs {
+            let txid = tx.txid();
+            if !tx.is_coin_base() {
+                let mut prevouts = Vec::with_capacity(tx.input.len());
+                for input in &tx.input {
+                    let outpoint = input.previous_output;
+                    let prevout = match created.get(&outpoint) {
+                        Some(output) => output.clone(),
+                        None => chain.output(&outpoint).await?.ok_or_else(|| {
+                            AnyaError::invalid_input(format!(
+                                "{} spends unknown output {}",
+                                txid, outpoint
+                            ))
+                        })?,
+                    };
+                    prevouts.push(prevout);
+                }
+                let wtxid = tx.wtxid();
+                let shared = Arc::new(tx.clone());
+                let prevouts = Arc::new(prevouts);
+                for index in 0..tx.input.len() {
+                    report.inputs += 1;
+                    let key = self.caches.script_key(&wtxid, index, &prevouts[index]);
+                    if self.caches.has_script(&key) {
+                        report.cached += 1;
+                        continue;
+                    }
+                    pending.push(Check {
+                        tx: shared.clone(),
+                        prevouts: prevouts.clone(),
+                        index,
+                        key,
+                    });
+                }
+            }
+            for (vout, output) in tx.output.iter().enumerate() {
+                let vout = u32::try_from(vout).unwrap_or(u32::MAX);
+                created.insert(OutPoint::new(txid, vout), output.clone());
+            }
+        }
+
+        let started = Instant::now();
+        let jobs = self
+            .jobs
+            .as_ref()
+            .ok_or_else(|| AnyaError::new(ErrorCode::Unavailable, "script validator stopped"))?;
+        let mut replies = Vec::new();
+        let mut pending = pending.into_iter().peekable();
+        while pending.peek().is_some() {
+            let (reply, receiver) = oneshot::channel();
+            let checks = pending.by_ref().take(self.batch_size).collect();
+            jobs.send(Job { checks, reply }).map_err(|_| stopped())?;
+            replies.push(receiver);
+        }
+        let mut failure = None;
+        for receiver in replies {
+            let batch = receiver.await.map_err(|_| stopped())?;
+            report.busy += batch.busy;
+            for (key, outcome) in batch.outcomes {
+                match outcome {
+                    Ok(Verdict::Valid) => {
+                        report.verified += 1;
+                        self.caches.add_script(key);
+                    }
+                    Ok(Verdict::Unsupported) => report.unsupported += 1,
+                    Err(e) => {
+                        failure.get_or_insert(e);
+                    }
+                }
+            }
+        }
+        report.wall = started.elapsed();
+        Ok((report, failure))
+    }
+
+    fn record(&self, report: &ValidationReport) {
+        self.stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add(report);
+        metrics::counter!("anya_script_checks_total", report.verified, "outcome" => "verified");
+        metrics::counter!("anya_script_checks_total", report.cached, "outcome" => "cached");
+        metrics::counter!("anya_script_checks_total", report.unsupported, "outcome" => "unsupported");
+        metrics::gauge!("anya_script_validation_speedup", report.speedup());
+    }
+}
+
+impl Drop for ScriptValidator {
+    fn drop(&mut self) {
+        // Closing the queue ends the workers once they drain it
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn stopped() -> AnyaError {
+    AnyaError::new(ErrorCode::Internal, "script validation workers stopped")
+}
+
+fn work(receiver: &Mutex<mpsc::Receiver<Job>>, caches: &ValidationCaches) {
+    let secp = Secp256k1::verification_only();
+    loop {
+        let job = receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .recv();
+        let Ok(job) = job else {
+            return;
+        };
+        let started = Instant::now();
+        let outcomes = job
+            .checks
+            .iter()
+            .map(|check| {
+                let outcome = verify(&secp, caches, check)
+                    .map_err(|e| format!("{} input {}: {}", check.tx.txid(), check.index, e));
+                (check.key, outcome)
+            })
+            .collect();
+        let _ = job.reply.send(Batch {
+            busy: started.elapsed(),
+            outcomes,
+        });
+    }
+}
+
+fn verify(
+    secp: &Secp256k1<VerifyOnly>,
+    caches: &ValidationCaches,
+    check: &Check,
+) -> AnyaResult<Verdict> {
+    let prevout = &check.prevouts[check.index];
+    let script = &prevout.script_pubkey;
+    let witness = &check.tx.input[check.index].witness;
+    let mut cache = SighashCache::new(&*check.tx);
+    if script.is_v0_p2wpkh() {
+        let (Some(sig_bytes), Some(key_bytes), 2) = (witness.nth(0), witness.nth(1), witness.len())
+        else {
+            return Err(AnyaError::invalid_input("expected a signature and key"));
+        };
+        let key = PublicKey::from_slice(key_bytes)?;
+        if key
+            .wpubkey_hash()
+            .map(|h| ScriptBuf::new_v0_p2wpkh(&h))
+            .as_ref()
+            != Some(script)
+        {
+            return Err(AnyaError::invalid_input("key does not match the script"));
+        }
+        let sig = ecdsa::Signature::from_slice(sig_bytes)
+            .map_err(|e| AnyaError::with_source(ErrorCode::InvalidInput, "bad signature", e))?;
+        let script_code = script
+            .p2wpkh_script_code()
+            .ok_or_else(|| AnyaError::invalid_input("not a P2WPKH script"))?;
+        let sighash =
+            cache.segwit_signature_hash(check.index, &script_code, prevout.value, sig.hash_ty)?;
+        let cached = caches.signature_key(&sighash[..], key_bytes, sig_bytes);
+        if !caches.has_signature(&cached) {
+            secp.verify_ecdsa(&Message::from_slice(&sighash[..])?, &sig.sig, &key.inner)
+                .map_err(|e| {
+                    AnyaError::with_source(ErrorCode::InvalidInput, "invalid signature", e)
+                })?;
+            caches.add_signature(cached);
+        }
+        Ok(Verdict::Valid)
+    } else if script.is_v1_p2tr() && witness.len() == 1 {
+        let key = XOnlyPublicKey::from_slice(&script.as_bytes()[2..])?;
+        let sig_bytes = witness.nth(0).unwrap_or_default();
+        let sig = taproot::Signature::from_slice(sig_bytes)
+            .map_err(|e| AnyaError::with_source(ErrorCode::InvalidInput, "bad signature", e))?;
+        let sighash = cache.taproot_key_spend_signature_hash(
+            check.index,
+            &Prevouts::All(&check.prevouts),
+            sig.hash_ty,
+        )?;
+        let cached = caches.signature_key(&sighash[..], &key.serialize(), sig_bytes);
+        if !caches.has_signature(&cached) {
+            secp.verify_schnorr(&sig.sig, &Message::from_slice(&sighash[..])?, &key)
+                .map_err(|e| {
+                    AnyaError::with_source(ErrorCode::InvalidInput, "invalid signature", e)
+                })?;
+            caches.add_signature(cached);
+        }
+        Ok(Verdict::Valid)
+    } else {
+        Ok(Verdict::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::index::tests::{block, tx};
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::secp256k1::SecretKey;
+    use ::bitcoin::sighash::EcdsaSighashType;
+    use ::bitcoin::{BlockHash, Witness};
+
+    #[tokio::test]
+    async fn test_scripts_verified_once_across_mempool_and_blocks() {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[7; 32]).unwrap();
+        let key = PublicKey::new(secret.public_key(&secp));
+        let owner = ScriptBuf::new_v0_p2wpkh(&key.wpubkey_hash().unwrap());
+        let other = ScriptBuf::from_bytes(vec![0x51]);
+        let index = ChainIndex::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let genesis = block(BlockHash::all_zeros(), 0, &owner, vec![]);
+        index.connect_block(&genesis).await.unwrap();
+
+        // A signed spend of the coinbase, and a spend of its output by a
+        // script type the pool does not verify
+        let coinbase = &genesis.txdata[0];
+        let mut spend = tx(&[OutPoint::new(coinbase.txid(), 0)], &[(&other, 40_000)]);
+        let sighash = SighashCache::new(&spend)
+            .segwit_signature_hash(
+                0,
+                &owner.p2wpkh_script_code().unwrap(),
+                coinbase.output[0].value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let sig = ecdsa::Signature::sighash_all(
+            secp.sign_ecdsa(&Message::from_slice(&sighash[..]).unwrap(), &secret),
+        );
+        spend.input[0].witness = Witness::from_slice(&[sig.to_vec(), key.to_bytes()]);
+        let chained = tx(&[OutPoint::new(spend.txid(), 0)], &[(&other, 30_000)]);
+        let next = block(
+            genesis.block_hash(),
+            1,
+            &other,
+            vec![spend.clone(), chained],
+        );
+
+        // Mempool acceptance verifies the spend; the block validator
+        // sharing its caches does not verify it again
+        let config = ValidationConfig {
+            threads: 2,
+            batch_size: 1,
+            ..ValidationConfig::default()
+        };
+        let mempool = ScriptValidator::new(config).unwrap();
+        let accepted = mempool.validate_transaction(&spend, &index).await.unwrap();
+        assert_eq!((accepted.verified, accepted.transactions), (1, 1));
+        let validator = ScriptValidator::with_caches(config, mempool.caches().clone()).unwrap();
+        assert_eq!(validator.threads(), 2);
+        let report = validator.validate_block(&next, &index).await.unwrap();
+        assert_eq!(
+            (report.verified, report.unsupported, report.cached),
+            (0, 1, 1)
+        );
+        assert_eq!(validator.stats().blocks, 1);
+
+        let mut forged = spend;
+        let mut sig = sig.to_vec();
+        sig[10] ^= 1;
+        forged.input[0].witness = Witness::from_slice(&[sig, key.to_bytes()]);
+        let bad = block(genesis.block_hash(), 1, &other, vec![forged]);
+        assert_eq!(
+            validator
+                .validate_block(&bad, &index)
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::InvalidInput
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transaction_may_spend_unconfirmed_parent() {
+        let other = ScriptBuf::from_bytes(vec![0x51]);
+        let index = ChainIndex::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let genesis = block(BlockHash::all_zeros(), 0, &other, vec![]);
+        index.connect_block(&genesis).await.unwrap();
+        let parent = tx(
+            &[OutPoint::new(genesis.txdata[0].txid(), 0)],
+            &[(&other, 40_000)],
+        );
+        let child = tx(&[OutPoint::new(parent.txid(), 0)], &[(&other, 30_000)]);
+
+        let validator = ScriptValidator::new(ValidationConfig::default()).unwrap();
+        let err = validator
+            .validate_transaction(&child, &index)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+
+        index.accept_to_mempool(parent).unwrap();
+        let report = validator
+            .validate_transaction(&child, &index)
+            .await
+            .unwrap();
+        assert_eq!((report.inputs, report.unsupported), (1, 1));
+    }
+}
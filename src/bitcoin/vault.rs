@@ -0,0 +1,747 @@
+//! Inheritance and recovery vaults
+//!
+//! A vault is a P2WSH wallet whose coins the primary key can spend at any
+//! time, while a threshold of recovery keys (heirs, a lawyer, a second
+//! device in a safe) can spend them only once a timelock has passed. The
+//! spending policy is the miniscript
+//!
+//! ```text
+//! or_d(pk(PRIMARY),and_v(v:multi(k,RECOVERY...),older(n)))
+//! ```
+//!
+//! with `after(n)` in place of `older(n)` for an absolute block height. The
+//! script is compiled by hand to the exact bytes a miniscript compiler
+//! produces for this policy, and [`Vault::descriptor`] exports it as a
+//! `wsh()` descriptor so other miniscript wallets can watch and sign it.
+//!
+//! A relative timelock restarts whenever a coin moves, so the owner keeps
+//! recovery paths dormant by re-vaulting: spending every coin with the
+//! primary key to a fresh vault address. [`VaultMonitor`] raises an alert
+//! once a coin comes within [`VaultConfig::warn_blocks`] of its recovery
+//! path activating, and again when it activates; [`Vault::revault`] builds
+//! the re-vaulting transaction.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use ::bitcoin::absolute::LockTime;
+use ::bitcoin::bip32::{ChildNumber, ExtendedPubKey, KeySource};
+use ::bitcoin::blockdata::opcodes::all::{
+    OP_CHECKMULTISIGVERIFY, OP_CHECKSIG, OP_CLTV, OP_CSV, OP_ENDIF, OP_IFDUP, OP_NOTIF,
+};
+use ::bitcoin::blockdata::script::Builder;
+use ::bitcoin::psbt::Psbt;
+use ::bitcoin::secp256k1::{Secp256k1, VerifyOnly};
+use ::bitcoin::{
+    Address, FeeRate, Network, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Witness,
+};
+use serde::{Deserialize, Serialize};
+
+use super::descriptor::checksum_of;
+use super::fees::fee_for;
+use super::tracker::{ChainSource, TxStatus};
+use crate::events::EventStore;
+use crate::{AnyaError, AnyaResult};
+
+/// Event log topic vault alerts are appended under
+pub const VAULT_TOPIC: &str = "wallet.vault";
+
+/// Lock times at or above this are Unix times, not heights
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Witness weight of a DER signature with its sighash byte and length
+const SIGNATURE_WEIGHT: u64 = 73;
+
+/// An extended public key with the origin it was derived along
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultKey {
+    /// Master fingerprint and derivation path, when known
+    pub origin: Option<KeySource>,
+    /// Account-level extended public key; addresses use `/0/*` below it
+    pub xpub: ExtendedPubKey,
+}
+
+impl VaultKey {
+    fn derive(&self, secp: &Secp256k1<VerifyOnly>, index: u32) -> AnyaResult<PublicKey> {
+        let path = [
+            ChildNumber::from_normal_idx(0)?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        Ok(PublicKey::new(
+            self.xpub.derive_pub(secp, &path)?.public_key,
+        ))
+    }
+
+    fn source(&self, index: u32) -> AnyaResult<KeySource> {
+        let steps = [
+            ChildNumber::from_normal_idx(0)?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        Ok(match &self.origin {
+            Some((fingerprint, path)) => (*fingerprint, path.extend(steps)),
+            None => (self.xpub.fingerprint(), steps.to_vec().into()),
+        })
+    }
+}
+
+impl fmt::Display for VaultKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((fingerprint, path)) = &self.origin {
+            let path = path.to_string().replace('\'', "h");
+            write!(f, "[{}{}]", fingerprint, path.trim_start_matches('m'))?;
+        }
+        write!(f, "{}/0/*", self.xpub)
+    }
+}
+
+/// When recovery keys become valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timelock {
+    /// Blocks after the coin confirmed (CSV), at most 65535
+    Relative(u16),
+    /// From this block height on (CLTV)
+    Absolute(u32),
+}
+
+impl Timelock {
+    fn validate(self) -> AnyaResult<()> {
+        let valid = match self {
+            Self::Relative(blocks) => blocks > 0,
+            Self::Absolute(height) => height > 0 && height < LOCKTIME_THRESHOLD,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(AnyaError::invalid_input(format!(
+                "invalid vault timelock {:?}",
+                self
+            )))
+        }
+    }
+
+    /// First height a recovery spend of a coin confirmed at `height` can be
+    /// mined in
+    pub const fn activation_height(self, height: u32) -> u32 {
+        match self {
+            Self::Relative(blocks) => height + blocks as u32,
+            Self::Absolute(at) => at,
+        }
+    }
+
+    fn fragment(self) -> String {
+        match self {
+            Self::Relative(blocks) => format!("older({})", blocks),
+            Self::Absolute(height) => format!("after({})", height),
+        }
+    }
+}
+
+/// A vault's keys and spending policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultConfig {
+    /// Name shown to the user and in alerts
+    pub name: String,
+    /// Network the vault lives on
+    pub network: Network,
+    /// Key spending at any time
+    pub primary: VaultKey,
+    /// Keys spending after the timelock
+    pub recovery: Vec<VaultKey>,
+    /// Recovery signatures required
+    pub threshold: usize,
+    /// When the recovery path opens
+    pub timelock: Timelock,
+    /// Blocks before activation at which the owner is warned
+    pub warn_blocks: u32,
+    /// Addresses scanned for coins
+    pub lookahead: u32,
+}
+
+/// A coin held by the vault
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultUtxo {
+    /// Output reference
+    pub outpoint: OutPoint,
+    /// Value and script
+    pub txout: TxOut,
+    /// Address index holding it
+    pub index: u32,
+    /// Confirmation height, `None` while in the mempool
+    pub height: Option<u32>,
+}
+
+/// Which spending path a transaction uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendPath {
+    /// Primary key, no timelock
+    Primary,
+    /// Recovery keys after the timelock
+    Recovery,
+}
+
+/// A vault wallet
+pub struct Vault {
+    config: VaultConfig,
+    secp: Secp256k1<VerifyOnly>,
+}
+
+impl Vault {
+    /// Vault with the given keys and policy
+    pub fn new(config: VaultConfig) -> AnyaResult<Self> {
+        config.timelock.validate()?;
+        if config.recovery.is_empty()
+            || config.threshold == 0
+            || config.threshold > config.recovery.len()
+            || config.recovery.len() > 20
+        {
+            return Err(AnyaError::invalid_input(format!(
+                "vault needs 1 to 20 recovery keys and a threshold between 1 and their \
+                 number, got {} of {}",
+                config.threshold,
+                config.recovery.len()
+            )));
+        }
+        let keys = std::iter::once(&config.primary).chain(&config.recovery);
+        // Extended keys only tell mainnet from the test networks
+        let mainnet = config.network == Network::Bitcoin;
+        if keys
+            .clone()
+            .any(|k| (k.xpub.network == Network::Bitcoin) != mainnet)
+        {
+            return Err(AnyaError::invalid_input(
+                "vault key network does not match the vault",
+            ));
+        }
+        let distinct: HashSet<_> = keys.clone().map(|k| k.xpub).collect();
+        if distinct.len() != config.recovery.len() + 1 {
+            return Err(AnyaError::invalid_input("vault keys must be distinct"));
+        }
+        Ok(Self {
+            config,
+            secp: Secp256k1::verification_only(),
+        })
+    }
+
+    /// Keys and policy
+    pub const fn config(&self) -> &VaultConfig {
+        &self.config
+    }
+
+    /// `wsh()` miniscript descriptor with its checksum
+    pub fn descriptor(&self) -> String {
+        let recovery: Vec<String> = self.config.recovery.iter().map(|k| k.to_string()).collect();
+        let body = format!(
+            "wsh(or_d(pk({}),and_v(v:multi({},{}),{})))",
+            self.config.primary,
+            self.config.threshold,
+            recovery.join(","),
+            self.config.timelock.fragment()
+        );
+        let checksum = checksum_of(&body).expect("rendered descriptors use the input charset");
+        format!("{}#{}", body, checksum)
+    }
+
+    /// Witness script of the address at `index`
+    pub fn witness_script(&self, index: u32) -> AnyaResult<ScriptBuf> {
+        let primary = self.config.primary.derive(&self.secp, index)?;
+        let mut builder = Builder::new()
+            .push_key(&primary)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_IFDUP)
+            .push_opcode(OP_NOTIF)
+            .push_int(self.config.threshold as i64);
+        for key in &self.config.recovery {
+            builder = builder.push_key(&key.derive(&self.secp, index)?);
+        }
+        builder = builder
+            .push_int(self.config.recovery.len() as i64)
+            .push_opcode(OP_CHECKMULTISIGVERIFY);
+        builder = match self.config.timelock {
+            Timelock::Relative(blocks) => builder.push_int(i64::from(blocks)).push_opcode(OP_CSV),
+            Timelock::Absolute(height) => builder.push_int(i64::from(height)).push_opcode(OP_CLTV),
+        };
+        Ok(builder.push_opcode(OP_ENDIF).into_script())
+    }
+
+    /// Address at `index`
+    pub fn address(&self, index: u32) -> AnyaResult<Address> {
+        Ok(Address::p2wsh(
+            &self.witness_script(index)?,
+            self.config.network,
+        ))
+    }
+
+    /// Unspent vault coins found through `chain` on the first
+    /// [`VaultConfig::lookahead`] addresses
+    pub async fn scan(&self, chain: &dyn ChainSource) -> AnyaResult<Vec<VaultUtxo>> {
+        let mut utxos = Vec::new();
+        for index in 0..self.config.lookahead {
+            let script = self.address(index)?.script_pubkey();
+            for txid in chain.script_history(&script).await? {
+                let Some(tx) = chain.transaction(&txid).await? else {
+                    continue;
+                };
+                let height = match chain.status(&txid).await? {
+                    TxStatus::Confirmed { height } => Some(height),
+                    _ => None,
+                };
+                for (vout, txout) in (0u32..).zip(tx.output) {
+                    let outpoint = OutPoint::new(txid, vout);
+                    if txout.script_pubkey == script
+                        && !utxos.iter().any(|u: &VaultUtxo| u.outpoint == outpoint)
+                        && chain.spender(&outpoint).await?.is_none()
+                    {
+                        utxos.push(VaultUtxo {
+                            outpoint,
+                            txout,
+                            index,
+                            height,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(utxos)
+    }
+
+    /// Weight of the witness spending the address at `index` along `path`
+    fn witness_weight(&self, path: SpendPath, index: u32) -> AnyaResult<u64> {
+        let script_len = self.witness_script(index)?.len() as u64;
+        let script_item = if script_len < 253 { 1 } else { 3 } + script_len;
+        let items = match path {
+            // item count, signature
+            SpendPath::Primary => 1 + SIGNATURE_WEIGHT,
+            // item count, multisig dummy, signatures, empty primary signature
+            SpendPath::Recovery => 1 + 1 + self.config.threshold as u64 * SIGNATURE_WEIGHT + 1,
+        };
+        Ok(items + script_item)
+    }
+
+    fn spend(
+        &self,
+        utxos: &[VaultUtxo],
+        path: SpendPath,
+        destination: ScriptBuf,
+        fee_rate: FeeRate,
+    ) -> AnyaResult<Psbt> {
+        if utxos.is_empty() {
+            return Err(AnyaError::invalid_input("no vault coins to spend"));
+        }
+        let (lock_time, sequence) = match (path, self.config.timelock) {
+            (SpendPath::Primary, _) => (LockTime::ZERO, Sequence::ENABLE_RBF_NO_LOCKTIME),
+            (SpendPath::Recovery, Timelock::Relative(blocks)) => {
+                (LockTime::ZERO, Sequence::from_height(blocks))
+            }
+            (SpendPath::Recovery, Timelock::Absolute(height)) => (
+                LockTime::from_height(height)
+                    .map_err(|e| AnyaError::invalid_input(e.to_string()))?,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ),
+        };
+        let total: u64 = utxos.iter().map(|u| u.txout.value).sum();
+        let mut tx = Transaction {
+            version: 2,
+            lock_time,
+            input: utxos
+                .iter()
+                .map(|u| TxIn {
+                    previous_output: u.outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: destination,
+            }],
+        };
+        // Segwit marker and flag plus the witnesses to come
+        let mut weight = tx.weight().to_wu() + 2;
+        for utxo in utxos {
+            weight += self.witness_weight(path, utxo.index)?;
+        }
+        let fee = fee_for(fee_rate, weight);
+        let dust = TxOut::minimal_non_dust(tx.output[0].script_pubkey.clone()).value;
+        tx.output[0].value = total
+            .checked_sub(fee)
+            .filter(|value| *value >= dust)
+            .ok_or_else(|| {
+                AnyaError::invalid_input(format!(
+                    "vault coins of {} sat cannot pay a fee of {} sat",
+                    total, fee
+                ))
+            })?;
+
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        for (input, utxo) in psbt.inputs.iter_mut().zip(utxos) {
+            input.witness_utxo = Some(utxo.txout.clone());
+            input.witness_script = Some(self.witness_script(utxo.index)?);
+            let keys: Vec<&VaultKey> = match path {
+                SpendPath::Primary => vec![&self.config.primary],
+                SpendPath::Recovery => self.config.recovery.iter().collect(),
+            };
+            for key in keys {
+                input.bip32_derivation.insert(
+                    key.derive(&self.secp, utxo.index)?.inner,
+                    key.source(utxo.index)?,
+                );
+            }
+        }
+        Ok(psbt)
+    }
+
+    /// Move every coin in `utxos` to the vault address at `index` with the
+    /// primary key, restarting their relative timelocks
+    pub fn revault(&self, utxos: &[VaultUtxo], index: u32, fee_rate: FeeRate) -> AnyaResult<Psbt> {
+        let destination = self.address(index)?.script_pubkey();
+        self.spend(utxos, SpendPath::Primary, destination, fee_rate)
+    }
+
+    /// Sweep `utxos` to `destination` with the recovery keys. Fails unless
+    /// every coin's recovery path is active at the next block after `tip`.
+    pub fn recover(
+        &self,
+        utxos: &[VaultUtxo],
+        destination: &Address,
+        fee_rate: FeeRate,
+        tip: u32,
+    ) -> AnyaResult<Psbt> {
+        for utxo in utxos {
+            let active = utxo
+                .height
+                .is_some_and(|h| self.config.timelock.activation_height(h) <= tip + 1);
+            if !active {
+                return Err(AnyaError::invalid_input(format!(
+                    "recovery path of {} is not active yet",
+                    utxo.outpoint
+                )));
+            }
+        }
+        self.spend(
+            utxos,
+            SpendPath::Recovery,
+            destination.script_pubkey(),
+            fee_rate,
+        )
+    }
+
+    /// Assemble the witnesses of a signed PSBT from [`Self::revault`] or
+    /// [`Self::recover`] and extract the transaction
+    pub fn finalize(&self, mut psbt: Psbt, utxos: &[VaultUtxo]) -> AnyaResult<Transaction> {
+        for (input, utxo) in psbt.inputs.iter_mut().zip(utxos) {
+            let script = self.witness_script(utxo.index)?;
+            let primary = self.config.primary.derive(&self.secp, utxo.index)?;
+            let mut witness = Witness::new();
+            if let Some(sig) = input.partial_sigs.get(&primary) {
+                witness.push(sig.to_vec());
+            } else {
+                // CHECKMULTISIG pops one element too many
+                witness.push([0u8; 0]);
+                let mut signed = 0;
+                for key in &self.config.recovery {
+                    if signed == self.config.threshold {
+                        break;
+                    }
+                    if let Some(sig) = input.partial_sigs.get(&key.derive(&self.secp, utxo.index)?)
+                    {
+                        witness.push(sig.to_vec());
+                        signed += 1;
+                    }
+                }
+                if signed < self.config.threshold {
+                    return Err(AnyaError::invalid_input(format!(
+                        "input {} has {} of {} recovery signatures",
+                        utxo.outpoint, signed, self.config.threshold
+                    )));
+                }
+                // Fails the primary CHECKSIG, taking the recovery branch
+                witness.push([0u8; 0]);
+            }
+            witness.push(script.as_bytes());
+            input.final_script_witness = Some(witness);
+            input.partial_sigs.clear();
+        }
+        Ok(psbt.extract_tx())
+    }
+}
+
+/// Stage of a coin's recovery path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// Activates within the warning period; re-vault to keep it dormant
+    Approaching,
+    /// Recovery keys can spend the coin now
+    Active,
+}
+
+/// A vault coin whose recovery path needs attention
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultAlert {
+    /// Vault name
+    pub vault: String,
+    /// Coin concerned
+    pub outpoint: OutPoint,
+    /// Stage reached
+    pub kind: AlertKind,
+    /// Height the recovery path activates at
+    pub activation_height: u32,
+    /// Blocks to be mined before a recovery spend can confirm, zero once
+    /// active
+    pub blocks_left: u32,
+}
+
+/// Raises alerts as vault recovery paths approach activation
+pub struct VaultMonitor {
+    events: Arc<EventStore>,
+    /// Alerts already raised
+    raised: Mutex<HashSet<(OutPoint, AlertKind)>>,
+}
+
+impl VaultMonitor {
+    /// Monitor appending alerts to `events`
+    pub fn new(events: Arc<EventStore>) -> Self {
+        Self {
+            events,
+            raised: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn raised(&self) -> MutexGuard<'_, HashSet<(OutPoint, AlertKind)>> {
+        self.raised
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Alerts due for `utxos` of `vault` at chain height `tip`. Each is
+    /// appended to the event log under [`VAULT_TOPIC`] once.
+    pub async fn check(
+        &self,
+        vault: &Vault,
+        utxos: &[VaultUtxo],
+        tip: u32,
+    ) -> AnyaResult<Vec<VaultAlert>> {
+        let config = vault.config();
+        let mut alerts = Vec::new();
+        for utxo in utxos {
+            let Some(height) = utxo.height else {
+                continue;
+            };
+            let activation_height = config.timelock.activation_height(height);
+            let blocks_left = activation_height.saturating_sub(tip + 1);
+            let kind = if blocks_left == 0 {
+                AlertKind::Active
+            } else if blocks_left <= config.warn_blocks {
+                AlertKind::Approaching
+            } else {
+                continue;
+            };
+            if !self.raised().insert((utxo.outpoint, kind)) {
+                continue;
+            }
+            let alert = VaultAlert {
+                vault: config.name.clone(),
+                outpoint: utxo.outpoint,
+                kind,
+                activation_height,
+                blocks_left,
+            };
+            let event_kind = match kind {
+                AlertKind::Approaching => "recovery_approaching",
+                AlertKind::Active => "recovery_active",
+            };
+            self.events
+                .append(VAULT_TOPIC, event_kind, serde_json::to_value(&alert)?)
+                .await?;
+            alerts.push(alert);
+        }
+        Ok(alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventStoreConfig;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::bip32::{DerivationPath, ExtendedPrivKey};
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::secp256k1::Message;
+    use ::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+    use ::bitcoin::Txid;
+    use std::str::FromStr;
+
+    fn master(seed: u8) -> ExtendedPrivKey {
+        ExtendedPrivKey::new_master(Network::Regtest, &[seed; 32]).unwrap()
+    }
+
+    fn key(seed: u8) -> VaultKey {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48h/1h/0h/2h").unwrap();
+        let master = master(seed);
+        let account = master.derive_priv(&secp, &path).unwrap();
+        VaultKey {
+            origin: Some((master.fingerprint(&secp), path)),
+            xpub: ExtendedPubKey::from_priv(&secp, &account),
+        }
+    }
+
+    fn vault(timelock: Timelock) -> Vault {
+        Vault::new(VaultConfig {
+            name: "family".into(),
+            network: Network::Regtest,
+            primary: key(1),
+            recovery: vec![key(2), key(3)],
+            threshold: 2,
+            timelock,
+            warn_blocks: 1_000,
+            lookahead: 5,
+        })
+        .unwrap()
+    }
+
+    fn utxo(vault: &Vault, n: u8, value: u64, height: u32) -> VaultUtxo {
+        VaultUtxo {
+            outpoint: OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+            txout: TxOut {
+                value,
+                script_pubkey: vault.address(u32::from(n)).unwrap().script_pubkey(),
+            },
+            index: u32::from(n),
+            height: Some(height),
+        }
+    }
+
+    /// Sign every input of `psbt` with the key of `seed` at its index
+    fn sign(psbt: &mut Psbt, seed: u8, utxos: &[VaultUtxo]) {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48h/1h/0h/2h/0").unwrap();
+        let chain = master(seed).derive_priv(&secp, &path).unwrap();
+        let tx = psbt.unsigned_tx.clone();
+        let mut cache = SighashCache::new(&tx);
+        for (i, (input, utxo)) in psbt.inputs.iter_mut().zip(utxos).enumerate() {
+            let child = chain
+                .derive_priv(&secp, &[ChildNumber::from_normal_idx(utxo.index).unwrap()])
+                .unwrap();
+            let script = input.witness_script.clone().unwrap();
+            let sighash = cache
+                .segwit_signature_hash(i, &script, utxo.txout.value, EcdsaSighashType::All)
+                .unwrap();
+            let message = Message::from_slice(&sighash[..]).unwrap();
+            let signature = ::bitcoin::ecdsa::Signature::sighash_all(
+                secp.sign_ecdsa(&message, &child.private_key),
+            );
+            input.partial_sigs.insert(
+                PublicKey::new(child.private_key.public_key(&secp)),
+                signature,
+            );
+        }
+    }
+
+    #[test]
+    fn test_script_and_descriptor() {
+        let relative = vault(Timelock::Relative(4_320));
+        let descriptor = relative.descriptor();
+        assert!(descriptor.starts_with("wsh(or_d(pk(["));
+        assert!(descriptor.contains("/48h/1h/0h/2h]tpub"));
+        assert!(descriptor.contains("),and_v(v:multi(2,["));
+        assert!(descriptor.contains("/0/*),older(4320))))#"));
+
+        let script = relative.witness_script(0).unwrap();
+        let asm = script.to_asm_string();
+        assert!(asm.contains("OP_CHECKSIG OP_IFDUP OP_NOTIF OP_PUSHNUM_2"));
+        assert!(asm
+            .ends_with("OP_PUSHNUM_2 OP_CHECKMULTISIGVERIFY OP_PUSHBYTES_2 e010 OP_CSV OP_ENDIF"));
+        assert_ne!(relative.address(0).unwrap(), relative.address(1).unwrap());
+
+        let absolute = vault(Timelock::Absolute(900_000));
+        assert!(absolute.descriptor().contains("after(900000)"));
+        assert!(absolute
+            .witness_script(0)
+            .unwrap()
+            .to_asm_string()
+            .contains("OP_CLTV"));
+
+        let mut config = relative.config().clone();
+        config.threshold = 3;
+        assert!(Vault::new(config).is_err());
+    }
+
+    #[test]
+    fn test_revault_and_recover() {
+        let vault = vault(Timelock::Relative(100));
+        let utxos = [
+            utxo(&vault, 0, 50_000, 1_000),
+            utxo(&vault, 1, 70_000, 1_050),
+        ];
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(2);
+
+        let mut psbt = vault.revault(&utxos, 2, fee_rate).unwrap();
+        assert_eq!(
+            psbt.unsigned_tx.output[0].script_pubkey,
+            vault.address(2).unwrap().script_pubkey()
+        );
+        sign(&mut psbt, 1, &utxos);
+        let tx = vault.finalize(psbt, &utxos).unwrap();
+        assert_eq!(tx.input[0].witness.len(), 2);
+        let fee = 120_000 - tx.output[0].value;
+        // The estimate covers the signed size
+        assert!(fee >= fee_for(fee_rate, tx.weight().to_wu()));
+
+        let heir = Address::p2wsh(&ScriptBuf::new(), Network::Regtest);
+        assert!(vault.recover(&utxos, &heir, fee_rate, 1_100).is_err());
+        let mut psbt = vault.recover(&utxos, &heir, fee_rate, 1_149).unwrap();
+        assert_eq!(
+            psbt.unsigned_tx.input[0].sequence,
+            Sequence::from_height(100)
+        );
+        sign(&mut psbt, 2, &utxos);
+        assert!(vault.finalize(psbt.clone(), &utxos).is_err());
+        sign(&mut psbt, 3, &utxos);
+        let tx = vault.finalize(psbt, &utxos).unwrap();
+        let witness: Vec<&[u8]> = tx.input[1].witness.iter().collect();
+        assert_eq!(witness.len(), 5);
+        assert!(witness[0].is_empty() && witness[3].is_empty());
+        assert_eq!(witness[4], vault.witness_script(1).unwrap().as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_monitor_alerts_once() {
+        let events = EventStore::open(EventStoreConfig::default(), Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let monitor = VaultMonitor::new(Arc::clone(&events));
+        let vault = vault(Timelock::Relative(4_320));
+        let utxos = [
+            utxo(&vault, 0, 50_000, 10_000),
+            utxo(&vault, 1, 50_000, 12_000),
+        ];
+
+        assert!(monitor
+            .check(&vault, &utxos, 12_000)
+            .await
+            .unwrap()
+            .is_empty());
+        let alerts = monitor.check(&vault, &utxos, 13_500).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::Approaching);
+        assert_eq!(alerts[0].blocks_left, 819);
+        assert!(monitor
+            .check(&vault, &utxos, 13_600)
+            .await
+            .unwrap()
+            .is_empty());
+        let alerts = monitor.check(&vault, &utxos, 14_319).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::Active);
+
+        let logged = events.read_from(0, 10).await.unwrap();
+        assert_eq!(logged.len(), 2);
+        assert!(logged.iter().all(|e| e.topic == VAULT_TOPIC));
+        assert_eq!(logged[1].kind, "recovery_active");
+    }
+}
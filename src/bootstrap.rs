@@ -0,0 +1,606 @@
+//! Provisioning of a new deployment
+//!
+//! A [`Bootstrapper`] takes a [`BootstrapPlan`] through a fixed sequence of
+//! [`BootstrapStep`]s:
+//!
+//! 1. `keys`: create the node's key rings for JWT signing, storage
+//!    encryption, Nostr, and API HMACs
+//! 2. `identity`: generate the node's `did:key` identity, whose seed is
+//!    stored sealed by the storage encryption ring
+//! 3. `wallet`: create the initial wallet account from a fresh seed, which
+//!    is returned once for the operator to back up and never stored
+//! 4. `relays`: validate and record the Nostr relays
+//! 5. `peers`: record the configured peers, plus discovered ones on request
+//! 6. `report`: sign a [`BootstrapReport`] with the node identity
+//!
+//! Progress is persisted after every step, so the CLI can walk an operator
+//! through one step at a time with [`Bootstrapper::step`], an installer can
+//! call [`Bootstrapper::run`], and either can resume after a failure. When
+//! resuming, the plan may be corrected, but not in the parts that finished
+//! steps already acted on.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use ::bitcoin::bip32::ExtendedPrivKey;
+use ::bitcoin::Network;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::bitcoin::accounts::{AccountManager, KeyChain, ScriptType};
+use crate::build_info::BuildInfo;
+use crate::keys::{KeyKind, KeyRing};
+use crate::net::discovery::{Discovery, PeerAddress};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::utils::time::unix_now;
+use crate::web5::auth::{verify_did_signature, DidSigner};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "bootstrap";
+const STATE_KEY: &str = "state";
+const IDENTITY_KEY: &str = "identity";
+
+/// Discovered peers recorded at most
+pub const MAX_DISCOVERED_PEERS: usize = 32;
+
+/// Key rings created by the `keys` step
+pub const NODE_KEY_RINGS: [(&str, KeyKind); 4] = [
+    ("node-jwt", KeyKind::JwtSigning),
+    ("node-storage", KeyKind::StorageEncryption),
+    ("node-nostr", KeyKind::Nostr),
+    ("node-api", KeyKind::ApiHmac),
+];
+
+const fn default_script_type() -> ScriptType {
+    ScriptType::NativeSegwit
+}
+
+/// Initial wallet account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletPlan {
+    /// Account label
+    pub label: String,
+    /// Account script type
+    #[serde(default = "default_script_type")]
+    pub script_type: ScriptType,
+}
+
+/// What to provision
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootstrapPlan {
+    /// Network the deployment runs on
+    pub network: Network,
+    /// Initial wallet account; none when the deployment holds no funds
+    #[serde(default)]
+    pub wallet: Option<WalletPlan>,
+    /// Nostr relay URLs, `ws://` or `wss://`
+    #[serde(default)]
+    pub relays: Vec<String>,
+    /// Peers as `host:port`
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Also record peers found by DNS and fixed seed discovery
+    #[serde(default)]
+    pub discover_peers: bool,
+}
+
+/// A stage of the bootstrap, in execution order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootstrapStep {
+    /// Node key rings
+    Keys,
+    /// Node DID
+    Identity,
+    /// Initial wallet account
+    Wallet,
+    /// Nostr relays
+    Relays,
+    /// Network peers
+    Peers,
+    /// Signed report
+    Report,
+}
+
+impl BootstrapStep {
+    /// Every step in execution order
+    pub const ALL: [Self; 6] = [
+        Self::Keys,
+        Self::Identity,
+        Self::Wallet,
+        Self::Relays,
+        Self::Peers,
+        Self::Report,
+    ];
+}
+
+/// Result of one step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepOutcome {
+    /// Step that ran
+    pub step: BootstrapStep,
+    /// What it provisioned, for display
+    pub summary: Value,
+    /// Secret to show the operator once, such as the hex wallet seed; it is
+    /// not stored anywhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// A wallet account created by the bootstrap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvisionedAccount {
+    /// Account identifier, e.g. `native_segwit/0`
+    pub id: String,
+    /// Account label
+    pub label: String,
+    /// Receive descriptor, for watch-only monitoring
+    pub receive_descriptor: String,
+}
+
+/// Signed record of what a bootstrap provisioned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootstrapReport {
+    /// Node DID, which signed the report
+    pub node_did: String,
+    /// Network
+    pub network: Network,
+    /// Hex x-only Nostr public key of the node
+    pub nostr_pubkey: String,
+    /// Active version of each node key ring
+    pub key_rings: BTreeMap<String, u32>,
+    /// Wallet accounts created
+    pub accounts: Vec<ProvisionedAccount>,
+    /// Nostr relays
+    pub relays: Vec<String>,
+    /// Network peers
+    pub peers: Vec<String>,
+    /// Build that ran the bootstrap
+    pub build: BuildInfo,
+    /// Unix completion time
+    pub completed_at: u64,
+    /// Hex Ed25519 signature by `node_did` over the report without it
+    pub signature: String,
+}
+
+impl BootstrapReport {
+    fn signing_payload(&self) -> AnyaResult<Vec<u8>> {
+        let unsigned = Self {
+            signature: String::new(),
+            ..self.clone()
+        };
+        Ok(serde_json::to_vec(&unsigned)?)
+    }
+
+    /// Check the report was signed by its node
+    pub fn verify(&self) -> AnyaResult<()> {
+        verify_did_signature(
+            &self.node_did,
+            &self.signing_payload()?,
+            &from_hex(&self.signature)?,
+        )
+    }
+}
+
+/// Persisted progress of a bootstrap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootstrapState {
+    /// Plan being carried out
+    pub plan: BootstrapPlan,
+    /// Steps finished, in order
+    pub completed: Vec<BootstrapStep>,
+    /// Active version of each node key ring
+    pub key_rings: BTreeMap<String, u32>,
+    /// Hex x-only Nostr public key
+    pub nostr_pubkey: Option<String>,
+    /// Node DID
+    pub node_did: Option<String>,
+    /// Wallet accounts created
+    pub accounts: Vec<ProvisionedAccount>,
+    /// Validated relays
+    pub relays: Vec<String>,
+    /// Recorded peers
+    pub peers: Vec<String>,
+    /// Signed report, once finished
+    pub report: Option<BootstrapReport>,
+}
+
+impl BootstrapState {
+    const fn new(plan: BootstrapPlan) -> Self {
+        Self {
+            plan,
+            completed: Vec::new(),
+            key_rings: BTreeMap::new(),
+            nostr_pubkey: None,
+            node_did: None,
+            accounts: Vec::new(),
+            relays: Vec::new(),
+            peers: Vec::new(),
+            report: None,
+        }
+    }
+
+    /// A finished step that `plan` would have done differently
+    fn conflicting_step(&self, plan: &BootstrapPlan) -> Option<BootstrapStep> {
+        let old = &self.plan;
+        self.completed.iter().copied().find(|step| match step {
+            // Everything provisioned belongs to one network
+            BootstrapStep::Keys | BootstrapStep::Identity => old.network != plan.network,
+            BootstrapStep::Wallet => old.network != plan.network || old.wallet != plan.wallet,
+            BootstrapStep::Relays => old.relays != plan.relays,
+            BootstrapStep::Peers => {
+                old.peers != plan.peers || old.discover_peers != plan.discover_peers
+            }
+            BootstrapStep::Report => old != plan,
+        })
+    }
+
+    /// First step not yet finished
+    pub fn next_step(&self) -> Option<BootstrapStep> {
+        BootstrapStep::ALL
+            .into_iter()
+            .find(|s| !self.completed.contains(s))
+    }
+}
+
+/// Carries out a bootstrap plan step by step
+pub struct Bootstrapper {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    discovery: Option<Discovery>,
+    state: Mutex<BootstrapState>,
+}
+
+impl Bootstrapper {
+    /// Start `plan`, or resume it if it was started before.
+    ///
+    /// Fails with [`ErrorCode::Conflict`] if `plan` changes what a finished
+    /// step acted on.
+    pub async fn open(storage: Arc<dyn StorageBackend>, plan: BootstrapPlan) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let state = match storage.get(&ns, STATE_KEY).await? {
+            Some(bytes) => {
+                let state: BootstrapState = serde_json::from_slice(&bytes)?;
+                if let Some(step) = state.conflicting_step(&plan) {
+                    return Err(AnyaError::new(
+                        ErrorCode::Conflict,
+                        format!("the plan changes what the finished {:?} step did", step),
+                    ));
+                }
+                BootstrapState { plan, ..state }
+            }
+            None => BootstrapState::new(plan),
+        };
+        storage
+            .put(&ns, STATE_KEY, &serde_json::to_vec(&state)?)
+            .await?;
+        Ok(Self {
+            storage,
+            ns,
+            discovery: None,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Discover peers through `discovery` when the plan asks for it
+    pub fn with_discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// Current progress
+    pub async fn state(&self) -> BootstrapState {
+        self.state.lock().await.clone()
+    }
+
+    /// Run the next pending step; `None` once every step has finished
+    pub async fn step(&self) -> AnyaResult<Option<StepOutcome>> {
+        let mut state = self.state.lock().await;
+        let Some(step) = state.next_step() else {
+            return Ok(None);
+        };
+        let mut next = state.clone();
+        let (summary, secret) = match step {
+            BootstrapStep::Keys => self.keys(&mut next).await?,
+            BootstrapStep::Identity => self.identity(&mut next).await?,
+            BootstrapStep::Wallet => self.wallet(&mut next).await?,
+            BootstrapStep::Relays => relays(&mut next)?,
+            BootstrapStep::Peers => self.peers(&mut next).await?,
+            BootstrapStep::Report => self.report(&mut next).await?,
+        };
+        next.completed.push(step);
+        self.storage
+            .put(&self.ns, STATE_KEY, &serde_json::to_vec(&next)?)
+            .await?;
+        *state = next;
+        drop(state);
+        info!(step = ?step, "bootstrap step completed");
+        Ok(Some(StepOutcome {
+            step,
+            summary,
+            secret,
+        }))
+    }
+
+    /// Run every pending step and return their outcomes
+    pub async fn run(&self) -> AnyaResult<Vec<StepOutcome>> {
+        let mut outcomes = Vec::new();
+        while let Some(outcome) = self.step().await? {
+            outcomes.push(outcome);
+        }
+        Ok(outcomes)
+    }
+
+    async fn keys(&self, state: &mut BootstrapState) -> AnyaResult<(Value, Option<String>)> {
+        for (name, kind) in NODE_KEY_RINGS {
+            let ring = KeyRing::open(Arc::clone(&self.storage), name, kind).await?;
+            state
+                .key_rings
+                .insert(name.to_string(), ring.active()?.version);
+            if kind == KeyKind::Nostr {
+                state.nostr_pubkey = ring.nostr_pubkeys()?.into_iter().next();
+            }
+        }
+        Ok((
+            json!({ "key_rings": state.key_rings, "nostr_pubkey": state.nostr_pubkey }),
+            None,
+        ))
+    }
+
+    async fn identity(&self, state: &mut BootstrapState) -> AnyaResult<(Value, Option<String>)> {
+        // A seed stored by an interrupted earlier attempt is reused
+        if self.storage.get(&self.ns, IDENTITY_KEY).await?.is_none() {
+            let sealed = self.storage_ring().await?.seal(&random_seed()?)?;
+            self.storage.put(&self.ns, IDENTITY_KEY, &sealed).await?;
+        }
+        let did = self.signer().await?.did().to_string();
+        state.node_did = Some(did.clone());
+        Ok((json!({ "did": did }), None))
+    }
+
+    async fn wallet(&self, state: &mut BootstrapState) -> AnyaResult<(Value, Option<String>)> {
+        let Some(plan) = state.plan.wallet.clone() else {
+            return Ok((json!({ "skipped": true }), None));
+        };
+        let accounts = AccountManager::open(state.plan.network, Arc::clone(&self.storage)).await?;
+        if !accounts.accounts().await.is_empty() {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                "the wallet already has accounts; restore it instead of bootstrapping one",
+            ));
+        }
+        let seed = random_seed()?;
+        let master = ExtendedPrivKey::new_master(state.plan.network, &seed)
+            .map_err(|e| AnyaError::new(ErrorCode::Internal, format!("master key: {}", e)))?;
+        let account = accounts
+            .create_account(&master, plan.script_type, plan.label)
+            .await?;
+        let provisioned = ProvisionedAccount {
+            id: account.id.to_string(),
+            receive_descriptor: account.descriptor(KeyChain::External),
+            label: account.label,
+        };
+        let summary = serde_json::to_value(&provisioned)?;
+        state.accounts.push(provisioned);
+        Ok((summary, Some(to_hex(&seed))))
+    }
+
+    async fn peers(&self, state: &mut BootstrapState) -> AnyaResult<(Value, Option<String>)> {
+        let mut peers = Vec::new();
+        for peer in &state.plan.peers {
+            let address: PeerAddress = peer.parse()?;
+            peers.push(address.to_string());
+        }
+        let mut discovered = 0;
+        if state.plan.discover_peers {
+            let discovery = self.discovery.as_ref().ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::Config,
+                    "the plan asks for peer discovery but none is configured",
+                )
+            })?;
+            for address in discovery.bootstrap(state.plan.network).await? {
+                if discovered == MAX_DISCOVERED_PEERS {
+                    break;
+                }
+                let address = address.to_string();
+                if !peers.contains(&address) {
+                    peers.push(address);
+                    discovered += 1;
+                }
+            }
+        }
+        dedup(&mut peers);
+        state.peers = peers;
+        Ok((
+            json!({ "peers": state.peers, "discovered": discovered }),
+            None,
+        ))
+    }
+
+    async fn report(&self, state: &mut BootstrapState) -> AnyaResult<(Value, Option<String>)> {
+        let signer = self.signer().await?;
+        let mut report = BootstrapReport {
+            node_did: signer.did().to_string(),
+            network: state.plan.network,
+            nostr_pubkey: state.nostr_pubkey.clone().unwrap_or_default(),
+            key_rings: state.key_rings.clone(),
+            accounts: state.accounts.clone(),
+            relays: state.relays.clone(),
+            peers: state.peers.clone(),
+            build: BuildInfo::current(),
+            completed_at: unix_now(),
+            signature: String::new(),
+        };
+        report.signature = to_hex(&signer.sign(&report.signing_payload()?));
+        let summary = serde_json::to_value(&report)?;
+        state.report = Some(report);
+        Ok((summary, None))
+    }
+
+    async fn storage_ring(&self) -> AnyaResult<Arc<KeyRing>> {
+        KeyRing::open(
+            Arc::clone(&self.storage),
+            NODE_KEY_RINGS[1].0,
+            KeyKind::StorageEncryption,
+        )
+        .await
+    }
+
+    /// The node identity created by the `identity` step
+    pub async fn signer(&self) -> AnyaResult<DidSigner> {
+        let sealed = self
+            .storage
+            .get(&self.ns, IDENTITY_KEY)
+            .await?
+            .ok_or_else(|| AnyaError::not_found("node identity has not been created"))?;
+        let seed: [u8; 32] = self
+            .storage_ring()
+            .await?
+            .unseal(&sealed)?
+            .try_into()
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "corrupt node identity"))?;
+        DidSigner::from_seed(&seed)
+    }
+}
+
+fn relays(state: &mut BootstrapState) -> AnyaResult<(Value, Option<String>)> {
+    let mut relays = Vec::new();
+    for relay in &state.plan.relays {
+        let host = relay
+            .strip_prefix("wss://")
+            .or_else(|| relay.strip_prefix("ws://"))
+            .map(|rest| rest.split('/').next().unwrap_or_default());
+        if host.map_or(true, str::is_empty) {
+            return Err(AnyaError::invalid_input(format!(
+                "relay {} is not a ws:// or wss:// URL",
+                relay
+            )));
+        }
+        relays.push(relay.trim_end_matches('/').to_string());
+    }
+    dedup(&mut relays);
+    state.relays = relays;
+    Ok((json!({ "relays": state.relays }), None))
+}
+
+/// Remove repeats, keeping the first occurrence
+fn dedup(items: &mut Vec<String>) {
+    let mut seen = std::collections::HashSet::new();
+    items.retain(|item| seen.insert(item.clone()));
+}
+
+fn random_seed() -> AnyaResult<[u8; 32]> {
+    let mut seed = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut seed)
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "system randomness unavailable"))?;
+    Ok(seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    fn plan() -> BootstrapPlan {
+        BootstrapPlan {
+            network: Network::Regtest,
+            wallet: Some(WalletPlan {
+                label: "treasury".into(),
+                script_type: ScriptType::NativeSegwit,
+            }),
+            relays: vec![
+                "wss://relay.example.com/".into(),
+                "wss://relay.example.com".into(),
+            ],
+            peers: vec!["127.0.0.1:18444".into()],
+            discover_peers: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn bootstrap_provisions_and_signs_report() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let bootstrap = Bootstrapper::open(Arc::clone(&storage), plan())
+            .await
+            .unwrap();
+        let outcomes = bootstrap.run().await.unwrap();
+        assert_eq!(
+            outcomes.iter().map(|o| o.step).collect::<Vec<_>>(),
+            BootstrapStep::ALL
+        );
+        let seed = outcomes[2].secret.as_deref().unwrap();
+        assert_eq!(seed.len(), 64);
+
+        let state = bootstrap.state().await;
+        let report = state.report.unwrap();
+        report.verify().unwrap();
+        assert_eq!(report.key_rings.len(), 4);
+        assert_eq!(report.relays, ["wss://relay.example.com"]);
+        assert_eq!(report.accounts[0].label, "treasury");
+        assert!(!serde_json::to_string(&report).unwrap().contains(seed));
+
+        let mut tampered = report.clone();
+        tampered.peers.push("10.0.0.1:18444".into());
+        assert!(tampered.verify().is_err());
+
+        // Reopening resumes with the same identity and nothing left to do
+        let again = Bootstrapper::open(Arc::clone(&storage), plan())
+            .await
+            .unwrap();
+        assert!(again.step().await.unwrap().is_none());
+        assert_eq!(
+            again.signer().await.unwrap().did().to_string(),
+            report.node_did
+        );
+        let other = BootstrapPlan {
+            network: Network::Signet,
+            ..plan()
+        };
+        let err = Bootstrapper::open(storage, other).await.err().unwrap();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+    }
+
+    #[tokio::test]
+    async fn failed_step_can_be_fixed_and_resumed() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let mut bad = plan();
+        bad.relays = vec!["https://relay.example.com".into()];
+        let bootstrap = Bootstrapper::open(Arc::clone(&storage), bad.clone())
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            bootstrap.step().await.unwrap();
+        }
+        let err = bootstrap.step().await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        assert_eq!(
+            bootstrap.state().await.next_step(),
+            Some(BootstrapStep::Relays)
+        );
+
+        // The relays may be corrected, but not the wallet already created
+        let fixed = BootstrapPlan {
+            relays: vec!["wss://relay.example.com".into()],
+            ..bad
+        };
+        let changed_wallet = BootstrapPlan {
+            wallet: None,
+            ..fixed.clone()
+        };
+        let err = Bootstrapper::open(Arc::clone(&storage), changed_wallet)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+        let resumed = Bootstrapper::open(storage, fixed).await.unwrap();
+        assert_eq!(resumed.run().await.unwrap().len(), 3);
+        resumed.state().await.report.unwrap().verify().unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+//! Build metadata and signed release attestations
+//!
+//! [`BuildInfo::current`] reports how the running binary was built: crate
+//! version, git commit, compiler, target, profile, and enabled features,
+//! recorded by the build script from inputs that are identical across
+//! reproducible builds of the same commit. Its [`BuildInfo::digest`]
+//! identifies the build.
+//!
+//! A [`ReleaseAttestation`] is signed by a release key over a build's
+//! metadata and the SHA-256 of the released artifact. Before trusting a
+//! node, a client such as the mobile app fetches the node's build info and
+//! attestation, checks the signature against the release keys it ships
+//! with, and checks that the attested build is the one the node reports.
+//! A node can also hash its own executable with
+//! [`ReleaseAttestation::check_artifact`].
+
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::encoding::{from_hex, sha256, to_hex};
+use crate::web5::credential::DidKey;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// How the running binary was built
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Crate version
+    pub version: String,
+    /// Git commit hash; empty when built outside a repository
+    pub git_commit: String,
+    /// Whether tracked files differed from the commit
+    pub git_dirty: bool,
+    /// `rustc --version` output
+    pub rustc: String,
+    /// Target triple
+    pub target: String,
+    /// Cargo profile, `debug` or `release`
+    pub profile: String,
+    /// Enabled cargo features, sorted
+    pub features: Vec<String>,
+    /// `SOURCE_DATE_EPOCH` or commit time, in Unix seconds
+    pub source_date_epoch: Option<u64>,
+}
+
+impl BuildInfo {
+    /// Metadata of this build
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: option_env!("ANYA_BUILD_GIT_COMMIT")
+                .unwrap_or_default()
+                .to_string(),
+            git_dirty: option_env!("ANYA_BUILD_GIT_DIRTY") == Some("true"),
+            rustc: option_env!("ANYA_BUILD_RUSTC")
+                .unwrap_or_default()
+                .to_string(),
+            target: option_env!("ANYA_BUILD_TARGET")
+                .unwrap_or_default()
+                .to_string(),
+            profile: option_env!("ANYA_BUILD_PROFILE")
+                .unwrap_or_default()
+                .to_string(),
+            features: option_env!("ANYA_BUILD_FEATURES")
+                .unwrap_or_default()
+                .split(',')
+                .filter(|f| !f.is_empty())
+                .map(str::to_string)
+                .collect(),
+            source_date_epoch: option_env!("ANYA_BUILD_SOURCE_DATE_EPOCH")
+                .and_then(|s| s.parse().ok()),
+        }
+    }
+
+    /// Hex SHA-256 of the canonical JSON encoding, identifying the build
+    pub fn digest(&self) -> String {
+        // Field order is fixed by the struct, so the encoding is canonical
+        to_hex(&sha256(&serde_json::to_vec(self).unwrap_or_default()))
+    }
+
+    /// Whether the build can be reproduced: from a clean commit and
+    /// optimized
+    pub fn is_release(&self) -> bool {
+        !self.git_commit.is_empty() && !self.git_dirty && self.profile == "release"
+    }
+}
+
+/// A release key's statement that an artifact is the given build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseAttestation {
+    /// Attested build
+    pub build: BuildInfo,
+    /// Artifact file name, e.g. `anya-core-x86_64-unknown-linux-gnu`
+    pub artifact: String,
+    /// Hex SHA-256 of the artifact
+    pub artifact_sha256: String,
+    /// Unix time of signing
+    pub issued_at: u64,
+    /// `did:key` of the release key
+    pub signer: String,
+    /// Hex Ed25519 signature over [`ReleaseAttestation::signing_payload`]
+    pub signature: String,
+}
+
+impl ReleaseAttestation {
+    /// Sign that `artifact_bytes`, named `artifact`, is `build`
+    pub fn sign(
+        key: &Ed25519KeyPair,
+        build: BuildInfo,
+        artifact: &str,
+        artifact_bytes: &[u8],
+        issued_at: u64,
+    ) -> AnyaResult<Self> {
+        let public_key: [u8; 32] =
+            key.public_key().as_ref().try_into().map_err(|_| {
+                AnyaError::new(ErrorCode::Internal, "unexpected Ed25519 key length")
+            })?;
+        let mut attestation = Self {
+            build,
+            artifact: artifact.to_string(),
+            artifact_sha256: to_hex(&sha256(artifact_bytes)),
+            issued_at,
+            signer: DidKey::from_public_key(public_key).to_string(),
+            signature: String::new(),
+        };
+        attestation.signature = to_hex(key.sign(&attestation.signing_payload()).as_ref());
+        Ok(attestation)
+    }
+
+    /// Bytes covered by the signature
+    pub fn signing_payload(&self) -> Vec<u8> {
+        format!(
+            "anya-release-attestation/v1\n{}\n{}\n{}\n{}\n{}",
+            self.build.digest(),
+            self.artifact,
+            self.artifact_sha256,
+            self.issued_at,
+            self.signer
+        )
+        .into_bytes()
+    }
+
+    /// Check the signature and that the signer is one of `trusted_signers`
+    pub fn verify(&self, trusted_signers: &[String]) -> AnyaResult<()> {
+        if !trusted_signers.iter().any(|s| s == &self.signer) {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("attestation signer {} is not trusted", self.signer),
+            ));
+        }
+        let signer = DidKey::parse(&self.signer)?;
+        UnparsedPublicKey::new(&ED25519, signer.public_key())
+            .verify(&self.signing_payload(), &from_hex(&self.signature)?)
+            .map_err(|_| AnyaError::new(ErrorCode::Unauthenticated, "bad attestation signature"))
+    }
+
+    /// Verify the attestation and that it covers `reported`, the build a
+    /// node claims to run
+    pub fn verify_build(&self, reported: &BuildInfo, trusted_signers: &[String]) -> AnyaResult<()> {
+        self.verify(trusted_signers)?;
+        if self.build.digest() != reported.digest() {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!(
+                    "node reports build {} ({}) but the attestation covers {} ({})",
+                    reported.version,
+                    reported.git_commit,
+                    self.build.version,
+                    self.build.git_commit
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that `artifact_bytes` are the attested artifact
+    pub fn check_artifact(&self, artifact_bytes: &[u8]) -> AnyaResult<()> {
+        if to_hex(&sha256(artifact_bytes)) == self.artifact_sha256 {
+            Ok(())
+        } else {
+            Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("{} does not match its attested hash", self.artifact),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build() -> BuildInfo {
+        BuildInfo {
+            version: "0.3.0".into(),
+            git_commit: "4f2c".repeat(10),
+            git_dirty: false,
+            rustc: "rustc 1.79.0".into(),
+            target: "aarch64-linux-android".into(),
+            profile: "release".into(),
+            features: vec!["bitcoin".into(), "mobile".into()],
+            source_date_epoch: Some(1_700_000_000),
+        }
+    }
+
+    #[test]
+    fn current_build_reports_version_and_features() {
+        let info = BuildInfo::current();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(info.features.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(info.digest(), BuildInfo::current().digest());
+    }
+
+    #[test]
+    fn attestation_binds_signer_build_and_artifact() {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[5; 32]).unwrap();
+        let artifact = b"\x7fELF release binary";
+        let attestation =
+            ReleaseAttestation::sign(&key, build(), "anya-core", artifact, 1_700_000_100).unwrap();
+        let trusted = vec![attestation.signer.clone()];
+
+        attestation.verify_build(&build(), &trusted).unwrap();
+        attestation.check_artifact(artifact).unwrap();
+        assert!(attestation.check_artifact(b"patched").is_err());
+
+        let other = BuildInfo {
+            git_dirty: true,
+            ..build()
+        };
+        let err = attestation.verify_build(&other, &trusted).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        assert!(attestation.verify(&[]).is_err());
+
+        let mut forged = attestation;
+        forged.build.features.push("chaos".into());
+        let err = forged.verify(&trusted).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+    }
+}
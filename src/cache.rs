@@ -0,0 +1,497 @@
+//! Generic async cache with TTL, LRU eviction, and stampede protection
+//!
+//! [`Cache`] is a size-bounded LRU map where every entry carries its own
+//! expiry. [`Cache::get_or_try_insert_with`] performs single-flight
+//! population: when many tasks miss on the same key at once, only one runs the
+//! loader and the rest wait for its result instead of hammering the backing
+//! service. Hit, miss, eviction, and load counts are published through the
+//! `metrics` facade with a `cache` label.
+//!
+//! Entries are spread over [`Sharded`] locks by key hash, each shard running
+//! its own LRU over its share of the capacity, so concurrent lookups of
+//! different keys rarely wait on each other; eviction is exact per shard and
+//! approximate across the cache. Small caches use fewer shards so every
+//! shard keeps at least [`MIN_SHARD_ENTRIES`] entries.
+//!
+//! [`CacheManager`] hands out named caches so DID resolution, fee estimation,
+//! and embeddings share one configuration surface.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::utils::sharded::{LockStats, Sharded, DEFAULT_SHARDS};
+use crate::AnyaResult;
+
+/// Fewest entries a shard is sized for
+pub const MIN_SHARD_ENTRIES: usize = 64;
+
+/// Configuration for a single cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Maximum number of entries before LRU eviction
+    pub capacity: usize,
+    /// Time-to-live for entries inserted without an explicit TTL
+    pub default_ttl: Duration,
+    /// Most lock shards to spread entries over
+    #[serde(default = "default_shards")]
+    pub shards: usize,
+}
+
+const fn default_shards() -> usize {
+    DEFAULT_SHARDS
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            default_ttl: Duration::from_secs(300),
+            shards: DEFAULT_SHARDS,
+        }
+    }
+}
+
+/// Point-in-time cache statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Lookups served from the cache
+    pub hits: u64,
+    /// Lookups that found nothing or an expired entry
+    pub misses: u64,
+    /// Entries evicted to respect capacity
+    pub evictions: u64,
+    /// Loader invocations performed by single-flight population
+    pub loads: u64,
+    /// Current number of entries
+    pub size: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    last_access: u64,
+}
+
+struct Inner<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    clock: u64,
+}
+
+type InFlight<V> = Arc<OnceCell<V>>;
+
+/// Size-bounded LRU cache with per-entry TTL
+pub struct Cache<K, V> {
+    name: String,
+    config: CacheConfig,
+    shard_capacity: usize,
+    inner: Sharded<Inner<K, V>>,
+    size: AtomicU64,
+    in_flight: Mutex<HashMap<K, InFlight<V>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    loads: AtomicU64,
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    /// Create a named cache
+    pub fn new(name: impl Into<String>, config: CacheConfig) -> Self {
+        let name = name.into();
+        let shards = config
+            .shards
+            .clamp(1, (config.capacity / MIN_SHARD_ENTRIES).max(1));
+        Self {
+            shard_capacity: ((config.capacity + shards - 1) / shards).max(1),
+            inner: Sharded::new(format!("cache.{}", name), shards, || Inner {
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+            name,
+            config,
+            size: AtomicU64::new(0),
+            in_flight: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            loads: AtomicU64::new(0),
+        }
+    }
+
+    /// Cache name used as the metrics label
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Look up a live entry, refreshing its LRU position
+    pub async fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.write(key);
+        inner.clock += 1;
+        let clock = inner.clock;
+        let now = Instant::now();
+        let found = match inner.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > now => {
+                entry.last_access = clock;
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                inner.entries.remove(key);
+                self.size.fetch_sub(1, Ordering::Relaxed);
+                None
+            }
+            None => None,
+        };
+        drop(inner);
+        self.record_lookup(found.is_some());
+        found
+    }
+
+    /// Insert with the default TTL
+    pub async fn insert(&self, key: K, value: V) {
+        self.insert_with_ttl(key, value, self.config.default_ttl)
+            .await;
+    }
+
+    /// Insert with an explicit TTL, evicting the least recently used entry
+    /// if the cache is full
+    pub async fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        let mut inner = self.inner.write(&key);
+        inner.clock += 1;
+        let clock = inner.clock;
+        let now = Instant::now();
+
+        if !inner.entries.contains_key(&key) && inner.entries.len() >= self.shard_capacity {
+            let before = inner.entries.len();
+            inner.entries.retain(|_, e| e.expires_at > now);
+            self.size
+                .fetch_sub((before - inner.entries.len()) as u64, Ordering::Relaxed);
+            while inner.entries.len() >= self.shard_capacity {
+                let Some(lru) = inner
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.last_access)
+                    .map(|(k, _)| k.clone())
+                else {
+                    break;
+                };
+                inner.entries.remove(&lru);
+                self.size.fetch_sub(1, Ordering::Relaxed);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+                metrics::increment_counter!("anya_cache_evictions_total", "cache" => self.name.clone());
+            }
+        }
+
+        let replaced = inner.entries.insert(
+            key,
+            Entry {
+                value,
+                expires_at: now + ttl,
+                last_access: clock,
+            },
+        );
+        drop(inner);
+        let size = if replaced.is_some() {
+            self.size.load(Ordering::Relaxed)
+        } else {
+            self.size.fetch_add(1, Ordering::Relaxed) + 1
+        };
+        metrics::gauge!("anya_cache_size", size as f64, "cache" => self.name.clone());
+    }
+
+    /// Remove an entry
+    pub async fn invalidate(&self, key: &K) -> Option<V> {
+        let removed = self.inner.write(key).entries.remove(key)?;
+        self.size.fetch_sub(1, Ordering::Relaxed);
+        Some(removed.value)
+    }
+
+    /// Remove every entry
+    pub async fn clear(&self) {
+        for shard in 0..self.inner.shard_count() {
+            let mut inner = self.inner.write_shard(shard);
+            self.size
+                .fetch_sub(inner.entries.len() as u64, Ordering::Relaxed);
+            inner.entries.clear();
+        }
+    }
+
+    /// Return the cached value or populate it with `loader`.
+    ///
+    /// Concurrent callers missing on the same key share a single loader
+    /// invocation. A failed load is returned to the caller whose loader ran
+    /// and nothing is cached; the next waiting caller then runs its own
+    /// loader.
+    pub async fn get_or_try_insert_with<F, Fut>(&self, key: K, loader: F) -> AnyaResult<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = AnyaResult<V>>,
+    {
+        self.get_or_try_insert_with_ttl(key, self.config.default_ttl, loader)
+            .await
+    }
+
+    /// Like [`Self::get_or_try_insert_with`] with an explicit TTL
+    pub async fn get_or_try_insert_with_ttl<F, Fut>(
+        &self,
+        key: K,
+        ttl: Duration,
+        loader: F,
+    ) -> AnyaResult<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = AnyaResult<V>>,
+    {
+        if let Some(value) = self.get(&key).await {
+            return Ok(value);
+        }
+
+        let cell = Arc::clone(
+            self.in_flight
+                .lock()
+                .await
+                .entry(key.clone())
+                .or_insert_with(|| Arc::new(OnceCell::new())),
+        );
+
+        let result = cell
+            .get_or_try_init(|| async {
+                self.loads.fetch_add(1, Ordering::Relaxed);
+                metrics::increment_counter!("anya_cache_loads_total", "cache" => self.name.clone());
+                let value = loader().await?;
+                self.insert_with_ttl(key.clone(), value.clone(), ttl).await;
+                Ok(value)
+            })
+            .await
+            .cloned();
+
+        let mut in_flight = self.in_flight.lock().await;
+        if in_flight.get(&key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+            in_flight.remove(&key);
+        }
+        drop(in_flight);
+        result
+    }
+
+    /// Current statistics
+    pub async fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            loads: self.loads.load(Ordering::Relaxed),
+            size: self.size.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Contention of the entry locks
+    pub fn lock_stats(&self) -> LockStats {
+        self.inner.stats()
+    }
+
+    fn record_lookup(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            metrics::increment_counter!("anya_cache_hits_total", "cache" => self.name.clone());
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            metrics::increment_counter!("anya_cache_misses_total", "cache" => self.name.clone());
+        }
+    }
+}
+
+/// Registry of named caches sharing per-name configuration
+#[derive(Default)]
+pub struct CacheManager {
+    configs: HashMap<String, CacheConfig>,
+    default_config: CacheConfig,
+}
+
+/// Cache name used for DID document resolution
+pub const DID_RESOLUTION_CACHE: &str = "web5.did_resolution";
+/// Cache name used for fee estimates
+pub const FEE_ESTIMATE_CACHE: &str = "bitcoin.fee_estimates";
+/// Cache name used for text embeddings
+pub const EMBEDDING_CACHE: &str = "ml.embeddings";
+
+impl CacheManager {
+    /// Create a manager with tuned defaults for the built-in caches
+    pub fn new() -> Self {
+        let mut manager = Self::default();
+        manager.configure(
+            DID_RESOLUTION_CACHE,
+            CacheConfig {
+                capacity: 5_000,
+                default_ttl: Duration::from_secs(15 * 60),
+                ..CacheConfig::default()
+            },
+        );
+        manager.configure(
+            FEE_ESTIMATE_CACHE,
+            CacheConfig {
+                capacity: 64,
+                default_ttl: Duration::from_secs(30),
+                ..CacheConfig::default()
+            },
+        );
+        manager.configure(
+            EMBEDDING_CACHE,
+            CacheConfig {
+                capacity: 50_000,
+                default_ttl: Duration::from_secs(24 * 60 * 60),
+                ..CacheConfig::default()
+            },
+        );
+        manager
+    }
+
+    /// Override the configuration for a named cache
+    pub fn configure(&mut self, name: impl Into<String>, config: CacheConfig) {
+        self.configs.insert(name.into(), config);
+    }
+
+    /// Configuration that will be used for `name`
+    pub fn config_for(&self, name: &str) -> CacheConfig {
+        self.configs
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| self.default_config.clone())
+    }
+
+    /// Build a cache for `name` using its configured settings
+    pub fn build<K, V>(&self, name: &str) -> Arc<Cache<K, V>>
+    where
+        K: Eq + Hash + Clone + Send + Sync,
+        V: Clone + Send + Sync,
+    {
+        Arc::new(Cache::new(name, self.config_for(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn test_ttl_and_lru_eviction() {
+        let cache = Cache::new(
+            "test",
+            CacheConfig {
+                capacity: 2,
+                default_ttl: Duration::from_secs(60),
+                ..CacheConfig::default()
+            },
+        );
+        cache.insert("a", 1).await;
+        cache.insert("b", 2).await;
+        assert_eq!(cache.get(&"a").await, Some(1));
+        cache.insert("c", 3).await;
+
+        assert_eq!(cache.get(&"b").await, None, "b was least recently used");
+        assert_eq!(cache.get(&"a").await, Some(1));
+
+        cache.insert_with_ttl("d", 4, Duration::ZERO).await;
+        assert_eq!(cache.get(&"d").await, None);
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.evictions, 2);
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 2);
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_population() {
+        let cache = Arc::new(Cache::<String, u64>::new("fees", CacheConfig::default()));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..16)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let calls = Arc::clone(&calls);
+                tokio::spawn(async move {
+                    cache
+                        .get_or_try_insert_with("6-blocks".to_string(), || async move {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            Ok(12)
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap(), 12);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().await.loads, 1);
+    }
+
+    #[tokio::test]
+    async fn test_entries_spread_over_shards() {
+        let cache = Arc::new(Cache::new(
+            "embeddings",
+            CacheConfig {
+                capacity: 1_024,
+                ..CacheConfig::default()
+            },
+        ));
+        let tasks: Vec<_> = (0..8u32)
+            .map(|task| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    for key in task * 256..(task + 1) * 256 {
+                        cache.insert(key, key).await;
+                        assert_eq!(cache.get(&key).await, Some(key));
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let stats = cache.stats().await;
+        assert!(stats.size <= 1_024);
+        assert_eq!(stats.size + stats.evictions, 2_048);
+        assert_eq!(stats.hits, 2_048);
+        assert_eq!(cache.lock_stats().acquisitions, 4_096);
+    }
+
+    #[tokio::test]
+    async fn test_loader_error_is_not_cached() {
+        let cache = Cache::<u8, u8>::new("did", CacheConfig::default());
+        let err = cache
+            .get_or_try_insert_with(1, || async {
+                Err(crate::AnyaError::new(
+                    crate::ErrorCode::Timeout,
+                    "resolver down",
+                ))
+            })
+            .await;
+        assert!(err.is_err());
+        let ok = cache.get_or_try_insert_with(1, || async { Ok(7) }).await;
+        assert_eq!(ok.unwrap(), 7);
+    }
+}
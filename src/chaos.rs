@@ -0,0 +1,462 @@
+//! Fault injection for resilience testing
+//!
+//! A shared [`FaultInjector`] decides, call by call, whether to delay, fail,
+//! or drop an operation according to [`FaultRule`]s matched against the
+//! operation name (`storage.put`, `chain.status`, `fees.estimate`, ...).
+//! Wrapping a dependency in one of the `Faulty*` adapters routes every call
+//! through the injector, so any subsystem built on that trait can be
+//! exercised against a flaky disk, node, or RPC endpoint; other traits can
+//! be wrapped the same way with [`FaultInjector::call`] and
+//! [`FaultInjector::send`]. Decisions come from a seeded RNG, so a failing
+//! run replays with the same seed.
+//!
+//! A dropped request surfaces as an [`ErrorCode::Timeout`] error. A dropped
+//! fire-and-forget call (a storage write, an event delivery) reports success
+//! without taking effect.
+//!
+//! Compiled for the crate's own tests and with the `chaos` feature.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use ::bitcoin::{FeeRate, OutPoint, Script, Transaction, Txid};
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::bitcoin::fees::FeeEstimator;
+use crate::bitcoin::tracker::{ChainSource, TxEvent, TxEventSink, TxStatus};
+use crate::storage::{BackendKind, Migration, Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Faults applied to matching operations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultRule {
+    /// Operation name, `prefix.*` pattern, or `*` for every operation
+    pub operation: String,
+    /// Probability that a call fails
+    pub error_rate: f64,
+    /// Code of injected errors
+    pub error_code: ErrorCode,
+    /// Probability that a call is dropped
+    pub drop_rate: f64,
+    /// Delay added before every call
+    pub latency: Duration,
+    /// Upper bound of a random delay added on top of `latency`
+    pub jitter: Duration,
+}
+
+impl FaultRule {
+    /// A rule for `operation` that injects nothing yet
+    pub fn new(operation: impl Into<String>) -> Self {
+        Self {
+            operation: operation.into(),
+            error_rate: 0.0,
+            error_code: ErrorCode::Unavailable,
+            drop_rate: 0.0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Fail a `rate` share of calls with `code`
+    #[must_use]
+    pub const fn with_errors(mut self, rate: f64, code: ErrorCode) -> Self {
+        self.error_rate = rate;
+        self.error_code = code;
+        self
+    }
+
+    /// Drop a `rate` share of calls
+    #[must_use]
+    pub const fn with_drops(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Delay calls by `latency` plus up to `jitter`
+    #[must_use]
+    pub const fn with_latency(mut self, latency: Duration, jitter: Duration) -> Self {
+        self.latency = latency;
+        self.jitter = jitter;
+        self
+    }
+
+    fn matches(&self, operation: &str) -> bool {
+        self.operation.strip_suffix('*').map_or_else(
+            || self.operation == operation,
+            |prefix| operation.starts_with(prefix),
+        )
+    }
+}
+
+/// Counts of injected faults
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaultStats {
+    /// Calls that matched a rule
+    pub calls: u64,
+    /// Calls failed with an injected error
+    pub errors: u64,
+    /// Calls dropped
+    pub dropped: u64,
+    /// Calls delayed
+    pub delayed: u64,
+}
+
+enum Outcome {
+    Proceed,
+    Fail(AnyaError),
+    Drop,
+}
+
+struct State {
+    enabled: bool,
+    rules: Vec<FaultRule>,
+    rng: StdRng,
+    stats: FaultStats,
+}
+
+/// Seeded source of injected faults shared by the `Faulty*` adapters
+pub struct FaultInjector {
+    state: Mutex<State>,
+}
+
+impl FaultInjector {
+    /// An enabled injector with no rules
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Mutex::new(State {
+                enabled: true,
+                rules: Vec::new(),
+                rng: StdRng::seed_from_u64(seed),
+                stats: FaultStats::default(),
+            }),
+        }
+    }
+
+    /// Add a rule; the first rule matching an operation applies
+    pub fn add_rule(&self, rule: FaultRule) {
+        self.state().rules.push(rule);
+    }
+
+    /// Remove every rule
+    pub fn clear(&self) {
+        self.state().rules.clear();
+    }
+
+    /// Pause or resume injection without losing the rules
+    pub fn set_enabled(&self, enabled: bool) {
+        self.state().enabled = enabled;
+    }
+
+    /// Faults injected so far
+    pub fn stats(&self) -> FaultStats {
+        self.state().stats
+    }
+
+    /// Run a request through the injector; a dropped request times out
+    pub async fn call<T>(
+        &self,
+        operation: &str,
+        request: impl Future<Output = AnyaResult<T>> + Send,
+    ) -> AnyaResult<T> {
+        match self.decide(operation).await {
+            Outcome::Proceed => request.await,
+            Outcome::Fail(err) => Err(err),
+            Outcome::Drop => Err(AnyaError::new(
+                ErrorCode::Timeout,
+                format!("{} dropped by fault injection", operation),
+            )),
+        }
+    }
+
+    /// Run a fire-and-forget call through the injector; a dropped call
+    /// reports success without running
+    pub async fn send(
+        &self,
+        operation: &str,
+        call: impl Future<Output = AnyaResult<()>> + Send,
+    ) -> AnyaResult<()> {
+        match self.decide(operation).await {
+            Outcome::Proceed => call.await,
+            Outcome::Fail(err) => Err(err),
+            Outcome::Drop => Ok(()),
+        }
+    }
+
+    async fn decide(&self, operation: &str) -> Outcome {
+        let (delay, outcome) = self.roll(operation);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        outcome
+    }
+
+    fn roll(&self, operation: &str) -> (Duration, Outcome) {
+        let mut state = self.state();
+        if !state.enabled {
+            return (Duration::ZERO, Outcome::Proceed);
+        }
+        let Some(rule) = state.rules.iter().find(|r| r.matches(operation)).cloned() else {
+            return (Duration::ZERO, Outcome::Proceed);
+        };
+        let jitter = state.rng.gen_range(Duration::ZERO..=rule.jitter);
+        let roll: f64 = state.rng.gen();
+        let delay = rule.latency + jitter;
+
+        state.stats.calls += 1;
+        if !delay.is_zero() {
+            state.stats.delayed += 1;
+        }
+        let outcome = if roll < rule.error_rate {
+            state.stats.errors += 1;
+            Outcome::Fail(AnyaError::new(
+                rule.error_code,
+                format!("injected fault in {}", operation),
+            ))
+        } else if roll < rule.error_rate + rule.drop_rate {
+            state.stats.dropped += 1;
+            Outcome::Drop
+        } else {
+            Outcome::Proceed
+        };
+        drop(state);
+        (delay, outcome)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// [`StorageBackend`] with injected faults (`storage.*`)
+pub struct FaultyStorage {
+    inner: Arc<dyn StorageBackend>,
+    faults: Arc<FaultInjector>,
+}
+
+impl FaultyStorage {
+    /// Wrap `inner`
+    pub fn new(inner: Arc<dyn StorageBackend>, faults: Arc<FaultInjector>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FaultyStorage {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    async fn ensure_namespace(&self, ns: &Namespace) -> AnyaResult<()> {
+        self.faults
+            .call("storage.ensure_namespace", self.inner.ensure_namespace(ns))
+            .await
+    }
+
+    async fn get(&self, ns: &Namespace, key: &str) -> AnyaResult<Option<Vec<u8>>> {
+        self.faults
+            .call("storage.get", self.inner.get(ns, key))
+            .await
+    }
+
+    async fn put(&self, ns: &Namespace, key: &str, value: &[u8]) -> AnyaResult<()> {
+        self.faults
+            .send("storage.put", self.inner.put(ns, key, value))
+            .await
+    }
+
+    async fn delete(&self, ns: &Namespace, key: &str) -> AnyaResult<bool> {
+        self.faults
+            .call("storage.delete", self.inner.delete(ns, key))
+            .await
+    }
+
+    async fn scan_prefix(
+        &self,
+        ns: &Namespace,
+        prefix: &str,
+    ) -> AnyaResult<Vec<(String, Vec<u8>)>> {
+        self.faults
+            .call("storage.scan_prefix", self.inner.scan_prefix(ns, prefix))
+            .await
+    }
+
+    async fn applied_migrations(&self, ns: &Namespace) -> AnyaResult<Vec<u32>> {
+        self.faults
+            .call(
+                "storage.applied_migrations",
+                self.inner.applied_migrations(ns),
+            )
+            .await
+    }
+
+    async fn apply_migration(&self, ns: &Namespace, migration: &Migration) -> AnyaResult<()> {
+        self.faults
+            .call(
+                "storage.apply_migration",
+                self.inner.apply_migration(ns, migration),
+            )
+            .await
+    }
+}
+
+/// [`ChainSource`] with injected faults (`chain.*`)
+pub struct FaultyChainSource {
+    inner: Arc<dyn ChainSource>,
+    faults: Arc<FaultInjector>,
+}
+
+impl FaultyChainSource {
+    /// Wrap `inner`
+    pub fn new(inner: Arc<dyn ChainSource>, faults: Arc<FaultInjector>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl ChainSource for FaultyChainSource {
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        self.faults
+            .call("chain.tip_height", self.inner.tip_height())
+            .await
+    }
+
+    async fn script_history(&self, script: &Script) -> AnyaResult<Vec<Txid>> {
+        self.faults
+            .call("chain.script_history", self.inner.script_history(script))
+            .await
+    }
+
+    async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        self.faults
+            .call("chain.transaction", self.inner.transaction(txid))
+            .await
+    }
+
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        self.faults
+            .call("chain.status", self.inner.status(txid))
+            .await
+    }
+
+    async fn spender(&self, outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+        self.faults
+            .call("chain.spender", self.inner.spender(outpoint))
+            .await
+    }
+}
+
+/// [`FeeEstimator`] with injected faults (`fees.estimate`)
+pub struct FaultyFeeEstimator {
+    inner: Arc<dyn FeeEstimator>,
+    faults: Arc<FaultInjector>,
+}
+
+impl FaultyFeeEstimator {
+    /// Wrap `inner`
+    pub fn new(inner: Arc<dyn FeeEstimator>, faults: Arc<FaultInjector>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl FeeEstimator for FaultyFeeEstimator {
+    async fn estimate(&self, target_blocks: u16) -> AnyaResult<FeeRate> {
+        self.faults
+            .call("fees.estimate", self.inner.estimate(target_blocks))
+            .await
+    }
+}
+
+/// [`TxEventSink`] with injected faults (`events.deliver`)
+pub struct FaultySink {
+    inner: Arc<dyn TxEventSink>,
+    faults: Arc<FaultInjector>,
+}
+
+impl FaultySink {
+    /// Wrap `inner`
+    pub fn new(inner: Arc<dyn TxEventSink>, faults: Arc<FaultInjector>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl TxEventSink for FaultySink {
+    async fn handle(&self, event: &TxEvent) -> AnyaResult<()> {
+        self.faults
+            .send("events.deliver", self.inner.handle(event))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use std::time::Instant;
+
+    async fn storage(faults: &Arc<FaultInjector>) -> (FaultyStorage, Namespace) {
+        let storage = FaultyStorage::new(Arc::new(MemoryBackend::new()), Arc::clone(faults));
+        let ns = Namespace::new("chaos").unwrap();
+        storage.ensure_namespace(&ns).await.unwrap();
+        (storage, ns)
+    }
+
+    #[tokio::test]
+    async fn test_rules_fail_drop_and_delay_matching_calls() {
+        let faults = Arc::new(FaultInjector::new(1));
+        let (storage, ns) = storage(&faults).await;
+        storage.put(&ns, "a", b"1").await.unwrap();
+
+        faults.add_rule(FaultRule::new("storage.put").with_drops(1.0));
+        faults.add_rule(FaultRule::new("storage.*").with_errors(1.0, ErrorCode::StorageFailure));
+        // Dropped writes look successful but are lost
+        storage.put(&ns, "b", b"2").await.unwrap();
+        faults.set_enabled(false);
+        assert!(storage.get(&ns, "b").await.unwrap().is_none());
+        faults.set_enabled(true);
+        assert_eq!(
+            storage.get(&ns, "a").await.unwrap_err().code(),
+            ErrorCode::StorageFailure
+        );
+
+        faults.clear();
+        faults
+            .add_rule(FaultRule::new("*").with_latency(Duration::from_millis(30), Duration::ZERO));
+        let started = Instant::now();
+        assert_eq!(storage.get(&ns, "a").await.unwrap(), Some(b"1".to_vec()));
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(
+            faults.stats(),
+            FaultStats {
+                calls: 3,
+                errors: 1,
+                dropped: 1,
+                delayed: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_same_seed_injects_same_faults() {
+        async fn outcomes(seed: u64) -> Vec<bool> {
+            let faults = Arc::new(FaultInjector::new(seed));
+            faults.add_rule(FaultRule::new("storage.get").with_errors(0.5, ErrorCode::Timeout));
+            let (storage, ns) = storage(&faults).await;
+            let mut outcomes = Vec::new();
+            for _ in 0..32 {
+                outcomes.push(storage.get(&ns, "k").await.is_ok());
+            }
+            outcomes
+        }
+
+        let first = outcomes(9).await;
+        assert!(first.contains(&true) && first.contains(&false));
+        assert_eq!(first, outcomes(9).await);
+        assert_ne!(first, outcomes(10).await);
+    }
+}
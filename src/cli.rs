@@ -0,0 +1,852 @@
+//! Command-line client for node operation and wallet management
+//!
+//! `anya-cli` parses a [`Cli`] and turns its [`Command`] into a JSON-RPC 2.0
+//! request sent to a running node through a [`NodeRpc`] transport. Every
+//! command maps to one RPC method:
+//!
+//! | Command                  | Method           |
+//! |--------------------------|------------------|
+//! | `node start/stop/status` | `node.<action>`  |
+//! | `wallet create`          | `wallet.create`  |
+//! | `wallet restore`         | `wallet.restore` |
+//! | `wallet send`            | `wallet.send`    |
+//! | `wallet receive`         | `wallet.receive` |
+//! | `dao proposals`          | `dao.proposals`  |
+//! | `dao vote`               | `dao.vote`       |
+//! | `metrics`                | `metrics.get`    |
+//! | `bootstrap`              | `node.bootstrap` |
+//!
+//! `seed` commands are the exception: they back up and restore seeds with
+//! SLIP-39 shares and SeedQR locally, reading secrets from standard input so
+//! they never reach the node or the shell history.
+//!
+//! With `--json` the raw result is printed so scripts can consume it; errors
+//! are then printed as an [`ErrorReport`](crate::error::ErrorReport).
+
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::bitcoin::accounts::ScriptType;
+use crate::bitcoin::ceremony::{
+    seed_fingerprint, RecoverySession, SharePlan, SplitCeremony, Wordlist,
+};
+use crate::bitcoin::seedqr::{SeedQr, SeedQrFormat};
+use crate::error::ErrorReport;
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::utils::zeroize::Zeroizing;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Node endpoint used when `--rpc-url` and `ANYA_RPC_URL` are unset
+pub const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8720/rpc";
+
+/// Top-level arguments
+#[derive(Debug, Parser)]
+#[command(
+    name = "anya-cli",
+    version,
+    about = "Operate an Anya node and its wallets"
+)]
+pub struct Cli {
+    /// Node RPC endpoint
+    #[arg(long, env = "ANYA_RPC_URL", default_value = DEFAULT_RPC_URL, global = true)]
+    pub rpc_url: String,
+    /// Bearer token for authenticated endpoints
+    #[arg(long, env = "ANYA_RPC_TOKEN", hide_env_values = true, global = true)]
+    pub token: Option<String>,
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Command to run
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    /// Output format selected by the flags
+    pub const fn format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            OutputFormat::Text
+        }
+    }
+}
+
+/// CLI commands
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Control the node's subsystems
+    #[command(subcommand)]
+    Node(NodeCommand),
+    /// Manage wallets
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// List and vote on DAO proposals
+    #[command(subcommand)]
+    Dao(DaoCommand),
+    /// Show node metrics
+    Metrics {
+        /// Only metrics whose name starts with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    /// Provision a new deployment: node keys, DID, wallet, relays, and peers
+    Bootstrap(BootstrapArgs),
+    /// Back up or restore a seed locally, without the node
+    #[command(subcommand)]
+    Seed(SeedCommand),
+}
+
+/// Bootstrap plan, see [`BootstrapPlan`](crate::bootstrap::BootstrapPlan)
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct BootstrapArgs {
+    /// Network: bitcoin, testnet, signet, or regtest
+    #[arg(long, default_value = "bitcoin")]
+    pub network: String,
+    /// Label of the initial wallet account
+    #[arg(long, default_value = "main")]
+    pub wallet_label: String,
+    /// Address type of the initial wallet account
+    #[arg(long, value_enum, default_value_t = AddressType::P2wpkh)]
+    pub address_type: AddressType,
+    /// Do not create a wallet
+    #[arg(long, conflicts_with_all = ["wallet_label", "address_type"])]
+    pub no_wallet: bool,
+    /// Nostr relay URL; repeat for several
+    #[arg(long = "relay")]
+    pub relays: Vec<String>,
+    /// Peer as host:port; repeat for several
+    #[arg(long = "peer")]
+    pub peers: Vec<String>,
+    /// Also record peers found through DNS seeds
+    #[arg(long)]
+    pub discover_peers: bool,
+    /// Run only the next pending step, for walking through interactively
+    #[arg(long)]
+    pub step: bool,
+}
+
+/// Node control commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+pub enum NodeCommand {
+    /// Start all registered subsystems
+    Start,
+    /// Gracefully stop all subsystems
+    Stop,
+    /// Show lifecycle phase, network, and chain tip
+    Status,
+}
+
+/// Wallet commands
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum WalletCommand {
+    /// Create a wallet with a fresh seed
+    Create {
+        /// Wallet name
+        name: String,
+        /// Address type of the default account
+        #[arg(long, value_enum, default_value_t = AddressType::P2wpkh)]
+        address_type: AddressType,
+    },
+    /// Restore a wallet from a mnemonic or an output descriptor
+    Restore {
+        /// Wallet name
+        name: String,
+        /// Key source
+        #[command(flatten)]
+        source: RestoreSource,
+        /// Block height to rescan from
+        #[arg(long)]
+        birthday: Option<u32>,
+    },
+    /// Send to an address
+    Send {
+        /// Wallet name
+        wallet: String,
+        /// Destination address
+        address: String,
+        /// Amount in satoshis
+        amount_sat: u64,
+        /// Fee rate in sat/vB; the node's estimate when omitted
+        #[arg(long)]
+        fee_rate: Option<f64>,
+        /// Build and sign but do not broadcast
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Derive a fresh receive address
+    Receive {
+        /// Wallet name
+        wallet: String,
+        /// Label attached to the address
+        #[arg(long)]
+        label: Option<String>,
+    },
+}
+
+/// Where a restored wallet's keys come from
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+#[group(required = true, multiple = false)]
+pub struct RestoreSource {
+    /// BIP-39 mnemonic
+    #[arg(long)]
+    pub mnemonic: Option<String>,
+    /// Output descriptor, for watch-only wallets
+    #[arg(long)]
+    pub descriptor: Option<String>,
+}
+
+/// Address types a new wallet can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressType {
+    /// Legacy pay-to-pubkey-hash
+    P2pkh,
+    /// Native SegWit v0
+    P2wpkh,
+    /// Taproot key path
+    P2tr,
+}
+
+impl AddressType {
+    /// Script type of accounts using this address type
+    pub const fn script_type(self) -> ScriptType {
+        match self {
+            Self::P2pkh => ScriptType::Legacy,
+            Self::P2wpkh => ScriptType::NativeSegwit,
+            Self::P2tr => ScriptType::Taproot,
+        }
+    }
+}
+
+/// DAO governance commands
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum DaoCommand {
+    /// List proposals
+    Proposals {
+        /// Only proposals in this state
+        #[arg(long, value_enum)]
+        status: Option<ProposalStatus>,
+    },
+    /// Vote on a proposal
+    Vote {
+        /// Proposal identifier
+        proposal: String,
+        /// Vote to cast
+        #[arg(value_enum)]
+        choice: VoteChoice,
+    },
+}
+
+/// Proposal states accepted by `dao proposals --status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProposalStatus {
+    /// Open for voting
+    Active,
+    /// Voting closed with approval
+    Passed,
+    /// Voting closed without approval
+    Rejected,
+    /// Approved and executed
+    Executed,
+}
+
+/// A vote on a proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteChoice {
+    /// In favor
+    Yes,
+    /// Against
+    No,
+    /// Counted towards quorum only
+    Abstain,
+}
+
+/// Seed backup commands. The hex seed or the shares, one per line, are
+/// read from standard input, after the passphrase line with `--passphrase`.
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum SeedCommand {
+    /// Split a seed into SLIP-39 shares
+    Split {
+        /// Shares needed to recover the seed
+        #[arg(long, default_value_t = 2)]
+        threshold: u8,
+        /// Shares to create
+        #[arg(long, default_value_t = 3)]
+        shares: u8,
+        /// File with the 1024-word SLIP-39 list
+        #[arg(long)]
+        wordlist: PathBuf,
+        /// Read a passphrase from the first line of standard input
+        #[arg(long)]
+        passphrase: bool,
+    },
+    /// Check written-down shares and report recovery progress and the
+    /// fingerprint they recover, without printing the seed
+    Verify {
+        /// File with the 1024-word SLIP-39 list
+        #[arg(long)]
+        wordlist: PathBuf,
+        /// Read a passphrase from the first line of standard input
+        #[arg(long)]
+        passphrase: bool,
+    },
+    /// Recover a seed from SLIP-39 shares
+    Recover {
+        /// File with the 1024-word SLIP-39 list
+        #[arg(long)]
+        wordlist: PathBuf,
+        /// Read a passphrase from the first line of standard input
+        #[arg(long)]
+        passphrase: bool,
+    },
+    /// Encode a 12 or 24 word seed's entropy as a SeedQR payload
+    Qr {
+        /// Compact binary format, printed as hex
+        #[arg(long)]
+        compact: bool,
+    },
+}
+
+impl SeedCommand {
+    /// Run with `input` read from standard input
+    pub fn run(&self, input: &str) -> AnyaResult<Value> {
+        let (passphrase, input) = self.passphrase(input);
+        match self {
+            Self::Split {
+                threshold,
+                shares,
+                wordlist,
+                ..
+            } => {
+                let wordlist = read_wordlist(wordlist)?;
+                let seed = Zeroizing::new(from_hex(input.trim())?);
+                let ceremony = SplitCeremony::start(
+                    &seed,
+                    &passphrase,
+                    &SharePlan::single(*threshold, *shares),
+                )?;
+                let phrases = (0..usize::from(*shares))
+                    .map(|m| {
+                        let phrase = wordlist.encode(&ceremony.share(0, m)?.to_words())?;
+                        Ok(phrase.to_string())
+                    })
+                    .collect::<AnyaResult<Vec<_>>>()?;
+                Ok(json!({
+                    "fingerprint": ceremony.fingerprint()?.to_string(),
+                    "shares": phrases,
+                }))
+            }
+            Self::Verify { wordlist, .. } => {
+                let session = collect_shares(&read_wordlist(wordlist)?, input)?;
+                let progress = session.progress();
+                let fingerprint = if progress.is_complete() {
+                    let seed = session.finish(&passphrase)?;
+                    Some(seed_fingerprint(&seed)?.to_string())
+                } else {
+                    None
+                };
+                Ok(json!({ "progress": progress, "fingerprint": fingerprint }))
+            }
+            Self::Recover { wordlist, .. } => {
+                let session = collect_shares(&read_wordlist(wordlist)?, input)?;
+                let seed = session.finish(&passphrase)?;
+                Ok(json!({
+                    "seed": to_hex(&seed),
+                    "fingerprint": seed_fingerprint(&seed)?.to_string(),
+                }))
+            }
+            Self::Qr { compact } => {
+                let seed = SeedQr::from_entropy(&Zeroizing::new(from_hex(input.trim())?))?;
+                Ok(if *compact {
+                    json!({
+                        "format": SeedQrFormat::Compact,
+                        "payload": to_hex(&seed.to_compact()),
+                    })
+                } else {
+                    json!({
+                        "format": SeedQrFormat::Standard,
+                        "payload": seed.to_standard().as_str(),
+                    })
+                })
+            }
+        }
+    }
+
+    /// Split the passphrase line off `input` when `--passphrase` is given
+    fn passphrase<'a>(&self, input: &'a str) -> (Zeroizing<String>, &'a str) {
+        match self {
+            Self::Split {
+                passphrase: true, ..
+            }
+            | Self::Verify {
+                passphrase: true, ..
+            }
+            | Self::Recover {
+                passphrase: true, ..
+            } => {
+                let (line, rest) = input.split_once('\n').unwrap_or((input, ""));
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                (Zeroizing::new(line.to_owned()), rest)
+            }
+            _ => (Zeroizing::default(), input),
+        }
+    }
+}
+
+fn read_wordlist(path: &Path) -> AnyaResult<Wordlist> {
+    Wordlist::parse(&std::fs::read_to_string(path)?)
+}
+
+fn collect_shares(wordlist: &Wordlist, input: &str) -> AnyaResult<RecoverySession> {
+    let mut session = RecoverySession::new();
+    for (n, line) in input.lines().filter(|l| !l.trim().is_empty()).enumerate() {
+        wordlist
+            .decode(line)
+            .and_then(|words| session.add(&words))
+            .map_err(|e| e.context(format!("share {}", n + 1)))?;
+    }
+    Ok(session)
+}
+
+impl Command {
+    /// RPC method and parameters this command calls, or `None` for
+    /// commands run locally
+    pub fn request(&self) -> Option<(&'static str, Value)> {
+        Some(match self {
+            Self::Node(NodeCommand::Start) => ("node.start", json!({})),
+            Self::Node(NodeCommand::Stop) => ("node.stop", json!({})),
+            Self::Node(NodeCommand::Status) => ("node.status", json!({})),
+            Self::Wallet(WalletCommand::Create { name, address_type }) => (
+                "wallet.create",
+                json!({ "name": name, "address_type": address_type }),
+            ),
+            Self::Wallet(WalletCommand::Restore {
+                name,
+                source,
+                birthday,
+            }) => (
+                "wallet.restore",
+                json!({
+                    "name": name,
+                    "mnemonic": source.mnemonic,
+                    "descriptor": source.descriptor,
+                    "birthday": birthday,
+                }),
+            ),
+            Self::Wallet(WalletCommand::Send {
+                wallet,
+                address,
+                amount_sat,
+                fee_rate,
+                dry_run,
+            }) => (
+                "wallet.send",
+                json!({
+                    "wallet": wallet,
+                    "address": address,
+                    "amount_sat": amount_sat,
+                    "fee_rate": fee_rate,
+                    "broadcast": !dry_run,
+                }),
+            ),
+            Self::Wallet(WalletCommand::Receive { wallet, label }) => (
+                "wallet.receive",
+                json!({ "wallet": wallet, "label": label }),
+            ),
+            Self::Dao(DaoCommand::Proposals { status }) => {
+                ("dao.proposals", json!({ "status": status }))
+            }
+            Self::Dao(DaoCommand::Vote { proposal, choice }) => (
+                "dao.vote",
+                json!({ "proposal": proposal, "choice": choice }),
+            ),
+            Self::Metrics { prefix } => ("metrics.get", json!({ "prefix": prefix })),
+            Self::Bootstrap(args) => (
+                "node.bootstrap",
+                json!({
+                    "plan": {
+                        "network": args.network,
+                        "wallet": (!args.no_wallet).then(|| json!({
+                            "label": args.wallet_label,
+                            "script_type": args.address_type.script_type(),
+                        })),
+                        "relays": args.relays,
+                        "peers": args.peers,
+                        "discover_peers": args.discover_peers,
+                    },
+                    "step": args.step,
+                }),
+            ),
+            Self::Seed(_) => return None,
+        })
+    }
+}
+
+/// How results are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Indented `key: value` lines for humans
+    Text,
+    /// Pretty-printed JSON
+    Json,
+}
+
+/// Transport carrying RPC calls to the node
+#[async_trait]
+pub trait NodeRpc: Send + Sync {
+    /// Call `method` with named `params` and return its result
+    async fn call(&self, method: &str, params: Value) -> AnyaResult<Value>;
+}
+
+/// Run a parsed command and format its result
+pub async fn run(cli: &Cli, rpc: &dyn NodeRpc) -> AnyaResult<String> {
+    let result = match (&cli.command, cli.command.request()) {
+        (_, Some((method, params))) => rpc.call(method, params).await?,
+        (Command::Seed(command), None) => {
+            let mut input = Zeroizing::new(String::new());
+            tokio::io::AsyncReadExt::read_to_string(&mut tokio::io::stdin(), &mut input).await?;
+            command.run(&input)?
+        }
+        (_, None) => {
+            return Err(AnyaError::new(
+                ErrorCode::Internal,
+                "command has no local handler",
+            ))
+        }
+    };
+    Ok(render(&result, cli.format()))
+}
+
+/// Format an RPC result
+pub fn render(value: &Value, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+        }
+        OutputFormat::Text => {
+            let mut out = String::new();
+            render_text(value, 0, &mut out);
+            out.trim_end().to_string()
+        }
+    }
+}
+
+/// Format an error for the selected output
+pub fn render_error(err: &AnyaError, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => render(&json!({ "error": err.to_report() }), format),
+        OutputFormat::Text => format!("error: {}", err),
+    }
+}
+
+fn render_text(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if value.is_object() || value.is_array() {
+                    out.push_str(&format!("{}{}:\n", pad, key));
+                    render_text(value, indent + 1, out);
+                } else {
+                    out.push_str(&format!("{}{}: {}\n", pad, key, scalar(value)));
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                if item.is_object() || item.is_array() {
+                    out.push_str(&format!("{}-\n", pad));
+                    render_text(item, indent + 1, out);
+                } else {
+                    out.push_str(&format!("{}- {}\n", pad, scalar(item)));
+                }
+            }
+        }
+        other => out.push_str(&format!("{}{}\n", pad, scalar(other))),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[derive(Serialize)]
+struct RpcRequest<'a> {
+    jsonrpc: &'static str,
+    id: u64,
+    method: &'a str,
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    /// Structured error attached by Anya nodes
+    #[serde(default)]
+    data: Option<ErrorReport>,
+}
+
+impl RpcResponse {
+    fn into_result(self, method: &str) -> AnyaResult<Value> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(error.data.map_or_else(
+                || {
+                    AnyaError::new(
+                        ErrorCode::Internal,
+                        format!("{} failed ({}): {}", method, error.code, error.message),
+                    )
+                },
+                AnyaError::from,
+            )),
+            (Some(result), None) => Ok(result),
+            (None, None) => Ok(Value::Null),
+        }
+    }
+}
+
+/// JSON-RPC 2.0 over HTTP POST
+#[cfg(feature = "http")]
+pub struct HttpRpcClient {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+#[cfg(feature = "http")]
+impl HttpRpcClient {
+    /// Client for the endpoint at `url`, sending `token` as a bearer token
+    pub fn new(url: impl Into<String>, token: Option<String>) -> AnyaResult<Self> {
+        use crate::ResultExt;
+
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(60))
+            .build()
+            .context("building RPC HTTP client")?;
+        Ok(Self {
+            client,
+            url: url.into(),
+            token,
+            next_id: std::sync::atomic::AtomicU64::new(1),
+        })
+    }
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl NodeRpc for HttpRpcClient {
+    async fn call(&self, method: &str, params: Value) -> AnyaResult<Value> {
+        use crate::ResultExt;
+
+        let request = RpcRequest {
+            jsonrpc: "2.0",
+            id: self
+                .next_id
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            method,
+            params,
+        };
+        let mut builder = self.client.post(&self.url).json(&request);
+        if let Some(token) = &self.token {
+            builder = builder.bearer_auth(token);
+        }
+        let response = builder
+            .send()
+            .await
+            .with_context(|| format!("calling {} on {}", method, self.url))?;
+        // JSON-RPC errors arrive with non-2xx statuses too, so parse first
+        let status = response.status();
+        let body = response.bytes().await?;
+        match serde_json::from_slice::<RpcResponse>(&body) {
+            Ok(parsed) => parsed.into_result(method),
+            Err(_) if !status.is_success() => Err(AnyaError::new(
+                ErrorCode::NetworkFailure,
+                format!("{} returned {}", self.url, status),
+            )),
+            Err(err) => Err(AnyaError::from(err).context(format!("decoding {} result", method))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockRpc {
+        calls: Mutex<Vec<(String, Value)>>,
+    }
+
+    #[async_trait]
+    impl NodeRpc for MockRpc {
+        async fn call(&self, method: &str, params: Value) -> AnyaResult<Value> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((method.to_string(), params));
+            match method {
+                "wallet.receive" => Ok(json!({ "address": "bcrt1qexample", "index": 3 })),
+                _ => Err(AnyaError::not_found(format!("unknown method {}", method))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let cli = Cli::try_parse_from([
+            "anya-cli",
+            "--json",
+            "wallet",
+            "send",
+            "main",
+            "bcrt1qdest",
+            "5000",
+            "--fee-rate",
+            "2.5",
+        ])
+        .unwrap();
+        assert_eq!(cli.format(), OutputFormat::Json);
+        let (method, params) = cli.command.request().unwrap();
+        assert_eq!(method, "wallet.send");
+        assert_eq!(params["amount_sat"], 5000);
+        assert_eq!(params["broadcast"], true);
+
+        let cli = Cli::try_parse_from(["anya-cli", "dao", "vote", "prop-7", "abstain"]).unwrap();
+        assert_eq!(cli.command.request().unwrap().1["choice"], "abstain");
+
+        let cli = Cli::try_parse_from([
+            "anya-cli",
+            "bootstrap",
+            "--network",
+            "regtest",
+            "--address-type",
+            "p2tr",
+            "--relay",
+            "wss://relay.example.com",
+        ])
+        .unwrap();
+        let (method, params) = cli.command.request().unwrap();
+        assert_eq!(method, "node.bootstrap");
+        let plan: crate::bootstrap::BootstrapPlan =
+            serde_json::from_value(params["plan"].clone()).unwrap();
+        assert_eq!(plan.wallet.unwrap().script_type, ScriptType::Taproot);
+        assert_eq!(plan.relays.len(), 1);
+        assert!(Cli::try_parse_from([
+            "anya-cli",
+            "bootstrap",
+            "--no-wallet",
+            "--wallet-label",
+            "x"
+        ])
+        .is_err());
+
+        // Restore needs exactly one key source
+        assert!(Cli::try_parse_from(["anya-cli", "wallet", "restore", "w"]).is_err());
+        assert!(Cli::try_parse_from([
+            "anya-cli",
+            "wallet",
+            "restore",
+            "w",
+            "--mnemonic",
+            "abandon",
+            "--descriptor",
+            "wpkh(xpub)"
+        ])
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_renders_text_and_json() {
+        let rpc = MockRpc::default();
+        let cli = Cli::try_parse_from(["anya-cli", "wallet", "receive", "main"]).unwrap();
+        assert_eq!(
+            run(&cli, &rpc).await.unwrap(),
+            "address: bcrt1qexample\nindex: 3"
+        );
+        assert_eq!(rpc.calls.lock().unwrap()[0].1["label"], Value::Null);
+
+        let cli = Cli::try_parse_from(["anya-cli", "--json", "node", "status"]).unwrap();
+        let err = run(&cli, &rpc).await.unwrap_err();
+        let rendered: Value = serde_json::from_str(&render_error(&err, cli.format())).unwrap();
+        assert_eq!(rendered["error"]["name"], "NOT_FOUND");
+    }
+
+    #[test]
+    fn test_seed_commands_run_locally() {
+        let cli = Cli::try_parse_from(["anya-cli", "seed", "qr", "--compact"]).unwrap();
+        assert!(cli.command.request().is_none());
+        let Command::Seed(command) = &cli.command else {
+            panic!("expected a seed command");
+        };
+        let entropy = "00".repeat(16);
+        assert_eq!(command.run(&entropy).unwrap()["payload"], entropy);
+        let standard = SeedCommand::Qr { compact: false }.run(&entropy).unwrap();
+        assert_eq!(standard["payload"], format!("{}0003", "0000".repeat(11)));
+        assert!(command.run("abcd").is_err());
+
+        let cli = Cli::try_parse_from([
+            "anya-cli",
+            "seed",
+            "split",
+            "--wordlist",
+            "slip39.txt",
+            "--threshold",
+            "3",
+            "--passphrase",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Seed(SeedCommand::Split {
+                threshold: 3,
+                shares: 3,
+                passphrase: true,
+                ..
+            })
+        ));
+        let Command::Seed(command) = &cli.command else {
+            panic!("expected a seed command");
+        };
+        let (passphrase, rest) = command.passphrase("TREZOR\r\nabcd\n");
+        assert_eq!((passphrase.as_str(), rest), ("TREZOR", "abcd\n"));
+        assert!(format!("{:?}", passphrase).contains("redacted"));
+    }
+
+    #[test]
+    fn test_rpc_error_keeps_remote_code() {
+        let response: RpcResponse = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": {
+                "code": -32000,
+                "message": "insufficient funds",
+                "data": AnyaError::new(ErrorCode::InsufficientFunds, "need 5000 sat").to_report(),
+            }
+        }))
+        .unwrap();
+        let err = response.into_result("wallet.send").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InsufficientFunds);
+        assert_eq!(err.to_string(), "need 5000 sat [INSUFFICIENT_FUNDS(8001)]");
+    }
+}
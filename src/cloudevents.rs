@@ -0,0 +1,585 @@
+//! CloudEvents envelopes, event schema registry, and webhooks
+//!
+//! Every record of the [`EventStore`] can be rendered as a CloudEvents 1.0
+//! [`CloudEvent`] in the JSON structured format. The event type is
+//! `org.anya.<topic>.<kind>`, e.g. `org.anya.wallet.escrow.funded`, so
+//! chain, wallet, DAO, and ML events all share one envelope.
+//!
+//! The [`EventSchemaRegistry`] lists the event types a node emits with
+//! versioned JSON schemas of their `data`. Consumers discover types with
+//! [`EventSchemaRegistry::types`], fetch a schema by type and version, and
+//! validate payloads against it. Events name the schema they conform to in
+//! `dataschema` as `urn:anya:schema:<type>:<version>`.
+//!
+//! [`WebhookForwarder`] follows the event log and POSTs matching events to
+//! an HTTP endpoint as `application/cloudevents+json`. With a secret
+//! configured, each request carries the same `X-Anya-Signature:
+//! sha256=<hex>` HMAC of the body as the wallet transaction webhooks.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::bitcoin::escrow::ESCROW_TOPIC;
+use crate::bitcoin::vault::VAULT_TOPIC;
+use crate::events::{EventRecord, EventStore, TX_TOPIC};
+use crate::integrations::queue::check as check_schema;
+use crate::integrations::{HttpRequest, HttpTransport};
+use crate::utils::encoding::to_hex;
+use crate::utils::time::UtcDateTime;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// CloudEvents specification version produced
+pub const SPEC_VERSION: &str = "1.0";
+/// Media type of structured-mode CloudEvents
+pub const CLOUDEVENTS_CONTENT_TYPE: &str = "application/cloudevents+json";
+/// Prefix of every Anya event type
+pub const TYPE_PREFIX: &str = "org.anya.";
+
+const SCHEMA_URN_PREFIX: &str = "urn:anya:schema:";
+
+/// Event type of `kind` events in `topic`
+pub fn event_type(topic: &str, kind: &str) -> String {
+    format!("{}{}.{}", TYPE_PREFIX, topic, kind)
+}
+
+/// A CloudEvents 1.0 event in the JSON structured format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CloudEvent {
+    /// CloudEvents version, always `1.0`
+    pub specversion: String,
+    /// Identifier, unique per source
+    pub id: String,
+    /// URI of the emitting node
+    pub source: String,
+    /// Event type, `org.anya.<topic>.<kind>`
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// RFC 3339 time the event was recorded
+    pub time: String,
+    /// Media type of `data`
+    pub datacontenttype: String,
+    /// Schema `data` conforms to, when one is registered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataschema: Option<String>,
+    /// Event payload
+    pub data: Value,
+    /// Extension attribute: position in the node's event log
+    pub anyaseq: u64,
+}
+
+impl CloudEvent {
+    /// Envelope for `record` emitted by `source`, naming the latest schema
+    /// of its type in `registry`
+    pub fn from_record(record: &EventRecord, source: &str, registry: &EventSchemaRegistry) -> Self {
+        let event_type = event_type(&record.topic, &record.kind);
+        let dataschema = registry
+            .schema(&event_type, None)
+            .map(|schema| schema.urn());
+        Self {
+            specversion: SPEC_VERSION.to_string(),
+            id: record.seq.to_string(),
+            source: source.to_string(),
+            time: UtcDateTime::from_unix_ms(i64::try_from(record.timestamp_ms).unwrap_or(i64::MAX))
+                .to_string(),
+            datacontenttype: "application/json".to_string(),
+            dataschema,
+            data: record.payload.clone(),
+            anyaseq: record.seq,
+            event_type,
+        }
+    }
+
+    /// Schema version named in `dataschema`
+    pub fn schema_version(&self) -> Option<u32> {
+        self.dataschema
+            .as_deref()?
+            .strip_prefix(SCHEMA_URN_PREFIX)?
+            .strip_prefix(self.event_type.as_str())?
+            .strip_prefix(':')?
+            .parse()
+            .ok()
+    }
+}
+
+/// A version of an event type's payload schema
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventSchema {
+    /// Event type
+    pub event_type: String,
+    /// Version, starting at 1
+    pub version: u32,
+    /// What the event means
+    pub description: String,
+    /// JSON schema of `data`
+    pub schema: Value,
+}
+
+impl EventSchema {
+    /// `dataschema` URI of this version
+    pub fn urn(&self) -> String {
+        format!("{}{}:{}", SCHEMA_URN_PREFIX, self.event_type, self.version)
+    }
+}
+
+/// An event type with its schema versions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventTypeInfo {
+    /// Event type
+    pub event_type: String,
+    /// Description of the latest version
+    pub description: String,
+    /// Registered versions, oldest first
+    pub versions: Vec<u32>,
+}
+
+/// Versioned payload schemas of the event types a node emits
+#[derive(Default)]
+pub struct EventSchemaRegistry {
+    types: RwLock<BTreeMap<String, Vec<EventSchema>>>,
+}
+
+impl EventSchemaRegistry {
+    /// Registry without any types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry with the schemas of the events the node emits itself
+    pub fn builtin() -> Self {
+        let registry = Self::new();
+        let txid = json!({ "type": "string", "minLength": 64, "maxLength": 64 });
+        let tx = |extra: Value| {
+            let mut schema = json!({
+                "type": "object",
+                "required": ["event", "txid"],
+                "properties": { "event": { "type": "string" }, "txid": txid },
+            });
+            if let (Some(props), Value::Object(extra)) =
+                (schema["properties"].as_object_mut(), extra)
+            {
+                props.extend(extra);
+            }
+            schema
+        };
+        let builtin = [
+            (
+                TX_TOPIC,
+                "first_seen",
+                "A wallet transaction was first seen",
+                tx(json!({ "confirmations": { "type": "integer" } })),
+            ),
+            (
+                TX_TOPIC,
+                "confirmed",
+                "A wallet transaction reached a confirmation milestone",
+                tx(json!({ "height": { "type": "integer" } })),
+            ),
+            (
+                TX_TOPIC,
+                "replaced",
+                "A wallet transaction was replaced",
+                tx(json!({ "replacement": txid })),
+            ),
+            (
+                TX_TOPIC,
+                "evicted",
+                "A wallet transaction left the mempool",
+                tx(json!({})),
+            ),
+            (
+                TX_TOPIC,
+                "reorged",
+                "A wallet transaction was reorganized out",
+                tx(json!({})),
+            ),
+        ];
+        let escrow = json!({
+            "type": "object",
+            "required": ["escrow_id", "state", "message", "at"],
+            "properties": {
+                "escrow_id": { "type": "string" },
+                "state": { "type": "object", "required": ["state"] },
+                "message": { "type": "string" },
+                "at": { "type": "integer" },
+            },
+        });
+        let vault = json!({
+            "type": "object",
+            "required": ["vault", "outpoint", "kind", "activation_height", "blocks_left"],
+        });
+        for (topic, kind, description, schema) in builtin {
+            registry.insert(topic, kind, description, schema);
+        }
+        for kind in [
+            "awaiting_funding",
+            "funded",
+            "disputed",
+            "settling",
+            "settled",
+        ] {
+            registry.insert(
+                ESCROW_TOPIC,
+                kind,
+                "An escrow changed state",
+                escrow.clone(),
+            );
+        }
+        for kind in ["recovery_approaching", "recovery_active"] {
+            registry.insert(
+                VAULT_TOPIC,
+                kind,
+                "A vault recovery path needs attention",
+                vault.clone(),
+            );
+        }
+        registry
+    }
+
+    fn insert(&self, topic: &str, kind: &str, description: &str, schema: Value) -> u32 {
+        let event_type = event_type(topic, kind);
+        let mut types = self.types.write().unwrap_or_else(PoisonError::into_inner);
+        let versions = types.entry(event_type.clone()).or_default();
+        let version = u32::try_from(versions.len() + 1).unwrap_or(u32::MAX);
+        versions.push(EventSchema {
+            event_type,
+            version,
+            description: description.to_string(),
+            schema,
+        });
+        drop(types);
+        version
+    }
+
+    /// Register a new schema version for `kind` events in `topic`,
+    /// returning the version
+    pub fn register(
+        &self,
+        topic: &str,
+        kind: &str,
+        description: &str,
+        schema: Value,
+    ) -> AnyaResult<u32> {
+        if !schema.is_object() && !schema.is_boolean() {
+            return Err(AnyaError::invalid_input(
+                "a JSON schema must be an object or a boolean",
+            ));
+        }
+        if topic.is_empty() || kind.is_empty() || kind.contains(':') || topic.contains(':') {
+            return Err(AnyaError::invalid_input(format!(
+                "invalid event type {}.{}",
+                topic, kind
+            )));
+        }
+        Ok(self.insert(topic, kind, description, schema))
+    }
+
+    /// Every registered event type
+    pub fn types(&self) -> Vec<EventTypeInfo> {
+        self.types
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(event_type, versions)| EventTypeInfo {
+                event_type: event_type.clone(),
+                description: versions
+                    .last()
+                    .map(|s| s.description.clone())
+                    .unwrap_or_default(),
+                versions: versions.iter().map(|s| s.version).collect(),
+            })
+            .collect()
+    }
+
+    /// Schema `version` of `event_type`, or its latest version
+    pub fn schema(&self, event_type: &str, version: Option<u32>) -> Option<EventSchema> {
+        self.types
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(event_type)
+            .and_then(|versions| {
+                version.map_or_else(
+                    || versions.last().cloned(),
+                    |version| versions.iter().find(|s| s.version == version).cloned(),
+                )
+            })
+    }
+
+    /// Check an event's envelope and validate its data against the schema
+    /// it names, or the latest one of its type. Types without a schema
+    /// are accepted.
+    pub fn validate(&self, event: &CloudEvent) -> AnyaResult<()> {
+        if event.specversion != SPEC_VERSION
+            || event.id.is_empty()
+            || event.source.is_empty()
+            || event.event_type.is_empty()
+        {
+            return Err(AnyaError::invalid_input("not a CloudEvents 1.0 envelope"));
+        }
+        let version = event.schema_version();
+        if event.dataschema.is_some() && version.is_none() {
+            return Err(AnyaError::invalid_input(format!(
+                "unknown dataschema {}",
+                event.dataschema.as_deref().unwrap_or_default()
+            )));
+        }
+        let Some(schema) = self.schema(&event.event_type, version) else {
+            return version.map_or(Ok(()), |version| {
+                Err(AnyaError::not_found(format!(
+                    "schema {} v{}",
+                    event.event_type, version
+                )))
+            });
+        };
+        check_schema(&schema.schema, &event.data, "").map_err(|e| {
+            AnyaError::invalid_input(format!("{} v{}: {}", schema.event_type, schema.version, e))
+        })
+    }
+}
+
+/// Where and what a [`WebhookForwarder`] delivers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Endpoint receiving `POST`s
+    pub url: String,
+    /// `source` of the events, usually the node's public URL
+    pub source: String,
+    /// Topics delivered, each with the topics nested below it; all when
+    /// empty
+    #[serde(default)]
+    pub topics: Vec<String>,
+    /// Attempts per event before it is skipped
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubling after each failure
+    pub retry_backoff: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            source: String::new(),
+            topics: Vec::new(),
+            max_attempts: 5,
+            retry_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Delivers event log records to a webhook as CloudEvents
+pub struct WebhookForwarder {
+    config: WebhookConfig,
+    transport: Arc<dyn HttpTransport>,
+    registry: Arc<EventSchemaRegistry>,
+    secret: Option<ring::hmac::Key>,
+}
+
+impl WebhookForwarder {
+    /// Forwarder posting through `transport`, signing with `secret` when
+    /// given
+    pub fn new(
+        config: WebhookConfig,
+        transport: Arc<dyn HttpTransport>,
+        registry: Arc<EventSchemaRegistry>,
+        secret: Option<&[u8]>,
+    ) -> Self {
+        Self {
+            config,
+            transport,
+            registry,
+            secret: secret.map(|s| ring::hmac::Key::new(ring::hmac::HMAC_SHA256, s)),
+        }
+    }
+
+    /// Whether `record` is in one of the configured topics
+    pub fn wants(&self, record: &EventRecord) -> bool {
+        self.config.topics.is_empty() || self.config.topics.iter().any(|t| record.in_topic(t))
+    }
+
+    /// POST one event, failing on a transport error or non-2xx status
+    pub async fn deliver(&self, event: &CloudEvent) -> AnyaResult<()> {
+        let body = serde_json::to_vec(event)?;
+        let mut request = HttpRequest::new("POST", self.config.url.clone());
+        request
+            .headers
+            .insert("Content-Type".into(), CLOUDEVENTS_CONTENT_TYPE.into());
+        if let Some(key) = &self.secret {
+            let tag = ring::hmac::sign(key, &body);
+            request.headers.insert(
+                "X-Anya-Signature".into(),
+                format!("sha256={}", to_hex(tag.as_ref())),
+            );
+        }
+        request.body = Some(body);
+        let response = self.transport.send(request).await?;
+        if (200..300).contains(&response.status) {
+            Ok(())
+        } else {
+            Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                format!("webhook answered {} to event {}", response.status, event.id),
+            ))
+        }
+    }
+
+    /// Follow `events` from sequence number `from` until `token` is
+    /// cancelled, delivering every wanted event with retries. Returns the
+    /// sequence number to resume from.
+    pub async fn run(
+        &self,
+        events: Arc<EventStore>,
+        from: u64,
+        token: CancellationToken,
+    ) -> AnyaResult<u64> {
+        let mut subscription = events.subscribe(from);
+        loop {
+            let record = tokio::select! {
+                () = token.cancelled() => return Ok(subscription.position()),
+                record = subscription.next() => record?,
+            };
+            if !self.wants(&record) {
+                continue;
+            }
+            let event = CloudEvent::from_record(&record, &self.config.source, &self.registry);
+            let mut backoff = self.config.retry_backoff;
+            for attempt in 1..=self.config.max_attempts.max(1) {
+                match self.deliver(&event).await {
+                    Ok(()) => break,
+                    Err(e) if attempt < self.config.max_attempts => {
+                        warn!(event = %event.id, attempt, error = %e, "webhook delivery failed");
+                        tokio::select! {
+                            () = token.cancelled() => return Ok(record.seq),
+                            () = tokio::time::sleep(backoff) => {}
+                        }
+                        backoff *= 2;
+                    }
+                    Err(e) => {
+                        warn!(event = %event.id, error = %e, "webhook delivery abandoned");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventStoreConfig;
+    use crate::integrations::HttpResponse;
+    use crate::storage::memory::MemoryBackend;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// Answers with canned statuses, then 200, and records requests
+    #[derive(Default)]
+    struct Endpoint {
+        statuses: Mutex<Vec<u16>>,
+        requests: Mutex<Vec<HttpRequest>>,
+    }
+
+    #[async_trait]
+    impl HttpTransport for Endpoint {
+        async fn send(&self, request: HttpRequest) -> AnyaResult<HttpResponse> {
+            self.requests.lock().unwrap().push(request);
+            Ok(HttpResponse {
+                status: self.statuses.lock().unwrap().pop().unwrap_or(200),
+                headers: BTreeMap::new(),
+                body: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_registry_validates_versioned_payloads() {
+        let registry = EventSchemaRegistry::builtin();
+        assert!(registry
+            .types()
+            .iter()
+            .any(|t| t.event_type == "org.anya.wallet.escrow.funded"));
+        let version = registry
+            .register(
+                "dao",
+                "proposal_executed",
+                "A proposal was executed",
+                json!({ "type": "object", "required": ["proposal_id"] }),
+            )
+            .unwrap();
+        assert_eq!(version, 1);
+
+        let record = EventRecord {
+            seq: 7,
+            topic: "dao".into(),
+            kind: "proposal_executed".into(),
+            payload: json!({ "proposal_id": 3 }),
+            timestamp_ms: 1_709_164_800_000,
+        };
+        let mut event = CloudEvent::from_record(&record, "https://node.example", &registry);
+        assert_eq!(event.event_type, "org.anya.dao.proposal_executed");
+        assert_eq!(event.time, "2024-02-29T00:00:00Z");
+        assert_eq!(event.schema_version(), Some(1));
+        registry.validate(&event).unwrap();
+
+        event.data = json!({});
+        assert_eq!(
+            registry.validate(&event).unwrap_err().code(),
+            ErrorCode::InvalidInput
+        );
+        event.dataschema = Some("urn:anya:schema:org.anya.dao.proposal_executed:9".into());
+        assert_eq!(
+            registry.validate(&event).unwrap_err().code(),
+            ErrorCode::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forwarder_retries_and_filters_topics() {
+        let events = EventStore::open(EventStoreConfig::default(), Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let endpoint = Arc::new(Endpoint::default());
+        endpoint.statuses.lock().unwrap().push(503);
+        let forwarder = WebhookForwarder::new(
+            WebhookConfig {
+                url: "https://hooks.example/anya".into(),
+                source: "https://node.example".into(),
+                topics: vec!["wallet".into()],
+                retry_backoff: Duration::from_millis(1),
+                ..WebhookConfig::default()
+            },
+            endpoint.clone(),
+            Arc::new(EventSchemaRegistry::builtin()),
+            Some(b"secret"),
+        );
+        events.append("ml", "drift", json!({})).await.unwrap();
+        events
+            .append(VAULT_TOPIC, "recovery_active", json!({ "vault": "family" }))
+            .await
+            .unwrap();
+
+        let token = CancellationToken::new();
+        let stop = token.clone();
+        let watcher = endpoint.clone();
+        tokio::spawn(async move {
+            while watcher.requests.lock().unwrap().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+            stop.cancel();
+        });
+        let resume = forwarder.run(events, 0, token).await.unwrap();
+        assert_eq!(resume, 2);
+
+        let requests = endpoint.requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body, requests[1].body);
+        assert!(requests[1].headers["X-Anya-Signature"].starts_with("sha256="));
+        let event: CloudEvent = serde_json::from_slice(requests[1].body.as_ref().unwrap()).unwrap();
+        assert_eq!(event.event_type, "org.anya.wallet.vault.recovery_active");
+        assert_eq!(event.anyaseq, 1);
+    }
+}
@@ -0,0 +1,515 @@
+//! Cost accounting and per-tenant chargeback
+//!
+//! Components report what they consume as [`Usage`] records attributed to
+//! an [`Owner`], a tenant and the component acting for it. The
+//! [`CostLedger`] sums usage per calendar month (UTC) in its own storage
+//! namespace, and a [`CostModel`] prices the totals into a
+//! [`ChargebackReport`].
+//!
+//! Once a month has ended, [`CostLedger::publish_report`] renders its
+//! report as CSV and stores it in an [`ObjectStore`] as an
+//! [`ObjectKind::Report`], so it is retained under the report pinning
+//! policy. The [`CostService`] does this on a schedule.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::storage::object::{ObjectKind, ObjectRef, ObjectStore};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::percent_encode;
+use crate::utils::time::UtcDateTime;
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "costs";
+const USAGE_PREFIX: &str = "usage/";
+const REPORT_PREFIX: &str = "report/";
+
+/// A billable resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    /// CPU time in seconds
+    CpuSeconds,
+    /// Storage held over time, in gigabyte-hours
+    StorageGbHours,
+    /// Calls to external APIs
+    ApiCalls,
+    /// On-chain fees paid, in satoshis
+    OnChainFeeSat,
+}
+
+impl Resource {
+    /// Stable name used in storage keys and reports
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::CpuSeconds => "cpu_seconds",
+            Self::StorageGbHours => "storage_gb_hours",
+            Self::ApiCalls => "api_calls",
+            Self::OnChainFeeSat => "on_chain_fee_sat",
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Who consumed a resource
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Owner {
+    /// Tenant charged, e.g. a hosted DID or customer id
+    pub tenant: String,
+    /// Component that consumed the resource, e.g. `ml` or `wallet`
+    pub component: String,
+}
+
+impl Owner {
+    /// `tenant` as served by `component`
+    pub fn new(tenant: &str, component: &str) -> Self {
+        Self {
+            tenant: tenant.to_string(),
+            component: component.to_string(),
+        }
+    }
+}
+
+/// Consumption of one resource
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Consumer
+    pub owner: Owner,
+    /// Resource consumed
+    pub resource: Resource,
+    /// Amount in the resource's unit
+    pub quantity: f64,
+    /// When it was consumed, in Unix milliseconds
+    pub timestamp_ms: u64,
+}
+
+impl Usage {
+    /// Storage of `bytes` held for `held`, converted to gigabyte-hours
+    pub fn storage(owner: Owner, bytes: u64, held: Duration, timestamp_ms: u64) -> Self {
+        Self {
+            owner,
+            resource: Resource::StorageGbHours,
+            quantity: bytes as f64 / 1e9 * held.as_secs_f64() / 3600.0,
+            timestamp_ms,
+        }
+    }
+}
+
+/// Prices of each resource in one currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CostModel {
+    /// Currency code of every price, e.g. `USD`
+    pub currency: String,
+    /// Price per unit of each resource; unpriced resources cost nothing
+    pub prices: BTreeMap<Resource, f64>,
+}
+
+impl CostModel {
+    /// Cost of `quantity` units of `resource`
+    pub fn cost(&self, resource: Resource, quantity: f64) -> f64 {
+        self.prices.get(&resource).copied().unwrap_or_default() * quantity
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            currency: "USD".into(),
+            prices: BTreeMap::from([
+                (Resource::CpuSeconds, 0.000_012),
+                (Resource::StorageGbHours, 0.000_14),
+                (Resource::ApiCalls, 0.000_4),
+                // At 60,000 USD per bitcoin
+                (Resource::OnChainFeeSat, 0.000_6),
+            ]),
+        }
+    }
+}
+
+/// One priced line of a report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChargebackLine {
+    /// Consumer
+    pub owner: Owner,
+    /// Resource
+    pub resource: Resource,
+    /// Amount consumed in the month
+    pub quantity: f64,
+    /// Price of the amount
+    pub cost: f64,
+}
+
+/// Priced usage of one month
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChargebackReport {
+    /// Month as `YYYY-MM`
+    pub month: String,
+    /// Currency of every cost
+    pub currency: String,
+    /// Lines ordered by tenant, component, and resource
+    pub lines: Vec<ChargebackLine>,
+}
+
+impl ChargebackReport {
+    /// Total cost per tenant
+    pub fn tenant_totals(&self) -> BTreeMap<String, f64> {
+        let mut totals = BTreeMap::new();
+        for line in &self.lines {
+            *totals.entry(line.owner.tenant.clone()).or_default() += line.cost;
+        }
+        totals
+    }
+
+    /// Total cost of the month
+    pub fn total(&self) -> f64 {
+        self.lines.iter().map(|l| l.cost).sum()
+    }
+
+    /// CSV with a header row, one row per line, and one total row per tenant
+    pub fn to_csv(&self) -> String {
+        let escape = |field: &str| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        };
+        let mut csv = String::from("month,tenant,component,resource,quantity,cost,currency\n");
+        for line in &self.lines {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{:.6},{}\n",
+                self.month,
+                escape(&line.owner.tenant),
+                escape(&line.owner.component),
+                line.resource,
+                line.quantity,
+                line.cost,
+                escape(&self.currency)
+            ));
+        }
+        for (tenant, total) in self.tenant_totals() {
+            csv.push_str(&format!(
+                "{},{},,total,,{:.6},{}\n",
+                self.month,
+                escape(&tenant),
+                total,
+                escape(&self.currency)
+            ));
+        }
+        csv
+    }
+}
+
+/// `YYYY-MM` of a Unix millisecond timestamp
+pub fn month_of(timestamp_ms: u64) -> String {
+    let t = UtcDateTime::from_unix_ms(i64::try_from(timestamp_ms).unwrap_or(i64::MAX));
+    format!("{:04}-{:02}", t.year, t.month)
+}
+
+/// The month before `month`, both as `YYYY-MM`
+fn previous_month(month: &str) -> Option<String> {
+    let (year, month) = month.split_once('-')?;
+    let (year, month): (i32, u8) = (year.parse().ok()?, month.parse().ok()?);
+    Some(if month <= 1 {
+        format!("{:04}-12", year - 1)
+    } else {
+        format!("{:04}-{:02}", year, month - 1)
+    })
+}
+
+fn usage_key(month: &str, owner: &Owner, resource: Resource) -> String {
+    // Tenant and component names may contain the separator
+    format!(
+        "{}{}/{}/{}/{}",
+        USAGE_PREFIX,
+        month,
+        percent_encode(&owner.tenant),
+        percent_encode(&owner.component),
+        resource
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+struct UsageTotal {
+    owner: Owner,
+    resource: Resource,
+    quantity: f64,
+}
+
+/// Monthly usage totals per owner and resource
+pub struct CostLedger {
+    model: CostModel,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    /// Held while updating totals so concurrent records are not lost
+    write_lock: Mutex<()>,
+}
+
+impl CostLedger {
+    /// Open the ledger in `storage`, pricing with `model`
+    pub async fn open(model: CostModel, storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            model,
+            storage,
+            ns,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Add usage to the totals of the month it happened in
+    pub async fn record(&self, usage: &[Usage]) -> AnyaResult<()> {
+        let guard = self.write_lock.lock().await;
+        for item in usage {
+            if !item.quantity.is_finite() || item.quantity < 0.0 {
+                return Err(AnyaError::invalid_input(format!(
+                    "usage of {} by {} must be a non-negative amount",
+                    item.resource, item.owner.tenant
+                )));
+            }
+            let key = usage_key(&month_of(item.timestamp_ms), &item.owner, item.resource);
+            let mut total = match self.storage.get(&self.ns, &key).await? {
+                Some(bytes) => serde_json::from_slice(&bytes)?,
+                None => UsageTotal {
+                    owner: item.owner.clone(),
+                    resource: item.resource,
+                    quantity: 0.0,
+                },
+            };
+            total.quantity += item.quantity;
+            self.storage
+                .put(&self.ns, &key, &serde_json::to_vec(&total)?)
+                .await?;
+        }
+        drop(guard);
+        Ok(())
+    }
+
+    /// Priced usage of `month` (`YYYY-MM`) so far
+    pub async fn report(&self, month: &str) -> AnyaResult<ChargebackReport> {
+        let prefix = format!("{}{}/", USAGE_PREFIX, month);
+        let mut lines = Vec::new();
+        for (_, bytes) in self.storage.scan_prefix(&self.ns, &prefix).await? {
+            let total: UsageTotal = serde_json::from_slice(&bytes)?;
+            lines.push(ChargebackLine {
+                cost: self.model.cost(total.resource, total.quantity),
+                owner: total.owner,
+                resource: total.resource,
+                quantity: total.quantity,
+            });
+        }
+        lines.sort_by(|a, b| (&a.owner, a.resource).cmp(&(&b.owner, b.resource)));
+        Ok(ChargebackReport {
+            month: month.to_string(),
+            currency: self.model.currency.clone(),
+            lines,
+        })
+    }
+
+    /// Reference of the published report of `month`, if any
+    pub async fn published(&self, month: &str) -> AnyaResult<Option<ObjectRef>> {
+        let key = format!("{}{}", REPORT_PREFIX, month);
+        match self.storage.get(&self.ns, &key).await? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the CSV report of `month` in `objects`, returning its
+    /// reference; a month is published once
+    pub async fn publish_report(
+        &self,
+        month: &str,
+        objects: &dyn ObjectStore,
+    ) -> AnyaResult<ObjectRef> {
+        if let Some(object) = self.published(month).await? {
+            return Ok(object);
+        }
+        let report = self.report(month).await?;
+        let object = objects
+            .put(report.to_csv().as_bytes(), "text/csv", ObjectKind::Report)
+            .await?;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", REPORT_PREFIX, month),
+                &serde_json::to_vec(&object)?,
+            )
+            .await?;
+        info!(month, total = report.total(), uri = %object.uri, "published chargeback report");
+        Ok(object)
+    }
+
+    /// Publish the report of the month before the one containing `now_ms`
+    pub async fn publish_due(
+        &self,
+        now_ms: u64,
+        objects: &dyn ObjectStore,
+    ) -> AnyaResult<ObjectRef> {
+        let month = previous_month(&month_of(now_ms))
+            .ok_or_else(|| AnyaError::invalid_input("timestamp has no previous month"))?;
+        self.publish_report(&month, objects).await
+    }
+
+    /// Publish each month's report after it ends, checking every
+    /// `interval`, until `token` is cancelled
+    pub async fn run_reports(
+        self: Arc<Self>,
+        objects: Arc<dyn ObjectStore>,
+        interval: Duration,
+        token: CancellationToken,
+    ) -> AnyaResult<()> {
+        run_loop(token, interval, || {
+            let ledger = Arc::clone(&self);
+            let objects = Arc::clone(&objects);
+            async move {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or_default();
+                if let Err(e) = ledger.publish_due(now, objects.as_ref()).await {
+                    warn!(error = %e, "publishing chargeback report failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+/// Scheduled report publishing as a lifecycle-managed subsystem
+pub struct CostService {
+    ledger: Arc<CostLedger>,
+    objects: Arc<dyn ObjectStore>,
+    interval: Duration,
+}
+
+impl CostService {
+    /// Check every `interval` whether a month's report is due
+    pub fn new(ledger: Arc<CostLedger>, objects: Arc<dyn ObjectStore>, interval: Duration) -> Self {
+        Self {
+            ledger,
+            objects,
+            interval,
+        }
+    }
+}
+
+#[async_trait]
+impl Subsystem for CostService {
+    fn name(&self) -> &str {
+        "costs"
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let (ledger, objects) = (Arc::clone(&self.ledger), Arc::clone(&self.objects));
+        let interval = self.interval;
+        spawner
+            .spawn("reports", move |token| {
+                ledger.run_reports(objects, interval, token)
+            })
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::object::LocalObjectStore;
+
+    /// 2024-02-10 and 2024-03-01 in Unix milliseconds
+    const FEB: u64 = 1_707_523_200_000;
+    const MAR: u64 = 1_709_251_200_000;
+
+    #[tokio::test]
+    async fn test_monthly_chargeback() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let ledger = Arc::new(
+            CostLedger::open(CostModel::default(), storage)
+                .await
+                .unwrap(),
+        );
+        let acme_ml = Owner::new("acme, inc", "ml");
+        let use_of = |owner: &Owner, resource, quantity, timestamp_ms| Usage {
+            owner: owner.clone(),
+            resource,
+            quantity,
+            timestamp_ms,
+        };
+        ledger
+            .record(&[
+                use_of(&acme_ml, Resource::CpuSeconds, 1000.0, FEB),
+                use_of(&acme_ml, Resource::CpuSeconds, 500.0, FEB + 1),
+                use_of(
+                    &Owner::new("acme, inc", "wallet"),
+                    Resource::OnChainFeeSat,
+                    2500.0,
+                    FEB,
+                ),
+                use_of(&Owner::new("beta", "api"), Resource::ApiCalls, 100.0, FEB),
+                use_of(&acme_ml, Resource::CpuSeconds, 7.0, MAR),
+                Usage::storage(
+                    Owner::new("beta", "dwn"),
+                    2_000_000_000,
+                    Duration::from_secs(36_000),
+                    FEB,
+                ),
+            ])
+            .await
+            .unwrap();
+        assert!(ledger
+            .record(&[use_of(&acme_ml, Resource::ApiCalls, -1.0, FEB)])
+            .await
+            .is_err());
+
+        let report = ledger.report("2024-02").await.unwrap();
+        assert_eq!(report.lines.len(), 4);
+        assert_eq!(report.lines[0].quantity, 1500.0);
+        assert!((report.lines[0].cost - 0.018).abs() < 1e-9);
+        assert_eq!(report.lines[3].resource, Resource::StorageGbHours);
+        assert!((report.lines[3].quantity - 20.0).abs() < 1e-9);
+        let totals = report.tenant_totals();
+        assert!((totals["acme, inc"] - 1.518).abs() < 1e-9);
+        assert!((totals["beta"] - 0.0428).abs() < 1e-9);
+        let csv = report.to_csv();
+        assert!(csv.contains("2024-02,\"acme, inc\",ml,cpu_seconds,1500,0.018000,USD\n"));
+        assert!(csv.ends_with("2024-02,beta,,total,,0.042800,USD\n"));
+
+        let root = std::env::temp_dir().join(format!("anya-costs-{}", std::process::id()));
+        let objects: Arc<dyn ObjectStore> = Arc::new(LocalObjectStore::new(&root));
+        let object = ledger.publish_due(MAR + 5, objects.as_ref()).await.unwrap();
+        assert_eq!(object.kind, ObjectKind::Report);
+        assert_eq!(objects.get(&object).await.unwrap(), csv.as_bytes());
+        // Late usage does not change a published report
+        ledger
+            .record(&[use_of(&acme_ml, Resource::CpuSeconds, 1.0, FEB)])
+            .await
+            .unwrap();
+        assert_eq!(
+            ledger
+                .publish_due(MAR + 10, objects.as_ref())
+                .await
+                .unwrap(),
+            object
+        );
+        assert_eq!(previous_month("2024-01").unwrap(), "2023-12");
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
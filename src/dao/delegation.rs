@@ -0,0 +1,330 @@
+//! Delegated voting
+//!
+//! A member may hand their voting power to another member, for one
+//! proposal category or for every category. Delegation is transitive: if
+//! the delegate does not vote, the power moves on to whoever they delegated
+//! to, up to [`DaoConfig::max_delegation_depth`] hops. Power that reaches no
+//! voter within that depth is not counted. A member who votes directly
+//! always overrides their own delegation for that proposal.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::{DaoConfig, DaoManager, Proposal, Tally, VoteChoice};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const DELEGATION_PREFIX: &str = "delegation/";
+/// Storage marker for delegations covering every category
+const ANY_CATEGORY: &str = "*";
+
+/// A member's standing delegation of voting power
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegation {
+    /// Member handing over their power
+    pub delegator: String,
+    /// Member receiving it
+    pub delegate: String,
+    /// Proposal category covered, or every category when `None`
+    pub category: Option<String>,
+    /// Unix time the delegation was made
+    pub created_at: u64,
+}
+
+/// Voting power gathered by one delegate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DelegateStats {
+    /// Member who voted with the power
+    pub delegate: String,
+    /// Members whose power they cast, directly or through other delegates
+    pub delegators: Vec<String>,
+    /// Delegated weight cast, excluding the delegate's own
+    pub weight: u64,
+}
+
+/// How the votes on a proposal add up, with delegation analytics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GovernanceReport {
+    /// Proposal the report describes
+    pub proposal_id: u64,
+    /// Category delegations were resolved for
+    pub category: String,
+    /// Votes including delegated power
+    pub tally: Tally,
+    /// Weight of members who voted themselves
+    pub direct_weight: u64,
+    /// Weight cast on members' behalf by delegates
+    pub delegated_weight: u64,
+    /// Weight of delegations that ran past the depth limit
+    pub truncated_weight: u64,
+    /// Delegates who cast delegated power, most weight first
+    pub delegates: Vec<DelegateStats>,
+    /// Longest delegation chain that reached a voter
+    pub max_chain: u32,
+}
+
+impl DaoManager {
+    /// Delegate `delegator`'s power to `delegate` for proposals in
+    /// `category`, or in every category when `None`, replacing any earlier
+    /// delegation with the same scope
+    pub async fn delegate(
+        &self,
+        delegator: &str,
+        delegate: &str,
+        category: Option<&str>,
+        now: u64,
+    ) -> AnyaResult<Delegation> {
+        if let Some(category) = category {
+            validate_category(category)?;
+        }
+        if delegator == delegate {
+            return Err(AnyaError::invalid_input(
+                "members cannot delegate to themselves",
+            ));
+        }
+        for member in [delegator, delegate] {
+            if self.voting_power(member, now).await? == 0 {
+                return Err(AnyaError::new(
+                    ErrorCode::PermissionDenied,
+                    format!("{} is not a member", member),
+                ));
+            }
+        }
+        let _guard = self.write.lock().await;
+        let delegations = self.delegation_map().await?;
+        let depth = self.config().await?.max_delegation_depth;
+        let mut current = delegate;
+        for _ in 0..depth {
+            match lookup(&delegations, current, category.unwrap_or(ANY_CATEGORY)) {
+                Some(next) if next == delegator => {
+                    return Err(AnyaError::new(
+                        ErrorCode::Conflict,
+                        format!("{} already delegates back to {}", delegate, delegator),
+                    ));
+                }
+                Some(next) => current = next,
+                None => break,
+            }
+        }
+        let delegation = Delegation {
+            delegator: delegator.to_string(),
+            delegate: delegate.to_string(),
+            category: category.map(str::to_string),
+            created_at: now,
+        };
+        self.storage
+            .put(
+                &self.ns,
+                &delegation_key(delegator, category),
+                &serde_json::to_vec(&delegation)?,
+            )
+            .await?;
+        Ok(delegation)
+    }
+
+    /// Revoke `delegator`'s delegation for `category`, or their
+    /// all-category delegation when `None`; returns whether one existed.
+    ///
+    /// Revoking takes effect on every proposal still open for voting.
+    pub async fn revoke_delegation(
+        &self,
+        delegator: &str,
+        category: Option<&str>,
+    ) -> AnyaResult<bool> {
+        let _guard = self.write.lock().await;
+        self.storage
+            .delete(&self.ns, &delegation_key(delegator, category))
+            .await
+    }
+
+    /// Delegations made by `delegator`
+    pub async fn delegations(&self, delegator: &str) -> AnyaResult<Vec<Delegation>> {
+        self.storage
+            .scan_prefix(&self.ns, &format!("{}{}/", DELEGATION_PREFIX, delegator))
+            .await?
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    /// Tally of a proposal at `now` with delegated power resolved
+    pub async fn governance_report(&self, id: u64, now: u64) -> AnyaResult<GovernanceReport> {
+        let proposal = self.proposal(id).await?;
+        let config = self.config().await?;
+        self.resolve(&proposal, &config, now).await
+    }
+
+    pub(super) async fn resolve(
+        &self,
+        proposal: &Proposal,
+        config: &DaoConfig,
+        now: u64,
+    ) -> AnyaResult<GovernanceReport> {
+        let delegations = self.delegation_map().await?;
+        let direct: HashMap<&str, VoteChoice> = proposal
+            .votes
+            .iter()
+            .map(|v| (v.voter.as_str(), v.choice))
+            .collect();
+        let mut report = GovernanceReport {
+            proposal_id: proposal.id,
+            category: proposal.category.clone(),
+            tally: proposal.tally(),
+            direct_weight: proposal.tally().total(),
+            delegated_weight: 0,
+            truncated_weight: 0,
+            delegates: Vec::new(),
+            max_chain: 0,
+        };
+        let mut delegates: BTreeMap<String, DelegateStats> = BTreeMap::new();
+
+        for (member, weight) in self.members(now).await? {
+            if direct.contains_key(member.as_str()) {
+                continue;
+            }
+            let mut current = member.as_str();
+            let mut hops = 0;
+            let voter = loop {
+                let Some(next) = lookup(&delegations, current, &proposal.category) else {
+                    break None;
+                };
+                hops += 1;
+                if hops > config.max_delegation_depth {
+                    report.truncated_weight += weight;
+                    break None;
+                }
+                if let Some(choice) = direct.get(next) {
+                    break Some((next, *choice));
+                }
+                current = next;
+            };
+            let Some((voter, choice)) = voter else {
+                continue;
+            };
+            match choice {
+                VoteChoice::Yes => report.tally.yes += weight,
+                VoteChoice::No => report.tally.no += weight,
+                VoteChoice::Abstain => report.tally.abstain += weight,
+            }
+            report.delegated_weight += weight;
+            report.max_chain = report.max_chain.max(hops);
+            let stats = delegates
+                .entry(voter.to_string())
+                .or_insert_with(|| DelegateStats {
+                    delegate: voter.to_string(),
+                    delegators: Vec::new(),
+                    weight: 0,
+                });
+            stats.delegators.push(member.clone());
+            stats.weight += weight;
+        }
+        report.delegates = delegates.into_values().collect();
+        report.delegates.sort_by_key(|d| Reverse(d.weight));
+        Ok(report)
+    }
+
+    /// Delegate of each (delegator, category) pair
+    async fn delegation_map(&self) -> AnyaResult<HashMap<(String, String), String>> {
+        self.storage
+            .scan_prefix(&self.ns, DELEGATION_PREFIX)
+            .await?
+            .iter()
+            .map(|(_, bytes)| {
+                let d: Delegation = serde_json::from_slice(bytes)?;
+                let category = d.category.unwrap_or_else(|| ANY_CATEGORY.to_string());
+                Ok(((d.delegator, category), d.delegate))
+            })
+            .collect()
+    }
+}
+
+/// Check a proposal category name
+pub(super) fn validate_category(category: &str) -> AnyaResult<()> {
+    if category.is_empty() || category == ANY_CATEGORY || category.contains('/') {
+        return Err(AnyaError::invalid_input(format!(
+            "invalid proposal category {:?}",
+            category
+        )));
+    }
+    Ok(())
+}
+
+/// Delegate of `member` for `category`, preferring a category-specific
+/// delegation over an all-category one
+fn lookup<'a>(
+    delegations: &'a HashMap<(String, String), String>,
+    member: &str,
+    category: &str,
+) -> Option<&'a str> {
+    delegations
+        .get(&(member.to_string(), category.to_string()))
+        .or_else(|| delegations.get(&(member.to_string(), ANY_CATEGORY.to_string())))
+        .map(String::as_str)
+}
+
+fn delegation_key(delegator: &str, category: Option<&str>) -> String {
+    format!(
+        "{}{}/{}",
+        DELEGATION_PREFIX,
+        delegator,
+        category.unwrap_or(ANY_CATEGORY)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{dao, MockTreasury};
+    use super::super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn delegated_power_follows_chains_and_revocation() {
+        let dao = dao(MockTreasury::new(0)).await;
+        dao.set_voting_power("dave", 4).await.unwrap();
+        // carol -> bob for treasury, bob -> alice for everything
+        dao.delegate("carol", "bob", Some("treasury"), 0)
+            .await
+            .unwrap();
+        dao.delegate("bob", "alice", None, 0).await.unwrap();
+        dao.delegate("dave", "carol", None, 0).await.unwrap();
+        let err = dao
+            .delegate("alice", "carol", Some("treasury"), 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+
+        let action = vec![ProposalAction::ConfigChange {
+            key: "note".into(),
+            value: json!("x"),
+        }];
+        let proposal = dao
+            .propose("alice", "treasury", "Spend", "", action, 0)
+            .await
+            .unwrap();
+        dao.vote(proposal.id, "alice", VoteChoice::Yes, 1)
+            .await
+            .unwrap();
+        let report = dao.governance_report(proposal.id, 1).await.unwrap();
+        // dave -> carol -> bob -> alice is three hops, within the default
+        // depth limit
+        assert_eq!(report.tally.yes, 6 + 3 + 2 + 4);
+        assert_eq!(report.delegated_weight, 9);
+        assert_eq!(report.max_chain, 3);
+        assert_eq!(report.delegates[0].delegators.len(), 3);
+
+        // bob votes himself, which overrides his delegation and takes the
+        // power delegated to him
+        dao.vote(proposal.id, "bob", VoteChoice::No, 2)
+            .await
+            .unwrap();
+        dao.revoke_delegation("dave", None).await.unwrap();
+        let report = dao.governance_report(proposal.id, 1).await.unwrap();
+        assert_eq!((report.tally.yes, report.tally.no), (6, 5));
+        assert_eq!(report.delegates[0].delegate, "bob");
+
+        let finalized = dao.finalize(proposal.id, 100).await.unwrap();
+        assert_eq!(finalized.status, ProposalStatus::Passed);
+        assert_eq!(finalized.report, Some(report));
+    }
+}
@@ -0,0 +1,270 @@
+//! Credential-based membership
+//!
+//! Besides token-based voting power set with
+//! [`DaoManager::set_voting_power`], a DID can join by presenting verifiable
+//! credentials. Each [`MembershipRule`] in [`DaoConfig::membership`] pairs a
+//! [`CredentialRequirement`] with the voting weight it grants, either fixed
+//! or read from a numeric claim. The credentials are verified when the DID
+//! joins; the membership lapses when the first of them expires and can be
+//! renewed by joining again with fresh credentials.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{DaoManager, MEMBER_PREFIX};
+use crate::web5::credential::verify_jwt;
+use crate::web5::gate::CredentialRequirement;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const CREDENTIAL_MEMBER_PREFIX: &str = "credential_member/";
+
+/// Voting weight granted for holding a credential
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipRule {
+    /// Credential the holder must present
+    pub requirement: CredentialRequirement,
+    /// Weight granted
+    pub weight: u64,
+    /// Numeric `credentialSubject` claim to take the weight from instead,
+    /// capped at `weight`
+    #[serde(default)]
+    pub weight_claim: Option<String>,
+}
+
+impl MembershipRule {
+    /// Grant `weight` to holders of credentials meeting `requirement`
+    pub const fn new(requirement: CredentialRequirement, weight: u64) -> Self {
+        Self {
+            requirement,
+            weight,
+            weight_claim: None,
+        }
+    }
+
+    /// Take the weight from the credential's `claim`, up to the rule's weight
+    #[must_use]
+    pub fn weighted_by(mut self, claim: impl Into<String>) -> Self {
+        self.weight_claim = Some(claim.into());
+        self
+    }
+}
+
+/// A credential counted towards a membership
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialGrant {
+    /// Credential type of the rule it met
+    pub credential_type: String,
+    /// Issuer DID
+    pub issuer: String,
+    /// Weight it granted
+    pub weight: u64,
+}
+
+/// Voting weight a DID holds through credentials
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialMembership {
+    /// Member DID
+    pub did: String,
+    /// Total weight granted
+    pub weight: u64,
+    /// Credentials that granted it, one per rule met
+    pub grants: Vec<CredentialGrant>,
+    /// Unix time the credentials were verified
+    pub verified_at: u64,
+    /// Unix time the first of the credentials expires
+    pub expires_at: Option<u64>,
+}
+
+impl CredentialMembership {
+    /// Whether the membership is in effect at `now`
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        self.expires_at.map_or(true, |exp| now < exp)
+    }
+}
+
+impl DaoManager {
+    /// Admit `did` on the strength of the `presented` JWT credentials,
+    /// replacing any earlier credential membership.
+    ///
+    /// Every credential must name `did` as its subject. Fails with
+    /// [`ErrorCode::PermissionDenied`] if no membership rule is met.
+    pub async fn join_with_credentials(
+        &self,
+        did: &str,
+        presented: &[String],
+        now: u64,
+    ) -> AnyaResult<CredentialMembership> {
+        let verified: Vec<_> = presented
+            .iter()
+            .filter_map(|jwt| match verify_jwt(jwt, now) {
+                Ok(credential) => Some(credential),
+                Err(e) => {
+                    tracing::debug!(%did, error = %e, "membership credential rejected");
+                    None
+                }
+            })
+            .filter(|c| c.subject.as_deref() == Some(did))
+            .collect();
+        let mut membership = CredentialMembership {
+            did: did.to_string(),
+            weight: 0,
+            grants: Vec::new(),
+            verified_at: now,
+            expires_at: None,
+        };
+        for rule in &self.config.membership {
+            let Some(credential) = verified.iter().find(|c| rule.requirement.is_met_by(c)) else {
+                continue;
+            };
+            let weight = rule.weight_claim.as_ref().map_or(rule.weight, |claim| {
+                credential
+                    .claims
+                    .get(claim)
+                    .and_then(serde_json::Value::as_u64)
+                    .unwrap_or(0)
+                    .min(rule.weight)
+            });
+            if weight == 0 {
+                continue;
+            }
+            membership.weight += weight;
+            membership.expires_at = match (membership.expires_at, credential.expires_at) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            membership.grants.push(CredentialGrant {
+                credential_type: rule.requirement.credential_type.clone(),
+                issuer: credential.issuer.clone(),
+                weight,
+            });
+        }
+        if membership.weight == 0 {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("{} presented no credential granting membership", did),
+            ));
+        }
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", CREDENTIAL_MEMBER_PREFIX, did),
+                &serde_json::to_vec(&membership)?,
+            )
+            .await?;
+        tracing::info!(%did, weight = membership.weight, "member joined with credentials");
+        Ok(membership)
+    }
+
+    /// Remove `did`'s credential membership, e.g. after one of its
+    /// credentials was revoked; returns whether one existed
+    pub async fn revoke_membership(&self, did: &str) -> AnyaResult<bool> {
+        self.storage
+            .delete(&self.ns, &format!("{}{}", CREDENTIAL_MEMBER_PREFIX, did))
+            .await
+    }
+
+    /// `did`'s credential membership, valid or not
+    pub async fn credential_membership(
+        &self,
+        did: &str,
+    ) -> AnyaResult<Option<CredentialMembership>> {
+        self.storage
+            .get(&self.ns, &format!("{}{}", CREDENTIAL_MEMBER_PREFIX, did))
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Every member with voting power at `now`, token and credential
+    /// weight combined
+    pub(super) async fn members(&self, now: u64) -> AnyaResult<BTreeMap<String, u64>> {
+        let mut members = BTreeMap::new();
+        for (key, bytes) in self.storage.scan_prefix(&self.ns, MEMBER_PREFIX).await? {
+            let member = key.strip_prefix(MEMBER_PREFIX).unwrap_or(&key).to_string();
+            members.insert(member, serde_json::from_slice(&bytes)?);
+        }
+        for (_, bytes) in self
+            .storage
+            .scan_prefix(&self.ns, CREDENTIAL_MEMBER_PREFIX)
+            .await?
+        {
+            let membership: CredentialMembership = serde_json::from_slice(&bytes)?;
+            if membership.is_valid_at(now) {
+                *members.entry(membership.did).or_default() += membership.weight;
+            }
+        }
+        Ok(members)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::sdk::{CredentialRequest, IdentityClient};
+    use crate::storage::memory::MemoryBackend;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn credentials_grant_voting_weight_until_expiry() {
+        let issuer = IdentityClient::from_seed(&[7; 32]).unwrap();
+        let holder = IdentityClient::from_seed(&[8; 32]).unwrap().did();
+        let config = DaoConfig {
+            membership: vec![
+                MembershipRule::new(
+                    CredentialRequirement::of_type("ContributorCredential")
+                        .issued_by([issuer.did()]),
+                    5,
+                )
+                .weighted_by("level"),
+                MembershipRule::new(CredentialRequirement::of_type("KycCredential"), 1),
+            ],
+            ..DaoConfig::default()
+        };
+        let dao = DaoManager::open(config, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let issue = |kind: &str, claims, expires_at| {
+            let request = CredentialRequest {
+                subject: holder.clone(),
+                types: vec![kind.to_string()],
+                claims,
+                expires_at: Some(expires_at),
+            };
+            issuer.issue_credential(&request, 0).unwrap()
+        };
+        let contributor = issue("ContributorCredential", json!({ "level": 9 }), 2_000);
+        let kyc = issue("KycCredential", json!({}), 5_000);
+
+        let err = dao
+            .join_with_credentials("did:key:zOther", std::slice::from_ref(&contributor), 10)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        let membership = dao
+            .join_with_credentials(&holder, &[contributor, kyc], 10)
+            .await
+            .unwrap();
+        assert_eq!((membership.weight, membership.expires_at), (6, Some(2_000)));
+        assert_eq!(dao.voting_power(&holder, 1_000).await.unwrap(), 6);
+        assert_eq!(dao.voting_power(&holder, 2_000).await.unwrap(), 0);
+
+        // Credential weight adds to token weight, and a DID with no tokens
+        // can propose
+        dao.set_voting_power(&holder, 2).await.unwrap();
+        assert_eq!(dao.voting_power(&holder, 1_000).await.unwrap(), 8);
+        dao.set_voting_power(&holder, 0).await.unwrap();
+        let action = vec![ProposalAction::ConfigChange {
+            key: "note".into(),
+            value: json!(1),
+        }];
+        assert!(dao
+            .propose(&holder, "general", "Hi", "", action, 100)
+            .await
+            .is_ok());
+        assert!(dao.revoke_membership(&holder).await.unwrap());
+        assert_eq!(dao.voting_power(&holder, 100).await.unwrap(), 0);
+    }
+}
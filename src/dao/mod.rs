@@ -0,0 +1,805 @@
+//! DAO governance
+//!
+//! Members hold voting power, from tokens or from verifiable credentials
+//! (see [`DaoManager::join_with_credentials`]). A member submits a [`Proposal`] carrying a
+//! list of [`ProposalAction`]s: treasury transfers, changes to governance
+//! parameters, and workflow triggers. Members vote during the voting
+//! period, [`DaoManager::finalize`] tallies the result against the quorum
+//! and approval threshold, and a passed proposal is carried out by
+//! [`DaoManager::execute_proposal`].
+//!
+//! Execution in [`ExecutionMode::DryRun`] simulates the actions instead,
+//! without moving funds, changing parameters, or starting workflows, and
+//! returns an [`ImpactReport`]. [`DaoManager::attach_impact`] stores that
+//! report on the proposal so voters see what they are voting for. A real
+//! execution simulates first and refuses to start if any action would
+//! fail, so a proposal is never half executed because of a foreseeable
+//! problem.
+//!
+//! Members may delegate their voting power per proposal category; see
+//! [`DaoManager::delegate`]. Finalizing a proposal stores a
+//! [`GovernanceReport`] with the delegated tally and delegation analytics.
+//!
+//! A [`ProposalTemplate`] gives common proposals (fee changes, budget
+//! allocations, model promotions) typed parameters, so passed ones can be
+//! executed automatically. When a [`PolicyEngine`] is attached, every
+//! execution is enforced against it first.
+//!
+//! Governance parameters changed by proposals (`quorum`, `approval_bps`,
+//! and `voting_period_secs`) override the [`DaoConfig`] the manager was
+//! opened with.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::policy::PolicyEngine;
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+mod delegation;
+mod membership;
+mod simulation;
+mod templates;
+
+pub use self::delegation::{DelegateStats, Delegation, GovernanceReport};
+pub use self::membership::{CredentialGrant, CredentialMembership, MembershipRule};
+pub use self::simulation::{
+    ConfigImpact, ImpactReport, TransferImpact, TreasuryImpact, WorkflowImpact,
+};
+pub use self::templates::{ProposalTemplate, FEE_PARAM_PREFIX, MODEL_PROMOTION_WORKFLOW};
+
+const NAMESPACE: &str = "dao";
+const PROPOSAL_PREFIX: &str = "proposal/";
+const MEMBER_PREFIX: &str = "member/";
+const PARAM_PREFIX: &str = "param/";
+
+/// Parameter overriding [`DaoConfig::quorum`]
+pub const PARAM_QUORUM: &str = "quorum";
+/// Parameter overriding [`DaoConfig::approval_bps`]
+pub const PARAM_APPROVAL_BPS: &str = "approval_bps";
+/// Parameter overriding [`DaoConfig::voting_period`], in seconds
+pub const PARAM_VOTING_PERIOD: &str = "voting_period_secs";
+
+/// Governance settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaoConfig {
+    /// Total voting weight, abstentions included, a vote needs to count
+    pub quorum: u64,
+    /// Share of yes among yes and no votes needed to pass, in basis points
+    pub approval_bps: u32,
+    /// How long proposals are open for voting
+    pub voting_period: Duration,
+    /// Most delegation hops voting power may travel to reach a voter
+    pub max_delegation_depth: u32,
+    /// Credentials that grant voting weight
+    #[serde(default)]
+    pub membership: Vec<MembershipRule>,
+}
+
+impl Default for DaoConfig {
+    fn default() -> Self {
+        Self {
+            quorum: 1,
+            approval_bps: 5_000,
+            voting_period: Duration::from_secs(7 * 24 * 3_600),
+            max_delegation_depth: 3,
+            membership: Vec::new(),
+        }
+    }
+}
+
+/// Where a proposal is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProposalStatus {
+    /// Open for voting
+    Active,
+    /// Voting closed with approval
+    Passed,
+    /// Voting closed without approval
+    Rejected,
+    /// Approved and executed
+    Executed,
+}
+
+/// A vote on a proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteChoice {
+    /// In favor
+    Yes,
+    /// Against
+    No,
+    /// Counted towards quorum only
+    Abstain,
+}
+
+/// Something a proposal does when executed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProposalAction {
+    /// Pay from the treasury
+    TreasuryTransfer {
+        /// Destination address
+        recipient: String,
+        /// Amount in satoshis
+        amount_sat: u64,
+        /// Note recorded with the payment
+        #[serde(default)]
+        memo: Option<String>,
+    },
+    /// Set a governance parameter
+    ConfigChange {
+        /// Parameter name
+        key: String,
+        /// New value
+        value: Value,
+    },
+    /// Start a workflow
+    TriggerWorkflow {
+        /// Workflow name
+        workflow: String,
+        /// Workflow input
+        #[serde(default)]
+        input: Value,
+    },
+}
+
+/// A cast vote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    /// Member who voted
+    pub voter: String,
+    /// Choice
+    pub choice: VoteChoice,
+    /// Voting power when the vote was cast
+    pub weight: u64,
+    /// Unix time the vote was cast
+    pub cast_at: u64,
+}
+
+/// Weight of votes by choice
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    /// Weight in favor
+    pub yes: u64,
+    /// Weight against
+    pub no: u64,
+    /// Weight abstaining
+    pub abstain: u64,
+}
+
+impl Tally {
+    /// Weight of every vote
+    pub const fn total(&self) -> u64 {
+        self.yes + self.no + self.abstain
+    }
+
+    /// Whether the tally meets the quorum and approval threshold
+    pub fn passes(&self, config: &DaoConfig) -> bool {
+        let decisive = u128::from(self.yes) + u128::from(self.no);
+        self.total() >= config.quorum
+            && decisive > 0
+            && u128::from(self.yes) * 10_000 > decisive * u128::from(config.approval_bps)
+    }
+}
+
+/// A governance proposal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proposal {
+    /// Sequential identifier
+    pub id: u64,
+    /// Short title
+    pub title: String,
+    /// Rationale
+    pub description: String,
+    /// Member who submitted it
+    pub proposer: String,
+    /// Category, which decides whose delegations apply
+    pub category: String,
+    /// Actions carried out on execution, in order
+    pub actions: Vec<ProposalAction>,
+    /// Template the proposal was made from
+    #[serde(default)]
+    pub template: Option<ProposalTemplate>,
+    /// Lifecycle state
+    pub status: ProposalStatus,
+    /// Unix submission time
+    pub created_at: u64,
+    /// Unix time voting closes
+    pub voting_ends_at: u64,
+    /// Votes cast
+    pub votes: Vec<Vote>,
+    /// Simulated impact attached for voters, or the report of the
+    /// execution once executed
+    pub impact: Option<ImpactReport>,
+    /// Final tally and delegation analytics, once voting has closed
+    pub report: Option<GovernanceReport>,
+    /// Unix execution time
+    pub executed_at: Option<u64>,
+}
+
+impl Proposal {
+    /// Weight of direct votes, without delegated power
+    pub fn tally(&self) -> Tally {
+        self.votes.iter().fold(Tally::default(), |mut tally, vote| {
+            match vote.choice {
+                VoteChoice::Yes => tally.yes += vote.weight,
+                VoteChoice::No => tally.no += vote.weight,
+                VoteChoice::Abstain => tally.abstain += vote.weight,
+            }
+            tally
+        })
+    }
+}
+
+/// Whether [`DaoManager::execute_proposal`] acts or only simulates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// Simulate every action and report the impact; nothing changes
+    DryRun,
+    /// Carry out the actions of a passed proposal
+    Execute,
+}
+
+/// Funds controlled by the DAO
+#[async_trait]
+pub trait Treasury: Send + Sync {
+    /// Spendable balance in satoshis
+    async fn balance_sat(&self) -> AnyaResult<u64>;
+    /// Check that `recipient` can be paid, without paying it
+    fn validate_recipient(&self, recipient: &str) -> AnyaResult<()>;
+    /// Pay `amount_sat` to `recipient`, returning the transaction id
+    async fn transfer(
+        &self,
+        recipient: &str,
+        amount_sat: u64,
+        memo: Option<&str>,
+    ) -> AnyaResult<String>;
+}
+
+/// Workflows proposals may trigger
+#[async_trait]
+pub trait WorkflowRunner: Send + Sync {
+    /// Describe the steps `workflow` would run for `input`, failing if it
+    /// does not exist or rejects the input
+    async fn plan(&self, workflow: &str, input: &Value) -> AnyaResult<Vec<String>>;
+    /// Start `workflow`, returning the run id
+    async fn start(&self, workflow: &str, input: &Value) -> AnyaResult<String>;
+}
+
+/// Proposals, votes, and members of the DAO
+pub struct DaoManager {
+    config: DaoConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    treasury: Option<Arc<dyn Treasury>>,
+    workflows: Option<Arc<dyn WorkflowRunner>>,
+    policy: Option<Arc<PolicyEngine>>,
+    write: Mutex<()>,
+}
+
+impl DaoManager {
+    /// Open the DAO state in `storage`
+    pub async fn open(config: DaoConfig, storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            config,
+            storage,
+            ns,
+            treasury: None,
+            workflows: None,
+            policy: None,
+            write: Mutex::new(()),
+        })
+    }
+
+    /// Pay treasury transfers from `treasury`
+    pub fn with_treasury(mut self, treasury: Arc<dyn Treasury>) -> Self {
+        self.treasury = Some(treasury);
+        self
+    }
+
+    /// Start workflow triggers through `workflows`
+    pub fn with_workflows(mut self, workflows: Arc<dyn WorkflowRunner>) -> Self {
+        self.workflows = Some(workflows);
+        self
+    }
+
+    /// Enforce `policy` before executing proposals
+    pub fn with_policy(mut self, policy: Arc<PolicyEngine>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Settings in effect: the configured ones overridden by parameters
+    /// set through proposals
+    pub async fn config(&self) -> AnyaResult<DaoConfig> {
+        let mut config = self.config.clone();
+        if let Some(value) = self.param(PARAM_QUORUM).await? {
+            config.quorum = governance_u64(PARAM_QUORUM, &value)?;
+        }
+        if let Some(value) = self.param(PARAM_APPROVAL_BPS).await? {
+            config.approval_bps =
+                u32::try_from(governance_u64(PARAM_APPROVAL_BPS, &value)?).unwrap_or(u32::MAX);
+        }
+        if let Some(value) = self.param(PARAM_VOTING_PERIOD).await? {
+            config.voting_period =
+                Duration::from_secs(governance_u64(PARAM_VOTING_PERIOD, &value)?);
+        }
+        Ok(config)
+    }
+
+    /// Current value of a governance parameter
+    pub async fn param(&self, key: &str) -> AnyaResult<Option<Value>> {
+        self.storage
+            .get(&self.ns, &format!("{}{}", PARAM_PREFIX, key))
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Set `member`'s token-based voting power; zero removes it
+    pub async fn set_voting_power(&self, member: &str, weight: u64) -> AnyaResult<()> {
+        let key = format!("{}{}", MEMBER_PREFIX, member);
+        if weight == 0 {
+            self.storage.delete(&self.ns, &key).await?;
+        } else {
+            self.storage
+                .put(&self.ns, &key, &serde_json::to_vec(&weight)?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// `member`'s voting power at `now`, token and credential weight
+    /// combined; zero for non-members
+    pub async fn voting_power(&self, member: &str, now: u64) -> AnyaResult<u64> {
+        let tokens: u64 = self
+            .storage
+            .get(&self.ns, &format!("{}{}", MEMBER_PREFIX, member))
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()?
+            .unwrap_or(0);
+        let credentials = self
+            .credential_membership(member)
+            .await?
+            .filter(|m| m.is_valid_at(now))
+            .map_or(0, |m| m.weight);
+        Ok(tokens + credentials)
+    }
+
+    /// Submit a proposal in `category`, open for voting from `now`
+    pub async fn propose(
+        &self,
+        proposer: &str,
+        category: &str,
+        title: &str,
+        description: &str,
+        actions: Vec<ProposalAction>,
+        now: u64,
+    ) -> AnyaResult<Proposal> {
+        if self.voting_power(proposer, now).await? == 0 {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("{} is not a member", proposer),
+            ));
+        }
+        if title.trim().is_empty() || actions.is_empty() {
+            return Err(AnyaError::invalid_input(
+                "a proposal needs a title and at least one action",
+            ));
+        }
+        delegation::validate_category(category)?;
+        let config = self.config().await?;
+        let _guard = self.write.lock().await;
+        let id = self
+            .storage
+            .scan_prefix(&self.ns, PROPOSAL_PREFIX)
+            .await?
+            .last()
+            .map(|(_, bytes)| serde_json::from_slice::<Proposal>(bytes))
+            .transpose()?
+            .map_or(1, |p| p.id + 1);
+        let proposal = Proposal {
+            id,
+            title: title.to_string(),
+            description: description.to_string(),
+            proposer: proposer.to_string(),
+            category: category.to_string(),
+            actions,
+            template: None,
+            status: ProposalStatus::Active,
+            created_at: now,
+            voting_ends_at: now.saturating_add(config.voting_period.as_secs()),
+            votes: Vec::new(),
+            impact: None,
+            report: None,
+            executed_at: None,
+        };
+        self.save(&proposal).await?;
+        info!(proposal = id, proposer, "proposal submitted");
+        Ok(proposal)
+    }
+
+    /// A proposal by id
+    pub async fn proposal(&self, id: u64) -> AnyaResult<Proposal> {
+        let bytes = self
+            .storage
+            .get(&self.ns, &proposal_key(id))
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("proposal {}", id)))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Proposals, oldest first, optionally only those in `status`
+    pub async fn proposals(&self, status: Option<ProposalStatus>) -> AnyaResult<Vec<Proposal>> {
+        self.storage
+            .scan_prefix(&self.ns, PROPOSAL_PREFIX)
+            .await?
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice::<Proposal>(bytes)?))
+            .filter(|p| {
+                p.as_ref()
+                    .map_or(true, |p| status.map_or(true, |s| p.status == s))
+            })
+            .collect()
+    }
+
+    /// Cast `voter`'s vote with their current voting power, returning the
+    /// tally with delegated power
+    pub async fn vote(
+        &self,
+        id: u64,
+        voter: &str,
+        choice: VoteChoice,
+        now: u64,
+    ) -> AnyaResult<Tally> {
+        let weight = self.voting_power(voter, now).await?;
+        if weight == 0 {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("{} has no voting power", voter),
+            ));
+        }
+        let _guard = self.write.lock().await;
+        let mut proposal = self.proposal(id).await?;
+        if proposal.status != ProposalStatus::Active || now >= proposal.voting_ends_at {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("proposal {} is not open for voting", id),
+            ));
+        }
+        if proposal.votes.iter().any(|v| v.voter == voter) {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("{} already voted on proposal {}", voter, id),
+            ));
+        }
+        proposal.votes.push(Vote {
+            voter: voter.to_string(),
+            choice,
+            weight,
+            cast_at: now,
+        });
+        self.save(&proposal).await?;
+        let config = self.config().await?;
+        Ok(self.resolve(&proposal, &config, now).await?.tally)
+    }
+
+    /// Close voting on a proposal whose period has ended, marking it passed
+    /// or rejected
+    pub async fn finalize(&self, id: u64, now: u64) -> AnyaResult<Proposal> {
+        let config = self.config().await?;
+        let _guard = self.write.lock().await;
+        let mut proposal = self.proposal(id).await?;
+        if proposal.status != ProposalStatus::Active {
+            return Ok(proposal);
+        }
+        if now < proposal.voting_ends_at {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("voting on proposal {} is still open", id),
+            ));
+        }
+        let report = self
+            .resolve(&proposal, &config, proposal.voting_ends_at)
+            .await?;
+        proposal.status = if report.tally.passes(&config) {
+            ProposalStatus::Passed
+        } else {
+            ProposalStatus::Rejected
+        };
+        proposal.report = Some(report);
+        self.save(&proposal).await?;
+        info!(proposal = id, status = ?proposal.status, "proposal finalized");
+        Ok(proposal)
+    }
+
+    /// Simulate the proposal and store the impact report on it for voters
+    pub async fn attach_impact(&self, id: u64, now: u64) -> AnyaResult<ImpactReport> {
+        let report = self
+            .execute_proposal(id, ExecutionMode::DryRun, now)
+            .await?;
+        let _guard = self.write.lock().await;
+        let mut proposal = self.proposal(id).await?;
+        if proposal.status == ProposalStatus::Executed {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("proposal {} has already been executed", id),
+            ));
+        }
+        proposal.impact = Some(report.clone());
+        self.save(&proposal).await?;
+        Ok(report)
+    }
+
+    /// Carry out a passed proposal, or with [`ExecutionMode::DryRun`]
+    /// simulate any proposal, and report the impact.
+    ///
+    /// A real execution fails with [`ErrorCode::Conflict`] before acting if
+    /// the simulation finds a problem.
+    pub async fn execute_proposal(
+        &self,
+        id: u64,
+        mode: ExecutionMode,
+        now: u64,
+    ) -> AnyaResult<ImpactReport> {
+        let proposal = self.proposal(id).await?;
+        let mut report = self.simulate(&proposal, now).await?;
+        if mode == ExecutionMode::DryRun {
+            return Ok(report);
+        }
+
+        let _guard = self.write.lock().await;
+        let mut proposal = self.proposal(id).await?;
+        if proposal.status != ProposalStatus::Passed {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("proposal {} is {:?}, not passed", id, proposal.status),
+            ));
+        }
+        if !report.feasible {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!(
+                    "proposal {} cannot be executed: {}",
+                    id,
+                    report.warnings.join("; ")
+                ),
+            ));
+        }
+        if let Some(policy) = &self.policy {
+            policy
+                .enforce(&templates::system_action(
+                    &proposal,
+                    report.treasury.outflow_sat,
+                ))
+                .await?;
+        }
+        report.dry_run = false;
+        let mut workflow = 0;
+        for action in &proposal.actions {
+            match action {
+                ProposalAction::TreasuryTransfer {
+                    recipient,
+                    amount_sat,
+                    memo,
+                } => {
+                    let treasury = self.require_treasury()?;
+                    let txid = treasury
+                        .transfer(recipient, *amount_sat, memo.as_deref())
+                        .await?;
+                    info!(proposal = id, recipient = %recipient, amount_sat, %txid, "treasury transfer");
+                }
+                ProposalAction::ConfigChange { key, value } => {
+                    self.storage
+                        .put(
+                            &self.ns,
+                            &format!("{}{}", PARAM_PREFIX, key),
+                            &serde_json::to_vec(value)?,
+                        )
+                        .await?;
+                }
+                ProposalAction::TriggerWorkflow {
+                    workflow: name,
+                    input,
+                } => {
+                    let run = self.require_workflows()?.start(name, input).await?;
+                    report.workflows[workflow].run_id = Some(run);
+                    workflow += 1;
+                }
+            }
+        }
+        proposal.status = ProposalStatus::Executed;
+        proposal.executed_at = Some(now);
+        proposal.impact = Some(report.clone());
+        self.save(&proposal).await?;
+        info!(proposal = id, "proposal executed");
+        Ok(report)
+    }
+
+    fn require_treasury(&self) -> AnyaResult<&Arc<dyn Treasury>> {
+        self.treasury
+            .as_ref()
+            .ok_or_else(|| AnyaError::new(ErrorCode::Config, "no treasury is configured"))
+    }
+
+    fn require_workflows(&self) -> AnyaResult<&Arc<dyn WorkflowRunner>> {
+        self.workflows
+            .as_ref()
+            .ok_or_else(|| AnyaError::new(ErrorCode::Config, "no workflow runner is configured"))
+    }
+
+    async fn save(&self, proposal: &Proposal) -> AnyaResult<()> {
+        self.storage
+            .put(
+                &self.ns,
+                &proposal_key(proposal.id),
+                &serde_json::to_vec(proposal)?,
+            )
+            .await
+    }
+}
+
+fn proposal_key(id: u64) -> String {
+    format!("{}{:016x}", PROPOSAL_PREFIX, id)
+}
+
+/// Value of a numeric governance parameter
+fn governance_u64(key: &str, value: &Value) -> AnyaResult<u64> {
+    value
+        .as_u64()
+        .ok_or_else(|| AnyaError::invalid_input(format!("{} must be a non-negative integer", key)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use serde_json::json;
+    use std::sync::Mutex as StdMutex;
+
+    /// Treasury holding a fixed balance and recording transfers
+    pub struct MockTreasury {
+        pub balance: StdMutex<u64>,
+        pub transfers: StdMutex<Vec<(String, u64)>>,
+    }
+
+    impl MockTreasury {
+        pub fn new(balance: u64) -> Arc<Self> {
+            Arc::new(Self {
+                balance: StdMutex::new(balance),
+                transfers: StdMutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Treasury for MockTreasury {
+        async fn balance_sat(&self) -> AnyaResult<u64> {
+            Ok(*self.balance.lock().unwrap())
+        }
+
+        fn validate_recipient(&self, recipient: &str) -> AnyaResult<()> {
+            if recipient.starts_with("bcrt1") {
+                Ok(())
+            } else {
+                Err(AnyaError::invalid_input(format!(
+                    "bad address {}",
+                    recipient
+                )))
+            }
+        }
+
+        async fn transfer(
+            &self,
+            recipient: &str,
+            amount_sat: u64,
+            _memo: Option<&str>,
+        ) -> AnyaResult<String> {
+            *self.balance.lock().unwrap() -= amount_sat;
+            self.transfers
+                .lock()
+                .unwrap()
+                .push((recipient.to_string(), amount_sat));
+            Ok(format!("tx{}", amount_sat))
+        }
+    }
+
+    pub async fn dao(treasury: Arc<MockTreasury>) -> DaoManager {
+        let dao = DaoManager::open(
+            DaoConfig {
+                quorum: 10,
+                voting_period: Duration::from_secs(100),
+                ..DaoConfig::default()
+            },
+            Arc::new(MemoryBackend::new()),
+        )
+        .await
+        .unwrap()
+        .with_treasury(treasury);
+        for (member, weight) in [("alice", 6), ("bob", 3), ("carol", 2)] {
+            dao.set_voting_power(member, weight).await.unwrap();
+        }
+        dao
+    }
+
+    #[tokio::test]
+    async fn proposal_lifecycle_and_config_change() {
+        let treasury = MockTreasury::new(1_000_000);
+        let dao = dao(Arc::clone(&treasury)).await;
+        let proposal = dao
+            .propose(
+                "alice",
+                "governance",
+                "Lower quorum",
+                "Participation is low",
+                vec![ProposalAction::ConfigChange {
+                    key: PARAM_QUORUM.into(),
+                    value: json!(5),
+                }],
+                1_000,
+            )
+            .await
+            .unwrap();
+        assert!(dao
+            .propose(
+                "mallory",
+                "governance",
+                "x",
+                "",
+                proposal.actions.clone(),
+                1_000
+            )
+            .await
+            .is_err());
+
+        dao.vote(proposal.id, "alice", VoteChoice::Yes, 1_010)
+            .await
+            .unwrap();
+        let err = dao
+            .vote(proposal.id, "alice", VoteChoice::No, 1_020)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+        assert!(dao.finalize(proposal.id, 1_050).await.is_err());
+        // 6 < quorum of 10 until bob votes
+        let tally = dao
+            .vote(proposal.id, "bob", VoteChoice::No, 1_030)
+            .await
+            .unwrap();
+        assert_eq!(tally.total(), 9);
+        dao.vote(proposal.id, "carol", VoteChoice::Abstain, 1_040)
+            .await
+            .unwrap();
+        let finalized = dao.finalize(proposal.id, 1_100).await.unwrap();
+        assert_eq!(finalized.status, ProposalStatus::Passed);
+
+        dao.execute_proposal(proposal.id, ExecutionMode::Execute, 1_200)
+            .await
+            .unwrap();
+        assert_eq!(dao.config().await.unwrap().quorum, 5);
+        assert_eq!(
+            dao.proposals(Some(ProposalStatus::Executed))
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(dao
+            .execute_proposal(proposal.id, ExecutionMode::Execute, 1_300)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! Dry-run simulation of proposal execution
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    governance_u64, DaoManager, Proposal, ProposalAction, PARAM_APPROVAL_BPS, PARAM_QUORUM,
+    PARAM_VOTING_PERIOD,
+};
+use crate::{AnyaError, AnyaResult};
+
+/// What executing a proposal does, or would do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImpactReport {
+    /// Proposal the report describes
+    pub proposal_id: u64,
+    /// Unix time the report was produced
+    pub generated_at: u64,
+    /// Whether this is a simulation rather than a record of execution
+    pub dry_run: bool,
+    /// Effect on treasury funds
+    pub treasury: TreasuryImpact,
+    /// Governance parameters changed, in action order
+    pub config: Vec<ConfigImpact>,
+    /// Workflows triggered, in action order
+    pub workflows: Vec<WorkflowImpact>,
+    /// Problems found; any warning makes the proposal infeasible
+    pub warnings: Vec<String>,
+    /// Whether every action can be carried out
+    pub feasible: bool,
+}
+
+/// Effect of a proposal on the treasury
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreasuryImpact {
+    /// Spendable balance before execution
+    pub balance_before_sat: u64,
+    /// Total paid out
+    pub outflow_sat: u64,
+    /// Balance left afterwards, zero if the outflow exceeds the balance
+    pub balance_after_sat: u64,
+    /// Individual payments
+    pub transfers: Vec<TransferImpact>,
+}
+
+/// A treasury payment made by a proposal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferImpact {
+    /// Destination address
+    pub recipient: String,
+    /// Amount in satoshis
+    pub amount_sat: u64,
+}
+
+/// A governance parameter changed by a proposal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigImpact {
+    /// Parameter name
+    pub key: String,
+    /// Value in effect before the change
+    pub before: Option<Value>,
+    /// Value after the change
+    pub after: Value,
+}
+
+/// A workflow started by a proposal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkflowImpact {
+    /// Workflow name
+    pub workflow: String,
+    /// Steps the workflow reported it would run
+    pub steps: Vec<String>,
+    /// Run id, once started
+    pub run_id: Option<String>,
+}
+
+impl DaoManager {
+    /// Work out the effects of `proposal` without changing anything
+    pub(super) async fn simulate(&self, proposal: &Proposal, now: u64) -> AnyaResult<ImpactReport> {
+        let mut warnings = Vec::new();
+        let mut treasury = TreasuryImpact::default();
+        let mut config = Vec::new();
+        let mut workflows = Vec::new();
+        let effective = self.config().await?;
+        let mut params: HashMap<String, Option<Value>> = HashMap::new();
+
+        if let Some(funds) = &self.treasury {
+            treasury.balance_before_sat = funds.balance_sat().await?;
+        }
+        for action in &proposal.actions {
+            match action {
+                ProposalAction::TreasuryTransfer {
+                    recipient,
+                    amount_sat,
+                    ..
+                } => {
+                    match &self.treasury {
+                        Some(funds) => {
+                            if let Err(err) = funds.validate_recipient(recipient) {
+                                warnings.push(format!("transfer to {}: {}", recipient, err));
+                            }
+                        }
+                        None if treasury.transfers.is_empty() => {
+                            warnings.push("no treasury is configured".into());
+                        }
+                        None => {}
+                    }
+                    if *amount_sat == 0 {
+                        warnings.push(format!("transfer to {} is for zero sats", recipient));
+                    }
+                    treasury.outflow_sat = treasury.outflow_sat.saturating_add(*amount_sat);
+                    treasury.transfers.push(TransferImpact {
+                        recipient: recipient.clone(),
+                        amount_sat: *amount_sat,
+                    });
+                }
+                ProposalAction::ConfigChange { key, value } => {
+                    if let Err(err) = validate_param(key, value) {
+                        warnings.push(err.to_string());
+                    }
+                    let before = match params.get(key) {
+                        Some(pending) => pending.clone(),
+                        None => self
+                            .param(key)
+                            .await?
+                            .or_else(|| default_param(key, &effective)),
+                    };
+                    params.insert(key.clone(), Some(value.clone()));
+                    config.push(ConfigImpact {
+                        key: key.clone(),
+                        before,
+                        after: value.clone(),
+                    });
+                }
+                ProposalAction::TriggerWorkflow { workflow, input } => {
+                    let steps = match &self.workflows {
+                        Some(runner) => runner.plan(workflow, input).await.unwrap_or_else(|err| {
+                            warnings.push(format!("workflow {}: {}", workflow, err));
+                            Vec::new()
+                        }),
+                        None => {
+                            warnings.push(format!(
+                                "workflow {}: no workflow runner is configured",
+                                workflow
+                            ));
+                            Vec::new()
+                        }
+                    };
+                    workflows.push(WorkflowImpact {
+                        workflow: workflow.clone(),
+                        steps,
+                        run_id: None,
+                    });
+                }
+            }
+        }
+
+        if let Some(policy) = &self.policy {
+            let action = super::templates::system_action(proposal, treasury.outflow_sat);
+            for violation in policy.evaluate(&action).await {
+                warnings.push(format!("policy {}: {}", violation.rule, violation.message));
+            }
+        }
+        if self.treasury.is_some() && treasury.outflow_sat > treasury.balance_before_sat {
+            warnings.push(format!(
+                "transfers of {} sats exceed the treasury balance of {} sats",
+                treasury.outflow_sat, treasury.balance_before_sat
+            ));
+        }
+        treasury.balance_after_sat = treasury
+            .balance_before_sat
+            .saturating_sub(treasury.outflow_sat);
+        Ok(ImpactReport {
+            proposal_id: proposal.id,
+            generated_at: now,
+            dry_run: true,
+            treasury,
+            config,
+            workflows,
+            feasible: warnings.is_empty(),
+            warnings,
+        })
+    }
+}
+
+/// Reject values the manager could not apply to a known parameter
+fn validate_param(key: &str, value: &Value) -> AnyaResult<()> {
+    match key {
+        PARAM_QUORUM | PARAM_VOTING_PERIOD => governance_u64(key, value).map(drop),
+        PARAM_APPROVAL_BPS => match governance_u64(key, value)? {
+            0..=10_000 => Ok(()),
+            _ => Err(AnyaError::invalid_input(format!(
+                "{} must be at most 10000",
+                key
+            ))),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Value a known parameter has when no proposal has set it
+fn default_param(key: &str, config: &super::DaoConfig) -> Option<Value> {
+    match key {
+        PARAM_QUORUM => Some(config.quorum.into()),
+        PARAM_APPROVAL_BPS => Some(config.approval_bps.into()),
+        PARAM_VOTING_PERIOD => Some(config.voting_period.as_secs().into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{dao, MockTreasury};
+    use super::super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn dry_run_reports_impact_without_side_effects() {
+        let treasury = MockTreasury::new(50_000);
+        let dao = dao(Arc::clone(&treasury)).await;
+        let proposal = dao
+            .propose(
+                "alice",
+                "treasury",
+                "Fund grants",
+                "",
+                vec![
+                    ProposalAction::TreasuryTransfer {
+                        recipient: "bcrt1qgrant".into(),
+                        amount_sat: 30_000,
+                        memo: None,
+                    },
+                    ProposalAction::ConfigChange {
+                        key: PARAM_APPROVAL_BPS.into(),
+                        value: json!(6_000),
+                    },
+                ],
+                0,
+            )
+            .await
+            .unwrap();
+
+        let report = dao.attach_impact(proposal.id, 10).await.unwrap();
+        assert!(report.dry_run && report.feasible);
+        assert_eq!(report.treasury.balance_after_sat, 20_000);
+        assert_eq!(report.config[0].before, Some(json!(5_000)));
+        assert!(treasury.transfers.lock().unwrap().is_empty());
+        assert_eq!(dao.param(PARAM_APPROVAL_BPS).await.unwrap(), None);
+        assert_eq!(
+            dao.proposal(proposal.id).await.unwrap().impact,
+            Some(report)
+        );
+
+        // Spending more than the treasury holds is reported and blocks
+        // execution even once passed
+        *treasury.balance.lock().unwrap() = 10_000;
+        dao.vote(proposal.id, "alice", VoteChoice::Yes, 20)
+            .await
+            .unwrap();
+        dao.vote(proposal.id, "bob", VoteChoice::Yes, 20)
+            .await
+            .unwrap();
+        dao.vote(proposal.id, "carol", VoteChoice::Yes, 20)
+            .await
+            .unwrap();
+        dao.finalize(proposal.id, 100).await.unwrap();
+        let report = dao
+            .execute_proposal(proposal.id, ExecutionMode::DryRun, 110)
+            .await
+            .unwrap();
+        assert!(!report.feasible);
+        let err = dao
+            .execute_proposal(proposal.id, ExecutionMode::Execute, 120)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+
+        *treasury.balance.lock().unwrap() = 40_000;
+        let report = dao
+            .execute_proposal(proposal.id, ExecutionMode::Execute, 130)
+            .await
+            .unwrap();
+        assert!(!report.dry_run);
+        assert_eq!(
+            *treasury.transfers.lock().unwrap(),
+            vec![("bcrt1qgrant".to_string(), 30_000)]
+        );
+        assert_eq!(dao.config().await.unwrap().approval_bps, 6_000);
+    }
+}
@@ -0,0 +1,278 @@
+//! Typed proposal templates
+//!
+//! A [`ProposalTemplate`] describes a common kind of proposal with
+//! machine-readable parameters instead of free text. The template fixes the
+//! proposal's category, title, and actions, and a passed templated proposal
+//! can be executed without a human reading it:
+//! [`DaoManager::execute_passed`] runs every such proposal, each checked by
+//! the policy engine as a `dao.<template>` [`SystemAction`].
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+
+use super::{DaoManager, ExecutionMode, ImpactReport, Proposal, ProposalAction, ProposalStatus};
+use crate::policy::SystemAction;
+use crate::{AnyaError, AnyaResult};
+
+/// Prefix of the governance parameters fee changes set
+pub const FEE_PARAM_PREFIX: &str = "fee.";
+/// Workflow model promotions trigger
+pub const MODEL_PROMOTION_WORKFLOW: &str = "ml.promote_model";
+
+/// A proposal kind with structured parameters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "template", rename_all = "snake_case")]
+pub enum ProposalTemplate {
+    /// Set a fee, stored as the governance parameter `fee.<fee>`
+    FeeChange {
+        /// Fee name, e.g. `withdrawal_bps`
+        fee: String,
+        /// New value in the fee's unit
+        value: u64,
+    },
+    /// Pay a budget from the treasury
+    BudgetAllocation {
+        /// Destination address
+        recipient: String,
+        /// Amount in satoshis
+        amount_sat: u64,
+        /// What the budget is for
+        purpose: String,
+    },
+    /// Promote a model version to production
+    ModelPromotion {
+        /// Model name
+        model: String,
+        /// Version to promote
+        version: String,
+    },
+}
+
+impl ProposalTemplate {
+    /// Template name, used in policy action kinds
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::FeeChange { .. } => "fee_change",
+            Self::BudgetAllocation { .. } => "budget_allocation",
+            Self::ModelPromotion { .. } => "model_promotion",
+        }
+    }
+
+    /// Proposal category, which decides whose delegations apply
+    pub const fn category(&self) -> &'static str {
+        match self {
+            Self::FeeChange { .. } => "fees",
+            Self::BudgetAllocation { .. } => "treasury",
+            Self::ModelPromotion { .. } => "models",
+        }
+    }
+
+    /// Generated proposal title
+    pub fn title(&self) -> String {
+        match self {
+            Self::FeeChange { fee, value } => format!("Set fee {} to {}", fee, value),
+            Self::BudgetAllocation {
+                amount_sat,
+                purpose,
+                ..
+            } => format!("Allocate {} sats for {}", amount_sat, purpose),
+            Self::ModelPromotion { model, version } => {
+                format!("Promote {} {} to production", model, version)
+            }
+        }
+    }
+
+    /// Check the parameters
+    pub fn validate(&self) -> AnyaResult<()> {
+        let valid = match self {
+            Self::FeeChange { fee, .. } => !fee.is_empty() && !fee.contains('/'),
+            Self::BudgetAllocation {
+                recipient,
+                amount_sat,
+                purpose,
+            } => !recipient.is_empty() && *amount_sat > 0 && !purpose.trim().is_empty(),
+            Self::ModelPromotion { model, version } => !model.is_empty() && !version.is_empty(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(AnyaError::invalid_input(format!(
+                "invalid {} parameters",
+                self.kind()
+            )))
+        }
+    }
+
+    /// Actions the proposal carries out
+    pub fn actions(&self) -> Vec<ProposalAction> {
+        match self {
+            Self::FeeChange { fee, value } => vec![ProposalAction::ConfigChange {
+                key: format!("{}{}", FEE_PARAM_PREFIX, fee),
+                value: (*value).into(),
+            }],
+            Self::BudgetAllocation {
+                recipient,
+                amount_sat,
+                purpose,
+            } => vec![ProposalAction::TreasuryTransfer {
+                recipient: recipient.clone(),
+                amount_sat: *amount_sat,
+                memo: Some(purpose.clone()),
+            }],
+            Self::ModelPromotion { model, version } => vec![ProposalAction::TriggerWorkflow {
+                workflow: MODEL_PROMOTION_WORKFLOW.to_string(),
+                input: json!({ "model": model, "version": version }),
+            }],
+        }
+    }
+}
+
+impl DaoManager {
+    /// Submit a proposal from `template`
+    pub async fn propose_template(
+        &self,
+        proposer: &str,
+        template: ProposalTemplate,
+        description: &str,
+        now: u64,
+    ) -> AnyaResult<Proposal> {
+        template.validate()?;
+        let mut proposal = self
+            .propose(
+                proposer,
+                template.category(),
+                &template.title(),
+                description,
+                template.actions(),
+                now,
+            )
+            .await?;
+        let _guard = self.write.lock().await;
+        proposal.template = Some(template);
+        self.save(&proposal).await?;
+        Ok(proposal)
+    }
+
+    /// Execute every passed proposal made from a template, returning the
+    /// reports of those executed. Proposals that fail stay passed and are
+    /// retried on the next call.
+    pub async fn execute_passed(&self, now: u64) -> AnyaResult<Vec<ImpactReport>> {
+        let mut executed = Vec::new();
+        for proposal in self.proposals(Some(ProposalStatus::Passed)).await? {
+            if proposal.template.is_none() {
+                continue;
+            }
+            match self
+                .execute_proposal(proposal.id, ExecutionMode::Execute, now)
+                .await
+            {
+                Ok(report) => executed.push(report),
+                Err(e) => warn!(proposal = proposal.id, error = %e, "automatic execution failed"),
+            }
+        }
+        Ok(executed)
+    }
+}
+
+/// The policy engine's view of executing `proposal`
+pub(super) fn system_action(proposal: &Proposal, outflow_sat: u64) -> SystemAction {
+    let kind = proposal
+        .template
+        .as_ref()
+        .map_or("proposal", ProposalTemplate::kind);
+    let mut attributes = match proposal.template.as_ref().map(serde_json::to_value) {
+        Some(Ok(Value::Object(parameters))) => parameters,
+        _ => serde_json::Map::new(),
+    };
+    attributes.insert("proposal_id".into(), proposal.id.into());
+    attributes.insert("category".into(), proposal.category.clone().into());
+    attributes.insert("outflow_sat".into(), outflow_sat.into());
+    SystemAction {
+        kind: format!("dao.{}", kind),
+        actor: proposal.proposer.clone(),
+        attributes: Value::Object(attributes),
+        approvals: proposal
+            .votes
+            .iter()
+            .filter(|v| v.choice == super::VoteChoice::Yes)
+            .map(|v| v.voter.clone())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{dao, MockTreasury};
+    use super::super::*;
+    use super::*;
+    use crate::policy::{PolicyConfig, PolicyEngine};
+    use crate::storage::memory::MemoryBackend;
+
+    #[tokio::test]
+    async fn passed_templates_execute_under_policy() {
+        let treasury = MockTreasury::new(1_000_000);
+        let policy = PolicyEngine::open(PolicyConfig::default(), Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        policy
+            .load_json(
+                r#"[{"name": "big-budgets", "actions": ["dao.budget_allocation"],
+                     "when": [{"field": "amount_sat", "op": "gt", "value": 100000}],
+                     "effect": {"require_approvals": 2}, "message": "two approvers"}]"#,
+            )
+            .await
+            .unwrap();
+        let dao = dao(Arc::clone(&treasury))
+            .await
+            .with_policy(Arc::new(policy));
+
+        let fee = dao
+            .propose_template(
+                "alice",
+                ProposalTemplate::FeeChange {
+                    fee: "withdrawal_bps".into(),
+                    value: 25,
+                },
+                "",
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(fee.category, "fees");
+        let budget = dao
+            .propose_template(
+                "alice",
+                ProposalTemplate::BudgetAllocation {
+                    recipient: "bcrt1qdocs".into(),
+                    amount_sat: 200_000,
+                    purpose: "documentation".into(),
+                },
+                "",
+                0,
+            )
+            .await
+            .unwrap();
+        for id in [fee.id, budget.id] {
+            dao.vote(id, "alice", VoteChoice::Yes, 1).await.unwrap();
+            dao.vote(id, "bob", VoteChoice::Yes, 1).await.unwrap();
+            dao.vote(id, "carol", VoteChoice::No, 1).await.unwrap();
+            dao.finalize(id, 100).await.unwrap();
+        }
+
+        // Only bob approved the budget besides its proposer
+        let report = dao.attach_impact(budget.id, 100).await.unwrap();
+        assert!(report.warnings.iter().any(|w| w.contains("big-budgets")));
+        let executed = dao.execute_passed(200).await.unwrap();
+        assert_eq!(executed.len(), 1);
+        assert_eq!(
+            dao.param("fee.withdrawal_bps").await.unwrap(),
+            Some(25.into())
+        );
+        assert!(treasury.transfers.lock().unwrap().is_empty());
+        assert_eq!(
+            dao.proposal(budget.id).await.unwrap().status,
+            ProposalStatus::Passed
+        );
+    }
+}
@@ -0,0 +1,609 @@
+//! Structured error taxonomy for the Anya system
+//!
+//! Every error carries a stable [`ErrorCode`] that downstream consumers (the
+//! API server, FFI bridge, CLI) can map without parsing messages. Errors can
+//! be wrapped with additional context while preserving the original source
+//! chain, and each code has a fixed retryability classification.
+
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Broad error category, used for routing and metrics labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCategory {
+    /// Machine learning subsystem
+    ML,
+    /// Web5 and decentralized identity
+    Web5,
+    /// Bitcoin and Lightning
+    Bitcoin,
+    /// Persistence layer
+    Storage,
+    /// Network and RPC transport
+    Network,
+    /// Authentication and authorization
+    Security,
+    /// Caller supplied invalid input
+    Validation,
+    /// Runtime and infrastructure
+    System,
+}
+
+impl ErrorCategory {
+    /// Lowercase label suitable for metrics and logs
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::ML => "ml",
+            Self::Web5 => "web5",
+            Self::Bitcoin => "bitcoin",
+            Self::Storage => "storage",
+            Self::Network => "network",
+            Self::Security => "security",
+            Self::Validation => "validation",
+            Self::System => "system",
+        }
+    }
+}
+
+/// Stable error codes.
+///
+/// Numeric values are part of the public contract: they are exposed over the
+/// API and FFI boundaries and must never be reused or renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum ErrorCode {
+    /// Unclassified internal error
+    Internal = 1000,
+    /// Operation timed out
+    Timeout = 1001,
+    /// Component is shutting down or not running
+    Unavailable = 1002,
+    /// Configuration is missing or inconsistent
+    Config = 1003,
+    /// I/O failure
+    Io = 1004,
+    /// Input failed validation
+    InvalidInput = 2000,
+    /// Requested entity does not exist
+    NotFound = 2001,
+    /// Entity already exists or conflicts with current state
+    Conflict = 2002,
+    /// Serialization or deserialization failed
+    Serialization = 2003,
+    /// Caller is not authenticated
+    Unauthenticated = 3000,
+    /// Caller lacks permission
+    PermissionDenied = 3001,
+    /// Rate limit exceeded
+    RateLimited = 3002,
+    /// Storage backend failure
+    StorageFailure = 4000,
+    /// Remote peer or service failure
+    NetworkFailure = 5000,
+    /// Generic ML failure
+    MLFailure = 6000,
+    /// Model not found or not loaded
+    ModelUnavailable = 6001,
+    /// Generic Web5 failure
+    Web5Failure = 7000,
+    /// DID could not be resolved
+    DidResolution = 7001,
+    /// Generic Bitcoin failure
+    BitcoinFailure = 8000,
+    /// Wallet has insufficient funds
+    InsufficientFunds = 8001,
+    /// Transaction rejected by policy or consensus
+    TransactionRejected = 8002,
+}
+
+impl ErrorCode {
+    /// Numeric code
+    pub const fn as_u32(self) -> u32 {
+        self as u32
+    }
+
+    /// Code with the given numeric value, as received from a remote node
+    pub const fn from_u32(code: u32) -> Option<Self> {
+        Some(match code {
+            1000 => Self::Internal,
+            1001 => Self::Timeout,
+            1002 => Self::Unavailable,
+            1003 => Self::Config,
+            1004 => Self::Io,
+            2000 => Self::InvalidInput,
+            2001 => Self::NotFound,
+            2002 => Self::Conflict,
+            2003 => Self::Serialization,
+            3000 => Self::Unauthenticated,
+            3001 => Self::PermissionDenied,
+            3002 => Self::RateLimited,
+            4000 => Self::StorageFailure,
+            5000 => Self::NetworkFailure,
+            6000 => Self::MLFailure,
+            6001 => Self::ModelUnavailable,
+            7000 => Self::Web5Failure,
+            7001 => Self::DidResolution,
+            8000 => Self::BitcoinFailure,
+            8001 => Self::InsufficientFunds,
+            8002 => Self::TransactionRejected,
+            _ => return None,
+        })
+    }
+
+    /// Symbolic name, stable across releases
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Internal => "INTERNAL",
+            Self::Timeout => "TIMEOUT",
+            Self::Unavailable => "UNAVAILABLE",
+            Self::Config => "CONFIG",
+            Self::Io => "IO",
+            Self::InvalidInput => "INVALID_INPUT",
+            Self::NotFound => "NOT_FOUND",
+            Self::Conflict => "CONFLICT",
+            Self::Serialization => "SERIALIZATION",
+            Self::Unauthenticated => "UNAUTHENTICATED",
+            Self::PermissionDenied => "PERMISSION_DENIED",
+            Self::RateLimited => "RATE_LIMITED",
+            Self::StorageFailure => "STORAGE_FAILURE",
+            Self::NetworkFailure => "NETWORK_FAILURE",
+            Self::MLFailure => "ML_FAILURE",
+            Self::ModelUnavailable => "MODEL_UNAVAILABLE",
+            Self::Web5Failure => "WEB5_FAILURE",
+            Self::DidResolution => "DID_RESOLUTION",
+            Self::BitcoinFailure => "BITCOIN_FAILURE",
+            Self::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            Self::TransactionRejected => "TRANSACTION_REJECTED",
+        }
+    }
+
+    /// Category this code belongs to
+    pub const fn category(self) -> ErrorCategory {
+        match self {
+            Self::Internal | Self::Timeout | Self::Unavailable | Self::Config | Self::Io => {
+                ErrorCategory::System
+            }
+            Self::InvalidInput | Self::NotFound | Self::Conflict | Self::Serialization => {
+                ErrorCategory::Validation
+            }
+            Self::Unauthenticated | Self::PermissionDenied | Self::RateLimited => {
+                ErrorCategory::Security
+            }
+            Self::StorageFailure => ErrorCategory::Storage,
+            Self::NetworkFailure => ErrorCategory::Network,
+            Self::MLFailure | Self::ModelUnavailable => ErrorCategory::ML,
+            Self::Web5Failure | Self::DidResolution => ErrorCategory::Web5,
+            Self::BitcoinFailure | Self::InsufficientFunds | Self::TransactionRejected => {
+                ErrorCategory::Bitcoin
+            }
+        }
+    }
+
+    /// Whether an operation failing with this code may succeed if retried
+    pub const fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            Self::Timeout
+                | Self::Unavailable
+                | Self::Io
+                | Self::RateLimited
+                | Self::StorageFailure
+                | Self::NetworkFailure
+                | Self::DidResolution
+        )
+    }
+
+    /// HTTP status the API server should use for this code
+    pub const fn http_status(self) -> u16 {
+        match self {
+            Self::InvalidInput | Self::Serialization => 400,
+            Self::Unauthenticated => 401,
+            Self::PermissionDenied => 403,
+            Self::NotFound => 404,
+            Self::Conflict => 409,
+            Self::InsufficientFunds | Self::TransactionRejected => 422,
+            Self::RateLimited => 429,
+            Self::Unavailable | Self::ModelUnavailable => 503,
+            Self::Timeout => 504,
+            Self::NetworkFailure | Self::DidResolution => 502,
+            _ => 500,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.as_str(), self.as_u32())
+    }
+}
+
+/// Boxed source error
+pub type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// Detailed error with code, context chain, and optional source
+#[derive(Debug)]
+pub struct ErrorDetail {
+    /// Stable error code
+    pub code: ErrorCode,
+    /// Human readable message
+    pub message: String,
+    /// Context frames, innermost first
+    pub context: Vec<String>,
+    /// Underlying cause
+    pub source: Option<BoxError>,
+}
+
+/// Core error type for the Anya system
+#[derive(Debug)]
+pub enum AnyaError {
+    /// ML-related errors
+    ML(String),
+    /// Web5-related errors
+    Web5(String),
+    /// Bitcoin-related errors
+    Bitcoin(String),
+    /// General system errors
+    System(String),
+    /// Structured error with code, context, and source chain
+    Detailed(Box<ErrorDetail>),
+}
+
+impl AnyaError {
+    /// Create a structured error with the given code
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::Detailed(Box::new(ErrorDetail {
+            code,
+            message: message.into(),
+            context: Vec::new(),
+            source: None,
+        }))
+    }
+
+    /// Create a structured error wrapping a source error
+    pub fn with_source(
+        code: ErrorCode,
+        message: impl Into<String>,
+        source: impl Into<BoxError>,
+    ) -> Self {
+        Self::Detailed(Box::new(ErrorDetail {
+            code,
+            message: message.into(),
+            context: Vec::new(),
+            source: Some(source.into()),
+        }))
+    }
+
+    /// Shorthand for [`ErrorCode::InvalidInput`]
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidInput, message)
+    }
+
+    /// Shorthand for [`ErrorCode::NotFound`]
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    /// Stable error code
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ML(_) => ErrorCode::MLFailure,
+            Self::Web5(_) => ErrorCode::Web5Failure,
+            Self::Bitcoin(_) => ErrorCode::BitcoinFailure,
+            Self::System(_) => ErrorCode::Internal,
+            Self::Detailed(d) => d.code,
+        }
+    }
+
+    /// Error category
+    pub fn category(&self) -> ErrorCategory {
+        self.code().category()
+    }
+
+    /// Whether the failed operation may be retried
+    pub fn is_retryable(&self) -> bool {
+        self.code().is_retryable()
+    }
+
+    /// Context frames attached via [`Self::context`], innermost first
+    pub fn context_frames(&self) -> &[String] {
+        match self {
+            Self::Detailed(d) => &d.context,
+            _ => &[],
+        }
+    }
+
+    /// Attach a context frame describing what was being attempted
+    #[must_use]
+    pub fn context(self, frame: impl Into<String>) -> Self {
+        let mut detail = self.into_detail();
+        detail.context.push(frame.into());
+        Self::Detailed(detail)
+    }
+
+    /// Replace the error code while keeping message, context, and source
+    #[must_use]
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        let mut detail = self.into_detail();
+        detail.code = code;
+        Self::Detailed(detail)
+    }
+
+    fn into_detail(self) -> Box<ErrorDetail> {
+        let code = self.code();
+        match self {
+            Self::Detailed(d) => d,
+            Self::ML(m) | Self::Web5(m) | Self::Bitcoin(m) | Self::System(m) => {
+                Box::new(ErrorDetail {
+                    code,
+                    message: m,
+                    context: Vec::new(),
+                    source: None,
+                })
+            }
+        }
+    }
+
+    /// Serializable representation for API and FFI consumers
+    pub fn to_report(&self) -> ErrorReport {
+        let mut causes = Vec::new();
+        let mut source = self.source();
+        while let Some(err) = source {
+            causes.push(err.to_string());
+            source = err.source();
+        }
+        ErrorReport {
+            code: self.code().as_u32(),
+            name: self.code().as_str().to_string(),
+            category: self.category(),
+            message: self.to_string(),
+            retryable: self.is_retryable(),
+            causes,
+        }
+    }
+}
+
+impl fmt::Display for AnyaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ML(msg) => write!(f, "ML error: {}", msg),
+            Self::Web5(msg) => write!(f, "Web5 error: {}", msg),
+            Self::Bitcoin(msg) => write!(f, "Bitcoin error: {}", msg),
+            Self::System(msg) => write!(f, "System error: {}", msg),
+            Self::Detailed(d) => {
+                for frame in d.context.iter().rev() {
+                    write!(f, "{}: ", frame)?;
+                }
+                write!(f, "{} [{}]", d.message, d.code)
+            }
+        }
+    }
+}
+
+impl Error for AnyaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Detailed(d) => d
+                .source
+                .as_ref()
+                .map(|s| s.as_ref() as &(dyn Error + 'static)),
+            _ => None,
+        }
+    }
+}
+
+/// Serializable error payload exposed across API and FFI boundaries
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorReport {
+    /// Numeric error code
+    pub code: u32,
+    /// Symbolic error code
+    pub name: String,
+    /// Error category
+    pub category: ErrorCategory,
+    /// Display message including context
+    pub message: String,
+    /// Whether the caller may retry
+    pub retryable: bool,
+    /// Messages of the source chain, outermost first
+    pub causes: Vec<String>,
+}
+
+impl From<ErrorReport> for AnyaError {
+    /// Rebuild an error received from a remote node, keeping its code
+    fn from(report: ErrorReport) -> Self {
+        let code = ErrorCode::from_u32(report.code).unwrap_or(ErrorCode::Internal);
+        let suffix = format!(" [{}]", code);
+        let message = report
+            .message
+            .strip_suffix(suffix.as_str())
+            .unwrap_or(&report.message);
+        Self::new(code, message)
+    }
+}
+
+/// Extension trait for attaching context to results
+pub trait ResultExt<T> {
+    /// Attach a context frame to the error, if any
+    fn context(self, frame: impl Into<String>) -> AnyaResult<T>;
+
+    /// Attach a lazily built context frame to the error, if any
+    fn with_context<F, S>(self, f: F) -> AnyaResult<T>
+    where
+        F: FnOnce() -> S,
+        S: Into<String>;
+}
+
+impl<T, E: Into<AnyaError>> ResultExt<T> for Result<T, E> {
+    fn context(self, frame: impl Into<String>) -> AnyaResult<T> {
+        self.map_err(|e| e.into().context(frame))
+    }
+
+    fn with_context<F, S>(self, f: F) -> AnyaResult<T>
+    where
+        F: FnOnce() -> S,
+        S: Into<String>,
+    {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+/// Result type for Anya operations
+pub type AnyaResult<T> = Result<T, AnyaError>;
+
+impl From<std::io::Error> for AnyaError {
+    fn from(err: std::io::Error) -> Self {
+        let code = match err.kind() {
+            std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            std::io::ErrorKind::TimedOut => ErrorCode::Timeout,
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput => {
+                ErrorCode::InvalidInput
+            }
+            _ => ErrorCode::Io,
+        };
+        Self::with_source(code, "I/O operation failed", err)
+    }
+}
+
+impl From<serde_json::Error> for AnyaError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::with_source(
+            ErrorCode::Serialization,
+            "JSON (de)serialization failed",
+            err,
+        )
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for AnyaError {
+    fn from(err: tokio::time::error::Elapsed) -> Self {
+        Self::with_source(ErrorCode::Timeout, "operation timed out", err)
+    }
+}
+
+impl From<::bitcoin::consensus::encode::Error> for AnyaError {
+    fn from(err: ::bitcoin::consensus::encode::Error) -> Self {
+        Self::with_source(
+            ErrorCode::InvalidInput,
+            "bitcoin consensus decoding failed",
+            err,
+        )
+    }
+}
+
+impl From<::bitcoin::address::Error> for AnyaError {
+    fn from(err: ::bitcoin::address::Error) -> Self {
+        Self::with_source(ErrorCode::InvalidInput, "invalid bitcoin address", err)
+    }
+}
+
+impl From<::bitcoin::psbt::Error> for AnyaError {
+    fn from(err: ::bitcoin::psbt::Error) -> Self {
+        Self::with_source(ErrorCode::InvalidInput, "invalid PSBT", err)
+    }
+}
+
+impl From<::bitcoin::bip32::Error> for AnyaError {
+    fn from(err: ::bitcoin::bip32::Error) -> Self {
+        Self::with_source(ErrorCode::BitcoinFailure, "BIP32 derivation failed", err)
+    }
+}
+
+impl From<::bitcoin::sighash::Error> for AnyaError {
+    fn from(err: ::bitcoin::sighash::Error) -> Self {
+        Self::with_source(ErrorCode::InvalidInput, "signature hash failed", err)
+    }
+}
+
+impl From<::bitcoin::secp256k1::Error> for AnyaError {
+    fn from(err: ::bitcoin::secp256k1::Error) -> Self {
+        Self::with_source(ErrorCode::InvalidInput, "invalid key or signature", err)
+    }
+}
+
+impl From<::bitcoin::key::Error> for AnyaError {
+    fn from(err: ::bitcoin::key::Error) -> Self {
+        Self::with_source(ErrorCode::InvalidInput, "invalid public key", err)
+    }
+}
+
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+impl From<sqlx::Error> for AnyaError {
+    fn from(err: sqlx::Error) -> Self {
+        let code = match &err {
+            sqlx::Error::RowNotFound => ErrorCode::NotFound,
+            sqlx::Error::PoolTimedOut => ErrorCode::Timeout,
+            sqlx::Error::PoolClosed => ErrorCode::Unavailable,
+            sqlx::Error::Configuration(_) => ErrorCode::Config,
+            _ => ErrorCode::StorageFailure,
+        };
+        Self::with_source(code, "SQL storage operation failed", err)
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<reqwest::Error> for AnyaError {
+    fn from(err: reqwest::Error) -> Self {
+        let code = if err.is_timeout() {
+            ErrorCode::Timeout
+        } else if err.is_decode() {
+            ErrorCode::Serialization
+        } else if err.status().map(|s| s.as_u16()) == Some(404) {
+            ErrorCode::NotFound
+        } else {
+            ErrorCode::NetworkFailure
+        };
+        Self::with_source(code, "HTTP request failed", err)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<::parquet::errors::ParquetError> for AnyaError {
+    fn from(err: ::parquet::errors::ParquetError) -> Self {
+        Self::with_source(ErrorCode::Serialization, "Parquet encoding failed", err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_and_retryability() {
+        let err = AnyaError::new(ErrorCode::Timeout, "peer did not answer");
+        assert_eq!(err.code().as_u32(), 1001);
+        assert_eq!(err.category(), ErrorCategory::System);
+        assert!(err.is_retryable());
+        assert!(!AnyaError::ML("bad".into()).is_retryable());
+        assert_eq!(ErrorCode::NotFound.http_status(), 404);
+    }
+
+    #[test]
+    fn test_context_and_source_chain() {
+        let io = std::io::Error::other("disk full");
+        let res: Result<(), _> = Err(io);
+        let err = res
+            .context("writing block index")
+            .context("connecting block")
+            .unwrap_err();
+
+        assert_eq!(err.code(), ErrorCode::Io);
+        assert_eq!(
+            err.to_string(),
+            "connecting block: writing block index: I/O operation failed [IO(1004)]"
+        );
+        let report = err.to_report();
+        assert_eq!(report.causes, vec!["disk full".to_string()]);
+        assert!(report.retryable);
+        assert_eq!(AnyaError::from(report).to_string(), err.to_string());
+    }
+
+    #[test]
+    fn test_legacy_variant_context_keeps_code() {
+        let err = AnyaError::Bitcoin("no utxos".into()).context("building tx");
+        assert_eq!(err.code(), ErrorCode::BitcoinFailure);
+        assert_eq!(err.context_frames(), ["building tx".to_string()]);
+    }
+}
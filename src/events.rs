@@ -0,0 +1,422 @@
+//! Event sourcing: a persistent event log with replay and live subscriptions
+//!
+//! The [`EventStore`] is an append-only log in its own storage namespace.
+//! Every appended [`EventRecord`] gets the next sequence number, is written
+//! to storage, and only then is published to live subscribers, so anything a
+//! subscriber sees can also be replayed later.
+//!
+//! A [`Subscription`] starts at any sequence number: it first replays history
+//! from storage and then follows live events without gaps or duplicates,
+//! falling back to storage whenever it lags behind the in-memory channel.
+//!
+//! Read models implement [`Projection`] and are rebuilt with
+//! [`EventStore::project`], which resumes from the latest snapshot and writes
+//! a new one every [`EventStoreConfig::snapshot_interval`] events.
+//!
+//! Events are stored in segments of [`SEGMENT_LEN`] keys so reads from an
+//! offset scan only the segments they need.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{broadcast, Mutex};
+
+use crate::bitcoin::tracker::{TxEvent, TxEventSink};
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "event_log";
+const HEAD_KEY: &str = "head";
+const EVENT_PREFIX: &str = "event/";
+const SNAPSHOT_PREFIX: &str = "snapshot/";
+
+/// Events per storage segment
+pub const SEGMENT_LEN: u64 = 1024;
+
+/// Topic of wallet transaction events recorded from the tracker
+pub const TX_TOPIC: &str = "bitcoin.tx";
+
+/// A persisted event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Position in the log, starting at 0
+    pub seq: u64,
+    /// Dotted topic, e.g. `bitcoin.tx` or `lifecycle`
+    pub topic: String,
+    /// Event type within the topic
+    pub kind: String,
+    /// Event data
+    pub payload: Value,
+    /// Append time in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+}
+
+impl EventRecord {
+    /// Whether the topic equals `prefix` or is nested below it
+    pub fn in_topic(&self, prefix: &str) -> bool {
+        self.topic
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    }
+}
+
+/// Event store settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventStoreConfig {
+    /// Live events buffered per subscriber before it falls back to storage
+    pub live_buffer: usize,
+    /// Events applied by [`EventStore::project`] before a new snapshot
+    pub snapshot_interval: u64,
+}
+
+impl Default for EventStoreConfig {
+    fn default() -> Self {
+        Self {
+            live_buffer: 1024,
+            snapshot_interval: 500,
+        }
+    }
+}
+
+/// A read model rebuilt from the event log
+pub trait Projection: Default + Serialize + DeserializeOwned + Send {
+    /// Fold one event into the state
+    fn apply(&mut self, event: &EventRecord);
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<P> {
+    next_seq: u64,
+    state: P,
+}
+
+/// Append-only event log with publish/subscribe
+pub struct EventStore {
+    config: EventStoreConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    /// Next sequence number; held while appending so events persist in order
+    head: Mutex<u64>,
+    live: broadcast::Sender<EventRecord>,
+}
+
+impl EventStore {
+    /// Open the log in `storage`, recovering its head
+    pub async fn open(
+        config: EventStoreConfig,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Arc<Self>> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let mut head = match storage.get(&ns, HEAD_KEY).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => 0,
+        };
+        // The head is written after the event, so a crash can leave it behind
+        while storage.get(&ns, &event_key(head)).await?.is_some() {
+            head += 1;
+        }
+        let (live, _) = broadcast::channel(config.live_buffer.max(1));
+        Ok(Arc::new(Self {
+            config,
+            storage,
+            ns,
+            head: Mutex::new(head),
+            live,
+        }))
+    }
+
+    /// Sequence number the next event will get
+    pub async fn head(&self) -> u64 {
+        *self.head.lock().await
+    }
+
+    /// Persist an event and publish it to subscribers
+    pub async fn append(
+        &self,
+        topic: impl Into<String>,
+        kind: impl Into<String>,
+        payload: Value,
+    ) -> AnyaResult<EventRecord> {
+        let mut head = self.head.lock().await;
+        let record = EventRecord {
+            seq: *head,
+            topic: topic.into(),
+            kind: kind.into(),
+            payload,
+            timestamp_ms: now_ms(),
+        };
+        self.storage
+            .put(
+                &self.ns,
+                &event_key(record.seq),
+                &serde_json::to_vec(&record)?,
+            )
+            .await?;
+        *head += 1;
+        self.storage
+            .put(&self.ns, HEAD_KEY, &serde_json::to_vec(&*head)?)
+            .await?;
+        drop(head);
+        // No receivers is fine; history stays replayable
+        let _ = self.live.send(record.clone());
+        Ok(record)
+    }
+
+    /// Up to `limit` events starting at `from`, in order
+    pub async fn read_from(&self, from: u64, limit: usize) -> AnyaResult<Vec<EventRecord>> {
+        let head = self.head().await;
+        let mut events = Vec::new();
+        let mut segment = from / SEGMENT_LEN;
+        while events.len() < limit && segment * SEGMENT_LEN < head {
+            let prefix = format!("{}{:016x}/", EVENT_PREFIX, segment);
+            for (_, bytes) in self.storage.scan_prefix(&self.ns, &prefix).await? {
+                let record: EventRecord = serde_json::from_slice(&bytes)?;
+                if record.seq >= from && record.seq < head && events.len() < limit {
+                    events.push(record);
+                }
+            }
+            segment += 1;
+        }
+        Ok(events)
+    }
+
+    /// Subscribe from sequence number `from`: history first, then live events.
+    ///
+    /// Use `0` to replay everything or [`EventStore::head`] for new events
+    /// only.
+    pub fn subscribe(self: &Arc<Self>, from: u64) -> Subscription {
+        Subscription {
+            store: self.clone(),
+            live: self.live.subscribe(),
+            next: from,
+            backlog: VecDeque::new(),
+            topic: None,
+        }
+    }
+
+    /// Rebuild the projection `name` from its latest snapshot and the events
+    /// after it, snapshotting again if enough events were applied.
+    ///
+    /// Returns the state and the sequence number it is current up to
+    /// (exclusive).
+    pub async fn project<P: Projection>(&self, name: &str) -> AnyaResult<(P, u64)> {
+        let key = format!("{}{}", SNAPSHOT_PREFIX, name);
+        let (mut state, snapshot_seq) = match self.storage.get(&self.ns, &key).await? {
+            Some(bytes) => {
+                let snapshot: Snapshot<P> = serde_json::from_slice(&bytes).map_err(|e| {
+                    AnyaError::with_source(
+                        ErrorCode::Serialization,
+                        format!("unreadable snapshot of projection {}", name),
+                        e,
+                    )
+                })?;
+                (snapshot.state, snapshot.next_seq)
+            }
+            None => (P::default(), 0),
+        };
+        let mut next = snapshot_seq;
+        loop {
+            let batch = self.read_from(next, SEGMENT_LEN as usize).await?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            next = last.seq + 1;
+            batch.iter().for_each(|event| state.apply(event));
+        }
+        if next - snapshot_seq >= self.config.snapshot_interval {
+            let snapshot = Snapshot {
+                next_seq: next,
+                state,
+            };
+            self.storage
+                .put(&self.ns, &key, &serde_json::to_vec(&snapshot)?)
+                .await?;
+            state = snapshot.state;
+        }
+        Ok((state, next))
+    }
+}
+
+#[async_trait]
+impl TxEventSink for EventStore {
+    async fn handle(&self, event: &TxEvent) -> AnyaResult<()> {
+        let payload = serde_json::to_value(event)?;
+        let kind = payload["event"].as_str().unwrap_or("unknown").to_string();
+        self.append(TX_TOPIC, kind, payload).await?;
+        Ok(())
+    }
+}
+
+/// Gap-free stream of events from a starting sequence number
+pub struct Subscription {
+    store: Arc<EventStore>,
+    live: broadcast::Receiver<EventRecord>,
+    next: u64,
+    backlog: VecDeque<EventRecord>,
+    topic: Option<String>,
+}
+
+impl Subscription {
+    /// Only deliver events in `topic` or below it
+    #[must_use]
+    pub fn with_topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
+    /// Sequence number of the next event this subscription will consider
+    pub const fn position(&self) -> u64 {
+        self.next
+    }
+
+    /// Wait for the next event
+    pub async fn next(&mut self) -> AnyaResult<EventRecord> {
+        loop {
+            let event = self.next_any().await?;
+            if self.topic.iter().all(|topic| event.in_topic(topic)) {
+                return Ok(event);
+            }
+        }
+    }
+
+    async fn next_any(&mut self) -> AnyaResult<EventRecord> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                self.next = event.seq + 1;
+                return Ok(event);
+            }
+            if self.next < self.store.head().await {
+                self.backlog = self
+                    .store
+                    .read_from(self.next, SEGMENT_LEN as usize)
+                    .await?
+                    .into();
+                continue;
+            }
+            match self.live.recv().await {
+                Ok(event) if event.seq < self.next => {}
+                Ok(event) if event.seq == self.next => {
+                    self.next += 1;
+                    return Ok(event);
+                }
+                // Missed events are read back from storage on the next pass
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(AnyaError::new(ErrorCode::Unavailable, "event store closed"))
+                }
+            }
+        }
+    }
+}
+
+fn event_key(seq: u64) -> String {
+    format!("{}{:016x}/{:016x}", EVENT_PREFIX, seq / SEGMENT_LEN, seq)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use serde_json::json;
+
+    #[derive(Default, Serialize, Deserialize)]
+    struct Totals {
+        deposits: u64,
+        events: u64,
+    }
+
+    impl Projection for Totals {
+        fn apply(&mut self, event: &EventRecord) {
+            self.events += 1;
+            if event.kind == "deposit" {
+                self.deposits += event.payload["amount"].as_u64().unwrap_or(0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_then_live_without_gaps() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let config = EventStoreConfig {
+            live_buffer: 2,
+            ..EventStoreConfig::default()
+        };
+        let store = EventStore::open(config.clone(), storage.clone())
+            .await
+            .unwrap();
+        for i in 0..3 {
+            store
+                .append("wallet.deposits", "deposit", json!({ "amount": i }))
+                .await
+                .unwrap();
+        }
+        let mut all = store.subscribe(0);
+        let mut deposits = store.subscribe(0).with_topic("wallet");
+        store
+            .append("lifecycle", "started", json!({}))
+            .await
+            .unwrap();
+        // More live events than the channel holds: the subscriber lags
+        for i in 3..8 {
+            store
+                .append("wallet.deposits", "deposit", json!({ "amount": i }))
+                .await
+                .unwrap();
+        }
+        for seq in 0..9 {
+            assert_eq!(all.next().await.unwrap().seq, seq);
+        }
+        let mut seen = Vec::new();
+        for _ in 0..8 {
+            seen.push(deposits.next().await.unwrap().payload["amount"].clone());
+        }
+        assert_eq!(seen, (0..8).map(|i| json!(i)).collect::<Vec<_>>());
+
+        // Reopening recovers the head and history
+        let reopened = EventStore::open(config, storage).await.unwrap();
+        assert_eq!(reopened.head().await, 9);
+        assert_eq!(reopened.read_from(7, 10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_projection_resumes_from_snapshot() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let config = EventStoreConfig {
+            snapshot_interval: 3,
+            ..EventStoreConfig::default()
+        };
+        let store = EventStore::open(config, storage.clone()).await.unwrap();
+        for amount in [5, 7, 11] {
+            store
+                .append("wallet", "deposit", json!({ "amount": amount }))
+                .await
+                .unwrap();
+        }
+        let (totals, next) = store.project::<Totals>("totals").await.unwrap();
+        assert_eq!((totals.deposits, next), (23, 3));
+
+        store
+            .append("wallet", "deposit", json!({ "amount": 2 }))
+            .await
+            .unwrap();
+        let (totals, next) = store.project::<Totals>("totals").await.unwrap();
+        assert_eq!((totals.deposits, totals.events, next), (25, 4, 4));
+
+        let ns = Namespace::new(NAMESPACE).unwrap();
+        let snapshot = storage.get(&ns, "snapshot/totals").await.unwrap().unwrap();
+        let snapshot: Snapshot<Totals> = serde_json::from_slice(&snapshot).unwrap();
+        assert_eq!(snapshot.next_seq, 3);
+    }
+}
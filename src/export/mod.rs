@@ -0,0 +1,322 @@
+//! Batch export of chain and analytics data to Parquet
+//!
+//! An [`Exporter`] periodically drains every registered [`ExportSource`]
+//! from its last watermark and writes the new rows as Parquet files through
+//! a [`FileStore`]: [`LocalFileStore`](crate::integrations::batch::LocalFileStore)
+//! for local disk or [`S3FileStore`](crate::integrations::s3::S3FileStore)
+//! for S3-compatible storage. Files are partitioned Hive-style by dataset
+//! and UTC day of the source's timestamp column,
+//! `<prefix>/<dataset>/date=YYYY-MM-DD/part-<from>-<to>.parquet`, so
+//! warehouse engines can prune by date.
+//!
+//! A source's watermark is saved only after its files were written. A crash
+//! in between exports the same rows again under the same file names,
+//! replacing the earlier files instead of duplicating their rows.
+//!
+//! Built-in sources in [`sources`] cover blocks and transactions from a
+//! [`BlockProvider`](sources::BlockProvider) and any topic of the event log,
+//! such as wallet events and anomaly reports.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::integrations::batch::FileStore;
+use crate::integrations::Record;
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::time::UtcDateTime;
+use crate::{AnyaError, AnyaResult};
+
+pub mod parquet;
+pub mod sources;
+
+const NAMESPACE: &str = "export";
+const WATERMARK_PREFIX: &str = "watermark/";
+/// Partition of rows without a usable timestamp, as named by Hive
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Value type of an exported column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    /// Boolean
+    Bool,
+    /// Signed 64-bit integer
+    Int64,
+    /// 64-bit float
+    Float64,
+    /// UTF-8 string; other JSON values are written as their JSON text
+    Utf8,
+    /// Any JSON value, written as JSON text
+    Json,
+    /// Milliseconds since the Unix epoch, UTC
+    TimestampMillis,
+}
+
+/// A column of an exported dataset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Column {
+    /// Column and record field name
+    pub name: String,
+    /// Value type
+    pub kind: ColumnType,
+    /// Whether rows may omit the value
+    pub nullable: bool,
+}
+
+impl Column {
+    /// A column every row has a value for
+    pub fn required(name: &str, kind: ColumnType) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            nullable: false,
+        }
+    }
+
+    /// A column rows may leave out or set to null
+    pub fn nullable(name: &str, kind: ColumnType) -> Self {
+        Self {
+            nullable: true,
+            ..Self::required(name, kind)
+        }
+    }
+}
+
+/// Rows read from a source and the watermark after them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportBatch {
+    /// Rows, one record per row
+    pub rows: Vec<Record>,
+    /// Watermark to resume from; equal to the requested one when the source
+    /// has nothing new
+    pub watermark: u64,
+}
+
+/// A dataset that can be exported incrementally
+///
+/// Watermarks are opaque positions, such as a block height or an event
+/// sequence number, that only the source interprets. Reading twice from
+/// the same watermark with the same limit must return the same rows.
+#[async_trait]
+pub trait ExportSource: Send + Sync {
+    /// Dataset name, used as directory name
+    fn dataset(&self) -> &str;
+
+    /// Columns of every row
+    fn columns(&self) -> Vec<Column>;
+
+    /// Timestamp column rows are partitioned by
+    fn partition_column(&self) -> &str;
+
+    /// Up to about `limit` rows after `watermark`
+    async fn rows_after(&self, watermark: u64, limit: usize) -> AnyaResult<ExportBatch>;
+}
+
+/// Export scheduling and layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportConfig {
+    /// Directory of the file store all datasets are written below
+    pub prefix: String,
+    /// Rows per source read; a read may produce one file per day it spans
+    pub batch_rows: usize,
+    /// Interval between scheduled exports
+    pub interval: Duration,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "warehouse".into(),
+            batch_rows: 50_000,
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// A Parquet file written by an export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedFile {
+    /// Dataset the rows belong to
+    pub dataset: String,
+    /// Directory of the file in the store
+    pub dir: String,
+    /// File name
+    pub name: String,
+    /// Rows in the file
+    pub rows: usize,
+}
+
+/// Writes new rows of every source as partitioned Parquet files
+pub struct Exporter {
+    config: ExportConfig,
+    store: Arc<dyn FileStore>,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    sources: Vec<Arc<dyn ExportSource>>,
+}
+
+impl Exporter {
+    /// Create an exporter writing to `store` and keeping watermarks in
+    /// `storage`
+    pub async fn new(
+        config: ExportConfig,
+        store: Arc<dyn FileStore>,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            config,
+            store,
+            storage,
+            ns,
+            sources: Vec::new(),
+        })
+    }
+
+    /// Register a source; dataset names must be unique
+    pub fn add_source(&mut self, source: Arc<dyn ExportSource>) -> AnyaResult<()> {
+        if self.sources.iter().any(|s| s.dataset() == source.dataset()) {
+            return Err(AnyaError::new(
+                crate::ErrorCode::Conflict,
+                format!("export dataset {} is already registered", source.dataset()),
+            ));
+        }
+        self.sources.push(source);
+        Ok(())
+    }
+
+    /// Watermark `dataset` was exported up to
+    pub async fn watermark(&self, dataset: &str) -> AnyaResult<u64> {
+        let key = format!("{}{}", WATERMARK_PREFIX, dataset);
+        match self.storage.get(&self.ns, &key).await? {
+            Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Export everything new from every source, returning the files
+    /// written. A failing source does not hold up the others.
+    pub async fn export_once(&self) -> AnyaResult<Vec<ExportedFile>> {
+        let mut files = Vec::new();
+        let mut first_error = None;
+        for source in &self.sources {
+            match self.export_source(source.as_ref()).await {
+                Ok(written) => files.extend(written),
+                Err(e) => {
+                    warn!(dataset = source.dataset(), error = %e, "export failed");
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if files.is_empty() => Err(e),
+            _ => Ok(files),
+        }
+    }
+
+    async fn export_source(&self, source: &dyn ExportSource) -> AnyaResult<Vec<ExportedFile>> {
+        let dataset = source.dataset();
+        let columns = source.columns();
+        let mut files = Vec::new();
+        let mut watermark = self.watermark(dataset).await?;
+        loop {
+            let batch = source
+                .rows_after(watermark, self.config.batch_rows.max(1))
+                .await?;
+            if batch.watermark == watermark {
+                break;
+            }
+            let mut partitions: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+            for row in batch.rows {
+                let date = row
+                    .get(source.partition_column())
+                    .and_then(Value::as_i64)
+                    .map_or_else(
+                        || DEFAULT_PARTITION.to_string(),
+                        |ms| UtcDateTime::from_unix_ms(ms).date(),
+                    );
+                partitions.entry(date).or_default().push(row);
+            }
+            let name = format!("part-{:020}-{:020}.parquet", watermark, batch.watermark);
+            for (date, rows) in partitions {
+                let dir = format!("{}/{}/date={}", self.config.prefix, dataset, date);
+                let data = parquet::encode(&columns, &rows)
+                    .map_err(|e| e.context(format!("encoding {} rows", dataset)))?;
+                self.store.write(&dir, &name, &data).await?;
+                files.push(ExportedFile {
+                    dataset: dataset.to_string(),
+                    dir,
+                    name: name.clone(),
+                    rows: rows.len(),
+                });
+            }
+            watermark = batch.watermark;
+            self.storage
+                .put(
+                    &self.ns,
+                    &format!("{}{}", WATERMARK_PREFIX, dataset),
+                    &serde_json::to_vec(&watermark)?,
+                )
+                .await?;
+        }
+        if !files.is_empty() {
+            info!(dataset, files = files.len(), watermark, "exported new rows");
+        }
+        Ok(files)
+    }
+
+    /// Run scheduled exports until `token` is cancelled
+    pub async fn run_schedule(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.interval, || {
+            let exporter = Arc::clone(&self);
+            async move {
+                if let Err(e) = exporter.export_once().await {
+                    warn!(error = %e, "scheduled export failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+/// Scheduled exports as a lifecycle-managed subsystem
+pub struct ExportService {
+    exporter: Arc<Exporter>,
+}
+
+impl ExportService {
+    /// Wrap an exporter for registration with the lifecycle manager
+    pub const fn new(exporter: Arc<Exporter>) -> Self {
+        Self { exporter }
+    }
+}
+
+#[async_trait]
+impl Subsystem for ExportService {
+    fn name(&self) -> &str {
+        "export"
+    }
+
+    fn startup_order(&self) -> u32 {
+        // Like backups, start after the subsystems whose data is exported
+        910
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let exporter = Arc::clone(&self.exporter);
+        spawner
+            .spawn("schedule", move |token| exporter.run_schedule(token))
+            .await;
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Parquet encoding of exported rows
+//!
+//! Each call produces a complete file with a single row group and
+//! Snappy-compressed column chunks. Column types map to Parquet as follows:
+//! `Bool` to `BOOLEAN`, `Int64` to `INT64`, `Float64` to `DOUBLE`, `Utf8`
+//! and `Json` to `BYTE_ARRAY` annotated as strings, and `TimestampMillis`
+//! to `INT64` annotated as a UTC millisecond timestamp.
+
+use std::sync::Arc;
+
+use ::parquet::basic::{Compression, LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use ::parquet::data_type::{BoolType, ByteArray, ByteArrayType, DataType, DoubleType, Int64Type};
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use ::parquet::schema::types::Type;
+use serde_json::Value;
+
+use super::{Column, ColumnType};
+use crate::integrations::Record;
+use crate::{AnyaError, AnyaResult};
+
+/// Parquet schema of `columns`
+fn schema(columns: &[Column]) -> AnyaResult<Arc<Type>> {
+    let fields = columns
+        .iter()
+        .map(|column| {
+            let (physical, logical) = match column.kind {
+                ColumnType::Bool => (PhysicalType::BOOLEAN, None),
+                ColumnType::Int64 => (PhysicalType::INT64, None),
+                ColumnType::Float64 => (PhysicalType::DOUBLE, None),
+                ColumnType::Utf8 | ColumnType::Json => {
+                    (PhysicalType::BYTE_ARRAY, Some(LogicalType::String))
+                }
+                ColumnType::TimestampMillis => (
+                    PhysicalType::INT64,
+                    Some(LogicalType::Timestamp {
+                        is_adjusted_to_u_t_c: true,
+                        unit: TimeUnit::MILLIS(Default::default()),
+                    }),
+                ),
+            };
+            let repetition = if column.nullable {
+                Repetition::OPTIONAL
+            } else {
+                Repetition::REQUIRED
+            };
+            Ok(Arc::new(
+                Type::primitive_type_builder(&column.name, physical)
+                    .with_logical_type(logical)
+                    .with_repetition(repetition)
+                    .build()?,
+            ))
+        })
+        .collect::<AnyaResult<Vec<_>>>()?;
+    Ok(Arc::new(
+        Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?,
+    ))
+}
+
+/// Present values of one column and, for nullable columns, definition
+/// levels marking which rows have them
+struct ColumnData<T> {
+    values: Vec<T>,
+    levels: Option<Vec<i16>>,
+}
+
+impl<T> ColumnData<T> {
+    /// Convert the `column` field of every row with `convert`, which
+    /// returns `None` for values of the wrong type
+    fn collect(
+        column: &Column,
+        rows: &[Record],
+        convert: impl Fn(&Value) -> Option<T>,
+    ) -> AnyaResult<Self> {
+        let mut values = Vec::with_capacity(rows.len());
+        let mut levels = column.nullable.then(|| Vec::with_capacity(rows.len()));
+        for (i, row) in rows.iter().enumerate() {
+            match row.get(&column.name).filter(|v| !v.is_null()) {
+                Some(value) => {
+                    values.push(convert(value).ok_or_else(|| {
+                        AnyaError::invalid_input(format!(
+                            "row {} column {}: {} is not {:?}",
+                            i, column.name, value, column.kind
+                        ))
+                    })?);
+                    if let Some(levels) = &mut levels {
+                        levels.push(1);
+                    }
+                }
+                None => match &mut levels {
+                    Some(levels) => levels.push(0),
+                    None => {
+                        return Err(AnyaError::invalid_input(format!(
+                            "row {} has no value for required column {}",
+                            i, column.name
+                        )))
+                    }
+                },
+            }
+        }
+        Ok(Self { values, levels })
+    }
+
+    fn write<D>(self, writer: &mut SerializedColumnWriter<'_>) -> AnyaResult<()>
+    where
+        D: DataType<T = T>,
+    {
+        writer
+            .typed::<D>()
+            .write_batch(&self.values, self.levels.as_deref(), None)?;
+        Ok(())
+    }
+}
+
+fn text(value: &Value) -> ByteArray {
+    match value {
+        Value::String(s) => ByteArray::from(s.as_str()),
+        other => ByteArray::from(other.to_string().as_str()),
+    }
+}
+
+/// Encode `rows` as a Parquet file with the given columns
+pub fn encode(columns: &[Column], rows: &[Record]) -> AnyaResult<Vec<u8>> {
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_created_by(format!("anya-core {}", env!("CARGO_PKG_VERSION")))
+        .build();
+    let mut writer = SerializedFileWriter::new(Vec::new(), schema(columns)?, Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    for column in columns {
+        let Some(mut chunk) = row_group.next_column()? else {
+            break;
+        };
+        match column.kind {
+            ColumnType::Bool => {
+                ColumnData::collect(column, rows, Value::as_bool)?.write::<BoolType>(&mut chunk)?
+            }
+            ColumnType::Int64 | ColumnType::TimestampMillis => {
+                ColumnData::collect(column, rows, Value::as_i64)?.write::<Int64Type>(&mut chunk)?;
+            }
+            ColumnType::Float64 => {
+                ColumnData::collect(column, rows, Value::as_f64)?.write::<DoubleType>(&mut chunk)?
+            }
+            ColumnType::Utf8 | ColumnType::Json => {
+                ColumnData::collect(column, rows, |v| Some(text(v)))?
+                    .write::<ByteArrayType>(&mut chunk)?;
+            }
+        }
+        chunk.close()?;
+    }
+    row_group.close()?;
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_round_trip() {
+        let columns = vec![
+            Column::required("height", ColumnType::Int64),
+            Column::required("time", ColumnType::TimestampMillis),
+            Column::nullable("fee_rate", ColumnType::Float64),
+            Column::nullable("label", ColumnType::Utf8),
+            Column::required("coinbase", ColumnType::Bool),
+            Column::required("extra", ColumnType::Json),
+        ];
+        let rows: Vec<Record> = [
+            json!({"height": 1, "time": 1_700_000_000_000_i64, "fee_rate": 2.5, "label": "a",
+                   "coinbase": true, "extra": {"n": 1}}),
+            json!({"height": 2, "time": 1_700_000_600_000_i64, "label": null,
+                   "coinbase": false, "extra": [1, 2]}),
+        ]
+        .iter()
+        .map(|v| v.as_object().unwrap().clone())
+        .collect();
+        let data = encode(&columns, &rows).unwrap();
+        assert_eq!(&data[..4], b"PAR1");
+
+        let path =
+            std::env::temp_dir().join(format!("anya-parquet-{}.parquet", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let decoded: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert!(decoded[0].contains("fee_rate: 2.5"), "{}", decoded[0]);
+        assert!(decoded[1].contains("label: null"), "{}", decoded[1]);
+        assert!(decoded[1].contains("extra: \"[1,2]\""), "{}", decoded[1]);
+
+        let mut missing = rows[0].clone();
+        missing.remove("height");
+        assert!(encode(&columns, &[missing]).is_err());
+        let mut wrong = rows[0].clone();
+        wrong.insert("coinbase".into(), json!("yes"));
+        assert!(encode(&columns, &[wrong]).is_err());
+    }
+}
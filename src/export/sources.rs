@@ -0,0 +1,422 @@
+//! Built-in export sources
+//!
+//! [`BlockSource`] and [`TransactionSource`] export the chain block by
+//! block from a [`BlockProvider`], staying `finality` blocks behind the tip
+//! so reorganisations do not leave stale rows in the warehouse. Their
+//! watermark is the next height to export.
+//!
+//! [`EventSource`] exports the events of one topic of the [`EventStore`],
+//! e.g. [`TX_TOPIC`](crate::events::TX_TOPIC) for wallet events or the
+//! anomaly topics for analytics results. Its watermark is the next event
+//! sequence number.
+
+use std::sync::Arc;
+
+use ::bitcoin::Block;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::{Column, ColumnType, ExportBatch, ExportSource};
+use crate::events::EventStore;
+use crate::integrations::Record;
+use crate::AnyaResult;
+
+/// Full blocks by height, e.g. from a node's RPC interface
+#[async_trait]
+pub trait BlockProvider: Send + Sync {
+    /// Height of the best block
+    async fn tip_height(&self) -> AnyaResult<u32>;
+
+    /// Block of the best chain at `height`
+    async fn block(&self, height: u32) -> AnyaResult<Option<Block>>;
+}
+
+fn row(value: Value) -> Record {
+    match value {
+        Value::Object(record) => record,
+        _ => Record::new(),
+    }
+}
+
+fn block_time_ms(block: &Block) -> i64 {
+    i64::from(block.header.time) * 1000
+}
+
+/// Final blocks from `watermark` on, until they hold `limit` rows as
+/// counted by `rows_of`
+async fn final_blocks(
+    provider: &dyn BlockProvider,
+    finality: u32,
+    watermark: u64,
+    limit: usize,
+    rows_of: impl Fn(&Block) -> usize,
+) -> AnyaResult<Vec<(u32, Block)>> {
+    let tip = provider.tip_height().await?;
+    let last_final = i64::from(tip) - i64::from(finality);
+    let mut blocks = Vec::new();
+    let mut rows = 0;
+    let mut height = watermark;
+    while rows < limit && i64::try_from(height).is_ok_and(|h| h <= last_final) {
+        let height32 = u32::try_from(height).unwrap_or(u32::MAX);
+        let Some(block) = provider.block(height32).await? else {
+            break;
+        };
+        rows += rows_of(&block);
+        blocks.push((height32, block));
+        height += 1;
+    }
+    Ok(blocks)
+}
+
+/// Blocks of the best chain, one row per block
+pub struct BlockSource {
+    provider: Arc<dyn BlockProvider>,
+    finality: u32,
+}
+
+impl BlockSource {
+    /// Export blocks buried under at least `finality` blocks
+    pub fn new(provider: Arc<dyn BlockProvider>, finality: u32) -> Self {
+        Self { provider, finality }
+    }
+}
+
+#[async_trait]
+impl ExportSource for BlockSource {
+    fn dataset(&self) -> &str {
+        "blocks"
+    }
+
+    fn columns(&self) -> Vec<Column> {
+        vec![
+            Column::required("height", ColumnType::Int64),
+            Column::required("hash", ColumnType::Utf8),
+            Column::required("previous_hash", ColumnType::Utf8),
+            Column::required("timestamp", ColumnType::TimestampMillis),
+            Column::required("tx_count", ColumnType::Int64),
+            Column::required("size", ColumnType::Int64),
+            Column::required("weight", ColumnType::Int64),
+        ]
+    }
+
+    fn partition_column(&self) -> &str {
+        "timestamp"
+    }
+
+    async fn rows_after(&self, watermark: u64, limit: usize) -> AnyaResult<ExportBatch> {
+        let blocks = final_blocks(
+            self.provider.as_ref(),
+            self.finality,
+            watermark,
+            limit,
+            |_| 1,
+        )
+        .await?;
+        Ok(ExportBatch {
+            watermark: watermark + blocks.len() as u64,
+            rows: blocks
+                .iter()
+                .map(|(height, block)| {
+                    row(json!({
+                        "height": height,
+                        "hash": block.block_hash().to_string(),
+                        "previous_hash": block.header.prev_blockhash.to_string(),
+                        "timestamp": block_time_ms(block),
+                        "tx_count": block.txdata.len(),
+                        "size": block.size(),
+                        "weight": block.weight().to_wu(),
+                    }))
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Transactions of the best chain, one row per transaction
+pub struct TransactionSource {
+    provider: Arc<dyn BlockProvider>,
+    finality: u32,
+}
+
+impl TransactionSource {
+    /// Export transactions of blocks buried under at least `finality` blocks
+    pub fn new(provider: Arc<dyn BlockProvider>, finality: u32) -> Self {
+        Self { provider, finality }
+    }
+}
+
+#[async_trait]
+impl ExportSource for TransactionSource {
+    fn dataset(&self) -> &str {
+        "transactions"
+    }
+
+    fn columns(&self) -> Vec<Column> {
+        vec![
+            Column::required("txid", ColumnType::Utf8),
+            Column::required("height", ColumnType::Int64),
+            Column::required("position", ColumnType::Int64),
+            Column::required("timestamp", ColumnType::TimestampMillis),
+            Column::required("coinbase", ColumnType::Bool),
+            Column::required("inputs", ColumnType::Int64),
+            Column::required("outputs", ColumnType::Int64),
+            Column::required("output_value_sat", ColumnType::Int64),
+            Column::required("vsize", ColumnType::Int64),
+            Column::required("weight", ColumnType::Int64),
+        ]
+    }
+
+    fn partition_column(&self) -> &str {
+        "timestamp"
+    }
+
+    /// Whole blocks are exported, so a batch may exceed `limit` by the
+    /// transactions of its last block
+    async fn rows_after(&self, watermark: u64, limit: usize) -> AnyaResult<ExportBatch> {
+        let blocks = final_blocks(
+            self.provider.as_ref(),
+            self.finality,
+            watermark,
+            limit,
+            |block| block.txdata.len(),
+        )
+        .await?;
+        let mut rows = Vec::new();
+        for (height, block) in &blocks {
+            for (position, tx) in block.txdata.iter().enumerate() {
+                rows.push(row(json!({
+                    "txid": tx.txid().to_string(),
+                    "height": height,
+                    "position": position,
+                    "timestamp": block_time_ms(block),
+                    "coinbase": tx.is_coin_base(),
+                    "inputs": tx.input.len(),
+                    "outputs": tx.output.len(),
+                    "output_value_sat": tx.output.iter().map(|o| o.value).sum::<u64>(),
+                    "vsize": tx.vsize(),
+                    "weight": tx.weight().to_wu(),
+                })));
+            }
+        }
+        Ok(ExportBatch {
+            rows,
+            watermark: watermark + blocks.len() as u64,
+        })
+    }
+}
+
+/// Events of one topic of the event log, one row per event
+pub struct EventSource {
+    events: Arc<EventStore>,
+    dataset: String,
+    topic: String,
+}
+
+impl EventSource {
+    /// Export events in `topic` or below it as `dataset`
+    pub fn new(events: Arc<EventStore>, dataset: &str, topic: &str) -> Self {
+        Self {
+            events,
+            dataset: dataset.to_string(),
+            topic: topic.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ExportSource for EventSource {
+    fn dataset(&self) -> &str {
+        &self.dataset
+    }
+
+    fn columns(&self) -> Vec<Column> {
+        vec![
+            Column::required("seq", ColumnType::Int64),
+            Column::required("topic", ColumnType::Utf8),
+            Column::required("kind", ColumnType::Utf8),
+            Column::required("timestamp", ColumnType::TimestampMillis),
+            Column::required("payload", ColumnType::Json),
+        ]
+    }
+
+    fn partition_column(&self) -> &str {
+        "timestamp"
+    }
+
+    async fn rows_after(&self, watermark: u64, limit: usize) -> AnyaResult<ExportBatch> {
+        let events = self.events.read_from(watermark, limit).await?;
+        Ok(ExportBatch {
+            watermark: events.last().map_or(watermark, |e| e.seq + 1),
+            rows: events
+                .into_iter()
+                .filter(|e| e.in_topic(&self.topic))
+                .map(|e| {
+                    row(json!({
+                        "seq": e.seq,
+                        "topic": e.topic,
+                        "kind": e.kind,
+                        "timestamp": e.timestamp_ms,
+                        "payload": e.payload,
+                    }))
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventStoreConfig, TX_TOPIC};
+    use crate::export::{ExportConfig, Exporter};
+    use crate::integrations::batch::{FileStore, LocalFileStore};
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::StorageBackend;
+    use ::bitcoin::absolute::LockTime;
+    use ::bitcoin::block::{Header, Version};
+    use ::bitcoin::hash_types::TxMerkleNode;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{
+        BlockHash, CompactTarget, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+
+    /// A chain of blocks one day apart, each with `height + 1` transactions
+    struct Chain(Vec<Block>);
+
+    impl Chain {
+        fn new(len: u32) -> Self {
+            let mut blocks: Vec<Block> = Vec::new();
+            for height in 0..len {
+                let txdata = (0..=height)
+                    .map(|i| Transaction {
+                        version: 2,
+                        lock_time: LockTime::from_consensus(height * 100 + i),
+                        input: vec![TxIn {
+                            previous_output: OutPoint::null(),
+                            script_sig: ScriptBuf::new(),
+                            sequence: Sequence::MAX,
+                            witness: Witness::new(),
+                        }],
+                        output: vec![TxOut {
+                            value: 1_000,
+                            script_pubkey: ScriptBuf::new(),
+                        }],
+                    })
+                    .collect();
+                blocks.push(Block {
+                    header: Header {
+                        version: Version::TWO,
+                        prev_blockhash: blocks
+                            .last()
+                            .map_or_else(BlockHash::all_zeros, Block::block_hash),
+                        merkle_root: TxMerkleNode::all_zeros(),
+                        time: 1_704_067_200 + height * 86_400,
+                        bits: CompactTarget::from_consensus(0x207f_ffff),
+                        nonce: 0,
+                    },
+                    txdata,
+                });
+            }
+            Self(blocks)
+        }
+    }
+
+    #[async_trait]
+    impl BlockProvider for Chain {
+        async fn tip_height(&self) -> AnyaResult<u32> {
+            Ok(u32::try_from(self.0.len()).unwrap() - 1)
+        }
+
+        async fn block(&self, height: u32) -> AnyaResult<Option<Block>> {
+            Ok(self.0.get(height as usize).cloned())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exporter_partitions_and_resumes() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let events = EventStore::open(EventStoreConfig::default(), storage.clone())
+            .await
+            .unwrap();
+        events
+            .append(TX_TOPIC, "received", json!({"txid": "aa"}))
+            .await
+            .unwrap();
+        events
+            .append("lifecycle", "started", json!({}))
+            .await
+            .unwrap();
+
+        let root = std::env::temp_dir().join(format!("anya-export-{}", std::process::id()));
+        let store = Arc::new(LocalFileStore::new(&root));
+        let chain = Arc::new(Chain::new(4));
+        let config = ExportConfig {
+            batch_rows: 3,
+            ..ExportConfig::default()
+        };
+        let mut exporter = Exporter::new(config, store.clone(), storage).await.unwrap();
+        exporter
+            .add_source(Arc::new(BlockSource::new(chain.clone(), 1)))
+            .unwrap();
+        exporter
+            .add_source(Arc::new(TransactionSource::new(chain, 1)))
+            .unwrap();
+        exporter
+            .add_source(Arc::new(EventSource::new(
+                events.clone(),
+                "wallet_events",
+                TX_TOPIC,
+            )))
+            .unwrap();
+        assert!(exporter
+            .add_source(Arc::new(EventSource::new(events.clone(), "blocks", "x")))
+            .is_err());
+
+        let files = exporter.export_once().await.unwrap();
+        // Height 3 is not final yet; heights 0 to 2 are one day each
+        let blocks: Vec<_> = files.iter().filter(|f| f.dataset == "blocks").collect();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].dir, "warehouse/blocks/date=2024-01-01");
+        assert_eq!(
+            blocks[0].name,
+            "part-00000000000000000000-00000000000000000003.parquet"
+        );
+        assert_eq!(exporter.watermark("blocks").await.unwrap(), 3);
+        // Two batches: heights 0 and 1 (3 rows), then height 2 (3 rows)
+        let txs: Vec<_> = files
+            .iter()
+            .filter(|f| f.dataset == "transactions")
+            .collect();
+        assert_eq!(
+            txs.iter().map(|f| f.rows).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            txs[2].name,
+            "part-00000000000000000002-00000000000000000003.parquet"
+        );
+        let wallet: Vec<_> = files
+            .iter()
+            .filter(|f| f.dataset == "wallet_events")
+            .collect();
+        assert_eq!(wallet.len(), 1);
+        assert_eq!(wallet[0].rows, 1);
+        assert_eq!(exporter.watermark("wallet_events").await.unwrap(), 2);
+        let data = store.read(&wallet[0].dir, &wallet[0].name).await.unwrap();
+        assert_eq!(&data[..4], b"PAR1");
+
+        // Nothing new until more events arrive
+        assert!(exporter.export_once().await.unwrap().is_empty());
+        events
+            .append(TX_TOPIC, "confirmed", json!({"txid": "aa"}))
+            .await
+            .unwrap();
+        let files = exporter.export_once().await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files[0].name,
+            "part-00000000000000000002-00000000000000000003.parquet"
+        );
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
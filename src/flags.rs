@@ -0,0 +1,529 @@
+//! Runtime feature flags
+//!
+//! Flags are declared in a local JSON file shipped with the deployment:
+//!
+//! ```json
+//! {
+//!   "flags": [
+//!     {"name": "psbt_batching", "rollout_percent": 25, "tenants": ["acme"]},
+//!     {"name": "agent_autotrade", "enabled": false}
+//!   ]
+//! }
+//! ```
+//!
+//! Operators change flags at runtime through [`FeatureFlags::set_override`],
+//! which persists a [`FlagOverride`] in shared storage so every node picks
+//! it up on its next refresh. Overridden fields replace the file's. The file
+//! is re-read on the same interval, so neither path needs a redeploy.
+//!
+//! A flag is evaluated for a [`FlagContext`] in this order: unknown flags
+//! are off, a disabled flag is off for everyone (the kill switch), excluded
+//! tenants are off, targeted tenants are on, and everyone else is on when
+//! their stable rollout bucket, derived from the flag name and the tenant
+//! (or subject, when set), falls below `rollout_percent`. Raising the
+//! percentage only adds tenants; nobody already in the rollout drops out.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::sha256;
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+const NAMESPACE: &str = "feature_flags";
+const OVERRIDE_PREFIX: &str = "override/";
+
+const fn default_true() -> bool {
+    true
+}
+
+const fn default_percent() -> u8 {
+    100
+}
+
+/// A flag as declared in the flag file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Flag {
+    /// Flag name checked by callers
+    pub name: String,
+    /// What the flag gates
+    #[serde(default)]
+    pub description: String,
+    /// `false` turns the flag off for everyone
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Share of tenants, 0 to 100, the flag is on for
+    #[serde(default = "default_percent")]
+    pub rollout_percent: u8,
+    /// Tenants the flag is always on for
+    #[serde(default)]
+    pub tenants: Vec<String>,
+    /// Tenants the flag is always off for
+    #[serde(default)]
+    pub excluded_tenants: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct FlagFile {
+    flags: Vec<Flag>,
+}
+
+/// Runtime change to a flag; `None` fields keep the file's value
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlagOverride {
+    /// Replaces [`Flag::enabled`]
+    pub enabled: Option<bool>,
+    /// Replaces [`Flag::rollout_percent`]
+    pub rollout_percent: Option<u8>,
+    /// Replaces [`Flag::tenants`]
+    pub tenants: Option<Vec<String>>,
+    /// Replaces [`Flag::excluded_tenants`]
+    pub excluded_tenants: Option<Vec<String>>,
+    /// Operator who made the change
+    pub updated_by: String,
+    /// Unix time of the change
+    pub updated_at: u64,
+}
+
+impl FlagOverride {
+    fn apply(&self, flag: &mut Flag) {
+        if let Some(enabled) = self.enabled {
+            flag.enabled = enabled;
+        }
+        if let Some(percent) = self.rollout_percent {
+            flag.rollout_percent = percent;
+        }
+        if let Some(tenants) = &self.tenants {
+            flag.tenants.clone_from(tenants);
+        }
+        if let Some(excluded) = &self.excluded_tenants {
+            flag.excluded_tenants.clone_from(excluded);
+        }
+    }
+}
+
+/// Who a flag is evaluated for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlagContext {
+    /// Tenant making the request
+    pub tenant: String,
+    /// User or agent within the tenant; when set, rollout is per subject
+    /// rather than per tenant
+    pub subject: Option<String>,
+}
+
+impl FlagContext {
+    /// Context for `tenant`
+    pub fn tenant(tenant: &str) -> Self {
+        Self {
+            tenant: tenant.to_string(),
+            subject: None,
+        }
+    }
+
+    /// Roll out per `subject` within the tenant
+    pub fn with_subject(mut self, subject: &str) -> Self {
+        self.subject = Some(subject.to_string());
+        self
+    }
+}
+
+/// Why a flag evaluated as it did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagReason {
+    /// No such flag
+    Unknown,
+    /// The flag is disabled for everyone
+    Killed,
+    /// The tenant is excluded
+    Excluded,
+    /// The tenant is targeted
+    Targeted,
+    /// The rollout bucket is below the percentage
+    InRollout,
+    /// The rollout bucket is at or above the percentage
+    OutOfRollout,
+}
+
+/// Result of evaluating a flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlagDecision {
+    /// Whether the gated feature may run
+    pub enabled: bool,
+    /// Why
+    pub reason: FlagReason,
+}
+
+/// Flag service settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlagsConfig {
+    /// Flag file; without one, only overrides define flags
+    pub file: Option<PathBuf>,
+    /// Interval between reloads of the file and overrides
+    pub refresh_interval: Duration,
+}
+
+impl Default for FlagsConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            refresh_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Evaluates feature flags from the flag file and stored overrides
+pub struct FeatureFlags {
+    config: FlagsConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    flags: RwLock<Arc<BTreeMap<String, Flag>>>,
+}
+
+impl FeatureFlags {
+    /// Load the flag file and the overrides in `storage`
+    pub async fn open(
+        config: FlagsConfig,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Arc<Self>> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let flags = Arc::new(Self {
+            config,
+            storage,
+            ns,
+            flags: RwLock::new(Arc::default()),
+        });
+        flags.reload().await?;
+        Ok(flags)
+    }
+
+    /// Re-read the flag file and overrides and swap them in. On error the
+    /// previous flags stay in effect.
+    pub async fn reload(&self) -> AnyaResult<()> {
+        let mut flags = BTreeMap::new();
+        if let Some(path) = &self.config.file {
+            let json = tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("reading flag file {}", path.display()))?;
+            let file: FlagFile = serde_json::from_str(&json).map_err(|e| {
+                AnyaError::with_source(
+                    ErrorCode::Config,
+                    format!("invalid flag file {}", path.display()),
+                    e,
+                )
+            })?;
+            for flag in file.flags {
+                validate(&flag)?;
+                if flags.contains_key(&flag.name) {
+                    return Err(AnyaError::new(
+                        ErrorCode::Config,
+                        format!("duplicate flag {}", flag.name),
+                    ));
+                }
+                flags.insert(flag.name.clone(), flag);
+            }
+        }
+        for (name, over) in self.overrides().await? {
+            let flag = flags.entry(name.clone()).or_insert_with(|| Flag {
+                name,
+                description: String::new(),
+                enabled: true,
+                rollout_percent: 100,
+                tenants: Vec::new(),
+                excluded_tenants: Vec::new(),
+            });
+            over.apply(flag);
+        }
+        *self.flags.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(flags);
+        Ok(())
+    }
+
+    /// Effective flags after overrides
+    pub fn flags(&self) -> Vec<Flag> {
+        self.snapshot().values().cloned().collect()
+    }
+
+    /// Evaluate `name` for `ctx`
+    pub fn evaluate(&self, name: &str, ctx: &FlagContext) -> FlagDecision {
+        let decision = |enabled, reason| FlagDecision { enabled, reason };
+        let flags = self.snapshot();
+        let Some(flag) = flags.get(name) else {
+            return decision(false, FlagReason::Unknown);
+        };
+        if !flag.enabled {
+            return decision(false, FlagReason::Killed);
+        }
+        if flag.excluded_tenants.contains(&ctx.tenant) {
+            return decision(false, FlagReason::Excluded);
+        }
+        if flag.tenants.contains(&ctx.tenant) {
+            return decision(true, FlagReason::Targeted);
+        }
+        let unit = ctx.subject.as_deref().unwrap_or(&ctx.tenant);
+        if bucket(name, &ctx.tenant, unit) < flag.rollout_percent {
+            decision(true, FlagReason::InRollout)
+        } else {
+            decision(false, FlagReason::OutOfRollout)
+        }
+    }
+
+    /// Whether `name` is on for `ctx`
+    pub fn is_enabled(&self, name: &str, ctx: &FlagContext) -> bool {
+        self.evaluate(name, ctx).enabled
+    }
+
+    /// Fail with [`ErrorCode::Unavailable`] unless `name` is on for `ctx`
+    pub fn require(&self, name: &str, ctx: &FlagContext) -> AnyaResult<()> {
+        let decision = self.evaluate(name, ctx);
+        if decision.enabled {
+            Ok(())
+        } else {
+            Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                format!(
+                    "feature {} is not enabled for tenant {} ({:?})",
+                    name, ctx.tenant, decision.reason
+                ),
+            ))
+        }
+    }
+
+    /// Persist an override of `name` and apply it on this node immediately;
+    /// other nodes apply it on their next refresh
+    pub async fn set_override(&self, name: &str, over: FlagOverride) -> AnyaResult<()> {
+        if over.rollout_percent.is_some_and(|p| p > 100) {
+            return Err(AnyaError::invalid_input(format!(
+                "rollout for {} must be at most 100%",
+                name
+            )));
+        }
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", OVERRIDE_PREFIX, name),
+                &serde_json::to_vec(&over)?,
+            )
+            .await?;
+        info!(flag = name, by = %over.updated_by, "feature flag overridden");
+        self.reload().await
+    }
+
+    /// Turn `name` off for everyone
+    pub async fn kill(&self, name: &str, operator: &str) -> AnyaResult<()> {
+        let mut over = self.override_of(name).await?.unwrap_or_default();
+        over.enabled = Some(false);
+        over.updated_by = operator.to_string();
+        over.updated_at = unix_now();
+        self.set_override(name, over).await
+    }
+
+    /// Remove the override of `name`, returning to the file's definition
+    pub async fn clear_override(&self, name: &str) -> AnyaResult<bool> {
+        let removed = self
+            .storage
+            .delete(&self.ns, &format!("{}{}", OVERRIDE_PREFIX, name))
+            .await?;
+        self.reload().await?;
+        Ok(removed)
+    }
+
+    /// Stored override of `name`
+    pub async fn override_of(&self, name: &str) -> AnyaResult<Option<FlagOverride>> {
+        self.storage
+            .get(&self.ns, &format!("{}{}", OVERRIDE_PREFIX, name))
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Reload on the configured interval until `token` is cancelled
+    pub async fn run_schedule(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.refresh_interval, || {
+            let flags = Arc::clone(&self);
+            async move {
+                if let Err(e) = flags.reload().await {
+                    warn!(error = %e, "feature flag reload failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    fn snapshot(&self) -> Arc<BTreeMap<String, Flag>> {
+        Arc::clone(&self.flags.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    async fn overrides(&self) -> AnyaResult<Vec<(String, FlagOverride)>> {
+        self.storage
+            .scan_prefix(&self.ns, OVERRIDE_PREFIX)
+            .await?
+            .into_iter()
+            .map(|(key, bytes)| {
+                let name = key[OVERRIDE_PREFIX.len()..].to_string();
+                Ok((name, serde_json::from_slice(&bytes)?))
+            })
+            .collect()
+    }
+}
+
+/// Feature flag reloading as a lifecycle-managed subsystem
+pub struct FeatureFlagService {
+    flags: Arc<FeatureFlags>,
+}
+
+impl FeatureFlagService {
+    /// Wrap the flags for registration with the lifecycle manager
+    pub const fn new(flags: Arc<FeatureFlags>) -> Self {
+        Self { flags }
+    }
+}
+
+#[async_trait]
+impl Subsystem for FeatureFlagService {
+    fn name(&self) -> &str {
+        "feature-flags"
+    }
+
+    fn startup_order(&self) -> u32 {
+        // Start before the subsystems that check flags
+        10
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let flags = Arc::clone(&self.flags);
+        spawner
+            .spawn("reload", move |token| flags.run_schedule(token))
+            .await;
+        Ok(())
+    }
+}
+
+fn validate(flag: &Flag) -> AnyaResult<()> {
+    if flag.name.is_empty() {
+        return Err(AnyaError::new(ErrorCode::Config, "flag without a name"));
+    }
+    if flag.rollout_percent > 100 {
+        return Err(AnyaError::new(
+            ErrorCode::Config,
+            format!("rollout for {} must be at most 100%", flag.name),
+        ));
+    }
+    Ok(())
+}
+
+/// Stable bucket in 0..100 of `unit` within `tenant` for `flag`
+fn bucket(flag: &str, tenant: &str, unit: &str) -> u8 {
+    let hash = sha256(format!("{}\0{}\0{}", flag, tenant, unit).as_bytes());
+    let value = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]);
+    u8::try_from(value % 100).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    #[tokio::test]
+    async fn file_flags_target_tenants_and_roll_out_by_percentage() {
+        let dir = std::env::temp_dir().join(format!("anya-flags-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("flags.json");
+        std::fs::write(
+            &path,
+            r#"{"flags": [
+                {"name": "batching", "rollout_percent": 30,
+                 "tenants": ["acme"], "excluded_tenants": ["globex"]},
+                {"name": "autotrade", "enabled": false}
+            ]}"#,
+        )
+        .unwrap();
+        let flags = FeatureFlags::open(
+            FlagsConfig {
+                file: Some(path.clone()),
+                ..FlagsConfig::default()
+            },
+            Arc::new(MemoryBackend::new()),
+        )
+        .await
+        .unwrap();
+
+        let acme = FlagContext::tenant("acme");
+        assert_eq!(
+            flags.evaluate("batching", &acme).reason,
+            FlagReason::Targeted
+        );
+        assert!(!flags.is_enabled("batching", &FlagContext::tenant("globex")));
+        assert_eq!(
+            flags.evaluate("autotrade", &acme).reason,
+            FlagReason::Killed
+        );
+        assert_eq!(flags.evaluate("missing", &acme).reason, FlagReason::Unknown);
+
+        let on = (0..1000)
+            .filter(|i| flags.is_enabled("batching", &FlagContext::tenant(&format!("t{}", i))))
+            .count();
+        assert!(
+            (250..350).contains(&on),
+            "{} of 1000 tenants in a 30% rollout",
+            on
+        );
+
+        // Edits to the file apply on the next reload
+        std::fs::write(&path, r#"{"flags": [{"name": "batching"}]}"#).unwrap();
+        flags.reload().await.unwrap();
+        assert!(flags.is_enabled("batching", &FlagContext::tenant("globex")));
+        std::fs::write(&path, "{").unwrap();
+        assert!(flags.reload().await.is_err());
+        assert!(flags.is_enabled("batching", &FlagContext::tenant("globex")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overrides_are_shared_through_storage_and_kill_switch_wins() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let node_a = FeatureFlags::open(FlagsConfig::default(), Arc::clone(&storage))
+            .await
+            .unwrap();
+        let node_b = FeatureFlags::open(FlagsConfig::default(), storage)
+            .await
+            .unwrap();
+        let ctx = FlagContext::tenant("acme").with_subject("agent-7");
+
+        node_a
+            .set_override(
+                "agents.rebalance",
+                FlagOverride {
+                    rollout_percent: Some(100),
+                    ..FlagOverride::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(node_a.is_enabled("agents.rebalance", &ctx));
+        assert!(!node_b.is_enabled("agents.rebalance", &ctx));
+        node_b.reload().await.unwrap();
+        assert!(node_b.is_enabled("agents.rebalance", &ctx));
+
+        node_a.kill("agents.rebalance", "oncall").await.unwrap();
+        let err = node_a.require("agents.rebalance", &ctx).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unavailable);
+        assert!(node_a.clear_override("agents.rebalance").await.unwrap());
+        assert_eq!(
+            node_a.evaluate("agents.rebalance", &ctx).reason,
+            FlagReason::Unknown
+        );
+    }
+}
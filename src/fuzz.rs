@@ -0,0 +1,103 @@
+//! Fuzzing entry points for parsers of untrusted input
+//!
+//! Each `fuzz_*` function feeds arbitrary bytes to one parser and checks the
+//! invariants that must hold for whatever it accepts, so it only panics on a
+//! genuine bug. The `fuzz/` crate wraps them as cargo-fuzz targets, with seed
+//! corpora checked in under `fuzz/corpus/<target>/`:
+//!
+//! ```text
+//! cargo +nightly fuzz run psbt
+//! ```
+//!
+//! Compiled for the crate's own tests and with the `fuzzing` feature.
+
+use crate::bitcoin::accounts::ScriptType;
+use crate::bitcoin::labels::Label;
+
+/// Output descriptors as accepted by account import
+pub fn fuzz_descriptor(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(script_type) = ScriptType::from_descriptor(text) {
+        let padded = format!(" {}\n", text);
+        assert_eq!(ScriptType::from_descriptor(&padded).ok(), Some(script_type));
+    }
+}
+
+/// BIP-329 label lines
+pub fn fuzz_label(data: &[u8]) {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(label) = Label::parse(line) {
+        let encoded = serde_json::to_string(&label).expect("labels serialize");
+        assert_eq!(Label::parse(&encoded).ok(), Some(label));
+    }
+}
+
+/// PSBTs in binary, hex, or base64 form
+#[cfg(feature = "mobile")]
+pub fn fuzz_psbt(data: &[u8]) {
+    use ::bitcoin::psbt::PartiallySignedTransaction as Psbt;
+
+    if let Ok(psbt) = crate::mobile::signer::import_psbt(data) {
+        let encoded = psbt.serialize();
+        let decoded = Psbt::deserialize(&encoded).expect("serialized PSBT parses");
+        assert_eq!(decoded.serialize(), encoded);
+    }
+}
+
+/// Scanned QR text: payment URIs, invoices, BBQr fragments, pairing offers
+#[cfg(feature = "mobile")]
+pub fn fuzz_qr_payload(data: &[u8]) {
+    use crate::mobile::qr::{PaymentUri, QrPayload};
+
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(QrPayload::Bitcoin(uri)) = QrPayload::parse(text) {
+        let again = PaymentUri::parse(&uri.to_uri()).expect("serialized URI parses");
+        assert_eq!(again.amount, uri.amount);
+        assert_eq!(again.label, uri.label);
+        assert_eq!(again.message, uri.message);
+        // Invoices are case-insensitive and uppercased for QR alphanumeric mode
+        let lower = |invoice: &Option<String>| invoice.as_deref().map(str::to_ascii_lowercase);
+        assert_eq!(lower(&again.lightning), lower(&uri.lightning));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn seeds(target: &str) -> Vec<Vec<u8>> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/corpus")
+            .join(target);
+        let seeds: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+            .collect();
+        assert!(!seeds.is_empty());
+        seeds
+    }
+
+    #[test]
+    fn test_seed_corpora_pass() {
+        seeds("descriptor").iter().for_each(|s| fuzz_descriptor(s));
+        seeds("label").iter().for_each(|s| fuzz_label(s));
+    }
+
+    #[cfg(feature = "mobile")]
+    #[test]
+    fn test_mobile_seed_corpora_pass() {
+        seeds("psbt").iter().for_each(|s| fuzz_psbt(s));
+        seeds("qr_payload").iter().for_each(|s| fuzz_qr_payload(s));
+
+        // Scheme matching once sliced through the middle of a character
+        fuzz_qr_payload("bitcoin\u{e9}:x".as_bytes());
+        fuzz_qr_payload("bitcoi\u{e9}".as_bytes());
+    }
+}
@@ -0,0 +1,629 @@
+//! Business rules as decision tables
+//!
+//! Rule files are JSON documents in a DMN-like format: a [`DecisionTable`]
+//! declares typed input and output columns and a list of rows whose input
+//! cells are unary tests, e.g.
+//!
+//! ```json
+//! {
+//!   "name": "fee_tier",
+//!   "version": 2,
+//!   "hit_policy": "first",
+//!   "inputs": [{"field": "amount_sat", "type": "number"},
+//!              {"field": "customer.tier", "type": "string"}],
+//!   "outputs": [{"field": "fee_bps", "type": "number"}],
+//!   "rules": [
+//!     {"id": "big-gold", "when": [">= 1000000", "\"gold\""], "then": [5]},
+//!     {"id": "default", "when": ["-", "-"], "then": [25]}
+//!   ]
+//! }
+//! ```
+//!
+//! A cell is `-` (anything), a comparison (`< 10`, `>= 5`), a range
+//! (`[1..10]`, `(1..10)`), a literal (`"gold"`, `42`, `true`), a
+//! comma-separated list of those, or `not(...)` of a list. Cells are parsed
+//! once when the table is deployed, so evaluation never fails on syntax.
+//!
+//! [`RuleEngine`] holds the deployed version of every table. Deploying a
+//! newer version swaps it in atomically; executions already running keep
+//! the version they started with, and the previous versions are kept for
+//! [`RuleEngine::rollback`]. Every [`Evaluation`] carries a trace of which
+//! rows matched and which input ruled the others out.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+/// Type of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    /// JSON number
+    Number,
+    /// JSON string
+    String,
+    /// JSON boolean
+    Boolean,
+}
+
+impl FieldType {
+    const fn accepts(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (Self::Number, Value::Number(_))
+                | (Self::String, Value::String(_))
+                | (Self::Boolean, Value::Bool(_))
+        )
+    }
+}
+
+/// A typed column
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Column {
+    /// Dotted path in the input context, or output key
+    pub field: String,
+    /// Value type
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+}
+
+/// How matching rows produce the result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HitPolicy {
+    /// First matching row only
+    First,
+    /// At most one row may match
+    Unique,
+    /// Every matching row, in order
+    Collect,
+}
+
+/// One row as written in the rule file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleRow {
+    /// Row id used in traces
+    pub id: String,
+    /// One unary test per input column
+    pub when: Vec<String>,
+    /// One value per output column
+    pub then: Vec<Value>,
+}
+
+/// A decision table as written in the rule file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecisionTable {
+    /// Table name
+    pub name: String,
+    /// Version; deploying requires a higher one than the current
+    pub version: u64,
+    /// How matching rows combine
+    pub hit_policy: HitPolicy,
+    /// Input columns
+    pub inputs: Vec<Column>,
+    /// Output columns
+    pub outputs: Vec<Column>,
+    /// Rows in order
+    pub rules: Vec<RuleRow>,
+}
+
+/// Outcome of one row
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Row id
+    pub rule: String,
+    /// Whether all cells matched
+    pub matched: bool,
+    /// First input that did not match
+    pub failed_input: Option<String>,
+}
+
+/// Result of executing a table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Evaluation {
+    /// Table name
+    pub table: String,
+    /// Table version that produced the result
+    pub version: u64,
+    /// Output contexts of the matching rows
+    pub outputs: Vec<Map<String, Value>>,
+    /// Per-row trace, in evaluation order
+    pub trace: Vec<TraceEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    String(String),
+    Bool(bool),
+}
+
+impl Literal {
+    fn parse(text: &str) -> Option<Self> {
+        if let Some(s) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            return Some(Self::String(s.to_string()));
+        }
+        match text {
+            "true" => Some(Self::Bool(true)),
+            "false" => Some(Self::Bool(false)),
+            _ => text.parse().ok().map(Self::Number),
+        }
+    }
+
+    fn compare(&self, value: &Value) -> Option<std::cmp::Ordering> {
+        match (value, self) {
+            (Value::Number(n), Self::Number(x)) => n.as_f64()?.partial_cmp(x),
+            (Value::String(s), Self::String(x)) => Some(s.as_str().cmp(x)),
+            (Value::Bool(b), Self::Bool(x)) => Some(b.cmp(x)),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed cell
+#[derive(Debug, Clone, PartialEq)]
+enum UnaryTest {
+    Any,
+    Compare(std::cmp::Ordering, bool, Literal),
+    Range(Literal, bool, Literal, bool),
+    Equal(Literal),
+    AnyOf(Vec<Self>),
+    Not(Box<Self>),
+}
+
+impl UnaryTest {
+    fn parse(cell: &str) -> Option<Self> {
+        let cell = cell.trim();
+        if cell == "-" || cell.is_empty() {
+            return Some(Self::Any);
+        }
+        if let Some(inner) = cell.strip_prefix("not(").and_then(|c| c.strip_suffix(')')) {
+            return Some(Self::Not(Box::new(Self::parse(inner)?)));
+        }
+        let parts = split_list(cell);
+        if parts.len() > 1 {
+            return parts
+                .iter()
+                .map(|p| Self::parse_single(p))
+                .collect::<Option<_>>()
+                .map(Self::AnyOf);
+        }
+        Self::parse_single(cell)
+    }
+
+    fn parse_single(cell: &str) -> Option<Self> {
+        use std::cmp::Ordering::{Greater, Less};
+        let cell = cell.trim();
+        for (op, ordering, or_equal) in [
+            ("<=", Less, true),
+            (">=", Greater, true),
+            ("<", Less, false),
+            (">", Greater, false),
+        ] {
+            if let Some(rest) = cell.strip_prefix(op) {
+                return Literal::parse(rest.trim()).map(|l| Self::Compare(ordering, or_equal, l));
+            }
+        }
+        let open = match cell.chars().next()? {
+            '[' => Some(true),
+            '(' => Some(false),
+            _ => None,
+        };
+        let close = match cell.chars().last()? {
+            ']' => Some(true),
+            ')' => Some(false),
+            _ => None,
+        };
+        if let (Some(open), Some(close)) = (open, close) {
+            let (low, high) = cell[1..cell.len() - 1].split_once("..")?;
+            return Some(Self::Range(
+                Literal::parse(low.trim())?,
+                open,
+                Literal::parse(high.trim())?,
+                close,
+            ));
+        }
+        Literal::parse(cell).map(Self::Equal)
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Compare(ordering, or_equal, literal) => literal
+                .compare(value)
+                .is_some_and(|o| o == *ordering || (*or_equal && o.is_eq())),
+            Self::Range(low, low_inclusive, high, high_inclusive) => {
+                let above = low
+                    .compare(value)
+                    .is_some_and(|o| o.is_gt() || (*low_inclusive && o.is_eq()));
+                let below = high
+                    .compare(value)
+                    .is_some_and(|o| o.is_lt() || (*high_inclusive && o.is_eq()));
+                above && below
+            }
+            Self::Equal(literal) => literal.compare(value).is_some_and(|o| o.is_eq()),
+            Self::AnyOf(tests) => tests.iter().any(|t| t.matches(value)),
+            Self::Not(test) => !test.matches(value),
+        }
+    }
+}
+
+/// Split a cell on commas outside of quotes and brackets
+fn split_list(cell: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quoted, mut start) = (0i32, false, 0);
+    for (i, c) in cell.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' | '(' if !quoted => depth += 1,
+            ']' | ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                parts.push(&cell[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&cell[start..]);
+    parts
+}
+
+/// A table ready to execute
+#[derive(Debug)]
+struct CompiledTable {
+    table: DecisionTable,
+    tests: Vec<Vec<UnaryTest>>,
+}
+
+impl CompiledTable {
+    fn compile(table: DecisionTable) -> AnyaResult<Self> {
+        let invalid = |msg: String| {
+            AnyaError::invalid_input(format!("table {} v{}: {}", table.name, table.version, msg))
+        };
+        if table.name.is_empty() || table.inputs.is_empty() || table.outputs.is_empty() {
+            return Err(invalid("needs a name, inputs, and outputs".into()));
+        }
+        let mut tests = Vec::with_capacity(table.rules.len());
+        for row in &table.rules {
+            if row.when.len() != table.inputs.len() || row.then.len() != table.outputs.len() {
+                return Err(invalid(format!(
+                    "rule {} has the wrong number of cells",
+                    row.id
+                )));
+            }
+            for (value, column) in row.then.iter().zip(&table.outputs) {
+                if !column.kind.accepts(value) {
+                    return Err(invalid(format!(
+                        "rule {} outputs {} for {:?} column {}",
+                        row.id, value, column.kind, column.field
+                    )));
+                }
+            }
+            let row_tests = row
+                .when
+                .iter()
+                .map(|cell| {
+                    UnaryTest::parse(cell).ok_or_else(|| {
+                        invalid(format!("rule {} has invalid cell {:?}", row.id, cell))
+                    })
+                })
+                .collect::<AnyaResult<Vec<_>>>()?;
+            tests.push(row_tests);
+        }
+        Ok(Self { table, tests })
+    }
+
+    fn execute(&self, input: &Value) -> AnyaResult<Evaluation> {
+        let table = &self.table;
+        let values = table
+            .inputs
+            .iter()
+            .map(|column| {
+                let value = column
+                    .field
+                    .split('.')
+                    .try_fold(input, |v, key| v.get(key))
+                    .unwrap_or(&Value::Null);
+                if value.is_null() || column.kind.accepts(value) {
+                    Ok(value)
+                } else {
+                    Err(AnyaError::invalid_input(format!(
+                        "input {} must be a {:?}, got {}",
+                        column.field, column.kind, value
+                    )))
+                }
+            })
+            .collect::<AnyaResult<Vec<_>>>()?;
+
+        let mut outputs = Vec::new();
+        let mut trace = Vec::with_capacity(table.rules.len());
+        for (row, tests) in table.rules.iter().zip(&self.tests) {
+            let failed = tests
+                .iter()
+                .zip(&values)
+                .position(|(test, value)| !test.matches(value));
+            trace.push(TraceEntry {
+                rule: row.id.clone(),
+                matched: failed.is_none(),
+                failed_input: failed.map(|i| table.inputs[i].field.clone()),
+            });
+            if failed.is_some() {
+                continue;
+            }
+            outputs.push(
+                table
+                    .outputs
+                    .iter()
+                    .zip(&row.then)
+                    .map(|(column, value)| (column.field.clone(), value.clone()))
+                    .collect(),
+            );
+            if table.hit_policy == HitPolicy::First {
+                break;
+            }
+        }
+        if table.hit_policy == HitPolicy::Unique && outputs.len() > 1 {
+            let matched: Vec<&str> = trace
+                .iter()
+                .filter(|t| t.matched)
+                .map(|t| t.rule.as_str())
+                .collect();
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!(
+                    "table {} is unique but rules {} all matched",
+                    table.name,
+                    matched.join(", ")
+                ),
+            ));
+        }
+        Ok(Evaluation {
+            table: table.name.clone(),
+            version: table.version,
+            outputs,
+            trace,
+        })
+    }
+}
+
+/// Deployed decision tables with version history
+#[derive(Default)]
+pub struct RuleEngine {
+    tables: RwLock<HashMap<String, Vec<Arc<CompiledTable>>>>,
+}
+
+impl RuleEngine {
+    /// Create an engine without tables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validate and deploy `table`, replacing an older version
+    pub fn deploy(&self, table: DecisionTable) -> AnyaResult<()> {
+        let compiled = Arc::new(CompiledTable::compile(table)?);
+        let mut tables = self.tables.write().unwrap_or_else(PoisonError::into_inner);
+        let versions = tables.entry(compiled.table.name.clone()).or_default();
+        if let Some(current) = versions.last() {
+            if current.table.version >= compiled.table.version {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!(
+                        "table {} is at v{}, cannot deploy v{}",
+                        compiled.table.name, current.table.version, compiled.table.version
+                    ),
+                ));
+            }
+        }
+        tracing::info!(
+            table = %compiled.table.name,
+            version = compiled.table.version,
+            "decision table deployed"
+        );
+        versions.push(compiled);
+        drop(tables);
+        Ok(())
+    }
+
+    /// Parse a rule file and deploy it
+    pub fn deploy_json(&self, json: &str) -> AnyaResult<()> {
+        let table: DecisionTable = serde_json::from_str(json)
+            .map_err(|e| AnyaError::with_source(ErrorCode::Config, "invalid rule file", e))?;
+        self.deploy(table)
+    }
+
+    /// Deploy every `*.json` rule file in `dir` that is newer than what is
+    /// deployed; returns the tables swapped in. Calling this periodically
+    /// hot-reloads edited rule files.
+    pub async fn load_dir(&self, dir: &Path) -> AnyaResult<Vec<String>> {
+        let mut entries = tokio::fs::read_dir(dir)
+            .await
+            .with_context(|| format!("reading rule directory {}", dir.display()))?;
+        let mut paths = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let mut deployed = Vec::new();
+        for path in paths {
+            let json = tokio::fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("reading rule file {}", path.display()))?;
+            let table: DecisionTable = serde_json::from_str(&json).map_err(|e| {
+                AnyaError::with_source(
+                    ErrorCode::Config,
+                    format!("invalid rule file {}", path.display()),
+                    e,
+                )
+            })?;
+            if self
+                .version(&table.name)
+                .is_some_and(|current| current >= table.version)
+            {
+                continue;
+            }
+            let name = table.name.clone();
+            self.deploy(table)?;
+            deployed.push(name);
+        }
+        Ok(deployed)
+    }
+
+    /// Deployed version of `table`
+    pub fn version(&self, table: &str) -> Option<u64> {
+        self.current(table).map(|t| t.table.version)
+    }
+
+    /// Return `table` to its previous version; returns that version
+    pub fn rollback(&self, table: &str) -> AnyaResult<u64> {
+        let mut tables = self.tables.write().unwrap_or_else(PoisonError::into_inner);
+        let versions = tables
+            .get_mut(table)
+            .filter(|v| v.len() > 1)
+            .ok_or_else(|| AnyaError::not_found(format!("previous version of table {}", table)))?;
+        versions.pop();
+        let version = versions.last().map_or(0, |t| t.table.version);
+        drop(tables);
+        Ok(version)
+    }
+
+    /// Evaluate `table` against the `input` context
+    pub fn execute(&self, table: &str, input: &Value) -> AnyaResult<Evaluation> {
+        self.current(table)
+            .ok_or_else(|| AnyaError::not_found(format!("decision table {}", table)))?
+            .execute(input)
+    }
+
+    fn current(&self, table: &str) -> Option<Arc<CompiledTable>> {
+        self.tables
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(table)
+            .and_then(|versions| versions.last().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fee_table(version: u64, default_bps: u64) -> String {
+        json!({
+            "name": "fee_tier",
+            "version": version,
+            "hit_policy": "first",
+            "inputs": [
+                {"field": "amount_sat", "type": "number"},
+                {"field": "customer.tier", "type": "string"}
+            ],
+            "outputs": [{"field": "fee_bps", "type": "number"}],
+            "rules": [
+                {"id": "big-gold", "when": [">= 1000000", "\"gold\""], "then": [5]},
+                {"id": "mid", "when": ["[10000..1000000)", "not(\"blocked\")"], "then": [15]},
+                {"id": "vip", "when": ["-", "\"gold\", \"platinum\""], "then": [10]},
+                {"id": "default", "when": ["-", "-"], "then": [default_bps]}
+            ]
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_decision_table_evaluation_and_trace() {
+        let engine = RuleEngine::new();
+        engine.deploy_json(&fee_table(1, 25)).unwrap();
+        let fee = |amount: u64, tier: &str| {
+            let evaluation = engine
+                .execute(
+                    "fee_tier",
+                    &json!({"amount_sat": amount, "customer": {"tier": tier}}),
+                )
+                .unwrap();
+            evaluation.outputs[0]["fee_bps"].as_u64().unwrap()
+        };
+        assert_eq!(fee(2_000_000, "gold"), 5);
+        assert_eq!(fee(50_000, "silver"), 15);
+        assert_eq!(fee(1_000_000, "silver"), 25);
+        assert_eq!(fee(500, "platinum"), 10);
+        assert_eq!(fee(50_000, "blocked"), 25);
+
+        let evaluation = engine
+            .execute(
+                "fee_tier",
+                &json!({"amount_sat": 500, "customer": {"tier": "gold"}}),
+            )
+            .unwrap();
+        assert_eq!(
+            evaluation.trace[0].failed_input.as_deref(),
+            Some("amount_sat")
+        );
+        assert_eq!(evaluation.trace.len(), 3);
+        assert!(evaluation.trace[2].matched);
+
+        assert!(engine
+            .execute("fee_tier", &json!({"amount_sat": "lots"}))
+            .is_err());
+        let mut bad = fee_table(2, 25).replace("[10000..1000000)", "between 1 and 2");
+        assert!(engine.deploy_json(&bad).is_err());
+        bad = fee_table(2, 25).replace("\"then\":[5]", "\"then\":[\"5\"]");
+        assert!(engine.deploy_json(&bad).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hot_swap_versions_and_rollback() {
+        let dir = std::env::temp_dir().join(format!("anya-gorules-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("fee_tier.json"), fee_table(1, 25)).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a rule file").unwrap();
+
+        let engine = RuleEngine::new();
+        assert_eq!(engine.load_dir(&dir).await.unwrap(), vec!["fee_tier"]);
+        assert!(engine.load_dir(&dir).await.unwrap().is_empty());
+        let input = json!({"amount_sat": 1, "customer": {"tier": "bronze"}});
+        assert_eq!(
+            engine.execute("fee_tier", &input).unwrap().outputs[0]["fee_bps"],
+            25
+        );
+
+        std::fs::write(dir.join("fee_tier.json"), fee_table(2, 30)).unwrap();
+        assert_eq!(engine.load_dir(&dir).await.unwrap(), vec!["fee_tier"]);
+        let evaluation = engine.execute("fee_tier", &input).unwrap();
+        assert_eq!(
+            (
+                evaluation.version,
+                evaluation.outputs[0]["fee_bps"].as_u64()
+            ),
+            (2, Some(30))
+        );
+        assert_eq!(
+            engine.deploy_json(&fee_table(2, 40)).unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+
+        assert_eq!(engine.rollback("fee_tier").unwrap(), 1);
+        assert_eq!(engine.version("fee_tier"), Some(1));
+        assert!(engine.rollback("fee_tier").is_err());
+
+        let unique = fee_table(3, 25).replace("\"first\"", "\"unique\"");
+        engine.deploy_json(&unique).unwrap();
+        assert_eq!(
+            engine
+                .execute(
+                    "fee_tier",
+                    &json!({"amount_sat": 2_000_000, "customer": {"tier": "gold"}})
+                )
+                .unwrap_err()
+                .code(),
+            ErrorCode::Conflict
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
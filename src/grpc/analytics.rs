@@ -0,0 +1,140 @@
+//! `AnalyticsService` over a [`FeeMarket`]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic::{Request, Response, Status};
+
+use super::pb::analytics_service_server::AnalyticsService;
+use super::pb::{self, GetFeeMarketRequest};
+use crate::ml::fee_market::{self, FeeMarket};
+use crate::utils::time::unix_now;
+
+const DEFAULT_HORIZON_HOURS: u32 = 24;
+const MAX_HORIZON_HOURS: u32 = 7 * 24;
+const DEFAULT_WINDOWS: u32 = 3;
+
+/// Serves `anya.v1.AnalyticsService`
+pub struct AnalyticsGrpc {
+    fees: Arc<FeeMarket>,
+}
+
+impl AnalyticsGrpc {
+    /// Service reporting on `fees`
+    pub const fn new(fees: Arc<FeeMarket>) -> Self {
+        Self { fees }
+    }
+
+    /// Tonic server wrapper for this service
+    pub fn into_server(self) -> pb::analytics_service_server::AnalyticsServiceServer<Self> {
+        pb::analytics_service_server::AnalyticsServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl AnalyticsService for AnalyticsGrpc {
+    async fn get_fee_market(
+        &self,
+        request: Request<GetFeeMarketRequest>,
+    ) -> Result<Response<pb::FeeMarketReport>, Status> {
+        let request = request.into_inner();
+        let hours = match request.horizon_hours {
+            0 => DEFAULT_HORIZON_HOURS,
+            h => h.min(MAX_HORIZON_HOURS),
+        };
+        let windows = match request.windows {
+            0 => DEFAULT_WINDOWS,
+            w => w,
+        };
+        let report = self.fees.report(
+            unix_now(),
+            Duration::from_secs(u64::from(hours) * 3_600),
+            windows as usize,
+        );
+        Ok(Response::new(pb::FeeMarketReport {
+            generated_at: report.generated_at,
+            current: report.current.map(percentiles),
+            blocks_to_clear: report.blocks_to_clear,
+            series: report
+                .series
+                .into_iter()
+                .map(|s| pb::FeeSample {
+                    timestamp: s.timestamp,
+                    mempool_vsize: s.mempool_vsize,
+                    percentiles: Some(percentiles(s.percentiles)),
+                })
+                .collect(),
+            forecast: report
+                .forecast
+                .into_iter()
+                .map(|f| pb::CongestionForecast {
+                    timestamp: f.timestamp,
+                    mempool_vsize: f.mempool_vsize,
+                    blocks_to_clear: f.blocks_to_clear,
+                    expected_median_sat_vb: f.expected_median_sat_vb,
+                })
+                .collect(),
+            windows: report
+                .windows
+                .into_iter()
+                .map(|w| pb::SendWindow {
+                    start: w.start,
+                    end: w.end,
+                    expected_median_sat_vb: w.expected_median_sat_vb,
+                })
+                .collect(),
+        }))
+    }
+}
+
+const fn percentiles(p: fee_market::FeePercentiles) -> pb::FeePercentiles {
+    pb::FeePercentiles {
+        p10: p.p10,
+        p25: p.p25,
+        p50: p.p50,
+        p75: p.p75,
+        p90: p.p90,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ml::fee_market::{FeeBucket, FeeMarketConfig, MempoolSnapshot};
+
+    #[tokio::test]
+    async fn test_fee_market_report() {
+        let fees = Arc::new(FeeMarket::new(FeeMarketConfig::default()));
+        let service = AnalyticsGrpc::new(fees.clone());
+        let empty = service
+            .get_fee_market(Request::new(GetFeeMarketRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(empty.current.is_none());
+        assert!(empty.forecast.is_empty());
+
+        let now = unix_now();
+        for h in 0..4u64 {
+            fees.record(MempoolSnapshot {
+                timestamp: now - (3 - h) * 3_600,
+                buckets: vec![FeeBucket {
+                    fee_rate: 5.0 + h as f64,
+                    vsize: 500_000,
+                }],
+            });
+        }
+        let report = service
+            .get_fee_market(Request::new(GetFeeMarketRequest {
+                horizon_hours: 6,
+                windows: 2,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(report.current.unwrap().p50, 8.0);
+        assert_eq!(report.series.len(), 4);
+        assert_eq!(report.forecast.len(), 6);
+        assert!((report.blocks_to_clear - 0.5).abs() < 1e-9);
+    }
+}
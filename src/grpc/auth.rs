@@ -0,0 +1,155 @@
+//! `AuthService` over a [`DidAuthenticator`] and the session interceptor
+//! protecting the other services
+
+use std::sync::Arc;
+
+use tonic::service::Interceptor;
+use tonic::{Request, Response, Status};
+
+use super::pb::auth_service_server::AuthService;
+use super::pb::{self, AuthenticateRequest, AuthenticateResponse};
+use super::pb::{ChallengeRequest, ChallengeResponse};
+use crate::web5::auth::{self, DidAuthenticator};
+use crate::{AnyaError, ErrorCode};
+
+/// Metadata key carrying the session token
+pub const AUTHORIZATION_METADATA: &str = "authorization";
+
+/// Serves `anya.v1.AuthService`
+pub struct AuthGrpc {
+    authenticator: Arc<DidAuthenticator>,
+}
+
+impl AuthGrpc {
+    /// Service establishing sessions with `authenticator`
+    pub const fn new(authenticator: Arc<DidAuthenticator>) -> Self {
+        Self { authenticator }
+    }
+
+    /// Tonic server wrapper for this service
+    pub fn into_server(self) -> pb::auth_service_server::AuthServiceServer<Self> {
+        pb::auth_service_server::AuthServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl AuthService for AuthGrpc {
+    async fn challenge(
+        &self,
+        request: Request<ChallengeRequest>,
+    ) -> Result<Response<ChallengeResponse>, Status> {
+        let request = request.into_inner();
+        let challenge = self.authenticator.challenge(&auth::Hello {
+            did: request.did,
+            nonce: request.nonce,
+        })?;
+        Ok(Response::new(ChallengeResponse {
+            did: challenge.did,
+            nonce: challenge.nonce,
+            signature: challenge.signature,
+        }))
+    }
+
+    async fn authenticate(
+        &self,
+        request: Request<AuthenticateRequest>,
+    ) -> Result<Response<AuthenticateResponse>, Status> {
+        let request = request.into_inner();
+        let session = self.authenticator.authenticate(&auth::Response {
+            nonce: request.nonce,
+            signature: request.signature,
+        })?;
+        Ok(Response::new(AuthenticateResponse {
+            token: session.token,
+            expires_at: session.expires_at,
+        }))
+    }
+}
+
+/// Rejects calls without a live session and attaches the [`auth::Session`]
+/// to the request extensions
+///
+/// Use with `WalletServiceServer::with_interceptor` and friends.
+#[derive(Clone)]
+pub struct SessionInterceptor {
+    authenticator: Arc<DidAuthenticator>,
+}
+
+impl SessionInterceptor {
+    /// Interceptor checking sessions issued by `authenticator`
+    pub const fn new(authenticator: Arc<DidAuthenticator>) -> Self {
+        Self { authenticator }
+    }
+}
+
+impl Interceptor for SessionInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_METADATA)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| AnyaError::new(ErrorCode::Unauthenticated, "missing bearer token"))?;
+        let session = self.authenticator.session(token)?;
+        request.extensions_mut().insert(session);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web5::auth::{AccessPolicy, AuthConfig, ClientHandshake, DidSigner, Session};
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn test_handshake_issues_token_accepted_by_interceptor() {
+        let authenticator = Arc::new(DidAuthenticator::new(
+            AuthConfig::default(),
+            DidSigner::from_seed(&[1; 32]).unwrap(),
+            AccessPolicy::default(),
+        ));
+        let service = AuthGrpc::new(authenticator.clone());
+        let client = Arc::new(DidSigner::from_seed(&[2; 32]).unwrap());
+
+        let (handshake, hello) =
+            ClientHandshake::start(client.clone(), AccessPolicy::default()).unwrap();
+        let challenge = service
+            .challenge(Request::new(ChallengeRequest {
+                did: hello.did,
+                nonce: hello.nonce,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let (response, _) = handshake
+            .finish(&auth::Challenge {
+                did: challenge.did,
+                nonce: challenge.nonce,
+                signature: challenge.signature,
+            })
+            .unwrap();
+        let token = service
+            .authenticate(Request::new(AuthenticateRequest {
+                nonce: response.nonce,
+                signature: response.signature,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .token;
+
+        let mut interceptor = SessionInterceptor::new(authenticator);
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            AUTHORIZATION_METADATA,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let request = interceptor.call(request).unwrap();
+        let session = request.extensions().get::<Session>().unwrap();
+        assert_eq!(session.peer, client.did().to_string());
+
+        let status = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+}
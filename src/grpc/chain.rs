@@ -0,0 +1,158 @@
+//! `ChainService` over a [`ChainSource`]
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::Txid;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use super::pb::chain_service_server::ChainService;
+use super::pb::{self, BlockEvent, GetTipRequest, GetTipResponse, GetTransactionRequest};
+use super::pb::{GetTransactionResponse, SubscribeBlocksRequest, TxState};
+use crate::bitcoin::tracker::{ChainSource, TxStatus};
+use crate::AnyaError;
+
+/// Buffered block events per subscriber before the poller waits
+const SUBSCRIBER_BUFFER: usize = 16;
+
+/// Serves `anya.v1.ChainService`
+pub struct ChainGrpc {
+    source: Arc<dyn ChainSource>,
+    poll_interval: Duration,
+}
+
+impl ChainGrpc {
+    /// Service over `source`, polling its tip every `poll_interval` for
+    /// block subscriptions
+    pub fn new(source: Arc<dyn ChainSource>, poll_interval: Duration) -> Self {
+        Self {
+            source,
+            poll_interval,
+        }
+    }
+
+    /// Tonic server wrapper for this service
+    pub fn into_server(self) -> pb::chain_service_server::ChainServiceServer<Self> {
+        pb::chain_service_server::ChainServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl ChainService for ChainGrpc {
+    type SubscribeBlocksStream = ReceiverStream<Result<BlockEvent, Status>>;
+
+    async fn get_tip(
+        &self,
+        _request: Request<GetTipRequest>,
+    ) -> Result<Response<GetTipResponse>, Status> {
+        let height = self.source.tip_height().await?;
+        Ok(Response::new(GetTipResponse { height }))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<GetTransactionRequest>,
+    ) -> Result<Response<GetTransactionResponse>, Status> {
+        let txid = Txid::from_str(&request.into_inner().txid)
+            .map_err(|e| AnyaError::invalid_input(format!("invalid txid: {}", e)))?;
+        let tx = self
+            .source
+            .transaction(&txid)
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("transaction {}", txid)))?;
+        let (state, height, confirmations) = match self.source.status(&txid).await? {
+            TxStatus::Unknown => (TxState::Unknown, None, 0),
+            TxStatus::Mempool => (TxState::Mempool, None, 0),
+            TxStatus::Confirmed { height } => {
+                let tip = self.source.tip_height().await?;
+                (
+                    TxState::Confirmed,
+                    Some(height),
+                    tip.saturating_sub(height) + 1,
+                )
+            }
+        };
+        Ok(Response::new(GetTransactionResponse {
+            raw: ::bitcoin::consensus::serialize(&tx),
+            state: state.into(),
+            height,
+            confirmations,
+        }))
+    }
+
+    async fn subscribe_blocks(
+        &self,
+        _request: Request<SubscribeBlocksRequest>,
+    ) -> Result<Response<Self::SubscribeBlocksStream>, Status> {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BUFFER);
+        let source = self.source.clone();
+        let mut ticker = tokio::time::interval(self.poll_interval);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                ticker.tick().await;
+                let event = match source.tip_height().await {
+                    Ok(height) if Some(height) == last => continue,
+                    Ok(height) => {
+                        let reorg = last.is_some_and(|l| height < l);
+                        last = Some(height);
+                        Ok(BlockEvent { height, reorg })
+                    }
+                    Err(err) => Err(Status::from(err)),
+                };
+                // Stop polling once the subscriber hangs up
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::regtest::RegtestChain;
+    use ::bitcoin::{Network, ScriptBuf};
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_queries_and_block_stream() {
+        let chain = Arc::new(RegtestChain::new(Network::Regtest).unwrap());
+        let outpoint = chain.fund(&ScriptBuf::new(), 50_000);
+        chain.generate(2);
+        let service = ChainGrpc::new(chain.clone(), Duration::from_millis(5));
+
+        let tx = service
+            .get_transaction(Request::new(GetTransactionRequest {
+                txid: outpoint.txid.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(tx.state(), TxState::Confirmed);
+        assert_eq!(tx.confirmations, 3);
+
+        let mut blocks = service
+            .subscribe_blocks(Request::new(SubscribeBlocksRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = blocks.next().await.unwrap().unwrap();
+        assert_eq!(first.height, chain.height());
+        chain.generate(1);
+        let next = blocks.next().await.unwrap().unwrap();
+        assert_eq!(next.height, first.height + 1);
+        assert!(!next.reorg);
+
+        let err = service
+            .get_transaction(Request::new(GetTransactionRequest { txid: "00".into() }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+}
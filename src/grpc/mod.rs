@@ -0,0 +1,92 @@
+//! gRPC services for service-mesh integration
+//!
+//! Protobuf definitions live in `proto/anya/v1` and are compiled by the build
+//! script when the `grpc` feature is enabled. The node serves:
+//! - [`WalletGrpc`]: accounts, balances, address derivation, and coins
+//! - [`ChainGrpc`]: tip and transaction queries plus a block event stream
+//! - [`AnalyticsGrpc`]: fee market percentiles, congestion forecast, and
+//!   recommended send windows
+//! - [`PeerGrpc`]: peer misbehavior scores and manual bans
+//! - [`AuthGrpc`]: DID handshake issuing session tokens, which
+//!   [`SessionInterceptor`] requires on the services it wraps
+//!
+//! `InferenceService` and `WorkflowService` are defined for clients to
+//! generate against but have no server implementation yet.
+//!
+//! Errors map to gRPC status codes by [`ErrorCode`]; the stable symbolic code
+//! is also sent in the `anya-error-code` metadata entry.
+
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Status};
+
+use crate::{AnyaError, ErrorCode};
+
+mod analytics;
+mod auth;
+mod chain;
+mod peers;
+mod wallet;
+
+pub use analytics::AnalyticsGrpc;
+pub use auth::{AuthGrpc, SessionInterceptor, AUTHORIZATION_METADATA};
+pub use chain::ChainGrpc;
+pub use peers::PeerGrpc;
+pub use wallet::WalletGrpc;
+
+/// Generated protobuf messages and service stubs
+#[allow(missing_docs, clippy::all, clippy::nursery, clippy::cargo)]
+pub mod pb {
+    tonic::include_proto!("anya.v1");
+}
+
+/// Metadata key carrying [`ErrorCode::as_str`] on failed calls
+pub const ERROR_CODE_METADATA: &str = "anya-error-code";
+
+/// gRPC status code for an error code
+pub const fn grpc_code(code: ErrorCode) -> Code {
+    match code {
+        ErrorCode::InvalidInput | ErrorCode::Serialization => Code::InvalidArgument,
+        ErrorCode::NotFound => Code::NotFound,
+        ErrorCode::Conflict => Code::AlreadyExists,
+        ErrorCode::Unauthenticated => Code::Unauthenticated,
+        ErrorCode::PermissionDenied => Code::PermissionDenied,
+        ErrorCode::RateLimited => Code::ResourceExhausted,
+        ErrorCode::InsufficientFunds | ErrorCode::TransactionRejected => Code::FailedPrecondition,
+        ErrorCode::Timeout => Code::DeadlineExceeded,
+        ErrorCode::Unavailable
+        | ErrorCode::ModelUnavailable
+        | ErrorCode::NetworkFailure
+        | ErrorCode::DidResolution => Code::Unavailable,
+        _ => Code::Internal,
+    }
+}
+
+impl From<AnyaError> for Status {
+    fn from(err: AnyaError) -> Self {
+        let code = err.code();
+        let mut status = Self::new(grpc_code(code), err.to_string());
+        status.metadata_mut().insert(
+            ERROR_CODE_METADATA,
+            MetadataValue::from_static(code.as_str()),
+        );
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_carries_error_code() {
+        let status = Status::from(AnyaError::new(
+            ErrorCode::InsufficientFunds,
+            "need 5000 sat",
+        ));
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status.metadata().get(ERROR_CODE_METADATA).unwrap(),
+            "INSUFFICIENT_FUNDS"
+        );
+    }
+}
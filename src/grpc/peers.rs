@@ -0,0 +1,141 @@
+//! `PeerService` over a [`BanManager`]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic::{Request, Response, Status};
+
+use super::pb::peer_service_server::PeerService;
+use super::pb::{self, BanRequest, BanResponse, ListBansRequest, ListBansResponse};
+use super::pb::{ListPeerScoresRequest, ListPeerScoresResponse, UnbanRequest, UnbanResponse};
+use crate::net::peers::{BanManager, Subnet};
+
+/// Serves `anya.v1.PeerService`
+pub struct PeerGrpc {
+    bans: Arc<BanManager>,
+}
+
+impl PeerGrpc {
+    /// Service managing `bans`
+    pub const fn new(bans: Arc<BanManager>) -> Self {
+        Self { bans }
+    }
+
+    /// Tonic server wrapper for this service
+    pub fn into_server(self) -> pb::peer_service_server::PeerServiceServer<Self> {
+        pb::peer_service_server::PeerServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl PeerService for PeerGrpc {
+    async fn list_peer_scores(
+        &self,
+        _request: Request<ListPeerScoresRequest>,
+    ) -> Result<Response<ListPeerScoresResponse>, Status> {
+        Ok(Response::new(ListPeerScoresResponse {
+            peers: self
+                .bans
+                .scores()
+                .into_iter()
+                .map(|s| pb::PeerScore {
+                    peer: s.peer,
+                    addr: s.addr.to_string(),
+                    score: s.score,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn list_bans(
+        &self,
+        _request: Request<ListBansRequest>,
+    ) -> Result<Response<ListBansResponse>, Status> {
+        Ok(Response::new(ListBansResponse {
+            bans: self
+                .bans
+                .bans()
+                .into_iter()
+                .map(|b| pb::Ban {
+                    subnet: b.subnet.to_string(),
+                    created_at: b.created_at,
+                    until: b.until,
+                    reason: b.reason,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn ban(&self, request: Request<BanRequest>) -> Result<Response<BanResponse>, Status> {
+        let request = request.into_inner();
+        let subnet: Subnet = request.subnet.parse()?;
+        let duration = match request.duration_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let disconnected = self.bans.ban(subnet, duration, &request.reason).await?;
+        Ok(Response::new(BanResponse { disconnected }))
+    }
+
+    async fn unban(
+        &self,
+        request: Request<UnbanRequest>,
+    ) -> Result<Response<UnbanResponse>, Status> {
+        let subnet: Subnet = request.into_inner().subnet.parse()?;
+        let removed = self.bans.unban(subnet).await?;
+        Ok(Response::new(UnbanResponse { removed }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::peers::BanConfig;
+    use crate::storage::memory::MemoryBackend;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn test_ban_and_unban() {
+        let bans = Arc::new(
+            BanManager::open(BanConfig::default(), Arc::new(MemoryBackend::new()))
+                .await
+                .unwrap(),
+        );
+        bans.peer_connected(7, "198.51.100.7".parse().unwrap());
+        let service = PeerGrpc::new(bans);
+        let banned = service
+            .ban(Request::new(BanRequest {
+                subnet: "198.51.100.0/24".into(),
+                duration_secs: 3600,
+                reason: "spam".into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(banned.disconnected, vec![7]);
+        let listed = service
+            .list_bans(Request::new(ListBansRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.bans[0].subnet, "198.51.100.0/24");
+        assert_eq!(listed.bans[0].until - listed.bans[0].created_at, 3600);
+
+        let bad = service
+            .ban(Request::new(BanRequest {
+                subnet: "not-an-ip".into(),
+                ..BanRequest::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(bad.code(), Code::InvalidArgument);
+        let unbanned = service
+            .unban(Request::new(UnbanRequest {
+                subnet: "198.51.100.0/24".into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(unbanned.removed);
+    }
+}
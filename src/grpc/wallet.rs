@@ -0,0 +1,272 @@
+//! `WalletService` over the account manager and coin store
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status};
+
+use super::pb::wallet_service_server::WalletService;
+use super::pb::{self, ListAccountsRequest, ListAccountsResponse, ListCoinsRequest};
+use super::pb::{ListCoinsResponse, NewAddressRequest, NewAddressResponse};
+use crate::bitcoin::accounts::{AccountId, AccountManager, Balance, KeyChain, ScriptType};
+use crate::bitcoin::coins::{CoinQuery, CoinStore};
+use crate::utils::pagination::{PageRequest, DEFAULT_PAGE_LIMIT};
+use crate::{AnyaError, AnyaResult};
+
+/// Serves `anya.v1.WalletService`
+pub struct WalletGrpc {
+    accounts: Arc<AccountManager>,
+    coins: Arc<CoinStore>,
+}
+
+impl WalletGrpc {
+    /// Service over a wallet's accounts and coins
+    pub const fn new(accounts: Arc<AccountManager>, coins: Arc<CoinStore>) -> Self {
+        Self { accounts, coins }
+    }
+
+    /// Tonic server wrapper for this service
+    pub fn into_server(self) -> pb::wallet_service_server::WalletServiceServer<Self> {
+        pb::wallet_service_server::WalletServiceServer::new(self)
+    }
+
+    async fn balances(&self) -> AnyaResult<HashMap<AccountId, Balance>> {
+        let mut balances: HashMap<AccountId, Balance> = HashMap::new();
+        for coin in self.coins.coins(None).await? {
+            let balance = balances.entry(coin.utxo.account).or_default();
+            if coin.utxo.height.is_some() {
+                balance.confirmed_sat += coin.utxo.txout.value;
+            } else {
+                balance.unconfirmed_sat += coin.utxo.txout.value;
+            }
+        }
+        Ok(balances)
+    }
+}
+
+fn account_id(id: Option<pb::AccountId>) -> AnyaResult<AccountId> {
+    let id = id.ok_or_else(|| AnyaError::invalid_input("account is required"))?;
+    let script_type = match pb::ScriptType::try_from(id.script_type) {
+        Ok(pb::ScriptType::Legacy) => ScriptType::Legacy,
+        Ok(pb::ScriptType::NativeSegwit) => ScriptType::NativeSegwit,
+        Ok(pb::ScriptType::Taproot) => ScriptType::Taproot,
+        _ => return Err(AnyaError::invalid_input("account script type is required")),
+    };
+    Ok(AccountId {
+        script_type,
+        index: id.index,
+    })
+}
+
+fn to_pb_id(id: AccountId) -> pb::AccountId {
+    let script_type = match id.script_type {
+        ScriptType::Legacy => pb::ScriptType::Legacy,
+        ScriptType::NativeSegwit => pb::ScriptType::NativeSegwit,
+        ScriptType::Taproot => pb::ScriptType::Taproot,
+    };
+    pb::AccountId {
+        script_type: script_type.into(),
+        index: id.index,
+    }
+}
+
+const fn to_pb_chain(chain: KeyChain) -> pb::KeyChain {
+    match chain {
+        KeyChain::External => pb::KeyChain::External,
+        KeyChain::Internal => pb::KeyChain::Internal,
+    }
+}
+
+const fn to_pb_balance(balance: Balance) -> pb::Balance {
+    pb::Balance {
+        confirmed_sat: balance.confirmed_sat,
+        unconfirmed_sat: balance.unconfirmed_sat,
+    }
+}
+
+#[tonic::async_trait]
+impl WalletService for WalletGrpc {
+    async fn list_accounts(
+        &self,
+        _request: Request<ListAccountsRequest>,
+    ) -> Result<Response<ListAccountsResponse>, Status> {
+        let balances = self.balances().await?;
+        let view = self
+            .accounts
+            .balance_view(|a| balances.get(&a.id).copied().unwrap_or_default())
+            .await;
+        let mut accounts = Vec::with_capacity(view.accounts.len());
+        for entry in view.accounts {
+            let account = self.accounts.account(entry.id).await?;
+            accounts.push(pb::Account {
+                id: Some(to_pb_id(entry.id)),
+                label: entry.label,
+                descriptor: account.descriptor(KeyChain::External),
+                balance: Some(to_pb_balance(entry.balance)),
+            });
+        }
+        Ok(Response::new(ListAccountsResponse {
+            accounts,
+            total: Some(to_pb_balance(view.total)),
+        }))
+    }
+
+    async fn new_address(
+        &self,
+        request: Request<NewAddressRequest>,
+    ) -> Result<Response<NewAddressResponse>, Status> {
+        let request = request.into_inner();
+        let chain = match request.chain() {
+            pb::KeyChain::External => KeyChain::External,
+            pb::KeyChain::Internal => KeyChain::Internal,
+        };
+        let (index, address) = self
+            .accounts
+            .next_address(account_id(request.account)?, chain)
+            .await?;
+        Ok(Response::new(NewAddressResponse {
+            address: address.to_string(),
+            index,
+        }))
+    }
+
+    async fn list_coins(
+        &self,
+        request: Request<ListCoinsRequest>,
+    ) -> Result<Response<ListCoinsResponse>, Status> {
+        let request = request.into_inner();
+        let query = CoinQuery {
+            account: request.account.map(|id| account_id(Some(id))).transpose()?,
+            ..CoinQuery::default()
+        };
+        let page = PageRequest {
+            cursor: match request.page_token.as_str() {
+                "" => None,
+                token => Some(token.parse()?),
+            },
+            limit: match request.page_size {
+                0 => DEFAULT_PAGE_LIMIT,
+                size => size as usize,
+            },
+            ..PageRequest::default()
+        };
+        let page = self.coins.query(&query, &page).await?;
+        let coins = page
+            .items
+            .into_iter()
+            .map(|coin| pb::Coin {
+                outpoint: coin.utxo.outpoint.to_string(),
+                value_sat: coin.utxo.txout.value,
+                account: Some(to_pb_id(coin.utxo.account)),
+                chain: to_pb_chain(coin.utxo.chain).into(),
+                index: coin.utxo.index,
+                height: coin.utxo.height,
+                locked: coin.lock.is_some(),
+            })
+            .collect();
+        Ok(Response::new(ListCoinsResponse {
+            coins,
+            next_page_token: page.next_cursor.map(|c| c.to_string()).unwrap_or_default(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::coins::Utxo;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::bip32::ExtendedPrivKey;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{Network, OutPoint, ScriptBuf, TxOut, Txid};
+
+    #[tokio::test]
+    async fn test_accounts_addresses_and_coins() {
+        let storage = Arc::new(MemoryBackend::new());
+        let accounts = Arc::new(
+            AccountManager::open(Network::Regtest, storage.clone())
+                .await
+                .unwrap(),
+        );
+        let coins = Arc::new(CoinStore::open(storage).await.unwrap());
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[3; 32]).unwrap();
+        let account = accounts
+            .create_account(&master, ScriptType::NativeSegwit, "Spending")
+            .await
+            .unwrap();
+        for (vout, height) in [(0, Some(100)), (1, None)] {
+            coins
+                .insert(&Utxo {
+                    outpoint: OutPoint::new(Txid::all_zeros(), vout),
+                    txout: TxOut {
+                        value: 10_000,
+                        script_pubkey: ScriptBuf::new(),
+                    },
+                    account: account.id,
+                    chain: KeyChain::External,
+                    index: vout,
+                    height,
+                })
+                .await
+                .unwrap();
+        }
+        let service = WalletGrpc::new(accounts, coins);
+
+        let listed = service
+            .list_accounts(Request::new(ListAccountsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.accounts[0].label, "Spending");
+        assert!(listed.accounts[0].descriptor.starts_with("wpkh(["));
+        let total = listed.total.unwrap();
+        assert_eq!(
+            (total.confirmed_sat, total.unconfirmed_sat),
+            (10_000, 10_000)
+        );
+
+        let address = service
+            .new_address(Request::new(NewAddressRequest {
+                account: Some(to_pb_id(account.id)),
+                chain: pb::KeyChain::External.into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(address.address.starts_with("bcrt1q"));
+
+        let first = service
+            .list_coins(Request::new(ListCoinsRequest {
+                account: None,
+                page_size: 1,
+                page_token: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let last = service
+            .list_coins(Request::new(ListCoinsRequest {
+                account: None,
+                page_size: 1,
+                page_token: first.next_page_token,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(last.coins.len(), 1);
+        assert_ne!(last.coins[0].outpoint, first.coins[0].outpoint);
+        assert!(last.next_page_token.is_empty());
+
+        let missing = service
+            .list_coins(Request::new(ListCoinsRequest {
+                account: Some(pb::AccountId {
+                    script_type: pb::ScriptType::Unspecified.into(),
+                    index: 0,
+                }),
+                ..ListCoinsRequest::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,647 @@
+//! Accounting exports: OFX, QIF, and mapped CSV
+//!
+//! Finance teams import wallet activity into their accounting software as
+//! a bank statement. Activity is first turned into [`LedgerEntry`] values,
+//! from the mobile wallet history or any other ledger, and then written as
+//! an OFX 2.2 statement ([`to_ofx`]), a QIF bank register ([`to_qif`]), or
+//! CSV laid out by a [`CsvMapping`]. Mappings are plain data, so the column
+//! layout an accounting package expects can be configured without code;
+//! [`CsvMapping::quickbooks`] and [`CsvMapping::xero`] are provided.
+//!
+//! Amounts are written in BTC, satoshis, or the fiat value recorded with
+//! each entry. With [`ExportOptions::split_fees`] the network fee of a
+//! payment is booked as its own line, so it can be categorised as an
+//! expense.
+
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::time::UtcDateTime;
+use crate::{AnyaError, AnyaResult};
+
+const SATS_PER_BTC: f64 = 100_000_000.0;
+/// Longest `NAME` OFX allows
+const OFX_NAME_LEN: usize = 32;
+
+/// Fiat price attached to an entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FiatPrice {
+    /// ISO 4217 currency code
+    pub currency: String,
+    /// Price of one bitcoin when the entry was booked
+    pub price: f64,
+}
+
+/// One movement of funds to export
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// Unique reference, e.g. the transaction id
+    pub id: String,
+    /// Booking time, seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Net effect on the balance in satoshis, fee included
+    pub amount_sat: i64,
+    /// Fee paid by the wallet, already included in `amount_sat`
+    #[serde(default)]
+    pub fee_sat: Option<u64>,
+    /// Counterparty
+    #[serde(default)]
+    pub payee: Option<String>,
+    /// Free-form note
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Accounting category
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Fiat price at `timestamp`
+    #[serde(default)]
+    pub fiat: Option<FiatPrice>,
+}
+
+#[cfg(feature = "mobile")]
+impl From<&crate::mobile::history::WalletTx> for LedgerEntry {
+    fn from(tx: &crate::mobile::history::WalletTx) -> Self {
+        Self {
+            id: tx.txid.to_string(),
+            timestamp: tx.timestamp,
+            amount_sat: tx.amount_sat,
+            fee_sat: tx.fee_sat,
+            payee: None,
+            memo: tx.label.clone(),
+            category: tx.category.and_then(|c| {
+                serde_json::to_value(c)
+                    .ok()
+                    .and_then(|v| v.as_str().map(str::to_string))
+            }),
+            fiat: tx.fiat.as_ref().map(|f| FiatPrice {
+                currency: f.currency.clone(),
+                price: f.price,
+            }),
+        }
+    }
+}
+
+/// Unit amounts are written in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmountUnit {
+    /// Bitcoin with eight decimals
+    Btc,
+    /// Whole satoshis
+    Sat,
+    /// Fiat value with two decimals; every entry must carry a price in
+    /// this currency
+    Fiat(String),
+}
+
+/// Options shared by every format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportOptions {
+    /// Unit of exported amounts
+    pub unit: AmountUnit,
+    /// Book fees as separate lines
+    pub split_fees: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            unit: AmountUnit::Btc,
+            split_fees: true,
+        }
+    }
+}
+
+/// Calendar date layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateFormat {
+    /// `YYYY-MM-DD`
+    Iso,
+    /// `MM/DD/YYYY`
+    Us,
+    /// `DD/MM/YYYY`
+    Day,
+}
+
+impl DateFormat {
+    fn format(self, at: &UtcDateTime) -> String {
+        match self {
+            Self::Iso => at.date(),
+            Self::Us => format!("{:02}/{:02}/{:04}", at.month, at.day, at.year),
+            Self::Day => format!("{:02}/{:02}/{:04}", at.day, at.month, at.year),
+        }
+    }
+}
+
+/// An exported statement line
+struct Line<'a> {
+    entry: &'a LedgerEntry,
+    reference: String,
+    at: UtcDateTime,
+    amount: String,
+    /// Sign of the amount, for debit/credit columns and transaction types
+    outflow: bool,
+    fee: bool,
+    /// Amount in satoshis or fiat cents
+    minor: i64,
+}
+
+impl Line<'_> {
+    fn payee(&self) -> &str {
+        if self.fee {
+            "Network fee"
+        } else {
+            self.entry.payee.as_deref().unwrap_or_default()
+        }
+    }
+
+    fn memo(&self) -> &str {
+        self.entry.memo.as_deref().unwrap_or_default()
+    }
+
+    fn category(&self) -> &str {
+        if self.fee {
+            "Fees"
+        } else {
+            self.entry.category.as_deref().unwrap_or_default()
+        }
+    }
+}
+
+fn lines<'a>(entries: &'a [LedgerEntry], options: &ExportOptions) -> AnyaResult<Vec<Line<'a>>> {
+    let mut lines = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let fee = entry
+            .fee_sat
+            .filter(|_| options.split_fees && entry.amount_sat < 0)
+            .and_then(|f| i64::try_from(f).ok())
+            .filter(|f| *f > 0);
+        let principal = entry.amount_sat + fee.unwrap_or_default();
+        let at = UtcDateTime::from_unix(i64::try_from(entry.timestamp).unwrap_or(i64::MAX));
+        let mut push = |sat: i64, reference: String, fee: bool| -> AnyaResult<()> {
+            let minor = minor_units(entry, sat, &options.unit)?;
+            lines.push(Line {
+                entry,
+                reference,
+                at,
+                amount: format_minor(minor, &options.unit),
+                outflow: sat < 0,
+                fee,
+                minor,
+            });
+            Ok(())
+        };
+        if principal != 0 || fee.is_none() {
+            push(principal, entry.id.clone(), false)?;
+        }
+        if let Some(fee) = fee {
+            push(-fee, format!("{}-fee", entry.id), true)?;
+        }
+    }
+    Ok(lines)
+}
+
+/// `sat` in satoshis, or in cents of the fiat unit at the entry's price
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn minor_units(entry: &LedgerEntry, sat: i64, unit: &AmountUnit) -> AnyaResult<i64> {
+    let AmountUnit::Fiat(currency) = unit else {
+        return Ok(sat);
+    };
+    let price = entry
+        .fiat
+        .as_ref()
+        .filter(|f| f.currency.eq_ignore_ascii_case(currency))
+        .ok_or_else(|| {
+            AnyaError::invalid_input(format!("entry {} has no {} price", entry.id, currency))
+        })?
+        .price;
+    Ok((sat as f64 / SATS_PER_BTC * price * 100.0).round() as i64)
+}
+
+fn format_minor(minor: i64, unit: &AmountUnit) -> String {
+    let sign = if minor < 0 { "-" } else { "" };
+    let abs = minor.unsigned_abs();
+    match unit {
+        AmountUnit::Sat => minor.to_string(),
+        AmountUnit::Btc => format!("{}{}.{:08}", sign, abs / 100_000_000, abs % 100_000_000),
+        AmountUnit::Fiat(_) => format!("{}{}.{:02}", sign, abs / 100, abs % 100),
+    }
+}
+
+/// Bank account the OFX statement is issued for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfxAccount {
+    /// Institution identifier shown by the importing software
+    pub bank_id: String,
+    /// Account identifier, stable across exports so imports are matched
+    pub account_id: String,
+}
+
+/// OFX 2.2 bank statement of `entries`.
+///
+/// `LEDGERBAL` is the sum of the exported lines, as of the last entry.
+/// Satoshi amounts are refused: OFX has no currency code for them.
+pub fn to_ofx(
+    entries: &[LedgerEntry],
+    account: &OfxAccount,
+    options: &ExportOptions,
+) -> AnyaResult<String> {
+    let currency = match &options.unit {
+        AmountUnit::Btc => "XBT".to_string(),
+        AmountUnit::Fiat(currency) => currency.to_uppercase(),
+        AmountUnit::Sat => {
+            return Err(AnyaError::invalid_input(
+                "OFX amounts must be in BTC or fiat",
+            ))
+        }
+    };
+    let lines = lines(entries, options)?;
+    let ofx_time = |at: &UtcDateTime| {
+        format!(
+            "{:04}{:02}{:02}{:02}{:02}{:02}",
+            at.year, at.month, at.day, at.hour, at.minute, at.second
+        )
+    };
+    let start = lines.iter().map(|l| l.at).min();
+    let end = lines.iter().map(|l| l.at).max();
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) => (ofx_time(&start), ofx_time(&end)),
+        _ => (String::new(), String::new()),
+    };
+    let balance = format_minor(lines.iter().map(|l| l.minor).sum(), &options.unit);
+
+    let mut ofx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+         <?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>\n\
+         <OFX>\n\
+         <SIGNONMSGSRSV1><SONRS><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>",
+    );
+    let _ = write!(
+        ofx,
+        "<DTSERVER>{end}</DTSERVER><LANGUAGE>ENG</LANGUAGE></SONRS></SIGNONMSGSRSV1>\n\
+         <BANKMSGSRSV1><STMTTRNRS><TRNUID>0</TRNUID>\
+         <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n\
+         <STMTRS><CURDEF>{}</CURDEF>\n\
+         <BANKACCTFROM><BANKID>{}</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>\n\
+         <BANKTRANLIST><DTSTART>{start}</DTSTART><DTEND>{end}</DTEND>\n",
+        xml_escape(&currency),
+        xml_escape(&account.bank_id),
+        xml_escape(&account.account_id),
+    );
+    for line in &lines {
+        let kind = match (line.fee, line.outflow) {
+            (true, _) => "FEE",
+            (false, true) => "DEBIT",
+            (false, false) => "CREDIT",
+        };
+        let _ = write!(
+            ofx,
+            "<STMTTRN><TRNTYPE>{}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{}</TRNAMT><FITID>{}</FITID>",
+            kind,
+            ofx_time(&line.at),
+            line.amount,
+            xml_escape(&line.reference),
+        );
+        let name: String = line.payee().chars().take(OFX_NAME_LEN).collect();
+        if !name.is_empty() {
+            let _ = write!(ofx, "<NAME>{}</NAME>", xml_escape(&name));
+        }
+        if !line.memo().is_empty() {
+            let _ = write!(ofx, "<MEMO>{}</MEMO>", xml_escape(line.memo()));
+        }
+        ofx.push_str("</STMTTRN>\n");
+    }
+    let _ = write!(
+        ofx,
+        "</BANKTRANLIST>\n\
+         <LEDGERBAL><BALAMT>{balance}</BALAMT><DTASOF>{end}</DTASOF></LEDGERBAL>\n\
+         </STMTRS></STMTTRNRS></BANKMSGSRSV1>\n\
+         </OFX>\n"
+    );
+    Ok(ofx)
+}
+
+/// QIF bank register of `entries` with dates in `dates`
+pub fn to_qif(
+    entries: &[LedgerEntry],
+    options: &ExportOptions,
+    dates: DateFormat,
+) -> AnyaResult<String> {
+    let mut qif = String::from("!Type:Bank\n");
+    for line in lines(entries, options)? {
+        let _ = writeln!(qif, "D{}", dates.format(&line.at));
+        let _ = writeln!(qif, "T{}", line.amount);
+        let _ = writeln!(qif, "N{}", single_line(&line.reference));
+        for (code, value) in [
+            ('P', line.payee()),
+            ('M', line.memo()),
+            ('L', line.category()),
+        ] {
+            if !value.is_empty() {
+                let _ = writeln!(qif, "{}{}", code, single_line(value));
+            }
+        }
+        qif.push_str("^\n");
+    }
+    Ok(qif)
+}
+
+/// Value written to a CSV column
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvField {
+    /// Booking date in the mapping's date format
+    Date,
+    /// Booking time, RFC 3339
+    DateTime,
+    /// Signed amount
+    Amount,
+    /// Amount of outflows as a positive number, blank for inflows
+    Debit,
+    /// Amount of inflows, blank for outflows
+    Credit,
+    /// Counterparty
+    Payee,
+    /// Note
+    Memo,
+    /// Category
+    Category,
+    /// Unique reference
+    Reference,
+    /// Currency code of the amounts
+    Currency,
+    /// The same text on every row
+    Constant(String),
+}
+
+/// A named CSV column
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvColumn {
+    /// Header text
+    pub header: String,
+    /// Content
+    pub field: CsvField,
+}
+
+impl CsvColumn {
+    /// Column `header` holding `field`
+    pub fn new(header: impl Into<String>, field: CsvField) -> Self {
+        Self {
+            header: header.into(),
+            field,
+        }
+    }
+}
+
+/// Column layout of a CSV export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvMapping {
+    /// Columns in order
+    pub columns: Vec<CsvColumn>,
+    /// Field separator
+    pub delimiter: char,
+    /// Layout of [`CsvField::Date`]
+    pub date_format: DateFormat,
+    /// Whether to write a header row
+    pub header: bool,
+}
+
+impl Default for CsvMapping {
+    /// Every field with ISO dates
+    fn default() -> Self {
+        Self {
+            columns: vec![
+                CsvColumn::new("date", CsvField::DateTime),
+                CsvColumn::new("reference", CsvField::Reference),
+                CsvColumn::new("amount", CsvField::Amount),
+                CsvColumn::new("currency", CsvField::Currency),
+                CsvColumn::new("payee", CsvField::Payee),
+                CsvColumn::new("memo", CsvField::Memo),
+                CsvColumn::new("category", CsvField::Category),
+            ],
+            delimiter: ',',
+            date_format: DateFormat::Iso,
+            header: true,
+        }
+    }
+}
+
+impl CsvMapping {
+    /// QuickBooks three-column bank upload
+    pub fn quickbooks() -> Self {
+        Self {
+            columns: vec![
+                CsvColumn::new("Date", CsvField::Date),
+                CsvColumn::new("Description", CsvField::Memo),
+                CsvColumn::new("Amount", CsvField::Amount),
+            ],
+            delimiter: ',',
+            date_format: DateFormat::Us,
+            header: true,
+        }
+    }
+
+    /// Xero bank statement import
+    pub fn xero() -> Self {
+        Self {
+            columns: vec![
+                CsvColumn::new("*Date", CsvField::Date),
+                CsvColumn::new("*Amount", CsvField::Amount),
+                CsvColumn::new("Payee", CsvField::Payee),
+                CsvColumn::new("Description", CsvField::Memo),
+                CsvColumn::new("Reference", CsvField::Reference),
+            ],
+            delimiter: ',',
+            date_format: DateFormat::Day,
+            header: true,
+        }
+    }
+
+    /// Parse a mapping from JSON
+    pub fn from_json(json: &str) -> AnyaResult<Self> {
+        let mapping: Self = serde_json::from_str(json)?;
+        if mapping.columns.is_empty() {
+            return Err(AnyaError::invalid_input("CSV mapping has no columns"));
+        }
+        Ok(mapping)
+    }
+
+    /// Write `entries` as CSV
+    pub fn export(&self, entries: &[LedgerEntry], options: &ExportOptions) -> AnyaResult<String> {
+        let escape = |field: &str| {
+            if field.contains([self.delimiter, '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        };
+        let separator = self.delimiter.to_string();
+        let currency = match &options.unit {
+            AmountUnit::Btc => "BTC".to_string(),
+            AmountUnit::Sat => "SAT".to_string(),
+            AmountUnit::Fiat(currency) => currency.to_uppercase(),
+        };
+        let mut csv = String::new();
+        if self.header {
+            let header: Vec<String> = self.columns.iter().map(|c| escape(&c.header)).collect();
+            csv.push_str(&header.join(&separator));
+            csv.push_str("\r\n");
+        }
+        for line in lines(entries, options)? {
+            let unsigned = line.amount.trim_start_matches('-');
+            let row: Vec<String> = self
+                .columns
+                .iter()
+                .map(|c| match &c.field {
+                    CsvField::Date => self.date_format.format(&line.at),
+                    CsvField::DateTime => line.at.to_string(),
+                    CsvField::Amount => line.amount.clone(),
+                    CsvField::Debit if line.outflow => unsigned.to_string(),
+                    CsvField::Credit if !line.outflow => unsigned.to_string(),
+                    CsvField::Debit | CsvField::Credit => String::new(),
+                    CsvField::Payee => line.payee().to_string(),
+                    CsvField::Memo => line.memo().to_string(),
+                    CsvField::Category => line.category().to_string(),
+                    CsvField::Reference => line.reference.clone(),
+                    CsvField::Currency => currency.clone(),
+                    CsvField::Constant(text) => text.clone(),
+                })
+                .map(|v| escape(&v))
+                .collect();
+            csv.push_str(&row.join(&separator));
+            csv.push_str("\r\n");
+        }
+        Ok(csv)
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// QIF fields end at a newline
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<LedgerEntry> {
+        let price = Some(FiatPrice {
+            currency: "USD".into(),
+            price: 40_000.0,
+        });
+        vec![
+            LedgerEntry {
+                id: "aa11".into(),
+                // 2024-01-02T03:04:05Z
+                timestamp: 1_704_164_645,
+                amount_sat: 1_500_000,
+                fee_sat: None,
+                payee: Some("Acme & Sons".into()),
+                memo: Some("Invoice 17".into()),
+                category: Some("income".into()),
+                fiat: price.clone(),
+            },
+            LedgerEntry {
+                id: "bb22".into(),
+                timestamp: 1_704_250_000,
+                amount_sat: -250_500,
+                fee_sat: Some(500),
+                payee: Some("Hosting, Inc.".into()),
+                memo: Some("January\nservers".into()),
+                category: Some("payment".into()),
+                fiat: price,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_ofx_and_qif_statements() {
+        let account = OfxAccount {
+            bank_id: "ANYA".into(),
+            account_id: "84h/0h".into(),
+        };
+        let ofx = to_ofx(&entries(), &account, &ExportOptions::default()).unwrap();
+        assert!(ofx.contains("<CURDEF>XBT</CURDEF>"));
+        assert!(ofx.contains(
+            "<TRNTYPE>CREDIT</TRNTYPE><DTPOSTED>20240102030405</DTPOSTED>\
+             <TRNAMT>0.01500000</TRNAMT><FITID>aa11</FITID><NAME>Acme &amp; Sons</NAME>"
+        ));
+        assert!(ofx.contains("<TRNTYPE>DEBIT</TRNTYPE>"));
+        assert!(ofx.contains("<TRNAMT>-0.00250000</TRNAMT><FITID>bb22</FITID>"));
+        assert!(ofx.contains("<TRNTYPE>FEE</TRNTYPE>"));
+        assert!(ofx.contains("<TRNAMT>-0.00000500</TRNAMT><FITID>bb22-fee</FITID>"));
+        assert!(ofx.contains("<BALAMT>0.01249500</BALAMT>"));
+        let sats = ExportOptions {
+            unit: AmountUnit::Sat,
+            ..ExportOptions::default()
+        };
+        assert!(to_ofx(&entries(), &account, &sats).is_err());
+
+        let fiat = ExportOptions {
+            unit: AmountUnit::Fiat("USD".into()),
+            split_fees: false,
+        };
+        let qif = to_qif(&entries(), &fiat, DateFormat::Us).unwrap();
+        assert_eq!(
+            qif,
+            "!Type:Bank\n\
+             D01/02/2024\nT600.00\nNaa11\nPAcme & Sons\nMInvoice 17\nLincome\n^\n\
+             D01/03/2024\nT-100.20\nNbb22\nPHosting, Inc.\nMJanuary servers\nLpayment\n^\n"
+        );
+        let mut unpriced = entries();
+        unpriced[1].fiat = None;
+        assert!(to_qif(&unpriced, &fiat, DateFormat::Us).is_err());
+    }
+
+    #[test]
+    fn test_csv_mappings() {
+        let options = ExportOptions::default();
+        let xero = CsvMapping::xero().export(&entries(), &options).unwrap();
+        let rows: Vec<&str> = xero.split("\r\n").collect();
+        assert_eq!(rows[0], "*Date,*Amount,Payee,Description,Reference");
+        assert_eq!(rows[1], "02/01/2024,0.01500000,Acme & Sons,Invoice 17,aa11");
+        assert_eq!(
+            rows[2],
+            "03/01/2024,-0.00250000,\"Hosting, Inc.\",\"January\nservers\",bb22"
+        );
+        assert_eq!(
+            rows[3],
+            "03/01/2024,-0.00000500,Network fee,\"January\nservers\",bb22-fee"
+        );
+
+        let mapping = CsvMapping::from_json(
+            r#"{
+                "columns": [
+                    {"header": "Booked", "field": "date"},
+                    {"header": "Out", "field": "debit"},
+                    {"header": "In", "field": "credit"},
+                    {"header": "Ledger", "field": {"constant": "1050 Bitcoin"}}
+                ],
+                "delimiter": ";",
+                "date_format": "iso",
+                "header": false
+            }"#,
+        )
+        .unwrap();
+        let sats = ExportOptions {
+            unit: AmountUnit::Sat,
+            split_fees: false,
+        };
+        assert_eq!(
+            mapping.export(&entries(), &sats).unwrap(),
+            "2024-01-02;;1500000;1050 Bitcoin\r\n2024-01-03;250500;;1050 Bitcoin\r\n"
+        );
+        assert!(CsvMapping::from_json(
+            r#"{"columns": [], "delimiter": ",", "date_format": "iso", "header": true}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,326 @@
+//! CSV batch connector for ERP file drops
+//!
+//! Many ERP systems exchange data as CSV files in an inbox and an outbox
+//! directory, typically on an SFTP share. [`CsvBatchConnector`] reads each
+//! inbox file as one page, oldest name first, and writes pushed records as
+//! a new outbox file. File access goes through [`FileStore`] so remote
+//! shares can be plugged in; [`LocalFileStore`] covers local and mounted
+//! directories.
+
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Connector, Record, RecordPage};
+use crate::{AnyaError, AnyaResult, ResultExt};
+
+/// Flat file storage a batch connector exchanges files through
+#[async_trait]
+pub trait FileStore: Send + Sync {
+    /// Names of the files in `dir`, sorted
+    async fn list(&self, dir: &str) -> AnyaResult<Vec<String>>;
+
+    /// Contents of `dir/name`
+    async fn read(&self, dir: &str, name: &str) -> AnyaResult<Vec<u8>>;
+
+    /// Create or replace `dir/name` so readers never see a partial file
+    async fn write(&self, dir: &str, name: &str, data: &[u8]) -> AnyaResult<()>;
+}
+
+/// Files below a local directory
+pub struct LocalFileStore {
+    root: PathBuf,
+}
+
+impl LocalFileStore {
+    /// Store files below `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl FileStore for LocalFileStore {
+    async fn list(&self, dir: &str) -> AnyaResult<Vec<String>> {
+        let mut names = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.root.join(dir)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+            Err(e) => return Err(AnyaError::from(e).context("listing batch files")),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    async fn read(&self, dir: &str, name: &str) -> AnyaResult<Vec<u8>> {
+        let path = self.root.join(dir).join(name);
+        tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("reading {}", path.display()))
+    }
+
+    async fn write(&self, dir: &str, name: &str, data: &[u8]) -> AnyaResult<()> {
+        let dir = self.root.join(dir);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("creating {}", dir.display()))?;
+        // Dot-prefixed while partial so pollers skip it
+        let partial = dir.join(format!(".{}.partial", name));
+        tokio::fs::write(&partial, data)
+            .await
+            .with_context(|| format!("writing {}", partial.display()))?;
+        tokio::fs::rename(&partial, dir.join(name))
+            .await
+            .context("publishing batch file")
+    }
+}
+
+/// Configuration of a [`CsvBatchConnector`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvBatchConfig {
+    /// Connector name
+    pub name: String,
+    /// Directory files are picked up from
+    pub inbox: String,
+    /// Directory pushed files are written to
+    pub outbox: String,
+    /// Field delimiter, usually `,` or `;`
+    pub delimiter: char,
+}
+
+/// Connector exchanging CSV files with headers
+pub struct CsvBatchConnector {
+    config: CsvBatchConfig,
+    store: Box<dyn FileStore>,
+}
+
+impl CsvBatchConnector {
+    /// Create a connector exchanging files through `store`
+    pub fn new(config: CsvBatchConfig, store: Box<dyn FileStore>) -> Self {
+        Self { config, store }
+    }
+}
+
+#[async_trait]
+impl Connector for CsvBatchConnector {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Read the first CSV file named after `cursor`; the cursor of the
+    /// next page is the name of the file just read
+    async fn fetch(&self, cursor: Option<&str>) -> AnyaResult<RecordPage> {
+        let files: Vec<String> = self
+            .store
+            .list(&self.config.inbox)
+            .await?
+            .into_iter()
+            .filter(|name| !name.starts_with('.') && name.to_lowercase().ends_with(".csv"))
+            .filter(|name| cursor.map_or(true, |c| name.as_str() > c))
+            .collect();
+        let Some(file) = files.first() else {
+            return Ok(RecordPage::default());
+        };
+        let data = self.store.read(&self.config.inbox, file).await?;
+        let text = String::from_utf8(data)
+            .map_err(|_| AnyaError::invalid_input(format!("{} is not UTF-8", file)))?;
+        let records = parse_csv(&text, self.config.delimiter)
+            .map_err(|e| e.context(format!("parsing {}", file)))?;
+        Ok(RecordPage {
+            records,
+            next: (files.len() > 1).then(|| file.clone()),
+        })
+    }
+
+    async fn push(&self, records: &[Record]) -> AnyaResult<usize> {
+        if records.is_empty() {
+            return Ok(0);
+        }
+        let name = format!("{}-{}.csv", self.config.name, unix_nanos());
+        let csv = write_csv(records, self.config.delimiter);
+        self.store
+            .write(&self.config.outbox, &name, csv.as_bytes())
+            .await?;
+        Ok(records.len())
+    }
+}
+
+/// Parse RFC 4180 CSV with a header row into string-valued records
+pub fn parse_csv(text: &str, delimiter: char) -> AnyaResult<Vec<Record>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => field.push(c),
+            }
+        } else if c == delimiter {
+            row.push(std::mem::take(&mut field));
+        } else {
+            match c {
+                '"' if field.is_empty() => quoted = true,
+                '\r' if chars.peek() == Some(&'\n') => {}
+                '\n' => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                _ => field.push(c),
+            }
+        }
+    }
+    if quoted {
+        return Err(AnyaError::invalid_input("unterminated quoted field"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    let mut rows = rows.into_iter().filter(|r| r.iter().any(|f| !f.is_empty()));
+    let Some(header) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    rows.enumerate()
+        .map(|(i, row)| {
+            if row.len() != header.len() {
+                return Err(AnyaError::invalid_input(format!(
+                    "row {} has {} fields, header has {}",
+                    i + 2,
+                    row.len(),
+                    header.len()
+                )));
+            }
+            Ok(header
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(Value::String))
+                .collect())
+        })
+        .collect()
+}
+
+/// Write records as CSV; columns are the union of all keys, nested
+/// values are written as JSON
+pub fn write_csv(records: &[Record], delimiter: char) -> String {
+    let mut columns: Vec<&String> = Vec::new();
+    for key in records.iter().flat_map(|r| r.keys()) {
+        if !columns.contains(&key) {
+            columns.push(key);
+        }
+    }
+    let escape = |field: &str| {
+        if field.contains([delimiter, '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    };
+    let separator = delimiter.to_string();
+    let mut out = columns
+        .iter()
+        .map(|c| escape(c))
+        .collect::<Vec<_>>()
+        .join(&separator);
+    out.push_str("\r\n");
+    for record in records {
+        let line = columns
+            .iter()
+            .map(|c| match record.get(c.as_str()) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => escape(s),
+                Some(other) => escape(&other.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join(&separator);
+        out.push_str(&line);
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_csv_round_trip() {
+        let text = "\u{feff}id;name;note\r\n1;\"Acme; Ltd\";\"said \"\"hi\"\"\nthen left\"\r\n2;Globex;\r\n\r\n";
+        let records = parse_csv(text, ';').unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["name"], "Acme; Ltd");
+        assert_eq!(records[0]["note"], "said \"hi\"\nthen left");
+        assert_eq!(records[1]["note"], "");
+        assert_eq!(parse_csv(&write_csv(&records, ';'), ';').unwrap(), records);
+
+        assert!(parse_csv("a,b\n1\n", ',').is_err());
+        assert!(parse_csv("a\n\"open\n", ',').is_err());
+        let nested = json!({"id": 7, "tags": ["x"]});
+        let csv = write_csv(&[nested.as_object().unwrap().clone()], ',');
+        assert_eq!(csv, "id,tags\r\n7,\"[\"\"x\"\"]\"\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_batch_connector_pages_files() {
+        let root = std::env::temp_dir().join(format!("anya-batch-{}", unix_nanos()));
+        let store = LocalFileStore::new(&root);
+        store
+            .write("in", "2024-01-01.csv", b"sku,qty\nA,1\nB,2\n")
+            .await
+            .unwrap();
+        store
+            .write("in", "2024-01-02.csv", b"sku,qty\nC,3\n")
+            .await
+            .unwrap();
+        store.write("in", "notes.txt", b"ignore me").await.unwrap();
+        let connector = CsvBatchConnector::new(
+            CsvBatchConfig {
+                name: "erp".into(),
+                inbox: "in".into(),
+                outbox: "out".into(),
+                delimiter: ',',
+            },
+            Box::new(LocalFileStore::new(&root)),
+        );
+
+        let first = connector.fetch(None).await.unwrap();
+        assert_eq!(first.records.len(), 2);
+        assert_eq!(first.next.as_deref(), Some("2024-01-01.csv"));
+        let second = connector.fetch(first.next.as_deref()).await.unwrap();
+        assert_eq!(second.records[0]["sku"], "C");
+        assert_eq!(second.next, None);
+
+        assert_eq!(connector.push(&second.records).await.unwrap(), 1);
+        let written = store.list("out").await.unwrap();
+        assert_eq!(written.len(), 1);
+        assert!(written[0].starts_with("erp-"));
+        let data = store.read("out", &written[0]).await.unwrap();
+        assert_eq!(data, b"qty,sku\r\n3,C\r\n");
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+//! Kafka through a REST proxy
+//!
+//! [`KafkaRestQueue`] talks to the v2 API of a Confluent-compatible Kafka
+//! REST proxy, so no Kafka client library is needed. The proxy joins the
+//! consumer group on our behalf: each (group, topic) pair gets one consumer
+//! instance with auto-commit disabled, and offsets are committed only when
+//! deliveries are acknowledged.
+//!
+//! Kafka tracks one committed offset per partition, so acknowledging a
+//! delivery also acknowledges every earlier delivery of its partition.
+//! Deliveries should therefore be acknowledged in the order they were
+//! polled, which [`QueueConnector`](super::queue::QueueConnector) does.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use super::queue::{Delivery, MessageQueue, QueueMessage};
+use super::{Auth, Authenticator, HttpRequest, HttpResponse, HttpTransport};
+use crate::utils::encoding::percent_encode;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const JSON_RECORDS: &str = "application/vnd.kafka.json.v2+json";
+const CONTROL: &str = "application/vnd.kafka.v2+json";
+
+/// Configuration of a [`KafkaRestQueue`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KafkaRestConfig {
+    /// Base URL of the REST proxy
+    pub base_url: String,
+    /// Consumer instance name, unique per process within a group
+    pub instance: String,
+    /// Credentials for the proxy
+    pub auth: Auth,
+    /// Upper bound on the bytes returned by one poll
+    pub max_bytes: usize,
+}
+
+/// Kafka broker reached through a REST proxy
+pub struct KafkaRestQueue {
+    config: KafkaRestConfig,
+    transport: Arc<dyn HttpTransport>,
+    auth: Authenticator,
+    /// Consumer instance URL by (topic, group)
+    consumers: Mutex<HashMap<(String, String), String>>,
+}
+
+impl KafkaRestQueue {
+    /// Create a queue sending requests through `transport`
+    pub fn new(config: KafkaRestConfig, transport: Arc<dyn HttpTransport>) -> Self {
+        let auth = Authenticator::new(config.auth.clone(), Arc::clone(&transport));
+        Self {
+            config,
+            transport,
+            auth,
+            consumers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.config.base_url.trim_end_matches('/'), path)
+    }
+
+    async fn send(
+        &self,
+        method: &str,
+        url: String,
+        body: Option<(&str, Value)>,
+    ) -> AnyaResult<(HttpRequest, HttpResponse)> {
+        let mut request = HttpRequest::new(method, url);
+        request.headers.insert("accept".into(), CONTROL.into());
+        if let Some((content_type, body)) = body {
+            request
+                .headers
+                .insert("content-type".into(), content_type.into());
+            request.body = Some(serde_json::to_vec(&body)?);
+        }
+        let mut attempt = request.clone();
+        self.auth.apply(&mut attempt).await?;
+        let response = self.transport.send(attempt).await?;
+        Ok((request, response))
+    }
+
+    async fn expect_success(
+        &self,
+        method: &str,
+        url: String,
+        body: Option<(&str, Value)>,
+    ) -> AnyaResult<HttpResponse> {
+        let (request, response) = self.send(method, url, body).await?;
+        if (200..300).contains(&response.status) {
+            Ok(response)
+        } else {
+            Err(super::http_error(&request, &response))
+        }
+    }
+
+    /// URL of the consumer instance for `group` on `topic`, creating and
+    /// subscribing it on first use
+    async fn consumer(&self, topic: &str, group: &str) -> AnyaResult<String> {
+        let key = (topic.to_string(), group.to_string());
+        let mut consumers = self.consumers.lock().await;
+        if let Some(url) = consumers.get(&key) {
+            return Ok(url.clone());
+        }
+        let name = format!("{}-{}", self.config.instance, topic);
+        let group_url = self.url(&format!("consumers/{}", percent_encode(group)));
+        let (request, response) = self
+            .send(
+                "POST",
+                group_url.clone(),
+                Some((
+                    CONTROL,
+                    json!({
+                        "name": name,
+                        "format": "json",
+                        "auto.offset.reset": "earliest",
+                        "auto.commit.enable": "false",
+                    }),
+                )),
+            )
+            .await?;
+        let url = match response.status {
+            200..=299 => response.json()?["base_uri"]
+                .as_str()
+                .ok_or_else(|| {
+                    AnyaError::new(ErrorCode::Serialization, "consumer has no base_uri")
+                })?
+                .to_string(),
+            // Created by an earlier run and not yet expired by the proxy
+            409 => format!("{}/instances/{}", group_url, percent_encode(&name)),
+            _ => return Err(super::http_error(&request, &response)),
+        };
+        self.expect_success(
+            "POST",
+            format!("{}/subscription", url),
+            Some((CONTROL, json!({ "topics": [topic] }))),
+        )
+        .await?;
+        consumers.insert(key, url.clone());
+        drop(consumers);
+        Ok(url)
+    }
+
+    /// Forget a consumer instance the proxy no longer knows
+    async fn forget(&self, topic: &str, group: &str, error: &AnyaError) {
+        if error.code() == ErrorCode::NotFound {
+            self.consumers
+                .lock()
+                .await
+                .remove(&(topic.to_string(), group.to_string()));
+        }
+    }
+}
+
+#[async_trait]
+impl MessageQueue for KafkaRestQueue {
+    async fn publish(&self, topic: &str, messages: &[QueueMessage]) -> AnyaResult<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<Value> = messages
+            .iter()
+            .map(|m| {
+                m.key.as_ref().map_or_else(
+                    || json!({"value": m.payload}),
+                    |key| json!({"key": key, "value": m.payload}),
+                )
+            })
+            .collect();
+        let response = self
+            .expect_success(
+                "POST",
+                self.url(&format!("topics/{}", percent_encode(topic))),
+                Some((JSON_RECORDS, json!({ "records": records }))),
+            )
+            .await?
+            .json()?;
+        let failed = response["offsets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|o| !o["error_code"].is_null())
+            .count();
+        if failed > 0 {
+            return Err(AnyaError::new(
+                ErrorCode::NetworkFailure,
+                format!("Kafka rejected {} of {} records", failed, messages.len()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Poll the group's consumer instance; `max` is advisory because the
+    /// proxy only limits responses by size
+    async fn poll(&self, topic: &str, group: &str, _max: usize) -> AnyaResult<Vec<Delivery>> {
+        let url = self.consumer(topic, group).await?;
+        let (request, response) = self
+            .send(
+                "GET",
+                format!("{}/records?max_bytes={}", url, self.config.max_bytes),
+                None,
+            )
+            .await?;
+        if !(200..300).contains(&response.status) {
+            let error = super::http_error(&request, &response);
+            self.forget(topic, group, &error).await;
+            return Err(error);
+        }
+        let records = response.json()?;
+        let records = records.as_array().ok_or_else(|| {
+            AnyaError::new(ErrorCode::Serialization, "proxy returned no record array")
+        })?;
+        records
+            .iter()
+            .map(|record| {
+                let (Some(partition), Some(offset)) =
+                    (record["partition"].as_u64(), record["offset"].as_u64())
+                else {
+                    return Err(AnyaError::new(
+                        ErrorCode::Serialization,
+                        "record without partition or offset",
+                    ));
+                };
+                Ok(Delivery {
+                    topic: record["topic"].as_str().unwrap_or(topic).to_string(),
+                    key: match &record["key"] {
+                        Value::Null => None,
+                        Value::String(s) => Some(s.clone()),
+                        other => Some(other.to_string()),
+                    },
+                    payload: record["value"].clone(),
+                    attempt: 1,
+                    ack_token: format!("{}:{}", partition, offset),
+                })
+            })
+            .collect()
+    }
+
+    async fn ack(&self, topic: &str, group: &str, deliveries: &[Delivery]) -> AnyaResult<()> {
+        let mut latest: BTreeMap<u64, u64> = BTreeMap::new();
+        for delivery in deliveries {
+            let parsed = delivery
+                .ack_token
+                .split_once(':')
+                .and_then(|(p, o)| Some((p.parse().ok()?, o.parse().ok()?)));
+            let Some((partition, offset)) = parsed else {
+                return Err(AnyaError::invalid_input(format!(
+                    "bad ack token {}",
+                    delivery.ack_token
+                )));
+            };
+            let entry = latest.entry(partition).or_insert(offset);
+            *entry = (*entry).max(offset);
+        }
+        if latest.is_empty() {
+            return Ok(());
+        }
+        // The proxy commits the offset after the one given
+        let offsets: Vec<Value> = latest
+            .into_iter()
+            .map(|(partition, offset)| {
+                json!({"topic": topic, "partition": partition, "offset": offset})
+            })
+            .collect();
+        let url = self.consumer(topic, group).await?;
+        let result = self
+            .expect_success(
+                "POST",
+                format!("{}/offsets", url),
+                Some((CONTROL, json!({ "offsets": offsets }))),
+            )
+            .await;
+        if let Err(error) = &result {
+            self.forget(topic, group, error).await;
+        }
+        result.map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::tests::MockTransport;
+
+    #[tokio::test]
+    async fn test_kafka_rest_consumer_lifecycle() {
+        let base = "https://kafka.example.com/consumers/etl/instances/anya-blocks";
+        let transport = MockTransport::new(vec![
+            (
+                200,
+                json!({"offsets": [{"partition": 0, "offset": 7, "error_code": null}]}),
+            ),
+            (409, json!({"error_code": 40902, "message": "exists"})),
+            (204, json!(null)),
+            (
+                200,
+                json!([
+                    {"topic": "blocks", "key": "a", "value": {"h": 1}, "partition": 0, "offset": 7},
+                    {"topic": "blocks", "key": null, "value": {"h": 2}, "partition": 1, "offset": 3},
+                    {"topic": "blocks", "key": "a", "value": {"h": 3}, "partition": 0, "offset": 8}
+                ]),
+            ),
+            (204, json!(null)),
+            (
+                404,
+                json!({"error_code": 40403, "message": "instance not found"}),
+            ),
+            (200, json!({"instance_id": "anya-blocks", "base_uri": base})),
+            (204, json!(null)),
+            (200, json!([])),
+        ]);
+        let queue = KafkaRestQueue::new(
+            KafkaRestConfig {
+                base_url: "https://kafka.example.com/".into(),
+                instance: "anya".into(),
+                auth: Auth::Bearer("tok".into()),
+                max_bytes: 65536,
+            },
+            transport.clone(),
+        );
+
+        queue
+            .publish(
+                "blocks",
+                &[QueueMessage {
+                    key: Some("a".into()),
+                    payload: json!({"h": 1}),
+                }],
+            )
+            .await
+            .unwrap();
+        let deliveries = queue.poll("blocks", "etl", 10).await.unwrap();
+        assert_eq!(deliveries.len(), 3);
+        assert_eq!(deliveries[1].key, None);
+        assert_eq!(deliveries[2].ack_token, "0:8");
+        queue.ack("blocks", "etl", &deliveries).await.unwrap();
+        // An expired instance is recreated on the next poll
+        let err = queue.poll("blocks", "etl", 10).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert!(queue.poll("blocks", "etl", 10).await.unwrap().is_empty());
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].url, "https://kafka.example.com/topics/blocks");
+        assert_eq!(requests[0].headers["content-type"], JSON_RECORDS);
+        assert_eq!(requests[0].headers["authorization"], "Bearer tok");
+        assert_eq!(
+            requests[0].body.as_deref(),
+            Some(&br#"{"records":[{"key":"a","value":{"h":1}}]}"#[..])
+        );
+        assert_eq!(requests[2].url, format!("{}/subscription", base));
+        assert_eq!(requests[3].url, format!("{}/records?max_bytes=65536", base));
+        let commit: Value = serde_json::from_slice(requests[4].body.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            commit["offsets"],
+            json!([
+                {"topic": "blocks", "partition": 0, "offset": 8},
+                {"topic": "blocks", "partition": 1, "offset": 3}
+            ])
+        );
+        assert_eq!(requests[6].method, "POST");
+        assert_eq!(requests.len(), 9);
+    }
+}
@@ -0,0 +1,553 @@
+//! NATS JetStream over the plain text protocol
+//!
+//! [`NatsQueue`] speaks the NATS client protocol over one TCP connection
+//! and uses the JetStream API subjects for persistence. Topics are
+//! subjects captured by an existing stream; each consumer group is a
+//! durable pull consumer with explicit acknowledgement on that stream, so
+//! several processes polling the same group share its messages.
+//!
+//! Requests are serialized over the connection. A connection that fails or
+//! times out mid-frame is dropped and reopened on the next call.
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use super::queue::{is_connection_error, Delivery, MessageQueue, QueueMessage};
+use super::Auth;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Header carrying the partitioning key of a message
+const KEY_HEADER: &str = "Anya-Key";
+
+/// Subscription id of the connection's reply inbox
+const INBOX_SID: &str = "1";
+
+/// Configuration of a [`NatsQueue`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NatsConfig {
+    /// Server address, `host:port`
+    pub address: String,
+    /// JetStream stream capturing the topics
+    pub stream: String,
+    /// Token or user/password credentials
+    pub auth: Auth,
+    /// Redelivery delay of unacknowledged messages
+    pub ack_wait: Duration,
+    /// How long a poll waits for messages
+    pub poll_wait: Duration,
+    /// Timeout of connecting and of API requests
+    pub timeout: Duration,
+}
+
+/// A message received on a subscription
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NatsMessage {
+    subject: String,
+    sid: String,
+    reply: Option<String>,
+    /// Status code of a header-only status message
+    status: Option<u16>,
+    headers: BTreeMap<String, String>,
+    payload: Vec<u8>,
+}
+
+/// One protocol frame from the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Frame {
+    Info,
+    Msg(NatsMessage),
+    Ping,
+    Pong,
+    Ok,
+    Err(String),
+}
+
+fn protocol_error(message: impl Into<String>) -> AnyaError {
+    AnyaError::new(ErrorCode::NetworkFailure, message)
+}
+
+fn size(field: &str) -> AnyaResult<usize> {
+    field
+        .parse()
+        .map_err(|_| protocol_error(format!("bad NATS size {}", field)))
+}
+
+/// Status line and headers of an `HMSG` header block
+fn parse_headers(block: &[u8]) -> (Option<u16>, BTreeMap<String, String>) {
+    let text = String::from_utf8_lossy(block);
+    let mut lines = text.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok());
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    (status, headers)
+}
+
+/// Read the next frame
+async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> AnyaResult<Frame> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(protocol_error("NATS connection closed"));
+    }
+    let line = line.trim_end();
+    let (op, args) = line.split_once(' ').unwrap_or((line, ""));
+    let op = op.to_ascii_uppercase();
+    match op.as_str() {
+        "INFO" => Ok(Frame::Info),
+        "PING" => Ok(Frame::Ping),
+        "PONG" => Ok(Frame::Pong),
+        "+OK" => Ok(Frame::Ok),
+        "-ERR" => Ok(Frame::Err(args.trim().trim_matches('\'').to_string())),
+        "MSG" | "HMSG" => {
+            let fields: Vec<&str> = args.split_whitespace().collect();
+            let (subject, sid, reply, header_len, total) = match (op.as_str(), &fields[..]) {
+                ("MSG", [subject, sid, total]) => (*subject, *sid, None, 0, size(total)?),
+                ("MSG", [subject, sid, reply, total]) => {
+                    (*subject, *sid, Some(*reply), 0, size(total)?)
+                }
+                ("HMSG", [subject, sid, headers, total]) => {
+                    (*subject, *sid, None, size(headers)?, size(total)?)
+                }
+                ("HMSG", [subject, sid, reply, headers, total]) => {
+                    (*subject, *sid, Some(*reply), size(headers)?, size(total)?)
+                }
+                _ => return Err(protocol_error(format!("bad NATS frame {}", line))),
+            };
+            if header_len > total {
+                return Err(protocol_error(format!("bad NATS frame {}", line)));
+            }
+            let mut data = vec![0; total + 2];
+            reader.read_exact(&mut data).await?;
+            data.truncate(total);
+            let payload = data.split_off(header_len);
+            let (status, headers) = if header_len > 0 {
+                parse_headers(&data)
+            } else {
+                (None, BTreeMap::new())
+            };
+            Ok(Frame::Msg(NatsMessage {
+                subject: subject.to_string(),
+                sid: sid.to_string(),
+                reply: reply.map(str::to_string),
+                status,
+                headers,
+                payload,
+            }))
+        }
+        _ => Err(protocol_error(format!("unknown NATS operation {}", op))),
+    }
+}
+
+/// Error of a JetStream API response, if it is one
+fn api_error(body: &Value) -> Option<AnyaError> {
+    let error = body.get("error")?;
+    let code = match error["code"].as_u64() {
+        Some(400) => ErrorCode::InvalidInput,
+        Some(404) => ErrorCode::NotFound,
+        Some(409) => ErrorCode::Conflict,
+        _ => ErrorCode::Unavailable,
+    };
+    Some(AnyaError::new(
+        code,
+        format!(
+            "JetStream: {}",
+            error["description"].as_str().unwrap_or("request failed")
+        ),
+    ))
+}
+
+/// Delivery count encoded in a JetStream ack subject
+fn delivery_count(ack_subject: &str) -> u32 {
+    let tokens: Vec<&str> = ack_subject.split('.').collect();
+    // $JS.ACK.<stream>.<consumer>.<delivered>... or, with domain and
+    // account hash, $JS.ACK.<domain>.<hash>.<stream>.<consumer>.<delivered>...
+    let index = if tokens.len() >= 12 { 6 } else { 4 };
+    tokens.get(index).and_then(|t| t.parse().ok()).unwrap_or(1)
+}
+
+struct NatsConnection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    inbox: String,
+    requests: u64,
+    /// Durable consumers known to exist
+    durables: HashSet<String>,
+}
+
+impl NatsConnection {
+    async fn open(config: &NatsConfig) -> AnyaResult<Self> {
+        let stream =
+            tokio::time::timeout(config.timeout, TcpStream::connect(&config.address)).await??;
+        let (read, write) = stream.into_split();
+        let mut conn = Self {
+            reader: BufReader::new(read),
+            writer: write,
+            inbox: format!(
+                "_INBOX.{}",
+                crate::utils::encoding::to_hex(&rand::random::<[u8; 8]>())
+            ),
+            requests: 0,
+            durables: HashSet::new(),
+        };
+        if read_frame(&mut conn.reader).await? != Frame::Info {
+            return Err(protocol_error("NATS server did not send INFO"));
+        }
+
+        let mut options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "headers": true,
+            "no_responders": true,
+            "lang": "rust",
+            "name": "anya-core",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        match &config.auth {
+            Auth::None => {}
+            Auth::Bearer(token) => options["auth_token"] = json!(token),
+            Auth::Basic { username, password } => {
+                options["user"] = json!(username);
+                options["pass"] = json!(password);
+            }
+            _ => {
+                return Err(AnyaError::new(
+                    ErrorCode::Config,
+                    "NATS supports token or user/password credentials",
+                ))
+            }
+        }
+        let handshake = format!(
+            "CONNECT {}\r\nSUB {}.* {}\r\nPING\r\n",
+            options, conn.inbox, INBOX_SID
+        );
+        conn.writer.write_all(handshake.as_bytes()).await?;
+        loop {
+            match read_frame(&mut conn.reader).await? {
+                Frame::Pong => return Ok(conn),
+                Frame::Err(e) => {
+                    return Err(AnyaError::new(
+                        ErrorCode::Unauthenticated,
+                        format!("NATS refused the connection: {}", e),
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn next_reply(&mut self) -> String {
+        self.requests += 1;
+        format!("{}.{}", self.inbox, self.requests)
+    }
+
+    async fn publish(
+        &mut self,
+        subject: &str,
+        reply: Option<&str>,
+        key: Option<&str>,
+        payload: &[u8],
+    ) -> AnyaResult<()> {
+        let reply = reply.map(|r| format!(" {}", r)).unwrap_or_default();
+        let mut out = Vec::with_capacity(payload.len() + 64);
+        match key {
+            Some(key) => {
+                if key.chars().any(char::is_control) {
+                    return Err(AnyaError::invalid_input(
+                        "message keys must not contain control characters",
+                    ));
+                }
+                let headers = format!("NATS/1.0\r\n{}: {}\r\n\r\n", KEY_HEADER, key);
+                out.extend_from_slice(
+                    format!(
+                        "HPUB {}{} {} {}\r\n",
+                        subject,
+                        reply,
+                        headers.len(),
+                        headers.len() + payload.len()
+                    )
+                    .as_bytes(),
+                );
+                out.extend_from_slice(headers.as_bytes());
+            }
+            None => out.extend_from_slice(
+                format!("PUB {}{} {}\r\n", subject, reply, payload.len()).as_bytes(),
+            ),
+        }
+        out.extend_from_slice(payload);
+        out.extend_from_slice(b"\r\n");
+        self.writer.write_all(&out).await?;
+        Ok(())
+    }
+
+    /// Next message on the reply inbox, answering pings on the way
+    async fn next_message(&mut self) -> AnyaResult<NatsMessage> {
+        loop {
+            match read_frame(&mut self.reader).await? {
+                Frame::Msg(msg) if msg.sid == INBOX_SID => return Ok(msg),
+                Frame::Ping => self.writer.write_all(b"PONG\r\n").await?,
+                Frame::Err(e) => return Err(protocol_error(format!("NATS error: {}", e))),
+                _ => {}
+            }
+        }
+    }
+
+    /// Send a request and parse its JSON reply
+    async fn request(
+        &mut self,
+        subject: &str,
+        key: Option<&str>,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> AnyaResult<Value> {
+        let reply = self.next_reply();
+        self.publish(subject, Some(&reply), key, payload).await?;
+        let msg = tokio::time::timeout(timeout, async {
+            loop {
+                let msg = self.next_message().await?;
+                // Replies to abandoned requests are skipped
+                if msg.subject == reply {
+                    return Ok::<_, AnyaError>(msg);
+                }
+            }
+        })
+        .await??;
+        if msg.status == Some(503) {
+            return Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                format!("no JetStream stream or service handles {}", subject),
+            ));
+        }
+        let body: Value = serde_json::from_slice(&msg.payload)?;
+        api_error(&body).map_or(Ok(body), Err)
+    }
+}
+
+/// NATS JetStream broker
+pub struct NatsQueue {
+    config: NatsConfig,
+    conn: Mutex<Option<NatsConnection>>,
+}
+
+impl NatsQueue {
+    /// Create a queue; the connection is opened on first use
+    pub fn new(config: NatsConfig) -> Self {
+        Self {
+            config,
+            conn: Mutex::new(None),
+        }
+    }
+
+    /// Durable consumer name of `group` on `topic`
+    fn durable(group: &str, topic: &str) -> String {
+        format!("{}_{}", group, topic).replace(['.', '*', '>', ' '], "_")
+    }
+
+    async fn publish_all(
+        &self,
+        conn: &mut NatsConnection,
+        topic: &str,
+        messages: &[QueueMessage],
+    ) -> AnyaResult<()> {
+        for message in messages {
+            let payload = serde_json::to_vec(&message.payload)?;
+            let ack = conn
+                .request(topic, message.key.as_deref(), &payload, self.config.timeout)
+                .await?;
+            if ack.get("seq").is_none() {
+                return Err(protocol_error(format!("unexpected publish ack {}", ack)));
+            }
+        }
+        Ok(())
+    }
+
+    async fn pull(
+        &self,
+        conn: &mut NatsConnection,
+        topic: &str,
+        group: &str,
+        max: usize,
+    ) -> AnyaResult<Vec<Delivery>> {
+        let stream = &self.config.stream;
+        let durable = Self::durable(group, topic);
+        let nanos = |d: Duration| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+        if !conn.durables.contains(&durable) {
+            let config = json!({
+                "stream_name": stream,
+                "config": {
+                    "durable_name": durable,
+                    "ack_policy": "explicit",
+                    "deliver_policy": "all",
+                    "filter_subject": topic,
+                    "ack_wait": nanos(self.config.ack_wait),
+                },
+            });
+            conn.request(
+                &format!("$JS.API.CONSUMER.DURABLE.CREATE.{}.{}", stream, durable),
+                None,
+                config.to_string().as_bytes(),
+                self.config.timeout,
+            )
+            .await?;
+            conn.durables.insert(durable.clone());
+        }
+
+        let reply = conn.next_reply();
+        let request = json!({"batch": max, "expires": nanos(self.config.poll_wait)});
+        conn.publish(
+            &format!("$JS.API.CONSUMER.MSG.NEXT.{}.{}", stream, durable),
+            Some(&reply),
+            None,
+            request.to_string().as_bytes(),
+        )
+        .await?;
+        let mut out = Vec::new();
+        // The server ends a short batch with a status message when the
+        // request expires
+        let wait = self.config.poll_wait + self.config.timeout;
+        tokio::time::timeout(wait, async {
+            while out.len() < max {
+                let msg = conn.next_message().await?;
+                match (msg.status, msg.reply) {
+                    (None, Some(ack)) if ack.starts_with("$JS.ACK.") => out.push(Delivery {
+                        topic: msg.subject,
+                        key: msg.headers.get(KEY_HEADER).cloned(),
+                        payload: serde_json::from_slice(&msg.payload).unwrap_or_else(|_| {
+                            Value::String(String::from_utf8_lossy(&msg.payload).into_owned())
+                        }),
+                        attempt: delivery_count(&ack),
+                        ack_token: ack,
+                    }),
+                    // Idle heartbeat
+                    (Some(100), _) => {}
+                    (Some(404 | 408 | 409), _) if msg.subject == reply => break,
+                    (Some(status), _) if msg.subject == reply => {
+                        return Err(protocol_error(format!(
+                            "pull request failed with {}",
+                            status
+                        )))
+                    }
+                    // Leftovers of an earlier request
+                    _ => {}
+                }
+            }
+            Ok::<_, AnyaError>(())
+        })
+        .await??;
+        Ok(out)
+    }
+}
+
+/// Put `conn` back unless `result` shows the connection is broken
+fn restore<T>(slot: &mut Option<NatsConnection>, conn: NatsConnection, result: &AnyaResult<T>) {
+    if !result.as_ref().err().is_some_and(is_connection_error) {
+        *slot = Some(conn);
+    }
+}
+
+#[async_trait]
+impl MessageQueue for NatsQueue {
+    async fn publish(&self, topic: &str, messages: &[QueueMessage]) -> AnyaResult<()> {
+        let mut slot = self.conn.lock().await;
+        let mut conn = match slot.take() {
+            Some(conn) => conn,
+            None => NatsConnection::open(&self.config).await?,
+        };
+        let result = self.publish_all(&mut conn, topic, messages).await;
+        restore(&mut slot, conn, &result);
+        drop(slot);
+        result
+    }
+
+    async fn poll(&self, topic: &str, group: &str, max: usize) -> AnyaResult<Vec<Delivery>> {
+        let mut slot = self.conn.lock().await;
+        let mut conn = match slot.take() {
+            Some(conn) => conn,
+            None => NatsConnection::open(&self.config).await?,
+        };
+        let result = self.pull(&mut conn, topic, group, max.max(1)).await;
+        restore(&mut slot, conn, &result);
+        drop(slot);
+        result
+    }
+
+    async fn ack(&self, _topic: &str, _group: &str, deliveries: &[Delivery]) -> AnyaResult<()> {
+        let mut slot = self.conn.lock().await;
+        let mut conn = match slot.take() {
+            Some(conn) => conn,
+            None => NatsConnection::open(&self.config).await?,
+        };
+        let mut result = Ok(());
+        for delivery in deliveries {
+            result = conn.publish(&delivery.ack_token, None, None, b"+ACK").await;
+            if result.is_err() {
+                break;
+            }
+        }
+        restore(&mut slot, conn, &result);
+        drop(slot);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_nats_frames() {
+        let wire = concat!(
+            "INFO {\"server_id\":\"x\"}\r\n",
+            "PING\r\n",
+            "MSG chain.blocks 1 $JS.ACK.CHAIN.etl.3.10.4.1700000000.0 7\r\n{\"h\":1}\r\n",
+            "HMSG chain.blocks 1 $JS.ACK.CHAIN.etl.1.11.5.1700000000.0 25 32\r\n",
+            "NATS/1.0\r\nAnya-Key: a\r\n\r\n{\"h\":2}\r\n",
+            "HMSG _INBOX.ab.2 1 32 32\r\nNATS/1.0 408 Request Timeout\r\n\r\n\r\n",
+            "-ERR 'Authorization Violation'\r\n",
+            "MSG x 1 10\r\nshort\r\n",
+        )
+        .as_bytes();
+        let mut reader = wire;
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Frame::Info);
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Frame::Ping);
+        let Frame::Msg(plain) = read_frame(&mut reader).await.unwrap() else {
+            panic!("expected a message");
+        };
+        assert_eq!(plain.subject, "chain.blocks");
+        assert_eq!(plain.payload, b"{\"h\":1}");
+        assert_eq!(delivery_count(plain.reply.as_deref().unwrap()), 3);
+        let Frame::Msg(keyed) = read_frame(&mut reader).await.unwrap() else {
+            panic!("expected a message");
+        };
+        assert_eq!(keyed.headers[KEY_HEADER], "a");
+        assert_eq!(keyed.payload, b"{\"h\":2}");
+        assert_eq!(keyed.status, None);
+        let Frame::Msg(status) = read_frame(&mut reader).await.unwrap() else {
+            panic!("expected a status message");
+        };
+        assert_eq!(status.status, Some(408));
+        assert!(status.payload.is_empty());
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap(),
+            Frame::Err("Authorization Violation".into())
+        );
+        assert!(read_frame(&mut reader).await.is_err());
+        assert!(read_frame(&mut reader).await.is_err());
+
+        let err = api_error(&json!({"error": {"code": 404, "description": "stream not found"}}));
+        assert_eq!(err.unwrap().code(), ErrorCode::NotFound);
+        assert!(api_error(&json!({"stream": "CHAIN", "seq": 4})).is_none());
+        assert_eq!(NatsQueue::durable("etl", "chain.*"), "etl_chain__");
+    }
+}
@@ -0,0 +1,699 @@
+//! Message queue sources and sinks for data pipelines
+//!
+//! [`QueueConnector`] consumes from and publishes to one topic of a
+//! [`MessageQueue`] with at-least-once delivery: the messages of a page are
+//! acknowledged only when the next page is fetched, i.e. after the records
+//! were handed on. A crash in between redelivers them, so consumers must
+//! tolerate duplicates.
+//!
+//! Records are checked against the topic's JSON schema in the
+//! [`SchemaRegistry`]. Invalid records are refused on publish; invalid
+//! messages on consume are diverted to a dead-letter topic, if one is
+//! configured, instead of blocking the group.
+//!
+//! Brokers:
+//! - [`MemoryQueue`]: in-process, for tests and single-node setups
+//! - [`KafkaRestQueue`](super::kafka::KafkaRestQueue): Kafka through a REST
+//!   proxy, which manages consumer groups and offsets on the broker side
+//! - [`NatsQueue`](super::nats::NatsQueue): NATS JetStream, with a durable
+//!   pull consumer per group
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{Mutex, RwLock};
+
+use super::{Connector, Record, RecordPage};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// A message to publish
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueMessage {
+    /// Partitioning key
+    pub key: Option<String>,
+    /// JSON payload
+    pub payload: Value,
+}
+
+/// A message handed to a consumer group, to be acknowledged once processed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    /// Topic the message was published to
+    pub topic: String,
+    /// Partitioning key
+    pub key: Option<String>,
+    /// Payload; bodies that are not JSON are passed on as a string
+    pub payload: Value,
+    /// Delivery attempt, 1 on first delivery where the broker tracks it
+    pub attempt: u32,
+    /// Broker-specific handle used to acknowledge the message
+    pub ack_token: String,
+}
+
+/// A broker with topics and consumer groups
+///
+/// Every message of a topic is delivered to one member of each consumer
+/// group. Messages not acknowledged in time are delivered again.
+#[async_trait]
+pub trait MessageQueue: Send + Sync {
+    /// Publish `messages` to `topic`, returning once the broker stored them
+    async fn publish(&self, topic: &str, messages: &[QueueMessage]) -> AnyaResult<()>;
+
+    /// Up to `max` messages of `topic` for `group`
+    async fn poll(&self, topic: &str, group: &str, max: usize) -> AnyaResult<Vec<Delivery>>;
+
+    /// Acknowledge processed deliveries of `topic` for `group`
+    async fn ack(&self, topic: &str, group: &str, deliveries: &[Delivery]) -> AnyaResult<()>;
+}
+
+/// Per-group position in a [`MemoryQueue`] topic
+#[derive(Debug, Default)]
+struct GroupCursor {
+    /// Offset of the next message never delivered
+    next: usize,
+    /// Delivered, unacknowledged offsets with redelivery deadline and attempt
+    pending: BTreeMap<usize, (Instant, u32)>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    topics: HashMap<String, Vec<QueueMessage>>,
+    groups: HashMap<(String, String), GroupCursor>,
+}
+
+/// In-process broker keeping every topic in memory
+pub struct MemoryQueue {
+    ack_wait: Duration,
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryQueue {
+    /// Redeliver messages not acknowledged within `ack_wait`
+    pub fn new(ack_wait: Duration) -> Self {
+        Self {
+            ack_wait,
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    /// Offset below which `group` acknowledged every message of `topic`
+    pub async fn committed(&self, topic: &str, group: &str) -> usize {
+        let state = self.state.lock().await;
+        state
+            .groups
+            .get(&(topic.to_string(), group.to_string()))
+            .map_or(0, |c| c.pending.keys().next().copied().unwrap_or(c.next))
+    }
+
+    /// Number of messages ever published to `topic`
+    pub async fn published(&self, topic: &str) -> usize {
+        self.state
+            .lock()
+            .await
+            .topics
+            .get(topic)
+            .map_or(0, Vec::len)
+    }
+}
+
+#[async_trait]
+impl MessageQueue for MemoryQueue {
+    async fn publish(&self, topic: &str, messages: &[QueueMessage]) -> AnyaResult<()> {
+        self.state
+            .lock()
+            .await
+            .topics
+            .entry(topic.to_string())
+            .or_default()
+            .extend_from_slice(messages);
+        Ok(())
+    }
+
+    async fn poll(&self, topic: &str, group: &str, max: usize) -> AnyaResult<Vec<Delivery>> {
+        let mut state = self.state.lock().await;
+        let MemoryState { topics, groups } = &mut *state;
+        let log = topics.get(topic).map(Vec::as_slice).unwrap_or_default();
+        let cursor = groups
+            .entry((topic.to_string(), group.to_string()))
+            .or_default();
+        let now = Instant::now();
+        let delivery = |offset: usize, attempt: u32| Delivery {
+            topic: topic.to_string(),
+            key: log[offset].key.clone(),
+            payload: log[offset].payload.clone(),
+            attempt,
+            ack_token: offset.to_string(),
+        };
+
+        let mut out = Vec::new();
+        for (offset, (deadline, attempt)) in &mut cursor.pending {
+            if out.len() >= max {
+                break;
+            }
+            if *deadline <= now {
+                *deadline = now + self.ack_wait;
+                *attempt += 1;
+                out.push(delivery(*offset, *attempt));
+            }
+        }
+        while out.len() < max && cursor.next < log.len() {
+            cursor.pending.insert(cursor.next, (now + self.ack_wait, 1));
+            out.push(delivery(cursor.next, 1));
+            cursor.next += 1;
+        }
+        drop(state);
+        Ok(out)
+    }
+
+    async fn ack(&self, topic: &str, group: &str, deliveries: &[Delivery]) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        let Some(cursor) = state
+            .groups
+            .get_mut(&(topic.to_string(), group.to_string()))
+        else {
+            return Err(AnyaError::not_found(format!(
+                "consumer group {} on {}",
+                group, topic
+            )));
+        };
+        for delivery in deliveries {
+            let offset: usize = delivery.ack_token.parse().map_err(|_| {
+                AnyaError::invalid_input(format!("bad ack token {}", delivery.ack_token))
+            })?;
+            cursor.pending.remove(&offset);
+        }
+        drop(state);
+        Ok(())
+    }
+}
+
+/// Versioned JSON schemas of topic payloads
+///
+/// Supports the commonly used validation keywords: `type`, `enum`,
+/// `const`, `properties`, `required`, `additionalProperties`, `items`,
+/// `minItems`, `maxItems`, `minLength`, `maxLength`, `minimum`, `maximum`,
+/// `exclusiveMinimum`, `exclusiveMaximum`, `allOf` and `anyOf`. Other
+/// keywords are ignored.
+#[derive(Default)]
+pub struct SchemaRegistry {
+    schemas: RwLock<HashMap<String, Vec<Value>>>,
+}
+
+impl SchemaRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new schema version for `topic`; versions start at 1
+    pub async fn register(&self, topic: &str, schema: Value) -> AnyaResult<u32> {
+        if !schema.is_object() && !schema.is_boolean() {
+            return Err(AnyaError::invalid_input(
+                "a JSON schema must be an object or a boolean",
+            ));
+        }
+        let mut schemas = self.schemas.write().await;
+        let versions = schemas.entry(topic.to_string()).or_default();
+        versions.push(schema);
+        let version = u32::try_from(versions.len()).unwrap_or(u32::MAX);
+        drop(schemas);
+        Ok(version)
+    }
+
+    /// Latest schema of `topic` with its version
+    pub async fn latest(&self, topic: &str) -> Option<(u32, Value)> {
+        self.schemas.read().await.get(topic).and_then(|versions| {
+            let version = u32::try_from(versions.len()).unwrap_or(u32::MAX);
+            versions.last().map(|s| (version, s.clone()))
+        })
+    }
+
+    /// Check `value` against the latest schema of `topic`; topics without
+    /// a schema accept anything
+    pub async fn validate(&self, topic: &str, value: &Value) -> AnyaResult<()> {
+        let Some((version, schema)) = self.latest(topic).await else {
+            return Ok(());
+        };
+        check(&schema, value, "")
+            .map_err(|e| AnyaError::invalid_input(format!("{} v{}: {}", topic, version, e)))
+    }
+}
+
+fn type_matches(name: &str, value: &Value) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+/// Validate `value` against `schema`, naming the failing JSON pointer
+pub(crate) fn check(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let at = |message: String| {
+        let path = if path.is_empty() { "/" } else { path };
+        format!("{} {}", path, message)
+    };
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(at("is not allowed".into())),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+    let number = |key: &str| schema.get(key).and_then(Value::as_f64);
+    let count = |key: &str| {
+        schema
+            .get(key)
+            .and_then(Value::as_u64)
+            .and_then(|n| usize::try_from(n).ok())
+    };
+
+    match schema.get("type") {
+        Some(Value::String(name)) if !type_matches(name, value) => {
+            return Err(at(format!("must be of type {}", name)));
+        }
+        Some(Value::Array(names))
+            if !names
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|name| type_matches(name, value)) =>
+        {
+            return Err(at(format!(
+                "must be one of the types {}",
+                Value::Array(names.clone())
+            )));
+        }
+        _ => {}
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return Err(at(format!(
+                "must be one of {}",
+                Value::Array(allowed.clone())
+            )));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            return Err(at(format!("must be {}", expected)));
+        }
+    }
+
+    match value {
+        Value::String(s) => {
+            let len = s.chars().count();
+            if count("minLength").is_some_and(|min| len < min) {
+                return Err(at("is too short".into()));
+            }
+            if count("maxLength").is_some_and(|max| len > max) {
+                return Err(at("is too long".into()));
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if number("minimum").is_some_and(|min| n < min)
+                || number("exclusiveMinimum").is_some_and(|min| n <= min)
+            {
+                return Err(at(format!("{} is below the minimum", n)));
+            }
+            if number("maximum").is_some_and(|max| n > max)
+                || number("exclusiveMaximum").is_some_and(|max| n >= max)
+            {
+                return Err(at(format!("{} is above the maximum", n)));
+            }
+        }
+        Value::Array(items) => {
+            if count("minItems").is_some_and(|min| items.len() < min) {
+                return Err(at("has too few items".into()));
+            }
+            if count("maxItems").is_some_and(|max| items.len() > max) {
+                return Err(at("has too many items".into()));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{}/{}", path, i))?;
+                }
+            }
+        }
+        Value::Object(fields) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                if let Some(missing) = required
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .find(|name| !fields.contains_key(*name))
+                {
+                    return Err(at(format!("is missing {}", missing)));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, field) in fields {
+                let field_path = format!("{}/{}", path, name);
+                match properties.and_then(|p| p.get(name)) {
+                    Some(field_schema) => check(field_schema, field, &field_path)?,
+                    None => {
+                        if let Some(extra) = schema.get("additionalProperties") {
+                            check(extra, field, &field_path)?;
+                        }
+                    }
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        for sub in all {
+            check(sub, value, path)?;
+        }
+    }
+    if let Some(Value::Array(any)) = schema.get("anyOf") {
+        if !any.iter().any(|sub| check(sub, value, path).is_ok()) {
+            return Err(at("matches none of the allowed schemas".into()));
+        }
+    }
+    Ok(())
+}
+
+/// Configuration of a [`QueueConnector`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueConfig {
+    /// Connector name
+    pub name: String,
+    /// Topic consumed from and published to
+    pub topic: String,
+    /// Consumer group
+    pub group: String,
+    /// Messages per page
+    pub batch: usize,
+    /// Dotted path of the record field used as message key
+    #[serde(default)]
+    pub key_field: Option<String>,
+    /// Topic receiving consumed messages that fail validation
+    #[serde(default)]
+    pub dead_letter: Option<String>,
+}
+
+/// Pipeline source and sink for one topic of a [`MessageQueue`]
+pub struct QueueConnector {
+    config: QueueConfig,
+    queue: Arc<dyn MessageQueue>,
+    schemas: Arc<SchemaRegistry>,
+    /// Unacknowledged pages by cursor, and the last cursor handed out
+    pages: Mutex<(u64, HashMap<String, Vec<Delivery>>)>,
+}
+
+impl QueueConnector {
+    /// Create a connector validating records against `schemas`
+    pub fn new(
+        config: QueueConfig,
+        queue: Arc<dyn MessageQueue>,
+        schemas: Arc<SchemaRegistry>,
+    ) -> Self {
+        Self {
+            config,
+            queue,
+            schemas,
+            pages: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    async fn accept(&self, delivery: &Delivery) -> AnyaResult<Record> {
+        self.schemas
+            .validate(&self.config.topic, &delivery.payload)
+            .await?;
+        match &delivery.payload {
+            Value::Object(record) => Ok(record.clone()),
+            _ => Err(AnyaError::invalid_input("message payload is not an object")),
+        }
+    }
+}
+
+#[async_trait]
+impl Connector for QueueConnector {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Acknowledge the page `cursor` names and poll the next one; an
+    /// empty page ends the run
+    async fn fetch(&self, cursor: Option<&str>) -> AnyaResult<RecordPage> {
+        let QueueConfig {
+            topic,
+            group,
+            batch,
+            ..
+        } = &self.config;
+        let mut pages = self.pages.lock().await;
+        match cursor {
+            Some(cursor) => {
+                if let Some(done) = pages.1.remove(cursor) {
+                    self.queue.ack(topic, group, &done).await?;
+                }
+            }
+            // Pages left over from an earlier run are redelivered instead
+            None => pages.1.clear(),
+        }
+        let deliveries = self.queue.poll(topic, group, *batch).await?;
+        if deliveries.is_empty() {
+            return Ok(RecordPage::default());
+        }
+
+        let mut records = Vec::new();
+        let mut rejected = Vec::new();
+        for delivery in &deliveries {
+            match self.accept(delivery).await {
+                Ok(record) => records.push(record),
+                Err(e) => {
+                    tracing::warn!(%topic, attempt = delivery.attempt, error = %e, "rejecting queued message");
+                    rejected.push(QueueMessage {
+                        key: delivery.key.clone(),
+                        payload: delivery.payload.clone(),
+                    });
+                }
+            }
+        }
+        if let (Some(dead_letter), false) = (&self.config.dead_letter, rejected.is_empty()) {
+            self.queue.publish(dead_letter, &rejected).await?;
+        }
+
+        pages.0 += 1;
+        let next = pages.0.to_string();
+        pages.1.insert(next.clone(), deliveries);
+        drop(pages);
+        Ok(RecordPage {
+            records,
+            next: Some(next),
+        })
+    }
+
+    /// Validate every record, then publish them all
+    async fn push(&self, records: &[Record]) -> AnyaResult<usize> {
+        let mut messages = Vec::with_capacity(records.len());
+        for record in records {
+            let payload = Value::Object(record.clone());
+            self.schemas.validate(&self.config.topic, &payload).await?;
+            let key = self
+                .config
+                .key_field
+                .as_deref()
+                .and_then(|field| super::lookup(&payload, field))
+                .map(|v| match v {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                });
+            messages.push(QueueMessage { key, payload });
+        }
+        if messages.is_empty() {
+            return Ok(0);
+        }
+        self.queue.publish(&self.config.topic, &messages).await?;
+        Ok(messages.len())
+    }
+}
+
+/// Whether `error` means the connection to a broker is unusable
+pub(crate) fn is_connection_error(error: &AnyaError) -> bool {
+    matches!(
+        error.code(),
+        ErrorCode::NetworkFailure | ErrorCode::Io | ErrorCode::Timeout
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn order_schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["id", "amount"],
+            "properties": {
+                "id": {"type": "string", "minLength": 1},
+                "amount": {"type": "integer", "minimum": 0},
+                "status": {"enum": ["open", "paid"]},
+                "lines": {"type": "array", "items": {"type": "object", "required": ["sku"]}}
+            },
+            "additionalProperties": false
+        })
+    }
+
+    #[tokio::test]
+    async fn test_schema_registry() {
+        let registry = SchemaRegistry::new();
+        let valid = json!({"id": "o-1", "amount": 5, "status": "paid", "lines": [{"sku": "A"}]});
+        registry
+            .validate("orders", &json!("anything"))
+            .await
+            .unwrap();
+        assert_eq!(registry.register("orders", json!(true)).await.unwrap(), 1);
+        assert_eq!(
+            registry.register("orders", order_schema()).await.unwrap(),
+            2
+        );
+        assert_eq!(registry.latest("orders").await.unwrap().0, 2);
+        assert!(registry.register("orders", json!(3)).await.is_err());
+
+        registry.validate("orders", &valid).await.unwrap();
+        for (value, message) in [
+            (json!({"id": "o-1"}), "/ is missing amount"),
+            (json!({"id": "o-1", "amount": -1}), "/amount -1 is below"),
+            (
+                json!({"id": "o-1", "amount": 1.5}),
+                "/amount must be of type integer",
+            ),
+            (
+                json!({"id": "o-1", "amount": 1, "status": "void"}),
+                "/status must be one of",
+            ),
+            (
+                json!({"id": "o-1", "amount": 1, "lines": [{}]}),
+                "/lines/0 is missing sku",
+            ),
+            (
+                json!({"id": "o-1", "amount": 1, "extra": 1}),
+                "/extra is not allowed",
+            ),
+        ] {
+            let err = registry.validate("orders", &value).await.unwrap_err();
+            assert_eq!(err.code(), ErrorCode::InvalidInput);
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_queue_redelivers_unacked() {
+        let queue = MemoryQueue::new(Duration::ZERO);
+        let messages: Vec<QueueMessage> = (0..3)
+            .map(|i| QueueMessage {
+                key: None,
+                payload: json!(i),
+            })
+            .collect();
+        queue.publish("t", &messages).await.unwrap();
+
+        let first = queue.poll("t", "g", 2).await.unwrap();
+        assert_eq!(first.len(), 2);
+        queue.ack("t", "g", &first[1..]).await.unwrap();
+        assert_eq!(queue.committed("t", "g").await, 0);
+
+        let second = queue.poll("t", "g", 5).await.unwrap();
+        assert_eq!(second.len(), 2);
+        assert_eq!(
+            (second[0].payload.clone(), second[0].attempt),
+            (json!(0), 2)
+        );
+        assert_eq!(
+            (second[1].payload.clone(), second[1].attempt),
+            (json!(2), 1)
+        );
+        queue.ack("t", "g", &second).await.unwrap();
+        assert_eq!(queue.committed("t", "g").await, 3);
+
+        // Other groups see the whole topic
+        assert_eq!(queue.poll("t", "other", 5).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_queue_connector_at_least_once() {
+        let queue = Arc::new(MemoryQueue::new(Duration::from_secs(60)));
+        let schemas = Arc::new(SchemaRegistry::new());
+        schemas.register("orders", order_schema()).await.unwrap();
+        let connector = QueueConnector::new(
+            QueueConfig {
+                name: "orders".into(),
+                topic: "orders".into(),
+                group: "pipeline".into(),
+                batch: 2,
+                key_field: Some("id".into()),
+                dead_letter: Some("orders.dead".into()),
+            },
+            queue.clone(),
+            schemas,
+        );
+
+        let record = |v: Value| v.as_object().unwrap().clone();
+        let err = connector
+            .push(&[
+                record(json!({"id": "o-1", "amount": 1})),
+                record(json!({"id": "o-2"})),
+            ])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        assert_eq!(queue.published("orders").await, 0);
+        let pushed = connector
+            .push(&[
+                record(json!({"id": "o-1", "amount": 1})),
+                record(json!({"id": "o-2", "amount": 2})),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(pushed, 2);
+        // Producers without validation can still publish bad messages
+        queue
+            .publish(
+                "orders",
+                &[QueueMessage {
+                    key: None,
+                    payload: json!({"id": "o-3"}),
+                }],
+            )
+            .await
+            .unwrap();
+
+        // A page is only acknowledged when the next one is fetched
+        let first = connector.fetch(None).await.unwrap();
+        assert_eq!(first.records.len(), 2);
+        assert_eq!(first.records[1]["id"], "o-2");
+        assert_eq!(queue.committed("orders", "pipeline").await, 0);
+        let second = connector.fetch(first.next.as_deref()).await.unwrap();
+        assert!(second.records.is_empty());
+        assert_eq!(queue.committed("orders", "pipeline").await, 2);
+        let last = connector.fetch(second.next.as_deref()).await.unwrap();
+        assert_eq!(last, RecordPage::default());
+        assert_eq!(queue.committed("orders", "pipeline").await, 3);
+
+        let dead = queue.poll("orders.dead", "ops", 10).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].payload["id"], "o-3");
+        let keys: Vec<_> = queue
+            .poll("orders", "audit", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.key)
+            .collect();
+        assert_eq!(keys, vec![Some("o-1".into()), Some("o-2".into()), None]);
+    }
+}
@@ -0,0 +1,429 @@
+//! Generic REST connector and signed webhook receiver
+//!
+//! [`RestConnector`] covers the common shape of CRM and SaaS APIs: JSON
+//! lists behind a `GET` with cursor, page or offset pagination, and a
+//! `POST` accepting a JSON array. [`WebhookReceiver`] accepts pushes from
+//! such systems, signed the same way Anya signs its own outgoing webhooks.
+//! The secret may come from a rotating [`crate::keys::KeyRing`].
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    Auth, Authenticator, Connector, HttpRequest, HttpResponse, HttpTransport, MappingTemplate,
+    RateLimiter, Record, RecordPage,
+};
+use crate::keys::KeyRing;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// How a REST API splits results into pages
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pagination {
+    /// A single page
+    None,
+    /// The response names the next cursor
+    Cursor {
+        /// Query parameter carrying the cursor
+        param: String,
+        /// Dotted path of the next cursor in the response
+        next_field: String,
+    },
+    /// Numbered pages starting at 1; a short page is the last
+    Page {
+        /// Query parameter carrying the page number
+        param: String,
+        /// Query parameter carrying the page size
+        size_param: String,
+        /// Records per page
+        size: usize,
+    },
+    /// Record offsets; a short page is the last
+    Offset {
+        /// Query parameter carrying the offset
+        param: String,
+        /// Query parameter carrying the page size
+        limit_param: String,
+        /// Records per page
+        limit: usize,
+    },
+}
+
+/// Configuration of a [`RestConnector`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestConfig {
+    /// Connector name
+    pub name: String,
+    /// Base URL, e.g. `https://crm.example.com/api`
+    pub base_url: String,
+    /// Path listing records
+    pub fetch_path: String,
+    /// Path accepting records, if pushing is supported
+    #[serde(default)]
+    pub push_path: Option<String>,
+    /// Credentials
+    pub auth: Auth,
+    /// Pagination scheme
+    pub pagination: Pagination,
+    /// Dotted path of the record array in responses; the body itself if
+    /// empty
+    #[serde(default)]
+    pub records_field: String,
+    /// Sustained calls per second
+    pub rate_per_sec: f64,
+    /// Burst size
+    pub burst: u32,
+}
+
+/// Connector for JSON REST APIs
+pub struct RestConnector {
+    config: RestConfig,
+    transport: Arc<dyn HttpTransport>,
+    auth: Authenticator,
+    limiter: RateLimiter,
+}
+
+impl RestConnector {
+    /// Create a connector sending requests through `transport`
+    pub fn new(config: RestConfig, transport: Arc<dyn HttpTransport>) -> Self {
+        let auth = Authenticator::new(config.auth.clone(), Arc::clone(&transport));
+        let limiter = RateLimiter::new(config.rate_per_sec, config.burst);
+        Self {
+            config,
+            transport,
+            auth,
+            limiter,
+        }
+    }
+
+    fn url(&self, path: &str, query: &[(&str, String)]) -> String {
+        let mut url = format!(
+            "{}/{}",
+            self.config.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+        for (i, (key, value)) in query.iter().enumerate() {
+            url.push(if i == 0 && !url.contains('?') {
+                '?'
+            } else {
+                '&'
+            });
+            url.push_str(&crate::utils::encoding::percent_encode(key));
+            url.push('=');
+            url.push_str(&crate::utils::encoding::percent_encode(value));
+        }
+        url
+    }
+
+    /// Send with credentials, retrying once with a fresh token on 401
+    async fn send(&self, request: HttpRequest) -> AnyaResult<HttpResponse> {
+        let mut retried = false;
+        loop {
+            self.limiter.acquire().await;
+            let mut attempt = request.clone();
+            self.auth.apply(&mut attempt).await?;
+            let response = self.transport.send(attempt).await?;
+            match response.status {
+                200..=299 => return Ok(response),
+                401 if !retried && matches!(self.config.auth, Auth::OAuth2 { .. }) => {
+                    self.auth.invalidate().await;
+                    retried = true;
+                }
+                _ => return Err(super::http_error(&request, &response)),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Connector for RestConnector {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    async fn fetch(&self, cursor: Option<&str>) -> AnyaResult<RecordPage> {
+        let position = |default: usize| -> AnyaResult<usize> {
+            cursor.map_or(Ok(default), |c| {
+                c.parse()
+                    .map_err(|_| AnyaError::invalid_input(format!("bad page cursor {}", c)))
+            })
+        };
+        let query = match &self.config.pagination {
+            Pagination::None => Vec::new(),
+            Pagination::Cursor { param, .. } => cursor
+                .map(|c| vec![(param.as_str(), c.to_string())])
+                .unwrap_or_default(),
+            Pagination::Page {
+                param,
+                size_param,
+                size,
+            } => vec![
+                (param.as_str(), position(1)?.to_string()),
+                (size_param.as_str(), size.to_string()),
+            ],
+            Pagination::Offset {
+                param,
+                limit_param,
+                limit,
+            } => vec![
+                (param.as_str(), position(0)?.to_string()),
+                (limit_param.as_str(), limit.to_string()),
+            ],
+        };
+        let mut request = HttpRequest::new("GET", self.url(&self.config.fetch_path, &query));
+        request
+            .headers
+            .insert("accept".into(), "application/json".into());
+        let body = self.send(request).await?.json()?;
+
+        let list = if self.config.records_field.is_empty() {
+            Some(&body)
+        } else {
+            super::lookup(&body, &self.config.records_field)
+        };
+        let records: Vec<Record> = list
+            .and_then(Value::as_array)
+            .ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::Serialization,
+                    format!("{} returned no record array", self.config.name),
+                )
+            })?
+            .iter()
+            .filter_map(|v| v.as_object().cloned())
+            .collect();
+        let next = match &self.config.pagination {
+            Pagination::None => None,
+            Pagination::Cursor { next_field, .. } => {
+                super::lookup(&body, next_field).and_then(|v| match v {
+                    Value::String(s) if !s.is_empty() => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+            }
+            Pagination::Page { size, .. } => (records.len() >= *size)
+                .then(|| position(1).map(|p| (p + 1).to_string()))
+                .transpose()?,
+            Pagination::Offset { limit, .. } => (records.len() >= *limit)
+                .then(|| position(0).map(|o| (o + records.len()).to_string()))
+                .transpose()?,
+        };
+        Ok(RecordPage { records, next })
+    }
+
+    async fn push(&self, records: &[Record]) -> AnyaResult<usize> {
+        let Some(path) = &self.config.push_path else {
+            return Err(AnyaError::new(
+                ErrorCode::Config,
+                format!("connector {} does not accept pushes", self.config.name),
+            ));
+        };
+        if records.is_empty() {
+            return Ok(0);
+        }
+        let mut request = HttpRequest::new("POST", self.url(path, &[]));
+        request
+            .headers
+            .insert("content-type".into(), "application/json".into());
+        request.body = Some(serde_json::to_vec(records)?);
+        self.send(request).await?;
+        Ok(records.len())
+    }
+}
+
+/// Verifies and decodes webhook deliveries from an external system
+///
+/// Deliveries carry an `X-Anya-Signature: sha256=<hex>` HMAC of the body
+/// and a JSON object or array of objects.
+pub struct WebhookReceiver {
+    key: WebhookKey,
+    mapping: Option<MappingTemplate>,
+}
+
+enum WebhookKey {
+    Secret(ring::hmac::Key),
+    Ring(Arc<KeyRing>),
+}
+
+impl WebhookReceiver {
+    /// Accept deliveries signed with `secret`, mapping them if `mapping`
+    /// is given
+    pub fn new(secret: &[u8], mapping: Option<MappingTemplate>) -> Self {
+        Self {
+            key: WebhookKey::Secret(ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret)),
+            mapping,
+        }
+    }
+
+    /// Accept deliveries signed with any secret `ring` currently accepts,
+    /// so senders can switch over during a rotation's overlap window
+    pub const fn with_key_ring(ring: Arc<KeyRing>, mapping: Option<MappingTemplate>) -> Self {
+        Self {
+            key: WebhookKey::Ring(ring),
+            mapping,
+        }
+    }
+
+    /// Verify a delivery and return its records
+    pub fn receive(
+        &self,
+        headers: &BTreeMap<String, String>,
+        body: &[u8],
+    ) -> AnyaResult<Vec<Record>> {
+        let signature = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("x-anya-signature"))
+            .and_then(|(_, value)| value.strip_prefix("sha256="))
+            .and_then(|hex| crate::utils::encoding::from_hex(hex).ok())
+            .ok_or_else(|| {
+                AnyaError::new(ErrorCode::Unauthenticated, "missing webhook signature")
+            })?;
+        let verified = match &self.key {
+            WebhookKey::Secret(key) => ring::hmac::verify(key, body, &signature).is_ok(),
+            WebhookKey::Ring(ring) => ring.verify_hmac(body, &signature).is_ok(),
+        };
+        if !verified {
+            return Err(AnyaError::new(
+                ErrorCode::Unauthenticated,
+                "bad webhook signature",
+            ));
+        }
+
+        let records = match serde_json::from_slice(body)? {
+            Value::Object(record) => vec![record],
+            Value::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::Object(record) => Ok(record),
+                    _ => Err(AnyaError::invalid_input("webhook items must be objects")),
+                })
+                .collect::<AnyaResult<_>>()?,
+            _ => return Err(AnyaError::invalid_input("webhook body must be an object")),
+        };
+        match &self.mapping {
+            Some(mapping) => records
+                .into_iter()
+                .map(|r| mapping.apply(&Value::Object(r)))
+                .collect(),
+            None => Ok(records),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::tests::MockTransport;
+    use serde_json::json;
+
+    fn config(auth: Auth, pagination: Pagination) -> RestConfig {
+        RestConfig {
+            name: "crm".into(),
+            base_url: "https://crm.example.com/api/".into(),
+            fetch_path: "/contacts".into(),
+            push_path: Some("contacts/import".into()),
+            auth,
+            pagination,
+            records_field: "data".into(),
+            rate_per_sec: 1000.0,
+            burst: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rest_connector_paginates_with_oauth() {
+        let transport = MockTransport::new(vec![
+            (200, json!({"access_token": "t1", "expires_in": 3600})),
+            (
+                200,
+                json!({"data": [{"id": 1}, {"id": 2}], "meta": {"next": "abc"}}),
+            ),
+            (401, json!({"error": "expired"})),
+            (200, json!({"access_token": "t2", "expires_in": 3600})),
+            (200, json!({"data": [{"id": 3}], "meta": {"next": null}})),
+            (202, json!({})),
+        ]);
+        let auth = Auth::OAuth2 {
+            token_url: "https://auth.example.com/token".into(),
+            client_id: "anya".into(),
+            client_secret: "s3cret".into(),
+            scope: Some("contacts".into()),
+        };
+        let pagination = Pagination::Cursor {
+            param: "cursor".into(),
+            next_field: "meta.next".into(),
+        };
+        let connector = RestConnector::new(config(auth, pagination), transport.clone());
+
+        let first = connector.fetch(None).await.unwrap();
+        assert_eq!(first.records.len(), 2);
+        assert_eq!(first.next.as_deref(), Some("abc"));
+        let second = connector.fetch(first.next.as_deref()).await.unwrap();
+        assert_eq!(second.records[0]["id"], 3);
+        assert_eq!(second.next, None);
+        assert_eq!(connector.push(&second.records).await.unwrap(), 1);
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[1].url, "https://crm.example.com/api/contacts");
+        assert_eq!(requests[1].headers["authorization"], "Bearer t1");
+        assert_eq!(
+            requests[2].url,
+            "https://crm.example.com/api/contacts?cursor=abc"
+        );
+        assert_eq!(requests[4].headers["authorization"], "Bearer t2");
+        assert_eq!(requests[5].method, "POST");
+        assert_eq!(
+            requests[5].url,
+            "https://crm.example.com/api/contacts/import"
+        );
+        assert_eq!(requests[5].body.as_deref(), Some(&b"[{\"id\":3}]"[..]));
+
+        let limited = RestConnector::new(
+            config(
+                Auth::ApiKey {
+                    header: "X-Api-Key".into(),
+                    key: "k".into(),
+                },
+                Pagination::Page {
+                    param: "page".into(),
+                    size_param: "per_page".into(),
+                    size: 2,
+                },
+            ),
+            MockTransport::new(vec![(429, json!({"error": "slow down"}))]),
+        );
+        let err = limited.fetch(Some("3")).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::RateLimited);
+    }
+
+    #[test]
+    fn test_webhook_signature() {
+        let mapping: MappingTemplate = serde_json::from_value(json!({
+            "fields": [{"target": "invoice", "source": "id"}]
+        }))
+        .unwrap();
+        let receiver = WebhookReceiver::new(b"shared", Some(mapping));
+        let body = br#"[{"id": "inv-1"}, {"id": "inv-2"}]"#;
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"shared");
+        let tag = ring::hmac::sign(&key, body);
+        let mut headers = BTreeMap::new();
+        headers.insert(
+            "X-Anya-Signature".to_string(),
+            format!("sha256={}", crate::utils::encoding::to_hex(tag.as_ref())),
+        );
+        let records = receiver.receive(&headers, body).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["invoice"], "inv-2");
+
+        let err = receiver
+            .receive(&headers, br#"[{"id": "inv-3"}]"#)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+        assert!(receiver.receive(&BTreeMap::new(), body).is_err());
+    }
+}
@@ -0,0 +1,1230 @@
+//! Key rings and coordinated key rotation
+//!
+//! A [`KeyRing`] holds the versions of one secret: a JWT signing key, a
+//! storage encryption key, a Nostr identity, or an API HMAC secret. The
+//! active version signs and encrypts; versions in their overlap window are
+//! still accepted when verifying or decrypting, so peers and stored data can
+//! catch up before the old key is retired and its material wiped.
+//!
+//! [`KeyRotationManager`] drives a rotation through every [`RotationTarget`]
+//! registered for the ring. Targets prepare for the staged key, the key is
+//! activated, targets migrate their data to it (for example
+//! [`SealedValues`] re-encrypts stored values in batches), and the previous
+//! key is retired once the overlap window has passed. Progress is persisted
+//! after every batch so an interrupted rotation resumes where it stopped. A
+//! failure at any step rolls the ring back to the previous key; the new key
+//! stays accepted until targets have moved their data back, and is never
+//! retired if that did not succeed.
+//!
+//! Sealed values are laid out as `version(u32 BE) | nonce(12) | AEAD(data)`
+//! using ChaCha20-Poly1305.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use ::bitcoin::base64;
+use ::bitcoin::secp256k1::{KeyPair, Secp256k1, SecretKey};
+use async_trait::async_trait;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair as _, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "keys";
+const RING_PREFIX: &str = "ring/";
+const ROTATION_PREFIX: &str = "rotation/";
+const KEY_LEN: usize = 32;
+const VERSION_LEN: usize = 4;
+
+/// What a key ring's material is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyKind {
+    /// Ed25519 seed signing EdDSA JWTs
+    JwtSigning,
+    /// ChaCha20-Poly1305 key sealing stored values
+    StorageEncryption,
+    /// secp256k1 secret key of a Nostr identity
+    Nostr,
+    /// HMAC-SHA256 secret authenticating API requests and webhooks
+    ApiHmac,
+}
+
+/// Lifecycle state of one key version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyState {
+    /// Generated but not yet used
+    Staged,
+    /// Used for new signatures and ciphertexts
+    Active,
+    /// Superseded but still accepted
+    Overlap,
+    /// No longer accepted; material has been wiped
+    Retired,
+}
+
+/// One version of a key ring
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyVersion {
+    /// Version number, starting at 1
+    pub version: u32,
+    /// Lifecycle state
+    pub state: KeyState,
+    /// Unix time the version was generated
+    pub created_at: u64,
+    /// Unix time an overlapping version stops being accepted; `None` keeps
+    /// it until explicitly retired
+    pub retire_at: Option<u64>,
+    material: String,
+}
+
+impl fmt::Debug for KeyVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyVersion")
+            .field("version", &self.version)
+            .field("state", &self.state)
+            .field("created_at", &self.created_at)
+            .field("retire_at", &self.retire_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyVersion {
+    fn material(&self) -> AnyaResult<Vec<u8>> {
+        from_hex(&self.material)
+    }
+
+    const fn accepted(&self) -> bool {
+        matches!(self.state, KeyState::Active | KeyState::Overlap)
+    }
+}
+
+/// Versioned secret material for one purpose
+pub struct KeyRing {
+    name: String,
+    kind: KeyKind,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    versions: RwLock<Vec<KeyVersion>>,
+    write: Mutex<()>,
+}
+
+impl KeyRing {
+    /// Open the ring `name`, generating an active first version if it does
+    /// not exist yet
+    pub async fn open(
+        storage: Arc<dyn StorageBackend>,
+        name: &str,
+        kind: KeyKind,
+    ) -> AnyaResult<Arc<Self>> {
+        if name.is_empty() || name.contains('#') {
+            return Err(AnyaError::invalid_input(format!(
+                "invalid key ring name {:?}",
+                name
+            )));
+        }
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let key = format!("{}{}", RING_PREFIX, name);
+        let versions = match storage.get(&ns, &key).await? {
+            Some(bytes) => {
+                let (stored_kind, versions): (KeyKind, Vec<KeyVersion>) =
+                    serde_json::from_slice(&bytes)?;
+                if stored_kind != kind {
+                    return Err(AnyaError::new(
+                        ErrorCode::Conflict,
+                        format!("key ring {} holds {:?} keys", name, stored_kind),
+                    ));
+                }
+                versions
+            }
+            None => {
+                let versions = vec![KeyVersion {
+                    version: 1,
+                    state: KeyState::Active,
+                    created_at: unix_now(),
+                    retire_at: None,
+                    material: to_hex(&generate(kind)?),
+                }];
+                storage
+                    .put(&ns, &key, &serde_json::to_vec(&(kind, &versions))?)
+                    .await?;
+                versions
+            }
+        };
+        Ok(Arc::new(Self {
+            name: name.to_string(),
+            kind,
+            storage,
+            ns,
+            versions: RwLock::new(versions),
+            write: Mutex::new(()),
+        }))
+    }
+
+    /// Ring name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What the ring's keys are used for
+    pub const fn kind(&self) -> KeyKind {
+        self.kind
+    }
+
+    /// All versions, oldest first
+    pub fn versions(&self) -> Vec<KeyVersion> {
+        self.read().clone()
+    }
+
+    /// The active version
+    pub fn active(&self) -> AnyaResult<KeyVersion> {
+        self.read()
+            .iter()
+            .find(|v| v.state == KeyState::Active)
+            .cloned()
+            .ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::Internal,
+                    format!("key ring {} has no active key", self.name),
+                )
+            })
+    }
+
+    /// Accepted versions, active first
+    fn accepted(&self) -> Vec<KeyVersion> {
+        let mut accepted: Vec<KeyVersion> = self
+            .read()
+            .iter()
+            .filter(|v| v.accepted())
+            .cloned()
+            .collect();
+        accepted.sort_by_key(|v| (v.state != KeyState::Active, std::cmp::Reverse(v.version)));
+        accepted
+    }
+
+    fn accepted_version(&self, version: u32) -> AnyaResult<KeyVersion> {
+        self.read()
+            .iter()
+            .find(|v| v.version == version && v.accepted())
+            .cloned()
+            .ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::Unauthenticated,
+                    format!("key {}#{} is not accepted", self.name, version),
+                )
+            })
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Vec<KeyVersion>> {
+        self.versions.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn expect(&self, kind: KeyKind) -> AnyaResult<()> {
+        if self.kind == kind {
+            Ok(())
+        } else {
+            Err(AnyaError::invalid_input(format!(
+                "key ring {} holds {:?} keys, not {:?}",
+                self.name, self.kind, kind
+            )))
+        }
+    }
+
+    /// HMAC-SHA256 of `data` under the active secret
+    pub fn sign_hmac(&self, data: &[u8]) -> AnyaResult<Vec<u8>> {
+        self.expect(KeyKind::ApiHmac)?;
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &self.active()?.material()?);
+        Ok(ring::hmac::sign(&key, data).as_ref().to_vec())
+    }
+
+    /// Check `tag` against every accepted secret, returning the version
+    /// that produced it
+    pub fn verify_hmac(&self, data: &[u8], tag: &[u8]) -> AnyaResult<u32> {
+        self.expect(KeyKind::ApiHmac)?;
+        for version in self.accepted() {
+            let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &version.material()?);
+            if ring::hmac::verify(&key, data, tag).is_ok() {
+                return Ok(version.version);
+            }
+        }
+        Err(AnyaError::new(
+            ErrorCode::Unauthenticated,
+            "signature matches no accepted secret",
+        ))
+    }
+
+    /// Encrypt `plaintext` under the active key
+    pub fn seal(&self, plaintext: &[u8]) -> AnyaResult<Vec<u8>> {
+        self.expect(KeyKind::StorageEncryption)?;
+        let version = self.active()?;
+        let key = aead_key(&version.material()?)?;
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "random generator failed"))?;
+        let mut sealed = Vec::with_capacity(VERSION_LEN + NONCE_LEN + plaintext.len() + 16);
+        sealed.extend_from_slice(&version.version.to_be_bytes());
+        sealed.extend_from_slice(&nonce);
+        let mut body = plaintext.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&sealed[..VERSION_LEN]),
+            &mut body,
+        )
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "encryption failed"))?;
+        sealed.extend_from_slice(&body);
+        Ok(sealed)
+    }
+
+    /// Decrypt a value sealed under any accepted key
+    pub fn unseal(&self, sealed: &[u8]) -> AnyaResult<Vec<u8>> {
+        self.expect(KeyKind::StorageEncryption)?;
+        let version = sealed_version(sealed)
+            .filter(|_| sealed.len() >= VERSION_LEN + NONCE_LEN)
+            .ok_or_else(|| AnyaError::new(ErrorCode::StorageFailure, "sealed value is corrupt"))?;
+        let key = aead_key(&self.accepted_version(version)?.material()?)?;
+        let nonce = Nonce::try_assume_unique_for_key(&sealed[VERSION_LEN..VERSION_LEN + NONCE_LEN])
+            .map_err(|_| AnyaError::new(ErrorCode::StorageFailure, "sealed value is corrupt"))?;
+        let mut body = sealed[VERSION_LEN + NONCE_LEN..].to_vec();
+        let plaintext = key
+            .open_in_place(nonce, Aad::from(&sealed[..VERSION_LEN]), &mut body)
+            .map_err(|_| {
+                AnyaError::new(
+                    ErrorCode::StorageFailure,
+                    "sealed value failed authentication",
+                )
+            })?;
+        Ok(plaintext.to_vec())
+    }
+
+    /// Sign `claims` as an EdDSA JWT whose `kid` names the active version
+    pub fn sign_jwt(&self, claims: &Value) -> AnyaResult<String> {
+        self.expect(KeyKind::JwtSigning)?;
+        let version = self.active()?;
+        let key = ed25519_key(&version.material()?)?;
+        let header = json!({
+            "alg": "EdDSA",
+            "typ": "JWT",
+            "kid": format!("{}#{}", self.name, version.version),
+        });
+        let signing_input = format!(
+            "{}.{}",
+            encode_segment(&serde_json::to_vec(&header)?),
+            encode_segment(&serde_json::to_vec(claims)?)
+        );
+        let signature = key.sign(signing_input.as_bytes());
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            encode_segment(signature.as_ref())
+        ))
+    }
+
+    /// Verify a JWT signed by an accepted version and return its claims.
+    ///
+    /// Only the signature is checked; expiry and audience are up to the
+    /// caller.
+    pub fn verify_jwt(&self, jwt: &str) -> AnyaResult<Value> {
+        self.expect(KeyKind::JwtSigning)?;
+        let malformed = || AnyaError::new(ErrorCode::Unauthenticated, "malformed JWT");
+        let mut parts = jwt.trim().split('.');
+        let (header, claims, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(c), Some(s)) if parts.next().is_none() => (h, c, s),
+            _ => return Err(malformed()),
+        };
+        let signing_input = format!("{}.{}", header, claims);
+        let header: Value =
+            serde_json::from_slice(&decode_segment(header)?).map_err(|_| malformed())?;
+        if header["alg"] != "EdDSA" {
+            return Err(AnyaError::new(
+                ErrorCode::Unauthenticated,
+                "unsupported JWT algorithm",
+            ));
+        }
+        let version = header["kid"]
+            .as_str()
+            .and_then(|kid| kid.strip_prefix(self.name.as_str()))
+            .and_then(|rest| rest.strip_prefix('#'))
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(malformed)?;
+        let key = ed25519_key(&self.accepted_version(version)?.material()?)?;
+        UnparsedPublicKey::new(&ED25519, key.public_key().as_ref())
+            .verify(signing_input.as_bytes(), &decode_segment(signature)?)
+            .map_err(|_| AnyaError::new(ErrorCode::Unauthenticated, "bad JWT signature"))?;
+        serde_json::from_slice(&decode_segment(claims)?).map_err(|_| malformed())
+    }
+
+    /// Key pair of the active Nostr identity
+    pub fn nostr_keys(&self) -> AnyaResult<KeyPair> {
+        self.expect(KeyKind::Nostr)?;
+        nostr_key(&self.active()?.material()?)
+    }
+
+    /// Hex x-only public keys of all accepted Nostr identities, active first
+    pub fn nostr_pubkeys(&self) -> AnyaResult<Vec<String>> {
+        self.expect(KeyKind::Nostr)?;
+        self.accepted()
+            .iter()
+            .map(|v| {
+                let keys = nostr_key(&v.material()?)?;
+                Ok(to_hex(&keys.x_only_public_key().0.serialize()))
+            })
+            .collect()
+    }
+
+    /// Generate a new staged version
+    async fn stage(&self, now: u64) -> AnyaResult<u32> {
+        let material = to_hex(&generate(self.kind)?);
+        self.update(|versions| {
+            if versions.iter().any(|v| v.state == KeyState::Staged) {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    "a key version is already staged",
+                ));
+            }
+            let version = versions.last().map_or(1, |v| v.version + 1);
+            versions.push(KeyVersion {
+                version,
+                state: KeyState::Staged,
+                created_at: now,
+                retire_at: None,
+                material,
+            });
+            Ok(version)
+        })
+        .await
+    }
+
+    /// Make the staged `version` active; the previous active version
+    /// overlaps until `retire_at`
+    async fn activate(&self, version: u32, retire_at: u64) -> AnyaResult<()> {
+        self.update(|versions| {
+            if !versions
+                .iter()
+                .any(|v| v.version == version && v.state == KeyState::Staged)
+            {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!("key version {} is not staged", version),
+                ));
+            }
+            for v in versions.iter_mut() {
+                if v.version == version {
+                    v.state = KeyState::Active;
+                } else if v.state == KeyState::Active {
+                    v.state = KeyState::Overlap;
+                    v.retire_at = Some(retire_at);
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Undo a rotation from `previous` to `version`.
+    ///
+    /// A staged version is dropped. An activated one is demoted to overlap
+    /// until `retire_at`, since data may already depend on it.
+    async fn roll_back(
+        &self,
+        previous: u32,
+        version: u32,
+        retire_at: Option<u64>,
+    ) -> AnyaResult<()> {
+        self.update(|versions| {
+            if !versions
+                .iter()
+                .any(|v| v.version == previous && v.accepted())
+            {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!("key version {} is no longer accepted", previous),
+                ));
+            }
+            versions.retain(|v| !(v.version == version && v.state == KeyState::Staged));
+            for v in versions.iter_mut() {
+                if v.version == previous {
+                    v.state = KeyState::Active;
+                    v.retire_at = None;
+                } else if v.version == version && v.state != KeyState::Retired {
+                    v.state = KeyState::Overlap;
+                    v.retire_at = retire_at;
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Retire an overlapping version whose window has passed, wiping its
+    /// material. Returns whether it was retired.
+    async fn retire(&self, version: u32, now: u64) -> AnyaResult<bool> {
+        self.update(|versions| {
+            let due = versions.iter_mut().find(|v| {
+                v.version == version
+                    && v.state == KeyState::Overlap
+                    && v.retire_at.is_some_and(|at| at <= now)
+            });
+            let Some(v) = due else {
+                return Ok(false);
+            };
+            v.state = KeyState::Retired;
+            v.material.clear();
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn update<T>(
+        &self,
+        change: impl FnOnce(&mut Vec<KeyVersion>) -> AnyaResult<T>,
+    ) -> AnyaResult<T> {
+        let _write = self.write.lock().await;
+        let mut versions = self.versions();
+        let result = change(&mut versions)?;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", RING_PREFIX, self.name),
+                &serde_json::to_vec(&(self.kind, &versions))?,
+            )
+            .await?;
+        *self.versions.write().unwrap_or_else(|e| e.into_inner()) = versions;
+        Ok(result)
+    }
+}
+
+/// Key version a sealed value was encrypted under
+pub fn sealed_version(sealed: &[u8]) -> Option<u32> {
+    sealed
+        .get(..VERSION_LEN)
+        .and_then(|b| b.try_into().ok())
+        .map(u32::from_be_bytes)
+}
+
+fn generate(kind: KeyKind) -> AnyaResult<[u8; KEY_LEN]> {
+    let rng = SystemRandom::new();
+    loop {
+        let mut material = [0u8; KEY_LEN];
+        rng.fill(&mut material)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "random generator failed"))?;
+        if kind != KeyKind::Nostr || SecretKey::from_slice(&material).is_ok() {
+            return Ok(material);
+        }
+    }
+}
+
+fn aead_key(material: &[u8]) -> AnyaResult<LessSafeKey> {
+    UnboundKey::new(&CHACHA20_POLY1305, material)
+        .map(LessSafeKey::new)
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "invalid encryption key"))
+}
+
+fn ed25519_key(material: &[u8]) -> AnyaResult<Ed25519KeyPair> {
+    Ed25519KeyPair::from_seed_unchecked(material)
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "invalid signing key"))
+}
+
+fn nostr_key(material: &[u8]) -> AnyaResult<KeyPair> {
+    let secret = SecretKey::from_slice(material)?;
+    Ok(KeyPair::from_secret_key(
+        &Secp256k1::signing_only(),
+        &secret,
+    ))
+}
+
+fn encode_segment(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_segment(segment: &str) -> AnyaResult<Vec<u8>> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| AnyaError::new(ErrorCode::Unauthenticated, "malformed JWT"))
+}
+
+/// Progress of one target through a rotation
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetProgress {
+    /// Items migrated so far
+    pub done: u64,
+    /// Items to migrate, if the target reported it
+    pub total: u64,
+    /// Target-defined position to resume from
+    pub cursor: Option<String>,
+    /// Whether migration has finished
+    pub finished: bool,
+}
+
+/// A subsystem that depends on a key ring and takes part in its rotations
+#[async_trait]
+pub trait RotationTarget: Send + Sync {
+    /// Target name, unique per ring
+    fn name(&self) -> &str;
+
+    /// Name of the ring the target uses
+    fn ring(&self) -> &str;
+
+    /// Get ready for the staged `version` before it becomes active, for
+    /// example by publishing a new public key or sharing a new secret
+    async fn prepare(&self, _ring: &KeyRing, _version: u32) -> AnyaResult<()> {
+        Ok(())
+    }
+
+    /// Move one batch of data to the active key, updating `progress`.
+    /// Returns `true` once everything has been migrated.
+    async fn migrate(&self, _ring: &KeyRing, _progress: &mut TargetProgress) -> AnyaResult<bool> {
+        Ok(true)
+    }
+
+    /// Undo [`prepare`](Self::prepare) and any migration to `version`;
+    /// the previous key is active again when this is called
+    async fn roll_back(&self, _ring: &KeyRing, _version: u32) -> AnyaResult<()> {
+        Ok(())
+    }
+}
+
+/// Re-encrypts values sealed with a storage encryption ring
+pub struct SealedValues {
+    name: String,
+    ring: String,
+    storage: Arc<dyn StorageBackend>,
+    namespaces: Vec<Namespace>,
+    batch: usize,
+}
+
+impl SealedValues {
+    /// Re-encrypt every value in `namespaces` sealed with the ring `ring`
+    pub fn new(
+        name: impl Into<String>,
+        ring: impl Into<String>,
+        storage: Arc<dyn StorageBackend>,
+        namespaces: Vec<Namespace>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            ring: ring.into(),
+            storage,
+            namespaces,
+            batch: 256,
+        }
+    }
+
+    /// Values re-encrypted per batch (default 256)
+    #[must_use]
+    pub fn with_batch(mut self, batch: usize) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+
+    async fn pending(&self, active: u32) -> AnyaResult<Vec<(usize, String, Vec<u8>)>> {
+        let mut pending = Vec::new();
+        for (index, ns) in self.namespaces.iter().enumerate() {
+            for (key, value) in self.storage.scan_prefix(ns, "").await? {
+                if sealed_version(&value) != Some(active) {
+                    pending.push((index, key, value));
+                }
+            }
+        }
+        Ok(pending)
+    }
+}
+
+#[async_trait]
+impl RotationTarget for SealedValues {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn ring(&self) -> &str {
+        &self.ring
+    }
+
+    async fn migrate(&self, ring: &KeyRing, progress: &mut TargetProgress) -> AnyaResult<bool> {
+        // Values already under the active key are skipped, so rescanning
+        // resumes an interrupted migration without a separate cursor
+        let pending = self.pending(ring.active()?.version).await?;
+        if progress.total == 0 {
+            progress.total = pending.len() as u64;
+        }
+        for (index, key, value) in pending.iter().take(self.batch) {
+            let resealed = ring.seal(&ring.unseal(value)?)?;
+            self.storage
+                .put(&self.namespaces[*index], key, &resealed)
+                .await?;
+            progress.done += 1;
+            progress.cursor = Some(format!("{}:{}", self.namespaces[*index].as_str(), key));
+        }
+        Ok(pending.len() <= self.batch)
+    }
+
+    async fn roll_back(&self, ring: &KeyRing, _version: u32) -> AnyaResult<()> {
+        let mut progress = TargetProgress::default();
+        while !self.migrate(ring, &mut progress).await? {}
+        Ok(())
+    }
+}
+
+/// Where a rotation is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationPhase {
+    /// New key generated; targets are preparing
+    Staged,
+    /// New key active; targets are migrating data
+    Migrating,
+    /// Migration done; previous key accepted until the window ends
+    Overlap,
+    /// Previous key retired
+    Completed,
+    /// A step failed and the previous key is active again
+    RolledBack,
+}
+
+/// Persisted record of one rotation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rotation {
+    /// Sequence number
+    pub id: u64,
+    /// Ring being rotated
+    pub ring: String,
+    /// Version active when the rotation started
+    pub from_version: u32,
+    /// Version being introduced
+    pub to_version: u32,
+    /// Current phase
+    pub phase: RotationPhase,
+    /// Unix start time
+    pub started_at: u64,
+    /// Unix time the superseded key is retired
+    pub retire_at: u64,
+    /// Per-target progress
+    pub progress: BTreeMap<String, TargetProgress>,
+    /// Why the rotation was rolled back
+    pub error: Option<String>,
+}
+
+impl Rotation {
+    /// Whether the rotation still has steps to run before its overlap
+    pub const fn in_progress(&self) -> bool {
+        matches!(self.phase, RotationPhase::Staged | RotationPhase::Migrating)
+    }
+}
+
+/// Automatic rotation schedule for a ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationPolicy {
+    /// Rotate once the active key is this old
+    pub every: Duration,
+    /// How long the previous key stays accepted
+    pub overlap: Duration,
+}
+
+/// Coordinates rotations across key rings and their targets
+pub struct KeyRotationManager {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    rings: BTreeMap<String, Arc<KeyRing>>,
+    targets: Vec<Arc<dyn RotationTarget>>,
+    policies: BTreeMap<String, RotationPolicy>,
+    interval: Duration,
+    next_id: Mutex<u64>,
+}
+
+impl KeyRotationManager {
+    /// Open the manager, restoring the rotation log
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let next_id = storage
+            .scan_prefix(&ns, ROTATION_PREFIX)
+            .await?
+            .last()
+            .map(|(_, bytes)| serde_json::from_slice::<Rotation>(bytes))
+            .transpose()?
+            .map_or(0, |r| r.id + 1);
+        Ok(Self {
+            storage,
+            ns,
+            rings: BTreeMap::new(),
+            targets: Vec::new(),
+            policies: BTreeMap::new(),
+            interval: Duration::from_secs(60),
+            next_id: Mutex::new(next_id),
+        })
+    }
+
+    /// Manage `ring`
+    #[must_use]
+    pub fn with_ring(mut self, ring: Arc<KeyRing>) -> Self {
+        self.rings.insert(ring.name().to_string(), ring);
+        self
+    }
+
+    /// Include `target` in rotations of its ring
+    #[must_use]
+    pub fn with_target(mut self, target: Arc<dyn RotationTarget>) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    /// Rotate `ring` automatically according to `policy`
+    #[must_use]
+    pub fn with_policy(mut self, ring: impl Into<String>, policy: RotationPolicy) -> Self {
+        self.policies.insert(ring.into(), policy);
+        self
+    }
+
+    /// How often the schedule checks for due work (default one minute)
+    #[must_use]
+    pub const fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// A managed ring
+    pub fn ring(&self, name: &str) -> Option<Arc<KeyRing>> {
+        self.rings.get(name).cloned()
+    }
+
+    /// All rotations, oldest first
+    pub async fn rotations(&self) -> AnyaResult<Vec<Rotation>> {
+        self.storage
+            .scan_prefix(&self.ns, ROTATION_PREFIX)
+            .await?
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    /// Rotate `ring`, keeping the previous key accepted for `overlap`.
+    ///
+    /// Returns the rotation in its overlap phase. If a target fails the ring
+    /// is rolled back, the rotation is recorded as rolled back, and the
+    /// target's error is returned.
+    pub async fn rotate(&self, ring: &str, overlap: Duration, now: u64) -> AnyaResult<Rotation> {
+        let mut next_id = self.next_id.lock().await;
+        self.start(&mut next_id, ring, overlap, now).await
+    }
+
+    /// Run due work: roll back rotations interrupted before activation,
+    /// resume interrupted migrations, retire keys whose overlap has ended,
+    /// and start scheduled rotations
+    pub async fn tick(&self, now: u64) -> AnyaResult<()> {
+        let mut next_id = self.next_id.lock().await;
+        for rotation in self.rotations().await? {
+            let ring = self.managed(&rotation.ring)?;
+            match rotation.phase {
+                RotationPhase::Staged => {
+                    let error = AnyaError::new(
+                        ErrorCode::Unavailable,
+                        "rotation interrupted before activation",
+                    );
+                    let _ = self.fail(rotation, &ring, error).await;
+                }
+                RotationPhase::Migrating => {
+                    if let Err(e) = self.advance(rotation, &ring).await {
+                        warn!(ring = ring.name(), error = %e, "resumed key rotation failed");
+                    }
+                }
+                RotationPhase::Overlap => {
+                    if ring.retire(rotation.from_version, now).await? {
+                        info!(
+                            ring = ring.name(),
+                            version = rotation.from_version,
+                            "key retired"
+                        );
+                        self.save(&Rotation {
+                            phase: RotationPhase::Completed,
+                            ..rotation
+                        })
+                        .await?;
+                    }
+                }
+                RotationPhase::RolledBack => {
+                    ring.retire(rotation.to_version, now).await?;
+                }
+                RotationPhase::Completed => {}
+            }
+        }
+
+        let pending = self.rotations().await?;
+        for (name, policy) in &self.policies {
+            let ring = self.managed(name)?;
+            let due = ring
+                .active()?
+                .created_at
+                .saturating_add(policy.every.as_secs())
+                <= now;
+            if due && !pending.iter().any(|r| &r.ring == name && r.in_progress()) {
+                if let Err(e) = self.start(&mut next_id, name, policy.overlap, now).await {
+                    warn!(ring = %name, error = %e, "scheduled key rotation failed");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run [`tick`](Self::tick) on the configured interval until cancelled
+    pub async fn run_schedule(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.interval, || {
+            let manager = Arc::clone(&self);
+            async move {
+                if let Err(e) = manager.tick(unix_now()).await {
+                    warn!(error = %e, "key rotation tick failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    async fn start(
+        &self,
+        next_id: &mut u64,
+        name: &str,
+        overlap: Duration,
+        now: u64,
+    ) -> AnyaResult<Rotation> {
+        let ring = self.managed(name)?;
+        if self
+            .rotations()
+            .await?
+            .iter()
+            .any(|r| r.ring == name && r.in_progress())
+        {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("key ring {} is already rotating", name),
+            ));
+        }
+        let from_version = ring.active()?.version;
+        let to_version = ring.stage(now).await?;
+        let rotation = Rotation {
+            id: *next_id,
+            ring: name.to_string(),
+            from_version,
+            to_version,
+            phase: RotationPhase::Staged,
+            started_at: now,
+            retire_at: now.saturating_add(overlap.as_secs()),
+            progress: BTreeMap::new(),
+            error: None,
+        };
+        *next_id += 1;
+        self.save(&rotation).await?;
+        info!(
+            ring = name,
+            from_version, to_version, "key rotation started"
+        );
+        self.advance(rotation, &ring).await
+    }
+
+    async fn advance(&self, mut rotation: Rotation, ring: &KeyRing) -> AnyaResult<Rotation> {
+        let targets = self.targets_for(ring.name());
+        if rotation.phase == RotationPhase::Staged {
+            for target in &targets {
+                if let Err(e) = target.prepare(ring, rotation.to_version).await {
+                    return self.fail(rotation, ring, e).await;
+                }
+            }
+            if let Err(e) = ring.activate(rotation.to_version, rotation.retire_at).await {
+                return self.fail(rotation, ring, e).await;
+            }
+            rotation.phase = RotationPhase::Migrating;
+            self.save(&rotation).await?;
+        }
+        if rotation.phase == RotationPhase::Migrating {
+            for target in &targets {
+                let mut progress = rotation
+                    .progress
+                    .get(target.name())
+                    .cloned()
+                    .unwrap_or_default();
+                while !progress.finished {
+                    match target.migrate(ring, &mut progress).await {
+                        Ok(finished) => progress.finished = finished,
+                        Err(e) => return self.fail(rotation, ring, e).await,
+                    }
+                    rotation
+                        .progress
+                        .insert(target.name().to_string(), progress.clone());
+                    self.save(&rotation).await?;
+                }
+            }
+            rotation.phase = RotationPhase::Overlap;
+            self.save(&rotation).await?;
+            info!(
+                ring = ring.name(),
+                version = rotation.to_version,
+                "key rotation migrated"
+            );
+        }
+        Ok(rotation)
+    }
+
+    async fn fail(
+        &self,
+        mut rotation: Rotation,
+        ring: &KeyRing,
+        error: AnyaError,
+    ) -> AnyaResult<Rotation> {
+        warn!(ring = ring.name(), error = %error, "key rotation failed, rolling back");
+        // Restore the previous key first so targets migrate back to it
+        ring.roll_back(rotation.from_version, rotation.to_version, None)
+            .await?;
+        let mut clean = true;
+        for target in self.targets_for(ring.name()) {
+            if let Err(e) = target.roll_back(ring, rotation.to_version).await {
+                warn!(target = target.name(), error = %e, "rotation target roll back failed");
+                clean = false;
+            }
+        }
+        // Keep the abandoned key accepted indefinitely if any target may
+        // still hold data under it
+        if clean {
+            ring.roll_back(
+                rotation.from_version,
+                rotation.to_version,
+                Some(rotation.retire_at),
+            )
+            .await?;
+        }
+        rotation.phase = RotationPhase::RolledBack;
+        rotation.error = Some(error.to_string());
+        self.save(&rotation).await?;
+        Err(error)
+    }
+
+    fn managed(&self, name: &str) -> AnyaResult<Arc<KeyRing>> {
+        self.ring(name)
+            .ok_or_else(|| AnyaError::not_found(format!("key ring {}", name)))
+    }
+
+    fn targets_for(&self, ring: &str) -> Vec<Arc<dyn RotationTarget>> {
+        self.targets
+            .iter()
+            .filter(|t| t.ring() == ring)
+            .cloned()
+            .collect()
+    }
+
+    async fn save(&self, rotation: &Rotation) -> AnyaResult<()> {
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{:016x}", ROTATION_PREFIX, rotation.id),
+                &serde_json::to_vec(rotation)?,
+            )
+            .await
+    }
+}
+
+/// Scheduled key rotation as a lifecycle-managed subsystem
+pub struct KeyRotationService {
+    manager: Arc<KeyRotationManager>,
+}
+
+impl KeyRotationService {
+    /// Wrap a manager for registration with the lifecycle manager
+    pub const fn new(manager: Arc<KeyRotationManager>) -> Self {
+        Self { manager }
+    }
+}
+
+#[async_trait]
+impl Subsystem for KeyRotationService {
+    fn name(&self) -> &str {
+        "key-rotation"
+    }
+
+    fn startup_order(&self) -> u32 {
+        // Start after the subsystems whose keys it rotates
+        900
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let manager = Arc::clone(&self.manager);
+        spawner
+            .spawn("schedule", move |token| manager.run_schedule(token))
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    struct Failing;
+
+    #[async_trait]
+    impl RotationTarget for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn ring(&self) -> &str {
+            "storage"
+        }
+
+        async fn migrate(
+            &self,
+            _ring: &KeyRing,
+            _progress: &mut TargetProgress,
+        ) -> AnyaResult<bool> {
+            Err(AnyaError::new(ErrorCode::Unavailable, "target offline"))
+        }
+    }
+
+    async fn sealed_store(storage: &Arc<dyn StorageBackend>, ring: &KeyRing) -> Namespace {
+        let ns = Namespace::new("vault").unwrap();
+        storage.ensure_namespace(&ns).await.unwrap();
+        for i in 0..5u8 {
+            let sealed = ring.seal(&[i; 8]).unwrap();
+            storage.put(&ns, &format!("k{}", i), &sealed).await.unwrap();
+        }
+        ns
+    }
+
+    #[tokio::test]
+    async fn hmac_and_jwt_keys_overlap_until_retired() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let hmac = KeyRing::open(Arc::clone(&storage), "api", KeyKind::ApiHmac)
+            .await
+            .unwrap();
+        let jwt = KeyRing::open(Arc::clone(&storage), "jwt", KeyKind::JwtSigning)
+            .await
+            .unwrap();
+        let manager = KeyRotationManager::open(Arc::clone(&storage))
+            .await
+            .unwrap()
+            .with_ring(Arc::clone(&hmac))
+            .with_ring(Arc::clone(&jwt));
+
+        let old_tag = hmac.sign_hmac(b"body").unwrap();
+        let old_token = jwt.sign_jwt(&json!({ "sub": "alice" })).unwrap();
+        manager
+            .rotate("api", Duration::from_secs(100), 1_000)
+            .await
+            .unwrap();
+        let rotation = manager
+            .rotate("jwt", Duration::from_secs(100), 1_000)
+            .await
+            .unwrap();
+        assert_eq!(rotation.phase, RotationPhase::Overlap);
+
+        assert_eq!(hmac.verify_hmac(b"body", &old_tag).unwrap(), 1);
+        assert_eq!(
+            hmac.verify_hmac(b"body", &hmac.sign_hmac(b"body").unwrap())
+                .unwrap(),
+            2
+        );
+        assert_eq!(jwt.verify_jwt(&old_token).unwrap()["sub"], "alice");
+
+        manager.tick(1_099).await.unwrap();
+        assert!(hmac.verify_hmac(b"body", &old_tag).is_ok());
+        manager.tick(1_100).await.unwrap();
+        assert!(hmac.verify_hmac(b"body", &old_tag).is_err());
+        assert!(jwt.verify_jwt(&old_token).is_err());
+        assert!(manager
+            .rotations()
+            .await
+            .unwrap()
+            .iter()
+            .all(|r| r.phase == RotationPhase::Completed));
+
+        // Reopening restores the rotated ring
+        let reopened = KeyRing::open(storage, "api", KeyKind::ApiHmac)
+            .await
+            .unwrap();
+        assert_eq!(reopened.active().unwrap().version, 2);
+        assert_eq!(reopened.versions()[0].state, KeyState::Retired);
+    }
+
+    #[tokio::test]
+    async fn storage_rotation_reencrypts_in_batches() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let ring = KeyRing::open(Arc::clone(&storage), "storage", KeyKind::StorageEncryption)
+            .await
+            .unwrap();
+        let ns = sealed_store(&storage, &ring).await;
+        let target = SealedValues::new("vault", "storage", Arc::clone(&storage), vec![ns.clone()])
+            .with_batch(2);
+        let manager = KeyRotationManager::open(Arc::clone(&storage))
+            .await
+            .unwrap()
+            .with_ring(Arc::clone(&ring))
+            .with_target(Arc::new(target));
+
+        let rotation = manager
+            .rotate("storage", Duration::ZERO, 1_000)
+            .await
+            .unwrap();
+        let progress = &rotation.progress["vault"];
+        assert_eq!(
+            (progress.done, progress.total, progress.finished),
+            (5, 5, true)
+        );
+        for (key, value) in storage.scan_prefix(&ns, "").await.unwrap() {
+            assert_eq!(sealed_version(&value), Some(2));
+            let i = key[1..].parse::<u8>().unwrap();
+            assert_eq!(ring.unseal(&value).unwrap(), vec![i; 8]);
+        }
+
+        manager.tick(1_000).await.unwrap();
+        assert_eq!(ring.versions()[0].state, KeyState::Retired);
+    }
+
+    #[tokio::test]
+    async fn failed_migration_rolls_back_to_previous_key() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let ring = KeyRing::open(Arc::clone(&storage), "storage", KeyKind::StorageEncryption)
+            .await
+            .unwrap();
+        let ns = sealed_store(&storage, &ring).await;
+        let manager = KeyRotationManager::open(Arc::clone(&storage))
+            .await
+            .unwrap()
+            .with_ring(Arc::clone(&ring))
+            .with_target(Arc::new(SealedValues::new(
+                "vault",
+                "storage",
+                Arc::clone(&storage),
+                vec![ns.clone()],
+            )))
+            .with_target(Arc::new(Failing));
+
+        let err = manager
+            .rotate("storage", Duration::from_secs(10), 1_000)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unavailable);
+        assert_eq!(ring.active().unwrap().version, 1);
+        let rotation = &manager.rotations().await.unwrap()[0];
+        assert_eq!(rotation.phase, RotationPhase::RolledBack);
+
+        // Values were moved back under the restored key before the
+        // abandoned one is retired
+        manager.tick(1_010).await.unwrap();
+        assert_eq!(ring.versions()[1].state, KeyState::Retired);
+        for (_, value) in storage.scan_prefix(&ns, "").await.unwrap() {
+            assert_eq!(sealed_version(&value), Some(1));
+            assert!(ring.unseal(&value).is_ok());
+        }
+    }
+}
@@ -6,10 +6,46 @@
 //! # Architecture
 //!
 //! The library is organized into several main modules:
+//! - `sdk`: Stable, semver-governed clients for wallets, chain queries, identity, and ML
 //! - `ml`: Machine learning components and AI agent system
 //! - `web5`: Web5 protocol integration and decentralized identity
 //! - `bitcoin`: Bitcoin and Lightning Network functionality
+//! - `net`: Peer-to-peer networking: misbehavior scoring, bans, bandwidth shaping, compact blocks, and peer discovery
 //! - `utils`: Common utilities and helper functions
+//! - `lifecycle`: Ordered startup and graceful shutdown of subsystems
+//! - `supervisor`: Restart policies and health of long-running components
+//! - `policy`: Declarative rules evaluated before system actions run
+//! - `gorules`: Versioned decision tables loaded from JSON rule files
+//! - `sandbox`: Out-of-process execution of untrusted plugins with resource limits
+//! - `integrations`: Connectors to ERP/CRM systems and Kafka/NATS message queues
+//! - `export`: Partitioned Parquet export of chain and analytics data (feature `parquet`)
+//! - `error`: Structured error taxonomy with stable error codes
+//! - `build_info`: Build metadata (compiler, features, git commit) and signed release attestations
+//! - `backup`: Encrypted snapshot, backup, and restore of node state
+//! - `keys`: Versioned key rings and coordinated rotation of signing, encryption, Nostr, and HMAC keys
+//! - `sessions`: Login sessions with rotating refresh tokens, device registry, and login anomaly alerts
+//! - `bootstrap`: Provisioning of a new deployment: node keys, DID, wallet, relays, peers, and a signed report
+//! - `flags`: Runtime feature flags with per-tenant targeting, percentage rollouts, and kill switches
+//! - `dao`: DAO proposals, voting, and execution with dry-run impact reports
+//! - `storage`: Pluggable storage backends (memory, Postgres, SQLite, sled)
+//! - `audit`: Read-only audit access to replicated node state that cannot sign or broadcast
+//! - `cache`: Async TTL/LRU caches with single-flight population
+//! - `events`: Persistent event log with replay, subscriptions, and projections
+//! - `cloudevents`: CloudEvents envelopes, event schema registry, and webhook forwarding
+//! - `timeseries`: Embedded metrics history with retention and downsampling
+//! - `sla`: Service level objectives, error budgets, and burn rate alerts
+//! - `costs`: Resource cost accounting and monthly chargeback reports per tenant
+//! - `waf`: API request validation: endpoint schemas, size and content-type limits, and injection detection
+//! - `nostr`: Nostr protocol types and an embeddable relay (websocket server behind feature `nostr-relay`)
+//! - `mobile`: Mobile wallet components exposed through the FFI bridge
+//! - `payments`: Signed payment requests and receipts over DWN records and Nostr events
+//! - `sim`: Deterministic multi-node simulation (feature `simulation`)
+//! - `fuzz`: Fuzzing entry points for untrusted-input parsers (feature `fuzzing`)
+//! - `chaos`: Fault injection for resilience testing (feature `chaos`)
+//! - `cli`: Command-line client for node and wallet operation (feature `cli`)
+//! - `wasm`: JavaScript bindings for browser light clients (feature `wasm`)
+//! - `python`: Python bindings for fee market analytics and federated learning (feature `python`)
+//! - `grpc`: gRPC wallet and chain services (feature `grpc`)
 //!
 //! # Features
 //!
@@ -44,42 +80,69 @@
 #![deny(clippy::cargo)]
 #![deny(clippy::nursery)]
 
-use std::error::Error;
-use std::fmt;
-
 pub mod ml;
 pub mod web5;
 pub mod bitcoin;
+pub mod net;
 pub mod utils;
+pub mod lifecycle;
+pub mod supervisor;
+pub mod policy;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gorules;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sandbox;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod integrations;
+#[cfg(all(feature = "parquet", not(target_arch = "wasm32")))]
+pub mod export;
+pub mod error;
+pub mod build_info;
+pub mod sdk;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod backup;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod keys;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sessions;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod flags;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bootstrap;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dao;
+pub mod storage;
+pub mod audit;
+pub mod cache;
+pub mod events;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cloudevents;
+pub mod timeseries;
+pub mod sla;
+pub mod costs;
+pub mod waf;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod nostr;
+#[cfg(feature = "mobile")]
+pub mod mobile;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod payments;
+#[cfg(any(test, feature = "simulation"))]
+pub mod sim;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 
-/// Core error type for the Anya system
-#[derive(Debug)]
-pub enum AnyaError {
-    /// ML-related errors
-    ML(String),
-    /// Web5-related errors
-    Web5(String),
-    /// Bitcoin-related errors
-    Bitcoin(String),
-    /// General system errors
-    System(String),
-}
-
-impl fmt::Display for AnyaError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            AnyaError::ML(msg) => write!(f, "ML error: {}", msg),
-            AnyaError::Web5(msg) => write!(f, "Web5 error: {}", msg),
-            AnyaError::Bitcoin(msg) => write!(f, "Bitcoin error: {}", msg),
-            AnyaError::System(msg) => write!(f, "System error: {}", msg),
-        }
-    }
-}
-
-impl Error for AnyaError {}
-
-/// Result type for Anya operations
-pub type AnyaResult<T> = Result<T, AnyaError>;
+pub use error::{AnyaError, AnyaResult, ErrorCode, ResultExt};
 
 /// Core configuration for the Anya system
 #[derive(Debug, Clone)]
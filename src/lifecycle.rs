@@ -0,0 +1,389 @@
+//! Lifecycle management for Anya subsystems
+//!
+//! The [`LifecycleManager`] owns every long-running subsystem in the process.
+//! Subsystems are started in ascending [`Subsystem::startup_order`] and shut
+//! down in the reverse order. Each subsystem receives a child
+//! [`CancellationToken`] that its long-running loops (data pipeline, agent
+//! coordinator, analysis loop, main loop) must observe via [`run_loop`] or by
+//! selecting on [`CancellationToken::cancelled`].
+//!
+//! On shutdown the root token is cancelled, in-flight tasks are given
+//! [`LifecycleConfig::drain_timeout`] to finish, and anything still running
+//! after that is aborted.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+/// Phase of the overall system lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LifecyclePhase {
+    /// Subsystems registered but not started
+    Created,
+    /// Subsystems are being started in order
+    Starting,
+    /// All subsystems are running
+    Running,
+    /// Shutdown requested, waiting for in-flight work to drain
+    Draining,
+    /// All subsystems stopped
+    Stopped,
+}
+
+/// Configuration for the lifecycle manager
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleConfig {
+    /// Maximum time to wait for in-flight work after cancellation
+    pub drain_timeout: Duration,
+    /// Maximum time a single subsystem may take to start
+    pub startup_timeout: Duration,
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(30),
+            startup_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A long-running component managed by the [`LifecycleManager`]
+#[async_trait]
+pub trait Subsystem: Send + Sync {
+    /// Stable name used in logs and errors
+    fn name(&self) -> &str;
+
+    /// Startup ordering; lower values start first and stop last
+    fn startup_order(&self) -> u32 {
+        100
+    }
+
+    /// Start the subsystem.
+    ///
+    /// Long-running work should be spawned through the provided
+    /// [`TaskSpawner`] so it is tracked and drained on shutdown.
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()>;
+
+    /// Release resources after all tasks have drained
+    async fn stop(&self) -> AnyaResult<()> {
+        Ok(())
+    }
+}
+
+/// Handle used by subsystems to spawn tracked, cancellable tasks
+#[derive(Clone)]
+pub struct TaskSpawner {
+    subsystem: String,
+    token: CancellationToken,
+    tasks: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl TaskSpawner {
+    /// Cancellation token for the owning subsystem
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Spawn a tracked task.
+    ///
+    /// The closure receives the subsystem's cancellation token and must
+    /// return once it is cancelled.
+    pub async fn spawn<F, Fut>(&self, task_name: &str, f: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = AnyaResult<()>> + Send + 'static,
+    {
+        let fut = f(self.token.clone());
+        let label = format!("{}/{}", self.subsystem, task_name);
+        self.tasks.lock().await.spawn(async move {
+            match fut.await {
+                Ok(()) => debug!(task = %label, "task finished"),
+                Err(e) => warn!(task = %label, error = %e, "task failed"),
+            }
+        });
+    }
+}
+
+/// Drive `tick` every `interval` until `token` is cancelled.
+///
+/// A tick that is already running when cancellation arrives is allowed to
+/// complete, so each iteration is never interrupted half-way.
+pub async fn run_loop<F, Fut>(
+    token: CancellationToken,
+    interval: Duration,
+    mut tick: F,
+) -> AnyaResult<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = AnyaResult<()>>,
+{
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            biased;
+            () = token.cancelled() => return Ok(()),
+            _ = ticker.tick() => tick().await?,
+        }
+    }
+}
+
+/// Coordinates ordered startup and graceful shutdown of subsystems
+pub struct LifecycleManager {
+    config: LifecycleConfig,
+    subsystems: Vec<Arc<dyn Subsystem>>,
+    root: CancellationToken,
+    tasks: Arc<Mutex<JoinSet<()>>>,
+    phase: RwLock<LifecyclePhase>,
+    started: Mutex<Vec<Arc<dyn Subsystem>>>,
+}
+
+impl LifecycleManager {
+    /// Create a new lifecycle manager
+    pub fn new(config: LifecycleConfig) -> Self {
+        Self {
+            config,
+            subsystems: Vec::new(),
+            root: CancellationToken::new(),
+            tasks: Arc::new(Mutex::new(JoinSet::new())),
+            phase: RwLock::new(LifecyclePhase::Created),
+            started: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Register a subsystem; must be called before [`Self::start`]
+    pub fn register(&mut self, subsystem: Arc<dyn Subsystem>) {
+        self.subsystems.push(subsystem);
+    }
+
+    /// Current lifecycle phase
+    pub async fn phase(&self) -> LifecyclePhase {
+        *self.phase.read().await
+    }
+
+    /// Root cancellation token; cancelled when shutdown begins
+    pub fn token(&self) -> CancellationToken {
+        self.root.clone()
+    }
+
+    /// Start all registered subsystems in order.
+    ///
+    /// If any subsystem fails to start, the ones already started are shut
+    /// down before the error is returned.
+    pub async fn start(&self) -> AnyaResult<()> {
+        {
+            let mut phase = self.phase.write().await;
+            if *phase != LifecyclePhase::Created {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!("cannot start from phase {:?}", *phase),
+                ));
+            }
+            *phase = LifecyclePhase::Starting;
+        }
+
+        let mut ordered = self.subsystems.clone();
+        ordered.sort_by_key(|s| s.startup_order());
+
+        for subsystem in ordered {
+            let spawner = TaskSpawner {
+                subsystem: subsystem.name().to_string(),
+                token: self.root.child_token(),
+                tasks: Arc::clone(&self.tasks),
+            };
+            info!(subsystem = subsystem.name(), "starting subsystem");
+            let result =
+                tokio::time::timeout(self.config.startup_timeout, subsystem.start(spawner))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(AnyaError::new(
+                            ErrorCode::Timeout,
+                            format!("subsystem {} timed out during startup", subsystem.name()),
+                        ))
+                    });
+            if let Err(e) = result {
+                warn!(subsystem = subsystem.name(), error = %e, "startup failed, rolling back");
+                self.shutdown().await?;
+                return Err(e);
+            }
+            self.started.lock().await.push(subsystem);
+        }
+
+        *self.phase.write().await = LifecyclePhase::Running;
+        Ok(())
+    }
+
+    /// Cancel all work, drain in-flight tasks, and stop subsystems in
+    /// reverse startup order.
+    ///
+    /// Returns `true` if all tasks drained within the configured timeout.
+    pub async fn shutdown(&self) -> AnyaResult<bool> {
+        {
+            let mut phase = self.phase.write().await;
+            if *phase == LifecyclePhase::Stopped {
+                return Ok(true);
+            }
+            *phase = LifecyclePhase::Draining;
+        }
+        info!("shutdown requested, draining in-flight work");
+        self.root.cancel();
+
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().await);
+        let drain = async { while tasks.join_next().await.is_some() {} };
+        let drained = tokio::time::timeout(self.config.drain_timeout, drain)
+            .await
+            .is_ok();
+        if !drained {
+            warn!(
+                remaining = tasks.len(),
+                "drain timeout elapsed, aborting tasks"
+            );
+            tasks.shutdown().await;
+        }
+
+        let mut started = self.started.lock().await;
+        while let Some(subsystem) = started.pop() {
+            info!(subsystem = subsystem.name(), "stopping subsystem");
+            if let Err(e) = subsystem.stop().await {
+                warn!(subsystem = subsystem.name(), error = %e, "stop failed");
+            }
+        }
+
+        *self.phase.write().await = LifecyclePhase::Stopped;
+        Ok(drained)
+    }
+
+    /// Run until a Ctrl-C signal is received, then shut down gracefully
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn run_until_signal(&self) -> AnyaResult<bool> {
+        self.start().await?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => {
+                res.context("installing signal handler")?;
+            }
+            () = self.root.cancelled() => {}
+        }
+        self.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Recorder {
+        name: &'static str,
+        order: u32,
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+        ticks: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Subsystem for Recorder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn startup_order(&self) -> u32 {
+            self.order
+        }
+
+        async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("start {}", self.name));
+            let ticks = Arc::clone(&self.ticks);
+            spawner
+                .spawn("loop", move |token| {
+                    run_loop(token, Duration::from_millis(5), move || {
+                        let ticks = Arc::clone(&ticks);
+                        async move {
+                            ticks.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        }
+                    })
+                })
+                .await;
+            Ok(())
+        }
+
+        async fn stop(&self) -> AnyaResult<()> {
+            self.log.lock().unwrap().push(format!("stop {}", self.name));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ordered_startup_and_shutdown() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let mut manager = LifecycleManager::new(LifecycleConfig::default());
+        for (name, order) in [("pipeline", 20), ("storage", 10)] {
+            manager.register(Arc::new(Recorder {
+                name,
+                order,
+                log: Arc::clone(&log),
+                ticks: Arc::clone(&ticks),
+            }));
+        }
+
+        manager.start().await.unwrap();
+        assert_eq!(manager.phase().await, LifecyclePhase::Running);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(manager.shutdown().await.unwrap());
+        assert_eq!(manager.phase().await, LifecyclePhase::Stopped);
+        assert!(ticks.load(Ordering::SeqCst) > 0);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "start storage",
+                "start pipeline",
+                "stop pipeline",
+                "stop storage"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout_aborts_stuck_tasks() {
+        struct Stuck;
+
+        #[async_trait]
+        impl Subsystem for Stuck {
+            fn name(&self) -> &str {
+                "stuck"
+            }
+
+            async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+                spawner
+                    .spawn("ignore-cancel", |_token| async {
+                        tokio::time::sleep(Duration::from_secs(3600)).await;
+                        Ok(())
+                    })
+                    .await;
+                Ok(())
+            }
+        }
+
+        let mut manager = LifecycleManager::new(LifecycleConfig {
+            drain_timeout: Duration::from_millis(10),
+            ..LifecycleConfig::default()
+        });
+        manager.register(Arc::new(Stuck));
+        manager.start().await.unwrap();
+        assert!(!manager.shutdown().await.unwrap());
+    }
+}
@@ -0,0 +1,607 @@
+//! Unsupervised anomaly detection over metric and chain streams
+//!
+//! Each registered stream is a fixed list of numeric features observed over
+//! time. Its [`AnomalyDetector`] buffers observations, fits an
+//! [`AnomalyModel`] once enough have arrived, and refits periodically on
+//! the recent normal ones. Observations scoring above the threshold become
+//! an [`Anomaly`] carrying the features that deviate most from their
+//! training baseline.
+//!
+//! [`AnomalyPipeline`] appends anomalies to the [`EventStore`]: chain
+//! streams raise a security incident, metric streams a threshold breach.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::fee_market::FeeSample;
+use crate::events::EventStore;
+use crate::{AnyaError, AnyaResult};
+
+/// Topic of anomalies in chain data
+pub const SECURITY_TOPIC: &str = "security";
+/// Event kind of anomalies in chain data
+pub const SECURITY_INCIDENT: &str = "incident";
+/// Topic of anomalies in internal metrics
+pub const METRICS_TOPIC: &str = "metrics";
+/// Event kind of anomalies in internal metrics
+pub const THRESHOLD_BREACHED: &str = "threshold_breached";
+
+/// Feature names of [`chain_features`]
+pub const CHAIN_FEATURES: [&str; 6] = [
+    "mempool_vsize",
+    "fee_p10",
+    "fee_p25",
+    "fee_p50",
+    "fee_p75",
+    "fee_p90",
+];
+
+/// Chain features of a fee market sample, in [`CHAIN_FEATURES`] order
+pub fn chain_features(sample: &FeeSample) -> Vec<f64> {
+    let p = sample.percentiles;
+    vec![
+        sample.mempool_vsize as f64,
+        p.p10,
+        p.p25,
+        p.p50,
+        p.p75,
+        p.p90,
+    ]
+}
+
+/// Where a stream's data comes from, deciding the event it raises
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamSource {
+    /// Internal service metrics
+    Metrics,
+    /// Blockchain and mempool data
+    Chain,
+}
+
+/// Model scoring how unusual an observation is
+pub trait AnomalyModel: Send {
+    /// Fit to `data`, one row per observation
+    fn fit(&mut self, data: &[Vec<f64>]);
+
+    /// Anomaly score in `[0, 1]`; around 0.5 and below is normal
+    fn score(&self, row: &[f64]) -> f64;
+}
+
+enum Node {
+    Leaf {
+        size: usize,
+    },
+    Split {
+        feature: usize,
+        threshold: f64,
+        /// Range of the feature in the rows this node split
+        range: (f64, f64),
+        left: Box<Self>,
+        right: Box<Self>,
+    },
+}
+
+/// Isolation forest: anomalies are isolated by fewer random splits.
+///
+/// Rows further outside the training range of a node's split feature than
+/// the range is wide count as isolated at that node, so values far beyond anything seen score high
+/// even when only one feature is off.
+pub struct IsolationForest {
+    trees: usize,
+    sample_size: usize,
+    rng: StdRng,
+    forest: Vec<Node>,
+    fitted_size: usize,
+}
+
+impl IsolationForest {
+    /// `trees` trees, each built from up to `sample_size` rows, with a
+    /// deterministic `seed`
+    pub fn new(trees: usize, sample_size: usize, seed: u64) -> Self {
+        Self {
+            trees: trees.max(1),
+            sample_size: sample_size.max(2),
+            rng: StdRng::seed_from_u64(seed),
+            forest: Vec::new(),
+            fitted_size: 0,
+        }
+    }
+
+    fn build(&mut self, rows: &[&[f64]], depth: usize, limit: usize) -> Node {
+        if depth >= limit || rows.len() <= 1 {
+            return Node::Leaf { size: rows.len() };
+        }
+        let width = rows[0].len();
+        // Try features in random order until one is not constant
+        let start = self.rng.gen_range(0..width);
+        for offset in 0..width {
+            let feature = (start + offset) % width;
+            let (lo, hi) = rows.iter().fold((f64::MAX, f64::MIN), |(lo, hi), r| {
+                (lo.min(r[feature]), hi.max(r[feature]))
+            });
+            if hi > lo {
+                let threshold = self.rng.gen_range(lo..hi);
+                let (left, right): (Vec<&[f64]>, Vec<&[f64]>) =
+                    rows.iter().partition(|r| r[feature] < threshold);
+                return Node::Split {
+                    feature,
+                    threshold,
+                    range: (lo, hi),
+                    left: Box::new(self.build(&left, depth + 1, limit)),
+                    right: Box::new(self.build(&right, depth + 1, limit)),
+                };
+            }
+        }
+        Node::Leaf { size: rows.len() }
+    }
+}
+
+impl AnomalyModel for IsolationForest {
+    fn fit(&mut self, data: &[Vec<f64>]) {
+        self.forest.clear();
+        if data.is_empty() {
+            return;
+        }
+        let size = self.sample_size.min(data.len());
+        let limit = (size as f64).log2().ceil() as usize;
+        for _ in 0..self.trees {
+            let sample: Vec<&[f64]> = rand::seq::index::sample(&mut self.rng, data.len(), size)
+                .into_iter()
+                .map(|i| data[i].as_slice())
+                .collect();
+            let tree = self.build(&sample, 0, limit);
+            self.forest.push(tree);
+        }
+        self.fitted_size = size;
+    }
+
+    fn score(&self, row: &[f64]) -> f64 {
+        if self.forest.is_empty() {
+            return 0.0;
+        }
+        let mean = self
+            .forest
+            .iter()
+            .map(|tree| path_length(tree, row, 0))
+            .sum::<f64>()
+            / self.forest.len() as f64;
+        let norm = average_path(self.fitted_size);
+        if norm > 0.0 {
+            (-mean / norm).exp2()
+        } else {
+            0.0
+        }
+    }
+}
+
+fn path_length(node: &Node, row: &[f64], depth: usize) -> f64 {
+    match node {
+        Node::Leaf { size } => depth as f64 + average_path(*size),
+        Node::Split {
+            feature,
+            threshold,
+            range: (lo, hi),
+            left,
+            right,
+        } => {
+            // Well beyond the range seen in training any split on this
+            // feature would isolate the row, so it ends here
+            let margin = hi - lo;
+            if row[*feature] < lo - margin || row[*feature] > hi + margin {
+                return depth as f64 + 1.0;
+            }
+            let next = if row[*feature] < *threshold {
+                left
+            } else {
+                right
+            };
+            path_length(next, row, depth + 1)
+        }
+    }
+}
+
+/// Average path length of an unsuccessful binary search tree lookup
+fn average_path(n: usize) -> f64 {
+    const EULER: f64 = 0.577_215_664_901_532_9;
+    if n <= 1 {
+        return 0.0;
+    }
+    let n = n as f64;
+    2.0f64.mul_add((n - 1.0).ln() + EULER, -2.0 * (n - 1.0) / n)
+}
+
+/// How much one feature contributed to an anomaly
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureContribution {
+    /// Feature name
+    pub feature: String,
+    /// Observed value
+    pub value: f64,
+    /// Median of the training data
+    pub baseline: f64,
+    /// Share of the total deviation, in `[0, 1]`
+    pub share: f64,
+}
+
+/// An observation the model flagged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Anomaly {
+    /// Stream name
+    pub stream: String,
+    /// Stream source
+    pub source: StreamSource,
+    /// Observation time, seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Model score
+    pub score: f64,
+    /// Largest contributors, biggest first
+    pub contributions: Vec<FeatureContribution>,
+}
+
+/// Anomaly detection settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnomalyConfig {
+    /// Score at or above which an observation is anomalous
+    pub threshold: f64,
+    /// Observations buffered before the first fit
+    pub min_training: usize,
+    /// Recent normal observations kept for refitting
+    pub history: usize,
+    /// Observations between refits
+    pub refit_every: usize,
+    /// Isolation forest trees
+    pub trees: usize,
+    /// Rows sampled per tree
+    pub sample_size: usize,
+    /// Contributions reported per anomaly
+    pub top_features: usize,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.65,
+            min_training: 64,
+            history: 1024,
+            refit_every: 256,
+            trees: 100,
+            sample_size: 256,
+            top_features: 3,
+        }
+    }
+}
+
+/// Per-feature median and median absolute deviation of the training data
+struct Baseline {
+    median: Vec<f64>,
+    spread: Vec<f64>,
+}
+
+impl Baseline {
+    fn fit(data: &[Vec<f64>], width: usize) -> Self {
+        let mut median = Vec::with_capacity(width);
+        let mut spread = Vec::with_capacity(width);
+        for feature in 0..width {
+            let column: Vec<f64> = data.iter().map(|r| r[feature]).collect();
+            let m = median_of(column.clone());
+            let mad = median_of(column.iter().map(|v| (v - m).abs()).collect());
+            median.push(m);
+            // Constant features still report deviations relative to their level
+            spread.push(if mad > 0.0 { mad } else { m.abs().max(1.0) });
+        }
+        Self { median, spread }
+    }
+}
+
+fn median_of(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() & 1 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Detector for one stream
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    features: Vec<String>,
+    model: Box<dyn AnomalyModel>,
+    baseline: Option<Baseline>,
+    history: VecDeque<Vec<f64>>,
+    since_fit: usize,
+}
+
+impl AnomalyDetector {
+    /// Detector over `features` using an isolation forest
+    pub fn new(config: AnomalyConfig, features: Vec<String>) -> Self {
+        let model = IsolationForest::new(config.trees, config.sample_size, rand::random());
+        Self::with_model(config, features, Box::new(model))
+    }
+
+    /// Detector over `features` using `model`
+    pub fn with_model(
+        config: AnomalyConfig,
+        features: Vec<String>,
+        model: Box<dyn AnomalyModel>,
+    ) -> Self {
+        Self {
+            config,
+            features,
+            model,
+            baseline: None,
+            history: VecDeque::new(),
+            since_fit: 0,
+        }
+    }
+
+    /// Whether the model has been fitted
+    pub const fn is_trained(&self) -> bool {
+        self.baseline.is_some()
+    }
+
+    /// Score one observation, returning the features behind it if anomalous.
+    ///
+    /// Anomalies are kept out of the training history.
+    pub fn observe(
+        &mut self,
+        values: &[f64],
+    ) -> AnyaResult<Option<(f64, Vec<FeatureContribution>)>> {
+        if values.len() != self.features.len() {
+            return Err(AnyaError::invalid_input(format!(
+                "expected {} features, got {}",
+                self.features.len(),
+                values.len()
+            )));
+        }
+        if values.iter().any(|v| !v.is_finite()) {
+            return Err(AnyaError::invalid_input("feature values must be finite"));
+        }
+        let mut flagged = None;
+        if let Some(baseline) = &self.baseline {
+            let score = self.model.score(values);
+            if score >= self.config.threshold {
+                flagged = Some((score, self.explain(baseline, values)));
+            }
+        }
+        if flagged.is_none() {
+            self.history.push_back(values.to_vec());
+            while self.history.len() > self.config.history.max(1) {
+                self.history.pop_front();
+            }
+            self.since_fit += 1;
+            let due = if self.baseline.is_some() {
+                self.since_fit >= self.config.refit_every.max(1)
+            } else {
+                self.history.len() >= self.config.min_training.max(2)
+            };
+            if due {
+                self.refit();
+            }
+        }
+        Ok(flagged)
+    }
+
+    fn refit(&mut self) {
+        let data: Vec<Vec<f64>> = self.history.iter().cloned().collect();
+        self.model.fit(&data);
+        self.baseline = Some(Baseline::fit(&data, self.features.len()));
+        self.since_fit = 0;
+    }
+
+    fn explain(&self, baseline: &Baseline, values: &[f64]) -> Vec<FeatureContribution> {
+        let deviations: Vec<f64> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v - baseline.median[i]).abs() / baseline.spread[i])
+            .collect();
+        let total: f64 = deviations.iter().sum();
+        let mut contributions: Vec<FeatureContribution> = deviations
+            .iter()
+            .enumerate()
+            .map(|(i, d)| FeatureContribution {
+                feature: self.features[i].clone(),
+                value: values[i],
+                baseline: baseline.median[i],
+                share: if total > 0.0 { d / total } else { 0.0 },
+            })
+            .collect();
+        contributions.sort_by(|a, b| b.share.total_cmp(&a.share));
+        contributions.truncate(self.config.top_features.max(1));
+        contributions
+    }
+}
+
+struct Stream {
+    source: StreamSource,
+    detector: AnomalyDetector,
+}
+
+/// Detectors for all registered streams, raising events on anomalies
+pub struct AnomalyPipeline {
+    config: AnomalyConfig,
+    events: Arc<EventStore>,
+    streams: Mutex<HashMap<String, Stream>>,
+}
+
+impl AnomalyPipeline {
+    /// Pipeline appending anomalies to `events`
+    pub fn new(config: AnomalyConfig, events: Arc<EventStore>) -> Self {
+        Self {
+            config,
+            events,
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a stream with an isolation forest detector
+    pub fn register(&self, stream: &str, source: StreamSource, features: &[&str]) {
+        let detector = AnomalyDetector::new(
+            self.config.clone(),
+            features.iter().map(ToString::to_string).collect(),
+        );
+        self.register_detector(stream, source, detector);
+    }
+
+    /// Register a stream with a custom detector
+    pub fn register_detector(&self, stream: &str, source: StreamSource, detector: AnomalyDetector) {
+        self.streams()
+            .insert(stream.to_string(), Stream { source, detector });
+    }
+
+    /// Feed one observation of `stream`, recording an event if it is
+    /// anomalous
+    pub async fn observe(
+        &self,
+        stream: &str,
+        timestamp: u64,
+        values: &[f64],
+    ) -> AnyaResult<Option<Anomaly>> {
+        let Some(anomaly) = self.detect(stream, timestamp, values)? else {
+            return Ok(None);
+        };
+        let (topic, kind) = match anomaly.source {
+            StreamSource::Chain => (SECURITY_TOPIC, SECURITY_INCIDENT),
+            StreamSource::Metrics => (METRICS_TOPIC, THRESHOLD_BREACHED),
+        };
+        self.events
+            .append(topic, kind, serde_json::to_value(&anomaly)?)
+            .await?;
+        Ok(Some(anomaly))
+    }
+
+    fn detect(&self, stream: &str, timestamp: u64, values: &[f64]) -> AnyaResult<Option<Anomaly>> {
+        let mut streams = self.streams();
+        let entry = streams
+            .get_mut(stream)
+            .ok_or_else(|| AnyaError::not_found(format!("anomaly stream {}", stream)))?;
+        let source = entry.source;
+        let flagged = entry.detector.observe(values)?;
+        drop(streams);
+        Ok(flagged.map(|(score, contributions)| Anomaly {
+            stream: stream.to_string(),
+            source,
+            timestamp,
+            score,
+            contributions,
+        }))
+    }
+
+    fn streams(&self) -> MutexGuard<'_, HashMap<String, Stream>> {
+        self.streams.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventStoreConfig;
+    use crate::ml::fee_market::FeePercentiles;
+    use crate::storage::memory::MemoryBackend;
+
+    fn config() -> AnomalyConfig {
+        AnomalyConfig {
+            min_training: 200,
+            ..AnomalyConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_isolation_forest_separates_outliers() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let data: Vec<Vec<f64>> = (0..500)
+            .map(|_| vec![rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)])
+            .collect();
+        let mut forest = IsolationForest::new(100, 256, 1);
+        forest.fit(&data);
+        let normal = forest.score(&[0.0, 0.0]);
+        assert!(normal < 0.55, "normal scored {}", normal);
+        for outlier in [[8.0, -8.0], [8.0, 0.0]] {
+            let score = forest.score(&outlier);
+            assert!(score > 0.75, "{:?} scored {}", outlier, score);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_raises_events_with_explanations() {
+        let events = EventStore::open(EventStoreConfig::default(), Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let pipeline = AnomalyPipeline::new(config(), events.clone());
+        pipeline.register("rpc", StreamSource::Metrics, &["latency_ms", "errors"]);
+        pipeline.register("mempool", StreamSource::Chain, &CHAIN_FEATURES);
+        assert!(pipeline.observe("rpc", 0, &[1.0]).await.is_err());
+
+        let mut rng = StdRng::seed_from_u64(3);
+        for t in 0..200 {
+            let latency = rng.gen_range(20.0..30.0);
+            let errors = rng.gen_range(0.0..2.0);
+            assert!(pipeline
+                .observe("rpc", t, &[latency, errors])
+                .await
+                .unwrap()
+                .is_none());
+            let sample = FeeSample {
+                timestamp: t,
+                mempool_vsize: rng.gen_range(900_000..1_100_000),
+                percentiles: FeePercentiles {
+                    p10: rng.gen_range(1.0..2.0),
+                    p25: rng.gen_range(2.0..3.0),
+                    p50: rng.gen_range(3.0..5.0),
+                    p75: rng.gen_range(5.0..8.0),
+                    p90: rng.gen_range(8.0..12.0),
+                },
+            };
+            pipeline
+                .observe("mempool", t, &chain_features(&sample))
+                .await
+                .unwrap();
+        }
+
+        let spike = pipeline
+            .observe("rpc", 200, &[450.0, 1.0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(spike.contributions[0].feature, "latency_ms");
+        assert!(spike.contributions[0].share > 0.9);
+
+        let flood = FeeSample {
+            timestamp: 201,
+            mempool_vsize: 40_000_000,
+            percentiles: FeePercentiles {
+                p10: 1.5,
+                p25: 2.5,
+                p50: 4.0,
+                p75: 6.0,
+                p90: 10.0,
+            },
+        };
+        let incident = pipeline
+            .observe("mempool", 201, &chain_features(&flood))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(incident.contributions[0].feature, "mempool_vsize");
+
+        let logged = events.read_from(0, 10).await.unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(
+            (logged[0].topic.as_str(), logged[0].kind.as_str()),
+            (METRICS_TOPIC, THRESHOLD_BREACHED)
+        );
+        assert_eq!(
+            (logged[1].topic.as_str(), logged[1].kind.as_str()),
+            (SECURITY_TOPIC, SECURITY_INCIDENT)
+        );
+    }
+}
@@ -0,0 +1,477 @@
+//! Shapley value attributions for model scores
+//!
+//! An [`Explainer`] splits a score into per-feature contributions relative
+//! to a baseline input, usually the mean of a background dataset. Features
+//! absent from a coalition take their baseline value. With few features the
+//! Shapley values are computed exactly over all coalitions; otherwise they
+//! are estimated from random feature orderings. Either way the
+//! contributions sum to the score minus the baseline score.
+//!
+//! Explained predictions are persisted per subject in the `ml_explanations`
+//! namespace, so [`Explanation::changes_since`] can show which features
+//! moved a score between two runs.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "ml_explanations";
+
+/// A model producing one score from a feature vector
+pub trait ScoreModel: Send + Sync {
+    /// Score for `features`
+    fn predict(&self, features: &[f64]) -> f64;
+}
+
+impl<F> ScoreModel for F
+where
+    F: Fn(&[f64]) -> f64 + Send + Sync,
+{
+    fn predict(&self, features: &[f64]) -> f64 {
+        self(features)
+    }
+}
+
+/// Attribution settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExplainerConfig {
+    /// Largest feature count explained exactly; costs 2^n model calls
+    pub exact_max_features: usize,
+    /// Random orderings sampled above that
+    pub permutations: usize,
+    /// Seed for the sampled orderings, so reports are reproducible
+    pub seed: u64,
+}
+
+impl Default for ExplainerConfig {
+    fn default() -> Self {
+        Self {
+            exact_max_features: 10,
+            permutations: 256,
+            seed: 0,
+        }
+    }
+}
+
+/// One feature's share of a score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attribution {
+    /// Feature name
+    pub feature: String,
+    /// Input value
+    pub value: f64,
+    /// Contribution to the score relative to the baseline
+    pub contribution: f64,
+}
+
+/// A score split into feature contributions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Explanation {
+    /// Model score
+    pub prediction: f64,
+    /// Score of the baseline input
+    pub base_value: f64,
+    /// Per-feature contributions, in feature order
+    pub attributions: Vec<Attribution>,
+    /// Whether the values are exact rather than sampled
+    pub exact: bool,
+}
+
+/// How one feature's contribution changed between two explanations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttributionChange {
+    /// Feature name
+    pub feature: String,
+    /// Earlier contribution
+    pub before: f64,
+    /// Later contribution
+    pub after: f64,
+}
+
+impl AttributionChange {
+    /// Change in contribution
+    pub fn delta(&self) -> f64 {
+        self.after - self.before
+    }
+}
+
+impl Explanation {
+    /// The `n` features with the largest absolute contribution
+    pub fn top(&self, n: usize) -> Vec<&Attribution> {
+        let mut ranked: Vec<&Attribution> = self.attributions.iter().collect();
+        ranked.sort_by(|a, b| b.contribution.abs().total_cmp(&a.contribution.abs()));
+        ranked.truncate(n);
+        ranked
+    }
+
+    /// Contribution changes from `earlier`, largest first. Features missing
+    /// from either side count as contributing zero there.
+    pub fn changes_since(&self, earlier: &Self) -> Vec<AttributionChange> {
+        let before: HashMap<&str, f64> = earlier
+            .attributions
+            .iter()
+            .map(|a| (a.feature.as_str(), a.contribution))
+            .collect();
+        let mut changes: Vec<AttributionChange> = self
+            .attributions
+            .iter()
+            .map(|a| AttributionChange {
+                feature: a.feature.clone(),
+                before: before.get(a.feature.as_str()).copied().unwrap_or(0.0),
+                after: a.contribution,
+            })
+            .collect();
+        for a in &earlier.attributions {
+            if !self.attributions.iter().any(|b| b.feature == a.feature) {
+                changes.push(AttributionChange {
+                    feature: a.feature.clone(),
+                    before: a.contribution,
+                    after: 0.0,
+                });
+            }
+        }
+        changes.sort_by(|a, b| b.delta().abs().total_cmp(&a.delta().abs()));
+        changes
+    }
+}
+
+/// Computes Shapley attributions against a fixed baseline
+pub struct Explainer {
+    config: ExplainerConfig,
+    features: Vec<String>,
+    baseline: Vec<f64>,
+}
+
+impl Explainer {
+    /// Explainer for `features` relative to `baseline`
+    pub fn new(
+        config: ExplainerConfig,
+        features: Vec<String>,
+        baseline: Vec<f64>,
+    ) -> AnyaResult<Self> {
+        if features.len() != baseline.len() {
+            return Err(AnyaError::invalid_input(format!(
+                "{} features but {} baseline values",
+                features.len(),
+                baseline.len()
+            )));
+        }
+        Ok(Self {
+            config,
+            features,
+            baseline,
+        })
+    }
+
+    /// Explainer whose baseline is the mean of `background`
+    pub fn from_background(
+        config: ExplainerConfig,
+        features: Vec<String>,
+        background: &[Vec<f64>],
+    ) -> AnyaResult<Self> {
+        if background.is_empty() {
+            return Err(AnyaError::invalid_input("background data is empty"));
+        }
+        let mut mean = vec![0.0; features.len()];
+        for row in background {
+            if row.len() != features.len() {
+                return Err(AnyaError::invalid_input(format!(
+                    "background row has {} values, expected {}",
+                    row.len(),
+                    features.len()
+                )));
+            }
+            for (m, v) in mean.iter_mut().zip(row) {
+                *m += v / background.len() as f64;
+            }
+        }
+        Self::new(config, features, mean)
+    }
+
+    /// Attribute `model`'s score for `input` to its features
+    pub fn explain(&self, model: &dyn ScoreModel, input: &[f64]) -> AnyaResult<Explanation> {
+        if input.len() != self.features.len() {
+            return Err(AnyaError::invalid_input(format!(
+                "expected {} features, got {}",
+                self.features.len(),
+                input.len()
+            )));
+        }
+        let exact = self.features.len() <= self.config.exact_max_features;
+        let contributions = if exact {
+            self.exact(model, input)
+        } else {
+            self.sampled(model, input)
+        };
+        Ok(Explanation {
+            prediction: model.predict(input),
+            base_value: model.predict(&self.baseline),
+            attributions: self
+                .features
+                .iter()
+                .zip(input)
+                .zip(contributions)
+                .map(|((feature, value), contribution)| Attribution {
+                    feature: feature.clone(),
+                    value: *value,
+                    contribution,
+                })
+                .collect(),
+            exact,
+        })
+    }
+
+    /// Input with the features in `mask` taken from `input`, the rest from
+    /// the baseline
+    fn blend(&self, input: &[f64], mask: usize) -> Vec<f64> {
+        (0..input.len())
+            .map(|i| {
+                if mask >> i & 1 == 1 {
+                    input[i]
+                } else {
+                    self.baseline[i]
+                }
+            })
+            .collect()
+    }
+
+    fn exact(&self, model: &dyn ScoreModel, input: &[f64]) -> Vec<f64> {
+        let n = input.len();
+        let values: Vec<f64> = (0..1usize << n)
+            .map(|mask| model.predict(&self.blend(input, mask)))
+            .collect();
+        // k! (n - k - 1)! / n! = 1 / (n * C(n - 1, k))
+        let weights: Vec<f64> = (0..n)
+            .map(|k| {
+                let choose = (0..k).fold(1.0, |c, j| c * (n - 1 - j) as f64 / (j + 1) as f64);
+                1.0 / (n as f64 * choose)
+            })
+            .collect();
+        (0..n)
+            .map(|i| {
+                (0..1usize << n)
+                    .filter(|mask| mask >> i & 1 == 0)
+                    .map(|mask| {
+                        let k = mask.count_ones() as usize;
+                        weights[k] * (values[mask | 1 << i] - values[mask])
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn sampled(&self, model: &dyn ScoreModel, input: &[f64]) -> Vec<f64> {
+        let n = input.len();
+        let rounds = self.config.permutations.max(1);
+        let mut rng = StdRng::seed_from_u64(self.config.seed);
+        let mut order: Vec<usize> = (0..n).collect();
+        let mut totals = vec![0.0; n];
+        for _ in 0..rounds {
+            order.shuffle(&mut rng);
+            let mut current = self.baseline.clone();
+            let mut previous = model.predict(&current);
+            for &i in &order {
+                current[i] = input[i];
+                let next = model.predict(&current);
+                totals[i] += next - previous;
+                previous = next;
+            }
+        }
+        totals.iter().map(|t| t / rounds as f64).collect()
+    }
+}
+
+/// A persisted, explained score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExplainedPrediction {
+    /// What was scored, e.g. a market, user, or DAO id
+    pub subject: String,
+    /// Model that produced the score
+    pub model: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Score and attributions
+    pub explanation: Explanation,
+}
+
+/// Explained predictions by subject, oldest first
+pub struct ExplanationStore {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    write_lock: Mutex<()>,
+}
+
+impl ExplanationStore {
+    /// Open the store in `storage`
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            storage,
+            ns,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Persist a prediction, replacing any for the same subject, model, and
+    /// time
+    pub async fn record(&self, prediction: &ExplainedPrediction) -> AnyaResult<()> {
+        validate_part("subject", &prediction.subject)?;
+        validate_part("model", &prediction.model)?;
+        let key = format!(
+            "{}/{}/{:016x}",
+            prediction.subject, prediction.model, prediction.timestamp
+        );
+        let _guard = self.write_lock.lock().await;
+        self.storage
+            .put(&self.ns, &key, &serde_json::to_vec(prediction)?)
+            .await
+    }
+
+    /// Up to `limit` most recent predictions of `model` for `subject`,
+    /// oldest first
+    pub async fn history(
+        &self,
+        subject: &str,
+        model: &str,
+        limit: usize,
+    ) -> AnyaResult<Vec<ExplainedPrediction>> {
+        validate_part("subject", subject)?;
+        validate_part("model", model)?;
+        let entries = self
+            .storage
+            .scan_prefix(&self.ns, &format!("{}/{}/", subject, model))
+            .await?;
+        entries[entries.len().saturating_sub(limit)..]
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    /// What moved `model`'s score for `subject` between its last two runs
+    pub async fn latest_changes(
+        &self,
+        subject: &str,
+        model: &str,
+    ) -> AnyaResult<Option<Vec<AttributionChange>>> {
+        let recent = self.history(subject, model, 2).await?;
+        Ok(match recent.as_slice() {
+            [earlier, later] => Some(later.explanation.changes_since(&earlier.explanation)),
+            _ => None,
+        })
+    }
+}
+
+fn validate_part(what: &str, value: &str) -> AnyaResult<()> {
+    if value.is_empty() || value.contains('/') {
+        return Err(AnyaError::invalid_input(format!(
+            "{} must be non-empty and not contain '/'",
+            what
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    fn names(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("f{}", i)).collect()
+    }
+
+    #[test]
+    fn test_exact_and_sampled_attributions() {
+        let linear = |x: &[f64]| 2.0f64.mul_add(x[0], 0.5f64.mul_add(x[2], -3.0 * x[1]));
+        let explainer = Explainer::from_background(
+            ExplainerConfig::default(),
+            names(3),
+            &[vec![0.0, 0.0, 0.0], vec![2.0, 2.0, 2.0]],
+        )
+        .unwrap();
+        let explanation = explainer.explain(&linear, &[3.0, 1.0, 1.0]).unwrap();
+        assert!(explanation.exact);
+        let contributions: Vec<f64> = explanation
+            .attributions
+            .iter()
+            .map(|a| a.contribution)
+            .collect();
+        for (got, want) in contributions.iter().zip([4.0, 0.0, 0.0]) {
+            assert!((got - want).abs() < 1e-9, "{} != {}", got, want);
+        }
+        assert_eq!(explanation.top(1)[0].feature, "f0");
+
+        // An interaction is split evenly, and sampled values still sum to
+        // the score minus the base value
+        let product = |x: &[f64]| x[0] * x[1];
+        let exact = Explainer::new(ExplainerConfig::default(), names(2), vec![0.0, 0.0]).unwrap();
+        let split = exact.explain(&product, &[2.0, 3.0]).unwrap();
+        assert!((split.attributions[0].contribution - 3.0).abs() < 1e-9);
+        assert!((split.attributions[1].contribution - 3.0).abs() < 1e-9);
+
+        let sampled = Explainer::new(
+            ExplainerConfig {
+                exact_max_features: 0,
+                ..ExplainerConfig::default()
+            },
+            names(2),
+            vec![0.0, 0.0],
+        )
+        .unwrap();
+        let estimate = sampled.explain(&product, &[2.0, 3.0]).unwrap();
+        assert!(!estimate.exact);
+        let total: f64 = estimate.attributions.iter().map(|a| a.contribution).sum();
+        assert!((total - (estimate.prediction - estimate.base_value)).abs() < 1e-9);
+        assert!(explainer.explain(&linear, &[1.0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_store_reports_what_moved() {
+        let store = ExplanationStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let explainer = Explainer::new(
+            ExplainerConfig::default(),
+            vec!["volume".into(), "volatility".into()],
+            vec![1.0, 1.0],
+        )
+        .unwrap();
+        let model = |x: &[f64]| 2.0f64.mul_add(-x[1], x[0]);
+        for (timestamp, input) in [(10, [2.0, 1.0]), (20, [2.0, 3.0])] {
+            store
+                .record(&ExplainedPrediction {
+                    subject: "btc-usd".into(),
+                    model: "market".into(),
+                    timestamp,
+                    explanation: explainer.explain(&model, &input).unwrap(),
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            store.history("btc-usd", "market", 5).await.unwrap().len(),
+            2
+        );
+        let changes = store
+            .latest_changes("btc-usd", "market")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(changes[0].feature, "volatility");
+        assert!((changes[0].delta() + 4.0).abs() < 1e-9);
+        assert!(store
+            .latest_changes("eth-usd", "market")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
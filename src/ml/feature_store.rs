@@ -0,0 +1,423 @@
+//! Versioned feature store with point-in-time reads
+//!
+//! A [`FeatureView`] is a named, ordered list of features. Registering a
+//! view with a different feature list creates a new version, and values are
+//! stored per version, so models trained on one version keep reading the
+//! same columns.
+//!
+//! Values are materialized per entity with the time they became true.
+//! [`FeatureStore::as_of`] returns the latest values at or before a given
+//! time, never later ones, so training rows built from labels at time `t`
+//! only see what was known at `t`, exactly like serving did.
+//!
+//! [`FeatureStore::create_dataset`] joins a spine of `(entity, time)` rows
+//! that way and saves a manifest with a content fingerprint;
+//! [`FeatureStore::reproduce_dataset`] rebuilds it and checks the
+//! fingerprint still matches.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "ml_features";
+const VIEW_PREFIX: &str = "view/";
+const VALUE_PREFIX: &str = "value/";
+const DATASET_PREFIX: &str = "dataset/";
+
+/// One version of a named feature list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeatureView {
+    /// View name
+    pub name: String,
+    /// Version, starting at 1
+    pub version: u32,
+    /// Feature names, in value order
+    pub features: Vec<String>,
+}
+
+/// Feature values of an entity from some time on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureRow {
+    /// Entity id
+    pub entity: String,
+    /// Seconds since the Unix epoch the values apply from
+    pub timestamp: u64,
+    /// Values in view feature order
+    pub values: Vec<f64>,
+}
+
+/// A training row request: the entity and the time of its label
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpineRow {
+    /// Entity id
+    pub entity: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Saved description of a dataset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatasetManifest {
+    /// Dataset name
+    pub name: String,
+    /// View the features come from
+    pub view: String,
+    /// View version
+    pub version: u32,
+    /// Rows requested
+    pub spine: Vec<SpineRow>,
+    /// Hex SHA-256 of the joined rows
+    pub fingerprint: String,
+}
+
+/// Point-in-time joined rows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dataset {
+    /// Feature names
+    pub features: Vec<String>,
+    /// One row per spine row; `None` when the entity had no values yet
+    pub rows: Vec<Option<Vec<f64>>>,
+}
+
+impl Dataset {
+    fn fingerprint(&self) -> AnyaResult<String> {
+        Ok(to_hex(&sha256(&serde_json::to_vec(self)?)))
+    }
+}
+
+/// Feature definitions, values, and dataset manifests in storage
+pub struct FeatureStore {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    write_lock: Mutex<()>,
+}
+
+impl FeatureStore {
+    /// Open the store in `storage`
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            storage,
+            ns,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Register `features` under `name`, returning the latest version if it
+    /// already has them or a new version otherwise
+    pub async fn register_view(&self, name: &str, features: &[&str]) -> AnyaResult<FeatureView> {
+        validate_id("view name", name)?;
+        if features.is_empty() {
+            return Err(AnyaError::invalid_input(
+                "a view needs at least one feature",
+            ));
+        }
+        let _guard = self.write_lock.lock().await;
+        let latest = self.latest_view(name).await?;
+        if let Some(view) = latest.as_ref().filter(|v| v.features == features) {
+            return Ok(view.clone());
+        }
+        let view = FeatureView {
+            name: name.to_string(),
+            version: latest.map_or(1, |v| v.version + 1),
+            features: features.iter().map(ToString::to_string).collect(),
+        };
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}/{:08x}", VIEW_PREFIX, name, view.version),
+                &serde_json::to_vec(&view)?,
+            )
+            .await?;
+        Ok(view)
+    }
+
+    /// A specific version of a view
+    pub async fn view(&self, name: &str, version: u32) -> AnyaResult<FeatureView> {
+        validate_id("view name", name)?;
+        let key = format!("{}{}/{:08x}", VIEW_PREFIX, name, version);
+        let bytes =
+            self.storage.get(&self.ns, &key).await?.ok_or_else(|| {
+                AnyaError::not_found(format!("feature view {} v{}", name, version))
+            })?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// The newest version of a view
+    pub async fn latest_view(&self, name: &str) -> AnyaResult<Option<FeatureView>> {
+        validate_id("view name", name)?;
+        let versions = self
+            .storage
+            .scan_prefix(&self.ns, &format!("{}{}/", VIEW_PREFIX, name))
+            .await?;
+        versions
+            .last()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .transpose()
+    }
+
+    /// Materialize values of `entity` under `view`
+    pub async fn write(&self, view: &FeatureView, row: &FeatureRow) -> AnyaResult<()> {
+        validate_id("entity", &row.entity)?;
+        if row.values.len() != view.features.len() {
+            return Err(AnyaError::invalid_input(format!(
+                "view {} v{} has {} features, got {} values",
+                view.name,
+                view.version,
+                view.features.len(),
+                row.values.len()
+            )));
+        }
+        let _guard = self.write_lock.lock().await;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{:016x}", value_prefix(view, &row.entity), row.timestamp),
+                &serde_json::to_vec(&row.values)?,
+            )
+            .await
+    }
+
+    /// Latest values of `entity` at or before `timestamp`
+    pub async fn as_of(
+        &self,
+        view: &FeatureView,
+        entity: &str,
+        timestamp: u64,
+    ) -> AnyaResult<Option<FeatureRow>> {
+        validate_id("entity", entity)?;
+        let prefix = value_prefix(view, entity);
+        let rows = self.storage.scan_prefix(&self.ns, &prefix).await?;
+        let mut found = None;
+        // Keys are ordered by time
+        for (key, bytes) in rows {
+            let time = u64::from_str_radix(key.rsplit('/').next().unwrap_or_default(), 16)
+                .map_err(|e| {
+                    AnyaError::with_source(
+                        ErrorCode::StorageFailure,
+                        format!("bad feature key {}", key),
+                        e,
+                    )
+                })?;
+            if time > timestamp {
+                break;
+            }
+            found = Some((time, bytes));
+        }
+        found
+            .map(|(time, bytes)| {
+                Ok(FeatureRow {
+                    entity: entity.to_string(),
+                    timestamp: time,
+                    values: serde_json::from_slice(&bytes)?,
+                })
+            })
+            .transpose()
+    }
+
+    /// Latest values of `entity`, for online serving
+    pub async fn latest(&self, view: &FeatureView, entity: &str) -> AnyaResult<Option<FeatureRow>> {
+        self.as_of(view, entity, u64::MAX).await
+    }
+
+    /// Point-in-time join of `spine` against `view`
+    pub async fn training_set(
+        &self,
+        view: &FeatureView,
+        spine: &[SpineRow],
+    ) -> AnyaResult<Dataset> {
+        let mut rows = Vec::with_capacity(spine.len());
+        for row in spine {
+            rows.push(
+                self.as_of(view, &row.entity, row.timestamp)
+                    .await?
+                    .map(|r| r.values),
+            );
+        }
+        Ok(Dataset {
+            features: view.features.clone(),
+            rows,
+        })
+    }
+
+    /// Build a training set and save its manifest under `name`
+    pub async fn create_dataset(
+        &self,
+        name: &str,
+        view: &FeatureView,
+        spine: Vec<SpineRow>,
+    ) -> AnyaResult<(DatasetManifest, Dataset)> {
+        validate_id("dataset name", name)?;
+        let key = format!("{}{}", DATASET_PREFIX, name);
+        if self.storage.get(&self.ns, &key).await?.is_some() {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("dataset {} already exists", name),
+            ));
+        }
+        let dataset = self.training_set(view, &spine).await?;
+        let manifest = DatasetManifest {
+            name: name.to_string(),
+            view: view.name.clone(),
+            version: view.version,
+            spine,
+            fingerprint: dataset.fingerprint()?,
+        };
+        let _guard = self.write_lock.lock().await;
+        self.storage
+            .put(&self.ns, &key, &serde_json::to_vec(&manifest)?)
+            .await?;
+        Ok((manifest, dataset))
+    }
+
+    /// Rebuild a saved dataset, failing if the stored values changed since
+    pub async fn reproduce_dataset(&self, name: &str) -> AnyaResult<Dataset> {
+        validate_id("dataset name", name)?;
+        let bytes = self
+            .storage
+            .get(&self.ns, &format!("{}{}", DATASET_PREFIX, name))
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("dataset {}", name)))?;
+        let manifest: DatasetManifest = serde_json::from_slice(&bytes)?;
+        let view = self.view(&manifest.view, manifest.version).await?;
+        let dataset = self.training_set(&view, &manifest.spine).await?;
+        if dataset.fingerprint()? != manifest.fingerprint {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("dataset {} no longer matches its fingerprint", name),
+            ));
+        }
+        Ok(dataset)
+    }
+}
+
+fn value_prefix(view: &FeatureView, entity: &str) -> String {
+    format!(
+        "{}{}/{:08x}/{}/",
+        VALUE_PREFIX, view.name, view.version, entity
+    )
+}
+
+fn validate_id(what: &str, value: &str) -> AnyaResult<()> {
+    if value.is_empty() || value.contains('/') {
+        return Err(AnyaError::invalid_input(format!(
+            "{} must be non-empty and not contain '/'",
+            what
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    fn row(entity: &str, timestamp: u64, values: &[f64]) -> FeatureRow {
+        FeatureRow {
+            entity: entity.into(),
+            timestamp,
+            values: values.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_views_are_versioned_and_reads_are_point_in_time() {
+        let store = FeatureStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let v1 = store
+            .register_view("wallet", &["balance", "tx_count"])
+            .await
+            .unwrap();
+        assert_eq!(v1.version, 1);
+        assert_eq!(
+            store
+                .register_view("wallet", &["balance", "tx_count"])
+                .await
+                .unwrap(),
+            v1
+        );
+        let v2 = store.register_view("wallet", &["balance"]).await.unwrap();
+        assert_eq!(v2.version, 2);
+        assert!(store
+            .write(&v2, &row("alice", 10, &[1.0, 2.0]))
+            .await
+            .is_err());
+
+        store
+            .write(&v1, &row("alice", 100, &[5.0, 1.0]))
+            .await
+            .unwrap();
+        store
+            .write(&v1, &row("alice", 200, &[7.0, 2.0]))
+            .await
+            .unwrap();
+        assert!(store.as_of(&v1, "alice", 99).await.unwrap().is_none());
+        assert_eq!(
+            store
+                .as_of(&v1, "alice", 150)
+                .await
+                .unwrap()
+                .unwrap()
+                .values,
+            vec![5.0, 1.0]
+        );
+        assert_eq!(
+            store.latest(&v1, "alice").await.unwrap().unwrap().timestamp,
+            200
+        );
+        // Values of one version are not visible through another
+        assert!(store.latest(&v2, "alice").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_datasets_reproduce_until_values_change() {
+        let store = FeatureStore::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let view = store.register_view("market", &["volume"]).await.unwrap();
+        store.write(&view, &row("btc", 100, &[1.0])).await.unwrap();
+        store.write(&view, &row("btc", 300, &[3.0])).await.unwrap();
+        let spine = vec![
+            SpineRow {
+                entity: "btc".into(),
+                timestamp: 50,
+            },
+            SpineRow {
+                entity: "btc".into(),
+                timestamp: 250,
+            },
+        ];
+        let (manifest, dataset) = store
+            .create_dataset("train", &view, spine.clone())
+            .await
+            .unwrap();
+        assert_eq!(dataset.rows, vec![None, Some(vec![1.0])]);
+        assert_eq!(manifest.version, 1);
+        assert_eq!(
+            store
+                .create_dataset("train", &view, spine)
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::Conflict
+        );
+
+        // Later values do not leak into the saved rows
+        store.write(&view, &row("btc", 400, &[4.0])).await.unwrap();
+        assert_eq!(store.reproduce_dataset("train").await.unwrap(), dataset);
+        // Rewriting history does
+        store.write(&view, &row("btc", 200, &[2.0])).await.unwrap();
+        assert_eq!(
+            store.reproduce_dataset("train").await.unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+    }
+}
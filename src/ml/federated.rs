@@ -0,0 +1,553 @@
+//! Federated learning coordination
+//!
+//! Participants register with an optional Lightning address and start at a
+//! neutral reputation. Each round the [`FederatedCoordinator`] selects
+//! participants at random or weighted by reputation, then aggregates their
+//! updates by sample-weighted averaging (FedAvg).
+//!
+//! Contributions are scored on a held-out validation set: a participant's
+//! score is how much worse the aggregate does without their update. Scores
+//! move reputations and split the round's reward pool among the updates that
+//! helped, which [`RewardHook`]s then pay out, e.g. as Lightning payments
+//! through [`LightningRewards`].
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::mobile::lightning::LightningNode;
+use crate::mobile::lnurl::{LnurlClient, LnurlRequest};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "ml_federated";
+const PARTICIPANT_PREFIX: &str = "participant/";
+
+/// A registered participant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Participant {
+    /// Participant id, e.g. a DID
+    pub id: String,
+    /// Lightning address rewards are paid to
+    pub lightning_address: Option<String>,
+    /// Reputation in `[0, 1]`
+    pub reputation: f64,
+    /// Rounds contributed to
+    pub rounds: u32,
+    /// Rewards earned in total
+    pub earned_msat: u64,
+    /// Registration time, seconds since the Unix epoch
+    pub registered_at: u64,
+}
+
+/// How participants are picked for a round
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionStrategy {
+    /// Uniformly at random
+    Random,
+    /// At random, proportionally to reputation
+    ReputationWeighted,
+}
+
+/// Model weights trained locally by a participant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelUpdate {
+    /// Participant id
+    pub participant: String,
+    /// Updated weights
+    pub weights: Vec<f64>,
+    /// Local training samples behind them
+    pub samples: u64,
+}
+
+/// Loss of a model on held-out data
+pub trait Validator: Send + Sync {
+    /// Validation loss of `weights`; lower is better
+    fn loss(&self, weights: &[f64]) -> f64;
+}
+
+/// A participant's scored contribution to a round
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contribution {
+    /// Participant id
+    pub participant: String,
+    /// Validation loss increase when the update is left out
+    pub score: f64,
+    /// Share of the reward pool
+    pub reward_msat: u64,
+    /// Whether every reward hook succeeded
+    pub paid: bool,
+}
+
+/// Result of aggregating a round
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoundResult {
+    /// Aggregated weights
+    pub weights: Vec<f64>,
+    /// Validation loss of the aggregate
+    pub loss: f64,
+    /// Contributions, in update order
+    pub contributions: Vec<Contribution>,
+}
+
+/// Called for every earned reward
+#[async_trait]
+pub trait RewardHook: Send + Sync {
+    /// Reward `participant` with `amount_msat`
+    async fn reward(&self, participant: &Participant, amount_msat: u64) -> AnyaResult<()>;
+}
+
+/// Pays rewards to participants' Lightning addresses
+pub struct LightningRewards {
+    lnurl: LnurlClient,
+    node: Arc<dyn LightningNode>,
+    max_fee_msat: u64,
+}
+
+impl LightningRewards {
+    /// Pay through `node`, spending at most `max_fee_msat` on routing each
+    pub const fn new(lnurl: LnurlClient, node: Arc<dyn LightningNode>, max_fee_msat: u64) -> Self {
+        Self {
+            lnurl,
+            node,
+            max_fee_msat,
+        }
+    }
+}
+
+#[async_trait]
+impl RewardHook for LightningRewards {
+    async fn reward(&self, participant: &Participant, amount_msat: u64) -> AnyaResult<()> {
+        let address = participant.lightning_address.as_deref().ok_or_else(|| {
+            AnyaError::invalid_input(format!(
+                "participant {} has no Lightning address",
+                participant.id
+            ))
+        })?;
+        let LnurlRequest::Pay(pay) = self.lnurl.resolve(address).await? else {
+            return Err(AnyaError::invalid_input(format!(
+                "{} is not a payable Lightning address",
+                address
+            )));
+        };
+        let invoice = self
+            .lnurl
+            .request_invoice(&pay, amount_msat, Some("federated learning reward"))
+            .await?;
+        self.node
+            .pay_invoice(&invoice.invoice, self.max_fee_msat)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Federated learning settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedConfig {
+    /// Participants below this reputation are not selected
+    pub min_reputation: f64,
+    /// Weight of the latest round in the reputation average
+    pub reputation_rate: f64,
+    /// Rewards shared per round
+    pub reward_pool_msat: u64,
+}
+
+impl Default for FederatedConfig {
+    fn default() -> Self {
+        Self {
+            min_reputation: 0.1,
+            reputation_rate: 0.2,
+            reward_pool_msat: 100_000,
+        }
+    }
+}
+
+/// Participant registry, selection, aggregation, and rewards
+pub struct FederatedCoordinator {
+    config: FederatedConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    hooks: Vec<Arc<dyn RewardHook>>,
+    write_lock: Mutex<()>,
+}
+
+impl FederatedCoordinator {
+    /// Open the registry in `storage`, paying rewards through `hooks`
+    pub async fn open(
+        config: FederatedConfig,
+        storage: Arc<dyn StorageBackend>,
+        hooks: Vec<Arc<dyn RewardHook>>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            config,
+            storage,
+            ns,
+            hooks,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Register a participant, or update the Lightning address of a known one
+    pub async fn register(
+        &self,
+        id: &str,
+        lightning_address: Option<String>,
+    ) -> AnyaResult<Participant> {
+        if id.is_empty() {
+            return Err(AnyaError::invalid_input("participant id is empty"));
+        }
+        let _guard = self.write_lock.lock().await;
+        let participant = match self.participant(id).await? {
+            Some(existing) => Participant {
+                lightning_address,
+                ..existing
+            },
+            None => Participant {
+                id: id.to_string(),
+                lightning_address,
+                reputation: 0.5,
+                rounds: 0,
+                earned_msat: 0,
+                registered_at: unix_now(),
+            },
+        };
+        self.save(&participant).await?;
+        Ok(participant)
+    }
+
+    /// A registered participant
+    pub async fn participant(&self, id: &str) -> AnyaResult<Option<Participant>> {
+        self.storage
+            .get(&self.ns, &format!("{}{}", PARTICIPANT_PREFIX, id))
+            .await?
+            .map(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            .transpose()
+    }
+
+    /// All registered participants, by id
+    pub async fn participants(&self) -> AnyaResult<Vec<Participant>> {
+        self.storage
+            .scan_prefix(&self.ns, PARTICIPANT_PREFIX)
+            .await?
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    /// Pick up to `count` eligible participants for a round
+    pub async fn select(
+        &self,
+        count: usize,
+        strategy: SelectionStrategy,
+    ) -> AnyaResult<Vec<Participant>> {
+        let eligible: Vec<Participant> = self
+            .participants()
+            .await?
+            .into_iter()
+            .filter(|p| p.reputation >= self.config.min_reputation)
+            .collect();
+        let mut rng = rand::thread_rng();
+        // Weighted sampling without replacement: keep the largest u^(1/w)
+        let mut keyed: Vec<(f64, Participant)> = eligible
+            .into_iter()
+            .map(|p| {
+                let weight = match strategy {
+                    SelectionStrategy::Random => 1.0,
+                    SelectionStrategy::ReputationWeighted => p.reputation.max(f64::EPSILON),
+                };
+                (rng.gen::<f64>().powf(1.0 / weight), p)
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(keyed.into_iter().take(count).map(|(_, p)| p).collect())
+    }
+
+    /// Aggregate a round's updates on top of `current` weights, score and
+    /// reward each participant
+    pub async fn aggregate(
+        &self,
+        current: &[f64],
+        updates: &[ModelUpdate],
+        validator: &dyn Validator,
+    ) -> AnyaResult<RoundResult> {
+        let mut participants = Vec::with_capacity(updates.len());
+        for update in updates {
+            if update.weights.len() != current.len() || update.samples == 0 {
+                return Err(AnyaError::invalid_input(format!(
+                    "update from {} has {} weights and {} samples, expected {} weights",
+                    update.participant,
+                    update.weights.len(),
+                    update.samples,
+                    current.len()
+                )));
+            }
+            if updates
+                .iter()
+                .filter(|u| u.participant == update.participant)
+                .count()
+                > 1
+            {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!("several updates from {}", update.participant),
+                ));
+            }
+            let participant = self
+                .participant(&update.participant)
+                .await?
+                .ok_or_else(|| {
+                    AnyaError::not_found(format!("participant {}", update.participant))
+                })?;
+            participants.push(participant);
+        }
+        let all: Vec<&ModelUpdate> = updates.iter().collect();
+        let weights = fed_avg(&all).unwrap_or_else(|| current.to_vec());
+        let loss = validator.loss(&weights);
+        let scores: Vec<f64> = (0..updates.len())
+            .map(|i| {
+                let others: Vec<&ModelUpdate> = all
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, u)| *u)
+                    .collect();
+                let without = fed_avg(&others).unwrap_or_else(|| current.to_vec());
+                validator.loss(&without) - loss
+            })
+            .collect();
+        let rewards = split_rewards(&scores, self.config.reward_pool_msat);
+        let scale = scores.iter().fold(0.0f64, |m, s| m.max(s.abs()));
+
+        let mut contributions = Vec::with_capacity(updates.len());
+        for ((mut participant, score), reward_msat) in
+            participants.into_iter().zip(scores).zip(rewards)
+        {
+            // Helpful updates move reputation up, harmful ones down
+            let quality = if scale > 0.0 {
+                (score / scale).mul_add(0.5, 0.5)
+            } else {
+                0.5
+            };
+            let rate = self.config.reputation_rate.clamp(0.0, 1.0);
+            participant.reputation = rate
+                .mul_add(quality, (1.0 - rate) * participant.reputation)
+                .clamp(0.0, 1.0);
+            participant.rounds += 1;
+            let mut paid = true;
+            if reward_msat > 0 {
+                participant.earned_msat += reward_msat;
+                for hook in &self.hooks {
+                    if let Err(e) = hook.reward(&participant, reward_msat).await {
+                        tracing::warn!(participant = %participant.id, error = %e, "reward failed");
+                        paid = false;
+                    }
+                }
+            }
+            let _guard = self.write_lock.lock().await;
+            self.save(&participant).await?;
+            contributions.push(Contribution {
+                participant: participant.id,
+                score,
+                reward_msat,
+                paid,
+            });
+        }
+        Ok(RoundResult {
+            weights,
+            loss,
+            contributions,
+        })
+    }
+
+    async fn save(&self, participant: &Participant) -> AnyaResult<()> {
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", PARTICIPANT_PREFIX, participant.id),
+                &serde_json::to_vec(participant)?,
+            )
+            .await
+    }
+}
+
+/// Sample-weighted average of the updates' weights
+fn fed_avg(updates: &[&ModelUpdate]) -> Option<Vec<f64>> {
+    let total: u64 = updates.iter().map(|u| u.samples).sum();
+    let first = updates.first()?;
+    let mut weights = vec![0.0; first.weights.len()];
+    for update in updates {
+        let share = update.samples as f64 / total as f64;
+        for (w, u) in weights.iter_mut().zip(&update.weights) {
+            *w = share.mul_add(*u, *w);
+        }
+    }
+    Some(weights)
+}
+
+/// Split `pool` in proportion to the positive scores
+fn split_rewards(scores: &[f64], pool: u64) -> Vec<u64> {
+    let positive: f64 = scores.iter().filter(|s| **s > 0.0).sum();
+    scores
+        .iter()
+        .map(|s| {
+            if *s > 0.0 && positive > 0.0 {
+                (pool as f64 * s / positive).floor() as u64
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use std::sync::Mutex as StdMutex;
+
+    /// Squared distance to the true weights
+    struct Target(Vec<f64>);
+
+    impl Validator for Target {
+        fn loss(&self, weights: &[f64]) -> f64 {
+            weights
+                .iter()
+                .zip(&self.0)
+                .map(|(w, t)| (w - t).powi(2))
+                .sum()
+        }
+    }
+
+    #[derive(Default)]
+    struct Ledger(StdMutex<Vec<(String, u64)>>);
+
+    #[async_trait]
+    impl RewardHook for Ledger {
+        async fn reward(&self, participant: &Participant, amount_msat: u64) -> AnyaResult<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((participant.id.clone(), amount_msat));
+            Ok(())
+        }
+    }
+
+    fn update(participant: &str, weights: &[f64], samples: u64) -> ModelUpdate {
+        ModelUpdate {
+            participant: participant.into(),
+            weights: weights.to_vec(),
+            samples,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_scores_rewards_and_reputation() {
+        let ledger = Arc::new(Ledger::default());
+        let coordinator = FederatedCoordinator::open(
+            FederatedConfig::default(),
+            Arc::new(MemoryBackend::new()),
+            vec![ledger.clone()],
+        )
+        .await
+        .unwrap();
+        for id in ["did:key:alice", "did:key:bob", "did:key:mallory"] {
+            coordinator.register(id, None).await.unwrap();
+        }
+        let target = Target(vec![1.0, 1.0]);
+        let updates = [
+            update("did:key:alice", &[1.0, 1.1], 100),
+            update("did:key:bob", &[0.9, 1.0], 100),
+            update("did:key:mallory", &[-5.0, 8.0], 50),
+        ];
+        let result = coordinator
+            .aggregate(&[0.0, 0.0], &updates, &target)
+            .await
+            .unwrap();
+        let [alice, bob, mallory] = &result.contributions[..] else {
+            panic!("expected three contributions");
+        };
+        assert!(alice.score > 0.0 && bob.score > 0.0);
+        assert!(mallory.score < 0.0);
+        assert_eq!(mallory.reward_msat, 0);
+        assert!(alice.reward_msat + bob.reward_msat <= 100_000);
+        assert!(alice.reward_msat > 0 && bob.reward_msat > 0);
+        assert_eq!(ledger.0.lock().unwrap().len(), 2);
+
+        let mallory = coordinator
+            .participant("did:key:mallory")
+            .await
+            .unwrap()
+            .unwrap();
+        let alice = coordinator
+            .participant("did:key:alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(mallory.reputation < 0.5 && alice.reputation > 0.5);
+        assert_eq!(
+            (alice.rounds, alice.earned_msat),
+            (1, result.contributions[0].reward_msat)
+        );
+
+        assert!(coordinator
+            .aggregate(
+                &[0.0, 0.0],
+                &[update("did:key:eve", &[1.0, 1.0], 1)],
+                &target
+            )
+            .await
+            .is_err());
+        assert!(coordinator
+            .aggregate(&[0.0, 0.0], &[update("did:key:alice", &[1.0], 1)], &target)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_selection_respects_reputation() {
+        let coordinator = FederatedCoordinator::open(
+            FederatedConfig::default(),
+            Arc::new(MemoryBackend::new()),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+        for i in 0..5 {
+            coordinator
+                .register(&format!("p{}", i), None)
+                .await
+                .unwrap();
+        }
+        let mut banned = coordinator.participant("p0").await.unwrap().unwrap();
+        banned.reputation = 0.05;
+        coordinator.save(&banned).await.unwrap();
+        let mut trusted = coordinator.participant("p1").await.unwrap().unwrap();
+        trusted.reputation = 1.0;
+        coordinator.save(&trusted).await.unwrap();
+
+        let all = coordinator
+            .select(10, SelectionStrategy::Random)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 4);
+        assert!(all.iter().all(|p| p.id != "p0"));
+
+        let mut trusted_first = 0;
+        for _ in 0..200 {
+            let picked = coordinator
+                .select(1, SelectionStrategy::ReputationWeighted)
+                .await
+                .unwrap();
+            trusted_first += usize::from(picked[0].id == "p1");
+        }
+        // p1 carries 1.0 of 2.5 total weight
+        assert!(trusted_first > 50, "picked {} times", trusted_first);
+    }
+}
@@ -0,0 +1,504 @@
+//! Fee market analytics
+//!
+//! [`FeeMarket`] keeps a rolling window of mempool snapshots, each a fee
+//! rate histogram like the one mempool explorers publish, and derives:
+//! - fee rate percentiles over time, weighted by the vbytes paying each rate
+//! - a congestion forecast, extrapolating the recent mempool size trend and
+//!   expecting the fee level usual for each hour of the day
+//! - recommended send windows, the upcoming hours with the lowest expected
+//!   median fee rate
+//!
+//! [`FeeMarketEstimator`] feeds the latest snapshot into the wallet's
+//! [`FeeEstimator`] interface, projecting which fee rate reaches each
+//! confirmation target and falling back to another estimator when the
+//! snapshot is stale.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use ::bitcoin::FeeRate;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::bitcoin::fees::FeeEstimator;
+use crate::utils::time::unix_now;
+use crate::AnyaResult;
+
+/// Virtual size of a full block
+pub const BLOCK_VSIZE: u64 = 1_000_000;
+const HOUR: u64 = 3_600;
+
+/// Vbytes waiting in the mempool at one fee rate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FeeBucket {
+    /// Fee rate in sat/vB
+    pub fee_rate: f64,
+    /// Total vsize of transactions paying it
+    pub vsize: u64,
+}
+
+/// Fee rate histogram of the mempool at one point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MempoolSnapshot {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Histogram buckets, in any order
+    pub buckets: Vec<FeeBucket>,
+}
+
+impl MempoolSnapshot {
+    /// Total vsize waiting
+    pub fn total_vsize(&self) -> u64 {
+        self.buckets.iter().map(|b| b.vsize).sum()
+    }
+
+    /// Blocks needed to clear the mempool at the current size
+    pub fn blocks_to_clear(&self) -> f64 {
+        self.total_vsize() as f64 / BLOCK_VSIZE as f64
+    }
+
+    /// Fee rate percentiles, weighted by vsize
+    pub fn percentiles(&self) -> FeePercentiles {
+        let mut buckets = self.buckets.clone();
+        buckets.sort_by(|a, b| a.fee_rate.total_cmp(&b.fee_rate));
+        let total = self.total_vsize();
+        let at = |fraction: f64| {
+            let threshold = (total as f64 * fraction).ceil() as u64;
+            let mut seen = 0;
+            buckets
+                .iter()
+                .find(|b| {
+                    seen += b.vsize;
+                    seen >= threshold.max(1)
+                })
+                .map_or(0.0, |b| b.fee_rate)
+        };
+        FeePercentiles {
+            p10: at(0.10),
+            p25: at(0.25),
+            p50: at(0.50),
+            p75: at(0.75),
+            p90: at(0.90),
+        }
+    }
+
+    /// Lowest fee rate expected to be mined within `target_blocks`, assuming
+    /// blocks are filled from the highest fee rate down and no new
+    /// transactions arrive
+    pub fn fee_for_target(&self, target_blocks: u16, min_relay: f64) -> f64 {
+        let mut buckets = self.buckets.clone();
+        buckets.sort_by(|a, b| b.fee_rate.total_cmp(&a.fee_rate));
+        let space = u64::from(target_blocks.max(1)) * BLOCK_VSIZE;
+        let mut used = 0;
+        buckets
+            .iter()
+            .find(|b| {
+                used += b.vsize;
+                used > space
+            })
+            .map_or(min_relay, |b| b.fee_rate.max(min_relay))
+    }
+}
+
+/// Fee rate percentiles in sat/vB
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeePercentiles {
+    /// 10th percentile
+    pub p10: f64,
+    /// 25th percentile
+    pub p25: f64,
+    /// Median
+    pub p50: f64,
+    /// 75th percentile
+    pub p75: f64,
+    /// 90th percentile
+    pub p90: f64,
+}
+
+/// One point of the fee time series
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FeeSample {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Mempool size
+    pub mempool_vsize: u64,
+    /// Fee rate percentiles
+    pub percentiles: FeePercentiles,
+}
+
+/// Expected mempool state at a future time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CongestionForecast {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Projected mempool size
+    pub mempool_vsize: u64,
+    /// Projected blocks needed to clear the mempool
+    pub blocks_to_clear: f64,
+    /// Median fee rate usually seen at that hour of the day
+    pub expected_median_sat_vb: f64,
+}
+
+/// An hour recommended for sending
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SendWindow {
+    /// Start, seconds since the Unix epoch
+    pub start: u64,
+    /// End, seconds since the Unix epoch
+    pub end: u64,
+    /// Median fee rate usually seen in this hour
+    pub expected_median_sat_vb: f64,
+}
+
+/// Everything the analytics API reports about the fee market
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeMarketReport {
+    /// Report time
+    pub generated_at: u64,
+    /// Percentiles of the latest snapshot
+    pub current: Option<FeePercentiles>,
+    /// Blocks to clear the latest snapshot's mempool
+    pub blocks_to_clear: f64,
+    /// Fee percentile time series over the window
+    pub series: Vec<FeeSample>,
+    /// Hourly congestion forecast
+    pub forecast: Vec<CongestionForecast>,
+    /// Cheapest upcoming hours, in time order
+    pub windows: Vec<SendWindow>,
+}
+
+/// Fee market analysis settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeMarketConfig {
+    /// History kept for the time series and hour-of-day profile
+    pub window: Duration,
+    /// Recent history the mempool size trend is fitted to
+    pub trend_window: Duration,
+    /// Minimum relay fee rate in sat/vB
+    pub min_relay_sat_vb: f64,
+}
+
+impl Default for FeeMarketConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(7 * 24 * HOUR),
+            trend_window: Duration::from_secs(3 * HOUR),
+            min_relay_sat_vb: 1.0,
+        }
+    }
+}
+
+struct MarketState {
+    samples: VecDeque<FeeSample>,
+    latest: Option<MempoolSnapshot>,
+}
+
+/// Rolling fee market history and the analytics derived from it
+pub struct FeeMarket {
+    config: FeeMarketConfig,
+    state: Mutex<MarketState>,
+}
+
+impl FeeMarket {
+    /// Empty history
+    pub const fn new(config: FeeMarketConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(MarketState {
+                samples: VecDeque::new(),
+                latest: None,
+            }),
+        }
+    }
+
+    /// Add a snapshot, dropping history older than the window
+    pub fn record(&self, snapshot: MempoolSnapshot) {
+        let sample = FeeSample {
+            timestamp: snapshot.timestamp,
+            mempool_vsize: snapshot.total_vsize(),
+            percentiles: snapshot.percentiles(),
+        };
+        let cutoff = snapshot
+            .timestamp
+            .saturating_sub(self.config.window.as_secs());
+        let mut guard = self.state();
+        let state = &mut *guard;
+        let at = state
+            .samples
+            .partition_point(|s| s.timestamp <= sample.timestamp);
+        state.samples.insert(at, sample);
+        while state.samples.front().is_some_and(|s| s.timestamp < cutoff) {
+            state.samples.pop_front();
+        }
+        if state
+            .latest
+            .iter()
+            .all(|l| l.timestamp <= snapshot.timestamp)
+        {
+            state.latest = Some(snapshot);
+        }
+        drop(guard);
+    }
+
+    /// Most recent snapshot
+    pub fn latest(&self) -> Option<MempoolSnapshot> {
+        self.state().latest.clone()
+    }
+
+    /// Time series of samples taken at or after `since`
+    pub fn series(&self, since: u64) -> Vec<FeeSample> {
+        self.state()
+            .samples
+            .iter()
+            .filter(|s| s.timestamp >= since)
+            .copied()
+            .collect()
+    }
+
+    /// Congestion expected every hour from `now` up to `horizon`
+    pub fn forecast(&self, now: u64, horizon: Duration) -> Vec<CongestionForecast> {
+        let samples = self.series(0);
+        let Some(last) = samples.last() else {
+            return Vec::new();
+        };
+        let (intercept, slope) = trend(&samples, last.timestamp, self.config.trend_window);
+        let profile = hourly_profile(&samples);
+        (1..=horizon.as_secs() / HOUR)
+            .map(|h| {
+                let timestamp = now + h * HOUR;
+                let elapsed = timestamp.saturating_sub(last.timestamp) as f64;
+                let vsize = slope.mul_add(elapsed, intercept).max(0.0) as u64;
+                CongestionForecast {
+                    timestamp,
+                    mempool_vsize: vsize,
+                    blocks_to_clear: vsize as f64 / BLOCK_VSIZE as f64,
+                    expected_median_sat_vb: profile[hour_of_day(timestamp)]
+                        .unwrap_or(last.percentiles.p50),
+                }
+            })
+            .collect()
+    }
+
+    /// The `count` upcoming hours within `horizon` with the lowest usual
+    /// median fee rate, in time order. Hours never observed are skipped.
+    pub fn send_windows(&self, now: u64, horizon: Duration, count: usize) -> Vec<SendWindow> {
+        let profile = hourly_profile(&self.series(0));
+        let first = now / HOUR * HOUR;
+        let mut windows: Vec<SendWindow> = (0..horizon.as_secs().div_euclid(HOUR).max(1))
+            .filter_map(|h| {
+                let start = first + h * HOUR;
+                profile[hour_of_day(start)].map(|median| SendWindow {
+                    start: start.max(now),
+                    end: start + HOUR,
+                    expected_median_sat_vb: median,
+                })
+            })
+            .collect();
+        windows.sort_by(|a, b| {
+            a.expected_median_sat_vb
+                .total_cmp(&b.expected_median_sat_vb)
+                .then(a.start.cmp(&b.start))
+        });
+        windows.truncate(count);
+        windows.sort_by_key(|w| w.start);
+        windows
+    }
+
+    /// Full report for the analytics API
+    pub fn report(&self, now: u64, horizon: Duration, windows: usize) -> FeeMarketReport {
+        let latest = self.latest();
+        FeeMarketReport {
+            generated_at: now,
+            current: latest.as_ref().map(MempoolSnapshot::percentiles),
+            blocks_to_clear: latest
+                .as_ref()
+                .map_or(0.0, MempoolSnapshot::blocks_to_clear),
+            series: self.series(now.saturating_sub(self.config.window.as_secs())),
+            forecast: self.forecast(now, horizon),
+            windows: self.send_windows(now, horizon, windows),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MarketState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Least-squares line of mempool size against seconds since `origin`,
+/// fitted to the samples within `window` of it
+fn trend(samples: &[FeeSample], origin: u64, window: Duration) -> (f64, f64) {
+    let recent: Vec<(f64, f64)> = samples
+        .iter()
+        .filter(|s| s.timestamp + window.as_secs() >= origin)
+        .map(|s| (s.timestamp as f64 - origin as f64, s.mempool_vsize as f64))
+        .collect();
+    let n = recent.len() as f64;
+    let mean_x = recent.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = recent.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = recent
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = recent.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let slope = if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    };
+    (slope.mul_add(-mean_x, mean_y), slope)
+}
+
+/// Mean median fee rate per UTC hour of the day
+fn hourly_profile(samples: &[FeeSample]) -> [Option<f64>; 24] {
+    let mut sums = [(0.0, 0u32); 24];
+    for sample in samples {
+        let slot = &mut sums[hour_of_day(sample.timestamp)];
+        slot.0 += sample.percentiles.p50;
+        slot.1 += 1;
+    }
+    sums.map(|(sum, count)| (count > 0).then(|| sum / f64::from(count)))
+}
+
+const fn hour_of_day(timestamp: u64) -> usize {
+    (timestamp / HOUR % 24) as usize
+}
+
+/// [`FeeEstimator`] projecting confirmation targets from the latest
+/// mempool snapshot
+pub struct FeeMarketEstimator {
+    market: Arc<FeeMarket>,
+    fallback: Arc<dyn FeeEstimator>,
+    max_age: Duration,
+}
+
+impl FeeMarketEstimator {
+    /// Estimate from `market`, using `fallback` when its latest snapshot is
+    /// older than `max_age`
+    pub fn new(market: Arc<FeeMarket>, fallback: Arc<dyn FeeEstimator>, max_age: Duration) -> Self {
+        Self {
+            market,
+            fallback,
+            max_age,
+        }
+    }
+}
+
+#[async_trait]
+impl FeeEstimator for FeeMarketEstimator {
+    async fn estimate(&self, target_blocks: u16) -> AnyaResult<FeeRate> {
+        let now = unix_now();
+        let fresh = self
+            .market
+            .latest()
+            .filter(|s| s.timestamp + self.max_age.as_secs() >= now);
+        match fresh {
+            Some(snapshot) => {
+                let rate =
+                    snapshot.fee_for_target(target_blocks, self.market.config.min_relay_sat_vb);
+                // 1 sat/vB is 250 sat per 1000 weight units
+                Ok(FeeRate::from_sat_per_kwu((rate * 250.0).ceil() as u64))
+            }
+            None => self.fallback.estimate(target_blocks).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(timestamp: u64, scale: u64) -> MempoolSnapshot {
+        MempoolSnapshot {
+            timestamp,
+            buckets: vec![
+                FeeBucket {
+                    fee_rate: 50.0,
+                    vsize: 200_000 * scale,
+                },
+                FeeBucket {
+                    fee_rate: 2.0,
+                    vsize: 600_000 * scale,
+                },
+                FeeBucket {
+                    fee_rate: 10.0,
+                    vsize: 200_000 * scale,
+                },
+            ],
+        }
+    }
+
+    struct Fixed;
+
+    #[async_trait]
+    impl FeeEstimator for Fixed {
+        async fn estimate(&self, _target_blocks: u16) -> AnyaResult<FeeRate> {
+            Ok(FeeRate::from_sat_per_vb_unchecked(7))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_statistics_and_estimator() {
+        let snap = snapshot(0, 1);
+        let p = snap.percentiles();
+        assert_eq!((p.p10, p.p50, p.p75, p.p90), (2.0, 2.0, 10.0, 50.0));
+        assert!((snap.blocks_to_clear() - 1.0).abs() < 1e-9);
+        // 3 MvB waiting: the first block takes 50 and 10 sat/vB, then 2 sat/vB
+        let busy = snapshot(0, 3);
+        assert_eq!(busy.fee_for_target(1, 1.0), 10.0);
+        assert_eq!(busy.fee_for_target(2, 1.0), 2.0);
+        assert_eq!(busy.fee_for_target(6, 1.0), 1.0);
+
+        let market = Arc::new(FeeMarket::new(FeeMarketConfig::default()));
+        let estimator =
+            FeeMarketEstimator::new(market.clone(), Arc::new(Fixed), Duration::from_secs(600));
+        assert_eq!(
+            estimator.estimate(1).await.unwrap(),
+            FeeRate::from_sat_per_vb_unchecked(7)
+        );
+        market.record(snapshot(unix_now(), 3));
+        assert_eq!(
+            estimator.estimate(1).await.unwrap(),
+            FeeRate::from_sat_per_vb_unchecked(10)
+        );
+    }
+
+    #[test]
+    fn test_forecast_and_send_windows() {
+        let market = FeeMarket::new(FeeMarketConfig::default());
+        let day = 24 * HOUR;
+        // Two days of hourly samples: the mempool is busy from 12:00 to
+        // 18:00 UTC, and grows by 100 kvB an hour over the last three hours
+        for h in 0..48 {
+            let scale = if (12..18).contains(&(h % 24)) { 4 } else { 1 };
+            let mut snap = snapshot(h * HOUR, scale);
+            if h >= 45 {
+                snap.buckets.push(FeeBucket {
+                    fee_rate: 1.0,
+                    vsize: (h - 44) * 100_000,
+                });
+            }
+            market.record(snap);
+        }
+        let now = 2 * day - HOUR;
+        let forecast = market.forecast(now, Duration::from_secs(3 * HOUR));
+        assert_eq!(forecast.len(), 3);
+        assert_eq!(forecast[0].timestamp, 2 * day);
+        assert!(forecast[0].mempool_vsize > 1_300_000);
+        assert!(forecast[2].mempool_vsize > forecast[0].mempool_vsize);
+
+        // The cheapest hours tomorrow avoid the busy afternoon
+        let windows = market.send_windows(2 * day, Duration::from_secs(day), 4);
+        assert_eq!(windows.len(), 4);
+        assert!(windows.windows(2).all(|w| w[0].start < w[1].start));
+        assert!(windows
+            .iter()
+            .all(|w| !(12..18).contains(&hour_of_day(w.start))));
+
+        let report = market.report(now, Duration::from_secs(6 * HOUR), 2);
+        assert_eq!(report.series.len(), 48);
+        assert_eq!(report.forecast.len(), 6);
+        assert_eq!(report.windows.len(), 2);
+        assert!(report.current.is_some());
+    }
+}
@@ -0,0 +1,587 @@
+//! Hyperparameter optimization
+//!
+//! A study searches a [`SearchSpace`] by running training trials through a
+//! [`ModelTrainer`]. Trials are suggested either at random or, once enough
+//! have finished, by a tree-structured Parzen estimator: finished trials are
+//! split into the best quarter and the rest, and of a batch of random
+//! candidates the one most likely under the good trials relative to the rest
+//! is tried next.
+//!
+//! Every finished trial is persisted in the `ml_hpo` namespace, so a study
+//! resumes where it stopped and its history counts against the
+//! [`HpoBudget`], which caps trials, concurrency, and total training cost.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::stream::{FuturesUnordered, StreamExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "ml_hpo";
+
+/// Range of one hyperparameter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Param {
+    /// Float drawn uniformly from `[low, high]`
+    Uniform {
+        /// Lower bound
+        low: f64,
+        /// Upper bound
+        high: f64,
+    },
+    /// Positive float drawn uniformly in log space
+    LogUniform {
+        /// Lower bound
+        low: f64,
+        /// Upper bound
+        high: f64,
+    },
+    /// Integer in `[low, high]`
+    Integer {
+        /// Lower bound
+        low: i64,
+        /// Upper bound
+        high: i64,
+    },
+    /// One of a set of labels
+    Choice {
+        /// Options
+        options: Vec<String>,
+    },
+}
+
+/// Value assigned to a hyperparameter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    /// Integer value
+    Int(i64),
+    /// Float value
+    Float(f64),
+    /// Choice label
+    Choice(String),
+}
+
+impl ParamValue {
+    /// Numeric value, if any
+    pub const fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(v) => Some(*v as f64),
+            Self::Float(v) => Some(*v),
+            Self::Choice(_) => None,
+        }
+    }
+}
+
+/// Hyperparameter assignment of one trial
+pub type Assignment = BTreeMap<String, ParamValue>;
+
+/// Named hyperparameter ranges
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchSpace {
+    params: BTreeMap<String, Param>,
+}
+
+impl SearchSpace {
+    /// Empty space
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a hyperparameter
+    pub fn param(mut self, name: impl Into<String>, param: Param) -> AnyaResult<Self> {
+        let valid = match &param {
+            Param::Uniform { low, high } => low < high,
+            Param::LogUniform { low, high } => *low > 0.0 && low < high,
+            Param::Integer { low, high } => low <= high,
+            Param::Choice { options } => !options.is_empty(),
+        };
+        if !valid {
+            return Err(AnyaError::invalid_input(format!(
+                "empty range for {:?}",
+                param
+            )));
+        }
+        self.params.insert(name.into(), param);
+        Ok(self)
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> Assignment {
+        self.params
+            .iter()
+            .map(|(name, param)| {
+                let value = match param {
+                    Param::Uniform { low, high } => ParamValue::Float(rng.gen_range(*low..=*high)),
+                    Param::LogUniform { low, high } => {
+                        ParamValue::Float(rng.gen_range(low.ln()..=high.ln()).exp())
+                    }
+                    Param::Integer { low, high } => ParamValue::Int(rng.gen_range(*low..=*high)),
+                    Param::Choice { options } => {
+                        ParamValue::Choice(options[rng.gen_range(0..options.len())].clone())
+                    }
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Distance between two assignments, each dimension scaled to `[0, 1]`
+    /// and choices counting 0 or 1
+    fn distance(&self, a: &Assignment, b: &Assignment) -> f64 {
+        self.params
+            .iter()
+            .map(|(name, param)| {
+                let d = match (param, a.get(name), b.get(name)) {
+                    (Param::Choice { .. }, Some(x), Some(y)) => f64::from(u8::from(x != y)),
+                    (Param::Uniform { low, high }, Some(x), Some(y)) => {
+                        scaled(x, y, *low, *high, |v| v)
+                    }
+                    (Param::LogUniform { low, high }, Some(x), Some(y)) => {
+                        scaled(x, y, *low, *high, f64::ln)
+                    }
+                    (Param::Integer { low, high }, Some(x), Some(y)) => {
+                        scaled(x, y, *low as f64, *high as f64, |v| v)
+                    }
+                    _ => 1.0,
+                };
+                d * d
+            })
+            .sum::<f64>()
+            .sqrt()
+    }
+}
+
+fn scaled(x: &ParamValue, y: &ParamValue, low: f64, high: f64, f: fn(f64) -> f64) -> f64 {
+    let span = f(high) - f(low);
+    match (x.as_f64(), y.as_f64()) {
+        (Some(x), Some(y)) if span > 0.0 => ((f(x) - f(y)) / span).abs(),
+        (Some(_), Some(_)) => 0.0,
+        _ => 1.0,
+    }
+}
+
+/// A trial to train
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trial {
+    /// Study name
+    pub study: String,
+    /// Trial number within the study
+    pub number: u32,
+    /// Hyperparameters to train with
+    pub params: Assignment,
+}
+
+/// Outcome of training one trial
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrialOutcome {
+    /// Objective value, e.g. validation loss
+    pub objective: f64,
+    /// Resources used, in the budget's unit (e.g. GPU-seconds)
+    pub cost: f64,
+}
+
+/// Trains a model for a trial
+#[async_trait]
+pub trait ModelTrainer: Send + Sync {
+    /// Train with `trial.params` and report the objective
+    async fn train(&self, trial: &Trial) -> AnyaResult<TrialOutcome>;
+}
+
+/// Whether lower or higher objectives are better
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Lower is better, e.g. loss
+    Minimize,
+    /// Higher is better, e.g. accuracy
+    Maximize,
+}
+
+/// How trials are suggested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SearchStrategy {
+    /// Independent random samples
+    Random,
+    /// Tree-structured Parzen estimator after `startup` random trials
+    Bayesian {
+        /// Random trials before modelling
+        startup: usize,
+        /// Random candidates ranked per suggestion
+        candidates: usize,
+    },
+}
+
+/// Resource limits of a study
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HpoBudget {
+    /// Trials in total, including earlier runs
+    pub max_trials: u32,
+    /// Trials trained at the same time
+    pub max_concurrent: usize,
+    /// Total cost after which no new trial starts
+    pub max_cost: f64,
+}
+
+impl Default for HpoBudget {
+    fn default() -> Self {
+        Self {
+            max_trials: 50,
+            max_concurrent: 2,
+            max_cost: f64::INFINITY,
+        }
+    }
+}
+
+/// State of a recorded trial
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrialState {
+    /// Trained successfully
+    Complete,
+    /// Training failed
+    Failed,
+}
+
+/// A persisted trial
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrialRecord {
+    /// Trial number
+    pub number: u32,
+    /// Hyperparameters
+    pub params: Assignment,
+    /// Outcome
+    pub state: TrialState,
+    /// Objective, when complete
+    pub objective: Option<f64>,
+    /// Cost charged against the budget
+    pub cost: f64,
+    /// Failure message, when failed
+    pub error: Option<String>,
+}
+
+/// Study settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StudyConfig {
+    /// Study name; trials are persisted under it
+    pub name: String,
+    /// Optimization direction
+    pub direction: Direction,
+    /// Suggestion strategy
+    pub strategy: SearchStrategy,
+    /// Resource limits
+    pub budget: HpoBudget,
+    /// Random seed
+    pub seed: u64,
+}
+
+/// Trials of a study, best first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StudySummary {
+    /// Best complete trial
+    pub best: Option<TrialRecord>,
+    /// All trials by number
+    pub trials: Vec<TrialRecord>,
+    /// Total cost so far
+    pub cost: f64,
+}
+
+/// Runs studies and persists their trials
+pub struct HpoService {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+}
+
+impl HpoService {
+    /// Open the service over `storage`
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self { storage, ns })
+    }
+
+    /// Recorded trials of `study`, by number
+    pub async fn trials(&self, study: &str) -> AnyaResult<Vec<TrialRecord>> {
+        self.storage
+            .scan_prefix(&self.ns, &format!("{}/", study))
+            .await?
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    /// Run `config`'s study until its budget is used up
+    pub async fn run(
+        &self,
+        config: &StudyConfig,
+        space: &SearchSpace,
+        trainer: Arc<dyn ModelTrainer>,
+    ) -> AnyaResult<StudySummary> {
+        if config.name.is_empty() || config.name.contains('/') {
+            return Err(AnyaError::invalid_input(
+                "study name must be non-empty and not contain '/'",
+            ));
+        }
+        let mut trials = self.trials(&config.name).await?;
+        let mut rng = StdRng::seed_from_u64(config.seed ^ trials.len() as u64);
+        let mut cost: f64 = trials.iter().map(|t| t.cost).sum();
+        let mut next = trials.iter().map(|t| t.number + 1).max().unwrap_or(0);
+        let mut running = FuturesUnordered::new();
+        loop {
+            while running.len() < config.budget.max_concurrent.max(1)
+                && next < config.budget.max_trials
+                && cost < config.budget.max_cost
+            {
+                let trial = Trial {
+                    study: config.name.clone(),
+                    number: next,
+                    params: suggest(config, space, &trials, &mut rng),
+                };
+                next += 1;
+                let trainer = Arc::clone(&trainer);
+                running.push(async move {
+                    let outcome = trainer.train(&trial).await;
+                    (trial, outcome)
+                });
+            }
+            let Some((trial, outcome)) = running.next().await else {
+                break;
+            };
+            let record = match outcome {
+                Ok(outcome) => TrialRecord {
+                    number: trial.number,
+                    params: trial.params,
+                    state: TrialState::Complete,
+                    objective: Some(outcome.objective),
+                    cost: outcome.cost,
+                    error: None,
+                },
+                Err(e) => TrialRecord {
+                    number: trial.number,
+                    params: trial.params,
+                    state: TrialState::Failed,
+                    objective: None,
+                    cost: 0.0,
+                    error: Some(e.to_string()),
+                },
+            };
+            cost += record.cost;
+            self.storage
+                .put(
+                    &self.ns,
+                    &format!("{}/{:08x}", config.name, record.number),
+                    &serde_json::to_vec(&record)?,
+                )
+                .await?;
+            trials.push(record);
+        }
+        trials.sort_by_key(|t| t.number);
+        let best = ranked(&trials, config.direction)
+            .first()
+            .map(|t| (*t).clone());
+        Ok(StudySummary { best, trials, cost })
+    }
+}
+
+/// Complete trials, best first
+fn ranked(trials: &[TrialRecord], direction: Direction) -> Vec<&TrialRecord> {
+    let mut complete: Vec<&TrialRecord> = trials.iter().filter(|t| t.objective.is_some()).collect();
+    complete.sort_by(|a, b| {
+        let (a, b) = (
+            a.objective.unwrap_or_default(),
+            b.objective.unwrap_or_default(),
+        );
+        match direction {
+            Direction::Minimize => a.total_cmp(&b),
+            Direction::Maximize => b.total_cmp(&a),
+        }
+    });
+    complete
+}
+
+fn suggest(
+    config: &StudyConfig,
+    space: &SearchSpace,
+    trials: &[TrialRecord],
+    rng: &mut StdRng,
+) -> Assignment {
+    let SearchStrategy::Bayesian {
+        startup,
+        candidates,
+    } = config.strategy
+    else {
+        return space.sample(rng);
+    };
+    let ranked = ranked(trials, config.direction);
+    if ranked.len() < startup.max(4) {
+        return space.sample(rng);
+    }
+    let split = (ranked.len() / 4).max(1);
+    let (good, bad) = ranked.split_at(split);
+    let density = |x: &Assignment, group: &[&TrialRecord]| {
+        // Gaussian kernels in the unit-scaled space
+        const BANDWIDTH: f64 = 0.2;
+        group
+            .iter()
+            .map(|t| (-(space.distance(x, &t.params) / BANDWIDTH).powi(2) / 2.0).exp())
+            .sum::<f64>()
+            / group.len() as f64
+            + f64::EPSILON
+    };
+    (0..candidates.max(1))
+        .map(|_| space.sample(rng))
+        .map(|x| {
+            let ratio = density(&x, good) / density(&x, bad);
+            (ratio, x)
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, x)| x)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use crate::ErrorCode;
+    use std::sync::Mutex;
+
+    /// Loss minimized at learning_rate 0.01 with the "adam" optimizer
+    struct Quadratic {
+        concurrent: Mutex<(usize, usize)>,
+    }
+
+    #[async_trait]
+    impl ModelTrainer for Quadratic {
+        async fn train(&self, trial: &Trial) -> AnyaResult<TrialOutcome> {
+            {
+                let mut c = self.concurrent.lock().unwrap();
+                c.0 += 1;
+                c.1 = c.1.max(c.0);
+            }
+            tokio::task::yield_now().await;
+            self.concurrent.lock().unwrap().0 -= 1;
+            let lr = trial.params["learning_rate"].as_f64().unwrap();
+            if lr > 0.5 {
+                return Err(AnyaError::new(ErrorCode::MLFailure, "diverged"));
+            }
+            let penalty = match &trial.params["optimizer"] {
+                ParamValue::Choice(o) if o == "adam" => 0.0,
+                _ => 1.0,
+            };
+            let distance = lr.log10() + 2.0;
+            Ok(TrialOutcome {
+                objective: distance.mul_add(distance, penalty),
+                cost: 1.0,
+            })
+        }
+    }
+
+    fn space() -> SearchSpace {
+        SearchSpace::new()
+            .param(
+                "learning_rate",
+                Param::LogUniform {
+                    low: 1e-5,
+                    high: 1.0,
+                },
+            )
+            .unwrap()
+            .param(
+                "optimizer",
+                Param::Choice {
+                    options: vec!["sgd".into(), "adam".into(), "rmsprop".into()],
+                },
+            )
+            .unwrap()
+    }
+
+    fn trainer() -> Arc<Quadratic> {
+        Arc::new(Quadratic {
+            concurrent: Mutex::new((0, 0)),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_bayesian_search_beats_random_start() {
+        let service = HpoService::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let config = StudyConfig {
+            name: "fee-model".into(),
+            direction: Direction::Minimize,
+            strategy: SearchStrategy::Bayesian {
+                startup: 10,
+                candidates: 32,
+            },
+            budget: HpoBudget {
+                max_trials: 40,
+                max_concurrent: 3,
+                max_cost: f64::INFINITY,
+            },
+            seed: 5,
+        };
+        let trainer = trainer();
+        let summary = service
+            .run(&config, &space(), trainer.clone())
+            .await
+            .unwrap();
+        assert_eq!(summary.trials.len(), 40);
+        assert!(trainer.concurrent.lock().unwrap().1 <= 3);
+        let best = summary.best.unwrap();
+        assert_eq!(best.params["optimizer"], ParamValue::Choice("adam".into()));
+        assert!(best.objective.unwrap() < 0.1, "{:?}", best);
+        // Modelled suggestions do better than the random start
+        let mean = |trials: &[TrialRecord]| {
+            let done: Vec<f64> = trials.iter().filter_map(|t| t.objective).collect();
+            done.iter().sum::<f64>() / done.len() as f64
+        };
+        assert!(mean(&summary.trials[25..]) < mean(&summary.trials[..10]));
+    }
+
+    #[tokio::test]
+    async fn test_budget_and_resume() {
+        let service = HpoService::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let mut config = StudyConfig {
+            name: "fraud".into(),
+            direction: Direction::Minimize,
+            strategy: SearchStrategy::Random,
+            budget: HpoBudget {
+                max_trials: 100,
+                max_concurrent: 1,
+                max_cost: 5.0,
+            },
+            seed: 1,
+        };
+        let first = service.run(&config, &space(), trainer()).await.unwrap();
+        // Failed trials cost nothing, so the cost budget stops at 5 complete
+        let complete = first
+            .trials
+            .iter()
+            .filter(|t| t.state == TrialState::Complete)
+            .count();
+        assert_eq!((complete, first.cost), (5, 5.0));
+
+        config.budget.max_cost = 8.0;
+        let resumed = service.run(&config, &space(), trainer()).await.unwrap();
+        assert_eq!(resumed.cost, 8.0);
+        let numbers = |trials: &[TrialRecord]| trials.iter().map(|t| t.number).collect::<Vec<_>>();
+        assert_eq!(
+            numbers(&resumed.trials[..first.trials.len()]),
+            numbers(&first.trials)
+        );
+        assert_eq!(
+            numbers(&service.trials("fraud").await.unwrap()),
+            numbers(&resumed.trials)
+        );
+    }
+}
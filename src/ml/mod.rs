@@ -0,0 +1,33 @@
+//! Machine learning components and AI agent system
+
+use serde::{Deserialize, Serialize};
+
+pub mod anomaly;
+pub mod explain;
+pub mod feature_store;
+pub mod federated;
+pub mod fee_market;
+pub mod hpo;
+pub mod scheduler;
+pub mod shadow;
+
+use self::anomaly::AnomalyConfig;
+
+/// Configuration for the ML subsystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MLConfig {
+    /// Whether the ML subsystem is enabled
+    pub enabled: bool,
+    /// Anomaly detection settings
+    #[serde(default)]
+    pub anomaly: AnomalyConfig,
+}
+
+impl Default for MLConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            anomaly: AnomalyConfig::default(),
+        }
+    }
+}
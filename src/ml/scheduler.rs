@@ -0,0 +1,420 @@
+//! Agent scheduling
+//!
+//! Instead of running every agent on every tick, the [`AgentScheduler`]
+//! spends a fixed time budget per tick and decides who gets it:
+//!
+//! 1. starving agents, which have been passed over for
+//!    [`SchedulerConfig::starvation_ticks`] ticks in a row,
+//! 2. agents whose deadline (the longest they may go without running) has
+//!    passed, earliest deadline first,
+//! 3. everybody else by priority, and within a priority by fair share: the
+//!    agent with the least run time relative to its share goes first.
+//!
+//! The first agent picked always runs; the rest run while budget remains.
+//! Per-agent [`AgentMetrics`] record run time, failures, deadline misses,
+//! and starvation.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::lifecycle::run_loop;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// A unit of periodic work
+#[async_trait]
+pub trait Agent: Send + Sync {
+    /// Unique name used in metrics and logs
+    fn name(&self) -> &str;
+
+    /// Do one step of work
+    async fn step(&self) -> AnyaResult<()>;
+}
+
+/// How an agent is scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentSpec {
+    /// Higher runs first
+    pub priority: u32,
+    /// Longest the agent may go without running
+    pub deadline: Option<Duration>,
+    /// Relative share of run time among agents of the same priority
+    pub share: u32,
+}
+
+impl Default for AgentSpec {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            deadline: None,
+            share: 1,
+        }
+    }
+}
+
+/// Scheduler settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerConfig {
+    /// Run time spent per tick
+    pub tick_budget: Duration,
+    /// Consecutive skipped ticks after which an agent counts as starving
+    pub starvation_ticks: u32,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            tick_budget: Duration::from_millis(200),
+            starvation_ticks: 10,
+        }
+    }
+}
+
+/// Scheduling metrics of one agent
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentMetrics {
+    /// Steps run
+    pub runs: u64,
+    /// Steps that returned an error
+    pub failures: u64,
+    /// Total time spent in steps
+    pub busy: Duration,
+    /// Longest single step
+    pub longest_step: Duration,
+    /// Steps started after the deadline had passed
+    pub deadline_misses: u64,
+    /// Ticks skipped since the last run
+    pub skipped_ticks: u32,
+    /// Times the agent was found starving
+    pub starvations: u64,
+}
+
+/// Outcome of one tick
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TickReport {
+    /// Agents run, in order
+    pub ran: Vec<String>,
+    /// Agents skipped for lack of budget
+    pub skipped: Vec<String>,
+    /// Time spent
+    pub elapsed: Duration,
+}
+
+struct Entry {
+    agent: Arc<dyn Agent>,
+    spec: AgentSpec,
+    last_run: Option<Instant>,
+    registered: Instant,
+    /// Run time divided by share, in seconds
+    vruntime: f64,
+    metrics: AgentMetrics,
+}
+
+impl Entry {
+    fn due(&self) -> Option<Instant> {
+        self.spec
+            .deadline
+            .map(|d| self.last_run.unwrap_or(self.registered) + d)
+    }
+}
+
+/// Priority, deadline, and fair-share scheduler for agents
+pub struct AgentScheduler {
+    config: SchedulerConfig,
+    entries: Mutex<Vec<Entry>>,
+    tick_lock: tokio::sync::Mutex<()>,
+}
+
+impl AgentScheduler {
+    /// Create an empty scheduler
+    pub const fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(Vec::new()),
+            tick_lock: tokio::sync::Mutex::const_new(()),
+        }
+    }
+
+    /// Add an agent
+    pub fn register(&self, agent: Arc<dyn Agent>, spec: AgentSpec) -> AnyaResult<()> {
+        if spec.share == 0 {
+            return Err(AnyaError::invalid_input(format!(
+                "agent {} needs a non-zero share",
+                agent.name()
+            )));
+        }
+        let mut entries = self.entries();
+        if entries.iter().any(|e| e.agent.name() == agent.name()) {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("agent {} is already registered", agent.name()),
+            ));
+        }
+        // Start at the least-served agent so a newcomer cannot monopolize
+        // the budget while it catches up
+        let vruntime = entries
+            .iter()
+            .map(|e| e.vruntime)
+            .min_by(f64::total_cmp)
+            .unwrap_or_default();
+        entries.push(Entry {
+            agent,
+            spec,
+            last_run: None,
+            registered: Instant::now(),
+            vruntime,
+            metrics: AgentMetrics::default(),
+        });
+        drop(entries);
+        Ok(())
+    }
+
+    /// Metrics of every agent, by name
+    pub fn metrics(&self) -> HashMap<String, AgentMetrics> {
+        self.entries()
+            .iter()
+            .map(|e| (e.agent.name().to_string(), e.metrics.clone()))
+            .collect()
+    }
+
+    /// Run one tick
+    pub async fn tick(&self) -> TickReport {
+        let _guard = self.tick_lock.lock().await;
+        let start = Instant::now();
+        let order = self.plan(start);
+
+        let mut ran = Vec::new();
+        let mut skipped = Vec::new();
+        for (agent, due) in order {
+            let name = agent.name().to_string();
+            if !ran.is_empty() && start.elapsed() >= self.config.tick_budget {
+                skipped.push(name);
+                continue;
+            }
+            let began = Instant::now();
+            let result = agent.step().await;
+            let took = began.elapsed();
+            if let Err(e) = &result {
+                tracing::warn!(agent = %name, error = %e, "agent step failed");
+            }
+
+            let mut entries = self.entries();
+            if let Some(entry) = entries.iter_mut().find(|e| e.agent.name() == name) {
+                entry.last_run = Some(began);
+                entry.vruntime += took.as_secs_f64() / f64::from(entry.spec.share);
+                let metrics = &mut entry.metrics;
+                metrics.runs += 1;
+                metrics.failures += u64::from(result.is_err());
+                metrics.busy += took;
+                metrics.longest_step = metrics.longest_step.max(took);
+                metrics.deadline_misses += u64::from(due.is_some_and(|d| began > d));
+                metrics.skipped_ticks = 0;
+            }
+            drop(entries);
+            ran.push(name);
+        }
+
+        let mut entries = self.entries();
+        for entry in entries
+            .iter_mut()
+            .filter(|e| skipped.contains(&e.agent.name().to_string()))
+        {
+            entry.metrics.skipped_ticks += 1;
+            if entry.metrics.skipped_ticks == self.config.starvation_ticks {
+                entry.metrics.starvations += 1;
+                tracing::warn!(
+                    agent = %entry.agent.name(),
+                    skipped = entry.metrics.skipped_ticks,
+                    "agent is starving"
+                );
+            }
+        }
+        drop(entries);
+        TickReport {
+            ran,
+            skipped,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Tick every `interval` until `token` is cancelled
+    pub async fn run(&self, token: CancellationToken, interval: Duration) -> AnyaResult<()> {
+        run_loop(token, interval, || async {
+            self.tick().await;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Agents in the order they get the budget, with their deadlines
+    fn plan(&self, now: Instant) -> Vec<(Arc<dyn Agent>, Option<Instant>)> {
+        let entries = self.entries();
+        let mut order: Vec<&Entry> = entries.iter().collect();
+        let class = |e: &Entry| {
+            if e.metrics.skipped_ticks >= self.config.starvation_ticks {
+                0
+            } else if e.due().is_some_and(|d| d <= now) {
+                1
+            } else {
+                2
+            }
+        };
+        order.sort_by(|a, b| {
+            class(a).cmp(&class(b)).then_with(|| match class(a) {
+                // Longest-starved first
+                0 => b.metrics.skipped_ticks.cmp(&a.metrics.skipped_ticks),
+                // Earliest deadline first
+                1 => a.due().cmp(&b.due()),
+                _ => b
+                    .spec
+                    .priority
+                    .cmp(&a.spec.priority)
+                    .then_with(|| a.vruntime.total_cmp(&b.vruntime)),
+            })
+        });
+        let plan = order
+            .into_iter()
+            .map(|e| (Arc::clone(&e.agent), e.due()))
+            .collect();
+        drop(entries);
+        plan
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Vec<Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Takes `cost` per step
+    struct Worker {
+        name: &'static str,
+        cost: Duration,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl Agent for Worker {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn step(&self) -> AnyaResult<()> {
+            tokio::time::sleep(self.cost).await;
+            if self.fail {
+                return Err(AnyaError::new(ErrorCode::MLFailure, "step failed"));
+            }
+            Ok(())
+        }
+    }
+
+    fn worker(name: &'static str, cost_ms: u64) -> Arc<Worker> {
+        Arc::new(Worker {
+            name,
+            cost: Duration::from_millis(cost_ms),
+            fail: false,
+        })
+    }
+
+    fn spec(priority: u32, share: u32) -> AgentSpec {
+        AgentSpec {
+            priority,
+            share,
+            ..AgentSpec::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_priority_then_fair_share() {
+        let scheduler = AgentScheduler::new(SchedulerConfig {
+            tick_budget: Duration::from_millis(1),
+            starvation_ticks: 100,
+        });
+        scheduler.register(worker("risk", 3), spec(10, 1)).unwrap();
+        scheduler
+            .register(worker("analytics", 3), spec(1, 3))
+            .unwrap();
+        scheduler
+            .register(worker("reports", 3), spec(1, 1))
+            .unwrap();
+        assert!(scheduler.register(worker("risk", 1), spec(0, 1)).is_err());
+        assert!(scheduler.register(worker("idle", 1), spec(0, 0)).is_err());
+
+        // Only one agent fits the budget per tick, and it is always the
+        // high-priority one
+        for _ in 0..3 {
+            assert_eq!(scheduler.tick().await.ran, vec!["risk"]);
+        }
+        let metrics = scheduler.metrics();
+        assert_eq!(metrics["risk"].runs, 3);
+        assert_eq!(metrics["reports"].skipped_ticks, 3);
+
+        // Among equal priorities, the larger share runs about three times
+        // as often
+        let lower = AgentScheduler::new(SchedulerConfig {
+            tick_budget: Duration::from_millis(1),
+            starvation_ticks: 100,
+        });
+        lower.register(worker("analytics", 3), spec(1, 3)).unwrap();
+        lower.register(worker("reports", 3), spec(1, 1)).unwrap();
+        for _ in 0..12 {
+            lower.tick().await;
+        }
+        let metrics = lower.metrics();
+        assert!(
+            metrics["analytics"].runs >= 7 && metrics["reports"].runs >= 2,
+            "{:?}",
+            metrics
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deadlines_and_starvation() {
+        let scheduler = AgentScheduler::new(SchedulerConfig {
+            tick_budget: Duration::from_millis(1),
+            starvation_ticks: 3,
+        });
+        scheduler.register(worker("hog", 2), spec(10, 1)).unwrap();
+        scheduler
+            .register(
+                worker("heartbeat", 1),
+                AgentSpec {
+                    deadline: Some(Duration::from_millis(5)),
+                    ..AgentSpec::default()
+                },
+            )
+            .unwrap();
+        scheduler
+            .register(
+                Arc::new(Worker {
+                    name: "flaky",
+                    cost: Duration::from_millis(1),
+                    fail: true,
+                }),
+                AgentSpec::default(),
+            )
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(6)).await;
+        // The heartbeat's deadline has passed, so it jumps the queue
+        assert_eq!(scheduler.tick().await.ran, vec!["heartbeat"]);
+        assert_eq!(scheduler.metrics()["heartbeat"].deadline_misses, 1);
+
+        let mut flaky_ran = false;
+        for _ in 0..5 {
+            flaky_ran |= scheduler.tick().await.ran.contains(&"flaky".to_string());
+        }
+        let metrics = scheduler.metrics();
+        assert!(flaky_ran);
+        assert_eq!(metrics["flaky"].starvations, 1);
+        assert_eq!(metrics["flaky"].failures, metrics["flaky"].runs);
+        assert!(metrics["hog"].busy >= Duration::from_millis(4));
+    }
+}
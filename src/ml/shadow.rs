@@ -0,0 +1,476 @@
+//! Shadow deployment of candidate models
+//!
+//! A [`ShadowDeployment`] serves every request from the production model
+//! while running the candidate on the same input. Only the production score
+//! is returned; the candidate's score, latency, and failures are tallied in
+//! the current evaluation window. Once the real outcome of a request is
+//! known, [`ShadowDeployment::record_outcome`] scores both models against it.
+//!
+//! [`ShadowDeployment::evaluate`] checks the window against the
+//! [`PromotionPolicy`] thresholds: a candidate that fails too often,
+//! disagrees too much, or errs more than production is rolled back, and one
+//! that beats production over enough outcomes is promoted.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{AnyaError, AnyaResult};
+
+/// A served model version
+#[async_trait]
+pub trait Predictor: Send + Sync {
+    /// Version label
+    fn version(&self) -> &str;
+
+    /// Score for `features`
+    async fn predict(&self, features: &[f64]) -> AnyaResult<f64>;
+}
+
+/// Thresholds a candidate must meet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromotionPolicy {
+    /// Shadowed requests before any decision
+    pub min_samples: u64,
+    /// Requests with known outcomes before promotion
+    pub min_outcomes: u64,
+    /// Scores within this distance count as agreeing
+    pub agreement_tolerance: f64,
+    /// Disagreement rate above which the candidate is rolled back
+    pub max_disagreement: f64,
+    /// Candidate failure rate above which it is rolled back
+    pub max_failure_rate: f64,
+    /// Candidate mean absolute error over production's at or below which it
+    /// is promoted; above 1.0 it is rolled back
+    pub promote_error_ratio: f64,
+    /// Candidate latency over production's above which it is rolled back
+    pub max_latency_ratio: f64,
+    /// Requests awaiting an outcome that are remembered
+    pub max_pending: usize,
+}
+
+impl Default for PromotionPolicy {
+    fn default() -> Self {
+        Self {
+            min_samples: 1_000,
+            min_outcomes: 200,
+            agreement_tolerance: 0.05,
+            max_disagreement: 0.2,
+            max_failure_rate: 0.01,
+            promote_error_ratio: 0.95,
+            max_latency_ratio: 2.0,
+            max_pending: 10_000,
+        }
+    }
+}
+
+/// Result of serving one request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServedPrediction {
+    /// Id to report the outcome under
+    pub id: u64,
+    /// Production score
+    pub value: f64,
+}
+
+/// Comparison of the models over the current window
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShadowStats {
+    /// Requests served
+    pub samples: u64,
+    /// Requests the candidate failed
+    pub candidate_failures: u64,
+    /// Requests where the scores differed by more than the tolerance
+    pub disagreements: u64,
+    /// Mean absolute difference between the scores
+    pub mean_difference: f64,
+    /// Requests with a known outcome
+    pub outcomes: u64,
+    /// Production mean absolute error against outcomes
+    pub production_error: f64,
+    /// Candidate mean absolute error against outcomes
+    pub candidate_error: f64,
+    /// Mean production latency
+    pub production_latency: Duration,
+    /// Mean candidate latency
+    pub candidate_latency: Duration,
+}
+
+/// What [`ShadowDeployment::evaluate`] did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum ShadowDecision {
+    /// No candidate is deployed
+    Idle,
+    /// Not enough evidence yet
+    Continue,
+    /// The candidate replaced production
+    Promoted {
+        /// New production version
+        version: String,
+    },
+    /// The candidate was removed
+    RolledBack {
+        /// Removed version
+        version: String,
+        /// Threshold it failed
+        reason: String,
+    },
+}
+
+#[derive(Default)]
+struct Window {
+    stats: ShadowStats,
+    difference_sum: f64,
+    production_error_sum: f64,
+    candidate_error_sum: f64,
+    production_latency_sum: Duration,
+    candidate_latency_sum: Duration,
+    candidate_successes: u64,
+    pending: HashMap<u64, (f64, f64)>,
+    pending_order: VecDeque<u64>,
+}
+
+struct Models {
+    production: Arc<dyn Predictor>,
+    candidate: Option<Arc<dyn Predictor>>,
+}
+
+/// Production model with an optional shadowed candidate
+pub struct ShadowDeployment {
+    policy: PromotionPolicy,
+    models: Mutex<Models>,
+    window: Mutex<Window>,
+    next_id: Mutex<u64>,
+}
+
+impl ShadowDeployment {
+    /// Serve `production` with no candidate
+    pub fn new(policy: PromotionPolicy, production: Arc<dyn Predictor>) -> Self {
+        Self {
+            policy,
+            models: Mutex::new(Models {
+                production,
+                candidate: None,
+            }),
+            window: Mutex::new(Window::default()),
+            next_id: Mutex::new(0),
+        }
+    }
+
+    /// Production version
+    pub fn production_version(&self) -> String {
+        self.models().production.version().to_string()
+    }
+
+    /// Candidate version, if one is shadowed
+    pub fn candidate_version(&self) -> Option<String> {
+        self.models()
+            .candidate
+            .as_ref()
+            .map(|c| c.version().to_string())
+    }
+
+    /// Start shadowing `candidate`, resetting the window
+    pub fn deploy_candidate(&self, candidate: Arc<dyn Predictor>) -> AnyaResult<()> {
+        let mut models = self.models();
+        if candidate.version() == models.production.version() {
+            return Err(AnyaError::invalid_input(format!(
+                "version {} is already in production",
+                candidate.version()
+            )));
+        }
+        models.candidate = Some(candidate);
+        drop(models);
+        *self.window() = Window::default();
+        Ok(())
+    }
+
+    /// Serve a request from production, shadowing it on the candidate
+    pub async fn predict(&self, features: &[f64]) -> AnyaResult<ServedPrediction> {
+        let (production, candidate) = {
+            let models = self.models();
+            (models.production.clone(), models.candidate.clone())
+        };
+        let Some(candidate) = candidate else {
+            let value = production.predict(features).await?;
+            return Ok(ServedPrediction {
+                id: self.take_id(),
+                value,
+            });
+        };
+        let (served, shadow) = tokio::join!(
+            timed(production.as_ref(), features),
+            timed(candidate.as_ref(), features)
+        );
+        let (value, production_latency) = served;
+        let value = value?;
+        let id = self.take_id();
+        self.tally(id, value, production_latency, shadow);
+        Ok(ServedPrediction { id, value })
+    }
+
+    /// Report the real outcome of request `id`. Returns whether the request
+    /// was shadowed and still remembered.
+    pub fn record_outcome(&self, id: u64, actual: f64) -> bool {
+        let mut window = self.window();
+        let Some((production, candidate)) = window.pending.remove(&id) else {
+            return false;
+        };
+        window.production_error_sum += (production - actual).abs();
+        window.candidate_error_sum += (candidate - actual).abs();
+        window.stats.outcomes += 1;
+        let n = window.stats.outcomes as f64;
+        window.stats.production_error = window.production_error_sum / n;
+        window.stats.candidate_error = window.candidate_error_sum / n;
+        drop(window);
+        true
+    }
+
+    /// Statistics of the current window
+    pub fn stats(&self) -> ShadowStats {
+        self.window().stats.clone()
+    }
+
+    /// Check the window against the policy, promoting or rolling back the
+    /// candidate when a threshold is crossed
+    pub fn evaluate(&self) -> ShadowDecision {
+        let Some(version) = self.candidate_version() else {
+            return ShadowDecision::Idle;
+        };
+        let stats = self.stats();
+        let decision = self.decide(&stats);
+        let mut models = self.models();
+        // Only act if the candidate was not swapped while deciding
+        if models.candidate.as_ref().map(|c| c.version()) != Some(version.as_str()) {
+            return ShadowDecision::Continue;
+        }
+        let decision = match decision {
+            Ok(true) => {
+                if let Some(candidate) = models.candidate.take() {
+                    models.production = candidate;
+                }
+                ShadowDecision::Promoted { version }
+            }
+            Ok(false) => return ShadowDecision::Continue,
+            Err(reason) => {
+                models.candidate = None;
+                ShadowDecision::RolledBack { version, reason }
+            }
+        };
+        drop(models);
+        *self.window() = Window::default();
+        decision
+    }
+
+    /// `Ok(true)` to promote, `Ok(false)` to wait, `Err` to roll back
+    fn decide(&self, stats: &ShadowStats) -> Result<bool, String> {
+        let policy = &self.policy;
+        if stats.samples < policy.min_samples.max(1) {
+            return Ok(false);
+        }
+        let samples = stats.samples as f64;
+        let failure_rate = stats.candidate_failures as f64 / samples;
+        if failure_rate > policy.max_failure_rate {
+            return Err(format!("failure rate {:.3} over threshold", failure_rate));
+        }
+        let disagreement = stats.disagreements as f64 / samples;
+        if disagreement > policy.max_disagreement {
+            return Err(format!("disagreement {:.3} over threshold", disagreement));
+        }
+        let production_latency = stats.production_latency.as_secs_f64();
+        if production_latency > 0.0
+            && stats.candidate_latency.as_secs_f64() / production_latency > policy.max_latency_ratio
+        {
+            return Err("latency over threshold".to_string());
+        }
+        if stats.outcomes < policy.min_outcomes.max(1) {
+            return Ok(false);
+        }
+        if stats.production_error <= 0.0 {
+            return if stats.candidate_error <= 0.0 {
+                Ok(false)
+            } else {
+                Err("production is exact and the candidate is not".to_string())
+            };
+        }
+        let ratio = stats.candidate_error / stats.production_error;
+        if ratio > 1.0 {
+            Err(format!("error ratio {:.3} worse than production", ratio))
+        } else {
+            Ok(ratio <= policy.promote_error_ratio)
+        }
+    }
+
+    fn tally(
+        &self,
+        id: u64,
+        value: f64,
+        production_latency: Duration,
+        shadow: (AnyaResult<f64>, Duration),
+    ) {
+        let mut guard = self.window();
+        let window = &mut *guard;
+        window.stats.samples += 1;
+        window.production_latency_sum += production_latency;
+        window.stats.production_latency =
+            window.production_latency_sum / u32::try_from(window.stats.samples).unwrap_or(u32::MAX);
+        match shadow {
+            (Ok(candidate), latency) => {
+                window.candidate_successes += 1;
+                window.candidate_latency_sum += latency;
+                window.stats.candidate_latency = window.candidate_latency_sum
+                    / u32::try_from(window.candidate_successes).unwrap_or(u32::MAX);
+                let difference = (candidate - value).abs();
+                window.difference_sum += difference;
+                window.stats.mean_difference =
+                    window.difference_sum / window.candidate_successes as f64;
+                if difference > self.policy.agreement_tolerance {
+                    window.stats.disagreements += 1;
+                }
+                window.pending.insert(id, (value, candidate));
+                window.pending_order.push_back(id);
+                while window.pending_order.len() > self.policy.max_pending {
+                    if let Some(old) = window.pending_order.pop_front() {
+                        window.pending.remove(&old);
+                    }
+                }
+            }
+            (Err(_), _) => window.stats.candidate_failures += 1,
+        }
+        drop(guard);
+    }
+
+    fn take_id(&self) -> u64 {
+        let mut next = self.next_id.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = *next;
+        *next += 1;
+        id
+    }
+
+    fn models(&self) -> MutexGuard<'_, Models> {
+        self.models.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn window(&self) -> MutexGuard<'_, Window> {
+        self.window.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+async fn timed(model: &dyn Predictor, features: &[f64]) -> (AnyaResult<f64>, Duration) {
+    let started = Instant::now();
+    let result = model.predict(features).await;
+    (result, started.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorCode;
+
+    struct Scaled {
+        version: &'static str,
+        factor: f64,
+        fail_every: u64,
+        calls: Mutex<u64>,
+    }
+
+    impl Scaled {
+        fn new(version: &'static str, factor: f64, fail_every: u64) -> Arc<Self> {
+            Arc::new(Self {
+                version,
+                factor,
+                fail_every,
+                calls: Mutex::new(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Predictor for Scaled {
+        fn version(&self) -> &str {
+            self.version
+        }
+
+        async fn predict(&self, features: &[f64]) -> AnyaResult<f64> {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+            if calls.checked_rem(self.fail_every) == Some(0) {
+                return Err(AnyaError::new(ErrorCode::MLFailure, "model crashed"));
+            }
+            drop(calls);
+            Ok(features[0] * self.factor)
+        }
+    }
+
+    fn policy() -> PromotionPolicy {
+        PromotionPolicy {
+            min_samples: 50,
+            min_outcomes: 20,
+            agreement_tolerance: 0.5,
+            max_disagreement: 0.5,
+            max_latency_ratio: f64::INFINITY,
+            ..PromotionPolicy::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_better_candidate_is_promoted() {
+        let shadow = ShadowDeployment::new(policy(), Scaled::new("v1", 1.2, 0));
+        assert_eq!(shadow.evaluate(), ShadowDecision::Idle);
+        assert!(shadow.deploy_candidate(Scaled::new("v1", 1.0, 0)).is_err());
+        shadow.deploy_candidate(Scaled::new("v2", 1.02, 0)).unwrap();
+        for i in 0..60 {
+            let x = 1.0 + f64::from(i) / 100.0;
+            let served = shadow.predict(&[x]).await.unwrap();
+            // Production's score is what callers see
+            assert!((served.value - x * 1.2).abs() < 1e-9);
+            if i % 2 == 0 {
+                assert!(shadow.record_outcome(served.id, x));
+            }
+            if i == 10 {
+                assert_eq!(shadow.evaluate(), ShadowDecision::Continue);
+            }
+        }
+        let stats = shadow.stats();
+        assert_eq!((stats.samples, stats.outcomes), (60, 30));
+        assert!(stats.candidate_error < stats.production_error);
+        assert_eq!(
+            shadow.evaluate(),
+            ShadowDecision::Promoted {
+                version: "v2".into()
+            }
+        );
+        assert_eq!(shadow.production_version(), "v2");
+        assert_eq!(shadow.candidate_version(), None);
+        assert_eq!(shadow.stats().samples, 0);
+    }
+
+    #[tokio::test]
+    async fn test_failing_or_worse_candidate_is_rolled_back() {
+        let shadow = ShadowDeployment::new(policy(), Scaled::new("v1", 1.0, 0));
+        shadow.deploy_candidate(Scaled::new("v2", 1.0, 10)).unwrap();
+        for _ in 0..50 {
+            shadow.predict(&[1.0]).await.unwrap();
+        }
+        assert_eq!(shadow.stats().candidate_failures, 5);
+        assert!(matches!(
+            shadow.evaluate(),
+            ShadowDecision::RolledBack { ref version, .. } if version == "v2"
+        ));
+        assert_eq!(shadow.production_version(), "v1");
+
+        shadow.deploy_candidate(Scaled::new("v3", 1.3, 0)).unwrap();
+        for _ in 0..50 {
+            let served = shadow.predict(&[1.0]).await.unwrap();
+            shadow.record_outcome(served.id, 1.0);
+        }
+        let ShadowDecision::RolledBack { reason, .. } = shadow.evaluate() else {
+            panic!("expected rollback");
+        };
+        assert!(reason.contains("production is exact"));
+    }
+}
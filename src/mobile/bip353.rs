@@ -0,0 +1,321 @@
+//! Human-readable payment instructions (BIP-353)
+//!
+//! A name like `₿alice@example.com` maps to a TXT record at
+//! `alice.user._bitcoin-payment.example.com` holding a BIP-21 URI, which may
+//! carry an on-chain address, a BOLT-11 invoice, a BOLT-12 offer (`lno`), or
+//! a silent payment address (`sp`). Resolution is only trusted when the
+//! answer was DNSSEC-validated; [`DnsResolver`] implementations report
+//! whether it was. [`DohResolver`] (feature `http`) queries a validating
+//! DNS-over-HTTPS resolver.
+//!
+//! [`txt_record`] produces the zone entry that publishes a name.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::qr::PaymentUri;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Longest character-string in a TXT record
+const TXT_CHUNK: usize = 255;
+
+/// `user@domain` payment name
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HumanReadableName {
+    /// User part, lowercased
+    pub user: String,
+    /// Domain part, lowercased and without a trailing dot
+    pub domain: String,
+}
+
+impl HumanReadableName {
+    /// Parse `₿user@domain` or `user@domain`
+    pub fn parse(name: &str) -> AnyaResult<Self> {
+        let name = name.trim();
+        let name = name.strip_prefix('₿').unwrap_or(name);
+        let invalid = || AnyaError::invalid_input(format!("invalid payment name {:?}", name));
+        let (user, domain) = name.split_once('@').ok_or_else(invalid)?;
+        let domain = domain.strip_suffix('.').unwrap_or(domain);
+        let labels_ok = |s: &str| {
+            !s.is_empty()
+                && s.split('.').all(|label| {
+                    !label.is_empty()
+                        && label.len() <= 63
+                        && label
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                })
+        };
+        if !labels_ok(user) || !labels_ok(domain) || !domain.contains('.') {
+            return Err(invalid());
+        }
+        let parsed = Self {
+            user: user.to_ascii_lowercase(),
+            domain: domain.to_ascii_lowercase(),
+        };
+        if parsed.dns_name().len() > 253 {
+            return Err(invalid());
+        }
+        Ok(parsed)
+    }
+
+    /// Fully qualified name of the TXT record
+    pub fn dns_name(&self) -> String {
+        format!("{}.user._bitcoin-payment.{}.", self.user, self.domain)
+    }
+}
+
+impl fmt::Display for HumanReadableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "₿{}@{}", self.user, self.domain)
+    }
+}
+
+/// TXT records returned for a name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxtLookup {
+    /// Each record's character-strings, in order
+    pub records: Vec<Vec<String>>,
+    /// Whether the resolver validated the answer with DNSSEC
+    pub authenticated: bool,
+}
+
+/// Resolves TXT records
+#[async_trait]
+pub trait DnsResolver: Send + Sync {
+    /// TXT records at `name`; an empty lookup when the name does not exist
+    async fn txt(&self, name: &str) -> AnyaResult<TxtLookup>;
+}
+
+/// Resolve `name` to its payment instructions.
+///
+/// Fails with [`ErrorCode::Unauthenticated`] when the answer is not
+/// DNSSEC-validated and [`ErrorCode::NotFound`] when there is no single
+/// `bitcoin:` record, as BIP-353 requires.
+pub async fn resolve(
+    resolver: &dyn DnsResolver,
+    name: &HumanReadableName,
+) -> AnyaResult<PaymentUri> {
+    let lookup = resolver.txt(&name.dns_name()).await?;
+    if !lookup.authenticated {
+        return Err(AnyaError::new(
+            ErrorCode::Unauthenticated,
+            format!("payment instructions for {} are not DNSSEC-signed", name),
+        ));
+    }
+    let mut uris = lookup
+        .records
+        .iter()
+        .map(|strings| strings.concat())
+        .filter(|txt| {
+            txt.get(..8)
+                .is_some_and(|s| s.eq_ignore_ascii_case("bitcoin:"))
+        });
+    match (uris.next(), uris.next()) {
+        (Some(uri), None) => PaymentUri::parse(&uri),
+        (None, _) => Err(AnyaError::not_found(format!(
+            "no payment instructions for {}",
+            name
+        ))),
+        (Some(_), Some(_)) => Err(AnyaError::invalid_input(format!(
+            "{} has more than one payment instruction record",
+            name
+        ))),
+    }
+}
+
+/// Zone file entry publishing `uri` under `name`
+pub fn txt_record(name: &HumanReadableName, uri: &PaymentUri, ttl: u32) -> String {
+    let text = uri.to_uri();
+    let strings: Vec<String> = text
+        .as_bytes()
+        .chunks(TXT_CHUNK)
+        .map(|chunk| format!("\"{}\"", String::from_utf8_lossy(chunk)))
+        .collect();
+    format!("{} {} IN TXT {}", name.dns_name(), ttl, strings.join(" "))
+}
+
+/// Split TXT RDATA in presentation form (`"a" "b"`) into its strings
+pub fn parse_txt_data(data: &str) -> AnyaResult<Vec<String>> {
+    let mut strings = Vec::new();
+    let mut chars = data.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut current = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => current.push(
+                            chars
+                                .next()
+                                .ok_or_else(|| AnyaError::invalid_input("dangling escape"))?,
+                        ),
+                        Some(c) => current.push(c),
+                        None => return Err(AnyaError::invalid_input("unterminated TXT string")),
+                    }
+                }
+                strings.push(current);
+            }
+            c if c.is_whitespace() => {}
+            _ => return Err(AnyaError::invalid_input("TXT strings must be quoted")),
+        }
+    }
+    Ok(strings)
+}
+
+/// Resolver using a DNSSEC-validating DNS-over-HTTPS JSON endpoint
+#[cfg(feature = "http")]
+pub struct DohResolver {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[cfg(feature = "http")]
+impl DohResolver {
+    /// Resolver querying `url`, e.g. `https://dns.google/resolve`
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "AD", default)]
+    authenticated: bool,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    kind: u16,
+    data: String,
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl DnsResolver for DohResolver {
+    async fn txt(&self, name: &str) -> AnyaResult<TxtLookup> {
+        /// RCODE for a name that does not exist
+        const NXDOMAIN: u32 = 3;
+        /// TXT record type
+        const TXT: u16 = 16;
+        let response: DohResponse = self
+            .client
+            .get(&self.url)
+            .header(reqwest::header::ACCEPT, "application/dns-json")
+            .query(&[("name", name), ("type", "TXT"), ("do", "1")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        match response.status {
+            0 | NXDOMAIN => Ok(TxtLookup {
+                records: response
+                    .answer
+                    .iter()
+                    .filter(|a| a.kind == TXT)
+                    .map(|a| parse_txt_data(&a.data))
+                    .collect::<AnyaResult<_>>()?,
+                authenticated: response.authenticated,
+            }),
+            rcode => Err(AnyaError::new(
+                ErrorCode::NetworkFailure,
+                format!("DNS lookup of {} failed with RCODE {}", name, rcode),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const SP: &str = "sp1qqweplq6ylpfrzuq6hfznzmv28djsraupudz0s0dclyt8erh70pgwxqkz2ydatksrdzf770umsntsmcjp4kcz7jqu03jeszh0gdmpjzmrf5u4zh0c";
+
+    struct StaticResolver {
+        records: HashMap<String, Vec<Vec<String>>>,
+        authenticated: bool,
+    }
+
+    #[async_trait]
+    impl DnsResolver for StaticResolver {
+        async fn txt(&self, name: &str) -> AnyaResult<TxtLookup> {
+            Ok(TxtLookup {
+                records: self.records.get(name).cloned().unwrap_or_default(),
+                authenticated: self.authenticated,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_requires_dnssec_and_one_uri() {
+        let name = HumanReadableName::parse("₿Alice@Example.com.").unwrap();
+        assert_eq!(name.to_string(), "₿alice@example.com");
+        assert_eq!(name.dns_name(), "alice.user._bitcoin-payment.example.com.");
+        assert!(HumanReadableName::parse("alice@localhost").is_err());
+        assert!(HumanReadableName::parse("al ice@example.com").is_err());
+
+        let uri = format!("bitcoin:?sp={}", SP);
+        let (head, tail) = uri.split_at(40);
+        let mut resolver = StaticResolver {
+            records: HashMap::from([(
+                name.dns_name(),
+                vec![
+                    vec!["v=spf1 -all".to_string()],
+                    vec![head.to_string(), tail.to_string()],
+                ],
+            )]),
+            authenticated: false,
+        };
+        let err = resolve(&resolver, &name).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+
+        resolver.authenticated = true;
+        let resolved = resolve(&resolver, &name).await.unwrap();
+        assert_eq!(resolved.silent_payment(), Some(SP));
+
+        let missing = HumanReadableName::parse("bob@example.com").unwrap();
+        let err = resolve(&resolver, &missing).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        resolver
+            .records
+            .get_mut(&name.dns_name())
+            .unwrap()
+            .push(vec![uri]);
+        assert!(resolve(&resolver, &name).await.is_err());
+    }
+
+    #[test]
+    fn test_published_record_round_trips() {
+        let name = HumanReadableName::parse("alice@example.com").unwrap();
+        let mut uri = PaymentUri::new("");
+        uri.extras.push(("sp".into(), SP.into()));
+        uri.extras.push(("lno".into(), "lno1".repeat(80)));
+        let record = txt_record(&name, &uri, 3600);
+
+        let prefix = format!("{} 3600 IN TXT ", name.dns_name());
+        let data = record.strip_prefix(&prefix).unwrap();
+        let strings = parse_txt_data(data).unwrap();
+        assert!(strings.len() > 1 && strings.iter().all(|s| s.len() <= TXT_CHUNK));
+        assert_eq!(PaymentUri::parse(&strings.concat()).unwrap(), uri);
+        assert_eq!(
+            parse_txt_data(r#""a\"b" "c""#).unwrap(),
+            ["a\"b".to_string(), "c".to_string()]
+        );
+        assert!(parse_txt_data("unquoted").is_err());
+    }
+}
@@ -0,0 +1,451 @@
+//! Local transaction history for the mobile wallet
+//!
+//! Transactions discovered by sync are persisted in the `mobile_history`
+//! storage namespace so the history screen works offline. Each record keeps
+//! the fiat value at the time of the transaction, looked up once from the
+//! [`PriceOracle`] and stored alongside the record; records saved while
+//! offline are valued later by [`TransactionHistory::backfill_fiat`].
+
+use std::sync::Arc;
+
+use ::bitcoin::Txid;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::pagination::Cursor;
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "mobile_history";
+const KEY_PREFIX: &str = "tx/";
+
+/// Source of historical BTC prices
+#[async_trait]
+pub trait PriceOracle: Send + Sync {
+    /// Price of one bitcoin in `currency` at the given Unix time
+    async fn price_at(&self, currency: &str, timestamp: u64) -> AnyaResult<f64>;
+}
+
+/// Direction of a wallet transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxDirection {
+    /// Funds received from outside the wallet
+    Incoming,
+    /// Funds sent out of the wallet
+    Outgoing,
+    /// Inputs and outputs all belong to the wallet
+    SelfTransfer,
+}
+
+/// User-facing category label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxCategory {
+    /// Paying for goods or services
+    Payment,
+    /// Salary, sales, and other income
+    Income,
+    /// Moving funds between own wallets
+    Transfer,
+    /// Buying or selling on an exchange
+    Exchange,
+    /// Anything else
+    Other,
+}
+
+/// Fiat valuation of a transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FiatValue {
+    /// ISO 4217 currency code
+    pub currency: String,
+    /// Price of one bitcoin when the transaction happened
+    pub price: f64,
+    /// Value of the transaction amount at that price
+    pub value: f64,
+}
+
+/// A persisted wallet transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletTx {
+    /// Transaction id
+    pub txid: Txid,
+    /// Direction relative to the wallet
+    pub direction: TxDirection,
+    /// Net effect on the wallet balance in satoshis
+    pub amount_sat: i64,
+    /// Fee paid, when the wallet funded the transaction
+    pub fee_sat: Option<u64>,
+    /// Confirming block height, `None` while unconfirmed
+    pub block_height: Option<u32>,
+    /// Block time, or first-seen time while unconfirmed (Unix seconds)
+    pub timestamp: u64,
+    /// Category chosen by the user
+    pub category: Option<TxCategory>,
+    /// Free-form label
+    pub label: Option<String>,
+    /// Fiat value at `timestamp`, if known
+    pub fiat: Option<FiatValue>,
+}
+
+/// A transaction together with its current confirmation count
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// The stored transaction
+    #[serde(flatten)]
+    pub tx: WalletTx,
+    /// Confirmations at the current tip, zero while unconfirmed
+    pub confirmations: u32,
+}
+
+/// Filter and pagination for [`TransactionHistory::page`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    /// Continue after the page that returned this cursor. Prefer it over
+    /// `offset`, which shifts when new transactions arrive.
+    pub cursor: Option<Cursor>,
+    /// Number of entries to skip
+    pub offset: usize,
+    /// Maximum number of entries to return
+    pub limit: usize,
+    /// Only return transactions in this category
+    pub category: Option<TxCategory>,
+}
+
+impl Default for HistoryQuery {
+    fn default() -> Self {
+        Self {
+            cursor: None,
+            offset: 0,
+            limit: 50,
+            category: None,
+        }
+    }
+}
+
+/// One page of history, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryPage {
+    /// Entries on this page
+    pub entries: Vec<HistoryEntry>,
+    /// Total entries matching the query
+    pub total: usize,
+    /// Whether more entries follow this page
+    pub has_more: bool,
+    /// Cursor for the following page, `None` on the last page
+    pub next_cursor: Option<Cursor>,
+}
+
+/// On-chain balance derived from stored history
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnchainBalance {
+    /// Sum of confirmed transactions
+    pub confirmed_sat: i64,
+    /// Net effect of unconfirmed transactions
+    pub pending_sat: i64,
+}
+
+/// Persistent, offline-queryable transaction history
+pub struct TransactionHistory {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    oracle: Option<Arc<dyn PriceOracle>>,
+    currency: String,
+    tip_height: RwLock<u32>,
+}
+
+impl TransactionHistory {
+    /// Open the history stored in `storage`, valuing new records in `currency`
+    pub async fn open(
+        storage: Arc<dyn StorageBackend>,
+        oracle: Option<Arc<dyn PriceOracle>>,
+        currency: impl Into<String>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            storage,
+            ns,
+            oracle,
+            currency: currency.into(),
+            tip_height: RwLock::new(0),
+        })
+    }
+
+    /// Set the chain tip used to compute confirmations
+    pub async fn set_tip_height(&self, height: u32) {
+        *self.tip_height.write().await = height;
+    }
+
+    /// Insert or update a transaction discovered by sync.
+    ///
+    /// User labels and an existing fiat valuation are kept when the same
+    /// transaction is recorded again, e.g. once it confirms.
+    pub async fn record(&self, mut tx: WalletTx) -> AnyaResult<()> {
+        if let Some(existing) = self.get(&tx.txid).await? {
+            tx.category = tx.category.or(existing.category);
+            tx.label = tx.label.or(existing.label);
+            tx.fiat = tx.fiat.or(existing.fiat);
+        }
+        if tx.fiat.is_none() {
+            tx.fiat = self.valuation(&tx).await;
+        }
+        self.save(&tx).await
+    }
+
+    /// Set the category and label of a stored transaction
+    pub async fn set_label(
+        &self,
+        txid: &Txid,
+        category: Option<TxCategory>,
+        label: Option<String>,
+    ) -> AnyaResult<()> {
+        let mut tx = self
+            .get(txid)
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("transaction {}", txid)))?;
+        tx.category = category;
+        tx.label = label;
+        self.save(&tx).await
+    }
+
+    /// Fetch a stored transaction
+    pub async fn get(&self, txid: &Txid) -> AnyaResult<Option<WalletTx>> {
+        self.storage
+            .get(&self.ns, &Self::key(txid))
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes).map_err(Into::into))
+            .transpose()
+    }
+
+    /// Query stored history, newest first
+    pub async fn page(&self, query: &HistoryQuery) -> AnyaResult<HistoryPage> {
+        let tip = *self.tip_height.read().await;
+        let mut all = self.load_all().await?;
+        all.retain(|tx| query.category.is_none() || tx.category == query.category);
+        all.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.txid.cmp(&a.txid)));
+        let total = all.len();
+        if let Some(cursor) = &query.cursor {
+            let after: (u64, Txid) = cursor.decode()?;
+            all.retain(|tx| (tx.timestamp, tx.txid) < after);
+        }
+
+        let remaining = all.len();
+        let entries: Vec<HistoryEntry> = all
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .map(|tx| HistoryEntry {
+                confirmations: confirmations(tx.block_height, tip),
+                tx,
+            })
+            .collect();
+        let has_more = query.offset + entries.len() < remaining;
+        let next_cursor = match entries.last() {
+            Some(last) if has_more => Some(Cursor::encode(&(last.tx.timestamp, last.tx.txid))?),
+            _ => None,
+        };
+        Ok(HistoryPage {
+            has_more,
+            entries,
+            total,
+            next_cursor,
+        })
+    }
+
+    /// Balance implied by the stored transactions
+    pub async fn balance(&self) -> AnyaResult<OnchainBalance> {
+        Ok(self
+            .load_all()
+            .await?
+            .iter()
+            .fold(OnchainBalance::default(), |mut balance, tx| {
+                if tx.block_height.is_some() {
+                    balance.confirmed_sat += tx.amount_sat;
+                } else {
+                    balance.pending_sat += tx.amount_sat;
+                }
+                balance
+            }))
+    }
+
+    /// Value records saved without a fiat price, returning how many were updated
+    pub async fn backfill_fiat(&self) -> AnyaResult<usize> {
+        let mut updated = 0;
+        for mut tx in self.load_all().await? {
+            if tx.fiat.is_some() {
+                continue;
+            }
+            if let Some(fiat) = self.valuation(&tx).await {
+                tx.fiat = Some(fiat);
+                self.save(&tx).await?;
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+
+    async fn valuation(&self, tx: &WalletTx) -> Option<FiatValue> {
+        let oracle = self.oracle.as_ref()?;
+        match oracle.price_at(&self.currency, tx.timestamp).await {
+            Ok(price) => Some(FiatValue {
+                currency: self.currency.clone(),
+                price,
+                value: tx.amount_sat as f64 / 100_000_000.0 * price,
+            }),
+            Err(e) => {
+                tracing::debug!(txid = %tx.txid, error = %e, "fiat valuation unavailable");
+                None
+            }
+        }
+    }
+
+    async fn load_all(&self) -> AnyaResult<Vec<WalletTx>> {
+        self.storage
+            .scan_prefix(&self.ns, KEY_PREFIX)
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| serde_json::from_slice(&bytes).map_err(Into::into))
+            .collect()
+    }
+
+    async fn save(&self, tx: &WalletTx) -> AnyaResult<()> {
+        let bytes = serde_json::to_vec(tx)?;
+        self.storage
+            .put(&self.ns, &Self::key(&tx.txid), &bytes)
+            .await
+    }
+
+    fn key(txid: &Txid) -> String {
+        format!("{}{}", KEY_PREFIX, txid)
+    }
+}
+
+const fn confirmations(block_height: Option<u32>, tip: u32) -> u32 {
+    match block_height {
+        Some(height) if height <= tip => tip - height + 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use crate::ErrorCode;
+    use ::bitcoin::hashes::Hash;
+
+    struct FixedOracle {
+        online: bool,
+    }
+
+    #[async_trait]
+    impl PriceOracle for FixedOracle {
+        async fn price_at(&self, _currency: &str, timestamp: u64) -> AnyaResult<f64> {
+            if self.online {
+                Ok(timestamp as f64)
+            } else {
+                Err(AnyaError::new(ErrorCode::NetworkFailure, "offline"))
+            }
+        }
+    }
+
+    fn tx(n: u8, timestamp: u64, block_height: Option<u32>) -> WalletTx {
+        WalletTx {
+            txid: Txid::from_byte_array([n; 32]),
+            direction: TxDirection::Incoming,
+            amount_sat: 50_000_000,
+            fee_sat: None,
+            block_height,
+            timestamp,
+            category: None,
+            label: None,
+            fiat: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history_paginates_newest_first_with_confirmations() {
+        let oracle = Arc::new(FixedOracle { online: true });
+        let history = TransactionHistory::open(Arc::new(MemoryBackend::new()), Some(oracle), "USD")
+            .await
+            .unwrap();
+        history.set_tip_height(110).await;
+        for (n, ts, height) in [
+            (1, 1_000, Some(100)),
+            (2, 3_000, None),
+            (3, 2_000, Some(110)),
+        ] {
+            history.record(tx(n, ts, height)).await.unwrap();
+        }
+
+        let page = history
+            .page(&HistoryQuery {
+                limit: 2,
+                ..HistoryQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.total, 3);
+        assert!(page.has_more);
+        let stamps: Vec<u64> = page.entries.iter().map(|e| e.tx.timestamp).collect();
+        assert_eq!(stamps, vec![3_000, 2_000]);
+        assert_eq!(page.entries[0].confirmations, 0);
+        assert_eq!(page.entries[1].confirmations, 1);
+        assert_eq!(page.entries[1].tx.fiat.as_ref().unwrap().value, 1_000.0);
+
+        // A newer transaction does not shift the cursor-based next page
+        history.record(tx(4, 5_000, None)).await.unwrap();
+        let next = history
+            .page(&HistoryQuery {
+                cursor: page.next_cursor,
+                limit: 2,
+                ..HistoryQuery::default()
+            })
+            .await
+            .unwrap();
+        let stamps: Vec<u64> = next.entries.iter().map(|e| e.tx.timestamp).collect();
+        assert_eq!(stamps, vec![1_000]);
+        assert!(!next.has_more && next.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_labels_survive_resync_and_fiat_backfills() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let offline = Arc::new(FixedOracle { online: false });
+        let history = TransactionHistory::open(Arc::clone(&storage), Some(offline), "EUR")
+            .await
+            .unwrap();
+        let unconfirmed = tx(7, 4_000, None);
+        history.record(unconfirmed.clone()).await.unwrap();
+        history
+            .set_label(
+                &unconfirmed.txid,
+                Some(TxCategory::Income),
+                Some("invoice #12".into()),
+            )
+            .await
+            .unwrap();
+        history.record(tx(7, 4_000, Some(5))).await.unwrap();
+
+        let stored = history.get(&unconfirmed.txid).await.unwrap().unwrap();
+        assert_eq!(stored.block_height, Some(5));
+        assert_eq!(stored.label.as_deref(), Some("invoice #12"));
+        assert!(stored.fiat.is_none());
+
+        let online = Arc::new(FixedOracle { online: true });
+        let reopened = TransactionHistory::open(storage, Some(online), "EUR")
+            .await
+            .unwrap();
+        assert_eq!(reopened.backfill_fiat().await.unwrap(), 1);
+        let income = reopened
+            .page(&HistoryQuery {
+                category: Some(TxCategory::Income),
+                ..HistoryQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(income.entries[0].tx.fiat.as_ref().unwrap().price, 4_000.0);
+    }
+}
@@ -0,0 +1,550 @@
+//! On-device inference with int8 models
+//!
+//! Small dense networks, such as the fraud and fee suggestion models, are
+//! trained as [`FloatModel`]s and quantized to [`QuantizedModel`]s: each
+//! weight row is scaled to int8 on its own, and at inference time the layer
+//! input is quantized too, so the dot products run in integer arithmetic and
+//! only the result is rescaled. That keeps models a quarter of their float
+//! size and fast enough to run on the phone without network calls.
+//!
+//! [`OnDeviceInference`] keeps installed models in the `mobile_models`
+//! namespace and exposes a synchronous predict call for the FFI bridge. It
+//! is also the DWN [`SyncJob`]: new model versions are published as signed
+//! records under [`MODEL_PROTOCOL`], and only records from trusted
+//! publishers with a higher version than the installed one are applied.
+
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::sync::{SyncJob, SyncProgress, SyncTarget};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::pagination::{Page, PageRequest};
+use crate::web5::dwn::{DwnHost, SyncEntry};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// DWN protocol model updates are published under
+pub const MODEL_PROTOCOL: &str = "https://anya.dev/protocols/ml-models";
+/// Protocol path of model records
+pub const MODEL_PATH: &str = "model";
+
+const NAMESPACE: &str = "mobile_models";
+const MODEL_PREFIX: &str = "model/";
+const CURSOR_KEY: &str = "dwn_cursor";
+const SYNC_PAGE: usize = 50;
+
+/// Layer output function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    /// Identity
+    Linear,
+    /// `max(0, x)`
+    Relu,
+    /// Logistic function
+    Sigmoid,
+}
+
+impl Activation {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Self::Linear => x,
+            Self::Relu => x.max(0.0),
+            Self::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+        }
+    }
+}
+
+/// Fully connected layer with float weights
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DenseLayer {
+    /// One row of input weights per output
+    pub weights: Vec<Vec<f32>>,
+    /// Bias per output
+    pub bias: Vec<f32>,
+    /// Output function
+    pub activation: Activation,
+}
+
+/// Float model as trained
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FloatModel {
+    /// Model name, e.g. `fraud`
+    pub name: String,
+    /// Version; higher replaces lower
+    pub version: u32,
+    /// Layers in order
+    pub layers: Vec<DenseLayer>,
+}
+
+impl FloatModel {
+    /// Reference float inference
+    pub fn predict(&self, input: &[f32]) -> AnyaResult<Vec<f32>> {
+        let mut x = input.to_vec();
+        for layer in &self.layers {
+            if layer.weights.iter().any(|row| row.len() != x.len()) {
+                return Err(shape_error(&self.name, x.len()));
+            }
+            x = layer
+                .weights
+                .iter()
+                .zip(&layer.bias)
+                .map(|(row, b)| {
+                    let sum: f32 = row.iter().zip(&x).map(|(w, v)| w * v).sum();
+                    layer.activation.apply(sum + b)
+                })
+                .collect();
+        }
+        Ok(x)
+    }
+
+    /// Quantize the weights to int8, one scale per row
+    pub fn quantize(&self) -> AnyaResult<QuantizedModel> {
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| {
+                let inputs = layer.weights.first().map_or(0, Vec::len);
+                let mut weights = Vec::with_capacity(inputs * layer.weights.len());
+                let mut scales = Vec::with_capacity(layer.weights.len());
+                for row in &layer.weights {
+                    let (q, scale) = quantize_row(row);
+                    weights.extend(q);
+                    scales.push(scale);
+                }
+                QuantizedLayer {
+                    inputs,
+                    weights,
+                    scales,
+                    bias: layer.bias.clone(),
+                    activation: layer.activation,
+                }
+            })
+            .collect();
+        let model = QuantizedModel {
+            name: self.name.clone(),
+            version: self.version,
+            layers,
+        };
+        model.validate()?;
+        Ok(model)
+    }
+}
+
+/// Fully connected layer with int8 weights
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantizedLayer {
+    /// Input width
+    pub inputs: usize,
+    /// Row-major weights, `inputs` per output
+    pub weights: Vec<i8>,
+    /// Dequantization scale per output row
+    pub scales: Vec<f32>,
+    /// Bias per output
+    pub bias: Vec<f32>,
+    /// Output function
+    pub activation: Activation,
+}
+
+/// Int8 model run on the device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantizedModel {
+    /// Model name
+    pub name: String,
+    /// Version; higher replaces lower
+    pub version: u32,
+    /// Layers in order
+    pub layers: Vec<QuantizedLayer>,
+}
+
+impl QuantizedModel {
+    /// Input width
+    pub fn input_len(&self) -> usize {
+        self.layers.first().map_or(0, |l| l.inputs)
+    }
+
+    /// Run the model on `input`
+    pub fn predict(&self, input: &[f32]) -> AnyaResult<Vec<f32>> {
+        if input.len() != self.input_len() {
+            return Err(shape_error(&self.name, input.len()));
+        }
+        let mut x = input.to_vec();
+        for layer in &self.layers {
+            let (qx, input_scale) = quantize_row(&x);
+            x = layer
+                .weights
+                .chunks(layer.inputs.max(1))
+                .zip(layer.scales.iter().zip(&layer.bias))
+                .map(|(row, (scale, bias))| {
+                    let acc: i32 = row
+                        .iter()
+                        .zip(&qx)
+                        .map(|(w, v)| i32::from(*w) * i32::from(*v))
+                        .sum();
+                    layer
+                        .activation
+                        .apply((acc as f32).mul_add(scale * input_scale, *bias))
+                })
+                .collect();
+        }
+        Ok(x)
+    }
+
+    fn validate(&self) -> AnyaResult<()> {
+        let invalid =
+            |msg: String| AnyaError::invalid_input(format!("model {}: {}", self.name, msg));
+        if self.name.is_empty() || self.name.contains('/') {
+            return Err(invalid("name must be non-empty and not contain '/'".into()));
+        }
+        if self.layers.is_empty() {
+            return Err(invalid("no layers".into()));
+        }
+        let mut width = self.input_len();
+        for (i, layer) in self.layers.iter().enumerate() {
+            let outputs = layer.bias.len();
+            if layer.inputs != width
+                || width == 0
+                || outputs == 0
+                || layer.scales.len() != outputs
+                || layer.weights.len() != width * outputs
+            {
+                return Err(invalid(format!("layer {} has inconsistent shapes", i)));
+            }
+            width = outputs;
+        }
+        Ok(())
+    }
+}
+
+/// Symmetric int8 quantization of `values` with a single scale
+fn quantize_row(values: &[f32]) -> (Vec<i8>, f32) {
+    let max = values.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    if max == 0.0 {
+        return (vec![0; values.len()], 0.0);
+    }
+    let scale = max / 127.0;
+    let q = values
+        .iter()
+        .map(|v| (v / scale).round().clamp(-127.0, 127.0) as i8)
+        .collect();
+    (q, scale)
+}
+
+fn shape_error(model: &str, got: usize) -> AnyaError {
+    AnyaError::invalid_input(format!(
+        "model {} cannot take {} inputs at this layer",
+        model, got
+    ))
+}
+
+/// Source of DWN records to replicate, in log order
+#[async_trait]
+pub trait ModelFeed: Send + Sync {
+    /// One page of the record log
+    async fn page(&self, request: &PageRequest) -> AnyaResult<Page<SyncEntry>>;
+}
+
+/// Feed from a tenant's log on a DWN host
+pub struct DwnModelFeed {
+    host: Arc<DwnHost>,
+    tenant: String,
+}
+
+impl DwnModelFeed {
+    /// Replicate `tenant`'s records from `host`
+    pub fn new(host: Arc<DwnHost>, tenant: impl Into<String>) -> Self {
+        Self {
+            host,
+            tenant: tenant.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl ModelFeed for DwnModelFeed {
+    async fn page(&self, request: &PageRequest) -> AnyaResult<Page<SyncEntry>> {
+        self.host.sync(&self.tenant, &self.tenant, request).await
+    }
+}
+
+/// Installed models, inference, and DWN model updates
+pub struct OnDeviceInference {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    models: RwLock<HashMap<String, Arc<QuantizedModel>>>,
+    publishers: Vec<String>,
+    feed: Arc<dyn ModelFeed>,
+    sync_lock: Mutex<()>,
+}
+
+impl OnDeviceInference {
+    /// Load installed models from `storage`, accepting updates from `feed`
+    /// signed by one of `publishers`
+    pub async fn open(
+        storage: Arc<dyn StorageBackend>,
+        publishers: Vec<String>,
+        feed: Arc<dyn ModelFeed>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let mut models = HashMap::new();
+        for (_, bytes) in storage.scan_prefix(&ns, MODEL_PREFIX).await? {
+            let model: QuantizedModel = serde_json::from_slice(&bytes)?;
+            models.insert(model.name.clone(), Arc::new(model));
+        }
+        Ok(Self {
+            storage,
+            ns,
+            models: RwLock::new(models),
+            publishers,
+            feed,
+            sync_lock: Mutex::new(()),
+        })
+    }
+
+    /// Run model `name` on `input`
+    pub fn predict(&self, name: &str, input: &[f32]) -> AnyaResult<Vec<f32>> {
+        self.model(name)
+            .ok_or_else(|| AnyaError::new(ErrorCode::ModelUnavailable, format!("model {}", name)))?
+            .predict(input)
+    }
+
+    /// Installed version of model `name`
+    pub fn version(&self, name: &str) -> Option<u32> {
+        self.model(name).map(|m| m.version)
+    }
+
+    /// Install `model` if it is newer than the installed version. Returns
+    /// whether it was installed.
+    pub async fn install(&self, model: QuantizedModel) -> AnyaResult<bool> {
+        model.validate()?;
+        if self
+            .version(&model.name)
+            .is_some_and(|v| v >= model.version)
+        {
+            return Ok(false);
+        }
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", MODEL_PREFIX, model.name),
+                &serde_json::to_vec(&model)?,
+            )
+            .await?;
+        self.models
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(model.name.clone(), Arc::new(model));
+        Ok(true)
+    }
+
+    /// Replicate model records from the feed, returning the models installed
+    pub async fn sync_models(&self, progress: Option<&SyncProgress>) -> AnyaResult<Vec<String>> {
+        let _guard = self.sync_lock.lock().await;
+        let mut request = PageRequest::first(SYNC_PAGE);
+        if let Some(bytes) = self.storage.get(&self.ns, CURSOR_KEY).await? {
+            request = request.after(serde_json::from_slice(&bytes)?);
+        }
+        let mut installed = Vec::new();
+        let mut seen = 0;
+        loop {
+            let page = self.feed.page(&request).await?;
+            seen += page.items.len() as u64;
+            for entry in &page.items {
+                match self.apply(entry).await {
+                    Ok(Some(name)) => installed.push(name),
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!(
+                        record = %entry.record.record_id,
+                        error = %e,
+                        "model update rejected"
+                    ),
+                }
+            }
+            if let Some(progress) = progress {
+                progress.report(seen, seen + u64::from(page.next_cursor.is_some()));
+            }
+            let Some(next) = page.next_cursor else {
+                break;
+            };
+            self.storage
+                .put(&self.ns, CURSOR_KEY, &serde_json::to_vec(&next)?)
+                .await?;
+            request = request.after(next);
+        }
+        Ok(installed)
+    }
+
+    async fn apply(&self, entry: &SyncEntry) -> AnyaResult<Option<String>> {
+        let record = &entry.record;
+        if record.protocol.as_deref() != Some(MODEL_PROTOCOL)
+            || record.protocol_path.as_deref() != Some(MODEL_PATH)
+        {
+            return Ok(None);
+        }
+        record.verify()?;
+        if !self.publishers.contains(&record.author) {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("{} is not a trusted model publisher", record.author),
+            ));
+        }
+        let model: QuantizedModel = serde_json::from_slice(&record.data_bytes()?)?;
+        let name = model.name.clone();
+        Ok(self.install(model).await?.then_some(name))
+    }
+
+    fn model(&self, name: &str) -> Option<Arc<QuantizedModel>> {
+        self.models
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl SyncJob for OnDeviceInference {
+    fn target(&self) -> SyncTarget {
+        SyncTarget::Dwn
+    }
+
+    async fn run(&self, progress: &SyncProgress) -> AnyaResult<()> {
+        self.sync_models(Some(progress)).await.map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use crate::web5::auth::DidSigner;
+    use crate::web5::dwn::{
+        Actor, DwnConfig, DwnRecord, ProtocolConfiguration, ProtocolDefinition, ProtocolRule,
+    };
+    use std::collections::BTreeMap;
+
+    fn fraud_model(version: u32) -> FloatModel {
+        FloatModel {
+            name: "fraud".into(),
+            version,
+            layers: vec![
+                DenseLayer {
+                    weights: vec![vec![0.8, -0.31, 0.05], vec![-0.4, 0.9, 0.27]],
+                    bias: vec![0.1, -0.2],
+                    activation: Activation::Relu,
+                },
+                DenseLayer {
+                    weights: vec![vec![1.5, -1.1]],
+                    bias: vec![0.05],
+                    activation: Activation::Sigmoid,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_quantized_model_tracks_float_model() {
+        let model = fraud_model(1);
+        let quantized = model.quantize().unwrap();
+        assert_eq!(quantized.input_len(), 3);
+        for input in [[1.0, 0.5, -2.0], [0.0, 0.0, 0.0], [3.0, -1.0, 0.25]] {
+            let float = model.predict(&input).unwrap()[0];
+            let int8 = quantized.predict(&input).unwrap()[0];
+            assert!((float - int8).abs() < 0.01, "{} vs {}", float, int8);
+        }
+        assert!(quantized.predict(&[1.0]).is_err());
+        let mut broken = quantized;
+        broken.layers[1].scales.clear();
+        assert!(broken.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_models_update_through_dwn_sync() {
+        let (tenant, publisher, stranger) = (
+            DidSigner::from_seed(&[1; 32]).unwrap(),
+            DidSigner::from_seed(&[2; 32]).unwrap(),
+            DidSigner::from_seed(&[3; 32]).unwrap(),
+        );
+        let tenant_did = tenant.did().to_string();
+        let host = DwnHost::open(
+            DwnConfig {
+                tenants: vec![tenant_did.clone()],
+                ..DwnConfig::default()
+            },
+            Arc::new(MemoryBackend::new()),
+        )
+        .await
+        .unwrap();
+        let definition = ProtocolDefinition {
+            protocol: MODEL_PROTOCOL.into(),
+            structure: BTreeMap::from([(
+                MODEL_PATH.into(),
+                ProtocolRule {
+                    schema: None,
+                    data_formats: vec!["application/json".into()],
+                    can_write: vec![Actor::Anyone],
+                },
+            )]),
+        };
+        host.configure_protocol(
+            &tenant_did,
+            &ProtocolConfiguration::sign(&tenant, definition, 1).unwrap(),
+        )
+        .await
+        .unwrap();
+        let publish = |signer: &DidSigner, id: &str, version: u32, at: u64| {
+            let model = fraud_model(version).quantize().unwrap();
+            DwnRecord::new(id, "application/json", &serde_json::to_vec(&model).unwrap())
+                .in_protocol(MODEL_PROTOCOL, MODEL_PATH)
+                .sign(signer, at)
+                .unwrap()
+        };
+        host.write(&tenant_did, publish(&stranger, "m-evil", 9, 1))
+            .await
+            .unwrap();
+        host.write(&tenant_did, publish(&publisher, "m-1", 1, 2))
+            .await
+            .unwrap();
+
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let feed = Arc::new(DwnModelFeed::new(host.clone(), tenant_did.clone()));
+        let device = OnDeviceInference::open(
+            storage.clone(),
+            vec![publisher.did().to_string()],
+            feed.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            device
+                .predict("fraud", &[1.0, 0.5, -2.0])
+                .unwrap_err()
+                .code(),
+            ErrorCode::ModelUnavailable
+        );
+        assert_eq!(device.sync_models(None).await.unwrap(), vec!["fraud"]);
+        // The stranger's higher version was ignored
+        assert_eq!(device.version("fraud"), Some(1));
+        assert!(device.predict("fraud", &[1.0, 0.5, -2.0]).is_ok());
+
+        host.write(&tenant_did, publish(&publisher, "m-2", 2, 3))
+            .await
+            .unwrap();
+        assert!(!device
+            .install(fraud_model(1).quantize().unwrap())
+            .await
+            .unwrap());
+        assert_eq!(device.sync_models(None).await.unwrap(), vec!["fraud"]);
+        assert_eq!(device.version("fraud"), Some(2));
+
+        // Installed models survive a restart
+        let restarted = OnDeviceInference::open(storage, Vec::new(), feed)
+            .await
+            .unwrap();
+        assert_eq!(restarted.version("fraud"), Some(2));
+    }
+}
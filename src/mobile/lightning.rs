@@ -0,0 +1,505 @@
+//! Lightning payments for the mobile wallet through an LSP
+//!
+//! The phone runs a light Lightning node (abstracted as [`LightningNode`]) and
+//! relies on a Lightning Service Provider for inbound liquidity. When an
+//! incoming payment does not fit the existing channels, a just-in-time
+//! channel is bought from the LSP (LSPS2 style) and the invoice carries the
+//! LSP's intercept route hint; the channel is opened when the payer's HTLC
+//! arrives, minus the quoted opening fee.
+//!
+//! Phones are offline most of the time, so every ready channel is registered
+//! with the configured [`Watchtower`]s. Registration runs as part of the
+//! background sync batch via the [`SyncJob`] implementation.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::OutPoint;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::history::TransactionHistory;
+use super::liquidity::LiquidityPolicy;
+use super::qr::validate_bolt11;
+use super::sync::{SyncJob, SyncProgress, SyncTarget};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Lightning configuration for the mobile wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightningConfig {
+    /// Whether Lightning is enabled
+    pub enabled: bool,
+    /// Node id of the LSP
+    pub lsp_node_id: String,
+    /// `host:port` the node connects to for the LSP
+    pub lsp_address: String,
+    /// Largest JIT channel opening fee accepted without asking the user
+    pub max_jit_fee_msat: u64,
+    /// Routing fee limit for outgoing payments in parts per million
+    pub max_routing_fee_ppm: u64,
+    /// Routing fee always allowed regardless of the proportional limit
+    pub min_routing_fee_msat: u64,
+    /// Expiry of generated invoices
+    pub invoice_expiry: Duration,
+    /// Automatic channel rebalancing
+    #[serde(default)]
+    pub liquidity: LiquidityPolicy,
+}
+
+impl Default for LightningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lsp_node_id: String::new(),
+            lsp_address: String::new(),
+            max_jit_fee_msat: 10_000_000,
+            max_routing_fee_ppm: 5_000,
+            min_routing_fee_msat: 10_000,
+            invoice_expiry: Duration::from_secs(3600),
+            liquidity: LiquidityPolicy::default(),
+        }
+    }
+}
+
+/// A channel reported by the node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    /// Hex channel id
+    pub channel_id: String,
+    /// Counterparty node id
+    pub counterparty: String,
+    /// Funding outpoint, once known
+    pub funding_txo: Option<OutPoint>,
+    /// Channel capacity in satoshis
+    pub capacity_sat: u64,
+    /// Amount we can currently send
+    pub outbound_msat: u64,
+    /// Amount we can currently receive
+    pub inbound_msat: u64,
+    /// Whether the channel is open and usable
+    pub is_ready: bool,
+}
+
+/// Route hint through the LSP for a just-in-time channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JitRouteHint {
+    /// LSP node id
+    pub lsp_node_id: String,
+    /// Intercept short channel id the payer routes to
+    pub intercept_scid: u64,
+    /// CLTV expiry delta required by the LSP
+    pub cltv_expiry_delta: u16,
+}
+
+/// JIT channel fee offer from the LSP
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JitFeeParams {
+    /// Minimum opening fee
+    pub min_fee_msat: u64,
+    /// Proportional opening fee in parts per million
+    pub proportional_ppm: u64,
+    /// Opaque LSP promise that must be echoed when buying
+    pub promise: String,
+}
+
+impl JitFeeParams {
+    /// Opening fee charged for a payment of `amount_msat`
+    pub fn fee_for(&self, amount_msat: u64) -> u64 {
+        let proportional = u128::from(amount_msat) * u128::from(self.proportional_ppm) / 1_000_000;
+        u64::try_from(proportional)
+            .unwrap_or(u64::MAX)
+            .max(self.min_fee_msat)
+    }
+}
+
+/// Invoice requested from the node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvoiceRequest {
+    /// Amount, or `None` for an amountless invoice
+    pub amount_msat: Option<u64>,
+    /// Description committed in the invoice
+    pub description: String,
+    /// Invoice expiry
+    pub expiry: Duration,
+    /// LSP route hint for a JIT channel
+    pub route_hint: Option<JitRouteHint>,
+}
+
+/// Result of a successful payment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentResult {
+    /// Hex payment hash
+    pub payment_hash: String,
+    /// Hex preimage proving payment
+    pub preimage: String,
+    /// Routing fee paid
+    pub fee_msat: u64,
+}
+
+/// Invoice returned to the app for an incoming payment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiveOffer {
+    /// BOLT-11 invoice
+    pub invoice: String,
+    /// Opening fee deducted if a JIT channel is needed
+    pub jit_fee_msat: Option<u64>,
+}
+
+/// On-chain and Lightning funds in one view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnifiedBalance {
+    /// Confirmed on-chain balance
+    pub onchain_confirmed_sat: i64,
+    /// Net effect of unconfirmed on-chain transactions
+    pub onchain_pending_sat: i64,
+    /// Spendable over Lightning
+    pub lightning_spendable_sat: u64,
+    /// Receivable over existing channels
+    pub lightning_inbound_sat: u64,
+    /// Confirmed on-chain plus spendable Lightning funds
+    pub total_sat: i64,
+}
+
+/// Embedded Lightning node
+#[async_trait]
+pub trait LightningNode: Send + Sync {
+    /// Channels known to the node
+    async fn channels(&self) -> AnyaResult<Vec<ChannelInfo>>;
+
+    /// Create a BOLT-11 invoice
+    async fn create_invoice(&self, request: InvoiceRequest) -> AnyaResult<String>;
+
+    /// Pay a BOLT-11 invoice, spending at most `max_fee_msat` on routing
+    async fn pay_invoice(&self, invoice: &str, max_fee_msat: u64) -> AnyaResult<PaymentResult>;
+}
+
+/// Client for the LSP's JIT channel service
+#[async_trait]
+pub trait LspClient: Send + Sync {
+    /// Current opening fee offer
+    async fn fee_params(&self) -> AnyaResult<JitFeeParams>;
+
+    /// Buy a JIT channel for a payment of `payment_size_msat`
+    async fn buy_jit_channel(
+        &self,
+        params: &JitFeeParams,
+        payment_size_msat: u64,
+    ) -> AnyaResult<JitRouteHint>;
+}
+
+/// Watchtower that monitors channels while the phone is offline
+#[async_trait]
+pub trait Watchtower: Send + Sync {
+    /// Stable identifier of the tower
+    fn id(&self) -> &str;
+
+    /// Start watching `channel`
+    async fn register(&self, channel: &ChannelInfo) -> AnyaResult<()>;
+}
+
+/// Lightning service for the mobile wallet
+pub struct MobileLightning {
+    config: LightningConfig,
+    node: Arc<dyn LightningNode>,
+    lsp: Arc<dyn LspClient>,
+    watchtowers: Vec<Arc<dyn Watchtower>>,
+    registered: Mutex<HashSet<(String, String)>>,
+}
+
+impl MobileLightning {
+    /// Create the service
+    pub fn new(
+        config: LightningConfig,
+        node: Arc<dyn LightningNode>,
+        lsp: Arc<dyn LspClient>,
+        watchtowers: Vec<Arc<dyn Watchtower>>,
+    ) -> Self {
+        Self {
+            config,
+            node,
+            lsp,
+            watchtowers,
+            registered: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Channels known to the node
+    pub async fn channels(&self) -> AnyaResult<Vec<ChannelInfo>> {
+        self.node.channels().await
+    }
+
+    /// Create an invoice, buying a JIT channel if inbound liquidity is short
+    pub async fn receive(&self, amount_msat: u64, description: &str) -> AnyaResult<ReceiveOffer> {
+        self.ensure_enabled()?;
+        let inbound: u64 = self
+            .channels()
+            .await?
+            .iter()
+            .filter(|c| c.is_ready)
+            .map(|c| c.inbound_msat)
+            .max()
+            .unwrap_or(0);
+        let mut request = InvoiceRequest {
+            amount_msat: Some(amount_msat),
+            description: description.to_string(),
+            expiry: self.config.invoice_expiry,
+            route_hint: None,
+        };
+        if inbound >= amount_msat {
+            return Ok(ReceiveOffer {
+                invoice: self.node.create_invoice(request).await?,
+                jit_fee_msat: None,
+            });
+        }
+
+        let params = self.lsp.fee_params().await?;
+        let fee = params.fee_for(amount_msat);
+        if fee >= amount_msat {
+            return Err(AnyaError::invalid_input(format!(
+                "amount too small to cover the {} msat channel opening fee",
+                fee
+            )));
+        }
+        if fee > self.config.max_jit_fee_msat {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!(
+                    "channel opening fee {} msat exceeds the configured limit",
+                    fee
+                ),
+            ));
+        }
+        request.route_hint = Some(self.lsp.buy_jit_channel(&params, amount_msat).await?);
+        Ok(ReceiveOffer {
+            invoice: self.node.create_invoice(request).await?,
+            jit_fee_msat: Some(fee),
+        })
+    }
+
+    /// Pay an invoice within the configured routing fee limit
+    pub async fn pay(&self, invoice: &str, amount_msat: u64) -> AnyaResult<PaymentResult> {
+        self.ensure_enabled()?;
+        validate_bolt11(invoice)?;
+        let proportional =
+            u128::from(amount_msat) * u128::from(self.config.max_routing_fee_ppm) / 1_000_000;
+        let max_fee = u64::try_from(proportional)
+            .unwrap_or(u64::MAX)
+            .max(self.config.min_routing_fee_msat);
+        self.node.pay_invoice(invoice, max_fee).await
+    }
+
+    /// Combined on-chain and Lightning balance
+    pub async fn balance(&self, history: &TransactionHistory) -> AnyaResult<UnifiedBalance> {
+        let onchain = history.balance().await?;
+        let ready: Vec<ChannelInfo> = if self.config.enabled {
+            self.channels()
+                .await?
+                .into_iter()
+                .filter(|c| c.is_ready)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let spendable = ready.iter().map(|c| c.outbound_msat).sum::<u64>() / 1_000;
+        Ok(UnifiedBalance {
+            onchain_confirmed_sat: onchain.confirmed_sat,
+            onchain_pending_sat: onchain.pending_sat,
+            lightning_spendable_sat: spendable,
+            lightning_inbound_sat: ready.iter().map(|c| c.inbound_msat).sum::<u64>() / 1_000,
+            total_sat: onchain
+                .confirmed_sat
+                .saturating_add(i64::try_from(spendable).unwrap_or(i64::MAX)),
+        })
+    }
+
+    /// Register every ready channel with every tower it is not yet known to.
+    ///
+    /// Returns the number of new registrations; failures are retried on the
+    /// next call.
+    pub async fn register_watchtowers(&self) -> AnyaResult<usize> {
+        let channels = self.channels().await?;
+        let mut registered = self.registered.lock().await;
+        let mut added = 0;
+        for channel in channels.iter().filter(|c| c.is_ready) {
+            for tower in &self.watchtowers {
+                let key = (channel.channel_id.clone(), tower.id().to_string());
+                if registered.contains(&key) {
+                    continue;
+                }
+                match tower.register(channel).await {
+                    Ok(()) => {
+                        registered.insert(key);
+                        added += 1;
+                    }
+                    Err(e) => tracing::warn!(
+                        channel = %channel.channel_id,
+                        tower = tower.id(),
+                        error = %e,
+                        "watchtower registration failed"
+                    ),
+                }
+            }
+        }
+        drop(registered);
+        Ok(added)
+    }
+
+    fn ensure_enabled(&self) -> AnyaResult<()> {
+        if self.config.enabled {
+            Ok(())
+        } else {
+            Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                "Lightning is disabled",
+            ))
+        }
+    }
+}
+
+#[async_trait]
+impl SyncJob for MobileLightning {
+    fn target(&self) -> SyncTarget {
+        SyncTarget::Lightning
+    }
+
+    async fn run(&self, progress: &SyncProgress) -> AnyaResult<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let added = self.register_watchtowers().await?;
+        progress.report(added as u64, added as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    struct FakeNode {
+        inbound_msat: u64,
+        invoices: Mutex<Vec<InvoiceRequest>>,
+    }
+
+    #[async_trait]
+    impl LightningNode for FakeNode {
+        async fn channels(&self) -> AnyaResult<Vec<ChannelInfo>> {
+            Ok(vec![ChannelInfo {
+                channel_id: "aa".into(),
+                counterparty: "lsp".into(),
+                funding_txo: None,
+                capacity_sat: 100_000,
+                outbound_msat: 40_000_000,
+                inbound_msat: self.inbound_msat,
+                is_ready: true,
+            }])
+        }
+
+        async fn create_invoice(&self, request: InvoiceRequest) -> AnyaResult<String> {
+            self.invoices.lock().await.push(request);
+            Ok("lnbc1pexample".into())
+        }
+
+        async fn pay_invoice(
+            &self,
+            _invoice: &str,
+            max_fee_msat: u64,
+        ) -> AnyaResult<PaymentResult> {
+            Ok(PaymentResult {
+                payment_hash: "00".into(),
+                preimage: "11".into(),
+                fee_msat: max_fee_msat,
+            })
+        }
+    }
+
+    struct FakeLsp;
+
+    #[async_trait]
+    impl LspClient for FakeLsp {
+        async fn fee_params(&self) -> AnyaResult<JitFeeParams> {
+            Ok(JitFeeParams {
+                min_fee_msat: 2_000_000,
+                proportional_ppm: 10_000,
+                promise: "signed-offer".into(),
+            })
+        }
+
+        async fn buy_jit_channel(
+            &self,
+            _params: &JitFeeParams,
+            _payment_size_msat: u64,
+        ) -> AnyaResult<JitRouteHint> {
+            Ok(JitRouteHint {
+                lsp_node_id: "lsp".into(),
+                intercept_scid: 42,
+                cltv_expiry_delta: 144,
+            })
+        }
+    }
+
+    struct FlakyTower(std::sync::atomic::AtomicBool);
+
+    #[async_trait]
+    impl Watchtower for FlakyTower {
+        fn id(&self) -> &str {
+            "tower"
+        }
+
+        async fn register(&self, _channel: &ChannelInfo) -> AnyaResult<()> {
+            if self.0.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(AnyaError::new(ErrorCode::NetworkFailure, "tower offline"))
+            }
+        }
+    }
+
+    fn service(inbound_msat: u64) -> (Arc<FakeNode>, MobileLightning) {
+        let node = Arc::new(FakeNode {
+            inbound_msat,
+            invoices: Mutex::new(Vec::new()),
+        });
+        let config = LightningConfig {
+            enabled: true,
+            ..LightningConfig::default()
+        };
+        let tower = Arc::new(FlakyTower(std::sync::atomic::AtomicBool::new(false)));
+        let service = MobileLightning::new(config, node.clone(), Arc::new(FakeLsp), vec![tower]);
+        (node, service)
+    }
+
+    #[tokio::test]
+    async fn test_receive_uses_jit_channel_when_inbound_is_short() {
+        let (node, lightning) = service(1_000_000);
+        let small = lightning.receive(500_000, "coffee").await.unwrap();
+        assert_eq!(small.jit_fee_msat, None);
+
+        let large = lightning.receive(300_000_000, "rent").await.unwrap();
+        assert_eq!(large.jit_fee_msat, Some(3_000_000));
+        let invoices = node.invoices.lock().await;
+        assert!(invoices[0].route_hint.is_none());
+        assert_eq!(invoices[1].route_hint.as_ref().unwrap().intercept_scid, 42);
+        drop(invoices);
+
+        assert!(lightning.receive(1_500_000, "too small").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_watchtower_retry_and_unified_balance() {
+        let (_, lightning) = service(0);
+        assert_eq!(lightning.register_watchtowers().await.unwrap(), 0);
+        assert_eq!(lightning.register_watchtowers().await.unwrap(), 1);
+        assert_eq!(lightning.register_watchtowers().await.unwrap(), 0);
+
+        let history = TransactionHistory::open(Arc::new(MemoryBackend::new()), None, "USD")
+            .await
+            .unwrap();
+        let balance = lightning.balance(&history).await.unwrap();
+        assert_eq!(balance.lightning_spendable_sat, 40_000);
+        assert_eq!(balance.total_sat, 40_000);
+    }
+}
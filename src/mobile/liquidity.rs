@@ -0,0 +1,502 @@
+//! Channel liquidity management
+//!
+//! The [`LiquidityManager`] watches each ready channel's outbound ratio,
+//! its share of the channel's balance that is on our side. A channel below
+//! [`LiquidityPolicy::low_outbound_ratio`] cannot send much and one above
+//! [`LiquidityPolicy::high_outbound_ratio`] cannot receive much; both are
+//! moved back towards the target ratio. Funds are shifted first by circular
+//! rebalancing, paying ourselves out of a saturated channel and back in
+//! through a depleted one, and what cannot be matched that way is fixed with
+//! a submarine swap: swapping out (Lightning to on-chain) frees inbound
+//! capacity, swapping in (on-chain to Lightning) adds outbound.
+//!
+//! Every action is capped by the fees the policy is willing to pay, and a
+//! channel is left alone for [`LiquidityPolicy::cooldown`] after it was
+//! touched, successfully or not.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::lightning::{ChannelInfo, LightningNode};
+use super::sync::{SyncJob, SyncProgress, SyncTarget};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// When and how to rebalance channels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiquidityPolicy {
+    /// Whether the manager acts on its own
+    pub enabled: bool,
+    /// Outbound ratio channels are moved towards
+    pub target_outbound_ratio: f64,
+    /// Channels below this outbound ratio need outbound liquidity
+    pub low_outbound_ratio: f64,
+    /// Channels above this outbound ratio need inbound liquidity
+    pub high_outbound_ratio: f64,
+    /// Most fee paid for a circular rebalance, in parts per million
+    pub max_rebalance_fee_ppm: u64,
+    /// Most fee paid for a submarine swap, in parts per million
+    pub max_swap_fee_ppm: u64,
+    /// Whether submarine swaps may be used when rebalancing cannot help
+    pub allow_swaps: bool,
+    /// Smallest amount worth moving
+    pub min_amount_sat: u64,
+    /// Largest amount moved by one action
+    pub max_amount_sat: u64,
+    /// Time a channel is left alone after an action
+    pub cooldown: Duration,
+}
+
+impl Default for LiquidityPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_outbound_ratio: 0.5,
+            low_outbound_ratio: 0.2,
+            high_outbound_ratio: 0.8,
+            max_rebalance_fee_ppm: 1_000,
+            max_swap_fee_ppm: 5_000,
+            allow_swaps: true,
+            min_amount_sat: 20_000,
+            max_amount_sat: 1_000_000,
+            cooldown: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl LiquidityPolicy {
+    /// Fee limit for a rebalance of `amount_sat`
+    pub const fn max_rebalance_fee_msat(&self, amount_sat: u64) -> u64 {
+        amount_sat * self.max_rebalance_fee_ppm / 1_000
+    }
+
+    /// Fee limit for a swap of `amount_sat`
+    pub const fn max_swap_fee_sat(&self, amount_sat: u64) -> u64 {
+        amount_sat * self.max_swap_fee_ppm / 1_000_000
+    }
+}
+
+/// Direction of a submarine swap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SwapDirection {
+    /// Pay over Lightning, receive on-chain; adds inbound liquidity
+    Out,
+    /// Pay on-chain, receive over Lightning; adds outbound liquidity
+    In,
+}
+
+/// Circular payments from one of our channels to another
+#[async_trait]
+pub trait Rebalancer: Send + Sync {
+    /// Move `amount_msat` out through `from_channel` and back in through
+    /// `to_channel`, returning the routing fee paid
+    async fn rebalance(
+        &self,
+        from_channel: &str,
+        to_channel: &str,
+        amount_msat: u64,
+        max_fee_msat: u64,
+    ) -> AnyaResult<u64>;
+}
+
+/// Submarine swap provider
+#[async_trait]
+pub trait SwapService: Send + Sync {
+    /// Swap `amount_sat` through `channel_id`, returning the swap id
+    async fn swap(
+        &self,
+        direction: SwapDirection,
+        channel_id: &str,
+        amount_sat: u64,
+        max_fee_sat: u64,
+    ) -> AnyaResult<String>;
+}
+
+/// A step towards balanced channels
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LiquidityAction {
+    /// Circular rebalance between two channels
+    Rebalance {
+        /// Channel with surplus outbound liquidity
+        from_channel: String,
+        /// Channel short of outbound liquidity
+        to_channel: String,
+        /// Amount moved
+        amount_sat: u64,
+    },
+    /// Submarine swap on one channel
+    Swap {
+        /// Swap direction
+        direction: SwapDirection,
+        /// Channel whose balance changes
+        channel_id: String,
+        /// Amount swapped
+        amount_sat: u64,
+    },
+}
+
+impl LiquidityAction {
+    fn channels(&self) -> Vec<&str> {
+        match self {
+            Self::Rebalance {
+                from_channel,
+                to_channel,
+                ..
+            } => vec![from_channel, to_channel],
+            Self::Swap { channel_id, .. } => vec![channel_id],
+        }
+    }
+}
+
+/// Result of an executed action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionReport {
+    /// The action
+    pub action: LiquidityAction,
+    /// Fee paid or swap id, or the failure
+    pub outcome: Result<String, String>,
+}
+
+/// Plans and runs rebalancing according to a [`LiquidityPolicy`]
+pub struct LiquidityManager {
+    policy: LiquidityPolicy,
+    node: Arc<dyn LightningNode>,
+    rebalancer: Arc<dyn Rebalancer>,
+    swaps: Option<Arc<dyn SwapService>>,
+    touched: Mutex<HashMap<String, Instant>>,
+}
+
+impl LiquidityManager {
+    /// Manager acting on `node`'s channels; swaps need a `swaps` provider
+    pub fn new(
+        policy: LiquidityPolicy,
+        node: Arc<dyn LightningNode>,
+        rebalancer: Arc<dyn Rebalancer>,
+        swaps: Option<Arc<dyn SwapService>>,
+    ) -> Self {
+        Self {
+            policy,
+            node,
+            rebalancer,
+            swaps,
+            touched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Policy in effect
+    pub const fn policy(&self) -> &LiquidityPolicy {
+        &self.policy
+    }
+
+    /// Actions that would bring `channels` back within the thresholds.
+    ///
+    /// Most depleted channels are served first, each from the channel with
+    /// the largest surplus; needs left over become swaps when allowed.
+    pub fn plan(&self, channels: &[ChannelInfo]) -> Vec<LiquidityAction> {
+        let policy = &self.policy;
+        let now = Instant::now();
+        let touched = self.touched();
+        let mut needs = Vec::new();
+        let mut surpluses = Vec::new();
+        for channel in channels.iter().filter(|c| c.is_ready) {
+            if touched
+                .get(&channel.channel_id)
+                .is_some_and(|at| now.duration_since(*at) < policy.cooldown)
+            {
+                continue;
+            }
+            let total = (channel.outbound_msat + channel.inbound_msat) / 1_000;
+            let outbound = channel.outbound_msat / 1_000;
+            if total == 0 {
+                continue;
+            }
+            let ratio = outbound as f64 / total as f64;
+            let target = (total as f64 * policy.target_outbound_ratio) as u64;
+            if ratio < policy.low_outbound_ratio {
+                needs.push((channel.channel_id.clone(), target.saturating_sub(outbound)));
+            } else if ratio > policy.high_outbound_ratio {
+                surpluses.push((channel.channel_id.clone(), outbound.saturating_sub(target)));
+            }
+        }
+        drop(touched);
+        needs.sort_by_key(|(_, need)| std::cmp::Reverse(*need));
+
+        let mut actions = Vec::new();
+        for (to_channel, need) in &mut needs {
+            let Some((from_channel, surplus)) = surpluses
+                .iter_mut()
+                .filter(|(_, s)| *s >= policy.min_amount_sat)
+                .max_by_key(|(_, s)| *s)
+            else {
+                break;
+            };
+            let amount_sat = (*need).min(*surplus).min(policy.max_amount_sat);
+            if amount_sat < policy.min_amount_sat {
+                continue;
+            }
+            *need -= amount_sat;
+            *surplus -= amount_sat;
+            actions.push(LiquidityAction::Rebalance {
+                from_channel: from_channel.clone(),
+                to_channel: to_channel.clone(),
+                amount_sat,
+            });
+        }
+        if policy.allow_swaps && self.swaps.is_some() {
+            let leftover = needs
+                .into_iter()
+                .map(|(c, a)| (SwapDirection::In, c, a))
+                .chain(
+                    surpluses
+                        .into_iter()
+                        .map(|(c, a)| (SwapDirection::Out, c, a)),
+                );
+            for (direction, channel_id, amount) in leftover {
+                let amount_sat = amount.min(policy.max_amount_sat);
+                if amount_sat >= policy.min_amount_sat {
+                    actions.push(LiquidityAction::Swap {
+                        direction,
+                        channel_id,
+                        amount_sat,
+                    });
+                }
+            }
+        }
+        actions
+    }
+
+    /// Plan against the node's current channels and execute the actions.
+    ///
+    /// Failed actions are reported, not returned as errors, so one stuck
+    /// channel does not block the others.
+    pub async fn run_once(&self) -> AnyaResult<Vec<ActionReport>> {
+        let actions = self.plan(&self.node.channels().await?);
+        let mut reports = Vec::with_capacity(actions.len());
+        for action in actions {
+            let outcome = self.execute(&action).await;
+            match &outcome {
+                Ok(result) => tracing::info!(?action, %result, "liquidity action completed"),
+                Err(e) => tracing::warn!(?action, error = %e, "liquidity action failed"),
+            }
+            let now = Instant::now();
+            let mut touched = self.touched();
+            for channel in action.channels() {
+                touched.insert(channel.to_string(), now);
+            }
+            drop(touched);
+            reports.push(ActionReport {
+                action,
+                outcome: outcome.map_err(|e| e.to_string()),
+            });
+        }
+        Ok(reports)
+    }
+
+    async fn execute(&self, action: &LiquidityAction) -> AnyaResult<String> {
+        match action {
+            LiquidityAction::Rebalance {
+                from_channel,
+                to_channel,
+                amount_sat,
+            } => {
+                let fee = self
+                    .rebalancer
+                    .rebalance(
+                        from_channel,
+                        to_channel,
+                        amount_sat * 1_000,
+                        self.policy.max_rebalance_fee_msat(*amount_sat),
+                    )
+                    .await?;
+                Ok(format!("{} msat fee", fee))
+            }
+            LiquidityAction::Swap {
+                direction,
+                channel_id,
+                amount_sat,
+            } => {
+                let swaps = self.swaps.as_ref().ok_or_else(|| {
+                    AnyaError::new(ErrorCode::Unavailable, "no swap provider configured")
+                })?;
+                swaps
+                    .swap(
+                        *direction,
+                        channel_id,
+                        *amount_sat,
+                        self.policy.max_swap_fee_sat(*amount_sat),
+                    )
+                    .await
+            }
+        }
+    }
+
+    fn touched(&self) -> MutexGuard<'_, HashMap<String, Instant>> {
+        self.touched.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl SyncJob for LiquidityManager {
+    fn target(&self) -> SyncTarget {
+        SyncTarget::Lightning
+    }
+
+    async fn run(&self, progress: &SyncProgress) -> AnyaResult<()> {
+        if !self.policy.enabled {
+            return Ok(());
+        }
+        let reports = self.run_once().await?;
+        let done = reports.iter().filter(|r| r.outcome.is_ok()).count();
+        progress.report(done as u64, reports.len() as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobile::lightning::{InvoiceRequest, PaymentResult};
+
+    struct Node(Vec<ChannelInfo>);
+
+    #[async_trait]
+    impl LightningNode for Node {
+        async fn channels(&self) -> AnyaResult<Vec<ChannelInfo>> {
+            Ok(self.0.clone())
+        }
+
+        async fn create_invoice(&self, _request: InvoiceRequest) -> AnyaResult<String> {
+            Err(AnyaError::new(ErrorCode::Unavailable, "no invoices"))
+        }
+
+        async fn pay_invoice(&self, _invoice: &str, _max_fee: u64) -> AnyaResult<PaymentResult> {
+            Err(AnyaError::new(ErrorCode::Unavailable, "no payments"))
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(String, u64, u64)>>);
+
+    #[async_trait]
+    impl Rebalancer for Recorder {
+        async fn rebalance(
+            &self,
+            from: &str,
+            to: &str,
+            amount: u64,
+            max_fee: u64,
+        ) -> AnyaResult<u64> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((format!("{}->{}", from, to), amount, max_fee));
+            Ok(max_fee / 2)
+        }
+    }
+
+    #[async_trait]
+    impl SwapService for Recorder {
+        async fn swap(
+            &self,
+            _: SwapDirection,
+            channel: &str,
+            amount: u64,
+            max_fee: u64,
+        ) -> AnyaResult<String> {
+            if channel == "broken" {
+                return Err(AnyaError::new(ErrorCode::Unavailable, "provider offline"));
+            }
+            self.0
+                .lock()
+                .unwrap()
+                .push((channel.to_string(), amount, max_fee));
+            Ok(format!("swap-{}", channel))
+        }
+    }
+
+    fn channel(id: &str, outbound_sat: u64, inbound_sat: u64) -> ChannelInfo {
+        ChannelInfo {
+            channel_id: id.into(),
+            counterparty: format!("peer-{}", id),
+            funding_txo: None,
+            capacity_sat: outbound_sat + inbound_sat,
+            outbound_msat: outbound_sat * 1_000,
+            inbound_msat: inbound_sat * 1_000,
+            is_ready: true,
+        }
+    }
+
+    fn setup(channels: Vec<ChannelInfo>, swaps: bool) -> (LiquidityManager, Arc<Recorder>) {
+        let recorder = Arc::new(Recorder::default());
+        let swaps = swaps.then(|| recorder.clone() as Arc<dyn SwapService>);
+        let manager = LiquidityManager::new(
+            LiquidityPolicy::default(),
+            Arc::new(Node(channels)),
+            recorder.clone(),
+            swaps,
+        );
+        (manager, recorder)
+    }
+
+    #[test]
+    fn test_plan_matches_surplus_to_need_then_swaps() {
+        let channels = vec![
+            channel("full", 900_000, 100_000),
+            channel("empty", 50_000, 950_000),
+            channel("dry", 0, 400_000),
+            channel("fine", 500_000, 500_000),
+        ];
+        let (manager, _) = setup(channels.clone(), true);
+        let actions = manager.plan(&channels);
+        assert_eq!(
+            actions,
+            [
+                // "empty" needs 450k, "full" has 400k to spare
+                LiquidityAction::Rebalance {
+                    from_channel: "full".into(),
+                    to_channel: "empty".into(),
+                    amount_sat: 400_000,
+                },
+                LiquidityAction::Swap {
+                    direction: SwapDirection::In,
+                    channel_id: "empty".into(),
+                    amount_sat: 50_000,
+                },
+                LiquidityAction::Swap {
+                    direction: SwapDirection::In,
+                    channel_id: "dry".into(),
+                    amount_sat: 200_000,
+                },
+            ]
+        );
+
+        // Without a swap provider only the rebalance remains
+        let (manager, _) = setup(channels.clone(), false);
+        assert_eq!(manager.plan(&channels).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_applies_fee_limits_and_cooldown() {
+        let channels = vec![
+            channel("full", 900_000, 100_000),
+            channel("broken", 950_000, 50_000),
+        ];
+        let (manager, recorder) = setup(channels, true);
+        let reports = manager.run_once().await.unwrap();
+        assert_eq!(reports.len(), 2);
+        // Swapping out of "full" may cost 0.5%; the provider failed "broken"
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [("full".to_string(), 400_000, 2_000)]
+        );
+        assert!(reports.iter().any(|r| r.outcome.is_err()));
+
+        // Both channels were touched and are cooling down
+        assert!(manager.run_once().await.unwrap().is_empty());
+        assert_eq!(manager.policy().max_rebalance_fee_msat(400_000), 400_000);
+    }
+}
@@ -0,0 +1,726 @@
+//! LNURL client flows for the mobile wallet
+//!
+//! Supports LNURL-pay (LUD-06, with comments from LUD-12 and success actions
+//! from LUD-09), Lightning addresses (LUD-16), LNURL-withdraw (LUD-03), and
+//! LNURL-auth (LUD-04/05). Links may be bech32 `lnurl1...` strings, with or
+//! without a `lightning:` prefix, LUD-17 `lnurlp://`/`lnurlw://`/`keyauth://`
+//! URLs, or plain HTTPS URLs.
+//!
+//! Before paying, the invoice returned by the service is checked against the
+//! request: its amount must match and its description hash must commit to
+//! the metadata that was shown to the user. Services are reached through an
+//! [`LnurlTransport`]; [`HttpLnurlTransport`] (feature `http`) uses HTTPS.
+
+use std::sync::Arc;
+
+use ::bitcoin::bech32::{self, FromBase32, ToBase32, Variant};
+use ::bitcoin::bip32::{ChildNumber, ExtendedPrivKey};
+use ::bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+use async_trait::async_trait;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::lightning::{MobileLightning, PaymentResult, ReceiveOffer};
+use super::qr::validate_bolt11;
+use crate::utils::encoding::{from_hex, percent_encode, sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Tagged-field type of a BOLT-11 payment hash
+const PAYMENT_HASH_TAG: u8 = 1;
+/// Tagged-field type of a BOLT-11 description hash
+const DESCRIPTION_HASH_TAG: u8 = 23;
+/// Words of the BOLT-11 timestamp
+const TIMESTAMP_WORDS: usize = 7;
+/// Words of the BOLT-11 recoverable signature
+const SIGNATURE_WORDS: usize = 104;
+/// LUD-05 hardened purpose of the hashing key
+const AUTH_PURPOSE: u32 = 138;
+
+/// Fetches LNURL service responses
+#[async_trait]
+pub trait LnurlTransport: Send + Sync {
+    /// GET `url` and parse the JSON body
+    async fn get_json(&self, url: &str) -> AnyaResult<Value>;
+}
+
+/// Payment parameters of an LNURL-pay service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequest {
+    /// URL to request invoices from
+    pub callback: String,
+    /// Smallest payable amount
+    pub min_sendable: u64,
+    /// Largest payable amount
+    pub max_sendable: u64,
+    /// JSON-encoded metadata the invoice commits to
+    pub metadata: String,
+    /// Longest accepted comment, 0 when comments are not accepted
+    #[serde(default)]
+    pub comment_allowed: usize,
+}
+
+impl PayRequest {
+    /// The `text/plain` description shown to the user
+    pub fn description(&self) -> AnyaResult<String> {
+        let entries: Vec<(String, Value)> = serde_json::from_str(&self.metadata)
+            .map_err(|e| AnyaError::invalid_input(format!("invalid LNURL metadata: {}", e)))?;
+        let mut plain = entries
+            .iter()
+            .filter(|(kind, _)| kind == "text/plain")
+            .filter_map(|(_, v)| v.as_str());
+        match (plain.next(), plain.next()) {
+            (Some(text), None) => Ok(text.to_string()),
+            _ => Err(AnyaError::invalid_input(
+                "LNURL metadata needs exactly one text/plain entry",
+            )),
+        }
+    }
+
+    fn identifies(&self, address: &str) -> AnyaResult<bool> {
+        let entries: Vec<(String, Value)> = serde_json::from_str(&self.metadata)?;
+        Ok(entries.iter().any(|(kind, v)| {
+            (kind == "text/identifier" || kind == "text/email")
+                && v.as_str().is_some_and(|v| v.eq_ignore_ascii_case(address))
+        }))
+    }
+}
+
+/// Parameters of an LNURL-withdraw service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawRequest {
+    /// URL to send the invoice to
+    pub callback: String,
+    /// Secret identifying the withdrawal
+    pub k1: String,
+    /// Description for the invoice
+    #[serde(default)]
+    pub default_description: String,
+    /// Smallest withdrawable amount
+    pub min_withdrawable: u64,
+    /// Largest withdrawable amount
+    pub max_withdrawable: u64,
+}
+
+/// An LNURL-auth login challenge
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthRequest {
+    /// Callback URL carrying the challenge
+    pub url: String,
+    /// Domain the linking key is derived for
+    pub domain: String,
+    /// Hex 32-byte challenge
+    pub k1: String,
+    /// `register`, `login`, `link`, or `auth`, when given
+    pub action: Option<String>,
+}
+
+/// A decoded LNURL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LnurlRequest {
+    /// LNURL-pay or Lightning address
+    Pay(PayRequest),
+    /// LNURL-withdraw
+    Withdraw(WithdrawRequest),
+    /// LNURL-auth
+    Auth(AuthRequest),
+}
+
+/// Action the service asks the wallet to show after paying
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "tag", rename_all = "lowercase")]
+pub enum SuccessAction {
+    /// Text to display
+    Message {
+        /// Message text
+        message: String,
+    },
+    /// Link to offer the user
+    Url {
+        /// Link description
+        description: String,
+        /// Link target
+        url: String,
+    },
+    /// Message encrypted with the payment preimage, shown undecrypted
+    Aes {
+        /// Description shown with the message
+        description: String,
+        /// Base64 AES-CBC ciphertext
+        ciphertext: String,
+        /// Base64 initialization vector
+        iv: String,
+    },
+}
+
+/// Invoice issued by an LNURL-pay service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayInvoice {
+    /// Verified BOLT-11 invoice
+    pub invoice: String,
+    /// Action to show once paid
+    pub success_action: Option<SuccessAction>,
+}
+
+/// LNURL client bound to a transport
+pub struct LnurlClient {
+    transport: Arc<dyn LnurlTransport>,
+}
+
+impl LnurlClient {
+    /// Client fetching through `transport`
+    pub fn new(transport: Arc<dyn LnurlTransport>) -> Self {
+        Self { transport }
+    }
+
+    /// Decode `text` and fetch the service parameters it points to
+    pub async fn resolve(&self, text: &str) -> AnyaResult<LnurlRequest> {
+        let text = text.trim();
+        let address = (text.contains('@') && !text.contains('/')).then_some(text);
+        let url = match address {
+            Some(address) => lightning_address_url(address)?,
+            None => decode_url(text)?,
+        };
+        if query_param(&url, "tag").as_deref() == Some("login") {
+            let k1 = query_param(&url, "k1")
+                .filter(|k1| from_hex(k1).is_ok_and(|b| b.len() == 32))
+                .ok_or_else(|| AnyaError::invalid_input("LNURL-auth needs a 32-byte k1"))?;
+            return Ok(LnurlRequest::Auth(AuthRequest {
+                domain: url_host(&url)?,
+                k1,
+                action: query_param(&url, "action"),
+                url,
+            }));
+        }
+
+        let response = self.get(&url).await?;
+        match response.get("tag").and_then(Value::as_str) {
+            Some("payRequest") => {
+                let pay: PayRequest = serde_json::from_value(response)?;
+                check_url(&pay.callback)?;
+                pay.description()?;
+                if pay.min_sendable == 0 || pay.min_sendable > pay.max_sendable {
+                    return Err(AnyaError::invalid_input("invalid LNURL-pay amount range"));
+                }
+                if let Some(address) = address {
+                    if !pay.identifies(address)? {
+                        return Err(AnyaError::invalid_input(
+                            "Lightning address metadata does not name the address",
+                        ));
+                    }
+                }
+                Ok(LnurlRequest::Pay(pay))
+            }
+            Some("withdrawRequest") => {
+                let withdraw: WithdrawRequest = serde_json::from_value(response)?;
+                check_url(&withdraw.callback)?;
+                if withdraw.min_withdrawable > withdraw.max_withdrawable {
+                    return Err(AnyaError::invalid_input(
+                        "invalid LNURL-withdraw amount range",
+                    ));
+                }
+                Ok(LnurlRequest::Withdraw(withdraw))
+            }
+            tag => Err(AnyaError::invalid_input(format!(
+                "unsupported LNURL tag {:?}",
+                tag
+            ))),
+        }
+    }
+
+    /// Request an invoice for `amount_msat` and check it against `pay`
+    pub async fn request_invoice(
+        &self,
+        pay: &PayRequest,
+        amount_msat: u64,
+        comment: Option<&str>,
+    ) -> AnyaResult<PayInvoice> {
+        if amount_msat < pay.min_sendable || amount_msat > pay.max_sendable {
+            return Err(AnyaError::invalid_input(format!(
+                "amount must be between {} and {} msat",
+                pay.min_sendable, pay.max_sendable
+            )));
+        }
+        let mut params = vec![("amount", amount_msat.to_string())];
+        if let Some(comment) = comment.filter(|c| !c.is_empty()) {
+            if comment.chars().count() > pay.comment_allowed {
+                return Err(AnyaError::invalid_input(format!(
+                    "comment is limited to {} characters",
+                    pay.comment_allowed
+                )));
+            }
+            params.push(("comment", comment.to_string()));
+        }
+
+        let response = self.get(&with_query(&pay.callback, &params)).await?;
+        let invoice = response["pr"]
+            .as_str()
+            .ok_or_else(|| AnyaError::invalid_input("LNURL-pay response has no invoice"))?
+            .to_string();
+        let fields = Bolt11Fields::parse(&invoice)?;
+        if fields.amount_msat != Some(amount_msat) {
+            return Err(AnyaError::invalid_input(
+                "invoice amount does not match the request",
+            ));
+        }
+        if fields.description_hash != Some(sha256(pay.metadata.as_bytes())) {
+            return Err(AnyaError::invalid_input(
+                "invoice description hash does not commit to the metadata",
+            ));
+        }
+        let success_action = match response.get("successAction") {
+            None | Some(Value::Null) => None,
+            Some(action) => Some(serde_json::from_value(action.clone())?),
+        };
+        Ok(PayInvoice {
+            invoice,
+            success_action,
+        })
+    }
+
+    /// Request, verify, and pay an invoice from an LNURL-pay service
+    pub async fn pay(
+        &self,
+        lightning: &MobileLightning,
+        pay: &PayRequest,
+        amount_msat: u64,
+        comment: Option<&str>,
+    ) -> AnyaResult<(PaymentResult, Option<SuccessAction>)> {
+        let invoice = self.request_invoice(pay, amount_msat, comment).await?;
+        let result = lightning.pay(&invoice.invoice, amount_msat).await?;
+        Ok((result, invoice.success_action))
+    }
+
+    /// Create an invoice for `amount_msat` and ask the service to pay it
+    pub async fn withdraw(
+        &self,
+        lightning: &MobileLightning,
+        withdraw: &WithdrawRequest,
+        amount_msat: u64,
+    ) -> AnyaResult<ReceiveOffer> {
+        if amount_msat < withdraw.min_withdrawable || amount_msat > withdraw.max_withdrawable {
+            return Err(AnyaError::invalid_input(format!(
+                "amount must be between {} and {} msat",
+                withdraw.min_withdrawable, withdraw.max_withdrawable
+            )));
+        }
+        let offer = lightning
+            .receive(amount_msat, &withdraw.default_description)
+            .await?;
+        let url = with_query(
+            &withdraw.callback,
+            &[("k1", withdraw.k1.clone()), ("pr", offer.invoice.clone())],
+        );
+        self.get(&url).await?;
+        Ok(offer)
+    }
+
+    /// Sign the challenge with the linking key for the service's domain.
+    ///
+    /// Returns the hex linking public key the service now knows the user by.
+    pub async fn login(&self, master: &ExtendedPrivKey, auth: &AuthRequest) -> AnyaResult<String> {
+        let secp = Secp256k1::new();
+        let key = linking_key(master, &auth.domain)?;
+        let k1 = Message::from_slice(&from_hex(&auth.k1)?)
+            .map_err(|_| AnyaError::invalid_input("k1 must be 32 bytes"))?;
+        let signature = secp.sign_ecdsa(&k1, &key);
+        let public = to_hex(&key.public_key(&secp).serialize());
+        let url = with_query(
+            &auth.url,
+            &[
+                ("sig", to_hex(&signature.serialize_der())),
+                ("key", public.clone()),
+            ],
+        );
+        self.get(&url).await?;
+        Ok(public)
+    }
+
+    async fn get(&self, url: &str) -> AnyaResult<Value> {
+        check_url(url)?;
+        let response = self.transport.get_json(url).await?;
+        if response["status"].as_str() == Some("ERROR") {
+            return Err(AnyaError::new(
+                ErrorCode::NetworkFailure,
+                format!(
+                    "LNURL service error: {}",
+                    response["reason"].as_str().unwrap_or("no reason given")
+                ),
+            ));
+        }
+        Ok(response)
+    }
+}
+
+/// LUD-05 linking key of `master` for `domain`
+pub fn linking_key(master: &ExtendedPrivKey, domain: &str) -> AnyaResult<SecretKey> {
+    let secp = Secp256k1::new();
+    let hardened = |index| {
+        ChildNumber::from_hardened_idx(index)
+            .map_err(|e| AnyaError::with_source(ErrorCode::Internal, "invalid index", e))
+    };
+    let hashing_key =
+        master.derive_priv(&secp, &[hardened(AUTH_PURPOSE)?, ChildNumber::from(0)])?;
+    let tag = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, &hashing_key.private_key.secret_bytes()),
+        domain.as_bytes(),
+    );
+    let mut path = vec![hardened(AUTH_PURPOSE)?];
+    for chunk in tag.as_ref()[..16].chunks(4) {
+        let index = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        path.push(ChildNumber::from(index));
+    }
+    Ok(master.derive_priv(&secp, &path)?.private_key)
+}
+
+/// Service URL a Lightning address (`user@domain`) points to
+pub fn lightning_address_url(address: &str) -> AnyaResult<String> {
+    let (user, domain) = address
+        .split_once('@')
+        .filter(|(u, d)| !u.is_empty() && d.contains('.'))
+        .ok_or_else(|| AnyaError::invalid_input("invalid Lightning address"))?;
+    let scheme = if domain.ends_with(".onion") {
+        "http"
+    } else {
+        "https"
+    };
+    Ok(format!(
+        "{}://{}/.well-known/lnurlp/{}",
+        scheme,
+        domain.to_ascii_lowercase(),
+        percent_encode(&user.to_ascii_lowercase())
+    ))
+}
+
+/// URL encoded by a bech32 LNURL, LUD-17 link, or plain URL
+pub fn decode_url(text: &str) -> AnyaResult<String> {
+    let text = text.trim();
+    let text = text
+        .get(..10)
+        .filter(|p| p.eq_ignore_ascii_case("lightning:"))
+        .map_or(text, |_| &text[10..]);
+    if text
+        .get(..6)
+        .is_some_and(|p| p.eq_ignore_ascii_case("lnurl1"))
+    {
+        let (hrp, data, _) = bech32::decode(text)
+            .map_err(|e| AnyaError::invalid_input(format!("invalid LNURL: {}", e)))?;
+        if hrp != "lnurl" {
+            return Err(AnyaError::invalid_input("invalid LNURL prefix"));
+        }
+        let bytes = Vec::<u8>::from_base32(&data)
+            .map_err(|e| AnyaError::invalid_input(format!("invalid LNURL: {}", e)))?;
+        let url = String::from_utf8(bytes)
+            .map_err(|_| AnyaError::invalid_input("LNURL is not a UTF-8 URL"))?;
+        check_url(&url)?;
+        return Ok(url);
+    }
+    for scheme in ["lnurlp://", "lnurlw://", "keyauth://"] {
+        if let Some(rest) = text.strip_prefix(scheme) {
+            let host = rest.split(['/', '?', ':']).next().unwrap_or_default();
+            let secure = if host.ends_with(".onion") {
+                "http"
+            } else {
+                "https"
+            };
+            return Ok(format!("{}://{}", secure, rest));
+        }
+    }
+    check_url(text)?;
+    Ok(text.to_string())
+}
+
+/// Bech32 LNURL for `url`
+pub fn encode_url(url: &str) -> AnyaResult<String> {
+    bech32::encode("lnurl", url.as_bytes().to_base32(), Variant::Bech32)
+        .map(|s| s.to_ascii_uppercase())
+        .map_err(|e| AnyaError::invalid_input(format!("cannot encode LNURL: {}", e)))
+}
+
+/// Services must use HTTPS unless they are onion services
+fn check_url(url: &str) -> AnyaResult<()> {
+    let host = url_host(url)?;
+    if url.starts_with("https://") || (url.starts_with("http://") && host.ends_with(".onion")) {
+        Ok(())
+    } else {
+        Err(AnyaError::invalid_input(
+            "LNURL services must use https or an onion address",
+        ))
+    }
+}
+
+fn url_host(url: &str) -> AnyaResult<String> {
+    url.split_once("://")
+        .and_then(|(_, rest)| rest.split(['/', '?', '#']).next())
+        .map(|authority| authority.rsplit('@').next().unwrap_or(authority))
+        .map(|host| host.split(':').next().unwrap_or(host).to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| AnyaError::invalid_input(format!("invalid URL {}", url)))
+}
+
+fn query_param(url: &str, key: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| crate::utils::encoding::percent_decode(v).ok())
+}
+
+fn with_query(url: &str, params: &[(&str, String)]) -> String {
+    let query: Vec<String> = params
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, percent_encode(v)))
+        .collect();
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", url, separator, query.join("&"))
+}
+
+/// Fields of a BOLT-11 invoice checked before paying it
+pub(crate) struct Bolt11Fields {
+    pub(crate) amount_msat: Option<u64>,
+    pub(crate) payment_hash: Option<[u8; 32]>,
+    pub(crate) description_hash: Option<[u8; 32]>,
+}
+
+impl Bolt11Fields {
+    pub(crate) fn parse(invoice: &str) -> AnyaResult<Self> {
+        validate_bolt11(invoice)?;
+        let invalid = |what: &str| AnyaError::invalid_input(format!("invalid invoice: {}", what));
+        let (hrp, data, _) =
+            bech32::decode(&invoice.to_ascii_lowercase()).map_err(|_| invalid("checksum"))?;
+
+        let amount = hrp
+            .strip_prefix("ln")
+            .map(|rest| rest.trim_start_matches(|c: char| c.is_ascii_alphabetic()))
+            .unwrap_or_default();
+        let amount_msat = if amount.is_empty() {
+            None
+        } else {
+            let (digits, multiplier) = match amount.char_indices().last() {
+                Some((i, c)) if c.is_ascii_alphabetic() => (&amount[..i], Some(c)),
+                _ => (amount, None),
+            };
+            let value: u64 = digits.parse().map_err(|_| invalid("amount"))?;
+            let msat = match multiplier {
+                None => value.checked_mul(100_000_000_000),
+                Some('m') => value.checked_mul(100_000_000),
+                Some('u') => value.checked_mul(100_000),
+                Some('n') => value.checked_mul(100),
+                Some('p') => Some(value / 10).filter(|msat| msat * 10 == value),
+                _ => None,
+            };
+            Some(msat.ok_or_else(|| invalid("amount"))?)
+        };
+
+        if data.len() < TIMESTAMP_WORDS + SIGNATURE_WORDS {
+            return Err(invalid("too short"));
+        }
+        let mut fields = &data[TIMESTAMP_WORDS..data.len() - SIGNATURE_WORDS];
+        let mut payment_hash = None;
+        let mut description_hash = None;
+        while fields.len() >= 3 {
+            let kind = fields[0].to_u8();
+            let len = usize::from(fields[1].to_u8()) * 32 + usize::from(fields[2].to_u8());
+            let value = fields
+                .get(3..3 + len)
+                .ok_or_else(|| invalid("truncated field"))?;
+            if (kind == PAYMENT_HASH_TAG || kind == DESCRIPTION_HASH_TAG) && len == 52 {
+                let bytes = Vec::<u8>::from_base32(value).map_err(|_| invalid("hash"))?;
+                let hash = bytes.try_into().ok();
+                if kind == PAYMENT_HASH_TAG {
+                    payment_hash = hash;
+                } else {
+                    description_hash = hash;
+                }
+            }
+            fields = &fields[3 + len..];
+        }
+        Ok(Self {
+            amount_msat,
+            payment_hash,
+            description_hash,
+        })
+    }
+}
+
+/// Transport fetching LNURL endpoints over HTTPS
+#[cfg(feature = "http")]
+pub struct HttpLnurlTransport {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "http")]
+impl HttpLnurlTransport {
+    /// Create a transport with a default HTTP client
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Default for HttpLnurlTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl LnurlTransport for HttpLnurlTransport {
+    async fn get_json(&self, url: &str) -> AnyaResult<Value> {
+        Ok(self.client.get(url).send().await?.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::bech32::u5;
+    use ::bitcoin::Network;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockService {
+        responses: HashMap<String, Value>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl LnurlTransport for MockService {
+        async fn get_json(&self, url: &str) -> AnyaResult<Value> {
+            self.requested.lock().unwrap().push(url.to_string());
+            let path = url.split('?').next().unwrap();
+            Ok(self
+                .responses
+                .get(path)
+                .cloned()
+                .unwrap_or_else(|| json!({ "status": "ERROR", "reason": "unknown endpoint" })))
+        }
+    }
+
+    /// Structurally valid invoice with a description hash; the signature is
+    /// zeroed since only the fields are inspected
+    fn invoice(hrp: &str, description_hash: &[u8; 32]) -> String {
+        let word = |v: u8| u5::try_from_u8(v).unwrap();
+        let mut data = vec![word(0); TIMESTAMP_WORDS];
+        let hash = description_hash.to_base32();
+        data.extend([word(DESCRIPTION_HASH_TAG), word(1), word(20)]);
+        data.extend(hash);
+        data.extend(vec![word(0); SIGNATURE_WORDS]);
+        bech32::encode(hrp, data, Variant::Bech32).unwrap()
+    }
+
+    fn pay_service(metadata: &str, pr: &str) -> MockService {
+        let mut service = MockService::default();
+        service.responses.insert(
+            "https://pay.example.com/.well-known/lnurlp/alice".into(),
+            json!({
+                "tag": "payRequest",
+                "callback": "https://pay.example.com/cb/alice",
+                "minSendable": 1_000,
+                "maxSendable": 1_000_000,
+                "metadata": metadata,
+                "commentAllowed": 10,
+            }),
+        );
+        service.responses.insert(
+            "https://pay.example.com/cb/alice".into(),
+            json!({ "pr": pr, "successAction": { "tag": "message", "message": "thanks" } }),
+        );
+        service
+    }
+
+    #[tokio::test]
+    async fn test_lightning_address_pay_checks_invoice() {
+        let metadata = json!([
+            ["text/plain", "Tip alice"],
+            ["text/identifier", "alice@pay.example.com"]
+        ])
+        .to_string();
+        let good = invoice("lnbc5u", &sha256(metadata.as_bytes()));
+        let service = Arc::new(pay_service(&metadata, &good));
+        let client = LnurlClient::new(service.clone());
+
+        let LnurlRequest::Pay(pay) = client.resolve("alice@pay.example.com").await.unwrap() else {
+            panic!("expected a pay request");
+        };
+        assert_eq!(pay.description().unwrap(), "Tip alice");
+        let paid = client
+            .request_invoice(&pay, 500_000, Some("gm"))
+            .await
+            .unwrap();
+        assert_eq!(paid.invoice, good);
+        assert_eq!(
+            paid.success_action,
+            Some(SuccessAction::Message {
+                message: "thanks".into()
+            })
+        );
+        let last = service.requested.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(
+            last,
+            "https://pay.example.com/cb/alice?amount=500000&comment=gm"
+        );
+
+        // Wrong amount, overlong comment, and out-of-range amounts fail
+        assert!(client.request_invoice(&pay, 400_000, None).await.is_err());
+        assert!(client
+            .request_invoice(&pay, 500_000, Some("far too long"))
+            .await
+            .is_err());
+        assert!(client.request_invoice(&pay, 999, None).await.is_err());
+
+        // An invoice not committing to the metadata is refused
+        let other = invoice("lnbc5u", &sha256(b"other"));
+        let client = LnurlClient::new(Arc::new(pay_service(&metadata, &other)));
+        let LnurlRequest::Pay(pay) = client.resolve("alice@pay.example.com").await.unwrap() else {
+            panic!("expected a pay request");
+        };
+        assert!(client.request_invoice(&pay, 500_000, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_links_and_auth_login() {
+        let url = "https://service.example.com/lnurl?tag=login&k1=".to_string() + &"ab".repeat(32);
+        let encoded = encode_url(&url).unwrap();
+        assert_eq!(decode_url(&format!("lightning:{}", encoded)).unwrap(), url);
+        assert_eq!(
+            decode_url("lnurlw://abc.onion/w?q=1").unwrap(),
+            "http://abc.onion/w?q=1"
+        );
+        assert!(decode_url("http://example.com/insecure").is_err());
+
+        let mut service = MockService::default();
+        service.responses.insert(
+            "https://service.example.com/lnurl".into(),
+            json!({ "status": "OK" }),
+        );
+        let service = Arc::new(service);
+        let client = LnurlClient::new(service.clone());
+        let LnurlRequest::Auth(auth) = client.resolve(&encoded).await.unwrap() else {
+            panic!("expected an auth request");
+        };
+        assert_eq!(auth.domain, "service.example.com");
+
+        let master = ExtendedPrivKey::new_master(Network::Bitcoin, &[3; 32]).unwrap();
+        let key = client.login(&master, &auth).await.unwrap();
+        let requested = service.requested.lock().unwrap().last().cloned().unwrap();
+        assert!(requested.contains("&sig=3") && requested.ends_with(&format!("&key={}", key)));
+        // Linking keys differ per domain and are stable
+        assert_eq!(
+            linking_key(&master, "service.example.com").unwrap(),
+            linking_key(&master, "service.example.com").unwrap()
+        );
+        assert_ne!(
+            linking_key(&master, "service.example.com").unwrap(),
+            linking_key(&master, "other.example.com").unwrap()
+        );
+    }
+}
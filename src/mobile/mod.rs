@@ -0,0 +1,138 @@
+//! Mobile wallet support
+//!
+//! Components used by the Anya mobile apps through the FFI bridge: payment
+//! and SeedQR codes, BIP-353 payment names, background sync, local transaction
+//! history, the security gate around signing, air-gapped PSBT signing
+//! under versioned spending policies, Lightning through an LSP with LNURL
+//! flows, channel splicing, multi-part payment routing, automatic
+//! rebalancing, and submarine swaps,
+//! encrypted payment notifications from a paired node, and on-device
+//! inference with quantized models.
+
+use std::sync::Arc;
+
+use ::bitcoin::Network;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::lifecycle::{Subsystem, TaskSpawner};
+use crate::AnyaResult;
+
+pub mod bip353;
+pub mod history;
+pub mod inference;
+pub mod lightning;
+pub mod liquidity;
+pub mod lnurl;
+pub mod push;
+pub mod qr;
+pub mod routing;
+pub mod security;
+pub mod signer;
+pub mod spending;
+pub mod splice;
+pub mod swap;
+pub mod sync;
+pub mod wallet;
+
+use self::lightning::LightningConfig;
+use self::security::SecurityPolicy;
+use self::sync::{PlatformHints, SyncEvent, SyncJob, SyncPolicy, SyncScheduler};
+
+/// Configuration for the mobile subsystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MobileConfig {
+    /// Whether the mobile subsystem is enabled
+    pub enabled: bool,
+    /// Bitcoin network the wallet operates on
+    pub network: Network,
+    /// Whether QR code generation and scanning is enabled
+    pub qr_enabled: bool,
+    /// Background sync policy
+    pub sync: SyncPolicy,
+    /// ISO 4217 currency used for fiat valuation of transactions
+    pub fiat_currency: String,
+    /// Authentication policy for signing and key export
+    pub security: SecurityPolicy,
+    /// Lightning and LSP settings
+    pub lightning: LightningConfig,
+}
+
+impl Default for MobileConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            network: Network::Bitcoin,
+            qr_enabled: true,
+            sync: SyncPolicy::default(),
+            fiat_currency: "USD".to_string(),
+            security: SecurityPolicy::default(),
+            lightning: LightningConfig::default(),
+        }
+    }
+}
+
+/// Entry point used by the FFI bridge
+///
+/// Owns the background [`SyncScheduler`] and runs it as a lifecycle
+/// [`Subsystem`]; the bridge forwards platform hints in and sync events out.
+pub struct MobileManager {
+    config: MobileConfig,
+    sync: Arc<SyncScheduler>,
+}
+
+impl MobileManager {
+    /// Create the manager with the sync jobs it should batch
+    pub fn new(config: MobileConfig, jobs: Vec<Arc<dyn SyncJob>>) -> Self {
+        let mut scheduler = SyncScheduler::new(config.sync.clone());
+        for job in jobs {
+            scheduler.register(job);
+        }
+        Self {
+            config,
+            sync: Arc::new(scheduler),
+        }
+    }
+
+    /// Mobile configuration
+    pub const fn config(&self) -> &MobileConfig {
+        &self.config
+    }
+
+    /// Update the device state reported by the host app
+    pub async fn set_platform_hints(&self, hints: PlatformHints) {
+        self.sync.set_hints(hints).await;
+    }
+
+    /// Sync everything now on user request, returning whether all targets succeeded
+    pub async fn refresh(&self) -> AnyaResult<bool> {
+        self.sync.refresh().await
+    }
+
+    /// Subscribe to sync progress events
+    pub fn subscribe_sync_events(&self) -> broadcast::Receiver<SyncEvent> {
+        self.sync.subscribe()
+    }
+}
+
+#[async_trait]
+impl Subsystem for MobileManager {
+    fn name(&self) -> &str {
+        "mobile"
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let scheduler = Arc::clone(&self.sync);
+        spawner
+            .spawn(
+                "sync",
+                move |token| async move { scheduler.run(token).await },
+            )
+            .await;
+        Ok(())
+    }
+}
@@ -0,0 +1,811 @@
+//! End-to-end encrypted payment notifications from a node to paired phones
+//!
+//! Pairing is a QR handshake. The node shows a [`PairingOffer`] holding an
+//! ephemeral X25519 key and a one-time secret; the phone scans it, answers
+//! with its own ephemeral key in a [`PairingResponse`] authenticated by an
+//! HMAC over the secret, and both sides derive the same ChaCha20-Poly1305
+//! key with HKDF. Nothing that can decrypt notifications ever leaves the two
+//! devices, so the delivery path — a push relay or a Nostr relay — only sees
+//! opaque [`Envelope`]s.
+//!
+//! Each notification carries a per-device sequence number inside the
+//! ciphertext, and [`PushReceiver`] drops anything it has already seen.
+//!
+//! On-chain payments reach the notifier through [`TrackerPushSink`], which
+//! turns wallet transaction tracker events into [`PaymentEvent`]s.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::secp256k1::{KeyPair, Message, Secp256k1};
+use ::bitcoin::{Address, Network};
+use async_trait::async_trait;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hkdf, hmac};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::bitcoin::tracker::{TxEvent, TxEventSink};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{from_hex, percent_decode, percent_encode, sha256, to_hex};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "push_devices";
+const DEVICE_PREFIX: &str = "device/";
+const PAIRING_SCHEME: &str = "anya-pair:";
+const KEY_INFO: &[u8] = b"anya-push-v1";
+const SECRET_LEN: usize = 16;
+
+/// Nostr event kind used for notification envelopes.
+///
+/// An ephemeral kind is used rather than NIP-04 DMs because the content is
+/// already end-to-end encrypted with the pairing key.
+pub const NOSTR_EVENT_KIND: u16 = 20_444;
+
+/// Payment event a node reports to its paired wallets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PaymentEvent {
+    /// An address received an on-chain payment
+    AddressFunded {
+        /// Receiving address
+        address: String,
+        /// Funding transaction id
+        txid: String,
+        /// Amount received in satoshis
+        amount_sat: u64,
+        /// Confirmations when the notification was sent
+        confirmations: u32,
+    },
+    /// A Lightning invoice was paid
+    InvoicePaid {
+        /// Hex payment hash
+        payment_hash: String,
+        /// Amount received in millisatoshis
+        amount_msat: u64,
+    },
+}
+
+/// Decrypted notification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    /// Per-device sequence number
+    pub sequence: u64,
+    /// Unix time the node sent the notification
+    pub timestamp: u64,
+    /// What happened
+    pub event: PaymentEvent,
+}
+
+/// Encrypted notification as seen by relays
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Recipient device
+    pub device_id: String,
+    /// Hex ChaCha20-Poly1305 nonce
+    pub nonce: String,
+    /// Hex ciphertext and tag
+    pub ciphertext: String,
+}
+
+/// How a paired device wants to be reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeliveryChannel {
+    /// Push relay that forwards to APNs/FCM
+    PushRelay {
+        /// Relay endpoint
+        url: String,
+        /// Platform push token
+        token: String,
+    },
+    /// Nostr relay, addressed to the device's public key
+    Nostr {
+        /// Relay websocket URL
+        relay: String,
+        /// Hex x-only public key of the device
+        pubkey: String,
+    },
+}
+
+/// QR payload shown by the node to start pairing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairingOffer {
+    /// Identifier of the node
+    pub node_id: String,
+    /// Hex X25519 public key of the node
+    pub node_key: String,
+    /// Hex one-time pairing secret
+    pub secret: String,
+    /// Unix time after which the offer is rejected
+    pub expires_at: u64,
+}
+
+impl PairingOffer {
+    /// Encode as `anya-pair:<percent-encoded JSON>`
+    pub fn to_qr_string(&self) -> AnyaResult<String> {
+        Ok(format!(
+            "{}{}",
+            PAIRING_SCHEME,
+            percent_encode(&serde_json::to_string(self)?)
+        ))
+    }
+
+    /// Parse a scanned pairing QR code
+    pub fn parse(text: &str) -> AnyaResult<Self> {
+        let body = text
+            .trim()
+            .strip_prefix(PAIRING_SCHEME)
+            .ok_or_else(|| AnyaError::invalid_input("not a pairing payload"))?;
+        Ok(serde_json::from_str(&percent_decode(body)?)?)
+    }
+}
+
+/// Phone's answer to a [`PairingOffer`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairingResponse {
+    /// Hex SHA-256 of the offer secret, identifying which offer is answered
+    pub offer_secret_hash: String,
+    /// Stable device identifier
+    pub device_id: String,
+    /// Human-readable device name
+    pub device_name: String,
+    /// Hex X25519 public key of the device
+    pub device_key: String,
+    /// How the device wants to be reached
+    pub channel: DeliveryChannel,
+    /// Hex HMAC-SHA256 over the response fields, keyed by the offer secret
+    pub proof: String,
+}
+
+impl PairingResponse {
+    fn proof_message(&self) -> Vec<u8> {
+        format!(
+            "{}|{}|{}",
+            self.device_id, self.device_key, self.offer_secret_hash
+        )
+        .into_bytes()
+    }
+}
+
+/// A phone paired with this node
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PairedDevice {
+    /// Stable device identifier
+    pub device_id: String,
+    /// Human-readable device name
+    pub device_name: String,
+    /// Delivery channel
+    pub channel: DeliveryChannel,
+    /// Unix time of pairing
+    pub paired_at: u64,
+    /// Sequence number for the next notification
+    pub next_sequence: u64,
+    key: String,
+}
+
+impl fmt::Debug for PairedDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PairedDevice")
+            .field("device_id", &self.device_id)
+            .field("device_name", &self.device_name)
+            .field("channel", &self.channel)
+            .field("paired_at", &self.paired_at)
+            .field("next_sequence", &self.next_sequence)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Delivers envelopes to devices over one kind of [`DeliveryChannel`]
+#[async_trait]
+pub trait NotificationTransport: Send + Sync {
+    /// Whether this transport handles `channel`
+    fn supports(&self, channel: &DeliveryChannel) -> bool;
+
+    /// Deliver an envelope
+    async fn deliver(&self, channel: &DeliveryChannel, envelope: &Envelope) -> AnyaResult<()>;
+}
+
+struct PendingPairing {
+    private_key: EphemeralPrivateKey,
+    secret: Vec<u8>,
+    expires_at: u64,
+}
+
+/// Node-side pairing and notification fan-out
+pub struct PushNotifier {
+    node_id: String,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    rng: SystemRandom,
+    transports: Vec<Arc<dyn NotificationTransport>>,
+    pending: Mutex<HashMap<String, PendingPairing>>,
+    // Serializes fan-out so sequence numbers are never reused
+    notify_lock: Mutex<()>,
+}
+
+impl PushNotifier {
+    /// Create a notifier storing paired devices in `storage`
+    pub async fn open(
+        node_id: impl Into<String>,
+        storage: Arc<dyn StorageBackend>,
+        transports: Vec<Arc<dyn NotificationTransport>>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            node_id: node_id.into(),
+            storage,
+            ns,
+            rng: SystemRandom::new(),
+            transports,
+            pending: Mutex::new(HashMap::new()),
+            notify_lock: Mutex::new(()),
+        })
+    }
+
+    /// Start pairing; the offer is valid for `ttl` and can be used once
+    pub async fn begin_pairing(&self, ttl: Duration) -> AnyaResult<PairingOffer> {
+        let private_key = EphemeralPrivateKey::generate(&X25519, &self.rng)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "system RNG failure"))?;
+        let public_key = private_key
+            .compute_public_key()
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "X25519 key generation failed"))?;
+        let mut secret = vec![0u8; SECRET_LEN];
+        self.rng
+            .fill(&mut secret)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "system RNG failure"))?;
+
+        let offer = PairingOffer {
+            node_id: self.node_id.clone(),
+            node_key: to_hex(public_key.as_ref()),
+            secret: to_hex(&secret),
+            expires_at: unix_now() + ttl.as_secs(),
+        };
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, p| p.expires_at > unix_now());
+        pending.insert(
+            to_hex(&sha256(&secret)),
+            PendingPairing {
+                private_key,
+                secret,
+                expires_at: offer.expires_at,
+            },
+        );
+        drop(pending);
+        Ok(offer)
+    }
+
+    /// Verify a device's response and store the pairing
+    pub async fn complete_pairing(&self, response: &PairingResponse) -> AnyaResult<PairedDevice> {
+        let pending = self
+            .pending
+            .lock()
+            .await
+            .remove(&response.offer_secret_hash)
+            .filter(|p| p.expires_at > unix_now())
+            .ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::Unauthenticated,
+                    "pairing offer unknown or expired",
+                )
+            })?;
+        let proof = from_hex(&response.proof)?;
+        hmac::verify(
+            &hmac::Key::new(hmac::HMAC_SHA256, &pending.secret),
+            &response.proof_message(),
+            &proof,
+        )
+        .map_err(|_| AnyaError::new(ErrorCode::Unauthenticated, "invalid pairing proof"))?;
+
+        let key = derive_key(
+            pending.private_key,
+            &from_hex(&response.device_key)?,
+            &pending.secret,
+        )?;
+        let device = PairedDevice {
+            device_id: response.device_id.clone(),
+            device_name: response.device_name.clone(),
+            channel: response.channel.clone(),
+            paired_at: unix_now(),
+            next_sequence: 1,
+            key: to_hex(&key),
+        };
+        self.save(&device).await?;
+        tracing::info!(device = %device.device_id, "mobile device paired for notifications");
+        Ok(device)
+    }
+
+    /// Remove a paired device, returning whether it existed
+    pub async fn unpair(&self, device_id: &str) -> AnyaResult<bool> {
+        self.storage
+            .delete(&self.ns, &format!("{}{}", DEVICE_PREFIX, device_id))
+            .await
+    }
+
+    /// All paired devices
+    pub async fn devices(&self) -> AnyaResult<Vec<PairedDevice>> {
+        self.storage
+            .scan_prefix(&self.ns, DEVICE_PREFIX)
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| serde_json::from_slice(&bytes).map_err(Into::into))
+            .collect()
+    }
+
+    /// Encrypt `event` for every paired device and hand it to the transports.
+    ///
+    /// Returns how many devices it was delivered to; delivery failures are
+    /// logged and do not stop the fan-out.
+    pub async fn notify(&self, event: &PaymentEvent) -> AnyaResult<usize> {
+        let _guard = self.notify_lock.lock().await;
+        let mut delivered = 0;
+        for mut device in self.devices().await? {
+            let notification = Notification {
+                sequence: device.next_sequence,
+                timestamp: unix_now(),
+                event: event.clone(),
+            };
+            let envelope = seal(&self.rng, &device, &notification)?;
+            device.next_sequence += 1;
+            self.save(&device).await?;
+
+            let Some(transport) = self.transports.iter().find(|t| t.supports(&device.channel))
+            else {
+                tracing::warn!(device = %device.device_id, "no transport for delivery channel");
+                continue;
+            };
+            match transport.deliver(&device.channel, &envelope).await {
+                Ok(()) => delivered += 1,
+                Err(e) => {
+                    tracing::warn!(device = %device.device_id, error = %e, "notification delivery failed");
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    async fn save(&self, device: &PairedDevice) -> AnyaResult<()> {
+        let bytes = serde_json::to_vec(device)?;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", DEVICE_PREFIX, device.device_id),
+                &bytes,
+            )
+            .await
+    }
+}
+
+/// Forwards payments seen by the wallet transaction tracker to paired devices
+pub struct TrackerPushSink {
+    notifier: Arc<PushNotifier>,
+    network: Network,
+}
+
+impl TrackerPushSink {
+    /// Sink notifying through `notifier`, rendering addresses for `network`
+    pub const fn new(notifier: Arc<PushNotifier>, network: Network) -> Self {
+        Self { notifier, network }
+    }
+}
+
+#[async_trait]
+impl TxEventSink for TrackerPushSink {
+    async fn handle(&self, event: &TxEvent) -> AnyaResult<()> {
+        let (txid, received, confirmations) = match event {
+            TxEvent::FirstSeen {
+                txid,
+                received,
+                confirmations,
+            }
+            | TxEvent::Confirmed {
+                txid,
+                received,
+                confirmations,
+                ..
+            } => (txid, received, *confirmations),
+            _ => return Ok(()),
+        };
+        for output in received {
+            let address = Address::from_script(&output.script_pubkey, self.network).map_or_else(
+                |_| to_hex(output.script_pubkey.as_bytes()),
+                |a| a.to_string(),
+            );
+            let event = PaymentEvent::AddressFunded {
+                address,
+                txid: txid.to_string(),
+                amount_sat: output.amount_sat,
+                confirmations,
+            };
+            self.notifier.notify(&event).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Phone-side decryption of notifications from one paired node
+pub struct PushReceiver {
+    device_id: String,
+    key: LessSafeKey,
+    last_sequence: u64,
+}
+
+impl PushReceiver {
+    /// Answer a scanned offer, returning the response to send to the node
+    pub fn accept(
+        offer: &PairingOffer,
+        device_id: impl Into<String>,
+        device_name: impl Into<String>,
+        channel: DeliveryChannel,
+    ) -> AnyaResult<(PairingResponse, Self)> {
+        if offer.expires_at <= unix_now() {
+            return Err(AnyaError::invalid_input("pairing offer expired"));
+        }
+        let rng = SystemRandom::new();
+        let private_key = EphemeralPrivateKey::generate(&X25519, &rng)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "system RNG failure"))?;
+        let public_key = private_key
+            .compute_public_key()
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "X25519 key generation failed"))?;
+        let secret = from_hex(&offer.secret)?;
+        let key = derive_key(private_key, &from_hex(&offer.node_key)?, &secret)?;
+
+        let mut response = PairingResponse {
+            offer_secret_hash: to_hex(&sha256(&secret)),
+            device_id: device_id.into(),
+            device_name: device_name.into(),
+            device_key: to_hex(public_key.as_ref()),
+            channel,
+            proof: String::new(),
+        };
+        let tag = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, &secret),
+            &response.proof_message(),
+        );
+        response.proof = to_hex(tag.as_ref());
+        let receiver = Self {
+            device_id: response.device_id.clone(),
+            key: aead_key(&key)?,
+            last_sequence: 0,
+        };
+        Ok((response, receiver))
+    }
+
+    /// Decrypt an envelope, rejecting replays and envelopes for other devices
+    pub fn open(&mut self, envelope: &Envelope) -> AnyaResult<Notification> {
+        if envelope.device_id != self.device_id {
+            return Err(AnyaError::invalid_input(
+                "envelope addressed to another device",
+            ));
+        }
+        let nonce: [u8; NONCE_LEN] = from_hex(&envelope.nonce)?
+            .try_into()
+            .map_err(|_| AnyaError::invalid_input("invalid envelope nonce"))?;
+        let mut data = from_hex(&envelope.ciphertext)?;
+        let plaintext = self
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(envelope.device_id.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_| {
+                AnyaError::new(ErrorCode::Unauthenticated, "notification failed to decrypt")
+            })?;
+        let notification: Notification = serde_json::from_slice(plaintext)?;
+        if notification.sequence <= self.last_sequence {
+            return Err(AnyaError::new(ErrorCode::Conflict, "replayed notification"));
+        }
+        self.last_sequence = notification.sequence;
+        Ok(notification)
+    }
+}
+
+/// Signed Nostr event carrying an [`Envelope`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NostrEvent {
+    /// Hex event id
+    pub id: String,
+    /// Hex x-only public key of the node
+    pub pubkey: String,
+    /// Unix creation time
+    pub created_at: u64,
+    /// Event kind, [`NOSTR_EVENT_KIND`]
+    pub kind: u16,
+    /// Tags; a single `p` tag addresses the device
+    pub tags: Vec<Vec<String>>,
+    /// JSON-encoded envelope
+    pub content: String,
+    /// Hex BIP-340 signature over the id
+    pub sig: String,
+}
+
+impl NostrEvent {
+    /// Build and sign an event addressed to `recipient`
+    pub fn envelope(keys: &KeyPair, recipient: &str, envelope: &Envelope) -> AnyaResult<Self> {
+        let secp = Secp256k1::signing_only();
+        let pubkey = to_hex(&keys.x_only_public_key().0.serialize());
+        let created_at = unix_now();
+        let tags = vec![vec!["p".to_string(), recipient.to_string()]];
+        let content = serde_json::to_string(envelope)?;
+        let id = sha256(
+            serde_json::to_string(&(0, &pubkey, created_at, NOSTR_EVENT_KIND, &tags, &content))?
+                .as_bytes(),
+        );
+        let message = Message::from_slice(&id)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "invalid Nostr event id"))?;
+        let sig = secp.sign_schnorr_no_aux_rand(&message, keys);
+        Ok(Self {
+            id: to_hex(&id),
+            pubkey,
+            created_at,
+            kind: NOSTR_EVENT_KIND,
+            tags,
+            content,
+            sig: to_hex(sig.as_ref()),
+        })
+    }
+}
+
+/// Publishes events to a Nostr relay
+#[async_trait]
+pub trait NostrRelay: Send + Sync {
+    /// Publish `event` to the relay at `url`
+    async fn publish(&self, url: &str, event: &NostrEvent) -> AnyaResult<()>;
+}
+
+/// Delivers envelopes as Nostr events signed with the node's key
+pub struct NostrTransport {
+    keys: KeyPair,
+    relay: Arc<dyn NostrRelay>,
+}
+
+impl NostrTransport {
+    /// Create a transport signing with `keys`
+    pub fn new(keys: KeyPair, relay: Arc<dyn NostrRelay>) -> Self {
+        Self { keys, relay }
+    }
+}
+
+#[async_trait]
+impl NotificationTransport for NostrTransport {
+    fn supports(&self, channel: &DeliveryChannel) -> bool {
+        matches!(channel, DeliveryChannel::Nostr { .. })
+    }
+
+    async fn deliver(&self, channel: &DeliveryChannel, envelope: &Envelope) -> AnyaResult<()> {
+        let DeliveryChannel::Nostr { relay, pubkey } = channel else {
+            return Err(AnyaError::invalid_input("not a Nostr delivery channel"));
+        };
+        let event = NostrEvent::envelope(&self.keys, pubkey, envelope)?;
+        self.relay.publish(relay, &event).await
+    }
+}
+
+/// Delivers envelopes through an HTTP push relay
+#[cfg(feature = "http")]
+pub struct PushRelayTransport {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "http")]
+impl PushRelayTransport {
+    /// Create a transport with a default HTTP client
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Default for PushRelayTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl NotificationTransport for PushRelayTransport {
+    fn supports(&self, channel: &DeliveryChannel) -> bool {
+        matches!(channel, DeliveryChannel::PushRelay { .. })
+    }
+
+    async fn deliver(&self, channel: &DeliveryChannel, envelope: &Envelope) -> AnyaResult<()> {
+        let DeliveryChannel::PushRelay { url, token } = channel else {
+            return Err(AnyaError::invalid_input("not a push relay channel"));
+        };
+        self.client
+            .post(url)
+            .json(&serde_json::json!({ "token": token, "envelope": envelope }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+fn derive_key(private_key: EphemeralPrivateKey, peer: &[u8], salt: &[u8]) -> AnyaResult<[u8; 32]> {
+    let peer = UnparsedPublicKey::new(&X25519, peer);
+    agreement::agree_ephemeral(
+        private_key,
+        &peer,
+        AnyaError::invalid_input("invalid pairing public key"),
+        |shared| {
+            let mut key = [0u8; 32];
+            hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+                .extract(shared)
+                .expand(&[KEY_INFO], hkdf::HKDF_SHA256)
+                .and_then(|okm| okm.fill(&mut key))
+                .map_err(|_| AnyaError::new(ErrorCode::Internal, "key derivation failed"))?;
+            Ok(key)
+        },
+    )
+}
+
+fn aead_key(key: &[u8]) -> AnyaResult<LessSafeKey> {
+    UnboundKey::new(&CHACHA20_POLY1305, key)
+        .map(LessSafeKey::new)
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "invalid notification key"))
+}
+
+fn seal(
+    rng: &SystemRandom,
+    device: &PairedDevice,
+    notification: &Notification,
+) -> AnyaResult<Envelope> {
+    let key = aead_key(&from_hex(&device.key)?)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut nonce)
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "system RNG failure"))?;
+    let mut data = serde_json::to_vec(notification)?;
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(device.device_id.as_bytes()),
+        &mut data,
+    )
+    .map_err(|_| AnyaError::new(ErrorCode::Internal, "notification encryption failed"))?;
+    Ok(Envelope {
+        device_id: device.device_id.clone(),
+        nonce: to_hex(&nonce),
+        ciphertext: to_hex(&data),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::tracker::ReceivedOutput;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::ScriptBuf;
+
+    #[derive(Default)]
+    struct Outbox(Mutex<Vec<Envelope>>);
+
+    #[async_trait]
+    impl NotificationTransport for Outbox {
+        fn supports(&self, _channel: &DeliveryChannel) -> bool {
+            true
+        }
+
+        async fn deliver(&self, _channel: &DeliveryChannel, envelope: &Envelope) -> AnyaResult<()> {
+            self.0.lock().await.push(envelope.clone());
+            Ok(())
+        }
+    }
+
+    fn channel() -> DeliveryChannel {
+        DeliveryChannel::PushRelay {
+            url: "https://push.example".into(),
+            token: "apns-token".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pairing_and_encrypted_delivery() {
+        let outbox = Arc::new(Outbox::default());
+        let notifier = Arc::new(
+            PushNotifier::open(
+                "node-1",
+                Arc::new(MemoryBackend::new()),
+                vec![outbox.clone()],
+            )
+            .await
+            .unwrap(),
+        );
+
+        let qr = notifier
+            .begin_pairing(Duration::from_secs(300))
+            .await
+            .unwrap()
+            .to_qr_string()
+            .unwrap();
+        let offer = PairingOffer::parse(&qr).unwrap();
+        let (response, mut receiver) =
+            PushReceiver::accept(&offer, "phone-1", "Pixel", channel()).unwrap();
+        notifier.complete_pairing(&response).await.unwrap();
+        // Offers are single use
+        assert!(notifier.complete_pairing(&response).await.is_err());
+
+        let event = PaymentEvent::InvoicePaid {
+            payment_hash: "ab".repeat(32),
+            amount_msat: 21_000,
+        };
+        assert_eq!(notifier.notify(&event).await.unwrap(), 1);
+        let envelope = outbox.0.lock().await[0].clone();
+        assert!(!envelope.ciphertext.contains("21000"));
+
+        let notification = receiver.open(&envelope).unwrap();
+        assert_eq!(notification.event, event);
+        assert_eq!(notification.sequence, 1);
+        assert_eq!(
+            receiver.open(&envelope).unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+
+        let sink = TrackerPushSink::new(Arc::clone(&notifier), Network::Bitcoin);
+        let script = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+        let received = ReceivedOutput {
+            vout: 0,
+            script_pubkey: ScriptBuf::from_bytes(from_hex(script).unwrap()),
+            amount_sat: 50_000,
+        };
+        let txid = "a".repeat(64).parse().unwrap();
+        let seen = TxEvent::FirstSeen {
+            txid,
+            received: vec![received],
+            confirmations: 0,
+        };
+        sink.handle(&seen).await.unwrap();
+        sink.handle(&TxEvent::Evicted { txid }).await.unwrap();
+        let envelope = outbox.0.lock().await[1].clone();
+        assert_eq!(outbox.0.lock().await.len(), 2);
+        let PaymentEvent::AddressFunded { address, .. } = receiver.open(&envelope).unwrap().event
+        else {
+            panic!("expected an on-chain payment");
+        };
+        assert_eq!(address, "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+    }
+
+    #[tokio::test]
+    async fn test_forged_pairing_response_is_rejected() {
+        let notifier = PushNotifier::open("node-1", Arc::new(MemoryBackend::new()), Vec::new())
+            .await
+            .unwrap();
+        let offer = notifier
+            .begin_pairing(Duration::from_secs(300))
+            .await
+            .unwrap();
+        let (mut response, _) =
+            PushReceiver::accept(&offer, "phone-1", "Pixel", channel()).unwrap();
+        response.device_id = "attacker".into();
+        let err = notifier.complete_pairing(&response).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+        assert!(notifier.devices().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_nostr_event_signature_verifies() {
+        let secp = Secp256k1::new();
+        let keys = KeyPair::from_seckey_slice(&secp, &[3u8; 32]).unwrap();
+        let envelope = Envelope {
+            device_id: "phone-1".into(),
+            nonce: "00".repeat(12),
+            ciphertext: "ff".into(),
+        };
+        let event = NostrEvent::envelope(&keys, &"11".repeat(32), &envelope).unwrap();
+        let sig =
+            ::bitcoin::secp256k1::schnorr::Signature::from_slice(&from_hex(&event.sig).unwrap())
+                .unwrap();
+        let message = Message::from_slice(&from_hex(&event.id).unwrap()).unwrap();
+        secp.verify_schnorr(&sig, &message, &keys.x_only_public_key().0)
+            .unwrap();
+    }
+}
@@ -0,0 +1,708 @@
+//! QR code payloads for the mobile wallet
+//!
+//! Supported payloads:
+//! - BIP-21 `bitcoin:` URIs, including unified URIs carrying a BOLT-11
+//!   invoice in the `lightning` parameter
+//! - bare `lightning:` invoices
+//! - PSBTs and other binary blobs split into animated BBQr fragments
+//! - DID exchange payloads used when pairing with another Web5 agent
+//! - BIP-353 names (`₿user@domain`), resolved with [`super::bip353`]
+//! - Standard SeedQR seed backups, and rendering of standard or compact
+//!   SeedQR codes for exporting a seed to an air-gapped signer
+//!
+//! Scanning is done by the platform camera; this module parses the scanned
+//! text with [`QrPayload::parse`] and renders outgoing payloads with
+//! [`QrService::render_svg`]. The UR (`ur:crypto-psbt`) animated format is
+//! not supported; BBQr is used for multi-frame PSBT exchange instead.
+
+use std::str::FromStr;
+
+use ::bitcoin::address::NetworkUnchecked;
+use ::bitcoin::{Address, Amount, Denomination, Network};
+use qrcode::{EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+
+use super::bip353::HumanReadableName;
+use super::push::PairingOffer;
+use super::MobileConfig;
+use crate::bitcoin::seedqr::{SeedQr, SeedQrFormat};
+use crate::utils::encoding::{
+    base32_decode, base32_encode, from_hex, percent_decode, percent_encode, to_hex,
+};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// BIP-21 payment URI, optionally unified with a Lightning invoice
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentUri {
+    /// On-chain address; may be empty for Lightning-only URIs
+    pub address: String,
+    /// Requested amount
+    #[serde(default, with = "::bitcoin::amount::serde::as_sat::opt")]
+    pub amount: Option<Amount>,
+    /// Label for the recipient
+    pub label: Option<String>,
+    /// Message describing the payment
+    pub message: Option<String>,
+    /// BOLT-11 invoice for unified payments
+    pub lightning: Option<String>,
+    /// Other non-required parameters, preserved in order
+    pub extras: Vec<(String, String)>,
+}
+
+impl PaymentUri {
+    /// URI for an on-chain address
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            amount: None,
+            label: None,
+            message: None,
+            lightning: None,
+            extras: Vec::new(),
+        }
+    }
+
+    /// Parse a `bitcoin:` URI.
+    ///
+    /// Unknown `req-` parameters cause an error as required by BIP-21.
+    pub fn parse(uri: &str) -> AnyaResult<Self> {
+        let rest = strip_scheme(uri, "bitcoin:")
+            .ok_or_else(|| AnyaError::invalid_input("not a bitcoin: URI"))?;
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut parsed = Self::new(address);
+
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = key.to_ascii_lowercase();
+            let value = percent_decode(value)?;
+            match key.as_str() {
+                "amount" => {
+                    parsed.amount = Some(
+                        Amount::from_str_in(&value, Denomination::Bitcoin).map_err(|e| {
+                            AnyaError::invalid_input(format!("invalid BIP-21 amount: {}", e))
+                        })?,
+                    );
+                }
+                "label" => parsed.label = Some(value),
+                "message" => parsed.message = Some(value),
+                "lightning" => {
+                    validate_bolt11(&value)?;
+                    parsed.lightning = Some(value);
+                }
+                k if k.starts_with("req-") => {
+                    return Err(AnyaError::invalid_input(format!(
+                        "unsupported required BIP-21 parameter {}",
+                        k
+                    )));
+                }
+                _ => parsed.extras.push((key, value)),
+            }
+        }
+
+        let other_destination = parsed.silent_payment().is_some() || parsed.offer().is_some();
+        if parsed.address.is_empty() && parsed.lightning.is_none() && !other_destination {
+            return Err(AnyaError::invalid_input("BIP-21 URI has no destination"));
+        }
+        Ok(parsed)
+    }
+
+    /// Silent payment address from the `sp` parameter
+    pub fn silent_payment(&self) -> Option<&str> {
+        self.extra("sp")
+    }
+
+    /// BOLT-12 offer from the `lno` parameter
+    pub fn offer(&self) -> Option<&str> {
+        self.extra("lno")
+    }
+
+    fn extra(&self, key: &str) -> Option<&str> {
+        self.extras
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Check that the on-chain address (if any) is valid for `network`
+    pub fn validate_for(&self, network: Network) -> AnyaResult<()> {
+        if self.address.is_empty() {
+            return Ok(());
+        }
+        let address = Address::<NetworkUnchecked>::from_str(&self.address)?;
+        if !address.is_valid_for_network(network) {
+            return Err(AnyaError::invalid_input(format!(
+                "address {} is not valid for {}",
+                self.address, network
+            )));
+        }
+        Ok(())
+    }
+
+    /// Serialize as a `bitcoin:` URI.
+    ///
+    /// The scheme and bech32 address are uppercased when the URI carries no
+    /// other lowercase content, which lets QR encoders use alphanumeric mode.
+    pub fn to_uri(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!("amount={}", format_btc(amount)));
+        }
+        if let Some(label) = &self.label {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+        if let Some(message) = &self.message {
+            params.push(format!("message={}", percent_encode(message)));
+        }
+        if let Some(invoice) = &self.lightning {
+            params.push(format!("lightning={}", invoice.to_ascii_uppercase()));
+        }
+        for (k, v) in &self.extras {
+            params.push(format!("{}={}", k, percent_encode(v)));
+        }
+
+        let bech32 = self.address.to_ascii_lowercase().starts_with("bc1")
+            || self.address.to_ascii_lowercase().starts_with("tb1")
+            || self.address.to_ascii_lowercase().starts_with("bcrt1");
+        let uppercase =
+            bech32 && self.label.is_none() && self.message.is_none() && self.extras.is_empty();
+        let head = if uppercase {
+            format!("BITCOIN:{}", self.address.to_ascii_uppercase())
+        } else {
+            format!("bitcoin:{}", self.address)
+        };
+        if params.is_empty() {
+            head
+        } else {
+            format!("{}?{}", head, params.join("&"))
+        }
+    }
+}
+
+fn format_btc(amount: Amount) -> String {
+    let s = amount.to_string_in(Denomination::Bitcoin);
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        s
+    }
+}
+
+fn strip_scheme<'a>(s: &'a str, scheme: &str) -> Option<&'a str> {
+    s.get(..scheme.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(scheme))
+        .map(|_| &s[scheme.len()..])
+}
+
+/// Basic structural validation of a BOLT-11 invoice string
+pub fn validate_bolt11(invoice: &str) -> AnyaResult<()> {
+    let lower = invoice.to_ascii_lowercase();
+    let known_prefix = ["lnbcrt", "lntbs", "lntb", "lnbc", "lnsb"]
+        .iter()
+        .any(|p| lower.starts_with(p));
+    let separator = lower.rfind('1');
+    let data_ok = separator.is_some_and(|i| {
+        lower.len() - i > 7
+            && lower[i + 1..]
+                .chars()
+                .all(|c| "qpzry9x8gf2tvdw0s3jn54khce6mua7l".contains(c))
+    });
+    if known_prefix && data_ok {
+        Ok(())
+    } else {
+        Err(AnyaError::invalid_input("malformed BOLT-11 invoice"))
+    }
+}
+
+/// Payload used to exchange DIDs when pairing two Web5 agents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidExchange {
+    /// DID of the presenting party
+    pub did: String,
+    /// Optional DWN or messaging endpoint
+    pub endpoint: Option<String>,
+    /// One-time challenge the counterparty must sign
+    pub challenge: String,
+}
+
+const DID_EXCHANGE_SCHEME: &str = "web5-did:";
+
+impl DidExchange {
+    /// Encode as `web5-did:<percent-encoded JSON>`
+    pub fn to_qr_string(&self) -> AnyaResult<String> {
+        Ok(format!(
+            "{}{}",
+            DID_EXCHANGE_SCHEME,
+            percent_encode(&serde_json::to_string(self)?)
+        ))
+    }
+
+    fn parse(s: &str) -> AnyaResult<Self> {
+        let body = strip_scheme(s, DID_EXCHANGE_SCHEME)
+            .ok_or_else(|| AnyaError::invalid_input("not a DID exchange payload"))?;
+        let exchange: Self = serde_json::from_str(&percent_decode(body)?)?;
+        if !exchange.did.starts_with("did:") || exchange.challenge.is_empty() {
+            return Err(AnyaError::invalid_input("invalid DID exchange payload"));
+        }
+        Ok(exchange)
+    }
+}
+
+/// BBQr content encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BbqrEncoding {
+    /// Uppercase hex
+    Hex,
+    /// RFC 4648 base32 without padding
+    Base32,
+}
+
+impl BbqrEncoding {
+    const fn code(self) -> char {
+        match self {
+            Self::Hex => 'H',
+            Self::Base32 => '2',
+        }
+    }
+
+    /// Characters per fragment must be a multiple of this
+    const fn alignment(self) -> usize {
+        match self {
+            Self::Hex => 2,
+            Self::Base32 => 8,
+        }
+    }
+}
+
+/// BBQr file type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BbqrFileType {
+    /// Partially signed Bitcoin transaction
+    Psbt,
+    /// Signed raw transaction
+    Transaction,
+    /// JSON document
+    Json,
+    /// UTF-8 text
+    Text,
+}
+
+impl BbqrFileType {
+    const fn code(self) -> char {
+        match self {
+            Self::Psbt => 'P',
+            Self::Transaction => 'T',
+            Self::Json => 'J',
+            Self::Text => 'U',
+        }
+    }
+
+    fn from_code(c: char) -> AnyaResult<Self> {
+        match c {
+            'P' => Ok(Self::Psbt),
+            'T' => Ok(Self::Transaction),
+            'J' => Ok(Self::Json),
+            'U' => Ok(Self::Text),
+            _ => Err(AnyaError::invalid_input(format!(
+                "unsupported BBQr file type {}",
+                c
+            ))),
+        }
+    }
+}
+
+/// One frame of an animated BBQr sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BbqrFragment {
+    /// Content encoding
+    pub encoding: BbqrEncoding,
+    /// File type
+    pub file_type: BbqrFileType,
+    /// Total number of fragments
+    pub total: usize,
+    /// Zero-based fragment index
+    pub index: usize,
+    /// Encoded fragment data
+    pub data: String,
+}
+
+const BBQR_HEADER_LEN: usize = 8;
+const BBQR_MAX_PARTS: usize = 36 * 36 - 1;
+
+impl BbqrFragment {
+    /// Render as QR text
+    pub fn to_qr_string(&self) -> String {
+        format!(
+            "B${}{}{}{}{}",
+            self.encoding.code(),
+            self.file_type.code(),
+            base36_2(self.total),
+            base36_2(self.index),
+            self.data
+        )
+    }
+
+    fn parse(s: &str) -> AnyaResult<Self> {
+        if s.len() < BBQR_HEADER_LEN || !s.starts_with("B$") || !s.is_ascii() {
+            return Err(AnyaError::invalid_input("not a BBQr fragment"));
+        }
+        let chars: Vec<char> = s[..BBQR_HEADER_LEN].chars().collect();
+        let encoding = match chars[2] {
+            'H' => BbqrEncoding::Hex,
+            '2' => BbqrEncoding::Base32,
+            'Z' => {
+                return Err(AnyaError::invalid_input(
+                    "zlib-compressed BBQr is not supported",
+                ))
+            }
+            c => {
+                return Err(AnyaError::invalid_input(format!(
+                    "unknown BBQr encoding {}",
+                    c
+                )))
+            }
+        };
+        let total = parse_base36(&s[4..6])?;
+        let index = parse_base36(&s[6..8])?;
+        if total == 0 || index >= total {
+            return Err(AnyaError::invalid_input("BBQr fragment index out of range"));
+        }
+        Ok(Self {
+            encoding,
+            file_type: BbqrFileType::from_code(chars[3])?,
+            total,
+            index,
+            data: s[BBQR_HEADER_LEN..].to_string(),
+        })
+    }
+}
+
+fn base36_2(n: usize) -> String {
+    const DIGITS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    format!(
+        "{}{}",
+        DIGITS[(n / 36) % 36] as char,
+        DIGITS[n % 36] as char
+    )
+}
+
+fn parse_base36(s: &str) -> AnyaResult<usize> {
+    usize::from_str_radix(s, 36).map_err(|_| AnyaError::invalid_input("invalid BBQr base36 field"))
+}
+
+/// Split binary data into BBQr fragments of at most `max_chars` characters
+pub fn bbqr_split(
+    data: &[u8],
+    file_type: BbqrFileType,
+    encoding: BbqrEncoding,
+    max_chars: usize,
+) -> AnyaResult<Vec<BbqrFragment>> {
+    let encoded = match encoding {
+        BbqrEncoding::Hex => to_hex(data).to_ascii_uppercase(),
+        BbqrEncoding::Base32 => base32_encode(data),
+    };
+    let align = encoding.alignment();
+    let per_part = max_chars.saturating_sub(BBQR_HEADER_LEN) / align * align;
+    if per_part == 0 {
+        return Err(AnyaError::invalid_input("BBQr fragment size too small"));
+    }
+    let chunks: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(per_part)
+        .map(|c| std::str::from_utf8(c).unwrap_or_default())
+        .collect();
+    let total = chunks.len().max(1);
+    if total > BBQR_MAX_PARTS {
+        return Err(AnyaError::invalid_input("payload too large for BBQr"));
+    }
+    Ok((0..total)
+        .map(|index| BbqrFragment {
+            encoding,
+            file_type,
+            total,
+            index,
+            data: chunks.get(index).copied().unwrap_or_default().to_string(),
+        })
+        .collect())
+}
+
+/// Collects BBQr fragments scanned in any order
+#[derive(Debug, Default)]
+pub struct BbqrAssembler {
+    header: Option<(BbqrEncoding, BbqrFileType, usize)>,
+    parts: Vec<Option<String>>,
+}
+
+impl BbqrAssembler {
+    /// Create an empty assembler
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a fragment; duplicates are ignored
+    pub fn add(&mut self, fragment: BbqrFragment) -> AnyaResult<()> {
+        let header = (fragment.encoding, fragment.file_type, fragment.total);
+        match self.header {
+            None => {
+                self.header = Some(header);
+                self.parts = vec![None; fragment.total];
+            }
+            Some(existing) if existing != header => {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    "BBQr fragment belongs to a different sequence",
+                ));
+            }
+            Some(_) => {}
+        }
+        self.parts[fragment.index].get_or_insert(fragment.data);
+        Ok(())
+    }
+
+    /// Scan progress as `(received, total)`
+    pub fn progress(&self) -> (usize, usize) {
+        (
+            self.parts.iter().filter(|p| p.is_some()).count(),
+            self.parts.len(),
+        )
+    }
+
+    /// Whether every fragment has been received
+    pub fn is_complete(&self) -> bool {
+        !self.parts.is_empty() && self.parts.iter().all(Option::is_some)
+    }
+
+    /// Decode the assembled payload
+    pub fn finish(&self) -> AnyaResult<(BbqrFileType, Vec<u8>)> {
+        let (encoding, file_type, _) = self
+            .header
+            .ok_or_else(|| AnyaError::invalid_input("no BBQr fragments received"))?;
+        if !self.is_complete() {
+            let (have, total) = self.progress();
+            return Err(AnyaError::invalid_input(format!(
+                "BBQr sequence incomplete ({}/{})",
+                have, total
+            )));
+        }
+        let joined: String = self.parts.iter().flatten().map(String::as_str).collect();
+        let data = match encoding {
+            BbqrEncoding::Hex => from_hex(&joined)?,
+            BbqrEncoding::Base32 => base32_decode(&joined)?,
+        };
+        Ok((file_type, data))
+    }
+}
+
+/// Any payload recognised by the mobile scanner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QrPayload {
+    /// BIP-21 URI, possibly unified with Lightning
+    Bitcoin(PaymentUri),
+    /// Bare BOLT-11 invoice
+    Lightning(String),
+    /// One frame of an animated BBQr sequence
+    Bbqr(BbqrFragment),
+    /// DID exchange for agent pairing
+    DidExchange(DidExchange),
+    /// Offer to pair with a node for payment notifications
+    Pairing(PairingOffer),
+    /// Plain on-chain address without a URI scheme
+    Address(String),
+    /// BIP-353 name to resolve into payment instructions
+    Name(HumanReadableName),
+    /// Seed backup in the standard SeedQR format
+    SeedQr(SeedQr),
+}
+
+impl QrPayload {
+    /// Classify and parse scanned QR text
+    pub fn parse(text: &str) -> AnyaResult<Self> {
+        let text = text.trim();
+        if strip_scheme(text, "bitcoin:").is_some() {
+            return PaymentUri::parse(text).map(Self::Bitcoin);
+        }
+        if let Some(invoice) = strip_scheme(text, "lightning:") {
+            validate_bolt11(invoice)?;
+            return Ok(Self::Lightning(invoice.to_string()));
+        }
+        if text.starts_with("B$") {
+            return BbqrFragment::parse(text).map(Self::Bbqr);
+        }
+        if strip_scheme(text, DID_EXCHANGE_SCHEME).is_some() {
+            return DidExchange::parse(text).map(Self::DidExchange);
+        }
+        if text.starts_with("anya-pair:") {
+            return PairingOffer::parse(text).map(Self::Pairing);
+        }
+        if (text.len() == 48 || text.len() == 96) && text.bytes().all(|b| b.is_ascii_digit()) {
+            return SeedQr::parse_standard(text).map(Self::SeedQr);
+        }
+        if validate_bolt11(text).is_ok() {
+            return Ok(Self::Lightning(text.to_string()));
+        }
+        if Address::<NetworkUnchecked>::from_str(text).is_ok() {
+            return Ok(Self::Address(text.to_string()));
+        }
+        if text.contains('@') {
+            return HumanReadableName::parse(text).map(Self::Name);
+        }
+        Err(AnyaError::invalid_input("unrecognised QR payload"))
+    }
+}
+
+/// QR rendering gated on [`MobileConfig::qr_enabled`]
+pub struct QrService {
+    network: Network,
+}
+
+impl QrService {
+    /// Create the service, failing if QR support is disabled
+    pub fn new(config: &MobileConfig) -> AnyaResult<Self> {
+        if !config.qr_enabled {
+            return Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                "QR support is disabled",
+            ));
+        }
+        Ok(Self {
+            network: config.network,
+        })
+    }
+
+    /// Parse scanned text and check on-chain addresses against the wallet network
+    pub fn scan(&self, text: &str) -> AnyaResult<QrPayload> {
+        let payload = QrPayload::parse(text)?;
+        match &payload {
+            QrPayload::Bitcoin(uri) => uri.validate_for(self.network)?,
+            QrPayload::Address(address) => {
+                PaymentUri::new(address.clone()).validate_for(self.network)?;
+            }
+            _ => {}
+        }
+        Ok(payload)
+    }
+
+    /// Module matrix for `text`; `true` is a dark module
+    pub fn render_matrix(&self, text: &str) -> AnyaResult<Vec<Vec<bool>>> {
+        Ok(matrix(&encode(text)?))
+    }
+
+    /// Module matrix of `seed` as a SeedQR code, for display only; the
+    /// matrix is not wiped, so drop it as soon as it has been shown
+    pub fn render_seed_matrix(
+        &self,
+        seed: &SeedQr,
+        format: SeedQrFormat,
+    ) -> AnyaResult<Vec<Vec<bool>>> {
+        let code = QrCode::with_error_correction_level(&*seed.encode(format), EcLevel::L).map_err(
+            |e| AnyaError::invalid_input(format!("seed cannot be encoded as QR: {:?}", e)),
+        )?;
+        Ok(matrix(&code))
+    }
+
+    /// SVG image for `text`
+    pub fn render_svg(&self, text: &str) -> AnyaResult<String> {
+        Ok(encode(text)?
+            .render::<qrcode::render::svg::Color<'_>>()
+            .min_dimensions(256, 256)
+            .build())
+    }
+}
+
+fn matrix(code: &QrCode) -> Vec<Vec<bool>> {
+    code.to_colors()
+        .chunks(code.width())
+        .map(|row| row.iter().map(|c| *c == qrcode::Color::Dark).collect())
+        .collect()
+}
+
+fn encode(text: &str) -> AnyaResult<QrCode> {
+    QrCode::with_error_correction_level(text.as_bytes(), EcLevel::M)
+        .map_err(|e| AnyaError::invalid_input(format!("payload cannot be encoded as QR: {:?}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+    const INVOICE: &str = "lnbc10u1pjexampleqqqsyqcyq5rqwzqfqypqdqqxqrrsssp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygs";
+
+    #[test]
+    fn test_unified_bip21_roundtrip() {
+        let uri = format!(
+            "bitcoin:{}?amount=0.0015&label=Coffee%20Shop&lightning={}",
+            ADDRESS, INVOICE
+        );
+        let parsed = PaymentUri::parse(&uri).unwrap();
+        assert_eq!(parsed.amount, Some(Amount::from_sat(150_000)));
+        assert_eq!(parsed.label.as_deref(), Some("Coffee Shop"));
+        assert_eq!(parsed.lightning.as_deref(), Some(INVOICE));
+        parsed.validate_for(Network::Bitcoin).unwrap();
+        assert!(parsed.validate_for(Network::Testnet).is_err());
+
+        let reparsed = PaymentUri::parse(&parsed.to_uri()).unwrap();
+        assert_eq!(reparsed.amount, parsed.amount);
+        assert_eq!(reparsed.label, parsed.label);
+
+        assert!(PaymentUri::parse(&format!("bitcoin:{}?req-somethingnew=1", ADDRESS)).is_err());
+    }
+
+    #[test]
+    fn test_bbqr_split_and_reassemble_out_of_order() {
+        let psbt: Vec<u8> = (0..=255u8).cycle().take(1500).collect();
+        for encoding in [BbqrEncoding::Hex, BbqrEncoding::Base32] {
+            let fragments = bbqr_split(&psbt, BbqrFileType::Psbt, encoding, 300).unwrap();
+            assert!(fragments.len() > 1);
+
+            let mut assembler = BbqrAssembler::new();
+            for fragment in fragments.iter().rev() {
+                match QrPayload::parse(&fragment.to_qr_string()).unwrap() {
+                    QrPayload::Bbqr(f) => assembler.add(f).unwrap(),
+                    other => panic!("unexpected payload {:?}", other),
+                }
+            }
+            let (file_type, data) = assembler.finish().unwrap();
+            assert_eq!(file_type, BbqrFileType::Psbt);
+            assert_eq!(data, psbt);
+        }
+    }
+
+    #[test]
+    fn test_did_exchange_and_rendering() {
+        let exchange = DidExchange {
+            did: "did:dht:abc123".into(),
+            endpoint: Some("https://dwn.example.com".into()),
+            challenge: "nonce-42".into(),
+        };
+        let text = exchange.to_qr_string().unwrap();
+        assert_eq!(
+            QrPayload::parse(&text).unwrap(),
+            QrPayload::DidExchange(exchange)
+        );
+
+        let service = QrService::new(&MobileConfig::default()).unwrap();
+        let matrix = service.render_matrix(&text).unwrap();
+        assert_eq!(matrix.len(), matrix[0].len());
+        assert!(service.render_svg(ADDRESS).unwrap().starts_with("<?xml"));
+
+        // SeedQR sizes: 25x25 standard and 21x21 compact for 12 words
+        let seed = SeedQr::from_entropy(&[0x3cu8; 16]).unwrap();
+        let standard = seed.to_standard();
+        assert_eq!(
+            QrPayload::parse(&standard).unwrap(),
+            QrPayload::SeedQr(seed.clone())
+        );
+        for (format, width) in [(SeedQrFormat::Standard, 25), (SeedQrFormat::Compact, 21)] {
+            assert_eq!(
+                service.render_seed_matrix(&seed, format).unwrap().len(),
+                width
+            );
+        }
+
+        let disabled = MobileConfig {
+            qr_enabled: false,
+            ..MobileConfig::default()
+        };
+        assert!(QrService::new(&disabled).is_err());
+    }
+}
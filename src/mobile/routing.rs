@@ -0,0 +1,984 @@
+//! Pathfinding and multi-part payments
+//!
+//! [`find_route`] searches the [`NetworkGraph`] backwards from the
+//! recipient, so each hop's fee is computed on the amount it actually
+//! forwards, and weighs every channel by fee plus a [`Scorer`] penalty. The
+//! scorer keeps, per channel, bounds on the liquidity it is known to have
+//! and a decayed count of past successes and failures; both are learned
+//! from payment outcomes, relax back towards "unknown" over a half-life,
+//! and are persisted so a restarted node keeps what it learned.
+//!
+//! [`PaymentRouter`] splits a payment into parts, routes them concurrently,
+//! and when a part fails, updates the scorer and retries it on another
+//! route, halving it if no single route can carry it. Parts are either
+//! basic MPP parts sharing the invoice's payment hash and secret, or AMP
+//! parts whose XOR-split seed shares only reveal the preimages once all of
+//! them have arrived.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{sha256, to_hex};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "lightning_scores";
+
+/// Directed channel in the network graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelEdge {
+    /// Short channel id
+    pub short_channel_id: u64,
+    /// Forwarding node
+    pub source: String,
+    /// Receiving node
+    pub target: String,
+    /// Channel capacity
+    pub capacity_sat: u64,
+    /// Fixed forwarding fee
+    pub fee_base_msat: u32,
+    /// Proportional forwarding fee in millionths
+    pub fee_ppm: u32,
+    /// CLTV delta the source requires
+    pub cltv_expiry_delta: u16,
+    /// Largest HTLC the source forwards, if limited
+    pub htlc_maximum_msat: Option<u64>,
+}
+
+impl ChannelEdge {
+    /// Fee the source charges to forward `amount_msat`
+    pub fn fee_for(&self, amount_msat: u64) -> u64 {
+        u64::from(self.fee_base_msat) + amount_msat * u64::from(self.fee_ppm) / 1_000_000
+    }
+
+    const fn capacity_msat(&self) -> u64 {
+        self.capacity_sat * 1_000
+    }
+}
+
+/// Known public and private channels, indexed by receiving node
+#[derive(Debug, Clone, Default)]
+pub struct NetworkGraph {
+    into: HashMap<String, Vec<ChannelEdge>>,
+}
+
+impl NetworkGraph {
+    /// Empty graph
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace a directed channel
+    pub fn add_channel(&mut self, edge: ChannelEdge) {
+        let edges = self.into.entry(edge.target.clone()).or_default();
+        edges.retain(|e| e.short_channel_id != edge.short_channel_id || e.source != edge.source);
+        edges.push(edge);
+    }
+
+    /// Remove both directions of a channel
+    pub fn remove_channel(&mut self, short_channel_id: u64) {
+        for edges in self.into.values_mut() {
+            edges.retain(|e| e.short_channel_id != short_channel_id);
+        }
+    }
+
+    /// Channels ending at `node`
+    pub fn channels_into(&self, node: &str) -> &[ChannelEdge] {
+        self.into.get(node).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// One hop of a route
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteHop {
+    /// Channel used
+    pub short_channel_id: u64,
+    /// Node reached
+    pub node_id: String,
+    /// Amount carried by the channel
+    pub amount_msat: u64,
+    /// CLTV delta added by the hop
+    pub cltv_expiry_delta: u16,
+}
+
+/// Path from the payer to the recipient
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    /// Hops in payment order
+    pub hops: Vec<RouteHop>,
+}
+
+impl Route {
+    /// Amount leaving the payer
+    pub fn sent_msat(&self) -> u64 {
+        self.hops.first().map_or(0, |h| h.amount_msat)
+    }
+
+    /// Amount reaching the recipient
+    pub fn delivered_msat(&self) -> u64 {
+        self.hops.last().map_or(0, |h| h.amount_msat)
+    }
+
+    /// Routing fees paid to intermediate nodes
+    pub fn fee_msat(&self) -> u64 {
+        self.sent_msat() - self.delivered_msat()
+    }
+}
+
+/// Scorer settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoringConfig {
+    /// Penalty for every channel used, favouring shorter routes
+    pub base_penalty_msat: u64,
+    /// Penalty per halving of the estimated success probability
+    pub liquidity_penalty_msat: u64,
+    /// Penalty per halving of the historical success rate
+    pub history_penalty_msat: u64,
+    /// Time for learned bounds and history to lose half their weight
+    pub half_life: Duration,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            base_penalty_msat: 500,
+            liquidity_penalty_msat: 30_000,
+            history_penalty_msat: 10_000,
+            half_life: Duration::from_secs(6 * 3600),
+        }
+    }
+}
+
+/// What is known about one channel's liquidity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelScore {
+    /// Liquidity the channel is known to have
+    pub min_liquidity_msat: u64,
+    /// Liquidity the channel is known to lack, from its capacity down
+    pub max_liquidity_offset_msat: u64,
+    /// Decayed count of parts the channel forwarded
+    pub successes: f64,
+    /// Decayed count of parts the channel failed
+    pub failures: f64,
+    /// Last update, seconds since the Unix epoch
+    pub updated_at: u64,
+}
+
+impl ChannelScore {
+    fn decayed(&self, half_life: Duration, now: u64) -> Self {
+        let elapsed = now.saturating_sub(self.updated_at) as f64;
+        let factor = 0.5f64.powf(elapsed / half_life.as_secs().max(1) as f64);
+        let scale = |v: u64| (v as f64 * factor) as u64;
+        Self {
+            min_liquidity_msat: scale(self.min_liquidity_msat),
+            max_liquidity_offset_msat: scale(self.max_liquidity_offset_msat),
+            successes: self.successes * factor,
+            failures: self.failures * factor,
+            updated_at: now,
+        }
+    }
+
+    fn bounds(&self, capacity_msat: u64) -> (u64, u64) {
+        let upper = capacity_msat.saturating_sub(self.max_liquidity_offset_msat);
+        (self.min_liquidity_msat.min(upper), upper)
+    }
+}
+
+/// Learns channel liquidity from payment outcomes
+pub struct Scorer {
+    config: ScoringConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    scores: Mutex<HashMap<u64, ChannelScore>>,
+}
+
+impl Scorer {
+    /// Open the scorer, loading scores persisted in `storage`
+    pub async fn open(config: ScoringConfig, storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let mut scores = HashMap::new();
+        for (key, value) in storage.scan_prefix(&ns, "").await? {
+            let scid = u64::from_str_radix(&key, 16).map_err(|_| {
+                AnyaError::new(ErrorCode::StorageFailure, format!("bad score key {}", key))
+            })?;
+            scores.insert(scid, serde_json::from_slice(&value)?);
+        }
+        Ok(Self {
+            config,
+            storage,
+            ns,
+            scores: Mutex::new(scores),
+        })
+    }
+
+    /// Current score of a channel, decayed to `now`
+    pub fn score(&self, short_channel_id: u64, now: u64) -> ChannelScore {
+        self.scores()
+            .get(&short_channel_id)
+            .map_or_else(ChannelScore::default, |s| {
+                s.decayed(self.config.half_life, now)
+            })
+    }
+
+    /// Estimated probability that `edge` can forward `amount_msat`,
+    /// assuming liquidity is uniform between the known bounds
+    pub fn success_probability(&self, edge: &ChannelEdge, amount_msat: u64, now: u64) -> f64 {
+        let score = self.score(edge.short_channel_id, now);
+        let (lower, upper) = score.bounds(edge.capacity_msat());
+        if amount_msat <= lower {
+            1.0
+        } else if amount_msat > upper {
+            0.0
+        } else {
+            (upper - amount_msat + 1) as f64 / (upper - lower + 1) as f64
+        }
+    }
+
+    /// Routing penalty for sending `amount_msat` over `edge`, or `None`
+    /// when the channel is known to lack the liquidity
+    pub fn penalty_msat(&self, edge: &ChannelEdge, amount_msat: u64, now: u64) -> Option<u64> {
+        let probability = self.success_probability(edge, amount_msat, now);
+        if probability <= 0.0 {
+            return None;
+        }
+        let score = self.score(edge.short_channel_id, now);
+        let history = (score.successes + 1.0) / (score.successes + score.failures + 2.0);
+        let liquidity = -probability.log2() * self.config.liquidity_penalty_msat as f64;
+        let historical = -history.log2() * self.config.history_penalty_msat as f64;
+        Some(self.config.base_penalty_msat + (liquidity + historical) as u64)
+    }
+
+    /// Learn from a part that `failed_channel` could not forward. Channels
+    /// before it had the liquidity; it did not.
+    pub async fn part_failed(
+        &self,
+        route: &Route,
+        failed_channel: u64,
+        graph: &NetworkGraph,
+        now: u64,
+    ) -> AnyaResult<()> {
+        let updated = self.record_failure(route, failed_channel, graph, now);
+        self.persist(&updated).await
+    }
+
+    /// Learn from a settled part: every channel forwarded it, and its
+    /// liquidity moved on by the amount
+    pub async fn part_succeeded(&self, route: &Route, now: u64) -> AnyaResult<()> {
+        let updated = self.record_success(route, now);
+        self.persist(&updated).await
+    }
+
+    fn record_failure(
+        &self,
+        route: &Route,
+        failed_channel: u64,
+        graph: &NetworkGraph,
+        now: u64,
+    ) -> Vec<(u64, ChannelScore)> {
+        let capacities = capacities(route, graph);
+        let mut updated = Vec::new();
+        let mut scores = self.scores();
+        for (hop, capacity) in route.hops.iter().zip(capacities) {
+            let entry = scores.entry(hop.short_channel_id).or_default();
+            let mut score = entry.decayed(self.config.half_life, now);
+            if hop.short_channel_id == failed_channel {
+                let offset = capacity.saturating_sub(hop.amount_msat.saturating_sub(1));
+                score.max_liquidity_offset_msat = score.max_liquidity_offset_msat.max(offset);
+                score.min_liquidity_msat = score.min_liquidity_msat.min(hop.amount_msat - 1);
+                score.failures += 1.0;
+            } else {
+                score.min_liquidity_msat = score.min_liquidity_msat.max(hop.amount_msat);
+                let offset = capacity.saturating_sub(hop.amount_msat);
+                score.max_liquidity_offset_msat = score.max_liquidity_offset_msat.min(offset);
+            }
+            *entry = score;
+            updated.push((hop.short_channel_id, score));
+            if hop.short_channel_id == failed_channel {
+                break;
+            }
+        }
+        drop(scores);
+        updated
+    }
+
+    fn record_success(&self, route: &Route, now: u64) -> Vec<(u64, ChannelScore)> {
+        let mut updated = Vec::new();
+        let mut scores = self.scores();
+        for hop in &route.hops {
+            let entry = scores.entry(hop.short_channel_id).or_default();
+            let mut score = entry.decayed(self.config.half_life, now);
+            score.min_liquidity_msat = score.min_liquidity_msat.saturating_sub(hop.amount_msat);
+            score.max_liquidity_offset_msat += hop.amount_msat;
+            score.successes += 1.0;
+            *entry = score;
+            updated.push((hop.short_channel_id, score));
+        }
+        drop(scores);
+        updated
+    }
+
+    async fn persist(&self, updated: &[(u64, ChannelScore)]) -> AnyaResult<()> {
+        for (scid, score) in updated {
+            let key = format!("{:016x}", scid);
+            self.storage
+                .put(&self.ns, &key, &serde_json::to_vec(score)?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn scores(&self) -> MutexGuard<'_, HashMap<u64, ChannelScore>> {
+        self.scores.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn capacities(route: &Route, graph: &NetworkGraph) -> Vec<u64> {
+    route
+        .hops
+        .iter()
+        .map(|hop| {
+            graph
+                .channels_into(&hop.node_id)
+                .iter()
+                .find(|e| e.short_channel_id == hop.short_channel_id)
+                .map_or(hop.amount_msat, ChannelEdge::capacity_msat)
+        })
+        .collect()
+}
+
+/// Route-finding limits and channel usage to account for
+#[derive(Debug, Clone, Default)]
+pub struct RouteParams {
+    /// Longest route allowed
+    pub max_hops: usize,
+    /// Channels not to use
+    pub excluded: HashSet<u64>,
+    /// Amounts already in flight per channel from other parts
+    pub in_flight_msat: HashMap<u64, u64>,
+}
+
+/// Cheapest route by fee plus scorer penalty delivering `amount_msat` from
+/// `payer` to `payee`. Fails with [`ErrorCode::NotFound`] when none exists.
+pub fn find_route(
+    graph: &NetworkGraph,
+    scorer: &Scorer,
+    payer: &str,
+    payee: &str,
+    amount_msat: u64,
+    params: &RouteParams,
+    now: u64,
+) -> AnyaResult<Route> {
+    struct Reached<'a> {
+        cost: u64,
+        /// Amount that must arrive at the node
+        amount_msat: u64,
+        hops: usize,
+        /// Channel towards the payee and the node it leads to
+        next: Option<(&'a ChannelEdge, &'a str)>,
+    }
+
+    let mut best: HashMap<&str, Reached<'_>> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    best.insert(
+        payee,
+        Reached {
+            cost: 0,
+            amount_msat,
+            hops: 0,
+            next: None,
+        },
+    );
+    queue.push(Reverse((0u64, payee)));
+    while let Some(Reverse((cost, node))) = queue.pop() {
+        if node == payer {
+            break;
+        }
+        let (amount, hops) = match best.get(node) {
+            Some(reached) if reached.cost == cost => (reached.amount_msat, reached.hops),
+            _ => continue,
+        };
+        if hops >= params.max_hops {
+            continue;
+        }
+        for edge in graph.channels_into(node) {
+            if params.excluded.contains(&edge.short_channel_id)
+                || edge.htlc_maximum_msat.is_some_and(|max| amount > max)
+            {
+                continue;
+            }
+            let in_flight = params
+                .in_flight_msat
+                .get(&edge.short_channel_id)
+                .copied()
+                .unwrap_or_default();
+            let Some(penalty) = scorer.penalty_msat(edge, amount + in_flight, now) else {
+                continue;
+            };
+            let fee = if edge.source == payer {
+                0
+            } else {
+                edge.fee_for(amount)
+            };
+            let candidate = cost + fee + penalty;
+            let source = edge.source.as_str();
+            if best.get(source).is_some_and(|r| r.cost <= candidate) {
+                continue;
+            }
+            best.insert(
+                source,
+                Reached {
+                    cost: candidate,
+                    amount_msat: amount + fee,
+                    hops: hops + 1,
+                    next: Some((edge, node)),
+                },
+            );
+            queue.push(Reverse((candidate, source)));
+        }
+    }
+
+    let mut hops = Vec::new();
+    let mut node = payer;
+    while let Some((edge, next)) = best.get(node).and_then(|r| r.next) {
+        hops.push(RouteHop {
+            short_channel_id: edge.short_channel_id,
+            node_id: next.to_string(),
+            amount_msat: best[next].amount_msat,
+            cltv_expiry_delta: edge.cltv_expiry_delta,
+        });
+        node = next;
+    }
+    if hops.is_empty() || payer == payee {
+        return Err(AnyaError::not_found(format!(
+            "no route to {} for {} msat",
+            payee, amount_msat
+        )));
+    }
+    Ok(Route { hops })
+}
+
+/// AMP share carried by one part
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AmpShare {
+    /// Hex id shared by all parts of the payment
+    pub set_id: String,
+    /// Index deriving this part's preimage
+    pub child_index: u32,
+    /// Share of the root seed; all shares XOR to the seed
+    pub share: [u8; 32],
+}
+
+impl AmpShare {
+    /// Preimage of child `child_index` under `root_seed`
+    pub fn child_preimage(root_seed: &[u8; 32], child_index: u32) -> [u8; 32] {
+        let mut data = root_seed.to_vec();
+        data.extend_from_slice(&child_index.to_be_bytes());
+        sha256(&data)
+    }
+
+    fn split(&self, next_index: u32) -> (Self, Self) {
+        let left: [u8; 32] = rand::random();
+        let mut right = self.share;
+        right.iter_mut().zip(left).for_each(|(r, l)| *r ^= l);
+        let part = |share, child_index| Self {
+            set_id: self.set_id.clone(),
+            child_index,
+            share,
+        };
+        (part(left, self.child_index), part(right, next_index))
+    }
+}
+
+/// How the parts of a payment are tied together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentMode {
+    /// Basic MPP for an invoice: all parts use its hash and secret
+    Mpp {
+        /// Hex payment hash
+        payment_hash: String,
+        /// Hex payment secret
+        payment_secret: String,
+    },
+    /// Spontaneous AMP payment under a fresh root seed
+    Amp {
+        /// Seed the recipient reconstructs from the part shares
+        root_seed: [u8; 32],
+    },
+}
+
+/// Payment to split and route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentRequest {
+    /// Recipient node id
+    pub payee: String,
+    /// Amount to deliver
+    pub amount_msat: u64,
+    /// Most routing fee to spend across all parts
+    pub max_fee_msat: u64,
+    /// Part binding
+    pub mode: PaymentMode,
+}
+
+/// One part handed to the node for sending
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentPart {
+    /// Route of the part
+    pub route: Route,
+    /// Hex payment hash locking the part's HTLC
+    pub payment_hash: String,
+    /// Total amount of the payment, for the recipient's MPP accounting
+    pub total_msat: u64,
+    /// Hex payment secret, for MPP parts
+    pub payment_secret: Option<String>,
+    /// Seed share, for AMP parts
+    pub amp: Option<AmpShare>,
+}
+
+/// Result of sending a part
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartOutcome {
+    /// The recipient settled the part
+    Settled {
+        /// Hex preimage
+        preimage: String,
+    },
+    /// A channel on the route could not forward the part
+    ChannelFailed {
+        /// Channel that failed
+        short_channel_id: u64,
+        /// Whether the channel should not be retried for this payment
+        permanent: bool,
+    },
+    /// The recipient refused the payment
+    Rejected {
+        /// Failure reported by the recipient
+        reason: String,
+    },
+}
+
+/// Sends payment parts over the node's channels
+#[async_trait]
+pub trait PartSender: Send + Sync {
+    /// Send `part`, returning once it settles or fails
+    async fn send_part(&self, part: PaymentPart) -> AnyaResult<PartOutcome>;
+}
+
+/// Splitting and retry limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouterConfig {
+    /// Most parts in flight at once
+    pub max_parts: usize,
+    /// Parts are not split below this amount
+    pub min_part_msat: u64,
+    /// Most part sends per payment, including retries
+    pub max_attempts: usize,
+    /// Longest route allowed
+    pub max_hops: usize,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            max_parts: 16,
+            min_part_msat: 10_000,
+            max_attempts: 64,
+            max_hops: 20,
+        }
+    }
+}
+
+/// Outcome of a completed multi-part payment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiPartResult {
+    /// Hex preimages of the settled parts; one for MPP, one per part for AMP
+    pub preimages: Vec<String>,
+    /// Routing fees paid
+    pub fee_msat: u64,
+    /// Number of settled parts
+    pub parts: usize,
+    /// Number of part sends, including failed ones
+    pub attempts: usize,
+}
+
+/// Splits, routes, and retries multi-part payments
+pub struct PaymentRouter {
+    config: RouterConfig,
+    node_id: String,
+    scorer: Arc<Scorer>,
+    sender: Arc<dyn PartSender>,
+}
+
+struct Pending {
+    amount_msat: u64,
+    amp: Option<AmpShare>,
+}
+
+impl PaymentRouter {
+    /// Router paying from `node_id`
+    pub fn new(
+        config: RouterConfig,
+        node_id: impl Into<String>,
+        scorer: Arc<Scorer>,
+        sender: Arc<dyn PartSender>,
+    ) -> Self {
+        Self {
+            config,
+            node_id: node_id.into(),
+            scorer,
+            sender,
+        }
+    }
+
+    /// Scorer used for routing
+    pub const fn scorer(&self) -> &Arc<Scorer> {
+        &self.scorer
+    }
+
+    /// Deliver `request.amount_msat` to the payee in as many parts as needed
+    pub async fn pay(
+        &self,
+        graph: &NetworkGraph,
+        request: &PaymentRequest,
+    ) -> AnyaResult<MultiPartResult> {
+        if request.amount_msat == 0 {
+            return Err(AnyaError::invalid_input("payment amount must be positive"));
+        }
+        let root = match &request.mode {
+            PaymentMode::Amp { root_seed } => Some(AmpShare {
+                set_id: to_hex(&sha256(root_seed)),
+                child_index: 0,
+                share: *root_seed,
+            }),
+            PaymentMode::Mpp { .. } => None,
+        };
+        let mut next_child = 1;
+        let mut queue = vec![Pending {
+            amount_msat: request.amount_msat,
+            amp: root,
+        }];
+        let mut params = RouteParams {
+            max_hops: self.config.max_hops,
+            ..RouteParams::default()
+        };
+        let mut in_flight = FuturesUnordered::new();
+        let mut fee_reserved = 0u64;
+        let mut result = MultiPartResult {
+            preimages: Vec::new(),
+            fee_msat: 0,
+            parts: 0,
+            attempts: 0,
+        };
+
+        loop {
+            // Route every queued part, splitting those no route can carry
+            while let Some(part) = queue.pop() {
+                let now = unix_now();
+                let budget = request.max_fee_msat.saturating_sub(fee_reserved);
+                let route = find_route(
+                    graph,
+                    &self.scorer,
+                    &self.node_id,
+                    &request.payee,
+                    part.amount_msat,
+                    &params,
+                    now,
+                )
+                .ok()
+                .filter(|r| r.fee_msat() <= budget);
+                let Some(route) = route else {
+                    let half = part.amount_msat / 2;
+                    if half < self.config.min_part_msat
+                        || in_flight.len() + queue.len() + 2 > self.config.max_parts
+                    {
+                        return Err(AnyaError::new(
+                            ErrorCode::Unavailable,
+                            format!(
+                                "no route for {} msat to {} within the fee budget",
+                                part.amount_msat, request.payee
+                            ),
+                        ));
+                    }
+                    let (left, right) = part.amp.map_or((None, None), |share| {
+                        next_child += 1;
+                        let (l, r) = share.split(next_child - 1);
+                        (Some(l), Some(r))
+                    });
+                    queue.push(Pending {
+                        amount_msat: part.amount_msat - half,
+                        amp: left,
+                    });
+                    queue.push(Pending {
+                        amount_msat: half,
+                        amp: right,
+                    });
+                    continue;
+                };
+                result.attempts += 1;
+                if result.attempts > self.config.max_attempts {
+                    return Err(AnyaError::new(
+                        ErrorCode::Timeout,
+                        format!("payment failed after {} attempts", self.config.max_attempts),
+                    ));
+                }
+                fee_reserved += route.fee_msat();
+                for hop in &route.hops {
+                    *params
+                        .in_flight_msat
+                        .entry(hop.short_channel_id)
+                        .or_default() += hop.amount_msat;
+                }
+                let sent = self.part(request, route, part.amp)?;
+                let sender = Arc::clone(&self.sender);
+                in_flight.push(async move {
+                    let outcome = sender.send_part(sent.clone()).await;
+                    (sent, outcome)
+                });
+            }
+
+            let Some((part, outcome)) = in_flight.next().await else {
+                return Ok(result);
+            };
+            for hop in &part.route.hops {
+                if let Some(amount) = params.in_flight_msat.get_mut(&hop.short_channel_id) {
+                    *amount -= hop.amount_msat;
+                }
+            }
+            let now = unix_now();
+            match outcome? {
+                PartOutcome::Settled { preimage } => {
+                    self.scorer.part_succeeded(&part.route, now).await?;
+                    result.fee_msat += part.route.fee_msat();
+                    result.parts += 1;
+                    if !result.preimages.contains(&preimage) {
+                        result.preimages.push(preimage);
+                    }
+                }
+                PartOutcome::ChannelFailed {
+                    short_channel_id,
+                    permanent,
+                } => {
+                    tracing::debug!(
+                        channel = short_channel_id,
+                        amount = part.route.delivered_msat(),
+                        "payment part failed; retrying"
+                    );
+                    self.scorer
+                        .part_failed(&part.route, short_channel_id, graph, now)
+                        .await?;
+                    if permanent {
+                        params.excluded.insert(short_channel_id);
+                    }
+                    fee_reserved -= part.route.fee_msat();
+                    queue.push(Pending {
+                        amount_msat: part.route.delivered_msat(),
+                        amp: part.amp,
+                    });
+                }
+                PartOutcome::Rejected { reason } => {
+                    return Err(AnyaError::new(
+                        ErrorCode::TransactionRejected,
+                        format!("{} rejected the payment: {}", request.payee, reason),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn part(
+        &self,
+        request: &PaymentRequest,
+        route: Route,
+        amp: Option<AmpShare>,
+    ) -> AnyaResult<PaymentPart> {
+        let (payment_hash, payment_secret) = match (&request.mode, &amp) {
+            (
+                PaymentMode::Mpp {
+                    payment_hash,
+                    payment_secret,
+                },
+                _,
+            ) => (payment_hash.clone(), Some(payment_secret.clone())),
+            (PaymentMode::Amp { root_seed }, Some(share)) => {
+                let preimage = AmpShare::child_preimage(root_seed, share.child_index);
+                (to_hex(&sha256(&preimage)), None)
+            }
+            (PaymentMode::Amp { .. }, None) => {
+                return Err(AnyaError::new(
+                    ErrorCode::Internal,
+                    "AMP part without a share",
+                ))
+            }
+        };
+        Ok(PaymentPart {
+            route,
+            payment_hash,
+            total_msat: request.amount_msat,
+            payment_secret,
+            amp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    fn edge(scid: u64, source: &str, target: &str, capacity_sat: u64, fee_ppm: u32) -> ChannelEdge {
+        ChannelEdge {
+            short_channel_id: scid,
+            source: source.into(),
+            target: target.into(),
+            capacity_sat,
+            fee_base_msat: 1_000,
+            fee_ppm,
+            cltv_expiry_delta: 40,
+            htlc_maximum_msat: None,
+        }
+    }
+
+    /// us -> a -> payee is cheap but small; us -> b -> payee is larger
+    fn graph() -> NetworkGraph {
+        let mut graph = NetworkGraph::new();
+        graph.add_channel(edge(1, "us", "a", 1_000, 0));
+        graph.add_channel(edge(2, "a", "payee", 1_000, 100));
+        graph.add_channel(edge(3, "us", "b", 2_000, 0));
+        graph.add_channel(edge(4, "b", "payee", 2_000, 1_000));
+        graph
+    }
+
+    /// Fails any part over a channel's actual liquidity and settles the
+    /// rest, revealing the MPP preimage or each AMP child preimage
+    struct LiquiditySender {
+        liquidity_msat: HashMap<u64, u64>,
+        sent: Mutex<Vec<PaymentPart>>,
+    }
+
+    #[async_trait]
+    impl PartSender for LiquiditySender {
+        async fn send_part(&self, part: PaymentPart) -> AnyaResult<PartOutcome> {
+            self.sent.lock().unwrap().push(part.clone());
+            for hop in &part.route.hops {
+                if self.liquidity_msat[&hop.short_channel_id] < hop.amount_msat {
+                    return Ok(PartOutcome::ChannelFailed {
+                        short_channel_id: hop.short_channel_id,
+                        permanent: false,
+                    });
+                }
+            }
+            let preimage = part.amp.as_ref().map_or_else(
+                || "00".repeat(32),
+                |share| to_hex(&AmpShare::child_preimage(&[7; 32], share.child_index)),
+            );
+            Ok(PartOutcome::Settled { preimage })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_splits_and_retries_with_learned_scores() {
+        let storage = Arc::new(MemoryBackend::new());
+        let scorer = Arc::new(
+            Scorer::open(ScoringConfig::default(), storage.clone())
+                .await
+                .unwrap(),
+        );
+        let sender = Arc::new(LiquiditySender {
+            liquidity_msat: HashMap::from([
+                (1, 600_000),
+                (2, 600_000),
+                (3, 2_000_000),
+                (4, 900_000),
+            ]),
+            sent: Mutex::new(Vec::new()),
+        });
+        let router = PaymentRouter::new(
+            RouterConfig::default(),
+            "us",
+            scorer.clone(),
+            sender.clone(),
+        );
+        let request = PaymentRequest {
+            payee: "payee".into(),
+            amount_msat: 1_200_000,
+            max_fee_msat: 20_000,
+            mode: PaymentMode::Mpp {
+                payment_hash: "ab".repeat(32),
+                payment_secret: "cd".repeat(32),
+            },
+        };
+        let graph = graph();
+        let result = router.pay(&graph, &request).await.unwrap();
+        assert!(result.parts > 1 && result.attempts > result.parts);
+        assert_eq!(result.preimages, ["00".repeat(32)]);
+        let sent = sender.sent.lock().unwrap().clone();
+        assert!(sent
+            .iter()
+            .all(|p| p.total_msat == 1_200_000
+                && p.payment_secret.as_deref() == Some(&"cd".repeat(32))));
+
+        // Failures taught the scorer channel 4 cannot carry 1M msat, and the
+        // scores survive a restart
+        let reopened = Scorer::open(ScoringConfig::default(), storage)
+            .await
+            .unwrap();
+        let now = unix_now();
+        assert_eq!(
+            reopened.score(4, now).failures,
+            scorer.score(4, now).failures
+        );
+        assert!(reopened.score(4, now).failures >= 1.0);
+        assert_eq!(
+            reopened.success_probability(&edge(4, "b", "payee", 2_000, 0), 1_000_000, now),
+            0.0
+        );
+        let decayed = reopened.score(4, now + 6 * 3600);
+        assert!((decayed.failures - reopened.score(4, now).failures / 2.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_route_fees_and_amp_shares() {
+        let scorer = Scorer::open(ScoringConfig::default(), Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let graph = graph();
+        let params = RouteParams {
+            max_hops: 20,
+            ..RouteParams::default()
+        };
+        let route = find_route(&graph, &scorer, "us", "payee", 500_000, &params, 0).unwrap();
+        // The larger channels are likelier to succeed, outweighing b's fee
+        // of 1000 + 500_000 * 1000 / 1M
+        assert_eq!(route.hops[0].short_channel_id, 3);
+        assert_eq!(route.fee_msat(), 1_500);
+        assert_eq!(route.sent_msat(), 501_500);
+        assert_eq!(route.delivered_msat(), 500_000);
+        assert!(find_route(&graph, &scorer, "us", "nobody", 1_000, &params, 0).is_err());
+        let too_big = find_route(&graph, &scorer, "us", "payee", 3_000_000, &params, 0);
+        assert_eq!(too_big.unwrap_err().code(), ErrorCode::NotFound);
+
+        let root = AmpShare {
+            set_id: "set".into(),
+            child_index: 0,
+            share: [9; 32],
+        };
+        let (left, right) = root.split(1);
+        let (left, middle) = left.split(2);
+        let combined: Vec<u8> = (0..32)
+            .map(|i| left.share[i] ^ middle.share[i] ^ right.share[i])
+            .collect();
+        assert_eq!(combined, [9; 32]);
+        let indexes: HashSet<_> = [&left, &middle, &right]
+            .iter()
+            .map(|s| s.child_index)
+            .collect();
+        assert_eq!(indexes.len(), 3);
+    }
+}
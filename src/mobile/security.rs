@@ -0,0 +1,489 @@
+//! Authentication gate for sensitive wallet operations
+//!
+//! The [`SecurityManager`] holds the current [`AuthSession`] and decides,
+//! per [`Operation`], whether the session is good enough or the user has to
+//! authenticate again. Biometric checks are performed by the platform
+//! authenticator and reported through the FFI bridge; PINs are verified here
+//! against PBKDF2 hashes stored in the `mobile_security` namespace.
+//!
+//! A duress PIN can be configured. Entering it succeeds like the real PIN but
+//! opens a session on the [`WalletProfile::Decoy`] profile, so callers route
+//! every subsequent operation to the decoy wallet.
+
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "mobile_security";
+const STATE_KEY: &str = "pin_state";
+const PIN_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+
+/// How the user authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// Platform biometric prompt (Face ID, fingerprint)
+    Biometric,
+    /// Numeric PIN
+    Pin,
+}
+
+/// Credential presented to [`SecurityManager::authenticate`]
+#[derive(Debug, Clone)]
+pub enum Credential {
+    /// Successful assertion from the platform biometric authenticator
+    Biometric,
+    /// PIN entered by the user
+    Pin(String),
+}
+
+/// Wallet opened by a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WalletProfile {
+    /// The user's real wallet
+    Primary,
+    /// Decoy wallet opened with the duress PIN
+    Decoy,
+}
+
+/// Operation guarded by the security gate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Sign a transaction spending `amount_sat`
+    Sign {
+        /// Amount leaving the wallet in satoshis
+        amount_sat: u64,
+    },
+    /// Export private keys or the seed
+    ExportKeys,
+}
+
+/// What an operation requires from the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AuthRequirement {
+    /// Any unexpired session
+    Session,
+    /// Authentication within [`SecurityPolicy::fresh_auth_window`]
+    Fresh,
+    /// Fresh authentication by PIN
+    FreshPin,
+}
+
+/// Configurable authentication policy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityPolicy {
+    /// Idle time after which the session expires
+    pub session_timeout: Duration,
+    /// How recent an authentication must be to count as fresh
+    pub fresh_auth_window: Duration,
+    /// Signing at or above this amount requires fresh authentication
+    pub sign_fresh_above_sat: u64,
+    /// Signing at or above this amount requires a fresh PIN
+    pub sign_pin_above_sat: Option<u64>,
+    /// Whether exporting keys requires a fresh PIN rather than biometrics
+    pub export_requires_pin: bool,
+    /// Whether biometric authentication is accepted at all
+    pub biometric_enabled: bool,
+    /// Failed PIN attempts before the wallet is locked out
+    pub max_pin_attempts: u32,
+    /// Lockout duration after too many failed attempts
+    pub lockout: Duration,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        Self {
+            session_timeout: Duration::from_secs(5 * 60),
+            fresh_auth_window: Duration::from_secs(30),
+            sign_fresh_above_sat: 1_000_000,
+            sign_pin_above_sat: None,
+            export_requires_pin: true,
+            biometric_enabled: true,
+            max_pin_attempts: 5,
+            lockout: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl SecurityPolicy {
+    /// Requirement for `operation` under this policy
+    pub fn requirement(&self, operation: Operation) -> AuthRequirement {
+        match operation {
+            Operation::Sign { amount_sat } => {
+                if self.sign_pin_above_sat.is_some_and(|t| amount_sat >= t) {
+                    AuthRequirement::FreshPin
+                } else if amount_sat >= self.sign_fresh_above_sat {
+                    AuthRequirement::Fresh
+                } else {
+                    AuthRequirement::Session
+                }
+            }
+            Operation::ExportKeys if self.export_requires_pin => AuthRequirement::FreshPin,
+            Operation::ExportKeys => AuthRequirement::Fresh,
+        }
+    }
+}
+
+/// An authenticated session
+#[derive(Debug, Clone)]
+pub struct AuthSession {
+    /// How the session was last authenticated
+    pub method: AuthMethod,
+    /// Wallet the session operates on
+    pub profile: WalletProfile,
+    last_auth: Instant,
+    last_activity: Instant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PinRecord {
+    salt: String,
+    hash: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PinState {
+    pin: Option<PinRecord>,
+    duress: Option<PinRecord>,
+    failed_attempts: u32,
+    locked_until: Option<u64>,
+}
+
+struct GateState {
+    pins: PinState,
+    session: Option<AuthSession>,
+}
+
+/// Biometric/PIN gate in front of signing and key export
+pub struct SecurityManager {
+    policy: SecurityPolicy,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    rng: SystemRandom,
+    state: Mutex<GateState>,
+}
+
+impl SecurityManager {
+    /// Load the PIN configuration from `storage`
+    pub async fn open(
+        policy: SecurityPolicy,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let pins = match storage.get(&ns, STATE_KEY).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => PinState::default(),
+        };
+        Ok(Self {
+            policy,
+            storage,
+            ns,
+            rng: SystemRandom::new(),
+            state: Mutex::new(GateState {
+                pins,
+                session: None,
+            }),
+        })
+    }
+
+    /// Active policy
+    pub const fn policy(&self) -> &SecurityPolicy {
+        &self.policy
+    }
+
+    /// Set or change the PIN.
+    ///
+    /// Changing an existing PIN requires a fresh PIN session on the primary
+    /// wallet.
+    pub async fn set_pin(&self, pin: &str) -> AnyaResult<()> {
+        validate_pin(pin)?;
+        let mut state = self.state.lock().await;
+        if state.pins.pin.is_some()
+            && self.check_session(&mut state, AuthRequirement::FreshPin)? != WalletProfile::Primary
+        {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                "PIN can only be changed from the primary wallet",
+            ));
+        }
+        state.pins.pin = Some(self.hash_pin(pin)?);
+        self.persist(&state.pins).await
+    }
+
+    /// Set the duress PIN that opens the decoy wallet
+    pub async fn set_duress_pin(&self, pin: &str) -> AnyaResult<()> {
+        validate_pin(pin)?;
+        let mut state = self.state.lock().await;
+        if self.check_session(&mut state, AuthRequirement::FreshPin)? != WalletProfile::Primary {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                "duress PIN can only be set from the primary wallet",
+            ));
+        }
+        if state.pins.pin.as_ref().is_some_and(|r| verify_pin(r, pin)) {
+            return Err(AnyaError::invalid_input(
+                "duress PIN must differ from the wallet PIN",
+            ));
+        }
+        state.pins.duress = Some(self.hash_pin(pin)?);
+        self.persist(&state.pins).await
+    }
+
+    /// Authenticate and start a session, returning the profile it opens
+    pub async fn authenticate(&self, credential: Credential) -> AnyaResult<WalletProfile> {
+        let mut state = self.state.lock().await;
+        let now = unix_now();
+        if state.pins.locked_until.is_some_and(|until| until > now) {
+            return Err(AnyaError::new(
+                ErrorCode::RateLimited,
+                "too many failed PIN attempts; try again later",
+            ));
+        }
+
+        let (method, profile) = match credential {
+            Credential::Biometric if self.policy.biometric_enabled => {
+                (AuthMethod::Biometric, WalletProfile::Primary)
+            }
+            Credential::Biometric => {
+                return Err(AnyaError::new(
+                    ErrorCode::PermissionDenied,
+                    "biometric authentication is disabled",
+                ))
+            }
+            Credential::Pin(pin) => {
+                let Some(record) = state.pins.pin.clone() else {
+                    return Err(AnyaError::new(ErrorCode::Config, "no PIN configured"));
+                };
+                if verify_pin(&record, &pin) {
+                    (AuthMethod::Pin, WalletProfile::Primary)
+                } else if state
+                    .pins
+                    .duress
+                    .as_ref()
+                    .is_some_and(|r| verify_pin(r, &pin))
+                {
+                    tracing::debug!("duress PIN entered");
+                    (AuthMethod::Pin, WalletProfile::Decoy)
+                } else {
+                    state.pins.failed_attempts += 1;
+                    if state.pins.failed_attempts >= self.policy.max_pin_attempts {
+                        state.pins.failed_attempts = 0;
+                        state.pins.locked_until = Some(now + self.policy.lockout.as_secs());
+                    }
+                    self.persist(&state.pins).await?;
+                    return Err(AnyaError::new(ErrorCode::Unauthenticated, "incorrect PIN"));
+                }
+            }
+        };
+
+        if state.pins.failed_attempts > 0 || state.pins.locked_until.is_some() {
+            state.pins.failed_attempts = 0;
+            state.pins.locked_until = None;
+            self.persist(&state.pins).await?;
+        }
+        let now = Instant::now();
+        state.session = Some(AuthSession {
+            method,
+            profile,
+            last_auth: now,
+            last_activity: now,
+        });
+        drop(state);
+        Ok(profile)
+    }
+
+    /// Check that the current session may perform `operation`.
+    ///
+    /// Returns the profile the operation must be performed against.
+    pub async fn authorize(&self, operation: Operation) -> AnyaResult<WalletProfile> {
+        let requirement = self.policy.requirement(operation);
+        let mut state = self.state.lock().await;
+        self.check_session(&mut state, requirement)
+    }
+
+    /// Current session, if one is active
+    pub async fn session(&self) -> Option<AuthSession> {
+        let state = self.state.lock().await;
+        state
+            .session
+            .clone()
+            .filter(|s| s.last_activity.elapsed() < self.policy.session_timeout)
+    }
+
+    /// End the current session
+    pub async fn lock(&self) {
+        self.state.lock().await.session = None;
+    }
+
+    fn check_session(
+        &self,
+        state: &mut GateState,
+        requirement: AuthRequirement,
+    ) -> AnyaResult<WalletProfile> {
+        let session = match state.session.as_mut() {
+            Some(s) if s.last_activity.elapsed() < self.policy.session_timeout => s,
+            _ => {
+                state.session = None;
+                return Err(AnyaError::new(
+                    ErrorCode::Unauthenticated,
+                    "authentication required",
+                ));
+            }
+        };
+        let fresh = session.last_auth.elapsed() <= self.policy.fresh_auth_window;
+        let satisfied = match requirement {
+            AuthRequirement::Session => true,
+            AuthRequirement::Fresh => fresh,
+            AuthRequirement::FreshPin => fresh && session.method == AuthMethod::Pin,
+        };
+        if !satisfied {
+            return Err(AnyaError::new(
+                ErrorCode::Unauthenticated,
+                format!("re-authentication required ({:?})", requirement),
+            ));
+        }
+        session.last_activity = Instant::now();
+        Ok(session.profile)
+    }
+
+    fn hash_pin(&self, pin: &str) -> AnyaResult<PinRecord> {
+        let mut salt = [0u8; SALT_LEN];
+        self.rng
+            .fill(&mut salt)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "system RNG failure"))?;
+        let mut hash = [0u8; 32];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            pin_iterations(),
+            &salt,
+            pin.as_bytes(),
+            &mut hash,
+        );
+        Ok(PinRecord {
+            salt: to_hex(&salt),
+            hash: to_hex(&hash),
+        })
+    }
+
+    async fn persist(&self, pins: &PinState) -> AnyaResult<()> {
+        let bytes = serde_json::to_vec(pins)?;
+        self.storage.put(&self.ns, STATE_KEY, &bytes).await
+    }
+}
+
+fn validate_pin(pin: &str) -> AnyaResult<()> {
+    if (4..=12).contains(&pin.len()) && pin.chars().all(|c| c.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(AnyaError::invalid_input("PIN must be 4 to 12 digits"))
+    }
+}
+
+fn verify_pin(record: &PinRecord, pin: &str) -> bool {
+    let (Ok(salt), Ok(hash)) = (from_hex(&record.salt), from_hex(&record.hash)) else {
+        return false;
+    };
+    ring::pbkdf2::verify(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        pin_iterations(),
+        &salt,
+        pin.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+fn pin_iterations() -> NonZeroU32 {
+    NonZeroU32::new(PIN_ITERATIONS).unwrap_or(NonZeroU32::MIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    async fn manager(policy: SecurityPolicy) -> SecurityManager {
+        let manager = SecurityManager::open(policy, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        manager.set_pin("2468").await.unwrap();
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_policy_thresholds_and_session_freshness() {
+        let policy = SecurityPolicy {
+            fresh_auth_window: Duration::ZERO,
+            ..SecurityPolicy::default()
+        };
+        let gate = manager(policy).await;
+        let small = Operation::Sign { amount_sat: 10_000 };
+        assert!(gate.authorize(small).await.is_err());
+
+        gate.authenticate(Credential::Biometric).await.unwrap();
+        assert_eq!(gate.authorize(small).await.unwrap(), WalletProfile::Primary);
+        // The fresh window has already elapsed, so large spends and exports re-prompt
+        let err = gate
+            .authorize(Operation::Sign {
+                amount_sat: 5_000_000,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+        assert!(gate.authorize(Operation::ExportKeys).await.is_err());
+
+        gate.lock().await;
+        assert!(gate.authorize(small).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_duress_pin_opens_decoy_and_lockout() {
+        let gate = manager(SecurityPolicy {
+            max_pin_attempts: 2,
+            ..SecurityPolicy::default()
+        })
+        .await;
+        gate.authenticate(Credential::Pin("2468".into()))
+            .await
+            .unwrap();
+        assert!(gate.set_duress_pin("2468").await.is_err());
+        gate.set_duress_pin("1357").await.unwrap();
+
+        assert_eq!(
+            gate.authenticate(Credential::Pin("1357".into()))
+                .await
+                .unwrap(),
+            WalletProfile::Decoy
+        );
+        assert_eq!(
+            gate.authorize(Operation::ExportKeys).await.unwrap(),
+            WalletProfile::Decoy
+        );
+
+        assert!(gate
+            .authenticate(Credential::Pin("0000".into()))
+            .await
+            .is_err());
+        assert!(gate
+            .authenticate(Credential::Pin("0000".into()))
+            .await
+            .is_err());
+        let err = gate
+            .authenticate(Credential::Pin("2468".into()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::RateLimited);
+    }
+}
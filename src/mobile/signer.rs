@@ -0,0 +1,402 @@
+//! Air-gapped PSBT signing
+//!
+//! In air-gapped mode the phone never touches the network for spending: a
+//! watch-only coordinator builds the PSBT, the [`AirGapSigner`] imports it
+//! from a file or an animated BBQr code, shows a [`TransactionSummary`] for
+//! the user to verify, signs offline, and exports the signed PSBT back.
+//!
+//! Outputs claiming to be ours are re-derived from the wallet key before they
+//! are shown as change, so a compromised coordinator cannot hide a payment
+//! behind a fake change label. Each signed input is also recorded, and a
+//! second PSBT spending the same input with a different transaction id is
+//! refused, so an earlier approval cannot be replayed with altered outputs.
+//!
+//! When a [`SpendingGuard`] is attached, its spending policy is enforced
+//! after authentication and before signing, and every signed spend counts
+//! towards its limits.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::bitcoin::bip32::{ExtendedPrivKey, Fingerprint};
+use ::bitcoin::psbt::Psbt;
+use ::bitcoin::{Address, OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+
+use super::qr::{bbqr_split, BbqrAssembler, BbqrEncoding, BbqrFileType};
+use super::security::{Operation, SecurityManager, WalletProfile};
+use super::spending::SpendingGuard;
+use crate::bitcoin::psbt_verify::{
+    is_internal_chain, spent_output, sum_sat, SignedLedger, WalletKey,
+};
+use crate::storage::StorageBackend;
+use crate::utils::encoding::from_hex;
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "mobile_signer";
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+const PSBT_MAGIC_HEX: &str = "70736274ff";
+
+/// How an output relates to the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKind {
+    /// Payment to someone else
+    External,
+    /// Verified change back to the wallet's internal chain
+    Change,
+    /// Verified payment to one of the wallet's own receive addresses
+    OwnReceive,
+}
+
+/// Input shown to the user before signing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputSummary {
+    /// Outpoint being spent
+    pub outpoint: OutPoint,
+    /// Value of the spent output in satoshis
+    pub amount_sat: u64,
+    /// Whether this wallet can sign the input
+    pub owned: bool,
+}
+
+/// Output shown to the user before signing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputSummary {
+    /// Destination address, if the script is a standard address type
+    pub address: Option<String>,
+    /// Output value in satoshis
+    pub amount_sat: u64,
+    /// Relationship to the wallet
+    pub kind: OutputKind,
+}
+
+/// Structured view of a PSBT for on-device verification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionSummary {
+    /// Id of the transaction being signed
+    pub txid: Txid,
+    /// Inputs in transaction order
+    pub inputs: Vec<InputSummary>,
+    /// Outputs in transaction order
+    pub outputs: Vec<OutputSummary>,
+    /// Fee paid in satoshis
+    pub fee_sat: u64,
+    /// Total paid to external outputs in satoshis
+    pub external_sat: u64,
+}
+
+/// Parse a PSBT from binary, hex, or base64 file contents
+pub fn import_psbt(data: &[u8]) -> AnyaResult<Psbt> {
+    if data.starts_with(PSBT_MAGIC) {
+        return Ok(Psbt::deserialize(data)?);
+    }
+    let text = std::str::from_utf8(data)
+        .map_err(|_| AnyaError::invalid_input("PSBT is neither binary nor text"))?
+        .trim();
+    if text.starts_with(PSBT_MAGIC_HEX) {
+        return Ok(Psbt::deserialize(&from_hex(text)?)?);
+    }
+    Psbt::from_str(text)
+        .map_err(|e| AnyaError::with_source(ErrorCode::InvalidInput, "invalid base64 PSBT", e))
+}
+
+/// Parse a PSBT from a completed BBQr sequence
+pub fn import_bbqr(assembler: &BbqrAssembler) -> AnyaResult<Psbt> {
+    match assembler.finish()? {
+        (BbqrFileType::Psbt, data) => import_psbt(&data),
+        (other, _) => Err(AnyaError::invalid_input(format!(
+            "expected a PSBT, scanned {:?}",
+            other
+        ))),
+    }
+}
+
+/// Offline signer holding the wallet's master key
+pub struct AirGapSigner {
+    key: WalletKey,
+    ledger: SignedLedger,
+    spending: Option<Arc<SpendingGuard>>,
+    /// Serializes ledger checks with the writes that follow them
+    signing: tokio::sync::Mutex<()>,
+}
+
+impl AirGapSigner {
+    /// Create a signer for `xpriv`, recording signed inputs in `storage`
+    pub async fn open(
+        xpriv: ExtendedPrivKey,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        Ok(Self {
+            key: WalletKey::new(xpriv),
+            ledger: SignedLedger::open(storage, NAMESPACE).await?,
+            spending: None,
+            signing: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Enforce `guard`'s spending policy on every signature
+    #[must_use]
+    pub fn with_spending_policy(mut self, guard: Arc<SpendingGuard>) -> Self {
+        self.spending = Some(guard);
+        self
+    }
+
+    /// Master key fingerprint the coordinator should reference
+    pub const fn fingerprint(&self) -> Fingerprint {
+        self.key.fingerprint()
+    }
+
+    /// Summarize a PSBT, rejecting it if any output falsely claims to be ours
+    pub fn inspect(&self, psbt: &Psbt) -> AnyaResult<TransactionSummary> {
+        let tx = &psbt.unsigned_tx;
+        let mut inputs = Vec::with_capacity(tx.input.len());
+        for (index, (txin, input)) in tx.input.iter().zip(&psbt.inputs).enumerate() {
+            let spent = spent_output(index, txin.previous_output, input)?;
+            inputs.push(InputSummary {
+                outpoint: txin.previous_output,
+                amount_sat: spent.value,
+                owned: self
+                    .key
+                    .verified_path(
+                        &spent.script_pubkey,
+                        &input.bip32_derivation,
+                        &input.tap_key_origins,
+                    )?
+                    .is_some(),
+            });
+        }
+
+        let mut outputs = Vec::with_capacity(tx.output.len());
+        for (txout, output) in tx.output.iter().zip(&psbt.outputs) {
+            let kind = match self.key.verified_path(
+                &txout.script_pubkey,
+                &output.bip32_derivation,
+                &output.tap_key_origins,
+            )? {
+                None => OutputKind::External,
+                Some(path) if is_internal_chain(&path) => OutputKind::Change,
+                Some(_) => OutputKind::OwnReceive,
+            };
+            outputs.push(OutputSummary {
+                address: Address::from_script(&txout.script_pubkey, self.key.network())
+                    .ok()
+                    .map(|a| a.to_string()),
+                amount_sat: txout.value,
+                kind,
+            });
+        }
+
+        let input_total = sum_sat(inputs.iter().map(|i| i.amount_sat))?;
+        let output_total = sum_sat(outputs.iter().map(|o| o.amount_sat))?;
+        let fee_sat = input_total
+            .checked_sub(output_total)
+            .ok_or_else(|| AnyaError::invalid_input("PSBT outputs exceed its inputs"))?;
+        Ok(TransactionSummary {
+            txid: tx.txid(),
+            external_sat: sum_sat(
+                outputs
+                    .iter()
+                    .filter(|o| o.kind == OutputKind::External)
+                    .map(|o| o.amount_sat),
+            )?,
+            inputs,
+            outputs,
+            fee_sat,
+        })
+    }
+
+    /// Verify, authorize, and sign every input this wallet owns
+    pub async fn sign(
+        &self,
+        psbt: &mut Psbt,
+        security: &SecurityManager,
+    ) -> AnyaResult<TransactionSummary> {
+        let summary = self.inspect(psbt)?;
+        let profile = security
+            .authorize(Operation::Sign {
+                amount_sat: sum_sat([summary.external_sat, summary.fee_sat])?,
+            })
+            .await?;
+        if profile != WalletProfile::Primary || !summary.inputs.iter().any(|i| i.owned) {
+            return Err(AnyaError::invalid_input(
+                "PSBT does not spend from this wallet",
+            ));
+        }
+
+        let owned: Vec<OutPoint> = summary
+            .inputs
+            .iter()
+            .filter(|i| i.owned)
+            .map(|i| i.outpoint)
+            .collect();
+        let lock = self.signing.lock().await;
+        self.ledger.check(&owned, &summary.txid).await?;
+        let now = unix_now();
+        if let Some(guard) = &self.spending {
+            guard.enforce(&summary, now).await?;
+        }
+
+        self.key.sign(psbt)?;
+        self.ledger.record(&owned, &summary.txid).await?;
+        if let Some(guard) = &self.spending {
+            guard.record_spend(&summary, now).await?;
+        }
+        drop(lock);
+        tracing::info!(txid = %summary.txid, inputs = owned.len(), "signed PSBT offline");
+        Ok(summary)
+    }
+
+    /// Signed PSBT as animated BBQr frames for the coordinator to scan
+    pub fn export_bbqr(psbt: &Psbt, max_chars: usize) -> AnyaResult<Vec<String>> {
+        Ok(bbqr_split(
+            &psbt.serialize(),
+            BbqrFileType::Psbt,
+            BbqrEncoding::Base32,
+            max_chars,
+        )?
+        .iter()
+        .map(|f| f.to_qr_string())
+        .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::psbt_verify::fixtures;
+    use crate::chaos::{FaultInjector, FaultRule, FaultyStorage};
+    use crate::mobile::security::{Credential, SecurityPolicy};
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::Network;
+
+    struct Fixture {
+        signer: AirGapSigner,
+        security: SecurityManager,
+        xpriv: ExtendedPrivKey,
+    }
+
+    async fn fixture() -> Fixture {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let xpriv = ExtendedPrivKey::new_master(Network::Testnet, &[7u8; 32]).unwrap();
+        let security = SecurityManager::open(SecurityPolicy::default(), Arc::clone(&storage))
+            .await
+            .unwrap();
+        security.set_pin("2468").await.unwrap();
+        security
+            .authenticate(Credential::Pin("2468".into()))
+            .await
+            .unwrap();
+        Fixture {
+            signer: AirGapSigner::open(xpriv, storage).await.unwrap(),
+            security,
+            xpriv,
+        }
+    }
+
+    fn build_psbt(f: &Fixture, external_value: u64) -> Psbt {
+        fixtures::build_psbt(&f.signer.key, external_value)
+    }
+
+    #[tokio::test]
+    async fn test_inspect_sign_and_roundtrip() {
+        let f = fixture().await;
+        let psbt = build_psbt(&f, 60_000);
+        let mut imported = import_psbt(psbt.to_string().as_bytes()).unwrap();
+        assert_eq!(imported, import_psbt(&psbt.serialize()).unwrap());
+
+        let summary = f.signer.sign(&mut imported, &f.security).await.unwrap();
+        assert_eq!(summary.fee_sat, 1_000);
+        assert_eq!(summary.external_sat, 60_000);
+        assert_eq!(summary.outputs[1].kind, OutputKind::Change);
+        assert!(summary.inputs[0].owned);
+        assert_eq!(imported.inputs[0].partial_sigs.len(), 1);
+
+        let frames = AirGapSigner::export_bbqr(&imported, 400).unwrap();
+        let mut assembler = BbqrAssembler::new();
+        for frame in frames {
+            match crate::mobile::qr::QrPayload::parse(&frame).unwrap() {
+                crate::mobile::qr::QrPayload::Bbqr(fragment) => assembler.add(fragment).unwrap(),
+                other => panic!("unexpected payload {:?}", other),
+            }
+        }
+        assert_eq!(import_bbqr(&assembler).unwrap(), imported);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_fake_change_and_replayed_inputs() {
+        let f = fixture().await;
+        let mut tampered = build_psbt(&f, 60_000);
+        tampered.unsigned_tx.output[1].script_pubkey =
+            tampered.unsigned_tx.output[0].script_pubkey.clone();
+        assert!(f.signer.inspect(&tampered).is_err());
+        let mut inflated = build_psbt(&f, 60_000);
+        inflated.unsigned_tx.output[0].value = u64::MAX;
+        let err = f.signer.inspect(&inflated).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+
+        let mut first = build_psbt(&f, 60_000);
+        f.signer.sign(&mut first, &f.security).await.unwrap();
+        // Same inputs, different payment amount
+        let mut replay = build_psbt(&f, 90_000);
+        let err = f.signer.sign(&mut replay, &f.security).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_conflicting_signs() {
+        let f = fixture().await;
+        // Slow ledger writes let both signs pass the check before either records
+        let faults = Arc::new(FaultInjector::new(1));
+        let mut slow = FaultRule::new("storage.put");
+        slow.latency = std::time::Duration::from_millis(5);
+        faults.add_rule(slow);
+        let storage = FaultyStorage::new(Arc::new(MemoryBackend::new()), faults);
+        let signer = AirGapSigner::open(f.xpriv, Arc::new(storage))
+            .await
+            .unwrap();
+
+        let (mut first, mut second) = (build_psbt(&f, 60_000), build_psbt(&f, 90_000));
+        let (a, b) = tokio::join!(
+            signer.sign(&mut first, &f.security),
+            signer.sign(&mut second, &f.security),
+        );
+        assert!(a.is_ok() != b.is_ok());
+        let err = a.err().or_else(|| b.err()).unwrap();
+        assert_eq!(err.code(), ErrorCode::Conflict);
+    }
+
+    #[tokio::test]
+    async fn test_spending_policy_blocks_before_signing() {
+        let f = fixture().await;
+        let guard = Arc::new(
+            SpendingGuard::open(Arc::new(MemoryBackend::new()))
+                .await
+                .unwrap(),
+        );
+        guard
+            .set_policy(
+                crate::mobile::spending::SpendingPolicy {
+                    daily_limit_sat: Some(100_000),
+                    ..Default::default()
+                },
+                "owner",
+            )
+            .await
+            .unwrap();
+        let signer = AirGapSigner::open(f.xpriv, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap()
+            .with_spending_policy(Arc::clone(&guard));
+
+        let mut first = build_psbt(&f, 60_000);
+        signer.sign(&mut first, &f.security).await.unwrap();
+        let mut second = build_psbt(&f, 50_000);
+        second.unsigned_tx.input[0].previous_output.vout = 1;
+        let err = signer.sign(&mut second, &f.security).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        assert!(second.inputs[0].partial_sigs.is_empty());
+        assert_eq!(guard.audit_log(0, 10).await.unwrap().len(), 2);
+    }
+}
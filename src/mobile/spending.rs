@@ -0,0 +1,647 @@
+//! Spending policies enforced at signing
+//!
+//! A [`SpendingPolicy`] caps what the wallet will sign: a rolling 24-hour
+//! limit, a maximum number of spends per velocity window, an optional
+//! destination allowlist, and amount thresholds above which named co-signers
+//! must approve the transaction first. The [`SpendingGuard`] is attached to
+//! the [`AirGapSigner`](super::signer::AirGapSigner), which consults it after
+//! the security gate and before any key is touched.
+//!
+//! Policies are versioned: every change is stored as a new version and the
+//! old ones are kept, so each audit record names the exact rules a decision
+//! was made under. Every evaluation at signing time is written to the audit
+//! log, whether it was allowed or not.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::Txid;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::signer::{OutputKind, TransactionSummary};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "mobile_spending";
+const POLICY_PREFIX: &str = "policy/";
+const ACTIVE_KEY: &str = "active";
+const SPEND_PREFIX: &str = "spend/";
+const APPROVAL_PREFIX: &str = "approval/";
+const AUDIT_PREFIX: &str = "audit/";
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Maximum number of signed spends within a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VelocityLimit {
+    /// Length of the rolling window
+    pub window: Duration,
+    /// Spends allowed within it, including the one being signed
+    pub max_spends: u32,
+}
+
+/// Spends at or above an amount need co-signer approval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalThreshold {
+    /// Amount leaving the wallet, in satoshis, that triggers the rule
+    pub at_or_above_sat: u64,
+    /// Distinct co-signer approvals required
+    pub approvals: usize,
+}
+
+/// Rules checked before a transaction is signed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendingPolicy {
+    /// Total leaving the wallet in any rolling 24 hours, in satoshis
+    pub daily_limit_sat: Option<u64>,
+    /// Limit on how often the wallet signs
+    pub velocity: Option<VelocityLimit>,
+    /// External destinations allowed; any destination when `None`
+    pub allowlist: Option<BTreeSet<String>>,
+    /// Approval requirements by amount
+    #[serde(default)]
+    pub approval_thresholds: Vec<ApprovalThreshold>,
+    /// Identities whose approvals count
+    #[serde(default)]
+    pub cosigners: BTreeSet<String>,
+}
+
+impl SpendingPolicy {
+    /// Approvals needed for a spend of `amount_sat`
+    pub fn approvals_required(&self, amount_sat: u64) -> usize {
+        self.approval_thresholds
+            .iter()
+            .filter(|t| amount_sat >= t.at_or_above_sat)
+            .map(|t| t.approvals)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn validate(&self) -> AnyaResult<()> {
+        if self.velocity.is_some_and(|v| v.window.is_zero()) {
+            return Err(AnyaError::invalid_input("velocity window must be non-zero"));
+        }
+        if let Some(threshold) = self
+            .approval_thresholds
+            .iter()
+            .find(|t| t.approvals > self.cosigners.len())
+        {
+            return Err(AnyaError::invalid_input(format!(
+                "threshold at {} sat needs {} approvals but only {} co-signers are configured",
+                threshold.at_or_above_sat,
+                threshold.approvals,
+                self.cosigners.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A stored policy version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyVersion {
+    /// Version number, starting at 1
+    pub version: u32,
+    /// When it was installed, seconds since the Unix epoch
+    pub created_at: u64,
+    /// Who installed it
+    pub author: String,
+    /// The rules
+    pub policy: SpendingPolicy,
+}
+
+/// A rule a spend broke
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum SpendViolation {
+    /// The rolling 24-hour total would exceed the limit
+    DailyLimit {
+        /// Already spent in the window
+        spent_sat: u64,
+        /// Configured limit
+        limit_sat: u64,
+    },
+    /// Too many spends within the velocity window
+    Velocity {
+        /// Spends already signed in the window
+        spends: u32,
+        /// Configured maximum
+        max_spends: u32,
+    },
+    /// Pays an address that is not on the allowlist
+    Destination {
+        /// Offending address, or the empty string for a non-standard script
+        address: String,
+    },
+    /// Not enough co-signers have approved
+    Approvals {
+        /// Approvals present
+        have: usize,
+        /// Approvals required
+        need: usize,
+    },
+}
+
+impl SpendViolation {
+    /// Explanation shown to the user
+    pub fn message(&self) -> String {
+        match self {
+            Self::DailyLimit {
+                spent_sat,
+                limit_sat,
+            } => format!(
+                "daily limit of {} sat reached ({} sat already spent)",
+                limit_sat, spent_sat
+            ),
+            Self::Velocity { spends, max_spends } => format!(
+                "{} spends already signed, at most {} allowed in the window",
+                spends, max_spends
+            ),
+            Self::Destination { address } if address.is_empty() => {
+                "non-standard destination is not on the allowlist".to_string()
+            }
+            Self::Destination { address } => format!("{} is not on the allowlist", address),
+            Self::Approvals { have, need } => {
+                format!("{} of {} co-signer approvals present", have, need)
+            }
+        }
+    }
+}
+
+/// Audit record of one evaluation at signing time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendAudit {
+    /// Position in the audit log
+    pub seq: u64,
+    /// Evaluation time, seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Transaction evaluated
+    pub txid: Txid,
+    /// Amount leaving the wallet, fee included
+    pub amount_sat: u64,
+    /// Policy version applied; 0 when no policy was installed
+    pub policy_version: u32,
+    /// Co-signers that had approved
+    pub approvals: Vec<String>,
+    /// Rules broken
+    pub violations: Vec<SpendViolation>,
+    /// Whether signing went ahead
+    pub allowed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SpendRecord {
+    timestamp: u64,
+    amount_sat: u64,
+}
+
+struct State {
+    active: Option<PolicyVersion>,
+    next_seq: u64,
+}
+
+/// Versioned spending policy, co-signer approvals, and the audit log
+pub struct SpendingGuard {
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    state: Mutex<State>,
+}
+
+impl SpendingGuard {
+    /// Open the guard in `storage`, restoring the active policy version
+    pub async fn open(storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let active = match storage.get(&ns, ACTIVE_KEY).await? {
+            Some(bytes) => Some(serde_json::from_slice(&bytes)?),
+            None => None,
+        };
+        let next_seq = storage
+            .scan_prefix(&ns, AUDIT_PREFIX)
+            .await?
+            .last()
+            .map(|(_, bytes)| serde_json::from_slice::<SpendAudit>(bytes))
+            .transpose()?
+            .map_or(0, |a| a.seq + 1);
+        Ok(Self {
+            storage,
+            ns,
+            state: Mutex::new(State { active, next_seq }),
+        })
+    }
+
+    /// Install `policy` as a new version and make it active
+    pub async fn set_policy(&self, policy: SpendingPolicy, author: &str) -> AnyaResult<u32> {
+        policy.validate()?;
+        let mut state = self.state.lock().await;
+        let version = PolicyVersion {
+            version: state.active.as_ref().map_or(1, |v| v.version + 1),
+            created_at: unix_now(),
+            author: author.to_string(),
+            policy,
+        };
+        let bytes = serde_json::to_vec(&version)?;
+        self.storage
+            .put(&self.ns, &policy_key(version.version), &bytes)
+            .await?;
+        self.storage.put(&self.ns, ACTIVE_KEY, &bytes).await?;
+        tracing::info!(version = version.version, author, "spending policy updated");
+        let number = version.version;
+        state.active = Some(version);
+        drop(state);
+        Ok(number)
+    }
+
+    /// Active policy version, if one was installed
+    pub async fn active(&self) -> Option<PolicyVersion> {
+        self.state.lock().await.active.clone()
+    }
+
+    /// Every stored policy version, oldest first
+    pub async fn versions(&self) -> AnyaResult<Vec<PolicyVersion>> {
+        self.storage
+            .scan_prefix(&self.ns, POLICY_PREFIX)
+            .await?
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    /// Record `cosigner`'s approval of `txid`
+    pub async fn approve(&self, txid: &Txid, cosigner: &str) -> AnyaResult<()> {
+        let known = self
+            .active()
+            .await
+            .is_some_and(|v| v.policy.cosigners.contains(cosigner));
+        if !known {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("{} is not a co-signer", cosigner),
+            ));
+        }
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}/{}", APPROVAL_PREFIX, txid, cosigner),
+                &unix_now().to_be_bytes(),
+            )
+            .await
+    }
+
+    /// Co-signers that approved `txid` and are still listed in the active policy
+    pub async fn approvals(&self, txid: &Txid) -> AnyaResult<Vec<String>> {
+        let cosigners = self
+            .active()
+            .await
+            .map(|v| v.policy.cosigners)
+            .unwrap_or_default();
+        self.approvals_among(txid, &cosigners).await
+    }
+
+    /// Rules `summary` would break at `now`, without logging
+    pub async fn evaluate(
+        &self,
+        summary: &TransactionSummary,
+        now: u64,
+    ) -> AnyaResult<Vec<SpendViolation>> {
+        let Some(active) = self.active().await else {
+            return Ok(Vec::new());
+        };
+        self.violations(&active.policy, summary, now).await
+    }
+
+    /// Evaluate `summary`, write an audit record, and fail with
+    /// `PermissionDenied` if the active policy blocks it
+    pub async fn enforce(&self, summary: &TransactionSummary, now: u64) -> AnyaResult<SpendAudit> {
+        let mut state = self.state.lock().await;
+        let (policy_version, violations) = match &state.active {
+            Some(active) => (
+                active.version,
+                self.violations(&active.policy, summary, now).await?,
+            ),
+            None => (0, Vec::new()),
+        };
+        let audit = SpendAudit {
+            seq: state.next_seq,
+            timestamp: now,
+            txid: summary.txid,
+            amount_sat: spent_amount(summary),
+            policy_version,
+            approvals: match &state.active {
+                Some(active) => {
+                    self.approvals_among(&summary.txid, &active.policy.cosigners)
+                        .await?
+                }
+                None => Vec::new(),
+            },
+            allowed: violations.is_empty(),
+            violations,
+        };
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{:016x}", AUDIT_PREFIX, audit.seq),
+                &serde_json::to_vec(&audit)?,
+            )
+            .await?;
+        state.next_seq += 1;
+        drop(state);
+
+        if audit.allowed {
+            return Ok(audit);
+        }
+        let reasons: Vec<String> = audit
+            .violations
+            .iter()
+            .map(SpendViolation::message)
+            .collect();
+        tracing::warn!(
+            txid = %audit.txid,
+            version = audit.policy_version,
+            reasons = ?reasons,
+            "spend blocked by policy"
+        );
+        Err(AnyaError::new(
+            ErrorCode::PermissionDenied,
+            format!("spend denied: {}", reasons.join("; ")),
+        ))
+    }
+
+    /// Count a signed spend towards the daily and velocity limits
+    pub async fn record_spend(&self, summary: &TransactionSummary, now: u64) -> AnyaResult<()> {
+        let record = SpendRecord {
+            timestamp: now,
+            amount_sat: spent_amount(summary),
+        };
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", SPEND_PREFIX, summary.txid),
+                &serde_json::to_vec(&record)?,
+            )
+            .await?;
+
+        let retention = self
+            .active()
+            .await
+            .and_then(|v| v.policy.velocity)
+            .map_or(DAY, |v| v.window.max(DAY))
+            .as_secs();
+        for (key, bytes) in self.storage.scan_prefix(&self.ns, SPEND_PREFIX).await? {
+            let record: SpendRecord = serde_json::from_slice(&bytes)?;
+            if record.timestamp + retention < now {
+                self.storage.delete(&self.ns, &key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Audit records from `from_seq` on, oldest first
+    pub async fn audit_log(&self, from_seq: u64, limit: usize) -> AnyaResult<Vec<SpendAudit>> {
+        let mut records = Vec::new();
+        for (_, bytes) in self.storage.scan_prefix(&self.ns, AUDIT_PREFIX).await? {
+            let audit: SpendAudit = serde_json::from_slice(&bytes)?;
+            if audit.seq >= from_seq {
+                records.push(audit);
+            }
+            if records.len() == limit {
+                break;
+            }
+        }
+        Ok(records)
+    }
+
+    async fn violations(
+        &self,
+        policy: &SpendingPolicy,
+        summary: &TransactionSummary,
+        now: u64,
+    ) -> AnyaResult<Vec<SpendViolation>> {
+        let amount = spent_amount(summary);
+        let mut violations = Vec::new();
+
+        let spends = self.recent_spends(summary.txid).await?;
+        if let Some(limit_sat) = policy.daily_limit_sat {
+            let spent_sat: u64 = spends
+                .iter()
+                .filter(|s| s.timestamp + DAY.as_secs() > now)
+                .map(|s| s.amount_sat)
+                .sum();
+            if spent_sat + amount > limit_sat {
+                violations.push(SpendViolation::DailyLimit {
+                    spent_sat,
+                    limit_sat,
+                });
+            }
+        }
+        if let Some(velocity) = policy.velocity {
+            let window = velocity.window.as_secs();
+            let count = spends.iter().filter(|s| s.timestamp + window > now).count();
+            let spends = u32::try_from(count).unwrap_or(u32::MAX);
+            if spends >= velocity.max_spends {
+                violations.push(SpendViolation::Velocity {
+                    spends,
+                    max_spends: velocity.max_spends,
+                });
+            }
+        }
+        if let Some(allowlist) = &policy.allowlist {
+            for output in summary
+                .outputs
+                .iter()
+                .filter(|o| o.kind == OutputKind::External)
+            {
+                let address = output.address.clone().unwrap_or_default();
+                if !allowlist.contains(&address) {
+                    violations.push(SpendViolation::Destination { address });
+                }
+            }
+        }
+        let need = policy.approvals_required(amount);
+        if need > 0 {
+            let have = self
+                .approvals_among(&summary.txid, &policy.cosigners)
+                .await?
+                .len();
+            if have < need {
+                violations.push(SpendViolation::Approvals { have, need });
+            }
+        }
+        Ok(violations)
+    }
+
+    async fn approvals_among(
+        &self,
+        txid: &Txid,
+        cosigners: &BTreeSet<String>,
+    ) -> AnyaResult<Vec<String>> {
+        let prefix = format!("{}{}/", APPROVAL_PREFIX, txid);
+        Ok(self
+            .storage
+            .scan_prefix(&self.ns, &prefix)
+            .await?
+            .into_iter()
+            .filter_map(|(key, _)| key.strip_prefix(&prefix).map(str::to_string))
+            .filter(|c| cosigners.contains(c))
+            .collect())
+    }
+
+    /// Spends signed so far, excluding `txid` so re-signing the same
+    /// transaction does not count against itself
+    async fn recent_spends(&self, txid: Txid) -> AnyaResult<Vec<SpendRecord>> {
+        let own_key = format!("{}{}", SPEND_PREFIX, txid);
+        self.storage
+            .scan_prefix(&self.ns, SPEND_PREFIX)
+            .await?
+            .iter()
+            .filter(|(key, _)| *key != own_key)
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+}
+
+/// Amount leaving the wallet: external payments plus the fee
+const fn spent_amount(summary: &TransactionSummary) -> u64 {
+    summary.external_sat + summary.fee_sat
+}
+
+fn policy_key(version: u32) -> String {
+    format!("{}{:08x}", POLICY_PREFIX, version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobile::signer::OutputSummary;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::hashes::Hash;
+
+    const NOW: u64 = 1_700_000_000;
+    const SHOP: &str = "tb1qshop";
+
+    fn summary(seed: u8, address: &str, external_sat: u64) -> TransactionSummary {
+        TransactionSummary {
+            txid: Txid::from_byte_array([seed; 32]),
+            inputs: Vec::new(),
+            outputs: vec![OutputSummary {
+                address: Some(address.to_string()),
+                amount_sat: external_sat,
+                kind: OutputKind::External,
+            }],
+            fee_sat: 0,
+            external_sat,
+        }
+    }
+
+    async fn guard() -> SpendingGuard {
+        SpendingGuard::open(Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_daily_limit_velocity_and_allowlist() {
+        let guard = guard().await;
+        guard
+            .set_policy(
+                SpendingPolicy {
+                    daily_limit_sat: Some(100_000),
+                    velocity: Some(VelocityLimit {
+                        window: Duration::from_secs(3600),
+                        max_spends: 2,
+                    }),
+                    allowlist: Some([SHOP.to_string()].into()),
+                    ..SpendingPolicy::default()
+                },
+                "owner",
+            )
+            .await
+            .unwrap();
+
+        let first = summary(1, SHOP, 60_000);
+        guard.enforce(&first, NOW).await.unwrap();
+        guard.record_spend(&first, NOW).await.unwrap();
+        // Re-signing the same transaction does not count against itself
+        assert!(guard.evaluate(&first, NOW).await.unwrap().is_empty());
+
+        let violations = guard
+            .evaluate(&summary(2, SHOP, 50_000), NOW)
+            .await
+            .unwrap();
+        assert_eq!(
+            violations,
+            vec![SpendViolation::DailyLimit {
+                spent_sat: 60_000,
+                limit_sat: 100_000
+            }]
+        );
+        let violations = guard
+            .evaluate(&summary(3, "tb1qelsewhere", 1_000), NOW)
+            .await
+            .unwrap();
+        assert!(matches!(
+            violations[..],
+            [SpendViolation::Destination { .. }]
+        ));
+
+        let second = summary(4, SHOP, 1_000);
+        guard.record_spend(&second, NOW + 10).await.unwrap();
+        let violations = guard
+            .evaluate(&summary(5, SHOP, 1_000), NOW + 20)
+            .await
+            .unwrap();
+        assert!(matches!(
+            violations[..],
+            [SpendViolation::Velocity { spends: 2, .. }]
+        ));
+        // Both windows have rolled over a day later
+        assert!(guard
+            .evaluate(&summary(5, SHOP, 90_000), NOW + DAY.as_secs())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cosigner_approvals_versions_and_audit() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let guard = SpendingGuard::open(Arc::clone(&storage)).await.unwrap();
+        let mut policy = SpendingPolicy {
+            approval_thresholds: vec![ApprovalThreshold {
+                at_or_above_sat: 500_000,
+                approvals: 2,
+            }],
+            ..SpendingPolicy::default()
+        };
+        assert!(guard.set_policy(policy.clone(), "owner").await.is_err());
+        policy.cosigners = ["alice".to_string(), "bob".to_string()].into();
+        assert_eq!(guard.set_policy(policy.clone(), "owner").await.unwrap(), 1);
+
+        let large = summary(1, SHOP, 600_000);
+        let err = guard.enforce(&large, NOW).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        assert!(guard.approve(&large.txid, "mallory").await.is_err());
+        guard.approve(&large.txid, "alice").await.unwrap();
+        guard.approve(&large.txid, "bob").await.unwrap();
+        let audit = guard.enforce(&large, NOW).await.unwrap();
+        assert_eq!(audit.approvals, vec!["alice", "bob"]);
+        assert_eq!(audit.policy_version, 1);
+
+        // Removing a co-signer in a new version drops their approval
+        policy.cosigners = ["alice".to_string(), "carol".to_string()].into();
+        assert_eq!(guard.set_policy(policy, "owner").await.unwrap(), 2);
+        assert!(guard.enforce(&large, NOW).await.is_err());
+
+        let reopened = SpendingGuard::open(storage).await.unwrap();
+        assert_eq!(reopened.active().await.unwrap().version, 2);
+        assert_eq!(reopened.versions().await.unwrap().len(), 2);
+        let log = reopened.audit_log(0, 10).await.unwrap();
+        assert_eq!(
+            log.iter().map(|a| (a.seq, a.allowed)).collect::<Vec<_>>(),
+            vec![(0, false), (1, true), (2, false)]
+        );
+        assert_eq!(log[2].policy_version, 2);
+    }
+}
@@ -0,0 +1,794 @@
+//! Channel splicing
+//!
+//! Splicing changes a channel's capacity without closing it: a new funding
+//! transaction spends the current funding output and creates a larger
+//! (splice-in) or smaller (splice-out) one, while the channel keeps
+//! operating. The transaction is built with the BOLT-2 interactive
+//! construction protocol: each side adds its inputs and outputs with
+//! [`TxMessage`]s tagged by serial ids (even for the initiator, odd for the
+//! peer) until both send `Complete` in a row.
+//!
+//! [`SpliceManager`] initiates splices for the wallet, paying for the
+//! shared input and output plus its own contribution, and persists each
+//! channel's pending splice so its state survives restarts until the new
+//! funding transaction is deep enough to lock in the new capacity.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use ::bitcoin::absolute::LockTime;
+use ::bitcoin::{OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::lightning::ChannelInfo;
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "lightning_splices";
+
+/// Most inputs or outputs either side may add, as in BOLT-2
+const MAX_ADDS: usize = 4096;
+/// Outputs below this are not created
+const DUST_LIMIT_SAT: u64 = 546;
+
+/// Virtual sizes used for fee estimation
+const COMMON_VBYTES: u64 = 11;
+const SHARED_INPUT_VBYTES: u64 = 104;
+const WALLET_INPUT_VBYTES: u64 = 68;
+const OUTPUT_VBYTES: u64 = 43;
+
+/// Interactive transaction construction message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxMessage {
+    /// Add an input spending `prevout`
+    AddInput {
+        /// Sender-chosen id; its parity identifies the sender
+        serial_id: u64,
+        /// Spent outpoint
+        prevout: OutPoint,
+        /// Output being spent, for fee and amount checks
+        prev_txout: TxOut,
+        /// Input sequence
+        sequence: u32,
+    },
+    /// Add an output
+    AddOutput {
+        /// Sender-chosen id; its parity identifies the sender
+        serial_id: u64,
+        /// Output value
+        value_sat: u64,
+        /// Output script
+        script: ScriptBuf,
+    },
+    /// Remove an input the sender added
+    RemoveInput {
+        /// Serial id of the input
+        serial_id: u64,
+    },
+    /// Remove an output the sender added
+    RemoveOutput {
+        /// Serial id of the output
+        serial_id: u64,
+    },
+    /// The sender has nothing more to add
+    Complete,
+}
+
+/// Transaction under interactive construction
+#[derive(Debug, Clone, Default)]
+pub struct InteractiveTx {
+    initiator: bool,
+    inputs: BTreeMap<u64, (OutPoint, TxOut, Sequence)>,
+    outputs: BTreeMap<u64, TxOut>,
+    adds: [usize; 2],
+}
+
+impl InteractiveTx {
+    /// Empty construction; `initiator` selects the local serial id parity
+    pub fn new(initiator: bool) -> Self {
+        Self {
+            initiator,
+            ..Self::default()
+        }
+    }
+
+    /// Apply a message sent by the local side (`local`) or the peer
+    pub fn apply(&mut self, message: &TxMessage, local: bool) -> AnyaResult<()> {
+        let sender_is_initiator = local == self.initiator;
+        let check_serial = |serial_id: u64| {
+            if (serial_id & 1 == 0) == sender_is_initiator {
+                Ok(())
+            } else {
+                Err(AnyaError::invalid_input(format!(
+                    "serial id {} has the wrong parity",
+                    serial_id
+                )))
+            }
+        };
+        let side = usize::from(local);
+        match message {
+            TxMessage::AddInput {
+                serial_id,
+                prevout,
+                prev_txout,
+                sequence,
+            } => {
+                check_serial(*serial_id)?;
+                if self.inputs.values().any(|(p, _, _)| p == prevout) {
+                    return Err(AnyaError::invalid_input(format!(
+                        "{} is already spent by the transaction",
+                        prevout
+                    )));
+                }
+                self.count_add(side)?;
+                if self.inputs.contains_key(serial_id) {
+                    return Err(duplicate(*serial_id));
+                }
+                self.inputs.insert(
+                    *serial_id,
+                    (*prevout, prev_txout.clone(), Sequence(*sequence)),
+                );
+            }
+            TxMessage::AddOutput {
+                serial_id,
+                value_sat,
+                script,
+            } => {
+                check_serial(*serial_id)?;
+                self.count_add(side)?;
+                if self.outputs.contains_key(serial_id) {
+                    return Err(duplicate(*serial_id));
+                }
+                self.outputs.insert(
+                    *serial_id,
+                    TxOut {
+                        value: *value_sat,
+                        script_pubkey: script.clone(),
+                    },
+                );
+            }
+            TxMessage::RemoveInput { serial_id } => {
+                check_serial(*serial_id)?;
+                self.inputs
+                    .remove(serial_id)
+                    .ok_or_else(|| unknown(*serial_id))?;
+            }
+            TxMessage::RemoveOutput { serial_id } => {
+                check_serial(*serial_id)?;
+                self.outputs
+                    .remove(serial_id)
+                    .ok_or_else(|| unknown(*serial_id))?;
+            }
+            TxMessage::Complete => {}
+        }
+        Ok(())
+    }
+
+    /// Sum of the spent outputs
+    pub fn input_value(&self) -> u64 {
+        self.inputs.values().map(|(_, txout, _)| txout.value).sum()
+    }
+
+    /// Sum of the created outputs
+    pub fn output_value(&self) -> u64 {
+        self.outputs.values().map(|txout| txout.value).sum()
+    }
+
+    /// Unsigned transaction with inputs and outputs ordered by serial id
+    pub fn to_transaction(&self) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: self
+                .inputs
+                .values()
+                .map(|(prevout, _, sequence)| TxIn {
+                    previous_output: *prevout,
+                    script_sig: ScriptBuf::new(),
+                    sequence: *sequence,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: self.outputs.values().cloned().collect(),
+        }
+    }
+
+    fn count_add(&mut self, side: usize) -> AnyaResult<()> {
+        self.adds[side] += 1;
+        if self.adds[side] > MAX_ADDS {
+            return Err(AnyaError::invalid_input(
+                "too many inputs and outputs added",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn duplicate(serial_id: u64) -> AnyaError {
+    AnyaError::invalid_input(format!("serial id {} is already in use", serial_id))
+}
+
+fn unknown(serial_id: u64) -> AnyaError {
+    AnyaError::invalid_input(format!("serial id {} was never added", serial_id))
+}
+
+/// Peer's answer to a splice proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpliceAck {
+    /// Amount the peer adds (positive) or removes (negative) from the channel
+    pub remote_contribution_sat: i64,
+}
+
+/// Lightning node operations needed to splice a channel
+#[async_trait]
+pub trait SpliceNode: Send + Sync {
+    /// Funding output script of the channel, kept by the splice
+    async fn funding_script(&self, channel_id: &str) -> AnyaResult<ScriptBuf>;
+
+    /// Propose a splice of `local_contribution_sat` at `feerate_sat_vb`
+    async fn propose(
+        &self,
+        channel_id: &str,
+        local_contribution_sat: i64,
+        feerate_sat_vb: u64,
+    ) -> AnyaResult<SpliceAck>;
+
+    /// Send one construction message and return the peer's reply
+    async fn exchange(&self, channel_id: &str, message: TxMessage) -> AnyaResult<TxMessage>;
+
+    /// Sign the shared and wallet inputs, exchanging signatures with the peer
+    async fn sign(&self, channel_id: &str, tx: Transaction) -> AnyaResult<Transaction>;
+}
+
+/// A requested capacity change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpliceRequest {
+    /// Sats to add (positive) or withdraw (negative)
+    pub relative_sat: i64,
+    /// Destination of withdrawn funds; required for a splice-out
+    pub splice_out_script: Option<ScriptBuf>,
+    /// Wallet coins funding a splice-in and the fee
+    pub wallet_inputs: Vec<(OutPoint, TxOut)>,
+    /// Where leftover wallet funds go
+    pub change_script: ScriptBuf,
+    /// Fee rate of the splice transaction
+    pub feerate_sat_vb: u64,
+}
+
+/// Progress of a splice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpliceState {
+    /// Transaction built and signed
+    Signed,
+    /// Transaction handed to the network
+    Broadcast,
+    /// New funding output is deep enough; the new capacity applies
+    Locked,
+    /// Abandoned before broadcast
+    Aborted,
+}
+
+/// Persisted state of a channel's latest splice
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpliceRecord {
+    /// Channel id
+    pub channel_id: String,
+    /// Funding outpoint being spent
+    pub previous_funding: OutPoint,
+    /// Capacity before the splice
+    pub previous_capacity_sat: u64,
+    /// Capacity after the splice
+    pub new_capacity_sat: u64,
+    /// Signed splice transaction
+    pub tx: Transaction,
+    /// Fee paid by the wallet
+    pub fee_sat: u64,
+    /// Current state
+    pub state: SpliceState,
+    /// Creation time, seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+impl SpliceRecord {
+    /// Txid of the splice transaction
+    pub fn txid(&self) -> Txid {
+        self.tx.txid()
+    }
+
+    /// New funding outpoint
+    pub fn funding_txo(&self) -> AnyaResult<OutPoint> {
+        let vout = self
+            .tx
+            .output
+            .iter()
+            .position(|o| o.value == self.new_capacity_sat)
+            .ok_or_else(|| AnyaError::new(ErrorCode::Internal, "splice has no funding output"))?;
+        Ok(OutPoint::new(
+            self.txid(),
+            u32::try_from(vout).unwrap_or(u32::MAX),
+        ))
+    }
+}
+
+/// Splice settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpliceConfig {
+    /// Confirmations before the new capacity is locked in
+    pub min_depth: u32,
+    /// Most construction round trips before giving up
+    pub max_rounds: usize,
+}
+
+impl Default for SpliceConfig {
+    fn default() -> Self {
+        Self {
+            min_depth: 3,
+            max_rounds: 64,
+        }
+    }
+}
+
+/// Initiates splices and tracks them until locked
+pub struct SpliceManager {
+    config: SpliceConfig,
+    node: Arc<dyn SpliceNode>,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    /// Serializes splices so a channel never has two in flight
+    splicing: Mutex<()>,
+}
+
+impl SpliceManager {
+    /// Open the splice store in `storage`
+    pub async fn open(
+        config: SpliceConfig,
+        node: Arc<dyn SpliceNode>,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            config,
+            node,
+            storage,
+            ns,
+            splicing: Mutex::new(()),
+        })
+    }
+
+    /// Negotiate and sign a splice of `channel`
+    pub async fn splice(
+        &self,
+        channel: &ChannelInfo,
+        request: SpliceRequest,
+    ) -> AnyaResult<SpliceRecord> {
+        let guard = self.splicing.lock().await;
+        if let Some(current) = self.record(&channel.channel_id).await? {
+            if matches!(current.state, SpliceState::Signed | SpliceState::Broadcast) {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    "channel already has a splice in progress",
+                ));
+            }
+        }
+        let previous_funding = channel
+            .funding_txo
+            .ok_or_else(|| AnyaError::invalid_input("channel has no funding outpoint"))?;
+        if !channel.is_ready {
+            return Err(AnyaError::invalid_input("channel is not ready"));
+        }
+        let withdrawn = request.relative_sat.min(0).unsigned_abs();
+        let added = u64::try_from(request.relative_sat.max(0)).unwrap_or_default();
+        if withdrawn > channel.outbound_msat / 1_000 {
+            return Err(AnyaError::new(
+                ErrorCode::InsufficientFunds,
+                "splice-out exceeds the local channel balance",
+            ));
+        }
+        let splice_out = match (&request.splice_out_script, withdrawn) {
+            (_, 0) => None,
+            (Some(script), amount) => Some((script.clone(), amount)),
+            (None, _) => {
+                return Err(AnyaError::invalid_input(
+                    "splice-out needs a destination script",
+                ))
+            }
+        };
+
+        let channel_id = &channel.channel_id;
+        let script = self.node.funding_script(channel_id).await?;
+        let ack = self
+            .node
+            .propose(channel_id, request.relative_sat, request.feerate_sat_vb)
+            .await?;
+
+        // The initiator pays for the common fields and shared input and output
+        let outputs = 1 + u64::from(splice_out.is_some()) + 1;
+        let vbytes = COMMON_VBYTES
+            + SHARED_INPUT_VBYTES
+            + WALLET_INPUT_VBYTES * request.wallet_inputs.len() as u64
+            + OUTPUT_VBYTES * outputs;
+        let fee = request.feerate_sat_vb.saturating_mul(vbytes);
+        let wallet_in: u64 = request.wallet_inputs.iter().map(|(_, o)| o.value).sum();
+        let (change, fee_from_channel) = if request.wallet_inputs.is_empty() {
+            (0, fee)
+        } else {
+            let change = wallet_in.checked_sub(added + fee).ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::InsufficientFunds,
+                    "wallet inputs do not cover the splice-in and fee",
+                )
+            })?;
+            (change, 0)
+        };
+        let new_capacity = channel
+            .capacity_sat
+            .checked_add(added)
+            .and_then(|c| c.checked_sub(withdrawn + fee_from_channel))
+            .and_then(|c| c.checked_add_signed(ack.remote_contribution_sat))
+            .filter(|c| *c > DUST_LIMIT_SAT)
+            .ok_or_else(|| AnyaError::invalid_input("splice leaves no channel capacity"))?;
+
+        let mut ours = VecDeque::new();
+        let mut serials = (0..).step_by(2);
+        let mut next_serial = || serials.next().unwrap_or_default();
+        let shared_prev = TxOut {
+            value: channel.capacity_sat,
+            script_pubkey: script.clone(),
+        };
+        for (prevout, prev_txout) in
+            std::iter::once((previous_funding, shared_prev)).chain(request.wallet_inputs.clone())
+        {
+            ours.push_back(TxMessage::AddInput {
+                serial_id: next_serial(),
+                prevout,
+                prev_txout,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME.0,
+            });
+        }
+        let mut expected = vec![(new_capacity, script)];
+        expected.extend(splice_out.map(|(script, amount)| (amount, script)));
+        if change >= DUST_LIMIT_SAT {
+            expected.push((change, request.change_script.clone()));
+        }
+        for (value_sat, script) in &expected {
+            ours.push_back(TxMessage::AddOutput {
+                serial_id: next_serial(),
+                value_sat: *value_sat,
+                script: script.clone(),
+            });
+        }
+
+        let construction = self.negotiate(channel_id, ours).await?;
+        let tx = construction.to_transaction();
+        check_splice(&construction, &tx, previous_funding, &expected)?;
+        let signed = self.node.sign(channel_id, tx).await?;
+        let record = SpliceRecord {
+            channel_id: channel_id.clone(),
+            previous_funding,
+            previous_capacity_sat: channel.capacity_sat,
+            new_capacity_sat: new_capacity,
+            tx: signed,
+            fee_sat: fee,
+            state: SpliceState::Signed,
+            created_at: unix_now(),
+        };
+        self.save(&record).await?;
+        drop(guard);
+        tracing::info!(
+            channel = %channel_id,
+            from = channel.capacity_sat,
+            to = new_capacity,
+            "splice signed"
+        );
+        Ok(record)
+    }
+
+    /// Latest splice of a channel
+    pub async fn record(&self, channel_id: &str) -> AnyaResult<Option<SpliceRecord>> {
+        self.storage
+            .get(&self.ns, channel_id)
+            .await?
+            .map(|v| serde_json::from_slice(&v))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Record that the splice transaction was broadcast
+    pub async fn mark_broadcast(&self, channel_id: &str) -> AnyaResult<SpliceRecord> {
+        self.transition(channel_id, |state| match state {
+            SpliceState::Signed | SpliceState::Broadcast => Some(SpliceState::Broadcast),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Record `depth` confirmations, locking the splice at the minimum depth
+    pub async fn confirm(&self, channel_id: &str, depth: u32) -> AnyaResult<SpliceRecord> {
+        let min_depth = self.config.min_depth;
+        self.transition(channel_id, |state| match state {
+            SpliceState::Signed | SpliceState::Broadcast if depth >= min_depth => {
+                Some(SpliceState::Locked)
+            }
+            SpliceState::Signed | SpliceState::Broadcast => Some(SpliceState::Broadcast),
+            SpliceState::Locked => Some(SpliceState::Locked),
+            SpliceState::Aborted => None,
+        })
+        .await
+    }
+
+    /// Abandon a splice that was never broadcast
+    pub async fn abort(&self, channel_id: &str) -> AnyaResult<SpliceRecord> {
+        self.transition(channel_id, |state| {
+            (state == SpliceState::Signed).then_some(SpliceState::Aborted)
+        })
+        .await
+    }
+
+    /// Channel with the capacity and funding outpoint of a locked splice
+    pub async fn apply(&self, channel: &ChannelInfo) -> AnyaResult<ChannelInfo> {
+        let mut channel = channel.clone();
+        if let Some(record) = self.record(&channel.channel_id).await? {
+            if record.state == SpliceState::Locked {
+                channel.capacity_sat = record.new_capacity_sat;
+                channel.funding_txo = Some(record.funding_txo()?);
+            }
+        }
+        Ok(channel)
+    }
+
+    async fn negotiate(
+        &self,
+        channel_id: &str,
+        mut ours: VecDeque<TxMessage>,
+    ) -> AnyaResult<InteractiveTx> {
+        let mut construction = InteractiveTx::new(true);
+        for _ in 0..self.config.max_rounds {
+            let message = ours.pop_front().unwrap_or(TxMessage::Complete);
+            construction.apply(&message, true)?;
+            let done = message == TxMessage::Complete;
+            let reply = self.node.exchange(channel_id, message).await?;
+            construction.apply(&reply, false)?;
+            if done && reply == TxMessage::Complete {
+                return Ok(construction);
+            }
+        }
+        Err(AnyaError::new(
+            ErrorCode::Timeout,
+            "splice negotiation did not complete",
+        ))
+    }
+
+    async fn transition(
+        &self,
+        channel_id: &str,
+        next: impl FnOnce(SpliceState) -> Option<SpliceState>,
+    ) -> AnyaResult<SpliceRecord> {
+        let _guard = self.splicing.lock().await;
+        let mut record = self
+            .record(channel_id)
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("no splice for {}", channel_id)))?;
+        record.state = next(record.state).ok_or_else(|| {
+            AnyaError::new(ErrorCode::Conflict, format!("splice is {:?}", record.state))
+        })?;
+        self.save(&record).await?;
+        Ok(record)
+    }
+
+    async fn save(&self, record: &SpliceRecord) -> AnyaResult<()> {
+        self.storage
+            .put(&self.ns, &record.channel_id, &serde_json::to_vec(record)?)
+            .await
+    }
+}
+
+/// Check the negotiated transaction spends the funding output once, keeps
+/// every output we asked for, and does not take more than it spends
+fn check_splice(
+    construction: &InteractiveTx,
+    tx: &Transaction,
+    previous_funding: OutPoint,
+    expected: &[(u64, ScriptBuf)],
+) -> AnyaResult<()> {
+    let spends = tx
+        .input
+        .iter()
+        .filter(|i| i.previous_output == previous_funding)
+        .count();
+    if spends != 1 {
+        return Err(AnyaError::invalid_input(
+            "splice must spend the funding output exactly once",
+        ));
+    }
+    let mut claimed = HashSet::new();
+    for (value, script) in expected {
+        let found =
+            tx.output.iter().enumerate().find(|(i, o)| {
+                !claimed.contains(i) && o.value == *value && o.script_pubkey == *script
+            });
+        let (index, _) = found.ok_or_else(|| {
+            AnyaError::invalid_input("peer removed or changed one of our outputs")
+        })?;
+        claimed.insert(index);
+    }
+    if construction.output_value() > construction.input_value() {
+        return Err(AnyaError::invalid_input("splice outputs exceed its inputs"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::hashes::Hash;
+    use std::sync::Mutex as StdMutex;
+
+    /// Peer that adds one input and one change output of its own
+    struct PeerNode {
+        queued: StdMutex<VecDeque<TxMessage>>,
+        sent: StdMutex<Vec<TxMessage>>,
+    }
+
+    impl PeerNode {
+        fn new() -> Self {
+            let peer_input = TxMessage::AddInput {
+                serial_id: 1,
+                prevout: outpoint(9),
+                prev_txout: txout(30_000),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME.0,
+            };
+            let peer_change = TxMessage::AddOutput {
+                serial_id: 3,
+                value_sat: 9_000,
+                script: ScriptBuf::from(vec![0x51]),
+            };
+            Self {
+                queued: StdMutex::new(VecDeque::from([peer_input, peer_change])),
+                sent: StdMutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SpliceNode for PeerNode {
+        async fn funding_script(&self, _channel_id: &str) -> AnyaResult<ScriptBuf> {
+            Ok(ScriptBuf::from(vec![0x00, 0x20]))
+        }
+
+        async fn propose(&self, _: &str, _: i64, _: u64) -> AnyaResult<SpliceAck> {
+            Ok(SpliceAck {
+                remote_contribution_sat: 20_000,
+            })
+        }
+
+        async fn exchange(&self, _: &str, message: TxMessage) -> AnyaResult<TxMessage> {
+            self.sent.lock().unwrap().push(message);
+            let reply = self.queued.lock().unwrap().pop_front();
+            Ok(reply.unwrap_or(TxMessage::Complete))
+        }
+
+        async fn sign(&self, _: &str, mut tx: Transaction) -> AnyaResult<Transaction> {
+            for input in &mut tx.input {
+                input.witness.push([1u8; 64]);
+            }
+            Ok(tx)
+        }
+    }
+
+    fn outpoint(n: u8) -> OutPoint {
+        OutPoint::new(Txid::from_byte_array([n; 32]), 0)
+    }
+
+    fn txout(value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: ScriptBuf::from(vec![0x00, 0x14]),
+        }
+    }
+
+    fn channel() -> ChannelInfo {
+        ChannelInfo {
+            channel_id: "chan".into(),
+            counterparty: "peer".into(),
+            funding_txo: Some(outpoint(1)),
+            capacity_sat: 100_000,
+            outbound_msat: 60_000_000,
+            inbound_msat: 40_000_000,
+            is_ready: true,
+        }
+    }
+
+    fn splice_in() -> SpliceRequest {
+        SpliceRequest {
+            relative_sat: 50_000,
+            splice_out_script: None,
+            wallet_inputs: vec![(outpoint(2), txout(80_000))],
+            change_script: ScriptBuf::from(vec![0x52]),
+            feerate_sat_vb: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_splice_in_negotiates_and_locks() {
+        let node = Arc::new(PeerNode::new());
+        let storage = Arc::new(MemoryBackend::new());
+        let manager = SpliceManager::open(SpliceConfig::default(), node.clone(), storage.clone())
+            .await
+            .unwrap();
+
+        let record = manager.splice(&channel(), splice_in()).await.unwrap();
+        // 100k + 50k from the wallet + 20k from the peer
+        assert_eq!(record.new_capacity_sat, 170_000);
+        let fee = 2 * (11 + 104 + 68 + 43 * 2);
+        assert_eq!(record.fee_sat, fee);
+        let tx = &record.tx;
+        let spent: Vec<_> = tx.input.iter().map(|i| i.previous_output).collect();
+        assert_eq!(spent, [outpoint(1), outpoint(9), outpoint(2)]);
+        let values: Vec<_> = tx.output.iter().map(|o| o.value).collect();
+        assert_eq!(values, [9_000, 170_000, 80_000 - 50_000 - fee]);
+        assert!(tx.input.iter().all(|i| !i.witness.is_empty()));
+        assert_eq!(node.sent.lock().unwrap().last(), Some(&TxMessage::Complete));
+
+        // One splice at a time per channel, and it survives a restart
+        assert_eq!(
+            manager
+                .splice(&channel(), splice_in())
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::Conflict
+        );
+        let manager = SpliceManager::open(SpliceConfig::default(), node, storage)
+            .await
+            .unwrap();
+        manager.mark_broadcast("chan").await.unwrap();
+        assert_eq!(
+            manager.apply(&channel()).await.unwrap().capacity_sat,
+            100_000
+        );
+        assert_eq!(
+            manager.confirm("chan", 1).await.unwrap().state,
+            SpliceState::Broadcast
+        );
+        assert_eq!(
+            manager.confirm("chan", 3).await.unwrap().state,
+            SpliceState::Locked
+        );
+        let resized = manager.apply(&channel()).await.unwrap();
+        assert_eq!(resized.capacity_sat, 170_000);
+        assert_eq!(resized.funding_txo, Some(OutPoint::new(record.txid(), 1)));
+        assert!(manager.abort("chan").await.is_err());
+    }
+
+    #[test]
+    fn test_interactive_tx_rules() {
+        let mut tx = InteractiveTx::new(true);
+        let add = |serial_id, n| TxMessage::AddInput {
+            serial_id,
+            prevout: outpoint(n),
+            prev_txout: txout(1_000),
+            sequence: 0,
+        };
+        tx.apply(&add(0, 1), true).unwrap();
+        // Parity must match the sender's role
+        assert!(tx.apply(&add(2, 2), false).is_err());
+        assert!(tx.apply(&add(1, 2), true).is_err());
+        // No spending the same outpoint twice or reusing serial ids
+        assert!(tx.apply(&add(1, 1), false).is_err());
+        tx.apply(&add(1, 2), false).unwrap();
+        assert!(tx.apply(&add(0, 3), true).is_err());
+        // Each side may only remove its own additions
+        assert!(tx
+            .apply(&TxMessage::RemoveInput { serial_id: 0 }, false)
+            .is_err());
+        tx.apply(&TxMessage::RemoveInput { serial_id: 1 }, false)
+            .unwrap();
+        assert_eq!(tx.to_transaction().input.len(), 1);
+        assert_eq!(tx.input_value(), 1_000);
+    }
+}
@@ -0,0 +1,953 @@
+//! Submarine swaps between on-chain funds and Lightning
+//!
+//! Both directions lock on-chain funds in a P2WSH HTLC that the claimer can
+//! spend with the payment preimage and the funder can take back after a
+//! timeout:
+//!
+//! ```text
+//! OP_SHA256 <payment_hash> OP_EQUAL
+//! OP_IF <claim_key>
+//! OP_ELSE <timeout> OP_CHECKLOCKTIMEVERIFY OP_DROP <refund_key>
+//! OP_ENDIF OP_CHECKSIG
+//! ```
+//!
+//! A swap-in (loop-in) pays the HTLC from the wallet and the provider pays
+//! our invoice, learning the preimage it needs to claim; if it never does,
+//! the funds are refunded after the timeout. A swap-out (loop-out) pays the
+//! provider's invoice for a preimage only we know; the provider locks funds
+//! in the HTLC and we claim them, revealing the preimage that settles our
+//! payment. The script is always rebuilt from the agreed keys, hash, and
+//! timeout rather than taken from the provider.
+//!
+//! [`SwapManager::poll`] drives each swap from the chain state, so it can be
+//! resumed after a restart; the on-chain legs are recorded in the
+//! [`TransactionHistory`] as transfers.
+
+use std::sync::Arc;
+
+use ::bitcoin::absolute::LockTime;
+use ::bitcoin::blockdata::opcodes::all::{
+    OP_CHECKSIG, OP_CLTV, OP_DROP, OP_ELSE, OP_ENDIF, OP_EQUAL, OP_IF, OP_SHA256,
+};
+use ::bitcoin::blockdata::script::Builder;
+use ::bitcoin::ecdsa;
+use ::bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use ::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use ::bitcoin::{OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::history::{TransactionHistory, TxCategory, TxDirection, WalletTx};
+use super::lightning::{InvoiceRequest, LightningNode};
+use super::liquidity::{SwapDirection, SwapService};
+use super::lnurl::Bolt11Fields;
+use super::sync::{SyncJob, SyncProgress, SyncTarget};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{from_hex, sha256, to_hex};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "mobile_swaps";
+/// Outputs below this are not swept
+const DUST_LIMIT_SAT: u64 = 546;
+/// Virtual size of a one-input, one-output HTLC sweep
+const SWEEP_VBYTES: u64 = 150;
+
+/// Swap settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapConfig {
+    /// Confirmations of the provider's lockup before claiming it
+    pub min_confirmations: u32,
+    /// Fee rate of claim and refund transactions
+    pub sweep_feerate_sat_vb: u64,
+    /// Fewest blocks until the timeout accepted in an offer
+    pub min_timeout_blocks: u32,
+    /// Most blocks our own funds may stay locked in a swap-in
+    pub max_timeout_blocks: u32,
+    /// Expiry of invoices created for swap-ins, in seconds
+    pub invoice_expiry_secs: u64,
+}
+
+impl Default for SwapConfig {
+    fn default() -> Self {
+        Self {
+            min_confirmations: 1,
+            sweep_feerate_sat_vb: 5,
+            min_timeout_blocks: 24,
+            max_timeout_blocks: 1_008,
+            invoice_expiry_secs: 86_400,
+        }
+    }
+}
+
+/// Provider terms for a swap-in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapInOffer {
+    /// Provider's swap id
+    pub id: String,
+    /// Key the provider claims the HTLC with
+    pub claim_key: PublicKey,
+    /// Height after which we may refund
+    pub timeout_height: u32,
+    /// Amount to lock on-chain, including the provider's fee
+    pub onchain_amount_sat: u64,
+}
+
+/// Provider terms for a swap-out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapOutOffer {
+    /// Provider's swap id
+    pub id: String,
+    /// Invoice to pay, locked to our payment hash
+    pub invoice: String,
+    /// Key the provider refunds the HTLC with
+    pub refund_key: PublicKey,
+    /// Height after which the provider may refund
+    pub timeout_height: u32,
+    /// Amount the provider locks on-chain
+    pub onchain_amount_sat: u64,
+}
+
+/// Submarine swap provider
+#[async_trait]
+pub trait SwapProvider: Send + Sync {
+    /// Agree to pay `invoice` once we lock funds refundable to `refund_key`
+    async fn create_swap_in(
+        &self,
+        invoice: &str,
+        refund_key: &PublicKey,
+    ) -> AnyaResult<SwapInOffer>;
+
+    /// Agree to lock `amount_sat` on-chain, claimable by `claim_key` with
+    /// the preimage of `payment_hash`, against payment of an invoice
+    async fn create_swap_out(
+        &self,
+        payment_hash: &str,
+        claim_key: &PublicKey,
+        amount_sat: u64,
+    ) -> AnyaResult<SwapOutOffer>;
+}
+
+/// Chain access for watching and settling HTLCs
+#[async_trait]
+pub trait SwapChain: Send + Sync {
+    /// Current chain height
+    async fn tip_height(&self) -> AnyaResult<u32>;
+
+    /// Output paying `script_pubkey` and its confirmations
+    async fn find_output(
+        &self,
+        script_pubkey: &Script,
+    ) -> AnyaResult<Option<(OutPoint, TxOut, u32)>>;
+
+    /// Transaction spending `outpoint`, if any
+    async fn find_spend(&self, outpoint: &OutPoint) -> AnyaResult<Option<Transaction>>;
+
+    /// Broadcast a transaction
+    async fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid>;
+}
+
+/// On-chain wallet funding swap-ins and receiving sweeps
+#[async_trait]
+pub trait SwapWallet: Send + Sync {
+    /// Pay `amount_sat` to `script_pubkey`, returning the new output
+    async fn fund(&self, script_pubkey: &Script, amount_sat: u64) -> AnyaResult<OutPoint>;
+
+    /// Fresh script to sweep funds to
+    async fn receive_script(&self) -> AnyaResult<ScriptBuf>;
+}
+
+/// Progress of a swap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapStatus {
+    /// Agreed; for a swap-out, our payment is in flight
+    Created,
+    /// Funds are locked in the HTLC
+    LockedUp,
+    /// The HTLC was claimed with the preimage
+    Claimed,
+    /// Our swap-in funds were refunded after the timeout
+    Refunded,
+    /// The provider never locked funds before the timeout
+    Expired,
+}
+
+impl SwapStatus {
+    /// Whether the swap needs no further action
+    pub const fn is_final(self) -> bool {
+        matches!(self, Self::Claimed | Self::Refunded | Self::Expired)
+    }
+}
+
+/// Persisted swap state, including the keys needed to settle it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapRecord {
+    /// Provider's swap id
+    pub id: String,
+    /// Direction
+    pub direction: SwapDirection,
+    /// Current status
+    pub status: SwapStatus,
+    /// Amount moved over Lightning
+    pub amount_sat: u64,
+    /// Amount locked on-chain
+    pub onchain_amount_sat: u64,
+    /// Invoice paid by the provider (in) or by us (out)
+    pub invoice: String,
+    /// Hex payment hash
+    pub payment_hash: String,
+    /// Hex preimage, known up front for swap-outs only
+    pub preimage: Option<String>,
+    /// Hex secret key for our side of the HTLC
+    pub local_key: String,
+    /// Provider's HTLC key
+    pub remote_key: PublicKey,
+    /// HTLC timeout height
+    pub timeout_height: u32,
+    /// HTLC witness script
+    pub redeem_script: ScriptBuf,
+    /// HTLC output, once known
+    pub lockup: Option<OutPoint>,
+    /// Our claim or refund transaction
+    pub sweep_txid: Option<Txid>,
+    /// Channel the swap is meant to rebalance, if any
+    pub channel_id: Option<String>,
+    /// Creation time, seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+impl SwapRecord {
+    /// Script of the HTLC output
+    pub fn lockup_script(&self) -> ScriptBuf {
+        self.redeem_script.to_v0_p2wsh()
+    }
+}
+
+/// HTLC witness script for a swap
+pub fn htlc_script(
+    payment_hash: &[u8; 32],
+    claim_key: &PublicKey,
+    refund_key: &PublicKey,
+    timeout_height: u32,
+) -> AnyaResult<ScriptBuf> {
+    let timeout = LockTime::from_height(timeout_height)
+        .map_err(|_| AnyaError::invalid_input("swap timeout must be a block height"))?;
+    Ok(Builder::new()
+        .push_opcode(OP_SHA256)
+        .push_slice(payment_hash)
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_IF)
+        .push_slice(claim_key.serialize())
+        .push_opcode(OP_ELSE)
+        .push_lock_time(timeout)
+        .push_opcode(OP_CLTV)
+        .push_opcode(OP_DROP)
+        .push_slice(refund_key.serialize())
+        .push_opcode(OP_ENDIF)
+        .push_opcode(OP_CHECKSIG)
+        .into_script())
+}
+
+/// Runs submarine swaps against a provider and tracks them to completion
+pub struct SwapManager {
+    config: SwapConfig,
+    provider: Arc<dyn SwapProvider>,
+    node: Arc<dyn LightningNode>,
+    chain: Arc<dyn SwapChain>,
+    wallet: Arc<dyn SwapWallet>,
+    history: Arc<TransactionHistory>,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    /// Serializes record updates
+    writes: Mutex<()>,
+}
+
+impl SwapManager {
+    /// Open the swap store in `storage`
+    pub async fn open(
+        config: SwapConfig,
+        provider: Arc<dyn SwapProvider>,
+        node: Arc<dyn LightningNode>,
+        chain: Arc<dyn SwapChain>,
+        wallet: Arc<dyn SwapWallet>,
+        history: Arc<TransactionHistory>,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            config,
+            provider,
+            node,
+            chain,
+            wallet,
+            history,
+            storage,
+            ns,
+            writes: Mutex::new(()),
+        })
+    }
+
+    /// Move `amount_sat` of on-chain funds into our Lightning channels,
+    /// paying the provider at most `max_fee_sat`
+    pub async fn swap_in(&self, amount_sat: u64, max_fee_sat: u64) -> AnyaResult<SwapRecord> {
+        let invoice = self
+            .node
+            .create_invoice(InvoiceRequest {
+                amount_msat: Some(amount_sat * 1_000),
+                description: "Submarine swap-in".into(),
+                expiry: std::time::Duration::from_secs(self.config.invoice_expiry_secs),
+                route_hint: None,
+            })
+            .await?;
+        let payment_hash = Bolt11Fields::parse(&invoice)?
+            .payment_hash
+            .ok_or_else(|| AnyaError::new(ErrorCode::Internal, "invoice has no payment hash"))?;
+        let local_key = new_key()?;
+        let refund_key = local_key.public_key(&Secp256k1::new());
+        let offer = self.provider.create_swap_in(&invoice, &refund_key).await?;
+        let fee = offer.onchain_amount_sat.saturating_sub(amount_sat);
+        if offer.onchain_amount_sat < amount_sat || fee > max_fee_sat {
+            return Err(fee_too_high(fee));
+        }
+        let tip = self.chain.tip_height().await?;
+        if offer.timeout_height > tip + self.config.max_timeout_blocks {
+            return Err(AnyaError::invalid_input(
+                "swap-in would lock funds for too long",
+            ));
+        }
+        self.check_timeout(offer.timeout_height, tip)?;
+        let redeem_script = htlc_script(
+            &payment_hash,
+            &offer.claim_key,
+            &refund_key,
+            offer.timeout_height,
+        )?;
+        let mut record = SwapRecord {
+            id: offer.id,
+            direction: SwapDirection::In,
+            status: SwapStatus::Created,
+            amount_sat,
+            onchain_amount_sat: offer.onchain_amount_sat,
+            invoice,
+            payment_hash: to_hex(&payment_hash),
+            preimage: None,
+            local_key: to_hex(&local_key.secret_bytes()),
+            remote_key: offer.claim_key,
+            timeout_height: offer.timeout_height,
+            redeem_script,
+            lockup: None,
+            sweep_txid: None,
+            channel_id: None,
+            created_at: unix_now(),
+        };
+        self.save(&record).await?;
+
+        let lockup = self
+            .wallet
+            .fund(&record.lockup_script(), record.onchain_amount_sat)
+            .await?;
+        record.lockup = Some(lockup);
+        record.status = SwapStatus::LockedUp;
+        self.save(&record).await?;
+        self.account(
+            lockup.txid,
+            -i64::try_from(record.onchain_amount_sat).unwrap_or(i64::MAX),
+            None,
+            format!("Swap in {}", record.id),
+        )
+        .await?;
+        tracing::info!(swap = %record.id, amount = amount_sat, "swap-in locked up");
+        Ok(record)
+    }
+
+    /// Move `amount_sat` out of our Lightning channels to the wallet,
+    /// paying the provider at most `max_fee_sat`.
+    ///
+    /// The payment stays in flight until [`SwapManager::poll`] claims the
+    /// provider's lockup.
+    pub async fn swap_out(&self, amount_sat: u64, max_fee_sat: u64) -> AnyaResult<SwapRecord> {
+        let preimage: [u8; 32] = rand::random();
+        let payment_hash = sha256(&preimage);
+        let local_key = new_key()?;
+        let claim_key = local_key.public_key(&Secp256k1::new());
+        let offer = self
+            .provider
+            .create_swap_out(&to_hex(&payment_hash), &claim_key, amount_sat)
+            .await?;
+        let fields = Bolt11Fields::parse(&offer.invoice)?;
+        if fields.payment_hash != Some(payment_hash) {
+            return Err(AnyaError::invalid_input(
+                "swap invoice is not locked to our payment hash",
+            ));
+        }
+        let invoice_sat = fields
+            .amount_msat
+            .ok_or_else(|| AnyaError::invalid_input("swap invoice has no amount"))?
+            / 1_000;
+        let fee = invoice_sat.saturating_sub(offer.onchain_amount_sat);
+        if offer.onchain_amount_sat > invoice_sat
+            || offer.onchain_amount_sat <= DUST_LIMIT_SAT
+            || fee > max_fee_sat
+        {
+            return Err(fee_too_high(fee));
+        }
+        self.check_timeout(offer.timeout_height, self.chain.tip_height().await?)?;
+        let record = SwapRecord {
+            redeem_script: htlc_script(
+                &payment_hash,
+                &claim_key,
+                &offer.refund_key,
+                offer.timeout_height,
+            )?,
+            id: offer.id,
+            direction: SwapDirection::Out,
+            status: SwapStatus::Created,
+            amount_sat: invoice_sat,
+            onchain_amount_sat: offer.onchain_amount_sat,
+            invoice: offer.invoice,
+            payment_hash: to_hex(&payment_hash),
+            preimage: Some(to_hex(&preimage)),
+            local_key: to_hex(&local_key.secret_bytes()),
+            remote_key: offer.refund_key,
+            timeout_height: offer.timeout_height,
+            lockup: None,
+            sweep_txid: None,
+            channel_id: None,
+            created_at: unix_now(),
+        };
+        self.save(&record).await?;
+
+        // Settles only once we claim on-chain, so it must not be awaited here
+        let routing_budget_msat = (max_fee_sat - fee) * 1_000;
+        let node = Arc::clone(&self.node);
+        let (id, invoice) = (record.id.clone(), record.invoice.clone());
+        tokio::spawn(async move {
+            match node.pay_invoice(&invoice, routing_budget_msat).await {
+                Ok(result) => tracing::info!(swap = %id, fee = result.fee_msat, "swap-out paid"),
+                Err(e) => tracing::warn!(swap = %id, error = %e, "swap-out payment failed"),
+            }
+        });
+        Ok(record)
+    }
+
+    /// Fetch a swap
+    pub async fn get(&self, id: &str) -> AnyaResult<Option<SwapRecord>> {
+        self.storage
+            .get(&self.ns, id)
+            .await?
+            .map(|v| serde_json::from_slice(&v))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Swaps that still need action
+    pub async fn pending(&self) -> AnyaResult<Vec<SwapRecord>> {
+        let mut pending = Vec::new();
+        for (_, value) in self.storage.scan_prefix(&self.ns, "").await? {
+            let record: SwapRecord = serde_json::from_slice(&value)?;
+            if !record.status.is_final() {
+                pending.push(record);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Advance a swap from the chain state: claim a confirmed swap-out
+    /// lockup, notice a swap-in claim, or refund an expired swap-in
+    pub async fn poll(&self, id: &str) -> AnyaResult<SwapRecord> {
+        let guard = self.writes.lock().await;
+        let mut record = self
+            .get(id)
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("swap {}", id)))?;
+        if record.status.is_final() {
+            return Ok(record);
+        }
+        let tip = self.chain.tip_height().await?;
+        match record.direction {
+            SwapDirection::Out => self.poll_out(&mut record, tip).await?,
+            SwapDirection::In => self.poll_in(&mut record, tip).await?,
+        }
+        self.put(&record).await?;
+        drop(guard);
+        Ok(record)
+    }
+
+    /// Poll every pending swap, returning those that changed status
+    pub async fn poll_all(&self) -> AnyaResult<Vec<SwapRecord>> {
+        let mut changed = Vec::new();
+        for record in self.pending().await? {
+            match self.poll(&record.id).await {
+                Ok(updated) if updated.status != record.status => changed.push(updated),
+                Ok(_) => {}
+                Err(e) => tracing::warn!(swap = %record.id, error = %e, "swap poll failed"),
+            }
+        }
+        Ok(changed)
+    }
+
+    async fn poll_out(&self, record: &mut SwapRecord, tip: u32) -> AnyaResult<()> {
+        let found = self.chain.find_output(&record.lockup_script()).await?;
+        let Some((outpoint, txout, confirmations)) = found else {
+            if tip >= record.timeout_height {
+                record.status = SwapStatus::Expired;
+            }
+            return Ok(());
+        };
+        if txout.value < record.onchain_amount_sat {
+            return Err(AnyaError::invalid_input(format!(
+                "swap {} lockup pays {} sat instead of {}",
+                record.id, txout.value, record.onchain_amount_sat
+            )));
+        }
+        record.lockup = Some(outpoint);
+        record.status = SwapStatus::LockedUp;
+        if confirmations < self.config.min_confirmations {
+            return Ok(());
+        }
+        let preimage = record
+            .preimage
+            .as_deref()
+            .map(from_hex)
+            .transpose()?
+            .ok_or_else(|| AnyaError::new(ErrorCode::Internal, "swap-out lost its preimage"))?;
+        let sweep = self
+            .sweep(record, outpoint, &txout, Some(&preimage))
+            .await?;
+        let txid = self.chain.broadcast(&sweep).await?;
+        record.sweep_txid = Some(txid);
+        record.status = SwapStatus::Claimed;
+        self.account_sweep(&sweep, &txout, format!("Swap out {}", record.id))
+            .await
+    }
+
+    async fn poll_in(&self, record: &mut SwapRecord, tip: u32) -> AnyaResult<()> {
+        let lockup = record
+            .lockup
+            .ok_or_else(|| AnyaError::new(ErrorCode::Internal, "swap-in was never funded"))?;
+        let hash = from_hex(&record.payment_hash)?;
+        if let Some(spend) = self.chain.find_spend(&lockup).await? {
+            let revealed = spend
+                .input
+                .iter()
+                .filter(|i| i.previous_output == lockup)
+                .filter_map(|i| i.witness.nth(1))
+                .any(|preimage| preimage.len() == 32 && sha256(preimage)[..] == hash[..]);
+            record.sweep_txid = Some(spend.txid());
+            record.status = if revealed {
+                SwapStatus::Claimed
+            } else {
+                SwapStatus::Refunded
+            };
+            return Ok(());
+        }
+        if tip < record.timeout_height {
+            return Ok(());
+        }
+        let (_, txout, _) = self
+            .chain
+            .find_output(&record.lockup_script())
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("swap {} lockup", record.id)))?;
+        let refund = self.sweep(record, lockup, &txout, None).await?;
+        record.sweep_txid = Some(self.chain.broadcast(&refund).await?);
+        record.status = SwapStatus::Refunded;
+        tracing::warn!(swap = %record.id, "swap-in expired; refunded");
+        self.account_sweep(&refund, &txout, format!("Swap in {} refund", record.id))
+            .await
+    }
+
+    /// Spend the HTLC to the wallet, with the preimage to claim or without
+    /// it to refund after the timeout
+    async fn sweep(
+        &self,
+        record: &SwapRecord,
+        lockup: OutPoint,
+        txout: &TxOut,
+        preimage: Option<&[u8]>,
+    ) -> AnyaResult<Transaction> {
+        let fee = self.config.sweep_feerate_sat_vb * SWEEP_VBYTES;
+        let value = txout
+            .value
+            .checked_sub(fee)
+            .filter(|v| *v > DUST_LIMIT_SAT)
+            .ok_or_else(|| {
+                AnyaError::new(ErrorCode::InsufficientFunds, "HTLC too small to sweep")
+            })?;
+        let lock_time = match preimage {
+            Some(_) => LockTime::ZERO,
+            None => LockTime::from_height(record.timeout_height)
+                .map_err(|_| AnyaError::invalid_input("swap timeout must be a block height"))?,
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time,
+            input: vec![TxIn {
+                previous_output: lockup,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: self.wallet.receive_script().await?,
+            }],
+        };
+        let sighash = SighashCache::new(&tx)
+            .segwit_signature_hash(0, &record.redeem_script, txout.value, EcdsaSighashType::All)
+            .map_err(|e| AnyaError::new(ErrorCode::Internal, format!("sighash: {}", e)))?;
+        let key = SecretKey::from_slice(&from_hex(&record.local_key)?)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "invalid swap key"))?;
+        let signature = ecdsa::Signature {
+            sig: Secp256k1::new().sign_ecdsa(&Message::from(sighash), &key),
+            hash_ty: EcdsaSighashType::All,
+        };
+        let witness = &mut tx.input[0].witness;
+        witness.push(signature.to_vec());
+        witness.push(preimage.unwrap_or_default());
+        witness.push(record.redeem_script.as_bytes());
+        Ok(tx)
+    }
+
+    fn check_timeout(&self, timeout_height: u32, tip: u32) -> AnyaResult<()> {
+        if timeout_height < tip + self.config.min_timeout_blocks {
+            return Err(AnyaError::invalid_input("swap timeout is too close"));
+        }
+        Ok(())
+    }
+
+    async fn account_sweep(
+        &self,
+        sweep: &Transaction,
+        spent: &TxOut,
+        label: String,
+    ) -> AnyaResult<()> {
+        let received: u64 = sweep.output.iter().map(|o| o.value).sum();
+        self.account(
+            sweep.txid(),
+            i64::try_from(received).unwrap_or(i64::MAX),
+            Some(spent.value - received),
+            label,
+        )
+        .await
+    }
+
+    async fn account(
+        &self,
+        txid: Txid,
+        amount_sat: i64,
+        fee_sat: Option<u64>,
+        label: String,
+    ) -> AnyaResult<()> {
+        self.history
+            .record(WalletTx {
+                txid,
+                direction: if amount_sat < 0 {
+                    TxDirection::Outgoing
+                } else {
+                    TxDirection::Incoming
+                },
+                amount_sat,
+                fee_sat,
+                block_height: None,
+                timestamp: unix_now(),
+                category: Some(TxCategory::Transfer),
+                label: Some(label),
+                fiat: None,
+            })
+            .await
+    }
+
+    async fn save(&self, record: &SwapRecord) -> AnyaResult<()> {
+        let _guard = self.writes.lock().await;
+        self.put(record).await
+    }
+
+    async fn put(&self, record: &SwapRecord) -> AnyaResult<()> {
+        self.storage
+            .put(&self.ns, &record.id, &serde_json::to_vec(record)?)
+            .await
+    }
+}
+
+#[async_trait]
+impl SwapService for SwapManager {
+    async fn swap(
+        &self,
+        direction: SwapDirection,
+        channel_id: &str,
+        amount_sat: u64,
+        max_fee_sat: u64,
+    ) -> AnyaResult<String> {
+        let mut record = match direction {
+            SwapDirection::In => self.swap_in(amount_sat, max_fee_sat).await?,
+            SwapDirection::Out => self.swap_out(amount_sat, max_fee_sat).await?,
+        };
+        record.channel_id = Some(channel_id.to_string());
+        self.save(&record).await?;
+        Ok(record.id)
+    }
+}
+
+#[async_trait]
+impl SyncJob for SwapManager {
+    fn target(&self) -> SyncTarget {
+        SyncTarget::Lightning
+    }
+
+    async fn run(&self, progress: &SyncProgress) -> AnyaResult<()> {
+        let pending = self.pending().await?.len() as u64;
+        let changed = self.poll_all().await?;
+        progress.report(changed.len() as u64, pending);
+        Ok(())
+    }
+}
+
+fn new_key() -> AnyaResult<SecretKey> {
+    SecretKey::from_slice(&rand::random::<[u8; 32]>())
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "failed to generate a swap key"))
+}
+
+fn fee_too_high(fee: u64) -> AnyaError {
+    AnyaError::new(
+        ErrorCode::PermissionDenied,
+        format!("swap fee of {} sat exceeds the limit", fee),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobile::lightning::{ChannelInfo, PaymentResult};
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::bech32::{self, u5, ToBase32, Variant};
+    use ::bitcoin::hashes::Hash;
+    use std::sync::Mutex as StdMutex;
+
+    /// Structurally valid invoice carrying only an amount and payment hash
+    fn invoice(amount_sat: u64, payment_hash: &[u8; 32]) -> String {
+        let word = |v: u8| u5::try_from_u8(v).unwrap();
+        let mut data = vec![word(0); 7];
+        data.extend([word(1), word(1), word(20)]);
+        data.extend(payment_hash.to_base32());
+        data.extend(vec![word(0); 104]);
+        let hrp = format!("lnbcrt{}n", amount_sat * 10);
+        bech32::encode(&hrp, data, Variant::Bech32).unwrap()
+    }
+
+    fn provider_key() -> SecretKey {
+        SecretKey::from_slice(&[3; 32]).unwrap()
+    }
+
+    #[derive(Default)]
+    struct World {
+        tip: StdMutex<u32>,
+        outputs: StdMutex<Vec<(OutPoint, TxOut, u32)>>,
+        spends: StdMutex<Vec<Transaction>>,
+        paid: StdMutex<Vec<String>>,
+        swap_out_hash: StdMutex<Option<String>>,
+    }
+
+    impl World {
+        fn lock(&self, script: ScriptBuf, value: u64, confirmations: u32) -> OutPoint {
+            let mut outputs = self.outputs.lock().unwrap();
+            let outpoint = OutPoint::new(Txid::from_byte_array([outputs.len() as u8 + 1; 32]), 0);
+            let txout = TxOut {
+                value,
+                script_pubkey: script,
+            };
+            outputs.push((outpoint, txout, confirmations));
+            outpoint
+        }
+    }
+
+    #[async_trait]
+    impl SwapProvider for World {
+        async fn create_swap_in(&self, _: &str, _: &PublicKey) -> AnyaResult<SwapInOffer> {
+            Ok(SwapInOffer {
+                id: "in-1".into(),
+                claim_key: provider_key().public_key(&Secp256k1::new()),
+                timeout_height: 200,
+                onchain_amount_sat: 101_000,
+            })
+        }
+
+        async fn create_swap_out(
+            &self,
+            payment_hash: &str,
+            _: &PublicKey,
+            amount_sat: u64,
+        ) -> AnyaResult<SwapOutOffer> {
+            *self.swap_out_hash.lock().unwrap() = Some(payment_hash.to_string());
+            let hash: [u8; 32] = from_hex(payment_hash).unwrap().try_into().unwrap();
+            Ok(SwapOutOffer {
+                id: "out-1".into(),
+                invoice: invoice(amount_sat + 1_500, &hash),
+                refund_key: provider_key().public_key(&Secp256k1::new()),
+                timeout_height: 150,
+                onchain_amount_sat: amount_sat,
+            })
+        }
+    }
+
+    #[async_trait]
+    impl SwapChain for World {
+        async fn tip_height(&self) -> AnyaResult<u32> {
+            Ok(*self.tip.lock().unwrap())
+        }
+
+        async fn find_output(&self, script: &Script) -> AnyaResult<Option<(OutPoint, TxOut, u32)>> {
+            let outputs = self.outputs.lock().unwrap();
+            Ok(outputs
+                .iter()
+                .find(|(_, o, _)| o.script_pubkey == *script)
+                .cloned())
+        }
+
+        async fn find_spend(&self, outpoint: &OutPoint) -> AnyaResult<Option<Transaction>> {
+            let spends = self.spends.lock().unwrap();
+            Ok(spends
+                .iter()
+                .find(|tx| tx.input.iter().any(|i| i.previous_output == *outpoint))
+                .cloned())
+        }
+
+        async fn broadcast(&self, tx: &Transaction) -> AnyaResult<Txid> {
+            self.spends.lock().unwrap().push(tx.clone());
+            Ok(tx.txid())
+        }
+    }
+
+    #[async_trait]
+    impl SwapWallet for World {
+        async fn fund(&self, script: &Script, amount_sat: u64) -> AnyaResult<OutPoint> {
+            Ok(self.lock(script.to_owned(), amount_sat, 0))
+        }
+
+        async fn receive_script(&self) -> AnyaResult<ScriptBuf> {
+            Ok(ScriptBuf::from(vec![0x51]))
+        }
+    }
+
+    #[async_trait]
+    impl LightningNode for World {
+        async fn channels(&self) -> AnyaResult<Vec<ChannelInfo>> {
+            Ok(Vec::new())
+        }
+
+        async fn create_invoice(&self, request: InvoiceRequest) -> AnyaResult<String> {
+            Ok(invoice(request.amount_msat.unwrap() / 1_000, &[4; 32]))
+        }
+
+        async fn pay_invoice(&self, invoice: &str, _: u64) -> AnyaResult<PaymentResult> {
+            self.paid.lock().unwrap().push(invoice.to_string());
+            Ok(PaymentResult {
+                payment_hash: String::new(),
+                preimage: String::new(),
+                fee_msat: 0,
+            })
+        }
+    }
+
+    async fn setup() -> (Arc<World>, SwapManager, Arc<TransactionHistory>) {
+        let world = Arc::new(World::default());
+        *world.tip.lock().unwrap() = 100;
+        let storage = Arc::new(MemoryBackend::new());
+        let history = Arc::new(
+            TransactionHistory::open(storage.clone(), None, "USD")
+                .await
+                .unwrap(),
+        );
+        let manager = SwapManager::open(
+            SwapConfig::default(),
+            world.clone(),
+            world.clone(),
+            world.clone(),
+            world.clone(),
+            history.clone(),
+            storage,
+        )
+        .await
+        .unwrap();
+        (world, manager, history)
+    }
+
+    #[tokio::test]
+    async fn test_swap_out_claims_lockup_with_preimage() {
+        let (world, manager, history) = setup().await;
+        assert_eq!(
+            manager.swap_out(50_000, 1_000).await.unwrap_err().code(),
+            ErrorCode::PermissionDenied
+        );
+        let record = manager.swap_out(50_000, 2_000).await.unwrap();
+        tokio::task::yield_now().await;
+        assert_eq!(
+            *world.paid.lock().unwrap(),
+            std::slice::from_ref(&record.invoice)
+        );
+
+        // Nothing to claim until the provider's lockup confirms
+        world.lock(record.lockup_script(), 50_000, 0);
+        assert_eq!(
+            manager.poll("out-1").await.unwrap().status,
+            SwapStatus::LockedUp
+        );
+        world.outputs.lock().unwrap()[0].2 = 1;
+        let claimed = manager.poll("out-1").await.unwrap();
+        assert_eq!(claimed.status, SwapStatus::Claimed);
+
+        let sweep = world.spends.lock().unwrap()[0].clone();
+        assert_eq!(sweep.lock_time, LockTime::ZERO);
+        let witness: Vec<_> = sweep.input[0].witness.iter().collect();
+        let hash = world.swap_out_hash.lock().unwrap().clone().unwrap();
+        assert_eq!(to_hex(&sha256(witness[1])), hash);
+        assert_eq!(witness[2], record.redeem_script.as_bytes());
+        // The signature is valid for our claim key over the sweep
+        let sighash = SighashCache::new(&sweep)
+            .segwit_signature_hash(0, &record.redeem_script, 50_000, EcdsaSighashType::All)
+            .unwrap();
+        let signature = ecdsa::Signature::from_slice(witness[0]).unwrap();
+        let key = SecretKey::from_slice(&from_hex(&record.local_key).unwrap()).unwrap();
+        let secp = Secp256k1::new();
+        secp.verify_ecdsa(
+            &Message::from(sighash),
+            &signature.sig,
+            &key.public_key(&secp),
+        )
+        .unwrap();
+
+        let entry = history.get(&sweep.txid()).await.unwrap().unwrap();
+        assert_eq!(entry.amount_sat, 50_000 - 750);
+        assert_eq!(entry.category, Some(TxCategory::Transfer));
+        assert!(manager.pending().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_swap_in_refunds_after_timeout() {
+        let (world, manager, history) = setup().await;
+        let record = manager.swap_in(100_000, 1_000).await.unwrap();
+        assert_eq!(record.status, SwapStatus::LockedUp);
+        assert_eq!(record.payment_hash, to_hex(&[4; 32]));
+        let lockup = record.lockup.unwrap();
+        assert_eq!(
+            history.get(&lockup.txid).await.unwrap().unwrap().amount_sat,
+            -101_000
+        );
+
+        // Before the timeout nothing happens; after it, we take the funds back
+        assert_eq!(
+            manager.poll("in-1").await.unwrap().status,
+            SwapStatus::LockedUp
+        );
+        *world.tip.lock().unwrap() = 200;
+        let refunded = manager.poll("in-1").await.unwrap();
+        assert_eq!(refunded.status, SwapStatus::Refunded);
+        let refund = world.spends.lock().unwrap()[0].clone();
+        assert_eq!(refund.lock_time, LockTime::from_height(200).unwrap());
+        assert!(refund.input[0].witness.nth(1).unwrap().is_empty());
+        assert_eq!(refunded.sweep_txid, Some(refund.txid()));
+        assert!(manager.poll_all().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,477 @@
+//! Background sync scheduling for the mobile wallet
+//!
+//! Mobile platforms hand out short and infrequent background windows, so SPV
+//! and DWN syncs are batched and run together whenever the [`SyncPolicy`]
+//! allows it. The host app reports [`PlatformHints`] (network type, charging,
+//! foreground) through the FFI bridge, the [`SyncScheduler`] turns them into a
+//! [`SyncDecision`], and progress is published as [`SyncEvent`]s that the
+//! bridge forwards to the UI.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+
+use crate::lifecycle::run_loop;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Data set synchronized by a [`SyncJob`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncTarget {
+    /// Block headers and compact filters for the SPV wallet
+    Spv,
+    /// Decentralized Web Node records
+    Dwn,
+    /// Lightning channel state and watchtower registrations
+    Lightning,
+}
+
+/// Device state reported by the host app
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlatformHints {
+    /// Whether any network connection is available
+    pub connected: bool,
+    /// Whether the connection is unmetered Wi-Fi
+    pub on_wifi: bool,
+    /// Whether the device is charging
+    pub charging: bool,
+    /// Whether the app is in the foreground
+    pub foreground: bool,
+    /// Battery level in percent, if the platform reports it
+    pub battery_percent: Option<u8>,
+}
+
+impl Default for PlatformHints {
+    fn default() -> Self {
+        Self {
+            connected: true,
+            on_wifi: false,
+            charging: false,
+            foreground: true,
+            battery_percent: None,
+        }
+    }
+}
+
+/// Rules deciding when a sync batch may run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncPolicy {
+    /// Minimum time between batches while the app is in the foreground
+    pub foreground_interval: Duration,
+    /// Minimum time between batches while the app is in the background
+    pub background_interval: Duration,
+    /// Only sync in the background when on Wi-Fi
+    pub background_requires_wifi: bool,
+    /// Only sync in the background when charging
+    pub background_requires_charging: bool,
+    /// Skip background syncs below this battery level unless charging
+    pub min_battery_percent: u8,
+    /// Only sync DWN records over Wi-Fi; SPV still runs on cellular
+    pub dwn_requires_wifi: bool,
+    /// How often the scheduler re-evaluates the policy
+    pub poll_interval: Duration,
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self {
+            foreground_interval: Duration::from_secs(60),
+            background_interval: Duration::from_secs(15 * 60),
+            background_requires_wifi: true,
+            background_requires_charging: false,
+            min_battery_percent: 20,
+            dwn_requires_wifi: false,
+            poll_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Why a batch was not started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeferReason {
+    /// No network connection
+    Offline,
+    /// The last batch ran too recently
+    NotDue,
+    /// Battery is below [`SyncPolicy::min_battery_percent`]
+    BatteryLow,
+    /// Background sync needs Wi-Fi
+    NeedsWifi,
+    /// Background sync needs the device to be charging
+    NeedsCharging,
+}
+
+/// Outcome of evaluating the policy against the current hints
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncDecision {
+    /// Run a batch covering these targets
+    Run(Vec<SyncTarget>),
+    /// Do nothing for now
+    Defer(DeferReason),
+}
+
+impl SyncPolicy {
+    /// Decide whether a batch should run, given how long ago the last one ran
+    pub fn decide(
+        &self,
+        hints: &PlatformHints,
+        since_last: Option<Duration>,
+        available: &[SyncTarget],
+    ) -> SyncDecision {
+        if !hints.connected {
+            return SyncDecision::Defer(DeferReason::Offline);
+        }
+        let interval = if hints.foreground {
+            self.foreground_interval
+        } else {
+            self.background_interval
+        };
+        if since_last.is_some_and(|elapsed| elapsed < interval) {
+            return SyncDecision::Defer(DeferReason::NotDue);
+        }
+        if !hints.foreground {
+            if self.background_requires_wifi && !hints.on_wifi {
+                return SyncDecision::Defer(DeferReason::NeedsWifi);
+            }
+            if self.background_requires_charging && !hints.charging {
+                return SyncDecision::Defer(DeferReason::NeedsCharging);
+            }
+            if !hints.charging
+                && hints
+                    .battery_percent
+                    .is_some_and(|level| level < self.min_battery_percent)
+            {
+                return SyncDecision::Defer(DeferReason::BatteryLow);
+            }
+        }
+        SyncDecision::Run(self.targets_for(hints, available))
+    }
+
+    fn targets_for(&self, hints: &PlatformHints, available: &[SyncTarget]) -> Vec<SyncTarget> {
+        available
+            .iter()
+            .copied()
+            .filter(|t| *t != SyncTarget::Dwn || hints.on_wifi || !self.dwn_requires_wifi)
+            .collect()
+    }
+}
+
+/// Progress notification published while syncing
+///
+/// Serialized as tagged JSON when passed across the FFI bridge.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SyncEvent {
+    /// A batch started
+    BatchStarted {
+        /// Targets included in the batch
+        targets: Vec<SyncTarget>,
+        /// Whether the batch was requested by the user
+        manual: bool,
+    },
+    /// Incremental progress for one target
+    Progress {
+        /// Target being synced
+        target: SyncTarget,
+        /// Units completed so far (headers, records)
+        completed: u64,
+        /// Total units expected
+        total: u64,
+    },
+    /// A target finished syncing
+    TargetFinished {
+        /// Target that finished
+        target: SyncTarget,
+    },
+    /// A target failed; the rest of the batch still runs
+    TargetFailed {
+        /// Target that failed
+        target: SyncTarget,
+        /// Error description
+        message: String,
+    },
+    /// A batch finished
+    BatchFinished {
+        /// Whether every target succeeded
+        success: bool,
+    },
+    /// A scheduled batch was skipped
+    Deferred {
+        /// Why the batch was skipped
+        reason: DeferReason,
+    },
+}
+
+/// Handle a [`SyncJob`] uses to report progress
+pub struct SyncProgress {
+    target: SyncTarget,
+    events: broadcast::Sender<SyncEvent>,
+}
+
+impl SyncProgress {
+    /// Report that `completed` of `total` units are done
+    pub fn report(&self, completed: u64, total: u64) {
+        // Nobody listening is fine; progress is advisory
+        let _ = self.events.send(SyncEvent::Progress {
+            target: self.target,
+            completed,
+            total,
+        });
+    }
+}
+
+/// A single sync operation run as part of a batch
+#[async_trait]
+pub trait SyncJob: Send + Sync {
+    /// Data set this job synchronizes
+    fn target(&self) -> SyncTarget;
+
+    /// Run the sync, reporting progress along the way
+    async fn run(&self, progress: &SyncProgress) -> AnyaResult<()>;
+}
+
+/// Batches registered [`SyncJob`]s according to a [`SyncPolicy`]
+pub struct SyncScheduler {
+    policy: SyncPolicy,
+    jobs: Vec<Arc<dyn SyncJob>>,
+    hints: RwLock<PlatformHints>,
+    last_batch: Mutex<Option<Instant>>,
+    running: Mutex<()>,
+    events: broadcast::Sender<SyncEvent>,
+}
+
+impl SyncScheduler {
+    /// Create a scheduler with no jobs
+    pub fn new(policy: SyncPolicy) -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            policy,
+            jobs: Vec::new(),
+            hints: RwLock::new(PlatformHints::default()),
+            last_batch: Mutex::new(None),
+            running: Mutex::new(()),
+            events,
+        }
+    }
+
+    /// Add a job; jobs run in [`SyncTarget`] order within a batch
+    pub fn register(&mut self, job: Arc<dyn SyncJob>) {
+        self.jobs.push(job);
+        self.jobs.sort_by_key(|j| j.target());
+    }
+
+    /// Replace the current platform hints
+    pub async fn set_hints(&self, hints: PlatformHints) {
+        *self.hints.write().await = hints;
+    }
+
+    /// Current platform hints
+    pub async fn hints(&self) -> PlatformHints {
+        *self.hints.read().await
+    }
+
+    /// Subscribe to sync events
+    pub fn subscribe(&self) -> broadcast::Receiver<SyncEvent> {
+        self.events.subscribe()
+    }
+
+    /// Evaluate the policy against the current hints
+    pub async fn evaluate(&self) -> SyncDecision {
+        let hints = self.hints().await;
+        let since_last = self.last_batch.lock().await.map(|at| at.elapsed());
+        let mut available: Vec<SyncTarget> = self.jobs.iter().map(|j| j.target()).collect();
+        available.dedup();
+        self.policy.decide(&hints, since_last, &available)
+    }
+
+    /// Run a batch if the policy allows it, returning whether one ran
+    pub async fn tick(&self) -> AnyaResult<bool> {
+        match self.evaluate().await {
+            SyncDecision::Run(targets) if !targets.is_empty() => {
+                let Ok(_guard) = self.running.try_lock() else {
+                    return Ok(false);
+                };
+                self.run_batch(&targets, false).await;
+                Ok(true)
+            }
+            SyncDecision::Run(_) => Ok(false),
+            SyncDecision::Defer(reason) => {
+                tracing::debug!(?reason, "mobile sync deferred");
+                let _ = self.events.send(SyncEvent::Deferred { reason });
+                Ok(false)
+            }
+        }
+    }
+
+    /// Sync every target now, ignoring intervals and battery rules.
+    ///
+    /// Returns whether all targets succeeded.
+    pub async fn refresh(&self) -> AnyaResult<bool> {
+        if !self.hints().await.connected {
+            return Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                "cannot sync while offline",
+            ));
+        }
+        let Ok(_guard) = self.running.try_lock() else {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                "a sync is already in progress",
+            ));
+        };
+        let mut targets: Vec<SyncTarget> = self.jobs.iter().map(|j| j.target()).collect();
+        targets.dedup();
+        Ok(self.run_batch(&targets, true).await)
+    }
+
+    /// Evaluate the policy every poll interval until `token` is cancelled
+    pub async fn run(&self, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.policy.poll_interval, || async {
+            self.tick().await.map(|_| ())
+        })
+        .await
+    }
+
+    async fn run_batch(&self, targets: &[SyncTarget], manual: bool) -> bool {
+        let _ = self.events.send(SyncEvent::BatchStarted {
+            targets: targets.to_vec(),
+            manual,
+        });
+        let mut success = true;
+        for job in self.jobs.iter().filter(|j| targets.contains(&j.target())) {
+            let target = job.target();
+            let progress = SyncProgress {
+                target,
+                events: self.events.clone(),
+            };
+            let event = match job.run(&progress).await {
+                Ok(()) => SyncEvent::TargetFinished { target },
+                Err(e) => {
+                    tracing::warn!(?target, error = %e, "mobile sync failed");
+                    success = false;
+                    SyncEvent::TargetFailed {
+                        target,
+                        message: e.to_string(),
+                    }
+                }
+            };
+            let _ = self.events.send(event);
+        }
+        *self.last_batch.lock().await = Some(Instant::now());
+        let _ = self.events.send(SyncEvent::BatchFinished { success });
+        success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountingJob(SyncTarget);
+
+    #[async_trait]
+    impl SyncJob for CountingJob {
+        fn target(&self) -> SyncTarget {
+            self.0
+        }
+
+        async fn run(&self, progress: &SyncProgress) -> AnyaResult<()> {
+            progress.report(1, 2);
+            progress.report(2, 2);
+            if self.0 == SyncTarget::Dwn {
+                return Err(AnyaError::new(ErrorCode::NetworkFailure, "dwn unreachable"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_policy_respects_platform_hints() {
+        let policy = SyncPolicy::default();
+        let all = [SyncTarget::Spv, SyncTarget::Dwn];
+        let background = PlatformHints {
+            foreground: false,
+            ..PlatformHints::default()
+        };
+
+        assert_eq!(
+            policy.decide(&background, None, &all),
+            SyncDecision::Defer(DeferReason::NeedsWifi)
+        );
+        let on_wifi = PlatformHints {
+            on_wifi: true,
+            battery_percent: Some(5),
+            ..background
+        };
+        assert_eq!(
+            policy.decide(&on_wifi, None, &all),
+            SyncDecision::Defer(DeferReason::BatteryLow)
+        );
+        let charging = PlatformHints {
+            charging: true,
+            ..on_wifi
+        };
+        assert_eq!(
+            policy.decide(&charging, None, &all),
+            SyncDecision::Run(all.to_vec())
+        );
+        assert_eq!(
+            policy.decide(&charging, Some(Duration::from_secs(60)), &all),
+            SyncDecision::Defer(DeferReason::NotDue)
+        );
+
+        let cellular_only_spv = SyncPolicy {
+            dwn_requires_wifi: true,
+            ..SyncPolicy::default()
+        };
+        assert_eq!(
+            cellular_only_spv.decide(&PlatformHints::default(), None, &all),
+            SyncDecision::Run(vec![SyncTarget::Spv])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manual_refresh_reports_progress() {
+        let mut scheduler = SyncScheduler::new(SyncPolicy::default());
+        scheduler.register(Arc::new(CountingJob(SyncTarget::Dwn)));
+        scheduler.register(Arc::new(CountingJob(SyncTarget::Spv)));
+        let mut events = scheduler.subscribe();
+
+        assert!(!scheduler.refresh().await.unwrap());
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(received.len(), 8);
+        assert!(matches!(
+            received[1],
+            SyncEvent::Progress {
+                target: SyncTarget::Spv,
+                ..
+            }
+        ));
+        assert!(matches!(
+            received[3],
+            SyncEvent::TargetFinished {
+                target: SyncTarget::Spv
+            }
+        ));
+        assert_eq!(received[7], SyncEvent::BatchFinished { success: false });
+
+        // The manual batch counts as the last sync, so the scheduler waits
+        assert!(!scheduler.tick().await.unwrap());
+
+        scheduler
+            .set_hints(PlatformHints {
+                connected: false,
+                ..PlatformHints::default()
+            })
+            .await;
+        assert!(scheduler.refresh().await.is_err());
+    }
+}
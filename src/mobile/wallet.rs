@@ -0,0 +1,190 @@
+//! Mobile wallet facade exposed to the apps
+
+use std::sync::Arc;
+
+use ::bitcoin::psbt::Psbt;
+use ::bitcoin::Txid;
+
+use super::bip353::{self, DnsResolver};
+use super::history::{
+    HistoryPage, HistoryQuery, PriceOracle, TransactionHistory, TxCategory, WalletTx,
+};
+use super::lightning::{MobileLightning, UnifiedBalance};
+use super::qr::{PaymentUri, QrPayload};
+use super::security::SecurityManager;
+use super::signer::{AirGapSigner, TransactionSummary};
+use super::spending::SpendingGuard;
+use super::MobileConfig;
+use crate::bitcoin::accounts::{AccountId, AccountManager};
+use crate::bitcoin::builder::BuiltTx;
+use crate::bitcoin::labels::{ImportReport, Label, LabelStore, LabelType};
+use crate::bitcoin::privacy::{self, PrivacyContext, PrivacyReport};
+use crate::storage::StorageBackend;
+use crate::{AnyaError, AnyaResult};
+
+/// Wallet operations available to the mobile apps
+pub struct MobileWallet {
+    config: MobileConfig,
+    accounts: AccountManager,
+    history: TransactionHistory,
+    labels: LabelStore,
+    security: SecurityManager,
+    spending: Arc<SpendingGuard>,
+}
+
+impl MobileWallet {
+    /// Open the wallet on top of `storage`
+    pub async fn open(
+        config: MobileConfig,
+        storage: Arc<dyn StorageBackend>,
+        oracle: Option<Arc<dyn PriceOracle>>,
+    ) -> AnyaResult<Self> {
+        let accounts = AccountManager::open(config.network, Arc::clone(&storage)).await?;
+        let labels = LabelStore::open(Arc::clone(&storage)).await?;
+        let security = SecurityManager::open(config.security.clone(), Arc::clone(&storage)).await?;
+        let spending = Arc::new(SpendingGuard::open(Arc::clone(&storage)).await?);
+        let history =
+            TransactionHistory::open(storage, oracle, config.fiat_currency.clone()).await?;
+        Ok(Self {
+            config,
+            accounts,
+            history,
+            labels,
+            security,
+            spending,
+        })
+    }
+
+    /// Mobile configuration
+    pub const fn config(&self) -> &MobileConfig {
+        &self.config
+    }
+
+    /// HD accounts, shared with the core wallet through the same storage
+    pub const fn accounts(&self) -> &AccountManager {
+        &self.accounts
+    }
+
+    /// Paginated transaction history, newest first
+    pub async fn transactions(&self, query: &HistoryQuery) -> AnyaResult<HistoryPage> {
+        self.history.page(query).await
+    }
+
+    /// Look up a single transaction
+    pub async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<WalletTx>> {
+        self.history.get(txid).await
+    }
+
+    /// Categorize and label a transaction
+    pub async fn label_transaction(
+        &self,
+        txid: &Txid,
+        category: Option<TxCategory>,
+        label: Option<String>,
+    ) -> AnyaResult<()> {
+        if let Some(label) = &label {
+            self.labels.set(&Label::tx(txid, label.clone())).await?;
+        }
+        self.history.set_label(txid, category, label).await
+    }
+
+    /// Wallet labels, exchangeable with other wallets as BIP-329
+    pub const fn labels(&self) -> &LabelStore {
+        &self.labels
+    }
+
+    /// Import BIP-329 labels, applying transaction labels to the history
+    pub async fn import_labels(&self, jsonl: &str, overwrite: bool) -> AnyaResult<ImportReport> {
+        let report = self.labels.import_jsonl(jsonl, overwrite).await?;
+        for label in self.labels.labels(Some(LabelType::Tx)).await? {
+            let Ok(txid) = label.reference.parse::<Txid>() else {
+                continue;
+            };
+            if let Some(tx) = self.history.get(&txid).await? {
+                if overwrite || tx.label.is_none() {
+                    self.history
+                        .set_label(&txid, tx.category, Some(label.label))
+                        .await?;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Transaction history store, used by sync to record new transactions
+    pub const fn history(&self) -> &TransactionHistory {
+        &self.history
+    }
+
+    /// Authentication gate guarding signing and key export
+    pub const fn security(&self) -> &SecurityManager {
+        &self.security
+    }
+
+    /// Spending policy, co-signer approvals, and the spend audit log. Attach
+    /// it to the signer with [`AirGapSigner::with_spending_policy`].
+    pub const fn spending(&self) -> &Arc<SpendingGuard> {
+        &self.spending
+    }
+
+    /// Sign a PSBT from an air-gapped coordinator behind the security gate
+    pub async fn sign_offline(
+        &self,
+        signer: &AirGapSigner,
+        psbt: &mut Psbt,
+    ) -> AnyaResult<TransactionSummary> {
+        signer.sign(psbt, &self.security).await
+    }
+
+    /// Privacy review of a transaction built from `account`, shown in the
+    /// send flow before signing
+    pub async fn review_privacy(
+        &self,
+        account: AccountId,
+        built: &BuiltTx,
+    ) -> AnyaResult<PrivacyReport> {
+        let context =
+            PrivacyContext::gather(&self.accounts, account, Some(&self.labels), built).await?;
+        Ok(privacy::analyze(built, &context))
+    }
+
+    /// Payment destination for text entered or scanned in the send flow.
+    ///
+    /// BIP-353 names are resolved through `resolver`; on-chain addresses are
+    /// checked against the wallet network.
+    pub async fn resolve_recipient(
+        &self,
+        text: &str,
+        resolver: &dyn DnsResolver,
+    ) -> AnyaResult<PaymentUri> {
+        let uri = match QrPayload::parse(text)? {
+            QrPayload::Bitcoin(uri) => uri,
+            QrPayload::Address(address) => PaymentUri::new(address),
+            QrPayload::Lightning(invoice) => PaymentUri {
+                lightning: Some(invoice),
+                ..PaymentUri::new("")
+            },
+            QrPayload::Name(name) => bip353::resolve(resolver, &name).await?,
+            _ => return Err(AnyaError::invalid_input("not a payment destination")),
+        };
+        uri.validate_for(self.config.network)?;
+        Ok(uri)
+    }
+
+    /// On-chain balance combined with Lightning funds when `lightning` is set
+    pub async fn balance(&self, lightning: Option<&MobileLightning>) -> AnyaResult<UnifiedBalance> {
+        match lightning {
+            Some(lightning) => lightning.balance(&self.history).await,
+            None => {
+                let onchain = self.history.balance().await?;
+                Ok(UnifiedBalance {
+                    onchain_confirmed_sat: onchain.confirmed_sat,
+                    onchain_pending_sat: onchain.pending_sat,
+                    lightning_spendable_sat: 0,
+                    lightning_inbound_sat: 0,
+                    total_sat: onchain.confirmed_sat,
+                })
+            }
+        }
+    }
+}
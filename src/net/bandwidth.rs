@@ -0,0 +1,432 @@
+//! Bandwidth throttling and traffic shaping for peer connections
+//!
+//! The connection layer calls [`BandwidthManager::acquire`] before writing
+//! a message and after reading a message header, before reading its body,
+//! so a throttled peer is slowed down by TCP backpressure. Limits apply per
+//! direction, both globally and per peer, and are token buckets: up to
+//! `burst` worth of traffic passes at once, and anything beyond waits until
+//! the rate has paid for it.
+//!
+//! Block and transaction relay has strict priority over bulk serving of
+//! historical blocks: relay traffic waits only for other relay traffic,
+//! while bulk traffic waits for everything sent before it, so bulk only
+//! gets the capacity relay leaves over. Per-peer limits treat both classes
+//! alike.
+//!
+//! Bytes moved and time spent throttled are counted per peer and class and
+//! exposed to the metrics store as a [`MetricsSource`].
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::timeseries::{MetricsSource, SeriesKey};
+use crate::AnyaResult;
+
+/// Kind of traffic, in priority order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficClass {
+    /// New blocks, transactions, headers, and control messages
+    Relay,
+    /// Historical blocks and filters served to syncing peers
+    Bulk,
+}
+
+impl TrafficClass {
+    /// Label used in metrics
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Relay => "relay",
+            Self::Bulk => "bulk",
+        }
+    }
+}
+
+/// Direction of traffic as seen from this node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Sent to peers
+    Upload,
+    /// Received from peers
+    Download,
+}
+
+/// Rate limits in bytes per second; `None` is unlimited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandwidthConfig {
+    /// Total upload rate
+    pub upload: Option<u64>,
+    /// Total download rate
+    pub download: Option<u64>,
+    /// Upload rate to each peer
+    pub peer_upload: Option<u64>,
+    /// Download rate from each peer
+    pub peer_download: Option<u64>,
+    /// Traffic allowed through at once, as time at the limit's rate
+    pub burst: Duration,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            upload: None,
+            download: None,
+            peer_upload: None,
+            peer_download: None,
+            burst: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Token bucket that may go into debt, so messages larger than the burst
+/// still pass after waiting
+#[derive(Debug, Clone)]
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(bytes_per_sec: u64, burst: Duration, now: Instant) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let burst = (rate * burst.as_secs_f64()).max(1.0);
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    /// Take `bytes` tokens, returning how long until the debt is repaid
+    fn debit(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = elapsed.mul_add(self.rate, self.tokens).min(self.burst);
+        self.updated = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Global limit of one direction with relay priority
+#[derive(Debug, Clone)]
+struct Shaper {
+    /// Debited by relay traffic only, pacing relay at the full rate
+    relay: Bucket,
+    /// Debited by all traffic, pacing bulk to what relay leaves over
+    shared: Bucket,
+}
+
+impl Shaper {
+    fn new(bytes_per_sec: u64, burst: Duration, now: Instant) -> Self {
+        let bucket = Bucket::new(bytes_per_sec, burst, now);
+        Self {
+            relay: bucket.clone(),
+            shared: bucket,
+        }
+    }
+
+    fn debit(&mut self, bytes: u64, class: TrafficClass, now: Instant) -> Duration {
+        let shared = self.shared.debit(bytes, now);
+        match class {
+            TrafficClass::Relay => self.relay.debit(bytes, now),
+            TrafficClass::Bulk => shared,
+        }
+    }
+}
+
+/// Traffic counters of one peer and class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassTraffic {
+    /// Bytes sent
+    pub bytes_sent: u64,
+    /// Bytes received
+    pub bytes_received: u64,
+    /// Time messages were held back by limits
+    pub throttled: Duration,
+}
+
+/// Traffic counters of one peer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerTraffic {
+    /// Relay traffic
+    pub relay: ClassTraffic,
+    /// Bulk traffic
+    pub bulk: ClassTraffic,
+}
+
+impl PeerTraffic {
+    const fn class_mut(&mut self, class: TrafficClass) -> &mut ClassTraffic {
+        match class {
+            TrafficClass::Relay => &mut self.relay,
+            TrafficClass::Bulk => &mut self.bulk,
+        }
+    }
+}
+
+struct PeerState {
+    upload: Option<Bucket>,
+    download: Option<Bucket>,
+    traffic: PeerTraffic,
+}
+
+struct State {
+    upload: Option<Shaper>,
+    download: Option<Shaper>,
+    peers: HashMap<u64, PeerState>,
+}
+
+/// Enforces bandwidth limits and counts traffic per peer
+pub struct BandwidthManager {
+    config: BandwidthConfig,
+    state: Mutex<State>,
+}
+
+impl BandwidthManager {
+    /// Create a manager enforcing `config`
+    pub fn new(config: BandwidthConfig) -> Self {
+        let now = Instant::now();
+        let shaper = |limit: Option<u64>| limit.map(|rate| Shaper::new(rate, config.burst, now));
+        let state = State {
+            upload: shaper(config.upload),
+            download: shaper(config.download),
+            peers: HashMap::new(),
+        };
+        Self {
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Start shaping a new connection with the default per-peer limits
+    pub fn peer_connected(&self, peer: u64) {
+        self.set_peer_limits(peer, self.config.peer_upload, self.config.peer_download);
+    }
+
+    /// Replace a peer's limits, e.g. to exempt a trusted peer
+    pub fn set_peer_limits(&self, peer: u64, upload: Option<u64>, download: Option<u64>) {
+        let now = Instant::now();
+        let burst = self.config.burst;
+        let mut state = self.state();
+        let entry = state.peers.entry(peer).or_insert_with(|| PeerState {
+            upload: None,
+            download: None,
+            traffic: PeerTraffic::default(),
+        });
+        entry.upload = upload.map(|rate| Bucket::new(rate, burst, now));
+        entry.download = download.map(|rate| Bucket::new(rate, burst, now));
+        drop(state);
+    }
+
+    /// Stop shaping a closed connection, returning its final counters
+    pub fn peer_disconnected(&self, peer: u64) -> Option<PeerTraffic> {
+        self.state().peers.remove(&peer).map(|p| p.traffic)
+    }
+
+    /// Counters of a connected peer
+    pub fn traffic(&self, peer: u64) -> Option<PeerTraffic> {
+        self.state().peers.get(&peer).map(|p| p.traffic.clone())
+    }
+
+    /// Account for `bytes` of `class` traffic and return how long to hold
+    /// it back
+    fn reserve(
+        &self,
+        peer: u64,
+        direction: Direction,
+        class: TrafficClass,
+        bytes: u64,
+        now: Instant,
+    ) -> Duration {
+        let mut state = self.state();
+        let global = match direction {
+            Direction::Upload => state.upload.as_mut(),
+            Direction::Download => state.download.as_mut(),
+        }
+        .map_or(Duration::ZERO, |shaper| shaper.debit(bytes, class, now));
+        let Some(entry) = state.peers.get_mut(&peer) else {
+            return global;
+        };
+        let own = match direction {
+            Direction::Upload => entry.upload.as_mut(),
+            Direction::Download => entry.download.as_mut(),
+        }
+        .map_or(Duration::ZERO, |bucket| bucket.debit(bytes, now));
+        let wait = global.max(own);
+        let counters = entry.traffic.class_mut(class);
+        match direction {
+            Direction::Upload => counters.bytes_sent += bytes,
+            Direction::Download => counters.bytes_received += bytes,
+        }
+        counters.throttled += wait;
+        drop(state);
+        wait
+    }
+
+    /// Wait until `bytes` of `class` traffic with `peer` fit within the
+    /// limits
+    pub async fn acquire(&self, peer: u64, direction: Direction, class: TrafficClass, bytes: u64) {
+        let wait = self.reserve(peer, direction, class, bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Per-peer traffic counters, labelled by peer and class
+#[async_trait]
+impl MetricsSource for BandwidthManager {
+    fn name(&self) -> &str {
+        "bandwidth"
+    }
+
+    async fn collect(&self) -> AnyaResult<Vec<(SeriesKey, f64)>> {
+        let mut values = Vec::new();
+        for (peer, state) in &self.state().peers {
+            let peer = peer.to_string();
+            for class in [TrafficClass::Relay, TrafficClass::Bulk] {
+                let counters = match class {
+                    TrafficClass::Relay => state.traffic.relay,
+                    TrafficClass::Bulk => state.traffic.bulk,
+                };
+                let key = |name: &str| {
+                    SeriesKey::new(name)
+                        .with_label("peer", &peer)
+                        .with_label("class", class.as_str())
+                };
+                values.push((
+                    key("anya_peer_bytes_sent_total"),
+                    counters.bytes_sent as f64,
+                ));
+                values.push((
+                    key("anya_peer_bytes_received_total"),
+                    counters.bytes_received as f64,
+                ));
+                values.push((
+                    key("anya_peer_throttled_seconds_total"),
+                    counters.throttled.as_secs_f64(),
+                ));
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: Duration, expected_ms: u64) -> bool {
+        let expected = Duration::from_millis(expected_ms);
+        let diff = actual
+            .checked_sub(expected)
+            .or_else(|| expected.checked_sub(actual))
+            .unwrap_or_default();
+        diff < Duration::from_millis(1)
+    }
+
+    #[test]
+    fn test_relay_has_priority_over_bulk() {
+        let bandwidth = BandwidthManager::new(BandwidthConfig {
+            upload: Some(1000),
+            peer_upload: Some(4000),
+            ..BandwidthConfig::default()
+        });
+        bandwidth.peer_connected(1);
+        bandwidth.peer_connected(2);
+        let t0 = Instant::now();
+        let up = Direction::Upload;
+        // The burst passes, then relay waits only for relay
+        assert!(bandwidth
+            .reserve(1, up, TrafficClass::Relay, 1000, t0)
+            .is_zero());
+        assert!(close(
+            bandwidth.reserve(2, up, TrafficClass::Bulk, 500, t0),
+            500
+        ));
+        assert!(close(
+            bandwidth.reserve(1, up, TrafficClass::Relay, 500, t0),
+            500
+        ));
+        // Bulk waits behind everything sent so far
+        assert!(close(
+            bandwidth.reserve(2, up, TrafficClass::Bulk, 500, t0),
+            1500
+        ));
+        // A second later the relay debt is repaid, the shared one is not
+        let t1 = t0 + Duration::from_secs(1);
+        assert!(bandwidth
+            .reserve(1, up, TrafficClass::Relay, 100, t1)
+            .is_zero());
+        assert!(close(
+            bandwidth.reserve(2, up, TrafficClass::Bulk, 100, t1),
+            700
+        ));
+        // Downloads are unlimited globally
+        assert!(bandwidth
+            .reserve(1, Direction::Download, TrafficClass::Bulk, 1 << 20, t1)
+            .is_zero());
+
+        let traffic = bandwidth.traffic(2).unwrap();
+        assert_eq!(traffic.bulk.bytes_sent, 1100);
+        assert_eq!(bandwidth.traffic(1).unwrap().bulk.bytes_received, 1 << 20);
+        assert!(close(traffic.bulk.throttled, 2700));
+    }
+
+    #[tokio::test]
+    async fn test_peer_limits_and_metrics() {
+        let bandwidth = BandwidthManager::new(BandwidthConfig {
+            peer_download: Some(1000),
+            burst: Duration::from_millis(100),
+            ..BandwidthConfig::default()
+        });
+        bandwidth.peer_connected(9);
+        let t0 = Instant::now();
+        let down = Direction::Download;
+        assert!(bandwidth
+            .reserve(9, down, TrafficClass::Relay, 100, t0)
+            .is_zero());
+        assert!(close(
+            bandwidth.reserve(9, down, TrafficClass::Relay, 200, t0),
+            200
+        ));
+        // Exempting the peer lifts its limit
+        bandwidth.set_peer_limits(9, None, None);
+        assert!(bandwidth
+            .reserve(9, down, TrafficClass::Relay, 10_000, t0)
+            .is_zero());
+        bandwidth.acquire(9, down, TrafficClass::Bulk, 10).await;
+
+        let metrics = bandwidth.collect().await.unwrap();
+        assert_eq!(metrics.len(), 6);
+        let received = metrics
+            .iter()
+            .find(|(k, _)| {
+                k.name == "anya_peer_bytes_received_total" && k.labels["class"] == "relay"
+            })
+            .unwrap();
+        assert_eq!(received.1, 10_300.0);
+        assert_eq!(
+            bandwidth.peer_disconnected(9).unwrap().bulk.bytes_received,
+            10
+        );
+        assert!(bandwidth.traffic(9).is_none());
+    }
+}
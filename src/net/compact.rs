@@ -0,0 +1,734 @@
+//! Compact block relay (BIP-152)
+//!
+//! A compact block carries the header, the coinbase, and a 6-byte short ID
+//! per other transaction. The receiver rebuilds the block from its own
+//! mempool, asks the sender for whatever it lacks with `getblocktxn`, and
+//! falls back to downloading the full block when short IDs collide or the
+//! result does not match the header's merkle root.
+//!
+//! Peers announce new blocks to us in one of two modes:
+//!
+//! - high bandwidth: the peer sends `cmpctblock` unsolicited, before it has
+//!   fully validated the block, saving a round trip. We ask this of the
+//!   [`MAX_HIGH_BANDWIDTH_PEERS`] peers that most recently delivered a new
+//!   block first.
+//! - low bandwidth: the peer announces with `headers`/`inv` and we request
+//!   the compact block if we want it.
+//!
+//! Only version 2 (witness transactions, short IDs over wtxids) is spoken.
+//! Reconstruction outcomes are counted and exposed as a [`MetricsSource`].
+//!
+//! High-bandwidth peers send blocks before we asked for them, so a
+//! `cmpctblock` must build on a known block and carry valid proof of work
+//! for a target no easier than that block's successors may declare before
+//! any reconstruction state is allocated for it. Each peer has at
+//! most one block waiting for `blocktxn`, and at most
+//! [`MAX_PENDING_BLOCKS`] wait across all peers.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+
+use ::bitcoin::bip152::{BlockTransactions, BlockTransactionsRequest, HeaderAndShortIds, ShortId};
+use ::bitcoin::block::Header;
+use ::bitcoin::network::message::NetworkMessage;
+use ::bitcoin::network::message_blockdata::Inventory;
+use ::bitcoin::network::message_compact_blocks::{BlockTxn, CmpctBlock, GetBlockTxn, SendCmpct};
+use ::bitcoin::pow::Target;
+use ::bitcoin::{Block, BlockHash, Transaction};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::bitcoin::spv::{pow_limit, HeaderChain};
+use crate::timeseries::{MetricsSource, SeriesKey};
+use crate::{AnyaError, AnyaResult};
+
+/// Compact block protocol version spoken
+pub const COMPACT_VERSION: u64 = 2;
+
+/// Peers asked to announce blocks in high-bandwidth mode
+pub const MAX_HIGH_BANDWIDTH_PEERS: usize = 3;
+
+/// Upper bound on transactions in a block: the block weight limit divided
+/// by the weight of the smallest possible transaction
+const MAX_BLOCK_TXS: usize = 4_000_000 / 240;
+
+/// Blocks waiting for `blocktxn` across all peers; more fall back to a
+/// full download
+pub const MAX_PENDING_BLOCKS: usize = 8;
+
+/// Transactions available for reconstruction, usually the mempool
+pub trait TxPool: Send + Sync {
+    /// Transactions currently held
+    fn transactions(&self) -> Vec<Transaction>;
+}
+
+/// Headers already accepted, usually the header chain
+pub trait HeaderIndex: Send + Sync {
+    /// Easiest target a child of `parent` may declare, or `None` if
+    /// `parent` is unknown
+    fn max_target_after(&self, parent: &BlockHash) -> Option<Target>;
+}
+
+/// A [`HeaderChain`] does not recompute retargets, so children of its
+/// headers are held to the network's proof-of-work limit
+impl HeaderIndex for HeaderChain {
+    fn max_target_after(&self, parent: &BlockHash) -> Option<Target> {
+        self.height_of(*parent).map(|_| pow_limit(self.network()))
+    }
+}
+
+/// What to do after handling a compact block message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reconstruction {
+    /// The block was rebuilt
+    Complete(Box<Block>),
+    /// Send this `getblocktxn` to the peer and wait for `blocktxn`
+    Incomplete(NetworkMessage),
+    /// Reconstruction failed; send this `getdata` for the full block
+    Fallback(NetworkMessage),
+}
+
+/// Reconstruction counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactStats {
+    /// Compact blocks received
+    pub received: u64,
+    /// Blocks rebuilt from the mempool alone
+    pub immediate: u64,
+    /// Blocks rebuilt after a `getblocktxn` round trip
+    pub round_trip: u64,
+    /// Blocks that needed a full download
+    pub fallback: u64,
+    /// Transactions found in the mempool
+    pub txs_from_pool: u64,
+    /// Transactions requested with `getblocktxn`
+    pub txs_requested: u64,
+}
+
+#[derive(Default)]
+struct PeerState {
+    /// Peer speaks version 2
+    compact: bool,
+    /// Peer asked us for high-bandwidth announcements
+    wants_announce: bool,
+}
+
+/// A block waiting for `blocktxn`
+struct Partial {
+    header: Header,
+    slots: Vec<Option<Transaction>>,
+    missing: Vec<usize>,
+}
+
+impl Partial {
+    fn request(&self) -> NetworkMessage {
+        NetworkMessage::GetBlockTxn(GetBlockTxn {
+            txs_request: BlockTransactionsRequest {
+                block_hash: self.header.block_hash(),
+                indexes: self.missing.iter().map(|&i| i as u64).collect(),
+            },
+        })
+    }
+
+    fn into_block(self) -> Option<Block> {
+        let block = Block {
+            header: self.header,
+            txdata: self.slots.into_iter().collect::<Option<_>>()?,
+        };
+        (block.check_merkle_root() && block.check_witness_commitment()).then_some(block)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    peers: HashMap<u64, PeerState>,
+    /// Peers we asked for high-bandwidth mode, oldest first
+    high_bandwidth: VecDeque<u64>,
+    /// Block each peer owes us `blocktxn` for
+    pending: HashMap<u64, Partial>,
+    stats: CompactStats,
+}
+
+/// Compact block relay state across peers
+#[derive(Default)]
+pub struct CompactRelay {
+    state: Mutex<State>,
+}
+
+fn full_block(hash: BlockHash) -> NetworkMessage {
+    NetworkMessage::GetData(vec![Inventory::WitnessBlock(hash)])
+}
+
+const fn send_compact(announce: bool) -> NetworkMessage {
+    NetworkMessage::SendCmpct(SendCmpct {
+        send_compact: announce,
+        version: COMPACT_VERSION,
+    })
+}
+
+impl CompactRelay {
+    /// Create relay state with no peers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Track a new connection; returns the `sendcmpct` offering low-bandwidth
+    /// mode to send after the handshake
+    pub fn peer_connected(&self, peer: u64) -> NetworkMessage {
+        self.state().peers.insert(peer, PeerState::default());
+        send_compact(false)
+    }
+
+    /// Forget a closed connection and its partial blocks
+    pub fn peer_disconnected(&self, peer: u64) {
+        let mut state = self.state();
+        state.peers.remove(&peer);
+        state.high_bandwidth.retain(|p| *p != peer);
+        state.pending.remove(&peer);
+        drop(state);
+    }
+
+    /// Record a peer's `sendcmpct`; versions other than 2 are ignored
+    pub fn on_sendcmpct(&self, peer: u64, message: &SendCmpct) {
+        if message.version != COMPACT_VERSION {
+            return;
+        }
+        if let Some(entry) = self.state().peers.get_mut(&peer) {
+            entry.compact = true;
+            entry.wants_announce = message.send_compact;
+        }
+    }
+
+    /// Note that `peer` delivered a new valid block first. It becomes a
+    /// high-bandwidth peer, displacing the one that has gone longest without
+    /// doing so; returns the `sendcmpct` messages to send.
+    pub fn block_delivered(&self, peer: u64) -> Vec<(u64, NetworkMessage)> {
+        let mut state = self.state();
+        if !state.peers.get(&peer).is_some_and(|p| p.compact) {
+            return Vec::new();
+        }
+        let mut messages = Vec::new();
+        if let Some(pos) = state.high_bandwidth.iter().position(|p| *p == peer) {
+            state.high_bandwidth.remove(pos);
+        } else {
+            messages.push((peer, send_compact(true)));
+        }
+        state.high_bandwidth.push_back(peer);
+        while state.high_bandwidth.len() > MAX_HIGH_BANDWIDTH_PEERS {
+            if let Some(demoted) = state.high_bandwidth.pop_front() {
+                messages.push((demoted, send_compact(false)));
+            }
+        }
+        drop(state);
+        messages
+    }
+
+    /// Peers currently asked for high-bandwidth announcements
+    pub fn high_bandwidth_peers(&self) -> Vec<u64> {
+        self.state().high_bandwidth.iter().copied().collect()
+    }
+
+    /// Announcements of a new block: `cmpctblock` to peers that asked for
+    /// high-bandwidth mode, `headers` to everyone else
+    pub fn announce(&self, block: &Block) -> AnyaResult<Vec<(u64, NetworkMessage)>> {
+        let compact = HeaderAndShortIds::from_block(block, rand::random(), 2, &[])
+            .map_err(|e| AnyaError::invalid_input(format!("compact block: {:?}", e)))?;
+        let state = self.state();
+        let messages = state
+            .peers
+            .iter()
+            .map(|(&peer, entry)| {
+                let message = if entry.compact && entry.wants_announce {
+                    NetworkMessage::CmpctBlock(CmpctBlock {
+                        compact_block: compact.clone(),
+                    })
+                } else {
+                    NetworkMessage::Headers(vec![block.header])
+                };
+                (peer, message)
+            })
+            .collect();
+        drop(state);
+        Ok(messages)
+    }
+
+    /// Answer a peer's `getblocktxn` from the full block
+    pub fn serve_getblocktxn(
+        &self,
+        request: &GetBlockTxn,
+        block: &Block,
+    ) -> AnyaResult<NetworkMessage> {
+        let transactions = BlockTransactions::from_request(&request.txs_request, block)
+            .map_err(|e| AnyaError::invalid_input(format!("getblocktxn: {}", e)))?;
+        Ok(NetworkMessage::BlockTxn(BlockTxn { transactions }))
+    }
+
+    /// Rebuild a block from a peer's `cmpctblock` and the transactions in
+    /// `pool`. A malformed message or one without valid proof of work is an
+    /// `InvalidInput` error, and the caller should treat it as misbehavior.
+    /// A block whose parent is not in `headers` is `NotFound`; sync headers
+    /// from the peer instead.
+    ///
+    /// A new partial block replaces any the peer left waiting for
+    /// `blocktxn`.
+    pub fn on_cmpctblock(
+        &self,
+        peer: u64,
+        message: &CmpctBlock,
+        pool: &dyn TxPool,
+        headers: &dyn HeaderIndex,
+    ) -> AnyaResult<Reconstruction> {
+        let compact = &message.compact_block;
+        let header = &compact.header;
+        let Some(max_target) = headers.max_target_after(&header.prev_blockhash) else {
+            return Err(AnyaError::not_found(format!(
+                "cmpctblock {} builds on unknown block {}",
+                header.block_hash(),
+                header.prev_blockhash
+            )));
+        };
+        let target = header.target();
+        let hash = (target <= max_target)
+            .then(|| header.validate_pow(target).ok())
+            .flatten()
+            .ok_or_else(|| {
+                AnyaError::invalid_input(format!(
+                    "cmpctblock {} has insufficient proof of work",
+                    header.block_hash()
+                ))
+            })?;
+        let total = compact.short_ids.len() + compact.prefilled_txs.len();
+        if compact.prefilled_txs.is_empty() || total > MAX_BLOCK_TXS {
+            return Err(AnyaError::invalid_input(format!(
+                "cmpctblock {} with {} transactions",
+                hash, total
+            )));
+        }
+
+        let mut slots: Vec<Option<Transaction>> = vec![None; total];
+        let mut next = 0usize;
+        for prefilled in &compact.prefilled_txs {
+            let index = next + usize::from(prefilled.idx);
+            if index >= total {
+                return Err(AnyaError::invalid_input(format!(
+                    "cmpctblock {} prefills index {} of {}",
+                    hash, index, total
+                )));
+            }
+            slots[index] = Some(prefilled.tx.clone());
+            next = index + 1;
+        }
+
+        let mut state = self.state();
+        state.stats.received += 1;
+
+        // Slot of each short ID; a block whose own IDs collide can only be
+        // downloaded in full
+        let mut by_id: HashMap<ShortId, usize> = HashMap::with_capacity(compact.short_ids.len());
+        let empty = slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.is_none().then_some(i));
+        for (id, slot) in compact.short_ids.iter().zip(empty) {
+            if by_id.insert(*id, slot).is_some() {
+                state.stats.fallback += 1;
+                return Ok(Reconstruction::Fallback(full_block(hash)));
+            }
+        }
+        drop(state);
+
+        // Mempool transactions whose short ID matches; two matches for one
+        // ID leave the slot to be requested
+        let keys = ShortId::calculate_siphash_keys(&compact.header, compact.nonce);
+        let mut ambiguous = Vec::new();
+        for tx in pool.transactions() {
+            let id = ShortId::with_siphash_keys(&tx.wtxid().to_raw_hash(), keys);
+            if let Some(&slot) = by_id.get(&id) {
+                if slots[slot].is_some() {
+                    ambiguous.push(slot);
+                } else {
+                    slots[slot] = Some(tx);
+                }
+            }
+        }
+        for slot in ambiguous {
+            slots[slot] = None;
+        }
+
+        let missing: Vec<usize> = slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.is_none().then_some(i))
+            .collect();
+        let partial = Partial {
+            header: compact.header,
+            slots,
+            missing,
+        };
+        let mut state = self.state();
+        state.stats.txs_from_pool += (compact.short_ids.len() - partial.missing.len()) as u64;
+        let outcome = if partial.missing.is_empty() {
+            if let Some(block) = partial.into_block() {
+                state.stats.immediate += 1;
+                Reconstruction::Complete(Box::new(block))
+            } else {
+                state.stats.fallback += 1;
+                Reconstruction::Fallback(full_block(hash))
+            }
+        } else {
+            state.pending.remove(&peer);
+            if state.pending.len() >= MAX_PENDING_BLOCKS {
+                state.stats.fallback += 1;
+                Reconstruction::Fallback(full_block(hash))
+            } else {
+                state.stats.txs_requested += partial.missing.len() as u64;
+                let request = partial.request();
+                state.pending.insert(peer, partial);
+                Reconstruction::Incomplete(request)
+            }
+        };
+        drop(state);
+        Ok(outcome)
+    }
+
+    /// Complete a block with the transactions a peer sent in `blocktxn`
+    pub fn on_blocktxn(&self, peer: u64, message: &BlockTxn) -> AnyaResult<Reconstruction> {
+        let response = &message.transactions;
+        let hash = response.block_hash;
+        let mut state = self.state();
+        let Some(mut partial) = state
+            .pending
+            .remove(&peer)
+            .filter(|p| p.header.block_hash() == hash)
+        else {
+            return Err(AnyaError::invalid_input(format!(
+                "unsolicited blocktxn for {}",
+                hash
+            )));
+        };
+        if response.transactions.len() != partial.missing.len() {
+            state.stats.fallback += 1;
+            return Ok(Reconstruction::Fallback(full_block(hash)));
+        }
+        for (&slot, tx) in partial.missing.iter().zip(&response.transactions) {
+            partial.slots[slot] = Some(tx.clone());
+        }
+        let outcome = if let Some(block) = partial.into_block() {
+            state.stats.round_trip += 1;
+            Reconstruction::Complete(Box::new(block))
+        } else {
+            state.stats.fallback += 1;
+            Reconstruction::Fallback(full_block(hash))
+        };
+        drop(state);
+        Ok(outcome)
+    }
+
+    /// Reconstruction counters since startup
+    pub fn stats(&self) -> CompactStats {
+        self.state().stats
+    }
+}
+
+/// Reconstruction counters, labelled by outcome
+#[async_trait]
+impl MetricsSource for CompactRelay {
+    fn name(&self) -> &str {
+        "compact_blocks"
+    }
+
+    async fn collect(&self) -> AnyaResult<Vec<(SeriesKey, f64)>> {
+        let stats = self.stats();
+        let outcome = |name: &str| SeriesKey::new("compact_blocks").with_label("outcome", name);
+        Ok(vec![
+            (
+                SeriesKey::new("compact_blocks_received"),
+                stats.received as f64,
+            ),
+            (outcome("immediate"), stats.immediate as f64),
+            (outcome("round_trip"), stats.round_trip as f64),
+            (outcome("fallback"), stats.fallback as f64),
+            (
+                SeriesKey::new("compact_txs_from_pool"),
+                stats.txs_from_pool as f64,
+            ),
+            (
+                SeriesKey::new("compact_txs_requested"),
+                stats.txs_requested as f64,
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorCode;
+    use ::bitcoin::absolute::LockTime;
+    use ::bitcoin::bip152::PrefilledTransaction;
+    use ::bitcoin::block::Version;
+    use ::bitcoin::hash_types::TxMerkleNode;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{
+        CompactTarget, Network, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Txid, Witness,
+    };
+
+    struct Pool(Vec<Transaction>);
+
+    impl TxPool for Pool {
+        fn transactions(&self) -> Vec<Transaction> {
+            self.0.clone()
+        }
+    }
+
+    /// Knows only the parent of the test blocks, on regtest
+    struct Headers;
+
+    impl HeaderIndex for Headers {
+        fn max_target_after(&self, parent: &BlockHash) -> Option<Target> {
+            (*parent == BlockHash::all_zeros()).then(|| pow_limit(Network::Regtest))
+        }
+    }
+
+    fn tx(vout: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), vout),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn mine(header: &mut Header) {
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+    }
+
+    fn block_at(time: u32) -> Block {
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time,
+                bits: CompactTarget::from_consensus(0x207f_ffff),
+                nonce: 0,
+            },
+            txdata: (0..5).map(tx).collect(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        mine(&mut block.header);
+        block
+    }
+
+    fn block() -> Block {
+        block_at(1_700_000_000)
+    }
+
+    /// Relay with peer 1 in high-bandwidth mode and peer 2 without compact
+    /// blocks, and the compact block announced to peer 1
+    fn announced(block: &Block) -> (CompactRelay, CmpctBlock) {
+        let relay = CompactRelay::new();
+        relay.peer_connected(1);
+        relay.peer_connected(2);
+        relay.on_sendcmpct(
+            1,
+            &SendCmpct {
+                send_compact: true,
+                version: 2,
+            },
+        );
+        let mut announcements = relay.announce(block).unwrap();
+        announcements.sort_by_key(|(peer, _)| *peer);
+        let [(1, NetworkMessage::CmpctBlock(message)), (2, NetworkMessage::Headers(headers))] =
+            <[_; 2]>::try_from(announcements).unwrap()
+        else {
+            panic!("unexpected announcements");
+        };
+        assert_eq!(headers, vec![block.header]);
+        (relay, message)
+    }
+
+    #[test]
+    fn test_reconstruction() {
+        let block = block();
+        let (relay, message) = announced(&block);
+        assert_eq!(message.compact_block.short_ids.len(), 4);
+
+        // Everything in the mempool
+        let pool = Pool(block.txdata[1..].to_vec());
+        let outcome = relay.on_cmpctblock(1, &message, &pool, &Headers).unwrap();
+        assert_eq!(outcome, Reconstruction::Complete(Box::new(block.clone())));
+
+        // Two missing, fetched with getblocktxn
+        let pool = Pool(vec![block.txdata[1].clone(), block.txdata[3].clone()]);
+        let Reconstruction::Incomplete(NetworkMessage::GetBlockTxn(request)) =
+            relay.on_cmpctblock(1, &message, &pool, &Headers).unwrap()
+        else {
+            panic!("expected getblocktxn");
+        };
+        assert_eq!(request.txs_request.indexes, vec![2, 4]);
+        let NetworkMessage::BlockTxn(response) = relay.serve_getblocktxn(&request, &block).unwrap()
+        else {
+            panic!("expected blocktxn");
+        };
+        let outcome = relay.on_blocktxn(1, &response).unwrap();
+        assert_eq!(outcome, Reconstruction::Complete(Box::new(block.clone())));
+        assert!(relay.on_blocktxn(1, &response).is_err());
+
+        // Wrong transactions fail the merkle check and fall back
+        let _ = relay.on_cmpctblock(1, &message, &pool, &Headers).unwrap();
+        let mut wrong = response;
+        wrong.transactions.transactions[0] = tx(99);
+        let outcome = relay.on_blocktxn(1, &wrong).unwrap();
+        assert_eq!(
+            outcome,
+            Reconstruction::Fallback(full_block(block.block_hash()))
+        );
+
+        assert_eq!(
+            relay.stats(),
+            CompactStats {
+                received: 3,
+                immediate: 1,
+                round_trip: 1,
+                fallback: 1,
+                txs_from_pool: 8,
+                txs_requested: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn test_malformed_prefill() {
+        let block = block();
+        let (relay, mut message) = announced(&block);
+        assert!(relay
+            .on_cmpctblock(1, &message, &Pool(Vec::new()), &Headers)
+            .is_ok());
+        message
+            .compact_block
+            .prefilled_txs
+            .push(PrefilledTransaction { idx: 9, tx: tx(7) });
+        assert!(relay
+            .on_cmpctblock(1, &message, &Pool(Vec::new()), &Headers)
+            .is_err());
+    }
+
+    #[test]
+    fn test_pending_blocks_are_bounded() {
+        let block = block();
+        let (relay, message) = announced(&block);
+        let empty = Pool(Vec::new());
+        let mut weak = message.clone();
+        weak.compact_block.header.bits = CompactTarget::from_consensus(0x1d00_ffff);
+        let err = relay.on_cmpctblock(1, &weak, &empty, &Headers).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        let mut orphan = message.clone();
+        orphan.compact_block.header.prev_blockhash = block.block_hash();
+        mine(&mut orphan.compact_block.header);
+        let err = relay
+            .on_cmpctblock(1, &orphan, &empty, &Headers)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert_eq!(relay.stats().received, 0);
+
+        // One peer flooding distinct blocks keeps only its latest
+        let mut last = None;
+        for time in 0..32 {
+            let flood = CmpctBlock {
+                compact_block: HeaderAndShortIds::from_block(&block_at(time), 0, 2, &[]).unwrap(),
+            };
+            let outcome = relay.on_cmpctblock(1, &flood, &empty, &Headers).unwrap();
+            assert!(matches!(outcome, Reconstruction::Incomplete(_)));
+            last = Some(flood.compact_block.header.block_hash());
+        }
+        assert_eq!(relay.state().pending.len(), 1);
+        assert_eq!(relay.state().pending[&1].header.block_hash(), last.unwrap());
+
+        // Across peers the total is capped
+        for peer in 2..=MAX_PENDING_BLOCKS as u64 {
+            let outcome = relay
+                .on_cmpctblock(peer, &message, &empty, &Headers)
+                .unwrap();
+            assert!(matches!(outcome, Reconstruction::Incomplete(_)));
+        }
+        let outcome = relay.on_cmpctblock(99, &message, &empty, &Headers).unwrap();
+        assert_eq!(
+            outcome,
+            Reconstruction::Fallback(full_block(block.block_hash()))
+        );
+        assert_eq!(relay.state().pending.len(), MAX_PENDING_BLOCKS);
+    }
+
+    #[test]
+    fn test_rejects_self_declared_easy_target() {
+        let checkpoint = block_at(1).header;
+        let mut child = block_at(2);
+        child.header.prev_blockhash = checkpoint.block_hash();
+        mine(&mut child.header);
+        let message = CmpctBlock {
+            compact_block: HeaderAndShortIds::from_block(&child, 0, 2, &[]).unwrap(),
+        };
+        let empty = Pool(Vec::new());
+        let relay = CompactRelay::new();
+
+        // Valid work for its own regtest bits, far easier than mainnet allows
+        let mainnet = HeaderChain::new(Network::Bitcoin, checkpoint, 0);
+        let err = relay
+            .on_cmpctblock(1, &message, &empty, &mainnet)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        assert!(relay.state().pending.is_empty());
+
+        let regtest = HeaderChain::new(Network::Regtest, checkpoint, 0);
+        let outcome = relay.on_cmpctblock(1, &message, &empty, &regtest).unwrap();
+        assert!(matches!(outcome, Reconstruction::Incomplete(_)));
+    }
+
+    #[test]
+    fn test_high_bandwidth_rotation() {
+        let relay = CompactRelay::new();
+        for peer in 1..=4 {
+            relay.peer_connected(peer);
+            relay.on_sendcmpct(
+                peer,
+                &SendCmpct {
+                    send_compact: false,
+                    version: 2,
+                },
+            );
+        }
+        relay.peer_connected(5);
+        assert!(relay.block_delivered(5).is_empty());
+        for peer in 1..=3 {
+            assert_eq!(
+                relay.block_delivered(peer),
+                vec![(peer, send_compact(true))]
+            );
+        }
+        assert!(relay.block_delivered(1).is_empty());
+        assert_eq!(
+            relay.block_delivered(4),
+            vec![(4, send_compact(true)), (2, send_compact(false))]
+        );
+        assert_eq!(relay.high_bandwidth_peers(), vec![3, 1, 4]);
+        relay.peer_disconnected(3);
+        assert_eq!(relay.high_bandwidth_peers(), vec![1, 4]);
+    }
+}
@@ -0,0 +1,380 @@
+//! Private broadcast of the node's own transactions (Dandelion-style)
+//!
+//! Announcing a wallet transaction to every peer at once lets an observer
+//! connected to many nodes find its origin: the first node to announce it.
+//! With [`DandelionConfig::enabled`], [`PrivateBroadcaster::broadcast`]
+//! instead hands the transaction on along a stem before it fluffs:
+//!
+//! - [`StemRoute::Peer`]: the transaction goes to one outbound peer only,
+//!   and spreads from there. The stem peer is drawn at random and kept for
+//!   a whole [`DandelionConfig::epoch`], as in Dandelion++, so repeated
+//!   broadcasts cannot be intersected to find this node.
+//! - [`StemRoute::Tor`]: the caller sends the transaction over a fresh Tor
+//!   circuit to a random peer, unlinking it from this node's address.
+//!
+//! While a transaction is on the stem it is embargoed: the node must not
+//! announce it or serve it to anyone else, see
+//! [`PrivateBroadcaster::is_embargoed`]. The embargo ends when another
+//! peer announces the transaction back, showing it has propagated, or when
+//! the embargo timer runs out, in which case the node fluffs it itself by
+//! announcing it to every peer. Bitcoin peers do not speak Dandelion, so
+//! the stem is a single hop.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use ::bitcoin::network::message::NetworkMessage;
+use ::bitcoin::network::message_blockdata::Inventory;
+use ::bitcoin::{Transaction, Txid, Wtxid};
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// How a transaction leaves this node during its stem phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StemRoute {
+    /// Through one outbound peer chosen per epoch
+    Peer,
+    /// Over a fresh Tor circuit to a random peer
+    Tor,
+}
+
+/// Private broadcast settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DandelionConfig {
+    /// Send own transactions along a stem first; when off they are
+    /// announced to every peer at once
+    pub enabled: bool,
+    /// Stem path
+    pub route: StemRoute,
+    /// How long one stem peer is used
+    pub epoch: Duration,
+    /// Shortest time before an unseen transaction is fluffed
+    pub embargo: Duration,
+    /// Random extra embargo, so the fluff time does not reveal the origin
+    pub embargo_jitter: Duration,
+}
+
+impl Default for DandelionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            route: StemRoute::Peer,
+            epoch: Duration::from_secs(10 * 60),
+            embargo: Duration::from_secs(30),
+            embargo_jitter: Duration::from_secs(30),
+        }
+    }
+}
+
+/// What the connection layer should do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastAction {
+    /// Send `message` to `peer`
+    Send {
+        /// Connected peer
+        peer: u64,
+        /// `tx` on the stem, `inv` when fluffing
+        message: NetworkMessage,
+    },
+    /// Open a new Tor circuit to a random peer, send `tx` and disconnect
+    SendOverTor(Box<Transaction>),
+}
+
+struct Pending {
+    wtxid: Wtxid,
+    tx: Transaction,
+    stem: Option<u64>,
+    embargo_until: Instant,
+}
+
+struct Stem {
+    peer: u64,
+    until: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    /// Connected peers and whether each is outbound
+    peers: BTreeMap<u64, bool>,
+    stem: Option<Stem>,
+    pending: HashMap<Txid, Pending>,
+}
+
+/// Stem-then-fluff broadcaster of the node's own transactions
+pub struct PrivateBroadcaster {
+    config: DandelionConfig,
+    state: Mutex<State>,
+}
+
+impl PrivateBroadcaster {
+    /// Broadcaster following `config`
+    pub fn new(config: DandelionConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Track a new connection
+    pub fn peer_connected(&self, peer: u64, outbound: bool) {
+        self.state().peers.insert(peer, outbound);
+    }
+
+    /// Forget a closed connection; transactions stemmed through it are
+    /// sent along a new stem
+    pub fn peer_disconnected(&self, peer: u64) -> Vec<BroadcastAction> {
+        self.peer_disconnected_at(peer, Instant::now())
+    }
+
+    fn peer_disconnected_at(&self, peer: u64, now: Instant) -> Vec<BroadcastAction> {
+        let mut state = self.state();
+        state.peers.remove(&peer);
+        if state.stem.as_ref().is_some_and(|s| s.peer == peer) {
+            state.stem = None;
+        }
+        let orphaned: Vec<Txid> = state
+            .pending
+            .iter()
+            .filter(|(_, p)| p.stem == Some(peer))
+            .map(|(txid, _)| *txid)
+            .collect();
+        let mut actions = Vec::new();
+        for txid in orphaned {
+            if let Some(pending) = state.pending.remove(&txid) {
+                actions.extend(self.stem(&mut state, pending.tx, now));
+            }
+        }
+        drop(state);
+        actions
+    }
+
+    /// Send the node's own transaction
+    pub fn broadcast(&self, tx: Transaction) -> Vec<BroadcastAction> {
+        self.broadcast_at(tx, Instant::now())
+    }
+
+    fn broadcast_at(&self, tx: Transaction, now: Instant) -> Vec<BroadcastAction> {
+        let mut state = self.state();
+        let actions = if self.config.enabled {
+            self.stem(&mut state, tx, now)
+        } else {
+            fluff(&state, tx.txid(), "disabled")
+        };
+        drop(state);
+        actions
+    }
+
+    fn stem(&self, state: &mut State, tx: Transaction, now: Instant) -> Vec<BroadcastAction> {
+        let txid = tx.txid();
+        let (stem, action) = match self.config.route {
+            StemRoute::Tor => (None, BroadcastAction::SendOverTor(Box::new(tx.clone()))),
+            StemRoute::Peer => {
+                let Some(peer) = self.stem_peer(state, now) else {
+                    warn!(%txid, "no outbound peer to stem through, fluffing");
+                    return fluff(state, txid, "no_stem_peer");
+                };
+                let message = NetworkMessage::Tx(tx.clone());
+                (Some(peer), BroadcastAction::Send { peer, message })
+            }
+        };
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=self.config.embargo_jitter);
+        state.pending.insert(
+            txid,
+            Pending {
+                wtxid: tx.wtxid(),
+                tx,
+                stem,
+                embargo_until: now + self.config.embargo + jitter,
+            },
+        );
+        metrics::increment_counter!("anya_dandelion_stem_total");
+        debug!(%txid, ?stem, "transaction sent on stem");
+        vec![action]
+    }
+
+    /// Stem peer of the current epoch, drawing a new one when the epoch is
+    /// over or the peer is gone
+    fn stem_peer(&self, state: &mut State, now: Instant) -> Option<u64> {
+        if let Some(stem) = &state.stem {
+            if stem.until > now && state.peers.contains_key(&stem.peer) {
+                return Some(stem.peer);
+            }
+        }
+        let peer = state
+            .peers
+            .iter()
+            .filter(|(_, outbound)| **outbound)
+            .map(|(peer, _)| *peer)
+            .choose(&mut rand::thread_rng())?;
+        state.stem = Some(Stem {
+            peer,
+            until: now + self.config.epoch,
+        });
+        Some(peer)
+    }
+
+    /// Note an `inv` from `peer`: a stemmed transaction announced by anyone
+    /// but its stem peer has propagated and leaves the embargo
+    pub fn on_inventory(&self, peer: u64, inventory: &[Inventory]) {
+        let mut state = self.state();
+        for item in inventory {
+            let txid = match item {
+                Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => Some(*txid),
+                Inventory::WTx(wtxid) => state
+                    .pending
+                    .iter()
+                    .find(|(_, p)| p.wtxid == *wtxid)
+                    .map(|(txid, _)| *txid),
+                _ => None,
+            };
+            let Some(txid) = txid else {
+                continue;
+            };
+            if state
+                .pending
+                .get(&txid)
+                .is_some_and(|p| p.stem != Some(peer))
+            {
+                state.pending.remove(&txid);
+                metrics::increment_counter!("anya_dandelion_diffused_total");
+                debug!(%txid, peer, "stemmed transaction seen from the network");
+            }
+        }
+        drop(state);
+    }
+
+    /// Fluff every transaction whose embargo ran out
+    pub fn poll(&self) -> Vec<BroadcastAction> {
+        self.poll_at(Instant::now())
+    }
+
+    fn poll_at(&self, now: Instant) -> Vec<BroadcastAction> {
+        let mut state = self.state();
+        let expired: Vec<Txid> = state
+            .pending
+            .iter()
+            .filter(|(_, p)| p.embargo_until <= now)
+            .map(|(txid, _)| *txid)
+            .collect();
+        let mut actions = Vec::new();
+        for txid in expired {
+            state.pending.remove(&txid);
+            actions.extend(fluff(&state, txid, "embargo"));
+        }
+        drop(state);
+        actions
+    }
+
+    /// Whether `txid` is on its stem: the node must neither announce it
+    /// nor answer `getdata` for it
+    pub fn is_embargoed(&self, txid: &Txid) -> bool {
+        self.state().pending.contains_key(txid)
+    }
+}
+
+/// Announce `txid` to every peer
+fn fluff(state: &State, txid: Txid, reason: &'static str) -> Vec<BroadcastAction> {
+    metrics::increment_counter!("anya_dandelion_fluff_total", "reason" => reason);
+    state
+        .peers
+        .keys()
+        .map(|&peer| BroadcastAction::Send {
+            peer,
+            message: NetworkMessage::Inv(vec![Inventory::Transaction(txid)]),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::index::tests::tx;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{OutPoint, ScriptBuf};
+
+    fn payment(vout: u32) -> Transaction {
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        tx(
+            &[OutPoint::new(Txid::all_zeros(), vout)],
+            &[(&script, 1_000)],
+        )
+    }
+
+    #[test]
+    fn test_stem_embargo_and_fluff() {
+        let config = DandelionConfig {
+            enabled: true,
+            ..DandelionConfig::default()
+        };
+        let max_embargo = config.embargo + config.embargo_jitter;
+        let broadcaster = PrivateBroadcaster::new(config);
+        for (peer, outbound) in [(1, true), (2, true), (3, false)] {
+            broadcaster.peer_connected(peer, outbound);
+        }
+        let now = Instant::now();
+
+        // Only one outbound peer sees the transaction, and it stays
+        // embargoed when that peer announces it back
+        let first = payment(0);
+        let actions = broadcaster.broadcast_at(first.clone(), now);
+        let [BroadcastAction::Send {
+            peer: stem,
+            message,
+        }] = actions.as_slice()
+        else {
+            panic!("expected a single stem send, got {:?}", actions);
+        };
+        assert!([1, 2].contains(stem));
+        assert_eq!(*message, NetworkMessage::Tx(first.clone()));
+        broadcaster.on_inventory(*stem, &[Inventory::Transaction(first.txid())]);
+        assert!(broadcaster.is_embargoed(&first.txid()));
+        broadcaster.on_inventory(3, &[Inventory::WTx(first.wtxid())]);
+        assert!(!broadcaster.is_embargoed(&first.txid()));
+
+        // The stem peer is kept for the epoch and replaced when it leaves
+        let second = payment(1);
+        let actions = broadcaster.broadcast_at(second.clone(), now);
+        assert!(matches!(actions[0], BroadcastAction::Send { peer, .. } if peer == *stem));
+        let other = if *stem == 1 { 2 } else { 1 };
+        let actions = broadcaster.peer_disconnected_at(*stem, now);
+        assert_eq!(
+            actions,
+            [BroadcastAction::Send {
+                peer: other,
+                message: NetworkMessage::Tx(second.clone()),
+            }]
+        );
+
+        // Unseen until the embargo ends, it is announced to everyone
+        assert!(broadcaster.poll_at(now + Duration::from_secs(1)).is_empty());
+        let actions = broadcaster.poll_at(now + max_embargo);
+        assert_eq!(actions.len(), 2);
+        assert!(actions.iter().all(|a| matches!(
+            a,
+            BroadcastAction::Send { message: NetworkMessage::Inv(inv), .. }
+                if inv == &[Inventory::Transaction(second.txid())]
+        )));
+        assert!(!broadcaster.is_embargoed(&second.txid()));
+
+        // Over Tor nothing is sent to connected peers
+        let tor = PrivateBroadcaster::new(DandelionConfig {
+            enabled: true,
+            route: StemRoute::Tor,
+            ..DandelionConfig::default()
+        });
+        assert_eq!(
+            tor.broadcast_at(first.clone(), now),
+            [BroadcastAction::SendOverTor(Box::new(first))]
+        );
+    }
+}
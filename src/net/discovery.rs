@@ -0,0 +1,413 @@
+//! Peer discovery from DNS seeds and fixed seeds
+//!
+//! [`Discovery::bootstrap`] queries every DNS seed of the network at once,
+//! each bounded by a timeout, and falls back to the fixed seeds when the
+//! seeds return too few addresses. Fixed seeds use the `host:port` per line
+//! format of Bitcoin Core's `contrib/seeds/nodes_*.txt` and are supplied by
+//! the operator through [`FixedSeeds::parse`].
+//!
+//! Names are resolved through a [`Resolver`]. [`SystemResolver`] uses the
+//! operating system. Tor-only nodes must not leak DNS queries, so they use
+//! [`TorResolver`], which resolves through the Tor SOCKS proxy, or set
+//! [`DiscoveryConfig::dns`] to `false` and bootstrap from fixed onion seeds
+//! only.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::bitcoin::Network;
+use async_trait::async_trait;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Address of a peer to connect to
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PeerAddress {
+    /// IPv4 or IPv6 address
+    Ip(SocketAddr),
+    /// Tor onion service, reachable only through a Tor proxy
+    Onion {
+        /// `<name>.onion`
+        host: String,
+        /// Port
+        port: u16,
+    },
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(addr) => write!(f, "{}", addr),
+            Self::Onion { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl FromStr for PeerAddress {
+    type Err = AnyaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self::Ip(addr));
+        }
+        let invalid = || AnyaError::invalid_input(format!("invalid peer address: {}", s));
+        let (host, port) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse().map_err(|_| invalid())?;
+        if host.ends_with(".onion") && host.len() > ".onion".len() {
+            Ok(Self::Onion {
+                host: host.to_ascii_lowercase(),
+                port,
+            })
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl TryFrom<String> for PeerAddress {
+    type Error = AnyaError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PeerAddress> for String {
+    fn from(addr: PeerAddress) -> Self {
+        addr.to_string()
+    }
+}
+
+/// P2P port peers of `network` listen on by default
+pub const fn default_port(network: Network) -> u16 {
+    match network {
+        Network::Testnet => 18333,
+        Network::Signet => 38333,
+        Network::Regtest => 18444,
+        _ => 8333,
+    }
+}
+
+/// DNS seeds of `network`, as listed in Bitcoin Core's chain parameters
+pub const fn dns_seeds(network: Network) -> &'static [&'static str] {
+    match network {
+        Network::Bitcoin => &[
+            "seed.bitcoin.sipa.be",
+            "dnsseed.bluematt.me",
+            "dnsseed.bitcoin.dashjr-list-of-p2p-nodes.us",
+            "seed.bitcoinstats.com",
+            "seed.bitcoin.jonasschnelli.ch",
+            "seed.btc.petertodd.net",
+            "seed.bitcoin.sprovoost.nl",
+            "dnsseed.emzy.de",
+            "seed.bitcoin.wiz.biz",
+        ],
+        Network::Testnet => &[
+            "testnet-seed.bitcoin.jonasschnelli.ch",
+            "seed.tbtc.petertodd.net",
+            "seed.testnet.bitcoin.sprovoost.nl",
+            "testnet-seed.bluematt.me",
+        ],
+        Network::Signet => &["seed.signet.bitcoin.sprovoost.nl"],
+        _ => &[],
+    }
+}
+
+/// Resolves seed host names to peer addresses
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// Addresses of `host`, each with `port`
+    async fn resolve(&self, host: &str, port: u16) -> AnyaResult<Vec<PeerAddress>>;
+}
+
+/// Resolution through the operating system
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> AnyaResult<Vec<PeerAddress>> {
+        let addrs = tokio::net::lookup_host((host, port)).await.map_err(|e| {
+            AnyaError::with_source(
+                ErrorCode::NetworkFailure,
+                format!("resolving {} failed", host),
+                e,
+            )
+        })?;
+        Ok(addrs.map(PeerAddress::Ip).collect())
+    }
+}
+
+/// Resolution through Tor's SOCKS5 `RESOLVE` extension, so no DNS query
+/// leaves the node. Tor answers with one address per query.
+pub struct TorResolver {
+    proxy: SocketAddr,
+}
+
+impl TorResolver {
+    /// Resolve through the SOCKS proxy at `proxy`, usually `127.0.0.1:9050`
+    pub const fn new(proxy: SocketAddr) -> Self {
+        Self { proxy }
+    }
+}
+
+/// Tor's SOCKS5 command for name resolution
+const SOCKS_RESOLVE: u8 = 0xF0;
+
+#[async_trait]
+impl Resolver for TorResolver {
+    async fn resolve(&self, host: &str, port: u16) -> AnyaResult<Vec<PeerAddress>> {
+        let failed = |what: &str| {
+            AnyaError::new(
+                ErrorCode::NetworkFailure,
+                format!("resolving {} through Tor: {}", host, what),
+            )
+        };
+        let name_len = u8::try_from(host.len()).map_err(|_| failed("name too long"))?;
+        let mut stream = TcpStream::connect(self.proxy).await?;
+        // Greeting without authentication
+        stream.write_all(&[5, 1, 0]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply != [5, 0] {
+            return Err(failed("proxy refused the greeting"));
+        }
+        let mut request = vec![5, SOCKS_RESOLVE, 0, 3, name_len];
+        request.extend_from_slice(host.as_bytes());
+        request.extend_from_slice(&0u16.to_be_bytes());
+        stream.write_all(&request).await?;
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        if head[1] != 0 {
+            return Err(failed(&format!("proxy replied with status {}", head[1])));
+        }
+        let ip = match head[3] {
+            1 => {
+                let mut octets = [0u8; 4];
+                stream.read_exact(&mut octets).await?;
+                IpAddr::from(octets)
+            }
+            4 => {
+                let mut octets = [0u8; 16];
+                stream.read_exact(&mut octets).await?;
+                IpAddr::from(octets)
+            }
+            _ => return Err(failed("unexpected address type")),
+        };
+        Ok(vec![PeerAddress::Ip(SocketAddr::new(ip, port))])
+    }
+}
+
+/// Fallback addresses per network
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedSeeds(HashMap<Network, Vec<PeerAddress>>);
+
+impl FixedSeeds {
+    /// Add the addresses listed in `text` for `network`: one `host:port`
+    /// per line, `#` starting a comment
+    pub fn parse(&mut self, network: Network, text: &str) -> AnyaResult<()> {
+        let mut addresses = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            addresses.push(
+                line.parse()
+                    .map_err(|e: AnyaError| e.context(format!("fixed seeds line {}", n + 1)))?,
+            );
+        }
+        self.0.entry(network).or_default().extend(addresses);
+        Ok(())
+    }
+
+    /// Addresses for `network`
+    pub fn get(&self, network: Network) -> &[PeerAddress] {
+        self.0.get(&network).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Bootstrap settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryConfig {
+    /// Whether to query DNS seeds at all
+    pub dns: bool,
+    /// Time each seed query may take
+    pub seed_timeout: Duration,
+    /// Fewer addresses than this from DNS seeds adds the fixed seeds
+    pub min_addresses: usize,
+    /// Fallback addresses per network
+    pub fixed_seeds: FixedSeeds,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            dns: true,
+            seed_timeout: Duration::from_secs(10),
+            min_addresses: 16,
+            fixed_seeds: FixedSeeds::default(),
+        }
+    }
+}
+
+/// Finds initial peers for a network
+pub struct Discovery {
+    config: DiscoveryConfig,
+    resolver: Arc<dyn Resolver>,
+}
+
+impl Discovery {
+    /// Bootstrap with `config`, resolving names through `resolver`
+    pub fn new(config: DiscoveryConfig, resolver: Arc<dyn Resolver>) -> Self {
+        Self { config, resolver }
+    }
+
+    /// Addresses from the given DNS seeds, skipping seeds that fail or time
+    /// out
+    pub async fn query_seeds(&self, seeds: &[&str], port: u16) -> BTreeSet<PeerAddress> {
+        let queries = seeds.iter().map(|seed| async move {
+            let result =
+                tokio::time::timeout(self.config.seed_timeout, self.resolver.resolve(seed, port))
+                    .await
+                    .map_err(AnyaError::from)
+                    .and_then(|r| r);
+            (*seed, result)
+        });
+        let mut found = BTreeSet::new();
+        for (seed, result) in futures::future::join_all(queries).await {
+            match result {
+                Ok(addrs) => {
+                    debug!(seed, count = addrs.len(), "DNS seed answered");
+                    found.extend(addrs);
+                }
+                Err(e) => warn!(seed, error = %e, "DNS seed query failed"),
+            }
+        }
+        found
+    }
+
+    /// Shuffled peer addresses for `network`, from DNS seeds and, if those
+    /// return too few, fixed seeds
+    pub async fn bootstrap(&self, network: Network) -> AnyaResult<Vec<PeerAddress>> {
+        let mut found = if self.config.dns {
+            self.query_seeds(dns_seeds(network), default_port(network))
+                .await
+        } else {
+            BTreeSet::new()
+        };
+        if found.len() < self.config.min_addresses {
+            found.extend(self.config.fixed_seeds.get(network).iter().cloned());
+        }
+        if found.is_empty() {
+            return Err(AnyaError::new(
+                ErrorCode::Unavailable,
+                format!("no bootstrap peers found for {}", network),
+            ));
+        }
+        let mut addresses: Vec<PeerAddress> = found.into_iter().collect();
+        addresses.shuffle(&mut rand::thread_rng());
+        Ok(addresses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    struct FakeResolver;
+
+    #[async_trait]
+    impl Resolver for FakeResolver {
+        async fn resolve(&self, host: &str, port: u16) -> AnyaResult<Vec<PeerAddress>> {
+            match host {
+                "seed.bitcoin.sipa.be" => Ok(vec![
+                    PeerAddress::Ip(SocketAddr::new([192, 0, 2, 1].into(), port)),
+                    PeerAddress::Ip(SocketAddr::new([192, 0, 2, 2].into(), port)),
+                ]),
+                "dnsseed.bluematt.me" => {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    Ok(vec![PeerAddress::Ip(SocketAddr::new(
+                        [192, 0, 2, 3].into(),
+                        port,
+                    ))])
+                }
+                _ => Err(AnyaError::new(ErrorCode::NetworkFailure, "NXDOMAIN")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_with_fallback() {
+        let mut fixed = FixedSeeds::default();
+        fixed
+            .parse(
+                Network::Bitcoin,
+                "# nodes_main.txt\n198.51.100.1:8333\n[2001:db8::1]:8333 # v6\n\
+                 abcdefghijklmnop.onion:8333\n",
+            )
+            .unwrap();
+        assert!(fixed.parse(Network::Bitcoin, "example.com:8333").is_err());
+        let config = DiscoveryConfig {
+            seed_timeout: Duration::from_millis(50),
+            min_addresses: 3,
+            fixed_seeds: fixed,
+            ..DiscoveryConfig::default()
+        };
+        let discovery = Discovery::new(config.clone(), Arc::new(FakeResolver));
+        let found = discovery.bootstrap(Network::Bitcoin).await.unwrap();
+        // The slow seed timed out; two from DNS are too few, so fixed seeds join
+        assert_eq!(found.len(), 5);
+        assert!(found.contains(&"192.0.2.1:8333".parse().unwrap()));
+        assert!(found.contains(&"abcdefghijklmnop.onion:8333".parse().unwrap()));
+        assert!(discovery.bootstrap(Network::Regtest).await.is_err());
+
+        // Tor-only: no DNS at all
+        let offline = Discovery::new(
+            DiscoveryConfig {
+                dns: false,
+                ..config
+            },
+            Arc::new(FakeResolver),
+        );
+        assert_eq!(offline.bootstrap(Network::Bitcoin).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_tor_resolve() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            socket.read_exact(&mut greeting).await.unwrap();
+            socket.write_all(&[5, 0]).await.unwrap();
+            let mut head = [0u8; 5];
+            socket.read_exact(&mut head).await.unwrap();
+            assert_eq!(&head[..4], &[5, SOCKS_RESOLVE, 0, 3]);
+            let mut name = vec![0u8; head[4] as usize + 2];
+            socket.read_exact(&mut name).await.unwrap();
+            assert_eq!(&name[..name.len() - 2], b"seed.bitcoin.sipa.be");
+            socket
+                .write_all(&[5, 0, 0, 1, 203, 0, 113, 5, 0, 0])
+                .await
+                .unwrap();
+        });
+        let found = TorResolver::new(proxy)
+            .resolve("seed.bitcoin.sipa.be", 8333)
+            .await
+            .unwrap();
+        assert_eq!(found, vec!["203.0.113.5:8333".parse().unwrap()]);
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,491 @@
+//! Wire framing and zero-copy message views
+//!
+//! A [`FrameDecoder`] owns one peer's receive buffer. Socket reads go
+//! straight into [`FrameDecoder::buffer_mut`], and [`FrameDecoder::next_frame`]
+//! cuts complete messages off the front as they arrive: the 24-byte header
+//! is checked as soon as it is in, the buffer is grown once to the declared
+//! payload size, and the finished payload is split off as a shared
+//! [`Bytes`] without copying.
+//!
+//! Payloads are then read through views instead of being decoded into owned
+//! types. [`BlockView`] finds each transaction's bytes inside a block by
+//! walking its length prefixes, so relaying or hashing a transaction never
+//! copies it and only the transactions actually needed are decoded;
+//! [`Frame::inventory`] yields `inv`/`getdata` entries lazily. Messages
+//! without a view are decoded with [`Frame::decode`].
+
+use std::ops::Range;
+
+use ::bitcoin::block::Header;
+use ::bitcoin::consensus::encode::{deserialize, serialize};
+use ::bitcoin::hashes::{sha256d, Hash};
+use ::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use ::bitcoin::network::message_blockdata::Inventory;
+use ::bitcoin::network::Magic;
+use ::bitcoin::{Block, BlockHash, Transaction};
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{AnyaError, AnyaResult};
+
+/// Length of a message header: magic, command, length, checksum
+pub const HEADER_LEN: usize = 24;
+/// Largest payload accepted, as in Bitcoin Core
+pub const MAX_PAYLOAD: usize = 4_000_000;
+/// Size of one inventory entry
+const INV_ENTRY_LEN: usize = 36;
+
+fn malformed(what: impl std::fmt::Display) -> AnyaError {
+    AnyaError::invalid_input(format!("malformed message: {}", what))
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = sha256d::Hash::hash(payload).to_byte_array();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// A complete message whose payload shares the receive buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    magic: Magic,
+    command: [u8; 12],
+    payload: Bytes,
+}
+
+impl Frame {
+    /// Frame `payload` as message `command` for the network of `magic`
+    pub fn new(magic: Magic, command: &str, payload: Bytes) -> AnyaResult<Self> {
+        if command.len() > 12 || !command.is_ascii() {
+            return Err(AnyaError::invalid_input(format!(
+                "invalid command {:?}",
+                command
+            )));
+        }
+        let mut padded = [0; 12];
+        padded[..command.len()].copy_from_slice(command.as_bytes());
+        Ok(Self {
+            magic,
+            command: padded,
+            payload,
+        })
+    }
+
+    /// Frame an owned message
+    pub fn from_message(message: &RawNetworkMessage) -> Self {
+        let mut wire = Bytes::from(serialize(message));
+        let header = wire.split_to(HEADER_LEN);
+        let mut command = [0; 12];
+        command.copy_from_slice(&header[4..16]);
+        Self {
+            magic: message.magic,
+            command,
+            payload: wire,
+        }
+    }
+
+    /// Network magic
+    pub const fn magic(&self) -> Magic {
+        self.magic
+    }
+
+    /// Command name, e.g. `block`
+    pub fn command(&self) -> &str {
+        let end = self.command.iter().position(|&b| b == 0).unwrap_or(12);
+        std::str::from_utf8(&self.command[..end]).unwrap_or_default()
+    }
+
+    /// Raw payload
+    pub const fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Header and payload as sent on the wire
+    pub fn encode(&self) -> Bytes {
+        let mut wire = BytesMut::with_capacity(HEADER_LEN + self.payload.len());
+        wire.put_slice(&self.magic.to_bytes());
+        wire.put_slice(&self.command);
+        wire.put_u32_le(u32::try_from(self.payload.len()).unwrap_or(u32::MAX));
+        wire.put_slice(&checksum(&self.payload));
+        wire.put_slice(&self.payload);
+        wire.freeze()
+    }
+
+    /// Decode into an owned message, copying the payload
+    pub fn decode(&self) -> AnyaResult<NetworkMessage> {
+        let message: RawNetworkMessage = deserialize(&self.encode()).map_err(malformed)?;
+        Ok(message.payload)
+    }
+
+    /// View of a `block` message
+    pub fn block(&self) -> AnyaResult<BlockView> {
+        self.expect("block")?;
+        BlockView::parse(self.payload.clone())
+    }
+
+    /// Entries of an `inv`, `getdata` or `notfound` message, decoded as
+    /// they are iterated
+    pub fn inventory(&self) -> AnyaResult<impl Iterator<Item = AnyaResult<Inventory>> + '_> {
+        if !matches!(self.command(), "inv" | "getdata" | "notfound") {
+            return Err(malformed(format!("{} is not an inventory", self.command())));
+        }
+        let mut reader = Reader::new(&self.payload);
+        let count = reader.count(INV_ENTRY_LEN)?;
+        let start = reader.pos;
+        if self.payload.len() != start + count * INV_ENTRY_LEN {
+            return Err(malformed("inventory length"));
+        }
+        Ok(self.payload[start..]
+            .chunks_exact(INV_ENTRY_LEN)
+            .map(|entry| deserialize(entry).map_err(malformed)))
+    }
+
+    fn expect(&self, command: &str) -> AnyaResult<()> {
+        if self.command() == command {
+            Ok(())
+        } else {
+            Err(malformed(format!(
+                "expected {}, got {}",
+                command,
+                self.command()
+            )))
+        }
+    }
+}
+
+/// Header of the frame being received
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    command: [u8; 12],
+    len: usize,
+    checksum: [u8; 4],
+}
+
+/// Incremental framer of one peer's byte stream
+#[derive(Debug)]
+pub struct FrameDecoder {
+    magic: Magic,
+    max_payload: usize,
+    buffer: BytesMut,
+    pending: Option<Pending>,
+}
+
+impl FrameDecoder {
+    /// Framer for a peer on the network of `magic`
+    pub fn new(magic: Magic) -> Self {
+        Self {
+            magic,
+            max_payload: MAX_PAYLOAD,
+            buffer: BytesMut::with_capacity(HEADER_LEN),
+            pending: None,
+        }
+    }
+
+    /// Refuse payloads larger than `max_payload` bytes
+    #[must_use]
+    pub const fn with_max_payload(mut self, max_payload: usize) -> Self {
+        self.max_payload = max_payload;
+        self
+    }
+
+    /// Buffer to read from the socket into
+    pub const fn buffer_mut(&mut self) -> &mut BytesMut {
+        &mut self.buffer
+    }
+
+    /// Append received bytes
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Bytes received but not yet returned in a frame
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() + self.pending.map_or(0, |_| HEADER_LEN)
+    }
+
+    /// Next complete frame, `None` until more bytes arrive.
+    ///
+    /// Fails with [`ErrorCode::InvalidInput`](crate::ErrorCode::InvalidInput)
+    /// on a wrong magic, an oversized payload or a bad checksum, after
+    /// which the peer should be scored for
+    /// [`Misbehavior::MalformedMessage`](super::peers::Misbehavior::MalformedMessage)
+    /// and disconnected.
+    pub fn next_frame(&mut self) -> AnyaResult<Option<Frame>> {
+        let pending = match self.pending {
+            Some(pending) => pending,
+            None => {
+                if self.buffer.len() < HEADER_LEN {
+                    return Ok(None);
+                }
+                let header = self.buffer.split_to(HEADER_LEN);
+                if header[..4] != self.magic.to_bytes() {
+                    return Err(malformed("wrong network magic"));
+                }
+                let len = u32::from_le_bytes([header[16], header[17], header[18], header[19]]);
+                let len = usize::try_from(len).unwrap_or(usize::MAX);
+                if len > self.max_payload {
+                    return Err(malformed(format!("payload of {} bytes", len)));
+                }
+                let mut command = [0; 12];
+                command.copy_from_slice(&header[4..16]);
+                let pending = Pending {
+                    command,
+                    len,
+                    checksum: [header[20], header[21], header[22], header[23]],
+                };
+                // Grow once so the rest of the payload lands contiguously
+                self.buffer.reserve(len.saturating_sub(self.buffer.len()));
+                self.pending = Some(pending);
+                pending
+            }
+        };
+        if self.buffer.len() < pending.len {
+            return Ok(None);
+        }
+        self.pending = None;
+        let payload = self.buffer.split_to(pending.len).freeze();
+        if checksum(&payload) != pending.checksum {
+            return Err(malformed("checksum mismatch"));
+        }
+        Ok(Some(Frame {
+            magic: self.magic,
+            command: pending.command,
+            payload,
+        }))
+    }
+}
+
+/// A block whose transactions are slices of the message payload
+#[derive(Debug, Clone)]
+pub struct BlockView {
+    header: Header,
+    payload: Bytes,
+    txs: Vec<Range<usize>>,
+}
+
+impl BlockView {
+    /// Index the transactions of serialized block `payload`
+    pub fn parse(payload: Bytes) -> AnyaResult<Self> {
+        if payload.len() < 80 {
+            return Err(malformed("block shorter than its header"));
+        }
+        let header: Header = deserialize(&payload[..80]).map_err(malformed)?;
+        let mut reader = Reader::new(&payload);
+        reader.pos = 80;
+        // The smallest transaction is 60 bytes
+        let count = reader.count(60)?;
+        let mut txs = Vec::with_capacity(count);
+        for _ in 0..count {
+            let start = reader.pos;
+            reader.skip_transaction()?;
+            txs.push(start..reader.pos);
+        }
+        if reader.pos != payload.len() {
+            return Err(malformed("trailing bytes after the last transaction"));
+        }
+        Ok(Self {
+            header,
+            payload,
+            txs,
+        })
+    }
+
+    /// Block header
+    pub const fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Block hash
+    pub fn block_hash(&self) -> BlockHash {
+        self.header.block_hash()
+    }
+
+    /// Number of transactions
+    pub fn tx_count(&self) -> usize {
+        self.txs.len()
+    }
+
+    /// Serialized transaction `index`, sharing the payload
+    pub fn raw_transaction(&self, index: usize) -> Option<Bytes> {
+        self.txs
+            .get(index)
+            .map(|range| self.payload.slice(range.clone()))
+    }
+
+    /// Every serialized transaction, in block order
+    pub fn raw_transactions(&self) -> impl Iterator<Item = Bytes> + '_ {
+        self.txs
+            .iter()
+            .map(|range| self.payload.slice(range.clone()))
+    }
+
+    /// Decode transaction `index`
+    pub fn transaction(&self, index: usize) -> AnyaResult<Option<Transaction>> {
+        self.txs
+            .get(index)
+            .map(|range| deserialize(&self.payload[range.clone()]).map_err(malformed))
+            .transpose()
+    }
+
+    /// Decode the whole block
+    pub fn to_block(&self) -> AnyaResult<Block> {
+        deserialize(&self.payload).map_err(malformed)
+    }
+}
+
+/// Cursor over consensus-encoded bytes that only skips
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn byte(&mut self) -> AnyaResult<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| malformed("truncated"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn skip(&mut self, len: usize) -> AnyaResult<()> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| malformed("truncated"))?;
+        self.pos = end;
+        Ok(())
+    }
+
+    fn varint(&mut self) -> AnyaResult<u64> {
+        let width = match self.byte()? {
+            0xfd => 2,
+            0xfe => 4,
+            0xff => 8,
+            n => return Ok(u64::from(n)),
+        };
+        let start = self.pos;
+        self.skip(width)?;
+        let mut value = [0; 8];
+        value[..width].copy_from_slice(&self.data[start..self.pos]);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    /// A length prefix, bounded by what the remaining bytes can hold when
+    /// every item takes at least `min_item` bytes
+    fn len(&mut self, min_item: usize) -> AnyaResult<usize> {
+        let len = usize::try_from(self.varint()?).unwrap_or(usize::MAX);
+        if len.saturating_mul(min_item.max(1)) > self.data.len() - self.pos {
+            return Err(malformed("length prefix exceeds the message"));
+        }
+        Ok(len)
+    }
+
+    fn count(&mut self, min_item: usize) -> AnyaResult<usize> {
+        self.len(min_item)
+    }
+
+    fn skip_bytes(&mut self) -> AnyaResult<()> {
+        let len = self.len(1)?;
+        self.skip(len)
+    }
+
+    fn skip_transaction(&mut self) -> AnyaResult<()> {
+        self.skip(4)?;
+        let mut inputs = self.count(41)?;
+        let segwit = inputs == 0;
+        if segwit {
+            if self.byte()? != 1 {
+                return Err(malformed("unknown segwit flag"));
+            }
+            inputs = self.count(41)?;
+        }
+        for _ in 0..inputs {
+            self.skip(36)?;
+            self.skip_bytes()?;
+            self.skip(4)?;
+        }
+        for _ in 0..self.count(9)? {
+            self.skip(8)?;
+            self.skip_bytes()?;
+        }
+        if segwit {
+            for _ in 0..inputs {
+                for _ in 0..self.count(1)? {
+                    self.skip_bytes()?;
+                }
+            }
+        }
+        self.skip(4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::index::tests::{block, tx};
+    use ::bitcoin::{Network, OutPoint, ScriptBuf, Witness};
+
+    #[test]
+    fn test_frames_and_views_over_chunked_stream() {
+        let magic = Network::Regtest.magic();
+        let miner = ScriptBuf::from_bytes(vec![0x51]);
+        let genesis = block(BlockHash::all_zeros(), 0, &miner, vec![]);
+        let mut spend = tx(
+            &[OutPoint::new(genesis.txdata[0].txid(), 0)],
+            &[(&miner, 1)],
+        );
+        spend.input[0].witness = Witness::from_slice(&[vec![7; 72], vec![2; 33]]);
+        let next = block(genesis.block_hash(), 1, &miner, vec![spend.clone()]);
+        let messages = [
+            RawNetworkMessage {
+                magic,
+                payload: NetworkMessage::Block(next.clone()),
+            },
+            RawNetworkMessage {
+                magic,
+                payload: NetworkMessage::Inv(vec![Inventory::WitnessBlock(next.block_hash())]),
+            },
+        ];
+        let wire: Vec<u8> = messages.iter().flat_map(serialize).collect();
+
+        let mut decoder = FrameDecoder::new(magic);
+        let mut frames = Vec::new();
+        for chunk in wire.chunks(7) {
+            decoder.extend(chunk);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(decoder.buffered(), 0);
+        assert_eq!(frames[0], Frame::from_message(&messages[0]));
+        assert_eq!(frames[1].encode(), serialize(&messages[1]));
+
+        let view = frames[0].block().unwrap();
+        assert_eq!((view.block_hash(), view.tx_count()), (next.block_hash(), 2));
+        assert_eq!(view.raw_transaction(1).unwrap(), serialize(&spend));
+        assert_eq!(view.transaction(1).unwrap().unwrap(), spend);
+        assert_eq!(view.to_block().unwrap(), next);
+        let inventory: Vec<_> = frames[1]
+            .inventory()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(inventory, [Inventory::WitnessBlock(next.block_hash())]);
+        assert_eq!(frames[1].decode().unwrap(), messages[1].payload);
+
+        // A corrupted payload and a foreign network are refused
+        let mut corrupt = serialize(&messages[1]);
+        *corrupt.last_mut().unwrap() ^= 1;
+        let mut decoder = FrameDecoder::new(magic);
+        decoder.extend(&corrupt);
+        assert!(decoder.next_frame().is_err());
+        let mut decoder = FrameDecoder::new(Network::Bitcoin.magic());
+        decoder.extend(&wire);
+        assert!(decoder.next_frame().is_err());
+    }
+}
@@ -0,0 +1,15 @@
+//! Bitcoin peer-to-peer networking
+//!
+//! - [`peers`]: misbehavior scoring, discouragement, and persistent bans
+//! - [`bandwidth`]: per-peer and global rate limits with relay priority
+//! - [`compact`]: compact block relay (BIP-152)
+//! - [`dandelion`]: stem-then-fluff broadcast of own transactions
+//! - [`discovery`]: bootstrap from DNS seeds and fixed seeds
+//! - [`messages`]: incremental framing and zero-copy message views
+
+pub mod bandwidth;
+pub mod compact;
+pub mod dandelion;
+pub mod discovery;
+pub mod messages;
+pub mod peers;
@@ -0,0 +1,533 @@
+//! Peer misbehavior scoring and bans
+//!
+//! Every protocol violation a peer commits adds the [`Misbehavior`]'s score
+//! to the peer's total. A peer reaching [`BanConfig::threshold`] is
+//! disconnected and its address discouraged: for
+//! [`BanConfig::discourage_duration`] new connections from it are only
+//! accepted while slots are free and it is evicted first. Discouragement is
+//! automatic and kept in memory only, since an attacker can get honest
+//! addresses discouraged by relaying through them.
+//!
+//! Bans are set by operators, cover a [`Subnet`], refuse every connection,
+//! and are persisted in their own storage namespace until they expire.
+//! Connected peers inside a new ban are announced on
+//! [`BanManager::subscribe_disconnects`] for the connection manager to drop.
+//! Peers on a [`BanConfig::noban`] subnet are scored but never
+//! discouraged.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "peer_bans";
+const BAN_PREFIX: &str = "ban/";
+/// Disconnect requests buffered per subscriber
+const DISCONNECT_BUFFER: usize = 64;
+
+/// A protocol violation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Misbehavior {
+    /// Block failing consensus validation
+    InvalidBlock,
+    /// Header that does not connect or lacks proof of work
+    InvalidHeader,
+    /// Transaction failing consensus rules
+    InvalidTransaction,
+    /// Message that cannot be decoded or exceeds size limits
+    MalformedMessage,
+    /// Data sent without being requested
+    Unsolicited,
+    /// Messages beyond the rate the protocol allows
+    Spam,
+    /// Not delivering requested data in time
+    Stalling,
+    /// Any other violation with its own score
+    Other(u32),
+}
+
+impl Misbehavior {
+    /// Score added to the peer's total
+    pub const fn score(&self) -> u32 {
+        match self {
+            Self::InvalidBlock | Self::InvalidHeader | Self::MalformedMessage => 100,
+            Self::Stalling => 50,
+            Self::Spam | Self::Unsolicited => 20,
+            Self::InvalidTransaction => 10,
+            Self::Other(score) => *score,
+        }
+    }
+}
+
+/// An address range in CIDR notation, e.g. `203.0.113.0/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    /// The range of addresses sharing the first `prefix` bits of `addr`
+    pub fn new(addr: IpAddr, prefix: u8) -> AnyaResult<Self> {
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > bits {
+            return Err(AnyaError::invalid_input(format!(
+                "prefix /{} is longer than {} bits",
+                prefix, bits
+            )));
+        }
+        Ok(Self {
+            network: mask(addr, prefix),
+            prefix,
+        })
+    }
+
+    /// The single address `addr`
+    pub const fn single(addr: IpAddr) -> Self {
+        Self {
+            network: addr,
+            prefix: if addr.is_ipv4() { 32 } else { 128 },
+        }
+    }
+
+    /// Whether `addr` is in the range
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match (self.network, addr) {
+            (IpAddr::V6(_), IpAddr::V4(v4)) => IpAddr::V6(v4.to_ipv6_mapped()),
+            (IpAddr::V4(_), IpAddr::V6(v6)) => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => return false,
+            },
+            _ => addr,
+        };
+        mask(addr, self.prefix) == self.network
+    }
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(v4);
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4((bits & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(v6);
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6((bits & mask).into())
+        }
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = AnyaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AnyaError::invalid_input(format!("invalid subnet: {}", s));
+        match s.split_once('/') {
+            Some((addr, prefix)) => Self::new(
+                addr.parse().map_err(|_| invalid())?,
+                prefix.parse().map_err(|_| invalid())?,
+            ),
+            None => Ok(Self::single(s.parse().map_err(|_| invalid())?)),
+        }
+    }
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = AnyaError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Subnet> for String {
+    fn from(subnet: Subnet) -> Self {
+        subnet.to_string()
+    }
+}
+
+/// Scoring and ban settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanConfig {
+    /// Score at which a peer is disconnected and discouraged
+    pub threshold: u32,
+    /// How long a misbehaving peer's address stays discouraged
+    pub discourage_duration: Duration,
+    /// Ban length when a manual ban gives none
+    pub default_ban_duration: Duration,
+    /// Subnets never discouraged, e.g. the operator's own nodes
+    pub noban: Vec<Subnet>,
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 100,
+            discourage_duration: Duration::from_secs(24 * 60 * 60),
+            default_ban_duration: Duration::from_secs(24 * 60 * 60),
+            noban: Vec::new(),
+        }
+    }
+}
+
+/// A persisted ban
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanEntry {
+    /// Banned range
+    pub subnet: Subnet,
+    /// Unix seconds the ban was set
+    pub created_at: u64,
+    /// Unix seconds the ban ends
+    pub until: u64,
+    /// Operator's reason
+    pub reason: String,
+}
+
+/// What the caller should do after a violation was reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Keep the connection
+    Keep,
+    /// Disconnect the peer
+    Disconnect,
+}
+
+/// Misbehavior score of a connected peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerScore {
+    /// Connection id
+    pub peer: u64,
+    /// Remote address
+    pub addr: IpAddr,
+    /// Accumulated score
+    pub score: u32,
+}
+
+#[derive(Default)]
+struct State {
+    peers: HashMap<u64, PeerScore>,
+    /// Discouraged addresses and when discouragement ends, in Unix seconds
+    discouraged: HashMap<IpAddr, u64>,
+    bans: HashMap<Subnet, BanEntry>,
+}
+
+/// Tracks misbehavior, discouragement, and bans
+pub struct BanManager {
+    config: BanConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    state: Mutex<State>,
+    disconnects: broadcast::Sender<u64>,
+}
+
+impl BanManager {
+    /// Open the manager, loading unexpired bans from `storage`
+    pub async fn open(config: BanConfig, storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let now = unix_now();
+        let mut bans = HashMap::new();
+        for (key, value) in storage.scan_prefix(&ns, BAN_PREFIX).await? {
+            let entry: BanEntry = serde_json::from_slice(&value)?;
+            if entry.until > now {
+                bans.insert(entry.subnet, entry);
+            } else {
+                storage.delete(&ns, &key).await?;
+            }
+        }
+        Ok(Self {
+            config,
+            storage,
+            ns,
+            state: Mutex::new(State {
+                bans,
+                ..State::default()
+            }),
+            disconnects: broadcast::channel(DISCONNECT_BUFFER).0,
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Ids of connected peers that a new ban covers
+    pub fn subscribe_disconnects(&self) -> broadcast::Receiver<u64> {
+        self.disconnects.subscribe()
+    }
+
+    /// Start scoring a new connection
+    pub fn peer_connected(&self, peer: u64, addr: IpAddr) {
+        self.state().peers.insert(
+            peer,
+            PeerScore {
+                peer,
+                addr,
+                score: 0,
+            },
+        );
+    }
+
+    /// Forget a closed connection's score
+    pub fn peer_disconnected(&self, peer: u64) {
+        self.state().peers.remove(&peer);
+    }
+
+    /// Scores of every connected peer
+    pub fn scores(&self) -> Vec<PeerScore> {
+        let mut scores: Vec<PeerScore> = self.state().peers.values().cloned().collect();
+        scores.sort_by_key(|s| s.peer);
+        scores
+    }
+
+    /// Score a violation by `peer`, discouraging its address once the
+    /// threshold is reached
+    pub fn misbehaving(&self, peer: u64, what: Misbehavior, detail: &str) -> Verdict {
+        let mut state = self.state();
+        let Some(entry) = state.peers.get_mut(&peer) else {
+            return Verdict::Keep;
+        };
+        entry.score = entry.score.saturating_add(what.score());
+        let (addr, score) = (entry.addr, entry.score);
+        warn!(peer, %addr, ?what, score, detail, "peer misbehaving");
+        if score < self.config.threshold {
+            return Verdict::Keep;
+        }
+        if self.config.noban.iter().any(|s| s.contains(addr)) {
+            return Verdict::Keep;
+        }
+        let until = unix_now() + self.config.discourage_duration.as_secs();
+        state.discouraged.insert(addr, until);
+        drop(state);
+        info!(peer, %addr, "discouraging peer address");
+        Verdict::Disconnect
+    }
+
+    /// Whether `addr` is covered by an unexpired ban
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        let now = unix_now();
+        self.state()
+            .bans
+            .values()
+            .any(|b| b.until > now && b.subnet.contains(addr))
+    }
+
+    /// Whether `addr` was recently discouraged for misbehaving
+    pub fn is_discouraged(&self, addr: IpAddr) -> bool {
+        let now = unix_now();
+        self.state()
+            .discouraged
+            .get(&addr)
+            .is_some_and(|until| *until > now)
+    }
+
+    /// Whether to accept an inbound connection from `addr`; discouraged
+    /// addresses only get slots nobody else wants
+    pub fn should_accept(&self, addr: IpAddr, slots_free: bool) -> bool {
+        !self.is_banned(addr) && (slots_free || !self.is_discouraged(addr))
+    }
+
+    /// Ban `subnet` for `duration`, or the configured default, replacing
+    /// any ban of the same subnet. Connected peers in the subnet are
+    /// announced for disconnection and returned.
+    pub async fn ban(
+        &self,
+        subnet: Subnet,
+        duration: Option<Duration>,
+        reason: &str,
+    ) -> AnyaResult<Vec<u64>> {
+        let now = unix_now();
+        let entry = BanEntry {
+            subnet,
+            created_at: now,
+            until: now
+                + duration
+                    .unwrap_or(self.config.default_ban_duration)
+                    .as_secs(),
+            reason: reason.to_string(),
+        };
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", BAN_PREFIX, subnet),
+                &serde_json::to_vec(&entry)?,
+            )
+            .await?;
+        let mut state = self.state();
+        state.bans.insert(subnet, entry);
+        let mut affected: Vec<u64> = state
+            .peers
+            .values()
+            .filter(|p| subnet.contains(p.addr))
+            .map(|p| p.peer)
+            .collect();
+        drop(state);
+        affected.sort_unstable();
+        for peer in &affected {
+            // Nobody listening means no connection manager is running
+            let _ = self.disconnects.send(*peer);
+        }
+        info!(%subnet, reason, "banned subnet");
+        Ok(affected)
+    }
+
+    /// Lift the ban of exactly `subnet`, returning whether there was one
+    pub async fn unban(&self, subnet: Subnet) -> AnyaResult<bool> {
+        self.storage
+            .delete(&self.ns, &format!("{}{}", BAN_PREFIX, subnet))
+            .await?;
+        let removed = self.state().bans.remove(&subnet).is_some();
+        if removed {
+            info!(%subnet, "unbanned subnet");
+        }
+        Ok(removed)
+    }
+
+    /// Unexpired bans, soonest ending first
+    pub fn bans(&self) -> Vec<BanEntry> {
+        let now = unix_now();
+        let mut bans: Vec<BanEntry> = self
+            .state()
+            .bans
+            .values()
+            .filter(|b| b.until > now)
+            .cloned()
+            .collect();
+        bans.sort_by_key(|b| (b.until, b.subnet.to_string()));
+        bans
+    }
+
+    /// Drop expired bans and discouragements, returning how many bans ended
+    pub async fn sweep(&self) -> AnyaResult<usize> {
+        let now = unix_now();
+        let expired: Vec<Subnet> = {
+            let mut state = self.state();
+            state.discouraged.retain(|_, until| *until > now);
+            let expired = state
+                .bans
+                .values()
+                .filter(|b| b.until <= now)
+                .map(|b| b.subnet)
+                .collect();
+            drop(state);
+            expired
+        };
+        for subnet in &expired {
+            self.unban(*subnet).await?;
+        }
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_subnets() {
+        let net: Subnet = "203.0.113.77/24".parse().unwrap();
+        assert_eq!(net.to_string(), "203.0.113.0/24");
+        assert!(net.contains(ip("203.0.113.5")));
+        assert!(net.contains(ip("::ffff:203.0.113.5")));
+        assert!(!net.contains(ip("203.0.114.5")));
+        let v6: Subnet = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:1::1")));
+        assert!(!v6.contains(ip("10.0.0.1")));
+        assert!("0.0.0.0/0"
+            .parse::<Subnet>()
+            .unwrap()
+            .contains(ip("8.8.8.8")));
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert_eq!(
+            serde_json::to_string(&Subnet::single(ip("10.0.0.1"))).unwrap(),
+            "\"10.0.0.1/32\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scores_discourage_and_persist_bans() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let config = BanConfig {
+            noban: vec!["192.168.0.0/16".parse().unwrap()],
+            ..BanConfig::default()
+        };
+        let bans = BanManager::open(config.clone(), storage.clone())
+            .await
+            .unwrap();
+        bans.peer_connected(1, ip("198.51.100.7"));
+        bans.peer_connected(2, ip("192.168.1.2"));
+        bans.peer_connected(3, ip("203.0.113.9"));
+
+        assert_eq!(
+            bans.misbehaving(1, Misbehavior::Stalling, "no block"),
+            Verdict::Keep
+        );
+        assert_eq!(
+            bans.misbehaving(1, Misbehavior::Stalling, "no block"),
+            Verdict::Disconnect
+        );
+        assert!(bans.is_discouraged(ip("198.51.100.7")));
+        assert!(bans.should_accept(ip("198.51.100.7"), true));
+        assert!(!bans.should_accept(ip("198.51.100.7"), false));
+        assert_eq!(
+            bans.misbehaving(2, Misbehavior::InvalidBlock, "bad merkle root"),
+            Verdict::Keep
+        );
+        assert!(!bans.is_discouraged(ip("192.168.1.2")));
+        assert_eq!(bans.scores()[1].score, 100);
+
+        let mut disconnects = bans.subscribe_disconnects();
+        let affected = bans
+            .ban("203.0.113.0/24".parse().unwrap(), None, "spam")
+            .await
+            .unwrap();
+        assert_eq!(affected, vec![3]);
+        assert_eq!(disconnects.try_recv().unwrap(), 3);
+        bans.ban("10.0.0.1".parse().unwrap(), Some(Duration::ZERO), "expired")
+            .await
+            .unwrap();
+        assert!(bans.is_banned(ip("203.0.113.200")));
+        assert!(!bans.should_accept(ip("203.0.113.200"), true));
+        assert!(!bans.is_banned(ip("10.0.0.1")));
+
+        // Bans survive a restart, discouragement does not
+        let reopened = BanManager::open(config, storage).await.unwrap();
+        assert_eq!(reopened.bans().len(), 1);
+        assert_eq!(reopened.bans()[0].reason, "spam");
+        assert!(reopened.is_banned(ip("203.0.113.1")));
+        assert!(!reopened.is_discouraged(ip("198.51.100.7")));
+        assert!(reopened
+            .unban("203.0.113.0/24".parse().unwrap())
+            .await
+            .unwrap());
+        assert!(!reopened.is_banned(ip("203.0.113.1")));
+        assert_eq!(bans.sweep().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,341 @@
+//! Nostr protocol types and an embeddable relay
+//!
+//! [`Event`], [`Filter`], and the client/relay messages follow NIP-01. The
+//! [`relay::Relay`] stores events in a storage namespace, serves
+//! subscriptions, honours NIP-09 deletion requests, and describes itself
+//! with a NIP-11 information document, so operators can run a private relay
+//! for notification and agent traffic. The websocket server in `server` is
+//! behind the `nostr-relay` feature.
+
+use std::collections::BTreeMap;
+
+use ::bitcoin::secp256k1::schnorr::Signature;
+use ::bitcoin::secp256k1::{KeyPair, Message, Secp256k1, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::utils::encoding::{from_hex, sha256, to_hex};
+use crate::{AnyaError, AnyaResult};
+
+pub mod relay;
+#[cfg(feature = "nostr-relay")]
+pub mod server;
+
+/// NIP-09 deletion request kind
+pub const DELETION_KIND: u16 = 5;
+
+/// A signed Nostr event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Hex SHA-256 of the serialized event
+    pub id: String,
+    /// Hex x-only public key of the author
+    pub pubkey: String,
+    /// Unix creation time
+    pub created_at: u64,
+    /// Event kind
+    pub kind: u16,
+    /// Tags, each a name followed by values
+    pub tags: Vec<Vec<String>>,
+    /// Arbitrary content
+    pub content: String,
+    /// Hex BIP-340 signature over the id
+    pub sig: String,
+}
+
+impl Event {
+    /// Build and sign an event
+    pub fn sign(
+        keys: &KeyPair,
+        kind: u16,
+        tags: Vec<Vec<String>>,
+        content: impl Into<String>,
+        created_at: u64,
+    ) -> AnyaResult<Self> {
+        let mut event = Self {
+            id: String::new(),
+            pubkey: to_hex(&keys.x_only_public_key().0.serialize()),
+            created_at,
+            kind,
+            tags,
+            content: content.into(),
+            sig: String::new(),
+        };
+        let id = event.compute_id()?;
+        let sig = Secp256k1::signing_only().sign_schnorr_no_aux_rand(&digest(&id)?, keys);
+        event.id = to_hex(&id);
+        event.sig = to_hex(sig.as_ref());
+        Ok(event)
+    }
+
+    /// Check that the id matches the contents and the signature is valid
+    pub fn verify(&self) -> AnyaResult<()> {
+        let id = self.compute_id()?;
+        if to_hex(&id) != self.id {
+            return Err(AnyaError::invalid_input(
+                "event id does not match its contents",
+            ));
+        }
+        let pubkey = XOnlyPublicKey::from_slice(&from_hex(&self.pubkey)?)
+            .map_err(|_| AnyaError::invalid_input("invalid event pubkey"))?;
+        let sig = Signature::from_slice(&from_hex(&self.sig)?)
+            .map_err(|_| AnyaError::invalid_input("invalid event signature encoding"))?;
+        Secp256k1::verification_only()
+            .verify_schnorr(&sig, &digest(&id)?, &pubkey)
+            .map_err(|_| AnyaError::invalid_input("invalid event signature"))
+    }
+
+    /// Values of the first element of every tag named `name`
+    pub fn tag_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.tags
+            .iter()
+            .filter(move |t| t.first().is_some_and(|n| n == name))
+            .filter_map(|t| t.get(1).map(String::as_str))
+    }
+
+    /// Ephemeral events are relayed but never stored
+    pub const fn is_ephemeral(&self) -> bool {
+        self.kind >= 20_000 && self.kind < 30_000
+    }
+
+    /// Replaceable events keep only the latest per author and kind
+    pub const fn is_replaceable(&self) -> bool {
+        matches!(self.kind, 0 | 3) || (self.kind >= 10_000 && self.kind < 20_000)
+    }
+
+    /// Addressable events keep only the latest per author, kind, and `d` tag
+    pub const fn is_addressable(&self) -> bool {
+        self.kind >= 30_000 && self.kind < 40_000
+    }
+
+    /// Identifier of the `d` tag, empty when absent
+    pub fn d_tag(&self) -> &str {
+        self.tag_values("d").next().unwrap_or_default()
+    }
+
+    fn compute_id(&self) -> AnyaResult<[u8; 32]> {
+        let serialized = serde_json::to_string(&(
+            0,
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        ))?;
+        Ok(sha256(serialized.as_bytes()))
+    }
+}
+
+fn digest(id: &[u8; 32]) -> AnyaResult<Message> {
+    Message::from_slice(id).map_err(|_| AnyaError::invalid_input("invalid event id"))
+}
+
+/// Subscription filter; all given conditions must hold
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Filter {
+    /// Event ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<String>>,
+    /// Author pubkeys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authors: Option<Vec<String>>,
+    /// Event kinds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<u16>>,
+    /// Oldest creation time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    /// Newest creation time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    /// Maximum stored events returned before EOSE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Tag conditions keyed `#<letter>`, e.g. `#p`
+    #[serde(flatten)]
+    pub tags: BTreeMap<String, Vec<String>>,
+}
+
+impl Filter {
+    /// Whether `event` satisfies the filter
+    pub fn matches(&self, event: &Event) -> bool {
+        // An absent condition iterates as empty and so always holds
+        let listed = |list: &Option<Vec<String>>, value: &str| {
+            list.iter().all(|l| l.iter().any(|v| v == value))
+        };
+        listed(&self.ids, &event.id)
+            && listed(&self.authors, &event.pubkey)
+            && self.kinds.iter().all(|k| k.contains(&event.kind))
+            && self.since.iter().all(|s| event.created_at >= *s)
+            && self.until.iter().all(|u| event.created_at <= *u)
+            && self.tags.iter().all(|(key, values)| {
+                key.strip_prefix('#').iter().all(|name| {
+                    event
+                        .tag_values(name)
+                        .any(|v| values.iter().any(|w| w == v))
+                })
+            })
+    }
+}
+
+/// Message sent by a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    /// Publish an event
+    Event(Event),
+    /// Open or replace a subscription
+    Req {
+        /// Subscription id chosen by the client
+        subscription: String,
+        /// Events matching any filter are sent
+        filters: Vec<Filter>,
+    },
+    /// Close a subscription
+    Close(String),
+}
+
+impl ClientMessage {
+    /// Parse a client message from its JSON array form
+    pub fn parse(text: &str) -> AnyaResult<Self> {
+        let invalid = |what: &str| AnyaError::invalid_input(format!("invalid message: {}", what));
+        let Value::Array(mut parts) = serde_json::from_str(text)? else {
+            return Err(invalid("expected a JSON array"));
+        };
+        if parts.is_empty() {
+            return Err(invalid("empty array"));
+        }
+        let rest = parts.split_off(1);
+        let subscription = |rest: &[Value]| {
+            rest.first()
+                .and_then(Value::as_str)
+                .filter(|s| !s.is_empty() && s.len() <= 64)
+                .map(str::to_string)
+                .ok_or_else(|| invalid("subscription id must be a string of 1 to 64 chars"))
+        };
+        match parts[0].as_str() {
+            Some("EVENT") => match <[Value; 1]>::try_from(rest) {
+                Ok([event]) => Ok(Self::Event(serde_json::from_value(event)?)),
+                Err(_) => Err(invalid("EVENT takes one event")),
+            },
+            Some("REQ") => Ok(Self::Req {
+                subscription: subscription(&rest)?,
+                filters: rest
+                    .into_iter()
+                    .skip(1)
+                    .map(serde_json::from_value)
+                    .collect::<Result<_, _>>()?,
+            }),
+            Some("CLOSE") => Ok(Self::Close(subscription(&rest)?)),
+            _ => Err(invalid("unknown message type")),
+        }
+    }
+}
+
+/// Message sent by the relay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayMessage {
+    /// An event matching a subscription
+    Event {
+        /// Subscription id
+        subscription: String,
+        /// Matching event
+        event: Event,
+    },
+    /// Result of publishing an event
+    Ok {
+        /// Event id
+        event_id: String,
+        /// Whether the event was accepted
+        accepted: bool,
+        /// Reason, prefixed like `invalid:` or `rate-limited:` when rejected
+        message: String,
+    },
+    /// Stored events for the subscription have all been sent
+    Eose(String),
+    /// The relay ended a subscription
+    Closed {
+        /// Subscription id
+        subscription: String,
+        /// Prefixed reason
+        message: String,
+    },
+    /// Human-readable notice
+    Notice(String),
+}
+
+impl RelayMessage {
+    /// JSON array form of the message
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Event {
+                subscription,
+                event,
+            } => json!(["EVENT", subscription, event]),
+            Self::Ok {
+                event_id,
+                accepted,
+                message,
+            } => json!(["OK", event_id, accepted, message]),
+            Self::Eose(subscription) => json!(["EOSE", subscription]),
+            Self::Closed {
+                subscription,
+                message,
+            } => json!(["CLOSED", subscription, message]),
+            Self::Notice(message) => json!(["NOTICE", message]),
+        }
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> KeyPair {
+        KeyPair::from_seckey_slice(&Secp256k1::new(), &[7; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_signed_events_verify_and_match_filters() {
+        let tags = vec![vec!["p".to_string(), "ab".repeat(32)]];
+        let event = Event::sign(&keys(), 1, tags, "hello", 1_700_000_000).unwrap();
+        event.verify().unwrap();
+
+        let mut tampered = event.clone();
+        tampered.content = "hullo".into();
+        assert!(tampered.verify().is_err());
+
+        let filter: Filter = serde_json::from_value(json!({
+            "kinds": [1],
+            "since": 1_700_000_000,
+            "#p": ["ab".repeat(32)],
+        }))
+        .unwrap();
+        assert!(filter.matches(&event));
+        let other: Filter = serde_json::from_value(json!({ "#p": ["cd".repeat(32)] })).unwrap();
+        assert!(!other.matches(&event));
+    }
+
+    #[test]
+    fn test_client_messages_parse() {
+        let req = ClientMessage::parse(r#"["REQ","sub1",{"kinds":[1]},{"limit":5}]"#).unwrap();
+        let ClientMessage::Req {
+            subscription,
+            filters,
+        } = req
+        else {
+            panic!("expected REQ");
+        };
+        assert_eq!((subscription.as_str(), filters.len()), ("sub1", 2));
+        assert_eq!(
+            ClientMessage::parse(r#"["CLOSE","sub1"]"#).unwrap(),
+            ClientMessage::Close("sub1".into())
+        );
+        assert!(ClientMessage::parse(r#"["REQ",""]"#).is_err());
+        assert!(ClientMessage::parse(r#"{"EVENT":1}"#).is_err());
+        assert_eq!(
+            RelayMessage::Eose("sub1".into()).to_json(),
+            r#"["EOSE","sub1"]"#
+        );
+    }
+}
@@ -0,0 +1,651 @@
+//! Transport-independent Nostr relay
+//!
+//! [`Relay`] validates and stores events and fans them out to live
+//! subscribers; a [`Connection`] holds one client's subscriptions and rate
+//! limits and turns [`ClientMessage`]s into [`RelayMessage`]s, so any
+//! transport that carries text frames can serve clients.
+//!
+//! Ephemeral kinds are relayed without being stored, replaceable and
+//! addressable kinds keep only their newest version, and NIP-09 deletion
+//! requests remove the author's own events and keep them from being
+//! re-published.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+
+use super::{ClientMessage, Event, Filter, RelayMessage, DELETION_KIND};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "nostr_relay";
+const EVENT_PREFIX: &str = "event/";
+const TIME_PREFIX: &str = "time/";
+const ADDRESS_PREFIX: &str = "addr/";
+const DELETED_PREFIX: &str = "deleted/";
+
+/// Relay settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayConfig {
+    /// Name in the NIP-11 document
+    pub name: String,
+    /// Description in the NIP-11 document
+    pub description: String,
+    /// Operator pubkey in the NIP-11 document
+    pub pubkey: Option<String>,
+    /// Operator contact in the NIP-11 document
+    pub contact: Option<String>,
+    /// Largest accepted client message in bytes
+    pub max_message_bytes: usize,
+    /// Open subscriptions per connection
+    pub max_subscriptions: usize,
+    /// Filters per subscription
+    pub max_filters: usize,
+    /// Largest `limit` honoured for stored events
+    pub max_limit: usize,
+    /// How far in the future `created_at` may be, in seconds
+    pub max_future_secs: u64,
+    /// Events a connection may publish per minute
+    pub events_per_minute: u32,
+    /// Subscriptions a connection may open per minute
+    pub reqs_per_minute: u32,
+    /// Only these pubkeys may publish; anyone when unset
+    pub allowed_authors: Option<Vec<String>>,
+    /// Live events buffered per connection
+    pub live_buffer: usize,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            name: "anya relay".to_string(),
+            description: "Private Nostr relay embedded in Anya".to_string(),
+            pubkey: None,
+            contact: None,
+            max_message_bytes: 128 * 1024,
+            max_subscriptions: 20,
+            max_filters: 10,
+            max_limit: 500,
+            max_future_secs: 900,
+            events_per_minute: 120,
+            reqs_per_minute: 60,
+            allowed_authors: None,
+            live_buffer: 1024,
+        }
+    }
+}
+
+/// NIP-11 relay information document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayInfo {
+    /// Relay name
+    pub name: String,
+    /// Relay description
+    pub description: String,
+    /// Operator pubkey
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    /// Operator contact
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    /// Implemented NIPs
+    pub supported_nips: Vec<u16>,
+    /// Software URL
+    pub software: String,
+    /// Software version
+    pub version: String,
+    /// Limits enforced on clients
+    pub limitation: RelayLimitation,
+}
+
+/// `limitation` section of the NIP-11 document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayLimitation {
+    /// Largest accepted message in bytes
+    pub max_message_length: usize,
+    /// Open subscriptions per connection
+    pub max_subscriptions: usize,
+    /// Filters per subscription
+    pub max_filters: usize,
+    /// Largest honoured `limit`
+    pub max_limit: usize,
+    /// Whether NIP-42 authentication is required
+    pub auth_required: bool,
+    /// Whether only some pubkeys may publish
+    pub restricted_writes: bool,
+}
+
+/// Result of publishing an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Published {
+    /// Stored and relayed
+    Stored,
+    /// Relayed to live subscribers only
+    Ephemeral,
+    /// Already stored
+    Duplicate,
+    /// A newer version of this replaceable event is stored
+    Superseded,
+}
+
+/// An embeddable Nostr relay
+pub struct Relay {
+    config: RelayConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    live: broadcast::Sender<Event>,
+    /// Serializes writes so replacement and deletion see a consistent store
+    writes: Mutex<()>,
+}
+
+impl Relay {
+    /// Open the relay's event store in `storage`
+    pub async fn open(
+        config: RelayConfig,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Arc<Self>> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let (live, _) = broadcast::channel(config.live_buffer.max(1));
+        Ok(Arc::new(Self {
+            config,
+            storage,
+            ns,
+            live,
+            writes: Mutex::new(()),
+        }))
+    }
+
+    /// Relay settings
+    pub const fn config(&self) -> &RelayConfig {
+        &self.config
+    }
+
+    /// NIP-11 information document
+    pub fn info(&self) -> RelayInfo {
+        RelayInfo {
+            name: self.config.name.clone(),
+            description: self.config.description.clone(),
+            pubkey: self.config.pubkey.clone(),
+            contact: self.config.contact.clone(),
+            supported_nips: vec![1, 9, 11],
+            software: env!("CARGO_PKG_REPOSITORY").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            limitation: RelayLimitation {
+                max_message_length: self.config.max_message_bytes,
+                max_subscriptions: self.config.max_subscriptions,
+                max_filters: self.config.max_filters,
+                max_limit: self.config.max_limit,
+                auth_required: false,
+                restricted_writes: self.config.allowed_authors.is_some(),
+            },
+        }
+    }
+
+    /// Open a client session
+    pub fn connect(self: &Arc<Self>) -> Connection {
+        Connection {
+            relay: self.clone(),
+            live: self.live.subscribe(),
+            subscriptions: HashMap::new(),
+            events: TokenBucket::per_minute(self.config.events_per_minute),
+            reqs: TokenBucket::per_minute(self.config.reqs_per_minute),
+        }
+    }
+
+    /// Validate, store, and relay an event
+    pub async fn publish(&self, event: Event) -> AnyaResult<Published> {
+        event.verify()?;
+        if event.created_at > unix_now() + self.config.max_future_secs {
+            return Err(AnyaError::invalid_input(
+                "created_at is too far in the future",
+            ));
+        }
+        if let Some(allowed) = &self.config.allowed_authors {
+            if !allowed.contains(&event.pubkey) {
+                return Err(AnyaError::new(
+                    ErrorCode::PermissionDenied,
+                    "pubkey may not publish to this relay",
+                ));
+            }
+        }
+
+        let guard = self.writes.lock().await;
+        let deleted_key = format!("{}{}", DELETED_PREFIX, event.id);
+        if let Some(deleter) = self.storage.get(&self.ns, &deleted_key).await? {
+            if deleter == event.pubkey.as_bytes() {
+                return Err(AnyaError::new(
+                    ErrorCode::PermissionDenied,
+                    "event was deleted by its author",
+                ));
+            }
+        }
+        if self.load(&event.id).await?.is_some() {
+            return Ok(Published::Duplicate);
+        }
+        if event.is_ephemeral() {
+            drop(guard);
+            let _ = self.live.send(event);
+            return Ok(Published::Ephemeral);
+        }
+
+        if event.is_replaceable() || event.is_addressable() {
+            let address = format!(
+                "{}{}/{}/{}",
+                ADDRESS_PREFIX,
+                event.pubkey,
+                event.kind,
+                event.d_tag()
+            );
+            if let Some(current) = self.storage.get(&self.ns, &address).await? {
+                let current = String::from_utf8_lossy(&current).into_owned();
+                if let Some(current) = self.load(&current).await? {
+                    // Newest wins; equal timestamps keep the lowest id
+                    if (current.created_at, &event.id) > (event.created_at, &current.id) {
+                        return Ok(Published::Superseded);
+                    }
+                    self.remove(&current).await?;
+                }
+            }
+            self.storage
+                .put(&self.ns, &address, event.id.as_bytes())
+                .await?;
+        }
+
+        if event.kind == DELETION_KIND {
+            for id in event.tag_values("e") {
+                if let Some(target) = self.load(id).await? {
+                    if target.pubkey != event.pubkey {
+                        continue;
+                    }
+                    self.remove(&target).await?;
+                }
+                self.storage
+                    .put(
+                        &self.ns,
+                        &format!("{}{}", DELETED_PREFIX, id),
+                        event.pubkey.as_bytes(),
+                    )
+                    .await?;
+            }
+        }
+
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{}", EVENT_PREFIX, event.id),
+                &serde_json::to_vec(&event)?,
+            )
+            .await?;
+        self.storage.put(&self.ns, &time_key(&event), &[]).await?;
+        drop(guard);
+        let _ = self.live.send(event);
+        Ok(Published::Stored)
+    }
+
+    /// Stored events matching any filter, newest first.
+    ///
+    /// Each filter contributes at most its `limit` (capped at
+    /// [`RelayConfig::max_limit`]) events.
+    pub async fn query(&self, filters: &[Filter]) -> AnyaResult<Vec<Event>> {
+        let mut remaining: Vec<usize> = filters
+            .iter()
+            .map(|f| {
+                f.limit
+                    .unwrap_or(self.config.max_limit)
+                    .min(self.config.max_limit)
+            })
+            .collect();
+        let mut events = Vec::new();
+        let index = self.storage.scan_prefix(&self.ns, TIME_PREFIX).await?;
+        for (key, _) in index.into_iter().rev() {
+            if remaining.iter().all(|r| *r == 0) {
+                break;
+            }
+            let Some(id) = key.rsplit('/').next() else {
+                continue;
+            };
+            let Some(event) = self.load(id).await? else {
+                continue;
+            };
+            let mut wanted = false;
+            for (filter, remaining) in filters.iter().zip(remaining.iter_mut()) {
+                if *remaining > 0 && filter.matches(&event) {
+                    *remaining -= 1;
+                    wanted = true;
+                }
+            }
+            if wanted {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    async fn load(&self, id: &str) -> AnyaResult<Option<Event>> {
+        self.storage
+            .get(&self.ns, &format!("{}{}", EVENT_PREFIX, id))
+            .await?
+            .map(|bytes| serde_json::from_slice(&bytes).map_err(Into::into))
+            .transpose()
+    }
+
+    async fn remove(&self, event: &Event) -> AnyaResult<()> {
+        self.storage
+            .delete(&self.ns, &format!("{}{}", EVENT_PREFIX, event.id))
+            .await?;
+        self.storage.delete(&self.ns, &time_key(event)).await?;
+        Ok(())
+    }
+}
+
+/// One client's session with a [`Relay`]
+pub struct Connection {
+    relay: Arc<Relay>,
+    live: broadcast::Receiver<Event>,
+    subscriptions: HashMap<String, Vec<Filter>>,
+    events: TokenBucket,
+    reqs: TokenBucket,
+}
+
+impl Connection {
+    /// Handle one text frame from the client
+    pub async fn handle(&mut self, text: &str) -> Vec<RelayMessage> {
+        if text.len() > self.relay.config.max_message_bytes {
+            return vec![RelayMessage::Notice(
+                "invalid: message too large".to_string(),
+            )];
+        }
+        let message = match ClientMessage::parse(text) {
+            Ok(message) => message,
+            Err(e) => return vec![RelayMessage::Notice(format!("invalid: {}", e))],
+        };
+        match message {
+            ClientMessage::Event(event) => vec![self.publish(event).await],
+            ClientMessage::Req {
+                subscription,
+                filters,
+            } => self.subscribe(subscription, filters).await,
+            ClientMessage::Close(subscription) => {
+                self.subscriptions.remove(&subscription);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Wait for live events matching this connection's subscriptions.
+    ///
+    /// Returns `None` once the relay is gone.
+    pub async fn next_live(&mut self) -> Option<Vec<RelayMessage>> {
+        loop {
+            match self.live.recv().await {
+                Ok(event) => {
+                    let messages: Vec<_> = self
+                        .subscriptions
+                        .iter()
+                        .filter(|(_, filters)| filters.iter().any(|f| f.matches(&event)))
+                        .map(|(id, _)| RelayMessage::Event {
+                            subscription: id.clone(),
+                            event: event.clone(),
+                        })
+                        .collect();
+                    if !messages.is_empty() {
+                        return Some(messages);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    return Some(vec![RelayMessage::Notice(format!(
+                        "error: {} live events were dropped; resubscribe to catch up",
+                        missed
+                    ))])
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    async fn publish(&mut self, event: Event) -> RelayMessage {
+        let event_id = event.id.clone();
+        let (accepted, message) = if !self.events.try_take() {
+            (false, "rate-limited: slow down".to_string())
+        } else {
+            match self.relay.publish(event).await {
+                Ok(Published::Stored | Published::Ephemeral) => (true, String::new()),
+                Ok(Published::Duplicate) => (true, "duplicate: already have this event".into()),
+                Ok(Published::Superseded) => (true, "duplicate: have a newer version".into()),
+                Err(e) => (false, rejection(&e)),
+            }
+        };
+        RelayMessage::Ok {
+            event_id,
+            accepted,
+            message,
+        }
+    }
+
+    async fn subscribe(&mut self, subscription: String, filters: Vec<Filter>) -> Vec<RelayMessage> {
+        let closed = |message: &str| {
+            vec![RelayMessage::Closed {
+                subscription: subscription.clone(),
+                message: message.to_string(),
+            }]
+        };
+        if !self.reqs.try_take() {
+            return closed("rate-limited: slow down");
+        }
+        if filters.is_empty() || filters.len() > self.relay.config.max_filters {
+            return closed("invalid: too many or no filters");
+        }
+        if !self.subscriptions.contains_key(&subscription)
+            && self.subscriptions.len() >= self.relay.config.max_subscriptions
+        {
+            return closed("blocked: too many open subscriptions");
+        }
+        let stored = match self.relay.query(&filters).await {
+            Ok(stored) => stored,
+            Err(e) => return closed(&format!("error: {}", e)),
+        };
+        self.subscriptions.insert(subscription.clone(), filters);
+        let mut messages: Vec<_> = stored
+            .into_iter()
+            .map(|event| RelayMessage::Event {
+                subscription: subscription.clone(),
+                event,
+            })
+            .collect();
+        messages.push(RelayMessage::Eose(subscription));
+        messages
+    }
+}
+
+/// Machine-readable NIP-01 rejection message for an error
+fn rejection(err: &AnyaError) -> String {
+    let prefix = match err.code() {
+        ErrorCode::InvalidInput | ErrorCode::Serialization => "invalid",
+        ErrorCode::PermissionDenied => "blocked",
+        ErrorCode::RateLimited => "rate-limited",
+        _ => "error",
+    };
+    format!("{}: {}", prefix, err)
+}
+
+/// Token bucket refilled continuously up to its capacity
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_sec: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(rate: u32) -> Self {
+        let capacity = f64::from(rate.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            per_sec: capacity / 60.0,
+            last: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = elapsed
+            .mul_add(self.per_sec, self.tokens)
+            .min(self.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn time_key(event: &Event) -> String {
+    format!("{}{:016x}/{}", TIME_PREFIX, event.created_at, event.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::secp256k1::{KeyPair, Secp256k1};
+
+    fn keys(seed: u8) -> KeyPair {
+        KeyPair::from_seckey_slice(&Secp256k1::new(), &[seed; 32]).unwrap()
+    }
+
+    async fn relay(config: RelayConfig) -> Arc<Relay> {
+        Relay::open(config, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap()
+    }
+
+    fn note(keys: &KeyPair, kind: u16, tags: Vec<Vec<String>>, at: u64) -> Event {
+        Event::sign(keys, kind, tags, format!("note at {}", at), at).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_subscription_replays_then_streams() {
+        let relay = relay(RelayConfig::default()).await;
+        let alice = keys(1);
+        for at in [100, 300, 200] {
+            relay
+                .publish(note(&alice, 1, Vec::new(), at))
+                .await
+                .unwrap();
+        }
+        let mut client = relay.connect();
+        let replay = client
+            .handle(r#"["REQ","s",{"kinds":[1],"limit":2}]"#)
+            .await;
+        let times: Vec<_> = replay
+            .iter()
+            .filter_map(|m| match m {
+                RelayMessage::Event { event, .. } => Some(event.created_at),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(times, [300, 200]);
+        assert_eq!(replay.last(), Some(&RelayMessage::Eose("s".into())));
+
+        let live = note(&alice, 1, Vec::new(), 400);
+        let frame = format!(r#"["EVENT",{}]"#, serde_json::to_string(&live).unwrap());
+        let ok = client.handle(&frame).await;
+        assert!(matches!(&ok[0], RelayMessage::Ok { accepted: true, .. }));
+        let streamed = client.next_live().await.unwrap();
+        assert!(matches!(&streamed[0], RelayMessage::Event { event, .. } if event.id == live.id));
+
+        let dup = client.handle(&frame).await;
+        assert!(
+            matches!(&dup[0], RelayMessage::Ok { accepted: true, message, .. } if message.starts_with("duplicate:"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replacement_deletion_and_limits() {
+        let alice = keys(1);
+        let mallory = keys(2);
+        let relay = relay(RelayConfig {
+            events_per_minute: 3,
+            ..RelayConfig::default()
+        })
+        .await;
+
+        // Replaceable kinds keep only the newest version
+        relay
+            .publish(note(&alice, 0, Vec::new(), 10))
+            .await
+            .unwrap();
+        relay
+            .publish(note(&alice, 0, Vec::new(), 20))
+            .await
+            .unwrap();
+        assert_eq!(
+            relay
+                .publish(note(&alice, 0, Vec::new(), 15))
+                .await
+                .unwrap(),
+            Published::Superseded
+        );
+        let profiles = relay
+            .query(&[Filter {
+                kinds: Some(vec![0]),
+                ..Filter::default()
+            }])
+            .await
+            .unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].created_at, 20);
+
+        // Only the author's deletion request removes an event
+        let post = note(&alice, 1, Vec::new(), 30);
+        relay.publish(post.clone()).await.unwrap();
+        let e_tag = vec![vec!["e".to_string(), post.id.clone()]];
+        relay
+            .publish(note(&mallory, DELETION_KIND, e_tag.clone(), 31))
+            .await
+            .unwrap();
+        let by_id = Filter {
+            ids: Some(vec![post.id.clone()]),
+            ..Filter::default()
+        };
+        assert_eq!(
+            relay
+                .query(std::slice::from_ref(&by_id))
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        relay
+            .publish(note(&alice, DELETION_KIND, e_tag, 32))
+            .await
+            .unwrap();
+        assert!(relay.query(&[by_id]).await.unwrap().is_empty());
+        let err = relay.publish(post).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+
+        // Per-connection rate limit
+        let mut client = relay.connect();
+        let mut accepted = 0;
+        for at in 40..45 {
+            let frame = format!(
+                r#"["EVENT",{}]"#,
+                serde_json::to_string(&note(&alice, 1, Vec::new(), at)).unwrap()
+            );
+            if let [RelayMessage::Ok { accepted: true, .. }] = client.handle(&frame).await[..] {
+                accepted += 1;
+            }
+        }
+        assert_eq!(accepted, 3);
+        assert_eq!(relay.info().supported_nips, [1, 9, 11]);
+    }
+}
@@ -0,0 +1,229 @@
+//! Websocket transport for the relay
+//!
+//! Each TCP connection is either a websocket upgrade, served as a relay
+//! [`Connection`](super::relay::Connection), or a plain HTTP request; a
+//! request accepting `application/nostr+json` receives the NIP-11 document.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+
+use super::relay::Relay;
+use crate::lifecycle::{Subsystem, TaskSpawner};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Largest HTTP request head inspected before the handshake
+const MAX_HEAD_BYTES: usize = 8 * 1024;
+
+/// Time a client has to send its request head
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Relay listening on a TCP address, run as a lifecycle subsystem
+pub struct RelayServer {
+    relay: Arc<Relay>,
+    addr: SocketAddr,
+}
+
+impl RelayServer {
+    /// Serve `relay` on `addr` once started
+    pub const fn new(relay: Arc<Relay>, addr: SocketAddr) -> Self {
+        Self { relay, addr }
+    }
+}
+
+#[async_trait]
+impl Subsystem for RelayServer {
+    fn name(&self) -> &str {
+        "nostr-relay"
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let listener = TcpListener::bind(self.addr).await?;
+        tracing::info!(addr = %self.addr, "nostr relay listening");
+        let relay = self.relay.clone();
+        spawner
+            .spawn("accept", move |token| serve(relay, listener, token))
+            .await;
+        Ok(())
+    }
+}
+
+/// Accept clients on `listener` until `token` is cancelled
+pub async fn serve(
+    relay: Arc<Relay>,
+    listener: TcpListener,
+    token: CancellationToken,
+) -> AnyaResult<()> {
+    loop {
+        let (stream, peer) = tokio::select! {
+            () = token.cancelled() => return Ok(()),
+            accepted = listener.accept() => accepted?,
+        };
+        let relay = relay.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(relay, stream, token).await {
+                tracing::debug!(%peer, error = %e, "relay client disconnected");
+            }
+        });
+    }
+}
+
+async fn serve_client(
+    relay: Arc<Relay>,
+    mut stream: TcpStream,
+    token: CancellationToken,
+) -> AnyaResult<()> {
+    let (head, head_len) = tokio::time::timeout(HEAD_TIMEOUT, peek_head(&stream)).await??;
+    if !head.contains("upgrade: websocket") {
+        // Consume the request so closing the socket does not reset it
+        let mut request = vec![0; head_len];
+        stream.read_exact(&mut request).await?;
+        let response = if head.contains("application/nostr+json") {
+            let body = serde_json::to_string(&relay.info())?;
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/nostr+json\r\n\
+                 Access-Control-Allow-Origin: *\r\nAccess-Control-Allow-Headers: *\r\n\
+                 Access-Control-Allow-Methods: GET\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        } else {
+            "HTTP/1.1 426 Upgrade Required\r\nUpgrade: websocket\r\n\
+             Content-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string()
+        };
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    let mut socket = tokio_tungstenite::accept_async(stream)
+        .await
+        .map_err(ws_error)?;
+    let mut connection = relay.connect();
+    loop {
+        let replies = tokio::select! {
+            () = token.cancelled() => {
+                let _ = socket.close(None).await;
+                return Ok(());
+            }
+            frame = socket.next() => match frame {
+                Some(Ok(Message::Text(text))) => connection.handle(&text).await,
+                Some(Ok(Message::Binary(_))) => vec![super::RelayMessage::Notice(
+                    "invalid: binary frames are not supported".to_string(),
+                )],
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(ws_error(e)),
+            },
+            live = connection.next_live() => match live {
+                Some(messages) => messages,
+                None => return Ok(()),
+            },
+        };
+        for reply in replies {
+            socket
+                .send(Message::Text(reply.to_json()))
+                .await
+                .map_err(ws_error)?;
+        }
+    }
+}
+
+/// Lowercased request head and its length, left unread for the websocket
+/// handshake
+async fn peek_head(stream: &TcpStream) -> AnyaResult<(String, usize)> {
+    let mut buf = vec![0; MAX_HEAD_BYTES];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        if n == 0 {
+            return Err(AnyaError::new(
+                ErrorCode::NetworkFailure,
+                "client closed before sending a request",
+            ));
+        }
+        let head = &buf[..n];
+        if head.windows(4).any(|w| w == b"\r\n\r\n") || n == buf.len() {
+            return Ok((String::from_utf8_lossy(head).to_ascii_lowercase(), n));
+        }
+        // Peeking returns at once while the head is still incomplete
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+fn ws_error(err: tokio_tungstenite::tungstenite::Error) -> AnyaError {
+    AnyaError::with_source(ErrorCode::NetworkFailure, "websocket error", err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::relay::RelayConfig;
+    use crate::nostr::Event;
+    use crate::storage::memory::MemoryBackend;
+    use ::bitcoin::secp256k1::{KeyPair, Secp256k1};
+
+    #[tokio::test]
+    async fn test_websocket_clients_and_nip11() {
+        let relay = Relay::open(RelayConfig::default(), Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let token = CancellationToken::new();
+        let server = tokio::spawn(serve(relay, listener, token.clone()));
+
+        let mut info = TcpStream::connect(addr).await.unwrap();
+        info.write_all(b"GET / HTTP/1.1\r\nHost: relay\r\nAccept: application/nostr+json\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        info.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains(r#""supported_nips":[1,9,11]"#));
+
+        let url = format!("ws://{}", addr);
+        let (mut subscriber, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut publisher, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        subscriber
+            .send(Message::Text(r#"["REQ","feed",{"kinds":[1]}]"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            subscriber.next().await.unwrap().unwrap(),
+            Message::Text(r#"["EOSE","feed"]"#.into())
+        );
+
+        let keys = KeyPair::from_seckey_slice(&Secp256k1::new(), &[9; 32]).unwrap();
+        let event = Event::sign(&keys, 1, Vec::new(), "gm", 1_700_000_000).unwrap();
+        let frame = format!(r#"["EVENT",{}]"#, serde_json::to_string(&event).unwrap());
+        publisher.send(Message::Text(frame)).await.unwrap();
+        let ok = publisher
+            .next()
+            .await
+            .unwrap()
+            .unwrap()
+            .into_text()
+            .unwrap();
+        assert_eq!(ok, format!(r#"["OK","{}",true,""]"#, event.id));
+        let pushed = subscriber
+            .next()
+            .await
+            .unwrap()
+            .unwrap()
+            .into_text()
+            .unwrap();
+        assert!(pushed.starts_with(r#"["EVENT","feed",{"#) && pushed.contains(&event.id));
+
+        token.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
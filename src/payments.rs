@@ -0,0 +1,609 @@
+//! Signed payment requests and receipts
+//!
+//! A merchant publishes a [`PaymentRequest`] (amount, memo, expiry, and the
+//! on-chain or Lightning destinations it accepts) either as a published
+//! record on its Decentralized Web Node or as a Nostr event. Either way the
+//! request is signed by the merchant, so a wallet that fetches it knows who
+//! it is paying and that the destinations were not swapped in transit.
+//!
+//! After paying, the wallet attaches a [`PaymentReceipt`] carrying proof of
+//! payment: the transaction output for on-chain payments, or the preimage
+//! for Lightning. Receipts are written as child records of the request
+//! under the [`payments_protocol`], or as Nostr events referencing it, and
+//! the merchant checks them with [`verify_receipt`].
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use ::bitcoin::secp256k1::KeyPair;
+use ::bitcoin::{Address, Network, Transaction, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::bitcoin::builder::Recipient;
+use crate::bitcoin::tracker::ChainSource;
+use crate::nostr::relay::Relay;
+use crate::nostr::{Event, Filter};
+use crate::utils::encoding::{from_hex, sha256, to_hex};
+use crate::web5::auth::DidSigner;
+use crate::web5::dwn::{Actor, DwnHost, DwnRecord, ProtocolDefinition, ProtocolRule, RecordsQuery};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// DWN protocol under which requests and receipts are stored
+pub const PAYMENTS_PROTOCOL: &str = "https://anya.org/protocols/payments";
+/// Protocol path of payment requests
+pub const REQUEST_PATH: &str = "request";
+/// Protocol path of receipts, nested under their request
+pub const RECEIPT_PATH: &str = "request/receipt";
+/// Schema URI of payment requests
+pub const REQUEST_SCHEMA: &str = "https://anya.org/schemas/payment-request";
+/// Schema URI of receipts
+pub const RECEIPT_SCHEMA: &str = "https://anya.org/schemas/payment-receipt";
+/// Nostr kind of requests and receipts: NIP-78 application data, addressed
+/// by a `d` tag
+pub const PAYMENTS_KIND: u16 = 30_078;
+
+const JSON: &str = "application/json";
+const REQUEST_D_PREFIX: &str = "anya.payments.request/";
+const RECEIPT_D_PREFIX: &str = "anya.payments.receipt/";
+
+/// Where a request can be paid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Destination {
+    /// On-chain address
+    Onchain {
+        /// Address on the request's network
+        address: String,
+    },
+    /// BOLT-11 invoice
+    Lightning {
+        /// The invoice
+        invoice: String,
+        /// Hex payment hash of the invoice, checked against receipts
+        payment_hash: String,
+    },
+}
+
+/// What a merchant asks to be paid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentRequest {
+    /// Merchant-chosen identifier, unique per merchant
+    pub id: String,
+    /// Amount in satoshis
+    pub amount_sat: u64,
+    /// Description shown to the payer
+    #[serde(default)]
+    pub memo: String,
+    /// Creation time, seconds since the Unix epoch
+    pub created_at: u64,
+    /// Time after which the request must not be paid
+    pub expires_at: u64,
+    /// Network of on-chain destinations
+    pub network: Network,
+    /// Accepted destinations in order of preference
+    pub destinations: Vec<Destination>,
+}
+
+impl PaymentRequest {
+    /// Check the request is well formed
+    pub fn validate(&self) -> AnyaResult<()> {
+        if self.id.is_empty() || self.id.contains('/') {
+            return Err(AnyaError::invalid_input(
+                "request id must be non-empty and contain no '/'",
+            ));
+        }
+        if self.amount_sat == 0 {
+            return Err(AnyaError::invalid_input("request amount must be positive"));
+        }
+        if self.expires_at <= self.created_at {
+            return Err(AnyaError::invalid_input(
+                "request expires before it is created",
+            ));
+        }
+        if self.destinations.is_empty() {
+            return Err(AnyaError::invalid_input("request has no destinations"));
+        }
+        for destination in &self.destinations {
+            match destination {
+                Destination::Onchain { address } => {
+                    parse_address(address, self.network)?;
+                }
+                Destination::Lightning { payment_hash, .. } => {
+                    if from_hex(payment_hash)?.len() != 32 {
+                        return Err(AnyaError::invalid_input("payment hash must be 32 bytes"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the request may no longer be paid at `now`
+    pub const fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Builder recipient for the first on-chain destination, if any
+    pub fn onchain_recipient(&self) -> AnyaResult<Option<Recipient>> {
+        self.destinations
+            .iter()
+            .find_map(|d| match d {
+                Destination::Onchain { address } => Some(address),
+                Destination::Lightning { .. } => None,
+            })
+            .map(|address| {
+                let mut recipient =
+                    Recipient::new(&parse_address(address, self.network)?, self.amount_sat);
+                recipient.metadata = Some(self.id.clone());
+                Ok(recipient)
+            })
+            .transpose()
+    }
+
+    /// Published DWN record of the request signed by `merchant`
+    pub fn to_dwn_record(&self, merchant: &DidSigner) -> AnyaResult<DwnRecord> {
+        self.validate()?;
+        DwnRecord::new(self.id.clone(), JSON, &serde_json::to_vec(self)?)
+            .in_protocol(PAYMENTS_PROTOCOL, REQUEST_PATH)
+            .with_schema(REQUEST_SCHEMA)
+            .published()
+            .sign(merchant, self.created_at)
+    }
+
+    /// Nostr event of the request signed by `merchant`
+    pub fn to_nostr_event(&self, merchant: &KeyPair) -> AnyaResult<Event> {
+        self.validate()?;
+        Event::sign(
+            merchant,
+            PAYMENTS_KIND,
+            vec![
+                vec!["d".into(), format!("{}{}", REQUEST_D_PREFIX, self.id)],
+                vec!["expiration".into(), self.expires_at.to_string()],
+            ],
+            serde_json::to_string(self)?,
+            self.created_at,
+        )
+    }
+}
+
+/// A request whose merchant signature was checked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifiedRequest {
+    /// The request
+    pub request: PaymentRequest,
+    /// Signer: a DID for DWN requests, a hex pubkey for Nostr requests
+    pub merchant: String,
+    /// DWN record id or Nostr event id the request was published under
+    pub reference: String,
+}
+
+/// Verify a request read from a DWN and check it has not expired
+pub fn verify_dwn_request(record: &DwnRecord, now: u64) -> AnyaResult<VerifiedRequest> {
+    record.verify()?;
+    if record.protocol.as_deref() != Some(PAYMENTS_PROTOCOL)
+        || record.protocol_path.as_deref() != Some(REQUEST_PATH)
+    {
+        return Err(AnyaError::invalid_input("record is not a payment request"));
+    }
+    let request: PaymentRequest = serde_json::from_slice(&record.data_bytes()?)?;
+    if request.id != record.record_id {
+        return Err(AnyaError::invalid_input(
+            "request id does not match its record",
+        ));
+    }
+    checked(
+        request,
+        record.author.clone(),
+        record.record_id.clone(),
+        now,
+    )
+}
+
+/// Verify a request read from Nostr and check it has not expired
+pub fn verify_nostr_request(event: &Event, now: u64) -> AnyaResult<VerifiedRequest> {
+    event.verify()?;
+    let request: PaymentRequest = serde_json::from_str(&event.content)?;
+    if event.kind != PAYMENTS_KIND || event.d_tag() != format!("{}{}", REQUEST_D_PREFIX, request.id)
+    {
+        return Err(AnyaError::invalid_input("event is not a payment request"));
+    }
+    checked(request, event.pubkey.clone(), event.id.clone(), now)
+}
+
+fn checked(
+    request: PaymentRequest,
+    merchant: String,
+    reference: String,
+    now: u64,
+) -> AnyaResult<VerifiedRequest> {
+    request.validate()?;
+    if request.is_expired(now) {
+        return Err(AnyaError::new(
+            ErrorCode::Timeout,
+            format!("payment request {} expired", request.id),
+        ));
+    }
+    Ok(VerifiedRequest {
+        request,
+        merchant,
+        reference,
+    })
+}
+
+/// Fetch and verify request `id` from `merchant`'s DWN
+pub async fn fetch_dwn(
+    host: &DwnHost,
+    merchant: &str,
+    id: &str,
+    now: u64,
+) -> AnyaResult<VerifiedRequest> {
+    let query = RecordsQuery {
+        protocol: Some(PAYMENTS_PROTOCOL.into()),
+        protocol_path: Some(REQUEST_PATH.into()),
+        author: Some(merchant.into()),
+        ..RecordsQuery::default()
+    };
+    let record = host
+        .query(merchant, None, &query)
+        .await?
+        .into_iter()
+        .find(|r| r.record_id == id)
+        .ok_or_else(|| AnyaError::not_found(format!("payment request {}", id)))?;
+    verify_dwn_request(&record, now)
+}
+
+/// Fetch and verify request `id` published by `merchant` (hex pubkey) on `relay`
+pub async fn fetch_nostr(
+    relay: &Relay,
+    merchant: &str,
+    id: &str,
+    now: u64,
+) -> AnyaResult<VerifiedRequest> {
+    let filter = Filter {
+        authors: Some(vec![merchant.into()]),
+        kinds: Some(vec![PAYMENTS_KIND]),
+        tags: BTreeMap::from([(
+            "#d".to_string(),
+            vec![format!("{}{}", REQUEST_D_PREFIX, id)],
+        )]),
+        ..Filter::default()
+    };
+    let event = relay
+        .query(&[filter])
+        .await?
+        .into_iter()
+        .max_by_key(|e| e.created_at)
+        .ok_or_else(|| AnyaError::not_found(format!("payment request {}", id)))?;
+    verify_nostr_request(&event, now)
+}
+
+/// DWN protocol a merchant installs to accept requests and receipts: only
+/// the merchant writes requests, anyone may attach a receipt to one
+pub fn payments_protocol() -> ProtocolDefinition {
+    let rule = |schema: &str, can_write| ProtocolRule {
+        schema: Some(schema.into()),
+        data_formats: vec![JSON.into()],
+        can_write: vec![can_write],
+    };
+    ProtocolDefinition {
+        protocol: PAYMENTS_PROTOCOL.into(),
+        structure: BTreeMap::from([
+            (REQUEST_PATH.into(), rule(REQUEST_SCHEMA, Actor::Tenant)),
+            (RECEIPT_PATH.into(), rule(RECEIPT_SCHEMA, Actor::Anyone)),
+        ]),
+    }
+}
+
+/// Evidence that a request was paid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum PaymentProof {
+    /// Output paying an on-chain destination
+    Onchain {
+        /// Paying transaction
+        txid: Txid,
+        /// Output index
+        vout: u32,
+    },
+    /// Lightning payment preimage
+    Lightning {
+        /// Hex preimage whose SHA-256 is the invoice payment hash
+        preimage: String,
+    },
+}
+
+/// Receipt attached by the payer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentReceipt {
+    /// Request paid
+    pub request_id: String,
+    /// Merchant of the request
+    pub merchant: String,
+    /// Amount paid in satoshis
+    pub amount_sat: u64,
+    /// Payment time, seconds since the Unix epoch
+    pub paid_at: u64,
+    /// Proof of payment
+    pub proof: PaymentProof,
+}
+
+impl PaymentReceipt {
+    /// Receipt for an on-chain payment of `request` made by `tx`
+    pub fn onchain(request: &VerifiedRequest, tx: &Transaction, paid_at: u64) -> AnyaResult<Self> {
+        let (vout, output) = tx
+            .output
+            .iter()
+            .enumerate()
+            .find(|(_, o)| pays_onchain(&request.request, o))
+            .ok_or_else(|| AnyaError::invalid_input("transaction does not pay the request"))?;
+        Ok(Self {
+            request_id: request.request.id.clone(),
+            merchant: request.merchant.clone(),
+            amount_sat: output.value,
+            paid_at,
+            proof: PaymentProof::Onchain {
+                txid: tx.txid(),
+                vout: u32::try_from(vout)
+                    .map_err(|_| AnyaError::invalid_input("too many outputs"))?,
+            },
+        })
+    }
+
+    /// Receipt for a Lightning payment of `request` settled with `preimage`
+    pub fn lightning(
+        request: &VerifiedRequest,
+        preimage: [u8; 32],
+        paid_at: u64,
+    ) -> AnyaResult<Self> {
+        let receipt = Self {
+            request_id: request.request.id.clone(),
+            merchant: request.merchant.clone(),
+            amount_sat: request.request.amount_sat,
+            paid_at,
+            proof: PaymentProof::Lightning {
+                preimage: to_hex(&preimage),
+            },
+        };
+        if lightning_paid(&request.request, &receipt)? {
+            Ok(receipt)
+        } else {
+            Err(AnyaError::invalid_input(
+                "preimage does not match any invoice of the request",
+            ))
+        }
+    }
+
+    /// DWN record of the receipt, nested under the request's record and
+    /// signed by `payer`
+    pub fn to_dwn_record(
+        &self,
+        request: &VerifiedRequest,
+        payer: &DidSigner,
+    ) -> AnyaResult<DwnRecord> {
+        DwnRecord::new(
+            format!("{}.receipt.{}", request.reference, payer.did()),
+            JSON,
+            &serde_json::to_vec(self)?,
+        )
+        .in_protocol(PAYMENTS_PROTOCOL, RECEIPT_PATH)
+        .with_schema(RECEIPT_SCHEMA)
+        .with_parent(request.reference.clone())
+        .sign(payer, self.paid_at)
+    }
+
+    /// Nostr event of the receipt referencing the request event, signed by `payer`
+    pub fn to_nostr_event(&self, request: &VerifiedRequest, payer: &KeyPair) -> AnyaResult<Event> {
+        Event::sign(
+            payer,
+            PAYMENTS_KIND,
+            vec![
+                vec![
+                    "d".into(),
+                    format!("{}{}", RECEIPT_D_PREFIX, self.request_id),
+                ],
+                vec!["e".into(), request.reference.clone()],
+                vec!["p".into(), request.merchant.clone()],
+            ],
+            serde_json::to_string(self)?,
+            self.paid_at,
+        )
+    }
+}
+
+/// Check `receipt` proves payment of `request`, looking on-chain payments
+/// up in `chain`. Succeeds once the paying transaction is known to the
+/// chain source, confirmed or not.
+pub async fn verify_receipt(
+    request: &PaymentRequest,
+    receipt: &PaymentReceipt,
+    chain: &dyn ChainSource,
+) -> AnyaResult<()> {
+    if receipt.request_id != request.id {
+        return Err(AnyaError::invalid_input("receipt is for another request"));
+    }
+    let paid = match &receipt.proof {
+        PaymentProof::Onchain { txid, vout } => chain
+            .transaction(txid)
+            .await?
+            .and_then(|tx| tx.output.get(*vout as usize).cloned())
+            .is_some_and(|output| {
+                output.value == receipt.amount_sat && pays_onchain(request, &output)
+            }),
+        PaymentProof::Lightning { .. } => lightning_paid(request, receipt)?,
+    };
+    if paid {
+        Ok(())
+    } else {
+        Err(AnyaError::new(
+            ErrorCode::PermissionDenied,
+            format!("receipt does not prove payment of {}", request.id),
+        ))
+    }
+}
+
+fn pays_onchain(request: &PaymentRequest, output: &::bitcoin::TxOut) -> bool {
+    output.value >= request.amount_sat
+        && request.destinations.iter().any(|d| match d {
+            Destination::Onchain { address } => parse_address(address, request.network)
+                .is_ok_and(|a| a.script_pubkey() == output.script_pubkey),
+            Destination::Lightning { .. } => false,
+        })
+}
+
+fn lightning_paid(request: &PaymentRequest, receipt: &PaymentReceipt) -> AnyaResult<bool> {
+    let PaymentProof::Lightning { preimage } = &receipt.proof else {
+        return Ok(false);
+    };
+    let hash = to_hex(&sha256(&from_hex(preimage)?));
+    Ok(request.destinations.iter().any(|d| {
+        matches!(d, Destination::Lightning { payment_hash, .. } if payment_hash.eq_ignore_ascii_case(&hash))
+    }))
+}
+
+fn parse_address(address: &str, network: Network) -> AnyaResult<Address> {
+    Ok(Address::from_str(address)?.require_network(network)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::regtest::RegtestChain;
+    use crate::nostr::relay::RelayConfig;
+    use crate::storage::memory::MemoryBackend;
+    use crate::web5::dwn::{DwnConfig, ProtocolConfiguration};
+    use ::bitcoin::secp256k1::Secp256k1;
+    use ::bitcoin::PublicKey;
+    use std::sync::Arc;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn address(seed: u8) -> Address {
+        let secp = Secp256k1::new();
+        let key = ::bitcoin::secp256k1::SecretKey::from_slice(&[seed; 32]).unwrap();
+        Address::p2wpkh(&PublicKey::new(key.public_key(&secp)), Network::Regtest).unwrap()
+    }
+
+    fn request(preimage: &[u8; 32]) -> PaymentRequest {
+        PaymentRequest {
+            id: "inv-1001".into(),
+            amount_sat: 25_000,
+            memo: "Coffee beans".into(),
+            created_at: NOW,
+            expires_at: NOW + 3_600,
+            network: Network::Regtest,
+            destinations: vec![
+                Destination::Onchain {
+                    address: address(1).to_string(),
+                },
+                Destination::Lightning {
+                    invoice: "lnbcrt250u1p...".into(),
+                    payment_hash: to_hex(&sha256(preimage)),
+                },
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dwn_request_and_onchain_receipt() {
+        let merchant = DidSigner::from_seed(&[1; 32]).unwrap();
+        let payer = DidSigner::from_seed(&[2; 32]).unwrap();
+        let merchant_did = merchant.did().to_string();
+        let host = DwnHost::open(
+            DwnConfig {
+                tenants: vec![merchant_did.clone()],
+                ..DwnConfig::default()
+            },
+            Arc::new(MemoryBackend::new()),
+        )
+        .await
+        .unwrap();
+        host.configure_protocol(
+            &merchant_did,
+            &ProtocolConfiguration::sign(&merchant, payments_protocol(), 1).unwrap(),
+        )
+        .await
+        .unwrap();
+        let request = request(&[7; 32]);
+        host.write(&merchant_did, request.to_dwn_record(&merchant).unwrap())
+            .await
+            .unwrap();
+
+        let fetched = fetch_dwn(&host, &merchant_did, "inv-1001", NOW + 60)
+            .await
+            .unwrap();
+        assert_eq!(fetched.request, request);
+        assert_eq!(fetched.merchant, merchant_did);
+        let err = fetch_dwn(&host, &merchant_did, "inv-1001", NOW + 3_600)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Timeout);
+
+        // A payer cannot publish a request in the merchant's name
+        let forged = request.to_dwn_record(&payer).unwrap();
+        assert!(host.write(&merchant_did, forged).await.is_err());
+
+        let chain = RegtestChain::new(Network::Regtest).unwrap();
+        let recipient = fetched.request.onchain_recipient().unwrap().unwrap();
+        assert_eq!(recipient.metadata.as_deref(), Some("inv-1001"));
+        let outpoint = chain.fund(&recipient.script_pubkey, recipient.amount_sat);
+        let tx = chain.transaction(&outpoint.txid).await.unwrap().unwrap();
+        let receipt = PaymentReceipt::onchain(&fetched, &tx, NOW + 120).unwrap();
+        host.write(
+            &merchant_did,
+            receipt.to_dwn_record(&fetched, &payer).unwrap(),
+        )
+        .await
+        .unwrap();
+        verify_receipt(&request, &receipt, &chain).await.unwrap();
+
+        let mut wrong_output = receipt;
+        wrong_output.proof = PaymentProof::Onchain {
+            txid: tx.txid(),
+            vout: outpoint.vout + 1,
+        };
+        assert!(verify_receipt(&request, &wrong_output, &chain).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_nostr_request_and_lightning_receipt() {
+        let secp = Secp256k1::new();
+        let merchant = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+        let payer = KeyPair::from_seckey_slice(&secp, &[4; 32]).unwrap();
+        let relay = Relay::open(RelayConfig::default(), Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap();
+        let preimage = [9; 32];
+        let request = request(&preimage);
+        let merchant_pubkey = to_hex(&merchant.x_only_public_key().0.serialize());
+        relay
+            .publish(request.to_nostr_event(&merchant).unwrap())
+            .await
+            .unwrap();
+
+        let fetched = fetch_nostr(&relay, &merchant_pubkey, "inv-1001", NOW)
+            .await
+            .unwrap();
+        assert_eq!(fetched.request, request);
+        assert!(fetch_nostr(&relay, &merchant_pubkey, "inv-9999", NOW)
+            .await
+            .is_err());
+
+        // Tampered content breaks the merchant signature
+        let mut tampered = request.to_nostr_event(&merchant).unwrap();
+        tampered.content = tampered.content.replace("25000", "2500");
+        assert!(verify_nostr_request(&tampered, NOW).is_err());
+
+        assert!(PaymentReceipt::lightning(&fetched, [8; 32], NOW).is_err());
+        let receipt = PaymentReceipt::lightning(&fetched, preimage, NOW + 5).unwrap();
+        let event = receipt.to_nostr_event(&fetched, &payer).unwrap();
+        relay.publish(event.clone()).await.unwrap();
+        assert_eq!(
+            event.tag_values("e").next(),
+            Some(fetched.reference.as_str())
+        );
+
+        let decoded: PaymentReceipt = serde_json::from_str(&event.content).unwrap();
+        let chain = RegtestChain::new(Network::Regtest).unwrap();
+        verify_receipt(&request, &decoded, &chain).await.unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+//! Declarative policies for system actions
+//!
+//! Operators describe what may not happen as data rather than code, e.g.
+//! "treasury transfers above 1 BTC need two approvals" or "no model
+//! promotion with a safety score below 0.9". Every [`PolicyRule`] names the
+//! [`SystemAction`] kinds it covers, a set of [`Condition`]s on the action's
+//! attributes, and an [`Effect`] that applies when all of them hold.
+//!
+//! [`PolicyEngine::enforce`] is called before an action runs. Each decision
+//! is appended to the `policy_decisions` log. In dry-run mode violations are
+//! logged but nothing is blocked, so new rules can be rolled out safely.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "policy_decisions";
+const DECISION_PREFIX: &str = "decision/";
+
+/// An action about to be executed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemAction {
+    /// Dotted action kind, e.g. `treasury.transfer`
+    pub kind: String,
+    /// Who requested it
+    pub actor: String,
+    /// Attributes rules can inspect, e.g. `{"amount_sat": 150000000}`
+    pub attributes: Value,
+    /// Distinct approvers
+    #[serde(default)]
+    pub approvals: Vec<String>,
+}
+
+/// Comparison applied to an attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    /// Equal
+    Eq,
+    /// Not equal
+    Ne,
+    /// Greater than
+    Gt,
+    /// Greater than or equal
+    Gte,
+    /// Less than
+    Lt,
+    /// Less than or equal
+    Lte,
+    /// Contained in the array value
+    In,
+    /// Attribute is absent or null
+    Missing,
+}
+
+/// A test on one attribute
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Condition {
+    /// Dotted path into the action's attributes
+    pub field: String,
+    /// Comparison
+    pub op: Operator,
+    /// Value compared against; unused by `missing`
+    #[serde(default)]
+    pub value: Value,
+}
+
+impl Condition {
+    fn holds(&self, attributes: &Value) -> bool {
+        let actual = self
+            .field
+            .split('.')
+            .try_fold(attributes, |v, key| v.get(key))
+            .filter(|v| !v.is_null());
+        let Some(actual) = actual else {
+            return self.op == Operator::Missing;
+        };
+        let ordering = match (actual.as_f64(), self.value.as_f64()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => match (actual.as_str(), self.value.as_str()) {
+                (Some(a), Some(b)) => Some(a.cmp(b)),
+                _ => None,
+            },
+        };
+        match self.op {
+            Operator::Eq => ordering.map_or(actual == &self.value, |o| o.is_eq()),
+            Operator::Ne => ordering.map_or(actual != &self.value, |o| o.is_ne()),
+            Operator::Gt => ordering.is_some_and(|o| o.is_gt()),
+            Operator::Gte => ordering.is_some_and(|o| o.is_ge()),
+            Operator::Lt => ordering.is_some_and(|o| o.is_lt()),
+            Operator::Lte => ordering.is_some_and(|o| o.is_le()),
+            Operator::In => self
+                .value
+                .as_array()
+                .is_some_and(|values| values.contains(actual)),
+            Operator::Missing => false,
+        }
+    }
+}
+
+/// What a matching rule does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    /// The action is not allowed
+    Deny,
+    /// The action needs this many approvals, not counting the requester
+    RequireApprovals(usize),
+}
+
+/// A declarative rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyRule {
+    /// Unique rule name
+    pub name: String,
+    /// Action kinds covered: exact, a `prefix.*` pattern, or `*`
+    pub actions: Vec<String>,
+    /// Conditions that must all hold for the rule to apply
+    #[serde(default)]
+    pub when: Vec<Condition>,
+    /// Effect when it applies
+    pub effect: Effect,
+    /// Explanation shown when the rule blocks an action
+    pub message: String,
+}
+
+impl PolicyRule {
+    fn covers(&self, kind: &str) -> bool {
+        self.actions.iter().any(|pattern| {
+            pattern == "*"
+                || pattern == kind
+                || pattern.strip_suffix(".*").is_some_and(|prefix| {
+                    kind.strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('.'))
+                })
+        })
+    }
+}
+
+/// A rule an action broke
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    /// Rule name
+    pub rule: String,
+    /// Rule message
+    pub message: String,
+}
+
+/// Outcome of evaluating an action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+    /// Position in the decision log
+    pub seq: u64,
+    /// Evaluation time, seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Action evaluated
+    pub action: SystemAction,
+    /// Rules broken
+    pub violations: Vec<Violation>,
+    /// Whether the action may proceed
+    pub allowed: bool,
+    /// Whether the engine was in dry-run mode
+    pub dry_run: bool,
+}
+
+/// Policy engine settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PolicyConfig {
+    /// Log violations without blocking actions
+    pub dry_run: bool,
+}
+
+/// Evaluates actions against the rule set and logs every decision
+pub struct PolicyEngine {
+    config: PolicyConfig,
+    rules: RwLock<Vec<PolicyRule>>,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    next_seq: Mutex<u64>,
+}
+
+impl PolicyEngine {
+    /// Open the decision log in `storage`
+    pub async fn open(config: PolicyConfig, storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let next_seq = storage
+            .scan_prefix(&ns, DECISION_PREFIX)
+            .await?
+            .last()
+            .map(|(_, bytes)| serde_json::from_slice::<Decision>(bytes))
+            .transpose()?
+            .map_or(0, |d| d.seq + 1);
+        Ok(Self {
+            config,
+            rules: RwLock::new(Vec::new()),
+            storage,
+            ns,
+            next_seq: Mutex::new(next_seq),
+        })
+    }
+
+    /// Replace the rule set
+    pub async fn set_rules(&self, rules: Vec<PolicyRule>) -> AnyaResult<()> {
+        for (i, rule) in rules.iter().enumerate() {
+            if rule.name.is_empty() || rule.actions.is_empty() {
+                return Err(AnyaError::invalid_input(format!(
+                    "rule {} needs a name and at least one action",
+                    i
+                )));
+            }
+            if rules[..i].iter().any(|r| r.name == rule.name) {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    format!("duplicate rule {}", rule.name),
+                ));
+            }
+        }
+        *self.rules.write().await = rules;
+        Ok(())
+    }
+
+    /// Parse and install a JSON array of rules
+    pub async fn load_json(&self, json: &str) -> AnyaResult<()> {
+        let rules: Vec<PolicyRule> = serde_json::from_str(json)
+            .map_err(|e| AnyaError::with_source(ErrorCode::Config, "invalid policy rules", e))?;
+        self.set_rules(rules).await
+    }
+
+    /// Rules broken by `action`, without logging
+    pub async fn evaluate(&self, action: &SystemAction) -> Vec<Violation> {
+        let approvers = {
+            let mut approvers: Vec<&String> = action
+                .approvals
+                .iter()
+                .filter(|a| **a != action.actor)
+                .collect();
+            approvers.sort();
+            approvers.dedup();
+            approvers.len()
+        };
+        self.rules
+            .read()
+            .await
+            .iter()
+            .filter(|rule| rule.covers(&action.kind))
+            .filter(|rule| rule.when.iter().all(|c| c.holds(&action.attributes)))
+            .filter(|rule| match rule.effect {
+                Effect::Deny => true,
+                Effect::RequireApprovals(required) => approvers < required,
+            })
+            .map(|rule| Violation {
+                rule: rule.name.clone(),
+                message: rule.message.clone(),
+            })
+            .collect()
+    }
+
+    /// Evaluate and log `action`. Fails with `PermissionDenied` if a rule
+    /// blocks it, unless in dry-run mode.
+    pub async fn enforce(&self, action: &SystemAction) -> AnyaResult<Decision> {
+        let violations = self.evaluate(action).await;
+        let mut next_seq = self.next_seq.lock().await;
+        let decision = Decision {
+            seq: *next_seq,
+            timestamp: unix_now(),
+            action: action.clone(),
+            allowed: violations.is_empty() || self.config.dry_run,
+            violations,
+            dry_run: self.config.dry_run,
+        };
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{:016x}", DECISION_PREFIX, decision.seq),
+                &serde_json::to_vec(&decision)?,
+            )
+            .await?;
+        *next_seq += 1;
+        drop(next_seq);
+
+        if !decision.violations.is_empty() {
+            tracing::warn!(
+                action = %action.kind,
+                actor = %action.actor,
+                rules = ?decision.violations.iter().map(|v| &v.rule).collect::<Vec<_>>(),
+                dry_run = self.config.dry_run,
+                "policy violation"
+            );
+        }
+        if decision.allowed {
+            return Ok(decision);
+        }
+        let reasons: Vec<&str> = decision
+            .violations
+            .iter()
+            .map(|v| v.message.as_str())
+            .collect();
+        Err(AnyaError::new(
+            ErrorCode::PermissionDenied,
+            format!("{} denied: {}", action.kind, reasons.join("; ")),
+        ))
+    }
+
+    /// Logged decisions from `from_seq` on, oldest first
+    pub async fn decisions(&self, from_seq: u64, limit: usize) -> AnyaResult<Vec<Decision>> {
+        let mut decisions = Vec::new();
+        for (_, bytes) in self.storage.scan_prefix(&self.ns, DECISION_PREFIX).await? {
+            let decision: Decision = serde_json::from_slice(&bytes)?;
+            if decision.seq >= from_seq {
+                decisions.push(decision);
+            }
+            if decisions.len() == limit {
+                break;
+            }
+        }
+        Ok(decisions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use serde_json::json;
+
+    const RULES: &str = r#"[
+        {
+            "name": "treasury-approvals",
+            "actions": ["treasury.*"],
+            "when": [{"field": "amount_sat", "op": "gt", "value": 100000000}],
+            "effect": {"require_approvals": 2},
+            "message": "treasury actions above 1 BTC need two approvals"
+        },
+        {
+            "name": "model-safety",
+            "actions": ["model.promote"],
+            "when": [{"field": "metrics.safety_score", "op": "lt", "value": 0.9}],
+            "effect": "deny",
+            "message": "safety score below 0.9"
+        }
+    ]"#;
+
+    fn action(kind: &str, attributes: Value, approvals: &[&str]) -> SystemAction {
+        SystemAction {
+            kind: kind.into(),
+            actor: "ops".into(),
+            attributes,
+            approvals: approvals.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rules_block_actions_and_log_decisions() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let engine = PolicyEngine::open(PolicyConfig::default(), storage.clone())
+            .await
+            .unwrap();
+        engine.load_json(RULES).await.unwrap();
+
+        let transfer = |approvals: &[&str]| {
+            action(
+                "treasury.transfer",
+                json!({"amount_sat": 150_000_000}),
+                approvals,
+            )
+        };
+        // The requester's own approval and duplicates do not count
+        let err = engine
+            .enforce(&transfer(&["ops", "alice", "alice"]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        assert!(engine.enforce(&transfer(&["alice", "bob"])).await.is_ok());
+        assert!(engine
+            .enforce(&action(
+                "treasury.transfer",
+                json!({"amount_sat": 5_000}),
+                &[]
+            ))
+            .await
+            .is_ok());
+        assert!(engine
+            .enforce(&action(
+                "model.promote",
+                json!({"metrics": {"safety_score": 0.85}}),
+                &[]
+            ))
+            .await
+            .is_err());
+        assert!(engine
+            .enforce(&action(
+                "model.promote",
+                json!({"metrics": {"safety_score": 0.95}}),
+                &[]
+            ))
+            .await
+            .is_ok());
+        // `treasury.*` does not cover `treasury_report`
+        assert!(engine
+            .evaluate(&action("treasury_report", json!({"amount_sat": 1e12}), &[]))
+            .await
+            .is_empty());
+
+        let reopened = PolicyEngine::open(PolicyConfig::default(), storage)
+            .await
+            .unwrap();
+        let log = reopened.decisions(0, 100).await.unwrap();
+        assert_eq!(log.len(), 5);
+        assert_eq!(
+            log.iter().map(|d| d.allowed).collect::<Vec<_>>(),
+            vec![false, true, true, false, true]
+        );
+        assert_eq!(log[3].violations[0].rule, "model-safety");
+        assert_eq!(reopened.decisions(4, 100).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_and_rule_validation() {
+        let engine = PolicyEngine::open(
+            PolicyConfig { dry_run: true },
+            Arc::new(MemoryBackend::new()),
+        )
+        .await
+        .unwrap();
+        engine.load_json(RULES).await.unwrap();
+        let decision = engine
+            .enforce(&action(
+                "model.promote",
+                json!({"metrics": {"safety_score": 0.2}}),
+                &[],
+            ))
+            .await
+            .unwrap();
+        assert!(decision.allowed && decision.dry_run);
+        assert_eq!(decision.violations.len(), 1);
+
+        let missing = Condition {
+            field: "metrics.safety_score".into(),
+            op: Operator::Missing,
+            value: Value::Null,
+        };
+        assert!(missing.holds(&json!({"metrics": {}})));
+        assert!(!missing.holds(&json!({"metrics": {"safety_score": 1.0}})));
+
+        assert_eq!(
+            engine
+                .load_json("[{\"name\": \"x\"}]")
+                .await
+                .unwrap_err()
+                .code(),
+            ErrorCode::Config
+        );
+        let duplicate = format!(
+            "[{0}, {0}]",
+            r#"{"name": "a", "actions": ["*"], "effect": "deny", "message": "no"}"#
+        );
+        assert_eq!(
+            engine.load_json(&duplicate).await.unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+    }
+}
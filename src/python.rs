@@ -0,0 +1,255 @@
+//! Python bindings for notebooks
+//!
+//! Build with `maturin develop --features python,pyo3/extension-module` to
+//! import `anya_core` from Python. [`FeeMarket`](PyFeeMarket) records
+//! mempool snapshots and returns the same fee market report as the
+//! analytics gRPC service; [`FederatedCoordinator`](PyFederatedCoordinator)
+//! registers participants, selects them for rounds, and aggregates their
+//! updates against a validation loss written in Python. Structured values
+//! are passed as JSON strings, and failures raise `ValueError` for invalid
+//! input and `RuntimeError` otherwise, carrying the error code in their
+//! message.
+
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::runtime::Runtime;
+
+use crate::error::ErrorCategory;
+use crate::ml::federated::{
+    FederatedConfig, FederatedCoordinator, ModelUpdate, SelectionStrategy, Validator,
+};
+use crate::ml::fee_market::{FeeMarket, FeeMarketConfig, MempoolSnapshot};
+use crate::storage::{open_backend, StorageConfig};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult};
+
+const MAX_HORIZON_HOURS: u32 = 7 * 24;
+
+fn py<T>(result: AnyaResult<T>) -> PyResult<T> {
+    result.map_err(|e| match e.code().category() {
+        ErrorCategory::Validation => PyValueError::new_err(e.to_string()),
+        _ => PyRuntimeError::new_err(e.to_string()),
+    })
+}
+
+fn from_json<T: DeserializeOwned + Default>(json: Option<&str>) -> AnyaResult<T> {
+    json.map_or_else(|| Ok(T::default()), |json| Ok(serde_json::from_str(json)?))
+}
+
+fn to_json<T: Serialize>(value: &T) -> AnyaResult<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+/// Rolling fee market history and the analytics derived from it
+#[pyclass(name = "FeeMarket", module = "anya_core")]
+pub struct PyFeeMarket {
+    market: FeeMarket,
+}
+
+#[pymethods]
+impl PyFeeMarket {
+    /// Empty history, with an optional JSON `FeeMarketConfig`
+    #[new]
+    #[pyo3(signature = (config = None))]
+    fn new(config: Option<&str>) -> PyResult<Self> {
+        let config: FeeMarketConfig = py(from_json(config))?;
+        Ok(Self {
+            market: FeeMarket::new(config),
+        })
+    }
+
+    /// Record a JSON mempool snapshot, `{"timestamp": ..., "buckets":
+    /// [{"fee_rate": ..., "vsize": ...}]}`
+    fn record(&self, snapshot: &str) -> PyResult<()> {
+        let snapshot: MempoolSnapshot = py(serde_json::from_str(snapshot).map_err(Into::into))?;
+        self.market.record(snapshot);
+        Ok(())
+    }
+
+    /// Fee percentile time series since `since` as JSON
+    fn series(&self, since: u64) -> PyResult<String> {
+        py(to_json(&self.market.series(since)))
+    }
+
+    /// Percentiles, forecast over `horizon_hours` and the `windows`
+    /// cheapest upcoming hours as JSON, as of `now` or the current time
+    #[pyo3(signature = (horizon_hours = 24, windows = 3, now = None))]
+    fn report(&self, horizon_hours: u32, windows: usize, now: Option<u64>) -> PyResult<String> {
+        let horizon = u64::from(horizon_hours.min(MAX_HORIZON_HOURS)) * 3_600;
+        let report = self.market.report(
+            now.unwrap_or_else(unix_now),
+            Duration::from_secs(horizon),
+            windows,
+        );
+        py(to_json(&report))
+    }
+}
+
+/// Validation loss computed by a Python callable
+struct PyValidator {
+    loss: PyObject,
+    /// First exception raised by the callable
+    error: Mutex<Option<PyErr>>,
+}
+
+impl Validator for PyValidator {
+    fn loss(&self, weights: &[f64]) -> f64 {
+        Python::with_gil(|py| self.loss.call1(py, (weights.to_vec(),))?.extract(py)).unwrap_or_else(
+            |e| {
+                self.error
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get_or_insert(e);
+                f64::NAN
+            },
+        )
+    }
+}
+
+/// Federated learning coordinator
+#[pyclass(name = "FederatedCoordinator", module = "anya_core")]
+pub struct PyFederatedCoordinator {
+    runtime: Runtime,
+    coordinator: FederatedCoordinator,
+}
+
+#[pymethods]
+impl PyFederatedCoordinator {
+    /// Coordinator with an optional JSON `FederatedConfig`, keeping its
+    /// registry in the backend of an optional JSON `StorageConfig`, in
+    /// memory by default
+    #[new]
+    #[pyo3(signature = (config = None, storage = None))]
+    fn new(config: Option<&str>, storage: Option<&str>) -> PyResult<Self> {
+        let config: FederatedConfig = py(from_json(config))?;
+        let storage: StorageConfig = py(from_json(storage))?;
+        let runtime = py(tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(AnyaError::from))?;
+        let coordinator = py(runtime.block_on(async {
+            let backend = open_backend(&storage).await?;
+            FederatedCoordinator::open(config, backend, Vec::new()).await
+        }))?;
+        Ok(Self {
+            runtime,
+            coordinator,
+        })
+    }
+
+    /// Register a participant, or update its Lightning address; returns the
+    /// participant as JSON
+    #[pyo3(signature = (id, lightning_address = None))]
+    fn register(&self, id: &str, lightning_address: Option<String>) -> PyResult<String> {
+        py(self
+            .runtime
+            .block_on(self.coordinator.register(id, lightning_address))
+            .and_then(|p| to_json(&p)))
+    }
+
+    /// Registered participants as JSON
+    fn participants(&self) -> PyResult<String> {
+        py(self
+            .runtime
+            .block_on(self.coordinator.participants())
+            .and_then(|p| to_json(&p)))
+    }
+
+    /// Up to `count` participants for a round as JSON, picked `"random"`ly
+    /// or `"reputation_weighted"`
+    #[pyo3(signature = (count, strategy = "reputation_weighted"))]
+    fn select(&self, count: usize, strategy: &str) -> PyResult<String> {
+        let strategy: SelectionStrategy = py(serde_json::from_value(strategy.into())
+            .map_err(|_| AnyaError::invalid_input(format!("unknown strategy {}", strategy))))?;
+        py(self
+            .runtime
+            .block_on(self.coordinator.select(count, strategy))
+            .and_then(|p| to_json(&p)))
+    }
+
+    /// Aggregate JSON model `updates` on top of `current` weights, scoring
+    /// them with `loss`, a callable from a list of weights to a float.
+    /// Returns the round result as JSON.
+    fn aggregate(&self, current: Vec<f64>, updates: &str, loss: PyObject) -> PyResult<String> {
+        let updates: Vec<ModelUpdate> = py(serde_json::from_str(updates).map_err(Into::into))?;
+        let validator = PyValidator {
+            loss,
+            error: Mutex::new(None),
+        };
+        let result = self
+            .runtime
+            .block_on(self.coordinator.aggregate(&current, &updates, &validator));
+        let error = validator
+            .error
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(e) = error {
+            return Err(e);
+        }
+        py(result.and_then(|r| to_json(&r)))
+    }
+}
+
+/// The `anya_core` Python module
+#[pymodule]
+fn anya_core(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyFeeMarket>()?;
+    m.add_class::<PyFederatedCoordinator>()?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use pyo3::types::PyDict;
+
+    const SESSION: &str = r#"
+import json
+fees = anya.FeeMarket()
+fees.record(json.dumps({"timestamp": 1700000000, "buckets": [
+    {"fee_rate": 5.0, "vsize": 600000}, {"fee_rate": 20.0, "vsize": 400000}]}))
+report = json.loads(fees.report(now=1700000060))
+assert report["current"]["p50"] == 5.0, report
+
+fed = anya.FederatedCoordinator()
+for name in ("alice", "bob"):
+    fed.register(name)
+assert len(json.loads(fed.select(2, "random"))) == 2
+updates = [{"participant": "alice", "weights": [1.0], "samples": 10},
+           {"participant": "bob", "weights": [3.0], "samples": 10}]
+result = json.loads(fed.aggregate([0.0], json.dumps(updates), lambda w: (w[0] - 2.0) ** 2))
+assert result["weights"] == [2.0], result
+
+try:
+    fed.select(1, "loudest")
+    raise AssertionError("unknown strategy accepted")
+except ValueError:
+    pass
+try:
+    fed.aggregate([0.0], json.dumps(updates), lambda w: 1 / 0)
+    raise AssertionError("loss error swallowed")
+except ZeroDivisionError:
+    pass
+"#;
+
+    #[test]
+    fn test_notebook_session() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "anya_core").unwrap();
+            anya_core(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("anya", module).unwrap();
+            py.run(&CString::new(SESSION).unwrap(), None, Some(&locals))
+                .unwrap();
+        });
+    }
+}
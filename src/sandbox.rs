@@ -0,0 +1,378 @@
+//! Sandboxed execution of custom actions and plugins
+//!
+//! Custom business-rule actions, integrations, and ML plugins are untrusted
+//! code, so they run out of process. A plugin is an executable described by
+//! a [`PluginManifest`]: it reads one JSON document from stdin and writes one
+//! to stdout.
+//!
+//! The [`Sandbox`] refuses plugins that ask for a [`Capability`] its
+//! [`SandboxPolicy`] does not grant, then runs them with:
+//!
+//! - an empty environment apart from `PATH` and granted variables,
+//! - a fresh scratch directory as working directory, removed afterwards,
+//! - address-space and CPU-time limits (`ulimit`, Unix only),
+//! - a wall-clock timeout after which the process is killed,
+//! - bounded stdout and stderr.
+//!
+//! Granted paths are passed to the plugin in `ANYA_SANDBOX_READ` and
+//! `ANYA_SANDBOX_WRITE` and network access in `ANYA_SANDBOX_NETWORK`; the
+//! sandbox does not confine file or socket access at the OS level, so those
+//! capabilities are only as strong as the plugin's honesty about them.
+
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+/// Something a plugin may be allowed to do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Open network connections
+    Network,
+    /// Read below a path
+    Read(PathBuf),
+    /// Read and write below a path
+    Write(PathBuf),
+    /// See an environment variable of the host
+    Env(String),
+}
+
+impl Capability {
+    /// Whether granting `self` also grants `requested`
+    fn covers(&self, requested: &Self) -> bool {
+        match (self, requested) {
+            (Self::Network, Self::Network) => true,
+            (Self::Write(granted), Self::Read(path) | Self::Write(path))
+            | (Self::Read(granted), Self::Read(path)) => path.starts_with(granted),
+            (Self::Env(granted), Self::Env(name)) => granted == name,
+            _ => false,
+        }
+    }
+}
+
+/// A plugin executable and what it needs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginManifest {
+    /// Plugin name
+    pub name: String,
+    /// Executable
+    pub program: PathBuf,
+    /// Arguments
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Capabilities requested
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+/// Limits and grants of a sandbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxPolicy {
+    /// Capabilities plugins may request
+    pub allowed: Vec<Capability>,
+    /// Wall-clock limit per run
+    pub timeout: Duration,
+    /// Address-space limit, in bytes
+    pub memory_limit: Option<u64>,
+    /// Largest accepted output, in bytes
+    pub max_output: usize,
+}
+
+impl Default for SandboxPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: Vec::new(),
+            timeout: Duration::from_secs(10),
+            memory_limit: Some(256 * 1024 * 1024),
+            max_output: 1024 * 1024,
+        }
+    }
+}
+
+/// Runs plugins under a [`SandboxPolicy`]
+pub struct Sandbox {
+    policy: SandboxPolicy,
+}
+
+impl Sandbox {
+    /// Create a sandbox enforcing `policy`
+    pub const fn new(policy: SandboxPolicy) -> Self {
+        Self { policy }
+    }
+
+    /// Fail with `PermissionDenied` unless every requested capability is
+    /// granted
+    pub fn check(&self, manifest: &PluginManifest) -> AnyaResult<()> {
+        let denied: Vec<String> = manifest
+            .capabilities
+            .iter()
+            .filter(|c| !self.policy.allowed.iter().any(|a| a.covers(c)))
+            .map(|c| format!("{:?}", c))
+            .collect();
+        if denied.is_empty() {
+            return Ok(());
+        }
+        Err(AnyaError::new(
+            ErrorCode::PermissionDenied,
+            format!(
+                "plugin {} requests capabilities outside the sandbox policy: {}",
+                manifest.name,
+                denied.join(", ")
+            ),
+        ))
+    }
+
+    /// Run `manifest` with `input` on stdin and return its JSON output
+    pub async fn run(&self, manifest: &PluginManifest, input: &Value) -> AnyaResult<Value> {
+        self.check(manifest)?;
+        let scratch = std::env::temp_dir().join(format!(
+            "anya-sandbox-{}-{}",
+            manifest
+                .name
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+            unix_nanos()
+        ));
+        tokio::fs::create_dir_all(&scratch)
+            .await
+            .context("creating sandbox directory")?;
+        let result = self.execute(manifest, input, &scratch).await;
+        if let Err(e) = tokio::fs::remove_dir_all(&scratch).await {
+            tracing::warn!(plugin = %manifest.name, error = %e, "sandbox cleanup failed");
+        }
+        result
+    }
+
+    async fn execute(
+        &self,
+        manifest: &PluginManifest,
+        input: &Value,
+        scratch: &std::path::Path,
+    ) -> AnyaResult<Value> {
+        let mut command = self.command(manifest);
+        command
+            .env_clear()
+            .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+            .env("HOME", scratch)
+            .env("TMPDIR", scratch)
+            .current_dir(scratch)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let (mut read, mut write) = (Vec::new(), Vec::new());
+        for capability in &manifest.capabilities {
+            match capability {
+                Capability::Network => {
+                    command.env("ANYA_SANDBOX_NETWORK", "1");
+                }
+                Capability::Read(path) => read.push(path.display().to_string()),
+                Capability::Write(path) => write.push(path.display().to_string()),
+                Capability::Env(name) => {
+                    if let Ok(value) = std::env::var(name) {
+                        command.env(name, value);
+                    }
+                }
+            }
+        }
+        command
+            .env("ANYA_SANDBOX_READ", read.join(":"))
+            .env("ANYA_SANDBOX_WRITE", write.join(":"));
+
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("starting plugin {}", manifest.name))?;
+        let (Some(mut stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(AnyaError::new(ErrorCode::Internal, "plugin pipes missing"));
+        };
+        let input = serde_json::to_vec(input)?;
+        let limit = self.policy.max_output;
+        let run = async {
+            // A plugin may exit without reading its input
+            let write = async {
+                let _ = stdin.write_all(&input).await;
+                drop(stdin);
+            };
+            let ((), stdout, stderr, status) = tokio::join!(
+                write,
+                read_bounded(stdout, limit),
+                read_bounded(stderr, 4096),
+                child.wait()
+            );
+            Ok::<_, AnyaError>((stdout?, stderr?, status?))
+        };
+        let Ok(finished) = tokio::time::timeout(self.policy.timeout, run).await else {
+            // Dropping the child kills it
+            return Err(AnyaError::new(
+                ErrorCode::Timeout,
+                format!(
+                    "plugin {} exceeded {:?}",
+                    manifest.name, self.policy.timeout
+                ),
+            ));
+        };
+        let ((stdout, stdout_truncated), (stderr, _), status) = finished?;
+        if !status.success() {
+            return Err(AnyaError::new(
+                ErrorCode::Internal,
+                format!(
+                    "plugin {} failed with {}: {}",
+                    manifest.name,
+                    status,
+                    String::from_utf8_lossy(&stderr).trim()
+                ),
+            ));
+        }
+        if stdout_truncated {
+            return Err(AnyaError::invalid_input(format!(
+                "plugin {} wrote more than {} bytes",
+                manifest.name, limit
+            )));
+        }
+        serde_json::from_slice(&stdout).map_err(|e| {
+            AnyaError::with_source(
+                ErrorCode::Serialization,
+                format!("plugin {} wrote invalid JSON", manifest.name),
+                e,
+            )
+        })
+    }
+
+    /// The plugin command, wrapped in a shell applying resource limits
+    fn command(&self, manifest: &PluginManifest) -> Command {
+        #[cfg(unix)]
+        {
+            let mut limits = vec![format!(
+                "ulimit -t {}",
+                self.policy.timeout.as_secs().max(1)
+            )];
+            if let Some(bytes) = self.policy.memory_limit {
+                limits.push(format!("ulimit -v {}", (bytes / 1024).max(1)));
+            }
+            let mut command = Command::new("/bin/sh");
+            command
+                .arg("-c")
+                .arg(format!("{} && exec \"$0\" \"$@\"", limits.join(" && ")))
+                .arg(&manifest.program)
+                .args(&manifest.args);
+            command
+        }
+        #[cfg(not(unix))]
+        {
+            let mut command = Command::new(&manifest.program);
+            command.args(&manifest.args);
+            command
+        }
+    }
+}
+
+/// Read at most `limit` bytes; the flag tells whether there was more
+async fn read_bounded(
+    reader: impl AsyncRead + Unpin,
+    limit: usize,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut buf = Vec::new();
+    let cap = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+    let mut reader = reader.take(cap);
+    reader.read_to_end(&mut buf).await?;
+    // Drain the rest so the plugin does not block on a full pipe
+    tokio::io::copy(&mut reader.into_inner(), &mut tokio::io::sink()).await?;
+    let truncated = buf.len() > limit;
+    buf.truncate(limit);
+    Ok((buf, truncated))
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn script(name: &str, body: &str, capabilities: Vec<Capability>) -> PluginManifest {
+        PluginManifest {
+            name: name.into(),
+            program: "/bin/sh".into(),
+            args: vec!["-c".into(), body.into()],
+            capabilities,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugin_runs_isolated() {
+        std::env::set_var("ANYA_SANDBOX_TEST_SECRET", "hunter2");
+        std::env::set_var("ANYA_SANDBOX_TEST_REGION", "eu");
+        let sandbox = Sandbox::new(SandboxPolicy {
+            allowed: vec![
+                Capability::Env("ANYA_SANDBOX_TEST_REGION".into()),
+                Capability::Write("/var/lib/anya".into()),
+            ],
+            ..SandboxPolicy::default()
+        });
+        let plugin = script(
+            "echo",
+            r#"read input; printf '{"input": %s, "secret": "%s", "region": "%s", "read": "%s", "cwd": "%s"}' "$input" "$ANYA_SANDBOX_TEST_SECRET" "$ANYA_SANDBOX_TEST_REGION" "$ANYA_SANDBOX_READ" "$PWD""#,
+            vec![
+                Capability::Env("ANYA_SANDBOX_TEST_REGION".into()),
+                Capability::Read("/var/lib/anya/models".into()),
+            ],
+        );
+        let output = sandbox.run(&plugin, &json!({"amount": 5})).await.unwrap();
+        assert_eq!(output["input"], json!({"amount": 5}));
+        assert_eq!(output["secret"], "");
+        assert_eq!(output["region"], "eu");
+        assert_eq!(output["read"], "/var/lib/anya/models");
+        let cwd = PathBuf::from(output["cwd"].as_str().unwrap());
+        assert!(cwd.starts_with(std::env::temp_dir()));
+        assert!(!cwd.exists());
+
+        let greedy = script(
+            "greedy",
+            "true",
+            vec![Capability::Network, Capability::Write("/etc".into())],
+        );
+        let err = sandbox.run(&greedy, &json!({})).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        assert!(err.to_string().contains("Network"));
+    }
+
+    #[tokio::test]
+    async fn test_limits_are_enforced() {
+        let sandbox = Sandbox::new(SandboxPolicy {
+            timeout: Duration::from_millis(200),
+            max_output: 64,
+            ..SandboxPolicy::default()
+        });
+        let sleeper = script("sleeper", "sleep 5; echo '{}'", Vec::new());
+        let started = std::time::Instant::now();
+        let err = sandbox.run(&sleeper, &json!({})).await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Timeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let chatty = script("chatty", "yes | head -c 100000", Vec::new());
+        assert!(sandbox.run(&chatty, &json!({})).await.is_err());
+
+        let failing = script("failing", "echo broken >&2; exit 3", Vec::new());
+        let err = sandbox.run(&failing, &json!({})).await.unwrap_err();
+        assert!(err.to_string().contains("broken"), "{}", err);
+
+        let garbage = script("garbage", "echo not-json", Vec::new());
+        assert_eq!(
+            sandbox.run(&garbage, &json!({})).await.unwrap_err().code(),
+            ErrorCode::Serialization
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Chain client
+
+use std::sync::Arc;
+
+use ::bitcoin::{Transaction, Txid};
+
+use crate::bitcoin::tracker::{ChainSource, TxStatus};
+use crate::AnyaResult;
+
+/// Chain lookups through a node, Electrum, or Esplora backend
+#[derive(Clone)]
+pub struct ChainClient {
+    source: Arc<dyn ChainSource>,
+}
+
+impl ChainClient {
+    /// Query `source`
+    pub fn new(source: Arc<dyn ChainSource>) -> Self {
+        Self { source }
+    }
+
+    /// Height of the best block
+    pub async fn tip_height(&self) -> AnyaResult<u32> {
+        self.source.tip_height().await
+    }
+
+    /// Fetch a transaction from the mempool or the chain
+    pub async fn transaction(&self, txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        self.source.transaction(txid).await
+    }
+
+    /// Where `txid` currently is
+    pub async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        self.source.status(txid).await
+    }
+
+    /// Confirmations of `txid`; 0 while unconfirmed or unknown
+    pub async fn confirmations(&self, txid: &Txid) -> AnyaResult<u32> {
+        match self.source.status(txid).await? {
+            TxStatus::Confirmed { height } => {
+                let tip = self.source.tip_height().await?;
+                Ok(tip.saturating_sub(height).saturating_add(1))
+            }
+            TxStatus::Mempool | TxStatus::Unknown => Ok(0),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Identity client
+
+use ::bitcoin::base64;
+use serde_json::{json, Value};
+
+use crate::web5::auth::{verify_did_signature, DidSigner};
+use crate::web5::credential::{self, VerifiedCredential};
+use crate::web5::dwn::DwnRecord;
+use crate::{AnyaError, AnyaResult};
+
+/// A credential to issue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialRequest {
+    /// Subject DID
+    pub subject: String,
+    /// Credential types besides `VerifiableCredential`
+    pub types: Vec<String>,
+    /// Claims about the subject, a JSON object
+    pub claims: Value,
+    /// Expiry, seconds since the Unix epoch
+    pub expires_at: Option<u64>,
+}
+
+/// A `did:key` identity that signs, issues credentials, and writes DWN
+/// records
+pub struct IdentityClient {
+    signer: DidSigner,
+}
+
+impl IdentityClient {
+    /// Identity for a new random key
+    pub fn generate() -> AnyaResult<Self> {
+        Ok(Self {
+            signer: DidSigner::generate()?,
+        })
+    }
+
+    /// Identity for the Ed25519 key derived from `seed`
+    pub fn from_seed(seed: &[u8; 32]) -> AnyaResult<Self> {
+        Ok(Self {
+            signer: DidSigner::from_seed(seed)?,
+        })
+    }
+
+    /// The identity's `did:key` DID
+    pub fn did(&self) -> String {
+        self.signer.did().to_string()
+    }
+
+    /// Ed25519 signature over `message`
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.signer.sign(message)
+    }
+
+    /// Check that `did` signed `message`
+    pub fn verify(did: &str, message: &[u8], signature: &[u8]) -> AnyaResult<()> {
+        verify_did_signature(did, message, signature)
+    }
+
+    /// Issue `request` as a JWT verifiable credential
+    pub fn issue_credential(
+        &self,
+        request: &CredentialRequest,
+        issued_at: u64,
+    ) -> AnyaResult<String> {
+        let Value::Object(mut subject) = request.claims.clone() else {
+            return Err(AnyaError::invalid_input(
+                "credential claims must be an object",
+            ));
+        };
+        subject.insert("id".into(), Value::String(request.subject.clone()));
+        let mut types = vec!["VerifiableCredential".to_string()];
+        types.extend(request.types.iter().cloned());
+        let did = self.did();
+        let mut claims = json!({
+            "iss": did,
+            "sub": request.subject,
+            "iat": issued_at,
+            "nbf": issued_at,
+            "vc": {
+                "@context": ["https://www.w3.org/2018/credentials/v1"],
+                "type": types,
+                "credentialSubject": subject,
+            },
+        });
+        if let Some(exp) = request.expires_at {
+            claims["exp"] = exp.into();
+        }
+        let header = json!({ "alg": "EdDSA", "typ": "JWT", "kid": format!("{}#0", did) });
+        let signing_input = format!("{}.{}", encode(&header)?, encode(&claims)?);
+        let signature = self.signer.sign(signing_input.as_bytes());
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        ))
+    }
+
+    /// Verify a JWT credential from any `did:key` issuer at `now`
+    pub fn verify_credential(jwt: &str, now: u64) -> AnyaResult<VerifiedCredential> {
+        credential::verify_jwt(jwt, now)
+    }
+
+    /// Sign `record` as its author
+    pub fn sign_record(&self, record: DwnRecord, date_created: u64) -> AnyaResult<DwnRecord> {
+        record.sign(&self.signer, date_created)
+    }
+}
+
+fn encode(value: &Value) -> AnyaResult<String> {
+    Ok(base64::encode_config(
+        serde_json::to_vec(value)?,
+        base64::URL_SAFE_NO_PAD,
+    ))
+}
@@ -0,0 +1,94 @@
+//! ML client
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::ml::anomaly::{AnomalyConfig, AnomalyDetector, FeatureContribution};
+use crate::ml::fee_market::{FeeMarket, FeeMarketConfig, FeeMarketReport, MempoolSnapshot};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// An observation flagged as anomalous
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct AnomalyScore {
+    /// Model score; higher is more anomalous
+    pub score: f64,
+    /// Features that deviated most, biggest first
+    pub contributions: Vec<FeatureContribution>,
+}
+
+/// Fee market analytics and anomaly detection over caller-supplied streams
+pub struct MlClient {
+    fees: FeeMarket,
+    anomaly: AnomalyConfig,
+    detectors: Mutex<HashMap<String, AnomalyDetector>>,
+}
+
+impl Default for MlClient {
+    fn default() -> Self {
+        Self::new(FeeMarketConfig::default(), AnomalyConfig::default())
+    }
+}
+
+impl MlClient {
+    /// Client with the given fee market and anomaly settings
+    pub fn new(fees: FeeMarketConfig, anomaly: AnomalyConfig) -> Self {
+        Self {
+            fees: FeeMarket::new(fees),
+            anomaly,
+            detectors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add a mempool fee histogram to the fee market history
+    pub fn record_mempool(&self, snapshot: MempoolSnapshot) {
+        self.fees.record(snapshot);
+    }
+
+    /// Current fee percentiles, history, congestion forecast over `horizon`,
+    /// and the `windows` cheapest upcoming hours to send
+    pub fn fee_report(&self, now: u64, horizon: Duration, windows: usize) -> FeeMarketReport {
+        self.fees.report(now, horizon, windows)
+    }
+
+    /// Start detecting anomalies in `stream`, whose observations carry
+    /// `features` in order
+    pub fn add_stream(&self, stream: &str, features: &[&str]) -> AnyaResult<()> {
+        let mut detectors = self
+            .detectors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if detectors.contains_key(stream) {
+            return Err(AnyaError::new(
+                ErrorCode::Conflict,
+                format!("stream {} already exists", stream),
+            ));
+        }
+        let features = features.iter().map(|f| (*f).to_string()).collect();
+        detectors.insert(
+            stream.to_string(),
+            AnomalyDetector::new(self.anomaly.clone(), features),
+        );
+        drop(detectors);
+        Ok(())
+    }
+
+    /// Score one observation of `stream`; the detector trains on normal
+    /// observations as they arrive
+    pub fn observe(&self, stream: &str, values: &[f64]) -> AnyaResult<Option<AnomalyScore>> {
+        let mut detectors = self
+            .detectors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let detector = detectors
+            .get_mut(stream)
+            .ok_or_else(|| AnyaError::not_found(format!("stream {}", stream)))?;
+        let flagged = detector.observe(values)?;
+        drop(detectors);
+        Ok(flagged.map(|(score, contributions)| AnomalyScore {
+            score,
+            contributions,
+        }))
+    }
+}
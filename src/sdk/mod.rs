@@ -0,0 +1,66 @@
+//! Stable high-level API
+//!
+//! The rest of the crate is organized around node internals and changes as
+//! they do. This module is the supported surface for applications embedding
+//! Anya: four clients covering the common tasks, plus the types their
+//! signatures use, all re-exported here.
+//!
+//! - [`WalletClient`]: HD accounts, receive addresses, balances, and unsigned
+//!   payments
+//! - [`ChainClient`]: tip, transaction, and confirmation lookups against any
+//!   [`ChainSource`]
+//! - [`IdentityClient`]: `did:key` identities, signatures, verifiable
+//!   credentials, and DWN records
+//! - [`MlClient`]: fee market analytics and streaming anomaly detection
+//!
+//! # Stability
+//!
+//! Everything reachable through `anya_core::sdk` follows semantic
+//! versioning: within a major version, items are not removed or renamed,
+//! function signatures do not change, and result structs only gain fields.
+//! Structs returned by the SDK are `#[non_exhaustive]` so that adding a
+//! field is not a breaking change. Types re-exported from other modules of
+//! this crate are covered while they are re-exported here; paths outside
+//! `sdk` carry no such guarantee. [`API_VERSION`] is raised with every
+//! breaking change to this module. `tests/sdk.rs` pins the surface.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use anya_core::sdk::{Network, ScriptType, StorageBackend, WalletClient};
+//! use anya_core::storage::memory::MemoryBackend;
+//!
+//! # async fn example(master: &anya_core::sdk::ExtendedPrivKey) -> anya_core::AnyaResult<()> {
+//! let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+//! let wallet = WalletClient::open(Network::Regtest, storage).await?;
+//! let account = wallet
+//!     .create_account(master, ScriptType::NativeSegwit, "savings")
+//!     .await?;
+//! let address = wallet.receive_address(account.id).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod chain;
+mod identity;
+mod ml;
+mod wallet;
+
+pub use ::bitcoin::bip32::ExtendedPrivKey;
+pub use ::bitcoin::psbt::Psbt;
+pub use ::bitcoin::{Address, Network, OutPoint, Transaction, Txid};
+
+pub use self::chain::ChainClient;
+pub use self::identity::{CredentialRequest, IdentityClient};
+pub use self::ml::{AnomalyScore, MlClient};
+pub use self::wallet::{AccountInfo, Payment, UnsignedTransaction, WalletBalance, WalletClient};
+pub use crate::bitcoin::accounts::{AccountId, KeyChain, ScriptType};
+pub use crate::bitcoin::tracker::{ChainSource, TxStatus};
+pub use crate::ml::anomaly::{AnomalyConfig, FeatureContribution};
+pub use crate::ml::fee_market::{FeeBucket, FeeMarketConfig, FeeMarketReport, MempoolSnapshot};
+pub use crate::storage::StorageBackend;
+pub use crate::web5::credential::VerifiedCredential;
+pub use crate::web5::dwn::DwnRecord;
+pub use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Version of the SDK surface, raised on every breaking change
+pub const API_VERSION: u32 = 1;
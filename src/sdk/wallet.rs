@@ -0,0 +1,220 @@
+//! Wallet client
+
+use std::sync::Arc;
+
+use ::bitcoin::address::NetworkUnchecked;
+use ::bitcoin::bip32::ExtendedPrivKey;
+use ::bitcoin::psbt::Psbt;
+use ::bitcoin::secp256k1::Secp256k1;
+use ::bitcoin::{Address, FeeRate, Network};
+
+use crate::bitcoin::accounts::{Account, AccountId, AccountManager, KeyChain, ScriptType};
+use crate::bitcoin::builder::{InputSelection, Recipient, TxBuilder, TxRequest};
+use crate::bitcoin::coins::CoinStore;
+use crate::storage::StorageBackend;
+use crate::{AnyaError, AnyaResult};
+
+/// An account as reported by [`WalletClient`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AccountInfo {
+    /// Account identifier
+    pub id: AccountId,
+    /// User-visible label
+    pub label: String,
+    /// Hex fingerprint of the master key
+    pub fingerprint: String,
+    /// Descriptor of the receive chain, for watch-only import elsewhere
+    pub receive_descriptor: String,
+    /// Descriptor of the change chain
+    pub change_descriptor: String,
+}
+
+impl From<Account> for AccountInfo {
+    fn from(account: Account) -> Self {
+        Self {
+            id: account.id,
+            receive_descriptor: account.descriptor(KeyChain::External),
+            change_descriptor: account.descriptor(KeyChain::Internal),
+            fingerprint: account.fingerprint.to_string(),
+            label: account.label,
+        }
+    }
+}
+
+/// Balance in satoshis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WalletBalance {
+    /// Confirmed, unlocked coins
+    pub confirmed_sat: u64,
+    /// Unlocked coins still in the mempool
+    pub unconfirmed_sat: u64,
+    /// Frozen or do-not-spend coins
+    pub locked_sat: u64,
+}
+
+/// An amount to pay to an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    /// Destination
+    pub address: Address,
+    /// Amount in satoshis
+    pub amount_sat: u64,
+}
+
+impl Payment {
+    /// Pay `amount_sat` to `address`
+    pub const fn new(address: Address, amount_sat: u64) -> Self {
+        Self {
+            address,
+            amount_sat,
+        }
+    }
+}
+
+/// A payment ready for signing
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct UnsignedTransaction {
+    /// BIP-174 PSBT with key origins for every input and the change output
+    pub psbt: Psbt,
+    /// Fee in satoshis
+    pub fee_sat: u64,
+    /// Index of the change output, if one was added
+    pub change_vout: Option<usize>,
+}
+
+/// HD wallet over a storage backend
+pub struct WalletClient {
+    network: Network,
+    accounts: AccountManager,
+    coins: CoinStore,
+}
+
+impl WalletClient {
+    /// Open the wallet for `network` kept in `storage`
+    pub async fn open(network: Network, storage: Arc<dyn StorageBackend>) -> AnyaResult<Self> {
+        Ok(Self {
+            network,
+            accounts: AccountManager::open(network, Arc::clone(&storage)).await?,
+            coins: CoinStore::open(storage).await?,
+        })
+    }
+
+    /// Network of the wallet
+    pub const fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Create the next BIP-44/49/84/86 account of `script_type`.
+    ///
+    /// Only the account's public key is stored; `master` is not kept.
+    pub async fn create_account(
+        &self,
+        master: &ExtendedPrivKey,
+        script_type: ScriptType,
+        label: &str,
+    ) -> AnyaResult<AccountInfo> {
+        self.accounts
+            .create_account(master, script_type, label)
+            .await
+            .map(AccountInfo::from)
+    }
+
+    /// All accounts
+    pub async fn accounts(&self) -> Vec<AccountInfo> {
+        self.accounts
+            .accounts()
+            .await
+            .into_iter()
+            .map(AccountInfo::from)
+            .collect()
+    }
+
+    /// Next unused receive address of `account`
+    pub async fn receive_address(&self, account: AccountId) -> AnyaResult<Address> {
+        self.accounts
+            .next_address(account, KeyChain::External)
+            .await
+            .map(|(_, address)| address)
+    }
+
+    /// Balance of `account`, or of the whole wallet when `None`
+    pub async fn balance(&self, account: Option<AccountId>) -> AnyaResult<WalletBalance> {
+        let mut balance = WalletBalance::default();
+        for coin in self.coins.coins(account).await? {
+            let value = coin.utxo.txout.value;
+            if coin.lock.is_some() {
+                balance.locked_sat += value;
+            } else if coin.utxo.height.is_some() {
+                balance.confirmed_sat += value;
+            } else {
+                balance.unconfirmed_sat += value;
+            }
+        }
+        Ok(balance)
+    }
+
+    /// Build an unsigned transaction paying `payments` from `account`'s
+    /// unlocked coins at `fee_rate_sat_vb`
+    pub async fn prepare_payment(
+        &self,
+        account: AccountId,
+        payments: &[Payment],
+        fee_rate_sat_vb: u64,
+    ) -> AnyaResult<UnsignedTransaction> {
+        if let Some(payment) = payments
+            .iter()
+            .find(|p| !valid_for_network(&p.address, self.network))
+        {
+            return Err(AnyaError::invalid_input(format!(
+                "{} is not a {} address",
+                payment.address, self.network
+            )));
+        }
+        let fee_rate = FeeRate::from_sat_per_vb(fee_rate_sat_vb)
+            .ok_or_else(|| AnyaError::invalid_input("fee rate out of range"))?;
+        let built = TxBuilder::new(&self.accounts, &self.coins)
+            .build(&TxRequest {
+                account,
+                recipients: payments
+                    .iter()
+                    .map(|p| Recipient::new(&p.address, p.amount_sat))
+                    .collect(),
+                fee_rate,
+                inputs: InputSelection::Auto,
+                shuffle_outputs: true,
+            })
+            .await?;
+        Ok(UnsignedTransaction {
+            psbt: built.psbt,
+            fee_sat: built.fee_sat,
+            change_vout: built.change_vout,
+        })
+    }
+
+    /// Sign the inputs of `tx` that `master` holds keys for, returning how
+    /// many were signed
+    pub fn sign(
+        &self,
+        tx: &mut UnsignedTransaction,
+        master: &ExtendedPrivKey,
+    ) -> AnyaResult<usize> {
+        tx.psbt
+            .sign(master, &Secp256k1::new())
+            .map(|signed| signed.len())
+            .map_err(|(_, errors)| {
+                AnyaError::invalid_input(format!("signing failed for {} inputs", errors.len()))
+            })
+    }
+}
+
+/// Whether `address` may be paid from `network`; testnet and signet share
+/// address formats, so this is looser than comparing `address.network`
+fn valid_for_network(address: &Address, network: Network) -> bool {
+    address
+        .to_string()
+        .parse::<Address<NetworkUnchecked>>()
+        .is_ok_and(|a| a.is_valid_for_network(network))
+}
@@ -0,0 +1,741 @@
+//! Login sessions with refresh tokens and device tracking
+//!
+//! [`SessionManager::login`] opens a session for a subject on a device and
+//! returns a short-lived access token, an EdDSA JWT signed with a
+//! [`KeyRing`] of kind [`KeyKind::JwtSigning`] so it survives key rotation,
+//! and a long-lived refresh token. Refresh tokens are single use: every
+//! refresh issues a new one, and presenting a spent token revokes the whole
+//! session, since only a stolen copy would be replayed.
+//!
+//! Every device a subject logs in from is kept in a registry, and sessions
+//! can be listed and revoked remotely, one at a time or all but the current
+//! one. Logins are checked for anomalies, a device never seen for the
+//! subject or travel between logins faster than an airliner, and anomalies
+//! are appended to the event log under [`SECURITY_TOPIC`] and passed to
+//! every registered [`AnomalyNotifier`], the same path SLA alerts take.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::events::EventStore;
+use crate::keys::{KeyKind, KeyRing};
+use crate::sla::Severity;
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Topic of session security events
+pub const SECURITY_TOPIC: &str = "security.sessions";
+/// Event kind of a suspicious login
+pub const LOGIN_ANOMALY: &str = "login_anomaly";
+/// Event kind of a revoked session
+pub const SESSION_REVOKED: &str = "session_revoked";
+
+const NAMESPACE: &str = "sessions";
+const SESSION_PREFIX: &str = "session/";
+const DEVICE_PREFIX: &str = "device/";
+const LAST_LOGIN_PREFIX: &str = "last_login/";
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Session settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionConfig {
+    /// Lifetime of an access token
+    pub access_ttl: Duration,
+    /// Lifetime of a session without a refresh
+    pub refresh_ttl: Duration,
+    /// Speed between two logins above which travel is impossible
+    pub max_travel_kmh: f64,
+    /// Distances below this are ignored as geolocation noise
+    pub min_travel_km: f64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            access_ttl: Duration::from_secs(15 * 60),
+            refresh_ttl: Duration::from_secs(30 * 24 * 60 * 60),
+            max_travel_kmh: 1000.0,
+            min_travel_km: 100.0,
+        }
+    }
+}
+
+/// Approximate location of a login, e.g. from IP geolocation
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    /// Latitude in degrees
+    pub lat: f64,
+    /// Longitude in degrees
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    /// Great-circle distance to `other` in kilometres
+    pub fn distance_km(&self, other: &Self) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (lat1.cos() * lat2.cos())
+            .mul_add((dlon / 2.0).sin().powi(2), (dlat / 2.0).sin().powi(2));
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// Where a login comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoginContext {
+    /// Authenticated subject, e.g. a DID or user id
+    pub subject: String,
+    /// Stable identifier of the client device
+    pub device_id: String,
+    /// Human-readable device name
+    pub device_name: String,
+    /// Client IP address
+    pub ip: String,
+    /// Client location, if known
+    pub location: Option<GeoPoint>,
+}
+
+/// A device a subject has logged in from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    /// Device identifier
+    pub device_id: String,
+    /// Human-readable name
+    pub name: String,
+    /// Unix time of the first login
+    pub first_seen: u64,
+    /// Unix time of the latest login
+    pub last_seen: u64,
+    /// IP address of the latest login
+    pub last_ip: String,
+}
+
+/// A login session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// Session identifier
+    pub id: String,
+    /// Subject the session belongs to
+    pub subject: String,
+    /// Device the session was opened on
+    pub device_id: String,
+    /// Unix time the session was opened
+    pub created_at: u64,
+    /// Unix time of the latest refresh
+    pub refreshed_at: u64,
+    /// Unix time the current refresh token expires
+    pub expires_at: u64,
+    /// Unix time the session was revoked
+    pub revoked_at: Option<u64>,
+    /// Why the session was revoked
+    pub revoked_reason: Option<String>,
+    refresh_hash: String,
+    /// Hashes of refresh tokens already exchanged
+    spent_hashes: Vec<String>,
+}
+
+impl Session {
+    /// Whether the session can still be used at `now`
+    pub const fn is_active(&self, now: u64) -> bool {
+        self.revoked_at.is_none() && now < self.expires_at
+    }
+}
+
+/// Tokens returned by a login or refresh
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenPair {
+    /// Session the tokens belong to
+    pub session_id: String,
+    /// JWT presented on every request
+    pub access_token: String,
+    /// Unix expiry of the access token
+    pub access_expires_at: u64,
+    /// Opaque single-use token exchanged for a new pair
+    pub refresh_token: String,
+    /// Unix expiry of the refresh token
+    pub refresh_expires_at: u64,
+}
+
+/// Verified claims of an access token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessClaims {
+    /// Subject
+    pub sub: String,
+    /// Session identifier
+    pub sid: String,
+    /// Device identifier
+    pub device: String,
+    /// Unix issue time
+    pub iat: u64,
+    /// Unix expiry
+    pub exp: u64,
+}
+
+/// A suspicious login or token use
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// First login from a device while the subject has others
+    NewDevice {
+        /// The new device
+        device_id: String,
+    },
+    /// Two logins further apart than could be travelled in between
+    ImpossibleTravel {
+        /// Distance between the logins in kilometres
+        distance_km: f64,
+        /// Implied speed in kilometres per hour
+        speed_kmh: f64,
+        /// Device of the previous login
+        previous_device: String,
+    },
+    /// A spent refresh token was presented again
+    RefreshReuse,
+}
+
+impl Anomaly {
+    /// How urgently the anomaly needs attention
+    pub const fn severity(&self) -> Severity {
+        match self {
+            Self::NewDevice { .. } => Severity::Ticket,
+            Self::ImpossibleTravel { .. } | Self::RefreshReuse => Severity::Page,
+        }
+    }
+}
+
+/// Payload of anomaly events and notifications
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnomalyEvent {
+    /// Affected subject
+    pub subject: String,
+    /// Affected session
+    pub session_id: String,
+    /// What was detected
+    pub anomaly: Anomaly,
+    /// Alert severity
+    pub severity: Severity,
+    /// Client IP address
+    pub ip: Option<String>,
+    /// Unix time of detection
+    pub timestamp: u64,
+}
+
+/// Receives login anomalies, e.g. to page an operator or warn the user
+#[async_trait]
+pub trait AnomalyNotifier: Send + Sync {
+    /// Deliver a detected anomaly
+    async fn notify(&self, event: &AnomalyEvent) -> AnyaResult<()>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LastLogin {
+    at: u64,
+    device_id: String,
+    location: Option<GeoPoint>,
+}
+
+/// Issues, refreshes, and revokes login sessions
+pub struct SessionManager {
+    config: SessionConfig,
+    keys: Arc<KeyRing>,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    events: Arc<EventStore>,
+    notifiers: Vec<Arc<dyn AnomalyNotifier>>,
+    rng: SystemRandom,
+    /// Serializes refreshes so a token cannot be exchanged twice
+    write: Mutex<()>,
+}
+
+impl SessionManager {
+    /// Open the session registry, signing access tokens with `keys`
+    pub async fn open(
+        config: SessionConfig,
+        keys: Arc<KeyRing>,
+        storage: Arc<dyn StorageBackend>,
+        events: Arc<EventStore>,
+    ) -> AnyaResult<Self> {
+        if keys.kind() != KeyKind::JwtSigning {
+            return Err(AnyaError::invalid_input(format!(
+                "key ring {} does not hold JWT signing keys",
+                keys.name()
+            )));
+        }
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Self {
+            config,
+            keys,
+            storage,
+            ns,
+            events,
+            notifiers: Vec::new(),
+            rng: SystemRandom::new(),
+            write: Mutex::new(()),
+        })
+    }
+
+    /// Add a notifier for anomalies
+    pub fn add_notifier(&mut self, notifier: Arc<dyn AnomalyNotifier>) {
+        self.notifiers.push(notifier);
+    }
+
+    /// Open a session for an authenticated subject.
+    ///
+    /// Anomalies are reported but do not block the login; callers that want
+    /// step-up authentication can inspect the returned list.
+    pub async fn login(
+        &self,
+        context: &LoginContext,
+        now: u64,
+    ) -> AnyaResult<(TokenPair, Vec<Anomaly>)> {
+        let _write = self.write.lock().await;
+        let subject = &context.subject;
+        let session_id = self.random_hex(16)?;
+        let anomalies = self.detect(context, now).await?;
+
+        let device_key = device_key(subject, &context.device_id);
+        let first_seen = self
+            .load::<Device>(&device_key)
+            .await?
+            .map_or(now, |d| d.first_seen);
+        let device = Device {
+            device_id: context.device_id.clone(),
+            name: context.device_name.clone(),
+            first_seen,
+            last_seen: now,
+            last_ip: context.ip.clone(),
+        };
+        self.store(&device_key, &device).await?;
+        self.store(
+            &format!("{}{}", LAST_LOGIN_PREFIX, subject),
+            &LastLogin {
+                at: now,
+                device_id: context.device_id.clone(),
+                location: context.location,
+            },
+        )
+        .await?;
+
+        let mut session = Session {
+            id: session_id,
+            subject: subject.clone(),
+            device_id: context.device_id.clone(),
+            created_at: now,
+            refreshed_at: now,
+            expires_at: 0,
+            revoked_at: None,
+            revoked_reason: None,
+            refresh_hash: String::new(),
+            spent_hashes: Vec::new(),
+        };
+        let tokens = self.issue(&mut session, now).await?;
+        for anomaly in &anomalies {
+            self.report(&session, anomaly.clone(), Some(&context.ip), now)
+                .await?;
+        }
+        Ok((tokens, anomalies))
+    }
+
+    /// Exchange a refresh token for a new token pair
+    pub async fn refresh(&self, refresh_token: &str, now: u64) -> AnyaResult<TokenPair> {
+        let _write = self.write.lock().await;
+        let invalid = || AnyaError::new(ErrorCode::Unauthenticated, "invalid refresh token");
+        let (session_id, _) = refresh_token.split_once('.').ok_or_else(invalid)?;
+        let mut session = self.session(session_id).await?.ok_or_else(invalid)?;
+        let hash = token_hash(refresh_token);
+
+        if session.spent_hashes.contains(&hash) {
+            if session.revoked_at.is_none() {
+                self.report(&session, Anomaly::RefreshReuse, None, now)
+                    .await?;
+                self.mark_revoked(&mut session, "refresh token reused", now)
+                    .await?;
+            }
+            return Err(invalid());
+        }
+        if session.refresh_hash != hash {
+            return Err(invalid());
+        }
+        if !session.is_active(now) {
+            return Err(AnyaError::new(
+                ErrorCode::Unauthenticated,
+                "session has expired or been revoked",
+            ));
+        }
+        session.spent_hashes.push(hash);
+        session.refreshed_at = now;
+        self.issue(&mut session, now).await
+    }
+
+    /// Verify an access token and check that its session is still active
+    pub async fn authenticate(&self, access_token: &str, now: u64) -> AnyaResult<AccessClaims> {
+        let claims: AccessClaims = serde_json::from_value(self.keys.verify_jwt(access_token)?)
+            .map_err(|_| AnyaError::new(ErrorCode::Unauthenticated, "malformed access token"))?;
+        if now >= claims.exp {
+            return Err(AnyaError::new(
+                ErrorCode::Unauthenticated,
+                "access token has expired",
+            ));
+        }
+        match self.session(&claims.sid).await? {
+            Some(session) if session.is_active(now) && session.subject == claims.sub => Ok(claims),
+            _ => Err(AnyaError::new(
+                ErrorCode::Unauthenticated,
+                "session has expired or been revoked",
+            )),
+        }
+    }
+
+    /// Revoke one session, e.g. from another device.
+    ///
+    /// Access tokens already issued stop authenticating immediately.
+    pub async fn revoke(&self, session_id: &str, reason: &str, now: u64) -> AnyaResult<()> {
+        let _write = self.write.lock().await;
+        let mut session = self
+            .session(session_id)
+            .await?
+            .ok_or_else(|| AnyaError::not_found(format!("session {}", session_id)))?;
+        if session.revoked_at.is_none() {
+            self.mark_revoked(&mut session, reason, now).await?;
+        }
+        Ok(())
+    }
+
+    /// Revoke every active session of `subject` except `keep`, returning
+    /// how many were revoked
+    pub async fn revoke_all(
+        &self,
+        subject: &str,
+        keep: Option<&str>,
+        reason: &str,
+        now: u64,
+    ) -> AnyaResult<usize> {
+        let _write = self.write.lock().await;
+        let mut revoked = 0;
+        for mut session in self.sessions(subject).await? {
+            if session.is_active(now) && keep != Some(session.id.as_str()) {
+                self.mark_revoked(&mut session, reason, now).await?;
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+
+    /// A session by id
+    pub async fn session(&self, session_id: &str) -> AnyaResult<Option<Session>> {
+        self.load(&format!("{}{}", SESSION_PREFIX, session_id))
+            .await
+    }
+
+    /// All sessions of `subject`, including expired and revoked ones
+    pub async fn sessions(&self, subject: &str) -> AnyaResult<Vec<Session>> {
+        let mut sessions = Vec::new();
+        for (_, bytes) in self.storage.scan_prefix(&self.ns, SESSION_PREFIX).await? {
+            let session: Session = serde_json::from_slice(&bytes)?;
+            if session.subject == subject {
+                sessions.push(session);
+            }
+        }
+        sessions.sort_by_key(|s| s.created_at);
+        Ok(sessions)
+    }
+
+    /// Devices `subject` has logged in from
+    pub async fn devices(&self, subject: &str) -> AnyaResult<Vec<Device>> {
+        self.storage
+            .scan_prefix(&self.ns, &device_key(subject, ""))
+            .await?
+            .iter()
+            .map(|(_, bytes)| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    /// Delete sessions that expired or were revoked before `cutoff`,
+    /// returning how many were removed
+    pub async fn prune(&self, cutoff: u64) -> AnyaResult<usize> {
+        let _write = self.write.lock().await;
+        let mut removed = 0;
+        for (key, bytes) in self.storage.scan_prefix(&self.ns, SESSION_PREFIX).await? {
+            let session: Session = serde_json::from_slice(&bytes)?;
+            if session.revoked_at.unwrap_or(session.expires_at) < cutoff {
+                self.storage.delete(&self.ns, &key).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn detect(&self, context: &LoginContext, now: u64) -> AnyaResult<Vec<Anomaly>> {
+        let mut anomalies = Vec::new();
+        let devices = self.devices(&context.subject).await?;
+        if !devices.is_empty() && !devices.iter().any(|d| d.device_id == context.device_id) {
+            anomalies.push(Anomaly::NewDevice {
+                device_id: context.device_id.clone(),
+            });
+        }
+
+        let last: Option<LastLogin> = self
+            .load(&format!("{}{}", LAST_LOGIN_PREFIX, context.subject))
+            .await?;
+        if let (Some(last), Some(here)) = (last, context.location) {
+            if let Some(there) = last.location {
+                let distance_km = here.distance_km(&there);
+                #[allow(clippy::cast_precision_loss)]
+                let hours = (now.saturating_sub(last.at) as f64 / 3600.0).max(1.0 / 60.0);
+                let speed_kmh = distance_km / hours;
+                if distance_km >= self.config.min_travel_km
+                    && speed_kmh > self.config.max_travel_kmh
+                {
+                    anomalies.push(Anomaly::ImpossibleTravel {
+                        distance_km,
+                        speed_kmh,
+                        previous_device: last.device_id,
+                    });
+                }
+            }
+        }
+        Ok(anomalies)
+    }
+
+    /// Issue a fresh token pair for `session` and persist it
+    async fn issue(&self, session: &mut Session, now: u64) -> AnyaResult<TokenPair> {
+        let refresh_token = format!("{}.{}", session.id, self.random_hex(32)?);
+        session.refresh_hash = token_hash(&refresh_token);
+        session.expires_at = now.saturating_add(self.config.refresh_ttl.as_secs());
+        self.store(&format!("{}{}", SESSION_PREFIX, session.id), session)
+            .await?;
+
+        let access_expires_at = now
+            .saturating_add(self.config.access_ttl.as_secs())
+            .min(session.expires_at);
+        let access_token = self.keys.sign_jwt(&json!({
+            "sub": session.subject,
+            "sid": session.id,
+            "device": session.device_id,
+            "iat": now,
+            "exp": access_expires_at,
+        }))?;
+        Ok(TokenPair {
+            session_id: session.id.clone(),
+            access_token,
+            access_expires_at,
+            refresh_token,
+            refresh_expires_at: session.expires_at,
+        })
+    }
+
+    async fn mark_revoked(&self, session: &mut Session, reason: &str, now: u64) -> AnyaResult<()> {
+        session.revoked_at = Some(now);
+        session.revoked_reason = Some(reason.to_string());
+        self.store(&format!("{}{}", SESSION_PREFIX, session.id), session)
+            .await?;
+        self.events
+            .append(
+                SECURITY_TOPIC,
+                SESSION_REVOKED,
+                json!({
+                    "subject": session.subject,
+                    "session_id": session.id,
+                    "reason": reason,
+                    "timestamp": now,
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn report(
+        &self,
+        session: &Session,
+        anomaly: Anomaly,
+        ip: Option<&str>,
+        now: u64,
+    ) -> AnyaResult<()> {
+        let event = AnomalyEvent {
+            subject: session.subject.clone(),
+            session_id: session.id.clone(),
+            severity: anomaly.severity(),
+            anomaly,
+            ip: ip.map(str::to_string),
+            timestamp: now,
+        };
+        warn!(subject = %event.subject, anomaly = ?event.anomaly, "login anomaly");
+        self.events
+            .append(SECURITY_TOPIC, LOGIN_ANOMALY, serde_json::to_value(&event)?)
+            .await?;
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(&event).await {
+                warn!(subject = %event.subject, error = %e, "anomaly notification failed");
+            }
+        }
+        Ok(())
+    }
+
+    fn random_hex(&self, len: usize) -> AnyaResult<String> {
+        let mut bytes = vec![0u8; len];
+        self.rng
+            .fill(&mut bytes)
+            .map_err(|_| AnyaError::new(ErrorCode::Internal, "system RNG failure"))?;
+        Ok(to_hex(&bytes))
+    }
+
+    async fn load<T: serde::de::DeserializeOwned>(&self, key: &str) -> AnyaResult<Option<T>> {
+        match self.storage.get(&self.ns, key).await? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn store<T: Serialize + Sync>(&self, key: &str, value: &T) -> AnyaResult<()> {
+        self.storage
+            .put(&self.ns, key, &serde_json::to_vec(value)?)
+            .await
+    }
+}
+
+fn device_key(subject: &str, device_id: &str) -> String {
+    format!("{}{}/{}", DEVICE_PREFIX, subject, device_id)
+}
+
+fn token_hash(token: &str) -> String {
+    to_hex(&sha256(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventStoreConfig;
+    use crate::storage::memory::MemoryBackend;
+
+    const LONDON: GeoPoint = GeoPoint {
+        lat: 51.5074,
+        lon: -0.1278,
+    };
+    const SYDNEY: GeoPoint = GeoPoint {
+        lat: -33.8688,
+        lon: 151.2093,
+    };
+
+    fn context(device: &str, location: GeoPoint) -> LoginContext {
+        LoginContext {
+            subject: "did:example:alice".into(),
+            device_id: device.into(),
+            device_name: format!("{} phone", device),
+            ip: "203.0.113.7".into(),
+            location: Some(location),
+        }
+    }
+
+    async fn manager() -> (SessionManager, Arc<EventStore>) {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let keys = KeyRing::open(Arc::clone(&storage), "sessions", KeyKind::JwtSigning)
+            .await
+            .unwrap();
+        let events = EventStore::open(EventStoreConfig::default(), Arc::clone(&storage))
+            .await
+            .unwrap();
+        let manager =
+            SessionManager::open(SessionConfig::default(), keys, storage, Arc::clone(&events))
+                .await
+                .unwrap();
+        (manager, events)
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_tokens_and_reuse_revokes_session() {
+        let (sessions, events) = manager().await;
+        let (first, anomalies) = sessions
+            .login(&context("pixel", LONDON), 1_000)
+            .await
+            .unwrap();
+        assert!(anomalies.is_empty());
+        let claims = sessions
+            .authenticate(&first.access_token, 1_000)
+            .await
+            .unwrap();
+        assert_eq!(claims.sid, first.session_id);
+        assert!(sessions
+            .authenticate(&first.access_token, first.access_expires_at)
+            .await
+            .is_err());
+
+        let second = sessions.refresh(&first.refresh_token, 1_100).await.unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+        assert!(sessions
+            .authenticate(&second.access_token, 1_100)
+            .await
+            .is_ok());
+
+        // Replaying the spent token kills the session and every token in it
+        assert!(sessions.refresh(&first.refresh_token, 1_200).await.is_err());
+        assert!(sessions
+            .refresh(&second.refresh_token, 1_200)
+            .await
+            .is_err());
+        assert!(sessions
+            .authenticate(&second.access_token, 1_200)
+            .await
+            .is_err());
+        let kinds: Vec<String> = events
+            .read_from(0, 100)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.topic == SECURITY_TOPIC)
+            .map(|e| e.kind)
+            .collect();
+        assert_eq!(kinds, [LOGIN_ANOMALY, SESSION_REVOKED]);
+    }
+
+    #[tokio::test]
+    async fn new_device_and_impossible_travel_are_flagged_and_revocable() {
+        let (sessions, _) = manager().await;
+        let (home, _) = sessions
+            .login(&context("pixel", LONDON), 1_000)
+            .await
+            .unwrap();
+        let (_, anomalies) = sessions
+            .login(&context("laptop", SYDNEY), 1_000 + 3_600)
+            .await
+            .unwrap();
+        assert!(matches!(anomalies[0], Anomaly::NewDevice { .. }));
+        assert!(matches!(
+            anomalies[1],
+            Anomaly::ImpossibleTravel { distance_km, .. } if distance_km > 16_000.0
+        ));
+        assert_eq!(anomalies[1].severity(), Severity::Page);
+        assert_eq!(
+            sessions.devices("did:example:alice").await.unwrap().len(),
+            2
+        );
+
+        let revoked = sessions
+            .revoke_all(
+                "did:example:alice",
+                Some(&home.session_id),
+                "suspicious login",
+                5_000,
+            )
+            .await
+            .unwrap();
+        assert_eq!(revoked, 1);
+        assert!(sessions
+            .session(&home.session_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_active(5_000));
+        let all = sessions.sessions("did:example:alice").await.unwrap();
+        assert_eq!(all.iter().filter(|s| s.is_active(5_000)).count(), 1);
+    }
+}
@@ -0,0 +1,298 @@
+//! Proof-of-work block relay for chain simulations
+//!
+//! Each [`ChainNode`] keeps a block tree, follows the branch with the most
+//! work (the longest, since difficulty is fixed), mines at a rate
+//! proportional to its hashrate share, and relays blocks to its peers. A
+//! block whose parent is unknown is parked and the parent requested from the
+//! sender, so partitioned nodes catch up once reconnected. Every switch to a
+//! branch not extending the previous tip is recorded with its depth in
+//! [`ChainNode::reorgs`].
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{Context, NodeId, SimNode};
+
+const MINE_TIMER: u64 = 0;
+const GENESIS_ID: u64 = 0;
+
+/// A simulated block header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimBlock {
+    /// Random block identifier
+    pub id: u64,
+    /// Parent block, `None` for genesis
+    pub parent: Option<u64>,
+    /// Height in the chain
+    pub height: u32,
+    /// Node that mined the block, `None` for genesis
+    pub miner: Option<NodeId>,
+}
+
+impl SimBlock {
+    /// The block every node starts from
+    pub const GENESIS: Self = Self {
+        id: GENESIS_ID,
+        parent: None,
+        height: 0,
+        miner: None,
+    };
+}
+
+/// Messages exchanged by chain nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChainMessage {
+    /// Announce a block
+    Block(SimBlock),
+    /// Ask for a block by id
+    GetBlock(u64),
+}
+
+/// How a node treats blocks mined by others
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Behavior {
+    /// Relays every block it accepts
+    Honest,
+    /// Ignores other nodes' blocks and mines a private branch, feeding the
+    /// peers it eclipses a chain the rest of the network never sees
+    PrivateChain,
+}
+
+/// A mining, block-relaying node
+pub struct ChainNode {
+    hashrate: f64,
+    block_interval: Duration,
+    behavior: Behavior,
+    blocks: HashMap<u64, SimBlock>,
+    orphans: HashMap<u64, Vec<SimBlock>>,
+    tip: u64,
+    mined: u32,
+    reorgs: Vec<u32>,
+}
+
+impl ChainNode {
+    /// A node holding `hashrate` of the network's total (0.0 to 1.0) where
+    /// the whole network finds a block every `block_interval` on average
+    pub fn new(hashrate: f64, block_interval: Duration, behavior: Behavior) -> Self {
+        Self {
+            hashrate,
+            block_interval,
+            behavior,
+            blocks: HashMap::from([(GENESIS_ID, SimBlock::GENESIS)]),
+            orphans: HashMap::new(),
+            tip: GENESIS_ID,
+            mined: 0,
+            reorgs: Vec::new(),
+        }
+    }
+
+    /// Block the node currently builds on
+    pub fn tip(&self) -> SimBlock {
+        self.blocks[&self.tip]
+    }
+
+    /// Height of the tip
+    pub fn height(&self) -> u32 {
+        self.tip().height
+    }
+
+    /// A known block
+    pub fn block(&self, id: u64) -> Option<SimBlock> {
+        self.blocks.get(&id).copied()
+    }
+
+    /// Blocks this node mined
+    pub const fn mined(&self) -> u32 {
+        self.mined
+    }
+
+    /// Depth of each reorg, in order
+    pub fn reorgs(&self) -> &[u32] {
+        &self.reorgs
+    }
+
+    /// Whether `ancestor` is on the branch ending at `block`
+    pub fn is_ancestor(&self, ancestor: u64, block: u64) -> bool {
+        let mut cursor = self.blocks.get(&block);
+        while let Some(b) = cursor {
+            if b.id == ancestor {
+                return true;
+            }
+            cursor = b.parent.and_then(|p| self.blocks.get(&p));
+        }
+        false
+    }
+
+    fn schedule_mining(&self, ctx: &mut Context<'_, ChainMessage>) {
+        if self.hashrate <= 0.0 {
+            return;
+        }
+        // Exponential inter-block time with mean interval / hashrate
+        let draw: f64 = ctx.rng().gen();
+        let mean = self.block_interval.as_secs_f64() / self.hashrate;
+        let delay = -(1.0 - draw).ln() * mean;
+        ctx.set_timer(Duration::from_secs_f64(delay), MINE_TIMER);
+    }
+
+    fn accept(
+        &mut self,
+        ctx: &mut Context<'_, ChainMessage>,
+        from: Option<NodeId>,
+        block: SimBlock,
+    ) {
+        let mut pending = vec![block];
+        while let Some(block) = pending.pop() {
+            if self.blocks.contains_key(&block.id) {
+                continue;
+            }
+            let Some(parent) = block.parent.filter(|p| self.blocks.contains_key(p)) else {
+                if let (Some(parent), Some(from)) = (block.parent, from) {
+                    self.orphans.entry(parent).or_default().push(block);
+                    ctx.send(from, ChainMessage::GetBlock(parent));
+                }
+                continue;
+            };
+            debug_assert_eq!(self.blocks[&parent].height + 1, block.height);
+            self.blocks.insert(block.id, block);
+            if block.height > self.height() {
+                if !self.is_ancestor(self.tip, block.id) {
+                    self.reorgs.push(self.reorg_depth(block.id));
+                }
+                self.tip = block.id;
+            }
+            ctx.broadcast(&ChainMessage::Block(block));
+            pending.extend(self.orphans.remove(&block.id).unwrap_or_default());
+        }
+    }
+
+    /// Blocks of the current branch disconnected by switching to `new_tip`
+    fn reorg_depth(&self, new_tip: u64) -> u32 {
+        let mut fork = self.tip();
+        while !self.is_ancestor(fork.id, new_tip) {
+            match fork.parent {
+                Some(parent) => fork = self.blocks[&parent],
+                None => break,
+            }
+        }
+        self.height() - fork.height
+    }
+}
+
+impl SimNode for ChainNode {
+    type Message = ChainMessage;
+
+    fn on_start(&mut self, ctx: &mut Context<'_, ChainMessage>) {
+        self.schedule_mining(ctx);
+    }
+
+    fn on_message(
+        &mut self,
+        ctx: &mut Context<'_, ChainMessage>,
+        from: NodeId,
+        message: ChainMessage,
+    ) {
+        match message {
+            ChainMessage::Block(block)
+                if self.behavior == Behavior::PrivateChain && block.miner != Some(ctx.id()) => {}
+            ChainMessage::Block(block) => self.accept(ctx, Some(from), block),
+            ChainMessage::GetBlock(id) => {
+                if let Some(block) = self.block(id) {
+                    ctx.send(from, ChainMessage::Block(block));
+                }
+            }
+        }
+    }
+
+    fn on_timer(&mut self, ctx: &mut Context<'_, ChainMessage>, _timer: u64) {
+        let parent = self.tip();
+        let block = SimBlock {
+            id: ctx.rng().gen_range(1..u64::MAX),
+            parent: Some(parent.id),
+            height: parent.height + 1,
+            miner: Some(ctx.id()),
+        };
+        self.mined += 1;
+        self.accept(ctx, None, block);
+        self.schedule_mining(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimConfig, Simulation};
+
+    const INTERVAL: Duration = Duration::from_secs(600);
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn mesh(sim: &mut Simulation<ChainNode>, ids: &[NodeId]) {
+        for a in ids {
+            for b in ids {
+                sim.connect(*a, *b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_partition_heals_with_minority_reorg() {
+        let mut sim = Simulation::new(SimConfig {
+            seed: 42,
+            ..SimConfig::default()
+        });
+        let shares = [0.3, 0.3, 0.2, 0.1, 0.1];
+        let ids: Vec<_> = shares
+            .iter()
+            .map(|share| sim.add_node(ChainNode::new(*share, INTERVAL, Behavior::Honest)))
+            .collect();
+        mesh(&mut sim, &ids);
+        sim.run_for(HOUR);
+
+        let cut = sim.partition(&[&ids[..3], &ids[3..]]);
+        sim.run_for(6 * HOUR);
+        let majority = sim.node(ids[0]).tip();
+        let minority = sim.node(ids[4]).tip();
+        assert_ne!(majority.id, minority.id);
+        assert!(majority.height > minority.height);
+
+        sim.reconnect(&cut);
+        sim.run_for(2 * HOUR);
+        let tip = sim.node(ids[0]).tip().id;
+        assert!(sim.nodes().iter().all(|n| n.tip().id == tip));
+        let deepest = sim.node(ids[4]).reorgs().iter().max().copied().unwrap();
+        assert!(deepest >= 2);
+        assert!(sim.node(ids[4]).is_ancestor(majority.id, tip));
+    }
+
+    #[test]
+    fn test_eclipsed_node_follows_attacker_until_reconnected() {
+        let mut sim = Simulation::new(SimConfig {
+            seed: 7,
+            ..SimConfig::default()
+        });
+        let honest: Vec<_> = (0..3)
+            .map(|_| sim.add_node(ChainNode::new(0.3, INTERVAL, Behavior::Honest)))
+            .collect();
+        let attacker = sim.add_node(ChainNode::new(0.1, INTERVAL, Behavior::PrivateChain));
+        let victim = sim.add_node(ChainNode::new(0.0, INTERVAL, Behavior::Honest));
+        mesh(&mut sim, &honest);
+        sim.connect(attacker, honest[0]);
+        sim.connect(attacker, victim);
+        sim.run_for(12 * HOUR);
+
+        // The victim only ever sees the attacker's weaker branch
+        let seen = sim.node(victim).tip();
+        assert_eq!(seen.miner, Some(attacker));
+        assert!(seen.height < sim.node(honest[0]).height());
+        assert!(!sim
+            .node(honest[0])
+            .is_ancestor(seen.id, sim.node(honest[0]).tip().id));
+
+        sim.connect(victim, honest[1]);
+        sim.run_for(2 * HOUR);
+        assert_eq!(sim.node(victim).tip().id, sim.node(honest[1]).tip().id);
+        assert!(!sim.node(victim).reorgs().is_empty());
+    }
+}
@@ -0,0 +1,585 @@
+//! SLA monitoring with error budgets
+//!
+//! An [`Slo`] sets an objective, such as 99.9% of requests succeeding or
+//! 95% of transactions confirming within an hour, over a rolling window.
+//! The [`SlaMonitor`] computes each objective's indicator from the
+//! [`TimeSeriesStore`] and how much of its error budget, the share of bad
+//! events the objective tolerates, is left.
+//!
+//! Alerts use multi-window burn rates: an alert fires when the budget is
+//! being spent at least `burn_rate` times faster than the window allows,
+//! measured over both a long and a short window, so it fires quickly on
+//! sharp outages and clears quickly once they end. Firing and resolved
+//! alerts are appended to the event log under [`COMPLIANCE_TOPIC`] and
+//! passed to every registered [`SlaNotifier`].
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::events::EventStore;
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::timeseries::{Aggregation, Labels, Query, SeriesData, TimeSeriesStore};
+use crate::{AnyaError, AnyaResult};
+
+/// Topic of SLA compliance events
+pub const COMPLIANCE_TOPIC: &str = "compliance.sla";
+/// Event kind of an alert starting to fire
+pub const BUDGET_BURN: &str = "budget_burn";
+/// Event kind of a firing alert clearing
+pub const BUDGET_BURN_RESOLVED: &str = "budget_burn_resolved";
+
+/// Series an indicator reads, summed over every matching series
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selector {
+    /// Metric name
+    pub name: String,
+    /// Labels the series must have
+    #[serde(default)]
+    pub labels: Labels,
+}
+
+impl Selector {
+    /// All series of metric `name`
+    pub fn metric(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// How good and total events are counted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Indicator {
+    /// Increase of a counter of good events over a counter of all events,
+    /// e.g. for availability
+    Ratio {
+        /// Counter of good events
+        good: Selector,
+        /// Counter of all events
+        total: Selector,
+    },
+    /// Share of samples of a gauge at or below a threshold, e.g. for
+    /// latency or confirmation time. Downsampled buckets count as good only
+    /// when their maximum is.
+    Threshold {
+        /// Gauge sampled per event or per scrape
+        series: Selector,
+        /// Largest good value
+        max: f64,
+    },
+}
+
+/// Alert severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Needs immediate attention
+    Page,
+    /// Needs attention within working hours
+    Ticket,
+}
+
+/// A burn rate alert over a long and a short window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BurnAlert {
+    /// Severity when firing
+    pub severity: Severity,
+    /// Window the burn rate must be sustained over
+    pub long_window: Duration,
+    /// Recent window the burn rate must still hold in
+    pub short_window: Duration,
+    /// Multiple of the sustainable burn rate that fires the alert
+    pub burn_rate: f64,
+}
+
+impl BurnAlert {
+    /// The usual pair for a 30 day window: page when 2% of the budget goes
+    /// in an hour, open a ticket when 5% goes in six hours
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self {
+                severity: Severity::Page,
+                long_window: Duration::from_secs(60 * 60),
+                short_window: Duration::from_secs(5 * 60),
+                burn_rate: 14.4,
+            },
+            Self {
+                severity: Severity::Ticket,
+                long_window: Duration::from_secs(6 * 60 * 60),
+                short_window: Duration::from_secs(30 * 60),
+                burn_rate: 6.0,
+            },
+        ]
+    }
+}
+
+/// A service level objective
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Slo {
+    /// Unique name, e.g. `api-availability`
+    pub name: String,
+    /// What is measured
+    pub indicator: Indicator,
+    /// Share of good events targeted, e.g. `0.999`
+    pub objective: f64,
+    /// Rolling window the objective and budget cover
+    pub window: Duration,
+    /// Burn rate alerts
+    pub alerts: Vec<BurnAlert>,
+}
+
+impl Slo {
+    /// Objective over a 30 day window with the default alerts
+    pub fn new(name: &str, indicator: Indicator, objective: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            indicator,
+            objective,
+            window: Duration::from_secs(30 * 24 * 60 * 60),
+            alerts: BurnAlert::defaults(),
+        }
+    }
+}
+
+/// Good and total events counted in a window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counts {
+    /// Good events
+    pub good: f64,
+    /// All events
+    pub total: f64,
+}
+
+impl Counts {
+    /// Share of bad events; zero without events
+    pub fn error_rate(&self) -> f64 {
+        if self.total > 0.0 {
+            ((self.total - self.good) / self.total).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// State of one objective at an evaluation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SloStatus {
+    /// Objective name
+    pub slo: String,
+    /// Events over the whole window
+    pub counts: Counts,
+    /// Share of good events over the window; 1 without events
+    pub sli: f64,
+    /// Share of the error budget left; negative once overspent
+    pub budget_remaining: f64,
+    /// Severities of the alerts currently firing
+    pub firing: Vec<Severity>,
+}
+
+/// Payload of compliance events and notifications
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BurnEvent {
+    /// Objective name
+    pub slo: String,
+    /// Alert severity
+    pub severity: Severity,
+    /// Whether the alert started firing or cleared
+    pub firing: bool,
+    /// Burn rate over the long window
+    pub long_burn_rate: f64,
+    /// Burn rate over the short window
+    pub short_burn_rate: f64,
+    /// Share of the error budget left
+    pub budget_remaining: f64,
+    /// Evaluation time in Unix milliseconds
+    pub timestamp_ms: u64,
+}
+
+/// Receives SLA alerts, e.g. to page an operator
+#[async_trait]
+pub trait SlaNotifier: Send + Sync {
+    /// Deliver an alert that started firing or cleared
+    async fn notify(&self, event: &BurnEvent) -> AnyaResult<()>;
+}
+
+/// Monitor settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaConfig {
+    /// Interval between evaluations
+    pub evaluation_interval: Duration,
+}
+
+impl Default for SlaConfig {
+    fn default() -> Self {
+        Self {
+            evaluation_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Counter increase within the points of every series, treating a drop as
+/// a restart from zero
+fn increase(series: &[SeriesData]) -> f64 {
+    series
+        .iter()
+        .map(|s| {
+            s.points
+                .windows(2)
+                .map(|w| {
+                    let (before, after) = (w[0].1, w[1].1);
+                    if after >= before {
+                        after - before
+                    } else {
+                        after
+                    }
+                })
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+/// Evaluates objectives and raises alerts on fast budget burn
+pub struct SlaMonitor {
+    config: SlaConfig,
+    metrics: Arc<TimeSeriesStore>,
+    events: Arc<EventStore>,
+    slos: Vec<Slo>,
+    notifiers: Vec<Arc<dyn SlaNotifier>>,
+    /// Objective name and severity of every firing alert
+    firing: Mutex<HashSet<(String, Severity)>>,
+}
+
+impl SlaMonitor {
+    /// Create a monitor reading `metrics` and logging to `events`
+    pub fn new(config: SlaConfig, metrics: Arc<TimeSeriesStore>, events: Arc<EventStore>) -> Self {
+        Self {
+            config,
+            metrics,
+            events,
+            slos: Vec::new(),
+            notifiers: Vec::new(),
+            firing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Add an objective; names must be unique and objectives below 1
+    pub fn add_slo(&mut self, slo: Slo) -> AnyaResult<()> {
+        if !(slo.objective > 0.0 && slo.objective < 1.0) {
+            return Err(AnyaError::invalid_input(format!(
+                "objective of SLO {} must be between 0 and 1",
+                slo.name
+            )));
+        }
+        if self.slos.iter().any(|s| s.name == slo.name) {
+            return Err(AnyaError::new(
+                crate::ErrorCode::Conflict,
+                format!("SLO {} is already defined", slo.name),
+            ));
+        }
+        self.slos.push(slo);
+        Ok(())
+    }
+
+    /// Add a notifier for alerts
+    pub fn add_notifier(&mut self, notifier: Arc<dyn SlaNotifier>) {
+        self.notifiers.push(notifier);
+    }
+
+    async fn query(
+        &self,
+        selector: &Selector,
+        aggregation: Aggregation,
+        from_ms: u64,
+        to_ms: u64,
+    ) -> AnyaResult<Vec<SeriesData>> {
+        self.metrics
+            .query(&Query {
+                name: selector.name.clone(),
+                labels: selector.labels.clone(),
+                from_ms,
+                to_ms,
+                step: None,
+                aggregation,
+            })
+            .await
+    }
+
+    /// Good and total events of `indicator` in the `window` before `now_ms`
+    pub async fn counts(
+        &self,
+        indicator: &Indicator,
+        window: Duration,
+        now_ms: u64,
+    ) -> AnyaResult<Counts> {
+        let from = now_ms.saturating_sub(millis(window));
+        match indicator {
+            Indicator::Ratio { good, total } => Ok(Counts {
+                good: increase(&self.query(good, Aggregation::Last, from, now_ms).await?),
+                total: increase(&self.query(total, Aggregation::Last, from, now_ms).await?),
+            }),
+            Indicator::Threshold { series, max } => {
+                let maxima = self.query(series, Aggregation::Max, from, now_ms).await?;
+                let counts = self.query(series, Aggregation::Count, from, now_ms).await?;
+                let mut result = Counts::default();
+                for (maxima, counts) in maxima.iter().zip(&counts) {
+                    for ((_, peak), (_, count)) in maxima.points.iter().zip(&counts.points) {
+                        result.total += count;
+                        if peak <= max {
+                            result.good += count;
+                        }
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    async fn burn_rate(&self, slo: &Slo, window: Duration, now_ms: u64) -> AnyaResult<f64> {
+        let counts = self.counts(&slo.indicator, window, now_ms).await?;
+        Ok(counts.error_rate() / (1.0 - slo.objective))
+    }
+
+    async fn publish(&self, event: &BurnEvent) -> AnyaResult<()> {
+        let kind = if event.firing {
+            BUDGET_BURN
+        } else {
+            BUDGET_BURN_RESOLVED
+        };
+        self.events
+            .append(COMPLIANCE_TOPIC, kind, serde_json::to_value(event)?)
+            .await?;
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(event).await {
+                warn!(slo = %event.slo, error = %e, "SLA notification failed");
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate every objective at `now_ms`, publishing alerts that
+    /// started firing or cleared since the last evaluation
+    pub async fn evaluate(&self, now_ms: u64) -> AnyaResult<Vec<SloStatus>> {
+        let mut statuses = Vec::with_capacity(self.slos.len());
+        for slo in &self.slos {
+            let counts = self.counts(&slo.indicator, slo.window, now_ms).await?;
+            let budget = 1.0 - slo.objective;
+            let budget_remaining = 1.0 - counts.error_rate() / budget;
+            let mut firing = Vec::new();
+            for alert in &slo.alerts {
+                let long = self.burn_rate(slo, alert.long_window, now_ms).await?;
+                let short = self.burn_rate(slo, alert.short_window, now_ms).await?;
+                let fires = long >= alert.burn_rate && short >= alert.burn_rate;
+                let key = (slo.name.clone(), alert.severity);
+                let changed = {
+                    let mut active = self.firing.lock().await;
+                    if fires {
+                        active.insert(key)
+                    } else {
+                        active.remove(&key)
+                    }
+                };
+                if fires {
+                    firing.push(alert.severity);
+                }
+                if changed {
+                    if fires {
+                        warn!(slo = %slo.name, severity = ?alert.severity, long, short, "error budget burning");
+                    } else {
+                        info!(slo = %slo.name, severity = ?alert.severity, "error budget burn resolved");
+                    }
+                    self.publish(&BurnEvent {
+                        slo: slo.name.clone(),
+                        severity: alert.severity,
+                        firing: fires,
+                        long_burn_rate: long,
+                        short_burn_rate: short,
+                        budget_remaining,
+                        timestamp_ms: now_ms,
+                    })
+                    .await?;
+                }
+            }
+            statuses.push(SloStatus {
+                slo: slo.name.clone(),
+                counts,
+                sli: 1.0 - counts.error_rate(),
+                budget_remaining,
+                firing,
+            });
+        }
+        Ok(statuses)
+    }
+
+    /// Evaluate objectives until `token` is cancelled
+    pub async fn run_schedule(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.evaluation_interval, || {
+            let monitor = Arc::clone(&self);
+            async move {
+                if let Err(e) = monitor.evaluate(now_ms()).await {
+                    warn!(error = %e, "SLA evaluation failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+/// Scheduled SLA evaluation as a lifecycle-managed subsystem
+pub struct SlaService {
+    monitor: Arc<SlaMonitor>,
+}
+
+impl SlaService {
+    /// Wrap a monitor for registration with the lifecycle manager
+    pub const fn new(monitor: Arc<SlaMonitor>) -> Self {
+        Self { monitor }
+    }
+}
+
+#[async_trait]
+impl Subsystem for SlaService {
+    fn name(&self) -> &str {
+        "sla"
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let monitor = Arc::clone(&self.monitor);
+        spawner
+            .spawn("evaluate", move |token| monitor.run_schedule(token))
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventStoreConfig;
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::StorageBackend;
+    use crate::timeseries::{Sample, SeriesKey, TimeSeriesConfig};
+
+    const MINUTE: u64 = 60_000;
+
+    struct Recorder(Mutex<Vec<BurnEvent>>);
+
+    #[async_trait]
+    impl SlaNotifier for Recorder {
+        async fn notify(&self, event: &BurnEvent) -> AnyaResult<()> {
+            self.0.lock().await.push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_burn_alert_fires_and_resolves() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let metrics = Arc::new(
+            TimeSeriesStore::open(TimeSeriesConfig::default(), storage.clone())
+                .await
+                .unwrap(),
+        );
+        let events = EventStore::open(EventStoreConfig::default(), storage)
+            .await
+            .unwrap();
+        let mut monitor = SlaMonitor::new(SlaConfig::default(), metrics.clone(), events.clone());
+        let availability = Indicator::Ratio {
+            good: Selector::metric("requests_ok_total"),
+            total: Selector::metric("requests_total"),
+        };
+        monitor
+            .add_slo(Slo::new("api", availability.clone(), 0.999))
+            .unwrap();
+        assert!(monitor
+            .add_slo(Slo::new("api", availability, 0.999))
+            .is_err());
+        monitor
+            .add_slo(Slo::new(
+                "confirmations",
+                Indicator::Threshold {
+                    series: Selector::metric("confirmation_seconds"),
+                    max: 3600.0,
+                },
+                0.5,
+            ))
+            .unwrap();
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        monitor.add_notifier(recorder.clone());
+
+        // 100 requests a minute for an hour; the last ten minutes fail half
+        let now = now_ms();
+        let start = now - 60 * MINUTE;
+        let (mut ok, mut total) = (0.0, 0.0);
+        let mut samples = Vec::new();
+        for m in 0..=60 {
+            let t = start + m * MINUTE;
+            samples.push(Sample {
+                series: SeriesKey::new("requests_ok_total"),
+                timestamp_ms: t,
+                value: ok,
+            });
+            samples.push(Sample {
+                series: SeriesKey::new("requests_total"),
+                timestamp_ms: t,
+                value: total,
+            });
+            samples.push(Sample {
+                series: SeriesKey::new("confirmation_seconds"),
+                timestamp_ms: t,
+                value: if m % 4 == 0 { 7200.0 } else { 600.0 },
+            });
+            total += 100.0;
+            ok += if m >= 50 { 50.0 } else { 100.0 };
+        }
+        metrics.record(&samples).await.unwrap();
+
+        let statuses = monitor.evaluate(now + 1).await.unwrap();
+        assert_eq!(
+            statuses[0].counts,
+            Counts {
+                good: 5500.0,
+                total: 6000.0
+            }
+        );
+        assert!(statuses[0].budget_remaining < 0.0);
+        assert_eq!(statuses[0].firing, vec![Severity::Page, Severity::Ticket]);
+        assert!(statuses[1].firing.is_empty());
+        assert!((statuses[1].sli - 45.0 / 61.0).abs() < 1e-9);
+
+        // Repeated evaluation does not repeat the alerts
+        monitor.evaluate(now + 1).await.unwrap();
+        let logged = events.read_from(0, 10).await.unwrap();
+        assert_eq!(logged.len(), 2);
+        assert!(logged
+            .iter()
+            .all(|e| e.topic == COMPLIANCE_TOPIC && e.kind == BUDGET_BURN));
+
+        // Hours later the short windows are clean and both alerts clear
+        let statuses = monitor.evaluate(now + 8 * 60 * MINUTE).await.unwrap();
+        assert!(statuses[0].firing.is_empty());
+        let notified = recorder.0.lock().await.clone();
+        assert_eq!(notified.len(), 4);
+        assert!(notified[2..].iter().all(|e| !e.firing));
+    }
+}
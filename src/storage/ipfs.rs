@@ -0,0 +1,188 @@
+//! IPFS object store driver
+//!
+//! Talks to a Kubo-compatible node through its HTTP RPC API (`/api/v0`).
+//! Objects are added without implicit pinning; pinning is controlled
+//! explicitly so [`PinnedObjectStore`](super::object::PinnedObjectStore) can
+//! apply the configured policy.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::object::{ObjectKind, ObjectRef, ObjectStore};
+use crate::utils::encoding::{sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+/// IPFS driver configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpfsConfig {
+    /// Base URL of the RPC API, e.g. `http://127.0.0.1:5001`
+    pub api_url: String,
+    /// Request timeout
+    pub timeout: Duration,
+    /// CID version used when adding content
+    pub cid_version: u8,
+}
+
+impl Default for IpfsConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "http://127.0.0.1:5001".to_string(),
+            timeout: Duration::from_secs(120),
+            cid_version: 1,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+/// Object store backed by an IPFS node
+pub struct IpfsObjectStore {
+    config: IpfsConfig,
+    client: reqwest::Client,
+}
+
+impl IpfsObjectStore {
+    /// Create a driver for the node at `config.api_url`
+    pub fn new(config: IpfsConfig) -> AnyaResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .context("building IPFS HTTP client")?;
+        Ok(Self { config, client })
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!(
+            "{}/api/v0/{}",
+            self.config.api_url.trim_end_matches('/'),
+            path
+        )
+    }
+
+    fn cid_of(object: &ObjectRef) -> AnyaResult<&str> {
+        object
+            .uri
+            .strip_prefix("ipfs://")
+            .filter(|cid| !cid.is_empty() && cid.chars().all(|c| c.is_ascii_alphanumeric()))
+            .ok_or_else(|| AnyaError::invalid_input(format!("not an IPFS object: {}", object.uri)))
+    }
+
+    async fn call(&self, path: &str, cid: &str) -> AnyaResult<reqwest::Response> {
+        let response = self
+            .client
+            .post(self.endpoint(path))
+            .query(&[("arg", cid)])
+            .send()
+            .await
+            .with_context(|| format!("calling IPFS {}", path))?;
+        check_status(response, path).await
+    }
+}
+
+async fn check_status(response: reqwest::Response, path: &str) -> AnyaResult<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    let code = if status.as_u16() == 404 {
+        ErrorCode::NotFound
+    } else {
+        ErrorCode::NetworkFailure
+    };
+    Err(AnyaError::new(
+        code,
+        format!("IPFS {} failed with {}: {}", path, status, body.trim()),
+    ))
+}
+
+#[async_trait]
+impl ObjectStore for IpfsObjectStore {
+    async fn put(
+        &self,
+        data: &[u8],
+        content_type: &str,
+        kind: ObjectKind,
+    ) -> AnyaResult<ObjectRef> {
+        let part = reqwest::multipart::Part::bytes(data.to_vec())
+            .file_name("object")
+            .mime_str(content_type)
+            .context("invalid content type")?;
+        let form = reqwest::multipart::Form::new().part("file", part);
+        let response = self
+            .client
+            .post(self.endpoint("add"))
+            .query(&[
+                ("pin", "false".to_string()),
+                ("cid-version", self.config.cid_version.to_string()),
+            ])
+            .multipart(form)
+            .send()
+            .await
+            .context("calling IPFS add")?;
+        let added: AddResponse = check_status(response, "add")
+            .await?
+            .json()
+            .await
+            .context("decoding IPFS add response")?;
+        Ok(ObjectRef {
+            uri: format!("ipfs://{}", added.hash),
+            sha256: to_hex(&sha256(data)),
+            size: data.len() as u64,
+            content_type: content_type.to_string(),
+            kind,
+        })
+    }
+
+    async fn fetch(&self, object: &ObjectRef) -> AnyaResult<Vec<u8>> {
+        let cid = Self::cid_of(object)?;
+        let bytes = self
+            .call("cat", cid)
+            .await?
+            .bytes()
+            .await
+            .context("reading IPFS content")?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn pin(&self, object: &ObjectRef) -> AnyaResult<()> {
+        self.call("pin/add", Self::cid_of(object)?).await?;
+        Ok(())
+    }
+
+    async fn unpin(&self, object: &ObjectRef) -> AnyaResult<()> {
+        match self.call("pin/rm", Self::cid_of(object)?).await {
+            Ok(_) => Ok(()),
+            // Kubo reports unpinning an unpinned CID as an error; treat it as done
+            Err(e) if e.to_string().contains("not pinned") => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cid_parsing_rejects_foreign_uris() {
+        let mut object = ObjectRef {
+            uri: "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi".into(),
+            sha256: String::new(),
+            size: 0,
+            content_type: "text/plain".into(),
+            kind: ObjectKind::Document,
+        };
+        assert!(IpfsObjectStore::cid_of(&object).is_ok());
+        object.uri = "file://abc".into();
+        assert!(IpfsObjectStore::cid_of(&object).is_err());
+        object.uri = "ipfs://abc/../etc".into();
+        assert!(IpfsObjectStore::cid_of(&object).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! In-memory storage backend
+//!
+//! Namespaces are spread over [`Sharded`] locks, so subsystems writing to
+//! their own namespaces do not wait on each other.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::{BackendKind, Migration, Namespace, StorageBackend};
+use crate::utils::sharded::{LockStats, Sharded, DEFAULT_SHARDS};
+use crate::AnyaResult;
+use async_trait::async_trait;
+
+#[derive(Default)]
+struct NamespaceData {
+    entries: BTreeMap<String, Vec<u8>>,
+    migrations: Vec<u32>,
+}
+
+/// Volatile backend keeping all namespaces in process memory
+pub struct MemoryBackend {
+    namespaces: Sharded<HashMap<Namespace, NamespaceData>>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self {
+            namespaces: Sharded::new("storage.memory", DEFAULT_SHARDS, HashMap::new),
+        }
+    }
+}
+
+impl MemoryBackend {
+    /// Create an empty backend
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Contention of the namespace locks
+    pub fn lock_stats(&self) -> LockStats {
+        self.namespaces.stats()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Memory
+    }
+
+    async fn ensure_namespace(&self, ns: &Namespace) -> AnyaResult<()> {
+        self.namespaces.write(ns).entry(ns.clone()).or_default();
+        Ok(())
+    }
+
+    async fn get(&self, ns: &Namespace, key: &str) -> AnyaResult<Option<Vec<u8>>> {
+        Ok(self
+            .namespaces
+            .read(ns)
+            .get(ns)
+            .and_then(|d| d.entries.get(key).cloned()))
+    }
+
+    async fn put(&self, ns: &Namespace, key: &str, value: &[u8]) -> AnyaResult<()> {
+        self.namespaces
+            .write(ns)
+            .entry(ns.clone())
+            .or_default()
+            .entries
+            .insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    async fn delete(&self, ns: &Namespace, key: &str) -> AnyaResult<bool> {
+        Ok(self
+            .namespaces
+            .write(ns)
+            .get_mut(ns)
+            .is_some_and(|d| d.entries.remove(key).is_some()))
+    }
+
+    async fn scan_prefix(
+        &self,
+        ns: &Namespace,
+        prefix: &str,
+    ) -> AnyaResult<Vec<(String, Vec<u8>)>> {
+        Ok(self
+            .namespaces
+            .read(ns)
+            .get(ns)
+            .map(|d| {
+                d.entries
+                    .range(prefix.to_string()..)
+                    .take_while(|(k, _)| k.starts_with(prefix))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn applied_migrations(&self, ns: &Namespace) -> AnyaResult<Vec<u32>> {
+        Ok(self
+            .namespaces
+            .read(ns)
+            .get(ns)
+            .map(|d| d.migrations.clone())
+            .unwrap_or_default())
+    }
+
+    async fn apply_migration(&self, ns: &Namespace, migration: &Migration) -> AnyaResult<()> {
+        self.namespaces
+            .write(ns)
+            .entry(ns.clone())
+            .or_default()
+            .migrations
+            .push(migration.version);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_namespaces_are_isolated() {
+        let backend = MemoryBackend::new();
+        let wallet = Namespace::new("wallet").unwrap();
+        let dao = Namespace::new("dao").unwrap();
+
+        backend.put(&wallet, "utxo/1", b"a").await.unwrap();
+        backend.put(&wallet, "utxo/2", b"b").await.unwrap();
+        backend.put(&wallet, "label/1", b"c").await.unwrap();
+        backend.put(&dao, "utxo/1", b"z").await.unwrap();
+
+        let utxos = backend.scan_prefix(&wallet, "utxo/").await.unwrap();
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(
+            backend.get(&dao, "utxo/1").await.unwrap(),
+            Some(b"z".to_vec())
+        );
+        assert!(backend.delete(&wallet, "utxo/1").await.unwrap());
+        assert!(!backend.delete(&wallet, "utxo/1").await.unwrap());
+    }
+}
@@ -0,0 +1,254 @@
+//! Pluggable storage backends for persistent module data
+//!
+//! Every module stores its data in its own [`Namespace`], which maps to a
+//! Postgres schema, a table prefix in SQLite, or a tree in sled. Backends
+//! expose a common key/value interface plus versioned [`Migration`]s so
+//! modules can evolve their storage layout independently. Changes to the
+//! format of the records themselves go through [`schema::migrate_records`].
+//!
+//! Available backends:
+//! - [`memory::MemoryBackend`]: always available, for tests and ephemeral nodes
+//! - `postgres::PostgresBackend`: behind the `postgres` feature
+//! - `sqlite::SqliteBackend`: behind the `sqlite` feature
+//! - `sled::SledBackend`: behind the `sled` feature
+//!
+//! Large artifacts go through the content-addressed [`object::ObjectStore`]
+//! instead, with a local filesystem driver and an IPFS driver behind the
+//! `ipfs` feature.
+//!
+//! [`replica::ReadOnlyBackend`] turns any backend into a write-refusing
+//! view, and [`replica::Replicator`] keeps a replica of selected namespaces
+//! in step with the primary for audit access.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+#[cfg(feature = "ipfs")]
+pub mod ipfs;
+pub mod memory;
+pub mod object;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod replica;
+pub mod schema;
+#[cfg(feature = "sled")]
+pub mod sled;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// Placeholder in migration SQL replaced by the backend's namespace prefix
+///
+/// Postgres expands it to `"<namespace>".` and SQLite to `<namespace>_`, so
+/// `CREATE TABLE <ns>utxos (...)` works on both.
+pub const NAMESPACE_PLACEHOLDER: &str = "<ns>";
+
+/// Validated storage namespace (lowercase ASCII, digits, and underscores)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Namespace(String);
+
+impl Namespace {
+    /// Create a namespace, rejecting names that are unsafe as SQL identifiers
+    pub fn new(name: impl Into<String>) -> AnyaResult<Self> {
+        let name = name.into();
+        let valid = !name.is_empty()
+            && name.len() <= 48
+            && name.starts_with(|c: char| c.is_ascii_lowercase())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if valid {
+            Ok(Self(name))
+        } else {
+            Err(AnyaError::invalid_input(format!(
+                "invalid storage namespace {:?}",
+                name
+            )))
+        }
+    }
+
+    /// Namespace name
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A versioned schema change for one namespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    /// Monotonically increasing version, unique within the namespace
+    pub version: u32,
+    /// Short description recorded alongside the version
+    pub description: &'static str,
+    /// SQL to execute on SQL backends; may use [`NAMESPACE_PLACEHOLDER`]
+    ///
+    /// Key/value backends record the version without executing anything.
+    pub sql: &'static str,
+}
+
+/// Supported backend kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendKind {
+    /// Volatile in-process storage
+    Memory,
+    /// PostgreSQL via a connection pool
+    Postgres,
+    /// SQLite database file
+    Sqlite,
+    /// Embedded sled database
+    Sled,
+}
+
+/// Storage configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Backend to use
+    pub backend: BackendKind,
+    /// Connection URL (`postgres://...`, `sqlite://...`) or sled directory
+    pub url: String,
+    /// Maximum pooled connections
+    pub max_connections: u32,
+    /// Minimum idle pooled connections
+    pub min_connections: u32,
+    /// Maximum time to wait for a pooled connection
+    pub acquire_timeout: Duration,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::Memory,
+            url: String::new(),
+            max_connections: 10,
+            min_connections: 1,
+            acquire_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Common interface implemented by every storage backend
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Backend kind
+    fn kind(&self) -> BackendKind;
+
+    /// Create the namespace and its bookkeeping tables if missing
+    async fn ensure_namespace(&self, ns: &Namespace) -> AnyaResult<()>;
+
+    /// Fetch a value
+    async fn get(&self, ns: &Namespace, key: &str) -> AnyaResult<Option<Vec<u8>>>;
+
+    /// Insert or replace a value
+    async fn put(&self, ns: &Namespace, key: &str, value: &[u8]) -> AnyaResult<()>;
+
+    /// Delete a value, returning whether it existed
+    async fn delete(&self, ns: &Namespace, key: &str) -> AnyaResult<bool>;
+
+    /// All entries whose key starts with `prefix`, ordered by key
+    async fn scan_prefix(&self, ns: &Namespace, prefix: &str)
+        -> AnyaResult<Vec<(String, Vec<u8>)>>;
+
+    /// Versions of migrations already applied to the namespace
+    async fn applied_migrations(&self, ns: &Namespace) -> AnyaResult<Vec<u32>>;
+
+    /// Apply a single migration atomically and record its version
+    async fn apply_migration(&self, ns: &Namespace, migration: &Migration) -> AnyaResult<()>;
+}
+
+/// Apply all pending migrations for a namespace in version order.
+///
+/// Returns the versions that were applied by this call.
+pub async fn run_migrations(
+    backend: &dyn StorageBackend,
+    ns: &Namespace,
+    migrations: &[Migration],
+) -> AnyaResult<Vec<u32>> {
+    let mut sorted: Vec<&Migration> = migrations.iter().collect();
+    sorted.sort_by_key(|m| m.version);
+    if sorted.windows(2).any(|w| w[0].version == w[1].version) {
+        return Err(AnyaError::new(
+            ErrorCode::Config,
+            format!("duplicate migration versions in namespace {}", ns.as_str()),
+        ));
+    }
+
+    backend.ensure_namespace(ns).await?;
+    let applied = backend.applied_migrations(ns).await?;
+    let mut newly_applied = Vec::new();
+    for migration in sorted {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+        tracing::info!(
+            namespace = ns.as_str(),
+            version = migration.version,
+            description = migration.description,
+            "applying storage migration"
+        );
+        backend.apply_migration(ns, migration).await?;
+        newly_applied.push(migration.version);
+    }
+    Ok(newly_applied)
+}
+
+/// Open the backend selected by `config`
+pub async fn open_backend(config: &StorageConfig) -> AnyaResult<Arc<dyn StorageBackend>> {
+    match config.backend {
+        BackendKind::Memory => Ok(Arc::new(memory::MemoryBackend::new())),
+        #[cfg(feature = "postgres")]
+        BackendKind::Postgres => Ok(Arc::new(postgres::PostgresBackend::connect(config).await?)),
+        #[cfg(feature = "sqlite")]
+        BackendKind::Sqlite => Ok(Arc::new(sqlite::SqliteBackend::connect(config).await?)),
+        #[cfg(feature = "sled")]
+        BackendKind::Sled => Ok(Arc::new(sled::SledBackend::open(config)?)),
+        #[allow(unreachable_patterns)]
+        other => Err(AnyaError::new(
+            ErrorCode::Config,
+            format!("storage backend {:?} not compiled in", other),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 2,
+            description: "add index",
+            sql: "CREATE INDEX <ns>utxo_height ON <ns>utxos (height)",
+        },
+        Migration {
+            version: 1,
+            description: "create utxos",
+            sql: "CREATE TABLE <ns>utxos (outpoint TEXT PRIMARY KEY, height INTEGER)",
+        },
+    ];
+
+    #[test]
+    fn test_namespace_validation() {
+        assert!(Namespace::new("wallet_v2").is_ok());
+        assert!(Namespace::new("Wallet").is_err());
+        assert!(Namespace::new("1wallet").is_err());
+        assert!(Namespace::new("wallet; DROP TABLE x").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrations_are_ordered_and_idempotent() {
+        let backend = open_backend(&StorageConfig::default()).await.unwrap();
+        let ns = Namespace::new("wallet").unwrap();
+        let applied = run_migrations(backend.as_ref(), &ns, MIGRATIONS)
+            .await
+            .unwrap();
+        assert_eq!(applied, vec![1, 2]);
+        let applied = run_migrations(backend.as_ref(), &ns, MIGRATIONS)
+            .await
+            .unwrap();
+        assert!(applied.is_empty());
+    }
+}
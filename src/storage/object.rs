@@ -0,0 +1,318 @@
+//! Content-addressed object storage for large artifacts
+//!
+//! Model weights, RAG documents, report exports, and offloaded blocks are
+//! too large for the key/value [`StorageBackend`](super::StorageBackend).
+//! They are written to an [`ObjectStore`] instead, and the returned
+//! [`ObjectRef`] (a URI plus SHA-256 digest and size) is what gets embedded
+//! in DWN records and other metadata. Every read is verified against the
+//! digest in the reference.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::encoding::{sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+/// Kind of artifact being stored, used to select a pinning policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ObjectKind {
+    /// Trained model weights and checkpoints
+    ModelArtifact,
+    /// Documents indexed for retrieval-augmented generation
+    Document,
+    /// Generated report exports
+    Report,
+    /// Blocks and chain index data moved to cold storage
+    ChainData,
+    /// Anything else
+    Other,
+}
+
+/// Reference to a stored object, suitable for embedding in DWN records
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectRef {
+    /// Location URI, e.g. `ipfs://<cid>` or `file://<digest>`
+    pub uri: String,
+    /// Hex SHA-256 of the object bytes
+    pub sha256: String,
+    /// Object size in bytes
+    pub size: u64,
+    /// MIME type supplied on upload
+    pub content_type: String,
+    /// Artifact kind
+    pub kind: ObjectKind,
+}
+
+/// How an object should be retained by the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PinMode {
+    /// Do not pin; the object may be garbage collected
+    Unpinned,
+    /// Pin indefinitely
+    Pinned,
+    /// Pin, then unpin once the duration has elapsed
+    PinnedFor(Duration),
+}
+
+/// Pinning policy configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinningPolicy {
+    /// Mode used when no per-kind override exists
+    pub default: PinMode,
+    /// Per-kind overrides
+    pub overrides: HashMap<ObjectKind, PinMode>,
+}
+
+impl Default for PinningPolicy {
+    fn default() -> Self {
+        Self {
+            default: PinMode::Pinned,
+            overrides: HashMap::from([
+                (
+                    ObjectKind::Report,
+                    PinMode::PinnedFor(Duration::from_secs(90 * 24 * 3600)),
+                ),
+                (ObjectKind::Other, PinMode::Unpinned),
+            ]),
+        }
+    }
+}
+
+impl PinningPolicy {
+    /// Pin mode for objects of `kind`
+    pub fn mode_for(&self, kind: ObjectKind) -> PinMode {
+        self.overrides.get(&kind).copied().unwrap_or(self.default)
+    }
+}
+
+/// Content-addressed object store
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Store `data` and return a reference to it
+    async fn put(&self, data: &[u8], content_type: &str, kind: ObjectKind)
+        -> AnyaResult<ObjectRef>;
+
+    /// Fetch raw bytes for a reference without verification
+    async fn fetch(&self, object: &ObjectRef) -> AnyaResult<Vec<u8>>;
+
+    /// Pin an object so it is retained
+    async fn pin(&self, object: &ObjectRef) -> AnyaResult<()>;
+
+    /// Unpin an object, allowing it to be garbage collected
+    async fn unpin(&self, object: &ObjectRef) -> AnyaResult<()>;
+
+    /// Fetch an object and verify its size and digest
+    async fn get(&self, object: &ObjectRef) -> AnyaResult<Vec<u8>> {
+        let data = self.fetch(object).await?;
+        verify(object, &data)?;
+        Ok(data)
+    }
+}
+
+/// Check that `data` matches the size and digest recorded in `object`
+pub fn verify(object: &ObjectRef, data: &[u8]) -> AnyaResult<()> {
+    if data.len() as u64 != object.size || to_hex(&sha256(data)) != object.sha256 {
+        return Err(AnyaError::new(
+            ErrorCode::StorageFailure,
+            format!("object {} failed integrity verification", object.uri),
+        ));
+    }
+    Ok(())
+}
+
+/// Applies a [`PinningPolicy`] on top of any [`ObjectStore`]
+pub struct PinnedObjectStore<S> {
+    inner: S,
+    policy: PinningPolicy,
+    expiring: tokio::sync::Mutex<Vec<(SystemTime, ObjectRef)>>,
+}
+
+impl<S: ObjectStore> PinnedObjectStore<S> {
+    /// Wrap `inner` with `policy`
+    pub fn new(inner: S, policy: PinningPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            expiring: tokio::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Store an object and pin it according to the policy
+    pub async fn put(
+        &self,
+        data: &[u8],
+        content_type: &str,
+        kind: ObjectKind,
+    ) -> AnyaResult<ObjectRef> {
+        let object = self.inner.put(data, content_type, kind).await?;
+        match self.policy.mode_for(kind) {
+            PinMode::Unpinned => {}
+            PinMode::Pinned => self.inner.pin(&object).await?,
+            PinMode::PinnedFor(ttl) => {
+                self.inner.pin(&object).await?;
+                self.expiring
+                    .lock()
+                    .await
+                    .push((SystemTime::now() + ttl, object.clone()));
+            }
+        }
+        Ok(object)
+    }
+
+    /// Fetch and verify an object
+    pub async fn get(&self, object: &ObjectRef) -> AnyaResult<Vec<u8>> {
+        self.inner.get(object).await
+    }
+
+    /// Unpin every object whose pin has expired, returning how many were released
+    pub async fn release_expired(&self) -> AnyaResult<usize> {
+        let now = SystemTime::now();
+        let pending = std::mem::take(&mut *self.expiring.lock().await);
+        let (expired, keep): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(at, _)| *at <= now);
+        self.expiring.lock().await.extend(keep);
+        let expired: Vec<ObjectRef> = expired.into_iter().map(|(_, o)| o).collect();
+        for object in &expired {
+            self.inner.unpin(object).await?;
+        }
+        Ok(expired.len())
+    }
+}
+
+/// Filesystem object store addressed by SHA-256 digest
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LocalObjectStore {
+    /// Store objects under `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path_for(&self, digest: &str) -> PathBuf {
+        self.root.join(&digest[..2]).join(digest)
+    }
+
+    fn pin_marker(&self, digest: &str) -> PathBuf {
+        self.root.join("pins").join(digest)
+    }
+
+    fn digest_of(object: &ObjectRef) -> AnyaResult<&str> {
+        object
+            .uri
+            .strip_prefix("file://")
+            .filter(|d| d.len() == 64 && d.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| AnyaError::invalid_input(format!("not a local object: {}", object.uri)))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl ObjectStore for LocalObjectStore {
+    async fn put(
+        &self,
+        data: &[u8],
+        content_type: &str,
+        kind: ObjectKind,
+    ) -> AnyaResult<ObjectRef> {
+        let digest = to_hex(&sha256(data));
+        let path = self.path_for(&digest);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("creating object directory")?;
+        }
+        tokio::fs::write(&path, data)
+            .await
+            .with_context(|| format!("writing object {}", digest))?;
+        Ok(ObjectRef {
+            uri: format!("file://{}", digest),
+            sha256: digest,
+            size: data.len() as u64,
+            content_type: content_type.to_string(),
+            kind,
+        })
+    }
+
+    async fn fetch(&self, object: &ObjectRef) -> AnyaResult<Vec<u8>> {
+        let digest = Self::digest_of(object)?;
+        Ok(tokio::fs::read(self.path_for(digest))
+            .await
+            .with_context(|| format!("reading object {}", digest))?)
+    }
+
+    async fn pin(&self, object: &ObjectRef) -> AnyaResult<()> {
+        let marker = self.pin_marker(Self::digest_of(object)?);
+        if let Some(parent) = marker.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(marker, []).await?;
+        Ok(())
+    }
+
+    async fn unpin(&self, object: &ObjectRef) -> AnyaResult<()> {
+        match tokio::fs::remove_file(self.pin_marker(Self::digest_of(object)?)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_store_verifies_content() {
+        let root = std::env::temp_dir().join(format!(
+            "anya-objects-{}",
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let store = LocalObjectStore::new(&root);
+        let object = store
+            .put(
+                b"model weights",
+                "application/octet-stream",
+                ObjectKind::ModelArtifact,
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.get(&object).await.unwrap(), b"model weights");
+
+        let mut tampered = object.clone();
+        tampered.size += 1;
+        assert!(store.get(&tampered).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pin_policy_expiry() {
+        let root = std::env::temp_dir().join("anya-objects-pins");
+        let policy = PinningPolicy {
+            default: PinMode::Pinned,
+            overrides: HashMap::from([(ObjectKind::Report, PinMode::PinnedFor(Duration::ZERO))]),
+        };
+        let store = PinnedObjectStore::new(LocalObjectStore::new(&root), policy);
+        let report = store
+            .put(b"q3 report", "application/pdf", ObjectKind::Report)
+            .await
+            .unwrap();
+        store
+            .put(b"doc", "text/plain", ObjectKind::Document)
+            .await
+            .unwrap();
+
+        assert_eq!(store.release_expired().await.unwrap(), 1);
+        assert!(!root.join("pins").join(&report.sha256).exists());
+        assert_eq!(store.release_expired().await.unwrap(), 0);
+    }
+}
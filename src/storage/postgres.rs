@@ -0,0 +1,158 @@
+//! PostgreSQL storage backend
+//!
+//! Each namespace is a Postgres schema holding a `kv` table and a
+//! `_migrations` bookkeeping table. Connections are pooled with the limits
+//! from [`StorageConfig`].
+
+use async_trait::async_trait;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::{Executor, Row};
+
+use super::{
+    BackendKind, Migration, Namespace, StorageBackend, StorageConfig, NAMESPACE_PLACEHOLDER,
+};
+use crate::{AnyaResult, ResultExt};
+
+/// Postgres-backed storage using a connection pool
+pub struct PostgresBackend {
+    pool: PgPool,
+}
+
+impl PostgresBackend {
+    /// Connect a pool using `config.url`
+    pub async fn connect(config: &StorageConfig) -> AnyaResult<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .connect(&config.url)
+            .await
+            .context("connecting to postgres")?;
+        Ok(Self { pool })
+    }
+
+    /// Wrap an existing pool
+    pub const fn from_pool(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn schema(ns: &Namespace) -> String {
+    format!("\"{}\"", ns.as_str())
+}
+
+#[async_trait]
+impl StorageBackend for PostgresBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Postgres
+    }
+
+    async fn ensure_namespace(&self, ns: &Namespace) -> AnyaResult<()> {
+        let schema = schema(ns);
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {}.kv (key TEXT PRIMARY KEY, value BYTEA NOT NULL)",
+            schema
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {}._migrations (\
+             version INTEGER PRIMARY KEY, \
+             description TEXT NOT NULL, \
+             applied_at TIMESTAMPTZ NOT NULL DEFAULT now())",
+            schema
+        ))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get(&self, ns: &Namespace, key: &str) -> AnyaResult<Option<Vec<u8>>> {
+        let row = sqlx::query(&format!(
+            "SELECT value FROM {}.kv WHERE key = $1",
+            schema(ns)
+        ))
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| r.get::<Vec<u8>, _>(0)))
+    }
+
+    async fn put(&self, ns: &Namespace, key: &str, value: &[u8]) -> AnyaResult<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {}.kv (key, value) VALUES ($1, $2) \
+             ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value",
+            schema(ns)
+        ))
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, ns: &Namespace, key: &str) -> AnyaResult<bool> {
+        let result = sqlx::query(&format!("DELETE FROM {}.kv WHERE key = $1", schema(ns)))
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn scan_prefix(
+        &self,
+        ns: &Namespace,
+        prefix: &str,
+    ) -> AnyaResult<Vec<(String, Vec<u8>)>> {
+        let rows = sqlx::query(&format!(
+            "SELECT key, value FROM {}.kv WHERE left(key, length($1)) = $1 ORDER BY key",
+            schema(ns)
+        ))
+        .bind(prefix)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| (r.get::<String, _>(0), r.get::<Vec<u8>, _>(1)))
+            .collect())
+    }
+
+    async fn applied_migrations(&self, ns: &Namespace) -> AnyaResult<Vec<u32>> {
+        let rows = sqlx::query(&format!(
+            "SELECT version FROM {}._migrations ORDER BY version",
+            schema(ns)
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| r.get::<i32, _>(0).unsigned_abs())
+            .collect())
+    }
+
+    async fn apply_migration(&self, ns: &Namespace, migration: &Migration) -> AnyaResult<()> {
+        let schema = schema(ns);
+        let sql = migration
+            .sql
+            .replace(NAMESPACE_PLACEHOLDER, &format!("{}.", schema));
+        let mut tx = self.pool.begin().await?;
+        tx.execute(sql.as_str())
+            .await
+            .with_context(|| format!("applying migration {}", migration.version))?;
+        sqlx::query(&format!(
+            "INSERT INTO {}._migrations (version, description) VALUES ($1, $2)",
+            schema
+        ))
+        .bind(i64::from(migration.version))
+        .bind(migration.description)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+//! Read-only replicas of node state
+//!
+//! [`ReadOnlyBackend`] wraps any backend and refuses every write, so code
+//! handed a replica cannot change state even through APIs that would
+//! normally persist something. [`Replicator`] keeps a replica backend in
+//! step with the primary by copying selected namespaces: changed entries are
+//! written and entries gone from the primary are deleted.
+//!
+//! A separate audit process can instead open a replica database maintained
+//! by the database itself, such as a Postgres hot standby, and wrap it in
+//! [`ReadOnlyBackend`] without running a [`Replicator`].
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::{BackendKind, Migration, Namespace, StorageBackend};
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Backend that serves reads from `inner` and rejects all writes
+pub struct ReadOnlyBackend {
+    inner: Arc<dyn StorageBackend>,
+}
+
+impl ReadOnlyBackend {
+    /// Read-only view of `inner`
+    pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+        Self { inner }
+    }
+
+    fn denied(operation: &str, ns: &Namespace) -> AnyaError {
+        AnyaError::new(
+            ErrorCode::PermissionDenied,
+            format!(
+                "{} on {} refused: storage is read-only",
+                operation,
+                ns.as_str()
+            ),
+        )
+    }
+}
+
+#[async_trait]
+impl StorageBackend for ReadOnlyBackend {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    /// Accepted without creating anything, so modules can be opened over
+    /// the replica; a namespace the primary never created reads as empty
+    async fn ensure_namespace(&self, _ns: &Namespace) -> AnyaResult<()> {
+        Ok(())
+    }
+
+    async fn get(&self, ns: &Namespace, key: &str) -> AnyaResult<Option<Vec<u8>>> {
+        self.inner.get(ns, key).await
+    }
+
+    async fn put(&self, ns: &Namespace, _key: &str, _value: &[u8]) -> AnyaResult<()> {
+        Err(Self::denied("write", ns))
+    }
+
+    async fn delete(&self, ns: &Namespace, _key: &str) -> AnyaResult<bool> {
+        Err(Self::denied("delete", ns))
+    }
+
+    async fn scan_prefix(
+        &self,
+        ns: &Namespace,
+        prefix: &str,
+    ) -> AnyaResult<Vec<(String, Vec<u8>)>> {
+        self.inner.scan_prefix(ns, prefix).await
+    }
+
+    async fn applied_migrations(&self, ns: &Namespace) -> AnyaResult<Vec<u32>> {
+        self.inner.applied_migrations(ns).await
+    }
+
+    async fn apply_migration(&self, ns: &Namespace, _migration: &Migration) -> AnyaResult<()> {
+        Err(Self::denied("migration", ns))
+    }
+}
+
+/// Replication settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicaConfig {
+    /// Namespaces copied to the replica
+    pub namespaces: Vec<String>,
+    /// Interval between sync passes
+    pub interval: Duration,
+}
+
+impl Default for ReplicaConfig {
+    fn default() -> Self {
+        Self {
+            namespaces: Vec::new(),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Outcome of one sync pass
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncReport {
+    /// Entries written because they were new or changed
+    pub copied: usize,
+    /// Entries deleted because the primary no longer has them
+    pub deleted: usize,
+    /// Entries already up to date
+    pub unchanged: usize,
+    /// Unix time in milliseconds the pass finished
+    pub finished_at_ms: u64,
+}
+
+/// Copies namespaces from the primary backend to a replica
+pub struct Replicator {
+    config: ReplicaConfig,
+    primary: Arc<dyn StorageBackend>,
+    replica: Arc<dyn StorageBackend>,
+    namespaces: Vec<Namespace>,
+    last: Mutex<Option<SyncReport>>,
+}
+
+impl Replicator {
+    /// Replicate `config.namespaces` from `primary` into `replica`
+    pub fn new(
+        config: ReplicaConfig,
+        primary: Arc<dyn StorageBackend>,
+        replica: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let namespaces = config
+            .namespaces
+            .iter()
+            .map(Namespace::new)
+            .collect::<AnyaResult<_>>()?;
+        Ok(Self {
+            config,
+            primary,
+            replica,
+            namespaces,
+            last: Mutex::new(None),
+        })
+    }
+
+    /// Read-only view of the replica for audit access
+    pub fn read_only(&self) -> Arc<dyn StorageBackend> {
+        Arc::new(ReadOnlyBackend::new(Arc::clone(&self.replica)))
+    }
+
+    /// Report of the latest completed pass
+    pub async fn last_sync(&self) -> Option<SyncReport> {
+        self.last.lock().await.clone()
+    }
+
+    /// Bring every replicated namespace up to date with the primary
+    pub async fn sync(&self) -> AnyaResult<SyncReport> {
+        let mut report = SyncReport::default();
+        for ns in &self.namespaces {
+            self.replica.ensure_namespace(ns).await?;
+            let source = self.primary.scan_prefix(ns, "").await?;
+            let target = self.replica.scan_prefix(ns, "").await?;
+
+            // Both scans are ordered by key, so walk them together
+            let mut existing = target.into_iter().peekable();
+            for (key, value) in &source {
+                while existing.peek().is_some_and(|(k, _)| k < key) {
+                    let (stale, _) = existing.next().unwrap_or_default();
+                    self.replica.delete(ns, &stale).await?;
+                    report.deleted += 1;
+                }
+                match existing.next_if(|(k, _)| k == key) {
+                    Some((_, current)) if &current == value => report.unchanged += 1,
+                    _ => {
+                        self.replica.put(ns, key, value).await?;
+                        report.copied += 1;
+                    }
+                }
+            }
+            for (stale, _) in existing {
+                self.replica.delete(ns, &stale).await?;
+                report.deleted += 1;
+            }
+        }
+        report.finished_at_ms = now_ms();
+        *self.last.lock().await = Some(report.clone());
+        Ok(report)
+    }
+
+    /// Sync on the configured interval until `token` is cancelled
+    pub async fn run_schedule(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.interval, || {
+            let replicator = Arc::clone(&self);
+            async move {
+                if let Err(e) = replicator.sync().await {
+                    warn!(error = %e, "replica sync failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+/// Replication as a lifecycle-managed subsystem
+pub struct ReplicaService {
+    replicator: Arc<Replicator>,
+}
+
+impl ReplicaService {
+    /// Wrap a replicator for registration with the lifecycle manager
+    pub const fn new(replicator: Arc<Replicator>) -> Self {
+        Self { replicator }
+    }
+}
+
+#[async_trait]
+impl Subsystem for ReplicaService {
+    fn name(&self) -> &str {
+        "replica"
+    }
+
+    fn startup_order(&self) -> u32 {
+        // Start after the subsystems whose state it copies
+        900
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let replicator = Arc::clone(&self.replicator);
+        spawner
+            .spawn("sync", move |token| replicator.run_schedule(token))
+            .await;
+        Ok(())
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    #[tokio::test]
+    async fn sync_copies_changes_and_deletes_and_view_is_read_only() {
+        let primary: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let ns = Namespace::new("wallet_coins").unwrap();
+        primary.ensure_namespace(&ns).await.unwrap();
+        for key in ["a", "b", "c"] {
+            primary.put(&ns, key, key.as_bytes()).await.unwrap();
+        }
+        let replicator = Replicator::new(
+            ReplicaConfig {
+                namespaces: vec!["wallet_coins".into()],
+                ..ReplicaConfig::default()
+            },
+            Arc::clone(&primary),
+            Arc::new(MemoryBackend::new()),
+        )
+        .unwrap();
+        assert_eq!(replicator.sync().await.unwrap().copied, 3);
+
+        primary.delete(&ns, "a").await.unwrap();
+        primary.put(&ns, "b", b"B").await.unwrap();
+        primary.put(&ns, "d", b"d").await.unwrap();
+        let report = replicator.sync().await.unwrap();
+        assert_eq!((report.copied, report.deleted, report.unchanged), (2, 1, 1));
+
+        let view = replicator.read_only();
+        let keys: Vec<String> = view
+            .scan_prefix(&ns, "")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, ["b", "c", "d"]);
+        assert_eq!(view.get(&ns, "b").await.unwrap().unwrap(), b"B");
+        let err = view.put(&ns, "e", b"e").await.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        assert!(view.delete(&ns, "b").await.is_err());
+    }
+}
@@ -0,0 +1,360 @@
+//! Versioned record schemas and data migrations
+//!
+//! [`Migration`](super::Migration)s change the storage layout; the
+//! migrations here change the JSON records stored in a namespace when their
+//! format evolves between crate versions. Each [`RecordMigration`] rewrites
+//! or drops the records under a key prefix, and the schema version reached
+//! is kept per namespace in the `schema_versions` namespace.
+//!
+//! [`migrate_records`] runs every step against a staged copy first, so a
+//! record that fails to transform aborts the upgrade before anything is
+//! written. [`MigrationMode::DryRun`] stops there and only reports what would
+//! change. Writes are not atomic across a crash; transforms must therefore
+//! accept records they already produced.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult, ErrorCode, ResultExt};
+
+const VERSIONS_NAMESPACE: &str = "schema_versions";
+
+/// Rewrites one record; `None` deletes it
+pub type RecordTransform = fn(Value) -> AnyaResult<Option<Value>>;
+
+/// One step in the evolution of a namespace's record format
+#[derive(Debug, Clone, Copy)]
+pub struct RecordMigration {
+    /// Schema version reached by this step, starting at 1
+    pub version: u32,
+    /// Short description recorded in the schema history
+    pub description: &'static str,
+    /// Only records whose key starts with this prefix are transformed
+    pub prefix: &'static str,
+    /// Transformation applied to each record
+    pub transform: RecordTransform,
+}
+
+/// Record schema of one namespace
+#[derive(Debug, Clone, Copy)]
+pub struct RecordSchema {
+    /// Namespace holding the records
+    pub namespace: &'static str,
+    /// Steps in any order; versions must be unique
+    pub migrations: &'static [RecordMigration],
+}
+
+impl RecordSchema {
+    /// Version written by this build
+    pub fn latest_version(&self) -> u32 {
+        self.migrations.iter().map(|m| m.version).max().unwrap_or(0)
+    }
+}
+
+/// Whether [`migrate_records`] writes its results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationMode {
+    /// Transform and persist the records
+    Apply,
+    /// Transform in memory and report without writing
+    DryRun,
+}
+
+/// Outcome of one migration step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepReport {
+    /// Version reached
+    pub version: u32,
+    /// Step description
+    pub description: String,
+    /// Records under the prefix
+    pub scanned: usize,
+    /// Records whose contents changed
+    pub rewritten: usize,
+    /// Records removed
+    pub deleted: usize,
+}
+
+/// Outcome of [`migrate_records`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    /// Namespace migrated
+    pub namespace: String,
+    /// Version before the run
+    pub from_version: u32,
+    /// Version after the run, or that would be reached by a dry run
+    pub to_version: u32,
+    /// Steps run, in version order
+    pub steps: Vec<StepReport>,
+    /// Whether nothing was written
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SchemaState {
+    version: u32,
+    history: Vec<AppliedStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AppliedStep {
+    version: u32,
+    description: String,
+    applied_at_ms: u64,
+}
+
+/// Record schema version currently stored for `namespace`
+pub async fn schema_version(backend: &dyn StorageBackend, namespace: &str) -> AnyaResult<u32> {
+    Ok(load_state(backend, namespace).await?.version)
+}
+
+/// Bring the records of `schema.namespace` up to the latest version
+pub async fn migrate_records(
+    backend: &dyn StorageBackend,
+    schema: &RecordSchema,
+    mode: MigrationMode,
+) -> AnyaResult<MigrationReport> {
+    let mut steps: Vec<&RecordMigration> = schema.migrations.iter().collect();
+    steps.sort_by_key(|m| m.version);
+    if steps.windows(2).any(|w| w[0].version == w[1].version) {
+        return Err(AnyaError::new(
+            ErrorCode::Config,
+            format!(
+                "duplicate schema versions in namespace {}",
+                schema.namespace
+            ),
+        ));
+    }
+
+    let ns = Namespace::new(schema.namespace)?;
+    backend.ensure_namespace(&ns).await?;
+    let mut state = load_state(backend, schema.namespace).await?;
+    let latest = schema.latest_version();
+    if state.version > latest {
+        return Err(AnyaError::new(
+            ErrorCode::Config,
+            format!(
+                "namespace {} has record schema {} but this build only knows up to {}",
+                schema.namespace, state.version, latest
+            ),
+        ));
+    }
+
+    // Stage every step in memory so one bad record leaves storage untouched
+    let mut staged: BTreeMap<String, Option<Value>> = BTreeMap::new();
+    let mut reports = Vec::new();
+    for step in steps.iter().filter(|m| m.version > state.version) {
+        let mut report = StepReport {
+            version: step.version,
+            description: step.description.to_string(),
+            scanned: 0,
+            rewritten: 0,
+            deleted: 0,
+        };
+        for (key, bytes) in backend.scan_prefix(&ns, step.prefix).await? {
+            let value = match staged.get(&key) {
+                Some(Some(value)) => value.clone(),
+                Some(None) => continue,
+                None => serde_json::from_slice(&bytes)
+                    .with_context(|| format!("reading record {}", key))?,
+            };
+            report.scanned += 1;
+            let context = || format!("migrating record {} to version {}", key, step.version);
+            match (step.transform)(value.clone()).with_context(context)? {
+                Some(next) if next == value => {}
+                Some(next) => {
+                    report.rewritten += 1;
+                    staged.insert(key, Some(next));
+                }
+                None => {
+                    report.deleted += 1;
+                    staged.insert(key, None);
+                }
+            }
+        }
+        reports.push(report);
+    }
+
+    let report = MigrationReport {
+        namespace: schema.namespace.to_string(),
+        from_version: state.version,
+        to_version: latest,
+        steps: reports,
+        dry_run: mode == MigrationMode::DryRun,
+    };
+    if report.dry_run || report.steps.is_empty() {
+        return Ok(report);
+    }
+
+    for (key, value) in &staged {
+        match value {
+            Some(value) => backend.put(&ns, key, &serde_json::to_vec(value)?).await?,
+            None => backend.delete(&ns, key).await.map(drop)?,
+        }
+    }
+    let applied_at_ms = now_ms();
+    state.version = latest;
+    state
+        .history
+        .extend(report.steps.iter().map(|s| AppliedStep {
+            version: s.version,
+            description: s.description.clone(),
+            applied_at_ms,
+        }));
+    save_state(backend, schema.namespace, &state).await?;
+    tracing::info!(
+        namespace = schema.namespace,
+        from = report.from_version,
+        to = report.to_version,
+        "migrated record schema"
+    );
+    Ok(report)
+}
+
+async fn load_state(backend: &dyn StorageBackend, namespace: &str) -> AnyaResult<SchemaState> {
+    let versions = Namespace::new(VERSIONS_NAMESPACE)?;
+    backend.ensure_namespace(&versions).await?;
+    match backend.get(&versions, namespace).await? {
+        Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        None => Ok(SchemaState::default()),
+    }
+}
+
+async fn save_state(
+    backend: &dyn StorageBackend,
+    namespace: &str,
+    state: &SchemaState,
+) -> AnyaResult<()> {
+    let versions = Namespace::new(VERSIONS_NAMESPACE)?;
+    backend
+        .put(&versions, namespace, &serde_json::to_vec(state)?)
+        .await
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+    use serde_json::json;
+
+    fn rename_amount(mut record: Value) -> AnyaResult<Option<Value>> {
+        let object = record
+            .as_object_mut()
+            .ok_or_else(|| AnyaError::invalid_input("record is not an object"))?;
+        if let Some(amount) = object.remove("amount") {
+            object.insert("amount_sat".into(), amount);
+        }
+        Ok(Some(record))
+    }
+
+    fn drop_zero(record: Value) -> AnyaResult<Option<Value>> {
+        Ok((record["amount_sat"] != json!(0)).then_some(record))
+    }
+
+    const SCHEMA: RecordSchema = RecordSchema {
+        namespace: "payments",
+        migrations: &[
+            RecordMigration {
+                version: 2,
+                description: "drop empty payments",
+                prefix: "payment/",
+                transform: drop_zero,
+            },
+            RecordMigration {
+                version: 1,
+                description: "rename amount to amount_sat",
+                prefix: "payment/",
+                transform: rename_amount,
+            },
+        ],
+    };
+
+    async fn seed(backend: &MemoryBackend, records: &[(&str, Value)]) {
+        let ns = Namespace::new("payments").unwrap();
+        backend.ensure_namespace(&ns).await.unwrap();
+        for (key, value) in records {
+            backend
+                .put(&ns, key, &serde_json::to_vec(value).unwrap())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_then_apply_upgrades() {
+        let backend = MemoryBackend::new();
+        seed(
+            &backend,
+            &[
+                ("payment/a", json!({ "amount": 5 })),
+                ("payment/b", json!({ "amount": 0 })),
+                ("other/c", json!("untouched")),
+            ],
+        )
+        .await;
+
+        let dry = migrate_records(&backend, &SCHEMA, MigrationMode::DryRun)
+            .await
+            .unwrap();
+        assert_eq!((dry.from_version, dry.to_version), (0, 2));
+        assert_eq!(
+            dry.steps
+                .iter()
+                .map(|s| (s.version, s.rewritten, s.deleted))
+                .collect::<Vec<_>>(),
+            [(1, 2, 0), (2, 0, 1)]
+        );
+        assert_eq!(schema_version(&backend, "payments").await.unwrap(), 0);
+
+        migrate_records(&backend, &SCHEMA, MigrationMode::Apply)
+            .await
+            .unwrap();
+        let ns = Namespace::new("payments").unwrap();
+        let records = backend.scan_prefix(&ns, "").await.unwrap();
+        assert_eq!(records.len(), 2);
+        let a: Value = serde_json::from_slice(&records[1].1).unwrap();
+        assert_eq!(a, json!({ "amount_sat": 5 }));
+        assert_eq!(schema_version(&backend, "payments").await.unwrap(), 2);
+
+        let again = migrate_records(&backend, &SCHEMA, MigrationMode::Apply)
+            .await
+            .unwrap();
+        assert!(again.steps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_transform_writes_nothing() {
+        let backend = MemoryBackend::new();
+        seed(
+            &backend,
+            &[
+                ("payment/a", json!({ "amount": 5 })),
+                ("payment/b", json!([1, 2])),
+            ],
+        )
+        .await;
+        let err = migrate_records(&backend, &SCHEMA, MigrationMode::Apply)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+
+        let ns = Namespace::new("payments").unwrap();
+        let a = backend.get(&ns, "payment/a").await.unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&a).unwrap(),
+            json!({ "amount": 5 })
+        );
+        assert_eq!(schema_version(&backend, "payments").await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,108 @@
+//! Embedded sled storage backend
+//!
+//! Each namespace is a sled tree; applied migration versions are stored in a
+//! companion `<namespace>/_migrations` tree.
+
+use async_trait::async_trait;
+
+use super::{BackendKind, Migration, Namespace, StorageBackend, StorageConfig};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Sled-backed storage
+pub struct SledBackend {
+    db: ::sled::Db,
+}
+
+impl SledBackend {
+    /// Open the database in the directory given by `config.url`
+    pub fn open(config: &StorageConfig) -> AnyaResult<Self> {
+        let db = ::sled::open(&config.url).map_err(sled_error)?;
+        Ok(Self { db })
+    }
+
+    fn tree(&self, ns: &Namespace) -> AnyaResult<::sled::Tree> {
+        self.db.open_tree(ns.as_str()).map_err(sled_error)
+    }
+
+    fn migrations_tree(&self, ns: &Namespace) -> AnyaResult<::sled::Tree> {
+        self.db
+            .open_tree(format!("{}/_migrations", ns.as_str()))
+            .map_err(sled_error)
+    }
+}
+
+fn sled_error(err: ::sled::Error) -> AnyaError {
+    AnyaError::with_source(ErrorCode::StorageFailure, "sled operation failed", err)
+}
+
+#[async_trait]
+impl StorageBackend for SledBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Sled
+    }
+
+    async fn ensure_namespace(&self, ns: &Namespace) -> AnyaResult<()> {
+        self.tree(ns)?;
+        self.migrations_tree(ns)?;
+        Ok(())
+    }
+
+    async fn get(&self, ns: &Namespace, key: &str) -> AnyaResult<Option<Vec<u8>>> {
+        Ok(self
+            .tree(ns)?
+            .get(key)
+            .map_err(sled_error)?
+            .map(|v| v.to_vec()))
+    }
+
+    async fn put(&self, ns: &Namespace, key: &str, value: &[u8]) -> AnyaResult<()> {
+        self.tree(ns)?.insert(key, value).map_err(sled_error)?;
+        Ok(())
+    }
+
+    async fn delete(&self, ns: &Namespace, key: &str) -> AnyaResult<bool> {
+        Ok(self.tree(ns)?.remove(key).map_err(sled_error)?.is_some())
+    }
+
+    async fn scan_prefix(
+        &self,
+        ns: &Namespace,
+        prefix: &str,
+    ) -> AnyaResult<Vec<(String, Vec<u8>)>> {
+        self.tree(ns)?
+            .scan_prefix(prefix)
+            .map(|item| {
+                let (k, v) = item.map_err(sled_error)?;
+                let key = String::from_utf8(k.to_vec()).map_err(|e| {
+                    AnyaError::with_source(ErrorCode::StorageFailure, "non-UTF-8 key", e)
+                })?;
+                Ok((key, v.to_vec()))
+            })
+            .collect()
+    }
+
+    async fn applied_migrations(&self, ns: &Namespace) -> AnyaResult<Vec<u32>> {
+        self.migrations_tree(ns)?
+            .iter()
+            .keys()
+            .map(|k| {
+                let k = k.map_err(sled_error)?;
+                let bytes: [u8; 4] = k.as_ref().try_into().map_err(|_| {
+                    AnyaError::new(ErrorCode::StorageFailure, "corrupt migration key")
+                })?;
+                Ok(u32::from_be_bytes(bytes))
+            })
+            .collect()
+    }
+
+    async fn apply_migration(&self, ns: &Namespace, migration: &Migration) -> AnyaResult<()> {
+        self.migrations_tree(ns)?
+            .insert(
+                migration.version.to_be_bytes(),
+                migration.description.as_bytes(),
+            )
+            .map_err(sled_error)?;
+        self.db.flush_async().await.map_err(sled_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! SQLite storage backend
+//!
+//! SQLite has no schemas, so each namespace is a table prefix:
+//! `<namespace>_kv` and `<namespace>__migrations`.
+
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{Executor, Row};
+use std::str::FromStr;
+
+use super::{
+    BackendKind, Migration, Namespace, StorageBackend, StorageConfig, NAMESPACE_PLACEHOLDER,
+};
+use crate::{AnyaResult, ResultExt};
+
+/// SQLite-backed storage using a connection pool
+pub struct SqliteBackend {
+    pool: SqlitePool,
+}
+
+impl SqliteBackend {
+    /// Open (creating if needed) the database at `config.url`
+    pub async fn connect(config: &StorageConfig) -> AnyaResult<Self> {
+        let options = SqliteConnectOptions::from_str(&config.url)
+            .context("parsing sqlite url")?
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .connect_with(options)
+            .await
+            .context("opening sqlite database")?;
+        Ok(Self { pool })
+    }
+}
+
+fn kv(ns: &Namespace) -> String {
+    format!("\"{}_kv\"", ns.as_str())
+}
+
+fn migrations(ns: &Namespace) -> String {
+    format!("\"{}__migrations\"", ns.as_str())
+}
+
+#[async_trait]
+impl StorageBackend for SqliteBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Sqlite
+    }
+
+    async fn ensure_namespace(&self, ns: &Namespace) -> AnyaResult<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (key TEXT PRIMARY KEY, value BLOB NOT NULL)",
+            kv(ns)
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             version INTEGER PRIMARY KEY, \
+             description TEXT NOT NULL, \
+             applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP)",
+            migrations(ns)
+        ))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get(&self, ns: &Namespace, key: &str) -> AnyaResult<Option<Vec<u8>>> {
+        let row = sqlx::query(&format!("SELECT value FROM {} WHERE key = ?1", kv(ns)))
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.get::<Vec<u8>, _>(0)))
+    }
+
+    async fn put(&self, ns: &Namespace, key: &str, value: &[u8]) -> AnyaResult<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (key, value) VALUES (?1, ?2) \
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            kv(ns)
+        ))
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, ns: &Namespace, key: &str) -> AnyaResult<bool> {
+        let result = sqlx::query(&format!("DELETE FROM {} WHERE key = ?1", kv(ns)))
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn scan_prefix(
+        &self,
+        ns: &Namespace,
+        prefix: &str,
+    ) -> AnyaResult<Vec<(String, Vec<u8>)>> {
+        let rows = sqlx::query(&format!(
+            "SELECT key, value FROM {} WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key",
+            kv(ns)
+        ))
+        .bind(prefix)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| (r.get::<String, _>(0), r.get::<Vec<u8>, _>(1)))
+            .collect())
+    }
+
+    async fn applied_migrations(&self, ns: &Namespace) -> AnyaResult<Vec<u32>> {
+        let rows = sqlx::query(&format!(
+            "SELECT version FROM {} ORDER BY version",
+            migrations(ns)
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| r.get::<i64, _>(0).unsigned_abs() as u32)
+            .collect())
+    }
+
+    async fn apply_migration(&self, ns: &Namespace, migration: &Migration) -> AnyaResult<()> {
+        let sql = migration
+            .sql
+            .replace(NAMESPACE_PLACEHOLDER, &format!("{}_", ns.as_str()));
+        let mut tx = self.pool.begin().await?;
+        tx.execute(sql.as_str())
+            .await
+            .with_context(|| format!("applying migration {}", migration.version))?;
+        sqlx::query(&format!(
+            "INSERT INTO {} (version, description) VALUES (?1, ?2)",
+            migrations(ns)
+        ))
+        .bind(i64::from(migration.version))
+        .bind(migration.description)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::run_migrations;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sqlite_roundtrip_with_migrations() {
+        let config = StorageConfig {
+            backend: BackendKind::Sqlite,
+            url: "sqlite::memory:".to_string(),
+            max_connections: 1,
+            ..StorageConfig::default()
+        };
+        let backend = SqliteBackend::connect(&config).await.unwrap();
+        let ns = Namespace::new("wallet").unwrap();
+        let applied = run_migrations(
+            &backend,
+            &ns,
+            &[Migration {
+                version: 1,
+                description: "labels",
+                sql: "CREATE TABLE <ns>labels (ref TEXT PRIMARY KEY, label TEXT)",
+            }],
+        )
+        .await
+        .unwrap();
+        assert_eq!(applied, vec![1]);
+        assert_eq!(backend.applied_migrations(&ns).await.unwrap(), vec![1]);
+
+        backend.put(&ns, "Addr/1", b"x").await.unwrap();
+        backend.put(&ns, "addr/2", b"y").await.unwrap();
+        let scanned = backend.scan_prefix(&ns, "addr/").await.unwrap();
+        assert_eq!(scanned, vec![("addr/2".to_string(), b"y".to_vec())]);
+    }
+}
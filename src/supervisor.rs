@@ -0,0 +1,597 @@
+//! Supervision of long-running components
+//!
+//! A [`Supervisor`] runs a set of [`Supervised`] children, e.g. agent loops
+//! or services, and restarts them when they fail:
+//!
+//! - [`Strategy::OneForOne`] restarts only the failed child,
+//!   [`Strategy::OneForAll`] restarts every child along with it.
+//! - Restarts back off exponentially with the number of failures inside the
+//!   [`RestartPolicy::window`].
+//! - A child failing more than [`RestartPolicy::max_restarts`] times inside
+//!   the window is flapping. It is either quarantined for a while or the
+//!   failure is escalated: the supervisor stops all children and fails
+//!   itself, so its own supervisor decides what happens next.
+//!
+//! Supervisors are [`Supervised`] themselves, which builds the tree, and a
+//! [`Subsystem`] so the root can be registered with the lifecycle manager.
+//! [`Supervisor::health`] reports the state of the whole tree.
+
+use std::collections::VecDeque;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::lifecycle::{Subsystem, TaskSpawner};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// A component that runs until cancelled or failed
+#[async_trait]
+pub trait Supervised: Send + Sync {
+    /// Stable name used in health reports and logs
+    fn name(&self) -> &str;
+
+    /// Run until `token` is cancelled. Returning `Ok` before that means the
+    /// component finished and is not restarted.
+    async fn run(&self, token: CancellationToken) -> AnyaResult<()>;
+
+    /// Health of the component's own children, for nested supervisors
+    fn health(&self) -> Option<SupervisorHealth> {
+        None
+    }
+}
+
+/// Which children are restarted when one fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Only the failed child
+    OneForOne,
+    /// Every child
+    OneForAll,
+}
+
+/// What happens to a flapping child
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFlapping {
+    /// Keep it stopped for the given time, then try again
+    Quarantine(Duration),
+    /// Stop the supervisor and fail it
+    Escalate,
+}
+
+/// Restart policy of a child
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RestartPolicy {
+    /// Failures tolerated inside `window`
+    pub max_restarts: u32,
+    /// Window failures are counted in
+    pub window: Duration,
+    /// Delay before the first restart
+    pub initial_backoff: Duration,
+    /// Longest delay between restarts
+    pub max_backoff: Duration,
+    /// Backoff growth per failure
+    pub multiplier: f64,
+    /// Handling of a child failing more often than allowed
+    pub on_flapping: OnFlapping,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            on_flapping: OnFlapping::Quarantine(Duration::from_secs(300)),
+        }
+    }
+}
+
+impl RestartPolicy {
+    fn backoff(&self, failures: usize) -> Duration {
+        let exponent = i32::try_from(failures.saturating_sub(1)).unwrap_or(i32::MAX);
+        let secs = self.initial_backoff.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        Duration::from_secs_f64(secs.min(self.max_backoff.as_secs_f64()))
+    }
+}
+
+/// Supervision state of a child
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChildStatus {
+    /// Not started yet
+    Pending,
+    /// Running
+    Running,
+    /// Waiting to be restarted after a failure
+    BackingOff,
+    /// Stopped for flapping
+    Quarantined,
+    /// Finished or stopped with the supervisor
+    Stopped,
+    /// Failed and escalated
+    Failed,
+}
+
+/// Health of one supervised child
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChildHealth {
+    /// Child name
+    pub name: String,
+    /// Current state
+    pub status: ChildStatus,
+    /// Restarts since the supervisor started
+    pub restarts: u64,
+    /// Failures inside the restart window
+    pub recent_failures: u32,
+    /// Last error
+    pub last_error: Option<String>,
+    /// End of the quarantine, seconds since the Unix epoch
+    pub quarantined_until: Option<u64>,
+    /// The child's own children, if it is a supervisor
+    pub subtree: Option<SupervisorHealth>,
+}
+
+/// Overall health
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// Everything is running
+    Healthy,
+    /// Some children are restarting
+    Degraded,
+    /// Some children are quarantined or failed
+    Unhealthy,
+}
+
+/// Health of a supervisor and its children
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupervisorHealth {
+    /// Supervisor name
+    pub name: String,
+    /// Worst status in the tree
+    pub status: HealthStatus,
+    /// Children, in registration order
+    pub children: Vec<ChildHealth>,
+}
+
+struct Child {
+    component: Arc<dyn Supervised>,
+    policy: RestartPolicy,
+    failures: VecDeque<Instant>,
+    generation: u64,
+    token: CancellationToken,
+    health: ChildHealth,
+}
+
+type Exit = (usize, u64, AnyaResult<()>);
+
+enum Action {
+    Ignore,
+    Restart(Duration),
+    Escalate(AnyaError),
+}
+
+/// Restarts failed children according to their policies
+pub struct Supervisor {
+    name: String,
+    strategy: Strategy,
+    children: Arc<Mutex<Vec<Child>>>,
+}
+
+impl Supervisor {
+    /// Create a supervisor without children
+    pub fn new(name: impl Into<String>, strategy: Strategy) -> Self {
+        Self {
+            name: name.into(),
+            strategy,
+            children: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Add a child; takes effect the next time the supervisor runs
+    pub fn supervise(&self, component: Arc<dyn Supervised>, policy: RestartPolicy) {
+        let health = ChildHealth {
+            name: component.name().to_string(),
+            status: ChildStatus::Pending,
+            restarts: 0,
+            recent_failures: 0,
+            last_error: None,
+            quarantined_until: None,
+            subtree: None,
+        };
+        self.children().push(Child {
+            component,
+            policy,
+            failures: VecDeque::new(),
+            generation: 0,
+            token: CancellationToken::new(),
+            health,
+        });
+    }
+
+    /// Health of this supervisor and everything below it
+    pub fn health(&self) -> SupervisorHealth {
+        let children: Vec<ChildHealth> = self
+            .children()
+            .iter()
+            .map(|c| ChildHealth {
+                subtree: c.component.health(),
+                ..c.health.clone()
+            })
+            .collect();
+        let status = children
+            .iter()
+            .map(|c| {
+                let own = match c.status {
+                    ChildStatus::Quarantined | ChildStatus::Failed => HealthStatus::Unhealthy,
+                    ChildStatus::BackingOff => HealthStatus::Degraded,
+                    _ => HealthStatus::Healthy,
+                };
+                c.subtree.as_ref().map_or(own, |s| own.max(s.status))
+            })
+            .max()
+            .unwrap_or(HealthStatus::Healthy);
+        SupervisorHealth {
+            name: self.name.clone(),
+            status,
+            children,
+        }
+    }
+
+    /// Run the children until `token` is cancelled or a failure escalates
+    pub async fn supervise_until(&self, token: CancellationToken) -> AnyaResult<()> {
+        let mut tasks = JoinSet::new();
+        let count = self.children().len();
+        for index in 0..count {
+            self.spawn(&mut tasks, &token, index, Duration::ZERO);
+        }
+        let mut result = Ok(());
+        loop {
+            let joined = tokio::select! {
+                () = token.cancelled() => break,
+                joined = tasks.join_next() => joined,
+            };
+            let Some(joined) = joined else {
+                break;
+            };
+            let Ok((index, generation, exit)) = joined else {
+                continue;
+            };
+            let action = self.handle_exit(&mut self.children()[index], generation, exit);
+            match action {
+                Action::Ignore => {}
+                Action::Restart(delay) => match self.strategy {
+                    Strategy::OneForOne => self.spawn(&mut tasks, &token, index, delay),
+                    Strategy::OneForAll => {
+                        for other in 0..count {
+                            let delay = if other == index {
+                                delay
+                            } else {
+                                Duration::ZERO
+                            };
+                            self.spawn(&mut tasks, &token, other, delay);
+                        }
+                    }
+                },
+                Action::Escalate(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        for child in self.children().iter_mut() {
+            child.token.cancel();
+            if child.health.status != ChildStatus::Failed {
+                child.health.status = ChildStatus::Stopped;
+            }
+        }
+        while tasks.join_next().await.is_some() {}
+        result
+    }
+
+    /// Start (or restart) child `index` after `delay`, superseding any
+    /// previous run
+    fn spawn(
+        &self,
+        tasks: &mut JoinSet<Exit>,
+        parent: &CancellationToken,
+        index: usize,
+        delay: Duration,
+    ) {
+        let mut children = self.children();
+        let child = &mut children[index];
+        child.token.cancel();
+        child.generation += 1;
+        child.token = parent.child_token();
+        let (component, token, generation) = (
+            Arc::clone(&child.component),
+            child.token.clone(),
+            child.generation,
+        );
+        drop(children);
+        let children = Arc::clone(&self.children);
+        tasks.spawn(async move {
+            tokio::select! {
+                () = token.cancelled() => return (index, generation, Ok(())),
+                () = tokio::time::sleep(delay) => {}
+            }
+            if let Some(child) = children
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get_mut(index)
+                .filter(|c| c.generation == generation)
+            {
+                child.health.status = ChildStatus::Running;
+                child.health.quarantined_until = None;
+            }
+            // A panicking component counts as a failed one
+            let exit = AssertUnwindSafe(component.run(token))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| Err(AnyaError::new(ErrorCode::Internal, "component panicked")));
+            (index, generation, exit)
+        });
+    }
+
+    fn handle_exit(&self, child: &mut Child, generation: u64, exit: AnyaResult<()>) -> Action {
+        if child.generation != generation {
+            // A run superseded by a restart
+            return Action::Ignore;
+        }
+        let error = match exit {
+            Ok(()) => {
+                child.health.status = ChildStatus::Stopped;
+                return Action::Ignore;
+            }
+            Err(e) => e,
+        };
+        let now = Instant::now();
+        child.failures.push_back(now);
+        while child
+            .failures
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > child.policy.window)
+        {
+            child.failures.pop_front();
+        }
+        tracing::warn!(
+            supervisor = %self.name,
+            child = %child.health.name,
+            error = %error,
+            "supervised component failed"
+        );
+        child.health.last_error = Some(error.to_string());
+        child.health.recent_failures = u32::try_from(child.failures.len()).unwrap_or(u32::MAX);
+        if child.failures.len() <= child.policy.max_restarts as usize {
+            child.health.restarts += 1;
+            child.health.status = ChildStatus::BackingOff;
+            return Action::Restart(child.policy.backoff(child.failures.len()));
+        }
+        match child.policy.on_flapping {
+            OnFlapping::Quarantine(period) => {
+                tracing::warn!(child = %child.health.name, "quarantining flapping component");
+                child.failures.clear();
+                child.health.restarts += 1;
+                child.health.status = ChildStatus::Quarantined;
+                child.health.quarantined_until = Some(unix_now() + period.as_secs());
+                Action::Restart(period)
+            }
+            OnFlapping::Escalate => {
+                child.health.status = ChildStatus::Failed;
+                Action::Escalate(AnyaError::new(
+                    ErrorCode::Unavailable,
+                    format!(
+                        "{} in {} failed {} times within {:?}: {}",
+                        child.health.name,
+                        self.name,
+                        child.failures.len(),
+                        child.policy.window,
+                        error
+                    ),
+                ))
+            }
+        }
+    }
+
+    fn children(&self) -> MutexGuard<'_, Vec<Child>> {
+        self.children.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl Supervised for Supervisor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&self, token: CancellationToken) -> AnyaResult<()> {
+        self.supervise_until(token).await
+    }
+
+    fn health(&self) -> Option<SupervisorHealth> {
+        Some(Self::health(self))
+    }
+}
+
+/// The root supervisor runs as a subsystem until shutdown
+#[async_trait]
+impl Subsystem for Arc<Supervisor> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let supervisor = Self::clone(self);
+        spawner
+            .spawn("supervise", move |token| async move {
+                supervisor.supervise_until(token).await
+            })
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails (or panics) on its first `failures` runs, then runs until
+    /// cancelled
+    struct Component {
+        name: &'static str,
+        failures: u32,
+        panics: bool,
+        starts: AtomicU32,
+    }
+
+    impl Component {
+        fn new(name: &'static str, failures: u32) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                failures,
+                panics: false,
+                starts: AtomicU32::new(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Supervised for Component {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn run(&self, token: CancellationToken) -> AnyaResult<()> {
+            let start = self.starts.fetch_add(1, Ordering::SeqCst);
+            if start < self.failures {
+                assert!(!self.panics, "{} crashed", self.name);
+                return Err(AnyaError::new(ErrorCode::Internal, "boom"));
+            }
+            token.cancelled().await;
+            Ok(())
+        }
+    }
+
+    /// Wait until `done` holds; panics take a while to report under the
+    /// test harness
+    async fn settle(supervisor: &Supervisor, done: impl Fn(&SupervisorHealth) -> bool) {
+        for _ in 0..500 {
+            if done(&supervisor.health()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("supervisor did not settle: {:?}", supervisor.health());
+    }
+
+    fn policy(max_restarts: u32, on_flapping: OnFlapping) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            on_flapping,
+            ..RestartPolicy::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restarts_quarantine_and_escalation() {
+        let root = Arc::new(Supervisor::new("root", Strategy::OneForOne));
+        let flaky = Component::new("flaky", 2);
+        root.supervise(flaky.clone(), policy(3, OnFlapping::Escalate));
+
+        let workers = Arc::new(Supervisor::new("workers", Strategy::OneForOne));
+        workers.supervise(
+            Component::new("broken", u32::MAX),
+            policy(1, OnFlapping::Escalate),
+        );
+        root.supervise(
+            workers,
+            policy(0, OnFlapping::Quarantine(Duration::from_secs(3600))),
+        );
+
+        let token = CancellationToken::new();
+        let handle = tokio::spawn({
+            let (root, token) = (Arc::clone(&root), token.clone());
+            async move { root.supervise_until(token).await }
+        });
+        settle(&root, |h| {
+            h.children[0].restarts == 2
+                && h.children[0].status == ChildStatus::Running
+                && h.children[1].status == ChildStatus::Quarantined
+        })
+        .await;
+
+        let health = root.health();
+        assert_eq!(health.status, HealthStatus::Unhealthy);
+        let [flaky_health, workers_health] = &health.children[..] else {
+            panic!("expected two children");
+        };
+        assert_eq!(flaky_health.status, ChildStatus::Running);
+        assert_eq!(flaky_health.restarts, 2);
+        assert_eq!(flaky.starts.load(Ordering::SeqCst), 3);
+        assert_eq!(workers_health.status, ChildStatus::Quarantined);
+        assert!(workers_health.quarantined_until.is_some());
+        let subtree = workers_health.subtree.as_ref().unwrap();
+        assert_eq!(subtree.children[0].status, ChildStatus::Failed);
+        assert!(workers_health
+            .last_error
+            .as_deref()
+            .unwrap()
+            .contains("broken in workers failed 2 times"));
+
+        token.cancel();
+        handle.await.unwrap().unwrap();
+        assert_eq!(root.health().children[0].status, ChildStatus::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_one_for_all_restarts_siblings_and_catches_panics() {
+        let supervisor = Arc::new(Supervisor::new("pipeline", Strategy::OneForAll));
+        let crashing = Arc::new(Component {
+            name: "crashing",
+            failures: 1,
+            panics: true,
+            starts: AtomicU32::new(0),
+        });
+        let sibling = Component::new("sibling", 0);
+        supervisor.supervise(crashing.clone(), policy(1, OnFlapping::Escalate));
+        supervisor.supervise(sibling.clone(), policy(1, OnFlapping::Escalate));
+
+        let token = CancellationToken::new();
+        let handle = tokio::spawn({
+            let (supervisor, token) = (Arc::clone(&supervisor), token.clone());
+            async move { supervisor.supervise_until(token).await }
+        });
+        settle(&supervisor, |h| {
+            h.children[0].status == ChildStatus::Running
+                && h.children[0].restarts == 1
+                && sibling.starts.load(Ordering::SeqCst) == 2
+        })
+        .await;
+        assert_eq!(crashing.starts.load(Ordering::SeqCst), 2);
+        assert_eq!(sibling.starts.load(Ordering::SeqCst), 2);
+        let health = supervisor.health();
+        assert_eq!(health.status, HealthStatus::Healthy);
+        assert!(health.children[0]
+            .last_error
+            .as_deref()
+            .unwrap()
+            .contains("component panicked"));
+        token.cancel();
+        handle.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,972 @@
+//! Embedded time-series storage for internal metrics
+//!
+//! The [`TimeSeriesStore`] keeps samples of every metric series in its own
+//! storage namespace, so metrics can be charted and alerted on over time
+//! rather than only read at one instant. Components expose their metrics
+//! through [`MetricsSource`], and the [`TimeSeriesService`] scrapes every
+//! source on an interval.
+//!
+//! Samples are kept in tiers: raw samples first, then each configured
+//! rollup, whose buckets aggregate the tier before it at a coarser
+//! resolution. Every tier drops data older than its retention, so storage
+//! stays bounded while long ranges remain queryable at low resolution.
+//! Queries read the finest tier that still covers the requested range and
+//! fill in from finer tiers where the rollups have not caught up yet.
+//!
+//! Each tier is stored in chunks of a fixed time span per series, keyed
+//! `chunk/<tier>/<series>/<start>`, so a range read fetches only the chunks
+//! overlapping it. The newest raw chunk of every series is held in memory
+//! and written back by [`TimeSeriesStore::flush`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::lifecycle::{run_loop, Subsystem, TaskSpawner};
+use crate::ml::scheduler::AgentScheduler;
+use crate::storage::{Namespace, StorageBackend};
+use crate::{AnyaError, AnyaResult};
+
+const NAMESPACE: &str = "timeseries";
+const SERIES_PREFIX: &str = "series/";
+const CHUNK_PREFIX: &str = "chunk/";
+const ROLLED_PREFIX: &str = "rolled/";
+const OLDEST_PREFIX: &str = "oldest/";
+
+/// Time span of one raw chunk
+const RAW_CHUNK_MS: u64 = 60 * 60 * 1000;
+/// Buckets per rollup chunk
+const ROLLUP_CHUNK_BUCKETS: u64 = 720;
+
+/// Label names and values of a series
+pub type Labels = BTreeMap<String, String>;
+
+/// Identity of a series: a metric name and its labels
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SeriesKey {
+    /// Metric name, e.g. `anya_agent_runs_total`
+    pub name: String,
+    /// Labels distinguishing series of the same metric
+    #[serde(default)]
+    pub labels: Labels,
+}
+
+impl SeriesKey {
+    /// A series without labels
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            labels: Labels::new(),
+        }
+    }
+
+    /// Add a label
+    pub fn with_label(mut self, name: &str, value: &str) -> Self {
+        self.labels.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Whether this series is of metric `name` and has all of `labels`
+    pub fn matches(&self, name: &str, labels: &Labels) -> bool {
+        self.name == name
+            && labels
+                .iter()
+                .all(|(k, v)| self.labels.get(k).is_some_and(|own| own == v))
+    }
+}
+
+/// Prometheus notation, e.g. `anya_agent_runs_total{agent="fees"}`
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if self.labels.is_empty() {
+            return Ok(());
+        }
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(k, v)| format!("{}={:?}", k, v))
+            .collect();
+        write!(f, "{{{}}}", labels.join(","))
+    }
+}
+
+/// A value of a series at a point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// Series the value belongs to
+    pub series: SeriesKey,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// Value
+    pub value: f64,
+}
+
+/// Summary of the samples in a time bucket; a raw sample is a bucket of one
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    /// Bucket start, or sample time for raw samples, in Unix milliseconds
+    pub start_ms: u64,
+    /// Samples summarized
+    pub count: u64,
+    /// Sum of the samples
+    pub sum: f64,
+    /// Smallest sample
+    pub min: f64,
+    /// Largest sample
+    pub max: f64,
+    /// Latest sample
+    pub last: f64,
+}
+
+impl Bucket {
+    const fn sample(timestamp_ms: u64, value: f64) -> Self {
+        Self {
+            start_ms: timestamp_ms,
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+            last: value,
+        }
+    }
+
+    /// Fold in a bucket that starts no earlier than this one
+    fn merge(&mut self, later: &Self) {
+        self.count += later.count;
+        self.sum += later.sum;
+        self.min = self.min.min(later.min);
+        self.max = self.max.max(later.max);
+        self.last = later.last;
+    }
+
+    /// The summary `aggregation` asks for
+    pub fn value(&self, aggregation: Aggregation) -> f64 {
+        match aggregation {
+            Aggregation::Avg => self.sum / self.count.max(1) as f64,
+            Aggregation::Min => self.min,
+            Aggregation::Max => self.max,
+            Aggregation::Sum => self.sum,
+            Aggregation::Count => self.count as f64,
+            Aggregation::Last => self.last,
+        }
+    }
+}
+
+/// How the samples of a bucket are reduced to one value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// Mean
+    #[default]
+    Avg,
+    /// Minimum
+    Min,
+    /// Maximum
+    Max,
+    /// Sum
+    Sum,
+    /// Number of samples
+    Count,
+    /// Latest sample, e.g. for counters
+    Last,
+}
+
+/// A downsampled tier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rollup {
+    /// Bucket width
+    pub resolution: Duration,
+    /// How long buckets are kept
+    pub retention: Duration,
+}
+
+/// Retention, downsampling, and scrape scheduling
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeSeriesConfig {
+    /// How long raw samples are kept
+    pub raw_retention: Duration,
+    /// Rollups from finest to coarsest; each resolution must be a multiple
+    /// of the one before
+    pub rollups: Vec<Rollup>,
+    /// Interval between scrapes of the registered sources
+    pub scrape_interval: Duration,
+    /// Interval between flushes, rollups, and retention passes
+    pub maintenance_interval: Duration,
+}
+
+impl Default for TimeSeriesConfig {
+    fn default() -> Self {
+        Self {
+            raw_retention: Duration::from_secs(2 * 24 * 60 * 60),
+            rollups: vec![
+                Rollup {
+                    resolution: Duration::from_secs(60),
+                    retention: Duration::from_secs(14 * 24 * 60 * 60),
+                },
+                Rollup {
+                    resolution: Duration::from_secs(60 * 60),
+                    retention: Duration::from_secs(400 * 24 * 60 * 60),
+                },
+            ],
+            scrape_interval: Duration::from_secs(15),
+            maintenance_interval: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// A range query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Query {
+    /// Metric name
+    pub name: String,
+    /// Labels every returned series must have
+    #[serde(default)]
+    pub labels: Labels,
+    /// Range start in Unix milliseconds, inclusive
+    pub from_ms: u64,
+    /// Range end in Unix milliseconds, exclusive
+    pub to_ms: u64,
+    /// Width of the returned buckets; `None` returns points as stored
+    pub step: Option<Duration>,
+    /// Reduction of each bucket
+    #[serde(default)]
+    pub aggregation: Aggregation,
+}
+
+/// Points of one series returned by a query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeriesData {
+    /// Series
+    pub key: SeriesKey,
+    /// Timestamp and value pairs in time order
+    pub points: Vec<(u64, f64)>,
+}
+
+/// A component whose current metrics are scraped periodically
+#[async_trait]
+pub trait MetricsSource: Send + Sync {
+    /// Source name used in logs
+    fn name(&self) -> &str;
+
+    /// Current value of every series the source exposes
+    async fn collect(&self) -> AnyaResult<Vec<(SeriesKey, f64)>>;
+}
+
+/// Agent run counts and busy time, labelled by agent
+#[async_trait]
+impl MetricsSource for AgentScheduler {
+    fn name(&self) -> &str {
+        "agent_scheduler"
+    }
+
+    async fn collect(&self) -> AnyaResult<Vec<(SeriesKey, f64)>> {
+        let mut values = Vec::new();
+        for (agent, m) in self.metrics() {
+            let key = |name: &str| SeriesKey::new(name).with_label("agent", &agent);
+            values.push((key("anya_agent_runs_total"), m.runs as f64));
+            values.push((key("anya_agent_failures_total"), m.failures as f64));
+            values.push((key("anya_agent_busy_seconds_total"), m.busy.as_secs_f64()));
+            values.push((
+                key("anya_agent_deadline_misses_total"),
+                m.deadline_misses as f64,
+            ));
+            values.push((key("anya_agent_starvations_total"), m.starvations as f64));
+        }
+        Ok(values)
+    }
+}
+
+/// A tier's bucket width and chunk span in milliseconds; raw is tier 0
+#[derive(Debug, Clone, Copy)]
+struct Tier {
+    resolution_ms: u64,
+    chunk_ms: u64,
+    retention_ms: u64,
+}
+
+/// The newest raw chunk of a series
+struct Head {
+    start: u64,
+    points: Vec<Bucket>,
+    dirty: bool,
+}
+
+struct State {
+    ids: HashMap<SeriesKey, u32>,
+    keys: Vec<SeriesKey>,
+    heads: HashMap<u32, Head>,
+    /// Per rollup tier, the time up to which it has been rolled up
+    rolled: Vec<u64>,
+    /// Per tier, the start of the oldest chunk that may exist
+    oldest: Vec<Option<u64>>,
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+const fn align(ms: u64, span: u64) -> u64 {
+    ms - ms % span
+}
+
+fn chunk_key(tier: usize, id: u32, start: u64) -> String {
+    format!("{}{:02}/{:010}/{:020}", CHUNK_PREFIX, tier, id, start)
+}
+
+/// Insert `bucket` in time order, replacing one with the same start
+fn upsert(points: &mut Vec<Bucket>, bucket: Bucket) {
+    match points.binary_search_by_key(&bucket.start_ms, |b| b.start_ms) {
+        Ok(i) => points[i] = bucket,
+        Err(i) => points.insert(i, bucket),
+    }
+}
+
+/// Embedded, downsampling time-series store
+pub struct TimeSeriesStore {
+    config: TimeSeriesConfig,
+    tiers: Vec<Tier>,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    state: Mutex<State>,
+    sources: Vec<Arc<dyn MetricsSource>>,
+}
+
+impl TimeSeriesStore {
+    /// Open the store, loading the series index and rollup progress
+    pub async fn open(
+        config: TimeSeriesConfig,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Self> {
+        let mut tiers = vec![Tier {
+            resolution_ms: 0,
+            chunk_ms: RAW_CHUNK_MS,
+            retention_ms: millis(config.raw_retention),
+        }];
+        for rollup in &config.rollups {
+            let resolution_ms = millis(rollup.resolution);
+            let previous = tiers[tiers.len() - 1].resolution_ms;
+            if resolution_ms == 0
+                || resolution_ms <= previous
+                || resolution_ms % previous.max(1) != 0
+            {
+                return Err(AnyaError::invalid_input(format!(
+                    "rollup resolution {:?} is not a multiple of the previous tier's",
+                    rollup.resolution
+                )));
+            }
+            tiers.push(Tier {
+                resolution_ms,
+                chunk_ms: resolution_ms * ROLLUP_CHUNK_BUCKETS,
+                retention_ms: millis(rollup.retention),
+            });
+        }
+
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        let mut keys = Vec::new();
+        for (_, value) in storage.scan_prefix(&ns, SERIES_PREFIX).await? {
+            keys.push(serde_json::from_slice::<SeriesKey>(&value)?);
+        }
+        let ids = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (key.clone(), u32::try_from(i).unwrap_or(u32::MAX)))
+            .collect();
+        let mut rolled = Vec::new();
+        for tier in 1..tiers.len() {
+            let key = format!("{}{:02}", ROLLED_PREFIX, tier);
+            rolled.push(match storage.get(&ns, &key).await? {
+                Some(bytes) => serde_json::from_slice(&bytes)?,
+                None => 0,
+            });
+        }
+        let mut oldest = Vec::new();
+        for tier in 0..tiers.len() {
+            let key = format!("{}{:02}", OLDEST_PREFIX, tier);
+            oldest.push(match storage.get(&ns, &key).await? {
+                Some(bytes) => Some(serde_json::from_slice(&bytes)?),
+                None => None,
+            });
+        }
+
+        Ok(Self {
+            config,
+            tiers,
+            storage,
+            ns,
+            state: Mutex::new(State {
+                ids,
+                keys,
+                heads: HashMap::new(),
+                rolled,
+                oldest,
+            }),
+            sources: Vec::new(),
+        })
+    }
+
+    /// Register a source for the scrape loop
+    pub fn add_source(&mut self, source: Arc<dyn MetricsSource>) {
+        self.sources.push(source);
+    }
+
+    /// Every series recorded so far
+    pub async fn series(&self) -> Vec<SeriesKey> {
+        self.state.lock().await.keys.clone()
+    }
+
+    async fn load_chunk(&self, tier: usize, id: u32, start: u64) -> AnyaResult<Vec<Bucket>> {
+        match self
+            .storage
+            .get(&self.ns, &chunk_key(tier, id, start))
+            .await?
+        {
+            Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn save_chunk(
+        &self,
+        state: &mut State,
+        tier: usize,
+        id: u32,
+        start: u64,
+        points: &[Bucket],
+    ) -> AnyaResult<()> {
+        self.storage
+            .put(
+                &self.ns,
+                &chunk_key(tier, id, start),
+                &serde_json::to_vec(points)?,
+            )
+            .await?;
+        if state.oldest[tier].map_or(true, |oldest| start < oldest) {
+            state.oldest[tier] = Some(start);
+            self.storage
+                .put(
+                    &self.ns,
+                    &format!("{}{:02}", OLDEST_PREFIX, tier),
+                    &serde_json::to_vec(&start)?,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn series_id(&self, state: &mut State, key: &SeriesKey) -> AnyaResult<u32> {
+        if let Some(id) = state.ids.get(key) {
+            return Ok(*id);
+        }
+        let id = u32::try_from(state.keys.len())
+            .map_err(|_| AnyaError::invalid_input("too many time series"))?;
+        self.storage
+            .put(
+                &self.ns,
+                &format!("{}{:010}", SERIES_PREFIX, id),
+                &serde_json::to_vec(key)?,
+            )
+            .await?;
+        state.ids.insert(key.clone(), id);
+        state.keys.push(key.clone());
+        Ok(id)
+    }
+
+    /// Record samples; a sample at an already recorded time replaces it
+    pub async fn record(&self, samples: &[Sample]) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        for sample in samples {
+            let id = self.series_id(&mut state, &sample.series).await?;
+            let start = align(sample.timestamp_ms, RAW_CHUNK_MS);
+            let bucket = Bucket::sample(sample.timestamp_ms, sample.value);
+            let head_start = state.heads.get(&id).map(|h| h.start);
+            if head_start.is_some_and(|h| start < h) {
+                // Late sample for a chunk already written back
+                let mut points = self.load_chunk(0, id, start).await?;
+                upsert(&mut points, bucket);
+                self.save_chunk(&mut state, 0, id, start, &points).await?;
+                continue;
+            }
+            if head_start != Some(start) {
+                if let Some(old) = state.heads.remove(&id) {
+                    if old.dirty {
+                        self.save_chunk(&mut state, 0, id, old.start, &old.points)
+                            .await?;
+                    }
+                }
+                let points = self.load_chunk(0, id, start).await?;
+                state.heads.insert(
+                    id,
+                    Head {
+                        start,
+                        points,
+                        dirty: false,
+                    },
+                );
+            }
+            if let Some(head) = state.heads.get_mut(&id) {
+                upsert(&mut head.points, bucket);
+                head.dirty = true;
+            }
+        }
+        drop(state);
+        Ok(())
+    }
+
+    /// Write back the in-memory raw chunks
+    pub async fn flush(&self) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        let result = self.flush_locked(&mut state).await;
+        drop(state);
+        result
+    }
+
+    async fn flush_locked(&self, state: &mut State) -> AnyaResult<()> {
+        let dirty: Vec<(u32, u64, Vec<Bucket>)> = state
+            .heads
+            .iter_mut()
+            .filter(|(_, head)| head.dirty)
+            .map(|(id, head)| {
+                head.dirty = false;
+                (*id, head.start, head.points.clone())
+            })
+            .collect();
+        for (id, start, points) in dirty {
+            self.save_chunk(state, 0, id, start, &points).await?;
+        }
+        Ok(())
+    }
+
+    /// Buckets of `tier` in `[from, to)`, read chunk by chunk
+    async fn read_tier(
+        &self,
+        state: &State,
+        tier: usize,
+        id: u32,
+        from: u64,
+        to: u64,
+    ) -> AnyaResult<Vec<Bucket>> {
+        let span = self.tiers[tier].chunk_ms;
+        // Raw chunks held in memory may not have been written yet
+        let heads = state.heads.values().map(|h| h.start).filter(|_| tier == 0);
+        let oldest = state.oldest[tier].into_iter().chain(heads).min();
+        let first = align(from, span).max(oldest.unwrap_or(u64::MAX).min(to));
+        let mut points = Vec::new();
+        let mut start = align(first, span);
+        while start < to {
+            let chunk = match state.heads.get(&id) {
+                Some(head) if tier == 0 && head.start == start => head.points.clone(),
+                _ => self.load_chunk(tier, id, start).await?,
+            };
+            points.extend(
+                chunk
+                    .into_iter()
+                    .filter(|b| b.start_ms >= from && b.start_ms < to),
+            );
+            start += span;
+        }
+        Ok(points)
+    }
+
+    /// Buckets in `[from, to)` from `tier`, with the part it has not been
+    /// rolled up to yet read from finer tiers
+    async fn read_range(
+        &self,
+        state: &State,
+        tier: usize,
+        id: u32,
+        mut from: u64,
+        to: u64,
+    ) -> AnyaResult<Vec<Bucket>> {
+        let mut points = Vec::new();
+        for tier in (0..=tier).rev() {
+            let rolled = if tier == 0 {
+                u64::MAX
+            } else {
+                state.rolled[tier - 1]
+            };
+            let upto = to.min(rolled);
+            if from < upto {
+                points.extend(self.read_tier(state, tier, id, from, upto).await?);
+                from = upto;
+            }
+            if from >= to {
+                break;
+            }
+        }
+        Ok(points)
+    }
+
+    /// Aggregate the tier before each rollup into it, up to the last
+    /// complete bucket before `now_ms`.
+    ///
+    /// Samples recorded after their bucket was rolled up stay in the raw
+    /// tier only.
+    pub async fn compact(&self, now_ms: u64) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        self.flush_locked(&mut state).await?;
+        for tier in 1..self.tiers.len() {
+            let resolution = self.tiers[tier].resolution_ms;
+            let end = align(now_ms, resolution);
+            let Some(oldest) = state.oldest[tier - 1] else {
+                continue;
+            };
+            let begin = state.rolled[tier - 1].max(align(oldest, resolution));
+            if begin >= end {
+                continue;
+            }
+            for id in 0..u32::try_from(state.keys.len()).unwrap_or(u32::MAX) {
+                let source = self.read_range(&state, tier - 1, id, begin, end).await?;
+                let mut chunks: BTreeMap<u64, Vec<Bucket>> = BTreeMap::new();
+                for point in source {
+                    let start = align(point.start_ms, resolution);
+                    let chunk = chunks
+                        .entry(align(start, self.tiers[tier].chunk_ms))
+                        .or_default();
+                    match chunk.last_mut() {
+                        Some(bucket) if bucket.start_ms == start => bucket.merge(&point),
+                        _ => chunk.push(Bucket {
+                            start_ms: start,
+                            ..point
+                        }),
+                    }
+                }
+                for (chunk_start, buckets) in chunks {
+                    let mut points = self.load_chunk(tier, id, chunk_start).await?;
+                    for bucket in buckets {
+                        upsert(&mut points, bucket);
+                    }
+                    self.save_chunk(&mut state, tier, id, chunk_start, &points)
+                        .await?;
+                }
+            }
+            state.rolled[tier - 1] = end;
+            self.storage
+                .put(
+                    &self.ns,
+                    &format!("{}{:02}", ROLLED_PREFIX, tier),
+                    &serde_json::to_vec(&end)?,
+                )
+                .await?;
+        }
+        drop(state);
+        Ok(())
+    }
+
+    /// Delete chunks that lie entirely before their tier's retention
+    pub async fn enforce_retention(&self, now_ms: u64) -> AnyaResult<()> {
+        let mut state = self.state.lock().await;
+        for (tier, spec) in self.tiers.iter().enumerate() {
+            let Some(mut start) = state.oldest[tier] else {
+                continue;
+            };
+            let cutoff = now_ms.saturating_sub(spec.retention_ms);
+            if start + spec.chunk_ms > cutoff {
+                continue;
+            }
+            while start + spec.chunk_ms <= cutoff {
+                for id in 0..u32::try_from(state.keys.len()).unwrap_or(u32::MAX) {
+                    self.storage
+                        .delete(&self.ns, &chunk_key(tier, id, start))
+                        .await?;
+                    if tier == 0 && state.heads.get(&id).is_some_and(|h| h.start == start) {
+                        state.heads.remove(&id);
+                    }
+                }
+                start += spec.chunk_ms;
+            }
+            state.oldest[tier] = Some(start);
+            self.storage
+                .put(
+                    &self.ns,
+                    &format!("{}{:02}", OLDEST_PREFIX, tier),
+                    &serde_json::to_vec(&start)?,
+                )
+                .await?;
+        }
+        drop(state);
+        Ok(())
+    }
+
+    /// Points of every series matching the query.
+    ///
+    /// Reads the finest tier still retaining `from_ms`; with a step, the
+    /// coarsest such tier whose resolution fits in the step.
+    pub async fn query(&self, query: &Query) -> AnyaResult<Vec<SeriesData>> {
+        if query.step.is_some_and(|s| s.is_zero()) {
+            return Err(AnyaError::invalid_input("query step must be non-zero"));
+        }
+        let age = now_ms().saturating_sub(query.from_ms);
+        let covering: Vec<usize> = (0..self.tiers.len())
+            .filter(|&t| self.tiers[t].retention_ms >= age || t == self.tiers.len() - 1)
+            .collect();
+        let tier = query
+            .step
+            .and_then(|step| {
+                covering
+                    .iter()
+                    .rev()
+                    .find(|&&t| self.tiers[t].resolution_ms <= millis(step))
+            })
+            .or_else(|| covering.first())
+            .copied()
+            .unwrap_or_default();
+
+        let state = self.state.lock().await;
+        let mut results = Vec::new();
+        for (id, key) in state.keys.iter().enumerate() {
+            if !key.matches(&query.name, &query.labels) {
+                continue;
+            }
+            let id = u32::try_from(id).unwrap_or(u32::MAX);
+            let mut buckets = self
+                .read_range(&state, tier, id, query.from_ms, query.to_ms)
+                .await?;
+            if let Some(step) = query.step {
+                let step = millis(step);
+                let mut merged: Vec<Bucket> = Vec::new();
+                for point in buckets {
+                    let start = align(point.start_ms, step);
+                    match merged.last_mut() {
+                        Some(bucket) if bucket.start_ms == start => bucket.merge(&point),
+                        _ => merged.push(Bucket {
+                            start_ms: start,
+                            ..point
+                        }),
+                    }
+                }
+                buckets = merged;
+            }
+            if !buckets.is_empty() {
+                results.push(SeriesData {
+                    key: key.clone(),
+                    points: buckets
+                        .iter()
+                        .map(|b| (b.start_ms, b.value(query.aggregation)))
+                        .collect(),
+                });
+            }
+        }
+        drop(state);
+        Ok(results)
+    }
+
+    /// Collect every source once and record the values at `now_ms`
+    pub async fn scrape_once(&self, now_ms: u64) -> AnyaResult<()> {
+        let mut samples = Vec::new();
+        for source in &self.sources {
+            match source.collect().await {
+                Ok(values) => samples.extend(values.into_iter().map(|(series, value)| Sample {
+                    series,
+                    timestamp_ms: now_ms,
+                    value,
+                })),
+                Err(e) => warn!(source = source.name(), error = %e, "metrics scrape failed"),
+            }
+        }
+        self.record(&samples).await
+    }
+
+    /// Scrape sources until `token` is cancelled
+    pub async fn run_scrapes(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.scrape_interval, || {
+            let store = Arc::clone(&self);
+            async move {
+                if let Err(e) = store.scrape_once(now_ms()).await {
+                    warn!(error = %e, "recording metrics failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    /// Flush, roll up, and expire data until `token` is cancelled
+    pub async fn run_maintenance(self: Arc<Self>, token: CancellationToken) -> AnyaResult<()> {
+        run_loop(token, self.config.maintenance_interval, || {
+            let store = Arc::clone(&self);
+            async move {
+                let now = now_ms();
+                if let Err(e) = store.compact(now).await {
+                    warn!(error = %e, "metrics rollup failed");
+                }
+                if let Err(e) = store.enforce_retention(now).await {
+                    warn!(error = %e, "metrics retention failed");
+                }
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+/// Scraping and maintenance as a lifecycle-managed subsystem
+pub struct TimeSeriesService {
+    store: Arc<TimeSeriesStore>,
+}
+
+impl TimeSeriesService {
+    /// Wrap a store for registration with the lifecycle manager
+    pub const fn new(store: Arc<TimeSeriesStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl Subsystem for TimeSeriesService {
+    fn name(&self) -> &str {
+        "timeseries"
+    }
+
+    async fn start(&self, spawner: TaskSpawner) -> AnyaResult<()> {
+        let store = Arc::clone(&self.store);
+        spawner
+            .spawn("scrape", move |token| store.run_scrapes(token))
+            .await;
+        let store = Arc::clone(&self.store);
+        spawner
+            .spawn("maintenance", move |token| store.run_maintenance(token))
+            .await;
+        Ok(())
+    }
+
+    async fn stop(&self) -> AnyaResult<()> {
+        self.store.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    const MINUTE: u64 = 60_000;
+
+    fn sample(key: &SeriesKey, timestamp_ms: u64, value: f64) -> Sample {
+        Sample {
+            series: key.clone(),
+            timestamp_ms,
+            value,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rollup_query_and_retention() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let now = now_ms();
+        let base = align(now, RAW_CHUNK_MS) - 3 * RAW_CHUNK_MS;
+        let config = TimeSeriesConfig {
+            raw_retention: Duration::from_secs(2 * 60 * 60),
+            ..TimeSeriesConfig::default()
+        };
+        let store = TimeSeriesStore::open(config.clone(), storage.clone())
+            .await
+            .unwrap();
+        let fees = SeriesKey::new("fee_rate").with_label("target", "1");
+        let other = SeriesKey::new("fee_rate").with_label("target", "6");
+        assert_eq!(fees.to_string(), "fee_rate{target=\"1\"}");
+        // Two samples a minute for three hours, rising by one a minute
+        let samples: Vec<Sample> = (0..180)
+            .flat_map(|m| {
+                let t = base + m * MINUTE;
+                [
+                    sample(&fees, t, m as f64),
+                    sample(&fees, t + 30_000, m as f64 + 0.5),
+                ]
+            })
+            .chain([sample(&other, base, 9.0)])
+            .collect();
+        store.record(&samples).await.unwrap();
+
+        let labels: Labels = [("target".to_string(), "1".to_string())].into();
+        let raw = Query {
+            name: "fee_rate".into(),
+            labels: labels.clone(),
+            from_ms: base,
+            to_ms: base + 2 * MINUTE,
+            step: None,
+            aggregation: Aggregation::Avg,
+        };
+        let result = store.query(&raw).await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].points.len(), 4);
+
+        store.compact(base + 180 * MINUTE).await.unwrap();
+        store.enforce_retention(now).await.unwrap();
+        // The first raw hour is past retention, the minute rollup still has it
+        let reopened = TimeSeriesStore::open(config, storage).await.unwrap();
+        assert_eq!(reopened.series().await.len(), 2);
+        let minutes = Query {
+            step: Some(Duration::from_secs(60)),
+            ..raw.clone()
+        };
+        let result = reopened.query(&minutes).await.unwrap();
+        assert_eq!(result[0].points, vec![(base, 0.25), (base + MINUTE, 1.25)]);
+        let hourly = Query {
+            from_ms: base,
+            to_ms: base + 3 * RAW_CHUNK_MS,
+            step: Some(Duration::from_secs(60 * 60)),
+            aggregation: Aggregation::Max,
+            ..raw
+        };
+        let result = reopened.query(&hourly).await.unwrap();
+        assert_eq!(
+            result[0].points,
+            vec![
+                (base, 59.5),
+                (base + RAW_CHUNK_MS, 119.5),
+                (base + 2 * RAW_CHUNK_MS, 179.5)
+            ]
+        );
+        let both = Query {
+            labels: Labels::new(),
+            aggregation: Aggregation::Count,
+            ..hourly
+        };
+        assert_eq!(reopened.query(&both).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_scrapes_scheduler_metrics() {
+        let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let mut store = TimeSeriesStore::open(TimeSeriesConfig::default(), storage)
+            .await
+            .unwrap();
+        store.add_source(Arc::new(AgentScheduler::new(Default::default())));
+        store.scrape_once(now_ms()).await.unwrap();
+        assert!(store.series().await.is_empty());
+        assert!(TimeSeriesStore::open(
+            TimeSeriesConfig {
+                rollups: vec![
+                    Rollup {
+                        resolution: Duration::from_millis(1500),
+                        retention: Duration::from_secs(60),
+                    },
+                    Rollup {
+                        resolution: Duration::from_secs(2),
+                        retention: Duration::from_secs(60),
+                    }
+                ],
+                ..TimeSeriesConfig::default()
+            },
+            Arc::new(MemoryBackend::new()),
+        )
+        .await
+        .is_err());
+    }
+}
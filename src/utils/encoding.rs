@@ -0,0 +1,154 @@
+//! Small encoding helpers shared across modules
+
+use crate::{AnyaError, AnyaResult};
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+/// Encode bytes as lowercase hex
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        out.push(HEX_CHARS[(b >> 4) as usize] as char);
+        out.push(HEX_CHARS[(b & 0x0f) as usize] as char);
+    }
+    out
+}
+
+/// Decode a hex string (either case) into bytes
+pub fn from_hex(s: &str) -> AnyaResult<Vec<u8>> {
+    if s.len() % 2 == 1 {
+        return Err(AnyaError::invalid_input("hex string has odd length"));
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let hi = hex_val(pair[0])?;
+            let lo = hex_val(pair[1])?;
+            Ok((hi << 4) | lo)
+        })
+        .collect()
+}
+
+fn hex_val(c: u8) -> AnyaResult<u8> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(AnyaError::invalid_input(format!(
+            "invalid hex character {:?}",
+            c as char
+        ))),
+    }
+}
+
+/// SHA-256 digest of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let digest = ring::digest::digest(&ring::digest::SHA256, data);
+    let mut out = [0u8; 32];
+    out.copy_from_slice(digest.as_ref());
+    out
+}
+
+/// Percent-encode a URI query component (RFC 3986 unreserved characters pass through)
+pub fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push('%');
+            out.push(HEX_CHARS[(b >> 4) as usize].to_ascii_uppercase() as char);
+            out.push(HEX_CHARS[(b & 0x0f) as usize].to_ascii_uppercase() as char);
+        }
+    }
+    out
+}
+
+/// Decode a percent-encoded URI component, treating `+` literally
+pub fn percent_decode(s: &str) -> AnyaResult<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let pair = bytes
+                .get(i + 1..i + 3)
+                .ok_or_else(|| AnyaError::invalid_input("truncated percent escape"))?;
+            out.push((hex_val(pair[0])? << 4) | hex_val(pair[1])?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out)
+        .map_err(|_| AnyaError::invalid_input("percent-decoded value is not UTF-8"))
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32 without padding
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 8 / 5 + 1);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &b in data {
+        buffer = (buffer << 8) | u32::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// Decode RFC 4648 base32, ignoring trailing padding
+pub fn base32_decode(s: &str) -> AnyaResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.trim_end_matches('=').bytes() {
+        let val = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())
+            .ok_or_else(|| {
+                AnyaError::invalid_input(format!("invalid base32 character {:?}", c as char))
+            })?;
+        buffer = (buffer << 5) | val as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push(((buffer >> bits) & 0xff) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let data = [0x00, 0xab, 0xff, 0x10];
+        assert_eq!(to_hex(&data), "00abff10");
+        assert_eq!(from_hex("00ABff10").unwrap(), data);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_percent_and_base32_roundtrip() {
+        let text = "Coffee & cake / 2 people";
+        let encoded = percent_encode(text);
+        assert_eq!(encoded, "Coffee%20%26%20cake%20%2F%202%20people");
+        assert_eq!(percent_decode(&encoded).unwrap(), text);
+
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+    }
+}
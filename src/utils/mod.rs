@@ -0,0 +1,7 @@
+//! Common utilities and helper functions
+
+pub mod encoding;
+pub mod pagination;
+pub mod sharded;
+pub mod time;
+pub mod zeroize;
@@ -0,0 +1,239 @@
+//! Cursor-based pagination for list APIs
+//!
+//! List endpoints take a [`PageRequest`] and return a [`Page`]. Items are
+//! ordered by a sort key that is unique per item (ties broken by an id), and
+//! the [`Cursor`] of a page records the key of its last item. The next page
+//! starts strictly after that key, so inserts and deletes between requests
+//! never cause items to be skipped or repeated, unlike offset paging.
+
+use std::fmt;
+use std::str::FromStr;
+
+use ::bitcoin::base64;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{AnyaError, AnyaResult};
+
+/// Page size used when a request does not set one
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+
+/// Largest page a request may ask for
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Sort direction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Smallest key first
+    #[default]
+    Asc,
+    /// Largest key first
+    Desc,
+}
+
+/// Opaque position in a listing, handed back to fetch the next page
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cursor(String);
+
+impl Cursor {
+    /// Cursor positioned after the item with sort key `key`
+    pub fn encode<K: Serialize>(key: &K) -> AnyaResult<Self> {
+        Ok(Self(base64::encode_config(
+            serde_json::to_vec(key)?,
+            base64::URL_SAFE_NO_PAD,
+        )))
+    }
+
+    /// Sort key recorded in the cursor
+    pub fn decode<K: DeserializeOwned>(&self) -> AnyaResult<K> {
+        base64::decode_config(&self.0, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| AnyaError::invalid_input(format!("invalid page cursor {}", self.0)))
+    }
+
+    /// Cursor text
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = AnyaError;
+
+    fn from_str(s: &str) -> AnyaResult<Self> {
+        if s.is_empty() {
+            return Err(AnyaError::invalid_input("empty page cursor"));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Which page of a listing to return
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PageRequest {
+    /// Continue after this cursor; the first page when unset
+    pub cursor: Option<Cursor>,
+    /// Maximum items on the page, clamped to `1..=MAX_PAGE_LIMIT`
+    pub limit: usize,
+    /// Sort direction
+    pub order: SortOrder,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        Self {
+            cursor: None,
+            limit: DEFAULT_PAGE_LIMIT,
+            order: SortOrder::Asc,
+        }
+    }
+}
+
+impl PageRequest {
+    /// First page of `limit` items in ascending order
+    pub fn first(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Same request continuing after `cursor`
+    #[must_use]
+    pub fn after(&self, cursor: Cursor) -> Self {
+        Self {
+            cursor: Some(cursor),
+            ..self.clone()
+        }
+    }
+
+    /// Effective page size
+    pub fn effective_limit(&self) -> usize {
+        self.limit.clamp(1, MAX_PAGE_LIMIT)
+    }
+}
+
+/// One page of a listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    /// Items in sort order
+    pub items: Vec<T>,
+    /// Cursor for the following page, `None` on the last page
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Convert the items, keeping the cursor
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// Sort `items` by `key` and cut out the page selected by `request`.
+///
+/// `key` must be unique per item for pages to be stable; append an id to
+/// keys that can tie.
+pub fn paginate<T, K, F>(items: Vec<T>, request: &PageRequest, key: F) -> AnyaResult<Page<T>>
+where
+    K: Ord + Serialize + DeserializeOwned,
+    F: Fn(&T) -> K,
+{
+    let after: Option<K> = request.cursor.as_ref().map(Cursor::decode).transpose()?;
+    let mut keyed: Vec<(K, T)> = items
+        .into_iter()
+        .map(|item| (key(&item), item))
+        .filter(|(k, _)| match (&after, request.order) {
+            (None, _) => true,
+            (Some(after), SortOrder::Asc) => k > after,
+            (Some(after), SortOrder::Desc) => k < after,
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    if request.order == SortOrder::Desc {
+        keyed.reverse();
+    }
+
+    let limit = request.effective_limit();
+    let has_more = keyed.len() > limit;
+    keyed.truncate(limit);
+    let next_cursor = match keyed.last() {
+        Some((last, _)) if has_more => Some(Cursor::encode(last)?),
+        _ => None,
+    };
+    Ok(Page {
+        items: keyed.into_iter().map(|(_, item)| item).collect(),
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect_all(items: &[(u32, &str)], mut request: PageRequest) -> Vec<String> {
+        let mut seen = Vec::new();
+        loop {
+            let page = paginate(items.to_vec(), &request, |(n, id)| (*n, id.to_string())).unwrap();
+            seen.extend(page.items.iter().map(|(_, id)| id.to_string()));
+            match page.next_cursor {
+                Some(cursor) => request = request.after(cursor),
+                None => return seen,
+            }
+        }
+    }
+
+    #[test]
+    fn test_pages_cover_items_once_in_order() {
+        let items = [(3, "c"), (1, "a"), (2, "b2"), (2, "b1"), (5, "e")];
+        assert_eq!(
+            collect_all(&items, PageRequest::first(2)),
+            ["a", "b1", "b2", "c", "e"]
+        );
+        let desc = PageRequest {
+            order: SortOrder::Desc,
+            ..PageRequest::first(3)
+        };
+        assert_eq!(collect_all(&items, desc), ["e", "c", "b2", "b1", "a"]);
+    }
+
+    #[test]
+    fn test_cursor_is_stable_across_inserts() {
+        let request = PageRequest::first(2);
+        let first = paginate(vec![1u32, 2, 3, 4], &request, |n| *n).unwrap();
+        assert_eq!(first.items, [1, 2]);
+        // An item inserted before the cursor does not shift the next page
+        let next = request.after(first.next_cursor.unwrap());
+        let second = paginate(vec![0u32, 1, 2, 3, 4], &next, |n| *n).unwrap();
+        assert_eq!(second.items, [3, 4]);
+        assert!(second.next_cursor.is_none());
+
+        let bad = request.after("not-a-cursor".parse().unwrap());
+        assert!(paginate(vec![1u32], &bad, |n| *n).is_err());
+    }
+}
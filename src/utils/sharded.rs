@@ -0,0 +1,195 @@
+//! Sharded locks with contention accounting
+//!
+//! [`Sharded`] splits state that would otherwise sit behind one lock into
+//! independently locked shards chosen by key hash, so tasks working on
+//! different keys stop queueing behind each other. Every acquisition first
+//! tries the lock without blocking; when that fails the acquisition counts
+//! as contended and the time spent waiting is recorded. [`LockStats`] and
+//! the `anya_lock_contended_total` / `anya_lock_wait_microseconds_total`
+//! counters, labelled with the lock name, show whether sharding pays off.
+//!
+//! Guards are plain `std::sync` guards: they must not be held across an
+//! `.await`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// Default number of shards
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Acquisition counters of a [`Sharded`] lock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockStats {
+    /// Shard locks taken
+    pub acquisitions: u64,
+    /// Acquisitions that had to wait for another holder
+    pub contended: u64,
+    /// Total time spent waiting, in microseconds
+    pub wait_micros: u64,
+}
+
+impl LockStats {
+    /// Fraction of acquisitions that had to wait
+    pub fn contention_rate(&self) -> f64 {
+        if self.acquisitions == 0 {
+            0.0
+        } else {
+            self.contended as f64 / self.acquisitions as f64
+        }
+    }
+}
+
+/// State partitioned over independently locked shards
+pub struct Sharded<T> {
+    name: String,
+    hasher: RandomState,
+    shards: Box<[RwLock<T>]>,
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_micros: AtomicU64,
+}
+
+impl<T> Sharded<T> {
+    /// `shards` shards (at least one) named `name` in metrics, each
+    /// initialized by `init`
+    pub fn new(name: impl Into<String>, shards: usize, init: impl FnMut() -> T) -> Self {
+        Self {
+            name: name.into(),
+            hasher: RandomState::new(),
+            shards: std::iter::repeat_with(init)
+                .take(shards.max(1))
+                .map(RwLock::new)
+                .collect(),
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            wait_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Lock name used as the metrics label
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of shards
+    pub const fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Shard holding `key`
+    pub fn shard_of<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        // Widening u64 to usize would only truncate on 32-bit targets,
+        // where the low bits are still evenly spread
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        (hasher.finish() as usize) % self.shards.len()
+    }
+
+    /// Read the shard holding `key`
+    pub fn read<K: Hash + ?Sized>(&self, key: &K) -> RwLockReadGuard<'_, T> {
+        self.read_shard(self.shard_of(key))
+    }
+
+    /// Write the shard holding `key`
+    pub fn write<K: Hash + ?Sized>(&self, key: &K) -> RwLockWriteGuard<'_, T> {
+        self.write_shard(self.shard_of(key))
+    }
+
+    /// Read shard `index`
+    pub fn read_shard(&self, index: usize) -> RwLockReadGuard<'_, T> {
+        let shard = &self.shards[index];
+        match shard.try_read() {
+            Ok(guard) => {
+                self.acquisitions.fetch_add(1, Ordering::Relaxed);
+                guard
+            }
+            Err(TryLockError::Poisoned(poisoned)) => {
+                self.acquisitions.fetch_add(1, Ordering::Relaxed);
+                poisoned.into_inner()
+            }
+            Err(TryLockError::WouldBlock) => {
+                let started = Instant::now();
+                let guard = shard.read().unwrap_or_else(PoisonError::into_inner);
+                self.record_wait(started);
+                guard
+            }
+        }
+    }
+
+    /// Write shard `index`
+    pub fn write_shard(&self, index: usize) -> RwLockWriteGuard<'_, T> {
+        let shard = &self.shards[index];
+        match shard.try_write() {
+            Ok(guard) => {
+                self.acquisitions.fetch_add(1, Ordering::Relaxed);
+                guard
+            }
+            Err(TryLockError::Poisoned(poisoned)) => {
+                self.acquisitions.fetch_add(1, Ordering::Relaxed);
+                poisoned.into_inner()
+            }
+            Err(TryLockError::WouldBlock) => {
+                let started = Instant::now();
+                let guard = shard.write().unwrap_or_else(PoisonError::into_inner);
+                self.record_wait(started);
+                guard
+            }
+        }
+    }
+
+    /// Acquisition counters
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            wait_micros: self.wait_micros.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_wait(&self, started: Instant) {
+        let waited = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.wait_micros.fetch_add(waited, Ordering::Relaxed);
+        metrics::increment_counter!("anya_lock_contended_total", "lock" => self.name.clone());
+        metrics::counter!("anya_lock_wait_microseconds_total", waited, "lock" => self.name.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_keys_spread_and_contention_is_counted() {
+        let map = Arc::new(Sharded::new("test", 4, HashMap::<u32, u32>::new));
+        for key in 0..64 {
+            map.write(&key).insert(key, key * 2);
+        }
+        assert_eq!(*map.read(&21).get(&21).unwrap(), 42);
+        let used: std::collections::HashSet<_> = (0..64).map(|k| map.shard_of(&k)).collect();
+        assert!(used.len() > 1);
+        assert_eq!(map.stats().contended, 0);
+
+        let shard = map.shard_of(&7);
+        let held = map.write_shard(shard);
+        let waiter = {
+            let map = Arc::clone(&map);
+            std::thread::spawn(move || map.read(&7).get(&7).copied())
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        drop(held);
+        assert_eq!(waiter.join().unwrap(), Some(14));
+        let stats = map.stats();
+        assert_eq!((stats.acquisitions, stats.contended), (67, 1));
+        assert!(stats.wait_micros > 0 && stats.contention_rate() > 0.0);
+    }
+}
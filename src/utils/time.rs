@@ -0,0 +1,129 @@
+//! Wall-clock time and UTC calendar conversions for timestamps in file
+//! names and exports
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch, or zero if the clock is set before it
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A point in time broken down into UTC calendar fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UtcDateTime {
+    /// Year, e.g. 2024
+    pub year: i32,
+    /// Month, 1 to 12
+    pub month: u8,
+    /// Day of the month, 1 to 31
+    pub day: u8,
+    /// Hour, 0 to 23
+    pub hour: u8,
+    /// Minute, 0 to 59
+    pub minute: u8,
+    /// Second, 0 to 59
+    pub second: u8,
+}
+
+impl UtcDateTime {
+    /// Break down seconds since the Unix epoch
+    pub fn from_unix(secs: i64) -> Self {
+        let days = secs.div_euclid(86_400);
+        let rem = secs.rem_euclid(86_400);
+        // Howard Hinnant's civil-from-days algorithm
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        // Every narrowed field is in range by construction
+        let narrow = |v: i64| u8::try_from(v).unwrap_or_default();
+        Self {
+            year: i32::try_from(year).unwrap_or(i32::MAX),
+            month: narrow(month),
+            day: narrow(day),
+            hour: narrow(rem / 3600),
+            minute: narrow(rem % 3600 / 60),
+            second: narrow(rem % 60),
+        }
+    }
+
+    /// Break down milliseconds since the Unix epoch, dropping the fraction
+    pub fn from_unix_ms(ms: i64) -> Self {
+        Self::from_unix(ms.div_euclid(1000))
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn to_unix(&self) -> i64 {
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    /// `YYYY-MM-DD`
+    pub fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// `YYYYMMDDTHHMMSSZ`, the basic ISO 8601 form
+    pub fn compact(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// RFC 3339, e.g. `2024-01-02T03:04:05Z`
+impl fmt::Display for UtcDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}T{:02}:{:02}:{:02}Z",
+            self.date(),
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_round_trip() {
+        let t = UtcDateTime::from_unix(1_709_164_800 + 3_723);
+        assert_eq!(t.to_string(), "2024-02-29T01:02:03Z");
+        assert_eq!(t.compact(), "20240229T010203Z");
+        assert_eq!(UtcDateTime::from_unix(0).date(), "1970-01-01");
+        assert_eq!(
+            UtcDateTime::from_unix(-1).to_string(),
+            "1969-12-31T23:59:59Z"
+        );
+        assert_eq!(
+            UtcDateTime::from_unix_ms(951_782_400_999).date(),
+            "2000-02-29"
+        );
+        for secs in [0, -86_400, 951_782_400, 4_102_444_800, 1_296_688_602] {
+            assert_eq!(UtcDateTime::from_unix(secs).to_unix(), secs);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Wiping secrets from memory
+//!
+//! [`Zeroizing`] owns a secret buffer and overwrites it, including any spare
+//! capacity, when dropped. Its `Debug` output is redacted so secrets do not
+//! reach logs by accident, and byte buffers compare in constant time.
+//!
+//! The crate forbids `unsafe`, so volatile writes are not available; the
+//! wipe is followed by a compiler fence and an opaque [`std::hint::black_box`]
+//! read so the stores are not optimized away as dead. Values moved out of a
+//! wrapper, or copied by a reallocation before they were wrapped, are not
+//! covered; build secrets directly into a wrapper with enough capacity.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// A buffer that can be overwritten in place
+pub trait Zeroize {
+    /// Overwrite the contents with zeros
+    fn zeroize(&mut self);
+}
+
+impl<T: Copy + Default> Zeroize for Vec<T> {
+    fn zeroize(&mut self) {
+        let capacity = self.capacity();
+        self.fill(T::default());
+        self.resize(capacity, T::default());
+        compiler_fence(Ordering::SeqCst);
+        std::hint::black_box(self.as_slice());
+        self.clear();
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Zeroize for [T; N] {
+    fn zeroize(&mut self) {
+        self.fill(T::default());
+        compiler_fence(Ordering::SeqCst);
+        std::hint::black_box(self.as_slice());
+    }
+}
+
+impl Zeroize for String {
+    fn zeroize(&mut self) {
+        std::mem::take(self).into_bytes().zeroize();
+    }
+}
+
+/// Secret wiped when dropped
+#[derive(Clone, Default)]
+pub struct Zeroizing<T: Zeroize>(T);
+
+impl<T: Zeroize> Zeroizing<T> {
+    /// Take ownership of `value`
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Zeroize> From<T> for Zeroizing<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Zeroize> Deref for Zeroizing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl PartialEq for Zeroizing<Vec<u8>> {
+    fn eq(&self, other: &Self) -> bool {
+        ring::constant_time::verify_slices_are_equal(&self.0, &other.0).is_ok()
+    }
+}
+
+impl Eq for Zeroizing<Vec<u8>> {}
+
+impl<T: Zeroize> fmt::Debug for Zeroizing<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Zeroizing(<redacted>)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wipes_contents_and_spare_capacity() {
+        let mut secret = Vec::with_capacity(8);
+        secret.extend_from_slice(&[0xAA; 5]);
+        secret.zeroize();
+        assert!(secret.is_empty() && secret.capacity() >= 8);
+        let mut key = [0x5Au8; 4];
+        key.zeroize();
+        assert_eq!(key, [0; 4]);
+
+        let mut phrase = String::from("correct horse");
+        phrase.zeroize();
+        assert!(phrase.is_empty());
+        let wrapped = Zeroizing::new(vec![7u8; 4]);
+        assert_eq!(format!("{:?}", wrapped), "Zeroizing(<redacted>)");
+        assert_eq!(wrapped.len(), 4);
+        assert_eq!(wrapped, Zeroizing::new(vec![7u8; 4]));
+        assert_ne!(wrapped, Zeroizing::new(vec![7u8; 5]));
+    }
+}
@@ -0,0 +1,785 @@
+//! Request validation in front of the API
+//!
+//! [`Firewall::check`] screens an inbound HTTP request before it reaches a
+//! handler. The request must match a registered [`Endpoint`]; its body must
+//! fit the size limit, carry an allowed content type, and validate against
+//! the endpoint's [`Schema`], a strict subset of JSON Schema in which
+//! objects reject unknown properties unless they opt in. The path, query
+//! string, and every JSON key and string value are also scanned for common
+//! SQL, script, path traversal, and shell injection patterns.
+//!
+//! Every rule that fires is counted, both through the `metrics` facade and
+//! as a [`MetricsSource`] labelled by rule. In [`FirewallMode::Monitor`]
+//! violations are only counted and logged, which lets new rules be tried
+//! against production traffic before they block anything.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::timeseries::{MetricsSource, SeriesKey};
+use crate::utils::encoding::percent_decode;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Rule id of requests matching no endpoint
+pub const RULE_UNKNOWN_ENDPOINT: &str = "unknown_endpoint";
+/// Rule id of oversized bodies
+pub const RULE_BODY_SIZE: &str = "body_size";
+/// Rule id of missing or disallowed content types
+pub const RULE_CONTENT_TYPE: &str = "content_type";
+/// Rule id of bodies failing schema validation
+pub const RULE_SCHEMA: &str = "schema";
+
+/// Whether violations block requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallMode {
+    /// Reject requests that violate a rule
+    Block,
+    /// Count and log violations but let requests through
+    Monitor,
+}
+
+/// Firewall settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirewallConfig {
+    /// Blocking or monitoring
+    pub mode: FirewallMode,
+    /// Body limit for endpoints without their own
+    pub max_body_bytes: usize,
+    /// Whether requests matching no endpoint are let through unvalidated
+    pub allow_unknown_endpoints: bool,
+}
+
+impl Default for FirewallConfig {
+    fn default() -> Self {
+        Self {
+            mode: FirewallMode::Block,
+            max_body_bytes: 1024 * 1024,
+            allow_unknown_endpoints: false,
+        }
+    }
+}
+
+/// JSON value type in a [`Schema`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaType {
+    /// JSON object
+    Object,
+    /// JSON array
+    Array,
+    /// String
+    String,
+    /// Number without a fractional part
+    Integer,
+    /// Any number
+    Number,
+    /// `true` or `false`
+    Boolean,
+    /// `null`
+    Null,
+}
+
+impl SchemaType {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Self::Object => value.is_object(),
+            Self::Array => value.is_array(),
+            Self::String => value.is_string(),
+            Self::Integer => value.is_i64() || value.is_u64(),
+            Self::Number => value.is_number(),
+            Self::Boolean => value.is_boolean(),
+            Self::Null => value.is_null(),
+        }
+    }
+}
+
+/// Strict subset of JSON Schema used to validate request bodies.
+///
+/// Deserializes from the usual JSON Schema keywords. Unlike JSON Schema,
+/// `additionalProperties` defaults to `false`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schema {
+    /// Required value type
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<SchemaType>,
+    /// Schemas of known object properties
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, Self>,
+    /// Properties that must be present
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
+    /// Whether properties not listed are allowed
+    #[serde(default)]
+    pub additional_properties: bool,
+    /// Schema of every array item
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub items: Option<Box<Self>>,
+    /// Allowed values
+    #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<Vec<Value>>,
+    /// Minimum string length in characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    /// Maximum string length in characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Smallest allowed number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<f64>,
+    /// Largest allowed number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maximum: Option<f64>,
+    /// Minimum array length
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_items: Option<usize>,
+    /// Maximum array length
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+}
+
+impl Schema {
+    /// Parse a schema from a JSON Schema document
+    pub fn from_json(json: &str) -> AnyaResult<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Validate `value`, returning every violation with its JSON pointer
+    pub fn validate(&self, value: &Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        self.check(value, "", &mut errors);
+        errors
+    }
+
+    fn check(&self, value: &Value, path: &str, errors: &mut Vec<String>) {
+        let at = if path.is_empty() { "/" } else { path };
+        if let Some(kind) = self.kind {
+            if !kind.matches(value) {
+                let expected = format!("{:?}", kind).to_lowercase();
+                errors.push(format!("{}: expected {}", at, expected));
+                return;
+            }
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.contains(value) {
+                errors.push(format!("{}: value is not allowed", at));
+            }
+        }
+        match value {
+            Value::Object(map) => {
+                for name in &self.required {
+                    if !map.contains_key(name) {
+                        errors.push(format!("{}: missing property {}", at, name));
+                    }
+                }
+                for (name, item) in map {
+                    let child = format!("{}/{}", path, name);
+                    match self.properties.get(name) {
+                        Some(schema) => schema.check(item, &child, errors),
+                        None if self.additional_properties => {}
+                        None => errors.push(format!("{}: unknown property", child)),
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if self.min_items.is_some_and(|min| items.len() < min) {
+                    errors.push(format!("{}: too few items", at));
+                }
+                if self.max_items.is_some_and(|max| items.len() > max) {
+                    errors.push(format!("{}: too many items", at));
+                }
+                if let Some(schema) = &self.items {
+                    for (i, item) in items.iter().enumerate() {
+                        schema.check(item, &format!("{}/{}", path, i), errors);
+                    }
+                }
+            }
+            Value::String(s) => {
+                let len = s.chars().count();
+                if self.min_length.is_some_and(|min| len < min) {
+                    errors.push(format!(
+                        "{}: shorter than {}",
+                        at,
+                        self.min_length.unwrap_or(0)
+                    ));
+                }
+                if self.max_length.is_some_and(|max| len > max) {
+                    errors.push(format!(
+                        "{}: longer than {}",
+                        at,
+                        self.max_length.unwrap_or(0)
+                    ));
+                }
+            }
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or(f64::NAN);
+                if self.minimum.is_some_and(|min| n < min)
+                    || self.maximum.is_some_and(|max| n > max)
+                {
+                    errors.push(format!("{}: out of range", at));
+                }
+            }
+            Value::Bool(_) | Value::Null => {}
+        }
+    }
+}
+
+/// An API endpoint and the requests it accepts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Endpoint {
+    /// HTTP method
+    pub method: String,
+    /// Path pattern; a `{name}` segment matches any single segment
+    pub path: String,
+    /// Accepted media types; empty accepts requests without a body only
+    #[serde(default)]
+    pub content_types: Vec<String>,
+    /// Body limit overriding [`FirewallConfig::max_body_bytes`]
+    #[serde(default)]
+    pub max_body_bytes: Option<usize>,
+    /// Schema the JSON body must satisfy
+    #[serde(default)]
+    pub schema: Option<Schema>,
+}
+
+impl Endpoint {
+    /// An endpoint accepting no body
+    pub fn new(method: &str, path: &str) -> Self {
+        Self {
+            method: method.to_ascii_uppercase(),
+            path: path.to_string(),
+            content_types: Vec::new(),
+            max_body_bytes: None,
+            schema: None,
+        }
+    }
+
+    /// Accept `application/json` bodies validated against `schema`
+    #[must_use]
+    pub fn json(mut self, schema: Schema) -> Self {
+        self.content_types = vec!["application/json".to_string()];
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Limit the body to `bytes`
+    #[must_use]
+    pub const fn max_body(mut self, bytes: usize) -> Self {
+        self.max_body_bytes = Some(bytes);
+        self
+    }
+
+    fn matches(&self, method: &str, path: &str) -> bool {
+        if !self.method.eq_ignore_ascii_case(method) {
+            return false;
+        }
+        let mut pattern = self.path.trim_matches('/').split('/');
+        let mut actual = path.trim_matches('/').split('/');
+        loop {
+            match (pattern.next(), actual.next()) {
+                (None, None) => return true,
+                (Some(p), Some(a)) if p == a || (p.starts_with('{') && p.ends_with('}')) => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// Category of an injection rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InjectionKind {
+    /// SQL injection
+    Sql,
+    /// Script injection into rendered pages
+    Xss,
+    /// Escaping a directory through `..`
+    PathTraversal,
+    /// Shell command injection
+    Command,
+}
+
+/// Case-insensitive substrings that mark a value as an injection attempt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InjectionRule {
+    /// Rule id used in metrics and rejections
+    pub id: String,
+    /// Category
+    pub kind: InjectionKind,
+    /// Lowercase patterns, any of which triggers the rule
+    pub patterns: Vec<String>,
+}
+
+impl InjectionRule {
+    /// A rule triggered by any of `patterns`
+    pub fn new(id: &str, kind: InjectionKind, patterns: &[&str]) -> Self {
+        Self {
+            id: id.to_string(),
+            kind,
+            patterns: patterns.iter().map(|p| p.to_lowercase()).collect(),
+        }
+    }
+
+    /// Built-in rules for each category
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new(
+                "sqli",
+                InjectionKind::Sql,
+                &[
+                    "' or '1'='1",
+                    "' or 1=1",
+                    "\" or \"1\"=\"1",
+                    "union select",
+                    "union all select",
+                    "; drop table",
+                    "'; --",
+                    "sleep(",
+                    "benchmark(",
+                    "information_schema",
+                ],
+            ),
+            Self::new(
+                "xss",
+                InjectionKind::Xss,
+                &[
+                    "<script",
+                    "</script",
+                    "javascript:",
+                    "onerror=",
+                    "onload=",
+                    "<iframe",
+                    "<svg",
+                ],
+            ),
+            Self::new(
+                "path_traversal",
+                InjectionKind::PathTraversal,
+                &["../", "..\\", "%2e%2e", "/etc/passwd"],
+            ),
+            Self::new(
+                "command",
+                InjectionKind::Command,
+                &["$(", "`", "; rm ", "| sh", "|sh", "&& curl", "; curl", "\0"],
+            ),
+        ]
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.patterns.iter().any(|p| text.contains(p.as_str()))
+    }
+}
+
+/// An inbound HTTP request as seen by the firewall
+#[derive(Debug, Clone, Copy)]
+pub struct InboundRequest<'a> {
+    /// HTTP method
+    pub method: &'a str,
+    /// Request target: path plus optional query string
+    pub target: &'a str,
+    /// Request headers
+    pub headers: &'a BTreeMap<String, String>,
+    /// Raw body
+    pub body: &'a [u8],
+}
+
+/// Screens API requests against endpoint definitions and injection rules
+pub struct Firewall {
+    config: FirewallConfig,
+    endpoints: Vec<Endpoint>,
+    rules: Vec<InjectionRule>,
+    counters: BTreeMap<String, AtomicU64>,
+    allowed: AtomicU64,
+}
+
+impl Firewall {
+    /// Firewall with the default injection rules and no endpoints
+    pub fn new(config: FirewallConfig) -> Self {
+        let mut firewall = Self {
+            config,
+            endpoints: Vec::new(),
+            rules: Vec::new(),
+            counters: BTreeMap::new(),
+            allowed: AtomicU64::new(0),
+        };
+        for id in [
+            RULE_UNKNOWN_ENDPOINT,
+            RULE_BODY_SIZE,
+            RULE_CONTENT_TYPE,
+            RULE_SCHEMA,
+        ] {
+            firewall.counters.insert(id.to_string(), AtomicU64::new(0));
+        }
+        for rule in InjectionRule::defaults() {
+            firewall.add_rule(rule);
+        }
+        firewall
+    }
+
+    /// Register an endpoint; the first matching endpoint applies
+    pub fn add_endpoint(&mut self, endpoint: Endpoint) {
+        self.endpoints.push(endpoint);
+    }
+
+    /// Add an injection rule, replacing any rule with the same id
+    pub fn add_rule(&mut self, rule: InjectionRule) {
+        self.counters
+            .entry(rule.id.clone())
+            .or_insert_with(|| AtomicU64::new(0));
+        self.rules.retain(|r| r.id != rule.id);
+        self.rules.push(rule);
+    }
+
+    /// Times each rule fired, by rule id
+    pub fn hits(&self) -> BTreeMap<String, u64> {
+        self.counters
+            .iter()
+            .map(|(id, count)| (id.clone(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Screen `request`, returning the parsed JSON body if it has one.
+    ///
+    /// In blocking mode the first violation is returned as an error:
+    /// [`ErrorCode::NotFound`] for unknown endpoints,
+    /// [`ErrorCode::PermissionDenied`] for injection attempts, and
+    /// [`ErrorCode::InvalidInput`] otherwise.
+    pub fn check(&self, request: &InboundRequest<'_>) -> AnyaResult<Option<Value>> {
+        let (path, query) = request
+            .target
+            .split_once('?')
+            .unwrap_or((request.target, ""));
+
+        self.scan(path, request)?;
+        self.scan(query, request)?;
+
+        let Some(endpoint) = self
+            .endpoints
+            .iter()
+            .find(|e| e.matches(request.method, path))
+        else {
+            if self.config.allow_unknown_endpoints {
+                self.allowed.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+            self.violation(
+                RULE_UNKNOWN_ENDPOINT,
+                ErrorCode::NotFound,
+                format!("no endpoint {} {}", request.method, path),
+                request,
+            )?;
+            return Ok(None);
+        };
+
+        let limit = endpoint
+            .max_body_bytes
+            .unwrap_or(self.config.max_body_bytes);
+        if request.body.len() > limit {
+            self.violation(
+                RULE_BODY_SIZE,
+                ErrorCode::InvalidInput,
+                format!("body of {} bytes exceeds {}", request.body.len(), limit),
+                request,
+            )?;
+        }
+
+        let body = if request.body.is_empty() {
+            None
+        } else {
+            let content_type = request
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+                .map(|(_, value)| {
+                    value
+                        .split(';')
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .to_ascii_lowercase()
+                });
+            let accepted = content_type.as_ref().is_some_and(|ct| {
+                endpoint
+                    .content_types
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(ct))
+            });
+            if !accepted {
+                self.violation(
+                    RULE_CONTENT_TYPE,
+                    ErrorCode::InvalidInput,
+                    format!(
+                        "content type {} is not accepted",
+                        content_type.as_deref().unwrap_or("(none)")
+                    ),
+                    request,
+                )?;
+            }
+            if content_type.as_deref() == Some("application/json") {
+                match serde_json::from_slice::<Value>(request.body) {
+                    Ok(json) => Some(json),
+                    Err(e) => {
+                        self.violation(
+                            RULE_SCHEMA,
+                            ErrorCode::InvalidInput,
+                            format!("body is not valid JSON: {}", e),
+                            request,
+                        )?;
+                        None
+                    }
+                }
+            } else {
+                None
+            }
+        };
+
+        if let Some(json) = &body {
+            let mut strings = Vec::new();
+            collect_strings(json, &mut strings);
+            for text in strings {
+                self.scan(text, request)?;
+            }
+        }
+
+        if let Some(schema) = &endpoint.schema {
+            let errors = body.as_ref().map_or_else(
+                || vec!["/: JSON body required".to_string()],
+                |json| schema.validate(json),
+            );
+            if !errors.is_empty() {
+                self.violation(
+                    RULE_SCHEMA,
+                    ErrorCode::InvalidInput,
+                    errors.join("; "),
+                    request,
+                )?;
+            }
+        }
+
+        self.allowed.fetch_add(1, Ordering::Relaxed);
+        Ok(body)
+    }
+
+    fn scan(&self, text: &str, request: &InboundRequest<'_>) -> AnyaResult<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        // Check the decoded form too so escaping a pattern does not hide it
+        let decoded = percent_decode(&text.replace('+', " ")).ok();
+        for rule in &self.rules {
+            if rule.matches(text) || decoded.as_deref().is_some_and(|d| rule.matches(d)) {
+                self.violation(
+                    &rule.id,
+                    ErrorCode::PermissionDenied,
+                    format!("request matches {:?} injection rule {}", rule.kind, rule.id),
+                    request,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a violation and fail in blocking mode
+    fn violation(
+        &self,
+        rule: &str,
+        code: ErrorCode,
+        message: String,
+        request: &InboundRequest<'_>,
+    ) -> AnyaResult<()> {
+        if let Some(counter) = self.counters.get(rule) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        metrics::increment_counter!("anya_waf_violations_total", "rule" => rule.to_string());
+        warn!(rule, method = request.method, target = request.target, %message, "request violates firewall rule");
+        match self.config.mode {
+            FirewallMode::Block => Err(AnyaError::new(code, message)),
+            FirewallMode::Monitor => Ok(()),
+        }
+    }
+}
+
+fn collect_strings<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+    match value {
+        Value::String(s) => out.push(s),
+        Value::Array(items) => items.iter().for_each(|v| collect_strings(v, out)),
+        Value::Object(map) => {
+            for (key, v) in map {
+                out.push(key);
+                collect_strings(v, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Violations per rule and requests let through
+#[async_trait]
+impl MetricsSource for Firewall {
+    fn name(&self) -> &str {
+        "waf"
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    async fn collect(&self) -> AnyaResult<Vec<(SeriesKey, f64)>> {
+        let mut values: Vec<(SeriesKey, f64)> = self
+            .hits()
+            .into_iter()
+            .map(|(rule, count)| {
+                (
+                    SeriesKey::new("anya_waf_violations_total").with_label("rule", &rule),
+                    count as f64,
+                )
+            })
+            .collect();
+        values.push((
+            SeriesKey::new("anya_waf_allowed_total"),
+            self.allowed.load(Ordering::Relaxed) as f64,
+        ));
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn firewall(mode: FirewallMode) -> Firewall {
+        let schema = Schema::from_json(
+            r#"{
+                "type": "object",
+                "required": ["address", "amount_sat"],
+                "properties": {
+                    "address": { "type": "string", "minLength": 14, "maxLength": 90 },
+                    "amount_sat": { "type": "integer", "minimum": 546 },
+                    "label": { "type": "string", "maxLength": 64 }
+                }
+            }"#,
+        )
+        .unwrap();
+        let mut firewall = Firewall::new(FirewallConfig {
+            mode,
+            ..FirewallConfig::default()
+        });
+        firewall.add_endpoint(
+            Endpoint::new("POST", "/v1/wallets/{id}/send")
+                .json(schema)
+                .max_body(256),
+        );
+        firewall.add_endpoint(Endpoint::new("GET", "/v1/wallets/{id}"));
+        firewall
+    }
+
+    fn json_headers() -> BTreeMap<String, String> {
+        BTreeMap::from([(
+            "Content-Type".to_string(),
+            "application/json; charset=utf-8".to_string(),
+        )])
+    }
+
+    fn send(firewall: &Firewall, body: &str) -> AnyaResult<Option<Value>> {
+        firewall.check(&InboundRequest {
+            method: "POST",
+            target: "/v1/wallets/main/send",
+            headers: &json_headers(),
+            body: body.as_bytes(),
+        })
+    }
+
+    #[test]
+    fn validates_schema_size_and_content_type() {
+        let waf = firewall(FirewallMode::Block);
+        let ok = send(
+            &waf,
+            r#"{"address": "bc1qexampleaddress", "amount_sat": 1000}"#,
+        )
+        .unwrap();
+        assert_eq!(ok.unwrap()["amount_sat"], 1000);
+
+        let err = send(
+            &waf,
+            r#"{"address": "bc1qexampleaddress", "amount_sat": 1, "memo": "x"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+        assert!(err.to_string().contains("/amount_sat: out of range"));
+        assert!(err.to_string().contains("/memo: unknown property"));
+
+        let big = format!(
+            r#"{{"address": "{}", "amount_sat": 1000}}"#,
+            "a".repeat(300)
+        );
+        assert!(send(&waf, &big).is_err());
+
+        let form = BTreeMap::from([(
+            "content-type".to_string(),
+            "application/x-www-form-urlencoded".to_string(),
+        )]);
+        let err = waf
+            .check(&InboundRequest {
+                method: "POST",
+                target: "/v1/wallets/main/send",
+                headers: &form,
+                body: b"amount_sat=1000",
+            })
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidInput);
+
+        let unknown = waf.check(&InboundRequest {
+            method: "DELETE",
+            target: "/v1/wallets/main",
+            headers: &BTreeMap::new(),
+            body: b"",
+        });
+        assert_eq!(unknown.unwrap_err().code(), ErrorCode::NotFound);
+
+        let hits = waf.hits();
+        assert_eq!(hits[RULE_SCHEMA], 1);
+        assert_eq!(hits[RULE_BODY_SIZE], 1);
+        assert_eq!(hits[RULE_CONTENT_TYPE], 1);
+        assert_eq!(hits[RULE_UNKNOWN_ENDPOINT], 1);
+    }
+
+    #[tokio::test]
+    async fn detects_injection_and_monitor_mode_lets_through() {
+        let waf = firewall(FirewallMode::Block);
+        let err = send(
+            &waf,
+            r#"{"address": "bc1qexampleaddress", "amount_sat": 1000, "label": "x' OR '1'='1"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        let traversal = waf.check(&InboundRequest {
+            method: "GET",
+            target: "/v1/wallets/main?file=%2E%2E%2Fetc%2Fpasswd",
+            headers: &BTreeMap::new(),
+            body: b"",
+        });
+        assert_eq!(traversal.unwrap_err().code(), ErrorCode::PermissionDenied);
+
+        let monitor = firewall(FirewallMode::Monitor);
+        let body = r#"{"address": "bc1qexampleaddress", "amount_sat": 1000, "label": "<script>alert(1)</script>"}"#;
+        assert!(send(&monitor, body).unwrap().is_some());
+        let metrics = monitor.collect().await.unwrap();
+        let value = |name: &str, rule: Option<&str>| {
+            metrics
+                .iter()
+                .find(|(k, _)| {
+                    k.name == name
+                        && rule.map_or(true, |r| {
+                            k.labels.get("rule").map(String::as_str) == Some(r)
+                        })
+                })
+                .map(|(_, v)| *v)
+        };
+        assert_eq!(value("anya_waf_violations_total", Some("xss")), Some(1.0));
+        assert_eq!(value("anya_waf_allowed_total", None), Some(1.0));
+    }
+}
@@ -0,0 +1,110 @@
+//! JavaScript bindings for browser light clients
+//!
+//! Build with `wasm-pack build --target web -- --no-default-features
+//! --features wasm` to verify SPV proofs, derive watch-only addresses, and
+//! check verifiable credentials and release attestations client-side. Binary data is passed as hex,
+//! structured results are returned as JSON strings, and failures throw a
+//! JavaScript `Error` carrying the error code in its message.
+
+use std::str::FromStr;
+
+use ::bitcoin::block::Header;
+use ::bitcoin::consensus::deserialize;
+use ::bitcoin::merkle_tree::MerkleBlock;
+use ::bitcoin::secp256k1::Secp256k1;
+use ::bitcoin::{Network, Txid};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::bitcoin::descriptor::WatchOnlyDescriptor;
+use crate::bitcoin::spv::HeaderChain;
+use crate::build_info::{BuildInfo, ReleaseAttestation};
+use crate::utils::encoding::from_hex;
+use crate::web5::credential;
+use crate::{AnyaError, AnyaResult};
+
+fn js<T>(result: AnyaResult<T>) -> Result<T, JsError> {
+    result.map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn decode<T: ::bitcoin::consensus::Decodable>(hex: &str, what: &str) -> AnyaResult<T> {
+    deserialize(&from_hex(hex)?)
+        .map_err(|e| AnyaError::invalid_input(format!("invalid {}: {}", what, e)))
+}
+
+fn to_json<T: Serialize>(value: &T) -> AnyaResult<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+/// Verify that `txid` is committed in a block of the header chain.
+///
+/// `headers` are hex block headers extending `checkpoint`, trusted to be at
+/// `checkpoint_height`; `proof` is a hex BIP-37 merkle block. Returns the
+/// inclusion (height, index, confirmations) as JSON.
+#[wasm_bindgen(js_name = verifyTxInclusion)]
+pub fn verify_tx_inclusion(
+    network: &str,
+    checkpoint: &str,
+    checkpoint_height: u32,
+    headers: Vec<String>,
+    proof: &str,
+    txid: &str,
+) -> Result<String, JsError> {
+    js((|| {
+        let network = Network::from_str(network)
+            .map_err(|_| AnyaError::invalid_input(format!("unknown network {}", network)))?;
+        let mut chain = HeaderChain::new(network, decode(checkpoint, "header")?, checkpoint_height);
+        let headers = headers
+            .iter()
+            .map(|h| decode::<Header>(h, "header"))
+            .collect::<AnyaResult<Vec<_>>>()?;
+        chain.extend(&headers)?;
+        let proof: MerkleBlock = decode(proof, "merkle block")?;
+        let txid = Txid::from_str(txid)
+            .map_err(|_| AnyaError::invalid_input(format!("invalid txid {}", txid)))?;
+        to_json(&chain.verify_inclusion(&proof, txid)?)
+    })())
+}
+
+/// Derive `count` addresses of a watch-only descriptor starting at `start`
+#[wasm_bindgen(js_name = deriveAddresses)]
+pub fn derive_addresses(descriptor: &str, start: u32, count: u32) -> Result<Vec<String>, JsError> {
+    js((|| {
+        let descriptor = WatchOnlyDescriptor::parse(descriptor)?;
+        let secp = Secp256k1::verification_only();
+        (start..start.saturating_add(count))
+            .map(|i| Ok(descriptor.address(&secp, i)?.to_string()))
+            .collect()
+    })())
+}
+
+/// Verify a JWT verifiable credential at `now` (seconds since the Unix
+/// epoch) and return its issuer, subject, and claims as JSON
+#[wasm_bindgen(js_name = verifyCredential)]
+pub fn verify_credential(jwt: &str, now: f64) -> Result<String, JsError> {
+    // Date.now() / 1000 is a float; fractions of a second do not matter here
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let now = now.max(0.0) as u64;
+    js(credential::verify_jwt(jwt, now).and_then(|c| to_json(&c)))
+}
+
+/// Metadata of this build as JSON
+#[wasm_bindgen(js_name = buildInfo)]
+pub fn build_info() -> Result<String, JsError> {
+    js(to_json(&BuildInfo::current()))
+}
+
+/// Verify a JSON release attestation against the trusted release key DIDs
+/// and the JSON build info a node reports
+#[wasm_bindgen(js_name = verifyAttestation)]
+pub fn verify_attestation(
+    attestation: &str,
+    reported_build: &str,
+    trusted_signers: Vec<String>,
+) -> Result<(), JsError> {
+    js((|| {
+        let attestation: ReleaseAttestation = serde_json::from_str(attestation)?;
+        let reported: BuildInfo = serde_json::from_str(reported_build)?;
+        attestation.verify_build(&reported, &trusted_signers)
+    })())
+}
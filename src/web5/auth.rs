@@ -0,0 +1,583 @@
+//! DID-based mutual authentication
+//!
+//! Peers identify themselves with Ed25519 `did:key` DIDs and prove control
+//! of them in a three-message handshake:
+//!
+//! 1. the client sends a [`Hello`] with its DID and a fresh nonce,
+//! 2. the server answers with a [`Challenge`] carrying its own DID and nonce,
+//!    signed over both nonces and both DIDs,
+//! 3. the client checks the server's signature and replies with a
+//!    [`Response`] signed over the same transcript.
+//!
+//! Each side checks the other's DID against its [`AccessPolicy`], so either
+//! can refuse to talk to an unknown peer. A successful handshake yields a
+//! [`Session`] whose bearer token the transport (RPC, gRPC, or agent
+//! messaging) attaches to later requests. Keys are rotated with a
+//! [`KeyRotation`] signed by the old key, which moves the peer's allowlist
+//! entry to its new DID and ends its sessions.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+
+use super::credential::DidKey;
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::utils::time::unix_now;
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Domain separator for handshake signatures
+const TRANSCRIPT_TAG: &str = "anya-did-auth/v1";
+
+/// Ed25519 key controlling a `did:key` identity
+pub struct DidSigner {
+    key: Ed25519KeyPair,
+    did: DidKey,
+}
+
+impl DidSigner {
+    /// Signer for the Ed25519 key derived from `seed`
+    pub fn from_seed(seed: &[u8; 32]) -> AnyaResult<Self> {
+        let key = Ed25519KeyPair::from_seed_unchecked(seed)
+            .map_err(|_| AnyaError::invalid_input("invalid Ed25519 seed"))?;
+        let public_key =
+            key.public_key().as_ref().try_into().map_err(|_| {
+                AnyaError::new(ErrorCode::Internal, "unexpected Ed25519 key length")
+            })?;
+        Ok(Self {
+            key,
+            did: DidKey::from_public_key(public_key),
+        })
+    }
+
+    /// Signer for a new random key
+    pub fn generate() -> AnyaResult<Self> {
+        Self::from_seed(&random_bytes()?)
+    }
+
+    /// DID of the key
+    pub const fn did(&self) -> &DidKey {
+        &self.did
+    }
+
+    /// Sign `message`
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.key.sign(message).as_ref().to_vec()
+    }
+}
+
+/// Check that `signature` over `message` was made by the key of `did`
+pub fn verify_did_signature(did: &str, message: &[u8], signature: &[u8]) -> AnyaResult<()> {
+    let key = DidKey::parse(did)?;
+    UnparsedPublicKey::new(&ED25519, key.public_key())
+        .verify(message, signature)
+        .map_err(|_| {
+            AnyaError::new(
+                ErrorCode::Unauthenticated,
+                format!("invalid signature for {}", did),
+            )
+        })
+}
+
+/// First handshake message, client to server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Client DID
+    pub did: String,
+    /// Hex client nonce
+    pub nonce: String,
+}
+
+/// Second handshake message, server to client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Challenge {
+    /// Server DID
+    pub did: String,
+    /// Hex server nonce
+    pub nonce: String,
+    /// Hex server signature over the transcript
+    pub signature: String,
+}
+
+/// Third handshake message, client to server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    /// Server nonce being answered
+    pub nonce: String,
+    /// Hex client signature over the transcript
+    pub signature: String,
+}
+
+/// An authenticated peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// Bearer token identifying the session
+    pub token: String,
+    /// Authenticated peer DID
+    pub peer: String,
+    /// Expiry, seconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+/// DIDs a party is willing to authenticate
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessPolicy {
+    /// Only these DIDs are accepted; any DID when unset
+    pub allow: Option<HashSet<String>>,
+    /// These DIDs are always refused
+    pub deny: HashSet<String>,
+}
+
+impl AccessPolicy {
+    /// Policy accepting only `dids`
+    pub fn allow_only<I, S>(dids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            allow: Some(dids.into_iter().map(Into::into).collect()),
+            deny: HashSet::new(),
+        }
+    }
+
+    /// Refuse `did` from now on
+    pub fn deny(&mut self, did: impl Into<String>) {
+        self.deny.insert(did.into());
+    }
+
+    /// Whether `did` may authenticate
+    pub fn check(&self, did: &str) -> AnyaResult<()> {
+        let allowed = self.allow.iter().all(|allow| allow.contains(did));
+        if allowed && !self.deny.contains(did) {
+            Ok(())
+        } else {
+            Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                format!("{} is not allowed", did),
+            ))
+        }
+    }
+
+    /// Move an allowlist entry to the rotated DID and deny the old one
+    pub fn apply_rotation(&mut self, rotation: &KeyRotation) -> AnyaResult<()> {
+        rotation.verify()?;
+        self.check(&rotation.from)?;
+        if let Some(allow) = &mut self.allow {
+            allow.remove(&rotation.from);
+            allow.insert(rotation.to.clone());
+        }
+        self.deny.insert(rotation.from.clone());
+        Ok(())
+    }
+}
+
+/// Statement by an old key that its identity moved to a new DID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    /// Retired DID
+    pub from: String,
+    /// Replacement DID
+    pub to: String,
+    /// Time of the rotation, seconds since the Unix epoch
+    pub issued_at: u64,
+    /// Hex signature by the retired key
+    pub signature: String,
+}
+
+impl KeyRotation {
+    /// Rotation from `old` to `new`, signed by `old`
+    pub fn sign(old: &DidSigner, new: &DidKey, issued_at: u64) -> Self {
+        let (from, to) = (old.did().to_string(), new.to_string());
+        let signature = to_hex(&old.sign(&Self::message(&from, &to, issued_at)));
+        Self {
+            from,
+            to,
+            issued_at,
+            signature,
+        }
+    }
+
+    /// Check the retired key's signature
+    pub fn verify(&self) -> AnyaResult<()> {
+        DidKey::parse(&self.to)?;
+        verify_did_signature(
+            &self.from,
+            &Self::message(&self.from, &self.to, self.issued_at),
+            &from_hex(&self.signature)?,
+        )
+    }
+
+    fn message(from: &str, to: &str, issued_at: u64) -> Vec<u8> {
+        format!(
+            "{}\nrotate\n{}\n{}\n{}",
+            TRANSCRIPT_TAG, from, to, issued_at
+        )
+        .into_bytes()
+    }
+}
+
+/// Handshake and session lifetimes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Time a client has to answer a challenge
+    pub challenge_ttl: Duration,
+    /// Lifetime of an established session
+    pub session_ttl: Duration,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            challenge_ttl: Duration::from_secs(60),
+            session_ttl: Duration::from_secs(3600),
+        }
+    }
+}
+
+struct PendingChallenge {
+    client_did: String,
+    client_nonce: String,
+    expires_at: u64,
+}
+
+struct AuthState {
+    signer: Arc<DidSigner>,
+    policy: AccessPolicy,
+    pending: HashMap<String, PendingChallenge>,
+    sessions: HashMap<String, Session>,
+}
+
+/// Server side of the handshake and the resulting sessions
+pub struct DidAuthenticator {
+    config: AuthConfig,
+    state: Mutex<AuthState>,
+}
+
+impl DidAuthenticator {
+    /// Authenticate peers as `signer`, accepting those allowed by `policy`
+    pub fn new(config: AuthConfig, signer: DidSigner, policy: AccessPolicy) -> Self {
+        Self {
+            config,
+            state: Mutex::new(AuthState {
+                signer: Arc::new(signer),
+                policy,
+                pending: HashMap::new(),
+                sessions: HashMap::new(),
+            }),
+        }
+    }
+
+    /// DID this node authenticates as
+    pub fn did(&self) -> String {
+        self.state().signer.did().to_string()
+    }
+
+    /// Answer a client's hello with a signed challenge
+    pub fn challenge(&self, hello: &Hello) -> AnyaResult<Challenge> {
+        DidKey::parse(&hello.did)?;
+        check_nonce(&hello.nonce)?;
+        let now = unix_now();
+        let mut state = self.state();
+        state.policy.check(&hello.did)?;
+        state.pending.retain(|_, p| p.expires_at > now);
+        let nonce = to_hex(&random_bytes()?);
+        let server_did = state.signer.did().to_string();
+        let signature = state.signer.sign(&transcript(
+            "server",
+            &hello.did,
+            &server_did,
+            &hello.nonce,
+            &nonce,
+        ));
+        state.pending.insert(
+            nonce.clone(),
+            PendingChallenge {
+                client_did: hello.did.clone(),
+                client_nonce: hello.nonce.clone(),
+                expires_at: now + self.config.challenge_ttl.as_secs(),
+            },
+        );
+        drop(state);
+        Ok(Challenge {
+            did: server_did,
+            nonce,
+            signature: to_hex(&signature),
+        })
+    }
+
+    /// Verify the client's response and open a session
+    pub fn authenticate(&self, response: &Response) -> AnyaResult<Session> {
+        let now = unix_now();
+        let mut state = self.state();
+        let pending = state
+            .pending
+            .remove(&response.nonce)
+            .filter(|p| p.expires_at > now)
+            .ok_or_else(|| {
+                AnyaError::new(ErrorCode::Unauthenticated, "unknown or expired challenge")
+            })?;
+        let message = transcript(
+            "client",
+            &pending.client_did,
+            &state.signer.did().to_string(),
+            &pending.client_nonce,
+            &response.nonce,
+        );
+        verify_did_signature(
+            &pending.client_did,
+            &message,
+            &from_hex(&response.signature)?,
+        )?;
+        state.policy.check(&pending.client_did)?;
+        let session = Session {
+            token: to_hex(&random_bytes()?),
+            peer: pending.client_did,
+            expires_at: now + self.config.session_ttl.as_secs(),
+        };
+        state.sessions.retain(|_, s| s.expires_at > now);
+        state
+            .sessions
+            .insert(session.token.clone(), session.clone());
+        drop(state);
+        tracing::debug!(peer = %session.peer, "DID session established");
+        Ok(session)
+    }
+
+    /// Session for a bearer token, rechecked against the current policy
+    pub fn session(&self, token: &str) -> AnyaResult<Session> {
+        let state = self.state();
+        let session = state
+            .sessions
+            .get(token)
+            .filter(|s| s.expires_at > unix_now())
+            .cloned()
+            .ok_or_else(|| {
+                AnyaError::new(ErrorCode::Unauthenticated, "invalid or expired session")
+            })?;
+        state.policy.check(&session.peer)?;
+        drop(state);
+        Ok(session)
+    }
+
+    /// End a session
+    pub fn revoke(&self, token: &str) -> bool {
+        self.state().sessions.remove(token).is_some()
+    }
+
+    /// Change the access policy; sessions of newly refused peers end
+    pub fn update_policy(&self, update: impl FnOnce(&mut AccessPolicy)) {
+        let mut guard = self.state();
+        let state = &mut *guard;
+        update(&mut state.policy);
+        let policy = &state.policy;
+        state.sessions.retain(|_, s| policy.check(&s.peer).is_ok());
+        drop(guard);
+    }
+
+    /// Accept a peer's key rotation, ending sessions of its old DID
+    pub fn accept_rotation(&self, rotation: &KeyRotation) -> AnyaResult<()> {
+        let mut state = self.state();
+        state.policy.apply_rotation(rotation)?;
+        state.sessions.retain(|_, s| s.peer != rotation.from);
+        drop(state);
+        Ok(())
+    }
+
+    /// Switch this node to `signer`, returning the rotation to send to peers
+    pub fn rotate(&self, signer: DidSigner) -> KeyRotation {
+        let mut state = self.state();
+        let rotation = KeyRotation::sign(&state.signer, signer.did(), unix_now());
+        state.signer = Arc::new(signer);
+        drop(state);
+        rotation
+    }
+
+    fn state(&self) -> MutexGuard<'_, AuthState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Client side of the handshake
+pub struct ClientHandshake {
+    signer: Arc<DidSigner>,
+    policy: AccessPolicy,
+    nonce: String,
+}
+
+impl ClientHandshake {
+    /// Start a handshake as `signer`, accepting servers allowed by `policy`
+    pub fn start(signer: Arc<DidSigner>, policy: AccessPolicy) -> AnyaResult<(Self, Hello)> {
+        let nonce = to_hex(&random_bytes()?);
+        let hello = Hello {
+            did: signer.did().to_string(),
+            nonce: nonce.clone(),
+        };
+        Ok((
+            Self {
+                signer,
+                policy,
+                nonce,
+            },
+            hello,
+        ))
+    }
+
+    /// Verify the server's challenge and produce the response.
+    ///
+    /// Returns the response and the authenticated server DID.
+    pub fn finish(self, challenge: &Challenge) -> AnyaResult<(Response, String)> {
+        check_nonce(&challenge.nonce)?;
+        self.policy.check(&challenge.did)?;
+        let client_did = self.signer.did().to_string();
+        verify_did_signature(
+            &challenge.did,
+            &transcript(
+                "server",
+                &client_did,
+                &challenge.did,
+                &self.nonce,
+                &challenge.nonce,
+            ),
+            &from_hex(&challenge.signature)?,
+        )?;
+        let signature = self.signer.sign(&transcript(
+            "client",
+            &client_did,
+            &challenge.did,
+            &self.nonce,
+            &challenge.nonce,
+        ));
+        Ok((
+            Response {
+                nonce: challenge.nonce.clone(),
+                signature: to_hex(&signature),
+            },
+            challenge.did.clone(),
+        ))
+    }
+}
+
+fn transcript(
+    role: &str,
+    client_did: &str,
+    server_did: &str,
+    client_nonce: &str,
+    server_nonce: &str,
+) -> Vec<u8> {
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        TRANSCRIPT_TAG, role, client_did, server_did, client_nonce, server_nonce
+    )
+    .into_bytes()
+}
+
+fn check_nonce(nonce: &str) -> AnyaResult<()> {
+    if from_hex(nonce)?.len() < 16 {
+        return Err(AnyaError::invalid_input("nonce must be at least 16 bytes"));
+    }
+    Ok(())
+}
+
+fn random_bytes() -> AnyaResult<[u8; 32]> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| AnyaError::new(ErrorCode::Internal, "system randomness unavailable"))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(seed: u8) -> DidSigner {
+        DidSigner::from_seed(&[seed; 32]).unwrap()
+    }
+
+    fn handshake(
+        server: &DidAuthenticator,
+        client: &Arc<DidSigner>,
+        expect: AccessPolicy,
+    ) -> AnyaResult<Session> {
+        let (handshake, hello) = ClientHandshake::start(client.clone(), expect)?;
+        let challenge = server.challenge(&hello)?;
+        let (response, server_did) = handshake.finish(&challenge)?;
+        assert_eq!(server_did, server.did());
+        server.authenticate(&response)
+    }
+
+    #[test]
+    fn test_mutual_handshake_and_policies() {
+        let client = Arc::new(signer(1));
+        let stranger = Arc::new(signer(2));
+        let server_signer = signer(3);
+        let server_did = server_signer.did().to_string();
+        let server = DidAuthenticator::new(
+            AuthConfig::default(),
+            server_signer,
+            AccessPolicy::allow_only([client.did().to_string()]),
+        );
+
+        let session = handshake(&server, &client, AccessPolicy::allow_only([server_did])).unwrap();
+        assert_eq!(session.peer, client.did().to_string());
+        assert_eq!(server.session(&session.token).unwrap(), session);
+
+        // The server refuses unknown clients, and clients refuse unknown servers
+        let err = handshake(&server, &stranger, AccessPolicy::default()).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        let other_server = signer(4).did().to_string();
+        let err =
+            handshake(&server, &client, AccessPolicy::allow_only([other_server])).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+
+        // A forged response and a replayed challenge both fail
+        let (pending, hello) =
+            ClientHandshake::start(client.clone(), AccessPolicy::default()).unwrap();
+        let challenge = server.challenge(&hello).unwrap();
+        let (mut response, _) = pending.finish(&challenge).unwrap();
+        let genuine = response.signature.clone();
+        response.signature = to_hex(&stranger.sign(b"forged"));
+        assert_eq!(
+            server.authenticate(&response).unwrap_err().code(),
+            ErrorCode::Unauthenticated
+        );
+        response.signature = genuine;
+        assert!(server.authenticate(&response).is_err());
+
+        // Denying a DID ends its sessions
+        server.update_policy(|p| p.deny(client.did().to_string()));
+        assert!(server.session(&session.token).is_err());
+    }
+
+    #[test]
+    fn test_key_rotation_moves_identity() {
+        let old = Arc::new(signer(1));
+        let server = DidAuthenticator::new(
+            AuthConfig::default(),
+            signer(3),
+            AccessPolicy::allow_only([old.did().to_string()]),
+        );
+        let session = handshake(&server, &old, AccessPolicy::default()).unwrap();
+
+        let new = Arc::new(signer(5));
+        let rotation = KeyRotation::sign(&old, new.did(), 1_700_000_000);
+        let mut forged = rotation.clone();
+        forged.to = signer(6).did().to_string();
+        assert!(server.accept_rotation(&forged).is_err());
+
+        server.accept_rotation(&rotation).unwrap();
+        assert!(server.session(&session.token).is_err());
+        assert!(handshake(&server, &old, AccessPolicy::default()).is_err());
+        handshake(&server, &new, AccessPolicy::default()).unwrap();
+
+        // The server's own rotation is verifiable by its peers
+        let before = server.did();
+        let rotation = server.rotate(signer(7));
+        rotation.verify().unwrap();
+        assert_eq!((rotation.from, rotation.to), (before, server.did()));
+    }
+}
@@ -0,0 +1,260 @@
+//! Verifiable credential verification
+//!
+//! Verifies credentials in the JWT encoding of the W3C VC data model, signed
+//! with `EdDSA` by an issuer identified by a `did:key` DID. `did:key`
+//! resolves locally from the identifier itself, so verification needs no
+//! network access and also runs in browsers through the `wasm` bindings.
+//! Credentials from other DID methods fail with
+//! [`ErrorCode::DidResolution`].
+
+use ::bitcoin::base64;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// Multicodec prefix of an Ed25519 public key
+const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+/// An Ed25519 `did:key` identifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DidKey {
+    public_key: [u8; 32],
+}
+
+impl DidKey {
+    /// DID for an Ed25519 public key
+    pub const fn from_public_key(public_key: [u8; 32]) -> Self {
+        Self { public_key }
+    }
+
+    /// Parse `did:key:z...`, optionally followed by a `#fragment`
+    pub fn parse(did: &str) -> AnyaResult<Self> {
+        let did = did.split_once('#').map_or(did, |(did, _)| did);
+        let Some(encoded) = did.strip_prefix("did:key:") else {
+            return Err(AnyaError::new(
+                ErrorCode::DidResolution,
+                format!("unsupported DID method: {}", did),
+            ));
+        };
+        let decoded = encoded
+            .strip_prefix('z')
+            .and_then(|b58| ::bitcoin::base58::decode(b58).ok())
+            .ok_or_else(|| AnyaError::invalid_input(format!("malformed did:key: {}", did)))?;
+        let public_key = decoded
+            .strip_prefix(&ED25519_MULTICODEC[..])
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or_else(|| {
+                AnyaError::new(
+                    ErrorCode::DidResolution,
+                    format!("did:key is not an Ed25519 key: {}", did),
+                )
+            })?;
+        Ok(Self { public_key })
+    }
+
+    /// Raw Ed25519 public key
+    pub const fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+}
+
+impl std::fmt::Display for DidKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = ED25519_MULTICODEC.to_vec();
+        bytes.extend_from_slice(&self.public_key);
+        write!(f, "did:key:z{}", ::bitcoin::base58::encode(&bytes))
+    }
+}
+
+/// A credential whose signature and validity period were checked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifiedCredential {
+    /// Issuer DID
+    pub issuer: String,
+    /// Subject DID, if the credential names one
+    pub subject: Option<String>,
+    /// Credential types, e.g. `["VerifiableCredential", "KycCredential"]`
+    pub types: Vec<String>,
+    /// The `credentialSubject` claims
+    pub claims: Value,
+    /// Issuance time, seconds since the Unix epoch
+    pub issued_at: Option<u64>,
+    /// Expiry time, seconds since the Unix epoch
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    iss: String,
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default)]
+    nbf: Option<u64>,
+    #[serde(default)]
+    iat: Option<u64>,
+    #[serde(default)]
+    exp: Option<u64>,
+    vc: VcClaim,
+}
+
+#[derive(Deserialize)]
+struct VcClaim {
+    #[serde(rename = "type", default)]
+    types: Vec<String>,
+    #[serde(rename = "credentialSubject", default)]
+    credential_subject: Value,
+}
+
+/// Verify a JWT credential at time `now` (seconds since the Unix epoch)
+pub fn verify_jwt(jwt: &str, now: u64) -> AnyaResult<VerifiedCredential> {
+    let jwt = jwt.trim();
+    let mut parts = jwt.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(AnyaError::invalid_input("credential is not a compact JWS"));
+    };
+    let signed = &jwt[..header.len() + 1 + payload.len()];
+    let header: JwtHeader = serde_json::from_slice(&decode_segment(header)?)?;
+    let claims: JwtClaims = serde_json::from_slice(&decode_segment(payload)?)?;
+    if header.alg != "EdDSA" {
+        return Err(AnyaError::invalid_input(format!(
+            "unsupported credential algorithm {}",
+            header.alg
+        )));
+    }
+    if let Some(kid) = &header.kid {
+        if !kid.starts_with(&claims.iss) {
+            return Err(AnyaError::new(
+                ErrorCode::Unauthenticated,
+                "signing key does not belong to the issuer",
+            ));
+        }
+    }
+
+    let issuer = DidKey::parse(&claims.iss)?;
+    UnparsedPublicKey::new(&ED25519, issuer.public_key())
+        .verify(signed.as_bytes(), &decode_segment(signature)?)
+        .map_err(|_| AnyaError::new(ErrorCode::Unauthenticated, "invalid credential signature"))?;
+
+    if claims.nbf.is_some_and(|nbf| now < nbf) {
+        return Err(AnyaError::new(
+            ErrorCode::Unauthenticated,
+            "credential is not valid yet",
+        ));
+    }
+    if claims.exp.is_some_and(|exp| now >= exp) {
+        return Err(AnyaError::new(
+            ErrorCode::Unauthenticated,
+            "credential has expired",
+        ));
+    }
+    let subject = claims.sub.or_else(|| {
+        claims.vc.credential_subject["id"]
+            .as_str()
+            .map(str::to_string)
+    });
+    Ok(VerifiedCredential {
+        issuer: claims.iss,
+        subject,
+        types: claims.vc.types,
+        claims: claims.vc.credential_subject,
+        issued_at: claims.iat.or(claims.nbf),
+        expires_at: claims.exp,
+    })
+}
+
+fn decode_segment(segment: &str) -> AnyaResult<Vec<u8>> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD)
+        .map_err(|e| AnyaError::invalid_input(format!("invalid JWT encoding: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+
+    fn issue(key: &Ed25519KeyPair, claims: &Value) -> String {
+        let did = DidKey::from_public_key(key.public_key().as_ref().try_into().unwrap());
+        let encode = |v: &Value| {
+            base64::encode_config(serde_json::to_vec(v).unwrap(), base64::URL_SAFE_NO_PAD)
+        };
+        let signing_input = format!(
+            "{}.{}",
+            encode(&json!({ "alg": "EdDSA", "typ": "JWT", "kid": format!("{}#0", did) })),
+            encode(claims)
+        );
+        let signature = key.sign(signing_input.as_bytes());
+        format!(
+            "{}.{}",
+            signing_input,
+            base64::encode_config(signature.as_ref(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    #[test]
+    fn test_verify_did_key_credential() {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let issuer = DidKey::from_public_key(key.public_key().as_ref().try_into().unwrap());
+        assert_eq!(DidKey::parse(&issuer.to_string()).unwrap(), issuer);
+
+        let claims = json!({
+            "iss": issuer.to_string(),
+            "nbf": 1_000,
+            "exp": 2_000,
+            "vc": {
+                "type": ["VerifiableCredential", "KycCredential"],
+                "credentialSubject": { "id": "did:key:zSubject", "level": 2 },
+            },
+        });
+        let jwt = issue(&key, &claims);
+        let verified = verify_jwt(&jwt, 1_500).unwrap();
+        assert_eq!(verified.issuer, issuer.to_string());
+        assert_eq!(verified.subject.as_deref(), Some("did:key:zSubject"));
+        assert_eq!(verified.claims["level"], 2);
+        assert_eq!(
+            verify_jwt(&jwt, 2_000).unwrap_err().code(),
+            ErrorCode::Unauthenticated
+        );
+        assert_eq!(
+            verify_jwt(&jwt, 999).unwrap_err().code(),
+            ErrorCode::Unauthenticated
+        );
+    }
+
+    #[test]
+    fn test_rejects_forged_credentials() {
+        let issuer = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let forger = Ed25519KeyPair::from_seed_unchecked(&[9; 32]).unwrap();
+        let did = DidKey::from_public_key(issuer.public_key().as_ref().try_into().unwrap());
+        let claims = json!({ "iss": did.to_string(), "vc": { "type": ["VerifiableCredential"] } });
+
+        // Signed by a key other than the issuer's
+        let forged = issue(&forger, &claims);
+        let payload = forged.split('.').nth(1).unwrap();
+        let genuine = issue(&issuer, &claims);
+        let mut parts: Vec<_> = genuine.split('.').collect();
+        parts[2] = forged.split('.').nth(2).unwrap();
+        assert_eq!(parts[1], payload);
+        let err = verify_jwt(&parts.join("."), 0).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+
+        // Claiming another issuer than the signing key's DID
+        let web = json!({ "iss": "did:web:example.com", "vc": {} });
+        let err = verify_jwt(&issue(&issuer, &web), 0).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unauthenticated);
+        let err = DidKey::parse("did:web:example.com").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::DidResolution);
+        assert!(verify_jwt("not-a-jwt", 0).is_err());
+    }
+}
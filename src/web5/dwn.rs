@@ -0,0 +1,714 @@
+//! Decentralized Web Node host
+//!
+//! A [`DwnHost`] stores records for the DIDs it hosts (its tenants) so
+//! mobile and enterprise deployments need no third-party DWN. Records are
+//! signed by their author's `did:key`. A tenant may always write to its own
+//! node; other DIDs may only write records under a protocol the tenant has
+//! configured, at a path whose [`ProtocolRule`] lets them. Rules also fix the
+//! schema and data formats of each path and require child records to name a
+//! parent record at the enclosing path.
+//!
+//! Each accepted write gets the next sequence number in the tenant's log,
+//! and [`DwnHost::sync`] pages through the log so a tenant's other devices
+//! can replicate the latest version of every record.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use ::bitcoin::base64;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::auth::{verify_did_signature, DidSigner};
+use crate::storage::{Namespace, StorageBackend};
+use crate::utils::encoding::{from_hex, to_hex};
+use crate::utils::pagination::{paginate, Page, PageRequest};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+const NAMESPACE: &str = "dwn";
+const RECORD_PREFIX: &str = "record/";
+const LOG_PREFIX: &str = "log/";
+const PROTOCOL_PREFIX: &str = "protocol/";
+const HEAD_PREFIX: &str = "head/";
+
+/// Host settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DwnConfig {
+    /// DIDs whose records this node hosts
+    pub tenants: Vec<String>,
+    /// Largest record payload in bytes
+    pub max_data_bytes: usize,
+}
+
+impl Default for DwnConfig {
+    fn default() -> Self {
+        Self {
+            tenants: Vec::new(),
+            max_data_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// A signed record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DwnRecord {
+    /// Identifier chosen by the first writer, stable across updates
+    pub record_id: String,
+    /// Author DID
+    pub author: String,
+    /// Protocol URI the record belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    /// Slash-separated type path within the protocol
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_path: Option<String>,
+    /// Parent record for nested protocol paths
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    /// Schema URI of the data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// MIME type of the data
+    pub data_format: String,
+    /// Base64url payload
+    pub data: String,
+    /// Whether anyone may read the record
+    #[serde(default)]
+    pub published: bool,
+    /// Write time, seconds since the Unix epoch; updates must increase it
+    pub date_created: u64,
+    /// Hex author signature over all other fields
+    pub signature: String,
+}
+
+impl DwnRecord {
+    /// Unsigned record with `data` in `data_format`
+    pub fn new(record_id: impl Into<String>, data_format: impl Into<String>, data: &[u8]) -> Self {
+        Self {
+            record_id: record_id.into(),
+            author: String::new(),
+            protocol: None,
+            protocol_path: None,
+            parent_id: None,
+            schema: None,
+            data_format: data_format.into(),
+            data: base64::encode_config(data, base64::URL_SAFE_NO_PAD),
+            published: false,
+            date_created: 0,
+            signature: String::new(),
+        }
+    }
+
+    /// Place the record at `path` in `protocol`
+    #[must_use]
+    pub fn in_protocol(mut self, protocol: impl Into<String>, path: impl Into<String>) -> Self {
+        self.protocol = Some(protocol.into());
+        self.protocol_path = Some(path.into());
+        self
+    }
+
+    /// Nest the record under `parent_id`
+    #[must_use]
+    pub fn with_parent(mut self, parent_id: impl Into<String>) -> Self {
+        self.parent_id = Some(parent_id.into());
+        self
+    }
+
+    /// Declare the schema of the data
+    #[must_use]
+    pub fn with_schema(mut self, schema: impl Into<String>) -> Self {
+        self.schema = Some(schema.into());
+        self
+    }
+
+    /// Make the record readable by anyone
+    #[must_use]
+    pub const fn published(mut self) -> Self {
+        self.published = true;
+        self
+    }
+
+    /// Sign as the author at `date_created`
+    pub fn sign(mut self, signer: &DidSigner, date_created: u64) -> AnyaResult<Self> {
+        self.author = signer.did().to_string();
+        self.date_created = date_created;
+        self.signature = to_hex(&signer.sign(&self.signing_payload()?));
+        Ok(self)
+    }
+
+    /// Check the author's signature
+    pub fn verify(&self) -> AnyaResult<()> {
+        verify_did_signature(
+            &self.author,
+            &self.signing_payload()?,
+            &from_hex(&self.signature)?,
+        )
+    }
+
+    /// Decoded payload
+    pub fn data_bytes(&self) -> AnyaResult<Vec<u8>> {
+        base64::decode_config(&self.data, base64::URL_SAFE_NO_PAD)
+            .map_err(|e| AnyaError::invalid_input(format!("invalid record data: {}", e)))
+    }
+
+    fn signing_payload(&self) -> AnyaResult<Vec<u8>> {
+        let unsigned = Self {
+            signature: String::new(),
+            ..self.clone()
+        };
+        Ok(serde_json::to_vec(&unsigned)?)
+    }
+}
+
+/// Who may write at a protocol path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Actor {
+    /// Any DID
+    Anyone,
+    /// The tenant
+    Tenant,
+    /// One specific DID
+    Did(String),
+}
+
+/// Constraints on records at one protocol path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolRule {
+    /// Required schema, if any
+    #[serde(default)]
+    pub schema: Option<String>,
+    /// Accepted data formats; any when empty
+    #[serde(default)]
+    pub data_formats: Vec<String>,
+    /// DIDs allowed to write
+    pub can_write: Vec<Actor>,
+}
+
+/// A tenant's protocol: record types by path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolDefinition {
+    /// Protocol URI
+    pub protocol: String,
+    /// Rules keyed by slash-separated path, e.g. `thread/reply`
+    pub structure: BTreeMap<String, ProtocolRule>,
+}
+
+/// A protocol definition signed by the tenant installing it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolConfiguration {
+    /// Installed definition
+    pub definition: ProtocolDefinition,
+    /// Tenant DID
+    pub author: String,
+    /// Configuration time; reconfiguration must increase it
+    pub date_created: u64,
+    /// Hex tenant signature
+    pub signature: String,
+}
+
+impl ProtocolConfiguration {
+    /// Configuration of `definition` signed by `tenant`
+    pub fn sign(
+        tenant: &DidSigner,
+        definition: ProtocolDefinition,
+        date_created: u64,
+    ) -> AnyaResult<Self> {
+        let author = tenant.did().to_string();
+        let payload = serde_json::to_vec(&(&definition, &author, date_created))?;
+        Ok(Self {
+            definition,
+            author,
+            date_created,
+            signature: to_hex(&tenant.sign(&payload)),
+        })
+    }
+
+    /// Check the tenant's signature
+    pub fn verify(&self) -> AnyaResult<()> {
+        let payload = serde_json::to_vec(&(&self.definition, &self.author, self.date_created))?;
+        verify_did_signature(&self.author, &payload, &from_hex(&self.signature)?)
+    }
+}
+
+/// Conditions on queried records; unset fields match anything
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordsQuery {
+    /// Protocol URI
+    pub protocol: Option<String>,
+    /// Protocol path
+    pub protocol_path: Option<String>,
+    /// Parent record
+    pub parent_id: Option<String>,
+    /// Schema URI
+    pub schema: Option<String>,
+    /// Author DID
+    pub author: Option<String>,
+}
+
+impl RecordsQuery {
+    /// Whether `record` satisfies the query
+    pub fn matches(&self, record: &DwnRecord) -> bool {
+        let eq = |want: &Option<String>, have: &Option<String>| {
+            want.iter().all(|w| have.as_ref() == Some(w))
+        };
+        eq(&self.protocol, &record.protocol)
+            && eq(&self.protocol_path, &record.protocol_path)
+            && eq(&self.parent_id, &record.parent_id)
+            && eq(&self.schema, &record.schema)
+            && self.author.iter().all(|a| *a == record.author)
+    }
+}
+
+/// Result of a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStatus {
+    /// Stored at this log sequence number
+    Stored(u64),
+    /// The same version is already stored
+    Duplicate,
+}
+
+/// A record and its position in the tenant's log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncEntry {
+    /// Log sequence number of the record's latest write
+    pub seq: u64,
+    /// Latest version of the record
+    pub record: DwnRecord,
+}
+
+/// Records and protocols of hosted tenants
+pub struct DwnHost {
+    config: DwnConfig,
+    storage: Arc<dyn StorageBackend>,
+    ns: Namespace,
+    /// Serializes writes so log sequence numbers and updates stay consistent
+    writes: Mutex<()>,
+}
+
+impl DwnHost {
+    /// Open the host's record store in `storage`
+    pub async fn open(
+        config: DwnConfig,
+        storage: Arc<dyn StorageBackend>,
+    ) -> AnyaResult<Arc<Self>> {
+        let ns = Namespace::new(NAMESPACE)?;
+        storage.ensure_namespace(&ns).await?;
+        Ok(Arc::new(Self {
+            config,
+            storage,
+            ns,
+            writes: Mutex::new(()),
+        }))
+    }
+
+    /// Host settings
+    pub const fn config(&self) -> &DwnConfig {
+        &self.config
+    }
+
+    /// Install or replace a protocol for `tenant`
+    pub async fn configure_protocol(
+        &self,
+        tenant: &str,
+        configuration: &ProtocolConfiguration,
+    ) -> AnyaResult<()> {
+        self.check_tenant(tenant)?;
+        configuration.verify()?;
+        if configuration.author != tenant {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                "only the tenant may configure its protocols",
+            ));
+        }
+        let _guard = self.writes.lock().await;
+        let key = protocol_key(tenant, &configuration.definition.protocol);
+        if let Some(current) = self.load::<ProtocolConfiguration>(&key).await? {
+            if current.date_created >= configuration.date_created {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    "a newer protocol configuration is installed",
+                ));
+            }
+        }
+        self.storage
+            .put(&self.ns, &key, &serde_json::to_vec(configuration)?)
+            .await
+    }
+
+    /// Validate and store a record for `tenant`
+    pub async fn write(&self, tenant: &str, record: DwnRecord) -> AnyaResult<WriteStatus> {
+        self.check_tenant(tenant)?;
+        record.verify()?;
+        if record.data_bytes()?.len() > self.config.max_data_bytes {
+            return Err(AnyaError::invalid_input("record data is too large"));
+        }
+        self.authorize_write(tenant, &record).await?;
+
+        let guard = self.writes.lock().await;
+        let key = record_key(tenant, &record.record_id);
+        if let Some(current) = self.load::<SyncEntry>(&key).await? {
+            if current.record == record {
+                return Ok(WriteStatus::Duplicate);
+            }
+            if current.record.author != record.author && record.author != tenant {
+                return Err(AnyaError::new(
+                    ErrorCode::PermissionDenied,
+                    "only the author or the tenant may update a record",
+                ));
+            }
+            if current.record.date_created >= record.date_created {
+                return Err(AnyaError::new(
+                    ErrorCode::Conflict,
+                    "a newer version of the record is stored",
+                ));
+            }
+            self.storage
+                .delete(&self.ns, &log_key(tenant, current.seq))
+                .await?;
+        }
+
+        let head_key = format!("{}{}", HEAD_PREFIX, tenant);
+        let seq = self.load::<u64>(&head_key).await?.unwrap_or_default() + 1;
+        let entry = SyncEntry { seq, record };
+        self.storage
+            .put(&self.ns, &key, &serde_json::to_vec(&entry)?)
+            .await?;
+        self.storage
+            .put(
+                &self.ns,
+                &log_key(tenant, seq),
+                entry.record.record_id.as_bytes(),
+            )
+            .await?;
+        self.storage
+            .put(&self.ns, &head_key, &serde_json::to_vec(&seq)?)
+            .await?;
+        drop(guard);
+        Ok(WriteStatus::Stored(seq))
+    }
+
+    /// Records of `tenant` matching `query` that `requester` may read.
+    ///
+    /// The tenant reads everything; others read published records and their
+    /// own.
+    pub async fn query(
+        &self,
+        tenant: &str,
+        requester: Option<&str>,
+        query: &RecordsQuery,
+    ) -> AnyaResult<Vec<DwnRecord>> {
+        self.check_tenant(tenant)?;
+        let prefix = format!("{}{}/", RECORD_PREFIX, tenant);
+        let mut records = Vec::new();
+        for (_, value) in self.storage.scan_prefix(&self.ns, &prefix).await? {
+            let record = serde_json::from_slice::<SyncEntry>(&value)?.record;
+            let readable =
+                record.published || requester.is_some_and(|r| r == tenant || r == record.author);
+            if readable && query.matches(&record) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Page through `tenant`'s log in write order; only the tenant may sync
+    pub async fn sync(
+        &self,
+        tenant: &str,
+        requester: &str,
+        request: &PageRequest,
+    ) -> AnyaResult<Page<SyncEntry>> {
+        self.check_tenant(tenant)?;
+        if requester != tenant {
+            return Err(AnyaError::new(
+                ErrorCode::PermissionDenied,
+                "only the tenant may sync its records",
+            ));
+        }
+        let log = self
+            .storage
+            .scan_prefix(&self.ns, &format!("{}{}/", LOG_PREFIX, tenant))
+            .await?;
+        let ids: Vec<(u64, String)> = log
+            .into_iter()
+            .filter_map(|(key, id)| {
+                let seq = u64::from_str_radix(key.rsplit('/').next()?, 16).ok()?;
+                Some((seq, String::from_utf8(id).ok()?))
+            })
+            .collect();
+        let page = paginate(ids, request, |(seq, _)| *seq)?;
+        let mut items = Vec::with_capacity(page.items.len());
+        for (_, id) in &page.items {
+            if let Some(entry) = self.load(&record_key(tenant, id)).await? {
+                items.push(entry);
+            }
+        }
+        Ok(Page {
+            items,
+            next_cursor: page.next_cursor,
+        })
+    }
+
+    async fn authorize_write(&self, tenant: &str, record: &DwnRecord) -> AnyaResult<()> {
+        let denied = |msg: &str| AnyaError::new(ErrorCode::PermissionDenied, msg.to_string());
+        let (Some(protocol), Some(path)) = (&record.protocol, &record.protocol_path) else {
+            return if record.author == tenant {
+                Ok(())
+            } else {
+                Err(denied(
+                    "records outside a protocol are written by the tenant",
+                ))
+            };
+        };
+        let configuration: ProtocolConfiguration = self
+            .load(&protocol_key(tenant, protocol))
+            .await?
+            .ok_or_else(|| {
+                AnyaError::not_found(format!("protocol {} is not installed", protocol))
+            })?;
+        let rule = configuration
+            .definition
+            .structure
+            .get(path)
+            .ok_or_else(|| {
+                AnyaError::invalid_input(format!("{} has no path {}", protocol, path))
+            })?;
+
+        let may_write = record.author == tenant
+            || rule.can_write.iter().any(|actor| match actor {
+                Actor::Anyone => true,
+                Actor::Tenant => false,
+                Actor::Did(did) => *did == record.author,
+            });
+        if !may_write {
+            return Err(denied("author may not write at this protocol path"));
+        }
+        if rule.schema.is_some() && rule.schema != record.schema {
+            return Err(AnyaError::invalid_input("record schema does not match"));
+        }
+        if !rule.data_formats.is_empty() && !rule.data_formats.contains(&record.data_format) {
+            return Err(AnyaError::invalid_input("data format is not allowed"));
+        }
+
+        match (path.rsplit_once('/'), &record.parent_id) {
+            (None, None) => Ok(()),
+            (Some((parent_path, _)), Some(parent_id)) => {
+                let parent: SyncEntry = self
+                    .load(&record_key(tenant, parent_id))
+                    .await?
+                    .ok_or_else(|| AnyaError::not_found("parent record not found"))?;
+                if parent.record.protocol.as_ref() == Some(protocol)
+                    && parent.record.protocol_path.as_deref() == Some(parent_path)
+                {
+                    Ok(())
+                } else {
+                    Err(AnyaError::invalid_input(
+                        "parent is not at the enclosing path",
+                    ))
+                }
+            }
+            _ => Err(AnyaError::invalid_input(
+                "nested paths need a parent and top-level paths must not have one",
+            )),
+        }
+    }
+
+    fn check_tenant(&self, tenant: &str) -> AnyaResult<()> {
+        if self.config.tenants.iter().any(|t| t == tenant) {
+            Ok(())
+        } else {
+            Err(AnyaError::not_found(format!(
+                "{} is not hosted here",
+                tenant
+            )))
+        }
+    }
+
+    async fn load<T: serde::de::DeserializeOwned>(&self, key: &str) -> AnyaResult<Option<T>> {
+        self.storage
+            .get(&self.ns, key)
+            .await?
+            .map(|v| serde_json::from_slice(&v))
+            .transpose()
+            .map_err(Into::into)
+    }
+}
+
+fn record_key(tenant: &str, record_id: &str) -> String {
+    format!("{}{}/{}", RECORD_PREFIX, tenant, record_id)
+}
+
+fn log_key(tenant: &str, seq: u64) -> String {
+    format!("{}{}/{:016x}", LOG_PREFIX, tenant, seq)
+}
+
+fn protocol_key(tenant: &str, protocol: &str) -> String {
+    format!("{}{}/{}", PROTOCOL_PREFIX, tenant, protocol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryBackend;
+
+    const CHAT: &str = "https://anya.dev/protocols/chat";
+
+    fn signer(seed: u8) -> DidSigner {
+        DidSigner::from_seed(&[seed; 32]).unwrap()
+    }
+
+    async fn host(tenant: &DidSigner) -> Arc<DwnHost> {
+        let config = DwnConfig {
+            tenants: vec![tenant.did().to_string()],
+            ..DwnConfig::default()
+        };
+        DwnHost::open(config, Arc::new(MemoryBackend::new()))
+            .await
+            .unwrap()
+    }
+
+    fn chat_protocol(tenant: &DidSigner) -> ProtocolConfiguration {
+        let rule = |can_write| ProtocolRule {
+            schema: None,
+            data_formats: vec!["text/plain".into()],
+            can_write,
+        };
+        let definition = ProtocolDefinition {
+            protocol: CHAT.into(),
+            structure: BTreeMap::from([
+                ("thread".into(), rule(vec![Actor::Tenant])),
+                ("thread/reply".into(), rule(vec![Actor::Anyone])),
+            ]),
+        };
+        ProtocolConfiguration::sign(tenant, definition, 1).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_protocol_rules_govern_writes_and_reads() {
+        let (tenant, guest) = (signer(1), signer(2));
+        let tenant_did = tenant.did().to_string();
+        let guest_did = guest.did().to_string();
+        let host = host(&tenant).await;
+
+        let thread = DwnRecord::new("t1", "text/plain", b"hello").in_protocol(CHAT, "thread");
+        let err = host
+            .write(&tenant_did, thread.clone().sign(&tenant, 10).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        host.configure_protocol(&tenant_did, &chat_protocol(&tenant))
+            .await
+            .unwrap();
+        let err = host
+            .configure_protocol(&tenant_did, &chat_protocol(&guest))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+
+        // Guests may reply under a thread but not start one
+        let err = host
+            .write(&tenant_did, thread.clone().sign(&guest, 10).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        host.write(&tenant_did, thread.published().sign(&tenant, 10).unwrap())
+            .await
+            .unwrap();
+        let reply = DwnRecord::new("r1", "text/plain", b"hi").in_protocol(CHAT, "thread/reply");
+        assert!(host
+            .write(&tenant_did, reply.clone().sign(&guest, 11).unwrap())
+            .await
+            .is_err());
+        let reply = reply.with_parent("t1").sign(&guest, 11).unwrap();
+        host.write(&tenant_did, reply.clone()).await.unwrap();
+        let mut forged = reply.clone();
+        forged.data = base64::encode_config(b"edited", base64::URL_SAFE_NO_PAD);
+        assert!(host.write(&tenant_did, forged).await.is_err());
+
+        // Unpublished replies are visible to their author and the tenant only
+        let replies = RecordsQuery {
+            protocol_path: Some("thread/reply".into()),
+            ..RecordsQuery::default()
+        };
+        assert_eq!(
+            host.query(&tenant_did, Some(&guest_did), &replies)
+                .await
+                .unwrap(),
+            [reply]
+        );
+        assert_eq!(
+            host.query(&tenant_did, Some(&tenant_did), &replies)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(host
+            .query(&tenant_did, None, &replies)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            host.query(&tenant_did, None, &RecordsQuery::default())
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(host.query(&guest_did, None, &replies).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_pages_latest_versions() {
+        let tenant = signer(1);
+        let did = tenant.did().to_string();
+        let host = host(&tenant).await;
+        for (id, at) in [("a", 1), ("b", 2), ("c", 3)] {
+            let record = DwnRecord::new(id, "text/plain", id.as_bytes())
+                .sign(&tenant, at)
+                .unwrap();
+            assert!(matches!(
+                host.write(&did, record).await.unwrap(),
+                WriteStatus::Stored(_)
+            ));
+        }
+        let update = DwnRecord::new("a", "text/plain", b"a2")
+            .sign(&tenant, 4)
+            .unwrap();
+        assert_eq!(
+            host.write(&did, update.clone()).await.unwrap(),
+            WriteStatus::Stored(4)
+        );
+        assert_eq!(
+            host.write(&did, update).await.unwrap(),
+            WriteStatus::Duplicate
+        );
+        let stale = DwnRecord::new("b", "text/plain", b"old")
+            .sign(&tenant, 1)
+            .unwrap();
+        assert_eq!(
+            host.write(&did, stale).await.unwrap_err().code(),
+            ErrorCode::Conflict
+        );
+
+        let first = host.sync(&did, &did, &PageRequest::first(2)).await.unwrap();
+        let ids: Vec<_> = first
+            .items
+            .iter()
+            .map(|e| e.record.record_id.as_str())
+            .collect();
+        assert_eq!(ids, ["b", "c"]);
+        let request = PageRequest::first(2).after(first.next_cursor.unwrap());
+        let rest = host.sync(&did, &did, &request).await.unwrap();
+        assert_eq!(rest.items[0].record.data_bytes().unwrap(), b"a2");
+        assert!(rest.next_cursor.is_none());
+        let other = signer(2).did().to_string();
+        assert!(host
+            .sync(&did, &other, &PageRequest::default())
+            .await
+            .is_err());
+    }
+}
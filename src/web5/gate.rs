@@ -0,0 +1,347 @@
+//! Credential-gated access
+//!
+//! An [`AccessGate`] maps scopes, such as an API permission or a workflow
+//! action, to the verifiable credentials a caller must present. Each
+//! [`CredentialRequirement`] names a credential type, optionally the issuers
+//! trusted for it and claim values it must carry. Presented credentials are
+//! verified with [`verify_jwt`] and must name the caller as their subject;
+//! results are cached until the credential expires or the cache TTL passes,
+//! whichever is first, so repeated calls do not re-verify signatures.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::credential::{verify_jwt, VerifiedCredential};
+use crate::utils::encoding::{sha256, to_hex};
+use crate::{AnyaError, AnyaResult, ErrorCode};
+
+/// A credential a scope requires
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialRequirement {
+    /// Credential type, e.g. `KycCredential`
+    pub credential_type: String,
+    /// Issuer DIDs trusted for this credential; any issuer when unset
+    #[serde(default)]
+    pub trusted_issuers: Option<HashSet<String>>,
+    /// `credentialSubject` claims that must have exactly these values
+    #[serde(default)]
+    pub claims: BTreeMap<String, Value>,
+}
+
+impl CredentialRequirement {
+    /// Requirement for a credential of `credential_type` from any issuer
+    pub fn of_type(credential_type: impl Into<String>) -> Self {
+        Self {
+            credential_type: credential_type.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Only accept credentials from `issuers`
+    #[must_use]
+    pub fn issued_by<I, S>(mut self, issuers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.trusted_issuers = Some(issuers.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Require the claim `name` to equal `value`
+    #[must_use]
+    pub fn with_claim(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.claims.insert(name.into(), value.into());
+        self
+    }
+
+    /// Whether `credential` satisfies the requirement
+    pub fn is_met_by(&self, credential: &VerifiedCredential) -> bool {
+        credential.types.contains(&self.credential_type)
+            && self
+                .trusted_issuers
+                .iter()
+                .all(|issuers| issuers.contains(&credential.issuer))
+            && self
+                .claims
+                .iter()
+                .all(|(name, value)| credential.claims.get(name) == Some(value))
+    }
+}
+
+/// Settings for an [`AccessGate`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GateConfig {
+    /// Longest time a verification result is reused
+    pub cache_ttl: Duration,
+    /// Most cached verification results
+    pub cache_capacity: usize,
+}
+
+impl Default for GateConfig {
+    fn default() -> Self {
+        Self {
+            cache_ttl: Duration::from_secs(300),
+            cache_capacity: 10_000,
+        }
+    }
+}
+
+struct CachedVerification {
+    credential: VerifiedCredential,
+    valid_until: u64,
+}
+
+/// Scope requirements and a cache of verified credentials
+pub struct AccessGate {
+    config: GateConfig,
+    scopes: HashMap<String, Vec<CredentialRequirement>>,
+    cache: Mutex<HashMap<String, CachedVerification>>,
+}
+
+impl AccessGate {
+    /// Gate with no gated scopes
+    pub fn new(config: GateConfig) -> Self {
+        Self {
+            config,
+            scopes: HashMap::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Require `requirement` for `scope`, in addition to any already set
+    pub fn require(&mut self, scope: impl Into<String>, requirement: CredentialRequirement) {
+        self.scopes
+            .entry(scope.into())
+            .or_default()
+            .push(requirement);
+    }
+
+    /// Requirements of `scope`; ungated scopes have none
+    pub fn requirements(&self, scope: &str) -> &[CredentialRequirement] {
+        self.scopes.get(scope).map_or(&[], Vec::as_slice)
+    }
+
+    /// Check that `holder` may use `scope` with the `presented` JWT
+    /// credentials at time `now` (seconds since the Unix epoch).
+    ///
+    /// Returns the credential used for each requirement, in order. Fails with
+    /// [`ErrorCode::PermissionDenied`] naming the first unmet requirement.
+    pub fn authorize(
+        &self,
+        scope: &str,
+        holder: &str,
+        presented: &[String],
+        now: u64,
+    ) -> AnyaResult<Vec<VerifiedCredential>> {
+        let requirements = self.requirements(scope);
+        if requirements.is_empty() {
+            return Ok(Vec::new());
+        }
+        let verified: Vec<_> = presented
+            .iter()
+            .filter_map(|jwt| match self.verify(jwt, now) {
+                Ok(credential) => Some(credential),
+                Err(e) => {
+                    tracing::debug!(%scope, error = %e, "presented credential rejected");
+                    None
+                }
+            })
+            .filter(|c| c.subject.as_deref() == Some(holder))
+            .collect();
+        requirements
+            .iter()
+            .map(|requirement| {
+                verified
+                    .iter()
+                    .find(|c| requirement.is_met_by(c))
+                    .cloned()
+                    .ok_or_else(|| {
+                        AnyaError::new(
+                            ErrorCode::PermissionDenied,
+                            format!(
+                                "{} requires a {} credential",
+                                scope, requirement.credential_type
+                            ),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Verify a JWT credential, reusing a cached result while it is valid
+    pub fn verify(&self, jwt: &str, now: u64) -> AnyaResult<VerifiedCredential> {
+        let key = to_hex(&sha256(jwt.trim().as_bytes()));
+        if let Some(cached) = self.cache().get(&key).filter(|c| c.valid_until > now) {
+            return Ok(cached.credential.clone());
+        }
+        let credential = verify_jwt(jwt, now)?;
+        let ttl_end = now + self.config.cache_ttl.as_secs();
+        let valid_until = credential
+            .expires_at
+            .map_or(ttl_end, |exp| exp.min(ttl_end));
+        let mut cache = self.cache();
+        if cache.len() >= self.config.cache_capacity {
+            cache.retain(|_, c| c.valid_until > now);
+        }
+        if cache.len() < self.config.cache_capacity {
+            cache.insert(
+                key,
+                CachedVerification {
+                    credential: credential.clone(),
+                    valid_until,
+                },
+            );
+        }
+        drop(cache);
+        Ok(credential)
+    }
+
+    /// Drop all cached verification results, e.g. after a revocation
+    pub fn clear_cache(&self) {
+        self.cache().clear();
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<String, CachedVerification>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web5::credential::DidKey;
+    use ::bitcoin::base64;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+
+    const HOLDER: &str = "did:key:zHolder";
+
+    fn issuer(seed: u8) -> (Ed25519KeyPair, String) {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+        let did = DidKey::from_public_key(key.public_key().as_ref().try_into().unwrap());
+        (key, did.to_string())
+    }
+
+    fn issue(key: &Ed25519KeyPair, iss: &str, kind: &str, subject: Value, exp: u64) -> String {
+        let encode = |v: &Value| {
+            base64::encode_config(serde_json::to_vec(v).unwrap(), base64::URL_SAFE_NO_PAD)
+        };
+        let claims = json!({
+            "iss": iss,
+            "exp": exp,
+            "vc": { "type": ["VerifiableCredential", kind], "credentialSubject": subject },
+        });
+        let input = format!("{}.{}", encode(&json!({ "alg": "EdDSA" })), encode(&claims));
+        let signature = key.sign(input.as_bytes());
+        format!(
+            "{}.{}",
+            input,
+            base64::encode_config(signature.as_ref(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    #[test]
+    fn test_scopes_require_matching_credentials() {
+        let (kyc_key, kyc_did) = issuer(1);
+        let (hr_key, hr_did) = issuer(2);
+        let mut gate = AccessGate::new(GateConfig::default());
+        gate.require(
+            "payments.send",
+            CredentialRequirement::of_type("KycCredential").issued_by([kyc_did.clone()]),
+        );
+        gate.require(
+            "payments.send",
+            CredentialRequirement::of_type("EmployeeCredential").with_claim("role", "treasury"),
+        );
+
+        let kyc = issue(
+            &kyc_key,
+            &kyc_did,
+            "KycCredential",
+            json!({ "id": HOLDER }),
+            5_000,
+        );
+        let treasury = json!({ "id": HOLDER, "role": "treasury" });
+        let employee = issue(&hr_key, &hr_did, "EmployeeCredential", treasury, 5_000);
+        let used = gate
+            .authorize(
+                "payments.send",
+                HOLDER,
+                &[employee.clone(), kyc.clone()],
+                1_000,
+            )
+            .unwrap();
+        assert_eq!(used[0].issuer, kyc_did);
+        assert_eq!(used[1].issuer, hr_did);
+        assert!(gate
+            .authorize("wallet.read", "did:key:zAnyone", &[], 1_000)
+            .unwrap()
+            .is_empty());
+
+        // Missing credential, wrong role, untrusted issuer, and another holder
+        let err = gate
+            .authorize("payments.send", HOLDER, std::slice::from_ref(&kyc), 1_000)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::PermissionDenied);
+        let auditor = json!({ "id": HOLDER, "role": "auditor" });
+        let auditor = issue(&hr_key, &hr_did, "EmployeeCredential", auditor, 5_000);
+        assert!(gate
+            .authorize("payments.send", HOLDER, &[kyc, auditor], 1_000)
+            .is_err());
+        let self_kyc = issue(
+            &hr_key,
+            &hr_did,
+            "KycCredential",
+            json!({ "id": HOLDER }),
+            5_000,
+        );
+        assert!(gate
+            .authorize(
+                "payments.send",
+                HOLDER,
+                &[self_kyc, employee.clone()],
+                1_000
+            )
+            .is_err());
+        let presented = [
+            employee,
+            issue(
+                &kyc_key,
+                &kyc_did,
+                "KycCredential",
+                json!({ "id": HOLDER }),
+                5_000,
+            ),
+        ];
+        assert!(gate
+            .authorize("payments.send", "did:key:zOther", &presented, 1_000)
+            .is_err());
+    }
+
+    #[test]
+    fn test_cached_results_expire_with_credential() {
+        let (key, did) = issuer(1);
+        let gate = AccessGate::new(GateConfig {
+            cache_ttl: Duration::from_secs(60),
+            cache_capacity: 8,
+        });
+        let jwt = issue(&key, &did, "KycCredential", json!({ "id": HOLDER }), 1_030);
+
+        gate.verify(&jwt, 1_000).unwrap();
+        assert_eq!(gate.cache().len(), 1);
+        // Served from the cache within its validity, re-verified after it
+        assert!(gate.verify(&jwt, 1_029).is_ok());
+        assert_eq!(
+            gate.verify(&jwt, 1_030).unwrap_err().code(),
+            ErrorCode::Unauthenticated
+        );
+
+        gate.clear_cache();
+        assert_eq!(gate.cache().len(), 0);
+    }
+}
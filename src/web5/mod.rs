@@ -0,0 +1,21 @@
+//! Web5 protocol integration and decentralized identity
+
+use serde::{Deserialize, Serialize};
+
+pub mod auth;
+pub mod credential;
+pub mod dwn;
+pub mod gate;
+
+/// Configuration for the Web5 subsystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Web5Config {
+    /// Whether the Web5 subsystem is enabled
+    pub enabled: bool,
+}
+
+impl Default for Web5Config {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
@@ -0,0 +1,166 @@
+//! Pins the `anya_core::sdk` surface.
+//!
+//! Everything here goes through `anya_core::sdk` only, except seeding wallet
+//! coins, which applications do through the transaction tracker. A change
+//! that breaks this file breaks SDK users and needs an `API_VERSION` bump.
+
+use std::sync::Arc;
+
+use anya_core::sdk::*;
+use anya_core::storage::memory::MemoryBackend;
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use bitcoin::{Script, TxOut};
+use serde_json::json;
+
+#[allow(dead_code)]
+fn pinned_signatures() {
+    let _: u32 = API_VERSION;
+    let _: fn(&WalletClient) -> Network = WalletClient::network;
+    let _: fn(&WalletClient, &mut UnsignedTransaction, &ExtendedPrivKey) -> AnyaResult<usize> =
+        WalletClient::sign;
+    let _: fn(Address, u64) -> Payment = Payment::new;
+    let _: fn(Arc<dyn ChainSource>) -> ChainClient = ChainClient::new;
+    let _: fn() -> AnyaResult<IdentityClient> = IdentityClient::generate;
+    let _: fn(&[u8; 32]) -> AnyaResult<IdentityClient> = IdentityClient::from_seed;
+    let _: fn(&IdentityClient) -> String = IdentityClient::did;
+    let _: fn(&IdentityClient, &[u8]) -> Vec<u8> = IdentityClient::sign;
+    let _: fn(&str, &[u8], &[u8]) -> AnyaResult<()> = IdentityClient::verify;
+    let _: fn(&IdentityClient, &CredentialRequest, u64) -> AnyaResult<String> =
+        IdentityClient::issue_credential;
+    let _: fn(&str, u64) -> AnyaResult<VerifiedCredential> = IdentityClient::verify_credential;
+    let _: fn(&IdentityClient, DwnRecord, u64) -> AnyaResult<DwnRecord> =
+        IdentityClient::sign_record;
+    let _: fn(FeeMarketConfig, AnomalyConfig) -> MlClient = MlClient::new;
+    let _: fn(&MlClient, MempoolSnapshot) = MlClient::record_mempool;
+    let _: fn(&MlClient, u64, std::time::Duration, usize) -> FeeMarketReport = MlClient::fee_report;
+    let _: fn(&MlClient, &str, &[&str]) -> AnyaResult<()> = MlClient::add_stream;
+    let _: fn(&MlClient, &str, &[f64]) -> AnyaResult<Option<AnomalyScore>> = MlClient::observe;
+}
+
+#[tokio::test]
+async fn wallet_receives_and_prepares_a_signed_payment() {
+    let storage: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+    let wallet = WalletClient::open(Network::Regtest, Arc::clone(&storage))
+        .await
+        .unwrap();
+    let master = ExtendedPrivKey::new_master(Network::Regtest, &[9; 32]).unwrap();
+    let account = wallet
+        .create_account(&master, ScriptType::NativeSegwit, "spending")
+        .await
+        .unwrap();
+    assert_eq!(wallet.accounts().await, vec![account.clone()]);
+    let address = wallet.receive_address(account.id).await.unwrap();
+
+    let coins = anya_core::bitcoin::coins::CoinStore::open(storage)
+        .await
+        .unwrap();
+    coins
+        .insert(&anya_core::bitcoin::coins::Utxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), 0),
+            txout: TxOut {
+                value: 100_000,
+                script_pubkey: address.script_pubkey(),
+            },
+            account: account.id,
+            chain: KeyChain::External,
+            index: 0,
+            height: Some(1),
+        })
+        .await
+        .unwrap();
+    assert_eq!(wallet.balance(None).await.unwrap().confirmed_sat, 100_000);
+
+    let destination = wallet.receive_address(account.id).await.unwrap();
+    let mut tx = wallet
+        .prepare_payment(account.id, &[Payment::new(destination, 30_000)], 2)
+        .await
+        .unwrap();
+    assert!(tx.change_vout.is_some());
+    assert_eq!(wallet.sign(&mut tx, &master).unwrap(), 1);
+
+    let mainnet = Address::p2wpkh(
+        &bitcoin::PublicKey::from_slice(&[2; 33]).unwrap(),
+        Network::Bitcoin,
+    )
+    .unwrap();
+    let err = wallet
+        .prepare_payment(account.id, &[Payment::new(mainnet, 1_000)], 2)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), ErrorCode::InvalidInput);
+}
+
+struct FixedChain;
+
+#[async_trait]
+impl ChainSource for FixedChain {
+    async fn tip_height(&self) -> AnyaResult<u32> {
+        Ok(110)
+    }
+    async fn script_history(&self, _script: &Script) -> AnyaResult<Vec<Txid>> {
+        Ok(Vec::new())
+    }
+    async fn transaction(&self, _txid: &Txid) -> AnyaResult<Option<Transaction>> {
+        Ok(None)
+    }
+    async fn status(&self, txid: &Txid) -> AnyaResult<TxStatus> {
+        Ok(if *txid == Txid::all_zeros() {
+            TxStatus::Confirmed { height: 101 }
+        } else {
+            TxStatus::Mempool
+        })
+    }
+    async fn spender(&self, _outpoint: &OutPoint) -> AnyaResult<Option<Txid>> {
+        Ok(None)
+    }
+}
+
+#[tokio::test]
+async fn chain_identity_and_ml_clients() {
+    let chain = ChainClient::new(Arc::new(FixedChain));
+    assert_eq!(chain.confirmations(&Txid::all_zeros()).await.unwrap(), 10);
+    let pending = Txid::from_byte_array([1; 32]);
+    assert_eq!(chain.confirmations(&pending).await.unwrap(), 0);
+
+    let issuer = IdentityClient::from_seed(&[1; 32]).unwrap();
+    let holder = IdentityClient::generate().unwrap();
+    let jwt = issuer
+        .issue_credential(
+            &CredentialRequest {
+                subject: holder.did(),
+                types: vec!["MembershipCredential".into()],
+                claims: json!({ "tier": "gold" }),
+                expires_at: Some(2_000),
+            },
+            1_000,
+        )
+        .unwrap();
+    let credential = IdentityClient::verify_credential(&jwt, 1_500).unwrap();
+    assert_eq!(credential.issuer, issuer.did());
+    assert_eq!(credential.subject, Some(holder.did()));
+    assert_eq!(credential.claims["tier"], "gold");
+    assert!(IdentityClient::verify_credential(&jwt, 2_000).is_err());
+    let signature = holder.sign(b"hello");
+    IdentityClient::verify(&holder.did(), b"hello", &signature).unwrap();
+
+    let ml = MlClient::new(
+        FeeMarketConfig::default(),
+        AnomalyConfig {
+            min_training: 16,
+            ..AnomalyConfig::default()
+        },
+    );
+    ml.add_stream("latency", &["p50_ms", "p99_ms"]).unwrap();
+    for i in 0..64 {
+        let jitter = f64::from(i % 5);
+        ml.observe("latency", &[20.0 + jitter, 80.0 + jitter])
+            .unwrap();
+    }
+    let spike = ml.observe("latency", &[21.0, 4_000.0]).unwrap().unwrap();
+    assert_eq!(spike.contributions[0].feature, "p99_ms");
+    assert_eq!(
+        ml.observe("missing", &[1.0]).unwrap_err().code(),
+        ErrorCode::NotFound
+    );
+}